@@ -10,8 +10,9 @@ pub struct Args {
 	pub read: Vec<String>,
 	#[clap(long = "write", value_name = "PATH")]
 	pub write: Vec<String>,
-	#[clap(long, default_value = "0.0.0.0:8832")]
-	pub ui_bind: String,
+	/// Address of the web UI, 0.0.0.0:8832 unless configured otherwise.
+	#[clap(long, value_name = "ADDR")]
+	pub ui_bind: Option<String>,
 	#[clap(long, value_name = "ADDR")]
 	pub http: Option<String>,
 	#[clap(subcommand)]
@@ -43,9 +44,14 @@ pub enum Command {
 		#[clap(long)]
 		system: bool,
 	},
+	/// Service state plus this node's peer id, listeners, shared folders
+	/// and peers.
 	Status {
 		#[clap(long)]
 		system: bool,
+		/// Print a versioned JSON document instead of a table.
+		#[clap(long)]
+		json: bool,
 	},
 	Uninstall {
 		#[clap(long)]
@@ -69,6 +75,138 @@ pub enum Command {
 		#[clap(long = "write", value_name = "PATH")]
 		write: Vec<String>,
 	},
-	Peers,
+	Revoke {
+		peer_id: String,
+	},
+	Suspend,
+	Resume,
+	/// Copy the database into `--dir`, or the configured backup folder.
+	Backup {
+		#[clap(long)]
+		dir: Option<String>,
+	},
+	/// Replace the database with a backup. Refuses while a scan is running
+	/// unless `--force` is given.
+	Restore {
+		path: String,
+		#[clap(long)]
+		force: bool,
+	},
+	/// Read dimensions and durations of indexed media under `path`, or
+	/// everywhere, that has none yet.
+	ExtractMetadata {
+		path: Option<String>,
+	},
+	/// Discovered and connected peers. Exits with 2 when there are none.
+	Peers {
+		#[clap(long)]
+		json: bool,
+		/// Print the list again whenever it changes.
+		#[clap(long)]
+		watch: bool,
+	},
+	/// Check the keypair, database, ports, network and shared folders
+	/// without changing anything. Exits with 1 when a check fails.
+	Doctor {
+		/// Print the results as JSON instead of a table.
+		#[clap(long)]
+		json: bool,
+	},
+	/// Secrets like the HTTP API's JWT key, kept in the OS keychain or a
+	/// private file.
+	Secrets {
+		#[clap(subcommand)]
+		command: SecretsCommand,
+	},
+	/// The configuration in effect.
+	Config {
+		#[clap(subcommand)]
+		command: ConfigCommand,
+	},
+	/// Stop every change to the running node, for example while its data
+	/// directory is copied.
+	Maintenance {
+		#[clap(subcommand)]
+		command: MaintenanceCommand,
+	},
+	/// Pair with a device on another network using a pairing string.
+	Pair {
+		#[clap(subcommand)]
+		command: PairCommand,
+	},
 	Daemon,
 }
+
+#[derive(Debug, Parser)]
+pub enum ConfigCommand {
+	/// Every setting's value and whether it comes from the default, the
+	/// config file, the environment, the command line or a runtime change.
+	/// Secrets are masked.
+	Show {
+		/// Explain each setting and where else it can be set.
+		#[clap(long)]
+		describe: bool,
+		/// Print a JSON document instead of a table.
+		#[clap(long)]
+		json: bool,
+	},
+}
+
+#[derive(Debug, Parser)]
+pub enum MaintenanceCommand {
+	/// Refuse every change, wait for running work and checkpoint the
+	/// database. Exits with 1 when it is not safe to copy yet.
+	Enter {
+		reason: String,
+		/// How long it is expected to take.
+		#[clap(long, value_name = "MINUTES")]
+		minutes: Option<u64>,
+	},
+	/// Let changes through again.
+	Exit,
+	/// Whether the node is in maintenance and what is still running.
+	Status,
+}
+
+#[derive(Debug, Parser)]
+pub enum PairCommand {
+	/// Print a new pairing string of the running node. It lets one device
+	/// pair without confirming a code, until it expires.
+	Show,
+	/// Pair with the device that showed `pairing`.
+	Join { pairing: String },
+}
+
+#[derive(Debug, Parser)]
+pub enum SecretsCommand {
+	/// Store a secret. The value is read from stdin when left out, which
+	/// keeps it out of the shell history.
+	Set { name: String, value: Option<String> },
+	/// Print a secret's value.
+	Get { name: String },
+	/// Names of the stored secrets and where each comes from.
+	List,
+	/// Keep secrets in `auto`, `file` or `keychain` from the next start,
+	/// copying the stored ones over.
+	Backend { backend: String },
+}
+
+impl Command {
+	/// Whether stdout has to carry nothing but the command's JSON, or the
+	/// secret it prints.
+	pub fn prints_json(&self) -> bool {
+		matches!(
+			self,
+			Command::Status { json: true, .. }
+				| Command::Peers { json: true, .. }
+				| Command::Doctor { json: true }
+				| Command::Secrets {
+					command: SecretsCommand::Get { .. }
+				} | Command::Config {
+				command: ConfigCommand::Show { json: true, .. }
+			} | Command::Pair {
+				command: PairCommand::Show
+			}
+		)
+	}
+}
@@ -1,11 +1,128 @@
-use args::Command;
+use args::{Command, ConfigCommand, MaintenanceCommand, PairCommand, SecretsCommand};
 use clap::Parser;
+use puppynet_daemon::config::{config_json, describe_config, render_config};
+use puppynet_daemon::doctor::{render_report, report_json};
+use puppynet_daemon::secrets;
+use puppynet_daemon::status::{
+	PeerStatus, StatusSource, peers_json, render_peers, render_status, same_peers, status_json,
+};
+use std::time::Duration;
 
 mod args;
 mod installer;
 mod updater;
 mod utility;
 
+const EXIT_ERROR: i32 = 1;
+/// `puppynet peers` found nothing, as opposed to failing.
+const EXIT_NO_PEERS: i32 = 2;
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Logging is off for JSON output, so errors go straight to stderr then.
+fn report_error(message: String) {
+	if log::log_enabled!(log::Level::Error) {
+		log::error!("{message}");
+	} else {
+		eprintln!("{message}");
+	}
+}
+
+async fn print_peers(json: bool, watch: bool) -> i32 {
+	let source = StatusSource::connect().await;
+	let mut shown: Option<Vec<PeerStatus>> = None;
+	loop {
+		let status = match source.status().await {
+			Ok(status) => status,
+			Err(err) => {
+				report_error(format!("failed to get peers: {err:?}"));
+				return EXIT_ERROR;
+			}
+		};
+		if !shown
+			.as_deref()
+			.is_some_and(|peers| same_peers(peers, &status.peers))
+		{
+			if json {
+				println!("{}", peers_json(&status));
+			} else {
+				if watch {
+					print!("\x1b[2J\x1b[H");
+				}
+				print!("{}", render_peers(&status.peers));
+			}
+		}
+		if !watch {
+			return if status.peers.is_empty() {
+				EXIT_NO_PEERS
+			} else {
+				0
+			};
+		}
+		shown = Some(status.peers);
+		tokio::time::sleep(WATCH_INTERVAL).await;
+	}
+}
+
+async fn run_secrets(command: &SecretsCommand) -> anyhow::Result<()> {
+	match command {
+		SecretsCommand::Set { name, value } => {
+			let value = match value {
+				Some(value) => value.clone(),
+				None => {
+					let mut line = String::new();
+					std::io::stdin().read_line(&mut line)?;
+					line.trim_end_matches(['\r', '\n']).to_string()
+				}
+			};
+			log::info!("{}", secrets::set(name, &value).await?);
+		}
+		SecretsCommand::Get { name } => println!("{}", secrets::get(name).await?),
+		SecretsCommand::List => {
+			let (backend, list) = secrets::list().await?;
+			print!("{}", secrets::render_list(&backend, &list));
+		}
+		SecretsCommand::Backend { backend } => {
+			log::info!("{}", secrets::set_backend(backend).await?);
+		}
+	}
+	Ok(())
+}
+
+async fn run_maintenance(command: &MaintenanceCommand) -> anyhow::Result<()> {
+	use puppynet_daemon::control;
+	match command {
+		MaintenanceCommand::Enter { reason, minutes } => {
+			let expected = minutes.map(|minutes| Duration::from_secs(minutes * 60));
+			log::info!("{}", control::enter_maintenance(reason, expected).await?);
+		}
+		MaintenanceCommand::Exit => log::info!("{}", control::exit_maintenance().await?),
+		MaintenanceCommand::Status => println!("{}", control::maintenance_status().await?),
+	}
+	Ok(())
+}
+
+async fn run_pair(command: &PairCommand) -> anyhow::Result<()> {
+	use puppynet_daemon::control;
+	match command {
+		PairCommand::Show => println!("{}", control::show_pairing().await?),
+		PairCommand::Join { pairing } => log::info!("{}", control::join_pairing(pairing).await?),
+	}
+	Ok(())
+}
+
+async fn show_config(command: &ConfigCommand) -> anyhow::Result<()> {
+	let ConfigCommand::Show { describe, json } = command;
+	let config = puppynet_daemon::config::show().await?;
+	if *json {
+		println!("{}", config_json(&config));
+	} else if *describe {
+		print!("{}", describe_config(&config));
+	} else {
+		print!("{}", render_config(&config));
+	}
+	Ok(())
+}
+
 fn daemon_config(args: &args::Args) -> puppynet_daemon::Config {
 	puppynet_daemon::Config {
 		read: args.read.clone(),
@@ -25,7 +142,9 @@ async fn run_daemon(args: &args::Args) {
 #[tokio::main]
 async fn main() {
 	let args = args::Args::parse();
-	simple_logger::init_with_level(log::Level::Info).unwrap();
+	if !args.command.as_ref().is_some_and(Command::prints_json) {
+		simple_logger::init_with_level(log::Level::Info).unwrap();
+	}
 
 	let version_label = utility::get_version_label();
 	log::info!("puppynet version {}", version_label);
@@ -69,14 +188,24 @@ async fn main() {
 			}
 			return;
 		}
-		Some(Command::Status { system }) => {
-			match installer::status(*system) {
-				Ok(status) => log::info!("service status: {status}"),
+		Some(Command::Status { system, json }) => {
+			let service = installer::status(*system);
+			let status = match StatusSource::connect().await.status().await {
+				Ok(status) => status,
 				Err(err) => {
-					log::error!("failed to get service status: {err:?}");
-					std::process::exit(1);
+					report_error(format!("failed to get node status: {err:?}"));
+					std::process::exit(EXIT_ERROR);
 				}
+			};
+			if *json {
+				println!("{}", status_json(&status));
+				return;
+			}
+			match service {
+				Ok(service) => println!("service      {service}"),
+				Err(err) => log::warn!("failed to get service status: {err:?}"),
 			}
+			print!("{}", render_status(&status));
 			return;
 		}
 		Some(Command::Uninstall { system }) => {
@@ -123,21 +252,116 @@ async fn main() {
 			};
 			return;
 		}
-		Some(Command::Peers) => {
-			match puppynet_daemon::control::connected_peers().await {
-				Ok(peers) => {
-					log::info!("connected peers: {}", peers.len());
-					for peer in peers {
-						log::info!("{peer}");
-					}
+		Some(Command::Revoke { peer_id }) => {
+			match puppynet_daemon::control::revoke(peer_id).await {
+				Ok(message) => {
+					log::info!("{message}");
 				}
 				Err(err) => {
-					log::error!("failed to get connected peers: {err:?}");
+					log::error!("failed to revoke peer {}: {err:?}", peer_id);
 					std::process::exit(1);
 				}
 			};
 			return;
 		}
+		Some(Command::Backup { dir }) => {
+			match puppynet_daemon::control::backup(dir.as_deref()).await {
+				Ok(message) => {
+					log::info!("{message}");
+				}
+				Err(err) => {
+					log::error!("failed to back up database: {err:?}");
+					std::process::exit(1);
+				}
+			};
+			return;
+		}
+		Some(Command::ExtractMetadata { path }) => {
+			match puppynet_daemon::control::extract_metadata(path.as_deref()).await {
+				Ok(message) => {
+					log::info!("{message}");
+				}
+				Err(err) => {
+					log::error!("failed to extract media metadata: {err:?}");
+					std::process::exit(1);
+				}
+			};
+			return;
+		}
+		Some(Command::Restore { path, force }) => {
+			match puppynet_daemon::control::restore(path, *force).await {
+				Ok(message) => {
+					log::info!("{message}");
+				}
+				Err(err) => {
+					log::error!("failed to restore database: {err:?}");
+					std::process::exit(1);
+				}
+			};
+			return;
+		}
+		Some(Command::Suspend) | Some(Command::Resume) => {
+			let suspended = matches!(args.command, Some(Command::Suspend));
+			match puppynet_daemon::control::set_remote_access_suspended(suspended).await {
+				Ok(message) => {
+					log::info!("{message}");
+				}
+				Err(err) => {
+					log::error!("failed to change remote access: {err:?}");
+					std::process::exit(1);
+				}
+			};
+			return;
+		}
+		Some(Command::Peers { json, watch }) => {
+			std::process::exit(print_peers(*json, *watch).await);
+		}
+		Some(Command::Doctor { json }) => {
+			let report = match puppynet_daemon::doctor::run().await {
+				Ok(report) => report,
+				Err(err) => {
+					report_error(format!("failed to run diagnostics: {err:?}"));
+					std::process::exit(EXIT_ERROR);
+				}
+			};
+			if *json {
+				println!("{}", report_json(&report));
+			} else {
+				print!("{}", render_report(&report));
+			}
+			if report.has_failures() {
+				std::process::exit(EXIT_ERROR);
+			}
+			return;
+		}
+		Some(Command::Secrets { command }) => {
+			if let Err(err) = run_secrets(command).await {
+				report_error(format!("{err:#}"));
+				std::process::exit(EXIT_ERROR);
+			}
+			return;
+		}
+		Some(Command::Config { command }) => {
+			if let Err(err) = show_config(command).await {
+				report_error(format!("failed to read the configuration: {err:#}"));
+				std::process::exit(EXIT_ERROR);
+			}
+			return;
+		}
+		Some(Command::Maintenance { command }) => {
+			if let Err(err) = run_maintenance(command).await {
+				report_error(format!("{err:#}"));
+				std::process::exit(EXIT_ERROR);
+			}
+			return;
+		}
+		Some(Command::Pair { command }) => {
+			if let Err(err) = run_pair(command).await {
+				report_error(format!("{err:#}"));
+				std::process::exit(EXIT_ERROR);
+			}
+			return;
+		}
 		Some(Command::Daemon) => {
 			run_daemon(&args).await;
 			return;
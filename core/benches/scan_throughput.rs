@@ -0,0 +1,60 @@
+//! Files indexed per second by a first scan and by a rescan that finds
+//! nothing changed, with the index on disk so commits pay for their
+//! syncs. Point `TMPDIR` at the disk to measure.
+//!
+//! Run with `cargo bench -p puppynet_core --bench scan_throughput`.
+
+use puppynet_core::index::FileIndex;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const DIRS: usize = 50;
+const FILES_PER_DIR: usize = 200;
+
+fn fill(root: &Path) {
+	for dir in 0..DIRS {
+		let dir_path = root.join(format!("dir-{dir:03}"));
+		fs::create_dir_all(&dir_path).unwrap();
+		for file in 0..FILES_PER_DIR {
+			let contents = format!("file {file} of dir {dir}\n").repeat(16);
+			fs::write(dir_path.join(format!("file-{file:04}.txt")), contents).unwrap();
+		}
+	}
+}
+
+fn per_second(files: usize, took: Duration) -> f64 {
+	files as f64 / took.as_secs_f64()
+}
+
+fn main() {
+	let root = std::env::temp_dir().join(format!("puppynet-scan-bench-{}", std::process::id()));
+	let files = root.join("files");
+	fill(&files);
+	let index = FileIndex::open(root.join("index.db")).unwrap();
+	let total = DIRS * FILES_PER_DIR;
+
+	let started = Instant::now();
+	let scanned = index.scan(&files).unwrap();
+	let first = started.elapsed();
+	assert_eq!(scanned.inserted_count as usize, total);
+
+	let started = Instant::now();
+	index.scan(&files).unwrap();
+	let again = started.elapsed();
+
+	println!("{:>10}  {:>10}  {:>12}", "scan", "took", "files/s");
+	println!(
+		"{:>10}  {:>8.2?}  {:>12.0}",
+		"first",
+		first,
+		per_second(total, first)
+	);
+	println!(
+		"{:>10}  {:>8.2?}  {:>12.0}",
+		"unchanged",
+		again,
+		per_second(total, again)
+	);
+	fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,49 @@
+//! Cost of cloning the state snapshot, which the UI does on every render,
+//! next to the cost of querying discovered peers, as the number of
+//! discovered addresses grows. The snapshot column should stay flat.
+//!
+//! Run with `cargo bench -p puppynet_core --bench snapshot`.
+
+use chrono::Utc;
+use puppynet_core::{DiscoveredPeerFilter, DiscoveredPeers, PeerId, State};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const ADDRESSES_PER_PEER: usize = 8;
+const ROUNDS: u32 = 1_000;
+
+fn time_per_round(mut f: impl FnMut()) -> Duration {
+	let started = Instant::now();
+	for _ in 0..ROUNDS {
+		f();
+	}
+	started.elapsed() / ROUNDS
+}
+
+fn main() {
+	let state = State::default();
+	println!(
+		"{:>10}  {:>12}  {:>12}",
+		"addresses", "snapshot", "query(50)"
+	);
+	for addresses in [0, 1_000, 10_000, 100_000] {
+		let mut discovered = DiscoveredPeers::default();
+		let now = Utc::now();
+		for _ in 0..addresses / ADDRESSES_PER_PEER {
+			let peer = PeerId::random();
+			for port in 0..ADDRESSES_PER_PEER {
+				let addr = format!("/ip4/192.168.1.10/tcp/{}", 4000 + port);
+				discovered.record(peer, addr.parse().unwrap(), now);
+			}
+		}
+		assert_eq!(discovered.address_count(), addresses);
+		let snapshot = time_per_round(|| {
+			black_box(state.clone());
+		});
+		let filter = DiscoveredPeerFilter::default();
+		let query = time_per_round(|| {
+			black_box(discovered.query(&filter, 50));
+		});
+		println!("{addresses:>10}  {snapshot:>12.2?}  {query:>12.2?}");
+	}
+}
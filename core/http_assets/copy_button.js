@@ -0,0 +1,51 @@
+const css = `
+.copy-button {
+	min-height: 30px;
+	border: 1px solid #2d6258;
+	background: #020807;
+	color: #79f2c0;
+	font: 13px system-ui, sans-serif;
+}
+`;
+
+// Copies `props.text` to the clipboard without a round trip to the server.
+export default class CopyButton {
+	constructor(element) {
+		this.element = element;
+		this.text = "";
+	}
+
+	mount(props) {
+		this.element.innerHTML = "";
+		this.style = document.createElement("style");
+		this.style.textContent = css;
+		this.button = document.createElement("button");
+		this.button.type = "button";
+		this.button.className = "copy-button";
+		this.button.textContent = "Copy";
+		this.button.addEventListener("click", () => this.copy());
+		this.element.append(this.style, this.button);
+		this.setProps(props);
+	}
+
+	setProps(props) {
+		this.text = String(props?.text ?? "");
+	}
+
+	dispose() {
+		clearTimeout(this.reset);
+	}
+
+	async copy() {
+		try {
+			await navigator.clipboard.writeText(this.text);
+			this.button.textContent = "Copied";
+		} catch {
+			this.button.textContent = "Copy failed";
+		}
+		clearTimeout(this.reset);
+		this.reset = setTimeout(() => {
+			this.button.textContent = "Copy";
+		}, 1500);
+	}
+}
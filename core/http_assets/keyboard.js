@@ -0,0 +1,71 @@
+const LIST_KEYS = new Set([
+	"ArrowUp",
+	"ArrowDown",
+	"Home",
+	"End",
+	"PageUp",
+	"PageDown",
+	"Enter",
+	"Escape",
+]);
+
+function isTyping(target) {
+	if (!(target instanceof HTMLElement)) {
+		return false;
+	}
+	const tag = target.tagName;
+	return tag === "INPUT" || tag === "TEXTAREA" || tag === "SELECT" || target.isContentEditable;
+}
+
+function isActivatable(target) {
+	if (!(target instanceof HTMLElement)) {
+		return false;
+	}
+	return target.tagName === "BUTTON" || target.tagName === "A";
+}
+
+// Forwards list navigation keys to the page. Keys typed into inputs and
+// Enter on a focused button or link keep their browser behavior.
+export default class Keyboard {
+	constructor(element, ctx) {
+		this.element = element;
+		this.ctx = ctx;
+	}
+
+	mount(props) {
+		this.element.innerHTML = "";
+		this.setProps(props);
+		this.onKeyDown = (event) => this.keyDown(event);
+		document.addEventListener("keydown", this.onKeyDown);
+	}
+
+	setProps(props) {
+		this.props = props ?? {};
+	}
+
+	dispose() {
+		document.removeEventListener("keydown", this.onKeyDown);
+	}
+
+	keyDown(event) {
+		if (event.ctrlKey || event.metaKey || event.altKey || event.isComposing) {
+			return;
+		}
+		const key = event.key;
+		if (isTyping(event.target)) {
+			if (key === "Escape") {
+				event.target.blur();
+			}
+			return;
+		}
+		if (key === "Enter" && isActivatable(event.target)) {
+			return;
+		}
+		const printable = key.length === 1 && key !== " ";
+		if (!LIST_KEYS.has(key) && !printable) {
+			return;
+		}
+		event.preventDefault();
+		this.ctx.emit("key", { key });
+	}
+}
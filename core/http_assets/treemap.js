@@ -0,0 +1,90 @@
+const css = `
+.treemap {
+	position: relative;
+	width: 100%;
+	background: #020807;
+	border: 1px solid #1f4b44;
+	overflow: hidden;
+	font: 12px system-ui, sans-serif;
+}
+.treemap-tile {
+	position: absolute;
+	box-sizing: border-box;
+	border: 1px solid #020807;
+	color: #eafff6;
+	overflow: hidden;
+	white-space: nowrap;
+	text-overflow: ellipsis;
+	padding: 2px 4px;
+}
+.treemap-tile.split {
+	background: transparent !important;
+	border: 2px solid #020807;
+	pointer-events: none;
+}
+.treemap-tile.zooms {
+	cursor: zoom-in;
+}
+.treemap-tile:hover {
+	filter: brightness(1.25);
+}
+`;
+
+// Draws the tiles laid out by the server, in percent of the area, and
+// reports a click on a tile that zooms with its path.
+export default class Treemap {
+	constructor(element, ctx) {
+		this.element = element;
+		this.ctx = ctx;
+	}
+
+	mount(props) {
+		this.element.innerHTML = "";
+		this.style = document.createElement("style");
+		this.style.textContent = css;
+		this.root = document.createElement("div");
+		this.root.className = "treemap";
+		this.onClick = (event) => {
+			const path = event.target?.dataset?.zoom;
+			if (path) {
+				this.ctx.emit("zoomed", { path });
+			}
+		};
+		this.root.addEventListener("click", this.onClick);
+		this.element.append(this.style, this.root);
+		this.setProps(props);
+	}
+
+	setProps(props) {
+		const tiles = Array.isArray(props?.tiles) ? props.tiles : [];
+		this.root.style.aspectRatio = String(Number(props?.aspect_ratio ?? 1.6));
+		const fragment = document.createDocumentFragment();
+		for (const tile of tiles) {
+			const node = document.createElement("div");
+			node.className = "treemap-tile";
+			if (tile.split) {
+				node.classList.add("split");
+			}
+			if (tile.zoom_to) {
+				node.classList.add("zooms");
+				node.dataset.zoom = tile.zoom_to;
+			}
+			node.style.left = `${tile.x}%`;
+			node.style.top = `${tile.y}%`;
+			node.style.width = `${tile.width}%`;
+			node.style.height = `${tile.height}%`;
+			node.style.background = tile.color;
+			node.title = tile.tooltip;
+			// Labels only fit tiles a few percent across.
+			if (!tile.split && tile.width > 6 && tile.height > 4) {
+				node.textContent = tile.label;
+			}
+			fragment.append(node);
+		}
+		this.root.replaceChildren(fragment);
+	}
+
+	dispose() {
+		this.root?.removeEventListener("click", this.onClick);
+	}
+}
@@ -0,0 +1,553 @@
+//! Alerts for peers reading or writing far more than they usually do in a
+//! day. The protocol counters hand over what each peer read and wrote on
+//! every rollup; [`AccessMonitor`] adds it to the peer's day and compares
+//! the day with the peer's usual one, the median of its recent active days.
+//! Days a peer asked nothing, because it was offline or idle, are left out,
+//! so a peer used once a week isn't measured against a row of zeroes.
+//! Peers with too little history are held to fixed thresholds instead.
+//!
+//! A day raises one alert per peer. Another follows only when the day
+//! reaches twice the threshold of the last one, then four times, and so on.
+
+use crate::db::{load_access_volume, load_setting};
+use crate::format::{SizeUnits, group_digits, human_size};
+use crate::protocol_stats::PROTOCOL_STATS_RETENTION_DAYS;
+use chrono::NaiveDate;
+use libp2p::PeerId;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+
+/// Where the [`AccessAlertLimits`] are stored, as JSON.
+pub const ACCESS_ALERT_SETTING: &str = "access_volume_alert";
+/// Active days a peer needs before its own usual day is the measure.
+pub(crate) const MIN_BASELINE_DAYS: usize = 3;
+/// Shared folders an alert names.
+const TOP_FOLDERS: usize = 3;
+
+/// What a peer read from and wrote to this node, in serialized bytes of
+/// its requests and of the answers to them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessVolume {
+	pub read: u64,
+	pub written: u64,
+	pub requests: u64,
+}
+
+impl AccessVolume {
+	pub(crate) fn add(&mut self, other: &AccessVolume) {
+		self.read = self.read.saturating_add(other.read);
+		self.written = self.written.saturating_add(other.written);
+		self.requests = self.requests.saturating_add(other.requests);
+	}
+
+	/// Whether the peer asked anything at all.
+	pub(crate) fn is_active(&self) -> bool {
+		self.requests > 0
+	}
+
+	/// The median of each field on its own.
+	fn median<'a>(days: impl Iterator<Item = &'a AccessVolume> + Clone) -> Self {
+		let median = |field: fn(&AccessVolume) -> u64| {
+			let mut values = days.clone().map(field).collect::<Vec<_>>();
+			values.sort_unstable();
+			values
+				.get(values.len().saturating_sub(1) / 2)
+				.copied()
+				.unwrap_or(0)
+		};
+		Self {
+			read: median(|day| day.read),
+			written: median(|day| day.written),
+			requests: median(|day| day.requests),
+		}
+	}
+}
+
+/// What one peer did since the last rollup.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct AccessSample {
+	pub(crate) volume: AccessVolume,
+	/// Bytes read and written by shared folder.
+	pub(crate) folders: HashMap<String, u64>,
+}
+
+impl AccessSample {
+	pub(crate) fn add(&mut self, other: AccessSample) {
+		self.volume.add(&other.volume);
+		for (folder, bytes) in other.folders {
+			*self.folders.entry(folder).or_default() += bytes;
+		}
+	}
+}
+
+/// When a peer's day raises the alert, and what the alert offers to do
+/// about it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessAlertLimits {
+	/// Times its usual day a peer's day has to reach; `0` turns the alert
+	/// off.
+	pub multiple: u64,
+	/// Active days the usual day is the median of.
+	pub baseline_days: usize,
+	/// Bytes read or written in a day that raise the alert for a peer with
+	/// fewer than [`MIN_BASELINE_DAYS`] active days.
+	pub new_peer_bytes: u64,
+	/// Requests in a day that raise the alert for such a peer.
+	pub new_peer_requests: u64,
+	/// Bytes read or written below which a day is never unusual, so a peer
+	/// that reads a few kilobytes a day isn't flagged for a few more.
+	pub min_bytes: u64,
+	/// Requests below which a day is never unusual.
+	pub min_requests: u64,
+	/// Bytes a second the temporary limit offered with an alert allows.
+	pub limit_bytes_per_sec: u64,
+	/// Minutes the temporary limit lasts.
+	pub limit_minutes: u64,
+}
+
+impl Default for AccessAlertLimits {
+	fn default() -> Self {
+		Self {
+			multiple: 10,
+			baseline_days: 14,
+			new_peer_bytes: 1 << 30,
+			new_peer_requests: 100_000,
+			min_bytes: 100 << 20,
+			min_requests: 10_000,
+			limit_bytes_per_sec: 1 << 20,
+			limit_minutes: 60,
+		}
+	}
+}
+
+impl AccessAlertLimits {
+	/// The limits stored in `conn`, or the default when none are stored or
+	/// they can't be read.
+	pub(crate) fn load(conn: &Connection) -> Self {
+		match load_setting(conn, ACCESS_ALERT_SETTING) {
+			Ok(Some(value)) => serde_json::from_str(&value).unwrap_or_else(|err| {
+				tracing::warn!("ignoring invalid access alert limits: {err}");
+				Self::default()
+			}),
+			Ok(None) => Self::default(),
+			Err(err) => {
+				tracing::error!("failed to load access alert limits: {err}");
+				Self::default()
+			}
+		}
+	}
+
+	/// The day that raises the alert for a peer whose usual day is `usual`.
+	pub fn threshold(&self, usual: Option<&AccessVolume>) -> AccessVolume {
+		match usual {
+			Some(usual) => AccessVolume {
+				read: usual.read.saturating_mul(self.multiple).max(self.min_bytes),
+				written: usual
+					.written
+					.saturating_mul(self.multiple)
+					.max(self.min_bytes),
+				requests: usual
+					.requests
+					.saturating_mul(self.multiple)
+					.max(self.min_requests),
+			},
+			None => AccessVolume {
+				read: self.new_peer_bytes,
+				written: self.new_peer_bytes,
+				requests: self.new_peer_requests,
+			},
+		}
+	}
+
+	/// How far `today` is past the threshold: 0 below it, 1 from it on, 2
+	/// from twice it, 3 from four times it and so on.
+	fn level(&self, today: &AccessVolume, usual: Option<&AccessVolume>) -> u32 {
+		if self.multiple == 0 {
+			return 0;
+		}
+		let threshold = self.threshold(usual);
+		let times = [
+			(today.read, threshold.read),
+			(today.written, threshold.written),
+			(today.requests, threshold.requests),
+		]
+		.into_iter()
+		.map(|(value, limit)| value / limit.max(1))
+		.max()
+		.unwrap_or(0);
+		u64::BITS - times.leading_zeros()
+	}
+}
+
+/// A peer's day went past its threshold, or further past it than when
+/// the last alert was raised.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessAlert {
+	pub peer: PeerId,
+	pub day: NaiveDate,
+	/// The day so far.
+	pub today: AccessVolume,
+	/// The peer's usual day; `None` while it has too little history.
+	pub usual: Option<AccessVolume>,
+	/// Shared folders read and written most today with their bytes,
+	/// busiest first.
+	pub top_folders: Vec<(String, u64)>,
+	/// Whether the day raised a smaller alert before this one.
+	pub escalated: bool,
+}
+
+impl AccessAlert {
+	pub fn message(&self) -> String {
+		let size = |bytes| human_size(bytes, SizeUnits::Binary);
+		let mut message = format!(
+			"{}: {} has read {} and written {} in {} requests today",
+			if self.escalated {
+				"Unusual access still growing"
+			} else {
+				"Unusual access"
+			},
+			self.peer,
+			size(self.today.read),
+			size(self.today.written),
+			group_digits(self.today.requests),
+		);
+		match &self.usual {
+			Some(usual) => message.push_str(&format!(
+				", against a usual {} read and {} written in {} requests",
+				size(usual.read),
+				size(usual.written),
+				group_digits(usual.requests),
+			)),
+			None => message.push_str(", with no usual day to compare to yet"),
+		}
+		if !self.top_folders.is_empty() {
+			let folders = self
+				.top_folders
+				.iter()
+				.map(|(folder, bytes)| format!("{folder} ({})", size(*bytes)))
+				.collect::<Vec<_>>();
+			message.push_str(&format!("; mostly {}", folders.join(", ")));
+		}
+		message
+	}
+}
+
+#[derive(Default)]
+struct PeerDays {
+	/// Totals of past days the peer was active, oldest first.
+	active_days: VecDeque<AccessVolume>,
+	today: AccessVolume,
+	folders: HashMap<String, u64>,
+	/// Level of the day's last alert; 0 before the first.
+	level: u32,
+}
+
+impl PeerDays {
+	fn usual(&self, baseline_days: usize) -> Option<AccessVolume> {
+		if self.active_days.len() < MIN_BASELINE_DAYS {
+			return None;
+		}
+		let recent = self.active_days.len().saturating_sub(baseline_days.max(1));
+		Some(AccessVolume::median(self.active_days.range(recent..)))
+	}
+
+	fn top_folders(&self) -> Vec<(String, u64)> {
+		let mut folders = self
+			.folders
+			.iter()
+			.map(|(folder, bytes)| (folder.clone(), *bytes))
+			.collect::<Vec<_>>();
+		folders.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+		folders.truncate(TOP_FOLDERS);
+		folders
+	}
+}
+
+/// Each peer's day and usual day, owned by the event loop.
+pub(crate) struct AccessMonitor {
+	day: NaiveDate,
+	peers: HashMap<PeerId, PeerDays>,
+	/// Volumes not yet written to the daily history.
+	unflushed: HashMap<PeerId, AccessVolume>,
+}
+
+impl AccessMonitor {
+	/// Starts on `day` from stored daily totals; the one of `day` itself is
+	/// where the day left off.
+	pub(crate) fn new(day: NaiveDate, mut history: Vec<(PeerId, NaiveDate, AccessVolume)>) -> Self {
+		history.sort_by_key(|(_, day, _)| *day);
+		let mut peers = HashMap::<PeerId, PeerDays>::new();
+		for (peer, at, volume) in history {
+			let days = peers.entry(peer).or_default();
+			if at == day {
+				days.today.add(&volume);
+			} else if at < day && volume.is_active() {
+				days.active_days.push_back(volume);
+			}
+		}
+		Self {
+			day,
+			peers,
+			unflushed: HashMap::new(),
+		}
+	}
+
+	/// Starts on `day` from the history in `conn`.
+	pub(crate) fn load(conn: &Connection, day: NaiveDate) -> Self {
+		let since = day - chrono::Days::new(PROTOCOL_STATS_RETENTION_DAYS);
+		let rows = load_access_volume(conn, since).unwrap_or_else(|err| {
+			tracing::error!("failed to load access history: {err}");
+			Vec::new()
+		});
+		let history = rows
+			.into_iter()
+			.filter_map(|(peer, day, volume)| Some((PeerId::from_str(&peer).ok()?, day, volume)))
+			.collect();
+		Self::new(day, history)
+	}
+
+	fn start_day(&mut self, day: NaiveDate) {
+		for days in self.peers.values_mut() {
+			let finished = std::mem::take(&mut days.today);
+			if finished.is_active() {
+				days.active_days.push_back(finished);
+			}
+			while days.active_days.len() > PROTOCOL_STATS_RETENTION_DAYS as usize {
+				days.active_days.pop_front();
+			}
+			days.folders.clear();
+			days.level = 0;
+		}
+		self.day = day;
+	}
+
+	/// Adds what `peer` did since the last rollup to its day, `day`, and
+	/// returns an alert when that takes the day to a new level.
+	pub(crate) fn record(
+		&mut self,
+		day: NaiveDate,
+		peer: PeerId,
+		sample: AccessSample,
+		limits: &AccessAlertLimits,
+	) -> Option<AccessAlert> {
+		if day != self.day {
+			self.start_day(day);
+		}
+		self.unflushed.entry(peer).or_default().add(&sample.volume);
+		let days = self.peers.entry(peer).or_default();
+		days.today.add(&sample.volume);
+		for (folder, bytes) in sample.folders {
+			*days.folders.entry(folder).or_default() += bytes;
+		}
+		let usual = days.usual(limits.baseline_days);
+		let level = limits.level(&days.today, usual.as_ref());
+		if level <= days.level {
+			return None;
+		}
+		let escalated = days.level > 0;
+		days.level = level;
+		Some(AccessAlert {
+			peer,
+			day,
+			today: days.today,
+			usual,
+			top_folders: days.top_folders(),
+			escalated,
+		})
+	}
+
+	/// Volumes since the last write of the history, by peer.
+	pub(crate) fn take_unflushed(&mut self) -> Vec<(String, AccessVolume)> {
+		self.unflushed
+			.drain()
+			.map(|(peer, volume)| (peer.to_string(), volume))
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util;
+
+	const MIB: u64 = 1 << 20;
+
+	fn day(n: u32) -> NaiveDate {
+		NaiveDate::from_ymd_opt(2026, 3, 1).unwrap() + chrono::Days::new(n.into())
+	}
+
+	fn sample(read: u64, requests: u64, folder: &str) -> AccessSample {
+		AccessSample {
+			volume: AccessVolume {
+				read,
+				written: requests * 100,
+				requests,
+			},
+			folders: HashMap::from([(folder.to_string(), read)]),
+		}
+	}
+
+	/// A monitor that has seen `peer` read `read` bytes on each of `days`
+	/// days, offline every other day.
+	fn with_history(peer: PeerId, days: u32, read: u64) -> AccessMonitor {
+		let limits = AccessAlertLimits::default();
+		let mut monitor = AccessMonitor::new(day(0), Vec::new());
+		for at in 0..days {
+			let alert = monitor.record(day(at * 2), peer, sample(read, 200, "/srv/docs"), &limits);
+			assert_eq!(alert, None);
+		}
+		monitor
+	}
+
+	#[test]
+	fn a_day_far_past_the_usual_one_raises_one_alert_then_escalates() {
+		let peer = PeerId::random();
+		let limits = AccessAlertLimits::default();
+		let mut monitor = with_history(peer, 5, 20 * MIB);
+		let today = day(20);
+
+		// Ten times 20 MiB is the threshold; reading up to it in small
+		// steps stays quiet.
+		for _ in 0..19 {
+			let alert = monitor.record(today, peer, sample(10 * MIB, 10, "/srv/photos"), &limits);
+			assert_eq!(alert, None);
+		}
+		let alert = monitor
+			.record(today, peer, sample(10 * MIB, 10, "/srv/photos"), &limits)
+			.unwrap();
+		assert!(!alert.escalated);
+		assert_eq!(alert.today.read, 200 * MIB);
+		assert_eq!(alert.usual.unwrap().read, 20 * MIB);
+		assert_eq!(
+			alert.top_folders,
+			vec![(String::from("/srv/photos"), 200 * MIB)]
+		);
+		assert!(alert.message().starts_with(&format!(
+			"Unusual access: {peer} has read 200.00 MiB and written"
+		)));
+		assert!(
+			alert
+				.message()
+				.ends_with("; mostly /srv/photos (200.00 MiB)")
+		);
+
+		// Staying over the threshold doesn't repeat the alert; doubling it
+		// escalates.
+		for _ in 0..19 {
+			let alert = monitor.record(today, peer, sample(10 * MIB, 10, "/srv/photos"), &limits);
+			assert_eq!(alert, None);
+		}
+		let alert = monitor
+			.record(today, peer, sample(10 * MIB, 10, "/srv/photos"), &limits)
+			.unwrap();
+		assert!(alert.escalated);
+		assert_eq!(alert.today.read, 400 * MIB);
+		assert!(alert.message().starts_with("Unusual access still growing"));
+
+		// The next day starts over, now with that day in the history.
+		let alert = monitor.record(day(21), peer, sample(190 * MIB, 10, "/srv/photos"), &limits);
+		assert_eq!(alert, None);
+	}
+
+	#[test]
+	fn offline_days_stay_out_of_the_usual_day() {
+		let peer = PeerId::random();
+		let limits = AccessAlertLimits::default();
+		// Three active days over a month, each reading 50 MiB.
+		let history = [0, 10, 20]
+			.into_iter()
+			.map(|n| {
+				let volume = AccessVolume {
+					read: 50 * MIB,
+					written: 1000,
+					requests: 100,
+				};
+				(peer, day(n), volume)
+			})
+			.collect();
+		let mut monitor = AccessMonitor::new(day(30), history);
+		// Averaged over the month, offline days included, the usual day
+		// would be under 5 MiB and 60 MiB more than ten times it.
+		let alert = monitor.record(day(30), peer, sample(60 * MIB, 100, "/srv"), &limits);
+		assert_eq!(alert, None);
+		let alert = monitor.record(day(30), peer, sample(450 * MIB, 100, "/srv"), &limits);
+		assert_eq!(alert.unwrap().usual.unwrap().read, 50 * MIB);
+	}
+
+	#[test]
+	fn peers_without_history_are_held_to_the_fixed_thresholds() {
+		let peer = PeerId::random();
+		let limits = AccessAlertLimits::default();
+		let mut monitor = with_history(peer, MIN_BASELINE_DAYS as u32 - 1, MIB);
+		let today = day(10);
+		// 900 MiB would be far past a usual 1 MiB, but two days aren't a
+		// usual day yet.
+		let alert = monitor.record(today, peer, sample(900 * MIB, 10, "/srv"), &limits);
+		assert_eq!(alert, None);
+		let alert = monitor
+			.record(today, peer, sample(200 * MIB, 10, "/srv"), &limits)
+			.unwrap();
+		assert_eq!(alert.usual, None);
+		assert!(
+			alert
+				.message()
+				.contains("with no usual day to compare to yet")
+		);
+
+		// Requests alone raise it too.
+		let other = PeerId::random();
+		let many = AccessSample {
+			volume: AccessVolume {
+				read: 0,
+				written: 0,
+				requests: limits.new_peer_requests,
+			},
+			folders: HashMap::new(),
+		};
+		assert!(monitor.record(today, other, many, &limits).is_some());
+	}
+
+	#[test]
+	fn small_days_and_turned_off_alerts_stay_quiet() {
+		let peer = PeerId::random();
+		let mut monitor = with_history(peer, 5, 1024);
+		// A hundred times a usual kilobyte is still under the floor.
+		let limits = AccessAlertLimits::default();
+		let alert = monitor.record(day(20), peer, sample(100 * 1024, 10, "/srv"), &limits);
+		assert_eq!(alert, None);
+
+		let off = AccessAlertLimits {
+			multiple: 0,
+			..AccessAlertLimits::default()
+		};
+		let alert = monitor.record(day(20), peer, sample(100 << 30, 1_000_000, "/srv"), &off);
+		assert_eq!(alert, None);
+	}
+
+	#[test]
+	fn history_gets_each_volume_once_and_loads_back() {
+		let conn = test_util::memory_db();
+		let peer = PeerId::random();
+		let limits = AccessAlertLimits::default();
+		let mut monitor = AccessMonitor::new(day(0), Vec::new());
+		for n in 0..4 {
+			monitor.record(day(n), peer, sample(MIB, 10, "/srv"), &limits);
+			monitor.record(day(n), peer, sample(MIB, 10, "/srv"), &limits);
+			let rows = monitor.take_unflushed();
+			assert_eq!(rows.len(), 1);
+			crate::db::record_access_volume(&conn, day(n), &rows).unwrap();
+		}
+		assert!(monitor.take_unflushed().is_empty());
+
+		// Three finished days make a usual day; the fourth is today so far.
+		let mut monitor = AccessMonitor::load(&conn, day(3));
+		let alert = monitor
+			.record(day(3), peer, sample(100 * MIB, 10, "/srv"), &limits)
+			.unwrap();
+		assert_eq!(alert.usual.unwrap().read, 2 * MIB);
+		assert_eq!(alert.today.read, 102 * MIB);
+	}
+}
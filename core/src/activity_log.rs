@@ -0,0 +1,479 @@
+//! Timeline of what happened on this node: scans, transfers, permission
+//! changes, peers coming and going and deletion proposals, one row per event in
+//! `activity_events` that points at the detailed row it came from.
+//!
+//! Every event is written by [`record_activity`], which folds an event
+//! repeating within [`DEDUPE_WINDOW`] into the earlier row and counts it,
+//! so reconnect churn shows as one entry. Subsystems that already write
+//! their own row from a blocking task record the event in the same
+//! transaction. The swarm loop hands its events to an [`ActivityLog`]
+//! instead, whose writer task also prunes rows past the retention.
+
+use crate::config;
+use crate::db::{load_setting, prune_activity, record_activity};
+use crate::db_pool::Db;
+use chrono::{DateTime, Utc};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+/// Days events are kept; 0 keeps them forever.
+pub(crate) const ACTIVITY_RETENTION_SETTING: &str = "activity_retention_days";
+pub(crate) const DEFAULT_ACTIVITY_RETENTION_DAYS: u32 = 30;
+/// Identical events closer together than this share one row.
+pub(crate) const DEDUPE_WINDOW: chrono::Duration = chrono::Duration::minutes(10);
+/// How often the writer drops events past the retention.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Events per page when the caller doesn't ask for a size.
+pub const DEFAULT_ACTIVITY_PAGE: u64 = 50;
+pub const MAX_ACTIVITY_PAGE: u64 = 500;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ActivityEventKind {
+	Scan,
+	Transfer,
+	Permission,
+	Peer,
+	Deletion,
+}
+
+impl ActivityEventKind {
+	pub const ALL: [ActivityEventKind; 5] = [
+		ActivityEventKind::Scan,
+		ActivityEventKind::Transfer,
+		ActivityEventKind::Permission,
+		ActivityEventKind::Peer,
+		ActivityEventKind::Deletion,
+	];
+
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			ActivityEventKind::Scan => "scan",
+			ActivityEventKind::Transfer => "transfer",
+			ActivityEventKind::Permission => "permission",
+			ActivityEventKind::Peer => "peer",
+			ActivityEventKind::Deletion => "deletion",
+		}
+	}
+
+	pub fn parse(value: &str) -> Option<Self> {
+		Self::ALL.into_iter().find(|kind| kind.as_str() == value)
+	}
+
+	pub fn label(&self) -> &'static str {
+		match self {
+			ActivityEventKind::Scan => "Scans",
+			ActivityEventKind::Transfer => "Transfers",
+			ActivityEventKind::Permission => "Permissions",
+			ActivityEventKind::Peer => "Peers",
+			ActivityEventKind::Deletion => "Deletions",
+		}
+	}
+
+	pub fn icon(&self) -> &'static str {
+		match self {
+			ActivityEventKind::Scan => "🔍",
+			ActivityEventKind::Transfer => "⇅",
+			ActivityEventKind::Permission => "🔑",
+			ActivityEventKind::Peer => "🔌",
+			ActivityEventKind::Deletion => "🗑",
+		}
+	}
+}
+
+/// An event as a subsystem reports it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ActivityEntry {
+	pub kind: ActivityEventKind,
+	pub at: DateTime<Utc>,
+	/// Peer the event concerns, when there is one.
+	pub peer: Option<String>,
+	pub summary: String,
+	/// Id of the row in the subsystem's own table: the scan run, the
+	/// transfer, the permission revision or the deletion proposal.
+	pub ref_id: Option<String>,
+}
+
+impl ActivityEntry {
+	pub(crate) fn new(
+		kind: ActivityEventKind,
+		at: DateTime<Utc>,
+		summary: impl Into<String>,
+	) -> Self {
+		Self {
+			kind,
+			at,
+			peer: None,
+			summary: summary.into(),
+			ref_id: None,
+		}
+	}
+
+	pub(crate) fn peer(mut self, peer: impl ToString) -> Self {
+		self.peer = Some(peer.to_string());
+		self
+	}
+
+	pub(crate) fn ref_id(mut self, ref_id: impl ToString) -> Self {
+		self.ref_id = Some(ref_id.to_string());
+		self
+	}
+}
+
+/// One row of the timeline.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ActivityEvent {
+	pub id: i64,
+	pub kind: ActivityEventKind,
+	/// When it last happened.
+	pub at: DateTime<Utc>,
+	/// When it first happened, earlier than `at` for collapsed repeats.
+	pub first_at: DateTime<Utc>,
+	pub peer: Option<String>,
+	pub summary: String,
+	pub ref_id: Option<String>,
+	/// How many identical events this row stands for.
+	pub count: u64,
+}
+
+/// Which events to list. Empty fields match everything; set fields all
+/// have to match.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ActivityFilter {
+	pub kinds: Vec<ActivityEventKind>,
+	pub peer: Option<String>,
+	pub since: Option<DateTime<Utc>>,
+	/// Case-insensitive substring of the summary.
+	pub text: Option<String>,
+}
+
+/// Days events are kept, as configured under
+/// [`ACTIVITY_RETENTION_SETTING`].
+pub(crate) fn activity_retention_days(conn: &Connection) -> u32 {
+	match load_setting(conn, ACTIVITY_RETENTION_SETTING) {
+		Ok(Some(value)) => value.parse().unwrap_or_else(|err| {
+			tracing::warn!("ignoring invalid activity retention {value:?}: {err}");
+			config::startup().activity_retention_days
+		}),
+		Ok(None) => config::startup().activity_retention_days,
+		Err(err) => {
+			tracing::error!("failed to load activity retention: {err}");
+			config::startup().activity_retention_days
+		}
+	}
+}
+
+/// Drops events older than the configured retention.
+pub(crate) fn prune_expired(conn: &Connection, now: DateTime<Utc>) -> anyhow::Result<usize> {
+	let days = activity_retention_days(conn);
+	if days == 0 {
+		return Ok(0);
+	}
+	prune_activity(conn, now - chrono::Duration::days(i64::from(days)))
+}
+
+/// Queue of events from code that must not wait on the database. Sending
+/// never blocks; events sent after the writer stopped are dropped.
+#[derive(Clone)]
+pub(crate) struct ActivityLog {
+	tx: UnboundedSender<ActivityEntry>,
+}
+
+impl ActivityLog {
+	/// A log and the receiving end, for callers that write the events
+	/// themselves.
+	pub(crate) fn channel() -> (Self, UnboundedReceiver<ActivityEntry>) {
+		let (tx, rx) = unbounded_channel();
+		(Self { tx }, rx)
+	}
+
+	/// A log whose events are written to `db` by a background task, which
+	/// also prunes expired events every [`PRUNE_INTERVAL`].
+	pub(crate) fn spawn(db: Arc<Db>) -> Self {
+		let (log, mut rx) = Self::channel();
+		tokio::spawn(async move {
+			let mut prune = tokio::time::interval(PRUNE_INTERVAL);
+			loop {
+				tokio::select! {
+					entry = rx.recv() => {
+						let Some(entry) = entry else {
+							break;
+						};
+						let mut entries = vec![entry];
+						while let Ok(entry) = rx.try_recv() {
+							entries.push(entry);
+						}
+						let db = db.clone();
+						let _ = tokio::task::spawn_blocking(move || {
+							let conn = db.lock().unwrap();
+							for entry in &entries {
+								if let Err(err) = record_activity(&conn, entry) {
+									tracing::warn!("failed to record activity: {err}");
+								}
+							}
+						})
+						.await;
+					}
+					_ = prune.tick() => {
+						let db = db.clone();
+						let _ = tokio::task::spawn_blocking(move || {
+							let conn = db.lock().unwrap();
+							match prune_expired(&conn, Utc::now()) {
+								Ok(0) => {}
+								Ok(pruned) => tracing::debug!("pruned {pruned} activity events"),
+								Err(err) => tracing::warn!("failed to prune activity: {err}"),
+							}
+						})
+						.await;
+					}
+				}
+			}
+		});
+		log
+	}
+
+	pub(crate) fn record(&self, entry: ActivityEntry) {
+		let _ = self.tx.send(entry);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::db::{
+		load_activity, record_delete_proposal, record_scan_run, record_transfer,
+		save_peer_permissions,
+	};
+	use crate::deletion::{DeleteProposal, DeletionStatus, ProposalHolder};
+	use crate::scan::ScanResult;
+	use crate::state::{FolderRule, Permission, Rule};
+	use crate::test_util;
+	use crate::transfers::{Transfer, TransferDirection};
+	use chrono::TimeZone;
+	use libp2p::PeerId;
+
+	fn conn() -> Connection {
+		test_util::memory_db()
+	}
+
+	fn at(minute: u32) -> DateTime<Utc> {
+		Utc.with_ymd_and_hms(2025, 4, 8, 12, minute, 0).unwrap()
+	}
+
+	fn all(conn: &Connection, filter: &ActivityFilter) -> Vec<ActivityEvent> {
+		load_activity(conn, filter, None, MAX_ACTIVITY_PAGE)
+			.unwrap()
+			.rows
+	}
+
+	fn kinds(conn: &Connection) -> Vec<ActivityEventKind> {
+		all(conn, &ActivityFilter::default())
+			.into_iter()
+			.map(|event| event.kind)
+			.collect()
+	}
+
+	#[test]
+	fn each_subsystem_records_its_events() {
+		let mut conn = conn();
+		let peer = PeerId::random();
+		let run_id = record_scan_run(
+			&conn,
+			"/photos",
+			at(0),
+			Duration::from_secs(2),
+			None,
+			&Ok(ScanResult {
+				updated_count: 1,
+				inserted_count: 2,
+				removed_count: 0,
+				duration: Duration::from_secs(2),
+				file_count: 40,
+				checksums: None,
+				run: None,
+				changes: Vec::new(),
+			}),
+			false,
+		)
+		.unwrap();
+		let mut transfer = Transfer::new(
+			TransferDirection::Upload,
+			peer,
+			"/in/a.jpg",
+			"/photos/a.jpg",
+			Duration::from_secs(1),
+		);
+		transfer.finished_at = at(1);
+		record_transfer(&conn, &transfer).unwrap();
+		let me = PeerId::random();
+		let grant = Permission::new(Rule::Folder(FolderRule::new("/photos".into(), 1)));
+		save_peer_permissions(&mut conn, &me, &peer, &[grant]).unwrap();
+		record_delete_proposal(
+			&conn,
+			&DeleteProposal {
+				id: String::from("proposal"),
+				hash: [1; 32],
+				reason: String::new(),
+				created_at: at(1),
+				expires_at: at(2),
+				holders: vec![ProposalHolder {
+					peer: peer.to_string(),
+					paths: vec![String::from("/photos/a.jpg")],
+					status: DeletionStatus::Undelivered,
+					detail: None,
+					updated_at: at(1),
+				}],
+			},
+		)
+		.unwrap();
+		let (log, mut rx) = ActivityLog::channel();
+		log.record(ActivityEntry::new(ActivityEventKind::Peer, at(2), "Connected").peer(peer));
+		record_activity(&conn, &rx.try_recv().unwrap()).unwrap();
+
+		let events = all(&conn, &ActivityFilter::default());
+		let mut seen = kinds(&conn);
+		seen.sort();
+		assert_eq!(seen, ActivityEventKind::ALL.to_vec());
+		let scan = events
+			.iter()
+			.find(|event| event.kind == ActivityEventKind::Scan)
+			.unwrap();
+		assert_eq!(scan.ref_id, Some(run_id.to_string()));
+		assert!(
+			events
+				.iter()
+				.filter(|event| {
+					!matches!(
+						event.kind,
+						ActivityEventKind::Scan | ActivityEventKind::Deletion
+					)
+				})
+				.all(|event| event.peer == Some(peer.to_string()))
+		);
+	}
+
+	#[test]
+	fn repeats_within_the_window_collapse_into_one_counted_row() {
+		let conn = conn();
+		let connected = |minute| {
+			ActivityEntry::new(ActivityEventKind::Peer, at(minute), "Connected").peer("laptop")
+		};
+		record_activity(&conn, &connected(0)).unwrap();
+		record_activity(&conn, &connected(5)).unwrap();
+		record_activity(&conn, &connected(9)).unwrap();
+		record_activity(
+			&conn,
+			&ActivityEntry::new(ActivityEventKind::Peer, at(9), "Connected").peer("phone"),
+		)
+		.unwrap();
+		let events = all(&conn, &ActivityFilter::default());
+		assert_eq!(events.len(), 2);
+		let laptop = events
+			.iter()
+			.find(|event| event.peer.as_deref() == Some("laptop"))
+			.unwrap();
+		assert_eq!(
+			(laptop.count, laptop.first_at, laptop.at),
+			(3, at(0), at(9))
+		);
+
+		record_activity(&conn, &connected(30)).unwrap();
+		assert_eq!(all(&conn, &ActivityFilter::default()).len(), 3);
+	}
+
+	#[test]
+	fn filters_compose_and_pages_continue_newest_first() {
+		let conn = conn();
+		let entries = [
+			(ActivityEventKind::Scan, None, "Scanned /photos"),
+			(
+				ActivityEventKind::Transfer,
+				Some("laptop"),
+				"Uploaded /photos/a.jpg",
+			),
+			(
+				ActivityEventKind::Transfer,
+				Some("phone"),
+				"Downloaded /notes.txt",
+			),
+			(ActivityEventKind::Peer, Some("laptop"), "Connected"),
+			(
+				ActivityEventKind::Permission,
+				Some("laptop"),
+				"Granted /photos",
+			),
+		];
+		for (minute, (kind, peer, summary)) in entries.into_iter().enumerate() {
+			let mut entry = ActivityEntry::new(kind, at(minute as u32), summary);
+			entry.peer = peer.map(str::to_string);
+			record_activity(&conn, &entry).unwrap();
+		}
+		let summaries = |filter: ActivityFilter| {
+			all(&conn, &filter)
+				.into_iter()
+				.map(|event| event.summary)
+				.collect::<Vec<_>>()
+		};
+
+		let laptop = ActivityFilter {
+			peer: Some(String::from("laptop")),
+			..Default::default()
+		};
+		assert_eq!(
+			summaries(laptop.clone()),
+			["Granted /photos", "Connected", "Uploaded /photos/a.jpg"]
+		);
+		let laptop_transfers = ActivityFilter {
+			kinds: vec![ActivityEventKind::Transfer, ActivityEventKind::Scan],
+			..laptop.clone()
+		};
+		assert_eq!(summaries(laptop_transfers), ["Uploaded /photos/a.jpg"]);
+		let photos_since = ActivityFilter {
+			since: Some(at(1)),
+			text: Some(String::from("PHOTOS")),
+			..Default::default()
+		};
+		assert_eq!(
+			summaries(photos_since),
+			["Granted /photos", "Uploaded /photos/a.jpg"]
+		);
+
+		let first = load_activity(&conn, &ActivityFilter::default(), None, 2).unwrap();
+		let cursor = first.next_cursor.unwrap();
+		let rest = load_activity(&conn, &ActivityFilter::default(), Some(&cursor), 10).unwrap();
+		assert_eq!(
+			first
+				.rows
+				.iter()
+				.chain(&rest.rows)
+				.map(|event| event.at)
+				.collect::<Vec<_>>(),
+			(0..5).rev().map(at).collect::<Vec<_>>()
+		);
+		assert!(rest.next_cursor.is_none());
+	}
+
+	#[test]
+	fn pruning_keeps_recent_events() {
+		let conn = conn();
+		record_activity(
+			&conn,
+			&ActivityEntry::new(ActivityEventKind::Scan, at(0), "old"),
+		)
+		.unwrap();
+		let now = at(0) + chrono::Duration::days(i64::from(DEFAULT_ACTIVITY_RETENTION_DAYS) + 1);
+		record_activity(
+			&conn,
+			&ActivityEntry::new(ActivityEventKind::Scan, now, "new"),
+		)
+		.unwrap();
+		crate::db::save_setting(&conn, ACTIVITY_RETENTION_SETTING, "30").unwrap();
+		assert_eq!(prune_expired(&conn, now).unwrap(), 1);
+		let events = all(&conn, &ActivityFilter::default());
+		assert_eq!(events.len(), 1);
+		assert_eq!(events[0].summary, "new");
+	}
+}
@@ -0,0 +1,295 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Bandwidth-heavy work that can be held to an activity window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityKind {
+	Scan,
+	Update,
+	Sync,
+}
+
+impl ActivityKind {
+	pub const ALL: [ActivityKind; 3] =
+		[ActivityKind::Scan, ActivityKind::Update, ActivityKind::Sync];
+
+	pub fn label(&self) -> &'static str {
+		match self {
+			Self::Scan => "scans",
+			Self::Update => "updates",
+			Self::Sync => "folder syncs",
+		}
+	}
+}
+
+/// Daily range in minutes after local midnight. `start > end` wraps past
+/// midnight and `start == end` covers the whole day.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeRange {
+	pub start: u16,
+	pub end: u16,
+}
+
+impl TimeRange {
+	pub fn contains(&self, minute: u16) -> bool {
+		if self.start == self.end {
+			true
+		} else if self.start < self.end {
+			self.start <= minute && minute < self.end
+		} else {
+			minute >= self.start || minute < self.end
+		}
+	}
+
+	/// Parses `HH:MM-HH:MM`.
+	pub fn parse(value: &str) -> Option<Self> {
+		let (start, end) = value.trim().split_once('-')?;
+		Some(Self {
+			start: parse_minute(start)?,
+			end: parse_minute(end)?,
+		})
+	}
+}
+
+impl std::fmt::Display for TimeRange {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"{}-{}",
+			format_minute(self.start),
+			format_minute(self.end)
+		)
+	}
+}
+
+fn parse_minute(value: &str) -> Option<u16> {
+	let (hours, minutes) = value.trim().split_once(':')?;
+	let hours = hours.parse::<u16>().ok()?;
+	let minutes = minutes.parse::<u16>().ok()?;
+	if minutes >= 60 || hours > 24 || (hours == 24 && minutes > 0) {
+		return None;
+	}
+	Some((hours * 60 + minutes) % MINUTES_PER_DAY)
+}
+
+fn format_minute(minute: u16) -> String {
+	format!("{:02}:{:02}", minute / 60, minute % 60)
+}
+
+/// Parses `+HH:MM`, `-HH`, or `Z` into minutes east of UTC.
+pub fn parse_utc_offset(value: &str) -> Option<i32> {
+	let value = value.trim().trim_start_matches("UTC").trim();
+	if value.is_empty() || value.eq_ignore_ascii_case("z") {
+		return Some(0);
+	}
+	let (sign, rest) = match value.as_bytes()[0] {
+		b'+' => (1, &value[1..]),
+		b'-' => (-1, &value[1..]),
+		_ => (1, value),
+	};
+	let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+	let hours = hours.trim().parse::<i32>().ok()?;
+	let minutes = minutes.trim().parse::<i32>().ok()?;
+	if !(0..=14).contains(&hours) || !(0..60).contains(&minutes) {
+		return None;
+	}
+	Some(sign * (hours * 60 + minutes))
+}
+
+pub fn format_utc_offset(minutes: i32) -> String {
+	let sign = if minutes < 0 { '-' } else { '+' };
+	let minutes = minutes.abs();
+	format!("{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// Times of day when the listed kinds of work may run. Kinds that are not
+/// listed, or a window without ranges, are never held back.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ActivityWindow {
+	pub categories: Vec<ActivityKind>,
+	pub allowed: Vec<TimeRange>,
+	/// Offset of the window's timezone from UTC.
+	pub utc_offset_minutes: i32,
+	/// Cancel work that is still running when the window closes instead of
+	/// letting it finish.
+	pub hard_stop: bool,
+}
+
+impl ActivityWindow {
+	pub fn governs(&self, kind: ActivityKind) -> bool {
+		!self.allowed.is_empty() && self.categories.contains(&kind)
+	}
+
+	fn local_seconds(&self, now: DateTime<Utc>) -> i64 {
+		(now.timestamp() + i64::from(self.utc_offset_minutes) * 60).rem_euclid(SECONDS_PER_DAY)
+	}
+
+	fn open_at(&self, now: DateTime<Utc>) -> bool {
+		let minute = (self.local_seconds(now) / 60) as u16;
+		self.allowed.iter().any(|range| range.contains(minute))
+	}
+
+	pub fn allows(&self, kind: ActivityKind, now: DateTime<Utc>) -> bool {
+		!self.governs(kind) || self.open_at(now)
+	}
+
+	/// Seconds from `now` until local `minute` next comes around.
+	fn until_minute(&self, now: DateTime<Utc>, minute: u16) -> Duration {
+		let delta = (i64::from(minute) * 60 - self.local_seconds(now)).rem_euclid(SECONDS_PER_DAY);
+		Duration::seconds(delta)
+	}
+
+	/// When held back work of `kind` may start, or `None` if it may start now.
+	pub fn next_opening(&self, kind: ActivityKind, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+		if self.allows(kind, now) {
+			return None;
+		}
+		self.allowed
+			.iter()
+			.map(|range| now + self.until_minute(now, range.start))
+			.min()
+	}
+
+	/// When the window next closes for `kind`, or `None` if it never does.
+	pub fn next_closing(&self, kind: ActivityKind, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+		if !self.governs(kind) || !self.open_at(now) {
+			return None;
+		}
+		let mut at = now;
+		// Adjacent or overlapping ranges keep the window open past one end.
+		for _ in 0..=self.allowed.len() {
+			at = self
+				.allowed
+				.iter()
+				.filter(|range| range.start != range.end)
+				.map(|range| at + self.until_minute(at, range.end))
+				.filter(|end| *end > at)
+				.min()?;
+			if !self.open_at(at) {
+				return Some(at);
+			}
+		}
+		None
+	}
+
+	/// `HH:MM` of `at` in the window's timezone.
+	pub fn local_time_label(&self, at: DateTime<Utc>) -> String {
+		format_minute((self.local_seconds(at) / 60) as u16)
+	}
+}
+
+/// Work held back until its activity window opens.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DeferredActivity {
+	pub id: u64,
+	pub kind: ActivityKind,
+	pub label: String,
+	/// Opening time in the window's timezone, `HH:MM`.
+	pub until: String,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::TimeZone;
+
+	fn night_window(utc_offset_minutes: i32) -> ActivityWindow {
+		ActivityWindow {
+			categories: vec![ActivityKind::Scan],
+			allowed: vec![TimeRange::parse("01:00-06:00").unwrap()],
+			utc_offset_minutes,
+			hard_stop: false,
+		}
+	}
+
+	#[test]
+	fn window_uses_its_timezone() {
+		let window = night_window(120);
+		// 23:30 UTC is 01:30 at UTC+2.
+		let now = Utc.with_ymd_and_hms(2025, 3, 1, 23, 30, 0).unwrap();
+		assert!(window.allows(ActivityKind::Scan, now));
+		assert!(window.allows(ActivityKind::Update, now + Duration::hours(12)));
+
+		let midday = Utc.with_ymd_and_hms(2025, 3, 1, 10, 0, 0).unwrap();
+		assert!(!window.allows(ActivityKind::Scan, midday));
+		let opening = window.next_opening(ActivityKind::Scan, midday).unwrap();
+		assert_eq!(opening, Utc.with_ymd_and_hms(2025, 3, 1, 23, 0, 0).unwrap());
+		assert_eq!(window.local_time_label(opening), "01:00");
+		assert_eq!(
+			window.next_closing(ActivityKind::Scan, now),
+			Some(Utc.with_ymd_and_hms(2025, 3, 2, 4, 0, 0).unwrap())
+		);
+	}
+
+	#[test]
+	fn window_wraps_midnight() {
+		let window = ActivityWindow {
+			allowed: vec![TimeRange::parse("22:00-02:00").unwrap()],
+			..night_window(-300)
+		};
+		// 04:00 UTC is 23:00 at UTC-5, 08:00 UTC is 03:00.
+		let late = Utc.with_ymd_and_hms(2025, 3, 1, 4, 0, 0).unwrap();
+		let early = Utc.with_ymd_and_hms(2025, 3, 1, 6, 30, 0).unwrap();
+		let closed = Utc.with_ymd_and_hms(2025, 3, 1, 8, 0, 0).unwrap();
+		assert!(window.allows(ActivityKind::Scan, late));
+		assert!(window.allows(ActivityKind::Scan, early));
+		assert!(!window.allows(ActivityKind::Scan, closed));
+		assert_eq!(
+			window.next_closing(ActivityKind::Scan, late),
+			Some(Utc.with_ymd_and_hms(2025, 3, 1, 7, 0, 0).unwrap())
+		);
+		assert_eq!(
+			window.next_opening(ActivityKind::Scan, closed),
+			Some(Utc.with_ymd_and_hms(2025, 3, 2, 3, 0, 0).unwrap())
+		);
+	}
+
+	#[test]
+	fn adjacent_ranges_close_at_the_last_end() {
+		let window = ActivityWindow {
+			allowed: vec![
+				TimeRange::parse("23:00-00:00").unwrap(),
+				TimeRange::parse("00:00-01:00").unwrap(),
+			],
+			..night_window(0)
+		};
+		let now = Utc.with_ymd_and_hms(2025, 3, 1, 23, 30, 0).unwrap();
+		assert_eq!(
+			window.next_closing(ActivityKind::Scan, now),
+			Some(Utc.with_ymd_and_hms(2025, 3, 2, 1, 0, 0).unwrap())
+		);
+	}
+
+	#[test]
+	fn time_ranges_parse_and_reject_invalid_times() {
+		let range = TimeRange::parse(" 01:00 - 06:30 ").unwrap();
+		assert_eq!(
+			range,
+			TimeRange {
+				start: 60,
+				end: 390
+			}
+		);
+		assert_eq!(range.to_string(), "01:00-06:30");
+		assert_eq!(TimeRange::parse("22:00-24:00").unwrap().end, 0);
+		assert!(TimeRange::parse("25:00-06:00").is_none());
+		assert!(TimeRange::parse("01:60-06:00").is_none());
+		assert!(TimeRange::parse("0100").is_none());
+	}
+
+	#[test]
+	fn utc_offsets_round_trip() {
+		assert_eq!(parse_utc_offset("+02:00"), Some(120));
+		assert_eq!(parse_utc_offset("UTC-5"), Some(-300));
+		assert_eq!(parse_utc_offset("+05:30"), Some(330));
+		assert_eq!(parse_utc_offset("Z"), Some(0));
+		assert_eq!(parse_utc_offset("+15:00"), None);
+		assert_eq!(format_utc_offset(-210), "-03:30");
+		assert_eq!(format_utc_offset(0), "+00:00");
+	}
+}
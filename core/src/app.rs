@@ -1,37 +1,146 @@
+use crate::access_volume::{AccessAlertLimits, AccessMonitor};
+use crate::activity_log::{ActivityEntry, ActivityEventKind, ActivityLog};
 use crate::audio;
-use crate::auth;
+use crate::client::{ResponseDecoder, decode_response};
+use crate::clock::Clock;
+use crate::clock_skew::{ClockOffsetRecord, ClockSample};
+use crate::config;
+use crate::contact_sheet::{
+	self, CONTACT_SHEET_TIME_BUDGET, ContactSheetOptions, ContactSheetResult,
+};
+use crate::content_negotiation::{blocks_held, find_block, hashes_held, read_known_block};
+use crate::content_store::ContentStore;
+use crate::db_pool::Db;
+use crate::deletion::{self, DeleteOutcome, DeletionStatus, ProposalOffer};
 use crate::desktop_input;
+use crate::dialer::{Dialer, dial_discovered, dial_finished};
+use crate::disk_history::{
+	self, DISK_HISTORY_MAX_SAMPLES, DISK_SAMPLE_INTERVAL, DISK_SAMPLE_RETENTION_DAYS, DiskSample,
+	LowSpaceAlerts,
+};
+use crate::event_channel::{send_blocking, send_superseded};
+use crate::event_loop::{BUSY_RETRY_SECS, Fairness, LoopInput, MAX_INBOUND_JOBS, next_input};
+use crate::format::{hex, human_duration};
+use crate::free_space::{DiskCache, free_hint};
+use crate::identity::{IdentityMismatch, resume_identity_adoption};
+use crate::image_decode::{
+	DecodeLimits, ImageTooLarge, THUMBNAIL_DECODE_BUDGET_SETTING, budget_from_setting,
+};
+use crate::index::{
+	self, PeerAccess, ScanRecordOptions, extract_media_metadata, scan_and_record, storage_files,
+};
+use crate::index_announce::{
+	ANNOUNCE_FLUSH_INTERVAL, AnnounceCoalescer, IndexChangeCounts, IndexFreshness, IndexPulls,
+	ScanTally, pull_delay, wants_pull,
+};
+use crate::locations::{self, LocationEnv, WellKnownFolder};
+use crate::maintenance::{MAINTENANCE_SETTING, Maintenance, MaintenanceGate};
+use crate::mime_hint;
+use crate::mounts::{
+	MOUNT_CHECK_INTERVAL, MountTable, ShareAvailability, ShareChange, ShareUnavailable,
+};
+use crate::nat::{NAT_MAPPING_SETTING, NatMapper, NatPorts, NatStatus};
+use crate::natural_sort::natural_cmp;
 use crate::p2p::{
-	AudioCapability, AudioDevice, AuthMethod, CpuInfo, DesktopInput, DirEntry, DiskInfo,
-	FileWriteAck, InterfaceInfo, LiveSearchArgs, LiveSearchRow, MediaCapability, MediaFrame,
-	MediaSource, PeerInfo, PeerReq, PeerRes, PermissionGrant, SearchEvent, Thumbnail,
-	permission_from_grant,
+	ACCESS_DENIED, AudioCapability, AudioDevice, AuthMethod, BrowseRoots, CpuInfo, DesktopInput,
+	DirEntry, DirListing, DiskInfo, FEATURE_ACCESS_EXPLAIN, FEATURE_DELETE_PROPOSALS,
+	FEATURE_DIAL_BACK, FEATURE_FREE_HINT, FEATURE_HAVE_HASHES, FEATURE_IMAGE_TOO_LARGE,
+	FEATURE_INDEX_ANNOUNCE, FEATURE_INDEX_REPLICATION, FEATURE_RATE_LIMIT, FEATURE_TRACING,
+	FEATURE_USER_REFUSED, FileWriteAck, InterfaceInfo, ListingAccess, LiveSearchArgs,
+	LiveSearchRow, MediaCapability, MediaFrame, MediaSource, MimeSource, PeerCapabilities,
+	PeerHealth, PeerInfo, PeerReq, PeerRes, PermissionGrant, REMOTE_ACCESS_SUSPENDED, SearchEvent,
+	Thumbnail, WRITE_REJECTED, WirePath, path_bytes, permission_from_grant,
+};
+use crate::pairing::{
+	NODE_NAME_SETTING, Pairing, PairingDirection, PairingStatus, expire_pairings,
+	missing_pairing_rules, normalize_node_name, peer_node_name, requested_pairing_folders,
+};
+use crate::pairing_invite::{
+	PairedWith, PairingInfo, PairingInvite, PairingSecrets, invite_addrs, secret_ttl,
 };
-use crate::types::FileChunk;
+use crate::path::SafePath;
+use crate::power::PowerState;
+use crate::protocol_stats::{
+	self, Answer, PROTOCOL_STATS_TICK, PeerCounters, ProtocolMonitor, ProtocolRate,
+	RATE_LIMIT_RETRY_SECS, RateLimited, kind_index, wire_len,
+};
+use crate::reachability::{
+	AddressReachability, DIAL_BACK_ANSWER_TIMEOUT, DIAL_BACK_TIMEOUT, DialBackLimiter,
+	DialBackOutcome, MAX_TESTERS, REACHABILITY_SETTING, aggregate, check_dial_back, testable_addrs,
+};
+use crate::remote_ops::{RemoteOps, idle_timeout};
+use crate::replication::{IndexDelta, IndexDeltaAck, ReplicationRole, pull_delta, receive_delta};
+use crate::request_trace::{RequestDirection, RequestLog, RequestTrace, millis};
+use crate::scan_limits::{NodeScanLimits, ScanOverrides, ScanThrottle};
+use crate::share_summary::{self, ShareSummary};
+use crate::sparse::{self, Extent};
+use crate::thumbnail_cache::{
+	PREVIEW_MAX_DIMENSION_SETTING, SourceStamp, THUMBNAIL_CONCURRENCY_SETTING, ThumbnailCache,
+	ThumbnailDisk, concurrency_from_setting, preview_max_from_setting,
+};
+use crate::thumbnail_pregen::{
+	PREGEN_MIN_FILE_SIZE, PREGEN_POLL_INTERVAL, PREGEN_THUMBNAIL_SIZE, PregenOutcome,
+	ThumbnailQueue,
+};
+use crate::transfers::Transfer;
+use crate::types::{FileChunk, ShellOutput};
 use crate::updater::{self, UpdateProgress, UpdateResult};
 use crate::version;
+use crate::wake;
 use crate::webcam;
 use crate::{
 	db::{
-		Cpu as DbCpu, FileEntry, Interface as DbInterface, Node, NodeID, StorageUsageFile,
-		delete_user, fetch_file_entries_paginated, load_discovered_peers, load_peer_permissions,
-		load_peers, load_shared_folders, load_users, remove_discovered_peer, remove_stale_cpus,
-		remove_stale_interfaces, save_cpu, save_discovered_peer, save_interface, save_node,
-		save_peer, save_shared_folder, save_user,
-	},
-	p2p::{AgentBehaviour, AgentEvent, build_swarm, load_or_generate_keypair},
-	scan::{self, ScanEvent},
+		Cpu as DbCpu, FileEntry, FileProvenance, FileSearchResult, Interface as DbInterface, Node,
+		NodeID, SearchFilesArgs, StorageUsageFile, delete_remote_grants, delete_setting,
+		delete_user, fetch_file_entries_paginated, find_previous_local_node, image_files_under,
+		index_change_seq, is_subscribed_to_index, live_locations_of_hash, load_discovered_peers,
+		load_disk_samples, load_indexed_mimes, load_peer_permissions, load_peers,
+		load_permission_revision, load_remote_grants, load_replication, load_setting,
+		load_shared_folders, load_users, mark_delete_outcome_reported, prune_clock_offsets,
+		prune_disk_samples, queue_permission_change, record_access_volume, record_clock_offset,
+		record_delete_outcome, record_disk_samples, record_pending_review, record_protocol_stats,
+		record_provenance, record_transfer, record_wake_target, remove_stale_cpus,
+		remove_stale_interfaces, save_cpu, save_interface, save_node, save_peer,
+		save_remote_grants, save_setting, save_shared_folder, save_user, search_files,
+		set_delete_holder_status, take_permission_change, take_rejected_review,
+		write_discovered_peers,
+	},
+	discovered::{
+		DISCOVERED_ADDRESS_TTL_SETTING, DISCOVERY_FLUSH_SETTING, DiscoveredPeerFilter,
+		DiscoveredPeerInfo, DiscoveredPeers, DiscoveryBatch, DiscoveryWrites,
+		flush_interval_from_setting, ttl_from_setting,
+	},
+	grant_cache::{self, GRANT_CACHE_TTL_SETTING, GrantCache, Lookup, RemoteGrants},
+	keepalive::{
+		ConnectionKeeper, ConnectionPolicy, IMPORTANT_PEERS_SETTING, REDIAL_CHECK_INTERVAL,
+		important_peers_json, load_important_peers,
+	},
+	p2p::{AgentBehaviour, AgentEvent, build_swarm, dial_order, is_quic_addr, listen_addrs},
+	password_policy::{CreateUserError, PasswordPolicy, check_new_user, hash_new_user},
+	safe_open::{self, OpenedPath, WriteTarget},
+	scan::{self, FileHash, ScanEvent},
+	scan_results::{ScanResults, scan_results_batch, scan_run_for},
 	state::{
-		Connection, DiscoveredPeer, FLAG_READ, FLAG_SEARCH, FLAG_WRITE, FolderRule, Peer,
-		Permission, State, User,
+		BatchGrantOutcome, Connection, ConnectionDirection, DisconnectReason, Disconnected,
+		FLAG_PREVIEW, FLAG_READ, FLAG_SEARCH, FLAG_WRITE, FolderRule, HiddenNames, Peer,
+		Permission, PermissionSet, Rule, RuleOverlap, State, TemporaryGrant, TrustLevel, User,
+		merge_folder_grant, pattern_error,
 	},
 };
 use anyhow::{Result, anyhow, bail};
 use chrono::{DateTime, Utc};
-use futures::StreamExt;
-use libp2p::{Multiaddr, PeerId, Swarm, core::connection::ConnectedPoint, mdns, swarm::SwarmEvent};
+use libp2p::{
+	Multiaddr, PeerId, Swarm,
+	core::connection::ConnectedPoint,
+	mdns,
+	multiaddr::Protocol,
+	swarm::{
+		ConnectionId, SwarmEvent,
+		dial_opts::{DialOpts, PeerCondition},
+	},
+};
 use rusqlite::{Connection as SqliteConnection, params};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex, mpsc};
 use std::{
 	env,
@@ -46,20 +155,24 @@ use tokio::process::Command as TokioCommand;
 use tokio::time::{Duration, timeout};
 use tokio::{
 	sync::{
+		Semaphore, broadcast,
 		mpsc::{UnboundedReceiver, UnboundedSender},
 		oneshot,
 	},
 	task::JoinHandle,
 };
+use tracing::Instrument;
 use walkdir::WalkDir;
 
 use libp2p::request_response::{OutboundRequestId, ResponseChannel};
 
 pub struct ReadFileCmd {
 	pub(crate) peer_id: libp2p::PeerId,
-	pub(crate) path: String,
+	pub(crate) path: SafePath,
 	pub(crate) offset: u64,
 	pub(crate) length: Option<u64>,
+	/// Ask for holes as their length; see [`FileChunk::hole`].
+	pub(crate) sparse: bool,
 	pub(crate) tx: oneshot::Sender<Result<FileChunk>>,
 }
 
@@ -72,10 +185,21 @@ pub enum Command {
 		peer_id: libp2p::PeerId,
 		addr: libp2p::Multiaddr,
 	},
+	/// Any request, answered undecoded; see [`crate::client::PeerTransport`].
+	Request {
+		peer: PeerId,
+		req: PeerReq,
+		tx: oneshot::Sender<Result<PeerRes>>,
+	},
 	ListDir {
 		peer: libp2p::PeerId,
-		path: String,
-		tx: oneshot::Sender<Result<Vec<DirEntry>>>,
+		path: SafePath,
+		tx: oneshot::Sender<Result<DirListing>>,
+	},
+	StatFile {
+		peer: libp2p::PeerId,
+		path: SafePath,
+		tx: oneshot::Sender<Result<DirEntry>>,
 	},
 	ListCpus {
 		tx: oneshot::Sender<Result<Vec<CpuInfo>>>,
@@ -85,6 +209,21 @@ pub enum Command {
 		tx: oneshot::Sender<Result<Vec<DiskInfo>>>,
 		peer_id: PeerId,
 	},
+	DiskHistory {
+		peer_id: PeerId,
+		mount: String,
+		from: DateTime<Utc>,
+		to: DateTime<Utc>,
+		tx: oneshot::Sender<Result<Vec<DiskSample>>>,
+	},
+	ListRoots {
+		tx: oneshot::Sender<Result<BrowseRoots>>,
+		peer_id: PeerId,
+	},
+	WellKnownFolders {
+		tx: oneshot::Sender<Result<Vec<WellKnownFolder>>>,
+		peer_id: PeerId,
+	},
 	ListInterfaces {
 		tx: oneshot::Sender<Result<Vec<InterfaceInfo>>>,
 		peer_id: PeerId,
@@ -136,9 +275,12 @@ pub enum Command {
 	ListStorageFiles {
 		tx: oneshot::Sender<Result<Vec<StorageUsageFile>>>,
 	},
+	/// Grants `peer` gave this node, from the grant cache unless
+	/// `force_refresh` or nothing is cached.
 	ListPermissions {
 		peer: PeerId,
-		tx: oneshot::Sender<Result<Vec<Permission>>>,
+		force_refresh: bool,
+		tx: oneshot::Sender<Result<RemoteGrants>>,
 	},
 	GrantPermissions {
 		peer: PeerId,
@@ -149,27 +291,77 @@ pub enum Command {
 	},
 	ReadFile(ReadFileCmd),
 	Scan {
-		path: String,
-		tx: mpsc::Sender<ScanEvent>,
+		path: SafePath,
+		tx: tokio::sync::mpsc::Sender<ScanEvent>,
 		cancel_flag: Arc<AtomicBool>,
+		/// Also extract media metadata for new content under `path`.
+		extract_metadata: bool,
+		limits: ScanOverrides,
 	},
 	RemoteScan {
 		peer: PeerId,
-		path: String,
+		path: SafePath,
 		scan_id: u64,
+		/// Asked of the peer, which holds them to its own limits.
+		limits: ScanOverrides,
+	},
+	/// The batch of results of `peer`'s scan run `run` after row `cursor`.
+	PullScanResults {
+		peer: PeerId,
+		run: i64,
+		cursor: u64,
+		tx: oneshot::Sender<Result<ScanResults>>,
 	},
 	LiveSearch {
 		peer: PeerId,
 		args: LiveSearchArgs,
 		search_id: u64,
 	},
+	/// One page of `peer_id`'s own index search.
+	SearchPeerFiles {
+		peer_id: PeerId,
+		args: SearchFilesArgs,
+		tx: oneshot::Sender<Result<Vec<FileSearchResult>>>,
+	},
+	/// What the folders `peer_id` lets this node search hold.
+	ShareSummaries {
+		peer_id: PeerId,
+		tx: oneshot::Sender<Result<Vec<ShareSummary>>>,
+	},
+	InvalidateDerived {
+		path: PathBuf,
+		tx: oneshot::Sender<()>,
+	},
 	GetThumbnail {
 		peer: PeerId,
-		path: String,
+		path: SafePath,
 		max_width: u32,
 		max_height: u32,
 		tx: oneshot::Sender<Result<Thumbnail>>,
 	},
+	/// The images in the folder `path` of `peer` as one grid.
+	ContactSheet {
+		peer: PeerId,
+		path: SafePath,
+		options: ContactSheetOptions,
+		tx: oneshot::Sender<Result<ContactSheetResult>>,
+	},
+	/// Ask a peer, or this node, to restart its puppynet process.
+	RestartPeer {
+		peer: PeerId,
+		delay_secs: u64,
+		tx: oneshot::Sender<Result<RestartAck>>,
+	},
+	HealthCheck {
+		peer: PeerId,
+		tx: oneshot::Sender<Result<PeerHealth>>,
+	},
+	/// Hand `peer` changes for the replica it keeps of this node's index.
+	SendIndexDelta {
+		peer: PeerId,
+		delta: IndexDelta,
+		tx: oneshot::Sender<Result<IndexDeltaAck>>,
+	},
 	/// Request a remote peer to update itself
 	RemoteUpdate {
 		peer: PeerId,
@@ -184,10 +376,29 @@ pub enum Command {
 	GetState {
 		tx: oneshot::Sender<State>,
 	},
+	QueryDiscoveredPeers {
+		filter: DiscoveredPeerFilter,
+		limit: usize,
+		tx: oneshot::Sender<Vec<DiscoveredPeerInfo>>,
+	},
+	QueryUsers {
+		tx: oneshot::Sender<Vec<String>>,
+	},
+	SetDiscoveredAddressTtl {
+		ttl: chrono::Duration,
+		tx: oneshot::Sender<anyhow::Result<()>>,
+	},
+	SetGrantCacheTtl {
+		ttl: chrono::Duration,
+		tx: oneshot::Sender<Result<()>>,
+	},
+	ReloadStoredState {
+		tx: oneshot::Sender<Result<()>>,
+	},
 	RegisterSharedFolder {
 		path: PathBuf,
 		flags: u8,
-		tx: oneshot::Sender<anyhow::Result<()>>,
+		tx: oneshot::Sender<anyhow::Result<Vec<RuleOverlap>>>,
 	},
 	CreateUser {
 		username: String,
@@ -201,11 +412,100 @@ pub enum Command {
 	SetPeerPermissions {
 		peer: PeerId,
 		permissions: Vec<Permission>,
+		expected_revision: Option<u64>,
+		tx: oneshot::Sender<anyhow::Result<Vec<RuleOverlap>>>,
+	},
+	/// Adds `folder` to the grants of each of `peers`.
+	GrantFolderToPeers {
+		folder: FolderRule,
+		peers: Vec<PeerId>,
+		tx: oneshot::Sender<anyhow::Result<Vec<(PeerId, BatchGrantOutcome)>>>,
+	},
+	SetRemoteAccessSuspended {
+		suspended: bool,
+		tx: oneshot::Sender<anyhow::Result<()>>,
+	},
+	/// Starts maintenance or, with `None`, ends it; stored so a restart
+	/// keeps it.
+	SetMaintenance {
+		maintenance: Option<Maintenance>,
+		tx: oneshot::Sender<anyhow::Result<()>>,
+	},
+	SetNatMapping {
+		enabled: bool,
+		tx: oneshot::Sender<anyhow::Result<()>>,
+	},
+	/// Keeps `peer` connected, redialing it whenever its connection drops,
+	/// or stops doing so.
+	SetPeerImportant {
+		peer: PeerId,
+		important: bool,
+		tx: oneshot::Sender<anyhow::Result<()>>,
+	},
+	/// Which kinds of request `peer` may send, from its next one on.
+	SetTrustLevel {
+		peer: PeerId,
+		trust: TrustLevel,
+		tx: oneshot::Sender<anyhow::Result<()>>,
+	},
+	/// Refuses `peer` while its traffic is over `bytes_per_sec`, for the
+	/// next `duration`.
+	LimitPeerTraffic {
+		peer: PeerId,
+		bytes_per_sec: u64,
+		duration: Duration,
 		tx: oneshot::Sender<anyhow::Result<()>>,
 	},
+	/// The power monitor's latest reading and hold, kept in [`State`].
+	SetPowerState {
+		power: PowerState,
+	},
+	/// A notification about this node from a background task, like
+	/// integrity sampling finding a damaged file.
+	NotifyLocal {
+		message: String,
+	},
+	/// Asks connected peers to dial this node's addresses back.
+	TestReachability {
+		tx: oneshot::Sender<Vec<AddressReachability>>,
+	},
 	ListGrantedPermissions {
 		peer: PeerId,
-		tx: oneshot::Sender<anyhow::Result<Vec<Permission>>>,
+		tx: oneshot::Sender<anyhow::Result<PermissionSet>>,
+	},
+	GrantTemporary {
+		peer: PeerId,
+		path: PathBuf,
+		flags: u8,
+		ttl: Duration,
+		tx: oneshot::Sender<anyhow::Result<TemporaryGrant>>,
+	},
+	RevokeTemporary {
+		peer: PeerId,
+		id: u64,
+		tx: oneshot::Sender<anyhow::Result<()>>,
+	},
+	SetNodeName {
+		name: String,
+		tx: oneshot::Sender<anyhow::Result<String>>,
+	},
+	StartPairing {
+		peer: PeerId,
+		tx: oneshot::Sender<anyhow::Result<Pairing>>,
+	},
+	AnswerPairing {
+		peer: PeerId,
+		accept: bool,
+		tx: oneshot::Sender<anyhow::Result<()>>,
+	},
+	/// Issues a pairing secret and puts it in a pairing string.
+	PairingInfo {
+		tx: oneshot::Sender<anyhow::Result<PairingInfo>>,
+	},
+	/// Pairs with the node a pairing string is from.
+	PairWith {
+		invite: PairingInvite,
+		tx: oneshot::Sender<anyhow::Result<PairedWith>>,
 	},
 	GetLocalPeerId {
 		tx: oneshot::Sender<PeerId>,
@@ -219,13 +519,155 @@ pub enum Command {
 		peer: PeerId,
 		session_id: u64,
 		data: Vec<u8>,
-		tx: oneshot::Sender<Result<Vec<u8>>>,
+		tx: oneshot::Sender<Result<ShellOutput>>,
+	},
+	CloseShell {
+		peer: PeerId,
+		session_id: u64,
+		tx: oneshot::Sender<Result<ShellOutput>>,
 	},
 	DesktopInput {
 		peer: PeerId,
 		input: DesktopInput,
 		tx: oneshot::Sender<Result<()>>,
 	},
+	OpenInbox {
+		peer: PeerId,
+		name: String,
+		size: u64,
+		tx: oneshot::Sender<Result<InboxSlot>>,
+	},
+	WriteFile {
+		peer: PeerId,
+		path: SafePath,
+		offset: u64,
+		data: Vec<u8>,
+		tx: oneshot::Sender<Result<FileWriteAck>>,
+	},
+	/// Writes `data` at `offset` of the inbox upload at `path`, relative to
+	/// the inbox as `OpenInbox` answered it.
+	WriteInbox {
+		peer: PeerId,
+		path: String,
+		offset: u64,
+		data: Vec<u8>,
+		tx: oneshot::Sender<Result<FileWriteAck>>,
+	},
+	HaveHashes {
+		peer: PeerId,
+		hashes: Vec<FileHash>,
+		tx: oneshot::Sender<Result<HaveBitmap>>,
+	},
+	HaveBlocks {
+		peer: PeerId,
+		hash: FileHash,
+		block_size: u64,
+		blocks: Vec<FileHash>,
+		tx: oneshot::Sender<Result<HaveBitmap>>,
+	},
+	WriteKnownBlock {
+		peer: PeerId,
+		path: String,
+		offset: u64,
+		block_hash: FileHash,
+		tx: oneshot::Sender<Result<FileWriteAck>>,
+	},
+	/// Adds a finished download or upload to the transfer history.
+	RecordTransfer {
+		transfer: Transfer,
+	},
+	/// Sends `peer` the deletion proposals and outcomes waiting for it, if
+	/// it is connected.
+	FlushDeleteOutbox {
+		peer: PeerId,
+	},
+}
+
+impl Command {
+	/// Variant name, for spans.
+	fn name(&self) -> &'static str {
+		match self {
+			Self::PeerInfo { .. } => "PeerInfo",
+			Self::Connect { .. } => "Connect",
+			Self::Request { .. } => "Request",
+			Self::ListDir { .. } => "ListDir",
+			Self::StatFile { .. } => "StatFile",
+			Self::ListCpus { .. } => "ListCpus",
+			Self::ListDisks { .. } => "ListDisks",
+			Self::DiskHistory { .. } => "DiskHistory",
+			Self::ListRoots { .. } => "ListRoots",
+			Self::WellKnownFolders { .. } => "WellKnownFolders",
+			Self::ListInterfaces { .. } => "ListInterfaces",
+			Self::AudioCapability { .. } => "AudioCapability",
+			Self::ListAudioDevices { .. } => "ListAudioDevices",
+			Self::SetAudioMuted { .. } => "SetAudioMuted",
+			Self::SetAudioVolume { .. } => "SetAudioVolume",
+			Self::SetDefaultAudioDevice { .. } => "SetDefaultAudioDevice",
+			Self::MediaCapability { .. } => "MediaCapability",
+			Self::ListMediaSources { .. } => "ListMediaSources",
+			Self::GetMediaFrame { .. } => "GetMediaFrame",
+			Self::ListFileEntries { .. } => "ListFileEntries",
+			Self::ListStorageFiles { .. } => "ListStorageFiles",
+			Self::ListPermissions { .. } => "ListPermissions",
+			Self::GrantPermissions { .. } => "GrantPermissions",
+			Self::ReadFile { .. } => "ReadFile",
+			Self::Scan { .. } => "Scan",
+			Self::RemoteScan { .. } => "RemoteScan",
+			Self::PullScanResults { .. } => "PullScanResults",
+			Self::LiveSearch { .. } => "LiveSearch",
+			Self::SearchPeerFiles { .. } => "SearchPeerFiles",
+			Self::ShareSummaries { .. } => "ShareSummaries",
+			Self::InvalidateDerived { .. } => "InvalidateDerived",
+			Self::GetThumbnail { .. } => "GetThumbnail",
+			Self::ContactSheet { .. } => "ContactSheet",
+			Self::RestartPeer { .. } => "RestartPeer",
+			Self::HealthCheck { .. } => "HealthCheck",
+			Self::SendIndexDelta { .. } => "SendIndexDelta",
+			Self::RemoteUpdate { .. } => "RemoteUpdate",
+			Self::InjectDiscoveredPeer { .. } => "InjectDiscoveredPeer",
+			Self::GetState { .. } => "GetState",
+			Self::QueryDiscoveredPeers { .. } => "QueryDiscoveredPeers",
+			Self::QueryUsers { .. } => "QueryUsers",
+			Self::SetDiscoveredAddressTtl { .. } => "SetDiscoveredAddressTtl",
+			Self::SetGrantCacheTtl { .. } => "SetGrantCacheTtl",
+			Self::ReloadStoredState { .. } => "ReloadStoredState",
+			Self::RegisterSharedFolder { .. } => "RegisterSharedFolder",
+			Self::CreateUser { .. } => "CreateUser",
+			Self::DeleteUser { .. } => "DeleteUser",
+			Self::SetPeerPermissions { .. } => "SetPeerPermissions",
+			Self::GrantFolderToPeers { .. } => "GrantFolderToPeers",
+			Self::SetRemoteAccessSuspended { .. } => "SetRemoteAccessSuspended",
+			Self::SetMaintenance { .. } => "SetMaintenance",
+			Self::SetNatMapping { .. } => "SetNatMapping",
+			Self::SetPeerImportant { .. } => "SetPeerImportant",
+			Self::SetTrustLevel { .. } => "SetTrustLevel",
+			Self::LimitPeerTraffic { .. } => "LimitPeerTraffic",
+			Self::SetPowerState { .. } => "SetPowerState",
+			Self::NotifyLocal { .. } => "NotifyLocal",
+			Self::TestReachability { .. } => "TestReachability",
+			Self::ListGrantedPermissions { .. } => "ListGrantedPermissions",
+			Self::GrantTemporary { .. } => "GrantTemporary",
+			Self::RevokeTemporary { .. } => "RevokeTemporary",
+			Self::SetNodeName { .. } => "SetNodeName",
+			Self::StartPairing { .. } => "StartPairing",
+			Self::AnswerPairing { .. } => "AnswerPairing",
+			Self::PairingInfo { .. } => "PairingInfo",
+			Self::PairWith { .. } => "PairWith",
+			Self::GetLocalPeerId { .. } => "GetLocalPeerId",
+			Self::StartShell { .. } => "StartShell",
+			Self::ShellInput { .. } => "ShellInput",
+			Self::CloseShell { .. } => "CloseShell",
+			Self::DesktopInput { .. } => "DesktopInput",
+			Self::OpenInbox { .. } => "OpenInbox",
+			Self::WriteFile { .. } => "WriteFile",
+			Self::WriteInbox { .. } => "WriteInbox",
+			Self::HaveHashes { .. } => "HaveHashes",
+			Self::HaveBlocks { .. } => "HaveBlocks",
+			Self::WriteKnownBlock { .. } => "WriteKnownBlock",
+			Self::RecordTransfer { .. } => "RecordTransfer",
+			Self::FlushDeleteOutbox { .. } => "FlushDeleteOutbox",
+		}
+	}
 }
 
 struct ShellSession {
@@ -234,13 +676,183 @@ struct ShellSession {
 	stdout: tokio::process::ChildStdout,
 }
 
-enum ShellInputResult {
-	Output(Vec<u8>),
-	Exited,
+const REMOTE_ACCESS_SUSPENDED_SETTING: &str = "remote_access_suspended";
+const TEMPORARY_GRANT_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+/// How often discovered address changes are checked for a due flush.
+const DISCOVERY_FLUSH_TICK: Duration = Duration::from_secs(5);
+/// How often connected peers are asked for their time again.
+const CLOCK_SAMPLE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Clock offset history older than this is dropped.
+const CLOCK_OFFSET_RETENTION_DAYS: i64 = 90;
+/// Share changes a slow subscriber may fall behind by.
+const SHARE_CHANGE_CAPACITY: usize = 16;
+/// Anything longer should be a folder rule the user can see and edit.
+pub(crate) const MAX_TEMPORARY_GRANT_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const INBOX_MAX_FILE_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+const INBOX_MIN_FREE_SPACE: u64 = 512 * 1024 * 1024;
+/// An upload nothing was written to for this long is given up on.
+const INBOX_UPLOAD_IDLE_SECS: i64 = 10 * 60;
+/// Restarts wait at least this long so the acknowledgement reaches the
+/// requester before the process goes down.
+const RESTART_MIN_DELAY_SECS: u64 = 1;
+const RESTART_MAX_DELAY_SECS: u64 = 60 * 60;
+/// `ENOENT`, and `ERROR_FILE_NOT_FOUND` on Windows.
+const NOT_FOUND_OS_ERROR: i32 = 2;
+
+/// Requests answered by a task holding one of the loop's inbound slots.
+fn answered_off_loop(request: &PeerReq) -> bool {
+	matches!(
+		request,
+		PeerReq::GetMediaFrame { .. }
+			| PeerReq::GetThumbnail { .. }
+			| PeerReq::ContactSheet { .. }
+			| PeerReq::ListDir { .. }
+			| PeerReq::ReadFile { .. }
+			| PeerReq::CreateUser { .. }
+	)
+}
+
+/// The path a filesystem request is about.
+fn request_path(request: &PeerReq) -> Option<PathBuf> {
+	match request {
+		PeerReq::ListDir { path }
+		| PeerReq::StatFile { path }
+		| PeerReq::ReadFile { path, .. }
+		| PeerReq::WriteFile { path, .. } => Some(path.to_path_buf()),
+		PeerReq::GetThumbnail { path, .. } | PeerReq::ContactSheet { path, .. } => {
+			Some(PathBuf::from(path))
+		}
+		_ => None,
+	}
+}
+
+/// The answer for a path the patterns hide from the peer asking, worded
+/// like the one for a path that isn't there, so the two can't be told
+/// apart.
+fn hidden_path(what: &str) -> PeerRes {
+	PeerRes::Error(format!(
+		"Failed to access {what}: {}",
+		std::io::Error::from_raw_os_error(NOT_FOUND_OS_ERROR)
+	))
 }
 
-async fn read_file(path: &Path, offset: u64, length: Option<u64>) -> Result<FileChunk> {
-	let file = fs::File::open(path).await?;
+struct InboxUpload {
+	peer: PeerId,
+	name: String,
+	size: u64,
+	path: PathBuf,
+	last_write: DateTime<Utc>,
+}
+
+pub(crate) fn inbox_dir() -> Option<PathBuf> {
+	let path = config::startup().inbox_dir.clone()?;
+	if let Err(err) = std::fs::create_dir_all(&path) {
+		tracing::warn!("failed to create inbox {}: {err}", path.display());
+		return None;
+	}
+	std::fs::canonicalize(&path).ok()
+}
+
+pub(crate) fn available_space_for(path: &Path) -> Option<u64> {
+	let disks = Disks::new_with_refreshed_list();
+	disks
+		.iter()
+		.filter(|disk| path.starts_with(disk.mount_point()))
+		.max_by_key(|disk| disk.mount_point().as_os_str().len())
+		.map(|disk| disk.available_space())
+}
+
+fn sanitize_inbox_name(name: &str) -> Option<String> {
+	let name = Path::new(name).file_name()?.to_str()?.trim();
+	if name.is_empty() || name.starts_with('.') {
+		return None;
+	}
+	Some(name.to_string())
+}
+
+/// Creates a new empty file in `dir`, appending " (n)" to the stem until the
+/// name is free.
+pub(crate) fn reserve_inbox_file(dir: &Path, name: &str) -> std::io::Result<PathBuf> {
+	let original = Path::new(name);
+	let stem = original
+		.file_stem()
+		.and_then(|stem| stem.to_str())
+		.unwrap_or(name);
+	let extension = original.extension().and_then(|ext| ext.to_str());
+	for attempt in 0..1000 {
+		let candidate = match (attempt, extension) {
+			(0, _) => name.to_string(),
+			(n, Some(ext)) => format!("{stem} ({n}).{ext}"),
+			(n, None) => format!("{stem} ({n})"),
+		};
+		let path = dir.join(candidate);
+		match std::fs::OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.open(&path)
+		{
+			Ok(_) => return Ok(path),
+			Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+			Err(err) => return Err(err),
+		}
+	}
+	Err(std::io::Error::new(
+		std::io::ErrorKind::AlreadyExists,
+		"no free inbox file name",
+	))
+}
+
+/// Drops the uploads nothing was written to for `INBOX_UPLOAD_IDLE_SECS`
+/// and deletes their partial files.
+fn discard_idle_uploads(
+	uploads: &mut HashMap<String, InboxUpload>,
+	now: DateTime<Utc>,
+) -> Vec<InboxUpload> {
+	let idle = uploads
+		.iter()
+		.filter(|(_, upload)| (now - upload.last_write).num_seconds() >= INBOX_UPLOAD_IDLE_SECS)
+		.map(|(name, _)| name.clone())
+		.collect::<Vec<_>>();
+	let mut discarded = Vec::with_capacity(idle.len());
+	for name in idle {
+		let Some(upload) = uploads.remove(&name) else {
+			continue;
+		};
+		if let Err(err) = std::fs::remove_file(&upload.path)
+			&& err.kind() != std::io::ErrorKind::NotFound
+		{
+			tracing::warn!("failed to remove {}: {err}", upload.path.display());
+		}
+		discarded.push(upload);
+	}
+	discarded
+}
+
+/// Whether the file system marks the file hidden. Only Windows has such
+/// a mark.
+fn hidden_attribute(meta: &std::fs::Metadata) -> bool {
+	#[cfg(windows)]
+	{
+		use std::os::windows::fs::MetadataExt;
+		const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+		meta.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0
+	}
+	#[cfg(not(windows))]
+	{
+		let _ = meta;
+		false
+	}
+}
+
+/// Reads up to `length` bytes of `file` from `offset`. A `sparse` read
+/// stops short of the next hole, and answers a read inside one with the
+/// hole's length instead of its zeros.
+async fn read_chunk(
+	file: fs::File,
+	offset: u64,
+	length: Option<u64>,
+	sparse: bool,
+) -> Result<FileChunk> {
 	let metadata = file.metadata().await?;
 	if metadata.is_dir() {
 		bail!("path is a directory")
@@ -251,13 +863,28 @@ async fn read_file(path: &Path, offset: u64, length: Option<u64>) -> Result<File
 			offset,
 			data: Vec::new(),
 			eof: true,
+			hole: 0,
 		});
 	}
 	let remaining = file_len - offset;
-	let to_read = match length {
+	let mut to_read = match length {
 		Some(l) => l.min(remaining),
 		None => remaining,
 	};
+	if sparse {
+		match sparse::extent_at(&file, offset, file_len) {
+			Extent::Hole(hole) => {
+				let hole = hole.min(to_read);
+				return Ok(FileChunk {
+					offset,
+					data: Vec::new(),
+					eof: offset + hole >= file_len,
+					hole,
+				});
+			}
+			Extent::Data(data) => to_read = to_read.min(data),
+		}
+	}
 	let mut reader = tokio::io::BufReader::new(file);
 	reader.seek(std::io::SeekFrom::Start(offset)).await?;
 	let mut buffer = vec![0u8; to_read as usize];
@@ -268,21 +895,21 @@ async fn read_file(path: &Path, offset: u64, length: Option<u64>) -> Result<File
 		offset,
 		data: buffer,
 		eof,
+		hole: 0,
 	})
 }
 
-async fn write_file(path: &Path, offset: u64, data: &[u8]) -> Result<FileWriteAck> {
-	// Open (or create) file with write capability
-	let mut file = match fs::OpenOptions::new()
-		.create(true)
-		.write(true)
-		.read(true)
-		.open(path)
-		.await
-	{
-		Ok(f) => f,
-		Err(e) => return Err(anyhow!("open failed: {}", e)),
-	};
+async fn read_file(
+	path: &Path,
+	offset: u64,
+	length: Option<u64>,
+	sparse: bool,
+) -> Result<FileChunk> {
+	read_chunk(fs::File::open(path).await?, offset, length, sparse).await
+}
+
+/// Writes `data` into `file` at `offset`, growing it as needed.
+async fn write_chunk(mut file: fs::File, offset: u64, data: &[u8]) -> Result<FileWriteAck> {
 	// Ensure we don't overflow length when extending
 	let current_len = match file.metadata().await {
 		Ok(m) => m.len(),
@@ -308,24 +935,72 @@ async fn write_file(path: &Path, offset: u64, data: &[u8]) -> Result<FileWriteAc
 	})
 }
 
-async fn generate_thumbnail(path: &Path, max_width: u32, max_height: u32) -> Result<Thumbnail> {
-	use image::ImageReader;
+async fn write_file(path: &Path, offset: u64, data: &[u8]) -> Result<FileWriteAck> {
+	// Open (or create) file with write capability
+	let file = match fs::OpenOptions::new()
+		.create(true)
+		.write(true)
+		.read(true)
+		.open(path)
+		.await
+	{
+		Ok(f) => f,
+		Err(e) => return Err(anyhow!("open failed: {}", e)),
+	};
+	write_chunk(file, offset, data).await
+}
+
+/// The image a thumbnail is made of: a local path, or a file a peer's
+/// request opened before it was checked.
+#[derive(Clone, Copy)]
+enum ThumbnailSource<'a> {
+	Path(&'a Path),
+	Opened(&'a OpenedPath),
+}
+
+impl ThumbnailSource<'_> {
+	/// Path the thumbnail is kept under.
+	fn path(&self) -> &Path {
+		match self {
+			Self::Path(path) => path,
+			Self::Opened(opened) => opened.canonical(),
+		}
+	}
+
+	async fn metadata(&self) -> std::io::Result<std::fs::Metadata> {
+		match self {
+			Self::Path(path) => fs::metadata(path).await,
+			Self::Opened(opened) => opened.metadata(),
+		}
+	}
+
+	async fn read(&self) -> std::io::Result<Vec<u8>> {
+		match self {
+			Self::Path(path) => fs::read(path).await,
+			Self::Opened(opened) => opened.read_all().await,
+		}
+	}
+}
+
+async fn generate_thumbnail(
+	image: ThumbnailSource<'_>,
+	max_width: u32,
+	max_height: u32,
+	limits: &Arc<DecodeLimits>,
+) -> Result<Thumbnail> {
 	use std::io::Cursor;
 
+	if !cfg!(feature = "thumbnails") {
+		bail!("this build of puppynet makes no thumbnails");
+	}
 	// Read the file data
-	let data = fs::read(path).await?;
+	let data = image.read().await?;
 
 	// Use spawn_blocking for CPU-intensive image processing
+	let limits = Arc::clone(limits);
 	let result = tokio::task::spawn_blocking(move || -> Result<Thumbnail> {
-		// Load the image
-		let reader = ImageReader::new(Cursor::new(&data))
-			.with_guessed_format()
-			.map_err(|e| anyhow!("failed to guess image format: {}", e))?;
-
-		let format = reader.format();
-		let img = reader
-			.decode()
-			.map_err(|e| anyhow!("failed to decode image: {}", e))?;
+		// Load the image, refusing it from the header when it is too large
+		let img = limits.decode(&data)?;
 
 		// Calculate thumbnail dimensions maintaining aspect ratio
 		let (orig_width, orig_height) = (img.width(), img.height());
@@ -365,41 +1040,362 @@ async fn generate_thumbnail(path: &Path, max_width: u32, max_height: u32) -> Res
 	Ok(result)
 }
 
-const LIVE_SEARCH_BATCH_SIZE: usize = 25;
-const LIVE_SEARCH_PROGRESS_INTERVAL: usize = 250;
-const LIVE_SEARCH_VISITED_CAP: usize = 50_000;
-const LIVE_SEARCH_MATCH_CAP: usize = 5_000;
+/// Serves a thumbnail from `cache`, then from `disk`, unless the file
+/// changed since it was made. Generating one waits for a permit of
+/// `limits`.
+async fn cached_thumbnail(
+	cache: &Mutex<ThumbnailCache>,
+	disk: &ThumbnailDisk,
+	limits: &Arc<DecodeLimits>,
+	path: &Path,
+	max_width: u32,
+	max_height: u32,
+) -> Result<Thumbnail> {
+	let image = ThumbnailSource::Path(path);
+	cached_thumbnail_of(cache, disk, limits, image, max_width, max_height).await
+}
 
-fn search_name_matches(path: &Path, query: &Option<String>) -> bool {
-	let Some(query) = query
-		.as_ref()
-		.map(|value| value.trim())
-		.filter(|value| !value.is_empty())
-	else {
-		return true;
+async fn cached_thumbnail_of(
+	cache: &Mutex<ThumbnailCache>,
+	disk: &ThumbnailDisk,
+	limits: &Arc<DecodeLimits>,
+	image: ThumbnailSource<'_>,
+	max_width: u32,
+	max_height: u32,
+) -> Result<Thumbnail> {
+	let path = image.path();
+	let source = SourceStamp::of(&image.metadata().await?);
+	let cached = cache
+		.lock()
+		.map_err(|_| anyhow!("thumbnail cache lock poisoned"))?
+		.get(path, max_width, max_height, source);
+	if let Some(thumbnail) = cached {
+		return Ok(thumbnail);
+	}
+	if let Some(thumbnail) = disk.get(path, max_width, max_height, source).await {
+		cache
+			.lock()
+			.map_err(|_| anyhow!("thumbnail cache lock poisoned"))?
+			.insert(path, max_width, max_height, source, thumbnail.clone());
+		return Ok(thumbnail);
+	}
+	let _permit = limits.acquire().await?;
+	// Another request for the same thumbnail may have made it meanwhile.
+	let cached = cache
+		.lock()
+		.map_err(|_| anyhow!("thumbnail cache lock poisoned"))?
+		.get(path, max_width, max_height, source);
+	if let Some(thumbnail) = cached {
+		return Ok(thumbnail);
+	}
+	let thumbnail = generate_thumbnail(image, max_width, max_height, limits).await?;
+	cache
+		.lock()
+		.map_err(|_| anyhow!("thumbnail cache lock poisoned"))?
+		.insert(path, max_width, max_height, source, thumbnail.clone());
+	if let Err(err) = disk
+		.insert(path, max_width, max_height, source, &thumbnail)
+		.await
+	{
+		tracing::warn!("failed to keep thumbnail of {}: {err}", path.display());
+	}
+	Ok(thumbnail)
+}
+
+/// Makes the browser-sized thumbnail of `path` on `disk` ahead of time.
+/// Only takes a permit that is free, so foreground requests never queue
+/// behind it, and leaves the in-memory cache to what is being browsed.
+async fn pregenerate_thumbnail(
+	disk: &ThumbnailDisk,
+	limits: &Arc<DecodeLimits>,
+	path: &Path,
+) -> PregenOutcome {
+	let size = PREGEN_THUMBNAIL_SIZE;
+	let source = match fs::metadata(path).await {
+		Ok(metadata) => SourceStamp::of(&metadata),
+		Err(_) => return PregenOutcome::Failed,
 	};
-	let query = query.to_ascii_lowercase();
-	path.file_name()
-		.and_then(|name| name.to_str())
-		.map(|name| name.to_ascii_lowercase().contains(&query))
-		.unwrap_or(false)
+	if disk.get(path, size, size, source).await.is_some() {
+		return PregenOutcome::Skipped;
+	}
+	let Ok(_permit) = limits.try_acquire() else {
+		return PregenOutcome::Yielded;
+	};
+	let thumbnail = match generate_thumbnail(ThumbnailSource::Path(path), size, size, limits).await
+	{
+		Ok(thumbnail) => thumbnail,
+		Err(err) => {
+			tracing::debug!(
+				"failed to pregenerate thumbnail of {}: {err}",
+				path.display()
+			);
+			return PregenOutcome::Failed;
+		}
+	};
+	match disk.insert(path, size, size, source, &thumbnail).await {
+		Ok(()) => PregenOutcome::Generated,
+		Err(err) => {
+			tracing::warn!("failed to keep thumbnail of {}: {err}", path.display());
+			PregenOutcome::Failed
+		}
+	}
 }
 
-fn search_mime_matches(mime_type: &Option<String>, selected: &[String]) -> bool {
-	if selected.is_empty() {
-		return true;
+/// A peer's thumbnail request that passed the access checks, run off the
+/// event loop.
+struct ThumbnailJob {
+	cache: Arc<Mutex<ThumbnailCache>>,
+	disk: Arc<ThumbnailDisk>,
+	limits: Arc<DecodeLimits>,
+	image: OpenedPath,
+	max_width: u32,
+	max_height: u32,
+	/// The peer announced [`FEATURE_IMAGE_TOO_LARGE`].
+	typed_too_large: bool,
+}
+
+impl ThumbnailJob {
+	async fn run(self) -> PeerRes {
+		match cached_thumbnail_of(
+			&self.cache,
+			&self.disk,
+			&self.limits,
+			ThumbnailSource::Opened(&self.image),
+			self.max_width,
+			self.max_height,
+		)
+		.await
+		{
+			Ok(thumb) => PeerRes::Thumbnail(thumb),
+			Err(err) if self.typed_too_large && err.is::<ImageTooLarge>() => {
+				tracing::info!(
+					"refused thumbnail of {}: {err}",
+					self.image.canonical().display()
+				);
+				PeerRes::ImageTooLarge(err.downcast().unwrap())
+			}
+			Err(err) => {
+				tracing::warn!(
+					"failed to generate thumbnail for {}: {err}",
+					self.image.canonical().display()
+				);
+				PeerRes::Error(format!("Failed to generate thumbnail: {err}"))
+			}
+		}
 	}
-	mime_type
-		.as_ref()
-		.map(|mime| selected.iter().any(|selected| selected == mime))
-		.unwrap_or(false)
 }
 
-fn live_search_row(path: &Path, metadata: &std::fs::Metadata) -> LiveSearchRow {
-	let name = path
-		.file_name()
-		.and_then(|name| name.to_str())
-		.unwrap_or_default()
+/// Fills in mime types the extension guess got wrong or missed, from the
+/// local scan index and, failing that, the files' leading bytes.
+async fn refine_mimes(db: &Db, node_id: Option<NodeID>, dir: &Path, entries: &mut [DirEntry]) {
+	let indexed = match (node_id, db.lock()) {
+		(Some(node_id), Ok(conn)) => {
+			let files = mime_hint::indexable_files(dir, entries);
+			load_indexed_mimes(&conn, &node_id, &files).unwrap_or_else(|err| {
+				tracing::warn!("failed to look up indexed mime types: {err}");
+				HashMap::new()
+			})
+		}
+		_ => HashMap::new(),
+	};
+	mime_hint::refine(dir, entries, &indexed).await;
+}
+
+/// A peer's `ListDir` that passed the access checks, run off the event
+/// loop.
+struct ListJob {
+	dir: OpenedPath,
+	db: Arc<Db>,
+	node_id: Option<NodeID>,
+	hidden: HiddenNames,
+	/// The free space hint and access for peers taking a [`DirListing`];
+	/// `None` for peers that only take bare entries.
+	listing: Option<(Option<u64>, ListingAccess)>,
+}
+
+impl ListJob {
+	async fn run(self) -> PeerRes {
+		let canonical = self.dir.canonical();
+		let mut entries = match App::collect_dir_entries(self.dir.through_handle()).await {
+			Ok(entries) => entries,
+			Err(err) => {
+				tracing::warn!("failed to list {}: {err}", canonical.display());
+				return PeerRes::Error("Internal error".into());
+			}
+		};
+		refine_mimes(&self.db, self.node_id, canonical, &mut entries).await;
+		entries.retain(|entry| {
+			!self
+				.hidden
+				.hides(&canonical.join(&entry.name), entry.is_dir)
+		});
+		match self.listing {
+			Some((free_hint, mut access)) => {
+				access.keep_listed(&entries);
+				PeerRes::DirListing(DirListing {
+					entries,
+					free_hint,
+					access: Some(access),
+				})
+			}
+			None => PeerRes::DirEntries(entries),
+		}
+	}
+}
+
+/// A peer's `ReadFile` that passed the access checks, run off the event
+/// loop.
+struct ReadJob {
+	file: fs::File,
+	offset: u64,
+	length: Option<u64>,
+	sparse: bool,
+}
+
+impl ReadJob {
+	async fn run(self) -> PeerRes {
+		match read_chunk(self.file, self.offset, self.length, self.sparse).await {
+			Ok(chunk) => PeerRes::FileChunk(chunk),
+			Err(err) => {
+				tracing::warn!("failed to read chunk at {}: {err}", self.offset);
+				PeerRes::Error("Internal error".into())
+			}
+		}
+	}
+}
+
+/// A peer's contact sheet request that passed the access checks, with the
+/// images it may see picked, run off the event loop.
+struct ContactSheetJob {
+	cache: Arc<Mutex<ThumbnailCache>>,
+	disk: Arc<ThumbnailDisk>,
+	limits: Arc<DecodeLimits>,
+	images: Vec<PathBuf>,
+	options: ContactSheetOptions,
+	deadline: std::time::Instant,
+}
+
+impl ContactSheetJob {
+	async fn run(self) -> PeerRes {
+		let (cache, disk, limits) = (&self.cache, &self.disk, &self.limits);
+		let cell = self.options.cell_size;
+		let result = contact_sheet::build(
+			self.images,
+			self.options,
+			self.deadline,
+			|path| async move { cached_thumbnail(cache, disk, limits, &path, cell, cell).await },
+		)
+		.await;
+		match result {
+			Ok(sheet) => PeerRes::ContactSheet(sheet),
+			Err(err) => {
+				tracing::warn!("failed to make contact sheet: {err}");
+				PeerRes::Error(format!("Failed to make contact sheet: {err}"))
+			}
+		}
+	}
+}
+
+/// Fails the requests `targets` says were sent to the peer that
+/// disconnected, and returns their ids.
+fn fail_disconnected<K: Copy + Eq + std::hash::Hash>(
+	targets: &mut HashMap<K, PeerId>,
+	pending: &mut HashMap<K, PendingRequest>,
+	disconnected: Disconnected,
+) -> Vec<K> {
+	let ids = targets
+		.iter()
+		.filter(|(_, target)| **target == disconnected.peer)
+		.map(|(id, _)| *id)
+		.collect::<Vec<_>>();
+	for id in &ids {
+		targets.remove(id);
+		if let Some(request) = pending.remove(id) {
+			request.fail(disconnected.into());
+		}
+	}
+	ids
+}
+
+/// Paths whose derived data (thumbnails) a scan made stale.
+fn stale_scan_paths(result: &Result<scan::ScanResult, String>) -> Vec<PathBuf> {
+	result
+		.as_ref()
+		.map(|result| {
+			result
+				.changes
+				.iter()
+				.filter(|change| change.kind != scan::ScanChangeKind::Added)
+				.map(|change| change.path.clone())
+				.collect()
+		})
+		.unwrap_or_default()
+}
+
+/// Queues the images a finished scan of `path` indexed for thumbnails.
+fn queue_scanned_images(
+	queue: &ThumbnailQueue,
+	conn: &SqliteConnection,
+	node_id: &NodeID,
+	path: &str,
+) {
+	if !queue.is_enabled() {
+		return;
+	}
+	match image_files_under(conn, node_id, path, PREGEN_MIN_FILE_SIZE) {
+		Ok(images) => {
+			let queued = queue.enqueue(images);
+			if queued > 0 {
+				tracing::debug!("queued {queued} thumbnail(s) to make for {path}");
+			}
+		}
+		Err(err) => tracing::warn!("failed to list scanned images under {path}: {err}"),
+	}
+}
+
+/// Hands the rows a scan committed since its last report to the event
+/// loop, which announces them to peers.
+fn send_index_change(internal_tx: &UnboundedSender<InternalCommand>, change: IndexChangeCounts) {
+	if !change.is_empty() {
+		let _ = internal_tx.send(InternalCommand::IndexChanged { change });
+	}
+}
+
+const LIVE_SEARCH_BATCH_SIZE: usize = 25;
+const LIVE_SEARCH_PROGRESS_INTERVAL: usize = 250;
+const LIVE_SEARCH_VISITED_CAP: usize = 50_000;
+const LIVE_SEARCH_MATCH_CAP: usize = 5_000;
+
+fn search_name_matches(path: &Path, query: &Option<String>) -> bool {
+	let Some(query) = query
+		.as_ref()
+		.map(|value| value.trim())
+		.filter(|value| !value.is_empty())
+	else {
+		return true;
+	};
+	let query = query.to_ascii_lowercase();
+	path.file_name()
+		.and_then(|name| name.to_str())
+		.map(|name| name.to_ascii_lowercase().contains(&query))
+		.unwrap_or(false)
+}
+
+fn search_mime_matches(mime_type: &Option<String>, selected: &[String]) -> bool {
+	if selected.is_empty() {
+		return true;
+	}
+	mime_type
+		.as_ref()
+		.map(|mime| selected.iter().any(|selected| selected == mime))
+		.unwrap_or(false)
+}
+
+fn live_search_row(path: &Path, metadata: &std::fs::Metadata) -> LiveSearchRow {
+	let name = path
+		.file_name()
+		.and_then(|name| name.to_str())
+		.unwrap_or_default()
 		.to_string();
 	let mime_type = mime_guess::from_path(path)
 		.first_raw()
@@ -418,7 +1414,7 @@ fn live_search_row(path: &Path, metadata: &std::fs::Metadata) -> LiveSearchRow {
 	}
 }
 
-fn live_search_roots<F>(roots: Vec<PathBuf>, args: LiveSearchArgs, mut emit: F)
+fn live_search_roots<F>(roots: Vec<PathBuf>, args: LiveSearchArgs, hidden: HiddenNames, mut emit: F)
 where
 	F: FnMut(SearchEvent),
 {
@@ -432,7 +1428,14 @@ where
 			truncated = true;
 			break;
 		}
-		for entry in WalkDir::new(&root).follow_links(false).into_iter() {
+		// Folders hidden by a pattern are pruned rather than walked.
+		let walk = WalkDir::new(&root)
+			.follow_links(false)
+			.into_iter()
+			.filter_entry(|entry| {
+				entry.depth() == 0 || !hidden.hides(entry.path(), entry.file_type().is_dir())
+			});
+		for entry in walk {
 			if visited >= LIVE_SEARCH_VISITED_CAP || matched >= LIVE_SEARCH_MATCH_CAP {
 				truncated = true;
 				break;
@@ -447,7 +1450,10 @@ where
 			let Ok(path) = std::fs::canonicalize(entry.path()) else {
 				continue;
 			};
-			if !path.starts_with(&root) || !search_name_matches(&path, &args.name_query) {
+			if !path.starts_with(&root)
+				|| hidden.hides(&path, false)
+				|| !search_name_matches(&path, &args.name_query)
+			{
 				continue;
 			}
 			let Ok(metadata) = std::fs::metadata(&path) else {
@@ -482,16 +1488,100 @@ where
 	});
 }
 
-trait ResponseDecoder: Sized + Send + 'static {
-	fn decode(response: PeerRes) -> anyhow::Result<Self>;
-}
-
 #[derive(Debug, Clone)]
 pub(crate) struct AccessGrantAck {
 	pub(crate) username: String,
 	pub(crate) permissions: Vec<PermissionGrant>,
 }
 
+#[derive(Debug, Clone)]
+pub(crate) struct InboxSlot {
+	pub(crate) path: String,
+}
+
+/// Which hashes a peer holds, one bit each, as `HaveHashes` and
+/// `HaveBlocks` answer.
+#[derive(Debug, Clone)]
+pub(crate) struct HaveBitmap(pub(crate) Vec<u8>);
+
+#[derive(Debug, Clone)]
+pub(crate) struct RestartAck {
+	pub(crate) delay_secs: u64,
+}
+
+impl ResponseDecoder for RestartAck {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		match response {
+			PeerRes::RestartScheduled { delay_secs } => Ok(Self { delay_secs }),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
+		}
+	}
+}
+
+impl ResponseDecoder for DialBackOutcome {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		match response {
+			PeerRes::DialBack {
+				reachable: true,
+				latency_ms,
+				..
+			} => Ok(Self::Reachable {
+				latency_ms: latency_ms.unwrap_or_default(),
+			}),
+			PeerRes::DialBack { error, .. } => Ok(Self::Unreachable {
+				error: error.unwrap_or_else(|| String::from("connection failed")),
+			}),
+			PeerRes::DialBackDeclined { reason } => Ok(Self::Declined { reason }),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
+		}
+	}
+}
+
+impl ResponseDecoder for IndexDeltaAck {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		match response {
+			PeerRes::IndexDeltaAck(ack) => Ok(ack),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
+		}
+	}
+}
+
+impl ResponseDecoder for PeerHealth {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		match response {
+			PeerRes::Health(health) => Ok(health),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
+		}
+	}
+}
+
+impl ResponseDecoder for InboxSlot {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		match response {
+			PeerRes::InboxOpened { path } => Ok(Self { path }),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
+		}
+	}
+}
+
+impl ResponseDecoder for HaveBitmap {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		match response {
+			PeerRes::Have { bitmap } => Ok(Self(bitmap)),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
+		}
+	}
+}
+
+impl ResponseDecoder for FileWriteAck {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		match response {
+			PeerRes::WriteAck(ack) => Ok(ack),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
+		}
+	}
+}
+
 impl ResponseDecoder for PeerInfo {
 	fn decode(response: PeerRes) -> anyhow::Result<Self> {
 		match response {
@@ -501,10 +1591,24 @@ impl ResponseDecoder for PeerInfo {
 	}
 }
 
-impl ResponseDecoder for Vec<DirEntry> {
+impl ResponseDecoder for DirListing {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		match response {
+			PeerRes::DirListing(listing) => Ok(listing),
+			PeerRes::DirEntries(entries) => Ok(DirListing {
+				entries,
+				free_hint: None,
+				access: None,
+			}),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
+		}
+	}
+}
+
+impl ResponseDecoder for DirEntry {
 	fn decode(response: PeerRes) -> anyhow::Result<Self> {
 		match response {
-			PeerRes::DirEntries(entries) => Ok(entries),
+			PeerRes::FileStat(entry) => Ok(entry),
 			other => Err(anyhow!("unexpected response: {:?}", other)),
 		}
 	}
@@ -528,6 +1632,69 @@ impl ResponseDecoder for Vec<DiskInfo> {
 	}
 }
 
+impl ResponseDecoder for Vec<DiskSample> {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		match response {
+			PeerRes::DiskHistory(samples) => Ok(samples),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
+		}
+	}
+}
+
+impl ResponseDecoder for Vec<FileSearchResult> {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		match response {
+			PeerRes::SearchResults(results) => Ok(results),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
+		}
+	}
+}
+
+impl ResponseDecoder for Vec<ShareSummary> {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		match response {
+			PeerRes::ShareSummaries(summaries) => Ok(summaries),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
+		}
+	}
+}
+
+impl ResponseDecoder for ContactSheetResult {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		match response {
+			PeerRes::ContactSheet(result) => Ok(result),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
+		}
+	}
+}
+
+impl ResponseDecoder for ScanResults {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		match response {
+			PeerRes::ScanResults(results) => Ok(results),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
+		}
+	}
+}
+
+impl ResponseDecoder for BrowseRoots {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		match response {
+			PeerRes::Roots(roots) => Ok(roots),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
+		}
+	}
+}
+
+impl ResponseDecoder for Vec<WellKnownFolder> {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		match response {
+			PeerRes::WellKnownFolders(folders) => Ok(folders),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
+		}
+	}
+}
+
 impl ResponseDecoder for Vec<FileEntry> {
 	fn decode(response: PeerRes) -> anyhow::Result<Self> {
 		match response {
@@ -660,11 +1827,11 @@ impl ResponseDecoder for u64 {
 	}
 }
 
-impl ResponseDecoder for Vec<u8> {
+impl ResponseDecoder for ShellOutput {
 	fn decode(response: PeerRes) -> anyhow::Result<Self> {
 		match response {
-			PeerRes::ShellOutput { data, .. } => Ok(data),
-			PeerRes::ShellExited { .. } => Ok(Vec::new()),
+			PeerRes::ShellOutput { data, .. } => Ok(ShellOutput::Output(data)),
+			PeerRes::ShellExited { .. } => Ok(ShellOutput::Exited),
 			other => Err(anyhow!("unexpected response: {:?}", other)),
 		}
 	}
@@ -697,14 +1864,11 @@ impl<T: ResponseDecoder> Pending<T> {
 
 struct PendingRemoteScanStart {
 	scan_id: u64,
-	channels: Arc<Mutex<HashMap<u64, mpsc::Sender<ScanEvent>>>>,
+	channels: Arc<RemoteOps<ScanEvent>>,
 }
 
 impl PendingRemoteScanStart {
-	fn new(
-		scan_id: u64,
-		channels: Arc<Mutex<HashMap<u64, mpsc::Sender<ScanEvent>>>>,
-	) -> PendingRequest {
+	fn new(scan_id: u64, channels: Arc<RemoteOps<ScanEvent>>) -> PendingRequest {
 		Box::new(Self { scan_id, channels })
 	}
 }
@@ -721,7 +1885,23 @@ impl PendingResponseHandler for PendingScanEventAck {
 	fn complete(self: Box<Self>, _response: PeerRes) {}
 
 	fn fail(self: Box<Self>, error: anyhow::Error) {
-		log::warn!("scan event delivery failed: {}", error);
+		tracing::warn!("scan event delivery failed: {}", error);
+	}
+}
+
+struct PendingIndexAnnounceAck;
+
+impl PendingIndexAnnounceAck {
+	fn new() -> PendingRequest {
+		Box::new(Self)
+	}
+}
+
+impl PendingResponseHandler for PendingIndexAnnounceAck {
+	fn complete(self: Box<Self>, _response: PeerRes) {}
+
+	fn fail(self: Box<Self>, error: anyhow::Error) {
+		tracing::debug!("index announcement delivery failed: {}", error);
 	}
 }
 
@@ -752,7 +1932,7 @@ impl PendingResponseHandler for PendingRemoteSearchStart {
 				}
 			}
 			other => {
-				log::warn!("unexpected response for remote search start {:?}", other);
+				tracing::warn!("unexpected response for remote search start {:?}", other);
 			}
 		}
 	}
@@ -778,20 +1958,17 @@ impl PendingResponseHandler for PendingSearchEventAck {
 	fn complete(self: Box<Self>, _response: PeerRes) {}
 
 	fn fail(self: Box<Self>, error: anyhow::Error) {
-		log::warn!("search event delivery failed: {}", error);
+		tracing::warn!("search event delivery failed: {}", error);
 	}
 }
 
 struct PendingRemoteUpdateStart {
 	update_id: u64,
-	channels: Arc<Mutex<HashMap<u64, mpsc::Sender<UpdateProgress>>>>,
+	channels: Arc<RemoteOps<UpdateProgress>>,
 }
 
 impl PendingRemoteUpdateStart {
-	fn new(
-		update_id: u64,
-		channels: Arc<Mutex<HashMap<u64, mpsc::Sender<UpdateProgress>>>>,
-	) -> PendingRequest {
+	fn new(update_id: u64, channels: Arc<RemoteOps<UpdateProgress>>) -> PendingRequest {
 		Box::new(Self {
 			update_id,
 			channels,
@@ -804,21 +1981,22 @@ impl PendingResponseHandler for PendingRemoteUpdateStart {
 		match response {
 			PeerRes::UpdateStarted(Ok(())) => {}
 			PeerRes::UpdateStarted(Err(err)) => {
-				if let Some(tx) = self.channels.lock().unwrap().remove(&self.update_id) {
-					let _ = tx.send(UpdateProgress::Failed { error: err });
+				if let Some(tx) = self.channels.remove(self.update_id) {
+					let _ = tx.send(UpdateProgress::Failed {
+						error: err,
+						kind: None,
+					});
 				}
 			}
 			other => {
-				log::warn!("unexpected response for remote update start {:?}", other);
+				tracing::warn!("unexpected response for remote update start {:?}", other);
 			}
 		}
 	}
 
 	fn fail(self: Box<Self>, error: anyhow::Error) {
-		if let Some(tx) = self.channels.lock().unwrap().remove(&self.update_id) {
-			let _ = tx.send(UpdateProgress::Failed {
-				error: error.to_string(),
-			});
+		if let Some(tx) = self.channels.remove(self.update_id) {
+			let _ = tx.send(UpdateProgress::failed(&error));
 		}
 	}
 }
@@ -835,39 +2013,512 @@ impl PendingResponseHandler for PendingUpdateEventAck {
 	fn complete(self: Box<Self>, _response: PeerRes) {}
 
 	fn fail(self: Box<Self>, error: anyhow::Error) {
-		log::warn!("update event delivery failed: {}", error);
+		tracing::warn!("update event delivery failed: {}", error);
 	}
 }
 
-impl PendingResponseHandler for PendingRemoteScanStart {
+struct PendingHello {
+	peer: PeerId,
+	sent_at: DateTime<Utc>,
+	clock: Arc<dyn Clock>,
+	internal_tx: tokio::sync::mpsc::UnboundedSender<InternalCommand>,
+}
+
+impl PendingHello {
+	fn new(
+		peer: PeerId,
+		clock: Arc<dyn Clock>,
+		internal_tx: tokio::sync::mpsc::UnboundedSender<InternalCommand>,
+	) -> PendingRequest {
+		Box::new(Self {
+			peer,
+			sent_at: clock.now(),
+			clock,
+			internal_tx,
+		})
+	}
+
+	fn record(self, capabilities: PeerCapabilities) {
+		let _ = self.internal_tx.send(InternalCommand::RecordCapabilities {
+			peer: self.peer,
+			capabilities,
+		});
+	}
+}
+
+impl PendingResponseHandler for PendingHello {
 	fn complete(self: Box<Self>, response: PeerRes) {
-		match response {
-			PeerRes::ScanStarted(Ok(())) => {}
-			PeerRes::ScanStarted(Err(err)) => {
-				if let Some(tx) = self.channels.lock().unwrap().remove(&self.scan_id) {
-					let _ = tx.send(ScanEvent::Finished(Err(err)));
+		let capabilities = match response {
+			PeerRes::Hello {
+				protocol_version,
+				features,
+				time,
+			} => {
+				if let Some(remote) = time {
+					let _ = self.internal_tx.send(InternalCommand::RecordClockSample {
+						peer: self.peer,
+						sample: ClockSample::new(self.sent_at, self.clock.now(), remote),
+					});
+				}
+				PeerCapabilities {
+					protocol_version,
+					features,
+					legacy: false,
 				}
 			}
 			other => {
-				log::warn!("unexpected response for remote scan start {:?}", other);
+				tracing::info!(
+					"peer {} answered Hello with {:?}; assuming legacy protocol",
+					self.peer,
+					other
+				);
+				PeerCapabilities::legacy()
 			}
-		}
+		};
+		self.record(capabilities);
 	}
 
 	fn fail(self: Box<Self>, error: anyhow::Error) {
-		if let Some(tx) = self.channels.lock().unwrap().remove(&self.scan_id) {
-			let _ = tx.send(ScanEvent::Finished(Err(error.to_string())));
-		}
+		tracing::info!(
+			"Hello to {} failed: {}; assuming legacy protocol",
+			self.peer,
+			error
+		);
+		self.record(PeerCapabilities::legacy());
 	}
 }
 
-impl<T: ResponseDecoder> PendingResponseHandler for Pending<T> {
+/// Keeps the MAC address and network of the interface a newly connected
+/// peer reached this node through, so it can be woken later.
+struct PendingWakeTarget {
+	peer: PeerId,
+	remote_ip: Option<std::net::IpAddr>,
+	db: Arc<Db>,
+	clock: Arc<dyn Clock>,
+}
+
+impl PendingWakeTarget {
+	fn new(
+		peer: PeerId,
+		remote_ip: Option<std::net::IpAddr>,
+		db: Arc<Db>,
+		clock: Arc<dyn Clock>,
+	) -> PendingRequest {
+		Box::new(Self {
+			peer,
+			remote_ip,
+			db,
+			clock,
+		})
+	}
+}
+
+impl PendingResponseHandler for PendingWakeTarget {
+	fn complete(self: Box<Self>, response: PeerRes) {
+		let PeerRes::Interfaces(interfaces) = response else {
+			return;
+		};
+		let Some((mac, subnet)) = wake::learn_target(&interfaces, self.remote_ip) else {
+			return;
+		};
+		let saved = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))
+			.and_then(|conn| {
+				record_wake_target(&conn, &self.peer, &mac, subnet.as_deref(), self.clock.now())
+			});
+		if let Err(err) = saved {
+			tracing::warn!("failed to record wake target of {}: {err}", self.peer);
+		}
+	}
+
+	fn fail(self: Box<Self>, error: anyhow::Error) {
+		tracing::debug!("could not list interfaces of {}: {error}", self.peer);
+	}
+}
+
+/// Waits for the answer to `PairRequest` or `PairResponse` and reports a
+/// failed delivery, or the name the peer gave itself.
+struct PendingPairing {
+	peer: PeerId,
+	internal_tx: tokio::sync::mpsc::UnboundedSender<InternalCommand>,
+}
+
+impl PendingPairing {
+	fn new(
+		peer: PeerId,
+		internal_tx: tokio::sync::mpsc::UnboundedSender<InternalCommand>,
+	) -> PendingRequest {
+		Box::new(Self { peer, internal_tx })
+	}
+
+	fn update(self, peer_name: Option<String>, failure: Option<String>) {
+		let _ = self.internal_tx.send(InternalCommand::PairingUpdate {
+			peer: self.peer,
+			peer_name,
+			failure,
+		});
+	}
+}
+
+impl PendingResponseHandler for PendingPairing {
+	fn complete(self: Box<Self>, response: PeerRes) {
+		match response {
+			PeerRes::PairPending { node_name } => self.update(Some(node_name), None),
+			PeerRes::PairResponseAck => {}
+			PeerRes::Error(err) => self.update(None, Some(err)),
+			other => self.update(None, Some(format!("unexpected response: {other:?}"))),
+		}
+	}
+
+	fn fail(self: Box<Self>, error: anyhow::Error) {
+		tracing::warn!("pairing message to {} failed: {}", self.peer, error);
+		self.update(None, Some(error.to_string()));
+	}
+}
+
+/// Waits for the answer to a `Pair` sent with the secret of a pairing
+/// string.
+struct PendingPairWith {
+	peer: PeerId,
+	tx: oneshot::Sender<Result<PairedWith>>,
+	internal_tx: tokio::sync::mpsc::UnboundedSender<InternalCommand>,
+}
+
+impl PendingPairWith {
+	fn new(
+		peer: PeerId,
+		tx: oneshot::Sender<Result<PairedWith>>,
+		internal_tx: tokio::sync::mpsc::UnboundedSender<InternalCommand>,
+	) -> PendingRequest {
+		Box::new(Self {
+			peer,
+			tx,
+			internal_tx,
+		})
+	}
+
+	fn failed(self, error: anyhow::Error) {
+		let _ = self.internal_tx.send(InternalCommand::PairingUpdate {
+			peer: self.peer,
+			peer_name: None,
+			failure: Some(error.to_string()),
+		});
+		let _ = self.tx.send(Err(error));
+	}
+}
+
+impl PendingResponseHandler for PendingPairWith {
+	fn complete(self: Box<Self>, response: PeerRes) {
+		match response {
+			PeerRes::Paired { node_name, granted } => {
+				let _ = self.internal_tx.send(InternalCommand::PairedWith {
+					peer: self.peer,
+					node_name: node_name.clone(),
+				});
+				let _ = self.tx.send(Ok(PairedWith {
+					peer_id: self.peer,
+					node_name: peer_node_name(&node_name),
+					granted,
+				}));
+			}
+			PeerRes::PairRejected { reason } => self.failed(anyhow::Error::new(reason)),
+			PeerRes::Error(err) => self.failed(anyhow!(err)),
+			other => self.failed(anyhow!("unexpected response: {other:?}")),
+		}
+	}
+
+	fn fail(self: Box<Self>, error: anyhow::Error) {
+		tracing::warn!("pairing with {} failed: {}", self.peer, error);
+		self.failed(error);
+	}
+}
+
+struct PendingPermissionsChangedAck {
+	peer: PeerId,
+	permissions: Vec<Permission>,
+	db: Arc<Db>,
+	clock: Arc<dyn Clock>,
+}
+
+impl PendingPermissionsChangedAck {
+	fn new(
+		peer: PeerId,
+		permissions: Vec<Permission>,
+		db: Arc<Db>,
+		clock: Arc<dyn Clock>,
+	) -> PendingRequest {
+		Box::new(Self {
+			peer,
+			permissions,
+			db,
+			clock,
+		})
+	}
+}
+
+impl PendingResponseHandler for PendingPermissionsChangedAck {
+	fn complete(self: Box<Self>, response: PeerRes) {
+		if !matches!(response, PeerRes::PermissionsChangedAck) {
+			tracing::warn!(
+				"unexpected response for permission change notification {:?}",
+				response
+			);
+		}
+	}
+
+	fn fail(self: Box<Self>, error: anyhow::Error) {
+		tracing::warn!(
+			"permission change delivery to {} failed: {}; queueing for next connection",
+			self.peer,
+			error
+		);
+		let now = self.clock.now().timestamp();
+		queue_permissions_changed(&self.db, &self.peer, &self.permissions, now);
+	}
+}
+
+/// A `DeleteProposal` sent to one holder; its answer becomes the holder's
+/// status, and a proposal that didn't arrive is sent again on the next
+/// connection.
+struct PendingDeleteProposal {
+	id: String,
+	peer: PeerId,
+	db: Arc<Db>,
+	clock: Arc<dyn Clock>,
+}
+
+impl PendingDeleteProposal {
+	fn new(id: String, peer: PeerId, db: Arc<Db>, clock: Arc<dyn Clock>) -> PendingRequest {
+		Box::new(Self {
+			id,
+			peer,
+			db,
+			clock,
+		})
+	}
+
+	fn undelivered(&self, error: &str) {
+		let now = self.clock.now();
+		let result = match self.db.lock() {
+			Ok(conn) => set_delete_holder_status(
+				&conn,
+				&self.id,
+				&self.peer,
+				DeletionStatus::Undelivered,
+				Some(error),
+				now,
+			),
+			Err(err) => Err(anyhow!("db lock poisoned: {err}")),
+		};
+		if let Err(err) = result {
+			tracing::error!("failed to record deletion proposal {}: {err}", self.id);
+		}
+	}
+}
+
+impl PendingResponseHandler for PendingDeleteProposal {
+	fn complete(self: Box<Self>, response: PeerRes) {
+		let outcome = match response {
+			PeerRes::DeleteProposalReceived { outcome } => outcome,
+			PeerRes::Error(err) => return self.undelivered(&err),
+			other => return self.undelivered(&format!("unexpected response: {other:?}")),
+		};
+		let now = self.clock.now();
+		let result = match self.db.lock() {
+			Ok(conn) => match outcome {
+				Some(outcome) => record_delete_outcome(&conn, &self.id, &self.peer, &outcome, now),
+				None => set_delete_holder_status(
+					&conn,
+					&self.id,
+					&self.peer,
+					DeletionStatus::Pending,
+					None,
+					now,
+				),
+			},
+			Err(err) => Err(anyhow!("db lock poisoned: {err}")),
+		};
+		if let Err(err) = result {
+			tracing::error!("failed to record deletion proposal {}: {err}", self.id);
+		}
+	}
+
+	fn fail(self: Box<Self>, error: anyhow::Error) {
+		tracing::warn!(
+			"deletion proposal {} to {} failed: {}; sending again on next connection",
+			self.id,
+			self.peer,
+			error
+		);
+		self.undelivered(&error.to_string());
+	}
+}
+
+/// A `DeleteOutcome` sent back to the proposer; until it is acknowledged it
+/// is sent again on the next connection.
+struct PendingDeleteOutcomeAck {
+	id: String,
+	peer: PeerId,
+	db: Arc<Db>,
+}
+
+impl PendingDeleteOutcomeAck {
+	fn new(id: String, peer: PeerId, db: Arc<Db>) -> PendingRequest {
+		Box::new(Self { id, peer, db })
+	}
+}
+
+impl PendingResponseHandler for PendingDeleteOutcomeAck {
+	fn complete(self: Box<Self>, response: PeerRes) {
+		if !matches!(response, PeerRes::DeleteOutcomeAck) {
+			tracing::warn!(
+				"unexpected response for deletion outcome {}: {:?}",
+				self.id,
+				response
+			);
+			return;
+		}
+		let result = match self.db.lock() {
+			Ok(conn) => mark_delete_outcome_reported(&conn, &self.peer, &self.id),
+			Err(err) => Err(anyhow!("db lock poisoned: {err}")),
+		};
+		if let Err(err) = result {
+			tracing::error!(
+				"failed to mark deletion outcome {} reported: {err}",
+				self.id
+			);
+		}
+	}
+
+	fn fail(self: Box<Self>, error: anyhow::Error) {
+		tracing::warn!(
+			"deletion outcome {} to {} failed: {}; sending again on next connection",
+			self.id,
+			self.peer,
+			error
+		);
+	}
+}
+
+/// A `ListPermissions` fetch; the answer goes into the grant cache, and to
+/// `tx` unless the fetch runs in the background.
+struct PendingRemoteGrants {
+	peer: PeerId,
+	tx: Option<oneshot::Sender<Result<RemoteGrants>>>,
+	clock: Arc<dyn Clock>,
+	internal_tx: tokio::sync::mpsc::UnboundedSender<InternalCommand>,
+}
+
+impl PendingRemoteGrants {
+	fn new(
+		peer: PeerId,
+		tx: Option<oneshot::Sender<Result<RemoteGrants>>>,
+		clock: Arc<dyn Clock>,
+		internal_tx: tokio::sync::mpsc::UnboundedSender<InternalCommand>,
+	) -> PendingRequest {
+		Box::new(Self {
+			peer,
+			tx,
+			clock,
+			internal_tx,
+		})
+	}
+}
+
+impl PendingResponseHandler for PendingRemoteGrants {
 	fn complete(self: Box<Self>, response: PeerRes) {
 		let result = match response {
 			PeerRes::Error(err) => Err(anyhow!(err)),
-			other => T::decode(other),
+			other => Vec::<Permission>::decode(other),
+		};
+		let _ = self.internal_tx.send(InternalCommand::RemoteGrantsFetched {
+			peer: self.peer,
+			permissions: result.as_ref().ok().cloned(),
+		});
+		if let Some(tx) = self.tx {
+			let _ = tx.send(result.map(|permissions| RemoteGrants {
+				permissions,
+				fetched_at: self.clock.now(),
+				revalidating: false,
+			}));
+		}
+	}
+
+	fn fail(self: Box<Self>, error: anyhow::Error) {
+		tracing::debug!("fetching grants from {} failed: {error}", self.peer);
+		let _ = self.internal_tx.send(InternalCommand::RemoteGrantsFetched {
+			peer: self.peer,
+			permissions: None,
+		});
+		if let Some(tx) = self.tx {
+			let _ = tx.send(Err(error));
+		}
+	}
+}
+
+/// An `IndexPull`, handed back to the event loop to apply.
+struct PendingIndexPull {
+	peer: PeerId,
+	internal_tx: tokio::sync::mpsc::UnboundedSender<InternalCommand>,
+}
+
+impl PendingIndexPull {
+	fn new(
+		peer: PeerId,
+		internal_tx: tokio::sync::mpsc::UnboundedSender<InternalCommand>,
+	) -> PendingRequest {
+		Box::new(Self { peer, internal_tx })
+	}
+}
+
+impl PendingResponseHandler for PendingIndexPull {
+	fn complete(self: Box<Self>, response: PeerRes) {
+		let delta = match response {
+			PeerRes::IndexDelta(delta) => Ok(delta),
+			PeerRes::Error(err) => Err(anyhow!(err)),
+			other => Err(anyhow!("unexpected response: {:?}", other)),
 		};
-		let _ = self.tx.send(result);
+		let _ = self.internal_tx.send(InternalCommand::IndexPulled {
+			peer: self.peer,
+			delta,
+		});
+	}
+
+	fn fail(self: Box<Self>, error: anyhow::Error) {
+		let _ = self.internal_tx.send(InternalCommand::IndexPulled {
+			peer: self.peer,
+			delta: Err(error),
+		});
+	}
+}
+
+impl PendingResponseHandler for PendingRemoteScanStart {
+	fn complete(self: Box<Self>, response: PeerRes) {
+		match response {
+			PeerRes::ScanStarted(Ok(())) => {}
+			PeerRes::ScanStarted(Err(err)) => {
+				if let Some(tx) = self.channels.remove(self.scan_id) {
+					let _ = tx.send(ScanEvent::Finished(Err(err)));
+				}
+			}
+			other => {
+				tracing::warn!("unexpected response for remote scan start {:?}", other);
+			}
+		}
+	}
+
+	fn fail(self: Box<Self>, error: anyhow::Error) {
+		if let Some(tx) = self.channels.remove(self.scan_id) {
+			let _ = tx.send(ScanEvent::Finished(Err(error.to_string())));
+		}
+	}
+}
+
+impl<T: ResponseDecoder> PendingResponseHandler for Pending<T> {
+	fn complete(self: Box<Self>, response: PeerRes) {
+		let _ = self.tx.send(decode_response(response));
 	}
 
 	fn fail(self: Box<Self>, error: anyhow::Error) {
@@ -895,430 +2546,2821 @@ enum InternalCommand {
 		update_id: u64,
 		event: UpdateProgress,
 	},
+	RecordCapabilities {
+		peer: PeerId,
+		capabilities: PeerCapabilities,
+	},
+	RecordClockSample {
+		peer: PeerId,
+		sample: ClockSample,
+	},
+	SampleClocks,
+	/// Sends a request over connections worth keeping before they go idle.
+	KeepConnectionsAlive,
+	RedialImportantPeers,
+	InvalidateDerived {
+		paths: Vec<PathBuf>,
+	},
+	RestartFailed {
+		error: String,
+	},
+	LowDiskSpace {
+		message: String,
+	},
+	NatStatus {
+		status: NatStatus,
+		external_addrs: Vec<Multiaddr>,
+	},
+	SweepTemporaryGrants,
+	ExpireDiscoveredAddresses,
+	/// Time to write discovered address changes, if the flush is due.
+	FlushDiscoveredPeers,
+	/// A flush that failed, to write again with the next one.
+	DiscoveredPeersNotSaved {
+		batch: DiscoveryBatch,
+	},
+	/// Time to fail remote scans and updates whose peer went quiet.
+	ExpireRemoteOps,
+	/// Time to give up on inbox uploads whose sender went quiet.
+	ExpireInboxUploads,
+	/// Time to fail pairings nobody answered.
+	ExpirePairings,
+	/// Time to roll protocol counters into rates and check them.
+	RollupProtocolStats,
+	/// Time to check whether shared folders on mounts are present.
+	CheckShares,
+	ShareAvailability {
+		checked: Vec<(PathBuf, ShareAvailability)>,
+	},
+	PairingUpdate {
+		peer: PeerId,
+		peer_name: Option<String>,
+		failure: Option<String>,
+	},
+	/// The node behind a pairing string accepted its secret.
+	PairedWith {
+		peer: PeerId,
+		node_name: String,
+	},
+	/// A dial-back to a peer's address took longer than [`DIAL_BACK_TIMEOUT`].
+	DialBackTimedOut {
+		connection_id: ConnectionId,
+	},
+	ReachabilityTested {
+		results: Vec<AddressReachability>,
+		tx: oneshot::Sender<Vec<AddressReachability>>,
+	},
+	/// A grant fetch finished; `None` when it failed.
+	RemoteGrantsFetched {
+		peer: PeerId,
+		permissions: Option<Vec<Permission>>,
+	},
+	/// A scan committed rows to this node's index.
+	IndexChanged {
+		change: IndexChangeCounts,
+	},
+	/// Time to announce index changes held back by `ANNOUNCE_INTERVAL`.
+	AnnounceIndex,
+	/// The delay before pulling `peer`'s index changes passed.
+	PullIndex {
+		peer: PeerId,
+	},
+	IndexPulled {
+		peer: PeerId,
+		delta: Result<IndexDelta>,
+	},
+	/// A new user's password was hashed off the loop; saved here.
+	UserHashed {
+		user: Result<User>,
+		reply: NewUserReply,
+	},
+}
+
+/// Who to tell once a new user was saved or refused.
+enum NewUserReply {
+	Command(oneshot::Sender<Result<()>>),
+	Peer(Box<PeerUserReply>),
+}
+
+/// A peer's `CreateUser` waiting for its password to be hashed.
+struct PeerUserReply {
+	peer: PeerId,
+	channel: ResponseChannel<PeerRes>,
+	inbound: InboundTrace,
+	started: Option<std::time::Instant>,
 }
 
 type PendingRequest = Box<dyn PendingResponseHandler>;
 
+/// A `DialBack` being dialed; answered once the connection is up or failed.
+struct PendingDialBack {
+	channel: ResponseChannel<PeerRes>,
+	inbound: InboundTrace,
+	started: std::time::Instant,
+}
+
+struct OutboundTrace {
+	corr: u64,
+	peer: PeerId,
+	request: &'static str,
+	at: DateTime<Utc>,
+	started: std::time::Instant,
+}
+
+/// An inbound request being handled; recorded once answered.
+struct InboundTrace {
+	log: Arc<RequestLog>,
+	corr: u64,
+	peer: PeerId,
+	request: &'static str,
+	/// Set while the request log records.
+	received: Option<std::time::Instant>,
+	counters: Arc<PeerCounters>,
+	kind: usize,
+	/// Shared folder the request is in, to count the answer against.
+	folder: Option<String>,
+}
+
+impl InboundTrace {
+	fn finish(self, handler_started: Option<std::time::Instant>, response: &PeerRes) {
+		self.finish_as(handler_started, response, Answer::of(response));
+	}
+
+	/// Like [`Self::finish`], counting the response as `answer`.
+	fn finish_as(
+		self,
+		handler_started: Option<std::time::Instant>,
+		response: &PeerRes,
+		answer: Answer,
+	) {
+		let bytes = wire_len(response);
+		self.counters.answered(self.kind, bytes, answer);
+		if let Some(folder) = &self.folder {
+			self.counters.accessed(folder, bytes);
+		}
+		let (Some(received), Some(started)) = (self.received, handler_started) else {
+			return;
+		};
+		self.log.record(RequestTrace {
+			corr: self.corr,
+			direction: RequestDirection::Inbound,
+			peer: self.peer.to_string(),
+			request: self.request.to_string(),
+			at: Utc::now() - chrono::Duration::from_std(received.elapsed()).unwrap_or_default(),
+			queue_ms: Some(millis(started.duration_since(received))),
+			handler_ms: Some(millis(started.elapsed())),
+			round_trip_ms: None,
+			error: match response {
+				PeerRes::Error(err) => Some(err.clone()),
+				PeerRes::Unavailable { share } => Some(
+					ShareUnavailable {
+						share: share.clone(),
+					}
+					.to_string(),
+				),
+				PeerRes::AccessDenied(explanation) => Some(explanation.to_string()),
+				PeerRes::RateLimited { .. } => Some(String::from("rate limited")),
+				PeerRes::ImageTooLarge(too_large) => Some(too_large.to_string()),
+				PeerRes::UserRefused(refused) => Some(refused.to_string()),
+				_ => None,
+			},
+		});
+	}
+}
+
+/// What [`App::new`] shares with [`crate::puppynet::PuppyNet`] and the
+/// background tasks around the event loop.
+pub struct AppDeps {
+	pub db: Arc<Db>,
+	pub remote_scans: Arc<RemoteOps<ScanEvent>>,
+	pub remote_searches: Arc<Mutex<HashMap<u64, mpsc::Sender<SearchEvent>>>>,
+	pub remote_updates: Arc<RemoteOps<UpdateProgress>>,
+	pub store: Arc<ContentStore>,
+	pub clock: Arc<dyn Clock>,
+	pub request_log: Arc<RequestLog>,
+	pub thumbnail_queue: Arc<ThumbnailQueue>,
+	pub maintenance: Arc<MaintenanceGate>,
+	pub protocol_rates: Arc<Mutex<Vec<ProtocolRate>>>,
+	pub activity: ActivityLog,
+}
+
 pub struct App {
 	state: State,
+	/// Kept out of `state` so snapshots stay cheap; read through queries.
+	discovered: DiscoveredPeers,
+	/// What of `discovered` still has to reach the database.
+	discovery_writes: DiscoveryWrites,
+	users: Vec<User>,
 	swarm: Swarm<AgentBehaviour>,
 	rx: UnboundedReceiver<Command>,
 	internal_rx: tokio::sync::mpsc::UnboundedReceiver<InternalCommand>,
 	internal_tx: tokio::sync::mpsc::UnboundedSender<InternalCommand>,
+	/// Which of `rx`, `internal_rx` and the swarm [`Self::run`] takes next.
+	fairness: Fairness,
+	/// Bounds the inbound requests answered off the loop at once.
+	inbound_jobs: Arc<Semaphore>,
 	pending_requests: HashMap<OutboundRequestId, PendingRequest>,
+	request_log: Arc<RequestLog>,
+	/// Outbound requests being timed while the request log records.
+	outbound_traces: HashMap<OutboundRequestId, OutboundTrace>,
 	system: System,
-	db: Arc<Mutex<SqliteConnection>>,
-	remote_scans: Arc<Mutex<HashMap<u64, mpsc::Sender<ScanEvent>>>>,
+	db: Arc<Db>,
+	remote_scans: Arc<RemoteOps<ScanEvent>>,
 	remote_searches: Arc<Mutex<HashMap<u64, mpsc::Sender<SearchEvent>>>>,
-	remote_updates: Arc<Mutex<HashMap<u64, mpsc::Sender<UpdateProgress>>>>,
-	shell_sessions: HashMap<u64, ShellSession>,
+	remote_updates: Arc<RemoteOps<UpdateProgress>>,
+	shell_sessions: HashMap<(PeerId, u64), ShellSession>,
+	inbox_uploads: HashMap<String, InboxUpload>,
+	/// Disks as last read for the free space hints of listings.
+	disk_cache: DiskCache,
+	/// Set when received inbox files should be deduplicated into the store.
+	inbox_store: Option<Arc<ContentStore>>,
+	thumbnails: Arc<Mutex<ThumbnailCache>>,
+	/// Thumbnails kept across restarts, also filled ahead of browsing.
+	thumbnail_disk: Arc<ThumbnailDisk>,
+	/// Bounds how many thumbnails are generated at once.
+	decode_limits: Arc<DecodeLimits>,
+	/// Images of finished scans waiting for a thumbnail.
+	thumbnail_queue: Arc<ThumbnailQueue>,
+	/// Refuses changes during maintenance and tracks the scans it waits
+	/// for; shared with [`crate::PuppyNet`].
+	maintenance: Arc<MaintenanceGate>,
+	/// Shared folders going offline or coming back.
+	share_changes: broadcast::Sender<ShareChange>,
+	clock: Arc<dyn Clock>,
+	started_at: std::time::Instant,
+	last_restart_error: Option<String>,
+	dialer: Dialer,
+	nat_mapper: Option<NatMapper>,
+	/// Addresses the router forwards to us, registered with the swarm.
+	nat_external_addrs: Vec<Multiaddr>,
+	listen_ports: NatPorts,
+	/// Secrets of the pairing strings shown so far.
+	pairing_secrets: PairingSecrets,
+	/// Dial-backs peers asked for, by the connection dialing them.
+	dial_backs: HashMap<ConnectionId, PendingDialBack>,
+	dial_back_limiter: DialBackLimiter,
+	/// What other peers allow this node, mirrored in `remote_grants`.
+	grant_cache: GrantCache,
+	/// Limits of scans, read by running scans as they change.
+	scan_limits: Arc<NodeScanLimits>,
+	/// Peers whose grants are being refetched in the background.
+	grant_refetches: HashSet<PeerId>,
+	/// Filesystem requests to peers with cached grants, with the path and
+	/// access they need, to notice grants that changed under the cache.
+	grant_checks: HashMap<OutboundRequestId, (PeerId, PathBuf, u8)>,
+	/// Changes to this node's index waiting to be announced.
+	index_announcer: AnnounceCoalescer,
+	/// Pulls of announced changes to peers' indexes.
+	index_pulls: IndexPulls,
+	/// Request rates by peer, and which peers the limiter refuses.
+	protocol: ProtocolMonitor,
+	/// Rates of the last rollup, read by [`crate::PuppyNet`].
+	protocol_rates: Arc<Mutex<Vec<ProtocolRate>>>,
+	/// Each peer's day of reads and writes against its usual one.
+	access: AccessMonitor,
+	/// Counters of sent requests, to count their responses.
+	outbound_counters: HashMap<OutboundRequestId, (Arc<PeerCounters>, usize)>,
+	/// The peer each unanswered request was sent to, to fail them when its
+	/// last connection closes.
+	outbound_peers: HashMap<OutboundRequestId, PeerId>,
+	keeper: ConnectionKeeper,
+	/// Peers coming and going, for the activity timeline.
+	activity: ActivityLog,
 }
 
 impl App {
 	fn can_access(&self, peer: PeerId, path: &Path, access: u8) -> bool {
-		self.state.has_fs_access(peer, path, access)
+		self.state
+			.has_fs_access_at(peer, path, access, self.clock.now())
 	}
 
-	async fn start_shell_session(&mut self, peer: PeerId, session_id: u64) -> anyhow::Result<()> {
-		if let Some(mut existing) = self.shell_sessions.remove(&session_id) {
-			let _ = existing.child.kill().await;
-		}
-		let shell_path = env::var("SHELL").unwrap_or_else(|_| String::from("/bin/sh"));
-		let mut child = TokioCommand::new(shell_path)
-			.env("TERM", "xterm-256color")
-			.env("PUPPYNET_REMOTE", "1")
-			.stdin(std::process::Stdio::piped())
-			.stdout(std::process::Stdio::piped())
-			.stderr(std::process::Stdio::piped())
-			.spawn()
-			.map_err(|e| anyhow!("failed to spawn shell: {e}"))?;
-		let stdin = child
-			.stdin
-			.take()
-			.ok_or_else(|| anyhow!("failed to take shell stdin"))?;
-		let stdout = child
-			.stdout
-			.take()
-			.ok_or_else(|| anyhow!("failed to take shell stdout"))?;
-		self.shell_sessions.insert(
-			session_id,
-			ShellSession {
-				child,
-				stdin,
-				stdout,
-			},
-		);
-		log::info!("[{}] Started remote shell session {}", peer, session_id);
-		Ok(())
+	/// The shared folder `request` is in, as the access counters name it.
+	fn accessed_folder(&self, request: &PeerReq) -> Option<String> {
+		let path = request_path(request)?;
+		self.state
+			.shared_folders
+			.iter()
+			.map(|folder| folder.path())
+			.filter(|folder| path.starts_with(folder))
+			.max_by_key(|folder| folder.as_os_str().len())
+			.map(|folder| folder.display().to_string())
 	}
 
-	fn record_peer_address(&mut self, peer: &PeerId, addr: &Multiaddr) {
-		let peer_id = *peer;
-		let multiaddr = addr.clone();
-		self.state.peer_discovered(peer_id, multiaddr.clone());
-		if let Ok(mut conn) = self.db.lock() {
-			let _ = save_discovered_peer(&mut *conn, &DiscoveredPeer { peer_id, multiaddr });
+	/// Whether the deny and allow patterns hide `path` from `peer`.
+	fn hidden_from(&self, peer: PeerId, path: &Path, is_dir: bool) -> bool {
+		let hidden = self
+			.state
+			.hidden_names(peer, self.clock.now())
+			.hides(path, is_dir);
+		if hidden {
+			tracing::info!("[{}] {} is hidden by a pattern", peer, path.display());
 		}
+		hidden
 	}
 
-	fn known_peer_addresses(&self, peer: &PeerId) -> Vec<Multiaddr> {
-		self.state
-			.discovered_peers
-			.iter()
-			.filter(|entry| entry.peer_id == *peer)
-			.map(|entry| entry.multiaddr.clone())
-			.collect()
+	/// The name a new user gets, when the stored [`PasswordPolicy`] allows
+	/// `username` and `password` and the name is free. Fails with a
+	/// [`CreateUserError`] otherwise. The password is hashed off the loop
+	/// with [`hash_new_user`] and the result handed to
+	/// [`Self::save_new_user`].
+	fn check_new_user(&self, username: &str, password: &str) -> Result<String> {
+		let policy = {
+			let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+			PasswordPolicy::load(&conn)
+		};
+		Ok(check_new_user(
+			&policy,
+			self.users.iter().map(|user| user.name.as_str()),
+			username,
+			password,
+		)?)
 	}
 
-	async fn process_shell_input(
-		&mut self,
-		session_id: u64,
-		data: &[u8],
-		peer: Option<PeerId>,
-	) -> anyhow::Result<ShellInputResult> {
-		let Some(session) = self.shell_sessions.get_mut(&session_id) else {
-			if let Some(peer_id) = peer {
-				log::warn!(
-					"peer {} requested missing shell session {}",
-					peer_id,
-					session_id
-				);
+	/// Saves a user hashed off the loop, unless one by the same name was
+	/// added while it hashed.
+	fn save_new_user(&mut self, user: User) -> Result<User> {
+		if self.users.iter().any(|existing| existing.name == user.name) {
+			bail!(CreateUserError::AlreadyExists {
+				username: user.name
+			});
+		}
+		let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+		save_user(&conn, &user)?;
+		drop(conn);
+		self.users.push(user.clone());
+		Ok(user)
+	}
+
+	/// The answer to `peer`'s `CreateUser`; refusals are typed for peers
+	/// that understand them, a plain error for older ones.
+	fn user_created(&self, peer: PeerId, user: Result<User>) -> PeerRes {
+		let err = match user {
+			Ok(user) => {
+				return PeerRes::UserCreated {
+					username: user.name,
+				};
 			}
-			return Err(anyhow!("shell session not found"));
+			Err(err) => err,
 		};
-
-		if !data.is_empty() {
-			if let Err(err) = session.stdin.write_all(data).await {
-				self.shell_sessions.remove(&session_id);
-				if let Some(peer_id) = peer {
-					log::warn!(
-						"[{}] shell stdin failed for session {}: {err}",
-						peer_id,
-						session_id
-					);
-				}
-				return Err(anyhow!("shell stdin failed: {err}"));
+		match err.downcast::<CreateUserError>() {
+			Ok(refused)
+				if self
+					.state
+					.peer_capabilities(&peer)
+					.is_some_and(|capabilities| capabilities.supports(FEATURE_USER_REFUSED)) =>
+			{
+				PeerRes::UserRefused(refused)
 			}
-			let _ = session.stdin.flush().await;
-		}
-
-		let mut out = Vec::new();
-		let mut buf = [0u8; 8192];
-		loop {
-			match timeout(Duration::from_millis(40), session.stdout.read(&mut buf)).await {
-				Ok(Ok(0)) => {
-					self.shell_sessions.remove(&session_id);
-					return Ok(ShellInputResult::Exited);
-				}
-				Ok(Ok(n)) => {
-					out.extend_from_slice(&buf[..n]);
-					if out.len() >= 64 * 1024 {
-						break;
-					}
-				}
-				Ok(Err(err)) => {
-					self.shell_sessions.remove(&session_id);
-					if let Some(peer_id) = peer {
-						log::warn!(
-							"[{}] shell stdout failed for session {}: {err}",
-							peer_id,
-							session_id
-						);
-					}
-					return Err(anyhow!("shell stdout failed: {err}"));
-				}
-				Err(_) => break,
+			Ok(refused) => PeerRes::Error(format!("Failed to create user: {refused}")),
+			Err(err) => {
+				tracing::error!("failed to create user: {err}");
+				PeerRes::Error(format!("Failed to create user: {err}"))
 			}
 		}
+	}
 
-		Ok(ShellInputResult::Output(out))
+	fn access_denied_message(&self, peer: PeerId, path: &Path) -> String {
+		self.state
+			.access_denied_message(&peer, path, self.clock.now())
 	}
 
-	pub fn new(
-		mut state: State,
-		db: Arc<Mutex<SqliteConnection>>,
-		remote_scans: Arc<Mutex<HashMap<u64, mpsc::Sender<ScanEvent>>>>,
-		remote_searches: Arc<Mutex<HashMap<u64, mpsc::Sender<SearchEvent>>>>,
-		remote_updates: Arc<Mutex<HashMap<u64, mpsc::Sender<UpdateProgress>>>>,
-	) -> (Self, tokio::sync::mpsc::UnboundedSender<Command>) {
-		let key_path = env::var("KEYPAIR").unwrap_or_else(|_| String::from("peer_keypair.bin"));
-		let key_path = Path::new(&key_path);
-		if !key_path.exists() {
-			log::warn!(
-				"keypair file {} does not exist, generating new keypair",
-				key_path.display()
-			);
+	/// The refusal of a request for `access` to `path`: explained to peers
+	/// that understand it, a plain error for older ones.
+	fn access_denied(&self, peer: PeerId, path: &Path, access: u8) -> PeerRes {
+		let explains = self
+			.state
+			.peer_capabilities(&peer)
+			.is_some_and(|capabilities| capabilities.supports(FEATURE_ACCESS_EXPLAIN));
+		if explains {
+			PeerRes::AccessDenied(self.state.explain_access_at(
+				peer,
+				path,
+				access,
+				self.clock.now(),
+			))
+		} else {
+			PeerRes::Error(self.access_denied_message(peer, path))
 		}
-		let id_keys = load_or_generate_keypair(key_path).unwrap_or_else(|err| {
-			log::warn!(
-				"failed to load persisted keypair at {}: {err}; using ephemeral keypair",
-				key_path.display()
-			);
-			libp2p::identity::Keypair::generate_ed25519()
-		});
-		let peer_id = PeerId::from(id_keys.public());
+	}
 
-		let mut swarm = build_swarm(id_keys, peer_id).unwrap();
-		let stored_permissions = {
-			let conn = db.lock().unwrap();
-			match load_peer_permissions(&conn, &peer_id) {
-				Ok(perms) => perms,
+	/// The refusal of `req` when `peer`'s trust level doesn't reach it,
+	/// explained like [`Self::access_denied`].
+	fn trust_refusal(&self, peer: PeerId, req: &PeerReq) -> Option<PeerRes> {
+		let refusal = self.state.trust_refusal(&peer, req)?;
+		tracing::info!("[{}] {} refused: {}", peer, req.name(), refusal);
+		let explains = self
+			.state
+			.peer_capabilities(&peer)
+			.is_some_and(|capabilities| capabilities.supports(FEATURE_ACCESS_EXPLAIN));
+		Some(if explains {
+			PeerRes::AccessDenied(refusal)
+		} else {
+			PeerRes::Error(refusal.to_string())
+		})
+	}
+
+	/// The answer for `peer` when `path` is in a shared folder that is
+	/// offline and `peer` could otherwise reach it with `access`.
+	fn offline_share(&self, peer: PeerId, path: &Path, access: u8) -> Option<PeerRes> {
+		let share = self.state.unavailable_share(path)?;
+		self.can_access(peer, path, access)
+			.then(|| PeerRes::Unavailable {
+				share: share.to_string_lossy().into_owned(),
+			})
+	}
+
+	/// Subscribes to shared folders going offline or coming back, so work
+	/// that waited for a mount can resume.
+	pub(crate) fn subscribe_share_changes(&self) -> broadcast::Receiver<ShareChange> {
+		self.share_changes.subscribe()
+	}
+
+	/// The node's scan limits, for changing them under running scans.
+	pub(crate) fn scan_limits(&self) -> Arc<NodeScanLimits> {
+		Arc::clone(&self.scan_limits)
+	}
+
+	/// Writes the discovered address changes once the flush interval has
+	/// passed, on the writer and off the swarm loop. A failed write is put
+	/// back for the next flush.
+	fn flush_discovered_peers(&mut self) {
+		let Some(batch) = self.discovery_writes.take_due(self.clock.now()) else {
+			return;
+		};
+		let db = self.db.clone();
+		let internal_tx = self.internal_tx.clone();
+		tokio::task::spawn_blocking(move || {
+			let written =
+				db.write(|conn| write_discovered_peers(conn, &batch.saved, &batch.removed));
+			match written {
+				Ok(()) => tracing::debug!("saved {} discovered address change(s)", batch.len()),
 				Err(err) => {
-					log::error!("failed to load peer permissions: {err}");
-					Vec::new()
+					tracing::error!("failed to save discovered peers: {err}");
+					let _ = internal_tx.send(InternalCommand::DiscoveredPeersNotSaved { batch });
 				}
 			}
+		});
+	}
+
+	/// Checks the shared folders on a blocking thread; a hung network
+	/// mount must not stall the event loop.
+	/// Rolls the protocol counters into rates, notifies about peers crossing
+	/// the alert rate or reading and writing far more than usual and, now
+	/// and then, adds the counts to the history.
+	fn rollup_protocol_stats(&mut self) {
+		let (limits, access_limits) = {
+			let conn = self.db.lock().unwrap();
+			(
+				protocol_stats::load_limits(&conn),
+				AccessAlertLimits::load(&conn),
+			)
 		};
-		let stored_peers = {
+		for alert in self.protocol.tick(limits) {
+			let message = alert.message();
+			tracing::warn!("{message}");
+			self.state.push_notification(alert.peer(), message);
+		}
+		*self.protocol_rates.lock().unwrap() = self.protocol.rates();
+		let day = self.clock.now().date_naive();
+		self.state.access_alerts.retain(|_, alert| alert.day == day);
+		for (peer, sample) in self.protocol.take_access() {
+			let Some(alert) = self.access.record(day, peer, sample, &access_limits) else {
+				continue;
+			};
+			let message = alert.message();
+			tracing::warn!("{message}");
+			self.state.push_notification(peer, message);
+			self.state.access_alerts.insert(peer, alert);
+		}
+		if !self.protocol.history_due() {
+			return;
+		}
+		let rows = self.protocol.take_unflushed();
+		let volumes = self.access.take_unflushed();
+		if rows.is_empty() && volumes.is_empty() {
+			return;
+		}
+		let db = self.db.clone();
+		tokio::task::spawn_blocking(move || {
 			let conn = db.lock().unwrap();
-			load_peers(&conn).unwrap_or_else(|err| {
-				log::error!("failed to load peers: {err}");
-				Vec::new()
-			})
+			if let Err(err) = record_protocol_stats(&conn, day, &rows) {
+				tracing::error!("failed to record protocol stats: {err}");
+			}
+			if let Err(err) = record_access_volume(&conn, day, &volumes) {
+				tracing::error!("failed to record access volume: {err}");
+			}
+		});
+	}
+
+	fn check_shares(&self) {
+		let shares = self
+			.state
+			.shared_folders
+			.iter()
+			.map(|folder| folder.path().to_path_buf())
+			.collect::<Vec<_>>();
+		let internal_tx = self.internal_tx.clone();
+		tokio::task::spawn_blocking(move || {
+			let table = MountTable::load();
+			let checked = shares
+				.into_iter()
+				.map(|share| {
+					let availability = table.availability(&share);
+					(share, availability)
+				})
+				.collect();
+			let _ = internal_tx.send(InternalCommand::ShareAvailability { checked });
+		});
+	}
+
+	fn record_share_availability(&mut self, checked: Vec<(PathBuf, ShareAvailability)>) {
+		let me = self.state.me;
+		for change in self.state.record_share_availability(checked) {
+			let share = change.share.display().to_string();
+			let message = if change.available {
+				format!("Share {share} is available again")
+			} else {
+				let reason = self
+					.state
+					.share_availability
+					.get(&change.share)
+					.map(ShareAvailability::describe)
+					.unwrap_or_default();
+				format!("Share {share} is {reason}")
+			};
+			tracing::info!("{message}");
+			self.state.push_notification(me, message);
+			let _ = self.share_changes.send(change);
+		}
+	}
+
+	/// Drops thumbnails made from `path` or anything under it, so the next
+	/// request regenerates them.
+	fn invalidate_derived(&mut self, path: &Path) {
+		let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+		let removed = match self.thumbnails.lock() {
+			Ok(mut thumbnails) => thumbnails.invalidate(&path),
+			Err(_) => 0,
 		};
-		let stored_discovered = {
-			let conn = db.lock().unwrap();
-			load_discovered_peers(&conn).unwrap_or_else(|err| {
-				log::error!("failed to load discovered peers: {err}");
-				Vec::new()
-			})
+		if removed > 0 {
+			tracing::debug!(
+				"dropped {removed} stale thumbnail(s) for {}",
+				path.display()
+			);
+		}
+		if let Err(err) = self.thumbnail_disk.invalidate(&path) {
+			tracing::warn!(
+				"failed to drop kept thumbnails of {}: {err}",
+				path.display()
+			);
+		}
+	}
+
+	/// Marks the index entry for `path` as written by `peer`.
+	async fn record_remote_write(&mut self, peer: PeerId, path: &Path) {
+		let Some(node_id) = self.local_node_id() else {
+			return;
 		};
-		let stored_users = {
-			let conn = db.lock().unwrap();
-			match load_users(&conn) {
-				Ok(users) => users,
-				Err(err) => {
-					log::error!("failed to load users: {err}");
-					Vec::new()
+		let size = fs::metadata(path).await.map(|meta| meta.len()).unwrap_or(0);
+		let now = self.clock.now();
+		let provenance = FileProvenance::remote_write(&node_id, &peer, now);
+		match self.db.lock() {
+			Ok(conn) => {
+				if let Err(err) = record_provenance(&conn, path, size, &provenance, now) {
+					tracing::warn!("failed to record who wrote {}: {err}", path.display());
 				}
 			}
+			Err(err) => tracing::error!("db lock poisoned while recording a write: {err}"),
+		}
+	}
+
+	/// Longest thumbnail edge served to peers that may only preview.
+	fn preview_max_dimension(&self) -> u32 {
+		let value = match self.db.lock() {
+			Ok(conn) => load_setting(&conn, PREVIEW_MAX_DIMENSION_SETTING).unwrap_or_else(|err| {
+				tracing::warn!("failed to load preview size limit: {err}");
+				None
+			}),
+			Err(_) => None,
 		};
-		let stored_shared_folders = {
-			let conn = db.lock().unwrap();
-			match load_shared_folders(&conn) {
-				Ok(folders) => folders,
-				Err(err) => {
-					log::error!("failed to load shared folders: {err}");
-					Vec::new()
-				}
+		preview_max_from_setting(value.as_deref())
+	}
+
+	/// Checks a peer's `GetThumbnail` and returns the job that answers it,
+	/// or the refusal.
+	async fn thumbnail_job(
+		&mut self,
+		peer: PeerId,
+		path: String,
+		max_width: u32,
+		max_height: u32,
+	) -> Result<ThumbnailJob, PeerRes> {
+		tracing::info!(
+			"[{}] GetThumbnail {} ({}x{})",
+			peer,
+			path,
+			max_width,
+			max_height
+		);
+		self.thumbnail_queue
+			.note_foreground(std::time::Instant::now());
+		let path = Self::request_path(peer, &WirePath::Display(path)).map_err(PeerRes::Error)?;
+		if let Some(offline) = self.offline_share(peer, &path, FLAG_PREVIEW) {
+			return Err(offline);
+		}
+		let image = match safe_open::open_read(&path).await {
+			Ok(image) => image,
+			Err(err) => {
+				tracing::warn!("failed to open thumbnail path {}: {err}", path.display());
+				return Err(PeerRes::Error(format!("Failed to access file: {err}")));
 			}
 		};
-		let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
-		let (internal_tx, internal_rx) = tokio::sync::mpsc::unbounded_channel();
+		let canonical = image.canonical();
+		if self.hidden_from(peer, canonical, false) {
+			return Err(hidden_path("file"));
+		}
+		let preview_max = self.preview_max_dimension();
+		let Some((max_width, max_height)) =
+			self.state
+				.thumbnail_bounds(peer, canonical, max_width, max_height, preview_max)
+		else {
+			tracing::warn!(
+				"peer {} denied thumbnail access for {}",
+				peer,
+				canonical.display()
+			);
+			return Err(self.access_denied(peer, canonical, FLAG_PREVIEW));
+		};
+		Ok(ThumbnailJob {
+			cache: Arc::clone(&self.thumbnails),
+			disk: Arc::clone(&self.thumbnail_disk),
+			limits: Arc::clone(&self.decode_limits),
+			image,
+			max_width,
+			max_height,
+			typed_too_large: self
+				.state
+				.peer_capabilities(&peer)
+				.is_some_and(|capabilities| capabilities.supports(FEATURE_IMAGE_TOO_LARGE)),
+		})
+	}
 
-		let listen_addr = "/ip4/0.0.0.0/tcp/0".parse().unwrap();
-		if let Err(err) = swarm.listen_on(listen_addr) {
-			log::warn!("failed to start swarm listener: {err}");
+	/// Checks a peer's `ListDir` and returns the job that answers it, or
+	/// the refusal.
+	async fn list_dir_job(&mut self, peer: PeerId, path: WirePath) -> Result<ListJob, PeerRes> {
+		tracing::info!("[{}] ListDir {}", peer, path);
+		let path = Self::request_path(peer, &path).map_err(PeerRes::Error)?;
+		if let Some(offline) = self.offline_share(peer, &path, FLAG_PREVIEW | FLAG_SEARCH) {
+			return Err(offline);
 		}
-		state.me = peer_id;
-		state.users = stored_users;
-		state.peers = stored_peers;
-		state.discovered_peers = stored_discovered;
-		for (target, permissions) in stored_permissions {
-			state.set_peer_permissions_from_storage(target, permissions);
+		let dir = match safe_open::open(&path).await {
+			Ok(dir) => dir,
+			Err(err) => {
+				tracing::warn!("failed to open directory {}: {err}", path.display());
+				return Err(PeerRes::Error(format!("Failed to access directory: {err}")));
+			}
+		};
+		let canonical = dir.canonical().to_path_buf();
+		if self.hidden_from(peer, &canonical, true) {
+			return Err(hidden_path("directory"));
 		}
-		for folder in stored_shared_folders {
-			state.add_shared_folder(folder);
+		if !self.can_access(peer, &canonical, FLAG_PREVIEW | FLAG_SEARCH) {
+			tracing::warn!(
+				"peer {} denied directory listing for {}",
+				peer,
+				canonical.display()
+			);
+			return Err(self.access_denied(peer, &canonical, FLAG_PREVIEW | FLAG_SEARCH));
 		}
-		let mut app = App {
-			state,
-			swarm,
-			rx,
-			internal_rx,
-			internal_tx,
-			pending_requests: HashMap::new(),
-			system: System::new(),
-			db,
-			remote_scans,
-			remote_searches,
-			remote_updates,
-			shell_sessions: HashMap::new(),
+		let now = self.clock.now();
+		let hints = self
+			.state
+			.peer_capabilities(&peer)
+			.is_some_and(|capabilities| capabilities.supports(FEATURE_FREE_HINT));
+		let listing = if hints {
+			let access = self.state.listing_access_at(peer, &canonical, now);
+			Some((self.free_hint_for(peer, &canonical).await, access))
+		} else {
+			None
 		};
-		app.normalize_file_location_node_ids();
-		app.persist_local_node();
-		(app, tx)
+		Ok(ListJob {
+			dir,
+			db: Arc::clone(&self.db),
+			node_id: self.local_node_id(),
+			hidden: self.state.hidden_names(peer, now),
+			listing,
+		})
 	}
 
-	fn local_os_label() -> String {
-		let name = System::name().unwrap_or_else(|| std::env::consts::OS.to_string());
-		match System::os_version() {
-			Some(version) if !version.trim().is_empty() => format!("{name} {version}"),
-			_ => name,
+	/// Checks a peer's `ReadFile` and returns the job that answers it, or
+	/// the refusal.
+	async fn read_job(
+		&mut self,
+		peer: PeerId,
+		path: WirePath,
+		offset: u64,
+		length: Option<u64>,
+		sparse: bool,
+	) -> Result<ReadJob, PeerRes> {
+		tracing::info!(
+			"[{}] ReadFile {} (offset {}, length {:?}, sparse {})",
+			peer,
+			path,
+			offset,
+			length,
+			sparse
+		);
+		self.thumbnail_queue
+			.note_foreground(std::time::Instant::now());
+		let path = Self::request_path(peer, &path).map_err(PeerRes::Error)?;
+		if let Some(offline) = self.offline_share(peer, &path, FLAG_READ | FLAG_SEARCH) {
+			return Err(offline);
 		}
-	}
-
-	fn local_peer_info() -> PeerInfo {
-		PeerInfo {
-			version: version::version_label(),
-			os: Self::local_os_label(),
-			uptime_seconds: System::uptime(),
+		let opened = match safe_open::open_read(&path).await {
+			Ok(opened) => opened,
+			Err(err) => {
+				tracing::warn!("failed to open read path {}: {err}", path.display());
+				return Err(PeerRes::Error(format!("Failed to access file: {err}")));
+			}
+		};
+		let canonical = opened.canonical();
+		if self.hidden_from(peer, canonical, false) {
+			return Err(hidden_path("file"));
 		}
+		if !self.can_access(peer, canonical, FLAG_READ | FLAG_SEARCH) {
+			tracing::warn!("peer {} denied read for {}", peer, canonical.display());
+			return Err(self.access_denied(peer, canonical, FLAG_READ | FLAG_SEARCH));
+		}
+		Ok(ReadJob {
+			file: fs::File::from_std(opened.into_file()),
+			offset,
+			length,
+			sparse,
+		})
 	}
 
-	async fn handle_puppy_peer_req(
+	/// Checks a peer's `ContactSheet` and returns the job that answers it,
+	/// or the refusal. The folder is listed here, while the grants can be
+	/// asked about each image.
+	async fn contact_sheet_job(
 		&mut self,
 		peer: PeerId,
-		req: PeerReq,
-	) -> anyhow::Result<PeerRes> {
-		let res = match req {
-			PeerReq::PeerInfo => PeerRes::PeerInfo(Self::local_peer_info()),
-			PeerReq::ListDir { path } => {
-				log::info!("[{}] ListDir {}", peer, path);
-				let canonical = match fs::canonicalize(&path).await {
-					Ok(p) => p,
-					Err(err) => {
-						log::warn!("failed to canonicalize directory {}: {err}", path);
-						return Ok(PeerRes::Error(format!("Failed to access directory: {err}")));
-					}
+		path: String,
+		options: ContactSheetOptions,
+	) -> Result<ContactSheetJob, PeerRes> {
+		tracing::info!("[{}] ContactSheet {} ({:?})", peer, path, options);
+		self.thumbnail_queue
+			.note_foreground(std::time::Instant::now());
+		let path = Self::request_path(peer, &WirePath::Display(path)).map_err(PeerRes::Error)?;
+		if let Some(offline) = self.offline_share(peer, &path, FLAG_PREVIEW) {
+			return Err(offline);
+		}
+		let canonical = match fs::canonicalize(&path).await {
+			Ok(p) => p,
+			Err(err) => {
+				tracing::warn!(
+					"failed to canonicalize contact sheet path {}: {err}",
+					path.display()
+				);
+				return Err(PeerRes::Error(format!("Failed to access folder: {err}")));
+			}
+		};
+		let preview_max = self.preview_max_dimension();
+		let cell = options.clamped().cell_size;
+		let Some((cell, _)) =
+			self.state
+				.thumbnail_bounds(peer, &canonical, cell, cell, preview_max)
+		else {
+			tracing::warn!(
+				"peer {} denied contact sheet of {}",
+				peer,
+				canonical.display()
+			);
+			return Err(self.access_denied(peer, &canonical, FLAG_PREVIEW));
+		};
+		let options = options.within(self.decode_limits.budget(), cell);
+		let hidden = self.state.hidden_names(peer, self.clock.now());
+		let images = contact_sheet::pick_images(&canonical, options.max_items, |image| {
+			!hidden.hides(image, false)
+				&& self
+					.state
+					.thumbnail_bounds(peer, image, cell, cell, preview_max)
+					.is_some()
+		})
+		.await
+		.map_err(|err| PeerRes::Error(format!("Failed to list folder: {err}")))?;
+		Ok(ContactSheetJob {
+			cache: Arc::clone(&self.thumbnails),
+			disk: Arc::clone(&self.thumbnail_disk),
+			limits: Arc::clone(&self.decode_limits),
+			images,
+			options,
+			deadline: std::time::Instant::now() + CONTACT_SHEET_TIME_BUDGET,
+		})
+	}
+
+	/// One page of the local index search for `peer`, which only sees files
+	/// on this node under folders it may search. Searches by this node
+	/// itself are not narrowed.
+	fn search_files_for(
+		&self,
+		peer: PeerId,
+		args: SearchFilesArgs,
+	) -> Result<Vec<FileSearchResult>> {
+		if peer == self.state.me {
+			let (page, _, _) = self.db.read(|conn| search_files(conn, args))?;
+			return Ok(page.rows);
+		}
+		let node_id = self
+			.local_node_id()
+			.ok_or_else(|| anyhow!("no node id for {}", self.state.me))?;
+		let now = self.clock.now();
+		self.db
+			.read(|conn| index::search_for_peer(conn, &self.state, peer, node_id, args, now))
+	}
+
+	/// Summaries of the folders `peer` may search, from this node's own
+	/// index.
+	fn share_summaries_for(&self, peer: PeerId) -> Result<Vec<ShareSummary>> {
+		let node_id = peer_to_node_id(&self.state.me)
+			.ok_or_else(|| anyhow!("no node id for {}", self.state.me))?;
+		let now = self.clock.now();
+		self.db
+			.read(|conn| share_summary::summarize(conn, &self.state, peer, &node_id, now))
+	}
+
+	/// Whether the owner rejected `path` from `peer` since its last write.
+	/// The rejection is forgotten once the peer has been told.
+	fn take_rejected_review(&self, peer: PeerId, path: &Path) -> bool {
+		let Ok(conn) = self.db.lock() else {
+			return false;
+		};
+		take_rejected_review(&conn, &peer, &path.to_string_lossy()).unwrap_or_else(|err| {
+			tracing::warn!("failed to check review of {}: {err}", path.display());
+			false
+		})
+	}
+
+	fn queue_review(&self, peer: PeerId, path: &Path) {
+		let size = std::fs::metadata(path).map(|meta| meta.len()).unwrap_or(0);
+		let result = match self.db.lock() {
+			Ok(conn) => record_pending_review(
+				&conn,
+				&peer,
+				&path.to_string_lossy(),
+				size,
+				self.clock.now(),
+			),
+			Err(_) => Err(anyhow!("db lock poisoned")),
+		};
+		if let Err(err) = result {
+			tracing::error!("failed to queue {} for review: {err}", path.display());
+		}
+	}
+
+	fn health_snapshot(&self) -> PeerHealth {
+		let db_ok = self.db.lock().is_ok_and(|conn| {
+			conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0))
+				.is_ok()
+		});
+		PeerHealth {
+			version: version::version_label(),
+			uptime_secs: self.started_at.elapsed().as_secs(),
+			db_ok,
+			listen_addrs: self
+				.swarm
+				.listeners()
+				.map(|addr| addr.to_string())
+				.collect(),
+			pending_requests: self.pending_requests.len(),
+			last_restart_error: self.last_restart_error.clone(),
+			pending_dials: self.dialer.pending(),
+			queued_dials: self.dialer.queued(),
+			dials: self.dialer.stats(),
+			churn: self.keeper.churn(),
+		}
+	}
+
+	/// Restarts the process after `delay_secs` (clamped), leaving time to
+	/// answer the request. When the restart fails the process keeps running
+	/// and the error shows up in the next health check.
+	fn schedule_restart(&mut self, delay_secs: u64) -> u64 {
+		let delay_secs = delay_secs.clamp(RESTART_MIN_DELAY_SECS, RESTART_MAX_DELAY_SECS);
+		self.last_restart_error = None;
+		let internal_tx = self.internal_tx.clone();
+		tokio::spawn(async move {
+			tokio::time::sleep(Duration::from_secs(delay_secs)).await;
+			tracing::info!("restarting puppynet");
+			if let Err(err) = updater::restart_process() {
+				tracing::error!("restart failed, continuing to run: {err}");
+				let _ = internal_tx.send(InternalCommand::RestartFailed {
+					error: err.to_string(),
+				});
+			}
+		});
+		delay_secs
+	}
+
+	pub(crate) fn collect_disk_info() -> Vec<DiskInfo> {
+		let disks = Disks::new_with_refreshed_list();
+		let uuids = disk_history::uuid_links();
+		disks
+			.iter()
+			.map(|disk| {
+				let total_space = disk.total_space();
+				let available_space = disk.available_space();
+				let usage_percent = if total_space == 0 {
+					0.0
+				} else {
+					let used = total_space.saturating_sub(available_space);
+					((used as f64 / total_space as f64) * 100.0) as f32
 				};
-				if !self.can_access(peer, &canonical, FLAG_READ | FLAG_SEARCH) {
-					log::warn!(
-						"peer {} denied directory listing for {}",
-						peer,
-						canonical.display()
-					);
-					return Ok(PeerRes::Error("Access denied".into()));
+				let usage = disk.usage();
+				let name = disk.name().to_string_lossy().to_string();
+				let mount_path = disk.mount_point().to_string_lossy().to_string();
+				DiskInfo {
+					id: disk_history::disk_id(&name, &mount_path, &uuids),
+					name,
+					mount_path,
+					filesystem: disk.file_system().to_string_lossy().to_string(),
+					total_space,
+					available_space,
+					usage_percent,
+					total_read_bytes: usage.total_read_bytes,
+					total_written_bytes: usage.total_written_bytes,
+					read_only: disk.is_read_only(),
+					removable: disk.is_removable(),
+					kind: format!("{:?}", disk.kind()),
 				}
-				let entries = Self::collect_dir_entries(&canonical).await?;
-				PeerRes::DirEntries(entries)
+			})
+			.collect()
+	}
+
+	/// Starts mapping the listen ports on the router. Status changes and
+	/// the mapped addresses come back as [`InternalCommand::NatStatus`].
+	fn start_nat_mapper(&mut self) {
+		let internal_tx = self.internal_tx.clone();
+		self.state.nat = NatStatus::Mapping;
+		self.nat_mapper = Some(NatMapper::start(
+			self.listen_ports,
+			move |status, external_addrs| {
+				let _ = internal_tx.send(InternalCommand::NatStatus {
+					status,
+					external_addrs,
+				});
+			},
+		));
+	}
+
+	/// Stops the port mapper, waiting for it to remove the router mapping.
+	async fn stop_nat_mapper(&mut self) {
+		if let Some(mapper) = self.nat_mapper.take() {
+			mapper.stop().await;
+		}
+		self.set_nat_external_addrs(Vec::new());
+		self.state.nat = NatStatus::Disabled;
+	}
+
+	fn set_nat_external_addrs(&mut self, addrs: Vec<Multiaddr>) {
+		for addr in &self.nat_external_addrs {
+			if !addrs.contains(addr) {
+				self.swarm.remove_external_address(addr);
 			}
-			PeerReq::StatFile { path } => {
-				log::info!("[{}] StatFile {}", peer, path);
-				let canonical = match fs::canonicalize(&path).await {
-					Ok(p) => p,
-					Err(err) => {
-						log::warn!("failed to canonicalize file {}: {err}", path);
-						return Ok(PeerRes::Error(format!("Failed to access file: {err}")));
-					}
+		}
+		for addr in &addrs {
+			if !self.nat_external_addrs.contains(addr) {
+				tracing::info!("advertising mapped address {addr}");
+				self.swarm.add_external_address(addr.clone());
+			}
+		}
+		self.nat_external_addrs = addrs;
+	}
+
+	/// Dials `peer` back at `addr` on a connection of its own. Refused for
+	/// addresses off the IPs `peer` is connected from and past the rate
+	/// limit.
+	fn start_dial_back(&mut self, peer: PeerId, addr: &str) -> Result<ConnectionId, String> {
+		let peer_ips = self
+			.state
+			.connections_to(&peer)
+			.into_iter()
+			.filter_map(|connection| wake::multiaddr_ip(&connection.remote_addr))
+			.collect::<Vec<_>>();
+		let addr = check_dial_back(addr, peer, &peer_ips)?;
+		self.dial_back_limiter
+			.admit(peer, self.dial_backs.len(), std::time::Instant::now())?;
+		let opts = DialOpts::peer_id(peer)
+			.addresses(vec![addr])
+			.condition(PeerCondition::Always)
+			.build();
+		let connection_id = opts.connection_id();
+		self.swarm.dial(opts).map_err(|err| err.to_string())?;
+		let internal_tx = self.internal_tx.clone();
+		tokio::spawn(async move {
+			tokio::time::sleep(DIAL_BACK_TIMEOUT).await;
+			let _ = internal_tx.send(InternalCommand::DialBackTimedOut { connection_id });
+		});
+		Ok(connection_id)
+	}
+
+	/// Answers the dial-back dialed on `connection_id`, if any. False for
+	/// connections of other dials.
+	fn finish_dial_back(
+		&mut self,
+		connection_id: ConnectionId,
+		result: Result<(), String>,
+	) -> bool {
+		let Some(pending) = self.dial_backs.remove(&connection_id) else {
+			return false;
+		};
+		let response = match result {
+			Ok(()) => PeerRes::DialBack {
+				reachable: true,
+				latency_ms: Some(millis(pending.started.elapsed())),
+				error: None,
+			},
+			Err(error) => PeerRes::DialBack {
+				reachable: false,
+				latency_ms: None,
+				error: Some(error),
+			},
+		};
+		pending.inbound.finish(Some(pending.started), &response);
+		let _ = self
+			.swarm
+			.behaviour_mut()
+			.puppynet
+			.send_response(pending.channel, response);
+		true
+	}
+
+	/// Asks up to [`MAX_TESTERS`] connected peers to dial each of this
+	/// node's addresses back, answering `tx` once all replied or timed out.
+	fn test_reachability(&mut self, tx: oneshot::Sender<Vec<AddressReachability>>) {
+		let addrs = testable_addrs(
+			self.swarm
+				.listeners()
+				.chain(self.swarm.external_addresses())
+				.cloned(),
+		);
+		let mut testers = Vec::new();
+		for connection in &self.state.connections {
+			let supported = self
+				.state
+				.peer_capabilities(&connection.peer_id)
+				.is_some_and(|capabilities| capabilities.supports(FEATURE_DIAL_BACK));
+			if supported && testers.len() < MAX_TESTERS && !testers.contains(&connection.peer_id) {
+				testers.push(connection.peer_id);
+			}
+		}
+		let mut answers = Vec::new();
+		for addr in &addrs {
+			for tester in &testers {
+				let (answer_tx, answer_rx) = oneshot::channel();
+				let request_id = self.send_peer_request(
+					tester,
+					PeerReq::DialBack {
+						addr: addr.to_string(),
+					},
+				);
+				self.pending_requests
+					.insert(request_id, Pending::<DialBackOutcome>::new(answer_tx));
+				answers.push((addr.clone(), *tester, answer_rx));
+			}
+		}
+		let clock = Arc::clone(&self.clock);
+		let internal_tx = self.internal_tx.clone();
+		tokio::spawn(async move {
+			let deadline = tokio::time::Instant::now() + DIAL_BACK_ANSWER_TIMEOUT;
+			let mut outcomes = HashMap::<Multiaddr, Vec<_>>::new();
+			for (addr, tester, answer) in answers {
+				let outcome = match tokio::time::timeout_at(deadline, answer).await {
+					Ok(Ok(Ok(outcome))) => outcome,
+					Ok(Ok(Err(err))) => DialBackOutcome::Declined {
+						reason: err.to_string(),
+					},
+					Ok(Err(_)) | Err(_) => DialBackOutcome::Declined {
+						reason: String::from("no answer"),
+					},
 				};
-				if !self.can_access(peer, &canonical, FLAG_READ | FLAG_SEARCH) {
-					log::warn!("peer {} denied stat for {}", peer, canonical.display());
-					return Ok(PeerRes::Error("Access denied".into()));
-				}
-				let meta = fs::metadata(&canonical).await?;
-				let file_type = meta.file_type();
-				let ext = canonical
-					.extension()
-					.and_then(|s| s.to_str().map(|s| s.to_string()));
-				let mime = if file_type.is_dir() {
-					None
-				} else {
-					mime_guess::from_path(&canonical)
-						.first_raw()
-						.map(|value| value.to_string())
+				outcomes.entry(addr).or_default().push((tester, outcome));
+			}
+			let now = clock.now();
+			let results = addrs
+				.iter()
+				.map(|addr| {
+					let answers = outcomes.get(addr).map(Vec::as_slice).unwrap_or_default();
+					aggregate(addr, answers, now)
+				})
+				.collect();
+			let _ = internal_tx.send(InternalCommand::ReachabilityTested { results, tx });
+		});
+	}
+
+	/// Remembers the ports the swarm listens on so the mapper forwards the
+	/// ones actually in use. Loopback and IPv6 listeners are ignored; routers
+	/// only map IPv4.
+	fn record_listen_port(&mut self, address: &Multiaddr) {
+		let mut routable = false;
+		let mut tcp = None;
+		let mut udp = None;
+		for protocol in address.iter() {
+			match protocol {
+				Protocol::Ip4(ip) => routable = !ip.is_loopback(),
+				Protocol::Tcp(port) => tcp = Some(port),
+				Protocol::Udp(port) => udp = Some(port),
+				_ => {}
+			}
+		}
+		if !routable {
+			return;
+		}
+		if is_quic_addr(address) {
+			self.listen_ports.quic = udp;
+		} else if tcp.is_some() {
+			self.listen_ports.tcp = tcp;
+		}
+		if let Some(mapper) = &self.nat_mapper {
+			mapper.set_ports(self.listen_ports);
+		}
+	}
+
+	fn spawn_grant_sweeper(internal_tx: UnboundedSender<InternalCommand>) {
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(TEMPORARY_GRANT_SWEEP_INTERVAL);
+			loop {
+				interval.tick().await;
+				let sweeps = [
+					InternalCommand::SweepTemporaryGrants,
+					InternalCommand::ExpireDiscoveredAddresses,
+					InternalCommand::ExpireRemoteOps,
+					InternalCommand::ExpireInboxUploads,
+					InternalCommand::ExpirePairings,
+				];
+				if sweeps.into_iter().any(|cmd| internal_tx.send(cmd).is_err()) {
+					break;
+				}
+			}
+		});
+	}
+
+	fn spawn_discovery_flusher(internal_tx: UnboundedSender<InternalCommand>) {
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(DISCOVERY_FLUSH_TICK);
+			loop {
+				interval.tick().await;
+				if internal_tx
+					.send(InternalCommand::FlushDiscoveredPeers)
+					.is_err()
+				{
+					break;
+				}
+			}
+		});
+	}
+
+	fn spawn_protocol_rollup(internal_tx: UnboundedSender<InternalCommand>) {
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(PROTOCOL_STATS_TICK);
+			interval.tick().await;
+			loop {
+				interval.tick().await;
+				if internal_tx
+					.send(InternalCommand::RollupProtocolStats)
+					.is_err()
+				{
+					break;
+				}
+			}
+		});
+	}
+
+	fn spawn_clock_sampler(internal_tx: UnboundedSender<InternalCommand>) {
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(CLOCK_SAMPLE_INTERVAL);
+			// Connecting already takes the first sample.
+			interval.tick().await;
+			loop {
+				interval.tick().await;
+				if internal_tx.send(InternalCommand::SampleClocks).is_err() {
+					break;
+				}
+			}
+		});
+	}
+
+	fn spawn_connection_keeper(
+		internal_tx: UnboundedSender<InternalCommand>,
+		policy: ConnectionPolicy,
+	) {
+		tokio::spawn(async move {
+			let mut keepalive = tokio::time::interval(policy.keepalive_interval());
+			let mut redial = tokio::time::interval(REDIAL_CHECK_INTERVAL);
+			loop {
+				let cmd = tokio::select! {
+					_ = keepalive.tick() => InternalCommand::KeepConnectionsAlive,
+					_ = redial.tick() => InternalCommand::RedialImportantPeers,
 				};
-				PeerRes::FileStat(DirEntry {
-					name: canonical
-						.file_name()
-						.and_then(|s| s.to_str().map(|s| s.to_string()))
-						.unwrap_or_default(),
-					is_dir: file_type.is_dir(),
-					extension: ext,
-					mime,
-					size: meta.len(),
-					created_at: meta
-						.created()
-						.ok()
-						.and_then(|t| DateTime::<Utc>::from(t).into()),
-					modified_at: meta
-						.modified()
-						.ok()
-						.and_then(|t| DateTime::<Utc>::from(t).into()),
-					accessed_at: meta
-						.accessed()
-						.ok()
-						.and_then(|t| DateTime::<Utc>::from(t).into()),
+				if internal_tx.send(cmd).is_err() {
+					break;
+				}
+			}
+		});
+	}
+
+	fn spawn_mount_checker(internal_tx: UnboundedSender<InternalCommand>) {
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(MOUNT_CHECK_INTERVAL);
+			loop {
+				interval.tick().await;
+				if internal_tx.send(InternalCommand::CheckShares).is_err() {
+					break;
+				}
+			}
+		});
+	}
+
+	fn spawn_index_announcer(internal_tx: UnboundedSender<InternalCommand>) {
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(ANNOUNCE_FLUSH_INTERVAL);
+			loop {
+				interval.tick().await;
+				if internal_tx.send(InternalCommand::AnnounceIndex).is_err() {
+					break;
+				}
+			}
+		});
+	}
+
+	fn spawn_thumbnail_pregenerator(
+		queue: std::sync::Weak<ThumbnailQueue>,
+		disk: Arc<ThumbnailDisk>,
+		limits: Arc<DecodeLimits>,
+	) {
+		tokio::spawn(async move {
+			while let Some(queue) = queue.upgrade() {
+				let Some(path) = queue.next(std::time::Instant::now(), Utc::now()) else {
+					drop(queue);
+					tokio::time::sleep(PREGEN_POLL_INTERVAL).await;
+					continue;
+				};
+				let outcome = pregenerate_thumbnail(&disk, &limits, &path).await;
+				queue.finish(path, outcome);
+				if outcome == PregenOutcome::Yielded {
+					drop(queue);
+					tokio::time::sleep(PREGEN_POLL_INTERVAL).await;
+				}
+			}
+		});
+	}
+
+	/// Samples local disks every [`DISK_SAMPLE_INTERVAL`] into the disk
+	/// history and raises a notification when one runs low on space.
+	fn spawn_disk_sampler(
+		db: Arc<Db>,
+		clock: Arc<dyn Clock>,
+		internal_tx: UnboundedSender<InternalCommand>,
+	) {
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(DISK_SAMPLE_INTERVAL);
+			let mut alerts = LowSpaceAlerts::default();
+			loop {
+				interval.tick().await;
+				let db = db.clone();
+				let now = clock.now();
+				let sampled = tokio::task::spawn_blocking(move || {
+					let disks = Self::collect_disk_info();
+					let samples = disks
+						.iter()
+						.filter(|disk| disk.total_space > 0)
+						.map(|disk| DiskSample {
+							disk_id: disk.id.clone(),
+							mount_path: disk.mount_path.clone(),
+							sampled_at: now,
+							total_space: disk.total_space,
+							available_space: disk.available_space,
+						})
+						.collect::<Vec<_>>();
+					let conn = db.lock().unwrap();
+					if let Err(err) = record_disk_samples(&conn, &samples) {
+						tracing::error!("failed to record disk samples: {err}");
+					}
+					let cutoff = now - chrono::Duration::days(DISK_SAMPLE_RETENTION_DAYS);
+					if let Err(err) = prune_disk_samples(&conn, cutoff) {
+						tracing::error!("failed to prune disk samples: {err}");
+					}
+					(disks, disk_history::low_space_percent(&conn))
 				})
+				.await;
+				let Ok((disks, threshold)) = sampled else {
+					continue;
+				};
+				for message in alerts.check(&disks, threshold) {
+					tracing::warn!("{message}");
+					if internal_tx
+						.send(InternalCommand::LowDiskSpace { message })
+						.is_err()
+					{
+						return;
+					}
+				}
 			}
-			PeerReq::ReadFile {
-				path,
-				offset,
-				length,
-			} => {
-				log::info!(
-					"[{}] ReadFile {} (offset {}, length {:?})",
-					peer,
-					path,
-					offset,
-					length
-				);
-				let canonical = match fs::canonicalize(&path).await {
-					Ok(p) => p,
+		});
+	}
+
+	async fn start_shell_session(&mut self, peer: PeerId, session_id: u64) -> anyhow::Result<()> {
+		if !cfg!(feature = "shell") {
+			bail!("this build of puppynet has no remote shell");
+		}
+		if let Some(mut existing) = self.shell_sessions.remove(&(peer, session_id)) {
+			let _ = existing.child.kill().await;
+		}
+		let shell_path = config::startup().shell.clone();
+		let mut child = TokioCommand::new(shell_path)
+			.env("TERM", "xterm-256color")
+			.env("PUPPYNET_REMOTE", "1")
+			.stdin(std::process::Stdio::piped())
+			.stdout(std::process::Stdio::piped())
+			.stderr(std::process::Stdio::piped())
+			.spawn()
+			.map_err(|e| anyhow!("failed to spawn shell: {e}"))?;
+		let stdin = child
+			.stdin
+			.take()
+			.ok_or_else(|| anyhow!("failed to take shell stdin"))?;
+		let stdout = child
+			.stdout
+			.take()
+			.ok_or_else(|| anyhow!("failed to take shell stdout"))?;
+		self.shell_sessions.insert(
+			(peer, session_id),
+			ShellSession {
+				child,
+				stdin,
+				stdout,
+			},
+		);
+		tracing::info!("[{}] Started remote shell session {}", peer, session_id);
+		Ok(())
+	}
+
+	/// Fails the requests still waiting on `peer`, whose last connection
+	/// closed: no answer can arrive on it, and libp2p may report their
+	/// failure late or not at all.
+	fn fail_requests_to(&mut self, peer: PeerId, reason: DisconnectReason) {
+		let disconnected = Disconnected { peer, reason };
+		let failed = fail_disconnected(
+			&mut self.outbound_peers,
+			&mut self.pending_requests,
+			disconnected,
+		);
+		if !failed.is_empty() {
+			tracing::info!("failed {} request(s) to {peer} on disconnect", failed.len());
+		}
+		for request_id in failed {
+			self.finish_outbound_trace(&request_id, Some(disconnected.to_string()));
+			self.outbound_counters.remove(&request_id);
+			self.grant_checks.remove(&request_id);
+		}
+	}
+
+	/// Kills the shell `peer` started as `session_id`, if it still runs.
+	fn close_shell_session(&mut self, peer: PeerId, session_id: u64) {
+		let Some(mut session) = self.shell_sessions.remove(&(peer, session_id)) else {
+			return;
+		};
+		tracing::info!("[{}] Closed remote shell session {}", peer, session_id);
+		if let Err(err) = session.child.start_kill() {
+			tracing::warn!("failed to end shell session {session_id}: {err}");
+		}
+	}
+
+	/// Kills the shells `peer` started here, which nobody can type into
+	/// once its last connection closed.
+	fn end_shell_sessions(&mut self, peer: PeerId) {
+		self.shell_sessions.retain(|(owner, session_id), session| {
+			if *owner != peer {
+				return true;
+			}
+			tracing::info!(
+				"[{}] Ended remote shell session {} on disconnect",
+				peer,
+				session_id
+			);
+			if let Err(err) = session.child.start_kill() {
+				tracing::warn!("failed to end shell session {session_id}: {err}");
+			}
+			false
+		});
+	}
+
+	/// Sends `request` under a fresh correlation id. Peers that support
+	/// tracing get the id with it and log their handling under it.
+	fn send_peer_request(&mut self, peer: &PeerId, request: PeerReq) -> OutboundRequestId {
+		let corr = self.request_log.next_corr();
+		let name = request.name();
+		let grant_check = self
+			.grant_cache
+			.contains(peer)
+			.then(|| grant_cache::requested_access(&request))
+			.flatten();
+		let traced = self
+			.state
+			.peer_capabilities(peer)
+			.is_some_and(|capabilities| capabilities.supports(FEATURE_TRACING));
+		let request = if traced {
+			PeerReq::Traced {
+				corr,
+				request: Box::new(request),
+			}
+		} else {
+			request
+		};
+		tracing::debug!(corr, %peer, request = name, "sending peer request");
+		let kind = kind_index(name);
+		let counters = self.protocol.counters(*peer);
+		counters.sent(kind, wire_len(&request));
+		let request_id = self
+			.swarm
+			.behaviour_mut()
+			.puppynet
+			.send_request(peer, request);
+		self.outbound_counters.insert(request_id, (counters, kind));
+		self.outbound_peers.insert(request_id, *peer);
+		if let Some(started) = self.request_log.start() {
+			self.outbound_traces.insert(
+				request_id,
+				OutboundTrace {
+					corr,
+					peer: *peer,
+					request: name,
+					at: Utc::now(),
+					started,
+				},
+			);
+		}
+		if let Some((path, flags)) = grant_check {
+			self.grant_checks.insert(request_id, (*peer, path, flags));
+		}
+		request_id
+	}
+
+	fn finish_outbound_trace(&mut self, request_id: &OutboundRequestId, error: Option<String>) {
+		let Some(trace) = self.outbound_traces.remove(request_id) else {
+			return;
+		};
+		let round_trip = trace.started.elapsed();
+		tracing::debug!(
+			corr = trace.corr,
+			peer = %trace.peer,
+			request = trace.request,
+			round_trip_ms = millis(round_trip),
+			"peer request finished"
+		);
+		self.request_log.record(RequestTrace {
+			corr: trace.corr,
+			direction: RequestDirection::Outbound,
+			peer: trace.peer.to_string(),
+			request: trace.request.to_string(),
+			at: trace.at,
+			queue_ms: None,
+			handler_ms: None,
+			round_trip_ms: Some(millis(round_trip)),
+			error,
+		});
+	}
+
+	fn send_permissions_changed(&mut self, peer: PeerId, permissions: Vec<Permission>) {
+		let request_id = self.send_peer_request(
+			&peer,
+			PeerReq::PermissionsChanged {
+				permissions: permissions.clone(),
+			},
+		);
+		self.pending_requests.insert(
+			request_id,
+			PendingPermissionsChangedAck::new(
+				peer,
+				permissions,
+				Arc::clone(&self.db),
+				Arc::clone(&self.clock),
+			),
+		);
+	}
+
+	fn notify_permissions_changed(&mut self, peer: PeerId) {
+		if peer == self.state.me {
+			return;
+		}
+		let permissions = self.state.permissions_granted_to_peer(&peer);
+		if self.state.connections.iter().any(|c| c.peer_id == peer) {
+			self.send_permissions_changed(peer, permissions);
+		} else {
+			let now = self.clock.now().timestamp();
+			queue_permissions_changed(&self.db, &peer, &permissions, now);
+		}
+	}
+
+	/// Adds `rule` to `peer`'s stored grants. False when the peer already
+	/// had it.
+	fn grant_folder_to_peer(&mut self, peer: PeerId, rule: &FolderRule) -> Result<bool> {
+		if peer == self.state.me {
+			bail!("this is the current device");
+		}
+		let existing = self.state.permissions_granted_to_peer(&peer);
+		let Some(permissions) = merge_folder_grant(&existing, rule) else {
+			return Ok(false);
+		};
+		let me = self.state.me;
+		{
+			let mut conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+			crate::db::save_peer_permissions(&mut conn, &me, &peer, &permissions)?;
+		}
+		for overlap in self.state.set_peer_permissions(peer, permissions) {
+			tracing::warn!("overlapping grant for {peer}: {}", overlap.describe());
+		}
+		self.notify_permissions_changed(peer);
+		Ok(true)
+	}
+
+	/// Grants `folder` to each of `peers`, on top of what they already
+	/// have. A peer that fails doesn't stop the others; the batch is
+	/// logged once.
+	fn grant_folder_to_peers(
+		&mut self,
+		folder: FolderRule,
+		mut peers: Vec<PeerId>,
+	) -> Result<Vec<(PeerId, BatchGrantOutcome)>> {
+		if folder.flags() == 0 {
+			bail!("choose at least one kind of access");
+		}
+		let mut seen = HashSet::new();
+		peers.retain(|peer| seen.insert(*peer));
+		if peers.is_empty() {
+			bail!("choose at least one peer");
+		}
+		let canonical = std::fs::canonicalize(folder.path())
+			.map_err(|err| anyhow!("cannot grant {}: {err}", folder.path().display()))?;
+		if !self.can_access(self.state.me, &canonical, folder.flags()) {
+			bail!(
+				"{} is outside the allowed folders for this access",
+				canonical.display()
+			);
+		}
+		for pattern in folder.deny_patterns().iter().chain(folder.allow_patterns()) {
+			if let Some(problem) = pattern_error(pattern) {
+				bail!("{pattern}: {problem}");
+			}
+		}
+		let rule = folder.with_path(canonical);
+		let results = peers
+			.into_iter()
+			.map(|peer| {
+				let outcome = match self.grant_folder_to_peer(peer, &rule) {
+					Ok(true) => BatchGrantOutcome::Granted,
+					Ok(false) => BatchGrantOutcome::Unchanged,
+					Err(err) => BatchGrantOutcome::Failed {
+						error: format!("{err:#}"),
+					},
+				};
+				(peer, outcome)
+			})
+			.collect::<Vec<_>>();
+		let count = |wanted: fn(&BatchGrantOutcome) -> bool| {
+			results
+				.iter()
+				.filter(|(_, outcome)| wanted(outcome))
+				.count()
+		};
+		tracing::info!(
+			"granted {} (flags {:#04x}) to {} peer(s): {} granted, {} unchanged, {} failed",
+			rule.path().display(),
+			rule.flags(),
+			results.len(),
+			count(|outcome| *outcome == BatchGrantOutcome::Granted),
+			count(|outcome| *outcome == BatchGrantOutcome::Unchanged),
+			count(|outcome| matches!(outcome, BatchGrantOutcome::Failed { .. })),
+		);
+		Ok(results)
+	}
+
+	/// Caches the grants `peer` gave this node, fetched or pushed just now.
+	fn store_remote_grants(&mut self, peer: PeerId, permissions: Vec<Permission>) {
+		let now = self.clock.now();
+		let saved = self
+			.db
+			.lock()
+			.map_err(|_| anyhow!("db lock poisoned"))
+			.and_then(|conn| save_remote_grants(&conn, &peer, &permissions, now.timestamp()));
+		if let Err(err) = saved {
+			tracing::warn!("failed to cache grants from {peer}: {err}");
+		}
+		self.grant_cache.store(peer, permissions, now);
+	}
+
+	/// Asks `peer` for its grants in the background, unless already asking.
+	fn refetch_remote_grants(&mut self, peer: PeerId) {
+		if !self.grant_refetches.insert(peer) {
+			return;
+		}
+		let request_id = self.send_peer_request(&peer, PeerReq::ListPermissions);
+		self.pending_requests.insert(
+			request_id,
+			PendingRemoteGrants::new(
+				peer,
+				None,
+				Arc::clone(&self.clock),
+				self.internal_tx.clone(),
+			),
+		);
+	}
+
+	/// `peer` refused a request its cached grants allowed, so they changed
+	/// without a `PermissionsChanged` push reaching us.
+	fn remote_grants_refuted(&mut self, peer: PeerId) {
+		let deleted = self
+			.db
+			.lock()
+			.map_err(|_| anyhow!("db lock poisoned"))
+			.and_then(|conn| delete_remote_grants(&conn, &peer));
+		if let Err(err) = deleted {
+			tracing::warn!("failed to drop cached grants from {peer}: {err}");
+		}
+		let label = self.state.peer_label(&peer);
+		self.state
+			.push_notification(peer, format!("Permissions on {label} changed, refreshing"));
+		self.refetch_remote_grants(peer);
+	}
+
+	fn list_remote_grants(
+		&mut self,
+		peer: PeerId,
+		force_refresh: bool,
+		tx: oneshot::Sender<Result<RemoteGrants>>,
+	) {
+		if self.state.me == peer {
+			let _ = tx.send(Ok(RemoteGrants {
+				permissions: self.state.permissions_for_peer(&peer),
+				fetched_at: self.clock.now(),
+				revalidating: false,
+			}));
+			return;
+		}
+		let lookup = if force_refresh {
+			Lookup::Missing
+		} else {
+			self.grant_cache.lookup(&peer, self.clock.now())
+		};
+		match lookup {
+			Lookup::Fresh(grants) => {
+				let _ = tx.send(Ok(grants));
+			}
+			Lookup::Stale(grants) => {
+				let _ = tx.send(Ok(grants));
+				self.refetch_remote_grants(peer);
+			}
+			Lookup::Missing => {
+				let request_id = self.send_peer_request(&peer, PeerReq::ListPermissions);
+				let pending = PendingRemoteGrants::new(
+					peer,
+					Some(tx),
+					Arc::clone(&self.clock),
+					self.internal_tx.clone(),
+				);
+				if let Some(prev) = self.pending_requests.insert(request_id, pending) {
+					prev.fail(anyhow!("pending ListPermissions request was replaced"));
+				}
+			}
+		}
+	}
+
+	fn flush_permission_outbox(&mut self, peer: PeerId) {
+		let queued = match self.db.lock() {
+			Ok(conn) => take_permission_change(&conn, &peer, self.clock.now().timestamp()),
+			Err(err) => {
+				tracing::error!("db lock poisoned while flushing permission outbox: {err}");
+				return;
+			}
+		};
+		match queued {
+			Ok(Some(permissions)) => self.send_permissions_changed(peer, permissions),
+			Ok(None) => {}
+			Err(err) => {
+				tracing::error!("failed to load queued permission change for {peer}: {err}")
+			}
+		}
+	}
+
+	fn flush_delete_outbox(&mut self, peer: PeerId) {
+		let supported = self
+			.state
+			.peer_capabilities(&peer)
+			.is_some_and(|capabilities| capabilities.supports(FEATURE_DELETE_PROPOSALS));
+		if !supported || !self.state.connections.iter().any(|c| c.peer_id == peer) {
+			return;
+		}
+		let queued = match self.db.lock() {
+			Ok(conn) => deletion::outbox_for(&conn, &peer, self.clock.now()),
+			Err(err) => {
+				tracing::error!("db lock poisoned while flushing deletion outbox: {err}");
+				return;
+			}
+		};
+		let requests = match queued {
+			Ok(requests) => requests,
+			Err(err) => {
+				tracing::error!("failed to load queued deletion messages for {peer}: {err}");
+				return;
+			}
+		};
+		for request in requests {
+			let pending = match &request {
+				PeerReq::DeleteProposal { id, .. } => PendingDeleteProposal::new(
+					id.clone(),
+					peer,
+					Arc::clone(&self.db),
+					Arc::clone(&self.clock),
+				),
+				PeerReq::DeleteOutcome { id, .. } => {
+					PendingDeleteOutcomeAck::new(id.clone(), peer, Arc::clone(&self.db))
+				}
+				_ => continue,
+			};
+			let request_id = self.send_peer_request(&peer, request);
+			self.pending_requests.insert(request_id, pending);
+		}
+	}
+
+	/// Queues a deletion proposal `peer` sent for the owner's review. Only
+	/// peers this node shares with may ask.
+	fn receive_delete_proposal(&mut self, peer: PeerId, offer: ProposalOffer) -> PeerRes {
+		if !self.state.shares_with(&peer) {
+			tracing::warn!("peer {} denied deletion proposal: nothing shared", peer);
+			return PeerRes::Error(ACCESS_DENIED.into());
+		}
+		let subject = deletion::subject(&offer.paths_hint, &offer.hash);
+		let received = match self.db.lock() {
+			Ok(conn) => deletion::receive(&conn, &peer, offer, self.clock.now()),
+			Err(err) => Err(anyhow!("db lock poisoned: {err}")),
+		};
+		match received {
+			Ok((_, outcome)) => {
+				if outcome.is_none() {
+					self.state.push_notification(
+						peer,
+						format!("Asked to delete {subject}; review it on the Review page"),
+					);
+				}
+				PeerRes::DeleteProposalReceived { outcome }
+			}
+			Err(err) => {
+				tracing::warn!("deletion proposal from {} refused: {err}", peer);
+				PeerRes::Error(err.to_string())
+			}
+		}
+	}
+
+	/// Records what `peer` did with this node's proposal `id`.
+	fn receive_delete_outcome(
+		&mut self,
+		peer: PeerId,
+		id: &str,
+		outcome: DeleteOutcome,
+	) -> PeerRes {
+		let recorded = match self.db.lock() {
+			Ok(conn) => record_delete_outcome(&conn, id, &peer, &outcome, self.clock.now()),
+			Err(err) => Err(anyhow!("db lock poisoned: {err}")),
+		};
+		match recorded {
+			Ok(true) => {
+				self.state.push_notification(
+					peer,
+					format!("Deletion proposal {}", outcome.status().label()),
+				);
+				PeerRes::DeleteOutcomeAck
+			}
+			Ok(false) => PeerRes::Error("unknown deletion proposal".into()),
+			Err(err) => {
+				tracing::error!("failed to record deletion outcome from {}: {err}", peer);
+				PeerRes::Error(err.to_string())
+			}
+		}
+	}
+
+	/// Reserves an inbox file for `name` and answers where it lies
+	/// relative to the inbox.
+	fn open_inbox(&mut self, peer: PeerId, name: &str, size: u64) -> Result<String> {
+		let Some(inbox) = self.state.inbox.clone() else {
+			bail!("Inbox unavailable");
+		};
+		if peer != self.state.me && !self.state.has_inbox_grant(&peer) {
+			bail!("Access denied");
+		}
+		if size > INBOX_MAX_FILE_SIZE {
+			bail!("File exceeds inbox size limit");
+		}
+		let available = available_space_for(&inbox).unwrap_or(0);
+		if available < size.saturating_add(INBOX_MIN_FREE_SPACE) {
+			bail!("Not enough disk space");
+		}
+		let Some(name) = sanitize_inbox_name(name) else {
+			bail!("Invalid file name");
+		};
+		let path = reserve_inbox_file(&inbox, &name)
+			.map_err(|err| anyhow!("failed to reserve inbox file: {err}"))?;
+		let Some(reserved) = path.file_name().and_then(|name| name.to_str()) else {
+			bail!("Invalid file name");
+		};
+		let reserved = reserved.to_string();
+		let upload = InboxUpload {
+			peer,
+			name,
+			size,
+			path,
+			last_write: self.clock.now(),
+		};
+		if size == 0 {
+			self.finish_inbox_upload(upload);
+		} else {
+			self.inbox_uploads.insert(reserved.clone(), upload);
+		}
+		Ok(reserved)
+	}
+
+	fn inbox_upload_path(&self, peer: PeerId, path: &str) -> Option<PathBuf> {
+		self.inbox_uploads
+			.get(path)
+			.filter(|upload| {
+				upload.peer == peer && (peer == self.state.me || self.state.has_inbox_grant(&peer))
+			})
+			.map(|upload| upload.path.clone())
+	}
+
+	/// Opens the inbox upload `peer` reserved at `path`, refusing it when
+	/// the file was swapped for a link elsewhere.
+	async fn open_inbox_upload(&mut self, peer: PeerId, path: &str) -> Result<WriteTarget> {
+		let Some(local) = self.inbox_upload_path(peer, path) else {
+			bail!("Access denied");
+		};
+		let target = WriteTarget::open(&local)
+			.await
+			.map_err(|err| anyhow!("Failed to access file: {err}"))?;
+		if target.canonical() != local {
+			bail!("Access denied");
+		}
+		Ok(target)
+	}
+
+	fn finish_inbox_upload(&mut self, upload: InboxUpload) {
+		tracing::info!("[{}] received inbox file {}", upload.peer, upload.name);
+		if let Some(store) = self.inbox_store.clone() {
+			let path = upload.path.clone();
+			let now = self.clock.now();
+			tokio::task::spawn_blocking(move || {
+				if let Err(err) = store.dedup_in_place(&path, now) {
+					tracing::warn!("failed to store inbox file {}: {err}", path.display());
+				}
+			});
+		}
+		let label = self.state.peer_label(&upload.peer);
+		self.state
+			.push_notification(upload.peer, format!("{label} sent you {}", upload.name));
+	}
+
+	/// Writes to the inbox upload at `path` through `target`, the handle
+	/// `open_inbox_upload` gave, so a swapped path can't redirect the write.
+	async fn write_inbox_chunk(
+		&mut self,
+		path: &str,
+		target: WriteTarget,
+		offset: u64,
+		data: &[u8],
+	) -> Result<FileWriteAck> {
+		let size = match self.inbox_uploads.get(path) {
+			Some(upload) => upload.size,
+			None => bail!("Access denied"),
+		};
+		let end = offset
+			.checked_add(data.len() as u64)
+			.ok_or_else(|| anyhow!("length overflow"))?;
+		if end > size {
+			bail!("Write exceeds announced file size");
+		}
+		let file = target
+			.into_file()
+			.await
+			.map_err(|err| anyhow!("open failed: {err}"))?;
+		let ack = write_chunk(fs::File::from_std(file), offset, data).await?;
+		let now = self.clock.now();
+		if let Some(upload) = self.inbox_uploads.get_mut(path) {
+			upload.last_write = now;
+		}
+		if end == size
+			&& let Some(upload) = self.inbox_uploads.remove(path)
+		{
+			self.record_remote_write(upload.peer, &upload.path).await;
+			self.finish_inbox_upload(upload);
+		}
+		Ok(ack)
+	}
+
+	/// Whether `peer` may see the file at `path` on this node: it is under a
+	/// folder the peer may read and no pattern hides it. This node itself
+	/// sees all of its files.
+	fn exposes(&self, peer: PeerId, hidden: &HiddenNames, path: &Path) -> bool {
+		peer == self.state.me
+			|| (self.can_access(peer, path, FLAG_READ) && !hidden.hides(path, false))
+	}
+
+	/// Only peers that may send into the inbox may ask which files and
+	/// blocks this node holds, and only files they are
+	/// [exposed](Self::exposes) to count.
+	fn may_negotiate(&self, peer: PeerId) -> bool {
+		peer == self.state.me || self.state.has_inbox_grant(&peer)
+	}
+
+	fn have_hashes(&self, peer: PeerId, hashes: &[FileHash]) -> Result<Vec<u8>> {
+		if !self.may_negotiate(peer) {
+			bail!("Access denied");
+		}
+		let node_id = self
+			.local_node_id()
+			.ok_or_else(|| anyhow!("no local node id"))?;
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		let hidden = self.state.hidden(peer, self.clock.now());
+		hashes_held(&conn, &node_id, hashes, |path| {
+			self.exposes(peer, &hidden, path)
+		})
+	}
+
+	fn have_blocks(&self, peer: PeerId, block_size: u64, blocks: &[FileHash]) -> Result<Vec<u8>> {
+		if !self.may_negotiate(peer) {
+			bail!("Access denied");
+		}
+		let node_id = self
+			.local_node_id()
+			.ok_or_else(|| anyhow!("no local node id"))?;
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		let hidden = self.state.hidden(peer, self.clock.now());
+		blocks_held(&conn, &node_id, block_size, blocks, |path| {
+			self.exposes(peer, &hidden, path)
+		})
+	}
+
+	/// Writes this node's own copy of the block hashing to `block_hash` at
+	/// `offset` of the inbox upload at `path`, opened as `target`, from a
+	/// file `peer` is [exposed](Self::exposes) to.
+	async fn write_known_block(
+		&mut self,
+		peer: PeerId,
+		path: &str,
+		target: WriteTarget,
+		offset: u64,
+		block_hash: &FileHash,
+	) -> Result<FileWriteAck> {
+		let found = {
+			let node_id = self
+				.local_node_id()
+				.ok_or_else(|| anyhow!("no local node id"))?;
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			let hidden = self.state.hidden_names(peer, self.clock.now());
+			find_block(&conn, &node_id, block_hash, |path| {
+				self.exposes(peer, &hidden, path)
+			})?
+		};
+		let Some((source, source_offset)) = found else {
+			bail!("block not held");
+		};
+		let data = read_known_block(&source, source_offset, block_hash).await?;
+		self.write_inbox_chunk(path, target, offset, &data).await
+	}
+
+	fn send_hello(&mut self, peer: PeerId) {
+		let local = PeerCapabilities::local();
+		let request_id = self.send_peer_request(
+			&peer,
+			PeerReq::Hello {
+				protocol_version: local.protocol_version,
+				features: local.features,
+			},
+		);
+		self.pending_requests.insert(
+			request_id,
+			PendingHello::new(peer, Arc::clone(&self.clock), self.internal_tx.clone()),
+		);
+	}
+
+	fn learn_wake_target(&mut self, peer: PeerId, remote_ip: Option<std::net::IpAddr>) {
+		let request_id = self.send_peer_request(&peer, PeerReq::ListInterfaces);
+		self.pending_requests.insert(
+			request_id,
+			PendingWakeTarget::new(peer, remote_ip, self.db.clone(), Arc::clone(&self.clock)),
+		);
+	}
+
+	/// Adds a clock sample from `peer` and records the new estimate.
+	fn record_clock_sample(&mut self, peer: PeerId, sample: ClockSample) {
+		let offset = self.state.clock_offsets.entry(peer).or_default();
+		let was_skewed = offset.is_skewed();
+		if !offset.record(sample) {
+			tracing::debug!(
+				"dropping clock sample from {peer}: {}ms round trip",
+				sample.rtt_ms
+			);
+			return;
+		}
+		let Some(offset_ms) = offset.offset_ms() else {
+			return;
+		};
+		if let Some(skew) = offset.describe()
+			&& !was_skewed
+		{
+			tracing::warn!("{}: {skew}", self.state.peer_label(&peer));
+		}
+		let now = self.clock.now();
+		let record = ClockOffsetRecord {
+			sampled_at: now,
+			offset_ms,
+			sample_offset_ms: sample.offset_ms,
+			rtt_ms: sample.rtt_ms,
+		};
+		if let Ok(conn) = self.db.lock() {
+			let retained = now - chrono::Duration::days(CLOCK_OFFSET_RETENTION_DAYS);
+			if let Err(err) = record_clock_offset(&conn, &peer, &record)
+				.and_then(|_| prune_clock_offsets(&conn, retained))
+			{
+				tracing::warn!("failed to record clock offset of {peer}: {err}");
+			}
+		}
+	}
+
+	/// Name chosen in the setup wizard, or the host name until one is set.
+	fn local_node_name(&self) -> String {
+		let stored = match self.db.lock() {
+			Ok(conn) => load_setting(&conn, NODE_NAME_SETTING).unwrap_or_else(|err| {
+				tracing::error!("failed to load node name: {err}");
+				None
+			}),
+			Err(_) => None,
+		};
+		stored.unwrap_or_else(|| System::host_name().unwrap_or_else(|| String::from("local-node")))
+	}
+
+	fn send_pairing_message(&mut self, peer: PeerId, req: PeerReq) {
+		let addresses = self.known_peer_addresses(&peer);
+		let request_id = self
+			.swarm
+			.behaviour_mut()
+			.puppynet
+			.send_request_with_addresses(&peer, req, addresses);
+		self.pending_requests.insert(
+			request_id,
+			PendingPairing::new(peer, self.internal_tx.clone()),
+		);
+	}
+
+	fn set_trust_level(&mut self, peer: PeerId, trust: TrustLevel) -> anyhow::Result<()> {
+		{
+			let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+			crate::db::save_trust_level(&conn, &peer, trust)?;
+		}
+		self.state.set_trust_level(peer, trust);
+		tracing::info!("[{}] trust level set to {}", peer, trust.as_str());
+		Ok(())
+	}
+
+	/// Grants `peer` read and search on every folder of `offered` it cannot
+	/// read and search yet. Returns the permissions added.
+	fn grant_pairing_access(
+		&mut self,
+		peer: PeerId,
+		offered: &[FolderRule],
+	) -> anyhow::Result<Vec<Permission>> {
+		// Pairing is the user accepting the peer, which has to reach at
+		// least the folders it grants.
+		if self.state.trust_level(&peer) < TrustLevel::Basic {
+			self.set_trust_level(peer, TrustLevel::Basic)?;
+		}
+		let mut permissions = self.state.permissions_granted_to_peer(&peer);
+		let existing = permissions
+			.iter()
+			.filter_map(|permission| match permission.rule() {
+				Rule::Folder(rule) => Some(rule.clone()),
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+		let added = missing_pairing_rules(offered, &existing)
+			.into_iter()
+			.map(|rule| Permission::new(Rule::Folder(rule)))
+			.collect::<Vec<_>>();
+		if added.is_empty() {
+			return Ok(added);
+		}
+		permissions.extend(added.iter().cloned());
+		{
+			let mut conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+			crate::db::save_peer_permissions_at(
+				&mut conn,
+				&self.state.me,
+				&peer,
+				&permissions,
+				None,
+			)?;
+		}
+		self.state.set_peer_permissions(peer, permissions);
+		self.notify_permissions_changed(peer);
+		Ok(added)
+	}
+
+	fn receive_pair_request(&mut self, peer: PeerId, node_name: String) -> PeerRes {
+		let node_name = peer_node_name(&node_name);
+		tracing::info!("[{}] PairRequest from {:?}", peer, node_name);
+		// When both users pressed pair at once, the lower peer id confirms
+		// and the other keeps waiting, so neither side waits forever.
+		let keep_outgoing = self.state.pairings.get(&peer).is_some_and(|pairing| {
+			pairing.status == PairingStatus::AwaitingPeer
+				&& self.state.me.to_bytes() > peer.to_bytes()
+		});
+		if keep_outgoing {
+			if let Some(pairing) = self.state.pairings.get_mut(&peer) {
+				pairing.peer_name = node_name;
+			}
+		} else {
+			let pairing = Pairing::new(
+				&self.state.me,
+				peer,
+				node_name.clone(),
+				PairingDirection::Incoming,
+				self.clock.now(),
+			);
+			let label = if node_name.is_empty() {
+				peer.to_string()
+			} else {
+				node_name
+			};
+			self.state.push_notification(
+				peer,
+				format!(
+					"{label} wants to pair with this device; confirm code {} in setup",
+					pairing.code
+				),
+			);
+			self.state.pairings.insert(peer, pairing);
+		}
+		PeerRes::PairPending {
+			node_name: self.local_node_name(),
+		}
+	}
+
+	/// Fails pairings nobody answered in time, so a late answer grants
+	/// nothing.
+	fn expire_pairings(&mut self) {
+		for peer in expire_pairings(&mut self.state.pairings, self.clock.now()) {
+			tracing::info!("[{}] pairing expired before both sides confirmed", peer);
+		}
+	}
+
+	/// The peer's answer to a pairing this node asked for. Accepting only
+	/// moves the pairing on to the user of this node, who still has to
+	/// confirm the code before anything is granted.
+	fn receive_pair_response(&mut self, peer: PeerId, accepted: bool) -> PeerRes {
+		self.expire_pairings();
+		let awaiting = self
+			.state
+			.pairings
+			.get(&peer)
+			.is_some_and(|pairing| pairing.status == PairingStatus::AwaitingPeer);
+		if !awaiting {
+			tracing::warn!("[{}] PairResponse without a pairing in progress", peer);
+			return PeerRes::Error(String::from("no pairing in progress"));
+		}
+		tracing::info!("[{}] PairResponse accepted={}", peer, accepted);
+		if let Some(pairing) = self.state.pairings.get_mut(&peer) {
+			let message = if accepted {
+				pairing.status = PairingStatus::AwaitingYou;
+				format!(
+					"{} confirmed pairing; confirm code {} in setup",
+					peer, pairing.code
+				)
+			} else {
+				pairing.status = PairingStatus::Declined;
+				format!("Pairing with {}: {}", peer, pairing.describe())
+			};
+			self.state.push_notification(peer, message);
+		}
+		PeerRes::PairResponseAck
+	}
+
+	/// A `Pair` from a device holding one of this node's pairing strings:
+	/// a good secret is spent and stands in for the user confirming a code.
+	fn receive_pair(
+		&mut self,
+		peer: PeerId,
+		secret: Vec<u8>,
+		requested_permissions: Vec<Permission>,
+	) -> PeerRes {
+		let now = self.clock.now();
+		if let Err(reason) = self.pairing_secrets.redeem(&secret, now) {
+			tracing::warn!("[{}] Pair refused: {}", peer, reason);
+			return PeerRes::PairRejected { reason };
+		}
+		let offered = requested_pairing_folders(&self.state.shared_folders, &requested_permissions);
+		let granted = match self.grant_pairing_access(peer, &offered) {
+			Ok(granted) => granted,
+			Err(err) => {
+				tracing::error!("failed to grant pairing access to {peer}: {err}");
+				return PeerRes::Error(String::from("failed to grant access"));
+			}
+		};
+		tracing::info!(
+			"[{}] paired with a pairing string; granted {} folder(s)",
+			peer,
+			granted.len()
+		);
+		let mut pairing = Pairing::new(
+			&self.state.me,
+			peer,
+			String::new(),
+			PairingDirection::Incoming,
+			now,
+		);
+		pairing.status = PairingStatus::Paired;
+		self.state.pairings.insert(peer, pairing);
+		self.state.push_notification(
+			peer,
+			format!("{peer} paired with this device using a pairing string"),
+		);
+		PeerRes::Paired {
+			node_name: self.local_node_name(),
+			granted,
+		}
+	}
+
+	/// A pairing string for this node with a fresh secret.
+	fn pairing_info(&mut self) -> anyhow::Result<PairingInfo> {
+		let ttl = self.db.read(secret_ttl);
+		let external = self
+			.nat_external_addrs
+			.iter()
+			.chain(self.swarm.external_addresses())
+			.cloned()
+			.collect::<Vec<_>>();
+		let listeners = self.swarm.listeners().cloned().collect::<Vec<_>>();
+		let addrs = invite_addrs(&self.state.reachability, external, listeners);
+		if addrs.is_empty() {
+			bail!("this device is not listening on any address other devices can reach");
+		}
+		let (secret, expires_at) = self.pairing_secrets.issue(self.clock.now(), ttl);
+		let invite = PairingInvite {
+			peer_id: self.state.me,
+			addrs,
+			secret,
+		};
+		Ok(PairingInfo {
+			peer_id: invite.peer_id.to_string(),
+			addrs: invite.addrs.iter().map(Multiaddr::to_string).collect(),
+			expires_at,
+			pairing_string: invite.encode(),
+		})
+	}
+
+	fn record_peer_address(&mut self, peer: &PeerId, addr: &Multiaddr) {
+		let peer_id = *peer;
+		let multiaddr = addr.clone();
+		self.discovered
+			.record(peer_id, multiaddr.clone(), self.clock.now());
+		self.discovery_writes.seen(peer_id, multiaddr);
+	}
+
+	fn known_peer_addresses(&self, peer: &PeerId) -> Vec<Multiaddr> {
+		dial_order(self.discovered.addresses(peer))
+	}
+
+	async fn process_shell_input(
+		&mut self,
+		session_id: u64,
+		data: &[u8],
+		peer: Option<PeerId>,
+	) -> anyhow::Result<ShellOutput> {
+		let key = (peer.unwrap_or(self.state.me), session_id);
+		let Some(session) = self.shell_sessions.get_mut(&key) else {
+			if let Some(peer_id) = peer {
+				tracing::warn!(
+					"peer {} requested missing shell session {}",
+					peer_id,
+					session_id
+				);
+			}
+			return Err(anyhow!("shell session not found"));
+		};
+
+		if !data.is_empty() {
+			if let Err(err) = session.stdin.write_all(data).await {
+				self.shell_sessions.remove(&key);
+				if let Some(peer_id) = peer {
+					tracing::warn!(
+						"[{}] shell stdin failed for session {}: {err}",
+						peer_id,
+						session_id
+					);
+				}
+				return Err(anyhow!("shell stdin failed: {err}"));
+			}
+			let _ = session.stdin.flush().await;
+		}
+
+		let mut out = Vec::new();
+		let mut buf = [0u8; 8192];
+		loop {
+			match timeout(Duration::from_millis(40), session.stdout.read(&mut buf)).await {
+				Ok(Ok(0)) => {
+					self.shell_sessions.remove(&key);
+					return Ok(ShellOutput::Exited);
+				}
+				Ok(Ok(n)) => {
+					out.extend_from_slice(&buf[..n]);
+					if out.len() >= 64 * 1024 {
+						break;
+					}
+				}
+				Ok(Err(err)) => {
+					self.shell_sessions.remove(&key);
+					if let Some(peer_id) = peer {
+						tracing::warn!(
+							"[{}] shell stdout failed for session {}: {err}",
+							peer_id,
+							session_id
+						);
+					}
+					return Err(anyhow!("shell stdout failed: {err}"));
+				}
+				Err(_) => break,
+			}
+		}
+
+		Ok(ShellOutput::Output(out))
+	}
+
+	pub fn new(
+		id_keys: libp2p::identity::Keypair,
+		mut state: State,
+		deps: AppDeps,
+	) -> (Self, tokio::sync::mpsc::UnboundedSender<Command>) {
+		let AppDeps {
+			db,
+			remote_scans,
+			remote_searches,
+			remote_updates,
+			store,
+			clock,
+			request_log,
+			thumbnail_queue,
+			maintenance,
+			protocol_rates,
+			activity,
+		} = deps;
+		let peer_id = PeerId::from(id_keys.public());
+
+		let policy = ConnectionPolicy::load(&db.lock().unwrap());
+		let mut swarm = build_swarm(id_keys, peer_id, policy).unwrap();
+		let stored_permissions = {
+			let conn = db.lock().unwrap();
+			match load_peer_permissions(&conn, &peer_id) {
+				Ok(perms) => perms,
+				Err(err) => {
+					tracing::error!("failed to load peer permissions: {err}");
+					Vec::new()
+				}
+			}
+		};
+		let stored_peers = {
+			let conn = db.lock().unwrap();
+			load_peers(&conn).unwrap_or_else(|err| {
+				tracing::error!("failed to load peers: {err}");
+				Vec::new()
+			})
+		};
+		let stored_discovered = {
+			let conn = db.lock().unwrap();
+			load_discovered_peers(&conn).unwrap_or_else(|err| {
+				tracing::error!("failed to load discovered peers: {err}");
+				Vec::new()
+			})
+		};
+		let grant_cache = {
+			let conn = db.lock().unwrap();
+			let ttl = match load_setting(&conn, GRANT_CACHE_TTL_SETTING) {
+				Ok(value) => grant_cache::ttl_from_setting(value.as_deref()),
+				Err(err) => {
+					tracing::error!("failed to load grant cache ttl: {err}");
+					grant_cache::ttl_from_setting(None)
+				}
+			};
+			let mut cache = GrantCache::new(ttl);
+			match load_remote_grants(&conn) {
+				Ok(stored) => cache.load(stored),
+				Err(err) => tracing::error!("failed to load cached grants: {err}"),
+			}
+			cache
+		};
+		let scan_limits = Arc::new(NodeScanLimits::load(&db.lock().unwrap()));
+		let discovered_address_ttl = {
+			let conn = db.lock().unwrap();
+			match load_setting(&conn, DISCOVERED_ADDRESS_TTL_SETTING) {
+				Ok(value) => ttl_from_setting(value.as_deref()),
+				Err(err) => {
+					tracing::error!("failed to load discovered address ttl: {err}");
+					ttl_from_setting(None)
+				}
+			}
+		};
+		let discovery_flush_interval = {
+			let conn = db.lock().unwrap();
+			match load_setting(&conn, DISCOVERY_FLUSH_SETTING) {
+				Ok(value) => flush_interval_from_setting(value.as_deref()),
+				Err(err) => {
+					tracing::error!("failed to load discovery flush interval: {err}");
+					flush_interval_from_setting(None)
+				}
+			}
+		};
+		let stored_users = {
+			let conn = db.lock().unwrap();
+			match load_users(&conn) {
+				Ok(users) => users,
+				Err(err) => {
+					tracing::error!("failed to load users: {err}");
+					Vec::new()
+				}
+			}
+		};
+		let stored_shared_folders = {
+			let conn = db.lock().unwrap();
+			match load_shared_folders(&conn) {
+				Ok(folders) => folders,
+				Err(err) => {
+					tracing::error!("failed to load shared folders: {err}");
+					Vec::new()
+				}
+			}
+		};
+		let remote_access_suspended = {
+			let conn = db.lock().unwrap();
+			match load_setting(&conn, REMOTE_ACCESS_SUSPENDED_SETTING) {
+				Ok(value) => value.as_deref() == Some("true"),
+				Err(err) => {
+					tracing::error!("failed to load remote access suspension: {err}");
+					false
+				}
+			}
+		};
+		let reachability = {
+			let conn = db.lock().unwrap();
+			match load_setting(&conn, REACHABILITY_SETTING) {
+				Ok(value) => value
+					.and_then(|json| serde_json::from_str(&json).ok())
+					.unwrap_or_default(),
+				Err(err) => {
+					tracing::error!("failed to load reachability results: {err}");
+					Vec::new()
+				}
+			}
+		};
+		let nat_mapping = {
+			let conn = db.lock().unwrap();
+			match load_setting(&conn, NAT_MAPPING_SETTING) {
+				Ok(value) => value.as_deref() == Some("true"),
+				Err(err) => {
+					tracing::error!("failed to load port mapping setting: {err}");
+					false
+				}
+			}
+		};
+		let thumbnail_concurrency = {
+			let conn = db.lock().unwrap();
+			match load_setting(&conn, THUMBNAIL_CONCURRENCY_SETTING) {
+				Ok(value) => concurrency_from_setting(value.as_deref()),
+				Err(err) => {
+					tracing::error!("failed to load thumbnail concurrency: {err}");
+					concurrency_from_setting(None)
+				}
+			}
+		};
+		let decode_budget = {
+			let conn = db.lock().unwrap();
+			match load_setting(&conn, THUMBNAIL_DECODE_BUDGET_SETTING) {
+				Ok(value) => budget_from_setting(value.as_deref()),
+				Err(err) => {
+					tracing::error!("failed to load thumbnail decode budget: {err}");
+					budget_from_setting(None)
+				}
+			}
+		};
+		let important_peers = load_important_peers(&db.lock().unwrap());
+		let access = AccessMonitor::load(&db.lock().unwrap(), clock.now().date_naive());
+		let mut keeper = ConnectionKeeper::default();
+		for peer in &important_peers {
+			keeper.redial_soon(*peer, std::time::Instant::now());
+		}
+		let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+		let (internal_tx, internal_rx) = tokio::sync::mpsc::unbounded_channel();
+
+		for listen_addr in listen_addrs() {
+			if let Err(err) = swarm.listen_on(listen_addr.clone()) {
+				tracing::warn!("failed to start swarm listener on {listen_addr}: {err}");
+			}
+		}
+		state.me = peer_id;
+		state.peers = stored_peers;
+		let discovery_writes =
+			DiscoveryWrites::new(&stored_discovered, discovery_flush_interval, clock.now());
+		let mut discovered = DiscoveredPeers::new(discovered_address_ttl);
+		discovered.load(stored_discovered, clock.now());
+		for (target, permissions) in stored_permissions {
+			state.set_peer_permissions_from_storage(target, permissions);
+		}
+		for folder in stored_shared_folders {
+			state.add_shared_folder(folder);
+		}
+		state.remote_access_suspended = remote_access_suspended;
+		state.maintenance = maintenance.active();
+		state.reachability = reachability;
+		state.important_peers = important_peers;
+		let mut app = App {
+			state,
+			discovered,
+			discovery_writes,
+			users: stored_users,
+			swarm,
+			rx,
+			internal_rx,
+			internal_tx,
+			fairness: Fairness::default(),
+			inbound_jobs: Arc::new(Semaphore::new(MAX_INBOUND_JOBS)),
+			pending_requests: HashMap::new(),
+			request_log,
+			outbound_traces: HashMap::new(),
+			system: System::new(),
+			db,
+			remote_scans,
+			remote_searches,
+			remote_updates,
+			shell_sessions: HashMap::new(),
+			inbox_uploads: HashMap::new(),
+			disk_cache: DiskCache::default(),
+			inbox_store: env::var_os("PUPPYNET_INBOX_STORE").map(|_| store),
+			thumbnails: Arc::new(Mutex::new(ThumbnailCache::default())),
+			thumbnail_disk: Arc::new(ThumbnailDisk::new(ThumbnailDisk::default_dir())),
+			decode_limits: Arc::new(DecodeLimits::for_this_machine(
+				thumbnail_concurrency,
+				decode_budget,
+			)),
+			thumbnail_queue,
+			maintenance,
+			share_changes: broadcast::channel(SHARE_CHANGE_CAPACITY).0,
+			clock,
+			started_at: std::time::Instant::now(),
+			last_restart_error: None,
+			dialer: Dialer::default(),
+			nat_mapper: None,
+			nat_external_addrs: Vec::new(),
+			listen_ports: NatPorts::default(),
+			pairing_secrets: PairingSecrets::default(),
+			dial_backs: HashMap::new(),
+			dial_back_limiter: DialBackLimiter::default(),
+			grant_cache,
+			scan_limits,
+			grant_refetches: HashSet::new(),
+			grant_checks: HashMap::new(),
+			index_announcer: AnnounceCoalescer::default(),
+			index_pulls: IndexPulls::default(),
+			protocol: ProtocolMonitor::default(),
+			protocol_rates,
+			access,
+			outbound_counters: HashMap::new(),
+			outbound_peers: HashMap::new(),
+			keeper,
+			activity,
+		};
+		app.resume_identity_adoption();
+		app.normalize_file_location_node_ids();
+		app.persist_local_node();
+		Self::spawn_disk_sampler(
+			app.db.clone(),
+			Arc::clone(&app.clock),
+			app.internal_tx.clone(),
+		);
+		Self::spawn_grant_sweeper(app.internal_tx.clone());
+		Self::spawn_discovery_flusher(app.internal_tx.clone());
+		Self::spawn_clock_sampler(app.internal_tx.clone());
+		Self::spawn_connection_keeper(app.internal_tx.clone(), policy);
+		Self::spawn_mount_checker(app.internal_tx.clone());
+		Self::spawn_index_announcer(app.internal_tx.clone());
+		Self::spawn_protocol_rollup(app.internal_tx.clone());
+		Self::spawn_thumbnail_pregenerator(
+			Arc::downgrade(&app.thumbnail_queue),
+			Arc::clone(&app.thumbnail_disk),
+			Arc::clone(&app.decode_limits),
+		);
+		if nat_mapping {
+			app.start_nat_mapper();
+		}
+		(app, tx)
+	}
+
+	fn local_os_label() -> String {
+		let name = System::name().unwrap_or_else(|| std::env::consts::OS.to_string());
+		match System::os_version() {
+			Some(version) if !version.trim().is_empty() => format!("{name} {version}"),
+			_ => name,
+		}
+	}
+
+	fn local_peer_info() -> PeerInfo {
+		PeerInfo {
+			version: version::version_label(),
+			os: Self::local_os_label(),
+			uptime_seconds: System::uptime(),
+		}
+	}
+
+	/// The local path a filesystem request names, or the refusal saying
+	/// which rule it broke. Runs before any filesystem call or access check.
+	fn request_path(peer: PeerId, path: &WirePath) -> Result<PathBuf, String> {
+		SafePath::from_wire(path)
+			.and_then(|path| path.to_local())
+			.map_err(|err| {
+				tracing::warn!("[{}] rejected path {}: {err}", peer, path);
+				err.to_string()
+			})
+	}
+
+	/// Tells `peer` to ask again in `retry_after_secs`, typed when it
+	/// announced [`FEATURE_RATE_LIMIT`].
+	fn rate_limited(&self, peer: PeerId, retry_after_secs: u64) -> PeerRes {
+		let typed = self
+			.state
+			.peer_capabilities(&peer)
+			.is_some_and(|capabilities| capabilities.supports(FEATURE_RATE_LIMIT));
+		if typed {
+			PeerRes::RateLimited { retry_after_secs }
+		} else {
+			PeerRes::Error(
+				RateLimited {
+					retry_after: Duration::from_secs(retry_after_secs),
+				}
+				.to_string(),
+			)
+		}
+	}
+
+	/// The refusal for `req` while remote access is suspended, during
+	/// maintenance or when this build lacks it.
+	fn request_refusal(&self, peer: PeerId, req: &PeerReq) -> Option<PeerRes> {
+		if self.state.remote_access_suspended && peer != self.state.me && req.touches_filesystem() {
+			tracing::info!(
+				"[{}] refused request while remote access is suspended",
+				peer
+			);
+			return Some(PeerRes::Error(REMOTE_ACCESS_SUSPENDED.to_string()));
+		}
+		if req.mutates()
+			&& let Err(refused) = self.maintenance.check()
+		{
+			tracing::info!("[{}] refused {} during maintenance", peer, req.name());
+			return Some(PeerRes::Error(refused.to_string()));
+		}
+		if !req.compiled_in() {
+			tracing::info!("[{}] {} is not built into this node", peer, req.name());
+			return Some(PeerRes::Unsupported {
+				request: req.name().to_string(),
+			});
+		}
+		None
+	}
+
+	/// Answers `req` once [`Self::request_refusal`] let it through.
+	async fn handle_puppy_peer_req(
+		&mut self,
+		peer: PeerId,
+		req: PeerReq,
+	) -> anyhow::Result<PeerRes> {
+		let res = match req {
+			PeerReq::PeerInfo => PeerRes::PeerInfo(Self::local_peer_info()),
+			PeerReq::ListDir { path } => match self.list_dir_job(peer, path).await {
+				Ok(job) => job.run().await,
+				Err(refused) => refused,
+			},
+			PeerReq::StatFile { path } => {
+				tracing::info!("[{}] StatFile {}", peer, path);
+				let path = match Self::request_path(peer, &path) {
+					Ok(path) => path,
+					Err(err) => return Ok(PeerRes::Error(err)),
+				};
+				if let Some(offline) = self.offline_share(peer, &path, FLAG_PREVIEW | FLAG_SEARCH) {
+					return Ok(offline);
+				}
+				let opened = match safe_open::open(&path).await {
+					Ok(opened) => opened,
 					Err(err) => {
-						log::warn!("failed to canonicalize read path {}: {err}", path);
+						tracing::warn!("failed to open file {}: {err}", path.display());
 						return Ok(PeerRes::Error(format!("Failed to access file: {err}")));
 					}
 				};
-				if !self.can_access(peer, &canonical, FLAG_READ | FLAG_SEARCH) {
-					log::warn!("peer {} denied read for {}", peer, canonical.display());
-					return Ok(PeerRes::Error("Access denied".into()));
+				let canonical = opened.canonical();
+				let metadata = opened.metadata()?;
+				if self.hidden_from(peer, canonical, metadata.is_dir()) {
+					return Ok(hidden_path("file"));
 				}
-				PeerRes::FileChunk(read_file(canonical.as_path(), offset, length).await?)
+				if !self.can_access(peer, canonical, FLAG_PREVIEW | FLAG_SEARCH) {
+					tracing::warn!("peer {} denied stat for {}", peer, canonical.display());
+					return Ok(self.access_denied(peer, canonical, FLAG_PREVIEW | FLAG_SEARCH));
+				}
+				PeerRes::FileStat(self.stat_local_entry(canonical, &metadata).await)
 			}
+			PeerReq::ReadFile {
+				path,
+				offset,
+				length,
+				sparse,
+			} => match self.read_job(peer, path, offset, length, sparse).await {
+				Ok(job) => job.run().await,
+				Err(refused) => refused,
+			},
 			PeerReq::WriteFile { path, offset, data } => {
-				log::info!(
+				tracing::info!(
 					"[{}] WriteFile {} (offset {}, {} bytes)",
 					peer,
 					path,
 					offset,
 					data.len()
 				);
-				let requested_path = PathBuf::from(&path);
-				let canonical = match fs::metadata(&requested_path).await {
-					Ok(_) => match fs::canonicalize(&requested_path).await {
-						Ok(p) => p,
-						Err(err) => {
-							log::warn!("failed to canonicalize write path {}: {err}", path);
-							return Ok(PeerRes::Error(format!("Failed to access file: {err}")));
-						}
-					},
-					Err(_) => {
-						let parent = match requested_path.parent() {
-							Some(p) => p,
-							None => {
-								log::warn!("peer {} provided invalid write path {}", peer, path);
-								return Ok(PeerRes::Error("Invalid path".into()));
-							}
-						};
-						let canonical_parent = match fs::canonicalize(parent).await {
-							Ok(p) => p,
-							Err(err) => {
-								log::warn!(
-									"failed to canonicalize parent {} for write: {err}",
-									parent.display()
-								);
-								return Ok(PeerRes::Error(format!(
-									"Failed to access parent directory: {err}"
-								)));
-							}
-						};
-						match requested_path.file_name() {
-							Some(name) => canonical_parent.join(name),
-							None => {
-								log::warn!(
-									"peer {} provided invalid file name in path {}",
-									peer,
-									path
-								);
-								return Ok(PeerRes::Error("Invalid file name".into()));
-							}
-						}
+				let requested_path = match Self::request_path(peer, &path) {
+					Ok(path) => path,
+					Err(err) => return Ok(PeerRes::Error(err)),
+				};
+				let target = match WriteTarget::open(&requested_path).await {
+					Ok(target) => target,
+					Err(err) => {
+						tracing::warn!("failed to open write path {}: {err}", path);
+						return Ok(PeerRes::Error(format!("Failed to access file: {err}")));
 					}
 				};
+				let canonical = target.canonical();
+				if self.hidden_from(peer, &canonical, false) {
+					return Ok(hidden_path("file"));
+				}
 				if !self.can_access(peer, &canonical, FLAG_WRITE | FLAG_READ | FLAG_SEARCH) {
-					log::warn!("peer {} denied write for {}", peer, canonical.display());
-					return Ok(PeerRes::Error("Access denied".into()));
+					tracing::warn!("peer {} denied write for {}", peer, canonical.display());
+					return Ok(self.access_denied(
+						peer,
+						&canonical,
+						FLAG_WRITE | FLAG_READ | FLAG_SEARCH,
+					));
+				}
+				let review = self
+					.state
+					.write_needs_review(peer, &canonical, self.clock.now());
+				if review && self.take_rejected_review(peer, &canonical) {
+					return Ok(PeerRes::Error(WRITE_REJECTED.to_string()));
+				}
+				let file = match target.into_file().await {
+					Ok(file) => fs::File::from_std(file),
+					Err(err) => return Err(anyhow!("open failed: {err}")),
+				};
+				let ack = write_chunk(file, offset, &data).await?;
+				self.invalidate_derived(&canonical);
+				self.record_remote_write(peer, &canonical).await;
+				if review {
+					self.queue_review(peer, &canonical);
 				}
-				PeerRes::WriteAck(write_file(canonical.as_path(), offset, &data).await?)
+				PeerRes::WriteAck(ack)
 			}
 			PeerReq::ListCpus => {
 				let cpus = self.collect_cpu_info();
 				PeerRes::Cpus(cpus)
 			}
 			PeerReq::ListDisks => {
-				let disks = self.collect_disk_info();
+				let disks = Self::collect_disk_info();
 				PeerRes::Disks(disks)
 			}
+			PeerReq::DiskHistory { mount, from, to } => match self.disk_history(&mount, from, to) {
+				Ok(samples) => PeerRes::DiskHistory(samples),
+				Err(err) => PeerRes::Error(err.to_string()),
+			},
+			PeerReq::ListRoots => PeerRes::Roots(self.collect_browse_roots(peer)),
+			PeerReq::WellKnownFolders => {
+				PeerRes::WellKnownFolders(self.collect_well_known_folders(peer))
+			}
 			PeerReq::ListInterfaces => {
 				let interfaces = self.collect_interface_info();
 				PeerRes::Interfaces(interfaces)
@@ -1355,27 +5397,43 @@ impl App {
 				PeerRes::Error(String::from("GetMediaFrame must be handled asynchronously"))
 			}
 			PeerReq::FileEntries { offset, limit } => {
-				match self.fetch_file_entries(offset, limit) {
+				match self.fetch_file_entries(peer, offset, limit) {
 					Ok(entries) => PeerRes::FileEntries(entries),
 					Err(err) => {
-						log::error!("failed to load file entries: {err}");
+						tracing::error!("failed to load file entries: {err}");
 						PeerRes::Error(format!("failed to load file entries: {err}"))
 					}
 				}
 			}
-			PeerReq::StartScan { id, path } => {
-				let requested_path = PathBuf::from(&path);
-				let canonical = match fs::canonicalize(&requested_path).await {
+			PeerReq::StartScan { id, path, limits } => {
+				let requested_path = match Self::request_path(peer, &WirePath::Display(path)) {
 					Ok(path) => path,
+					Err(err) => return Ok(PeerRes::ScanStarted(Err(err))),
+				};
+				if let Some(PeerRes::Unavailable { share }) =
+					self.offline_share(peer, &requested_path, FLAG_READ | FLAG_SEARCH)
+				{
+					return Ok(PeerRes::ScanStarted(Err(format!(
+						"share not mounted: {share}"
+					))));
+				}
+				let root = match safe_open::open(&requested_path).await {
+					Ok(root) => root,
 					Err(err) => {
-						log::warn!("failed to canonicalize scan path {}: {err}", path);
+						tracing::warn!(
+							"failed to open scan path {}: {err}",
+							requested_path.display()
+						);
 						return Ok(PeerRes::ScanStarted(Err(format!(
 							"failed to access path: {err}"
 						))));
 					}
 				};
-				if !self.can_access(peer, &canonical, FLAG_READ | FLAG_SEARCH) {
-					return Ok(PeerRes::ScanStarted(Err(String::from("Access denied"))));
+				let canonical = root.canonical();
+				if !self.can_access(peer, canonical, FLAG_READ | FLAG_SEARCH) {
+					return Ok(PeerRes::ScanStarted(Err(
+						self.access_denied_message(peer, canonical)
+					)));
 				}
 				let node_id = match self.local_node_id() {
 					Some(id) => id,
@@ -1385,10 +5443,25 @@ impl App {
 						))));
 					}
 				};
+				let path_string = canonical.to_string_lossy().to_string();
+				let work = match self
+					.maintenance
+					.begin(format!("scan of {path_string} for {peer}"), None)
+				{
+					Ok(work) => work,
+					Err(refused) => return Ok(PeerRes::ScanStarted(Err(refused.to_string()))),
+				};
 				let db = Arc::clone(&self.db);
 				let internal_tx = self.internal_tx.clone();
-				let path_string = canonical.to_string_lossy().to_string();
 				let target = peer;
+				let started_at = self.clock.now();
+				let thumbnail_queue = Arc::clone(&self.thumbnail_queue);
+				// What the peer asks for is held to this node's limits.
+				let throttle = ScanThrottle::for_peer(
+					self.scan_limits(),
+					limits,
+					self.state.hidden(peer, self.clock.now()),
+				);
 				tokio::spawn(async move {
 					let (progress_tx, mut progress_rx) =
 						tokio::sync::mpsc::unbounded_channel::<ScanEvent>();
@@ -1405,20 +5478,64 @@ impl App {
 						}
 					});
 					let _ = tokio::task::spawn_blocking(move || {
-						let result = db
-							.lock()
-							.map_err(|err| format!("db lock poisoned: {err}"))
-							.and_then(|mut conn| {
-								scan::scan_with_progress(
+						let _work = work;
+						let _scanning = thumbnail_queue.scan_started();
+						// The scan walks the folder by path, so it has to
+						// still be the one that was checked. A swap while
+						// it walks is not caught here: files are opened
+						// before their paths are indexed, but a walk can
+						// still be led into another folder.
+						if let Err(err) = root.verify() {
+							let _ = progress_tx.send(ScanEvent::Finished(Err(format!(
+								"failed to access path: {err}"
+							))));
+							return;
+						}
+						let result = db.write(|conn| {
+							let mut tally = ScanTally::default();
+							let result = scan_and_record(
+								conn,
+								ScanRecordOptions {
+									node_id: &node_id,
+									path: &path_string,
+									started_at,
+									initiated_by: Some(&target),
+									throttle: &throttle,
+								},
+								|progress| {
+									let _ = progress_tx.send(ScanEvent::Progress(progress.clone()));
+									send_index_change(
+										&internal_tx,
+										tally.advance(
+											progress.inserted_count,
+											progress.updated_count,
+											progress.removed_count,
+										),
+									);
+								},
+								|| false,
+							);
+							if let Ok(stats) = &result {
+								send_index_change(
+									&internal_tx,
+									tally.advance(
+										stats.inserted_count,
+										stats.updated_count,
+										stats.removed_count,
+									),
+								);
+								queue_scanned_images(
+									&thumbnail_queue,
+									conn,
 									&node_id,
 									&path_string,
-									&mut *conn,
-									|progress| {
-										let _ =
-											progress_tx.send(ScanEvent::Progress(progress.clone()));
-									},
-								)
+								);
+							}
+							let _ = internal_tx.send(InternalCommand::InvalidateDerived {
+								paths: stale_scan_paths(&result),
 							});
+							result
+						});
 						let final_event = match result {
 							Ok(stats) => ScanEvent::Finished(Ok(stats)),
 							Err(err) => ScanEvent::Finished(Err(err)),
@@ -1431,7 +5548,8 @@ impl App {
 				PeerRes::ScanStarted(Ok(()))
 			}
 			PeerReq::StartSearch { id, args } => {
-				let roots = self.state.search_roots_for_peer(&peer);
+				let roots = self.state.roots(peer, FLAG_SEARCH);
+				let hidden = self.state.hidden(peer, self.clock.now());
 				let target = peer;
 				let internal_tx = self.internal_tx.clone();
 				tokio::task::spawn_blocking(move || {
@@ -1446,7 +5564,7 @@ impl App {
 						});
 						return;
 					}
-					live_search_roots(roots, args, |event| {
+					live_search_roots(roots, args, hidden, |event| {
 						let _ = internal_tx.send(InternalCommand::SendSearchEvent {
 							target,
 							search_id: id,
@@ -1467,24 +5585,18 @@ impl App {
 						map.remove(&id);
 					}
 				} else {
-					log::warn!("received search event for unknown id {}", id);
+					tracing::warn!("received search event for unknown id {}", id);
 				}
 				PeerRes::SearchEventAck
 			}
 			PeerReq::ScanEvent { id, event } => {
-				let mut map = self.remote_scans.lock().unwrap();
-				if let Some(tx) = map.get(&id) {
-					let _ = tx.send(event.clone());
-					if matches!(event, ScanEvent::Finished(_)) {
-						map.remove(&id);
-					}
-				} else {
-					log::warn!("received scan event for unknown id {}", id);
+				if !self.remote_scans.deliver(id, event, self.clock.now()) {
+					tracing::warn!("received scan event for unknown id {}", id);
 				}
 				PeerRes::ScanEventAck
 			}
 			PeerReq::ListPermissions => {
-				log::info!("[{}] ListPermissions", peer);
+				tracing::info!("[{}] ListPermissions", peer);
 				let permissions = self.state.permissions_for_peer(&peer);
 				PeerRes::Permissions(permissions)
 			}
@@ -1498,40 +5610,14 @@ impl App {
 				roles: _,
 				permissions: _,
 			} => {
-				let passw = match auth::hash_password(&password) {
-					Ok(hash) => hash,
-					Err(err) => {
-						log::error!("failed to hash password for {}: {}", username, err);
-						return Ok(PeerRes::Error("Failed to hash password".into()));
-					}
-				};
-				let user = User {
-					name: username.clone(),
-					passw,
+				let user = match self.check_new_user(&username, &password) {
+					Ok(name) => match hash_new_user(name, password).await {
+						Ok(user) => self.save_new_user(user),
+						Err(err) => Err(err),
+					},
+					Err(err) => Err(err),
 				};
-				if self.state.users.iter().any(|u| u.name == user.name) {
-					return Ok(PeerRes::Error("User already exists".into()));
-				}
-				match self.db.lock() {
-					Ok(mut conn) => {
-						if let Err(err) = crate::db::save_user(&mut *conn, &user) {
-							log::error!("failed to persist user {}: {}", user.name, err);
-							return Ok(PeerRes::Error("Failed to save user".into()));
-						}
-					}
-					Err(err) => {
-						log::error!(
-							"db lock poisoned while creating user {}: {}",
-							user.name,
-							err
-						);
-						return Ok(PeerRes::Error("Database unavailable".into()));
-					}
-				}
-				self.state.users.push(user.clone());
-				PeerRes::UserCreated {
-					username: user.name,
-				}
+				self.user_created(peer, user)
 			}
 			PeerReq::CreateToken {
 				username,
@@ -1539,7 +5625,7 @@ impl App {
 				expires_in,
 				permissions,
 			} => {
-				if !self.state.users.iter().any(|u| u.name == username) {
+				if !self.users.iter().any(|u| u.name == username) {
 					return Ok(PeerRes::Error("User does not exist".into()));
 				}
 				PeerRes::TokenIssued {
@@ -1568,18 +5654,20 @@ impl App {
 					mapped = existing;
 				}
 				let me = self.state.me;
-				self.state.set_peer_permissions(peer, mapped.clone());
+				for overlap in self.state.set_peer_permissions(peer, mapped.clone()) {
+					tracing::warn!("overlapping grant for {peer}: {}", overlap.describe());
+				}
 				match self.db.lock() {
 					Ok(mut conn) => {
 						if let Err(err) =
 							crate::db::save_peer_permissions(&mut *conn, &me, &peer, &mapped)
 						{
-							log::error!("failed to persist granted permissions: {}", err);
+							tracing::error!("failed to persist granted permissions: {}", err);
 							return Ok(PeerRes::Error("Failed to save permissions".into()));
 						}
 					}
 					Err(err) => {
-						log::error!(
+						tracing::error!(
 							"db lock poisoned while granting access to {}: {}",
 							peer,
 							err
@@ -1587,6 +5675,7 @@ impl App {
 						return Ok(PeerRes::Error("Database unavailable".into()));
 					}
 				}
+				self.notify_permissions_changed(peer);
 				PeerRes::AccessGranted {
 					username,
 					permissions,
@@ -1600,39 +5689,28 @@ impl App {
 				path,
 				max_width,
 				max_height,
+			} => match self.thumbnail_job(peer, path, max_width, max_height).await {
+				Ok(job) => job.run().await,
+				Err(refused) => refused,
+			},
+			PeerReq::ContactSheet {
+				path,
+				columns,
+				cell_size,
+				max_items,
 			} => {
-				log::info!(
-					"[{}] GetThumbnail {} ({}x{})",
-					peer,
-					path,
-					max_width,
-					max_height
-				);
-				let canonical = match fs::canonicalize(&path).await {
-					Ok(p) => p,
-					Err(err) => {
-						log::warn!("failed to canonicalize thumbnail path {}: {err}", path);
-						return Ok(PeerRes::Error(format!("Failed to access file: {err}")));
-					}
+				let options = ContactSheetOptions {
+					columns,
+					cell_size,
+					max_items,
 				};
-				if !self.can_access(peer, &canonical, FLAG_READ | FLAG_SEARCH) {
-					log::warn!(
-						"peer {} denied thumbnail access for {}",
-						peer,
-						canonical.display()
-					);
-					return Ok(PeerRes::Error("Access denied".into()));
-				}
-				match generate_thumbnail(&canonical, max_width, max_height).await {
-					Ok(thumb) => PeerRes::Thumbnail(thumb),
-					Err(err) => {
-						log::warn!("failed to generate thumbnail for {}: {err}", path);
-						PeerRes::Error(format!("Failed to generate thumbnail: {err}"))
-					}
+				match self.contact_sheet_job(peer, path, options).await {
+					Ok(job) => job.run().await,
+					Err(refused) => refused,
 				}
 			}
 			PeerReq::UpdateSelf { id, version } => {
-				log::info!("[{}] UpdateSelf (id: {}, version: {:?})", peer, id, version);
+				tracing::info!("[{}] UpdateSelf (id: {}, version: {:?})", peer, id, version);
 
 				let current_version = version::version_number();
 
@@ -1661,9 +5739,7 @@ impl App {
 						let _ = internal_tx_for_error.send(InternalCommand::SendUpdateEvent {
 							target,
 							update_id: id,
-							event: UpdateProgress::Failed {
-								error: err.to_string(),
-							},
+							event: UpdateProgress::failed(&err),
 						});
 					}
 				});
@@ -1671,20 +5747,9 @@ impl App {
 				PeerRes::UpdateStarted(Ok(()))
 			}
 			PeerReq::UpdateEvent { id, event } => {
-				log::debug!("[{}] UpdateEvent (id: {})", peer, id);
-				let mut map = self.remote_updates.lock().unwrap();
-				if let Some(tx) = map.get(&id) {
-					let _ = tx.send(event.clone());
-					if matches!(
-						event,
-						UpdateProgress::Completed { .. }
-							| UpdateProgress::Failed { .. }
-							| UpdateProgress::AlreadyUpToDate { .. }
-					) {
-						map.remove(&id);
-					}
-				} else {
-					log::warn!("received update event for unknown id {}", id);
+				tracing::debug!("[{}] UpdateEvent (id: {})", peer, id);
+				if !self.remote_updates.deliver(id, event, self.clock.now()) {
+					tracing::warn!("received update event for unknown id {}", id);
 				}
 				PeerRes::UpdateEventAck
 			}
@@ -1694,18 +5759,497 @@ impl App {
 			}
 			PeerReq::ShellInput { id, data } => {
 				match self.process_shell_input(id, &data, Some(peer)).await {
-					Ok(ShellInputResult::Output(out)) => PeerRes::ShellOutput { id, data: out },
-					Ok(ShellInputResult::Exited) => PeerRes::ShellExited { id },
+					Ok(ShellOutput::Output(out)) => PeerRes::ShellOutput { id, data: out },
+					Ok(ShellOutput::Exited) => PeerRes::ShellExited { id },
+					Err(err) => PeerRes::Error(err.to_string()),
+				}
+			}
+			PeerReq::CloseShell { id } => {
+				self.close_shell_session(peer, id);
+				PeerRes::ShellExited { id }
+			}
+			PeerReq::DesktopInput { input } => PeerRes::DesktopInputAck(
+				desktop_input::apply(input)
+					.await
+					.map_err(|err| err.to_string()),
+			),
+			PeerReq::PermissionsChanged { permissions } => {
+				tracing::info!(
+					"[{}] PermissionsChanged ({} rules)",
+					peer,
+					permissions.len()
+				);
+				self.store_remote_grants(peer, permissions.clone());
+				self.state.apply_remote_permissions(peer, permissions);
+				PeerRes::PermissionsChangedAck
+			}
+			PeerReq::Hello {
+				protocol_version,
+				features,
+			} => {
+				tracing::info!("[{}] Hello v{} {:?}", peer, protocol_version, features);
+				self.state.capabilities.insert(
+					peer,
+					PeerCapabilities {
+						protocol_version,
+						features,
+						legacy: false,
+					},
+				);
+				self.flush_delete_outbox(peer);
+				let local = PeerCapabilities::local();
+				PeerRes::Hello {
+					protocol_version: local.protocol_version,
+					features: local.features,
+					time: Some(self.clock.now()),
+				}
+			}
+			PeerReq::Restart { delay_secs } => {
+				if !self.state.is_owner(&peer) {
+					tracing::warn!("peer {} denied restart: not an owner", peer);
+					return Ok(PeerRes::Error("Owner access required".into()));
+				}
+				tracing::info!("[{}] Restart in {}s", peer, delay_secs);
+				PeerRes::RestartScheduled {
+					delay_secs: self.schedule_restart(delay_secs),
+				}
+			}
+			PeerReq::HealthCheck => PeerRes::Health(self.health_snapshot()),
+			PeerReq::PairRequest { node_name } => self.receive_pair_request(peer, node_name),
+			PeerReq::PairResponse { accepted } => self.receive_pair_response(peer, accepted),
+			PeerReq::Pair {
+				secret,
+				requested_permissions,
+			} => self.receive_pair(peer, secret, requested_permissions),
+			PeerReq::OpenInbox { name, size } => {
+				tracing::info!("[{}] OpenInbox {} ({} bytes)", peer, name, size);
+				match self.open_inbox(peer, &name, size) {
+					Ok(path) => PeerRes::InboxOpened { path },
+					Err(err) => {
+						tracing::warn!("peer {} denied inbox upload {}: {err}", peer, name);
+						PeerRes::Error(err.to_string())
+					}
+				}
+			}
+			// The outer one was unwrapped; a second layer isn't sent.
+			PeerReq::Traced { .. } => PeerRes::Error(String::from("nested Traced request")),
+			// Answered where requests come in, once the dial back finished.
+			PeerReq::DialBack { .. } => PeerRes::DialBackDeclined {
+				reason: String::from("dial back not started"),
+			},
+			PeerReq::SearchFiles { args } => {
+				tracing::info!("[{}] SearchFiles {:?}", peer, args.name_query);
+				match self.search_files_for(peer, args) {
+					Ok(results) => PeerRes::SearchResults(results),
+					Err(err) => {
+						tracing::warn!("search for peer {} failed: {err}", peer);
+						PeerRes::Error(format!("search failed: {err}"))
+					}
+				}
+			}
+			PeerReq::ShareSummary => {
+				tracing::info!("[{}] ShareSummary", peer);
+				match self.share_summaries_for(peer) {
+					Ok(summaries) => PeerRes::ShareSummaries(summaries),
+					Err(err) => {
+						tracing::warn!("share summary for peer {} failed: {err}", peer);
+						PeerRes::Error(format!("share summary failed: {err}"))
+					}
+				}
+			}
+			PeerReq::DeleteProposal {
+				id,
+				hash,
+				paths_hint,
+				reason,
+				expires_at,
+			} => {
+				tracing::info!("[{}] DeleteProposal {}", peer, id);
+				let offer = ProposalOffer {
+					id,
+					hash,
+					paths_hint,
+					reason,
+					expires_at,
+				};
+				self.receive_delete_proposal(peer, offer)
+			}
+			PeerReq::DeleteOutcome { id, outcome } => {
+				tracing::info!(
+					"[{}] DeleteOutcome {} {}",
+					peer,
+					id,
+					outcome.status().as_str()
+				);
+				self.receive_delete_outcome(peer, &id, outcome)
+			}
+			PeerReq::IndexDelta { delta } => {
+				tracing::info!(
+					"[{}] IndexDelta {}..{} ({} rows)",
+					peer,
+					delta.after,
+					delta.through,
+					delta.entries.len() + delta.locations.len()
+				);
+				self.receive_index_delta(peer, &delta)
+			}
+			PeerReq::IndexAnnounce {
+				node_id,
+				added,
+				updated,
+				removed,
+				high_water_mark,
+			} => {
+				tracing::debug!("[{}] IndexAnnounce up to change {}", peer, high_water_mark);
+				let change = IndexChangeCounts {
+					added,
+					updated,
+					removed,
+				};
+				self.receive_index_announce(peer, &node_id, change, high_water_mark)
+			}
+			PeerReq::IndexPull { generation, after } => {
+				tracing::info!("[{}] IndexPull after {}", peer, after);
+				self.serve_index_pull(peer, &generation, after)
+			}
+			PeerReq::ScanResultsPull { run, cursor } => {
+				tracing::info!("[{}] ScanResultsPull of run {} after {}", peer, run, cursor);
+				self.serve_scan_results(peer, run, cursor)
+			}
+			PeerReq::HaveHashes { hashes } => match self.have_hashes(peer, &hashes) {
+				Ok(bitmap) => PeerRes::Have { bitmap },
+				Err(err) => PeerRes::Error(err.to_string()),
+			},
+			PeerReq::HaveBlocks {
+				hash,
+				block_size,
+				blocks,
+			} => {
+				tracing::debug!(
+					"[{}] HaveBlocks of {} ({} blocks)",
+					peer,
+					hex(&hash),
+					blocks.len()
+				);
+				match self.have_blocks(peer, block_size, &blocks) {
+					Ok(bitmap) => PeerRes::Have { bitmap },
+					Err(err) => PeerRes::Error(err.to_string()),
+				}
+			}
+			PeerReq::WriteKnownBlock {
+				path,
+				offset,
+				block_hash,
+			} => {
+				let target = match self.open_inbox_upload(peer, &path).await {
+					Ok(target) => target,
+					Err(err) => return Ok(PeerRes::Error(err.to_string())),
+				};
+				if self.hidden_from(peer, &target.canonical(), false) {
+					return Ok(hidden_path("file"));
+				}
+				match self
+					.write_known_block(peer, &path, target, offset, &block_hash)
+					.await
+				{
+					Ok(ack) => PeerRes::WriteAck(ack),
+					Err(err) => PeerRes::Error(err.to_string()),
+				}
+			}
+			PeerReq::WriteInbox { path, offset, data } => {
+				tracing::info!(
+					"[{}] WriteInbox {} (offset {}, {} bytes)",
+					peer,
+					path,
+					offset,
+					data.len()
+				);
+				let target = match self.open_inbox_upload(peer, &path).await {
+					Ok(target) => target,
+					Err(err) => return Ok(PeerRes::Error(err.to_string())),
+				};
+				if self.hidden_from(peer, &target.canonical(), false) {
+					return Ok(hidden_path("file"));
+				}
+				match self.write_inbox_chunk(&path, target, offset, &data).await {
+					Ok(ack) => PeerRes::WriteAck(ack),
 					Err(err) => PeerRes::Error(err.to_string()),
 				}
 			}
-			PeerReq::DesktopInput { input } => PeerRes::DesktopInputAck(
-				desktop_input::apply(input)
-					.await
-					.map_err(|err| err.to_string()),
-			),
-		};
-		Ok(res)
+			PeerReq::Unknown(request) => {
+				tracing::info!("[{}] unsupported request {}", peer, request);
+				PeerRes::Unsupported { request }
+			}
+		};
+		Ok(res)
+	}
+
+	/// Keeps the rows of `peer`'s index it sends under its node id. Only
+	/// paired peers whose index updates this node subscribed to may keep a
+	/// replica here.
+	fn receive_index_delta(&self, peer: PeerId, delta: &IndexDelta) -> PeerRes {
+		if self.state.permissions_granted_to_peer(&peer).is_empty() {
+			return PeerRes::Error(String::from(
+				"index replicas are only kept for paired peers",
+			));
+		}
+		let received = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))
+			.and_then(|conn| {
+				if !is_subscribed_to_index(&conn, &peer)? {
+					bail!("this node does not keep a replica of {peer}'s index");
+				}
+				receive_delta(&conn, &peer, delta, Utc::now())
+			});
+		match received {
+			Ok(ack) => PeerRes::IndexDeltaAck(ack),
+			Err(err) => {
+				tracing::warn!("failed to apply index delta from {}: {err:#}", peer);
+				PeerRes::Error(format!("index delta not applied: {err}"))
+			}
+		}
+	}
+
+	/// Notes that `peer`'s index changed and, when this node keeps a
+	/// replica of it or subscribed to it, schedules pulling the changes.
+	/// Only paired peers are listened to.
+	fn receive_index_announce(
+		&mut self,
+		peer: PeerId,
+		node_id: &str,
+		change: IndexChangeCounts,
+		high_water_mark: u64,
+	) -> PeerRes {
+		if self.state.permissions_granted_to_peer(&peer).is_empty() {
+			return PeerRes::Error(String::from(
+				"index announcements are only taken from paired peers",
+			));
+		}
+		if node_id != peer.to_string() {
+			return PeerRes::Error(String::from("peers only announce their own index"));
+		}
+		let now = self.clock.now();
+		self.state
+			.index_freshness
+			.entry(peer)
+			.or_insert_with(|| IndexFreshness::new(now))
+			.announce(change, high_water_mark, now);
+		let wanted = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))
+			.and_then(|conn| wants_pull(&conn, &peer));
+		match wanted {
+			Ok(true) => self.schedule_index_pull(peer),
+			Ok(false) => {}
+			Err(err) => tracing::warn!("failed to check index subscription to {}: {err}", peer),
+		}
+		PeerRes::IndexAnnounceAck
+	}
+
+	/// Answers `peer`'s pull of this node's index changes. Only owners may
+	/// pull, as only owners are sent replicas.
+	fn serve_index_pull(&self, peer: PeerId, generation: &str, after: u64) -> PeerRes {
+		if !self.state.is_owner(&peer) {
+			return PeerRes::Error(String::from("only owners may pull this device's index"));
+		}
+		let Some(node_id) = self.local_node_id() else {
+			return PeerRes::Error(String::from("failed to determine node id"));
+		};
+		let delta = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))
+			.and_then(|conn| pull_delta(&conn, &node_id, generation, after));
+		match delta {
+			Ok(delta) => PeerRes::IndexDelta(delta),
+			Err(err) => {
+				tracing::warn!("failed to read index changes for {}: {err:#}", peer);
+				PeerRes::Error(format!("index changes not read: {err}"))
+			}
+		}
+	}
+
+	/// Answers `peer`'s pull of the results of scan run `run`, which it
+	/// started, while it may still search the scanned folder.
+	fn serve_scan_results(&self, peer: PeerId, run: i64, cursor: u64) -> PeerRes {
+		let Some(node_id) = self.local_node_id() else {
+			return PeerRes::Error(String::from("failed to determine node id"));
+		};
+		let results = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))
+			.and_then(|conn| {
+				let scan = scan_run_for(&conn, &peer, run)?;
+				let path = Path::new(&scan.path);
+				if !self.can_access(peer, path, FLAG_READ | FLAG_SEARCH) {
+					bail!(self.access_denied_message(peer, path));
+				}
+				scan_results_batch(&conn, &node_id, &scan, cursor)
+			});
+		match results {
+			Ok(results) => PeerRes::ScanResults(results),
+			Err(err) => {
+				tracing::warn!(
+					"failed to read results of scan run {run} for {}: {err:#}",
+					peer
+				);
+				PeerRes::Error(format!("scan results not read: {err}"))
+			}
+		}
+	}
+
+	/// Tells the connected peers this node shares with that its index
+	/// changed, at most once per `ANNOUNCE_INTERVAL`.
+	fn announce_index(&mut self) {
+		let Some(change) = self.index_announcer.take_due(self.clock.now()) else {
+			return;
+		};
+		let high_water_mark = match self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))
+			.and_then(|conn| index_change_seq(&conn))
+		{
+			Ok(seq) => seq,
+			Err(err) => {
+				tracing::warn!("failed to read the index change number: {err}");
+				return;
+			}
+		};
+		let mut peers = self
+			.state
+			.connections
+			.iter()
+			.map(|connection| connection.peer_id)
+			.filter(|peer| {
+				*peer != self.state.me
+					&& !self.state.permissions_granted_to_peer(peer).is_empty()
+					&& self
+						.state
+						.peer_capabilities(peer)
+						.is_some_and(|capabilities| capabilities.supports(FEATURE_INDEX_ANNOUNCE))
+			})
+			.collect::<Vec<_>>();
+		peers.sort();
+		peers.dedup();
+		let node_id = self.state.me.to_string();
+		for peer in peers {
+			let request_id = self.send_peer_request(
+				&peer,
+				PeerReq::IndexAnnounce {
+					node_id: node_id.clone(),
+					added: change.added,
+					updated: change.updated,
+					removed: change.removed,
+					high_water_mark,
+				},
+			);
+			self.pending_requests
+				.insert(request_id, PendingIndexAnnounceAck::new());
+		}
+	}
+
+	/// Pulls `peer`'s announced index changes after a random delay, so
+	/// peers announcing together aren't all pulled at the same moment.
+	fn schedule_index_pull(&mut self, peer: PeerId) {
+		if self.index_pulls.schedule(peer) {
+			self.delay_index_pull(peer);
+		}
+	}
+
+	fn delay_index_pull(&self, peer: PeerId) {
+		let delay = pull_delay(rand::random());
+		let internal_tx = self.internal_tx.clone();
+		tokio::spawn(async move {
+			tokio::time::sleep(delay).await;
+			let _ = internal_tx.send(InternalCommand::PullIndex { peer });
+		});
+	}
+
+	fn start_index_pull(&mut self, peer: PeerId) {
+		let supported = self
+			.state
+			.peer_capabilities(&peer)
+			.is_some_and(|capabilities| capabilities.supports(FEATURE_INDEX_ANNOUNCE));
+		if !supported {
+			self.index_pulls.cancel(&peer);
+			return;
+		}
+		let target = self
+			.state
+			.index_freshness
+			.get(&peer)
+			.map_or(0, |freshness| freshness.high_water_mark);
+		if !self.index_pulls.start(peer, target) {
+			// Enough pulls are running; try again after another delay.
+			self.delay_index_pull(peer);
+			return;
+		}
+		self.send_index_pull(peer);
+	}
+
+	/// Asks `peer` for the changes after the ones this node holds.
+	fn send_index_pull(&mut self, peer: PeerId) {
+		let held = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))
+			.and_then(|conn| load_replication(&conn, &peer, ReplicationRole::Replica));
+		let (generation, after) = match held {
+			Ok(Some(held)) => (held.generation, held.acked_seq),
+			Ok(None) => (String::new(), 0),
+			Err(err) => {
+				tracing::warn!("failed to load the index replica of {}: {err}", peer);
+				self.finish_index_pull(peer);
+				return;
+			}
+		};
+		let request_id = self.send_peer_request(&peer, PeerReq::IndexPull { generation, after });
+		self.pending_requests.insert(
+			request_id,
+			PendingIndexPull::new(peer, self.internal_tx.clone()),
+		);
+	}
+
+	/// Applies a pulled delta and asks for the next one until caught up.
+	fn apply_index_pull(&mut self, peer: PeerId, delta: Result<IndexDelta>) {
+		let now = self.clock.now();
+		let pulled = delta.and_then(|delta| {
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			let ack = receive_delta(&conn, &peer, &delta, now)?;
+			// Caught up once a delta continuing what this node held left
+			// nothing after it.
+			let caught_up = ack.acked == delta.through && delta.remaining == 0;
+			Ok((ack.acked, caught_up))
+		});
+		match pulled {
+			Ok((acked, true)) => {
+				let target = self.index_pulls.target(&peer).unwrap_or_default();
+				if let Some(freshness) = self.state.index_freshness.get_mut(&peer) {
+					freshness.synced(acked.max(target));
+				}
+			}
+			Ok(_) if self.index_pulls.next_batch(&peer) => {
+				self.send_index_pull(peer);
+				return;
+			}
+			Ok(_) => {}
+			Err(err) => tracing::warn!("pulling index changes from {} failed: {err:#}", peer),
+		}
+		self.finish_index_pull(peer);
+	}
+
+	fn finish_index_pull(&mut self, peer: PeerId) {
+		if self.index_pulls.finish(&peer) {
+			self.schedule_index_pull(peer);
+		}
 	}
 
 	fn collect_cpu_info(&mut self) -> Vec<CpuInfo> {
@@ -1730,10 +6274,10 @@ impl App {
 			None => return,
 		};
 		self.system.refresh_memory();
-		let now = Utc::now();
+		let now = self.clock.now();
 		let node = Node {
 			id: node_id,
-			name: System::host_name().unwrap_or_else(|| String::from("local-node")),
+			name: self.local_node_name(),
 			you: true,
 			total_memory: self.system.total_memory(),
 			system_name: System::name().unwrap_or_else(|| String::from("unknown")),
@@ -1746,12 +6290,78 @@ impl App {
 		let conn = match self.db.lock() {
 			Ok(conn) => conn,
 			Err(err) => {
-				log::error!("failed to lock database for node persistence: {err}");
+				tracing::error!("failed to lock database for node persistence: {err}");
 				return;
 			}
 		};
+		match find_previous_local_node(&conn, &node_id) {
+			Ok(Some(previous)) => {
+				if self.state.identity_mismatch.is_none() {
+					tracing::warn!(
+						"database belongs to node {} but the keypair is node {}; not saving a second local node",
+						hex(&previous),
+						hex(&node_id)
+					);
+				}
+				self.state.identity_mismatch = Some(IdentityMismatch::new(&previous, &node_id));
+				return;
+			}
+			Ok(None) => self.state.identity_mismatch = None,
+			Err(err) => tracing::error!("failed to check local node identity: {err}"),
+		}
 		if let Err(err) = save_node(&conn, &node) {
-			log::error!("failed to persist local node: {err}");
+			tracing::error!("failed to persist local node: {err}");
+		}
+	}
+
+	/// Reloads what [`App::new`] reads from the database, after the
+	/// database file was replaced.
+	fn reload_stored_state(&mut self) -> Result<()> {
+		{
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			let permissions = load_peer_permissions(&conn, &self.state.me)?;
+			self.users = load_users(&conn)?;
+			self.state.peers = load_peers(&conn)?;
+			self.discovered.set_ttl(ttl_from_setting(
+				load_setting(&conn, DISCOVERED_ADDRESS_TTL_SETTING)?.as_deref(),
+			));
+			let stored_discovered = load_discovered_peers(&conn)?;
+			self.discovery_writes.load(&stored_discovered);
+			self.discovered.load(stored_discovered, self.clock.now());
+			self.grant_cache.set_ttl(grant_cache::ttl_from_setting(
+				load_setting(&conn, GRANT_CACHE_TTL_SETTING)?.as_deref(),
+			));
+			self.grant_cache.load(load_remote_grants(&conn)?);
+			self.scan_limits.reload(&conn);
+			self.state.replace_permissions_from_storage(permissions);
+			self.state.shared_folders.clear();
+			for folder in load_shared_folders(&conn)? {
+				self.state.add_shared_folder(folder);
+			}
+			self.state.remote_access_suspended =
+				load_setting(&conn, REMOTE_ACCESS_SUSPENDED_SETTING)?.as_deref() == Some("true");
+		}
+		self.normalize_file_location_node_ids();
+		self.persist_local_node();
+		Ok(())
+	}
+
+	/// Finishes an identity adoption a previous run was stopped in.
+	fn resume_identity_adoption(&self) {
+		let mut conn = match self.db.lock() {
+			Ok(conn) => conn,
+			Err(err) => {
+				tracing::error!("failed to lock database to resume identity adoption: {err}");
+				return;
+			}
+		};
+		match resume_identity_adoption(&mut conn) {
+			Ok(true) => tracing::info!("finished an interrupted identity adoption"),
+			Ok(false) => {}
+			Err(err) => tracing::error!("failed to resume identity adoption: {err}"),
 		}
 	}
 
@@ -1760,7 +6370,7 @@ impl App {
 		let conn = match self.db.lock() {
 			Ok(conn) => conn,
 			Err(err) => {
-				log::error!("failed to lock database to normalize node ids: {err}");
+				tracing::error!("failed to lock database to normalize node ids: {err}");
 				return;
 			}
 		};
@@ -1768,7 +6378,7 @@ impl App {
 			"UPDATE file_locations SET node_id = substr(node_id, 1, ?) WHERE length(node_id) != ?",
 			params![NODE_ID_LEN, NODE_ID_LEN],
 		) {
-			log::error!("failed to normalize legacy node ids: {err}");
+			tracing::error!("failed to normalize legacy node ids: {err}");
 		}
 	}
 
@@ -1783,11 +6393,11 @@ impl App {
 		let conn = match self.db.lock() {
 			Ok(conn) => conn,
 			Err(err) => {
-				log::error!("failed to lock database for CPU persistence: {err}");
+				tracing::error!("failed to lock database for CPU persistence: {err}");
 				return;
 			}
 		};
-		let now = Utc::now();
+		let now = self.clock.now();
 		let mut current_names = Vec::with_capacity(cpus.len());
 		for info in cpus {
 			let entry = DbCpu {
@@ -1799,13 +6409,13 @@ impl App {
 				modified_at: now,
 			};
 			if let Err(err) = save_cpu(&conn, &entry) {
-				log::error!("failed to save CPU {}: {err}", entry.name);
+				tracing::error!("failed to save CPU {}: {err}", entry.name);
 			} else {
 				current_names.push(entry.name.clone());
 			}
 		}
 		if let Err(err) = remove_stale_cpus(&conn, &node_id, &current_names) {
-			log::error!("failed to prune stale CPU entries: {err}");
+			tracing::error!("failed to prune stale CPU entries: {err}");
 		}
 	}
 
@@ -1841,11 +6451,11 @@ impl App {
 		let conn = match self.db.lock() {
 			Ok(conn) => conn,
 			Err(err) => {
-				log::error!("failed to lock database for interface persistence: {err}");
+				tracing::error!("failed to lock database for interface persistence: {err}");
 				return;
 			}
 		};
-		let now = Utc::now();
+		let now = self.clock.now();
 		let mut current_names = Vec::with_capacity(interfaces.len());
 		for info in interfaces {
 			let (ip, loopback, linklocal) = summarize_interface_ips(&info.ips);
@@ -1862,13 +6472,13 @@ impl App {
 				modified_at: now,
 			};
 			if let Err(err) = save_interface(&conn, &entry) {
-				log::error!("failed to save interface {}: {err}", entry.name);
+				tracing::error!("failed to save interface {}: {err}", entry.name);
 			} else {
 				current_names.push(entry.name.clone());
 			}
 		}
 		if let Err(err) = remove_stale_interfaces(&conn, &node_id, &current_names) {
-			log::error!("failed to prune stale interface entries: {err}");
+			tracing::error!("failed to prune stale interface entries: {err}");
 		}
 	}
 
@@ -1877,108 +6487,147 @@ impl App {
 			.db
 			.lock()
 			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
-		let mut stmt = conn
-			.prepare("SELECT id, name FROM nodes")
-			.map_err(|err| anyhow!("failed to prepare nodes query: {err}"))?;
-		let rows = stmt
-			.query_map([], |row| {
-				let id: Vec<u8> = row.get(0)?;
-				let name: String = row.get(1)?;
-				Ok((id, name))
-			})
-			.map_err(|err| anyhow!("failed to query nodes: {err}"))?;
-		let mut files = Vec::new();
-		for row in rows {
-			let (node_id, node_name) =
-				row.map_err(|err| anyhow!("failed to read node row: {err}"))?;
-			files.extend(Self::load_files_for_node(&conn, &node_id, &node_name)?);
-		}
-		Ok(files)
-	}
-
-	fn load_files_for_node(
-		conn: &SqliteConnection,
-		node_id: &[u8],
-		node_name: &str,
-	) -> Result<Vec<StorageUsageFile>> {
-		let mut stmt = conn
-			.prepare(
-				"SELECT path, size, timestamp, modified_at \
-				FROM file_locations WHERE node_id = ?1",
-			)
-			.map_err(|err| anyhow!("failed to prepare file_locations query: {err}"))?;
-		let rows = stmt
-			.query_map(params![node_id], |row| {
-				let path: String = row.get(0)?;
-				let size = row.get::<_, i64>(1)?.max(0) as u64;
-				let timestamp: Option<DateTime<Utc>> = row.get(2)?;
-				let modified: Option<DateTime<Utc>> = row.get(3)?;
-				Ok(StorageUsageFile {
-					node_id: node_id.to_vec(),
-					node_name: node_name.to_string(),
-					path,
-					size,
-					last_changed: modified.or(timestamp),
-				})
-			})
-			.map_err(|err| anyhow!("failed to query file_locations: {err}"))?;
-		let mut files = Vec::new();
-		for row in rows {
-			files.push(row.map_err(|err| anyhow!("failed to read file row: {err}"))?);
-		}
-		Ok(files)
+		storage_files(&conn)
 	}
 
-	fn fetch_file_entries(&self, offset: u64, limit: u64) -> Result<Vec<FileEntry>, String> {
+	/// One page of the file entries `peer` may see, which for another peer
+	/// are those with a live copy on this node it is [exposed](Self::exposes)
+	/// to. Pages of another peer can come back short.
+	fn fetch_file_entries(
+		&self,
+		peer: PeerId,
+		offset: u64,
+		limit: u64,
+	) -> Result<Vec<FileEntry>, String> {
 		let conn = self
 			.db
 			.lock()
 			.map_err(|err| format!("db lock poisoned: {err}"))?;
-		fetch_file_entries_paginated(&conn, offset, limit)
-			.map_err(|err| format!("failed to fetch file entries: {err}"))
+		let entries = fetch_file_entries_paginated(&conn, offset, limit)
+			.map_err(|err| format!("failed to fetch file entries: {err}"))?;
+		if peer == self.state.me {
+			return Ok(entries);
+		}
+		let Some(node_id) = self.local_node_id() else {
+			return Ok(Vec::new());
+		};
+		let hidden = self.state.hidden(peer, self.clock.now());
+		let mut visible = Vec::new();
+		for entry in entries {
+			let locations = live_locations_of_hash(&conn, &entry.hash)
+				.map_err(|err| format!("failed to fetch file entries: {err}"))?;
+			if locations
+				.iter()
+				.any(|(node, path)| *node == node_id && self.exposes(peer, &hidden, path))
+			{
+				visible.push(entry);
+			}
+		}
+		Ok(visible)
 	}
 
 	fn local_node_id(&self) -> Option<NodeID> {
 		match peer_to_node_id(&self.state.me) {
 			Some(id) => Some(id),
 			None => {
-				log::warn!("local peer id too short to derive node id; skipping persistence");
+				tracing::warn!("local peer id too short to derive node id; skipping persistence");
 				None
 			}
 		}
 	}
 
-	fn collect_disk_info(&self) -> Vec<DiskInfo> {
-		let disks = Disks::new_with_refreshed_list();
-		disks
+	fn disk_history(
+		&self,
+		mount: &str,
+		from: DateTime<Utc>,
+		to: DateTime<Utc>,
+	) -> anyhow::Result<Vec<DiskSample>> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		let samples = load_disk_samples(&conn, mount, from, to)?;
+		Ok(disk_history::thin_samples(
+			samples,
+			DISK_HISTORY_MAX_SAMPLES,
+		))
+	}
+
+	/// Browse roots as seen by `peer`: only disks and shared folders it may read.
+	fn collect_browse_roots(&self, peer: PeerId) -> BrowseRoots {
+		let shared_folders = self
+			.state
+			.roots_for_peer(&peer, FLAG_READ)
 			.iter()
-			.map(|disk| {
-				let total_space = disk.total_space();
-				let available_space = disk.available_space();
-				let usage_percent = if total_space == 0 {
-					0.0
-				} else {
-					let used = total_space.saturating_sub(available_space);
-					((used as f64 / total_space as f64) * 100.0) as f32
-				};
-				let usage = disk.usage();
-				DiskInfo {
-					name: disk.name().to_string_lossy().to_string(),
-					mount_path: disk.mount_point().to_string_lossy().to_string(),
-					filesystem: disk.file_system().to_string_lossy().to_string(),
-					total_space,
-					available_space,
-					usage_percent,
-					total_read_bytes: usage.total_read_bytes,
-					total_written_bytes: usage.total_written_bytes,
-					read_only: disk.is_read_only(),
-					removable: disk.is_removable(),
-					kind: format!("{:?}", disk.kind()),
-				}
+			.map(|path| path.to_string_lossy().into_owned())
+			.collect::<Vec<_>>();
+		let mut roots = BrowseRoots::collect(
+			cfg!(target_os = "windows"),
+			&Self::collect_disk_info(),
+			&shared_folders,
+			|mount| self.can_access(peer, Path::new(mount), FLAG_READ),
+		);
+		let now = self.clock.now();
+		for root in &mut roots.roots {
+			root.flags = Some(
+				self.state
+					.granted_flags_at(peer, Path::new(&root.path), now),
+			);
+		}
+		roots
+	}
+
+	/// Standard folders that exist here and that `peer` may read.
+	fn collect_well_known_folders(&self, peer: PeerId) -> Vec<WellKnownFolder> {
+		locations::well_known_folders(&LocationEnv::current(), &Self::collect_disk_info())
+			.into_iter()
+			.filter(|folder| {
+				let path = Path::new(&folder.path);
+				path.is_dir() && self.can_access(peer, path, FLAG_READ)
 			})
 			.collect()
 	}
 
+	fn stat_entry(canonical: &Path, meta: &std::fs::Metadata) -> DirEntry {
+		let file_type = meta.file_type();
+		let ext = canonical
+			.extension()
+			.and_then(|s| s.to_str().map(|s| s.to_string()));
+		let mime = if file_type.is_dir() {
+			None
+		} else {
+			mime_guess::from_path(canonical)
+				.first_raw()
+				.map(|value| value.to_string())
+		};
+		let file_name = canonical.file_name().map(Path::new);
+		DirEntry {
+			name: file_name
+				.map(|name| name.to_string_lossy().to_string())
+				.unwrap_or_default(),
+			name_raw: file_name.map(path_bytes).unwrap_or_default(),
+			is_dir: file_type.is_dir(),
+			extension: ext,
+			mime,
+			mime_source: MimeSource::Extension,
+			size: meta.len(),
+			disk_size: sparse::disk_size(meta),
+			created_at: meta
+				.created()
+				.ok()
+				.and_then(|t| DateTime::<Utc>::from(t).into()),
+			modified_at: meta
+				.modified()
+				.ok()
+				.and_then(|t| DateTime::<Utc>::from(t).into()),
+			accessed_at: meta
+				.accessed()
+				.ok()
+				.and_then(|t| DateTime::<Utc>::from(t).into()),
+			hidden: hidden_attribute(meta),
+		}
+	}
+
 	async fn collect_dir_entries(path: impl AsRef<Path>) -> Result<Vec<DirEntry>> {
 		let path = path.as_ref();
 		let mut entries = Vec::new();
@@ -1988,7 +6637,7 @@ impl App {
 			let metadata = match entry.metadata().await {
 				Ok(m) => m,
 				Err(err) => {
-					log::warn!("metadata failed for {:?}: {err}", entry.path());
+					tracing::warn!("metadata failed for {:?}: {err}", entry.path());
 					continue;
 				}
 			};
@@ -2003,12 +6652,16 @@ impl App {
 					.first_raw()
 					.map(|value| value.to_string())
 			};
+			let file_name = entry.file_name();
 			entries.push(DirEntry {
-				name: entry.file_name().to_string_lossy().to_string(),
+				name: file_name.to_string_lossy().to_string(),
+				name_raw: path_bytes(Path::new(&file_name)),
 				is_dir: file_type.is_dir(),
 				extension,
 				mime,
+				mime_source: MimeSource::Extension,
 				size: metadata.len(),
+				disk_size: sparse::disk_size(&metadata),
 				created_at: metadata
 					.created()
 					.ok()
@@ -2021,20 +6674,51 @@ impl App {
 					.accessed()
 					.ok()
 					.and_then(|t| DateTime::<Utc>::from(t).into()),
+				hidden: hidden_attribute(&metadata),
 			});
 		}
-		entries.sort_by(|a, b| match (a.is_dir, b.is_dir) {
-			(true, false) => std::cmp::Ordering::Less,
-			(false, true) => std::cmp::Ordering::Greater,
-			_ => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+		entries.sort_by(|a, b| {
+			b.is_dir
+				.cmp(&a.is_dir)
+				.then_with(|| natural_cmp(&a.name, &b.name))
 		});
 		Ok(entries)
 	}
 
+	/// What `peer` may still write under `dir`, for a peer that may write
+	/// there. Folders carry no quotas, so the disk's space is the hint.
+	async fn free_hint_for(&mut self, peer: PeerId, dir: &Path) -> Option<u64> {
+		if !self.can_access(peer, dir, FLAG_WRITE | FLAG_READ | FLAG_SEARCH) {
+			return None;
+		}
+		let now = std::time::Instant::now();
+		if self.disk_cache.is_stale(now) {
+			match tokio::task::spawn_blocking(Self::collect_disk_info).await {
+				Ok(disks) => self.disk_cache.refresh(disks, now),
+				Err(err) => tracing::warn!("failed to read disks: {err}"),
+			}
+		}
+		free_hint(self.disk_cache.available_for(dir), None)
+	}
+
+	async fn stat_local_entry(&mut self, canonical: &Path, meta: &std::fs::Metadata) -> DirEntry {
+		let mut entry = Self::stat_entry(canonical, meta);
+		if let Some(dir) = canonical.parent() {
+			refine_mimes(
+				&self.db,
+				self.local_node_id(),
+				dir,
+				std::slice::from_mut(&mut entry),
+			)
+			.await;
+		}
+		entry
+	}
+
 	async fn handle_agent_event(&mut self, event: AgentEvent) {
 		match event {
 			AgentEvent::Ping(event) => {
-				log::info!("Ping event: {:?}", event);
+				tracing::info!("Ping event: {:?}", event);
 			}
 			AgentEvent::PuppyNet(event) => match event {
 				libp2p::request_response::Event::Message {
@@ -2047,36 +6731,341 @@ impl App {
 						request,
 						channel,
 					} => {
-						if let PeerReq::GetMediaFrame { source_id } = request {
-							let internal_tx = self.internal_tx.clone();
-							tokio::spawn(async move {
-								let response = match webcam::capture_media_frame(source_id).await {
-									Ok(frame) => PeerRes::MediaFrame(frame),
-									Err(err) => PeerRes::Error(err.to_string()),
-								};
-								let _ = internal_tx
-									.send(InternalCommand::SendPeerResponse { channel, response });
-							});
-							return;
+						let received = self.request_log.start();
+						let (corr, request) = request.untraced();
+						let corr = corr.unwrap_or_else(|| self.request_log.next_corr());
+						let name = request.name();
+						let span = tracing::info_span!("peer_req", corr, %peer, request = name);
+						let kind = kind_index(name);
+						let counters = self.protocol.counters(peer);
+						let request_len = wire_len(&request);
+						counters.received(kind, request_len);
+						let folder = self.accessed_folder(&request);
+						if let Some(folder) = &folder {
+							counters.accessed(folder, request_len);
 						}
-						if let Ok(res) = self.handle_puppy_peer_req(peer, request).await {
+						let inbound = InboundTrace {
+							log: Arc::clone(&self.request_log),
+							corr,
+							peer,
+							request: name,
+							received,
+							counters,
+							kind,
+							folder,
+						};
+						// Refused before any work while the peer asks too often.
+						if self.protocol.is_limited(&peer) {
+							let response = self.rate_limited(peer, RATE_LIMIT_RETRY_SECS);
+							inbound.finish_as(
+								received.map(|_| std::time::Instant::now()),
+								&response,
+								Answer::RateLimited,
+							);
 							let _ = self
 								.swarm
 								.behaviour_mut()
 								.puppynet
-								.send_response(channel, res);
-						} else {
+								.send_response(channel, response);
+							return;
+						}
+						// Refused before any request's own checks, those
+						// answered off the event loop included.
+						let refused = self
+							.trust_refusal(peer, &request)
+							.or_else(|| self.request_refusal(peer, &request));
+						if let Some(refused) = refused {
+							inbound.finish(received.map(|_| std::time::Instant::now()), &refused);
 							let _ = self
 								.swarm
 								.behaviour_mut()
 								.puppynet
-								.send_response(channel, PeerRes::Error("Internal error".into()));
+								.send_response(channel, refused);
+							return;
+						}
+						// Work answered off the loop needs a slot; without
+						// one the peer is told to come back rather than
+						// queueing more.
+						let slot = if answered_off_loop(&request) {
+							match Arc::clone(&self.inbound_jobs).try_acquire_owned() {
+								Ok(slot) => Some(slot),
+								Err(_) => {
+									tracing::info!("[{}] too busy for {}", peer, name);
+									let response = self.rate_limited(peer, BUSY_RETRY_SECS);
+									inbound.finish_as(
+										received.map(|_| std::time::Instant::now()),
+										&response,
+										Answer::RateLimited,
+									);
+									let _ = self
+										.swarm
+										.behaviour_mut()
+										.puppynet
+										.send_response(channel, response);
+									return;
+								}
+							}
+						} else {
+							None
+						};
+						if let PeerReq::GetMediaFrame { source_id } = request {
+							let internal_tx = self.internal_tx.clone();
+							tokio::spawn(
+								async move {
+									let _slot = slot;
+									let started =
+										inbound.received.map(|_| std::time::Instant::now());
+									let response =
+										match webcam::capture_media_frame(source_id).await {
+											Ok(frame) => PeerRes::MediaFrame(frame),
+											Err(err) => PeerRes::Error(err.to_string()),
+										};
+									inbound.finish(started, &response);
+									let _ = internal_tx.send(InternalCommand::SendPeerResponse {
+										channel,
+										response,
+									});
+								}
+								.instrument(span),
+							);
+							return;
+						}
+						// Generation waits for a permit; the event loop must not.
+						if let PeerReq::GetThumbnail {
+							path,
+							max_width,
+							max_height,
+						} = request
+						{
+							let started = received.map(|_| std::time::Instant::now());
+							let job = self
+								.thumbnail_job(peer, path, max_width, max_height)
+								.instrument(span.clone())
+								.await;
+							let internal_tx = self.internal_tx.clone();
+							tokio::spawn(
+								async move {
+									let _slot = slot;
+									let response = match job {
+										Ok(job) => job.run().await,
+										Err(refused) => refused,
+									};
+									inbound.finish(started, &response);
+									let _ = internal_tx.send(InternalCommand::SendPeerResponse {
+										channel,
+										response,
+									});
+								}
+								.instrument(span),
+							);
+							return;
+						}
+						if let PeerReq::ContactSheet {
+							path,
+							columns,
+							cell_size,
+							max_items,
+						} = request
+						{
+							let started = received.map(|_| std::time::Instant::now());
+							let options = ContactSheetOptions {
+								columns,
+								cell_size,
+								max_items,
+							};
+							let job = self
+								.contact_sheet_job(peer, path, options)
+								.instrument(span.clone())
+								.await;
+							let internal_tx = self.internal_tx.clone();
+							tokio::spawn(
+								async move {
+									let _slot = slot;
+									let response = match job {
+										Ok(job) => job.run().await,
+										Err(refused) => refused,
+									};
+									inbound.finish(started, &response);
+									let _ = internal_tx.send(InternalCommand::SendPeerResponse {
+										channel,
+										response,
+									});
+								}
+								.instrument(span),
+							);
+							return;
+						}
+						// Argon2 is slow on purpose; the password is hashed
+						// off the loop and the user saved once it is back.
+						if let PeerReq::CreateUser {
+							username, password, ..
+						} = request
+						{
+							let started = received.map(|_| std::time::Instant::now());
+							let name = match self.check_new_user(&username, &password) {
+								Ok(name) => name,
+								Err(err) => {
+									let response = self.user_created(peer, Err(err));
+									inbound.finish(started, &response);
+									let _ = self
+										.swarm
+										.behaviour_mut()
+										.puppynet
+										.send_response(channel, response);
+									return;
+								}
+							};
+							let internal_tx = self.internal_tx.clone();
+							tokio::spawn(
+								async move {
+									let _slot = slot;
+									let user = hash_new_user(name, password).await;
+									let _ = internal_tx.send(InternalCommand::UserHashed {
+										user,
+										reply: NewUserReply::Peer(Box::new(PeerUserReply {
+											peer,
+											channel,
+											inbound,
+											started,
+										})),
+									});
+								}
+								.instrument(span),
+							);
+							return;
+						}
+						// Listings and reads can take long on big folders
+						// and slow disks; the checks stay on the loop.
+						if let PeerReq::ListDir { path } = request {
+							let started = received.map(|_| std::time::Instant::now());
+							let job = self.list_dir_job(peer, path).instrument(span.clone()).await;
+							let internal_tx = self.internal_tx.clone();
+							tokio::spawn(
+								async move {
+									let _slot = slot;
+									let response = match job {
+										Ok(job) => job.run().await,
+										Err(refused) => refused,
+									};
+									inbound.finish(started, &response);
+									let _ = internal_tx.send(InternalCommand::SendPeerResponse {
+										channel,
+										response,
+									});
+								}
+								.instrument(span),
+							);
+							return;
+						}
+						if let PeerReq::ReadFile {
+							path,
+							offset,
+							length,
+							sparse,
+						} = request
+						{
+							let started = received.map(|_| std::time::Instant::now());
+							let job = self
+								.read_job(peer, path, offset, length, sparse)
+								.instrument(span.clone())
+								.await;
+							let internal_tx = self.internal_tx.clone();
+							tokio::spawn(
+								async move {
+									let _slot = slot;
+									let response = match job {
+										Ok(job) => job.run().await,
+										Err(refused) => refused,
+									};
+									inbound.finish(started, &response);
+									let _ = internal_tx.send(InternalCommand::SendPeerResponse {
+										channel,
+										response,
+									});
+								}
+								.instrument(span),
+							);
+							return;
 						}
+						// Answered once the dial back finished.
+						if let PeerReq::DialBack { addr } = request {
+							match self.start_dial_back(peer, &addr) {
+								Ok(connection_id) => {
+									self.dial_backs.insert(
+										connection_id,
+										PendingDialBack {
+											channel,
+											inbound,
+											started: std::time::Instant::now(),
+										},
+									);
+								}
+								Err(reason) => {
+									tracing::info!(
+										"declined dial back to {addr} for {peer}: {reason}"
+									);
+									let response = PeerRes::DialBackDeclined { reason };
+									inbound.finish(
+										received.map(|_| std::time::Instant::now()),
+										&response,
+									);
+									let _ = self
+										.swarm
+										.behaviour_mut()
+										.puppynet
+										.send_response(channel, response);
+								}
+							}
+							return;
+						}
+						let started = received.map(|_| std::time::Instant::now());
+						let res = match self
+							.handle_puppy_peer_req(peer, request)
+							.instrument(span)
+							.await
+						{
+							Ok(res) => res,
+							Err(_) => PeerRes::Error("Internal error".into()),
+						};
+						inbound.finish(started, &res);
+						let _ = self
+							.swarm
+							.behaviour_mut()
+							.puppynet
+							.send_response(channel, res);
 					}
 					libp2p::request_response::Message::Response {
 						request_id,
 						response,
 					} => {
+						let error = match &response {
+							PeerRes::Error(err) => Some(err.clone()),
+							PeerRes::Unavailable { share } => Some(
+								ShareUnavailable {
+									share: share.clone(),
+								}
+								.to_string(),
+							),
+							PeerRes::AccessDenied(explanation) => Some(explanation.to_string()),
+							PeerRes::RateLimited { .. } => Some(String::from("rate limited")),
+							PeerRes::ImageTooLarge(too_large) => Some(too_large.to_string()),
+							PeerRes::UserRefused(refused) => Some(refused.to_string()),
+							_ => None,
+						};
+						if let Some((counters, kind)) = self.outbound_counters.remove(&request_id) {
+							counters.response(kind, wire_len(&response));
+						}
+						self.outbound_peers.remove(&request_id);
+						self.finish_outbound_trace(&request_id, error);
+						if let Some((peer, path, flags)) = self.grant_checks.remove(&request_id)
+							&& match &response {
+								PeerRes::Error(err) => err.starts_with(ACCESS_DENIED),
+								PeerRes::AccessDenied(_) => true,
+								_ => false,
+							} && self.grant_cache.refuted(&peer, &path, flags)
+						{
+							self.remote_grants_refuted(peer);
+						}
 						if let Some(pending) = self.pending_requests.remove(&request_id) {
 							pending.complete(response);
 						}
@@ -2088,7 +7077,11 @@ impl App {
 					request_id,
 					error,
 				} => {
-					log::warn!("outbound request to {} failed: {error}", peer);
+					tracing::warn!("outbound request to {} failed: {error}", peer);
+					self.finish_outbound_trace(&request_id, Some(error.to_string()));
+					self.outbound_counters.remove(&request_id);
+					self.outbound_peers.remove(&request_id);
+					self.grant_checks.remove(&request_id);
 					if let Some(pending) = self.pending_requests.remove(&request_id) {
 						pending.fail(anyhow!("request failed: {error}"));
 					}
@@ -2099,40 +7092,35 @@ impl App {
 					request_id: _,
 					error,
 				} => {
-					log::warn!("inbound failure from {}: {error}", peer);
+					tracing::warn!("inbound failure from {}: {error}", peer);
 				}
 				libp2p::request_response::Event::ResponseSent {
 					peer,
 					connection_id: _,
 					request_id: _,
 				} => {
-					log::debug!("response sent to {}", peer);
+					tracing::debug!("response sent to {}", peer);
 				}
 			},
 			AgentEvent::Mdns(event) => match event {
 				mdns::Event::Discovered(items) => {
+					let mut discovered: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
 					for (peer_id, multiaddr) in items {
-						log::info!("mDNS discovered peer {} at {}", peer_id, multiaddr);
-						self.state.peer_discovered(peer_id, multiaddr.clone());
-						if let Ok(mut conn) = self.db.lock() {
-							let _ = save_discovered_peer(
-								&mut *conn,
-								&DiscoveredPeer {
-									peer_id,
-									multiaddr: multiaddr.clone(),
-								},
-							);
-						}
-						self.swarm.dial(multiaddr).unwrap();
+						tracing::info!("mDNS discovered peer {} at {}", peer_id, multiaddr);
+						self.discovered
+							.record(peer_id, multiaddr.clone(), self.clock.now());
+						self.discovery_writes.seen(peer_id, multiaddr.clone());
+						discovered.entry(peer_id).or_default().push(multiaddr);
+					}
+					for (peer_id, addrs) in discovered {
+						dial_discovered(&mut self.swarm, &mut self.dialer, peer_id, addrs);
 					}
 				}
 				mdns::Event::Expired(items) => {
 					for (peer_id, multiaddr) in items {
-						log::info!("mDNS expired peer {} at {}", peer_id, multiaddr);
-						self.state.peer_expired(peer_id, multiaddr.clone());
-						if let Ok(mut conn) = self.db.lock() {
-							let _ = remove_discovered_peer(&mut *conn, &peer_id, &multiaddr);
-						}
+						tracing::info!("mDNS expired peer {} at {}", peer_id, multiaddr);
+						self.discovered.remove(&peer_id, &multiaddr);
+						self.discovery_writes.expired(peer_id, multiaddr);
 					}
 				}
 			},
@@ -2146,42 +7134,107 @@ impl App {
 				peer_id,
 				connection_id,
 				endpoint,
-				num_established: _,
+				num_established,
 				concurrent_dial_errors: _,
 				established_in: _,
 			} => {
-				log::info!("Connected to peer {}", peer_id);
+				// Dial-backs only prove the address works; the peer is
+				// already connected.
+				if self.finish_dial_back(connection_id, Ok(())) {
+					self.swarm.close_connection(connection_id);
+					return;
+				}
+				tracing::info!("Connected to peer {}", peer_id);
+				if endpoint.is_dialer() {
+					dial_finished(&mut self.swarm, &mut self.dialer, peer_id, Ok(()));
+				}
+				let (direction, local_addr, remote_addr) = match endpoint {
+					ConnectedPoint::Dialer { address, .. } => {
+						(ConnectionDirection::Outbound, None, address.clone())
+					}
+					ConnectedPoint::Listener {
+						local_addr,
+						send_back_addr,
+					} => (
+						ConnectionDirection::Inbound,
+						Some(local_addr.clone()),
+						send_back_addr.clone(),
+					),
+				};
+				self.record_peer_address(&peer_id, &remote_addr);
+				let remote_ip = wake::multiaddr_ip(&remote_addr);
 				self.state.connections.push(Connection {
 					peer_id: peer_id.clone(),
 					connection_id,
+					direction,
+					local_addr,
+					remote_addr,
+					connected_at: self.clock.now(),
 				});
-				if let Some(addr) = match endpoint {
-					ConnectedPoint::Dialer { address, .. } => Some(address.clone()),
-					ConnectedPoint::Listener { send_back_addr, .. } => Some(send_back_addr.clone()),
-				} {
-					self.record_peer_address(&peer_id, &addr);
-				}
 				if let Ok(mut conn) = self.db.lock() {
 					let _ = save_peer(
 						&mut *conn,
 						&Peer {
 							id: peer_id,
 							name: None,
+							trust: TrustLevel::Untrusted,
 						},
 					);
 				}
+				self.flush_permission_outbox(peer_id);
+				if num_established.get() == 1 {
+					self.activity.record(
+						ActivityEntry::new(ActivityEventKind::Peer, self.clock.now(), "Connected")
+							.peer(peer_id),
+					);
+					self.keeper.connected(peer_id);
+					self.send_hello(peer_id);
+					self.learn_wake_target(peer_id, remote_ip);
+				}
 			}
 			SwarmEvent::ConnectionClosed {
 				peer_id,
 				connection_id,
 				endpoint: _,
-				num_established: _,
-				cause: _,
+				num_established,
+				cause,
 			} => {
-				log::info!("Disconnected from peer {}", peer_id);
-				self.state
-					.connections
-					.retain(|c| c.connection_id != connection_id);
+				let reason = DisconnectReason::from_cause(cause.as_ref());
+				let now = self.clock.now();
+				if self
+					.state
+					.remove_connection(connection_id, reason, now)
+					.is_none()
+				{
+					return;
+				}
+				match &cause {
+					Some(err) if reason == DisconnectReason::Lost => {
+						tracing::info!("Disconnected from peer {peer_id}: {err}")
+					}
+					_ => tracing::debug!("Disconnected from peer {peer_id} ({reason:?})"),
+				}
+				if num_established == 0 {
+					let summary = match reason {
+						DisconnectReason::Idle => "Disconnected while idle",
+						DisconnectReason::Lost => "Connection lost",
+						DisconnectReason::Deliberate => "Disconnected",
+					};
+					self.activity.record(
+						ActivityEntry::new(ActivityEventKind::Peer, now, summary).peer(peer_id),
+					);
+					self.fail_requests_to(peer_id, reason);
+					self.end_shell_sessions(peer_id);
+					self.state.capabilities.remove(&peer_id);
+				}
+				let important = self.state.important_peers.contains(&peer_id);
+				self.keeper.disconnected(
+					peer_id,
+					reason,
+					num_established > 0,
+					important,
+					std::time::Instant::now(),
+				);
 			}
 			SwarmEvent::IncomingConnection {
 				connection_id: _,
@@ -2196,10 +7249,21 @@ impl App {
 				peer_id: _,
 			} => {}
 			SwarmEvent::OutgoingConnectionError {
-				connection_id: _,
-				peer_id: _,
-				error: _,
-			} => {}
+				connection_id,
+				peer_id: Some(peer_id),
+				error,
+			} => {
+				if self.finish_dial_back(connection_id, Err(error.to_string())) {
+					return;
+				}
+				tracing::debug!("dial to {peer_id} failed: {error}");
+				dial_finished(
+					&mut self.swarm,
+					&mut self.dialer,
+					peer_id,
+					Err(error.to_string()),
+				);
+			}
 			SwarmEvent::Dialing {
 				peer_id: _,
 				connection_id: _,
@@ -2215,7 +7279,8 @@ impl App {
 				listener_id: _,
 				address,
 			} => {
-				log::info!("listener address added: {:?}", address);
+				tracing::info!("listener address added: {:?}", address);
+				self.record_listen_port(&address);
 			}
 			SwarmEvent::ExpiredListenAddr {
 				listener_id: _,
@@ -2241,45 +7306,115 @@ impl App {
 					let _ = tx.send(Ok(Self::local_peer_info()));
 					return;
 				}
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer_id, PeerReq::PeerInfo);
+				let request_id = self.send_peer_request(&peer_id, PeerReq::PeerInfo);
 				self.pending_requests
 					.insert(request_id, Pending::<PeerInfo>::new(tx));
 			}
 			Command::Connect { peer_id: _, addr } => {
 				if let Err(err) = self.swarm.dial(addr) {
-					log::error!("dial failed: {err}");
+					tracing::error!("dial failed: {err}");
 				}
 			}
+			Command::Request { peer, req, tx } => {
+				let request_id = self.send_peer_request(&peer, req);
+				self.pending_requests
+					.insert(request_id, Pending::<PeerRes>::new(tx));
+			}
 			Command::ListDir { peer, path, tx } => {
 				let is_self = self.state.me == peer;
 				if is_self {
-					let result = match fs::canonicalize(&path).await {
+					let local = match path.to_local() {
+						Ok(local) => local,
+						Err(err) => {
+							let _ = tx.send(Err(anyhow!(err)));
+							return;
+						}
+					};
+					let canonical = match fs::canonicalize(local).await {
+						Ok(canonical) => canonical,
+						Err(err) => {
+							let _ = tx.send(Err(anyhow!("Failed to access directory: {err}")));
+							return;
+						}
+					};
+					if !self.can_access(peer, &canonical, FLAG_READ | FLAG_SEARCH) {
+						let _ = tx.send(Err(anyhow!("Access denied")));
+						return;
+					}
+					let free_hint = self.free_hint_for(peer, &canonical).await;
+					let mut access =
+						self.state
+							.listing_access_at(peer, &canonical, self.clock.now());
+					let db = Arc::clone(&self.db);
+					let node_id = self.local_node_id();
+					// Listed off the loop, so a big folder doesn't hold up
+					// peers' requests.
+					tokio::spawn(async move {
+						let result = match Self::collect_dir_entries(&canonical).await {
+							Ok(mut entries) => {
+								refine_mimes(&db, node_id, &canonical, &mut entries).await;
+								access.keep_listed(&entries);
+								Ok(DirListing {
+									entries,
+									free_hint,
+									access: Some(access),
+								})
+							}
+							Err(err) => Err(err),
+						};
+						let _ = tx.send(result);
+					});
+					return;
+				}
+				let request_id = self.send_peer_request(
+					&peer,
+					PeerReq::ListDir {
+						path: path.to_wire(),
+					},
+				);
+				if let Some(prev) = self
+					.pending_requests
+					.insert(request_id, Pending::<DirListing>::new(tx))
+				{
+					prev.fail(anyhow!("pending ListDir request was replaced"));
+				}
+			}
+			Command::StatFile { peer, path, tx } => {
+				if self.state.me == peer {
+					let local = match path.to_local() {
+						Ok(local) => local,
+						Err(err) => {
+							let _ = tx.send(Err(anyhow!(err)));
+							return;
+						}
+					};
+					let result = match fs::canonicalize(local).await {
 						Ok(canonical) => {
 							if self.can_access(peer, &canonical, FLAG_READ | FLAG_SEARCH) {
-								Self::collect_dir_entries(&canonical).await
+								match fs::metadata(&canonical).await {
+									Ok(meta) => Ok(self.stat_local_entry(&canonical, &meta).await),
+									Err(err) => Err(err.into()),
+								}
 							} else {
 								Err(anyhow!("Access denied"))
 							}
 						}
-						Err(err) => Err(anyhow!("Failed to access directory: {err}")),
+						Err(err) => Err(anyhow!("Failed to access file: {err}")),
 					};
 					let _ = tx.send(result);
 					return;
 				}
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer, PeerReq::ListDir { path: path.clone() });
+				let request_id = self.send_peer_request(
+					&peer,
+					PeerReq::StatFile {
+						path: path.to_wire(),
+					},
+				);
 				if let Some(prev) = self
 					.pending_requests
-					.insert(request_id, Pending::<Vec<DirEntry>>::new(tx))
+					.insert(request_id, Pending::<DirEntry>::new(tx))
 				{
-					prev.fail(anyhow!("pending ListDir request was replaced"));
+					prev.fail(anyhow!("pending StatFile request was replaced"));
 				}
 			}
 			Command::ListCpus { tx, peer_id } => {
@@ -2288,27 +7423,55 @@ impl App {
 					let _ = tx.send(Ok(cpus));
 					return;
 				}
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer_id, PeerReq::ListCpus);
+				let request_id = self.send_peer_request(&peer_id, PeerReq::ListCpus);
+				self.pending_requests
+					.insert(request_id, Pending::<Vec<CpuInfo>>::new(tx));
+			}
+			Command::ListDisks { tx, peer_id } => {
+				if self.state.me == peer_id {
+					let disks = Self::collect_disk_info();
+					let _ = tx.send(Ok(disks));
+					return;
+				}
+				let request_id = self.send_peer_request(&peer_id, PeerReq::ListDisks);
+				self.pending_requests
+					.insert(request_id, Pending::<Vec<DiskInfo>>::new(tx));
+			}
+			Command::DiskHistory {
+				peer_id,
+				mount,
+				from,
+				to,
+				tx,
+			} => {
+				if self.state.me == peer_id {
+					let _ = tx.send(self.disk_history(&mount, from, to));
+					return;
+				}
+				let request_id =
+					self.send_peer_request(&peer_id, PeerReq::DiskHistory { mount, from, to });
+				self.pending_requests
+					.insert(request_id, Pending::<Vec<DiskSample>>::new(tx));
+			}
+			Command::ListRoots { tx, peer_id } => {
+				if self.state.me == peer_id {
+					let roots = self.collect_browse_roots(peer_id);
+					let _ = tx.send(Ok(roots));
+					return;
+				}
+				let request_id = self.send_peer_request(&peer_id, PeerReq::ListRoots);
 				self.pending_requests
-					.insert(request_id, Pending::<Vec<CpuInfo>>::new(tx));
+					.insert(request_id, Pending::<BrowseRoots>::new(tx));
 			}
-			Command::ListDisks { tx, peer_id } => {
+			Command::WellKnownFolders { tx, peer_id } => {
 				if self.state.me == peer_id {
-					let disks = self.collect_disk_info();
-					let _ = tx.send(Ok(disks));
+					let folders = self.collect_well_known_folders(peer_id);
+					let _ = tx.send(Ok(folders));
 					return;
 				}
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer_id, PeerReq::ListDisks);
+				let request_id = self.send_peer_request(&peer_id, PeerReq::WellKnownFolders);
 				self.pending_requests
-					.insert(request_id, Pending::<Vec<DiskInfo>>::new(tx));
+					.insert(request_id, Pending::<Vec<WellKnownFolder>>::new(tx));
 			}
 			Command::ListInterfaces { tx, peer_id } => {
 				if self.state.me == peer_id {
@@ -2316,11 +7479,7 @@ impl App {
 					let _ = tx.send(Ok(interfaces));
 					return;
 				}
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer_id, PeerReq::ListInterfaces);
+				let request_id = self.send_peer_request(&peer_id, PeerReq::ListInterfaces);
 				self.pending_requests
 					.insert(request_id, Pending::<Vec<InterfaceInfo>>::new(tx));
 			}
@@ -2329,11 +7488,7 @@ impl App {
 					let _ = tx.send(Ok(audio::audio_capability().await));
 					return;
 				}
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer_id, PeerReq::AudioCapability);
+				let request_id = self.send_peer_request(&peer_id, PeerReq::AudioCapability);
 				self.pending_requests
 					.insert(request_id, Pending::<AudioCapability>::new(tx));
 			}
@@ -2343,11 +7498,7 @@ impl App {
 					let _ = tx.send(result);
 					return;
 				}
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer_id, PeerReq::ListAudioDevices);
+				let request_id = self.send_peer_request(&peer_id, PeerReq::ListAudioDevices);
 				self.pending_requests
 					.insert(request_id, Pending::<Vec<AudioDevice>>::new(tx));
 			}
@@ -2362,11 +7513,8 @@ impl App {
 					let _ = tx.send(result);
 					return;
 				}
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer_id, PeerReq::SetAudioMuted { device_id, muted });
+				let request_id =
+					self.send_peer_request(&peer_id, PeerReq::SetAudioMuted { device_id, muted });
 				self.pending_requests
 					.insert(request_id, Pending::<Vec<AudioDevice>>::new(tx));
 			}
@@ -2381,11 +7529,8 @@ impl App {
 					let _ = tx.send(result);
 					return;
 				}
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer_id, PeerReq::SetAudioVolume { device_id, volume });
+				let request_id =
+					self.send_peer_request(&peer_id, PeerReq::SetAudioVolume { device_id, volume });
 				self.pending_requests
 					.insert(request_id, Pending::<Vec<AudioDevice>>::new(tx));
 			}
@@ -2399,11 +7544,8 @@ impl App {
 					let _ = tx.send(result);
 					return;
 				}
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer_id, PeerReq::SetDefaultAudioDevice { device_id });
+				let request_id =
+					self.send_peer_request(&peer_id, PeerReq::SetDefaultAudioDevice { device_id });
 				self.pending_requests
 					.insert(request_id, Pending::<Vec<AudioDevice>>::new(tx));
 			}
@@ -2412,11 +7554,7 @@ impl App {
 					let _ = tx.send(Ok(webcam::media_capability().await));
 					return;
 				}
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer_id, PeerReq::MediaCapability);
+				let request_id = self.send_peer_request(&peer_id, PeerReq::MediaCapability);
 				self.pending_requests
 					.insert(request_id, Pending::<MediaCapability>::new(tx));
 			}
@@ -2426,11 +7564,7 @@ impl App {
 					let _ = tx.send(result);
 					return;
 				}
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer_id, PeerReq::ListMediaSources);
+				let request_id = self.send_peer_request(&peer_id, PeerReq::ListMediaSources);
 				self.pending_requests
 					.insert(request_id, Pending::<Vec<MediaSource>>::new(tx));
 			}
@@ -2446,11 +7580,8 @@ impl App {
 					});
 					return;
 				}
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer_id, PeerReq::GetMediaFrame { source_id });
+				let request_id =
+					self.send_peer_request(&peer_id, PeerReq::GetMediaFrame { source_id });
 				self.pending_requests
 					.insert(request_id, Pending::<MediaFrame>::new(tx));
 			}
@@ -2462,40 +7593,22 @@ impl App {
 			} => {
 				if self.state.me == peer {
 					let result = self
-						.fetch_file_entries(offset, limit)
+						.fetch_file_entries(peer, offset, limit)
 						.map_err(|err| anyhow!(err));
 					let _ = tx.send(result);
 					return;
 				}
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer, PeerReq::FileEntries { offset, limit });
+				let request_id =
+					self.send_peer_request(&peer, PeerReq::FileEntries { offset, limit });
 				self.pending_requests
 					.insert(request_id, Pending::<Vec<FileEntry>>::new(tx));
 			}
-			Command::ListPermissions { peer, tx } => {
-				let local_permissions = if self.state.me == peer {
-					Some(self.state.permissions_for_peer(&peer))
-				} else {
-					None
-				};
-				if let Some(permissions) = local_permissions {
-					let _ = tx.send(Ok(permissions));
-					return;
-				}
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer, PeerReq::ListPermissions);
-				if let Some(prev) = self
-					.pending_requests
-					.insert(request_id, Pending::<Vec<Permission>>::new(tx))
-				{
-					prev.fail(anyhow!("pending ListPermissions request was replaced"));
-				}
+			Command::ListPermissions {
+				peer,
+				force_refresh,
+				tx,
+			} => {
+				self.list_remote_grants(peer, force_refresh, tx);
 			}
 			Command::GrantPermissions {
 				peer,
@@ -2504,7 +7617,7 @@ impl App {
 				merge,
 				tx,
 			} => {
-				let request_id = self.swarm.behaviour_mut().puppynet.send_request(
+				let request_id = self.send_peer_request(
 					&peer,
 					PeerReq::GrantAccess {
 						username,
@@ -2521,10 +7634,19 @@ impl App {
 			}
 			Command::ReadFile(req) => {
 				if self.state.me == req.peer_id {
-					let chunk = match fs::canonicalize(&req.path).await {
+					self.thumbnail_queue
+						.note_foreground(std::time::Instant::now());
+					let local = match req.path.to_local() {
+						Ok(local) => local,
+						Err(err) => {
+							let _ = req.tx.send(Err(anyhow!(err)));
+							return;
+						}
+					};
+					let chunk = match fs::canonicalize(local).await {
 						Ok(canonical) => {
 							if self.can_access(req.peer_id, &canonical, FLAG_READ | FLAG_SEARCH) {
-								read_file(&canonical, req.offset, req.length).await
+								read_file(&canonical, req.offset, req.length, req.sparse).await
 							} else {
 								Err(anyhow!("Access denied"))
 							}
@@ -2534,12 +7656,13 @@ impl App {
 					let _ = req.tx.send(chunk);
 					return;
 				}
-				let request_id = self.swarm.behaviour_mut().puppynet.send_request(
+				let request_id = self.send_peer_request(
 					&req.peer_id,
 					PeerReq::ReadFile {
-						path: req.path.clone(),
+						path: req.path.to_wire(),
 						offset: req.offset,
 						length: req.length,
+						sparse: req.sparse,
 					},
 				);
 				self.pending_requests
@@ -2549,52 +7672,121 @@ impl App {
 				path,
 				tx,
 				cancel_flag,
+				extract_metadata,
+				limits,
 			} => {
-				let canonical = match fs::canonicalize(&path).await {
+				// The stream is still empty here, so these never hit a full
+				// channel and the event loop never waits on the consumer.
+				let local = match path.to_local() {
+					Ok(local) => local,
+					Err(err) => {
+						let _ = tx.try_send(ScanEvent::Finished(Err(err.to_string())));
+						return;
+					}
+				};
+				let canonical = match fs::canonicalize(&local).await {
 					Ok(canonical) => canonical,
 					Err(err) => {
-						let _ = tx.send(ScanEvent::Finished(Err(format!(
+						let _ = tx.try_send(ScanEvent::Finished(Err(format!(
 							"failed to access path: {err}"
 						))));
 						return;
 					}
 				};
 				if !self.can_access(self.state.me, &canonical, FLAG_READ | FLAG_SEARCH) {
-					let _ = tx.send(ScanEvent::Finished(Err(String::from("Access denied"))));
+					let _ = tx.try_send(ScanEvent::Finished(Err(String::from("Access denied"))));
 					return;
 				}
 				let node_id = match self.local_node_id() {
 					Some(id) => id,
 					None => {
-						let _ = tx.send(ScanEvent::Finished(Err(String::from(
+						let _ = tx.try_send(ScanEvent::Finished(Err(String::from(
 							"failed to determine node id",
 						))));
 						return;
 					}
 				};
+				let path = canonical.to_string_lossy().to_string();
+				let work = match self
+					.maintenance
+					.begin(format!("scan of {path}"), Some(Arc::clone(&cancel_flag)))
+				{
+					Ok(work) => work,
+					Err(refused) => {
+						let _ = tx.try_send(ScanEvent::Finished(Err(refused.to_string())));
+						return;
+					}
+				};
 				let db = Arc::clone(&self.db);
 				let cancel_flag = Arc::clone(&cancel_flag);
-				let path = canonical.to_string_lossy().to_string();
+				let started_at = self.clock.now();
+				let me = self.state.me;
+				let internal_tx = self.internal_tx.clone();
+				let thumbnail_queue = Arc::clone(&self.thumbnail_queue);
+				let throttle = ScanThrottle::local(self.scan_limits(), limits);
 				tokio::task::spawn_blocking(move || {
-					let result = db
-						.lock()
-						.map_err(|err| format!("db lock poisoned: {}", err))
-						.and_then(|mut guard| {
-							scan::scan_with_progress_cancelable(
-								&node_id,
-								&path,
-								&mut *guard,
-								|progress| {
-									let _ = tx.send(ScanEvent::Progress(progress.clone()));
-								},
-								|| cancel_flag.load(Ordering::SeqCst),
-							)
+					let _work = work;
+					let _scanning = thumbnail_queue.scan_started();
+					let result = db.write(|conn| {
+						let mut tally = ScanTally::default();
+						let result = scan_and_record(
+							conn,
+							ScanRecordOptions {
+								node_id: &node_id,
+								path: &path,
+								started_at,
+								initiated_by: Some(&me),
+								throttle: &throttle,
+							},
+							|progress| {
+								// Never waits on the consumer while the
+								// writer is held; one that is gone cancels
+								// the scan.
+								if !send_superseded(&tx, ScanEvent::Progress(progress.clone())) {
+									cancel_flag.store(true, Ordering::SeqCst);
+								}
+								send_index_change(
+									&internal_tx,
+									tally.advance(
+										progress.inserted_count,
+										progress.updated_count,
+										progress.removed_count,
+									),
+								);
+							},
+							|| cancel_flag.load(Ordering::SeqCst),
+						);
+						if let Ok(stats) = &result {
+							send_index_change(
+								&internal_tx,
+								tally.advance(
+									stats.inserted_count,
+									stats.updated_count,
+									stats.removed_count,
+								),
+							);
+							queue_scanned_images(&thumbnail_queue, conn, &node_id, &path);
+						}
+						let _ = internal_tx.send(InternalCommand::InvalidateDerived {
+							paths: stale_scan_paths(&result),
+						});
+						result
+					});
+					if extract_metadata && result.is_ok() && !cancel_flag.load(Ordering::SeqCst) {
+						let report = extract_media_metadata(&db, &node_id, Some(&path), || {
+							cancel_flag.load(Ordering::SeqCst)
 						});
+						tracing::info!(
+							"media metadata for {path}: {} extracted, {} failed",
+							report.extracted,
+							report.failed
+						);
+					}
 					let final_event = match result {
 						Ok(stats) => ScanEvent::Finished(Ok(stats)),
 						Err(err) => ScanEvent::Finished(Err(err)),
 					};
-					let _ = tx.send(final_event);
+					send_blocking(&tx, final_event);
 				});
 			}
 			Command::LiveSearch {
@@ -2604,6 +7796,7 @@ impl App {
 			} => {
 				if self.state.me == peer {
 					let roots = self.state.search_roots_for_peer(&peer);
+					let hidden = self.state.hidden_names(peer, self.clock.now());
 					let tx = self.remote_searches.lock().unwrap().remove(&search_id);
 					if let Some(tx) = tx {
 						tokio::task::spawn_blocking(move || {
@@ -2614,14 +7807,14 @@ impl App {
 								});
 								return;
 							}
-							live_search_roots(roots, args, |event| {
+							live_search_roots(roots, args, hidden, |event| {
 								let _ = tx.send(event);
 							});
 						});
 					}
 					return;
 				}
-				let request_id = self.swarm.behaviour_mut().puppynet.send_request(
+				let request_id = self.send_peer_request(
 					&peer,
 					PeerReq::StartSearch {
 						id: search_id,
@@ -2633,25 +7826,62 @@ impl App {
 					PendingRemoteSearchStart::request(search_id, Arc::clone(&self.remote_searches)),
 				);
 			}
+			Command::SearchPeerFiles { peer_id, args, tx } => {
+				if self.state.me == peer_id {
+					let _ = tx.send(self.search_files_for(peer_id, args));
+					return;
+				}
+				let request_id = self.send_peer_request(&peer_id, PeerReq::SearchFiles { args });
+				self.pending_requests
+					.insert(request_id, Pending::<Vec<FileSearchResult>>::new(tx));
+			}
+			Command::ShareSummaries { peer_id, tx } => {
+				if self.state.me == peer_id {
+					let _ = tx.send(self.share_summaries_for(peer_id));
+					return;
+				}
+				let request_id = self.send_peer_request(&peer_id, PeerReq::ShareSummary);
+				self.pending_requests
+					.insert(request_id, Pending::<Vec<ShareSummary>>::new(tx));
+			}
 			Command::RemoteScan {
 				peer,
 				path,
 				scan_id,
+				limits,
 			} => {
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&peer, PeerReq::StartScan { id: scan_id, path });
+				let request_id = self.send_peer_request(
+					&peer,
+					PeerReq::StartScan {
+						id: scan_id,
+						path: path.to_string(),
+						limits,
+					},
+				);
 				self.pending_requests.insert(
 					request_id,
 					PendingRemoteScanStart::new(scan_id, Arc::clone(&self.remote_scans)),
 				);
 			}
+			Command::PullScanResults {
+				peer,
+				run,
+				cursor,
+				tx,
+			} => {
+				let request_id =
+					self.send_peer_request(&peer, PeerReq::ScanResultsPull { run, cursor });
+				self.pending_requests
+					.insert(request_id, Pending::<ScanResults>::new(tx));
+			}
 			Command::ListStorageFiles { tx } => {
 				let result = self.fetch_storage_files();
 				let _ = tx.send(result);
 			}
+			Command::InvalidateDerived { path, tx } => {
+				self.invalidate_derived(&path);
+				let _ = tx.send(());
+			}
 			Command::GetThumbnail {
 				peer,
 				path,
@@ -2661,23 +7891,42 @@ impl App {
 			} => {
 				let is_self = self.state.me == peer;
 				if is_self {
-					let result = match fs::canonicalize(&path).await {
-						Ok(canonical) => {
-							if self.can_access(peer, &canonical, FLAG_READ | FLAG_SEARCH) {
-								generate_thumbnail(&canonical, max_width, max_height).await
-							} else {
-								Err(anyhow!("Access denied"))
-							}
+					self.thumbnail_queue
+						.note_foreground(std::time::Instant::now());
+					let local = match path.to_local() {
+						Ok(local) => local,
+						Err(err) => {
+							let _ = tx.send(Err(anyhow!(err)));
+							return;
 						}
-						Err(err) => Err(anyhow!("Failed to access file: {err}")),
 					};
-					let _ = tx.send(result);
+					let canonical = match fs::canonicalize(&local).await {
+						Ok(canonical) => canonical,
+						Err(err) => {
+							let _ = tx.send(Err(anyhow!("Failed to access file: {err}")));
+							return;
+						}
+					};
+					if !self.can_access(peer, &canonical, FLAG_READ | FLAG_SEARCH) {
+						let _ = tx.send(Err(anyhow!("Access denied")));
+						return;
+					}
+					let cache = Arc::clone(&self.thumbnails);
+					let disk = Arc::clone(&self.thumbnail_disk);
+					let limits = Arc::clone(&self.decode_limits);
+					tokio::spawn(async move {
+						let result = cached_thumbnail(
+							&cache, &disk, &limits, &canonical, max_width, max_height,
+						)
+						.await;
+						let _ = tx.send(result);
+					});
 					return;
 				}
-				let request_id = self.swarm.behaviour_mut().puppynet.send_request(
+				let request_id = self.send_peer_request(
 					&peer,
 					PeerReq::GetThumbnail {
-						path,
+						path: path.to_string(),
 						max_width,
 						max_height,
 					},
@@ -2685,12 +7934,87 @@ impl App {
 				self.pending_requests
 					.insert(request_id, Pending::<Thumbnail>::new(tx));
 			}
+			Command::ContactSheet {
+				peer,
+				path,
+				options,
+				tx,
+			} => {
+				if self.state.me == peer {
+					let job = self
+						.contact_sheet_job(peer, path.to_string(), options)
+						.await;
+					tokio::spawn(async move {
+						let response = match job {
+							Ok(job) => job.run().await,
+							Err(refused) => refused,
+						};
+						Pending::<ContactSheetResult>::new(tx).complete(response);
+					});
+					return;
+				}
+				let options = options.clamped();
+				let request_id = self.send_peer_request(
+					&peer,
+					PeerReq::ContactSheet {
+						path: path.to_string(),
+						columns: options.columns,
+						cell_size: options.cell_size,
+						max_items: options.max_items,
+					},
+				);
+				self.pending_requests
+					.insert(request_id, Pending::<ContactSheetResult>::new(tx));
+			}
+			Command::RestartPeer {
+				peer,
+				delay_secs,
+				tx,
+			} => {
+				if self.state.me == peer {
+					let delay_secs = self.schedule_restart(delay_secs);
+					let _ = tx.send(Ok(RestartAck { delay_secs }));
+					return;
+				}
+				let request_id = self.send_peer_request(&peer, PeerReq::Restart { delay_secs });
+				self.pending_requests
+					.insert(request_id, Pending::<RestartAck>::new(tx));
+			}
+			Command::HealthCheck { peer, tx } => {
+				if self.state.me == peer {
+					let _ = tx.send(Ok(self.health_snapshot()));
+					return;
+				}
+				// Health checks double as the reconnect probe after a restart,
+				// so dial every known address.
+				let addresses = self.known_peer_addresses(&peer);
+				let request_id = self
+					.swarm
+					.behaviour_mut()
+					.puppynet
+					.send_request_with_addresses(&peer, PeerReq::HealthCheck, addresses);
+				self.pending_requests
+					.insert(request_id, Pending::<PeerHealth>::new(tx));
+			}
+			Command::SendIndexDelta { peer, delta, tx } => {
+				let supported = self
+					.state
+					.peer_capabilities(&peer)
+					.is_some_and(|capabilities| capabilities.supports(FEATURE_INDEX_REPLICATION));
+				if !supported {
+					let _ = tx.send(Err(anyhow!("peer {peer} can't keep an index replica")));
+					return;
+				}
+				let request_id = self.send_peer_request(&peer, PeerReq::IndexDelta { delta });
+				self.pending_requests
+					.insert(request_id, Pending::<IndexDeltaAck>::new(tx));
+			}
 			Command::RemoteUpdate {
 				peer,
 				version,
 				update_id,
 			} => {
-				let request_id = self.swarm.behaviour_mut().puppynet.send_request(
+				let request_id = self.send_peer_request(
 					&peer,
 					PeerReq::UpdateSelf {
 						id: update_id,
@@ -2703,86 +8027,442 @@ impl App {
 				);
 			}
 			Command::InjectDiscoveredPeer { peer, addr, tx } => {
-				self.state.peer_discovered(peer, addr);
+				self.discovered.record(peer, addr, self.clock.now());
 				let _ = tx.send(());
 			}
 			Command::GetState { tx } => {
 				let _ = tx.send(self.state.clone());
 			}
-			Command::RegisterSharedFolder { path, flags, tx } => {
+			Command::QueryDiscoveredPeers { filter, limit, tx } => {
+				let _ = tx.send(self.discovered.query(&filter, limit));
+			}
+			Command::QueryUsers { tx } => {
+				let _ = tx.send(self.users.iter().map(|user| user.name.clone()).collect());
+			}
+			Command::SetDiscoveredAddressTtl { ttl, tx } => {
+				let result = (|| -> anyhow::Result<()> {
+					if ttl <= chrono::Duration::zero() {
+						bail!("Retention must be positive");
+					}
+					let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+					save_setting(
+						&conn,
+						DISCOVERED_ADDRESS_TTL_SETTING,
+						&ttl.num_seconds().to_string(),
+					)?;
+					Ok(())
+				})();
+				if result.is_ok() {
+					self.discovered.set_ttl(ttl);
+					self.discovered.expire(self.clock.now());
+				}
+				let _ = tx.send(result);
+			}
+			Command::SetGrantCacheTtl { ttl, tx } => {
 				let result = (|| -> anyhow::Result<()> {
+					if ttl <= chrono::Duration::zero() {
+						bail!("Cache lifetime must be positive");
+					}
+					let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+					save_setting(
+						&conn,
+						GRANT_CACHE_TTL_SETTING,
+						&ttl.num_seconds().to_string(),
+					)?;
+					Ok(())
+				})();
+				if result.is_ok() {
+					self.grant_cache.set_ttl(ttl);
+				}
+				let _ = tx.send(result);
+			}
+			Command::ReloadStoredState { tx } => {
+				let _ = tx.send(self.reload_stored_state());
+			}
+			Command::RegisterSharedFolder { path, flags, tx } => {
+				let result = (|| -> anyhow::Result<Vec<RuleOverlap>> {
 					let rule = FolderRule::new(path, flags);
 					{
 						let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
 						save_shared_folder(&conn, &rule)?;
 					}
-					self.state.add_shared_folder(rule);
+					let overlaps = self.state.add_shared_folder(rule);
+					for overlap in &overlaps {
+						tracing::warn!("overlapping shared folder: {}", overlap.describe());
+					}
+					Ok(overlaps)
+				})();
+				let _ = tx.send(result);
+			}
+			Command::CreateUser {
+				username,
+				password,
+				tx,
+			} => {
+				let name = match self
+					.maintenance
+					.check()
+					.map_err(anyhow::Error::from)
+					.and_then(|()| self.check_new_user(&username, &password))
+				{
+					Ok(name) => name,
+					Err(err) => {
+						let _ = tx.send(Err(err));
+						return;
+					}
+				};
+				let internal_tx = self.internal_tx.clone();
+				tokio::spawn(async move {
+					let user = hash_new_user(name, password).await;
+					let _ = internal_tx.send(InternalCommand::UserHashed {
+						user,
+						reply: NewUserReply::Command(tx),
+					});
+				});
+			}
+			Command::DeleteUser { username, tx } => {
+				let result = (|| -> anyhow::Result<()> {
+					self.maintenance.check()?;
+					if username.trim().is_empty() {
+						bail!("Username is required");
+					}
+					if self.users.len() <= 1 {
+						bail!("Cannot delete the last user");
+					}
+					if !self.users.iter().any(|u| u.name == username) {
+						bail!("User not found");
+					}
+					{
+						let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+						delete_user(&conn, &username)?;
+					}
+					self.users.retain(|u| u.name != username);
+					Ok(())
+				})();
+				let _ = tx.send(result);
+			}
+			Command::SetPeerPermissions {
+				peer,
+				permissions,
+				expected_revision,
+				tx,
+			} => {
+				let result = (|| -> anyhow::Result<Vec<RuleOverlap>> {
+					self.maintenance.check()?;
+					let me = self.state.me;
+					let mut conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+					crate::db::save_peer_permissions_at(
+						&mut conn,
+						&me,
+						&peer,
+						&permissions,
+						expected_revision,
+					)?;
+					Ok(self.state.set_peer_permissions(peer, permissions))
+				})();
+				if result.is_ok() {
+					self.notify_permissions_changed(peer);
+				}
+				let _ = tx.send(result);
+			}
+			Command::GrantFolderToPeers { folder, peers, tx } => {
+				let result = match self.maintenance.check() {
+					Ok(()) => self.grant_folder_to_peers(folder, peers),
+					Err(refused) => Err(refused.into()),
+				};
+				let _ = tx.send(result);
+			}
+			Command::SetRemoteAccessSuspended { suspended, tx } => {
+				let result = (|| -> anyhow::Result<()> {
+					self.maintenance.check()?;
+					let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+					save_setting(
+						&conn,
+						REMOTE_ACCESS_SUSPENDED_SETTING,
+						if suspended { "true" } else { "false" },
+					)?;
 					Ok(())
 				})();
+				if result.is_ok() {
+					self.state.remote_access_suspended = suspended;
+					if suspended {
+						tracing::warn!("remote filesystem access suspended");
+					} else {
+						tracing::info!("remote filesystem access resumed");
+					}
+				}
+				let _ = tx.send(result);
+			}
+			Command::SetMaintenance { maintenance, tx } => {
+				let result = (|| -> anyhow::Result<()> {
+					let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+					match &maintenance {
+						Some(maintenance) => save_setting(
+							&conn,
+							MAINTENANCE_SETTING,
+							&serde_json::to_string(maintenance)?,
+						),
+						None => delete_setting(&conn, MAINTENANCE_SETTING),
+					}
+				})();
+				if result.is_ok() {
+					match &maintenance {
+						Some(maintenance) => {
+							tracing::warn!("entering maintenance: {}", maintenance.reason)
+						}
+						None => tracing::info!("maintenance ended"),
+					}
+					self.maintenance.set(maintenance.clone());
+					self.state.maintenance = maintenance;
+				}
 				let _ = tx.send(result);
 			}
-			Command::CreateUser {
-				username,
-				password,
+			Command::GrantTemporary {
+				peer,
+				path,
+				flags,
+				ttl,
 				tx,
 			} => {
-				let result = (|| -> anyhow::Result<()> {
-					if self.state.users.iter().any(|u| u.name == username) {
-						bail!("User already exists");
+				let result = (|| -> anyhow::Result<TemporaryGrant> {
+					if ttl.is_zero() || ttl > MAX_TEMPORARY_GRANT_TTL {
+						bail!("temporary access must last between a second and a week");
 					}
-					let passw = auth::hash_password(&password)?;
-					let user = User {
-						name: username.clone(),
-						passw,
-					};
-					{
-						let mut conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
-						save_user(&mut *conn, &user)?;
+					let canonical = std::fs::canonicalize(&path)
+						.map_err(|err| anyhow!("cannot grant {}: {err}", path.display()))?;
+					if !self.can_access(self.state.me, &canonical, flags) {
+						bail!(
+							"{} is outside the allowed folders for this access",
+							canonical.display()
+						);
 					}
-					self.state.users.push(user);
-					Ok(())
+					let expires_at = self.clock.now() + ttl;
+					Ok(self.state.grant_temporary(
+						peer,
+						FolderRule::new(canonical, flags),
+						expires_at,
+					))
 				})();
+				if let Ok(grant) = &result {
+					tracing::info!(
+						"granted {} temporary access to {} until {}",
+						peer,
+						grant.rule.path().display(),
+						grant.expires_at
+					);
+				}
 				let _ = tx.send(result);
 			}
-			Command::DeleteUser { username, tx } => {
+			Command::RevokeTemporary { peer, id, tx } => {
+				let result = if self.state.revoke_temporary(&peer, id) {
+					tracing::info!("revoked temporary grant {id} of {peer}");
+					Ok(())
+				} else {
+					Err(anyhow!("temporary grant {id} not found"))
+				};
+				let _ = tx.send(result);
+			}
+			Command::SetNatMapping { enabled, tx } => {
 				let result = (|| -> anyhow::Result<()> {
-					if username.trim().is_empty() {
-						bail!("Username is required");
-					}
-					if self.state.users.len() <= 1 {
-						bail!("Cannot delete the last user");
-					}
-					if !self.state.users.iter().any(|u| u.name == username) {
-						bail!("User not found");
-					}
-					{
-						let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
-						delete_user(&conn, &username)?;
-					}
-					self.state.users.retain(|u| u.name != username);
+					let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+					save_setting(
+						&conn,
+						NAT_MAPPING_SETTING,
+						if enabled { "true" } else { "false" },
+					)?;
 					Ok(())
 				})();
+				if result.is_ok() {
+					if enabled && self.nat_mapper.is_none() {
+						tracing::info!("router port mapping enabled");
+						self.start_nat_mapper();
+					} else if !enabled && self.nat_mapper.is_some() {
+						tracing::info!("router port mapping disabled");
+						self.stop_nat_mapper().await;
+					}
+				}
 				let _ = tx.send(result);
 			}
-			Command::SetPeerPermissions {
+			Command::SetPeerImportant {
 				peer,
-				permissions,
+				important,
 				tx,
 			} => {
+				let mut peers = self.state.important_peers.clone();
+				if important {
+					peers.insert(peer);
+				} else {
+					peers.remove(&peer);
+				}
+				let result = self
+					.db
+					.lock()
+					.map_err(|_| anyhow!("db lock poisoned"))
+					.and_then(|conn| {
+						save_setting(
+							&conn,
+							IMPORTANT_PEERS_SETTING,
+							&important_peers_json(&peers),
+						)
+					});
+				if result.is_ok() {
+					self.state.important_peers = peers;
+					if important && !self.swarm.is_connected(&peer) {
+						self.keeper.redial_soon(peer, std::time::Instant::now());
+					} else if !important {
+						self.keeper.forget_redial(&peer);
+					}
+				}
+				let _ = tx.send(result);
+			}
+			Command::SetTrustLevel { peer, trust, tx } => {
 				let result = (|| -> anyhow::Result<()> {
-					let me = self.state.me;
-					self.state.set_peer_permissions(peer, permissions.clone());
-					let mut conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
-					crate::db::save_peer_permissions(&mut *conn, &me, &peer, &permissions)
-						.map_err(|err| anyhow!(err))?;
-					Ok(())
+					self.maintenance.check()?;
+					self.set_trust_level(peer, trust)
 				})();
 				let _ = tx.send(result);
 			}
+			Command::LimitPeerTraffic {
+				peer,
+				bytes_per_sec,
+				duration,
+				tx,
+			} => {
+				self.protocol.cap(peer, bytes_per_sec, duration);
+				tracing::info!(
+					"[{peer}] limited to {bytes_per_sec} bytes a second for {}",
+					human_duration(duration)
+				);
+				let _ = tx.send(Ok(()));
+			}
+			Command::SetPowerState { power } => self.state.power = power,
+			Command::NotifyLocal { message } => {
+				let me = self.state.me;
+				self.state.push_notification(me, message);
+			}
+			Command::TestReachability { tx } => self.test_reachability(tx),
 			Command::ListGrantedPermissions { peer, tx } => {
-				let result = Ok(self.state.permissions_granted_to_peer(&peer));
+				let result = (|| -> anyhow::Result<PermissionSet> {
+					let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+					Ok(PermissionSet {
+						revision: load_permission_revision(&conn, &self.state.me, &peer)?,
+						permissions: self.state.permissions_granted_to_peer(&peer),
+						temporary: self.state.temporary_grants_for(&peer, self.clock.now()),
+					})
+				})();
+				let _ = tx.send(result);
+			}
+			Command::SetNodeName { name, tx } => {
+				let result = (|| -> anyhow::Result<String> {
+					let name = normalize_node_name(&name)?;
+					let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+					save_setting(&conn, NODE_NAME_SETTING, &name)?;
+					Ok(name)
+				})();
+				if result.is_ok() {
+					self.persist_local_node();
+				}
+				let _ = tx.send(result);
+			}
+			Command::StartPairing { peer, tx } => {
+				if peer == self.state.me {
+					let _ = tx.send(Err(anyhow!("cannot pair this device with itself")));
+					return;
+				}
+				let pairing = Pairing::new(
+					&self.state.me,
+					peer,
+					self.state
+						.pairings
+						.get(&peer)
+						.map(|pairing| pairing.peer_name.clone())
+						.unwrap_or_default(),
+					PairingDirection::Outgoing,
+					self.clock.now(),
+				);
+				let node_name = self.local_node_name();
+				self.send_pairing_message(peer, PeerReq::PairRequest { node_name });
+				self.state.pairings.insert(peer, pairing.clone());
+				let _ = tx.send(Ok(pairing));
+			}
+			Command::AnswerPairing { peer, accept, tx } => {
+				self.expire_pairings();
+				let Some(direction) = self
+					.state
+					.pairings
+					.get(&peer)
+					.filter(|pairing| pairing.status == PairingStatus::AwaitingYou)
+					.map(|pairing| pairing.direction)
+				else {
+					let _ = tx.send(Err(anyhow!("no pairing request from {peer} is waiting")));
+					return;
+				};
+				let result = if accept {
+					let shared = self.state.shared_folders.clone();
+					self.grant_pairing_access(peer, &shared).map(|added| {
+						tracing::info!("paired with {peer}; granted {} folder(s)", added.len());
+					})
+				} else {
+					tracing::info!("declined pairing with {peer}");
+					Ok(())
+				};
+				let status = match (&result, accept) {
+					(Err(err), _) => PairingStatus::Failed(err.to_string()),
+					(Ok(()), true) => PairingStatus::Paired,
+					(Ok(()), false) => PairingStatus::Declined,
+				};
+				if let Some(pairing) = self.state.pairings.get_mut(&peer) {
+					pairing.status = status;
+				}
+				// The peer that asked already answered; only a request
+				// made to this node waits for the answer.
+				if direction == PairingDirection::Incoming {
+					self.send_pairing_message(
+						peer,
+						PeerReq::PairResponse {
+							accepted: accept && result.is_ok(),
+						},
+					);
+				}
 				let _ = tx.send(result);
 			}
+			Command::PairingInfo { tx } => {
+				let _ = tx.send(self.pairing_info());
+			}
+			Command::PairWith { invite, tx } => {
+				let peer = invite.peer_id;
+				if peer == self.state.me {
+					let _ = tx.send(Err(anyhow!("this pairing string is for this device")));
+					return;
+				}
+				for addr in &invite.addrs {
+					self.record_peer_address(&peer, addr);
+				}
+				let pairing = Pairing::new(
+					&self.state.me,
+					peer,
+					String::new(),
+					PairingDirection::Outgoing,
+					self.clock.now(),
+				);
+				self.state.pairings.insert(peer, pairing);
+				let request_id = self
+					.swarm
+					.behaviour_mut()
+					.puppynet
+					.send_request_with_addresses(
+						&peer,
+						PeerReq::Pair {
+							secret: invite.secret.to_vec(),
+							requested_permissions: Vec::new(),
+						},
+						invite.addrs,
+					);
+				self.pending_requests.insert(
+					request_id,
+					PendingPairWith::new(peer, tx, self.internal_tx.clone()),
+				);
+			}
 			Command::GetLocalPeerId { tx } => {
 				let _ = tx.send(self.state.me);
 			}
@@ -2806,22 +8486,240 @@ impl App {
 					.puppynet
 					.send_request_with_addresses(
 						&peer,
-						PeerReq::StartShell { id: session_id },
+						PeerReq::StartShell { id: session_id },
+						addresses,
+					);
+				self.pending_requests
+					.insert(request_id, Pending::<u64>::new(tx));
+			}
+			Command::ShellInput {
+				peer,
+				session_id,
+				data,
+				tx,
+			} => {
+				if self.state.me == peer {
+					let result = self.process_shell_input(session_id, &data, None).await;
+					let _ = tx.send(result);
+					return;
+				}
+				let addresses = self.known_peer_addresses(&peer);
+				let request_id = self
+					.swarm
+					.behaviour_mut()
+					.puppynet
+					.send_request_with_addresses(
+						&peer,
+						PeerReq::ShellInput {
+							id: session_id,
+							data,
+						},
+						addresses,
+					);
+				self.pending_requests
+					.insert(request_id, Pending::<ShellOutput>::new(tx));
+			}
+			Command::CloseShell {
+				peer,
+				session_id,
+				tx,
+			} => {
+				if self.state.me == peer {
+					self.close_shell_session(peer, session_id);
+					let _ = tx.send(Ok(ShellOutput::Exited));
+					return;
+				}
+				let addresses = self.known_peer_addresses(&peer);
+				let request_id = self
+					.swarm
+					.behaviour_mut()
+					.puppynet
+					.send_request_with_addresses(
+						&peer,
+						PeerReq::CloseShell { id: session_id },
+						addresses,
+					);
+				self.pending_requests
+					.insert(request_id, Pending::<ShellOutput>::new(tx));
+			}
+			Command::DesktopInput { peer, input, tx } => {
+				if self.state.me == peer {
+					let result = desktop_input::apply(input).await;
+					let _ = tx.send(result);
+					return;
+				}
+				let addresses = self.known_peer_addresses(&peer);
+				let request_id = self
+					.swarm
+					.behaviour_mut()
+					.puppynet
+					.send_request_with_addresses(&peer, PeerReq::DesktopInput { input }, addresses);
+				self.pending_requests
+					.insert(request_id, Pending::<()>::new(tx));
+			}
+			Command::OpenInbox {
+				peer,
+				name,
+				size,
+				tx,
+			} => {
+				if self.state.me == peer {
+					let result = self
+						.open_inbox(peer, &name, size)
+						.map(|path| InboxSlot { path });
+					let _ = tx.send(result);
+					return;
+				}
+				let addresses = self.known_peer_addresses(&peer);
+				let request_id = self
+					.swarm
+					.behaviour_mut()
+					.puppynet
+					.send_request_with_addresses(
+						&peer,
+						PeerReq::OpenInbox { name, size },
+						addresses,
+					);
+				self.pending_requests
+					.insert(request_id, Pending::<InboxSlot>::new(tx));
+			}
+			Command::WriteFile {
+				peer,
+				path,
+				offset,
+				data,
+				tx,
+			} => {
+				if self.state.me == peer {
+					let path = match path.to_local() {
+						Ok(path) => path,
+						Err(err) => {
+							let _ = tx.send(Err(anyhow!(err)));
+							return;
+						}
+					};
+					let result =
+						if self.can_access(peer, &path, FLAG_WRITE | FLAG_READ | FLAG_SEARCH) {
+							let result = write_file(&path, offset, &data).await;
+							if result.is_ok() {
+								self.invalidate_derived(&path);
+							}
+							result
+						} else {
+							Err(anyhow!("Access denied"))
+						};
+					let _ = tx.send(result);
+					return;
+				}
+				let addresses = self.known_peer_addresses(&peer);
+				let request_id = self
+					.swarm
+					.behaviour_mut()
+					.puppynet
+					.send_request_with_addresses(
+						&peer,
+						PeerReq::WriteFile {
+							path: path.to_wire(),
+							offset,
+							data,
+						},
+						addresses,
+					);
+				self.pending_requests
+					.insert(request_id, Pending::<FileWriteAck>::new(tx));
+			}
+			Command::WriteInbox {
+				peer,
+				path,
+				offset,
+				data,
+				tx,
+			} => {
+				if self.state.me == peer {
+					let result = match self.open_inbox_upload(peer, &path).await {
+						Ok(target) => self.write_inbox_chunk(&path, target, offset, &data).await,
+						Err(err) => Err(err),
+					};
+					let _ = tx.send(result);
+					return;
+				}
+				let addresses = self.known_peer_addresses(&peer);
+				let request_id = self
+					.swarm
+					.behaviour_mut()
+					.puppynet
+					.send_request_with_addresses(
+						&peer,
+						PeerReq::WriteInbox { path, offset, data },
+						addresses,
+					);
+				self.pending_requests
+					.insert(request_id, Pending::<FileWriteAck>::new(tx));
+			}
+			Command::HaveHashes { peer, hashes, tx } => {
+				if self.state.me == peer {
+					let _ = tx.send(self.have_hashes(peer, &hashes).map(HaveBitmap));
+					return;
+				}
+				if !self
+					.state
+					.peer_capabilities(&peer)
+					.is_some_and(|capabilities| capabilities.supports(FEATURE_HAVE_HASHES))
+				{
+					let _ = tx.send(Err(anyhow!("peer does not support the HaveHashes request")));
+					return;
+				}
+				let addresses = self.known_peer_addresses(&peer);
+				let request_id = self
+					.swarm
+					.behaviour_mut()
+					.puppynet
+					.send_request_with_addresses(&peer, PeerReq::HaveHashes { hashes }, addresses);
+				self.pending_requests
+					.insert(request_id, Pending::<HaveBitmap>::new(tx));
+			}
+			Command::HaveBlocks {
+				peer,
+				hash,
+				block_size,
+				blocks,
+				tx,
+			} => {
+				if self.state.me == peer {
+					let result = self.have_blocks(peer, block_size, &blocks).map(HaveBitmap);
+					let _ = tx.send(result);
+					return;
+				}
+				let addresses = self.known_peer_addresses(&peer);
+				let request_id = self
+					.swarm
+					.behaviour_mut()
+					.puppynet
+					.send_request_with_addresses(
+						&peer,
+						PeerReq::HaveBlocks {
+							hash,
+							block_size,
+							blocks,
+						},
 						addresses,
 					);
 				self.pending_requests
-					.insert(request_id, Pending::<u64>::new(tx));
+					.insert(request_id, Pending::<HaveBitmap>::new(tx));
 			}
-			Command::ShellInput {
+			Command::WriteKnownBlock {
 				peer,
-				session_id,
-				data,
+				path,
+				offset,
+				block_hash,
 				tx,
 			} => {
 				if self.state.me == peer {
-					let result = match self.process_shell_input(session_id, &data, None).await {
-						Ok(ShellInputResult::Output(out)) => Ok(out),
-						Ok(ShellInputResult::Exited) => Ok(Vec::new()),
+					let result = match self.open_inbox_upload(peer, &path).await {
+						Ok(target) => {
+							self.write_known_block(peer, &path, target, offset, &block_hash)
+								.await
+						}
 						Err(err) => Err(err),
 					};
 					let _ = tx.send(result);
@@ -2834,53 +8732,270 @@ impl App {
 					.puppynet
 					.send_request_with_addresses(
 						&peer,
-						PeerReq::ShellInput {
-							id: session_id,
-							data,
+						PeerReq::WriteKnownBlock {
+							path,
+							offset,
+							block_hash,
 						},
 						addresses,
 					);
 				self.pending_requests
-					.insert(request_id, Pending::<Vec<u8>>::new(tx));
+					.insert(request_id, Pending::<FileWriteAck>::new(tx));
 			}
-			Command::DesktopInput { peer, input, tx } => {
-				if self.state.me == peer {
-					let result = desktop_input::apply(input).await;
-					let _ = tx.send(result);
-					return;
+			Command::RecordTransfer { transfer } => match self.db.lock() {
+				Ok(conn) => {
+					if let Err(err) = record_transfer(&conn, &transfer) {
+						tracing::warn!("failed to record transfer: {err}");
+					}
 				}
-				let addresses = self.known_peer_addresses(&peer);
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request_with_addresses(&peer, PeerReq::DesktopInput { input }, addresses);
-				self.pending_requests
-					.insert(request_id, Pending::<()>::new(tx));
+				Err(err) => tracing::error!("db lock poisoned while recording transfer: {err}"),
+			},
+			Command::FlushDeleteOutbox { peer } => self.flush_delete_outbox(peer),
+		}
+	}
+
+	/// Releases what the node holds outside the process before it exits.
+	pub(crate) async fn shutdown(&mut self) {
+		self.stop_nat_mapper().await;
+		if let Some(batch) = self.discovery_writes.take(self.clock.now()) {
+			let db = self.db.clone();
+			let written = tokio::task::spawn_blocking(move || {
+				db.write(|conn| write_discovered_peers(conn, &batch.saved, &batch.removed))
+			})
+			.await;
+			match written {
+				Ok(Ok(())) => {}
+				Ok(Err(err)) => tracing::error!("failed to save discovered peers: {err}"),
+				Err(err) => tracing::error!("saving discovered peers panicked: {err}"),
 			}
 		}
 	}
 
 	pub async fn run(&mut self) {
-		tokio::select! {
-			event = self.swarm.select_next_some() => {
-				self.handle_swarm_event(event).await;
-			}
-			cmd = self.rx.recv() => {
-				if let Some(cmd) = cmd {
-					self.handle_cmd(cmd).await;
-				}
-			}
-			internal = self.internal_rx.recv() => {
-				if let Some(cmd) = internal {
-					self.handle_internal_cmd(cmd);
-				}
+		let input = next_input(
+			&mut self.fairness,
+			&mut self.rx,
+			&mut self.internal_rx,
+			&mut self.swarm,
+		)
+		.await;
+		match input {
+			LoopInput::Swarm(event) => self.handle_swarm_event(event).await,
+			LoopInput::Command(cmd) => {
+				let span = tracing::debug_span!("cmd", cmd = cmd.name());
+				self.handle_cmd(cmd).instrument(span).await;
 			}
+			LoopInput::Internal(cmd) => self.handle_internal_cmd(cmd),
 		}
 	}
 
 	fn handle_internal_cmd(&mut self, cmd: InternalCommand) {
 		match cmd {
+			InternalCommand::InvalidateDerived { paths } => {
+				for path in paths {
+					self.invalidate_derived(&path);
+				}
+			}
+			InternalCommand::RestartFailed { error } => {
+				self.last_restart_error = Some(error);
+			}
+			InternalCommand::LowDiskSpace { message } => {
+				let me = self.state.me;
+				self.state.push_notification(me, message);
+			}
+			InternalCommand::PairingUpdate {
+				peer,
+				peer_name,
+				failure,
+			} => {
+				let Some(pairing) = self.state.pairings.get_mut(&peer) else {
+					return;
+				};
+				if let Some(peer_name) = peer_name {
+					pairing.peer_name = peer_node_name(&peer_name);
+				}
+				if let Some(reason) = failure
+					&& pairing.is_pending()
+				{
+					pairing.status = PairingStatus::Failed(reason);
+				}
+			}
+			InternalCommand::PairedWith { peer, node_name } => {
+				let node_name = peer_node_name(&node_name);
+				let label = if node_name.is_empty() {
+					peer.to_string()
+				} else {
+					node_name.clone()
+				};
+				if let Some(pairing) = self.state.pairings.get_mut(&peer) {
+					pairing.peer_name = node_name;
+					pairing.status = PairingStatus::Paired;
+				}
+				self.state.push_notification(
+					peer,
+					format!("Paired with {label} using its pairing string"),
+				);
+			}
+			InternalCommand::SweepTemporaryGrants => {
+				for (peer, grant) in self.state.sweep_temporary_grants(self.clock.now()) {
+					tracing::info!(
+						"temporary access of {} to {} expired",
+						peer,
+						grant.rule.path().display()
+					);
+				}
+			}
+			InternalCommand::CheckShares => self.check_shares(),
+			InternalCommand::ShareAvailability { checked } => {
+				self.record_share_availability(checked);
+			}
+			InternalCommand::ExpireDiscoveredAddresses => {
+				let expired = self.discovered.expire(self.clock.now());
+				if expired > 0 {
+					tracing::debug!("forgot {expired} discovered address(es) not seen lately");
+				}
+			}
+			InternalCommand::FlushDiscoveredPeers => self.flush_discovered_peers(),
+			InternalCommand::DiscoveredPeersNotSaved { batch } => {
+				self.discovery_writes.requeue(batch);
+			}
+			InternalCommand::ExpireRemoteOps => {
+				let idle = idle_timeout(&self.db.lock().unwrap());
+				let now = self.clock.now();
+				let secs = idle.num_seconds();
+				for id in self.remote_scans.expire(now, idle) {
+					tracing::warn!("remote scan {id} got no events for {secs}s; giving up on it");
+				}
+				for id in self.remote_updates.expire(now, idle) {
+					tracing::warn!("remote update {id} got no events for {secs}s; giving up on it");
+				}
+			}
+			InternalCommand::ExpireInboxUploads => {
+				for upload in discard_idle_uploads(&mut self.inbox_uploads, self.clock.now()) {
+					tracing::warn!(
+						"[{}] inbox upload {} went quiet; discarded it",
+						upload.peer,
+						upload.name
+					);
+				}
+			}
+			InternalCommand::ExpirePairings => self.expire_pairings(),
+			InternalCommand::RollupProtocolStats => self.rollup_protocol_stats(),
+			InternalCommand::DialBackTimedOut { connection_id } => {
+				self.finish_dial_back(connection_id, Err(String::from("timed out")));
+			}
+			InternalCommand::ReachabilityTested { results, tx } => {
+				let saved = serde_json::to_string(&results)
+					.map_err(anyhow::Error::from)
+					.and_then(|json| {
+						let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+						save_setting(&conn, REACHABILITY_SETTING, &json)
+					});
+				if let Err(err) = saved {
+					tracing::warn!("failed to save reachability results: {err}");
+				}
+				self.state.reachability = results.clone();
+				let _ = tx.send(results);
+			}
+			InternalCommand::IndexChanged { change } => {
+				self.index_announcer.record(change);
+				self.announce_index();
+			}
+			InternalCommand::AnnounceIndex => self.announce_index(),
+			InternalCommand::PullIndex { peer } => self.start_index_pull(peer),
+			InternalCommand::IndexPulled { peer, delta } => self.apply_index_pull(peer, delta),
+			InternalCommand::UserHashed { user, reply } => {
+				let user = user.and_then(|user| self.save_new_user(user));
+				match reply {
+					NewUserReply::Command(tx) => {
+						let _ = tx.send(user.map(|_| ()));
+					}
+					NewUserReply::Peer(reply) => {
+						let response = self.user_created(reply.peer, user);
+						reply.inbound.finish(reply.started, &response);
+						let _ = self
+							.swarm
+							.behaviour_mut()
+							.puppynet
+							.send_response(reply.channel, response);
+					}
+				}
+			}
+			InternalCommand::RemoteGrantsFetched { peer, permissions } => {
+				self.grant_refetches.remove(&peer);
+				if let Some(permissions) = permissions {
+					self.store_remote_grants(peer, permissions);
+				}
+			}
+			InternalCommand::NatStatus {
+				status,
+				external_addrs,
+			} => {
+				// A report can race with the mapper being switched off.
+				if self.nat_mapper.is_some() {
+					self.state.nat = status;
+					self.set_nat_external_addrs(external_addrs);
+				}
+			}
+			InternalCommand::RecordCapabilities { peer, capabilities } => {
+				tracing::info!(
+					"peer {} speaks protocol v{} with features {:?}",
+					peer,
+					capabilities.protocol_version,
+					capabilities.features
+				);
+				self.state.capabilities.insert(peer, capabilities);
+				self.flush_delete_outbox(peer);
+			}
+			InternalCommand::RecordClockSample { peer, sample } => {
+				self.record_clock_sample(peer, sample);
+			}
+			InternalCommand::KeepConnectionsAlive => {
+				let mut peers = self
+					.state
+					.connections
+					.iter()
+					.map(|connection| connection.peer_id)
+					.filter(|peer| self.state.shares_with(peer))
+					.collect::<Vec<_>>();
+				peers.sort();
+				peers.dedup();
+				// Any request restarts both sides' idle timers; the answer
+				// itself isn't needed.
+				for peer in peers {
+					self.send_peer_request(&peer, PeerReq::HealthCheck);
+				}
+			}
+			InternalCommand::RedialImportantPeers => {
+				for peer in self.keeper.due_redials(std::time::Instant::now()) {
+					if self.swarm.is_connected(&peer) {
+						self.keeper.forget_redial(&peer);
+						continue;
+					}
+					let addresses = self.known_peer_addresses(&peer);
+					tracing::debug!("redialing important peer {peer}");
+					dial_discovered(&mut self.swarm, &mut self.dialer, peer, addresses);
+				}
+			}
+			InternalCommand::SampleClocks => {
+				let mut peers = self
+					.state
+					.connections
+					.iter()
+					.map(|connection| connection.peer_id)
+					.filter(|peer| {
+						self.state
+							.capabilities
+							.get(peer)
+							.is_some_and(|capabilities| !capabilities.legacy)
+					})
+					.collect::<Vec<_>>();
+				peers.sort();
+				peers.dedup();
+				for peer in peers {
+					self.send_hello(peer);
+				}
+			}
 			InternalCommand::SendPeerResponse { channel, response } => {
 				let _ = self
 					.swarm
@@ -2893,11 +9008,8 @@ impl App {
 				scan_id,
 				event,
 			} => {
-				let request_id = self
-					.swarm
-					.behaviour_mut()
-					.puppynet
-					.send_request(&target, PeerReq::ScanEvent { id: scan_id, event });
+				let request_id =
+					self.send_peer_request(&target, PeerReq::ScanEvent { id: scan_id, event });
 				self.pending_requests
 					.insert(request_id, PendingScanEventAck::new());
 			}
@@ -2906,7 +9018,7 @@ impl App {
 				search_id,
 				event,
 			} => {
-				let request_id = self.swarm.behaviour_mut().puppynet.send_request(
+				let request_id = self.send_peer_request(
 					&target,
 					PeerReq::SearchEvent {
 						id: search_id,
@@ -2921,7 +9033,7 @@ impl App {
 				update_id,
 				event,
 			} => {
-				let request_id = self.swarm.behaviour_mut().puppynet.send_request(
+				let request_id = self.send_peer_request(
 					&target,
 					PeerReq::UpdateEvent {
 						id: update_id,
@@ -2933,67 +9045,737 @@ impl App {
 			}
 		}
 	}
-}
+}
+
+fn queue_permissions_changed(db: &Arc<Db>, peer: &PeerId, permissions: &[Permission], now: i64) {
+	match db.lock() {
+		Ok(conn) => {
+			if let Err(err) = queue_permission_change(&conn, peer, permissions, now) {
+				tracing::error!("failed to queue permission change for {}: {err}", peer);
+			}
+		}
+		Err(err) => {
+			tracing::error!("db lock poisoned while queueing permission change: {err}");
+		}
+	}
+}
+
+pub(crate) fn peer_to_node_id(peer: &PeerId) -> Option<NodeID> {
+	let mut node_id = [0u8; std::mem::size_of::<NodeID>()];
+	let bytes = peer.to_bytes();
+	let len = node_id.len();
+	if bytes.len() < len {
+		return None;
+	}
+	node_id.copy_from_slice(&bytes[..len]);
+	Some(node_id)
+}
+
+fn summarize_interface_ips(ips: &[String]) -> (String, bool, bool) {
+	let mut first_ip = String::new();
+	let mut loopback = false;
+	let mut linklocal = false;
+	for entry in ips {
+		if first_ip.is_empty() {
+			first_ip = entry.clone();
+		}
+		if let Some(addr) = parse_ip_addr(entry) {
+			if addr.is_loopback() {
+				loopback = true;
+			}
+			match addr {
+				IpAddr::V4(v4) => {
+					if v4.is_link_local() {
+						linklocal = true;
+					}
+				}
+				IpAddr::V6(v6) => {
+					if v6.is_unicast_link_local() {
+						linklocal = true;
+					}
+				}
+			}
+		}
+	}
+	if first_ip.is_empty() {
+		first_ip = String::from("-");
+	}
+	(first_ip, loopback, linklocal)
+}
+
+fn parse_ip_addr(value: &str) -> Option<IpAddr> {
+	let mut parts = value.split('/');
+	let addr = parts.next()?.trim();
+	addr.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::checksum_manifest::{ChecksumAlgorithm, ChecksumSummary};
+	use crate::clock::ManualClock;
+	use crate::db::{load_hash_mismatches, path_column, record_scan_run};
+	use crate::file_read::ChunkReader;
+	use crate::sparse::SparseSink;
+	use crate::state::Rule;
+	use crate::test_util::{self, test_dir};
+
+	/// An [`App`] over an in-memory database, with its store under `dir`.
+	fn test_app(dir: &Path) -> (App, tokio::sync::mpsc::UnboundedSender<Command>) {
+		use crate::activity_window::ActivityWindow;
+		use crate::power::PowerGate;
+		let conn = test_util::memory_db();
+		let db = Arc::new(Db::single(conn));
+		let thumbnail_queue = ThumbnailQueue::new(
+			false,
+			Arc::new(Mutex::new(ActivityWindow::default())),
+			Arc::new(Mutex::new(PowerGate::default())),
+		);
+		App::new(
+			libp2p::identity::Keypair::generate_ed25519(),
+			State::default(),
+			AppDeps {
+				db: db.clone(),
+				remote_scans: Arc::new(RemoteOps::new("scan")),
+				remote_searches: Arc::new(Mutex::new(HashMap::new())),
+				remote_updates: Arc::new(RemoteOps::new("update")),
+				store: Arc::new(ContentStore::new(dir.join("store"), db.clone())),
+				clock: Arc::new(ManualClock::new(Utc::now())),
+				request_log: Arc::new(RequestLog::default()),
+				thumbnail_queue: Arc::new(thumbnail_queue),
+				maintenance: Arc::new(MaintenanceGate::default()),
+				protocol_rates: Arc::new(Mutex::new(Vec::new())),
+				activity: ActivityLog::spawn(db.clone()),
+			},
+		)
+	}
+
+	#[test]
+	fn inbox_files_never_overwrite_existing_names() {
+		let dir = test_dir("inbox-collision");
+		std::fs::create_dir_all(&dir).unwrap();
+
+		let first = reserve_inbox_file(&dir, "report.pdf").unwrap();
+		let second = reserve_inbox_file(&dir, "report.pdf").unwrap();
+		let third = reserve_inbox_file(&dir, "report.pdf").unwrap();
+
+		assert_eq!(first, dir.join("report.pdf"));
+		assert_eq!(second, dir.join("report (1).pdf"));
+		assert_eq!(third, dir.join("report (2).pdf"));
+		assert_eq!(
+			sanitize_inbox_name("../../etc/passwd"),
+			Some(String::from("passwd"))
+		);
+		assert_eq!(sanitize_inbox_name(".."), None);
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn idle_inbox_uploads_are_discarded_with_their_file() {
+		let dir = test_dir("inbox-idle");
+		std::fs::create_dir_all(&dir).unwrap();
+		let now = Utc::now();
+		let peer = PeerId::random();
+		let mut uploads = HashMap::new();
+		for (name, idle_secs) in [("stale.iso", INBOX_UPLOAD_IDLE_SECS), ("busy.iso", 5)] {
+			let path = reserve_inbox_file(&dir, name).unwrap();
+			uploads.insert(
+				name.to_string(),
+				InboxUpload {
+					peer,
+					name: name.to_string(),
+					size: 1024,
+					path,
+					last_write: now - chrono::Duration::seconds(idle_secs),
+				},
+			);
+		}
+
+		let discarded = discard_idle_uploads(&mut uploads, now);
+
+		assert_eq!(discarded.len(), 1);
+		assert_eq!(discarded[0].name, "stale.iso");
+		assert!(!dir.join("stale.iso").exists());
+		assert!(uploads.contains_key("busy.iso"));
+		assert!(dir.join("busy.iso").exists());
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	/// Copies `path` the way a download reads it from a peer.
+	async fn copy_sparse(path: &Path, dest: &Path) -> Vec<u8> {
+		let mut sink = SparseSink::new(fs::File::create(dest).await.unwrap());
+		let mut fetch = |offset, length| read_file(path, offset, Some(length), true);
+		let mut reader = ChunkReader::new(0, None, 256 * 1024);
+		while let Some(chunk) = reader.next(&mut fetch).await.unwrap() {
+			sink.write(&chunk).await.unwrap();
+		}
+		sink.finish().await.unwrap()
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn empty_sparse_and_mixed_files_copy_with_their_holes() {
+		use std::io::{Seek, SeekFrom, Write};
+		use std::os::unix::fs::MetadataExt;
+		const MIB: u64 = 1024 * 1024;
+		let dir = test_dir("sparse-copy");
+		std::fs::create_dir_all(&dir).unwrap();
+		let empty = dir.join("empty");
+		std::fs::File::create(&empty).unwrap();
+		let hollow = dir.join("hollow.img");
+		std::fs::File::create(&hollow)
+			.unwrap()
+			.set_len(64 * MIB)
+			.unwrap();
+		let mixed = dir.join("mixed.img");
+		let mut file = std::fs::File::create(&mixed).unwrap();
+		file.write_all(b"header").unwrap();
+		file.seek(SeekFrom::Start(16 * MIB)).unwrap();
+		file.write_all(&[7; 100_000]).unwrap();
+		file.set_len(40 * MIB).unwrap();
+		drop(file);
+
+		for source in [&empty, &hollow, &mixed] {
+			let dest = source.with_extension("copy");
+			let hash = copy_sparse(source, &dest).await;
+			let content = std::fs::read(source).unwrap();
+			assert_eq!(std::fs::read(&dest).unwrap(), content);
+			assert_eq!(hash, blake3::hash(&content).as_bytes().to_vec());
+			let (source_meta, copy_meta) = (source.metadata().unwrap(), dest.metadata().unwrap());
+			// Only Linux reports holes; a file system that stores the
+			// source densely has none to keep either.
+			if cfg!(target_os = "linux") && source_meta.blocks() * 512 < source_meta.len() / 2 {
+				assert!(
+					copy_meta.blocks() <= source_meta.blocks() + 8,
+					"{} lost its holes: {} blocks, source {}",
+					source.display(),
+					copy_meta.blocks(),
+					source_meta.blocks()
+				);
+			}
+		}
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	/// Drives the event loop's input selection and inbound slots the way
+	/// [`App::run`] does, with a local command arriving alongside every
+	/// remote read.
+	#[tokio::test]
+	async fn concurrent_remote_reads_dont_hold_up_local_commands() {
+		const READS: usize = 200;
+		const LEN: usize = 1024 * 1024;
+		let dir = test_dir("loop-reads");
+		std::fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("large.bin");
+		std::fs::write(&path, vec![7; LEN]).unwrap();
+
+		let (command_tx, mut commands) =
+			tokio::sync::mpsc::unbounded_channel::<std::time::Instant>();
+		let (internal_tx, mut internal) = tokio::sync::mpsc::unbounded_channel::<PeerRes>();
+		let mut swarm = futures::stream::iter(0..READS);
+		let slots = Arc::new(Semaphore::new(MAX_INBOUND_JOBS));
+		let mut fairness = Fairness::default();
+		let (mut answered, mut busy) = (0, 0);
+		let mut slowest = Duration::ZERO;
+		while answered + busy < READS {
+			match next_input(&mut fairness, &mut commands, &mut internal, &mut swarm).await {
+				LoopInput::Command(sent) => {
+					slowest = slowest.max(sent.elapsed());
+				}
+				LoopInput::Swarm(_) => {
+					command_tx.send(std::time::Instant::now()).unwrap();
+					let Ok(slot) = Arc::clone(&slots).try_acquire_owned() else {
+						busy += 1;
+						continue;
+					};
+					let job = ReadJob {
+						file: fs::File::open(&path).await.unwrap(),
+						offset: 0,
+						length: None,
+						sparse: false,
+					};
+					let internal_tx = internal_tx.clone();
+					tokio::spawn(async move {
+						let _slot = slot;
+						let _ = internal_tx.send(job.run().await);
+					});
+				}
+				LoopInput::Internal(response) => {
+					let PeerRes::FileChunk(chunk) = response else {
+						panic!("read failed: {response:?}");
+					};
+					assert_eq!(chunk.data.len(), LEN);
+					answered += 1;
+				}
+			}
+		}
+		assert!(
+			answered >= MAX_INBOUND_JOBS,
+			"only {answered} reads answered"
+		);
+		assert!(
+			slowest < Duration::from_millis(50),
+			"a local command waited {slowest:?}"
+		);
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	/// Two frontends starting a shell on this node at once, each with an id
+	/// from the shared allocator, get a session each instead of one
+	/// replacing the other in [`App::shell_sessions`].
+	#[cfg(all(unix, feature = "shell"))]
+	#[tokio::test]
+	async fn concurrent_shell_starts_get_their_own_sessions() {
+		use crate::ids::{IdAllocator, IdKind};
+		let dir = test_dir("app-shells");
+		let (mut app, cmd_tx) = test_app(&dir);
+		let me = app.state.me;
+		let ids = Arc::new(IdAllocator::new());
+		let starts = (0..2).map(|_| {
+			let (ids, cmd_tx) = (Arc::clone(&ids), cmd_tx.clone());
+			tokio::spawn(async move {
+				let (tx, rx) = oneshot::channel();
+				cmd_tx
+					.send(Command::StartShell {
+						peer: me,
+						session_id: ids.next(IdKind::Shell),
+						tx,
+					})
+					.unwrap();
+				rx.await.unwrap()
+			})
+		});
+
+		let started = tokio::select! {
+			started = futures::future::join_all(starts) => started,
+			_ = async {
+				loop {
+					app.run().await;
+				}
+			} => unreachable!(),
+		};
+
+		let mut session_ids = started
+			.into_iter()
+			.map(|started| started.unwrap().unwrap())
+			.collect::<Vec<_>>();
+		session_ids.sort();
+		assert_eq!(session_ids.len(), 2);
+		assert_ne!(session_ids[0], session_ids[1]);
+		let mut sessions = app.shell_sessions.keys().copied().collect::<Vec<_>>();
+		sessions.sort_by_key(|(_, session_id)| *session_id);
+		assert_eq!(
+			sessions,
+			session_ids
+				.iter()
+				.map(|session_id| (me, *session_id))
+				.collect::<Vec<_>>()
+		);
+
+		app.end_shell_sessions(me);
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn non_utf8_names_round_trip_from_listing_to_read_and_scan() {
+		use std::os::unix::ffi::OsStrExt;
+		let dir = test_dir("non-utf8");
+		std::fs::create_dir_all(&dir).unwrap();
+		let dir = std::fs::canonicalize(&dir).unwrap();
+		let file = dir.join(std::ffi::OsStr::from_bytes(b"caf\xe9.txt"));
+		std::fs::write(&file, "latin1").unwrap();
+
+		let entries = App::collect_dir_entries(&dir).await.unwrap();
+		let entry = entries.iter().find(|entry| !entry.is_dir).unwrap();
+		assert!(entry.has_undecodable_name());
+		let mut raw = path_bytes(&dir);
+		raw.push(b'/');
+		raw.extend_from_slice(&entry.name_raw);
+		let chunk = read_file(&WirePath::Raw(raw).to_path_buf(), 0, None, false)
+			.await
+			.unwrap();
+		assert_eq!(chunk.data, b"latin1");
+
+		let mut conn = test_util::memory_db();
+		let node_id = [7u8; 16];
+		let first = scan::scan(&node_id, &dir, &mut conn).unwrap();
+		let second = scan::scan(&node_id, &dir, &mut conn).unwrap();
+		let stored = conn
+			.query_row("SELECT path FROM file_locations", [], |row| {
+				path_column(row, 0)
+			})
+			.unwrap();
+		assert_eq!(first.inserted_count, 1);
+		assert_eq!(second.inserted_count + second.removed_count, 0);
+		assert_eq!(stored, file);
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[tokio::test]
+	async fn scans_and_remote_writes_record_who_introduced_each_file() {
+		use crate::db::{FileOrigin, file_provenance};
+		let dir = test_dir("provenance");
+		std::fs::create_dir_all(&dir).unwrap();
+		let dir = std::fs::canonicalize(&dir).unwrap();
+		let root = dir.to_string_lossy().into_owned();
+		std::fs::write(dir.join("found.txt"), "on disk").unwrap();
+		let mut conn = test_util::memory_db();
+		let node_id = [7u8; 16];
+		let peer = PeerId::random();
+		let now = Utc::now();
+		let scan = |conn: &mut SqliteConnection| {
+			let throttle = ScanThrottle::stored(conn);
+			scan_and_record(
+				conn,
+				ScanRecordOptions {
+					node_id: &node_id,
+					path: &root,
+					started_at: now,
+					initiated_by: None,
+					throttle: &throttle,
+				},
+				|_| {},
+				|| false,
+			)
+			.unwrap();
+			conn.query_row("SELECT MAX(id) FROM scan_runs", [], |row| {
+				row.get::<_, i64>(0)
+			})
+			.unwrap()
+		};
+		let provenance = |conn: &SqliteConnection, name: &str| {
+			let path = dir.join(name);
+			let hash: Vec<u8> = conn
+				.query_row(
+					"SELECT hash FROM file_locations WHERE path = ?1",
+					params![path.to_string_lossy().into_owned()],
+					|row| row.get(0),
+				)
+				.unwrap();
+			file_provenance(conn, &hash, &path).unwrap().unwrap()
+		};
+
+		let run = scan(&mut conn);
+		let found = provenance(&conn, "found.txt");
+		assert_eq!(found.origin, FileOrigin::LocalScan);
+		assert_eq!(found.scan_run, Some(run));
+		assert_eq!(found.peer, None);
+
+		// A peer writes a file the index has never seen; the next scan
+		// hashes it without forgetting who wrote it.
+		let written = dir.join("written.txt");
+		write_file(&written, 0, b"from a peer").await.unwrap();
+		let remote = FileProvenance::remote_write(&node_id, &peer, now);
+		record_provenance(&conn, &written, 11, &remote, now).unwrap();
+		scan(&mut conn);
+		let written = provenance(&conn, "written.txt");
+		assert_eq!(written.origin, FileOrigin::RemoteWrite);
+		assert_eq!(written.peer, Some(peer.to_string()));
+		assert_eq!(written.scan_run, None);
+
+		// Overwriting a scanned file hands it to the writer.
+		write_file(&dir.join("found.txt"), 0, b"REWRITTEN")
+			.await
+			.unwrap();
+		record_provenance(&conn, &dir.join("found.txt"), 9, &remote, now).unwrap();
+		scan(&mut conn);
+		assert_eq!(
+			provenance(&conn, "found.txt").origin,
+			FileOrigin::RemoteWrite
+		);
+
+		let names = |args: SearchFilesArgs| {
+			let mut names = search_files(&conn, args)
+				.unwrap()
+				.0
+				.rows
+				.into_iter()
+				.map(|row| row.name)
+				.collect::<Vec<_>>();
+			names.sort();
+			names
+		};
+		assert_eq!(
+			names(SearchFilesArgs {
+				introduced_by_peer: Some(peer.to_string()),
+				..Default::default()
+			}),
+			["found.txt", "written.txt"]
+		);
+		assert!(
+			names(SearchFilesArgs {
+				origin: Some(FileOrigin::LocalScan),
+				..Default::default()
+			})
+			.is_empty()
+		);
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
 
-fn peer_to_node_id(peer: &PeerId) -> Option<NodeID> {
-	let mut node_id = [0u8; std::mem::size_of::<NodeID>()];
-	let bytes = peer.to_bytes();
-	let len = node_id.len();
-	if bytes.len() < len {
-		return None;
+	#[tokio::test]
+	async fn listings_prefer_indexed_then_sniffed_mime_types() {
+		let dir = test_dir("listing-mime");
+		std::fs::create_dir_all(&dir).unwrap();
+		let dir = std::fs::canonicalize(&dir).unwrap();
+		image::RgbImage::new(4, 4)
+			.save_with_format(dir.join("photo"), image::ImageFormat::Jpeg)
+			.unwrap();
+		image::RgbImage::new(4, 4)
+			.save_with_format(dir.join("export.dat"), image::ImageFormat::Png)
+			.unwrap();
+		let mime = |entries: &[DirEntry], name: &str| {
+			let entry = entries.iter().find(|entry| entry.name == name).unwrap();
+			(entry.mime.clone(), entry.mime_source)
+		};
+
+		// Nothing indexed yet: the extensionless JPEG is sniffed.
+		let mut entries = App::collect_dir_entries(&dir).await.unwrap();
+		assert_eq!(mime(&entries, "photo"), (None, MimeSource::Extension));
+		mime_hint::refine(&dir, &mut entries, &HashMap::new()).await;
+		assert_eq!(
+			mime(&entries, "photo"),
+			(Some(String::from("image/jpeg")), MimeSource::Sniffed)
+		);
+
+		// Once scanned, the index knows what both files really are.
+		let mut conn = test_util::memory_db();
+		let node_id = [3u8; 16];
+		scan::scan(&node_id, &dir, &mut conn).unwrap();
+		let mut entries = App::collect_dir_entries(&dir).await.unwrap();
+		let files = mime_hint::indexable_files(&dir, &entries);
+		let indexed = load_indexed_mimes(&conn, &node_id, &files).unwrap();
+		mime_hint::refine(&dir, &mut entries, &indexed).await;
+		assert_eq!(
+			mime(&entries, "photo"),
+			(Some(String::from("image/jpeg")), MimeSource::Index)
+		);
+		assert_eq!(
+			mime(&entries, "export.dat"),
+			(Some(String::from("image/png")), MimeSource::Index)
+		);
+
+		// A file changed since the scan falls back to the guess.
+		std::fs::write(dir.join("export.dat"), b"plain").unwrap();
+		let entries = App::collect_dir_entries(&dir).await.unwrap();
+		let files = mime_hint::indexable_files(&dir, &entries);
+		let indexed = load_indexed_mimes(&conn, &node_id, &files).unwrap();
+		assert!(!indexed.contains_key(&dir.join("export.dat")));
+
+		let _ = std::fs::remove_dir_all(&dir);
 	}
-	node_id.copy_from_slice(&bytes[..len]);
-	Some(node_id)
-}
 
-fn summarize_interface_ips(ips: &[String]) -> (String, bool, bool) {
-	let mut first_ip = String::new();
-	let mut loopback = false;
-	let mut linklocal = false;
-	for entry in ips {
-		if first_ip.is_empty() {
-			first_ip = entry.clone();
-		}
-		if let Some(addr) = parse_ip_addr(entry) {
-			if addr.is_loopback() {
-				loopback = true;
-			}
-			match addr {
-				IpAddr::V4(v4) => {
-					if v4.is_link_local() {
-						linklocal = true;
-					}
-				}
-				IpAddr::V6(v6) => {
-					if v6.is_unicast_link_local() {
-						linklocal = true;
-					}
-				}
-			}
-		}
+	#[test]
+	fn scan_diff_reports_net_changes_and_skips_failed_runs() {
+		let dir = test_dir("scan-history");
+		std::fs::create_dir_all(&dir).unwrap();
+		let dir = std::fs::canonicalize(&dir).unwrap();
+		let root = dir.to_string_lossy().to_string();
+		std::fs::write(dir.join("kept.txt"), "one").unwrap();
+		std::fs::write(dir.join("edited.txt"), "before").unwrap();
+		std::fs::write(dir.join("gone.txt"), "bye").unwrap();
+
+		let mut conn = test_util::memory_db();
+		let node_id = [9u8; 16];
+		let now = Utc::now();
+		let record = |conn: &mut SqliteConnection, outcome: Result<scan::ScanResult, String>| {
+			record_scan_run(conn, &root, now, Duration::ZERO, None, &outcome, false).unwrap()
+		};
+		let first = scan::scan(&node_id, &dir, &mut conn);
+		let first = record(&mut conn, first);
+		std::fs::write(dir.join("edited.txt"), "after").unwrap();
+		std::fs::remove_file(dir.join("gone.txt")).unwrap();
+		let second = scan::scan(&node_id, &dir, &mut conn);
+		record(&mut conn, second);
+		let failed = record(&mut conn, Err(String::from("disk vanished")));
+		std::fs::write(dir.join("new.txt"), "hello").unwrap();
+		let third = scan::scan(&node_id, &dir, &mut conn);
+		let third = record(&mut conn, third);
+
+		let diff = crate::db::scan_diff(&conn, third, first, 0, 10).unwrap();
+		let changes = diff
+			.iter()
+			.map(|entry| {
+				let name = Path::new(&entry.path)
+					.file_name()
+					.unwrap()
+					.to_string_lossy();
+				format!("{} {name}", entry.change.as_str())
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(
+			changes,
+			["changed edited.txt", "removed gone.txt", "added new.txt"]
+		);
+		assert!(crate::db::scan_diff(&conn, first, failed, 0, 10).is_err());
+		let trend = crate::db::scan_trend(&conn, &root, now).unwrap().unwrap();
+		assert_eq!((trend.baseline_file_count, trend.file_count), (2, 3));
+
+		// A run changing too many files keeps only some, and no diff spans it.
+		let added = (0..=crate::db::MAX_RECORDED_CHANGES)
+			.map(|index| scan::ScanChange {
+				path: dir.join(format!("bulk-{index}.txt")),
+				kind: scan::ScanChangeKind::Added,
+				old_hash: None,
+				new_hash: Some([1; 32]),
+			})
+			.collect::<Vec<_>>();
+		let bulk = record(
+			&mut conn,
+			Ok(scan::ScanResult {
+				updated_count: 0,
+				inserted_count: added.len() as u64,
+				removed_count: 0,
+				duration: Duration::ZERO,
+				file_count: added.len() as u64 + 3,
+				checksums: None,
+				run: None,
+				changes: added,
+			}),
+		);
+		let run = crate::db::load_scan_run(&conn, bulk).unwrap().unwrap();
+		assert!(run.changes_truncated);
+		let kept: i64 = conn
+			.query_row(
+				"SELECT COUNT(*) FROM scan_run_changes WHERE run_id = ?1",
+				[bulk],
+				|row| row.get(0),
+			)
+			.unwrap();
+		assert_eq!(kept as usize, crate::db::MAX_RECORDED_CHANGES);
+		assert!(crate::db::scan_diff(&conn, third, bulk, 0, 10).is_err());
+		assert_eq!(
+			crate::db::scan_diff(&conn, first, third, 0, 10)
+				.unwrap()
+				.len(),
+			3
+		);
+
+		let _ = std::fs::remove_dir_all(&dir);
 	}
-	if first_ip.is_empty() {
-		first_ip = String::from("-");
+
+	#[test]
+	fn manifest_checksums_catch_files_that_changed_since() {
+		let dir = test_dir("manifests");
+		std::fs::create_dir_all(dir.join("sums")).unwrap();
+		let dir = std::fs::canonicalize(&dir).unwrap();
+		std::fs::write(dir.join("hello.txt"), "hello").unwrap();
+		std::fs::write(dir.join("empty.txt"), "").unwrap();
+		std::fs::write(
+			dir.join("SHA256SUMS"),
+			"2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824  hello.txt\n\
+			e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  empty.txt\n\
+			e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  lost.txt\n\
+			garbage\n",
+		)
+		.unwrap();
+		std::fs::write(
+			dir.join("sums/MD5SUMS"),
+			"5d41402abc4b2a76b9719d911017c592 *../hello.txt\n",
+		)
+		.unwrap();
+
+		let mut conn = test_util::memory_db();
+		let node_id = [5u8; 16];
+		let first = scan::scan(&node_id, &dir, &mut conn).unwrap();
+		assert_eq!(
+			first.checksums,
+			Some(ChecksumSummary {
+				manifests: 2,
+				claims: 3,
+				matched: 3,
+				missing: 1,
+				malformed_lines: 1,
+				..Default::default()
+			})
+		);
+
+		std::fs::write(dir.join("hello.txt"), "jello").unwrap();
+		let second = scan::scan(&node_id, &dir, &mut conn).unwrap();
+		assert_eq!(second.checksums.unwrap().mismatched, 2);
+		let mismatches = load_hash_mismatches(&conn, &node_id, None).unwrap();
+		assert_eq!(
+			mismatches
+				.iter()
+				.map(|m| (m.path.clone(), m.algorithm))
+				.collect::<Vec<_>>(),
+			vec![
+				(dir.join("hello.txt"), ChecksumAlgorithm::Sha256),
+				(dir.join("hello.txt"), ChecksumAlgorithm::Md5),
+			]
+		);
+		let elsewhere = dir.join("sums").to_string_lossy().to_string();
+		assert!(
+			load_hash_mismatches(&conn, &node_id, Some(&elsewhere))
+				.unwrap()
+				.is_empty()
+		);
+
+		let _ = std::fs::remove_dir_all(&dir);
 	}
-	(first_ip, loopback, linklocal)
-}
 
-fn parse_ip_addr(value: &str) -> Option<IpAddr> {
-	let mut parts = value.split('/');
-	let addr = parts.next()?.trim();
-	addr.parse().ok()
-}
+	#[tokio::test]
+	async fn pending_reads_fail_as_soon_as_the_peer_disconnects() {
+		let gone = PeerId::random();
+		let staying = PeerId::random();
+		let mut targets = HashMap::from([(1u64, gone), (2, staying), (3, gone)]);
+		let mut pending = HashMap::new();
+		let (read_tx, read_rx) = oneshot::channel();
+		pending.insert(1, Pending::<FileChunk>::new(read_tx));
+		let (other_tx, mut other_rx) = oneshot::channel();
+		pending.insert(2, Pending::<FileChunk>::new(other_tx));
 
-#[cfg(test)]
-mod tests {
-	use super::*;
+		let disconnected = Disconnected {
+			peer: gone,
+			reason: DisconnectReason::Lost,
+		};
+		let mut failed = fail_disconnected(&mut targets, &mut pending, disconnected);
+		failed.sort();
+		assert_eq!(failed, [1, 3]);
 
-	fn test_dir(name: &str) -> PathBuf {
-		let now = std::time::SystemTime::now()
-			.duration_since(std::time::UNIX_EPOCH)
+		let err = tokio::time::timeout(Duration::from_secs(1), read_rx)
+			.await
+			.expect("the read resolves without waiting for the transport")
 			.unwrap()
-			.as_nanos();
-		std::env::temp_dir().join(format!("puppynet-{name}-{}-{now}", std::process::id()))
+			.unwrap_err();
+		assert_eq!(err.downcast_ref::<Disconnected>(), Some(&disconnected));
+		assert!(other_rx.try_recv().is_err());
+		assert_eq!(targets, HashMap::from([(2, staying)]));
+		assert!(pending.contains_key(&2));
+	}
+
+	#[test]
+	fn unanswered_hello_records_legacy_capabilities() {
+		let (internal_tx, mut internal_rx) = tokio::sync::mpsc::unbounded_channel();
+		let answered = PeerId::random();
+		let silent = PeerId::random();
+
+		let clock: Arc<dyn Clock> = Arc::new(crate::clock::SystemClock);
+		PendingHello::new(answered, Arc::clone(&clock), internal_tx.clone()).complete(
+			PeerRes::Hello {
+				protocol_version: 7,
+				features: vec![String::from("org.example.relay")],
+				time: None,
+			},
+		);
+		PendingHello::new(silent, clock, internal_tx).fail(anyhow!("unsupported request"));
+
+		let mut recorded = HashMap::new();
+		while let Ok(InternalCommand::RecordCapabilities { peer, capabilities }) =
+			internal_rx.try_recv()
+		{
+			recorded.insert(peer, capabilities);
+		}
+		assert_eq!(recorded[&answered].protocol_version, 7);
+		assert!(recorded[&answered].supports("org.example.relay"));
+		assert_eq!(recorded[&silent], PeerCapabilities::legacy());
+		assert!(!recorded[&silent].supports(crate::p2p::FEATURE_SHELL));
 	}
 
 	#[test]
@@ -3014,6 +9796,7 @@ mod tests {
 				name_query: Some(String::from(".txt")),
 				..Default::default()
 			},
+			HiddenNames::default(),
 			|event| events.push(event),
 		);
 
@@ -3049,6 +9832,276 @@ mod tests {
 		let _ = std::fs::remove_dir_all(denied);
 	}
 
+	#[tokio::test]
+	async fn scans_finish_while_nobody_reads_their_events() {
+		let dir = test_dir("scan-unread");
+		let shared = dir.join("shared");
+		std::fs::create_dir_all(&shared).unwrap();
+		for index in 0..64 {
+			std::fs::write(shared.join(format!("{index}.txt")), index.to_string()).unwrap();
+		}
+		let shared = std::fs::canonicalize(&shared).unwrap();
+		let (mut app, _cmd_tx) = test_app(&dir);
+		app.state.shared_folders = vec![FolderRule::new(shared.clone(), FLAG_READ | FLAG_SEARCH)];
+
+		// A single slot: progress can only ever be skipped, never waited on.
+		let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+		app.handle_cmd(Command::Scan {
+			path: SafePath::new(shared.to_str().unwrap()).unwrap(),
+			tx,
+			cancel_flag: Arc::new(AtomicBool::new(false)),
+			extract_metadata: false,
+			limits: ScanOverrides::default(),
+		})
+		.await;
+		tokio::time::timeout(Duration::from_secs(10), async {
+			while rx.sender_strong_count() > 0 {
+				tokio::time::sleep(Duration::from_millis(10)).await;
+			}
+		})
+		.await
+		.expect("the scan waited on its reader");
+		let mut last = None;
+		while let Ok(event) = rx.try_recv() {
+			last = Some(event);
+		}
+		match last {
+			Some(ScanEvent::Finished(Ok(stats))) => assert_eq!(stats.inserted_count, 64),
+			other => panic!("unexpected last event: {other:?}"),
+		}
+	}
+
+	#[tokio::test]
+	async fn hidden_files_stay_out_of_search_and_file_entries() {
+		let dir = test_dir("hidden-search");
+		let shared = dir.join("shared");
+		std::fs::create_dir_all(shared.join("keys")).unwrap();
+		std::fs::write(shared.join("notes.txt"), "notes").unwrap();
+		std::fs::write(shared.join(".env"), "TOKEN=1").unwrap();
+		std::fs::write(shared.join("keys").join("id.txt"), "key").unwrap();
+		let shared = std::fs::canonicalize(&shared).unwrap();
+		let (mut app, _cmd_tx) = test_app(&dir);
+		let rule = FolderRule::new(shared.clone(), FLAG_READ | FLAG_SEARCH)
+			.with_patterns(vec![String::from(".env"), String::from("keys")], Vec::new());
+		app.state.shared_folders = vec![rule.clone()];
+		let peer = PeerId::random();
+		app.state
+			.set_peer_permissions(peer, vec![Permission::new(Rule::Folder(rule))]);
+
+		let mut events = Vec::new();
+		live_search_roots(
+			app.state.search_roots_for_peer(&peer),
+			LiveSearchArgs::default(),
+			app.state.hidden_names(peer, app.clock.now()),
+			|event| events.push(event),
+		);
+		let found = events
+			.iter()
+			.flat_map(|event| match event {
+				SearchEvent::Rows { rows } => rows.clone(),
+				_ => Vec::new(),
+			})
+			.map(|row| row.path)
+			.collect::<Vec<_>>();
+		assert_eq!(
+			found,
+			vec![shared.join("notes.txt").to_string_lossy().to_string()]
+		);
+
+		let node_id = app.local_node_id().unwrap();
+		for (byte, name) in [(1u8, "notes.txt"), (2, ".env"), (3, "keys/id.txt")] {
+			let conn = app.db.lock().unwrap();
+			crate::db::record_ingested_file(
+				&conn,
+				&node_id,
+				&shared.join(name),
+				&[byte; 32],
+				4,
+				None,
+				Some(Utc::now()),
+			)
+			.unwrap();
+		}
+		let listed = |res: PeerRes| match res {
+			PeerRes::FileEntries(entries) => entries
+				.into_iter()
+				.map(|entry| entry.hash[0])
+				.collect::<Vec<_>>(),
+			other => panic!("unexpected response: {other:?}"),
+		};
+		let res = app
+			.handle_puppy_peer_req(
+				peer,
+				PeerReq::FileEntries {
+					offset: 0,
+					limit: 10,
+				},
+			)
+			.await
+			.unwrap();
+		assert_eq!(listed(res), vec![1]);
+		let me = app.state.me;
+		let res = app
+			.handle_puppy_peer_req(
+				me,
+				PeerReq::FileEntries {
+					offset: 0,
+					limit: 10,
+				},
+			)
+			.await
+			.unwrap();
+		let mut own = listed(res);
+		own.sort();
+		assert_eq!(own, vec![1, 2, 3]);
+
+		let _ = std::fs::remove_dir_all(dir);
+	}
+
+	#[tokio::test]
+	async fn index_deltas_are_only_kept_from_subscribed_peers() {
+		let dir = test_dir("index-delta-consent");
+		let (mut app, _cmd_tx) = test_app(&dir);
+		let peer = PeerId::random();
+		app.state
+			.set_peer_permissions(peer, vec![Permission::new(Rule::Inbox)]);
+		let delta = IndexDelta {
+			generation: String::from("first"),
+			after: 0,
+			through: 0,
+			entries: Vec::new(),
+			locations: Vec::new(),
+			remaining: 0,
+		};
+		let res = app
+			.handle_puppy_peer_req(
+				peer,
+				PeerReq::IndexDelta {
+					delta: delta.clone(),
+				},
+			)
+			.await
+			.unwrap();
+		assert!(matches!(res, PeerRes::Error(err) if err.contains("does not keep a replica")));
+		assert!(
+			load_replication(&app.db.lock().unwrap(), &peer, ReplicationRole::Replica)
+				.unwrap()
+				.is_none()
+		);
+
+		crate::db::save_index_subscription(&app.db.lock().unwrap(), &peer, true, Utc::now())
+			.unwrap();
+		let res = app
+			.handle_puppy_peer_req(peer, PeerReq::IndexDelta { delta })
+			.await
+			.unwrap();
+		assert!(matches!(res, PeerRes::IndexDeltaAck(ack) if ack.generation == "first"));
+	}
+
+	#[tokio::test]
+	async fn negotiation_only_confirms_files_the_peer_may_read() {
+		use crate::content_negotiation::to_bitmap;
+		let dir = test_dir("negotiate-grants");
+		let shared = dir.join("shared");
+		std::fs::create_dir_all(&shared).unwrap();
+		let shared = std::fs::canonicalize(&shared).unwrap();
+		let (mut app, _cmd_tx) = test_app(&dir);
+		let folder = FolderRule::new(shared.clone(), FLAG_READ)
+			.with_patterns(vec![String::from("*.key")], Vec::new());
+		app.state.shared_folders = vec![folder.clone()];
+		let node_id = app.local_node_id().unwrap();
+		for (byte, name) in [(1u8, "notes.txt"), (2, "server.key")] {
+			let conn = app.db.lock().unwrap();
+			crate::db::record_ingested_file(
+				&conn,
+				&node_id,
+				&shared.join(name),
+				&[byte; 32],
+				4,
+				None,
+				Some(Utc::now()),
+			)
+			.unwrap();
+		}
+		let asked = vec![[1u8; 32], [2; 32]];
+		let peer = PeerId::random();
+
+		app.state
+			.set_peer_permissions(peer, vec![Permission::new(Rule::Inbox)]);
+		assert_eq!(
+			app.have_hashes(peer, &asked).unwrap(),
+			to_bitmap([false, false])
+		);
+
+		app.state.set_peer_permissions(
+			peer,
+			vec![
+				Permission::new(Rule::Inbox),
+				Permission::new(Rule::Folder(folder)),
+			],
+		);
+		assert_eq!(
+			app.have_hashes(peer, &asked).unwrap(),
+			to_bitmap([true, false])
+		);
+		let me = app.state.me;
+		assert_eq!(
+			app.have_hashes(me, &asked).unwrap(),
+			to_bitmap([true, true])
+		);
+
+		let _ = std::fs::remove_dir_all(dir);
+	}
+
+	#[tokio::test]
+	async fn pairing_grants_only_after_both_users_confirm() {
+		use crate::pairing::PAIRING_TTL;
+		let dir = test_dir("pairing-confirm");
+		let shared = dir.join("shared");
+		std::fs::create_dir_all(&shared).unwrap();
+		let (mut app, _cmd_tx) = test_app(&dir);
+		app.state.shared_folders = vec![FolderRule::new(shared, FLAG_READ | FLAG_SEARCH)];
+		let me = app.state.me;
+		let now = app.clock.now();
+		let (peer, late) = (PeerId::random(), PeerId::random());
+		for peer in [peer, late] {
+			let pairing = Pairing::new(&me, peer, String::new(), PairingDirection::Outgoing, now);
+			app.state.pairings.insert(peer, pairing);
+		}
+
+		assert!(matches!(
+			app.receive_pair_response(peer, true),
+			PeerRes::PairResponseAck
+		));
+		assert_eq!(app.state.pairings[&peer].status, PairingStatus::AwaitingYou);
+		assert!(app.state.permissions_granted_to_peer(&peer).is_empty());
+
+		let (tx, rx) = oneshot::channel();
+		app.handle_cmd(Command::AnswerPairing {
+			peer,
+			accept: true,
+			tx,
+		})
+		.await;
+		rx.await.unwrap().unwrap();
+		assert_eq!(app.state.pairings[&peer].status, PairingStatus::Paired);
+		assert_eq!(app.state.permissions_granted_to_peer(&peer).len(), 1);
+
+		app.state.pairings.get_mut(&late).unwrap().started_at =
+			now - PAIRING_TTL - chrono::Duration::seconds(1);
+		assert!(matches!(
+			app.receive_pair_response(late, true),
+			PeerRes::Error(_)
+		));
+		assert!(matches!(
+			app.state.pairings[&late].status,
+			PairingStatus::Failed(_)
+		));
+		assert!(app.state.permissions_granted_to_peer(&late).is_empty());
+
+		let _ = std::fs::remove_dir_all(dir);
+	}
+
 	#[test]
 	fn live_search_filters_by_mime_type() {
 		let root = test_dir("live-search-mime");
@@ -3065,6 +10118,7 @@ mod tests {
 				mime_types: vec![String::from("text/plain")],
 				..Default::default()
 			},
+			HiddenNames::default(),
 			|event| events.push(event),
 		);
 
@@ -3080,4 +10134,300 @@ mod tests {
 
 		let _ = std::fs::remove_dir_all(root);
 	}
+
+	#[test]
+	fn permission_outbox_expires_on_clock_advance() {
+		let clock = ManualClock::new(Utc::now());
+		let conn = test_util::memory_db();
+		let db = Arc::new(Db::single(conn));
+		let kept = PeerId::random();
+		let expired = PeerId::random();
+		let permissions = vec![Permission::new(Rule::Owner)];
+
+		queue_permissions_changed(&db, &expired, &permissions, clock.now().timestamp());
+		clock.advance(chrono::Duration::days(6));
+		queue_permissions_changed(&db, &kept, &permissions, clock.now().timestamp());
+		clock.advance(chrono::Duration::days(2));
+
+		let conn = db.lock().unwrap();
+		let now = clock.now().timestamp();
+		assert!(
+			take_permission_change(&conn, &expired, now)
+				.unwrap()
+				.is_none()
+		);
+		assert_eq!(
+			take_permission_change(&conn, &kept, now)
+				.unwrap()
+				.map(|permissions| permissions.len()),
+			Some(1)
+		);
+		assert!(take_permission_change(&conn, &kept, now).unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn temporary_grants_expire_on_the_app_clock() {
+		let dir = test_dir("temporary-grant-clock");
+		let (mut app, _tx) = test_app(&dir);
+		let clock = Arc::new(ManualClock::new(Utc::now()));
+		app.clock = clock.clone();
+		let peer = PeerId::random();
+		let path = Path::new("/media/photos");
+		app.state.shared_folders = vec![FolderRule::new(path.to_path_buf(), FLAG_READ)];
+		app.state.grant_temporary(
+			peer,
+			FolderRule::new(path.to_path_buf(), FLAG_READ),
+			clock.now() + chrono::Duration::minutes(10),
+		);
+
+		assert!(app.can_access(peer, path, FLAG_READ));
+		clock.advance(chrono::Duration::minutes(20));
+		assert!(!app.can_access(peer, path, FLAG_READ));
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[tokio::test]
+	async fn edited_images_never_serve_a_stale_thumbnail() {
+		let dir = test_dir("thumbnail-refresh");
+		std::fs::create_dir_all(&dir).unwrap();
+		let photo = std::fs::canonicalize(&dir).unwrap().join("photo.png");
+		image::RgbImage::new(40, 20).save(&photo).unwrap();
+		let cache = Mutex::new(ThumbnailCache::default());
+		let disk = ThumbnailDisk::new(dir.join("thumbnails"));
+		let limits = Arc::new(DecodeLimits::new(1, u64::MAX));
+
+		let first = cached_thumbnail(&cache, &disk, &limits, &photo, 100, 100)
+			.await
+			.unwrap();
+		assert_eq!((first.width, first.height), (40, 20));
+
+		// Rotated locally: same size on disk, newer mtime.
+		image::RgbImage::new(20, 40).save(&photo).unwrap();
+		let touched = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+		let file = std::fs::File::options().write(true).open(&photo).unwrap();
+		file.set_modified(touched).unwrap();
+		let rotated = cached_thumbnail(&cache, &disk, &limits, &photo, 100, 100)
+			.await
+			.unwrap();
+		assert_eq!((rotated.width, rotated.height), (20, 40));
+
+		// Rewritten through WriteFile within the same mtime tick: the write
+		// path invalidates explicitly.
+		image::RgbImage::new(40, 20).save(&photo).unwrap();
+		file.set_modified(touched).unwrap();
+		assert_eq!(cache.lock().unwrap().invalidate(&photo), 1);
+		disk.invalidate(&photo).unwrap();
+		let rewritten = cached_thumbnail(&cache, &disk, &limits, &photo, 100, 100)
+			.await
+			.unwrap();
+		assert_eq!((rewritten.width, rewritten.height), (40, 20));
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[tokio::test]
+	async fn pregenerated_thumbnails_match_on_demand_ones_byte_for_byte() {
+		let dir = test_dir("thumbnail-pregen");
+		std::fs::create_dir_all(&dir).unwrap();
+		let photo = std::fs::canonicalize(&dir).unwrap().join("photo.png");
+		image::RgbImage::from_fn(300, 200, |x, y| image::Rgb([x as u8, y as u8, 128]))
+			.save(&photo)
+			.unwrap();
+		let size = PREGEN_THUMBNAIL_SIZE;
+		let limits = Arc::new(DecodeLimits::new(1, u64::MAX));
+
+		let ahead = ThumbnailDisk::new(dir.join("ahead"));
+		assert_eq!(
+			pregenerate_thumbnail(&ahead, &limits, &photo).await,
+			PregenOutcome::Generated
+		);
+		assert_eq!(
+			pregenerate_thumbnail(&ahead, &limits, &photo).await,
+			PregenOutcome::Skipped
+		);
+
+		let on_demand = ThumbnailDisk::new(dir.join("on-demand"));
+		let cache = Mutex::new(ThumbnailCache::default());
+		let served = cached_thumbnail(&cache, &on_demand, &limits, &photo, size, size)
+			.await
+			.unwrap();
+		assert_eq!(
+			std::fs::read(ahead.entry_path(&photo, size, size)).unwrap(),
+			std::fs::read(on_demand.entry_path(&photo, size, size)).unwrap()
+		);
+
+		// Browsing after pregeneration is served from the kept entry.
+		let cache = Mutex::new(ThumbnailCache::default());
+		let browsed = cached_thumbnail(&cache, &ahead, &limits, &photo, size, size)
+			.await
+			.unwrap();
+		assert_eq!(browsed.data, served.data);
+
+		// A foreground request holding the only permit makes it step aside.
+		let other = ThumbnailDisk::new(dir.join("other"));
+		let _held = limits.acquire().await.unwrap();
+		assert_eq!(
+			pregenerate_thumbnail(&other, &limits, &photo).await,
+			PregenOutcome::Yielded
+		);
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn stale_permission_revision_is_rejected_with_latest_rules() {
+		use crate::db::{load_permission_set, save_peer_permissions_at};
+		use crate::state::PermissionConflict;
+		let mut conn = test_util::memory_db();
+		let me = PeerId::random();
+		let peer = PeerId::random();
+		let inbox = vec![Permission::new(Rule::Inbox)];
+		let owner = vec![Permission::new(Rule::Owner)];
+
+		// Desktop and phone both open the editor at the same revision.
+		let desktop = load_permission_set(&conn, &me, &peer).unwrap();
+		let phone = load_permission_set(&conn, &me, &peer).unwrap();
+		assert_eq!(desktop.revision, 0);
+
+		let saved = save_peer_permissions_at(&mut conn, &me, &peer, &inbox, Some(desktop.revision))
+			.unwrap();
+		assert_eq!(saved, 1);
+
+		let err = save_peer_permissions_at(&mut conn, &me, &peer, &owner, Some(phone.revision))
+			.unwrap_err();
+		let conflict = err.downcast_ref::<PermissionConflict>().unwrap();
+		assert_eq!(conflict.expected_revision, 0);
+		assert_eq!(conflict.latest.revision, 1);
+		assert!(matches!(
+			conflict.latest.permissions.as_slice(),
+			[permission] if matches!(permission.rule(), Rule::Inbox)
+		));
+		let stored = load_permission_set(&conn, &me, &peer).unwrap();
+		assert!(matches!(stored.permissions[0].rule(), Rule::Inbox));
+
+		// Retrying from the latest revision, or omitting it, goes through.
+		let mut merged = conflict.latest.permissions.clone();
+		merged.extend(owner.clone());
+		assert_eq!(
+			save_peer_permissions_at(&mut conn, &me, &peer, &merged, Some(1)).unwrap(),
+			2
+		);
+		assert_eq!(
+			save_peer_permissions_at(&mut conn, &me, &peer, &owner, None).unwrap(),
+			3
+		);
+		assert_eq!(
+			load_permission_set(&conn, &me, &peer)
+				.unwrap()
+				.permissions
+				.len(),
+			1
+		);
+	}
+
+	#[tokio::test]
+	async fn peers_creating_users_hear_why_they_were_refused() {
+		let dir = test_dir("peer-create-user");
+		let (mut app, _cmd_tx) = test_app(&dir);
+		let peer = PeerId::random();
+		let create = |username: &str, password: &str| PeerReq::CreateUser {
+			username: username.to_string(),
+			password: password.to_string(),
+			roles: Vec::new(),
+			permissions: Vec::new(),
+		};
+
+		let res = app
+			.handle_puppy_peer_req(peer, create(" ana ", "long enough"))
+			.await
+			.unwrap();
+		assert!(matches!(res, PeerRes::UserCreated { username } if username == "ana"));
+		let stored = crate::db::load_users(&app.db.lock().unwrap()).unwrap();
+		assert!(crate::auth::verify_password("long enough", &stored[0].passw).unwrap());
+
+		// Peers that predate typed refusals get the message.
+		let res = app
+			.handle_puppy_peer_req(peer, create("ana", "long enough"))
+			.await
+			.unwrap();
+		assert!(
+			matches!(res, PeerRes::Error(message) if message == "Failed to create user: user ana already exists")
+		);
+
+		app.state.capabilities.insert(
+			peer,
+			PeerCapabilities {
+				protocol_version: crate::p2p::PROTOCOL_VERSION,
+				features: vec![FEATURE_USER_REFUSED.to_string()],
+				legacy: false,
+			},
+		);
+		let res = app
+			.handle_puppy_peer_req(peer, create("bo", "short"))
+			.await
+			.unwrap();
+		let PeerRes::UserRefused(refused) = res else {
+			panic!("expected a typed refusal, got {res:?}");
+		};
+		assert!(refused.field("password").is_some());
+		assert_eq!(app.users.len(), 1);
+	}
+
+	#[tokio::test]
+	async fn commands_creating_users_hash_off_the_loop() {
+		let dir = test_dir("command-create-user");
+		let (mut app, _cmd_tx) = test_app(&dir);
+		let create = |username: &str| {
+			let (tx, rx) = oneshot::channel();
+			let cmd = Command::CreateUser {
+				username: username.to_string(),
+				password: String::from("long enough"),
+				tx,
+			};
+			(cmd, rx)
+		};
+
+		// Both pass the checks before either is hashed; only one is saved.
+		let (first, mut first_rx) = create("ana");
+		let (second, second_rx) = create("ana");
+		app.handle_cmd(first).await;
+		app.handle_cmd(second).await;
+		assert!(first_rx.try_recv().is_err());
+		let mut hashed = 0;
+		while hashed < 2 {
+			let cmd = app.internal_rx.recv().await.unwrap();
+			if matches!(cmd, InternalCommand::UserHashed { .. }) {
+				hashed += 1;
+			}
+			app.handle_internal_cmd(cmd);
+		}
+		let results = [first_rx.await.unwrap(), second_rx.await.unwrap()];
+		assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+		let refused = results.into_iter().find_map(Result::err).unwrap();
+		assert_eq!(
+			refused.downcast_ref::<CreateUserError>(),
+			Some(&CreateUserError::AlreadyExists {
+				username: String::from("ana")
+			})
+		);
+		assert_eq!(
+			crate::db::load_users(&app.db.lock().unwrap())
+				.unwrap()
+				.len(),
+			1
+		);
+
+		// Refused names are answered right away.
+		let (taken, taken_rx) = create(" ana ");
+		app.handle_cmd(taken).await;
+		assert!(
+			taken_rx
+				.await
+				.unwrap()
+				.unwrap_err()
+				.downcast_ref::<CreateUserError>()
+				.is_some()
+		);
+	}
 }
@@ -31,6 +31,18 @@ pub fn verify_password(password: &str, hash: &str) -> Result<bool> {
 		.is_ok())
 }
 
+/// Hash of an unguessable password, checked in place of a missing user's
+/// hash so unknown usernames cost as much as wrong passwords.
+pub fn dummy_password_hash() -> &'static str {
+	static HASH: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+	HASH.get_or_init(|| {
+		let mut bytes = [0u8; 32];
+		OsRng.fill_bytes(&mut bytes);
+		let password: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+		hash_password(&password).expect("hashing a random password")
+	})
+}
+
 pub fn issue_jwt(username: &str, secret: &[u8]) -> Result<String> {
 	let now = Utc::now();
 	let claims = Claims {
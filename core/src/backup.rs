@@ -0,0 +1,327 @@
+//! Backups of the node database. A backup is copied page by page through
+//! SQLite's online backup API from a separate read-only connection, so the
+//! node keeps running, and is only kept once `quick_check` passes on the
+//! copy. Restores swap a verified copy in place of the live file, keeping
+//! the old one next to it as `.pre-restore`.
+
+use crate::db::db_path;
+use anyhow::{Context, bail};
+use chrono::{DateTime, Local, Timelike, Utc};
+use rusqlite::backup::{Backup, StepResult};
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub(crate) const BACKUP_SETTINGS_SETTING: &str = "backup_settings";
+/// How often the scheduler checks whether a backup is due.
+pub(crate) const BACKUP_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const BACKUP_PREFIX: &str = "puppynet-";
+const BACKUP_EXTENSION: &str = ".db";
+/// Wait before retrying a backup step that found the source locked.
+const BACKUP_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Where backups go and when they are taken.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupSettings {
+	/// Take a backup every day at `hour`.
+	pub scheduled: bool,
+	/// Local hour of day, 0-23.
+	pub hour: u8,
+	/// Backups kept in the folder; older ones are removed after each run.
+	pub keep: usize,
+	/// `backups` next to the database when unset.
+	pub dir: Option<PathBuf>,
+}
+
+impl Default for BackupSettings {
+	fn default() -> Self {
+		Self {
+			scheduled: false,
+			hour: 3,
+			keep: 7,
+			dir: None,
+		}
+	}
+}
+
+impl BackupSettings {
+	pub fn backup_dir(&self) -> PathBuf {
+		self.dir.clone().unwrap_or_else(|| {
+			db_path()
+				.parent()
+				.map(|parent| parent.join("backups"))
+				.unwrap_or_else(|| PathBuf::from("backups"))
+		})
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupKind {
+	Backup,
+	Restore,
+}
+
+impl BackupKind {
+	pub(crate) fn as_str(&self) -> &'static str {
+		match self {
+			BackupKind::Backup => "backup",
+			BackupKind::Restore => "restore",
+		}
+	}
+
+	pub(crate) fn parse(value: &str) -> Option<Self> {
+		match value {
+			"backup" => Some(BackupKind::Backup),
+			"restore" => Some(BackupKind::Restore),
+			_ => None,
+		}
+	}
+}
+
+/// One backup or restore, successful or not.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BackupRun {
+	pub kind: BackupKind,
+	pub started_at: DateTime<Utc>,
+	pub finished_at: DateTime<Utc>,
+	/// The backup written, or the file restored from.
+	pub path: String,
+	pub size_bytes: Option<u64>,
+	pub scheduled: bool,
+	pub error: Option<String>,
+}
+
+fn backup_file_name(at: DateTime<Utc>) -> String {
+	format!(
+		"{BACKUP_PREFIX}{}{BACKUP_EXTENSION}",
+		at.format("%Y%m%d-%H%M%S")
+	)
+}
+
+/// `path` with `.{suffix}` appended to its file name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+	let mut name = path.file_name().unwrap_or_default().to_os_string();
+	name.push(format!(".{suffix}"));
+	path.with_file_name(name)
+}
+
+/// Checks that `path` is an intact puppynet database.
+pub(crate) fn verify_database(path: &Path) -> anyhow::Result<()> {
+	let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+		.with_context(|| format!("failed to open {}", path.display()))?;
+	let check: String = conn
+		.query_row("PRAGMA quick_check", [], |row| row.get(0))
+		.with_context(|| format!("{} is not a readable database", path.display()))?;
+	if check != "ok" {
+		bail!("{} failed the integrity check: {check}", path.display());
+	}
+	let has_migrations: bool = conn.query_row(
+		"SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'migrations')",
+		[],
+		|row| row.get(0),
+	)?;
+	if !has_migrations {
+		bail!("{} is not a puppynet database", path.display());
+	}
+	Ok(())
+}
+
+/// Backups in `dir`, oldest first.
+fn list_backups(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+	let mut backups = fs::read_dir(dir)?
+		.filter_map(|entry| entry.ok())
+		.map(|entry| entry.path())
+		.filter(|path| {
+			path.file_name()
+				.and_then(|name| name.to_str())
+				.is_some_and(|name| {
+					name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_EXTENSION)
+				})
+		})
+		.collect::<Vec<_>>();
+	// Names carry the timestamp, so they sort by age.
+	backups.sort();
+	Ok(backups)
+}
+
+fn rotate_backups(dir: &Path, keep: usize) -> anyhow::Result<()> {
+	let backups = list_backups(dir)?;
+	let excess = backups.len().saturating_sub(keep.max(1));
+	for old in &backups[..excess] {
+		fs::remove_file(old).with_context(|| format!("failed to remove {}", old.display()))?;
+	}
+	Ok(())
+}
+
+fn copy_database(source: &Connection, dest: &Path) -> anyhow::Result<()> {
+	let mut dest = Connection::open(dest)?;
+	let backup = Backup::new(source, &mut dest)?;
+	// All pages in one step copy a single consistent snapshot; smaller
+	// steps would restart whenever the node writes in between.
+	loop {
+		match backup.step(-1)? {
+			StepResult::Done => return Ok(()),
+			StepResult::More => {}
+			_ => std::thread::sleep(BACKUP_RETRY_DELAY),
+		}
+	}
+}
+
+/// Copies the database at `db_path` into `dest_dir`, verifies the copy and
+/// removes all but the newest `keep` backups there.
+pub(crate) fn write_backup(
+	db_path: &Path,
+	dest_dir: &Path,
+	keep: usize,
+	at: DateTime<Utc>,
+) -> anyhow::Result<PathBuf> {
+	fs::create_dir_all(dest_dir)
+		.with_context(|| format!("failed to create {}", dest_dir.display()))?;
+	let target = dest_dir.join(backup_file_name(at));
+	let partial = sibling(&target, "partial");
+	let _ = fs::remove_file(&partial);
+	let source = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+		.with_context(|| format!("failed to open {}", db_path.display()))?;
+	let copied = copy_database(&source, &partial).and_then(|()| verify_database(&partial));
+	if let Err(err) = copied {
+		let _ = fs::remove_file(&partial);
+		return Err(err);
+	}
+	fs::rename(&partial, &target)
+		.with_context(|| format!("failed to move backup to {}", target.display()))?;
+	rotate_backups(dest_dir, keep)?;
+	Ok(target)
+}
+
+/// Replaces the database file at `db_path` with a verified copy of `src`.
+/// The connection to `db_path` must be closed. Returns where the previous
+/// database was kept.
+pub(crate) fn swap_in_database(db_path: &Path, src: &Path) -> anyhow::Result<PathBuf> {
+	let staged = sibling(db_path, "restoring");
+	fs::copy(src, &staged).with_context(|| format!("failed to copy {}", src.display()))?;
+	if let Err(err) = verify_database(&staged) {
+		let _ = fs::remove_file(&staged);
+		return Err(err);
+	}
+	let previous = sibling(db_path, "pre-restore");
+	for suffix in ["", "-wal", "-shm", "-journal"] {
+		let mut from = db_path.as_os_str().to_os_string();
+		from.push(suffix);
+		let mut to = previous.as_os_str().to_os_string();
+		to.push(suffix);
+		let _ = fs::remove_file(&to);
+		if Path::new(&from).exists() {
+			fs::rename(&from, &to)
+				.with_context(|| format!("failed to move {} aside", Path::new(&from).display()))?;
+		}
+	}
+	fs::rename(&staged, db_path)
+		.with_context(|| format!("failed to move restored database to {}", db_path.display()))?;
+	Ok(previous)
+}
+
+/// Whether the scheduled backup should run at `now`: once a day, during the
+/// configured hour.
+pub(crate) fn scheduled_backup_due(
+	settings: &BackupSettings,
+	last_success: Option<DateTime<Utc>>,
+	now: DateTime<Local>,
+) -> bool {
+	settings.scheduled
+		&& now.hour() == u32::from(settings.hour)
+		&& last_success
+			.is_none_or(|last| last.with_timezone(&Local).date_naive() != now.date_naive())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::db::run_migrations;
+	use crate::test_util::test_dir;
+	use chrono::TimeZone;
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+	fn count_rows(path: &Path) -> u64 {
+		Connection::open(path)
+			.unwrap()
+			.query_row("SELECT COUNT(*) FROM filler", [], |row| row.get(0))
+			.unwrap()
+	}
+
+	#[test]
+	fn restores_a_backup_taken_during_concurrent_inserts() {
+		let dir = test_dir("backup-restore");
+		let db = dir.join("puppynet.db");
+		{
+			let mut conn = Connection::open(&db).unwrap();
+			run_migrations(&mut conn).unwrap();
+			conn.execute("CREATE TABLE filler (n INTEGER NOT NULL)", [])
+				.unwrap();
+		}
+		let stop = Arc::new(AtomicBool::new(false));
+		let inserted = Arc::new(AtomicU64::new(0));
+		let writer = {
+			let (db, stop, inserted) = (db.clone(), stop.clone(), inserted.clone());
+			std::thread::spawn(move || {
+				let conn = Connection::open(&db).unwrap();
+				while !stop.load(Ordering::SeqCst) {
+					let n = inserted.load(Ordering::SeqCst);
+					conn.execute("INSERT INTO filler (n) VALUES (?1)", [n as i64])
+						.unwrap();
+					inserted.fetch_add(1, Ordering::SeqCst);
+				}
+			})
+		};
+		while inserted.load(Ordering::SeqCst) < 50 {
+			std::thread::sleep(Duration::from_millis(1));
+		}
+		let backups = dir.join("backups");
+		let start = Utc.with_ymd_and_hms(2025, 3, 1, 3, 0, 0).unwrap();
+		let mut latest = PathBuf::new();
+		for minute in 0..3 {
+			latest =
+				write_backup(&db, &backups, 2, start + chrono::Duration::minutes(minute)).unwrap();
+		}
+		stop.store(true, Ordering::SeqCst);
+		writer.join().unwrap();
+
+		assert_eq!(list_backups(&backups).unwrap().len(), 2);
+		verify_database(&latest).unwrap();
+		let backed_up = count_rows(&latest);
+		let total = inserted.load(Ordering::SeqCst);
+		assert!(backed_up > 0 && backed_up <= total);
+
+		let previous = swap_in_database(&db, &latest).unwrap();
+		verify_database(&db).unwrap();
+		assert_eq!(count_rows(&db), backed_up);
+		assert_eq!(count_rows(&previous), total);
+
+		let junk = dir.join("junk.db");
+		fs::write(&junk, b"not a database").unwrap();
+		assert!(swap_in_database(&db, &junk).is_err());
+		assert_eq!(count_rows(&db), backed_up);
+		let _ = fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn scheduled_backup_runs_once_during_its_hour() {
+		let settings = BackupSettings {
+			scheduled: true,
+			hour: 3,
+			..BackupSettings::default()
+		};
+		let at = |day, hour| Local.with_ymd_and_hms(2025, 3, day, hour, 30, 0).unwrap();
+		assert!(scheduled_backup_due(&settings, None, at(1, 3)));
+		assert!(!scheduled_backup_due(&settings, None, at(1, 4)));
+		let done = at(1, 3).with_timezone(&Utc);
+		assert!(!scheduled_backup_due(&settings, Some(done), at(1, 3)));
+		assert!(scheduled_backup_due(&settings, Some(done), at(2, 3)));
+		let off = BackupSettings::default();
+		assert!(!scheduled_backup_due(&off, None, at(1, 3)));
+	}
+}
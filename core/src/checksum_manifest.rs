@@ -0,0 +1,683 @@
+//! Checksum manifests (`SHA256SUMS`, `*.md5`, `*.sfv`, ...) found while
+//! scanning. Every line of a manifest claims a digest for one file; the
+//! scanner stores the claims in `claimed_hashes` next to the file's
+//! location and checks them against the file's content, so a mismatch
+//! shows data that changed since the manifest was written.
+//!
+//! A claim is only checked with the algorithm it names: the scanner's own
+//! blake3 hash answers blake3 claims, and files named by other manifests
+//! are read once more for the digests they need. A check is reused while
+//! the file's blake3 hash stays the same. Lines that cannot be parsed are
+//! counted and skipped.
+
+use crate::db::{path_column, path_to_sql};
+use crate::scan::FileHash;
+use chrono::{DateTime, Utc};
+use md5::Md5;
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+pub(crate) const MANIFEST_PATTERNS_SETTING: &str = "checksum_manifest_patterns";
+/// File names treated as manifests unless [`MANIFEST_PATTERNS_SETTING`]
+/// lists others.
+pub(crate) const DEFAULT_MANIFEST_PATTERNS: &[&str] = &[
+	"*.sfv",
+	"MD5SUMS",
+	"SHA1SUMS",
+	"SHA256SUMS",
+	"*.md5",
+	"*.sha1",
+	"*.sha256",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+	Crc32,
+	Md5,
+	Sha1,
+	Sha256,
+	Blake3,
+}
+
+impl ChecksumAlgorithm {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Crc32 => "crc32",
+			Self::Md5 => "md5",
+			Self::Sha1 => "sha1",
+			Self::Sha256 => "sha256",
+			Self::Blake3 => "blake3",
+		}
+	}
+
+	pub fn parse(value: &str) -> Option<Self> {
+		match value.to_ascii_lowercase().as_str() {
+			"crc32" => Some(Self::Crc32),
+			"md5" => Some(Self::Md5),
+			"sha1" => Some(Self::Sha1),
+			"sha256" => Some(Self::Sha256),
+			"blake3" => Some(Self::Blake3),
+			_ => None,
+		}
+	}
+
+	fn digest_len(&self) -> usize {
+		match self {
+			Self::Crc32 => 4,
+			Self::Md5 => 16,
+			Self::Sha1 => 20,
+			Self::Sha256 | Self::Blake3 => 32,
+		}
+	}
+
+	/// The algorithm a manifest named `name` uses for every line, if the
+	/// name says.
+	fn from_manifest_name(name: &str) -> Option<Self> {
+		let name = name.to_ascii_lowercase();
+		if name.ends_with(".sfv") {
+			Some(Self::Crc32)
+		} else if name.contains("blake3") || name.contains("b3sum") {
+			Some(Self::Blake3)
+		} else if name.contains("sha256") {
+			Some(Self::Sha256)
+		} else if name.contains("sha1") {
+			Some(Self::Sha1)
+		} else if name.contains("md5") {
+			Some(Self::Md5)
+		} else {
+			None
+		}
+	}
+
+	/// Guess for a digest of `len` bytes in a manifest whose name does not
+	/// say. 32 bytes is taken as sha256, the more common of the two.
+	fn from_digest_len(len: usize) -> Option<Self> {
+		match len {
+			4 => Some(Self::Crc32),
+			16 => Some(Self::Md5),
+			20 => Some(Self::Sha1),
+			32 => Some(Self::Sha256),
+			_ => None,
+		}
+	}
+}
+
+/// What a scan learned from the manifests under the scanned folder.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChecksumSummary {
+	pub manifests: u64,
+	pub claims: u64,
+	pub matched: u64,
+	pub mismatched: u64,
+	/// Claims the file could not be read for.
+	pub unverified: u64,
+	/// Claims naming a file the scan did not find.
+	pub missing: u64,
+	pub malformed_lines: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ClaimStatus {
+	Match,
+	Mismatch,
+	Unverified,
+}
+
+impl ClaimStatus {
+	pub(crate) fn as_str(&self) -> &'static str {
+		match self {
+			Self::Match => "match",
+			Self::Mismatch => "mismatch",
+			Self::Unverified => "unverified",
+		}
+	}
+}
+
+/// A file whose content no longer matches the checksum a manifest claims.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HashMismatch {
+	pub path: PathBuf,
+	pub manifest: PathBuf,
+	pub algorithm: ChecksumAlgorithm,
+	pub claimed: Vec<u8>,
+	pub actual: Vec<u8>,
+	pub checked_at: DateTime<Utc>,
+}
+
+/// Patterns stored under [`MANIFEST_PATTERNS_SETTING`], comma separated.
+pub(crate) fn patterns_from_setting(value: Option<&str>) -> Vec<String> {
+	let patterns: Vec<String> = value
+		.unwrap_or_default()
+		.split(',')
+		.map(str::trim)
+		.filter(|pattern| !pattern.is_empty())
+		.map(str::to_string)
+		.collect();
+	if patterns.is_empty() {
+		return DEFAULT_MANIFEST_PATTERNS
+			.iter()
+			.map(|pattern| pattern.to_string())
+			.collect();
+	}
+	patterns
+}
+
+/// Whether file `name` matches `pattern`, where `*` stands for any run of
+/// characters and `?` for one. Case is ignored.
+pub(crate) fn matches_pattern(name: &str, pattern: &str) -> bool {
+	let name: Vec<char> = name.to_lowercase().chars().collect();
+	let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+	let (mut n, mut p) = (0, 0);
+	let mut star: Option<(usize, usize)> = None;
+	while n < name.len() {
+		if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+			n += 1;
+			p += 1;
+		} else if p < pattern.len() && pattern[p] == '*' {
+			star = Some((p, n));
+			p += 1;
+		} else if let Some((star_p, star_n)) = star {
+			p = star_p + 1;
+			n = star_n + 1;
+			star = Some((star_p, star_n + 1));
+		} else {
+			return false;
+		}
+	}
+	pattern[p..].iter().all(|c| *c == '*')
+}
+
+pub(crate) fn is_manifest(path: &Path, patterns: &[String]) -> bool {
+	let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+		return false;
+	};
+	patterns
+		.iter()
+		.any(|pattern| matches_pattern(name, pattern))
+}
+
+/// One line of a manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ManifestEntry {
+	/// The file the line names, resolved against the manifest's folder.
+	pub path: PathBuf,
+	pub algorithm: ChecksumAlgorithm,
+	pub digest: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ParsedManifest {
+	pub entries: Vec<ManifestEntry>,
+	pub malformed: u64,
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+	if text.is_empty() || !text.len().is_multiple_of(2) || !text.is_ascii() {
+		return None;
+	}
+	(0..text.len())
+		.step_by(2)
+		.map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+		.collect()
+}
+
+/// `path` with `.` and `..` worked out without touching the filesystem,
+/// since the file may no longer exist.
+fn normalize(path: &Path) -> PathBuf {
+	let mut out = PathBuf::new();
+	for component in path.components() {
+		match component {
+			Component::CurDir => {}
+			Component::ParentDir => {
+				out.pop();
+			}
+			other => out.push(other),
+		}
+	}
+	out
+}
+
+/// `DIGEST  name` or `DIGEST *name` as written by `md5sum`/`sha256sum`,
+/// with their `\` escape for names holding a newline or backslash.
+fn parse_gnu_line(line: &str) -> Option<(&str, String)> {
+	let (escaped, line) = match line.strip_prefix('\\') {
+		Some(rest) => (true, rest),
+		None => (false, line),
+	};
+	let (digest, rest) = line.split_once(' ')?;
+	let name = rest
+		.strip_prefix('*')
+		.or_else(|| rest.strip_prefix(' '))
+		.unwrap_or(rest);
+	if name.is_empty() {
+		return None;
+	}
+	let name = if escaped {
+		name.replace("\\n", "\n").replace("\\\\", "\\")
+	} else {
+		name.to_string()
+	};
+	Some((digest, name))
+}
+
+/// `ALGO (name) = DIGEST` as written by BSD tools and `--tag`.
+fn parse_tagged_line(line: &str) -> Option<(ChecksumAlgorithm, String, &str)> {
+	let (algorithm, rest) = line.split_once(" (")?;
+	let (name, digest) = rest.rsplit_once(") = ")?;
+	let algorithm = ChecksumAlgorithm::parse(&algorithm.replace('-', ""))?;
+	Some((algorithm, name.to_string(), digest))
+}
+
+/// `name DIGEST` as in `.sfv` files.
+fn parse_sfv_line(line: &str) -> Option<(&str, String)> {
+	let (name, digest) = line.trim_end().rsplit_once([' ', '\t'])?;
+	let name = name.trim_end();
+	if name.is_empty() {
+		return None;
+	}
+	Some((digest, name.to_string()))
+}
+
+/// The claims in `text`, the contents of the manifest at `manifest`.
+/// Blank lines and comments are skipped without counting as malformed.
+pub(crate) fn parse_manifest(manifest: &Path, text: &str) -> ParsedManifest {
+	let named = manifest
+		.file_name()
+		.and_then(|name| name.to_str())
+		.and_then(ChecksumAlgorithm::from_manifest_name);
+	let dir = manifest.parent().unwrap_or(Path::new(""));
+	let mut parsed = ParsedManifest::default();
+	for line in text.lines() {
+		let line = line.trim_end_matches('\r');
+		if line.trim().is_empty() || line.starts_with('#') || line.starts_with(';') {
+			continue;
+		}
+		let claim = if let Some((algorithm, name, digest)) = parse_tagged_line(line) {
+			Some((Some(algorithm), digest, name))
+		} else if named == Some(ChecksumAlgorithm::Crc32) {
+			parse_sfv_line(line).map(|(digest, name)| (named, digest, name))
+		} else {
+			parse_gnu_line(line).map(|(digest, name)| (named, digest, name))
+		};
+		let entry = claim.and_then(|(algorithm, digest, name)| {
+			let digest = decode_hex(digest.trim())?;
+			let algorithm =
+				algorithm.or_else(|| ChecksumAlgorithm::from_digest_len(digest.len()))?;
+			if digest.len() != algorithm.digest_len() {
+				return None;
+			}
+			Some(ManifestEntry {
+				path: normalize(&dir.join(name)),
+				algorithm,
+				digest,
+			})
+		});
+		match entry {
+			Some(entry) => parsed.entries.push(entry),
+			None => parsed.malformed += 1,
+		}
+	}
+	parsed
+}
+
+/// Digests of `reader` for each of `algorithms`, reading it once.
+fn compute_digests<R: Read>(
+	mut reader: R,
+	algorithms: &[ChecksumAlgorithm],
+) -> io::Result<HashMap<ChecksumAlgorithm, Vec<u8>>> {
+	let mut crc32 = crc32fast::Hasher::new();
+	let mut md5 = Md5::new();
+	let mut sha1 = Sha1::new();
+	let mut sha256 = Sha256::new();
+	let mut blake3 = blake3::Hasher::new();
+	let wanted: HashSet<ChecksumAlgorithm> = algorithms.iter().copied().collect();
+	let mut buffer = [0u8; 8192];
+	loop {
+		let count = reader.read(&mut buffer)?;
+		if count == 0 {
+			break;
+		}
+		let data = &buffer[..count];
+		for algorithm in &wanted {
+			match algorithm {
+				ChecksumAlgorithm::Crc32 => crc32.update(data),
+				ChecksumAlgorithm::Md5 => md5.update(data),
+				ChecksumAlgorithm::Sha1 => sha1.update(data),
+				ChecksumAlgorithm::Sha256 => sha256.update(data),
+				ChecksumAlgorithm::Blake3 => {
+					blake3.update(data);
+				}
+			}
+		}
+	}
+	let mut digests = HashMap::new();
+	for algorithm in wanted {
+		let digest = match algorithm {
+			ChecksumAlgorithm::Crc32 => crc32.clone().finalize().to_be_bytes().to_vec(),
+			ChecksumAlgorithm::Md5 => md5.clone().finalize().to_vec(),
+			ChecksumAlgorithm::Sha1 => sha1.clone().finalize().to_vec(),
+			ChecksumAlgorithm::Sha256 => sha256.clone().finalize().to_vec(),
+			ChecksumAlgorithm::Blake3 => blake3.finalize().as_bytes().to_vec(),
+		};
+		digests.insert(algorithm, digest);
+	}
+	Ok(digests)
+}
+
+/// A claim as last checked.
+struct StoredClaim {
+	claimed: Vec<u8>,
+	actual: Option<Vec<u8>>,
+	verified_hash: Option<Vec<u8>>,
+}
+
+fn load_claims(
+	conn: &Connection,
+	node_id: &[u8],
+	manifest: &Path,
+) -> rusqlite::Result<HashMap<(PathBuf, String), StoredClaim>> {
+	let mut stmt = conn.prepare(
+		"SELECT path, algorithm, claimed, actual, verified_hash FROM claimed_hashes
+		WHERE node_id = ?1 AND manifest_path = ?2",
+	)?;
+	let rows = stmt.query_map(params![node_id, path_to_sql(manifest)], |row| {
+		Ok((
+			(path_column(row, 0)?, row.get::<_, String>(1)?),
+			StoredClaim {
+				claimed: row.get(2)?,
+				actual: row.get(3)?,
+				verified_hash: row.get(4)?,
+			},
+		))
+	})?;
+	rows.collect()
+}
+
+/// Manifests under `root` that claims are stored for.
+fn stored_manifests(
+	conn: &Connection,
+	node_id: &[u8],
+	root: &Path,
+) -> rusqlite::Result<Vec<PathBuf>> {
+	let mut stmt =
+		conn.prepare("SELECT DISTINCT manifest_path FROM claimed_hashes WHERE node_id = ?1")?;
+	let rows = stmt.query_map(params![node_id], |row| path_column(row, 0))?;
+	let mut manifests = Vec::new();
+	for manifest in rows {
+		let manifest = manifest?;
+		if manifest.starts_with(root) {
+			manifests.push(manifest);
+		}
+	}
+	Ok(manifests)
+}
+
+/// One claim after checking.
+struct CheckedClaim {
+	path: PathBuf,
+	algorithm: ChecksumAlgorithm,
+	claimed: Vec<u8>,
+	actual: Option<Vec<u8>>,
+	verified_hash: Option<FileHash>,
+}
+
+/// Checks every claim in `manifests` against the files the scan of `root`
+/// found, with their blake3 hashes in `hashes`, and replaces the stored
+/// claims of each manifest. Claims of manifests under `root` that are gone
+/// are dropped. `None` when there was nothing to check.
+pub(crate) fn check_claims(
+	conn: &mut Connection,
+	node_id: &[u8],
+	root: &Path,
+	manifests: &[PathBuf],
+	hashes: &HashMap<PathBuf, Option<FileHash>>,
+) -> Result<Option<ChecksumSummary>, String> {
+	let stale: Vec<PathBuf> = stored_manifests(conn, node_id, root)
+		.map_err(|e| format!("error loading checksum claims: {:?}", e))?
+		.into_iter()
+		.filter(|manifest| !manifests.contains(manifest))
+		.collect();
+	if !stale.is_empty() {
+		let tx = conn
+			.transaction()
+			.map_err(|e| format!("error starting transaction: {:?}", e))?;
+		for manifest in &stale {
+			tx.execute(
+				"DELETE FROM claimed_hashes WHERE node_id = ?1 AND manifest_path = ?2",
+				params![node_id, path_to_sql(manifest)],
+			)
+			.map_err(|e| format!("error removing checksum claims: {:?}", e))?;
+		}
+		tx.commit()
+			.map_err(|e| format!("error removing checksum claims: {:?}", e))?;
+	}
+	if manifests.is_empty() {
+		return Ok(None);
+	}
+
+	let mut summary = ChecksumSummary::default();
+	for manifest in manifests {
+		let text = match std::fs::read(manifest) {
+			Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+			Err(err) => {
+				tracing::warn!("failed to read manifest {}: {err}", manifest.display());
+				continue;
+			}
+		};
+		summary.manifests += 1;
+		let parsed = parse_manifest(manifest, &text);
+		summary.malformed_lines += parsed.malformed;
+		let stored = load_claims(conn, node_id, manifest)
+			.map_err(|e| format!("error loading checksum claims: {:?}", e))?;
+
+		let mut checked = Vec::new();
+		let mut to_compute: HashMap<PathBuf, Vec<ChecksumAlgorithm>> = HashMap::new();
+		for entry in parsed.entries {
+			let Some(hash) = hashes.get(&entry.path) else {
+				summary.missing += 1;
+				continue;
+			};
+			summary.claims += 1;
+			let actual = if entry.algorithm == ChecksumAlgorithm::Blake3 {
+				hash.map(|hash| hash.to_vec())
+			} else {
+				stored
+					.get(&(entry.path.clone(), entry.algorithm.as_str().to_string()))
+					.filter(|prev| {
+						prev.claimed == entry.digest
+							&& hash.is_some() && prev.verified_hash.as_deref()
+							== hash.as_ref().map(|h| &h[..])
+					})
+					.and_then(|prev| prev.actual.clone())
+			};
+			if actual.is_none() && entry.algorithm != ChecksumAlgorithm::Blake3 {
+				to_compute
+					.entry(entry.path.clone())
+					.or_default()
+					.push(entry.algorithm);
+			}
+			checked.push(CheckedClaim {
+				path: entry.path,
+				algorithm: entry.algorithm,
+				claimed: entry.digest,
+				actual,
+				verified_hash: *hash,
+			});
+		}
+
+		let mut computed = HashMap::new();
+		for (path, algorithms) in to_compute {
+			match std::fs::File::open(&path).and_then(|file| compute_digests(file, &algorithms)) {
+				Ok(digests) => {
+					computed.insert(path, digests);
+				}
+				Err(err) => tracing::warn!("failed to check {}: {err}", path.display()),
+			}
+		}
+		for claim in &mut checked {
+			if claim.actual.is_none() {
+				claim.actual = computed
+					.get(&claim.path)
+					.and_then(|digests| digests.get(&claim.algorithm))
+					.cloned();
+			}
+			match &claim.actual {
+				Some(actual) if *actual == claim.claimed => summary.matched += 1,
+				Some(_) => summary.mismatched += 1,
+				None => summary.unverified += 1,
+			}
+		}
+
+		let now = Utc::now();
+		let tx = conn
+			.transaction()
+			.map_err(|e| format!("error starting transaction: {:?}", e))?;
+		let write = || -> rusqlite::Result<()> {
+			tx.execute(
+				"DELETE FROM claimed_hashes WHERE node_id = ?1 AND manifest_path = ?2",
+				params![node_id, path_to_sql(manifest)],
+			)?;
+			let mut stmt = tx.prepare_cached(
+				"INSERT OR REPLACE INTO claimed_hashes (node_id, path, manifest_path, algorithm, claimed, actual, verified_hash, status, checked_at)
+				VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+			)?;
+			for claim in &checked {
+				let status = match &claim.actual {
+					Some(actual) if *actual == claim.claimed => ClaimStatus::Match,
+					Some(_) => ClaimStatus::Mismatch,
+					None => ClaimStatus::Unverified,
+				};
+				stmt.execute(params![
+					node_id,
+					path_to_sql(&claim.path),
+					path_to_sql(manifest),
+					claim.algorithm.as_str(),
+					claim.claimed,
+					claim.actual,
+					claim
+						.actual
+						.as_ref()
+						.and(claim.verified_hash)
+						.map(|hash| hash.to_vec()),
+					status.as_str(),
+					now.timestamp(),
+				])?;
+			}
+			Ok(())
+		};
+		write().map_err(|e| format!("error writing checksum claims: {:?}", e))?;
+		tx.commit()
+			.map_err(|e| format!("error writing checksum claims: {:?}", e))?;
+	}
+	Ok(Some(summary))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::format::hex;
+
+	#[test]
+	fn manifest_lines_resolve_against_the_manifest_folder() {
+		let text = "\
+# made with sha256sum
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  empty.txt
+2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824 *../hello.txt
+
+not a checksum line
+abcd  short.txt
+";
+		let parsed = parse_manifest(Path::new("/archive/2019/SHA256SUMS"), text);
+		assert_eq!(parsed.malformed, 2);
+		assert_eq!(
+			parsed
+				.entries
+				.iter()
+				.map(|entry| (entry.path.clone(), entry.algorithm))
+				.collect::<Vec<_>>(),
+			vec![
+				(
+					PathBuf::from("/archive/2019/empty.txt"),
+					ChecksumAlgorithm::Sha256
+				),
+				(
+					PathBuf::from("/archive/hello.txt"),
+					ChecksumAlgorithm::Sha256
+				),
+			]
+		);
+	}
+
+	#[test]
+	fn sfv_and_tagged_lines_carry_their_algorithm() {
+		let sfv = parse_manifest(
+			Path::new("/music/album.sfv"),
+			"; generated\r\n01 intro.flac 3610a686\r\n",
+		);
+		assert_eq!(sfv.malformed, 0);
+		assert_eq!(sfv.entries[0].path, PathBuf::from("/music/01 intro.flac"));
+		assert_eq!(sfv.entries[0].algorithm, ChecksumAlgorithm::Crc32);
+
+		let tagged = parse_manifest(
+			Path::new("/data/CHECKSUMS"),
+			"MD5 (a.bin) = 5d41402abc4b2a76b9719d911017c592\n\
+			SHA1 (b.bin) = aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d\n",
+		);
+		assert_eq!(
+			tagged
+				.entries
+				.iter()
+				.map(|entry| entry.algorithm)
+				.collect::<Vec<_>>(),
+			vec![ChecksumAlgorithm::Md5, ChecksumAlgorithm::Sha1]
+		);
+	}
+
+	#[test]
+	fn digests_are_computed_per_algorithm() {
+		let digests = compute_digests(
+			&b"hello"[..],
+			&[
+				ChecksumAlgorithm::Crc32,
+				ChecksumAlgorithm::Md5,
+				ChecksumAlgorithm::Sha1,
+				ChecksumAlgorithm::Sha256,
+			],
+		)
+		.unwrap();
+		assert_eq!(hex(&digests[&ChecksumAlgorithm::Crc32]), "3610a686");
+		assert_eq!(
+			hex(&digests[&ChecksumAlgorithm::Md5]),
+			"5d41402abc4b2a76b9719d911017c592"
+		);
+		assert_eq!(
+			hex(&digests[&ChecksumAlgorithm::Sha1]),
+			"aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d"
+		);
+		assert_eq!(
+			hex(&digests[&ChecksumAlgorithm::Sha256]),
+			"2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+		);
+	}
+
+	#[test]
+	fn patterns_match_names_case_insensitively() {
+		let patterns = patterns_from_setting(None);
+		assert!(is_manifest(Path::new("/a/album.SFV"), &patterns));
+		assert!(is_manifest(Path::new("/a/SHA256SUMS"), &patterns));
+		assert!(is_manifest(Path::new("/a/photos.sha256"), &patterns));
+		assert!(!is_manifest(Path::new("/a/SHA256SUMS.asc"), &patterns));
+		assert_eq!(
+			patterns_from_setting(Some(" *.hash, ,CHECKSUMS ")),
+			vec!["*.hash", "CHECKSUMS"]
+		);
+		assert!(matches_pattern("backup-2019.md5", "backup-*.md5"));
+		assert!(!matches_pattern("backup.md5", "backup-*.md5"));
+	}
+}
@@ -0,0 +1,174 @@
+//! Typed requests to other peers without a database or the rest of the
+//! node. A [`PeerTransport`] carries a request to a peer and brings back
+//! its answer: [`PeerClient`] over a swarm of its own, a running node over
+//! its command channel (see [`crate::PuppyNet::client`]). Either way the
+//! answer is turned into the type asked for through [`ResponseDecoder`],
+//! the same decoding `App` uses for its requests.
+
+use crate::app::Command;
+use crate::keepalive::ConnectionPolicy;
+use crate::mounts::ShareUnavailable;
+use crate::p2p::{AgentBehaviour, AgentEvent, PeerReq, PeerRes, build_swarm};
+use crate::protocol_stats::RateLimited;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use futures::StreamExt;
+use libp2p::identity::Keypair;
+use libp2p::request_response::{Event as RequestResponseEvent, Message};
+use libp2p::swarm::SwarmEvent;
+use libp2p::{Multiaddr, PeerId, Swarm};
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+/// A response type that a [`PeerRes`] can be decoded into.
+pub trait ResponseDecoder: Sized + Send + 'static {
+	fn decode(response: PeerRes) -> anyhow::Result<Self>;
+}
+
+/// The answer as it came, for callers that look at it themselves.
+impl ResponseDecoder for PeerRes {
+	fn decode(response: PeerRes) -> anyhow::Result<Self> {
+		Ok(response)
+	}
+}
+
+/// Decodes `response` into `T`. A [`PeerRes::Error`] or
+/// [`PeerRes::Unsupported`] comes back as an error, the typed refusals
+/// as their own errors: [`ShareUnavailable`], an
+/// [`AccessExplanation`](crate::state::AccessExplanation),
+/// [`RateLimited`], [`ImageTooLarge`](crate::ImageTooLarge) and a
+/// [`CreateUserError`](crate::password_policy::CreateUserError).
+pub(crate) fn decode_response<T: ResponseDecoder>(response: PeerRes) -> Result<T> {
+	match response {
+		PeerRes::Error(err) => Err(anyhow!(err)),
+		PeerRes::Unavailable { share } => Err(ShareUnavailable { share }.into()),
+		PeerRes::AccessDenied(explanation) => Err(explanation.into()),
+		PeerRes::Unsupported { request } => {
+			Err(anyhow!("peer does not support the {request} request"))
+		}
+		PeerRes::RateLimited { retry_after_secs } => Err(RateLimited {
+			retry_after: Duration::from_secs(retry_after_secs),
+		}
+		.into()),
+		PeerRes::ImageTooLarge(too_large) => Err(too_large.into()),
+		PeerRes::UserRefused(refused) => Err(refused.into()),
+		other => T::decode(other),
+	}
+}
+
+/// Carries requests to other peers and brings back their answers.
+#[async_trait]
+pub trait PeerTransport: Send {
+	/// Sends `req` to `peer` and waits for its answer, undecoded.
+	async fn send(&mut self, peer: PeerId, req: PeerReq) -> Result<PeerRes>;
+
+	/// Sends `req` to `peer` and decodes the answer into `T`. Refusals
+	/// come back as errors, typed where the peer typed them.
+	async fn request<T: ResponseDecoder>(&mut self, peer: PeerId, req: PeerReq) -> Result<T>
+	where
+		Self: Sized,
+	{
+		decode_response(self.send(peer, req).await?)
+	}
+}
+
+/// Requests sent through a running node's swarm, over its command
+/// channel.
+pub(crate) struct NodeTransport(pub(crate) UnboundedSender<Command>);
+
+#[async_trait]
+impl PeerTransport for NodeTransport {
+	async fn send(&mut self, peer: PeerId, req: PeerReq) -> Result<PeerRes> {
+		let (tx, rx) = oneshot::channel();
+		self.0
+			.send(Command::Request { peer, req, tx })
+			.map_err(|e| anyhow!("failed to send Request command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("Request response channel closed: {e}"))?
+	}
+}
+
+/// A client-only peer: it dials others and asks them things, and answers
+/// every request it receives with an error.
+///
+/// ```no_run
+/// use puppynet_core::PeerId;
+/// use puppynet_core::client::PeerClient;
+/// use puppynet_core::p2p::{PeerInfo, PeerReq};
+///
+/// # async fn run(peer: PeerId) -> anyhow::Result<()> {
+/// let keypair = libp2p::identity::Keypair::generate_ed25519();
+/// let mut client = PeerClient::new(keypair)?;
+/// client.dial("/ip4/192.168.1.20/tcp/4001".parse()?)?;
+/// let info: PeerInfo = client.request(peer, PeerReq::PeerInfo).await?;
+/// println!("{} on {}", info.version, info.os);
+/// # Ok(())
+/// # }
+/// ```
+pub struct PeerClient {
+	swarm: Swarm<AgentBehaviour>,
+}
+
+impl PeerClient {
+	pub fn new(keypair: Keypair) -> Result<Self> {
+		let peer_id = keypair.public().to_peer_id();
+		Ok(Self {
+			swarm: build_swarm(keypair, peer_id, ConnectionPolicy::default())?,
+		})
+	}
+
+	pub fn peer_id(&self) -> PeerId {
+		*self.swarm.local_peer_id()
+	}
+
+	pub fn dial(&mut self, addr: Multiaddr) -> Result<()> {
+		self.swarm.dial(addr)?;
+		Ok(())
+	}
+
+	/// Sends `req` to `peer` and decodes the answer into `T`, as
+	/// [`PeerTransport::request`] does.
+	pub async fn request<T: ResponseDecoder>(&mut self, peer: PeerId, req: PeerReq) -> Result<T> {
+		PeerTransport::request(self, peer, req).await
+	}
+}
+
+#[async_trait]
+impl PeerTransport for PeerClient {
+	async fn send(&mut self, peer: PeerId, req: PeerReq) -> Result<PeerRes> {
+		let request_id = self.swarm.behaviour_mut().puppynet.send_request(&peer, req);
+		loop {
+			let event = self.swarm.select_next_some().await;
+			let SwarmEvent::Behaviour(AgentEvent::PuppyNet(event)) = event else {
+				continue;
+			};
+			match event {
+				RequestResponseEvent::Message {
+					message: Message::Response {
+						request_id: id,
+						response,
+					},
+					..
+				} if id == request_id => return Ok(response),
+				RequestResponseEvent::Message {
+					message: Message::Request { channel, .. },
+					..
+				} => {
+					let _ = self.swarm.behaviour_mut().puppynet.send_response(
+						channel,
+						PeerRes::Error(String::from("this peer does not serve requests")),
+					);
+				}
+				RequestResponseEvent::OutboundFailure {
+					request_id: id,
+					error,
+					..
+				} if id == request_id => {
+					return Err(anyhow!("request failed: {error}"));
+				}
+				_ => {}
+			}
+		}
+	}
+}
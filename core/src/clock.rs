@@ -0,0 +1,41 @@
+use chrono::{DateTime, Utc};
+
+/// Source of wall-clock time for the app so tests can drive it.
+pub trait Clock: Send + Sync {
+	fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now(&self) -> DateTime<Utc> {
+		Utc::now()
+	}
+}
+
+/// Clock that only moves when a test advances it.
+#[cfg(test)]
+pub(crate) struct ManualClock {
+	now: std::sync::Mutex<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+	pub(crate) fn new(now: DateTime<Utc>) -> Self {
+		Self {
+			now: std::sync::Mutex::new(now),
+		}
+	}
+
+	pub(crate) fn advance(&self, by: chrono::Duration) {
+		let mut now = self.now.lock().unwrap();
+		*now += by;
+	}
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+	fn now(&self) -> DateTime<Utc> {
+		*self.now.lock().unwrap()
+	}
+}
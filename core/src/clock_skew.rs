@@ -0,0 +1,204 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Offsets below this are treated as noise and not reported.
+pub const CLOCK_SKEW_WARN_SECS: i64 = 60;
+/// Samples kept per peer; the estimate is their median so a single
+/// delayed exchange can't move it.
+const MAX_SAMPLES: usize = 9;
+/// Samples whose round trip took longer than this say little about the
+/// offset and are dropped.
+const MAX_SAMPLE_RTT_MS: i64 = 30_000;
+
+/// One timestamp exchange with a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockSample {
+	/// Remote clock minus ours, assuming the reply was stamped halfway
+	/// through the round trip.
+	pub offset_ms: i64,
+	pub rtt_ms: i64,
+}
+
+impl ClockSample {
+	/// Sample from a request sent at `sent`, answered with the peer's
+	/// `remote` time and received at `received` (both local times).
+	pub fn new(sent: DateTime<Utc>, received: DateTime<Utc>, remote: DateTime<Utc>) -> Self {
+		let rtt_ms = (received - sent).num_milliseconds().max(0);
+		let midpoint = sent + chrono::Duration::milliseconds(rtt_ms / 2);
+		Self {
+			offset_ms: (remote - midpoint).num_milliseconds(),
+			rtt_ms,
+		}
+	}
+}
+
+/// Running estimate of how far a peer's clock is from ours.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClockOffset {
+	samples: VecDeque<ClockSample>,
+}
+
+impl ClockOffset {
+	/// Adds `sample`, dropping the oldest once full. Returns false when the
+	/// round trip was too slow for the sample to be used.
+	pub fn record(&mut self, sample: ClockSample) -> bool {
+		if sample.rtt_ms > MAX_SAMPLE_RTT_MS {
+			return false;
+		}
+		if self.samples.len() == MAX_SAMPLES {
+			self.samples.pop_front();
+		}
+		self.samples.push_back(sample);
+		true
+	}
+
+	/// Median offset of the recent samples in milliseconds.
+	pub fn offset_ms(&self) -> Option<i64> {
+		median(self.samples.iter().map(|sample| sample.offset_ms))
+	}
+
+	/// Median round trip of the recent samples in milliseconds.
+	pub fn rtt_ms(&self) -> Option<i64> {
+		median(self.samples.iter().map(|sample| sample.rtt_ms))
+	}
+
+	/// Whether the offset is large enough to report.
+	pub fn is_skewed(&self) -> bool {
+		self.offset_ms()
+			.is_some_and(|offset| offset.abs() >= CLOCK_SKEW_WARN_SECS * 1000)
+	}
+
+	/// "peer clock is ~4m ahead", or `None` while the offset is below the
+	/// threshold.
+	pub fn describe(&self) -> Option<String> {
+		if !self.is_skewed() {
+			return None;
+		}
+		self.offset_ms().map(describe_offset)
+	}
+
+	/// How far apart timestamps from this peer and ours may be and still
+	/// refer to the same moment: the offset plus half a round trip.
+	pub fn tolerance(&self) -> chrono::Duration {
+		let offset = self.offset_ms().unwrap_or(0).abs();
+		let rtt = self.rtt_ms().unwrap_or(0);
+		chrono::Duration::milliseconds(offset + rtt / 2)
+	}
+}
+
+fn median(values: impl Iterator<Item = i64>) -> Option<i64> {
+	let mut values = values.collect::<Vec<_>>();
+	if values.is_empty() {
+		return None;
+	}
+	values.sort_unstable();
+	let mid = values.len() / 2;
+	Some(if values.len().is_multiple_of(2) {
+		(values[mid - 1] + values[mid]) / 2
+	} else {
+		values[mid]
+	})
+}
+
+/// Rounded human reading of an offset, e.g. "peer clock is ~4m ahead".
+pub fn describe_offset(offset_ms: i64) -> String {
+	let secs = offset_ms.abs() / 1000;
+	let amount = if secs < 120 {
+		format!("{secs}s")
+	} else if secs < 2 * 3600 {
+		format!("{}m", (secs + 30) / 60)
+	} else if secs < 2 * 86_400 {
+		format!("{}h", (secs + 1800) / 3600)
+	} else {
+		format!("{}d", (secs + 43_200) / 86_400)
+	};
+	let direction = if offset_ms >= 0 { "ahead" } else { "behind" };
+	format!("peer clock is ~{amount} {direction}")
+}
+
+/// Persisted estimate of a peer's clock offset.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockOffsetRecord {
+	pub sampled_at: DateTime<Utc>,
+	/// Median estimate after the sample was taken.
+	pub offset_ms: i64,
+	/// The raw sample, which may be an outlier.
+	pub sample_offset_ms: i64,
+	pub rtt_ms: i64,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn at(ms: i64) -> DateTime<Utc> {
+		DateTime::from_timestamp_millis(1_700_000_000_000 + ms).unwrap()
+	}
+
+	#[test]
+	fn sample_splits_the_round_trip() {
+		// Sent at 0, received at 200: the peer stamped its reply at our 100.
+		let sample = ClockSample::new(at(0), at(200), at(240_100));
+		assert_eq!(sample.rtt_ms, 200);
+		assert_eq!(sample.offset_ms, 240_000);
+	}
+
+	#[test]
+	fn median_ignores_one_off_outliers() {
+		let mut offset = ClockOffset::default();
+		for skew in [240_000, 241_000, 3_600_000, 239_000, -500_000] {
+			assert!(offset.record(ClockSample {
+				offset_ms: skew,
+				rtt_ms: 50,
+			}));
+		}
+		assert_eq!(offset.offset_ms(), Some(240_000));
+		assert_eq!(
+			offset.describe().as_deref(),
+			Some("peer clock is ~4m ahead")
+		);
+		assert!(!offset.record(ClockSample {
+			offset_ms: 0,
+			rtt_ms: MAX_SAMPLE_RTT_MS + 1,
+		}));
+	}
+
+	#[test]
+	fn window_keeps_the_latest_samples() {
+		let mut offset = ClockOffset::default();
+		for _ in 0..MAX_SAMPLES {
+			offset.record(ClockSample {
+				offset_ms: -7_200_000,
+				rtt_ms: 10,
+			});
+		}
+		for _ in 0..MAX_SAMPLES / 2 + 1 {
+			offset.record(ClockSample {
+				offset_ms: 1_000,
+				rtt_ms: 10,
+			});
+		}
+		assert_eq!(offset.offset_ms(), Some(1_000));
+		assert!(!offset.is_skewed());
+		assert_eq!(offset.describe(), None);
+	}
+
+	#[test]
+	fn tolerance_covers_offset_and_half_the_round_trip() {
+		let mut offset = ClockOffset::default();
+		assert_eq!(offset.tolerance(), chrono::Duration::zero());
+		offset.record(ClockSample {
+			offset_ms: -90_000,
+			rtt_ms: 400,
+		});
+		assert_eq!(offset.tolerance(), chrono::Duration::milliseconds(90_200));
+	}
+
+	#[test]
+	fn offsets_are_described_in_rounded_units() {
+		assert_eq!(describe_offset(-75_000), "peer clock is ~75s behind");
+		assert_eq!(describe_offset(3 * 3_600_000), "peer clock is ~3h ahead");
+		assert_eq!(describe_offset(-5 * 86_400_000), "peer clock is ~5d behind");
+	}
+}
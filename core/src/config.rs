@@ -0,0 +1,1094 @@
+//! The node's configuration, one typed [`Config`] over every knob that used
+//! to be read from its own environment variable or setting. Each knob is
+//! taken from the first of these that sets a valid value:
+//!
+//! 1. Runtime: the settings table, for knobs with a [`Knob::setting`],
+//!    changed through [`crate::PuppyNet::set_setting`] or the UI.
+//! 2. Command line flags the daemon passes to [`init`].
+//! 3. The environment, under the names the knobs always had.
+//! 4. The config file, a JSON object of knob keys at `PUPPYNET_CONFIG` or
+//!    `~/.puppynet/config.json`.
+//! 5. The built in default.
+//!
+//! [`init`] checks the whole configuration and reports every invalid value
+//! at once. Code that reads the configuration without `init`, like tests
+//! and the CLI's local commands, logs them instead and falls back to the
+//! next layer. Thumbnails are cached in memory, so there is no cache folder
+//! to configure.
+
+use crate::activity_log::{ACTIVITY_RETENTION_SETTING, DEFAULT_ACTIVITY_RETENTION_DAYS};
+use crate::content_negotiation::{BLOCK_INDEX_MIN_MIB_SETTING, DEFAULT_BLOCK_INDEX_MIN_MIB};
+use crate::db::load_setting;
+use crate::deletion::{DEFAULT_DELETE_PROPOSAL_EXPIRY_DAYS, DELETE_PROPOSAL_EXPIRY_SETTING};
+use crate::discovered::{
+	DEFAULT_DISCOVERED_ADDRESS_TTL, DEFAULT_DISCOVERY_FLUSH_INTERVAL,
+	DISCOVERED_ADDRESS_TTL_SETTING, DISCOVERY_FLUSH_SETTING,
+};
+use crate::disk_history::{DEFAULT_LOW_SPACE_PERCENT, LOW_SPACE_PERCENT_SETTING};
+use crate::grant_cache::{DEFAULT_GRANT_CACHE_TTL, GRANT_CACHE_TTL_SETTING};
+use crate::image_decode::{DEFAULT_THUMBNAIL_DECODE_BUDGET_MIB, THUMBNAIL_DECODE_BUDGET_SETTING};
+use crate::keepalive::{
+	DEFAULT_IDLE_CONNECTION_TIMEOUT_SECS, DEFAULT_PING_INTERVAL_SECS,
+	IDLE_CONNECTION_TIMEOUT_SETTING, PING_INTERVAL_SETTING,
+};
+use crate::p2p::{DEFAULT_QUIC_LISTEN, DEFAULT_TCP_LISTEN, listen_addr_from};
+use crate::pairing_invite::{DEFAULT_PAIRING_SECRET_TTL, PAIRING_SECRET_TTL_SETTING};
+use crate::remote_ops::{DEFAULT_REMOTE_OP_IDLE, REMOTE_OP_IDLE_SETTING};
+use crate::scan::{DEFAULT_TOMBSTONE_RETENTION_DAYS, TOMBSTONE_RETENTION_SETTING};
+use crate::scan_limits::{
+	MAX_SCAN_READS, SCAN_LOW_PRIORITY_SETTING, SCAN_MAX_READ_MIB_SETTING, SCAN_MAX_READS_SETTING,
+	default_max_reads,
+};
+use crate::throughput::DEFAULT_PROGRESS_STALL_SECS;
+use crate::thumbnail_cache::{
+	DEFAULT_PREVIEW_MAX_DIMENSION, DEFAULT_THUMBNAIL_CONCURRENCY, PREVIEW_MAX_DIMENSION_SETTING,
+	THUMBNAIL_CONCURRENCY_SETTING,
+};
+use crate::transfers::DOWNLOAD_DIR_SETTING;
+use libp2p::Multiaddr;
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Read instead of `~/.puppynet/config.json` when set.
+const CONFIG_FILE_ENV: &str = "PUPPYNET_CONFIG";
+const CONFIG_FILE: &str = "config.json";
+/// Shown instead of the value of a secret knob.
+const REDACTED: &str = "********";
+pub(crate) const DEFAULT_UI_BIND: &str = "0.0.0.0:8832";
+
+/// Where a knob's value came from, lowest precedence first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigSource {
+	Default,
+	File,
+	Env,
+	Cli,
+	Runtime,
+}
+
+impl ConfigSource {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			ConfigSource::Default => "default",
+			ConfigSource::File => "file",
+			ConfigSource::Env => "env",
+			ConfigSource::Cli => "cli",
+			ConfigSource::Runtime => "runtime",
+		}
+	}
+}
+
+impl fmt::Display for ConfigSource {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(self.as_str())
+	}
+}
+
+/// What a knob's value has to look like.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KnobKind {
+	Path,
+	AbsolutePath,
+	/// A multiaddr, or "off" for no listener.
+	ListenAddr,
+	SocketAddr,
+	Text,
+	/// `true` or `false`.
+	Flag,
+	Number {
+		min: u64,
+		max: u64,
+	},
+}
+
+impl KnobKind {
+	pub fn label(&self) -> String {
+		match self {
+			KnobKind::Path => "path".to_string(),
+			KnobKind::AbsolutePath => "absolute path".to_string(),
+			KnobKind::ListenAddr => "multiaddr or off".to_string(),
+			KnobKind::SocketAddr => "host:port".to_string(),
+			KnobKind::Text => "text".to_string(),
+			KnobKind::Flag => "true or false".to_string(),
+			KnobKind::Number { min, max } => format!("number {min}..={max}"),
+		}
+	}
+
+	pub fn check(&self, value: &str) -> Result<(), String> {
+		let value = value.trim();
+		if value.is_empty() {
+			return Err("must not be empty".to_string());
+		}
+		match self {
+			KnobKind::Path | KnobKind::Text => Ok(()),
+			KnobKind::AbsolutePath if Path::new(value).is_absolute() => Ok(()),
+			KnobKind::AbsolutePath => Err("must be an absolute path".to_string()),
+			KnobKind::ListenAddr if value.eq_ignore_ascii_case("off") => Ok(()),
+			KnobKind::ListenAddr => value
+				.parse::<Multiaddr>()
+				.map(|_| ())
+				.map_err(|err| format!("not a multiaddr: {err}")),
+			KnobKind::SocketAddr => value
+				.parse::<SocketAddr>()
+				.map(|_| ())
+				.map_err(|_| format!("not an address like {DEFAULT_UI_BIND}")),
+			KnobKind::Flag => value
+				.parse::<bool>()
+				.map(|_| ())
+				.map_err(|_| "must be true or false".to_string()),
+			KnobKind::Number { min, max } => match value.parse::<u64>() {
+				Ok(number) if (*min..=*max).contains(&number) => Ok(()),
+				Ok(_) => Err(format!("must be between {min} and {max}")),
+				Err(_) => Err("must be a whole number".to_string()),
+			},
+		}
+	}
+}
+
+/// One setting of the node.
+pub struct Knob {
+	pub key: &'static str,
+	/// What it does, for `puppynet config show --describe`.
+	pub doc: &'static str,
+	pub kind: KnobKind,
+	/// Environment variables read for it, the first one set wins.
+	pub env: &'static [&'static str],
+	/// Settings table key for knobs that can change while running. The
+	/// others are read once at startup.
+	pub setting: Option<&'static str>,
+	/// Masked wherever the configuration is shown.
+	pub secret: bool,
+	default: fn() -> Option<String>,
+}
+
+impl Knob {
+	pub fn default_value(&self) -> Option<String> {
+		(self.default)()
+	}
+}
+
+fn home_dir() -> PathBuf {
+	homedir::my_home()
+		.ok()
+		.flatten()
+		.unwrap_or_else(env::temp_dir)
+}
+
+fn app_file(name: &str) -> Option<String> {
+	Some(
+		home_dir()
+			.join(".puppynet")
+			.join(name)
+			.to_string_lossy()
+			.into_owned(),
+	)
+}
+
+pub const KNOBS: &[Knob] = &[
+	Knob {
+		key: "keypair_path",
+		doc: "File holding this node's identity key, created on first start.",
+		kind: KnobKind::Path,
+		env: &["KEYPAIR"],
+		setting: None,
+		secret: false,
+		default: || Some("peer_keypair.bin".to_string()),
+	},
+	Knob {
+		key: "db_path",
+		doc: "SQLite database with the index, peers and settings.",
+		kind: KnobKind::Path,
+		env: &["DB"],
+		setting: None,
+		secret: false,
+		default: || app_file("puppynet.db"),
+	},
+	Knob {
+		key: "listen_tcp",
+		doc: "Address peers connect to over TCP, or off.",
+		kind: KnobKind::ListenAddr,
+		env: &["PUPPYNET_LISTEN_TCP"],
+		setting: None,
+		secret: false,
+		default: || Some(DEFAULT_TCP_LISTEN.to_string()),
+	},
+	Knob {
+		key: "listen_quic",
+		doc: "Address peers connect to over QUIC, or off. Builds without QUIC ignore it.",
+		kind: KnobKind::ListenAddr,
+		env: &["PUPPYNET_LISTEN_QUIC"],
+		setting: None,
+		secret: false,
+		default: || Some(DEFAULT_QUIC_LISTEN.to_string()),
+	},
+	Knob {
+		key: "ui_bind",
+		doc: "Address the web UI listens on.",
+		kind: KnobKind::SocketAddr,
+		env: &["PUPPYNET_UI_BIND"],
+		setting: None,
+		secret: false,
+		default: || Some(DEFAULT_UI_BIND.to_string()),
+	},
+	Knob {
+		key: "http_bind",
+		doc: "Address the HTTP API listens on. Unset leaves the API off.",
+		kind: KnobKind::SocketAddr,
+		env: &["PUPPYNET_HTTP_BIND"],
+		setting: None,
+		secret: false,
+		default: || None,
+	},
+	Knob {
+		key: "jwt_secret",
+		doc: "Key signing the HTTP API's login tokens. Unset uses the one in the secret store.",
+		kind: KnobKind::Text,
+		env: &["PUPPYNET_SECRET_JWT_SECRET", "JWT_SECRET"],
+		setting: None,
+		secret: true,
+		default: || None,
+	},
+	Knob {
+		key: "shell",
+		doc: "Program run for remote shell sessions.",
+		kind: KnobKind::Path,
+		env: &["SHELL"],
+		setting: None,
+		secret: false,
+		default: || Some("/bin/sh".to_string()),
+	},
+	Knob {
+		key: "inbox_dir",
+		doc: "Folder files sent to this node land in.",
+		kind: KnobKind::Path,
+		env: &["PUPPYNET_INBOX"],
+		setting: None,
+		secret: false,
+		default: || {
+			let home = homedir::my_home().ok()??;
+			Some(home.join("PuppyNet Inbox").to_string_lossy().into_owned())
+		},
+	},
+	Knob {
+		key: "content_store_dir",
+		doc: "Folder holding copies of pinned remote files.",
+		kind: KnobKind::Path,
+		env: &["PUPPYNET_STORE"],
+		setting: None,
+		secret: false,
+		default: || app_file("store"),
+	},
+	Knob {
+		key: "thumbnail_cache_dir",
+		doc: "Folder keeping generated thumbnails across restarts.",
+		kind: KnobKind::Path,
+		env: &["PUPPYNET_THUMBNAILS"],
+		setting: None,
+		secret: false,
+		default: || app_file("thumbnails"),
+	},
+	Knob {
+		key: "ui_prefs_path",
+		doc: "File keeping the web UI's preferences.",
+		kind: KnobKind::Path,
+		env: &["UI_PREFS"],
+		setting: None,
+		secret: false,
+		default: || app_file("ui_prefs.json"),
+	},
+	Knob {
+		key: "tombstone_retention_days",
+		doc: "Days deleted files stay searchable before scans purge them. 0 keeps them forever.",
+		kind: KnobKind::Number {
+			min: 0,
+			max: u32::MAX as u64,
+		},
+		env: &[],
+		setting: Some(TOMBSTONE_RETENTION_SETTING),
+		secret: false,
+		default: || Some(DEFAULT_TOMBSTONE_RETENTION_DAYS.to_string()),
+	},
+	Knob {
+		key: "activity_retention_days",
+		doc: "Days events stay on the activity timeline. 0 keeps them forever.",
+		kind: KnobKind::Number {
+			min: 0,
+			max: u32::MAX as u64,
+		},
+		env: &[],
+		setting: Some(ACTIVITY_RETENTION_SETTING),
+		secret: false,
+		default: || Some(DEFAULT_ACTIVITY_RETENTION_DAYS.to_string()),
+	},
+	Knob {
+		key: "delete_proposal_expiry_days",
+		doc: "Days a proposal to delete a file everywhere waits for each device's owner before it expires.",
+		kind: KnobKind::Number {
+			min: 1,
+			max: u32::MAX as u64,
+		},
+		env: &[],
+		setting: Some(DELETE_PROPOSAL_EXPIRY_SETTING),
+		secret: false,
+		default: || Some(DEFAULT_DELETE_PROPOSAL_EXPIRY_DAYS.to_string()),
+	},
+	Knob {
+		key: "thumbnail_concurrency",
+		doc: "Thumbnails generated at once. Read at startup.",
+		kind: KnobKind::Number { min: 1, max: 64 },
+		env: &[],
+		setting: Some(THUMBNAIL_CONCURRENCY_SETTING),
+		secret: false,
+		default: || Some(DEFAULT_THUMBNAIL_CONCURRENCY.to_string()),
+	},
+	Knob {
+		key: "thumbnail_decode_budget_mib",
+		doc: "MiB one image may take decoded for a thumbnail; larger images get no preview. Read at startup.",
+		kind: KnobKind::Number {
+			min: 1,
+			max: u32::MAX as u64,
+		},
+		env: &[],
+		setting: Some(THUMBNAIL_DECODE_BUDGET_SETTING),
+		secret: false,
+		default: || Some(DEFAULT_THUMBNAIL_DECODE_BUDGET_MIB.to_string()),
+	},
+	Knob {
+		key: "preview_max_dimension",
+		doc: "Longest edge, in pixels, of thumbnails served to peers that may only preview.",
+		kind: KnobKind::Number {
+			min: 1,
+			max: u32::MAX as u64,
+		},
+		env: &[],
+		setting: Some(PREVIEW_MAX_DIMENSION_SETTING),
+		secret: false,
+		default: || Some(DEFAULT_PREVIEW_MAX_DIMENSION.to_string()),
+	},
+	Knob {
+		key: "discovered_address_ttl_secs",
+		doc: "Seconds a discovered peer address is kept in memory without being seen again.",
+		kind: KnobKind::Number {
+			min: 1,
+			max: i64::MAX as u64 / 1000,
+		},
+		env: &[],
+		setting: Some(DISCOVERED_ADDRESS_TTL_SETTING),
+		secret: false,
+		default: || Some(DEFAULT_DISCOVERED_ADDRESS_TTL.num_seconds().to_string()),
+	},
+	Knob {
+		key: "discovery_flush_secs",
+		doc: "Seconds discovered peer addresses are collected in memory before the changes are written to the database together. Read at startup.",
+		kind: KnobKind::Number {
+			min: 1,
+			max: i64::MAX as u64 / 1000,
+		},
+		env: &[],
+		setting: Some(DISCOVERY_FLUSH_SETTING),
+		secret: false,
+		default: || Some(DEFAULT_DISCOVERY_FLUSH_INTERVAL.num_seconds().to_string()),
+	},
+	Knob {
+		key: "grant_cache_ttl_secs",
+		doc: "Seconds grants fetched from a peer are used before asking again.",
+		kind: KnobKind::Number {
+			min: 1,
+			max: i64::MAX as u64 / 1000,
+		},
+		env: &[],
+		setting: Some(GRANT_CACHE_TTL_SETTING),
+		secret: false,
+		default: || Some(DEFAULT_GRANT_CACHE_TTL.num_seconds().to_string()),
+	},
+	Knob {
+		key: "disk_alert_threshold",
+		doc: "Alert when a disk's free space drops below this percentage. 0 turns alerts off.",
+		kind: KnobKind::Number { min: 0, max: 100 },
+		env: &[],
+		setting: Some(LOW_SPACE_PERCENT_SETTING),
+		secret: false,
+		default: || Some(DEFAULT_LOW_SPACE_PERCENT.to_string()),
+	},
+	Knob {
+		key: "block_index_min_mib",
+		doc: "Scans store block hashes of files of at least this many MiB, so a peer sending a changed copy only sends the changed blocks. 0 turns it off.",
+		kind: KnobKind::Number {
+			min: 0,
+			max: u32::MAX as u64,
+		},
+		env: &[],
+		setting: Some(BLOCK_INDEX_MIN_MIB_SETTING),
+		secret: false,
+		default: || Some(DEFAULT_BLOCK_INDEX_MIN_MIB.to_string()),
+	},
+	Knob {
+		key: "scan_max_reads",
+		doc: "Files a scan reads at once. Lowering it takes effect in running scans.",
+		kind: KnobKind::Number {
+			min: 1,
+			max: MAX_SCAN_READS as u64,
+		},
+		env: &[],
+		setting: Some(SCAN_MAX_READS_SETTING),
+		secret: false,
+		default: || Some(default_max_reads().to_string()),
+	},
+	Knob {
+		key: "scan_max_read_mib",
+		doc: "MiB a scan reads per second at most, running scans included. 0 reads as fast as the disk allows.",
+		kind: KnobKind::Number {
+			min: 0,
+			max: u32::MAX as u64,
+		},
+		env: &[],
+		setting: Some(SCAN_MAX_READ_MIB_SETTING),
+		secret: false,
+		default: || Some(0.to_string()),
+	},
+	Knob {
+		key: "scan_low_priority",
+		doc: "Run scanning threads at background priority: nice and idle IO on Linux, background mode on macOS. Other platforms keep normal priority.",
+		kind: KnobKind::Flag,
+		env: &[],
+		setting: Some(SCAN_LOW_PRIORITY_SETTING),
+		secret: false,
+		default: || Some(false.to_string()),
+	},
+	Knob {
+		key: "remote_op_idle_secs",
+		doc: "Seconds a scan or update running on another peer may go without news before it is failed as abandoned.",
+		kind: KnobKind::Number {
+			min: 1,
+			max: i64::MAX as u64 / 1000,
+		},
+		env: &[],
+		setting: Some(REMOTE_OP_IDLE_SETTING),
+		secret: false,
+		default: || Some(DEFAULT_REMOTE_OP_IDLE.num_seconds().to_string()),
+	},
+	Knob {
+		key: "pairing_secret_ttl_secs",
+		doc: "Seconds a pairing string can be used before it expires. Each string pairs one device.",
+		kind: KnobKind::Number {
+			min: 1,
+			max: i64::MAX as u64 / 1000,
+		},
+		env: &[],
+		setting: Some(PAIRING_SECRET_TTL_SETTING),
+		secret: false,
+		default: || Some(DEFAULT_PAIRING_SECRET_TTL.num_seconds().to_string()),
+	},
+	Knob {
+		key: "idle_connection_timeout_secs",
+		doc: "Seconds a connection may carry no request before it closes. Connections to peers this node shares with are kept busy well inside it. Read at startup.",
+		kind: KnobKind::Number {
+			min: 1,
+			max: u32::MAX as u64,
+		},
+		env: &["PUPPYNET_IDLE_CONNECTION_TIMEOUT_SECS"],
+		setting: Some(IDLE_CONNECTION_TIMEOUT_SETTING),
+		secret: false,
+		default: || Some(DEFAULT_IDLE_CONNECTION_TIMEOUT_SECS.to_string()),
+	},
+	Knob {
+		key: "ping_interval_secs",
+		doc: "Seconds between pings on every connection, which keep NAT mappings open and notice dead links. Read at startup.",
+		kind: KnobKind::Number {
+			min: 1,
+			max: u32::MAX as u64,
+		},
+		env: &["PUPPYNET_PING_INTERVAL_SECS"],
+		setting: Some(PING_INTERVAL_SETTING),
+		secret: false,
+		default: || Some(DEFAULT_PING_INTERVAL_SECS.to_string()),
+	},
+	Knob {
+		key: "progress_stall_secs",
+		doc: "Seconds a scan, update or transfer may go without moving before its rate shows as stalled.",
+		kind: KnobKind::Number {
+			min: 1,
+			max: u32::MAX as u64,
+		},
+		env: &["PUPPYNET_PROGRESS_STALL_SECS"],
+		setting: None,
+		secret: false,
+		default: || Some(DEFAULT_PROGRESS_STALL_SECS.to_string()),
+	},
+	Knob {
+		key: "download_dir",
+		doc: "Folder downloads go to unless a peer has its own. Unset uses the Downloads folder.",
+		kind: KnobKind::AbsolutePath,
+		env: &[],
+		setting: Some(DOWNLOAD_DIR_SETTING),
+		secret: false,
+		default: || None,
+	},
+];
+
+pub fn knob(key: &str) -> Option<&'static Knob> {
+	KNOBS.iter().find(|knob| knob.key == key)
+}
+
+/// Values one source sets, by knob key.
+pub type Layer = BTreeMap<String, String>;
+
+#[derive(Clone, Debug, Default)]
+pub struct Layers {
+	pub file: Layer,
+	pub env: Layer,
+	pub cli: Layer,
+	pub runtime: Layer,
+}
+
+impl Layers {
+	fn by_precedence(&self) -> [(ConfigSource, &Layer); 4] {
+		[
+			(ConfigSource::Runtime, &self.runtime),
+			(ConfigSource::Cli, &self.cli),
+			(ConfigSource::Env, &self.env),
+			(ConfigSource::File, &self.file),
+		]
+	}
+}
+
+/// A value that was rejected. `key` is the config file's path for a file
+/// that could not be read at all.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigError {
+	pub key: String,
+	pub source: ConfigSource,
+	pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} ({}): {}", self.key, self.source, self.message)
+	}
+}
+
+/// Every problem found in the configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigErrors(pub Vec<ConfigError>);
+
+impl fmt::Display for ConfigErrors {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "invalid configuration:")?;
+		for error in &self.0 {
+			write!(f, "\n  {error}")?;
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for ConfigErrors {}
+
+/// A knob's value in effect, `None` when unset.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigValue {
+	pub key: String,
+	pub value: Option<String>,
+	pub source: ConfigSource,
+	pub secret: bool,
+}
+
+/// Every knob in [`KNOBS`] order with its value and where it came from.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EffectiveConfig {
+	pub values: Vec<ConfigValue>,
+}
+
+impl EffectiveConfig {
+	pub fn get(&self, key: &str) -> Option<&ConfigValue> {
+		self.values.iter().find(|value| value.key == key)
+	}
+
+	fn value(&self, key: &str) -> Option<&str> {
+		self.get(key)?.value.as_deref().map(str::trim)
+	}
+
+	fn parsed<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+		self.value(key)?.parse().ok()
+	}
+
+	/// Secrets replaced by a mask, for showing or sending anywhere.
+	pub fn redacted(mut self) -> Self {
+		for value in &mut self.values {
+			if value.secret && value.value.is_some() {
+				value.value = Some(REDACTED.to_string());
+			}
+		}
+		self
+	}
+}
+
+/// Picks each knob's value from the highest layer setting a valid one.
+/// Invalid values and unknown keys are skipped and all of them returned.
+pub fn resolve(layers: &Layers) -> (EffectiveConfig, Vec<ConfigError>) {
+	let mut errors = Vec::new();
+	for (source, layer) in layers.by_precedence().into_iter().rev() {
+		for key in layer.keys().filter(|key| knob(key).is_none()) {
+			errors.push(ConfigError {
+				key: key.clone(),
+				source,
+				message: "unknown setting".to_string(),
+			});
+		}
+	}
+	let mut values = Vec::with_capacity(KNOBS.len());
+	for knob in KNOBS {
+		let mut chosen = None;
+		for (source, layer) in layers.by_precedence() {
+			let Some(value) = layer.get(knob.key) else {
+				continue;
+			};
+			match knob.kind.check(value) {
+				Ok(()) if chosen.is_none() => chosen = Some((value.trim().to_string(), source)),
+				Ok(()) => {}
+				Err(message) => errors.push(ConfigError {
+					key: knob.key.to_string(),
+					source,
+					message,
+				}),
+			}
+		}
+		let (value, source) = match chosen {
+			Some((value, source)) => (Some(value), source),
+			None => (knob.default_value(), ConfigSource::Default),
+		};
+		values.push(ConfigValue {
+			key: knob.key.to_string(),
+			value,
+			source,
+			secret: knob.secret,
+		});
+	}
+	(EffectiveConfig { values }, errors)
+}
+
+/// The configuration, typed. Values were checked by [`resolve`], the
+/// defaults only step in for knobs changed in the settings table behind
+/// its back.
+#[derive(Clone)]
+pub struct Config {
+	pub keypair_path: PathBuf,
+	pub db_path: PathBuf,
+	/// `None` when turned off.
+	pub listen_tcp: Option<Multiaddr>,
+	pub listen_quic: Option<Multiaddr>,
+	pub ui_bind: SocketAddr,
+	pub http_bind: Option<SocketAddr>,
+	pub jwt_secret: Option<String>,
+	pub shell: String,
+	/// `None` without a home folder to put it in.
+	pub inbox_dir: Option<PathBuf>,
+	pub content_store_dir: PathBuf,
+	pub thumbnail_cache_dir: PathBuf,
+	pub ui_prefs_path: PathBuf,
+	pub tombstone_retention_days: u32,
+	pub activity_retention_days: u32,
+	pub delete_proposal_expiry_days: u32,
+	pub thumbnail_concurrency: usize,
+	pub thumbnail_decode_budget_mib: u64,
+	pub preview_max_dimension: u32,
+	pub discovered_address_ttl: chrono::Duration,
+	pub discovery_flush_interval: chrono::Duration,
+	pub grant_cache_ttl: chrono::Duration,
+	pub disk_alert_threshold: u8,
+	pub block_index_min_mib: u64,
+	pub scan_max_reads: u32,
+	/// 0 without a cap.
+	pub scan_max_read_mib: u32,
+	pub scan_low_priority: bool,
+	pub remote_op_idle: chrono::Duration,
+	pub pairing_secret_ttl: chrono::Duration,
+	pub idle_connection_timeout_secs: u64,
+	pub ping_interval_secs: u64,
+	pub progress_stall_secs: u64,
+	pub download_dir: Option<String>,
+}
+
+impl Config {
+	pub fn from_values(values: &EffectiveConfig) -> Self {
+		let path = |key: &str| values.value(key).map(PathBuf::from).unwrap_or_default();
+		Self {
+			keypair_path: path("keypair_path"),
+			db_path: path("db_path"),
+			listen_tcp: listen_addr_from(values.value("listen_tcp"), DEFAULT_TCP_LISTEN),
+			listen_quic: listen_addr_from(values.value("listen_quic"), DEFAULT_QUIC_LISTEN),
+			ui_bind: values
+				.parsed("ui_bind")
+				.unwrap_or_else(|| DEFAULT_UI_BIND.parse().unwrap()),
+			http_bind: values.parsed("http_bind"),
+			jwt_secret: values.value("jwt_secret").map(str::to_string),
+			shell: values.value("shell").unwrap_or("/bin/sh").to_string(),
+			inbox_dir: values.value("inbox_dir").map(PathBuf::from),
+			content_store_dir: path("content_store_dir"),
+			thumbnail_cache_dir: path("thumbnail_cache_dir"),
+			ui_prefs_path: path("ui_prefs_path"),
+			tombstone_retention_days: values
+				.parsed("tombstone_retention_days")
+				.unwrap_or(DEFAULT_TOMBSTONE_RETENTION_DAYS),
+			activity_retention_days: values
+				.parsed("activity_retention_days")
+				.unwrap_or(DEFAULT_ACTIVITY_RETENTION_DAYS),
+			delete_proposal_expiry_days: values
+				.parsed("delete_proposal_expiry_days")
+				.unwrap_or(DEFAULT_DELETE_PROPOSAL_EXPIRY_DAYS),
+			thumbnail_concurrency: values
+				.parsed("thumbnail_concurrency")
+				.unwrap_or(DEFAULT_THUMBNAIL_CONCURRENCY),
+			thumbnail_decode_budget_mib: values
+				.parsed("thumbnail_decode_budget_mib")
+				.unwrap_or(DEFAULT_THUMBNAIL_DECODE_BUDGET_MIB),
+			preview_max_dimension: values
+				.parsed("preview_max_dimension")
+				.unwrap_or(DEFAULT_PREVIEW_MAX_DIMENSION),
+			discovered_address_ttl: values
+				.parsed("discovered_address_ttl_secs")
+				.map(chrono::Duration::seconds)
+				.unwrap_or(DEFAULT_DISCOVERED_ADDRESS_TTL),
+			discovery_flush_interval: values
+				.parsed("discovery_flush_secs")
+				.map(chrono::Duration::seconds)
+				.unwrap_or(DEFAULT_DISCOVERY_FLUSH_INTERVAL),
+			grant_cache_ttl: values
+				.parsed("grant_cache_ttl_secs")
+				.map(chrono::Duration::seconds)
+				.unwrap_or(DEFAULT_GRANT_CACHE_TTL),
+			disk_alert_threshold: values
+				.parsed("disk_alert_threshold")
+				.unwrap_or(DEFAULT_LOW_SPACE_PERCENT),
+			block_index_min_mib: values
+				.parsed("block_index_min_mib")
+				.unwrap_or(DEFAULT_BLOCK_INDEX_MIN_MIB),
+			scan_max_reads: values
+				.parsed("scan_max_reads")
+				.unwrap_or_else(default_max_reads),
+			scan_max_read_mib: values.parsed("scan_max_read_mib").unwrap_or(0),
+			scan_low_priority: values.parsed("scan_low_priority").unwrap_or(false),
+			remote_op_idle: values
+				.parsed("remote_op_idle_secs")
+				.map(chrono::Duration::seconds)
+				.unwrap_or(DEFAULT_REMOTE_OP_IDLE),
+			pairing_secret_ttl: values
+				.parsed("pairing_secret_ttl_secs")
+				.map(chrono::Duration::seconds)
+				.unwrap_or(DEFAULT_PAIRING_SECRET_TTL),
+			idle_connection_timeout_secs: values
+				.parsed("idle_connection_timeout_secs")
+				.unwrap_or(DEFAULT_IDLE_CONNECTION_TIMEOUT_SECS),
+			ping_interval_secs: values
+				.parsed("ping_interval_secs")
+				.unwrap_or(DEFAULT_PING_INTERVAL_SECS),
+			progress_stall_secs: values
+				.parsed("progress_stall_secs")
+				.unwrap_or(DEFAULT_PROGRESS_STALL_SECS),
+			download_dir: values.value("download_dir").map(str::to_string),
+		}
+	}
+}
+
+fn env_value(name: &str) -> Option<String> {
+	env::var_os(name).map(|value| value.to_string_lossy().into_owned())
+}
+
+fn env_layer(read: impl Fn(&str) -> Option<String>) -> Layer {
+	KNOBS
+		.iter()
+		.filter_map(|knob| {
+			let value = knob.env.iter().find_map(|name| read(name))?;
+			Some((knob.key.to_string(), value))
+		})
+		.collect()
+}
+
+/// Knob values in a config file's JSON. Numbers and booleans are taken as
+/// written, `null` leaves a knob unset.
+fn parse_file(text: &str) -> Result<(Layer, Vec<ConfigError>), String> {
+	let object: serde_json::Map<String, serde_json::Value> =
+		serde_json::from_str(text).map_err(|err| format!("not a JSON object: {err}"))?;
+	let mut layer = Layer::new();
+	let mut errors = Vec::new();
+	for (key, value) in object {
+		let value = match value {
+			serde_json::Value::Null => continue,
+			serde_json::Value::String(value) => value,
+			serde_json::Value::Number(value) => value.to_string(),
+			serde_json::Value::Bool(value) => value.to_string(),
+			_ => {
+				errors.push(ConfigError {
+					key,
+					source: ConfigSource::File,
+					message: "must be a string or a number".to_string(),
+				});
+				continue;
+			}
+		};
+		layer.insert(key, value);
+	}
+	Ok((layer, errors))
+}
+
+fn file_layer() -> (Layer, Vec<ConfigError>) {
+	let explicit = env::var_os(CONFIG_FILE_ENV).map(PathBuf::from);
+	let path = explicit
+		.clone()
+		.unwrap_or_else(|| home_dir().join(".puppynet").join(CONFIG_FILE));
+	let file_error = |message: String| ConfigError {
+		key: path.display().to_string(),
+		source: ConfigSource::File,
+		message,
+	};
+	let text = match std::fs::read_to_string(&path) {
+		Ok(text) => text,
+		// Only a file asked for by name has to be there.
+		Err(err) if err.kind() == std::io::ErrorKind::NotFound && explicit.is_none() => {
+			return (Layer::new(), Vec::new());
+		}
+		Err(err) => return (Layer::new(), vec![file_error(err.to_string())]),
+	};
+	match parse_file(&text) {
+		Ok(parsed) => parsed,
+		Err(message) => (Layer::new(), vec![file_error(message)]),
+	}
+}
+
+fn startup_layers(cli: Layer) -> (Layers, Vec<ConfigError>) {
+	let (file, errors) = file_layer();
+	let layers = Layers {
+		file,
+		env: env_layer(env_value),
+		cli,
+		runtime: Layer::new(),
+	};
+	(layers, errors)
+}
+
+struct Startup {
+	layers: Layers,
+	config: Config,
+}
+
+static STARTUP: OnceLock<Startup> = OnceLock::new();
+
+/// Reads the config file and environment and lays the daemon's command
+/// line flags, by knob key, over them. Call before [`crate::PuppyNet::new`].
+pub fn init(cli: Layer) -> Result<Config, ConfigErrors> {
+	let (layers, mut errors) = startup_layers(cli);
+	let (values, invalid) = resolve(&layers);
+	errors.extend(invalid);
+	if !errors.is_empty() {
+		return Err(ConfigErrors(errors));
+	}
+	let config = Config::from_values(&values);
+	let startup = Startup {
+		config: config.clone(),
+		layers,
+	};
+	if STARTUP.set(startup).is_err() {
+		tracing::warn!("configuration was read before init; ignoring command line values");
+	}
+	Ok(config)
+}
+
+fn startup_state() -> &'static Startup {
+	STARTUP.get_or_init(|| {
+		let (layers, mut errors) = startup_layers(Layer::new());
+		let (values, invalid) = resolve(&layers);
+		errors.extend(invalid);
+		for error in errors {
+			tracing::warn!("ignoring invalid configuration: {error}");
+		}
+		Startup {
+			config: Config::from_values(&values),
+			layers,
+		}
+	})
+}
+
+/// The configuration without runtime settings, as read at startup.
+pub(crate) fn startup() -> &'static Config {
+	&startup_state().config
+}
+
+/// Where the startup value of `key` came from.
+pub(crate) fn startup_source(key: &str) -> ConfigSource {
+	let layers = &startup_state().layers;
+	layers
+		.by_precedence()
+		.into_iter()
+		.find(|(_, layer)| layer.contains_key(key))
+		.map(|(source, _)| source)
+		.unwrap_or(ConfigSource::Default)
+}
+
+/// Settings stored for the knobs that can change while running.
+pub(crate) fn runtime_layer(conn: &Connection) -> anyhow::Result<Layer> {
+	let mut layer = Layer::new();
+	for knob in KNOBS {
+		if let Some(setting) = knob.setting
+			&& let Some(value) = load_setting(conn, setting)?
+		{
+			layer.insert(knob.key.to_string(), value);
+		}
+	}
+	Ok(layer)
+}
+
+/// The startup configuration with `runtime` over it.
+pub(crate) fn effective(runtime: Layer) -> EffectiveConfig {
+	let mut layers = startup_state().layers.clone();
+	layers.runtime = runtime;
+	let (values, errors) = resolve(&layers);
+	for error in errors {
+		tracing::warn!("ignoring invalid configuration: {error}");
+	}
+	values
+}
+
+/// The configuration a node started now would get, with the settings in
+/// its database, for the CLI when no daemon is running. Nothing is created
+/// if there is no database yet.
+pub fn load_local() -> EffectiveConfig {
+	let runtime = Connection::open_with_flags(&startup().db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+		.ok()
+		.and_then(|conn| runtime_layer(&conn).ok())
+		.unwrap_or_default();
+	effective(runtime)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn layer(entries: &[(&str, &str)]) -> Layer {
+		entries
+			.iter()
+			.map(|(key, value)| (key.to_string(), value.to_string()))
+			.collect()
+	}
+
+	#[test]
+	fn higher_layers_win() {
+		let mut layers = Layers {
+			file: layer(&[
+				("ui_bind", "127.0.0.1:1001"),
+				("preview_max_dimension", "100"),
+			]),
+			env: layer(&[
+				("ui_bind", "127.0.0.1:1002"),
+				("preview_max_dimension", "200"),
+			]),
+			cli: layer(&[("ui_bind", "127.0.0.1:1003")]),
+			runtime: layer(&[("preview_max_dimension", "400")]),
+		};
+		let (values, errors) = resolve(&layers);
+		assert_eq!(errors, Vec::new());
+		let ui = values.get("ui_bind").unwrap();
+		assert_eq!(ui.value.as_deref(), Some("127.0.0.1:1003"));
+		assert_eq!(ui.source, ConfigSource::Cli);
+		let preview = values.get("preview_max_dimension").unwrap();
+		assert_eq!(preview.value.as_deref(), Some("400"));
+		assert_eq!(preview.source, ConfigSource::Runtime);
+		assert_eq!(
+			values.get("tombstone_retention_days").unwrap().source,
+			ConfigSource::Default
+		);
+
+		layers.cli.clear();
+		layers.runtime.clear();
+		let (values, _) = resolve(&layers);
+		assert_eq!(values.get("ui_bind").unwrap().source, ConfigSource::Env);
+		layers.env.clear();
+		let (values, _) = resolve(&layers);
+		let config = Config::from_values(&values);
+		assert_eq!(config.ui_bind, "127.0.0.1:1001".parse().unwrap());
+		assert_eq!(config.preview_max_dimension, 100);
+		assert_eq!(config.http_bind, None);
+	}
+
+	#[test]
+	fn every_invalid_value_is_reported() {
+		let layers = Layers {
+			file: layer(&[
+				("disk_alert_threshold", "20"),
+				("thumbnial_concurrency", "4"),
+			]),
+			env: layer(&[
+				("listen_tcp", "not an addr"),
+				("disk_alert_threshold", "150"),
+			]),
+			cli: layer(&[("ui_bind", "8832")]),
+			runtime: layer(&[("download_dir", "Downloads")]),
+		};
+		let (values, errors) = resolve(&layers);
+		let mut rejected = errors
+			.iter()
+			.map(|error| (error.key.as_str(), error.source))
+			.collect::<Vec<_>>();
+		rejected.sort();
+		assert_eq!(
+			rejected,
+			vec![
+				("disk_alert_threshold", ConfigSource::Env),
+				("download_dir", ConfigSource::Runtime),
+				("listen_tcp", ConfigSource::Env),
+				("thumbnial_concurrency", ConfigSource::File),
+				("ui_bind", ConfigSource::Cli),
+			]
+		);
+		// Lower layers fill in for the rejected values.
+		let threshold = values.get("disk_alert_threshold").unwrap();
+		assert_eq!(threshold.value.as_deref(), Some("20"));
+		assert_eq!(threshold.source, ConfigSource::File);
+		assert_eq!(
+			values.get("listen_tcp").unwrap().value.as_deref(),
+			Some(DEFAULT_TCP_LISTEN)
+		);
+		assert_eq!(values.get("download_dir").unwrap().value, None);
+		assert_eq!(ConfigErrors(errors).to_string().lines().count(), 6);
+	}
+
+	#[test]
+	fn environment_names_and_file_values_map_to_knobs() {
+		let env = env_layer(|name| match name {
+			"JWT_SECRET" => Some("legacy".to_string()),
+			"PUPPYNET_LISTEN_QUIC" => Some("off".to_string()),
+			_ => None,
+		});
+		assert_eq!(
+			env,
+			layer(&[("jwt_secret", "legacy"), ("listen_quic", "off")])
+		);
+
+		let (file, errors) =
+			parse_file(r#"{"tombstone_retention_days": 30, "http_bind": null, "shell": ["sh"]}"#)
+				.unwrap();
+		assert_eq!(file, layer(&[("tombstone_retention_days", "30")]));
+		assert_eq!(errors.len(), 1);
+		assert!(parse_file("[]").is_err());
+
+		let (values, _) = resolve(&Layers {
+			env,
+			file,
+			..Layers::default()
+		});
+		let config = Config::from_values(&values);
+		assert_eq!(config.listen_quic, None);
+		assert_eq!(config.tombstone_retention_days, 30);
+		let shown = values.redacted();
+		assert_eq!(
+			shown.get("jwt_secret").unwrap().value.as_deref(),
+			Some(REDACTED)
+		);
+		assert_eq!(shown.get("http_bind").unwrap().value, None);
+	}
+}
@@ -0,0 +1,596 @@
+//! Snapshots of what decides who may do what on this node: the grants
+//! given to peers, the shared folders, the settings and, when asked, the
+//! users. Taken on request and right before bulk changes, so an experiment
+//! on a production node can be rolled back. A rollback writes the
+//! snapshot back with the same save functions the setters use, in one
+//! transaction, and reports what it changed.
+
+use crate::db::{
+	INDEX_GENERATION_SETTING, delete_setting, delete_shared_folder, delete_user,
+	load_peer_permissions, load_peers, load_settings, load_shared_folders, load_users,
+	prune_config_snapshots, save_config_snapshot, save_setting, save_shared_folder, save_user,
+	write_peer_permissions,
+};
+use crate::format::abbrev_peer_id;
+use crate::identity::IDENTITY_ADOPTION_SETTING;
+use crate::reachability::REACHABILITY_SETTING;
+use crate::secrets::SECRET_STORE_SETTING;
+use crate::state::{FolderRule, Permission, User};
+use anyhow::bail;
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Format of [`ConfigState`] this build writes and understands.
+pub const CONFIG_SNAPSHOT_VERSION: u32 = 1;
+/// Unlabeled snapshots kept; labeled ones stay until deleted.
+pub(crate) const KEEP_UNLABELED_SNAPSHOTS: usize = 20;
+/// How long after a bulk change the UI offers to undo it.
+pub const UNDO_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// Settings the node keeps for itself rather than the user choosing them.
+const MACHINE_SETTINGS: &[&str] = &[
+	INDEX_GENERATION_SETTING,
+	IDENTITY_ADOPTION_SETTING,
+	REACHABILITY_SETTING,
+	SECRET_STORE_SETTING,
+];
+
+/// A user as stored, password hash included.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SnapshotUser {
+	name: String,
+	passw: String,
+}
+
+/// The configuration a snapshot holds.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ConfigState {
+	/// Grants by peer id.
+	permissions: BTreeMap<String, Vec<Permission>>,
+	shared_folders: Vec<FolderRule>,
+	settings: BTreeMap<String, String>,
+	/// `None` when the snapshot leaves users alone.
+	users: Option<Vec<SnapshotUser>>,
+}
+
+impl ConfigState {
+	pub(crate) fn includes_users(&self) -> bool {
+		self.users.is_some()
+	}
+
+	/// Reads the configuration of the node `me` as stored.
+	pub(crate) fn capture(
+		conn: &Connection,
+		me: &PeerId,
+		include_users: bool,
+	) -> anyhow::Result<Self> {
+		let permissions = load_peer_permissions(conn, me)?
+			.into_iter()
+			.filter(|(_, permissions)| !permissions.is_empty())
+			.map(|(peer, permissions)| (peer.to_string(), permissions))
+			.collect();
+		let settings = load_settings(conn)?
+			.into_iter()
+			.filter(|(key, _)| !MACHINE_SETTINGS.contains(&key.as_str()))
+			.collect();
+		let users = include_users
+			.then(|| load_users(conn))
+			.transpose()?
+			.map(|users| {
+				users
+					.into_iter()
+					.map(|user| SnapshotUser {
+						name: user.name,
+						passw: user.passw,
+					})
+					.collect()
+			});
+		Ok(Self {
+			permissions,
+			shared_folders: load_shared_folders(conn)?,
+			settings,
+			users,
+		})
+	}
+}
+
+/// A stored snapshot.
+#[derive(Clone, Debug)]
+pub(crate) struct ConfigSnapshot {
+	pub(crate) id: i64,
+	/// Set for snapshots taken on request; those are never pruned.
+	pub(crate) label: Option<String>,
+	/// What the snapshot was taken before.
+	pub(crate) reason: String,
+	pub(crate) taken_at: DateTime<Utc>,
+	pub(crate) version: u32,
+	pub(crate) config: ConfigState,
+}
+
+impl ConfigSnapshot {
+	pub(crate) fn info(&self) -> ConfigSnapshotInfo {
+		ConfigSnapshotInfo {
+			id: self.id,
+			label: self.label.clone(),
+			reason: self.reason.clone(),
+			taken_at: self.taken_at,
+			peers: self.config.permissions.len(),
+			shared_folders: self.config.shared_folders.len(),
+			settings: self.config.settings.len(),
+			users: self.config.users.as_ref().map(Vec::len),
+		}
+	}
+}
+
+/// What a snapshot holds, without the grants and password hashes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigSnapshotInfo {
+	pub id: i64,
+	pub label: Option<String>,
+	pub reason: String,
+	pub taken_at: DateTime<Utc>,
+	/// Peers with grants.
+	pub peers: usize,
+	pub shared_folders: usize,
+	pub settings: usize,
+	/// `None` when users were left out.
+	pub users: Option<usize>,
+}
+
+impl ConfigSnapshotInfo {
+	/// One line for lists, e.g. "before-vpn: 3 peers, 2 folders, 14 settings".
+	pub fn summary(&self) -> String {
+		let name = self.label.as_deref().unwrap_or(&self.reason);
+		let users = match self.users {
+			Some(users) => format!(", {users} users"),
+			None => String::new(),
+		};
+		format!(
+			"{name}: {} peers, {} folders, {} settings{users}",
+			self.peers, self.shared_folders, self.settings
+		)
+	}
+}
+
+/// The outcome of a rollback.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigRollback {
+	pub snapshot_id: i64,
+	/// Snapshot of the configuration the rollback replaced, to undo it.
+	pub previous_id: Option<i64>,
+	/// What changed, one line each.
+	pub changes: Vec<String>,
+	/// Peers and users of the snapshot that were left out because they no
+	/// longer exist.
+	pub skipped: Vec<String>,
+}
+
+/// A rollback refused because the snapshot grants access to peers or
+/// restores users that no longer exist. Forcing it skips them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotReferencesMissing {
+	pub peers: Vec<String>,
+	pub users: Vec<String>,
+}
+
+impl std::fmt::Display for SnapshotReferencesMissing {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let mut missing = self
+			.peers
+			.iter()
+			.map(|peer| format!("peer {}", abbrev_peer_id(peer)))
+			.chain(self.users.iter().map(|user| format!("user {user}")));
+		let first = missing.next().unwrap_or_default();
+		let rest = missing.collect::<Vec<_>>();
+		write!(f, "the snapshot refers to {first}")?;
+		for name in &rest {
+			write!(f, ", {name}")?;
+		}
+		write!(f, " that no longer exist; force the rollback to skip them")
+	}
+}
+
+impl std::error::Error for SnapshotReferencesMissing {}
+
+/// The last bulk change, while it can still be undone.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UndoableChange {
+	/// Snapshot taken right before the change.
+	pub snapshot_id: i64,
+	pub reason: String,
+	pub taken_at: DateTime<Utc>,
+}
+
+impl UndoableChange {
+	pub fn is_current(&self, now: DateTime<Utc>) -> bool {
+		now.signed_duration_since(self.taken_at)
+			.to_std()
+			.is_ok_and(|age| age < UNDO_WINDOW)
+	}
+}
+
+/// Saves `config` as a new snapshot and drops the oldest unlabeled ones.
+pub(crate) fn store(
+	conn: &Connection,
+	label: Option<String>,
+	reason: String,
+	config: ConfigState,
+) -> anyhow::Result<ConfigSnapshot> {
+	let mut snapshot = ConfigSnapshot {
+		id: 0,
+		label,
+		reason,
+		taken_at: Utc::now(),
+		version: CONFIG_SNAPSHOT_VERSION,
+		config,
+	};
+	snapshot.id = save_config_snapshot(conn, &snapshot)?;
+	prune_config_snapshots(conn, KEEP_UNLABELED_SNAPSHOTS)?;
+	Ok(snapshot)
+}
+
+/// `target` without what the node no longer knows, and what was left out.
+fn without_missing(
+	conn: &Connection,
+	current: &ConfigState,
+	mut target: ConfigState,
+	force: bool,
+) -> anyhow::Result<(ConfigState, Vec<String>)> {
+	let known_peers = load_peers(conn)?
+		.into_iter()
+		.map(|peer| peer.id.to_string())
+		.chain(current.permissions.keys().cloned())
+		.collect::<BTreeSet<_>>();
+	let missing = SnapshotReferencesMissing {
+		peers: target
+			.permissions
+			.keys()
+			.filter(|peer| !known_peers.contains(*peer))
+			.cloned()
+			.collect(),
+		users: match (&target.users, &current.users) {
+			(Some(target), Some(current)) => target
+				.iter()
+				.filter(|user| !current.iter().any(|existing| existing.name == user.name))
+				.map(|user| user.name.clone())
+				.collect(),
+			_ => Vec::new(),
+		},
+	};
+	if missing.peers.is_empty() && missing.users.is_empty() {
+		return Ok((target, Vec::new()));
+	}
+	if !force {
+		return Err(missing.into());
+	}
+	for peer in &missing.peers {
+		target.permissions.remove(peer);
+	}
+	if let Some(users) = &mut target.users {
+		users.retain(|user| !missing.users.contains(&user.name));
+		if users.is_empty() && !missing.users.is_empty() {
+			bail!("the snapshot would leave no users");
+		}
+	}
+	let skipped = missing
+		.peers
+		.iter()
+		.map(|peer| format!("peer {}", abbrev_peer_id(peer)))
+		.chain(missing.users.iter().map(|user| format!("user {user}")))
+		.collect();
+	Ok((target, skipped))
+}
+
+fn rule_count(permissions: Option<&Vec<Permission>>) -> usize {
+	permissions.map_or(0, Vec::len)
+}
+
+/// What writing `target` over `current` changes, one line each.
+fn describe_changes(current: &ConfigState, target: &ConfigState) -> Vec<String> {
+	let mut changes = Vec::new();
+	let peers = current
+		.permissions
+		.keys()
+		.chain(target.permissions.keys())
+		.collect::<BTreeSet<_>>();
+	for peer in peers {
+		let (now, then) = (current.permissions.get(peer), target.permissions.get(peer));
+		if now != then {
+			changes.push(format!(
+				"grants of {}: {} rules instead of {}",
+				abbrev_peer_id(peer),
+				rule_count(then),
+				rule_count(now)
+			));
+		}
+	}
+	for folder in &target.shared_folders {
+		match current
+			.shared_folders
+			.iter()
+			.find(|existing| existing.path() == folder.path())
+		{
+			None => changes.push(format!("{} shared again", folder.path().display())),
+			Some(existing) if existing != folder => {
+				changes.push(format!("access to {} restored", folder.path().display()));
+			}
+			Some(_) => {}
+		}
+	}
+	for folder in &current.shared_folders {
+		if !target
+			.shared_folders
+			.iter()
+			.any(|kept| kept.path() == folder.path())
+		{
+			changes.push(format!("{} no longer shared", folder.path().display()));
+		}
+	}
+	let keys = current
+		.settings
+		.keys()
+		.chain(target.settings.keys())
+		.collect::<BTreeSet<_>>();
+	for key in keys {
+		match (current.settings.get(key), target.settings.get(key)) {
+			(Some(_), None) => changes.push(format!("setting {key} reset to its default")),
+			(None, Some(_)) => changes.push(format!("setting {key} restored")),
+			(Some(now), Some(then)) if now != then => {
+				changes.push(format!("setting {key} restored"));
+			}
+			_ => {}
+		}
+	}
+	if let (Some(target), Some(current)) = (&target.users, &current.users) {
+		for user in current {
+			match target.iter().find(|kept| kept.name == user.name) {
+				None => changes.push(format!("user {} removed", user.name)),
+				Some(kept) if kept.passw != user.passw => {
+					changes.push(format!("password of {} restored", user.name));
+				}
+				Some(_) => {}
+			}
+		}
+	}
+	changes
+}
+
+/// Writes `target` over `current` in one transaction.
+fn write(
+	conn: &mut Connection,
+	me: &PeerId,
+	current: &ConfigState,
+	target: &ConfigState,
+) -> anyhow::Result<()> {
+	let tx = conn.transaction()?;
+	let peers = current
+		.permissions
+		.keys()
+		.chain(target.permissions.keys())
+		.collect::<BTreeSet<_>>();
+	for peer in peers {
+		let permissions = target.permissions.get(peer);
+		if current.permissions.get(peer) == permissions {
+			continue;
+		}
+		let peer = PeerId::from_str(peer)?;
+		let permissions = permissions.map_or(&[][..], Vec::as_slice);
+		write_peer_permissions(&tx, me, &peer, permissions, None)?;
+	}
+	for folder in &current.shared_folders {
+		if !target
+			.shared_folders
+			.iter()
+			.any(|kept| kept.path() == folder.path())
+		{
+			delete_shared_folder(&tx, folder.path())?;
+		}
+	}
+	for folder in &target.shared_folders {
+		if !current.shared_folders.contains(folder) {
+			save_shared_folder(&tx, folder)?;
+		}
+	}
+	for key in current.settings.keys() {
+		if !target.settings.contains_key(key) {
+			delete_setting(&tx, key)?;
+		}
+	}
+	for (key, value) in &target.settings {
+		if current.settings.get(key) != Some(value) {
+			save_setting(&tx, key, value)?;
+		}
+	}
+	if let (Some(target), Some(current)) = (&target.users, &current.users) {
+		for user in current {
+			if !target.iter().any(|kept| kept.name == user.name) {
+				delete_user(&tx, &user.name)?;
+			}
+		}
+		for user in target {
+			if !current.contains(user) {
+				save_user(
+					&tx,
+					&User {
+						name: user.name.clone(),
+						passw: user.passw.clone(),
+					},
+				)?;
+			}
+		}
+	}
+	tx.commit()?;
+	Ok(())
+}
+
+/// Writes `snapshot` back as the configuration of `me`. Refuses with
+/// [`SnapshotReferencesMissing`] when it names peers or users that are
+/// gone, unless `force` is set, which leaves them out.
+pub(crate) fn roll_back(
+	conn: &mut Connection,
+	me: &PeerId,
+	snapshot: &ConfigSnapshot,
+	force: bool,
+) -> anyhow::Result<ConfigRollback> {
+	if snapshot.version > CONFIG_SNAPSHOT_VERSION {
+		bail!(
+			"snapshot {} was taken by a newer version of PuppyNet",
+			snapshot.id
+		);
+	}
+	let current = ConfigState::capture(conn, me, snapshot.config.includes_users())?;
+	let (target, skipped) = without_missing(conn, &current, snapshot.config.clone(), force)?;
+	let changes = describe_changes(&current, &target);
+	write(conn, me, &current, &target)?;
+	Ok(ConfigRollback {
+		snapshot_id: snapshot.id,
+		previous_id: None,
+		changes,
+		skipped,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::db::{
+		load_config_snapshot, load_config_snapshots, save_peer, save_peer_permissions,
+	};
+	use crate::state::{FLAG_READ, FLAG_WRITE, Peer, Rule, TrustLevel};
+	use crate::test_util;
+	use std::path::PathBuf;
+
+	fn folder_grant(path: &str, flags: u8) -> Permission {
+		Permission::new(Rule::Folder(FolderRule::new(PathBuf::from(path), flags)))
+	}
+
+	fn take(conn: &Connection, me: &PeerId, label: Option<&str>) -> ConfigSnapshot {
+		let config = ConfigState::capture(conn, me, true).unwrap();
+		let snapshot = store(conn, label.map(str::to_string), "test".to_string(), config).unwrap();
+		load_config_snapshot(conn, snapshot.id).unwrap().unwrap()
+	}
+
+	#[test]
+	fn rolling_back_restores_the_captured_configuration() {
+		let mut conn = test_util::memory_db();
+		let me = PeerId::random();
+		let peer = PeerId::random();
+		save_peer(
+			&conn,
+			&Peer {
+				id: peer,
+				name: None,
+				trust: TrustLevel::Untrusted,
+			},
+		)
+		.unwrap();
+		save_peer_permissions(&mut conn, &me, &peer, &[folder_grant("/photos", FLAG_READ)])
+			.unwrap();
+		save_shared_folder(&conn, &FolderRule::new(PathBuf::from("/photos"), FLAG_READ)).unwrap();
+		save_setting(&conn, "backup_interval_hours", "24").unwrap();
+		save_user(
+			&conn,
+			&User {
+				name: "admin".to_string(),
+				passw: "hash".to_string(),
+			},
+		)
+		.unwrap();
+		let snapshot = take(&conn, &me, Some("before-vpn"));
+
+		save_peer_permissions(&mut conn, &me, &peer, &[]).unwrap();
+		delete_shared_folder(&conn, std::path::Path::new("/photos")).unwrap();
+		save_shared_folder(&conn, &FolderRule::new(PathBuf::from("/tmp"), FLAG_WRITE)).unwrap();
+		save_setting(&conn, "backup_interval_hours", "1").unwrap();
+		save_setting(&conn, "cors_origins", "*").unwrap();
+		save_user(
+			&conn,
+			&User {
+				name: "admin".to_string(),
+				passw: "other".to_string(),
+			},
+		)
+		.unwrap();
+
+		let rollback = roll_back(&mut conn, &me, &snapshot, false).unwrap();
+		assert_eq!(
+			ConfigState::capture(&conn, &me, true).unwrap(),
+			snapshot.config
+		);
+		assert!(rollback.skipped.is_empty());
+		assert!(
+			rollback
+				.changes
+				.contains(&"/tmp no longer shared".to_string())
+		);
+		assert!(
+			rollback
+				.changes
+				.contains(&"setting cors_origins reset to its default".to_string())
+		);
+		assert!(
+			rollback
+				.changes
+				.contains(&"password of admin restored".to_string())
+		);
+	}
+
+	#[test]
+	fn a_rollback_naming_a_forgotten_peer_is_refused_unless_forced() {
+		let mut conn = test_util::memory_db();
+		let me = PeerId::random();
+		let known = PeerId::random();
+		let forgotten = PeerId::random();
+		save_peer(
+			&conn,
+			&Peer {
+				id: known,
+				name: None,
+				trust: TrustLevel::Untrusted,
+			},
+		)
+		.unwrap();
+		save_peer_permissions(&mut conn, &me, &known, &[folder_grant("/a", FLAG_READ)]).unwrap();
+		save_peer_permissions(&mut conn, &me, &forgotten, &[folder_grant("/b", FLAG_READ)])
+			.unwrap();
+		let snapshot = take(&conn, &me, None);
+		save_peer_permissions(&mut conn, &me, &known, &[]).unwrap();
+		save_peer_permissions(&mut conn, &me, &forgotten, &[]).unwrap();
+
+		let err = roll_back(&mut conn, &me, &snapshot, false).unwrap_err();
+		let missing = err.downcast_ref::<SnapshotReferencesMissing>().unwrap();
+		assert_eq!(missing.peers, vec![forgotten.to_string()]);
+		assert!(
+			load_peer_permissions(&conn, &me)
+				.unwrap()
+				.iter()
+				.all(|(_, p)| p.is_empty())
+		);
+
+		let rollback = roll_back(&mut conn, &me, &snapshot, true).unwrap();
+		assert_eq!(rollback.skipped.len(), 1);
+		let restored = ConfigState::capture(&conn, &me, false).unwrap().permissions;
+		assert_eq!(
+			restored.keys().collect::<Vec<_>>(),
+			vec![&known.to_string()]
+		);
+	}
+
+	#[test]
+	fn pruning_keeps_labeled_snapshots() {
+		let conn = test_util::memory_db();
+		let me = PeerId::random();
+		let labeled = take(&conn, &me, Some("baseline"));
+		for _ in 0..5 {
+			take(&conn, &me, None);
+		}
+		assert_eq!(prune_config_snapshots(&conn, 2).unwrap(), 3);
+		let left = load_config_snapshots(&conn).unwrap();
+		assert_eq!(left.len(), 3);
+		assert_eq!(left.last().unwrap().id, labeled.id);
+	}
+}
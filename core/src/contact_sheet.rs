@@ -0,0 +1,471 @@
+//! Contact sheets: the images of one folder as a single JPEG grid with each
+//! file's name under its cell, for judging a remote folder at a glance. The
+//! serving node picks the images from a bounded listing, skipping those the
+//! asker may not preview, and makes the cells from the same thumbnails (and
+//! cache) as single thumbnail requests. Cells are made one at a time, so a
+//! sheet holds at most one thumbnail permit and never starves interactive
+//! requests, and the sheet is sent with however many cells finished within
+//! its time budget.
+
+use crate::natural_sort::natural_cmp;
+use crate::p2p::Thumbnail;
+use anyhow::{Result, anyhow};
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage, imageops};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub const CONTACT_SHEET_MAX_COLUMNS: u32 = 12;
+pub const CONTACT_SHEET_MIN_CELL: u32 = 48;
+pub const CONTACT_SHEET_MAX_CELL: u32 = 320;
+pub const CONTACT_SHEET_MAX_ITEMS: u32 = 100;
+/// Directory entries looked at when picking images.
+pub(crate) const CONTACT_SHEET_LISTING_LIMIT: usize = 2_000;
+/// Time a sheet may take before it is sent with the cells made so far.
+pub(crate) const CONTACT_SHEET_TIME_BUDGET: Duration = Duration::from_secs(8);
+/// Height of the caption strip under each cell.
+const CAPTION_HEIGHT: u32 = 12;
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_ADVANCE: u32 = GLYPH_WIDTH + 1;
+const BACKGROUND: Rgb<u8> = Rgb([2, 8, 7]);
+const CAPTION_COLOR: Rgb<u8> = Rgb([214, 238, 233]);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContactSheetOptions {
+	pub columns: u32,
+	/// Longest edge of each cell's image, in pixels.
+	pub cell_size: u32,
+	pub max_items: u32,
+}
+
+impl Default for ContactSheetOptions {
+	fn default() -> Self {
+		Self {
+			columns: 5,
+			cell_size: 160,
+			max_items: 40,
+		}
+	}
+}
+
+impl ContactSheetOptions {
+	/// Within the limits a serving node accepts.
+	pub fn clamped(self) -> Self {
+		Self {
+			columns: self.columns.clamp(1, CONTACT_SHEET_MAX_COLUMNS),
+			cell_size: self
+				.cell_size
+				.clamp(CONTACT_SHEET_MIN_CELL, CONTACT_SHEET_MAX_CELL),
+			max_items: self.max_items.clamp(1, CONTACT_SHEET_MAX_ITEMS),
+		}
+	}
+
+	/// Bytes the decoded sheet of `items` cells takes.
+	fn sheet_bytes(&self, items: u32) -> u64 {
+		let rows = items.div_ceil(self.columns).max(1);
+		u64::from(self.columns * self.cell_size)
+			* u64::from(rows * (self.cell_size + CAPTION_HEIGHT))
+			* 3
+	}
+
+	/// With `max_items` lowered until the sheet fits in `budget` bytes, and
+	/// the cells no larger than `max_cell`.
+	pub(crate) fn within(self, budget: u64, max_cell: u32) -> Self {
+		let mut options = self.clamped();
+		options.cell_size = options.cell_size.min(max_cell.max(CONTACT_SHEET_MIN_CELL));
+		while options.max_items > 1 && options.sheet_bytes(options.max_items) > budget {
+			options.max_items -= 1;
+		}
+		options
+	}
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ContactSheet {
+	/// The grid as a JPEG.
+	pub image: Thumbnail,
+	/// Cells on the sheet.
+	pub cells: u32,
+	/// Images picked for the sheet; more than `cells` when some could not
+	/// be made or time ran out.
+	pub images: u32,
+	/// Time ran out before every picked image had its cell.
+	pub partial: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ContactSheetResult {
+	Sheet(ContactSheet),
+	/// The folder holds no images the asker may see.
+	Empty,
+}
+
+/// Whether `path` names an image the thumbnailer decodes.
+fn is_sheet_image(path: &Path) -> bool {
+	matches!(
+		ImageFormat::from_path(path),
+		Ok(ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::Gif | ImageFormat::WebP)
+	)
+}
+
+/// Up to `max_items` images directly in `dir`, by name, of those among the
+/// first [`CONTACT_SHEET_LISTING_LIMIT`] entries that `may_show` lets on the
+/// sheet.
+pub(crate) async fn pick_images(
+	dir: &Path,
+	max_items: u32,
+	may_show: impl Fn(&Path) -> bool,
+) -> std::io::Result<Vec<PathBuf>> {
+	let mut entries = tokio::fs::read_dir(dir).await?;
+	let mut images = Vec::new();
+	let mut seen = 0;
+	while seen < CONTACT_SHEET_LISTING_LIMIT
+		&& let Some(entry) = entries.next_entry().await?
+	{
+		seen += 1;
+		let path = entry.path();
+		let is_file = entry.file_type().await.is_ok_and(|kind| kind.is_file());
+		if is_file && is_sheet_image(&path) && may_show(&path) {
+			images.push(path);
+		}
+	}
+	images.sort_by(|a, b| natural_cmp(&file_name(a), &file_name(b)));
+	images.truncate(max_items as usize);
+	Ok(images)
+}
+
+fn file_name(path: &Path) -> String {
+	path.file_name()
+		.map(|name| name.to_string_lossy().into_owned())
+		.unwrap_or_default()
+}
+
+/// Makes the sheet of `images` with `thumbnail`, which is asked for one cell
+/// at a time at `options.cell_size`. Images whose thumbnail fails are left
+/// out; once `deadline` passes the sheet is made of the cells finished.
+pub(crate) async fn build<F, Fut>(
+	images: Vec<PathBuf>,
+	options: ContactSheetOptions,
+	deadline: Instant,
+	mut thumbnail: F,
+) -> Result<ContactSheetResult>
+where
+	F: FnMut(PathBuf) -> Fut,
+	Fut: Future<Output = Result<Thumbnail>>,
+{
+	if images.is_empty() {
+		return Ok(ContactSheetResult::Empty);
+	}
+	let mut cells = Vec::new();
+	let mut partial = false;
+	for path in &images {
+		let made = tokio::time::timeout_at(deadline.into(), thumbnail(path.clone())).await;
+		let Ok(made) = made else {
+			partial = true;
+			break;
+		};
+		match made.and_then(|thumb| {
+			image::load_from_memory(&thumb.data).map_err(|err| anyhow!("bad thumbnail: {err}"))
+		}) {
+			Ok(image) => cells.push((file_name(path), image)),
+			Err(err) => tracing::debug!("left {} off a contact sheet: {err}", path.display()),
+		}
+	}
+	if cells.is_empty() {
+		if partial {
+			return Err(anyhow!("no cell of the contact sheet finished in time"));
+		}
+		return Err(anyhow!("none of the {} images could be read", images.len()));
+	}
+	let images = images.len() as u32;
+	tokio::task::spawn_blocking(move || {
+		let image = compose(&cells, options)?;
+		Ok(ContactSheetResult::Sheet(ContactSheet {
+			image,
+			cells: cells.len() as u32,
+			images,
+			partial,
+		}))
+	})
+	.await
+	.map_err(|err| anyhow!("contact sheet task panicked: {err}"))?
+}
+
+/// The grid of `cells`, each image centered in its cell with its name
+/// below, as a JPEG.
+fn compose(cells: &[(String, DynamicImage)], options: ContactSheetOptions) -> Result<Thumbnail> {
+	let cell = options.cell_size;
+	let columns = options.columns.min(cells.len() as u32).max(1);
+	let rows = (cells.len() as u32).div_ceil(columns);
+	let width = columns * cell;
+	let height = rows * (cell + CAPTION_HEIGHT);
+	let mut sheet = RgbImage::from_pixel(width, height, BACKGROUND);
+	for (i, (name, image)) in cells.iter().enumerate() {
+		let (column, row) = (i as u32 % columns, i as u32 / columns);
+		let (x, y) = (column * cell, row * (cell + CAPTION_HEIGHT));
+		let image = image.thumbnail(cell, cell).to_rgb8();
+		imageops::overlay(
+			&mut sheet,
+			&image,
+			i64::from(x + (cell - image.width()) / 2),
+			i64::from(y + (cell - image.height()) / 2),
+		);
+		draw_caption(&mut sheet, name, x, y + cell, cell);
+	}
+	let mut data = Vec::new();
+	DynamicImage::ImageRgb8(sheet)
+		.write_to(&mut Cursor::new(&mut data), ImageFormat::Jpeg)
+		.map_err(|err| anyhow!("failed to encode contact sheet: {err}"))?;
+	Ok(Thumbnail {
+		data,
+		width,
+		height,
+		mime_type: String::from("image/jpeg"),
+	})
+}
+
+/// `name` in the caption strip at `x`, `y`, shortened with `..` to fit
+/// `width`.
+fn draw_caption(sheet: &mut RgbImage, name: &str, x: u32, y: u32, width: u32) {
+	let fits = ((width.saturating_sub(4)) / GLYPH_ADVANCE) as usize;
+	let chars = name.chars().collect::<Vec<_>>();
+	let text = if chars.len() <= fits {
+		chars
+	} else {
+		let mut short = chars[..fits.saturating_sub(2)].to_vec();
+		short.extend(['.', '.']);
+		short
+	};
+	let left = x + (width - text.len() as u32 * GLYPH_ADVANCE) / 2;
+	let top = y + (CAPTION_HEIGHT - 7) / 2;
+	for (i, ch) in text.into_iter().enumerate() {
+		let rows = glyph(ch);
+		let gx = left + i as u32 * GLYPH_ADVANCE;
+		for (dy, bits) in rows.iter().enumerate() {
+			for dx in 0..GLYPH_WIDTH {
+				if bits & (1 << (GLYPH_WIDTH - 1 - dx)) != 0 {
+					sheet.put_pixel(gx + dx, top + dy as u32, CAPTION_COLOR);
+				}
+			}
+		}
+	}
+}
+
+/// Rows of the 5x7 glyph for `ch`, lowercase drawn as uppercase and
+/// anything without a glyph as `?`.
+fn glyph(ch: char) -> [u8; 7] {
+	match ch.to_ascii_uppercase() {
+		'A' => [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+		'B' => [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e],
+		'C' => [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e],
+		'D' => [0x1e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1e],
+		'E' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f],
+		'F' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10],
+		'G' => [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f],
+		'H' => [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+		'I' => [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e],
+		'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c],
+		'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+		'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f],
+		'M' => [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11],
+		'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+		'O' => [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+		'P' => [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10],
+		'Q' => [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d],
+		'R' => [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11],
+		'S' => [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e],
+		'T' => [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+		'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+		'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04],
+		'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a],
+		'X' => [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11],
+		'Y' => [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04],
+		'Z' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f],
+		'0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+		'1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+		'2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+		'3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+		'4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+		'5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+		'6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+		'7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+		'8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+		'9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+		'.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c],
+		'-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+		'_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f],
+		'(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+		')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+		' ' => [0x00; 7],
+		_ => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::state::{
+		FLAG_PREVIEW, FLAG_READ, FLAG_SEARCH, FLAG_WRITE, FolderRule, Permission, Rule, State,
+	};
+	use crate::test_util::test_dir;
+	use libp2p::PeerId;
+
+	fn jpeg(width: u32, height: u32) -> Thumbnail {
+		let image = RgbImage::from_fn(width, height, |x, y| Rgb([x as u8, y as u8, 90]));
+		let mut data = Vec::new();
+		DynamicImage::ImageRgb8(image)
+			.write_to(&mut Cursor::new(&mut data), ImageFormat::Jpeg)
+			.unwrap();
+		Thumbnail {
+			data,
+			width,
+			height,
+			mime_type: String::from("image/jpeg"),
+		}
+	}
+
+	#[tokio::test]
+	async fn the_sheet_stops_at_the_time_budget() {
+		let images = (1..=5)
+			.map(|n| PathBuf::from(format!("/photos/IMG_{n}.jpg")))
+			.collect::<Vec<_>>();
+		let options = ContactSheetOptions {
+			columns: 4,
+			cell_size: 64,
+			max_items: 5,
+		};
+		let deadline = Instant::now() + Duration::from_millis(300);
+		let result = build(images, options, deadline, |path| async move {
+			if path.ends_with("IMG_3.jpg") {
+				tokio::time::sleep(Duration::from_secs(5)).await;
+			}
+			Ok(jpeg(64, 48))
+		})
+		.await
+		.unwrap();
+		let ContactSheetResult::Sheet(sheet) = result else {
+			panic!("expected a sheet");
+		};
+		assert_eq!((sheet.cells, sheet.images, sheet.partial), (2, 5, true));
+		assert_eq!(
+			(sheet.image.width, sheet.image.height),
+			(2 * 64, 64 + CAPTION_HEIGHT)
+		);
+		let decoded = image::load_from_memory(&sheet.image.data).unwrap();
+		assert_eq!(decoded.width(), 128);
+	}
+
+	#[tokio::test]
+	async fn unreadable_images_are_left_off_and_no_images_is_empty() {
+		let images = vec![PathBuf::from("/a.jpg"), PathBuf::from("/b.jpg")];
+		let deadline = Instant::now() + CONTACT_SHEET_TIME_BUDGET;
+		let result = build(
+			images,
+			ContactSheetOptions::default(),
+			deadline,
+			|path| async move {
+				if path.ends_with("a.jpg") {
+					Err(anyhow!("truncated file"))
+				} else {
+					Ok(jpeg(32, 32))
+				}
+			},
+		)
+		.await
+		.unwrap();
+		let ContactSheetResult::Sheet(sheet) = result else {
+			panic!("expected a sheet");
+		};
+		assert_eq!((sheet.cells, sheet.images, sheet.partial), (1, 2, false));
+
+		let empty = build(
+			Vec::new(),
+			ContactSheetOptions::default(),
+			deadline,
+			|_| async { Ok(jpeg(32, 32)) },
+		)
+		.await
+		.unwrap();
+		assert!(matches!(empty, ContactSheetResult::Empty));
+	}
+
+	#[tokio::test]
+	async fn only_images_the_peer_may_preview_are_picked() {
+		let dir = test_dir("contact-sheet");
+		for name in [
+			"IMG_10.jpg",
+			"IMG_2.png",
+			"IMG_3.jpg",
+			"notes.txt",
+			"secret.jpg",
+		] {
+			std::fs::write(dir.join(name), b"x").unwrap();
+		}
+		std::fs::create_dir(dir.join("album.jpg")).unwrap();
+		let mut state = State::default();
+		let peer = PeerId::random();
+		state.add_shared_folder(FolderRule::new(
+			dir.clone(),
+			FLAG_READ | FLAG_SEARCH | FLAG_PREVIEW,
+		));
+		state.set_peer_permissions(
+			peer,
+			vec![
+				Permission::new(Rule::Folder(FolderRule::new(
+					dir.clone(),
+					FLAG_PREVIEW | FLAG_SEARCH,
+				))),
+				Permission::new(Rule::Folder(FolderRule::new(
+					dir.join("secret.jpg"),
+					FLAG_WRITE,
+				))),
+			],
+		);
+		let may_show = |path: &Path| state.thumbnail_bounds(peer, path, 64, 64, 512).is_some();
+
+		let picked = pick_images(&dir, 10, may_show).await.unwrap();
+		let names = picked
+			.iter()
+			.map(|path| file_name(path))
+			.collect::<Vec<_>>();
+		assert_eq!(names, ["IMG_2.png", "IMG_3.jpg", "IMG_10.jpg"]);
+		let picked = pick_images(&dir, 2, may_show).await.unwrap();
+		assert_eq!(picked.len(), 2);
+
+		let stranger = PeerId::random();
+		let picked = pick_images(&dir, 10, |path: &Path| {
+			state
+				.thumbnail_bounds(stranger, path, 64, 64, 512)
+				.is_some()
+		})
+		.await
+		.unwrap();
+		assert!(picked.is_empty());
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn options_shrink_to_fit_the_memory_budget() {
+		let options = ContactSheetOptions {
+			columns: 50,
+			cell_size: 4_000,
+			max_items: 1_000,
+		}
+		.clamped();
+		assert_eq!(
+			options,
+			ContactSheetOptions {
+				columns: CONTACT_SHEET_MAX_COLUMNS,
+				cell_size: CONTACT_SHEET_MAX_CELL,
+				max_items: CONTACT_SHEET_MAX_ITEMS,
+			}
+		);
+		let budget = 8 * 1024 * 1024;
+		let fitted = options.within(budget, 160);
+		assert_eq!(fitted.cell_size, 160);
+		assert!(fitted.sheet_bytes(fitted.max_items) <= budget);
+		assert!(fitted.sheet_bytes(fitted.max_items + 1) > budget);
+	}
+}
@@ -0,0 +1,776 @@
+//! Asking a receiver which files it already has before sending them, so
+//! data that is already on the other side isn't sent again. The sender
+//! asks with `HaveHashes` about the content hashes of what it is about to
+//! send, at most [`MAX_HAVE_HASHES`] per request, and the receiver answers
+//! one bit per hash from its own live files, counting only those under
+//! folders the sender may read that no pattern hides. Only possession of
+//! hashes the sender already knows is confirmed; no path is ever part of
+//! an answer.
+//! Files the receiver has are skipped.
+//!
+//! A file of several blocks that the receiver doesn't have whole is asked
+//! about again block by block with `HaveBlocks`. The receiver answers from
+//! the block hashes its scans stored, which they only do for files of at
+//! least `block_index_min_mib` MiB, off by default. Blocks it holds are
+//! copied from its own files with `WriteKnownBlock` instead of being sent.
+//! A receiver that doesn't know these requests, or fails them, gets the
+//! whole file.
+
+use crate::app::{Command, HaveBitmap};
+use crate::config;
+use crate::db::{load_setting, path_column};
+use crate::format::{SizeUnits, human_size};
+use crate::scan::{FileHash, hash_file_blocks};
+use anyhow::{Result, anyhow, bail};
+use async_trait::async_trait;
+use libp2p::PeerId;
+use rusqlite::{Connection, params};
+use std::collections::HashSet;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+/// Hashes asked about per `HaveHashes`, which keeps answers at 32 bytes.
+pub(crate) const MAX_HAVE_HASHES: usize = 256;
+/// Blocks asked about per `HaveBlocks`, a GiB of file at a time.
+pub(crate) const MAX_HAVE_BLOCKS: usize = 1024;
+/// Block hashes are taken over blocks of this size, which is also the
+/// size uploads are sent in.
+pub(crate) const BLOCK_SIZE: u64 = 1024 * 1024;
+pub(crate) const BLOCK_INDEX_MIN_MIB_SETTING: &str = "block_index_min_mib";
+/// Scans store no block hashes unless configured to.
+pub(crate) const DEFAULT_BLOCK_INDEX_MIN_MIB: u64 = 0;
+
+const HASH_LOCATIONS: &str = "SELECT path FROM file_locations \
+	WHERE node_id = ?1 AND hash = ?2 AND deleted_at IS NULL";
+const BLOCK_LOCATIONS: &str = "SELECT l.path, b.block_index FROM file_blocks b \
+	JOIN file_locations l ON l.hash = b.hash \
+	WHERE b.block_hash = ?2 AND l.node_id = ?1 AND l.deleted_at IS NULL";
+
+/// One bit per flag, the first in the lowest bit of the first byte.
+pub(crate) fn to_bitmap(flags: impl IntoIterator<Item = bool>) -> Vec<u8> {
+	let mut bitmap = Vec::new();
+	for (index, flag) in flags.into_iter().enumerate() {
+		if index % 8 == 0 {
+			bitmap.push(0);
+		}
+		if flag {
+			bitmap[index / 8] |= 1 << (index % 8);
+		}
+	}
+	bitmap
+}
+
+pub(crate) fn bit(bitmap: &[u8], index: usize) -> bool {
+	bitmap
+		.get(index / 8)
+		.is_some_and(|byte| byte & (1 << (index % 8)) != 0)
+}
+
+/// Minimum size, in MiB, of files scans store block hashes for, as
+/// configured under [`BLOCK_INDEX_MIN_MIB_SETTING`]. 0 is off.
+pub(crate) fn block_index_min_mib(conn: &Connection) -> u64 {
+	match load_setting(conn, BLOCK_INDEX_MIN_MIB_SETTING) {
+		Ok(Some(value)) => value.parse().unwrap_or_else(|err| {
+			tracing::warn!("ignoring invalid block index size {value:?}: {err}");
+			config::startup().block_index_min_mib
+		}),
+		Ok(None) => config::startup().block_index_min_mib,
+		Err(err) => {
+			tracing::error!("failed to load block index size: {err}");
+			config::startup().block_index_min_mib
+		}
+	}
+}
+
+/// Which files a scan stores block hashes for.
+pub(crate) struct BlockIndexing {
+	/// `None` when turned off.
+	min_size: Option<u64>,
+	/// Content hashes that have them already.
+	indexed: HashSet<FileHash>,
+}
+
+impl BlockIndexing {
+	pub(crate) fn load(conn: &Connection) -> Self {
+		let min_size = match block_index_min_mib(conn) {
+			0 => None,
+			mib => Some(mib.saturating_mul(1024 * 1024)),
+		};
+		let indexed = match min_size {
+			Some(_) => indexed_hashes(conn).unwrap_or_else(|err| {
+				tracing::error!("failed to load block indexed files: {err}");
+				HashSet::new()
+			}),
+			None => HashSet::new(),
+		};
+		Self { min_size, indexed }
+	}
+
+	/// Whether a file of `size` gets block hashes. A single block says
+	/// nothing its content hash doesn't.
+	pub(crate) fn wants(&self, size: u64) -> bool {
+		self.min_size
+			.is_some_and(|min| size >= min && size > BLOCK_SIZE)
+	}
+
+	/// Whether the file with `hash` should have block hashes that weren't
+	/// stored yet, like files scanned before this was turned on.
+	pub(crate) fn missing(&self, hash: Option<&FileHash>, size: u64) -> bool {
+		self.wants(size) && hash.is_none_or(|hash| !self.indexed.contains(hash))
+	}
+}
+
+fn indexed_hashes(conn: &Connection) -> rusqlite::Result<HashSet<FileHash>> {
+	let mut stmt = conn.prepare("SELECT DISTINCT hash FROM file_blocks")?;
+	let rows = stmt.query_map([], |row| row.get::<_, FileHash>(0))?;
+	rows.collect()
+}
+
+/// Answers `HaveHashes`: which of `hashes` are the content of a live file
+/// of `node_id`'s at a path `visible` lets the asker see.
+pub(crate) fn hashes_held(
+	conn: &Connection,
+	node_id: &[u8],
+	hashes: &[FileHash],
+	visible: impl Fn(&Path) -> bool,
+) -> Result<Vec<u8>> {
+	if hashes.len() > MAX_HAVE_HASHES {
+		bail!("at most {MAX_HAVE_HASHES} hashes may be asked about at once");
+	}
+	let mut stmt = conn.prepare_cached(HASH_LOCATIONS)?;
+	let mut held = Vec::with_capacity(hashes.len());
+	for hash in hashes {
+		let mut rows = stmt.query(params![node_id, hash])?;
+		let mut found = false;
+		while let Some(row) = rows.next()? {
+			if visible(&path_column(row, 0)?) {
+				found = true;
+				break;
+			}
+		}
+		held.push(found);
+	}
+	Ok(to_bitmap(held))
+}
+
+/// A live file of `node_id`'s at a path `visible` lets the asker see,
+/// holding the block that hashes to `block_hash`, and the offset of the
+/// block in it.
+pub(crate) fn find_block(
+	conn: &Connection,
+	node_id: &[u8],
+	block_hash: &FileHash,
+	visible: impl Fn(&Path) -> bool,
+) -> Result<Option<(PathBuf, u64)>> {
+	let mut stmt = conn.prepare_cached(BLOCK_LOCATIONS)?;
+	let mut rows = stmt.query(params![node_id, block_hash])?;
+	while let Some(row) = rows.next()? {
+		let path = path_column(row, 0)?;
+		if visible(&path) {
+			let index = row.get::<_, i64>(1)?;
+			return Ok(Some((path, index.max(0) as u64 * BLOCK_SIZE)));
+		}
+	}
+	Ok(None)
+}
+
+/// Answers `HaveBlocks`: which of `blocks` are a block of a live file of
+/// `node_id`'s at a path `visible` lets the asker see. Blocks of another
+/// size than the stored ones are never held.
+pub(crate) fn blocks_held(
+	conn: &Connection,
+	node_id: &[u8],
+	block_size: u64,
+	blocks: &[FileHash],
+	visible: impl Fn(&Path) -> bool,
+) -> Result<Vec<u8>> {
+	if blocks.len() > MAX_HAVE_BLOCKS {
+		bail!("at most {MAX_HAVE_BLOCKS} blocks may be asked about at once");
+	}
+	if block_size != BLOCK_SIZE {
+		return Ok(to_bitmap(blocks.iter().map(|_| false)));
+	}
+	let held = blocks
+		.iter()
+		.map(|block| Ok(find_block(conn, node_id, block, &visible)?.is_some()))
+		.collect::<Result<Vec<bool>>>()?;
+	Ok(to_bitmap(held))
+}
+
+/// Reads up to a block, stopping early only at the end of the file.
+async fn read_block<R: AsyncRead + Unpin>(
+	reader: &mut R,
+	buf: &mut [u8],
+) -> std::io::Result<usize> {
+	let mut filled = 0;
+	while filled < buf.len() {
+		let read = reader.read(&mut buf[filled..]).await?;
+		if read == 0 {
+			break;
+		}
+		filled += read;
+	}
+	Ok(filled)
+}
+
+/// The block at `offset` of `path`, as long as it still hashes to
+/// `block_hash`. The file may have changed since it was scanned.
+pub(crate) async fn read_known_block(
+	path: &Path,
+	offset: u64,
+	block_hash: &FileHash,
+) -> Result<Vec<u8>> {
+	let mut file = tokio::fs::File::open(path).await?;
+	file.seek(SeekFrom::Start(offset)).await?;
+	let mut data = vec![0u8; BLOCK_SIZE as usize];
+	let read = read_block(&mut file, &mut data).await?;
+	data.truncate(read);
+	if blake3::hash(&data).as_bytes() != block_hash {
+		bail!("block changed since it was scanned");
+	}
+	Ok(data)
+}
+
+/// What [`send_file`] did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Upload {
+	/// Written to `remote_path`, relative to the receiver's inbox. `reused`
+	/// of the `size` bytes were blocks the receiver had and copied itself.
+	Sent {
+		remote_path: String,
+		size: u64,
+		reused: u64,
+	},
+	/// The receiver already has this content, so nothing was sent.
+	AlreadyPresent { size: u64 },
+}
+
+impl Upload {
+	pub fn remote_path(&self) -> Option<&str> {
+		match self {
+			Self::Sent { remote_path, .. } => Some(remote_path),
+			Self::AlreadyPresent { .. } => None,
+		}
+	}
+
+	/// Bytes that went over the wire.
+	pub fn sent(&self) -> u64 {
+		match self {
+			Self::Sent { size, reused, .. } => size - reused,
+			Self::AlreadyPresent { .. } => 0,
+		}
+	}
+}
+
+/// What uploads didn't send because the receiver had it already.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SendSavings {
+	pub skipped_files: u64,
+	pub saved_bytes: u64,
+}
+
+impl SendSavings {
+	pub fn add(&mut self, upload: &Upload) {
+		match upload {
+			Upload::Sent { reused, .. } => self.saved_bytes += reused,
+			Upload::AlreadyPresent { size } => {
+				self.skipped_files += 1;
+				self.saved_bytes += size;
+			}
+		}
+	}
+
+	/// "skipped 14 files already present (3.20 GB saved)", or `None` when
+	/// everything was sent.
+	pub fn describe(&self) -> Option<String> {
+		let saved = human_size(self.saved_bytes, SizeUnits::Decimal);
+		match self.skipped_files {
+			0 if self.saved_bytes == 0 => None,
+			0 => Some(format!("{saved} already present, not sent")),
+			1 => Some(format!("skipped 1 file already present ({saved} saved)")),
+			files => Some(format!(
+				"skipped {files} files already present ({saved} saved)"
+			)),
+		}
+	}
+}
+
+/// Receiver access an upload needs. Implemented over the command channel;
+/// tests use an in-memory receiver.
+#[async_trait]
+pub(crate) trait UploadTarget: Send + Sync {
+	/// A [`to_bitmap`] of which of `hashes` `peer` holds.
+	async fn have_hashes(&self, peer: PeerId, hashes: Vec<FileHash>) -> Result<Vec<u8>>;
+	/// A [`to_bitmap`] of which of `blocks`, the blocks of the file with
+	/// `hash`, `peer` holds.
+	async fn have_blocks(
+		&self,
+		peer: PeerId,
+		hash: FileHash,
+		blocks: Vec<FileHash>,
+	) -> Result<Vec<u8>>;
+	/// Reserves `name` in `peer`'s inbox and returns the path to write,
+	/// relative to the inbox.
+	async fn open_inbox(&self, peer: PeerId, name: String, size: u64) -> Result<String>;
+	async fn write(&self, peer: PeerId, path: &str, offset: u64, data: Vec<u8>) -> Result<()>;
+	/// Has `peer` write its own copy of the block hashing to `block_hash`.
+	async fn write_known_block(
+		&self,
+		peer: PeerId,
+		path: &str,
+		offset: u64,
+		block_hash: FileHash,
+	) -> Result<()>;
+}
+
+#[async_trait]
+impl UploadTarget for UnboundedSender<Command> {
+	async fn have_hashes(&self, peer: PeerId, hashes: Vec<FileHash>) -> Result<Vec<u8>> {
+		let (tx, rx) = oneshot::channel();
+		self.send(Command::HaveHashes { peer, hashes, tx })
+			.map_err(|e| anyhow!("failed to send HaveHashes command: {e}"))?;
+		let HaveBitmap(bitmap) = rx
+			.await
+			.map_err(|e| anyhow!("HaveHashes response channel closed: {e}"))??;
+		Ok(bitmap)
+	}
+
+	async fn have_blocks(
+		&self,
+		peer: PeerId,
+		hash: FileHash,
+		blocks: Vec<FileHash>,
+	) -> Result<Vec<u8>> {
+		let (tx, rx) = oneshot::channel();
+		self.send(Command::HaveBlocks {
+			peer,
+			hash,
+			block_size: BLOCK_SIZE,
+			blocks,
+			tx,
+		})
+		.map_err(|e| anyhow!("failed to send HaveBlocks command: {e}"))?;
+		let HaveBitmap(bitmap) = rx
+			.await
+			.map_err(|e| anyhow!("HaveBlocks response channel closed: {e}"))??;
+		Ok(bitmap)
+	}
+
+	async fn open_inbox(&self, peer: PeerId, name: String, size: u64) -> Result<String> {
+		let (tx, rx) = oneshot::channel();
+		self.send(Command::OpenInbox {
+			peer,
+			name,
+			size,
+			tx,
+		})
+		.map_err(|e| anyhow!("failed to send OpenInbox command: {e}"))?;
+		Ok(rx
+			.await
+			.map_err(|e| anyhow!("OpenInbox response channel closed: {e}"))??
+			.path)
+	}
+
+	async fn write(&self, peer: PeerId, path: &str, offset: u64, data: Vec<u8>) -> Result<()> {
+		let (tx, rx) = oneshot::channel();
+		self.send(Command::WriteInbox {
+			peer,
+			path: path.to_string(),
+			offset,
+			data,
+			tx,
+		})
+		.map_err(|e| anyhow!("failed to send WriteInbox command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("WriteInbox response channel closed: {e}"))??;
+		Ok(())
+	}
+
+	async fn write_known_block(
+		&self,
+		peer: PeerId,
+		path: &str,
+		offset: u64,
+		block_hash: FileHash,
+	) -> Result<()> {
+		let (tx, rx) = oneshot::channel();
+		self.send(Command::WriteKnownBlock {
+			peer,
+			path: path.to_string(),
+			offset,
+			block_hash,
+			tx,
+		})
+		.map_err(|e| anyhow!("failed to send WriteKnownBlock command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("WriteKnownBlock response channel closed: {e}"))??;
+		Ok(())
+	}
+}
+
+/// Which of `hashes` `peer` has, asked in capped batches. Hashes it can't
+/// answer for, because it doesn't know the request or asking failed,
+/// count as missing.
+pub(crate) async fn files_present<T: UploadTarget + ?Sized>(
+	target: &T,
+	peer: PeerId,
+	hashes: &[FileHash],
+) -> Vec<bool> {
+	let mut present = Vec::with_capacity(hashes.len());
+	for batch in hashes.chunks(MAX_HAVE_HASHES) {
+		match target.have_hashes(peer, batch.to_vec()).await {
+			Ok(bitmap) => present.extend((0..batch.len()).map(|index| bit(&bitmap, index))),
+			Err(err) => {
+				tracing::debug!("{peer} can't say which files it has, sending them all: {err}");
+				break;
+			}
+		}
+	}
+	present.resize(hashes.len(), false);
+	present
+}
+
+/// Which of `blocks`, the blocks of the file with `hash`, `peer` has,
+/// under the same rules as [`files_present`].
+async fn blocks_present<T: UploadTarget + ?Sized>(
+	target: &T,
+	peer: PeerId,
+	hash: FileHash,
+	blocks: &[FileHash],
+) -> Vec<bool> {
+	let mut present = Vec::with_capacity(blocks.len());
+	for batch in blocks.chunks(MAX_HAVE_BLOCKS) {
+		match target.have_blocks(peer, hash, batch.to_vec()).await {
+			Ok(bitmap) => present.extend((0..batch.len()).map(|index| bit(&bitmap, index))),
+			Err(err) => {
+				tracing::debug!("{peer} can't say which blocks it has, sending them all: {err}");
+				break;
+			}
+		}
+	}
+	present.resize(blocks.len(), false);
+	present
+}
+
+/// Sends the file at `local_path` into `peer`'s inbox, reporting
+/// `(done, total)` after every block. Nothing is sent when `peer` has the
+/// file already, and blocks it has are copied from its own files.
+pub(crate) async fn send_file<T: UploadTarget + ?Sized>(
+	target: &T,
+	peer: PeerId,
+	local_path: &Path,
+	mut progress: impl FnMut(u64, u64),
+) -> Result<Upload> {
+	let name = local_path
+		.file_name()
+		.and_then(|name| name.to_str())
+		.ok_or_else(|| anyhow!("invalid file name: {}", local_path.display()))?
+		.to_string();
+	let mut file = tokio::fs::File::open(local_path).await?;
+	let size = file.metadata().await?.len();
+	let hashed = local_path.to_path_buf();
+	let (hash, blocks) = tokio::task::spawn_blocking(move || {
+		let file = std::fs::File::open(hashed)?;
+		hash_file_blocks(std::io::BufReader::new(file), true)
+	})
+	.await??;
+	if files_present(target, peer, &[hash]).await[0] {
+		return Ok(Upload::AlreadyPresent { size });
+	}
+	let held = if blocks.len() > 1 {
+		blocks_present(target, peer, hash, &blocks).await
+	} else {
+		vec![false; blocks.len()]
+	};
+	let remote_path = target.open_inbox(peer, name, size).await?;
+	let mut offset = 0u64;
+	let mut reused = 0u64;
+	let mut buf = vec![0u8; BLOCK_SIZE as usize];
+	progress(offset, size);
+	let mut index = 0;
+	while offset < size {
+		let read = read_block(&mut file, &mut buf).await?;
+		if read == 0 {
+			bail!("file shrank while sending: {}", local_path.display());
+		}
+		let data = &buf[..read];
+		// The file may have changed since it was hashed; only a block
+		// that still matches may be taken from the receiver.
+		let known = held.get(index).copied().unwrap_or(false)
+			&& blake3::hash(data).as_bytes() == &blocks[index];
+		let copied = known
+			&& match target
+				.write_known_block(peer, &remote_path, offset, blocks[index])
+				.await
+			{
+				Ok(()) => true,
+				Err(err) => {
+					tracing::debug!("{peer} could not copy block {index}, sending it: {err}");
+					false
+				}
+			};
+		if copied {
+			reused += read as u64;
+		} else {
+			target
+				.write(peer, &remote_path, offset, data.to_vec())
+				.await?;
+		}
+		offset += read as u64;
+		index += 1;
+		progress(offset, size);
+	}
+	Ok(Upload::Sent {
+		remote_path,
+		size,
+		reused,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::db::save_setting;
+	use crate::scan::scan;
+	use crate::test_util::{self, test_dir};
+	use std::collections::HashMap;
+	use std::sync::Mutex;
+
+	/// A receiver holding `files` whole and `blocks` by block hash.
+	struct FakeReceiver {
+		supported: bool,
+		files: HashSet<FileHash>,
+		blocks: HashMap<FileHash, Vec<u8>>,
+		inbox: Mutex<HashMap<String, Vec<u8>>>,
+		written: Mutex<u64>,
+	}
+
+	impl FakeReceiver {
+		fn new(supported: bool) -> Self {
+			Self {
+				supported,
+				files: HashSet::new(),
+				blocks: HashMap::new(),
+				inbox: Mutex::new(HashMap::new()),
+				written: Mutex::new(0),
+			}
+		}
+
+		fn unsupported(request: &str) -> anyhow::Error {
+			anyhow!("peer does not support the {request} request")
+		}
+
+		fn store(&self, path: &str, offset: u64, data: &[u8]) {
+			let mut inbox = self.inbox.lock().unwrap();
+			let file = inbox.get_mut(path).unwrap();
+			let start = offset as usize;
+			file[start..start + data.len()].copy_from_slice(data);
+		}
+	}
+
+	#[async_trait]
+	impl UploadTarget for FakeReceiver {
+		async fn have_hashes(&self, _peer: PeerId, hashes: Vec<FileHash>) -> Result<Vec<u8>> {
+			if !self.supported {
+				return Err(Self::unsupported("HaveHashes"));
+			}
+			assert!(hashes.len() <= MAX_HAVE_HASHES);
+			Ok(to_bitmap(
+				hashes.iter().map(|hash| self.files.contains(hash)),
+			))
+		}
+
+		async fn have_blocks(
+			&self,
+			_peer: PeerId,
+			_hash: FileHash,
+			blocks: Vec<FileHash>,
+		) -> Result<Vec<u8>> {
+			if !self.supported {
+				return Err(Self::unsupported("HaveBlocks"));
+			}
+			Ok(to_bitmap(
+				blocks.iter().map(|block| self.blocks.contains_key(block)),
+			))
+		}
+
+		async fn open_inbox(&self, _peer: PeerId, name: String, size: u64) -> Result<String> {
+			self.inbox
+				.lock()
+				.unwrap()
+				.insert(name.clone(), vec![0; size as usize]);
+			Ok(name)
+		}
+
+		async fn write(&self, _peer: PeerId, path: &str, offset: u64, data: Vec<u8>) -> Result<()> {
+			*self.written.lock().unwrap() += data.len() as u64;
+			self.store(path, offset, &data);
+			Ok(())
+		}
+
+		async fn write_known_block(
+			&self,
+			_peer: PeerId,
+			path: &str,
+			offset: u64,
+			block_hash: FileHash,
+		) -> Result<()> {
+			if !self.supported {
+				return Err(Self::unsupported("WriteKnownBlock"));
+			}
+			let data = self
+				.blocks
+				.get(&block_hash)
+				.ok_or_else(|| anyhow!("no such block"))?;
+			self.store(path, offset, data);
+			Ok(())
+		}
+	}
+
+	/// Three and a half blocks, each filled differently.
+	fn sample_data() -> Vec<u8> {
+		let len = 3 * BLOCK_SIZE as usize + BLOCK_SIZE as usize / 2;
+		(0..len)
+			.map(|i| (i / BLOCK_SIZE as usize * 31 + i % 251) as u8)
+			.collect()
+	}
+
+	#[tokio::test]
+	async fn files_the_receiver_has_are_skipped() {
+		let dir = test_dir("negotiate-skip");
+		let path = dir.join("video.mkv");
+		let data = sample_data();
+		std::fs::write(&path, &data).unwrap();
+		let mut receiver = FakeReceiver::new(true);
+		receiver.files.insert(*blake3::hash(&data).as_bytes());
+
+		let upload = send_file(&receiver, PeerId::random(), &path, |_, _| {})
+			.await
+			.unwrap();
+
+		assert_eq!(
+			upload,
+			Upload::AlreadyPresent {
+				size: data.len() as u64
+			}
+		);
+		assert!(receiver.inbox.lock().unwrap().is_empty());
+		assert_eq!(*receiver.written.lock().unwrap(), 0);
+		let mut savings = SendSavings::default();
+		savings.add(&upload);
+		savings.add(&Upload::AlreadyPresent {
+			size: 3_200_000_000,
+		});
+		assert_eq!(
+			savings.describe().as_deref(),
+			Some("skipped 2 files already present (3.20 GB saved)")
+		);
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[tokio::test]
+	async fn only_blocks_the_receiver_lacks_are_sent() {
+		let dir = test_dir("negotiate-blocks");
+		let path = dir.join("disk.img");
+		let data = sample_data();
+		std::fs::write(&path, &data).unwrap();
+		let mut receiver = FakeReceiver::new(true);
+		for index in [0, 2] {
+			let start = index * BLOCK_SIZE as usize;
+			let block = data[start..start + BLOCK_SIZE as usize].to_vec();
+			receiver
+				.blocks
+				.insert(*blake3::hash(&block).as_bytes(), block);
+		}
+
+		let upload = send_file(&receiver, PeerId::random(), &path, |_, _| {})
+			.await
+			.unwrap();
+
+		assert_eq!(
+			upload,
+			Upload::Sent {
+				remote_path: String::from("disk.img"),
+				size: data.len() as u64,
+				reused: 2 * BLOCK_SIZE,
+			}
+		);
+		assert_eq!(upload.sent(), data.len() as u64 - 2 * BLOCK_SIZE);
+		assert_eq!(*receiver.written.lock().unwrap(), upload.sent());
+		assert_eq!(receiver.inbox.lock().unwrap()["disk.img"], data);
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[tokio::test]
+	async fn receivers_without_negotiation_get_the_whole_file() {
+		let dir = test_dir("negotiate-unsupported");
+		let path = dir.join("notes.txt");
+		let data = sample_data();
+		std::fs::write(&path, &data).unwrap();
+		let receiver = FakeReceiver::new(false);
+
+		let hashes = vec![[7; 32]; MAX_HAVE_HASHES + 1];
+		assert_eq!(
+			files_present(&receiver, PeerId::random(), &hashes).await,
+			vec![false; hashes.len()]
+		);
+		let upload = send_file(&receiver, PeerId::random(), &path, |_, _| {})
+			.await
+			.unwrap();
+
+		assert_eq!(upload.sent(), data.len() as u64);
+		assert_eq!(receiver.inbox.lock().unwrap()["notes.txt"], data);
+		let mut savings = SendSavings::default();
+		savings.add(&upload);
+		assert_eq!(savings.describe(), None);
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn scans_store_block_hashes_once_opted_in() {
+		let dir = test_dir("negotiate-index");
+		let data = sample_data();
+		std::fs::write(dir.join("big.bin"), &data).unwrap();
+		std::fs::write(dir.join("small.txt"), b"hello").unwrap();
+		let mut conn = test_util::memory_db();
+		let node_id = [1u8; 16];
+		scan(&node_id, &dir, &mut conn).unwrap();
+		let blocks = |conn: &Connection| -> i64 {
+			conn.query_row("SELECT COUNT(*) FROM file_blocks", [], |row| row.get(0))
+				.unwrap()
+		};
+		assert_eq!(blocks(&conn), 0);
+
+		save_setting(&conn, BLOCK_INDEX_MIN_MIB_SETTING, "1").unwrap();
+		scan(&node_id, &dir, &mut conn).unwrap();
+		assert_eq!(blocks(&conn), 4);
+
+		let file = *blake3::hash(&data).as_bytes();
+		let small = *blake3::hash(b"hello").as_bytes();
+		let held = hashes_held(&conn, &node_id, &[file, [9; 32], small], |_| true).unwrap();
+		assert_eq!(held, to_bitmap([true, false, true]));
+		assert_eq!(
+			hashes_held(&conn, &[2u8; 16], &[file], |_| true).unwrap(),
+			to_bitmap([false])
+		);
+
+		let third =
+			*blake3::hash(&data[2 * BLOCK_SIZE as usize..3 * BLOCK_SIZE as usize]).as_bytes();
+		let held = blocks_held(&conn, &node_id, BLOCK_SIZE, &[[9; 32], third], |_| true).unwrap();
+		assert_eq!(held, to_bitmap([false, true]));
+		assert_eq!(
+			blocks_held(&conn, &node_id, BLOCK_SIZE / 2, &[third], |_| true).unwrap(),
+			to_bitmap([false])
+		);
+		let (path, offset) = find_block(&conn, &node_id, &third, |_| true)
+			.unwrap()
+			.unwrap();
+		assert_eq!(path, dir.canonicalize().unwrap().join("big.bin"));
+		assert_eq!(offset, 2 * BLOCK_SIZE);
+		assert!(hashes_held(&conn, &node_id, &vec![file; MAX_HAVE_HASHES + 1], |_| true).is_err());
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -0,0 +1,317 @@
+use crate::config;
+use crate::db::{
+	content_store_add_ref, content_store_contains, content_store_release,
+	content_store_remove_unreferenced, content_store_unreferenced,
+};
+use crate::db_pool::Db;
+use crate::scan::{FileHash, hash_file};
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use rusqlite::Connection as SqliteConnection;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A blob in the content store, addressed by its blake3 hash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContentRef {
+	pub hash: FileHash,
+	pub size: u64,
+}
+
+impl ContentRef {
+	pub fn hex(&self) -> String {
+		hash_hex(&self.hash)
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StoreMode {
+	/// Leave the source in place.
+	Copy,
+	/// Consume the source; renamed into the store when on the same disk.
+	Move,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct StoreGcReport {
+	pub removed: usize,
+	pub freed_bytes: u64,
+	/// Unreferenced blobs left alone because they were being read or stored.
+	pub in_use: usize,
+}
+
+fn hash_hex(hash: &FileHash) -> String {
+	hash.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Content-addressed blob store under `<dir>/<xx>/<hash>`. Blobs are written
+/// to `<dir>/tmp` and renamed into place, so concurrent stores of the same
+/// content never expose a partial file. Reference counts live in the
+/// database; in-flight stores and materializations pin their hash so the
+/// GC leaves them alone.
+pub(crate) struct ContentStore {
+	dir: PathBuf,
+	db: Arc<Db>,
+	pins: Mutex<HashMap<FileHash, usize>>,
+}
+
+struct Pin<'a> {
+	store: &'a ContentStore,
+	hash: FileHash,
+}
+
+impl Drop for Pin<'_> {
+	fn drop(&mut self) {
+		let mut pins = self.store.pins.lock().unwrap();
+		if let Some(count) = pins.get_mut(&self.hash) {
+			*count -= 1;
+			if *count == 0 {
+				pins.remove(&self.hash);
+			}
+		}
+	}
+}
+
+impl ContentStore {
+	pub(crate) fn new(dir: PathBuf, db: Arc<Db>) -> Self {
+		Self {
+			dir,
+			db,
+			pins: Mutex::new(HashMap::new()),
+		}
+	}
+
+	pub(crate) fn default_dir() -> PathBuf {
+		config::startup().content_store_dir.clone()
+	}
+
+	fn blob_path(&self, hash: &FileHash) -> PathBuf {
+		let hex = hash_hex(hash);
+		self.dir.join(&hex[..2]).join(hex)
+	}
+
+	fn pin(&self, hash: FileHash) -> Pin<'_> {
+		*self.pins.lock().unwrap().entry(hash).or_insert(0) += 1;
+		Pin { store: self, hash }
+	}
+
+	fn lock_db(&self) -> Result<std::sync::MutexGuard<'_, SqliteConnection>> {
+		self.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))
+	}
+
+	/// Whether a blob with this hash is already stored locally.
+	pub(crate) fn contains(&self, hash: &[u8]) -> Result<bool> {
+		let Ok(hash) = FileHash::try_from(hash) else {
+			return Ok(false);
+		};
+		Ok(content_store_contains(&*self.lock_db()?, &hash)? && self.blob_path(&hash).is_file())
+	}
+
+	/// Which of `hashes` are stored locally, under one database lock.
+	pub(crate) fn contained(&self, hashes: &[FileHash]) -> Result<HashSet<FileHash>> {
+		let conn = self.lock_db()?;
+		let mut stored = HashSet::new();
+		for hash in hashes {
+			if content_store_contains(&conn, hash)? && self.blob_path(hash).is_file() {
+				stored.insert(*hash);
+			}
+		}
+		Ok(stored)
+	}
+
+	/// Writes a temp copy of `source` (or takes it over in `Move` mode) and
+	/// renames it into place.
+	fn write_blob(&self, source: &Path, blob: &Path, mode: StoreMode) -> Result<()> {
+		let tmp_dir = self.dir.join("tmp");
+		fs::create_dir_all(&tmp_dir)?;
+		fs::create_dir_all(blob.parent().unwrap_or(&self.dir))?;
+		let tmp = tmp_dir.join(uuid::Uuid::new_v4().to_string());
+		let moved = mode == StoreMode::Move && fs::rename(source, &tmp).is_ok();
+		if !moved {
+			fs::copy(source, &tmp)?;
+		}
+		let mut permissions = fs::metadata(&tmp)?.permissions();
+		permissions.set_readonly(true);
+		fs::set_permissions(&tmp, permissions)?;
+		if let Err(err) = fs::rename(&tmp, blob) {
+			// Another store of the same content won the race.
+			let _ = fs::remove_file(&tmp);
+			if !blob.is_file() {
+				return Err(err.into());
+			}
+		}
+		if mode == StoreMode::Move && !moved {
+			fs::remove_file(source)?;
+		}
+		Ok(())
+	}
+
+	/// Stores `source` and takes a reference on it. Identical content is
+	/// kept once no matter how often it is stored.
+	pub(crate) fn store_file(
+		&self,
+		source: &Path,
+		mode: StoreMode,
+		now: DateTime<Utc>,
+	) -> Result<ContentRef> {
+		let size = fs::metadata(source)?.len();
+		let hash = hash_file(fs::File::open(source)?)?;
+		let _pin = self.pin(hash);
+		let blob = self.blob_path(&hash);
+		if blob.is_file() {
+			if mode == StoreMode::Move {
+				fs::remove_file(source)?;
+			}
+		} else {
+			self.write_blob(source, &blob, mode)?;
+		}
+		content_store_add_ref(&*self.lock_db()?, &hash, size, now)?;
+		Ok(ContentRef { hash, size })
+	}
+
+	/// Hard links the blob to `dest`, copying when linking is not possible.
+	/// Linked files share the read-only blob; copies are writable.
+	pub(crate) fn materialize(&self, content: &ContentRef, dest: &Path) -> Result<()> {
+		let _pin = self.pin(content.hash);
+		let blob = self.blob_path(&content.hash);
+		if !blob.is_file() {
+			bail!("content {} is not in the store", content.hex());
+		}
+		if dest.exists() {
+			bail!("{} already exists", dest.display());
+		}
+		if let Some(parent) = dest.parent() {
+			fs::create_dir_all(parent)?;
+		}
+		if fs::hard_link(&blob, dest).is_err() {
+			fs::copy(&blob, dest)?;
+			let mut permissions = fs::metadata(dest)?.permissions();
+			#[allow(clippy::permissions_set_readonly_false)]
+			permissions.set_readonly(false);
+			fs::set_permissions(dest, permissions)?;
+		}
+		Ok(())
+	}
+
+	/// Stores `path` and swaps it for a hard link to the blob, so a file
+	/// that is already stored stops taking extra space. Keeps the original
+	/// when the blob cannot be linked.
+	pub(crate) fn dedup_in_place(&self, path: &Path, now: DateTime<Utc>) -> Result<ContentRef> {
+		let content = self.store_file(path, StoreMode::Copy, now)?;
+		let _pin = self.pin(content.hash);
+		let link = path.with_file_name(format!(".{}.puppynet-link", uuid::Uuid::new_v4()));
+		if fs::hard_link(self.blob_path(&content.hash), &link).is_ok()
+			&& let Err(err) = fs::rename(&link, path)
+		{
+			let _ = fs::remove_file(&link);
+			return Err(err.into());
+		}
+		Ok(content)
+	}
+
+	/// Drops a reference taken by `store_file`; returns the remaining count.
+	pub(crate) fn release(&self, hash: &FileHash) -> Result<u64> {
+		content_store_release(&*self.lock_db()?, hash)?
+			.ok_or_else(|| anyhow!("content {} is not in the store", hash_hex(hash)))
+	}
+
+	/// Removes unreferenced blobs that nobody is currently storing or reading.
+	pub(crate) fn gc(&self) -> Result<StoreGcReport> {
+		let candidates = content_store_unreferenced(&*self.lock_db()?)?;
+		let mut report = StoreGcReport::default();
+		// Holding the pin map blocks new pins until this pass is done.
+		let pins = self.pins.lock().unwrap();
+		for hash in candidates {
+			if pins.contains_key(&hash) {
+				report.in_use += 1;
+				continue;
+			}
+			if !content_store_remove_unreferenced(&*self.lock_db()?, &hash)? {
+				continue;
+			}
+			let blob = self.blob_path(&hash);
+			let size = fs::metadata(&blob).map(|meta| meta.len()).unwrap_or(0);
+			let mut permissions = match fs::metadata(&blob) {
+				Ok(meta) => meta.permissions(),
+				Err(_) => continue,
+			};
+			#[allow(clippy::permissions_set_readonly_false)]
+			permissions.set_readonly(false);
+			let _ = fs::set_permissions(&blob, permissions);
+			match fs::remove_file(&blob) {
+				Ok(()) => {
+					report.removed += 1;
+					report.freed_bytes += size;
+				}
+				Err(err) => tracing::warn!("failed to remove blob {}: {err}", blob.display()),
+			}
+		}
+		Ok(report)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util;
+
+	fn test_store(name: &str) -> (ContentStore, PathBuf) {
+		let now = std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.unwrap()
+			.as_nanos();
+		let dir =
+			std::env::temp_dir().join(format!("puppynet-{name}-{}-{now}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let conn = test_util::memory_db();
+		let store = ContentStore::new(dir.join("store"), Arc::new(Db::single(conn)));
+		(store, dir)
+	}
+
+	#[test]
+	fn identical_files_are_stored_once_and_collected_when_released() {
+		let (store, dir) = test_store("content-store");
+		let first = dir.join("a.bin");
+		let second = dir.join("b.bin");
+		fs::write(&first, b"same bytes").unwrap();
+		fs::write(&second, b"same bytes").unwrap();
+
+		let a = store
+			.store_file(&first, StoreMode::Copy, Utc::now())
+			.unwrap();
+		let b = store
+			.store_file(&second, StoreMode::Move, Utc::now())
+			.unwrap();
+		assert_eq!(a, b);
+		assert!(first.exists());
+		assert!(!second.exists());
+		assert!(store.contains(&a.hash).unwrap());
+		assert_eq!(
+			store.contained(&[a.hash, [0; 32]]).unwrap(),
+			HashSet::from([a.hash])
+		);
+
+		let out = dir.join("out").join("copy.bin");
+		store.materialize(&a, &out).unwrap();
+		assert_eq!(fs::read(&out).unwrap(), b"same bytes");
+		assert!(store.materialize(&a, &out).is_err());
+
+		assert_eq!(store.release(&a.hash).unwrap(), 1);
+		assert_eq!(store.gc().unwrap(), StoreGcReport::default());
+		assert_eq!(store.release(&a.hash).unwrap(), 0);
+
+		let pinned = store.pin(a.hash);
+		assert_eq!(store.gc().unwrap().in_use, 1);
+		drop(pinned);
+		let report = store.gc().unwrap();
+		assert_eq!(report.removed, 1);
+		assert_eq!(report.freed_bytes, 10);
+		assert!(!store.contains(&a.hash).unwrap());
+		assert_eq!(fs::read(&out).unwrap(), b"same bytes");
+	}
+}
@@ -0,0 +1,220 @@
+//! CORS for the HTTP API. The default is same-origin only: no origin is
+//! listed, so cross-origin requests get no CORS headers and the browser
+//! refuses them, while the embedded web UI, served from the API's own
+//! origin, needs none and works as before.
+//!
+//! A separate frontend is let in by listing its origin, either in the
+//! stored settings or with `PUPPYNET_CORS_ORIGINS` (comma separated, or
+//! `*` for any origin), which replaces the stored list at startup. Listed
+//! origins are echoed back rather than answered with `*`, since browsers
+//! reject a wildcard on credentialed requests. Any origin is only ever
+//! answered with `*` and without credentials: echoing every origin with
+//! credentials would let any site call the API as the signed-in user, so
+//! credentials need a list of origins.
+
+use anyhow::bail;
+#[cfg(feature = "http-api")]
+use hyper::header::{
+	ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+	ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, HeaderValue, ORIGIN, VARY,
+};
+#[cfg(feature = "http-api")]
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+pub(crate) const CORS_SETTING: &str = "cors";
+const CORS_ORIGINS_ENV: &str = "PUPPYNET_CORS_ORIGINS";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AllowedOrigins {
+	Any,
+	/// Exact origins such as `https://app.example.com`. Empty means same
+	/// origin only.
+	List(Vec<String>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorsSettings {
+	pub allowed_origins: AllowedOrigins,
+	pub allowed_methods: Vec<String>,
+	pub allowed_headers: Vec<String>,
+	/// Lets cookies and `Authorization` ride along on cross-origin requests.
+	pub allow_credentials: bool,
+	/// How long browsers may cache a preflight answer.
+	pub max_age_secs: u64,
+}
+
+impl Default for CorsSettings {
+	fn default() -> Self {
+		Self {
+			allowed_origins: AllowedOrigins::List(Vec::new()),
+			allowed_methods: ["GET", "POST", "PUT", "DELETE", "OPTIONS"]
+				.map(String::from)
+				.to_vec(),
+			allowed_headers: ["content-type", "authorization"].map(String::from).to_vec(),
+			allow_credentials: true,
+			max_age_secs: 600,
+		}
+	}
+}
+
+fn normalize_origin(origin: &str) -> String {
+	origin.trim().trim_end_matches('/').to_ascii_lowercase()
+}
+
+fn parse_origins(value: &str) -> AllowedOrigins {
+	let value = value.trim();
+	if value == "*" || value.eq_ignore_ascii_case("any") {
+		return AllowedOrigins::Any;
+	}
+	AllowedOrigins::List(
+		value
+			.split(',')
+			.map(normalize_origin)
+			.filter(|origin| !origin.is_empty())
+			.collect(),
+	)
+}
+
+#[cfg(feature = "http-api")]
+fn header_value(values: &[String]) -> Option<HeaderValue> {
+	HeaderValue::from_str(&values.join(",")).ok()
+}
+
+impl CorsSettings {
+	/// These settings with `PUPPYNET_CORS_ORIGINS` applied, if it is set.
+	/// Any origin turns credentials off.
+	pub(crate) fn with_env(mut self) -> Self {
+		if let Ok(value) = std::env::var(CORS_ORIGINS_ENV) {
+			self.allowed_origins = parse_origins(&value);
+			if self.allowed_origins == AllowedOrigins::Any {
+				self.allow_credentials = false;
+			}
+		}
+		self
+	}
+
+	pub(crate) fn validate(&self) -> anyhow::Result<()> {
+		if self.allowed_origins == AllowedOrigins::Any && self.allow_credentials {
+			bail!("credentials need a list of allowed origins, not any origin");
+		}
+		if let AllowedOrigins::List(origins) = &self.allowed_origins {
+			for origin in origins {
+				let Some((scheme, host)) = origin.split_once("://") else {
+					bail!("origin {origin} must include a scheme, like https://{origin}");
+				};
+				if scheme.is_empty() || host.is_empty() || host.contains('/') {
+					bail!("origin {origin} must be scheme://host[:port] without a path");
+				}
+			}
+		}
+		if self.allowed_methods.is_empty() {
+			bail!("allow at least one method");
+		}
+		Ok(())
+	}
+
+	#[cfg(feature = "http-api")]
+	fn allows(&self, origin: &str) -> bool {
+		match &self.allowed_origins {
+			AllowedOrigins::Any => true,
+			AllowedOrigins::List(origins) => {
+				let origin = normalize_origin(origin);
+				origins
+					.iter()
+					.any(|allowed| normalize_origin(allowed) == origin)
+			}
+		}
+	}
+
+	/// Adds the CORS headers for a request from `origin`. Disallowed or
+	/// missing origins get none, only `Vary: Origin` so caches keep the
+	/// answers for different origins apart. Any origin gets `*` without
+	/// credentials, even from stored settings that ask for them.
+	#[cfg(feature = "http-api")]
+	pub(crate) fn apply(&self, mut resp: Response<Body>, origin: Option<&str>) -> Response<Body> {
+		resp.headers_mut()
+			.append(VARY, HeaderValue::from_static("Origin"));
+		let Some(origin) = origin.filter(|origin| self.allows(origin)) else {
+			return resp;
+		};
+		let any = self.allowed_origins == AllowedOrigins::Any;
+		let allow_origin = if any {
+			Some(HeaderValue::from_static("*"))
+		} else {
+			HeaderValue::from_str(origin).ok()
+		};
+		let Some(allow_origin) = allow_origin else {
+			return resp;
+		};
+		let headers = resp.headers_mut();
+		headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+		if self.allow_credentials && !any {
+			headers.insert(
+				ACCESS_CONTROL_ALLOW_CREDENTIALS,
+				HeaderValue::from_static("true"),
+			);
+		}
+		resp
+	}
+
+	/// Answers an `OPTIONS` request with 204 before routing or auth, so
+	/// preflights never depend on the path existing or a session.
+	#[cfg(feature = "http-api")]
+	pub(crate) fn preflight(&self, req: &Request<Body>) -> Option<Response<Body>> {
+		if req.method() != Method::OPTIONS {
+			return None;
+		}
+		let origin = req.headers().get(ORIGIN).and_then(|v| v.to_str().ok());
+		let resp = Response::builder()
+			.status(StatusCode::NO_CONTENT)
+			.body(Body::empty())
+			.unwrap();
+		let mut resp = self.apply(resp, origin);
+		if resp.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN) {
+			let headers = resp.headers_mut();
+			if let Some(methods) = header_value(&self.allowed_methods) {
+				headers.insert(ACCESS_CONTROL_ALLOW_METHODS, methods);
+			}
+			if let Some(allowed) = header_value(&self.allowed_headers) {
+				headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allowed);
+			}
+			headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(self.max_age_secs));
+		}
+		Some(resp)
+	}
+}
+
+#[cfg(all(test, feature = "http-api"))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn origins_env_value_parses_lists_and_wildcards() {
+		assert_eq!(parse_origins(" * "), AllowedOrigins::Any);
+		assert_eq!(
+			parse_origins("https://a.example.com/, http://localhost:5173,"),
+			AllowedOrigins::List(vec![
+				String::from("https://a.example.com"),
+				String::from("http://localhost:5173"),
+			])
+		);
+		let frontend = CorsSettings {
+			allowed_origins: AllowedOrigins::List(vec![String::from("https://app.example.com")]),
+			..CorsSettings::default()
+		};
+		assert!(frontend.validate().is_ok());
+		let with_path = CorsSettings {
+			allowed_origins: AllowedOrigins::List(vec![String::from("https://a.example.com/app")]),
+			..CorsSettings::default()
+		};
+		assert!(with_path.validate().is_err());
+		let any_with_credentials = CorsSettings {
+			allowed_origins: AllowedOrigins::Any,
+			..CorsSettings::default()
+		};
+		assert!(any_with_credentials.validate().is_err());
+	}
+}
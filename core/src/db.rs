@@ -1,20 +1,59 @@
-use std::env;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use anyhow::{anyhow, bail};
 use chrono::DateTime;
+use chrono::NaiveDate;
 use chrono::Utc;
 use libp2p::PeerId;
 use rusqlite::Connection;
+use rusqlite::Row;
 use rusqlite::ToSql;
 use rusqlite::params;
+use rusqlite::types::{Value, ValueRef};
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
 
+use crate::access_volume::AccessVolume;
+use crate::activity_log::{
+	ActivityEntry, ActivityEvent, ActivityEventKind, ActivityFilter, DEDUPE_WINDOW,
+};
+use crate::backup::{BackupKind, BackupRun};
+use crate::checksum_manifest::{ChecksumAlgorithm, ClaimStatus, HashMismatch};
+use crate::clock_skew::ClockOffsetRecord;
+use crate::config::{self, ConfigSource};
+use crate::config_snapshot::ConfigSnapshot;
+use crate::deletion::{
+	DeleteOutcome, DeleteProposal, DeletionStatus, ProposalHolder, ReceivedProposal,
+};
+use crate::disk_history::DiskSample;
+use crate::login_guard::{FailedLoginGroup, LoginAttempt, LoginOutcome};
+use crate::media_metadata::{MediaMetadata, PendingMedia, is_media_mime};
+use crate::natural_sort::natural_cmp;
+use crate::outbox::{OutboxRule, OutboxStatus};
+use crate::p2p::{WirePath, path_bytes, path_from_bytes};
+use crate::pagination::{CursorPage, PageCursor, order_clause};
+use crate::pins::{PinOptions, PinStatus, PinSyncReport, PinnedFile};
+use crate::protocol_stats::{PROTOCOL_STATS_RETENTION_DAYS, ProtocolCounts, ProtocolDay};
+use crate::replication::{
+	IndexChanges, IndexDelta, ReplicatedEntry, ReplicatedLocation, ReplicationRole,
+	ReplicationStatus,
+};
+use crate::review::{PeerTrust, PendingReview, ReviewDecision};
 use crate::scan::FileHash;
 use crate::scan::FileLocation;
-use crate::state::{DiscoveredPeer, FolderRule, Peer, Permission, Rule, User};
+use crate::scan::{ScanChangeKind, ScanResult};
+use crate::state::{
+	DiscoveredPeer, FolderRule, Peer, Permission, PermissionConflict, PermissionSet, Rule,
+	TrustLevel, User,
+};
+use crate::transfers::{TRANSFER_RETENTION, Transfer, TransferDirection, TransferStatus};
+use crate::wake::WakeTarget;
+
+mod search_query;
+
+pub use search_query::{MAX_SEARCH_PAGE_SIZE, SearchQueryError};
+pub(crate) use search_query::{SearchQuery, check_offset, location_scope};
 
 pub type NodeID = [u8; 16];
 
@@ -184,6 +223,577 @@ const MIGRATIONS: &[Migration] = &[
 			);
 		",
 	},
+	Migration {
+		id: 20250314,
+		name: "permission_outbox",
+		sql: r"
+			create table if not exists permission_outbox (
+				target_peer blob primary key,
+				permissions text not null,
+				queued_at integer not null
+			);
+			create index if not exists permission_outbox_queued_at on permission_outbox(queued_at);
+		",
+	},
+	Migration {
+		id: 20250315,
+		name: "scan_runs",
+		sql: r"
+			create table if not exists scan_runs (
+				id integer primary key autoincrement,
+				path text not null,
+				started_at integer not null,
+				duration_ms integer not null,
+				inserted_count integer not null default 0,
+				updated_count integer not null default 0,
+				removed_count integer not null default 0,
+				file_count integer null,
+				initiated_by text null,
+				status text not null,
+				error text null
+			);
+			create index if not exists scan_runs_path_started on scan_runs(path, started_at);
+			create table if not exists scan_run_changes (
+				id integer primary key autoincrement,
+				run_id integer not null references scan_runs(id) on delete cascade,
+				path text not null,
+				change text not null,
+				old_hash blob null,
+				new_hash blob null
+			);
+			create index if not exists scan_run_changes_run on scan_run_changes(run_id);
+		",
+	},
+	Migration {
+		id: 20250316,
+		name: "content_store",
+		sql: r"
+			create table if not exists content_store (
+				hash blob primary key,
+				size integer not null,
+				ref_count integer not null default 0,
+				stored_at integer not null
+			);
+		",
+	},
+	Migration {
+		id: 20250317,
+		name: "settings",
+		sql: r"
+			create table if not exists settings (
+				key text primary key,
+				value text not null
+			);
+		",
+	},
+	Migration {
+		id: 20250318,
+		name: "permission_revisions",
+		sql: r"
+			create table if not exists permission_revisions (
+				src_peer blob not null,
+				target_peer blob not null,
+				revision integer not null,
+				primary key (src_peer, target_peer)
+			);
+		",
+	},
+	Migration {
+		id: 20250319,
+		name: "disk_samples",
+		sql: r"
+			create table if not exists disk_samples (
+				disk_id text not null,
+				mount_path text not null,
+				sampled_at integer not null,
+				total_space integer not null,
+				available_space integer not null
+			);
+			create index if not exists disk_samples_disk on disk_samples(disk_id, sampled_at);
+			create index if not exists disk_samples_mount on disk_samples(mount_path, sampled_at);
+			create index if not exists disk_samples_sampled_at on disk_samples(sampled_at);
+		",
+	},
+	Migration {
+		id: 20250320,
+		name: "login_attempts",
+		sql: r"
+			create table if not exists login_attempts (
+				id integer primary key autoincrement,
+				attempted_at integer not null,
+				username text not null,
+				source_ip text not null,
+				user_agent text not null,
+				outcome text not null
+			);
+			create index if not exists login_attempts_user on login_attempts(username, attempted_at);
+			create index if not exists login_attempts_at on login_attempts(attempted_at);
+		",
+	},
+	Migration {
+		id: 20250321,
+		name: "backup_runs",
+		sql: r"
+			create table if not exists backup_runs (
+				id integer primary key autoincrement,
+				kind text not null,
+				started_at integer not null,
+				finished_at integer not null,
+				path text not null,
+				size_bytes integer,
+				scheduled integer not null default 0,
+				error text
+			);
+			create index if not exists backup_runs_started on backup_runs(started_at);
+		",
+	},
+	Migration {
+		id: 20250322,
+		name: "transfers",
+		sql: r"
+			create table if not exists transfers (
+				id integer primary key autoincrement,
+				direction text not null,
+				peer text not null,
+				remote_path text not null,
+				local_path text not null,
+				size integer not null,
+				duration_ms integer not null,
+				status text not null,
+				error text,
+				hash blob,
+				finished_at integer not null
+			);
+			create index if not exists transfers_peer on transfers(peer, id);
+			create index if not exists transfers_hash on transfers(hash);
+		",
+	},
+	Migration {
+		id: 20250323,
+		name: "pins",
+		sql: r"
+			create table if not exists pins (
+				id integer primary key autoincrement,
+				peer text not null,
+				remote_path text not null,
+				local_dest text not null,
+				interval_secs integer not null,
+				bandwidth_limit integer,
+				delete_removed integer not null default 1,
+				paused integer not null default 0,
+				created_at integer not null,
+				last_sync_at integer,
+				files_pending integer not null default 0,
+				bytes_transferred integer not null default 0,
+				last_error text
+			);
+			create table if not exists pin_files (
+				pin_id integer not null,
+				path text not null,
+				size integer not null,
+				modified_at integer,
+				hash blob not null,
+				primary key (pin_id, path)
+			);
+		",
+	},
+	Migration {
+		id: 20250324,
+		name: "media_metadata",
+		sql: r"
+			create table if not exists media_metadata (
+				hash blob primary key,
+				width integer,
+				height integer,
+				duration_ms integer,
+				codec text,
+				error text,
+				extracted_at integer not null
+			);
+			create index if not exists media_metadata_duration on media_metadata(duration_ms);
+		",
+	},
+	Migration {
+		id: 20250325,
+		name: "clock_offsets",
+		sql: r"
+			create table if not exists clock_offsets (
+				peer_id text not null,
+				sampled_at integer not null,
+				offset_ms integer not null,
+				sample_offset_ms integer not null,
+				rtt_ms integer not null
+			);
+			create index if not exists clock_offsets_peer on clock_offsets(peer_id, sampled_at);
+		",
+	},
+	Migration {
+		id: 20250326,
+		name: "pending_reviews",
+		sql: r"
+			create table if not exists pending_reviews (
+				id integer primary key autoincrement,
+				peer_id text not null,
+				path text not null,
+				hash blob,
+				size integer not null,
+				received_at integer not null,
+				status text not null default 'pending',
+				unique (peer_id, path)
+			);
+			create table if not exists peer_trust (
+				peer_id text primary key,
+				accepted integer not null default 0,
+				rejected integer not null default 0
+			);
+		",
+	},
+	Migration {
+		id: 20250327,
+		name: "peer_wake",
+		sql: r"
+			create table if not exists peer_wake (
+				peer_id text primary key,
+				mac text not null,
+				subnet text,
+				confirmed integer not null default 0,
+				updated_at integer not null
+			);
+			alter table pins add column wake_if_needed integer not null default 0;
+		",
+	},
+	Migration {
+		id: 20250328,
+		name: "claimed_hashes",
+		sql: r"
+			create table if not exists claimed_hashes (
+				node_id blob not null,
+				path text not null,
+				manifest_path text not null,
+				algorithm text not null,
+				claimed blob not null,
+				actual blob null,
+				verified_hash blob null,
+				status text not null,
+				checked_at integer not null,
+				primary key (node_id, path, manifest_path, algorithm)
+			);
+			create index if not exists idx_claimed_hashes_status on claimed_hashes(node_id, status);
+		",
+	},
+	Migration {
+		id: 20250329,
+		name: "remote_grants",
+		sql: r"
+			create table if not exists remote_grants (
+				peer blob primary key,
+				permissions text not null,
+				fetched_at integer not null
+			);
+		",
+	},
+	Migration {
+		id: 20250330,
+		name: "file_tombstones",
+		sql: r"
+			alter table file_locations add column deleted_at integer null;
+			alter table file_locations add column deleted_by_run integer null;
+			create index if not exists idx_file_locations_deleted on file_locations(deleted_at);
+		",
+	},
+	Migration {
+		id: 20250331,
+		name: "file_provenance",
+		sql: r"
+			alter table file_locations add column origin text not null default 'local_scan';
+			alter table file_locations add column origin_peer text null;
+			alter table file_locations add column origin_run integer null;
+			alter table file_locations add column origin_transfer text null;
+			alter table file_locations add column origin_user text null;
+			alter table file_locations add column introduced_at integer null;
+			update file_locations set introduced_at = cast(strftime('%s', timestamp) as integer);
+			create index if not exists idx_file_locations_origin on file_locations(origin, origin_peer);
+		",
+	},
+	Migration {
+		id: 20250401,
+		name: "search_indexes",
+		sql: r"
+			drop index if exists idx_file_locations_hash;
+			create index if not exists idx_file_locations_hash_deleted on file_locations(hash, deleted_at);
+			create index if not exists idx_file_entries_sort on file_entries(coalesce(latest_datetime, ''), hash);
+		",
+	},
+	Migration {
+		id: 20250402,
+		name: "index_replication",
+		sql: r"
+			alter table file_entries add column change_seq integer not null default 0;
+			alter table file_locations add column change_seq integer not null default 0;
+			update file_entries set change_seq = rowid;
+			update file_locations set change_seq = rowid + (select coalesce(max(rowid), 0) from file_entries);
+			create table if not exists index_changes (
+				id integer primary key check (id = 1),
+				seq integer not null
+			);
+			insert into index_changes (id, seq) values (1, (
+				select max(
+					(select coalesce(max(change_seq), 0) from file_entries),
+					(select coalesce(max(change_seq), 0) from file_locations)
+				)
+			));
+			create index if not exists idx_file_entries_change_seq on file_entries(change_seq);
+			create index if not exists idx_file_locations_change_seq on file_locations(node_id, change_seq);
+			create trigger if not exists file_entries_change_insert after insert on file_entries
+			begin
+				update index_changes set seq = seq + 1 where id = 1;
+				update file_entries set change_seq = (select seq from index_changes where id = 1)
+					where rowid = new.rowid;
+			end;
+			create trigger if not exists file_entries_change_update after update on file_entries
+			when new.change_seq = old.change_seq
+				and (new.size, new.mime_type, new.first_datetime, new.latest_datetime)
+					is not (old.size, old.mime_type, old.first_datetime, old.latest_datetime)
+			begin
+				update index_changes set seq = seq + 1 where id = 1;
+				update file_entries set change_seq = (select seq from index_changes where id = 1)
+					where rowid = new.rowid;
+			end;
+			create trigger if not exists file_locations_change_insert after insert on file_locations
+			begin
+				update index_changes set seq = seq + 1 where id = 1;
+				update file_locations set change_seq = (select seq from index_changes where id = 1)
+					where rowid = new.rowid;
+			end;
+			create trigger if not exists file_locations_change_update after update on file_locations
+			when new.change_seq = old.change_seq
+				and (new.hash, new.size, new.timestamp, new.created_at, new.modified_at,
+					new.accessed_at, new.deleted_at, new.origin, new.origin_peer, new.origin_run,
+					new.origin_transfer, new.origin_user, new.introduced_at)
+				is not (old.hash, old.size, old.timestamp, old.created_at, old.modified_at,
+					old.accessed_at, old.deleted_at, old.origin, old.origin_peer, old.origin_run,
+					old.origin_transfer, old.origin_user, old.introduced_at)
+			begin
+				update index_changes set seq = seq + 1 where id = 1;
+				update file_locations set change_seq = (select seq from index_changes where id = 1)
+					where rowid = new.rowid;
+			end;
+			create table if not exists replication_state (
+				peer text not null,
+				role text not null,
+				generation text not null,
+				acked_seq integer not null default 0,
+				behind integer not null default 0,
+				last_sync_at integer null,
+				last_error text null,
+				primary key (peer, role)
+			);
+		",
+	},
+	Migration {
+		id: 20250403,
+		name: "index_subscriptions",
+		sql: r"
+			create table if not exists index_subscriptions (
+				peer text not null primary key,
+				created_at integer not null
+			);
+		",
+	},
+	Migration {
+		id: 20250404,
+		name: "content_negotiation",
+		sql: r"
+			create table if not exists file_blocks (
+				hash blob not null,
+				block_index integer not null,
+				block_hash blob not null,
+				primary key (hash, block_index)
+			);
+			create index if not exists file_blocks_block_hash on file_blocks(block_hash);
+			alter table transfers add column note text null;
+		",
+	},
+	Migration {
+		id: 20250405,
+		name: "peer_protocol_stats",
+		sql: r"
+			create table if not exists peer_protocol_stats (
+				day text not null,
+				peer text not null,
+				request text not null,
+				requests integer not null default 0,
+				sent integer not null default 0,
+				bytes_in integer not null default 0,
+				bytes_out integer not null default 0,
+				errors integer not null default 0,
+				denials integer not null default 0,
+				rate_limited integer not null default 0,
+				primary key (day, peer, request)
+			);
+		",
+	},
+	Migration {
+		id: 20250406,
+		name: "config_snapshots",
+		sql: r"
+			create table if not exists config_snapshots (
+				id integer primary key autoincrement,
+				label text null,
+				reason text not null,
+				taken_at integer not null,
+				version integer not null,
+				config text not null
+			);
+		",
+	},
+	Migration {
+		id: 20250407,
+		name: "outbox_rules",
+		sql: r"
+			create table if not exists outbox_rules (
+				id integer primary key autoincrement,
+				watch_path text not null,
+				dest_peer text not null,
+				dest_path text not null,
+				delete_after_send integer not null default 0,
+				pattern text not null default '',
+				paused integer not null default 0,
+				created_at integer not null,
+				last_sent_file text,
+				last_sent_at integer,
+				files_sent integer not null default 0,
+				last_error text
+			);
+		",
+	},
+	Migration {
+		id: 20250408,
+		name: "activity_events",
+		sql: r"
+			create table if not exists activity_events (
+				id integer primary key autoincrement,
+				kind text not null,
+				at integer not null,
+				first_at integer not null,
+				peer text null,
+				summary text not null,
+				ref_id text null,
+				count integer not null default 1
+			);
+			create index if not exists activity_events_at on activity_events(at, id);
+			create index if not exists activity_events_peer on activity_events(peer, at);
+		",
+	},
+	Migration {
+		id: 20250409,
+		name: "delete_proposals",
+		sql: r"
+			create table if not exists delete_proposals (
+				id text primary key,
+				hash blob not null,
+				reason text not null,
+				created_at integer not null,
+				expires_at integer not null
+			);
+			create table if not exists delete_proposal_holders (
+				proposal_id text not null,
+				peer_id text not null,
+				paths text not null,
+				status text not null default 'undelivered',
+				detail text null,
+				updated_at integer not null,
+				primary key (proposal_id, peer_id)
+			);
+			create table if not exists received_delete_proposals (
+				id integer primary key autoincrement,
+				proposer text not null,
+				proposal_id text not null,
+				hash blob not null,
+				paths_hint text not null,
+				reason text not null,
+				received_at integer not null,
+				expires_at integer not null,
+				status text not null default 'pending',
+				outcome text null,
+				decided_at integer null,
+				reported integer not null default 0,
+				unique (proposer, proposal_id)
+			);
+		",
+	},
+	Migration {
+		id: 20250410,
+		name: "integrity_checks",
+		sql: r"
+			create table if not exists integrity_checks (
+				node_id blob not null,
+				path text not null,
+				expected blob not null,
+				actual blob null,
+				status text not null,
+				last_verified_at integer not null,
+				primary key (node_id, path)
+			);
+			create index if not exists idx_integrity_checks_status on integrity_checks(node_id, status);
+		",
+	},
+	Migration {
+		id: 20250411,
+		name: "scan_result_pulls",
+		sql: r"
+			create table if not exists scan_result_pulls (
+				peer text not null,
+				run integer not null,
+				path text not null,
+				cursor integer not null default 0,
+				total integer not null default 0,
+				remaining integer null,
+				error text null,
+				updated_at integer not null,
+				primary key (peer, run)
+			);
+		",
+	},
+	Migration {
+		id: 20250412,
+		name: "peer_trust_levels",
+		sql: r"
+			alter table peers add column trust text not null default 'untrusted';
+			update peers set trust = 'trusted';
+		",
+	},
+	Migration {
+		id: 20250413,
+		name: "folder_rule_patterns",
+		sql: r"
+			alter table shared_folders add column deny_patterns text not null default '';
+			alter table shared_folders add column allow_patterns text not null default '';
+			alter table peer_permissions add column deny_patterns text not null default '';
+			alter table peer_permissions add column allow_patterns text not null default '';
+		",
+	},
+	Migration {
+		id: 20250414,
+		name: "peer_access_volume",
+		sql: r"
+			create table if not exists peer_access_volume (
+				day text not null,
+				peer text not null,
+				read integer not null default 0,
+				written integer not null default 0,
+				requests integer not null default 0,
+				primary key (day, peer)
+			);
+		",
+	},
+	Migration {
+		id: 20250415,
+		name: "scan_run_changes_truncated",
+		sql: r"
+			alter table scan_runs add column changes_truncated integer not null default 0;
+		",
+	},
 ];
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
@@ -300,10 +910,6 @@ pub struct ListArgs {
 	search_word: Option<String>,
 }
 
-pub struct DB {
-	conn: Mutex<Connection>,
-}
-
 pub fn get_your_node(conn: &Connection) -> anyhow::Result<Option<[u8; 16]>> {
 	let mut stmt = conn.prepare("SELECT id FROM nodes WHERE you = 1")?;
 	let mut rows = stmt.query_map((), |row| row.get::<_, Vec<u8>>(0))?;
@@ -315,6 +921,37 @@ pub fn get_your_node(conn: &Connection) -> anyhow::Result<Option<[u8; 16]>> {
 	}
 }
 
+/// A `you` node other than `current`, left by a keypair this database was
+/// used with before.
+pub fn find_previous_local_node(
+	conn: &Connection,
+	current: &NodeID,
+) -> anyhow::Result<Option<NodeID>> {
+	let mut stmt = conn.prepare("SELECT id FROM nodes WHERE you = 1 AND id != ?1 LIMIT 1")?;
+	let mut rows = stmt.query_map([&current[..]], |row| row.get::<_, Vec<u8>>(0))?;
+	match rows.next() {
+		Some(id) => {
+			Ok(Some(id?.try_into().map_err(|_| {
+				anyhow::anyhow!("node id must be 16 bytes")
+			})?))
+		}
+		None => Ok(None),
+	}
+}
+
+/// Keeps a node's rows but stops treating it as this node.
+pub fn demote_node(conn: &Connection, id: &NodeID) -> anyhow::Result<()> {
+	conn.execute("UPDATE nodes SET you = 0 WHERE id = ?1", [&id[..]])?;
+	Ok(())
+}
+
+/// Name stored for this node, if it was persisted yet.
+pub fn load_local_node_name(conn: &Connection) -> anyhow::Result<Option<String>> {
+	let mut stmt = conn.prepare("SELECT name FROM nodes WHERE you = 1")?;
+	let mut rows = stmt.query_map((), |row| row.get::<_, String>(0))?;
+	Ok(rows.next().transpose()?)
+}
+
 /// Saves a fully‑populated `Node` row.
 pub fn save_node(conn: &Connection, node: &Node) -> anyhow::Result<()> {
 	conn.execute(
@@ -672,43 +1309,114 @@ pub fn get_file_entry(conn: &Connection, hash: &[u8]) -> anyhow::Result<Option<F
 	}
 }
 
-pub fn fetch_file_entries_paginated(
+/// Columns [`file_entry_from_row`] reads, from `file_entries fe`.
+pub(crate) const FILE_ENTRY_COLUMNS: &str =
+	"fe.hash, fe.size, fe.mime_type, fe.first_datetime, fe.latest_datetime";
+
+fn file_entry_from_row(row: &Row<'_>) -> rusqlite::Result<FileEntry> {
+	Ok(FileEntry {
+		hash: row.get(0)?,
+		size: row.get(1)?,
+		mime_type: row.get(2)?,
+		first_datetime: row.get(3)?,
+		latest_datetime: row.get(4)?,
+	})
+}
+
+/// Cursor just past the first `rows` rows of `file_entries fe` matching
+/// `where_clause`, or `None` to start at the beginning.
+pub(crate) fn skip_cursor(
 	conn: &Connection,
-	offset: u64,
-	limit: u64,
-) -> anyhow::Result<Vec<FileEntry>> {
-	let mut stmt = conn.prepare(
-		"SELECT hash, size, mime_type, first_datetime, latest_datetime \
-		FROM file_entries \
-		ORDER BY latest_datetime DESC \
-		LIMIT ?1 OFFSET ?2",
-	)?;
-	let rows = stmt.query_map(params![limit as i64, offset as i64], |row| {
-		Ok(FileEntry {
-			hash: row.get(0)?,
-			size: row.get(1)?,
-			mime_type: row.get(2)?,
-			first_datetime: row.get(3)?,
-			latest_datetime: row.get(4)?,
-		})
-	})?;
+	where_clause: &str,
+	params: &[Value],
+	desc: bool,
+	rows: usize,
+) -> anyhow::Result<Option<PageCursor>> {
+	if rows == 0 {
+		return Ok(None);
+	}
+	let sql = format!(
+		"SELECT fe.latest_datetime, fe.hash FROM file_entries fe{where_clause}{} LIMIT {rows}",
+		order_clause(desc)
+	);
+	let mut stmt = conn.prepare(&sql)?;
+	let mut keys = stmt.query(rusqlite::params_from_iter(params))?;
+	let mut last = None;
+	while let Some(row) = keys.next()? {
+		last = Some(PageCursor::new(row.get(0)?, row.get(1)?));
+	}
+	Ok(last)
+}
+
+/// Splits off the probe row fetched past `limit`; its presence means
+/// another page follows the last kept row.
+pub(crate) fn cursor_page<T>(
+	mut rows: Vec<T>,
+	limit: usize,
+	key: impl Fn(&T) -> PageCursor,
+) -> CursorPage<T> {
+	let more = rows.len() > limit;
+	rows.truncate(limit);
+	let next_cursor = if more { rows.last().map(key) } else { None };
+	CursorPage { rows, next_cursor }
+}
 
+/// Newest entries first, continuing after `cursor`.
+pub fn fetch_file_entries_after(
+	conn: &Connection,
+	cursor: Option<&PageCursor>,
+	limit: u64,
+) -> anyhow::Result<CursorPage<FileEntry>> {
+	let query = SearchQuery::all(usize::try_from(limit).unwrap_or(usize::MAX))?;
+	let (sql, params) = query.select_sql(FILE_ENTRY_COLUMNS, cursor);
+	let mut stmt = conn.prepare(&sql)?;
+	let rows = stmt.query_map(rusqlite::params_from_iter(&params), file_entry_from_row)?;
 	let mut entries = Vec::new();
 	for entry in rows {
 		entries.push(entry?);
 	}
-	Ok(entries)
+	Ok(cursor_page(entries, limit as usize, |entry| {
+		PageCursor::new(Some(entry.latest_datetime.clone()), entry.hash.to_vec())
+	}))
 }
 
-pub fn get_file_location(
+pub fn fetch_file_entries_paginated(
 	conn: &Connection,
-	node_id: &[u8],
-	hash: &[u8],
-) -> anyhow::Result<Option<FileLocation>> {
-	let mut stmt = conn.prepare(
-		"SELECT path, hash, size, timestamp, created_at, modified_at, accessed_at \
-		 FROM file_locations \
-		 WHERE node_id = ? AND hash = ?",
+	offset: u64,
+	limit: u64,
+) -> anyhow::Result<Vec<FileEntry>> {
+	let skip = check_offset(usize::try_from(offset).unwrap_or(usize::MAX))?;
+	let cursor = skip_cursor(conn, "", &[], true, skip)?;
+	Ok(fetch_file_entries_after(conn, cursor.as_ref(), limit)?.rows)
+}
+
+/// Value stored in `file_locations.path`: text for UTF-8 paths, otherwise a
+/// blob holding the raw path bytes so the original name is not lost.
+pub(crate) fn path_to_sql(path: &Path) -> Value {
+	match path.to_str() {
+		Some(path) => Value::Text(path.to_string()),
+		None => Value::Blob(path_bytes(path)),
+	}
+}
+
+/// Reads a path column written by [`path_to_sql`].
+pub(crate) fn path_column(row: &Row<'_>, idx: usize) -> rusqlite::Result<PathBuf> {
+	match row.get_ref(idx)? {
+		ValueRef::Text(text) => Ok(PathBuf::from(String::from_utf8_lossy(text).into_owned())),
+		ValueRef::Blob(bytes) => Ok(path_from_bytes(bytes)),
+		_ => row.get::<_, String>(idx).map(PathBuf::from),
+	}
+}
+
+pub fn get_file_location(
+	conn: &Connection,
+	node_id: &[u8],
+	hash: &[u8],
+) -> anyhow::Result<Option<FileLocation>> {
+	let mut stmt = conn.prepare(
+		"SELECT path, hash, size, timestamp, created_at, modified_at, accessed_at \
+		 FROM file_locations \
+		 WHERE node_id = ? AND hash = ? AND deleted_at IS NULL",
 	)?;
 	let mut rows = stmt.query_map(&[node_id, hash], |row| {
 		// get an optional Vec<u8> for the hash
@@ -718,7 +1426,7 @@ pub fn get_file_location(
 			.as_ref()
 			.map(|v| v.as_slice().try_into().expect("hash must be 32 bytes"));
 		Ok(FileLocation {
-			path: PathBuf::from(row.get::<_, String>(0)?),
+			path: path_column(row, 0)?,
 			hash,
 			size: row.get::<_, i64>(2)? as u64,
 			// file_locations does not store mime_type, set to None
@@ -727,6 +1435,7 @@ pub fn get_file_location(
 			created_at: row.get(4)?,
 			modified_at: row.get(5)?,
 			accessed_at: row.get(6)?,
+			blocks: Vec::new(),
 		})
 	})?;
 
@@ -738,8 +1447,33 @@ pub fn get_file_location(
 	}
 }
 
-/// Search arguments for filtering files
-#[derive(Debug, Default)]
+/// Mime types an earlier scan of `node_id` sniffed for `files`, given as
+/// path and current size. A file whose size changed since is left out as
+/// the stored type may be stale.
+pub fn load_indexed_mimes(
+	conn: &Connection,
+	node_id: &[u8],
+	files: &[(PathBuf, u64)],
+) -> anyhow::Result<HashMap<PathBuf, String>> {
+	let mut stmt = conn.prepare_cached(
+		"SELECT fe.mime_type FROM file_locations fl \
+		 JOIN file_entries fe ON fe.hash = fl.hash \
+		 WHERE fl.node_id = ?1 AND fl.path = ?2 AND fl.size = ?3 AND fe.mime_type IS NOT NULL",
+	)?;
+	let mut mimes = HashMap::new();
+	for (path, size) in files {
+		let mut rows = stmt.query(params![node_id, path_to_sql(path), *size as i64])?;
+		if let Some(row) = rows.next()? {
+			mimes.insert(path.clone(), row.get(0)?);
+		}
+	}
+	Ok(mimes)
+}
+
+/// Search arguments for filtering files. Sent to peers in
+/// `PeerReq::SearchFiles`, so fields added later need a default.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SearchFilesArgs {
 	pub name_query: Option<String>,
 	pub content_query: Option<String>,
@@ -747,14 +1481,57 @@ pub struct SearchFilesArgs {
 	pub date_to: Option<String>,
 	pub replicas_min: Option<u64>,
 	pub replicas_max: Option<u64>,
+	/// Let empty files match the replica bounds. Every empty file has the
+	/// same hash, so its replica count is every empty file indexed, and
+	/// they are left out of replica searches unless asked for.
+	pub include_empty: bool,
 	pub mime_types: Vec<String>,
+	/// Only files with a location on this node.
+	pub node_id: Option<NodeID>,
+	/// Only files with a location under this folder. `/` and `\` are
+	/// interchangeable, so a Windows folder can be given either way.
+	pub path_prefix: Option<String>,
+	/// Media playing at least this many seconds.
+	pub min_duration: Option<u64>,
+	/// Media playing at most this many seconds.
+	pub max_duration: Option<u64>,
+	/// Images and video with at least this many pixels per frame.
+	pub min_pixels: Option<u64>,
+	/// Also match files whose every location was deleted; see
+	/// [`FileSearchResult::deleted_at`].
+	pub include_deleted: bool,
+	/// Only files with a location that came into the index this way.
+	pub origin: Option<FileOrigin>,
+	/// Only files with a location this peer introduced, by peer id.
+	pub introduced_by_peer: Option<String>,
+	/// Peers from before it ignore it and sort by date.
+	pub sort_by: SearchSortBy,
 	pub sort_desc: bool,
 	pub page: usize,
 	pub page_size: usize,
 }
 
+impl SearchFilesArgs {
+	/// Refuses pages over the size and depth limits and inverted ranges,
+	/// the checks every search runs before it reaches the database.
+	pub fn validate(&self) -> Result<(), SearchQueryError> {
+		SearchQuery::new(self).map(|_| ())
+	}
+}
+
+/// Order of search results, reversed by [`SearchFilesArgs::sort_desc`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchSortBy {
+	/// By when the content was last seen.
+	#[default]
+	Date,
+	/// By file name, in [`natural_cmp`] order.
+	Name,
+}
+
 /// Search result with file info and replica count
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileSearchResult {
 	pub hash: Vec<u8>,
 	pub name: String,
@@ -765,150 +1542,231 @@ pub struct FileSearchResult {
 	pub replicas: u64,
 	pub first_datetime: Option<String>,
 	pub latest_datetime: Option<String>,
+	/// Set once metadata was extracted for the content.
+	pub duration_ms: Option<u64>,
+	pub width: Option<u32>,
+	pub height: Option<u32>,
+	pub codec: Option<String>,
+	/// When the shown location was deleted, for results matched through
+	/// [`SearchFilesArgs::include_deleted`]. Unix seconds.
+	#[serde(default)]
+	pub deleted_at: Option<i64>,
 }
 
-/// Search files using file_entries and file_locations tables
-/// Returns (results, mime_types, total_count)
-pub fn search_files(
-	conn: &Connection,
-	args: SearchFilesArgs,
-) -> anyhow::Result<(Vec<FileSearchResult>, Vec<String>, usize)> {
-	// Build WHERE conditions
-	let mut conditions: Vec<String> = Vec::new();
-	let mut param_values: Vec<String> = Vec::new();
-
-	// Name search - search in file_locations paths
-	if let Some(ref name) = args.name_query {
-		if !name.trim().is_empty() {
-			conditions.push(format!(
-				"EXISTS (SELECT 1 FROM file_locations fl WHERE fl.hash = fe.hash AND fl.path LIKE ?{})",
-				param_values.len() + 1
-			));
-			param_values.push(format!("%{}%", name));
-		}
-	}
+/// How a `file_locations` row came into the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileOrigin {
+	/// Found on disk by a scan of this node.
+	LocalScan,
+	/// Written to this node by a peer or an HTTP client.
+	RemoteWrite,
+	/// Brought in from another index.
+	Import,
+	/// Another node's index says the file is there.
+	Sync,
+}
 
-	// Mime type filter
-	if !args.mime_types.is_empty() {
-		let mut placeholders = Vec::new();
-		for mime in &args.mime_types {
-			param_values.push(mime.clone());
-			placeholders.push(format!("?{}", param_values.len()));
+impl FileOrigin {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::LocalScan => "local_scan",
+			Self::RemoteWrite => "remote_write",
+			Self::Import => "import",
+			Self::Sync => "sync",
 		}
-		conditions.push(format!("fe.mime_type IN ({})", placeholders.join(", ")));
 	}
 
-	// Date from filter
-	if let Some(ref date_from) = args.date_from {
-		if !date_from.trim().is_empty() {
-			conditions.push(format!("fe.first_datetime >= ?{}", param_values.len() + 1));
-			param_values.push(date_from.clone());
+	pub fn parse(value: &str) -> Option<Self> {
+		match value {
+			"local_scan" => Some(Self::LocalScan),
+			"remote_write" => Some(Self::RemoteWrite),
+			"import" => Some(Self::Import),
+			"sync" => Some(Self::Sync),
+			_ => None,
 		}
 	}
+}
 
-	// Date to filter
-	if let Some(ref date_to) = args.date_to {
-		if !date_to.trim().is_empty() {
-			conditions.push(format!("fe.latest_datetime <= ?{}", param_values.len() + 1));
-			param_values.push(date_to.clone());
-		}
-	}
+/// Who introduced a `file_locations` row. Rows indexed before provenance
+/// was recorded read as a local scan with nothing else known.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileProvenance {
+	pub node_id: Vec<u8>,
+	pub origin: FileOrigin,
+	/// Peer that wrote the file or whose index named it.
+	pub peer: Option<String>,
+	/// Scan run that found the file, see [`ScanRun::id`].
+	pub scan_run: Option<i64>,
+	pub transfer: Option<String>,
+	/// Account behind an HTTP-initiated write.
+	pub user: Option<String>,
+	/// Unix seconds.
+	pub introduced_at: Option<i64>,
+}
 
-	// Replicas min filter
-	if let Some(min) = args.replicas_min {
-		conditions.push(format!(
-			"(SELECT COUNT(*) FROM file_locations fl3 WHERE fl3.hash = fe.hash) >= ?{}",
-			param_values.len() + 1
-		));
-		param_values.push(min.to_string());
+impl FileProvenance {
+	/// Provenance of a file `peer` just wrote to this node.
+	pub fn remote_write(node_id: &[u8], peer: &PeerId, now: DateTime<Utc>) -> Self {
+		Self {
+			node_id: node_id.to_vec(),
+			origin: FileOrigin::RemoteWrite,
+			peer: Some(peer.to_string()),
+			scan_run: None,
+			transfer: None,
+			user: None,
+			introduced_at: Some(now.timestamp()),
+		}
 	}
 
-	// Replicas max filter
-	if let Some(max) = args.replicas_max {
-		conditions.push(format!(
-			"(SELECT COUNT(*) FROM file_locations fl3 WHERE fl3.hash = fe.hash) <= ?{}",
-			param_values.len() + 1
-		));
-		param_values.push(max.to_string());
+	/// "indexed by scan #42 on desktop, 2024-05-01" or "written by peer
+	/// laptop as user anna, 2024-06-12". `device` names the node holding
+	/// the file and `peer_name` the peer in [`Self::peer`], when known.
+	pub fn describe(&self, device: &str, peer_name: Option<&str>) -> String {
+		let peer = peer_name.or(self.peer.as_deref()).unwrap_or("unknown");
+		let mut text = match self.origin {
+			FileOrigin::LocalScan => match self.scan_run {
+				Some(run) => format!("indexed by scan #{run} on {device}"),
+				None => format!("indexed by a scan on {device}"),
+			},
+			FileOrigin::RemoteWrite => format!("written by peer {peer}"),
+			FileOrigin::Import => format!("imported on {device}"),
+			FileOrigin::Sync => format!("synced from peer {peer}"),
+		};
+		if let Some(user) = &self.user {
+			text.push_str(&format!(" as user {user}"));
+		}
+		if let Some(transfer) = &self.transfer {
+			text.push_str(&format!(" in transfer {transfer}"));
+		}
+		if let Some(at) = self
+			.introduced_at
+			.and_then(|at| DateTime::from_timestamp(at, 0))
+		{
+			text.push_str(&format!(", {}", at.format("%Y-%m-%d")));
+		}
+		text
 	}
+}
 
-	// Build WHERE clause
-	let where_clause = if conditions.is_empty() {
-		String::new()
-	} else {
-		format!(" WHERE {}", conditions.join(" AND "))
-	};
+fn search_result_from_row(row: &Row<'_>) -> rusqlite::Result<FileSearchResult> {
+	let path = path_column(row, 1)?.to_string_lossy().into_owned();
+	let node_id: Vec<u8> = row.get(2)?;
+	Ok(FileSearchResult {
+		hash: row.get(0)?,
+		name: file_name(&path).to_string(),
+		path,
+		node_id,
+		size: row.get::<_, i64>(3)? as u64,
+		mime_type: row.get(4)?,
+		replicas: row.get::<_, i64>(5)? as u64,
+		first_datetime: row.get(6)?,
+		latest_datetime: row.get(7)?,
+		duration_ms: row.get::<_, Option<i64>>(8)?.map(|ms| ms.max(0) as u64),
+		width: row.get(9)?,
+		height: row.get(10)?,
+		codec: row.get(11)?,
+		deleted_at: row.get(12)?,
+	})
+}
 
-	// First, get total count
-	let count_sql = format!("SELECT COUNT(*) FROM file_entries fe{}", where_clause);
-	let params: Vec<&dyn ToSql> = param_values.iter().map(|s| s as &dyn ToSql).collect();
-	let total_count: i64 = conn.query_row(&count_sql, params.as_slice(), |row| row.get(0))?;
+/// Last component of a `/` or `\` separated path.
+fn file_name(path: &str) -> &str {
+	path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
 
-	// Build data query with pagination
-	let page_size = if args.page_size == 0 {
-		50
-	} else {
-		args.page_size
+/// The page of a name-ordered search after `cursor`, whose key is the name
+/// of the last row seen, skipping `skip` more rows. SQLite cannot order
+/// names naturally, so every match's name is loaded and sorted here and
+/// only the page's rows are fetched in full. The probe row is kept.
+fn search_names_after(
+	conn: &Connection,
+	query: &SearchQuery,
+	cursor: Option<&PageCursor>,
+	skip: usize,
+) -> anyhow::Result<Vec<FileSearchResult>> {
+	let (names_sql, names_params) = query.names_sql();
+	let mut stmt = conn.prepare(&names_sql)?;
+	let rows = stmt.query_map(rusqlite::params_from_iter(&names_params), |row| {
+		let path = path_column(row, 1)?.to_string_lossy().into_owned();
+		Ok((file_name(&path).to_string(), row.get::<_, Vec<u8>>(0)?))
+	})?;
+	let mut names = Vec::new();
+	for row in rows {
+		names.push(row?);
+	}
+	let order = |a: &(String, Vec<u8>), b: &(String, Vec<u8>)| {
+		let order = natural_cmp(&a.0, &b.0).then_with(|| a.1.cmp(&b.1));
+		if query.sort_desc() {
+			order.reverse()
+		} else {
+			order
+		}
 	};
-	let offset = args.page * page_size;
-
-	let order_clause = if args.sort_desc {
-		" ORDER BY fe.latest_datetime DESC"
-	} else {
-		" ORDER BY fe.latest_datetime ASC"
+	names.sort_by(order);
+	let start = match cursor {
+		Some(cursor) => {
+			let seen = (cursor.key().to_string(), cursor.hash().to_vec());
+			names.partition_point(|name| order(name, &seen).is_le())
+		}
+		None => 0,
 	};
+	let hashes = names
+		.iter()
+		.skip(start.saturating_add(skip))
+		.take(query.page_size() + 1)
+		.map(|(_, hash)| hash.clone())
+		.collect::<Vec<_>>();
+	if hashes.is_empty() {
+		return Ok(Vec::new());
+	}
 
-	let data_sql = format!(
-		"SELECT
-			fe.hash,
-			COALESCE(
-				(SELECT fl2.path FROM file_locations fl2 WHERE fl2.hash = fe.hash LIMIT 1),
-				''
-			) as path,
-			COALESCE(
-				(SELECT fl2.node_id FROM file_locations fl2 WHERE fl2.hash = fe.hash LIMIT 1),
-				X''
-			) as node_id,
-			fe.size,
-			fe.mime_type,
-			(SELECT COUNT(*) FROM file_locations fl3 WHERE fl3.hash = fe.hash) as replicas,
-			fe.first_datetime,
-			fe.latest_datetime
-		FROM file_entries fe{}{}
-		LIMIT {} OFFSET {}",
-		where_clause, order_clause, page_size, offset
-	);
-
-	// Execute query
-	let mut stmt = conn.prepare(&data_sql)?;
-	let params: Vec<&dyn ToSql> = param_values.iter().map(|s| s as &dyn ToSql).collect();
-
-	let rows = stmt.query_map(params.as_slice(), |row| {
-		let path: String = row.get(1)?;
-		let node_id: Vec<u8> = row.get(2)?;
-		// Extract filename from path
-		let name = path
-			.rsplit(|c| c == '/' || c == '\\')
-			.next()
-			.unwrap_or(&path)
-			.to_string();
-		Ok(FileSearchResult {
-			hash: row.get(0)?,
-			name,
-			path,
-			node_id,
-			size: row.get::<_, i64>(3)? as u64,
-			mime_type: row.get(4)?,
-			replicas: row.get::<_, i64>(5)? as u64,
-			first_datetime: row.get(6)?,
-			latest_datetime: row.get(7)?,
-		})
-	})?;
-
-	let mut results = Vec::new();
+	let (rows_sql, rows_params) = query.rows_sql(&hashes);
+	let mut stmt = conn.prepare(&rows_sql)?;
+	let rows = stmt.query_map(
+		rusqlite::params_from_iter(&rows_params),
+		search_result_from_row,
+	)?;
+	let mut by_hash = HashMap::new();
 	for row in rows {
-		results.push(row?);
+		let result = row?;
+		by_hash.insert(result.hash.clone(), result);
 	}
+	Ok(hashes
+		.iter()
+		.filter_map(|hash| by_hash.remove(hash))
+		.collect())
+}
+
+fn search_page(
+	conn: &Connection,
+	query: &SearchQuery,
+	cursor: Option<&PageCursor>,
+	skip: usize,
+) -> anyhow::Result<(CursorPage<FileSearchResult>, Vec<String>, usize)> {
+	let (count_sql, count_params) = query.count_sql();
+	let total_count: i64 = conn.query_row(
+		&count_sql,
+		rusqlite::params_from_iter(&count_params),
+		|row| row.get(0),
+	)?;
+
+	let results = match query.sort_by() {
+		SearchSortBy::Date => {
+			let (data_sql, param_values) = query.page_sql(cursor);
+			let mut stmt = conn.prepare(&data_sql)?;
+			let rows = stmt.query_map(
+				rusqlite::params_from_iter(&param_values),
+				search_result_from_row,
+			)?;
+			let mut results = Vec::new();
+			for row in rows {
+				results.push(row?);
+			}
+			results
+		}
+		SearchSortBy::Name => search_names_after(conn, query, cursor, skip)?,
+	};
 
 	// Also fetch available mime types for the filter dropdown
 	let mut mime_stmt =
@@ -919,30 +1777,96 @@ pub fn search_files(
 		mime_types.push(mime?);
 	}
 
-	Ok((results, mime_types, total_count.max(0) as usize))
+	let sort_by = query.sort_by();
+	let page = cursor_page(results, query.page_size(), |result| {
+		let key = match sort_by {
+			SearchSortBy::Date => result.latest_datetime.clone(),
+			SearchSortBy::Name => Some(result.name.clone()),
+		};
+		PageCursor::new(key, result.hash.clone())
+	});
+	Ok((page, mime_types, total_count.max(0) as usize))
+}
+
+/// One page of search results continuing after `cursor`, ordered by
+/// `(latest_datetime, hash)`, or by `(name, hash)` for
+/// [`SearchSortBy::Name`]. `args.page` is ignored.
+/// Returns (page, mime_types, total_count)
+pub fn search_files_after(
+	conn: &Connection,
+	args: SearchFilesArgs,
+	cursor: Option<&PageCursor>,
+) -> anyhow::Result<(CursorPage<FileSearchResult>, Vec<String>, usize)> {
+	search_page(conn, &SearchQuery::new(&args)?, cursor, 0)
+}
+
+/// When the index last recorded a change for `node_id`, or `None` if it
+/// holds nothing from that node.
+pub fn node_index_updated_at(
+	conn: &Connection,
+	node_id: &NodeID,
+) -> anyhow::Result<Option<DateTime<Utc>>> {
+	Ok(conn.query_row(
+		"SELECT MAX(timestamp) FROM file_locations WHERE node_id = ?1",
+		[node_id.as_slice()],
+		|row| row.get(0),
+	)?)
+}
+
+/// Search files using file_entries and file_locations tables
+/// Returns (page, mime_types, total_count)
+pub fn search_files(
+	conn: &Connection,
+	args: SearchFilesArgs,
+) -> anyhow::Result<(CursorPage<FileSearchResult>, Vec<String>, usize)> {
+	let query = SearchQuery::new(&args)?;
+	if query.sort_by() == SearchSortBy::Name {
+		return search_page(conn, &query, None, query.offset());
+	}
+	let filter = query.filter();
+	let cursor = skip_cursor(
+		conn,
+		&filter.where_sql(),
+		filter.params(),
+		query.sort_desc(),
+		query.offset(),
+	)?;
+	search_page(conn, &query, cursor.as_ref(), 0)
 }
 
-/// Save or update a peer entry.
+/// Save or update a peer entry. A known peer keeps its trust level.
 pub fn save_peer(conn: &Connection, peer: &Peer) -> anyhow::Result<()> {
 	conn.execute(
-		"INSERT INTO peers (peer_id, name) VALUES (?1, ?2)
+		"INSERT INTO peers (peer_id, name, trust) VALUES (?1, ?2, ?3)
 		 ON CONFLICT(peer_id) DO UPDATE SET name = excluded.name",
-		params![peer.id.to_string(), peer.name],
+		params![peer.id.to_string(), peer.name, peer.trust.as_str()],
+	)?;
+	Ok(())
+}
+
+/// Sets the trust level of `peer`, adding it when it isn't known yet.
+pub fn save_trust_level(conn: &Connection, peer: &PeerId, trust: TrustLevel) -> anyhow::Result<()> {
+	conn.execute(
+		"INSERT INTO peers (peer_id, trust) VALUES (?1, ?2)
+		 ON CONFLICT(peer_id) DO UPDATE SET trust = excluded.trust",
+		params![peer.to_string(), trust.as_str()],
 	)?;
 	Ok(())
 }
 
 /// Load all peers.
 pub fn load_peers(conn: &Connection) -> anyhow::Result<Vec<Peer>> {
-	let mut stmt = conn.prepare("SELECT peer_id, name FROM peers")?;
+	let mut stmt = conn.prepare("SELECT peer_id, name, trust FROM peers")?;
 	let rows = stmt.query_map([], |row| {
 		let id_str: String = row.get(0)?;
 		let id = libp2p::PeerId::from_str(&id_str).map_err(|e| {
 			rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
 		})?;
+		let trust: String = row.get(2)?;
 		Ok(Peer {
 			id,
 			name: row.get(1)?,
+			trust: TrustLevel::parse(&trust).unwrap_or_default(),
 		})
 	})?;
 	let mut peers = Vec::new();
@@ -975,6 +1899,23 @@ pub fn remove_discovered_peer(
 	Ok(())
 }
 
+/// Adds `saved` and removes `removed` in one transaction.
+pub fn write_discovered_peers(
+	conn: &mut Connection,
+	saved: &[DiscoveredPeer],
+	removed: &[DiscoveredPeer],
+) -> anyhow::Result<()> {
+	let tx = conn.transaction()?;
+	for peer in saved {
+		save_discovered_peer(&tx, peer)?;
+	}
+	for peer in removed {
+		remove_discovered_peer(&tx, &peer.peer_id, &peer.multiaddr)?;
+	}
+	tx.commit()?;
+	Ok(())
+}
+
 /// Load all discovered peers.
 pub fn load_discovered_peers(conn: &Connection) -> anyhow::Result<Vec<DiscoveredPeer>> {
 	let mut stmt = conn.prepare("SELECT peer_id, multiaddr FROM discovered_peers")?;
@@ -996,33 +1937,241 @@ pub fn load_discovered_peers(conn: &Connection) -> anyhow::Result<Vec<Discovered
 	Ok(peers)
 }
 
+/// Adds `entry` to the activity timeline, or counts it on the row of an
+/// identical event (same kind, peer, summary and reference) less than
+/// [`DEDUPE_WINDOW`] older. Returns the row's id.
+pub(crate) fn record_activity(conn: &Connection, entry: &ActivityEntry) -> anyhow::Result<i64> {
+	let at = entry.at.timestamp();
+	let mut stmt = conn.prepare(
+		"SELECT id FROM activity_events
+		WHERE at >= ?1 AND kind = ?2 AND peer IS ?3 AND summary = ?4 AND ref_id IS ?5
+		ORDER BY at DESC, id DESC LIMIT 1",
+	)?;
+	let mut rows = stmt.query(params![
+		(entry.at - DEDUPE_WINDOW).timestamp(),
+		entry.kind.as_str(),
+		entry.peer,
+		entry.summary,
+		entry.ref_id,
+	])?;
+	if let Some(row) = rows.next()? {
+		let id: i64 = row.get(0)?;
+		conn.execute(
+			"UPDATE activity_events SET count = count + 1, at = MAX(at, ?2) WHERE id = ?1",
+			params![id, at],
+		)?;
+		return Ok(id);
+	}
+	conn.execute(
+		"INSERT INTO activity_events (kind, at, first_at, peer, summary, ref_id)
+		VALUES (?1, ?2, ?2, ?3, ?4, ?5)",
+		params![
+			entry.kind.as_str(),
+			at,
+			entry.peer,
+			entry.summary,
+			entry.ref_id
+		],
+	)?;
+	Ok(conn.last_insert_rowid())
+}
+
+fn activity_row(row: &Row<'_>) -> rusqlite::Result<ActivityEvent> {
+	let kind: String = row.get(1)?;
+	Ok(ActivityEvent {
+		id: row.get(0)?,
+		kind: ActivityEventKind::parse(&kind).unwrap_or(ActivityEventKind::Peer),
+		at: DateTime::from_timestamp(row.get(2)?, 0).unwrap_or_default(),
+		first_at: DateTime::from_timestamp(row.get(3)?, 0).unwrap_or_default(),
+		peer: row.get(4)?,
+		summary: row.get(5)?,
+		ref_id: row.get(6)?,
+		count: row.get::<_, i64>(7)?.max(1) as u64,
+	})
+}
+
+/// Timeline events matching `filter`, newest first, continuing after
+/// `cursor`. The cursor's key is the event's timestamp and its hash the
+/// row id, so events recorded meanwhile never shift later pages.
+pub fn load_activity(
+	conn: &Connection,
+	filter: &ActivityFilter,
+	cursor: Option<&PageCursor>,
+	limit: u64,
+) -> anyhow::Result<CursorPage<ActivityEvent>> {
+	let mut clauses = Vec::new();
+	let mut values = Vec::new();
+	let mut bind = |value: Value| {
+		values.push(value);
+		format!("?{}", values.len())
+	};
+	if !filter.kinds.is_empty() {
+		let kinds = filter
+			.kinds
+			.iter()
+			.map(|kind| bind(Value::Text(kind.as_str().to_string())))
+			.collect::<Vec<_>>();
+		clauses.push(format!("kind IN ({})", kinds.join(", ")));
+	}
+	if let Some(peer) = &filter.peer {
+		clauses.push(format!("peer = {}", bind(Value::Text(peer.clone()))));
+	}
+	if let Some(since) = filter.since {
+		clauses.push(format!("at >= {}", bind(Value::Integer(since.timestamp()))));
+	}
+	if let Some(text) = filter.text.as_deref().filter(|text| !text.is_empty()) {
+		clauses.push(format!(
+			"instr(lower(summary), lower({})) > 0",
+			bind(Value::Text(text.to_string()))
+		));
+	}
+	if let Some(cursor) = cursor {
+		let at: i64 = cursor
+			.key()
+			.parse()
+			.map_err(|_| anyhow!("invalid cursor: bad key"))?;
+		let id = <[u8; 8]>::try_from(cursor.hash())
+			.map(i64::from_be_bytes)
+			.map_err(|_| anyhow!("invalid cursor: bad id"))?;
+		let (at, id) = (bind(Value::Integer(at)), bind(Value::Integer(id)));
+		clauses.push(format!("(at < {at} OR (at = {at} AND id < {id}))"));
+	}
+	let limit_param = bind(Value::Integer(limit.saturating_add(1) as i64));
+	let where_sql = if clauses.is_empty() {
+		String::new()
+	} else {
+		format!(" WHERE {}", clauses.join(" AND "))
+	};
+	let mut stmt = conn.prepare(&format!(
+		"SELECT id, kind, at, first_at, peer, summary, ref_id, count FROM activity_events{where_sql}
+		ORDER BY at DESC, id DESC LIMIT {limit_param}"
+	))?;
+	let rows = stmt.query_map(rusqlite::params_from_iter(&values), activity_row)?;
+	let mut events = Vec::new();
+	for row in rows {
+		events.push(row?);
+	}
+	Ok(cursor_page(events, limit as usize, |event| {
+		PageCursor::new(
+			Some(event.at.timestamp().to_string()),
+			event.id.to_be_bytes().to_vec(),
+		)
+	}))
+}
+
+/// Forgets timeline events last seen before `before`. Returns how many.
+pub fn prune_activity(conn: &Connection, before: DateTime<Utc>) -> anyhow::Result<usize> {
+	Ok(conn.execute(
+		"DELETE FROM activity_events WHERE at < ?1",
+		params![before.timestamp()],
+	)?)
+}
+
 const RULE_TYPE_OWNER: i64 = 0;
 const RULE_TYPE_FOLDER: i64 = 1;
+const RULE_TYPE_INBOX: i64 = 2;
+
+/// Revision of the rules `src_peer` grants `target_peer`; 0 before the
+/// first save.
+pub fn load_permission_revision(
+	conn: &Connection,
+	src_peer: &PeerId,
+	target_peer: &PeerId,
+) -> anyhow::Result<u64> {
+	let mut stmt = conn.prepare(
+		"SELECT revision FROM permission_revisions WHERE src_peer = ?1 AND target_peer = ?2",
+	)?;
+	let mut rows = stmt.query(params![src_peer.to_bytes(), target_peer.to_bytes()])?;
+	match rows.next()? {
+		Some(row) => Ok(row.get::<_, i64>(0)? as u64),
+		None => Ok(0),
+	}
+}
+
+pub fn load_permission_set(
+	conn: &Connection,
+	src_peer: &PeerId,
+	target_peer: &PeerId,
+) -> anyhow::Result<PermissionSet> {
+	let permissions = load_peer_permissions(conn, src_peer)?
+		.into_iter()
+		.find(|(peer, _)| peer == target_peer)
+		.map(|(_, permissions)| permissions)
+		.unwrap_or_default();
+	Ok(PermissionSet {
+		revision: load_permission_revision(conn, src_peer, target_peer)?,
+		permissions,
+		temporary: Vec::new(),
+	})
+}
 
+/// Replaces the rules `src_peer` grants `target_peer`, overwriting whatever
+/// is stored.
 pub fn save_peer_permissions(
 	conn: &mut Connection,
 	src_peer: &PeerId,
 	target_peer: &PeerId,
 	permissions: &[Permission],
 ) -> anyhow::Result<()> {
+	save_peer_permissions_at(conn, src_peer, target_peer, permissions, None).map(|_| ())
+}
+
+/// Replaces the rules and bumps their revision, returning the new one. With
+/// `expected_revision` set the save fails with [`PermissionConflict`] when
+/// someone else saved since that revision was read.
+pub fn save_peer_permissions_at(
+	conn: &mut Connection,
+	src_peer: &PeerId,
+	target_peer: &PeerId,
+	permissions: &[Permission],
+	expected_revision: Option<u64>,
+) -> anyhow::Result<u64> {
+	let tx = conn.transaction()?;
+	let revision =
+		write_peer_permissions(&tx, src_peer, target_peer, permissions, expected_revision)?;
+	tx.commit()?;
+	Ok(revision)
+}
+
+/// [`save_peer_permissions_at`] inside a transaction the caller commits.
+pub(crate) fn write_peer_permissions(
+	tx: &Connection,
+	src_peer: &PeerId,
+	target_peer: &PeerId,
+	permissions: &[Permission],
+	expected_revision: Option<u64>,
+) -> anyhow::Result<u64> {
 	let src_bytes = src_peer.to_bytes();
 	let target_bytes = target_peer.to_bytes();
-	let tx = conn.transaction()?;
+	let revision = load_permission_revision(tx, src_peer, target_peer)?;
+	if let Some(expected_revision) = expected_revision
+		&& expected_revision != revision
+	{
+		let latest = load_permission_set(tx, src_peer, target_peer)?;
+		return Err(PermissionConflict {
+			expected_revision,
+			latest,
+		}
+		.into());
+	}
 	tx.execute(
 		"DELETE FROM peer_permissions WHERE src_peer = ?1 AND target_peer = ?2",
 		params![&src_bytes, &target_bytes],
 	)?;
 	for permission in permissions {
-		let (rule_type, path_value, flags_value) = match permission.rule() {
-			Rule::Owner => (RULE_TYPE_OWNER, None, None),
+		let (rule_type, path_value, flags_value, deny, allow) = match permission.rule() {
+			Rule::Owner => (RULE_TYPE_OWNER, None, None, String::new(), String::new()),
+			Rule::Inbox => (RULE_TYPE_INBOX, None, None, String::new(), String::new()),
 			Rule::Folder(folder) => (
 				RULE_TYPE_FOLDER,
 				Some(folder.path().to_string_lossy().into_owned()),
 				Some(folder.flags() as i64),
+				patterns_column(folder.deny_patterns()),
+				patterns_column(folder.allow_patterns()),
 			),
 		};
 		tx.execute(
-			"INSERT INTO peer_permissions (src_peer, target_peer, rule_type, path, flags, expires_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+			"INSERT INTO peer_permissions (src_peer, target_peer, rule_type, path, flags, expires_at, deny_patterns, allow_patterns) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
 			params![
 				&src_bytes,
 				&target_bytes,
@@ -1030,11 +2179,49 @@ pub fn save_peer_permissions(
 				path_value.as_deref(),
 				flags_value,
 				permission.expires_at(),
+				deny,
+				allow,
 			],
 		)?;
 	}
-	tx.commit()?;
-	Ok(())
+	tx.execute(
+		"INSERT INTO permission_revisions (src_peer, target_peer, revision) VALUES (?1, ?2, ?3)
+		ON CONFLICT(src_peer, target_peer) DO UPDATE SET revision = excluded.revision",
+		params![&src_bytes, &target_bytes, (revision + 1) as i64],
+	)?;
+	let rules = permissions
+		.iter()
+		.map(|permission| match permission.rule() {
+			Rule::Owner => String::from("owner"),
+			Rule::Inbox => String::from("inbox"),
+			Rule::Folder(folder) => folder.path().display().to_string(),
+		})
+		.collect::<Vec<_>>();
+	let summary = if rules.is_empty() {
+		String::from("Permissions revoked")
+	} else {
+		format!("Permissions set: {}", rules.join(", "))
+	};
+	record_activity(
+		tx,
+		&ActivityEntry::new(ActivityEventKind::Permission, Utc::now(), summary)
+			.peer(target_peer)
+			.ref_id(revision + 1),
+	)?;
+	Ok(revision + 1)
+}
+
+/// Deny or allow patterns as stored, one per line.
+fn patterns_column(patterns: &[String]) -> String {
+	patterns.join("\n")
+}
+
+fn patterns_from_column(column: &str) -> Vec<String> {
+	column
+		.lines()
+		.filter(|pattern| !pattern.is_empty())
+		.map(String::from)
+		.collect()
 }
 
 pub fn load_peer_permissions(
@@ -1043,7 +2230,7 @@ pub fn load_peer_permissions(
 ) -> anyhow::Result<Vec<(PeerId, Vec<Permission>)>> {
 	let src_bytes = src_peer.to_bytes();
 	let mut stmt = conn.prepare(
-		"SELECT target_peer, rule_type, path, flags, expires_at FROM peer_permissions WHERE src_peer = ?1 ORDER BY id ASC",
+		"SELECT target_peer, rule_type, path, flags, expires_at, deny_patterns, allow_patterns FROM peer_permissions WHERE src_peer = ?1 ORDER BY id ASC",
 	)?;
 	let mut rows = stmt.query(params![&src_bytes])?;
 	let mut results: Vec<(PeerId, Vec<Permission>)> = Vec::new();
@@ -1054,12 +2241,16 @@ pub fn load_peer_permissions(
 		let rule_type: i64 = row.get(1)?;
 		let permission = match rule_type {
 			RULE_TYPE_OWNER => Permission::with_expiration(Rule::Owner, row.get(4)?),
+			RULE_TYPE_INBOX => Permission::with_expiration(Rule::Inbox, row.get(4)?),
 			RULE_TYPE_FOLDER => {
 				let path: Option<String> = row.get(2)?;
 				let flags: Option<i64> = row.get(3)?;
 				let path = path.ok_or_else(|| anyhow!("missing folder path for permission"))?;
 				let flags = flags.ok_or_else(|| anyhow!("missing folder flags for permission"))?;
-				let folder = FolderRule::new(PathBuf::from(path), flags as u8);
+				let folder = FolderRule::new(PathBuf::from(path), flags as u8).with_patterns(
+					patterns_from_column(&row.get::<_, String>(5)?),
+					patterns_from_column(&row.get::<_, String>(6)?),
+				);
 				Permission::with_expiration(Rule::Folder(folder), row.get(4)?)
 			}
 			other => bail!("unsupported rule type {other}"),
@@ -1073,58 +2264,3050 @@ pub fn load_peer_permissions(
 	Ok(results)
 }
 
-pub fn save_shared_folder(conn: &Connection, rule: &FolderRule) -> anyhow::Result<()> {
+const PERMISSION_OUTBOX_CAP: i64 = 256;
+const PERMISSION_OUTBOX_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// Drop expired permission notifications and keep only the newest entries.
+pub fn prune_permission_outbox(conn: &Connection, now: i64) -> anyhow::Result<()> {
+	conn.execute(
+		"DELETE FROM permission_outbox WHERE queued_at < ?1",
+		params![now - PERMISSION_OUTBOX_TTL_SECS],
+	)?;
 	conn.execute(
-		"INSERT INTO shared_folders (path, flags) VALUES (?1, ?2)
-		ON CONFLICT(path) DO UPDATE SET flags = excluded.flags",
-		params![rule.path().to_string_lossy(), rule.flags() as i64],
+		"DELETE FROM permission_outbox WHERE target_peer NOT IN (
+			SELECT target_peer FROM permission_outbox ORDER BY queued_at DESC LIMIT ?1
+		)",
+		params![PERMISSION_OUTBOX_CAP],
 	)?;
 	Ok(())
 }
 
-pub fn load_shared_folders(conn: &Connection) -> anyhow::Result<Vec<FolderRule>> {
-	let mut stmt = conn.prepare("SELECT path, flags FROM shared_folders ORDER BY path ASC")?;
-	let rows = stmt.query_map([], |row| {
-		let path: String = row.get(0)?;
-		let flags: i64 = row.get(1)?;
-		Ok(FolderRule::new(PathBuf::from(path), flags as u8))
-	})?;
-	let mut folders = Vec::new();
-	for row in rows {
-		folders.push(row?);
-	}
-	Ok(folders)
+/// Queue the latest permission set for a peer that could not be notified.
+pub fn queue_permission_change(
+	conn: &Connection,
+	target_peer: &PeerId,
+	permissions: &[Permission],
+	now: i64,
+) -> anyhow::Result<()> {
+	let payload = serde_json::to_string(permissions)?;
+	conn.execute(
+		"INSERT INTO permission_outbox (target_peer, permissions, queued_at) VALUES (?1, ?2, ?3)
+		ON CONFLICT(target_peer) DO UPDATE SET
+			permissions = excluded.permissions,
+			queued_at = excluded.queued_at",
+		params![target_peer.to_bytes(), payload, now],
+	)?;
+	prune_permission_outbox(conn, now)
 }
 
-pub fn save_user(conn: &Connection, user: &User) -> anyhow::Result<()> {
+/// Remove and return the queued permission set for a peer.
+pub fn take_permission_change(
+	conn: &Connection,
+	target_peer: &PeerId,
+	now: i64,
+) -> anyhow::Result<Option<Vec<Permission>>> {
+	prune_permission_outbox(conn, now)?;
+	let target_bytes = target_peer.to_bytes();
+	let payload: Option<String> = {
+		let mut stmt =
+			conn.prepare("SELECT permissions FROM permission_outbox WHERE target_peer = ?1")?;
+		let mut rows = stmt.query(params![&target_bytes])?;
+		match rows.next()? {
+			Some(row) => Some(row.get(0)?),
+			None => None,
+		}
+	};
+	let Some(payload) = payload else {
+		return Ok(None);
+	};
 	conn.execute(
-		r#"
-		INSERT INTO users (username, password)
-		VALUES (?1, ?2)
-		ON CONFLICT(username) DO UPDATE SET password = excluded.password
-		"#,
-		params![&user.name, &user.passw],
+		"DELETE FROM permission_outbox WHERE target_peer = ?1",
+		params![&target_bytes],
+	)?;
+	Ok(Some(serde_json::from_str(&payload)?))
+}
+
+/// Remember what `peer` granted this node, as fetched at `fetched_at`.
+pub fn save_remote_grants(
+	conn: &Connection,
+	peer: &PeerId,
+	permissions: &[Permission],
+	fetched_at: i64,
+) -> anyhow::Result<()> {
+	let payload = serde_json::to_string(permissions)?;
+	conn.execute(
+		"INSERT INTO remote_grants (peer, permissions, fetched_at) VALUES (?1, ?2, ?3)
+		ON CONFLICT(peer) DO UPDATE SET
+			permissions = excluded.permissions,
+			fetched_at = excluded.fetched_at",
+		params![peer.to_bytes(), payload, fetched_at],
 	)?;
 	Ok(())
 }
 
-pub fn load_users(conn: &Connection) -> anyhow::Result<Vec<User>> {
-	let mut stmt = conn.prepare("SELECT username, password FROM users ORDER BY username ASC")?;
-	let rows = stmt.query_map([], |row| {
-		Ok(User {
-			name: row.get(0)?,
-			passw: row.get(1)?,
-		})
-	})?;
-	let mut users = Vec::new();
-	for user in rows {
-		users.push(user?);
-	}
-	Ok(users)
+pub fn delete_remote_grants(conn: &Connection, peer: &PeerId) -> anyhow::Result<()> {
+	conn.execute(
+		"DELETE FROM remote_grants WHERE peer = ?1",
+		params![peer.to_bytes()],
+	)?;
+	Ok(())
 }
 
-pub fn load_user(conn: &Connection, username: &str) -> anyhow::Result<Option<User>> {
-	let mut stmt =
+/// Every remembered grant set with the unix time it was fetched at.
+pub fn load_remote_grants(
+	conn: &Connection,
+) -> anyhow::Result<Vec<(PeerId, Vec<Permission>, i64)>> {
+	let mut stmt = conn.prepare("SELECT peer, permissions, fetched_at FROM remote_grants")?;
+	let mut rows = stmt.query([])?;
+	let mut results = Vec::new();
+	while let Some(row) = rows.next()? {
+		let peer_bytes: Vec<u8> = row.get(0)?;
+		let peer = PeerId::from_bytes(&peer_bytes)
+			.map_err(|err| anyhow!("invalid peer id from database: {err}"))?;
+		let payload: String = row.get(1)?;
+		results.push((peer, serde_json::from_str(&payload)?, row.get(2)?));
+	}
+	Ok(results)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanRunStatus {
+	Completed,
+	Failed,
+	Cancelled,
+}
+
+impl ScanRunStatus {
+	fn as_str(&self) -> &'static str {
+		match self {
+			Self::Completed => "completed",
+			Self::Failed => "failed",
+			Self::Cancelled => "cancelled",
+		}
+	}
+
+	fn parse(value: &str) -> Self {
+		match value {
+			"completed" => Self::Completed,
+			"cancelled" => Self::Cancelled,
+			_ => Self::Failed,
+		}
+	}
+}
+
+/// One recorded scan of a folder.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanRun {
+	pub id: i64,
+	pub path: String,
+	pub started_at: DateTime<Utc>,
+	pub duration_ms: u64,
+	pub inserted_count: u64,
+	pub updated_count: u64,
+	pub removed_count: u64,
+	/// Files under `path` after the run; only known for completed runs.
+	pub file_count: Option<u64>,
+	pub initiated_by: Option<String>,
+	pub status: ScanRunStatus,
+	pub error: Option<String>,
+	/// The run changed more than [`MAX_RECORDED_CHANGES`] files and only
+	/// that many were kept; the counts are still exact.
+	pub changes_truncated: bool,
+}
+
+/// File count of the latest completed run compared to an older baseline run.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanTrend {
+	pub path: String,
+	pub file_count: u64,
+	pub baseline_file_count: u64,
+	pub baseline_started_at: DateTime<Utc>,
+}
+
+impl ScanTrend {
+	pub fn file_delta(&self) -> i64 {
+		self.file_count as i64 - self.baseline_file_count as i64
+	}
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanDiffEntry {
+	pub path: String,
+	pub change: ScanChangeKind,
+	pub old_hash: Option<Vec<u8>>,
+	pub new_hash: Option<Vec<u8>>,
+}
+
+/// Changed files kept per scan run, so one huge scan can't fill the
+/// database.
+pub(crate) const MAX_RECORDED_CHANGES: usize = 10_000;
+
+const SCAN_RUN_COLUMNS: &str = "id, path, started_at, duration_ms, inserted_count, updated_count, \
+	removed_count, file_count, initiated_by, status, error, changes_truncated";
+
+fn scan_run_from_row(row: &Row<'_>) -> rusqlite::Result<ScanRun> {
+	let started_at: i64 = row.get(2)?;
+	let status: String = row.get(9)?;
+	Ok(ScanRun {
+		id: row.get(0)?,
+		path: row.get(1)?,
+		started_at: DateTime::from_timestamp(started_at, 0).unwrap_or_default(),
+		duration_ms: row.get::<_, i64>(3)?.max(0) as u64,
+		inserted_count: row.get::<_, i64>(4)?.max(0) as u64,
+		updated_count: row.get::<_, i64>(5)?.max(0) as u64,
+		removed_count: row.get::<_, i64>(6)?.max(0) as u64,
+		file_count: row
+			.get::<_, Option<i64>>(7)?
+			.map(|count| count.max(0) as u64),
+		initiated_by: row.get(8)?,
+		status: ScanRunStatus::parse(&status),
+		error: row.get(10)?,
+		changes_truncated: row.get(11)?,
+	})
+}
+
+/// Records a finished scan and the file changes it made, up to
+/// [`MAX_RECORDED_CHANGES`] of them. `cancelled` marks an aborted run;
+/// failed and cancelled runs never store changes.
+pub fn record_scan_run(
+	conn: &Connection,
+	path: &str,
+	started_at: DateTime<Utc>,
+	duration: std::time::Duration,
+	initiated_by: Option<&PeerId>,
+	outcome: &Result<ScanResult, String>,
+	cancelled: bool,
+) -> anyhow::Result<i64> {
+	let tx = conn.unchecked_transaction()?;
+	let (status, result, error) = match outcome {
+		Ok(result) => (ScanRunStatus::Completed, Some(result), None),
+		Err(_) if cancelled => (ScanRunStatus::Cancelled, None, None),
+		Err(err) => (ScanRunStatus::Failed, None, Some(err.as_str())),
+	};
+	let truncated = result.is_some_and(|result| result.changes.len() > MAX_RECORDED_CHANGES);
+	tx.execute(
+		"INSERT INTO scan_runs (path, started_at, duration_ms, inserted_count, updated_count, removed_count, file_count, initiated_by, status, error, changes_truncated)
+		VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+		params![
+			path,
+			started_at.timestamp(),
+			duration.as_millis() as i64,
+			result.map(|r| r.inserted_count as i64).unwrap_or(0),
+			result.map(|r| r.updated_count as i64).unwrap_or(0),
+			result.map(|r| r.removed_count as i64).unwrap_or(0),
+			result.map(|r| r.file_count as i64),
+			initiated_by.map(|peer| peer.to_string()),
+			status.as_str(),
+			error,
+			truncated,
+		],
+	)?;
+	let run_id = tx.last_insert_rowid();
+	if let Some(result) = result {
+		let mut stmt = tx.prepare(
+			"INSERT INTO scan_run_changes (run_id, path, change, old_hash, new_hash) VALUES (?1, ?2, ?3, ?4, ?5)",
+		)?;
+		for change in result.changes.iter().take(MAX_RECORDED_CHANGES) {
+			stmt.execute(params![
+				run_id,
+				path_to_sql(&change.path),
+				change.kind.as_str(),
+				change.old_hash.map(|hash| hash.to_vec()),
+				change.new_hash.map(|hash| hash.to_vec()),
+			])?;
+		}
+	}
+	let summary = match (outcome, status) {
+		(Ok(result), _) => format!(
+			"Scanned {path}: {} new, {} changed, {} removed",
+			result.inserted_count, result.updated_count, result.removed_count
+		),
+		(Err(_), ScanRunStatus::Cancelled) => format!("Scan of {path} cancelled"),
+		(Err(err), _) => format!("Scan of {path} failed: {err}"),
+	};
+	let mut entry = ActivityEntry::new(
+		ActivityEventKind::Scan,
+		started_at + chrono::Duration::from_std(duration).unwrap_or(chrono::Duration::zero()),
+		summary,
+	)
+	.ref_id(run_id);
+	if let Some(peer) = initiated_by {
+		entry = entry.peer(peer);
+	}
+	record_activity(&tx, &entry)?;
+	tx.commit()?;
+	Ok(run_id)
+}
+
+/// Links the files a scan found deleted to its run in `scan_runs`.
+pub fn attribute_tombstones(
+	conn: &Connection,
+	node_id: &[u8],
+	run_id: i64,
+	paths: &[&Path],
+) -> anyhow::Result<()> {
+	let tx = conn.unchecked_transaction()?;
+	{
+		let mut stmt = tx.prepare(
+			"UPDATE file_locations SET deleted_by_run = ?1
+			WHERE node_id = ?2 AND path = ?3 AND deleted_at IS NOT NULL AND deleted_by_run IS NULL",
+		)?;
+		for path in paths {
+			stmt.execute(params![run_id, node_id, path_to_sql(path)])?;
+		}
+	}
+	tx.commit()?;
+	Ok(())
+}
+
+/// Links the files a scan added to its run in `scan_runs`.
+pub fn attribute_additions(
+	conn: &Connection,
+	node_id: &[u8],
+	run_id: i64,
+	paths: &[&Path],
+) -> anyhow::Result<()> {
+	let tx = conn.unchecked_transaction()?;
+	{
+		let mut stmt = tx.prepare(
+			"UPDATE file_locations SET origin_run = ?1
+			WHERE node_id = ?2 AND path = ?3 AND origin = 'local_scan' AND origin_run IS NULL",
+		)?;
+		for path in paths {
+			stmt.execute(params![run_id, node_id, path_to_sql(path)])?;
+		}
+	}
+	tx.commit()?;
+	Ok(())
+}
+
+/// Records who put the file at `path` on `provenance.node_id`. A file new
+/// to the index gets a row without a hash for the next scan to fill in,
+/// which keeps the provenance; a deleted one comes back the same way.
+pub fn record_provenance(
+	conn: &Connection,
+	path: &Path,
+	size: u64,
+	provenance: &FileProvenance,
+	now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+	conn.execute(
+		"INSERT INTO file_locations
+			(node_id, path, size, timestamp, origin, origin_peer, origin_run, origin_transfer, origin_user, introduced_at)
+		VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+		ON CONFLICT(node_id, path) DO UPDATE SET
+			hash = CASE WHEN deleted_at IS NULL THEN hash ELSE NULL END,
+			origin = excluded.origin,
+			origin_peer = excluded.origin_peer,
+			origin_run = excluded.origin_run,
+			origin_transfer = excluded.origin_transfer,
+			origin_user = excluded.origin_user,
+			introduced_at = excluded.introduced_at,
+			deleted_at = NULL,
+			deleted_by_run = NULL",
+		params![
+			provenance.node_id,
+			path_to_sql(path),
+			size as i64,
+			now,
+			provenance.origin.as_str(),
+			provenance.peer,
+			provenance.scan_run,
+			provenance.transfer,
+			provenance.user,
+			provenance.introduced_at,
+		],
+	)?;
+	Ok(())
+}
+
+/// Indexes a file an ingest copied to `path` on `node_id`, hash and all, so
+/// it counts as held before the next scan of its folder.
+pub(crate) fn record_ingested_file(
+	conn: &Connection,
+	node_id: &[u8],
+	path: &Path,
+	hash: &[u8],
+	size: u64,
+	mime_type: Option<&str>,
+	modified_at: Option<DateTime<Utc>>,
+) -> anyhow::Result<()> {
+	let now = Utc::now();
+	let tx = conn.unchecked_transaction()?;
+	tx.execute(
+		"INSERT INTO file_entries (hash, size, mime_type, first_datetime, latest_datetime)
+		VALUES (?1, ?2, ?3, ?4, ?4)
+		ON CONFLICT(hash) DO UPDATE SET latest_datetime = excluded.latest_datetime",
+		params![hash, size as i64, mime_type, modified_at],
+	)?;
+	tx.execute(
+		"INSERT INTO file_locations
+			(node_id, path, hash, size, timestamp, modified_at, origin, introduced_at)
+		VALUES (?1, ?2, ?3, ?4, ?5, ?6, 'import', ?7)
+		ON CONFLICT(node_id, path) DO UPDATE SET
+			hash = excluded.hash,
+			size = excluded.size,
+			timestamp = excluded.timestamp,
+			created_at = NULL,
+			modified_at = excluded.modified_at,
+			accessed_at = NULL,
+			deleted_at = NULL,
+			deleted_by_run = NULL,
+			origin = 'import',
+			origin_peer = NULL,
+			origin_run = NULL,
+			origin_transfer = NULL,
+			origin_user = NULL,
+			introduced_at = excluded.introduced_at",
+		params![
+			node_id,
+			path_to_sql(path),
+			hash,
+			size as i64,
+			now,
+			modified_at,
+			now.timestamp(),
+		],
+	)?;
+	tx.commit()?;
+	Ok(())
+}
+
+/// Provenance of the location of `hash` at `path`, a live one over a
+/// deleted one when several nodes hold it there.
+pub fn file_provenance(
+	conn: &Connection,
+	hash: &[u8],
+	path: &Path,
+) -> anyhow::Result<Option<FileProvenance>> {
+	let mut stmt = conn.prepare(
+		"SELECT node_id, origin, origin_peer, origin_run, origin_transfer, origin_user, introduced_at
+		FROM file_locations WHERE hash = ?1 AND path = ?2
+		ORDER BY deleted_at IS NOT NULL, introduced_at LIMIT 1",
+	)?;
+	let mut rows = stmt.query(params![hash, path_to_sql(path)])?;
+	let Some(row) = rows.next()? else {
+		return Ok(None);
+	};
+	let origin: String = row.get(1)?;
+	Ok(Some(FileProvenance {
+		node_id: row.get(0)?,
+		origin: FileOrigin::parse(&origin).unwrap_or(FileOrigin::LocalScan),
+		peer: row.get(2)?,
+		scan_run: row.get(3)?,
+		transfer: row.get(4)?,
+		user: row.get(5)?,
+		introduced_at: row.get(6)?,
+	}))
+}
+
+/// Drops the rows of files deleted before `before`, so they no longer
+/// show up as history. Returns how many were dropped. Replicas of this
+/// node's index that had not seen a dropped tombstone can no longer get it
+/// in a delta, so the last change dropped is kept as the purge horizon; see
+/// [`index_purged_through`].
+pub fn purge_tombstones(conn: &Connection, before: DateTime<Utc>) -> anyhow::Result<usize> {
+	let tx = conn.unchecked_transaction()?;
+	let horizon: Option<i64> = tx.query_row(
+		"SELECT max(change_seq) FROM file_locations
+		WHERE deleted_at IS NOT NULL AND deleted_at < ?1
+		AND node_id IN (SELECT id FROM nodes WHERE you = 1)",
+		params![before.timestamp()],
+		|row| row.get(0),
+	)?;
+	let purged = tx.execute(
+		"DELETE FROM file_locations WHERE deleted_at IS NOT NULL AND deleted_at < ?1",
+		params![before.timestamp()],
+	)?;
+	if let Some(horizon) = horizon
+		&& horizon as u64 > index_purged_through(&tx)?
+	{
+		save_setting(&tx, INDEX_PURGED_THROUGH_SETTING, &horizon.to_string())?;
+	}
+	tx.commit()?;
+	Ok(purged)
+}
+
+/// Recent scan runs, newest first, optionally limited to one path.
+pub fn load_scan_history(
+	conn: &Connection,
+	path: Option<&str>,
+	offset: u64,
+	limit: u64,
+) -> anyhow::Result<Vec<ScanRun>> {
+	let sql = format!(
+		"SELECT {SCAN_RUN_COLUMNS} FROM scan_runs WHERE (?1 IS NULL OR path = ?1)
+		ORDER BY started_at DESC, id DESC LIMIT ?2 OFFSET ?3"
+	);
+	let mut stmt = conn.prepare(&sql)?;
+	let rows = stmt.query_map(
+		params![path, limit as i64, offset as i64],
+		scan_run_from_row,
+	)?;
+	let mut runs = Vec::new();
+	for row in rows {
+		runs.push(row?);
+	}
+	Ok(runs)
+}
+
+pub(crate) fn load_scan_run(conn: &Connection, id: i64) -> anyhow::Result<Option<ScanRun>> {
+	let sql = format!("SELECT {SCAN_RUN_COLUMNS} FROM scan_runs WHERE id = ?1");
+	let mut stmt = conn.prepare(&sql)?;
+	let mut rows = stmt.query_map(params![id], scan_run_from_row)?;
+	Ok(rows.next().transpose()?)
+}
+
+/// Compares the latest completed run of `path` with the newest completed run
+/// started before `since`, or the oldest one if none is that old. Failed and
+/// cancelled runs are ignored.
+pub fn scan_trend(
+	conn: &Connection,
+	path: &str,
+	since: DateTime<Utc>,
+) -> anyhow::Result<Option<ScanTrend>> {
+	let completed = format!(
+		"SELECT {SCAN_RUN_COLUMNS} FROM scan_runs
+		WHERE path = ?1 AND status = 'completed' AND file_count IS NOT NULL"
+	);
+	let query = |sql: String, args: &[&dyn ToSql]| -> anyhow::Result<Option<ScanRun>> {
+		let mut stmt = conn.prepare(&sql)?;
+		let mut rows = stmt.query_map(args, scan_run_from_row)?;
+		Ok(rows.next().transpose()?)
+	};
+	let Some(latest) = query(
+		format!("{completed} ORDER BY started_at DESC, id DESC LIMIT 1"),
+		&[&path],
+	)?
+	else {
+		return Ok(None);
+	};
+	let since_ts = since.timestamp();
+	let baseline = match query(
+		format!(
+			"{completed} AND started_at <= ?2 AND id != ?3 ORDER BY started_at DESC, id DESC LIMIT 1"
+		),
+		&[&path, &since_ts, &latest.id],
+	)? {
+		Some(run) => Some(run),
+		None => query(
+			format!("{completed} AND id != ?2 ORDER BY started_at ASC, id ASC LIMIT 1"),
+			&[&path, &latest.id],
+		)?,
+	};
+	Ok(baseline.map(|baseline| ScanTrend {
+		path: latest.path.clone(),
+		file_count: latest.file_count.unwrap_or(0),
+		baseline_file_count: baseline.file_count.unwrap_or(0),
+		baseline_started_at: baseline.started_at,
+	}))
+}
+
+/// Files added, removed, or changed in content between two completed runs of
+/// the same path, derived from the changes recorded by the runs in between.
+/// Refuses when one of those runs kept only part of its changes. Moves are
+/// not told apart: a renamed file or folder shows as its old paths removed
+/// and its new paths added.
+pub fn scan_diff(
+	conn: &Connection,
+	run_a: i64,
+	run_b: i64,
+	offset: u64,
+	limit: u64,
+) -> anyhow::Result<Vec<ScanDiffEntry>> {
+	let (Some(a), Some(b)) = (load_scan_run(conn, run_a)?, load_scan_run(conn, run_b)?) else {
+		bail!("unknown scan run");
+	};
+	if a.path != b.path {
+		bail!("scan runs cover different paths");
+	}
+	if a.status != ScanRunStatus::Completed || b.status != ScanRunStatus::Completed {
+		bail!("only completed scan runs can be compared");
+	}
+	let (older, newer) = if a.id <= b.id { (a, b) } else { (b, a) };
+	let mut truncated = conn.prepare(
+		"SELECT id FROM scan_runs WHERE path = ?1 AND status = 'completed'
+		AND id > ?2 AND id <= ?3 AND changes_truncated != 0 LIMIT 1",
+	)?;
+	let mut truncated = truncated.query_map(params![older.path, older.id, newer.id], |row| {
+		row.get::<_, i64>(0)
+	})?;
+	if let Some(run) = truncated.next().transpose()? {
+		bail!(
+			"scan run {run} changed more than {MAX_RECORDED_CHANGES} files and kept only \
+			 those, so the difference can't be told"
+		);
+	}
+	let mut stmt = conn.prepare(
+		"SELECT c.path, c.change, c.old_hash, c.new_hash FROM scan_run_changes c
+		JOIN scan_runs r ON r.id = c.run_id
+		WHERE r.path = ?1 AND r.status = 'completed' AND r.id > ?2 AND r.id <= ?3
+		ORDER BY c.run_id ASC, c.id ASC",
+	)?;
+	let rows = stmt.query_map(params![older.path, older.id, newer.id], |row| {
+		let change: String = row.get(1)?;
+		Ok((
+			path_column(row, 0)?.to_string_lossy().into_owned(),
+			change,
+			row.get::<_, Option<Vec<u8>>>(2)?,
+			row.get::<_, Option<Vec<u8>>>(3)?,
+		))
+	})?;
+	// (existed before, exists after, hash before, hash after)
+	type NetChange = (bool, bool, Option<Vec<u8>>, Option<Vec<u8>>);
+	let mut net: HashMap<String, NetChange> = HashMap::new();
+	for row in rows {
+		let (path, change, old_hash, new_hash) = row?;
+		let entry = net
+			.entry(path)
+			.or_insert_with(|| (change != "added", false, old_hash, None));
+		entry.1 = change != "removed";
+		entry.3 = new_hash;
+	}
+	let mut entries = net
+		.into_iter()
+		.filter_map(|(path, (before, after, old_hash, new_hash))| {
+			let change = match (before, after) {
+				(false, true) => ScanChangeKind::Added,
+				(true, false) => ScanChangeKind::Removed,
+				(true, true) if old_hash != new_hash => ScanChangeKind::Changed,
+				_ => return None,
+			};
+			Some(ScanDiffEntry {
+				path,
+				change,
+				old_hash,
+				new_hash,
+			})
+		})
+		.collect::<Vec<_>>();
+	entries.sort_by(|left, right| left.path.cmp(&right.path));
+	Ok(entries
+		.into_iter()
+		.skip(offset as usize)
+		.take(limit as usize)
+		.collect())
+}
+
+pub fn record_disk_samples(conn: &Connection, samples: &[DiskSample]) -> anyhow::Result<()> {
+	let tx = conn.unchecked_transaction()?;
+	{
+		let mut stmt = tx.prepare(
+			"INSERT INTO disk_samples (disk_id, mount_path, sampled_at, total_space, available_space)
+			VALUES (?1, ?2, ?3, ?4, ?5)",
+		)?;
+		for sample in samples {
+			stmt.execute(params![
+				sample.disk_id,
+				sample.mount_path,
+				sample.sampled_at.timestamp(),
+				sample.total_space as i64,
+				sample.available_space as i64,
+			])?;
+		}
+	}
+	tx.commit()?;
+	Ok(())
+}
+
+pub fn prune_disk_samples(conn: &Connection, before: DateTime<Utc>) -> anyhow::Result<usize> {
+	Ok(conn.execute(
+		"DELETE FROM disk_samples WHERE sampled_at < ?1",
+		params![before.timestamp()],
+	)?)
+}
+
+/// Adds `rows` of (peer, request, counts) to the totals of `day` and drops
+/// days past the retention.
+pub fn record_protocol_stats(
+	conn: &Connection,
+	day: NaiveDate,
+	rows: &[(String, &str, ProtocolCounts)],
+) -> anyhow::Result<()> {
+	let cutoff = day - chrono::Days::new(PROTOCOL_STATS_RETENTION_DAYS);
+	let day = day.to_string();
+	let tx = conn.unchecked_transaction()?;
+	{
+		let mut stmt = tx.prepare(
+			"INSERT INTO peer_protocol_stats
+				(day, peer, request, requests, sent, bytes_in, bytes_out, errors, denials, rate_limited)
+			VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+			ON CONFLICT(day, peer, request) DO UPDATE SET
+				requests = requests + excluded.requests,
+				sent = sent + excluded.sent,
+				bytes_in = bytes_in + excluded.bytes_in,
+				bytes_out = bytes_out + excluded.bytes_out,
+				errors = errors + excluded.errors,
+				denials = denials + excluded.denials,
+				rate_limited = rate_limited + excluded.rate_limited",
+		)?;
+		for (peer, request, counts) in rows {
+			stmt.execute(params![
+				day,
+				peer,
+				request,
+				counts.requests as i64,
+				counts.sent as i64,
+				counts.bytes_in as i64,
+				counts.bytes_out as i64,
+				counts.errors as i64,
+				counts.denials as i64,
+				counts.rate_limited as i64,
+			])?;
+		}
+		tx.execute(
+			"DELETE FROM peer_protocol_stats WHERE day < ?1",
+			params![cutoff.to_string()],
+		)?;
+	}
+	tx.commit()?;
+	Ok(())
+}
+
+/// Daily totals of `peer` since `since`, newest day first.
+pub fn load_protocol_stats(
+	conn: &Connection,
+	peer: &PeerId,
+	since: NaiveDate,
+) -> anyhow::Result<Vec<ProtocolDay>> {
+	let mut stmt = conn.prepare(
+		"SELECT day, request, requests, sent, bytes_in, bytes_out, errors, denials, rate_limited
+		FROM peer_protocol_stats
+		WHERE peer = ?1 AND day >= ?2
+		ORDER BY day DESC, requests DESC, request",
+	)?;
+	let rows = stmt.query_map(params![peer.to_string(), since.to_string()], |row| {
+		let count = |index: usize| row.get::<_, i64>(index).map(|value| value.max(0) as u64);
+		let counts = ProtocolCounts {
+			requests: count(2)?,
+			sent: count(3)?,
+			bytes_in: count(4)?,
+			bytes_out: count(5)?,
+			errors: count(6)?,
+			denials: count(7)?,
+			rate_limited: count(8)?,
+		};
+		Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, counts))
+	})?;
+	let mut days = Vec::new();
+	for row in rows {
+		let (day, request, counts) = row?;
+		days.push(ProtocolDay {
+			day: NaiveDate::from_str(&day)?,
+			request,
+			counts,
+		});
+	}
+	Ok(days)
+}
+
+/// Adds `rows` of (peer, volume) to the totals of `day` and drops days
+/// past the retention.
+pub fn record_access_volume(
+	conn: &Connection,
+	day: NaiveDate,
+	rows: &[(String, AccessVolume)],
+) -> anyhow::Result<()> {
+	let cutoff = day - chrono::Days::new(PROTOCOL_STATS_RETENTION_DAYS);
+	let day = day.to_string();
+	let tx = conn.unchecked_transaction()?;
+	{
+		let mut stmt = tx.prepare(
+			"INSERT INTO peer_access_volume (day, peer, read, written, requests)
+			VALUES (?1, ?2, ?3, ?4, ?5)
+			ON CONFLICT(day, peer) DO UPDATE SET
+				read = read + excluded.read,
+				written = written + excluded.written,
+				requests = requests + excluded.requests",
+		)?;
+		for (peer, volume) in rows {
+			stmt.execute(params![
+				day,
+				peer,
+				volume.read as i64,
+				volume.written as i64,
+				volume.requests as i64,
+			])?;
+		}
+		tx.execute(
+			"DELETE FROM peer_access_volume WHERE day < ?1",
+			params![cutoff.to_string()],
+		)?;
+	}
+	tx.commit()?;
+	Ok(())
+}
+
+/// Daily totals of every peer since `since`, as (peer, day, volume).
+pub fn load_access_volume(
+	conn: &Connection,
+	since: NaiveDate,
+) -> anyhow::Result<Vec<(String, NaiveDate, AccessVolume)>> {
+	let mut stmt = conn.prepare(
+		"SELECT peer, day, read, written, requests
+		FROM peer_access_volume
+		WHERE day >= ?1",
+	)?;
+	let rows = stmt.query_map(params![since.to_string()], |row| {
+		let count = |index: usize| row.get::<_, i64>(index).map(|value| value.max(0) as u64);
+		let volume = AccessVolume {
+			read: count(2)?,
+			written: count(3)?,
+			requests: count(4)?,
+		};
+		Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, volume))
+	})?;
+	let mut volumes = Vec::new();
+	for row in rows {
+		let (peer, day, volume) = row?;
+		volumes.push((peer, NaiveDate::from_str(&day)?, volume));
+	}
+	Ok(volumes)
+}
+
+/// Stores `snapshot` and returns its id; `snapshot.id` is ignored.
+pub fn save_config_snapshot(conn: &Connection, snapshot: &ConfigSnapshot) -> anyhow::Result<i64> {
+	conn.execute(
+		"INSERT INTO config_snapshots (label, reason, taken_at, version, config)
+		VALUES (?1, ?2, ?3, ?4, ?5)",
+		params![
+			snapshot.label,
+			snapshot.reason,
+			snapshot.taken_at.timestamp(),
+			snapshot.version,
+			serde_json::to_string(&snapshot.config)?,
+		],
+	)?;
+	Ok(conn.last_insert_rowid())
+}
+
+/// Drops unlabeled snapshots beyond the newest `keep`; labeled ones stay.
+pub fn prune_config_snapshots(conn: &Connection, keep: usize) -> anyhow::Result<usize> {
+	Ok(conn.execute(
+		"DELETE FROM config_snapshots WHERE label IS NULL AND id NOT IN (
+			SELECT id FROM config_snapshots WHERE label IS NULL ORDER BY id DESC LIMIT ?1
+		)",
+		params![keep as i64],
+	)?)
+}
+
+fn config_snapshot_from_row(row: &Row) -> anyhow::Result<ConfigSnapshot> {
+	let taken_at: i64 = row.get(3)?;
+	let config: String = row.get(5)?;
+	Ok(ConfigSnapshot {
+		id: row.get(0)?,
+		label: row.get(1)?,
+		reason: row.get(2)?,
+		taken_at: DateTime::from_timestamp(taken_at, 0).unwrap_or_default(),
+		version: row.get(4)?,
+		config: serde_json::from_str(&config)?,
+	})
+}
+
+pub fn load_config_snapshot(conn: &Connection, id: i64) -> anyhow::Result<Option<ConfigSnapshot>> {
+	let mut stmt = conn.prepare(
+		"SELECT id, label, reason, taken_at, version, config FROM config_snapshots WHERE id = ?1",
+	)?;
+	let mut rows = stmt.query(params![id])?;
+	match rows.next()? {
+		Some(row) => Ok(Some(config_snapshot_from_row(row)?)),
+		None => Ok(None),
+	}
+}
+
+/// Every stored snapshot, newest first.
+pub fn load_config_snapshots(conn: &Connection) -> anyhow::Result<Vec<ConfigSnapshot>> {
+	let mut stmt = conn.prepare(
+		"SELECT id, label, reason, taken_at, version, config FROM config_snapshots ORDER BY id DESC",
+	)?;
+	let mut rows = stmt.query([])?;
+	let mut snapshots = Vec::new();
+	while let Some(row) = rows.next()? {
+		snapshots.push(config_snapshot_from_row(row)?);
+	}
+	Ok(snapshots)
+}
+
+pub fn record_clock_offset(
+	conn: &Connection,
+	peer: &PeerId,
+	record: &ClockOffsetRecord,
+) -> anyhow::Result<()> {
+	conn.execute(
+		"INSERT INTO clock_offsets (peer_id, sampled_at, offset_ms, sample_offset_ms, rtt_ms)
+		VALUES (?1, ?2, ?3, ?4, ?5)",
+		params![
+			peer.to_string(),
+			record.sampled_at.timestamp(),
+			record.offset_ms,
+			record.sample_offset_ms,
+			record.rtt_ms,
+		],
+	)?;
+	Ok(())
+}
+
+pub fn prune_clock_offsets(conn: &Connection, before: DateTime<Utc>) -> anyhow::Result<usize> {
+	Ok(conn.execute(
+		"DELETE FROM clock_offsets WHERE sampled_at < ?1",
+		params![before.timestamp()],
+	)?)
+}
+
+/// Recorded clock offsets of `peer` since `from`, oldest first.
+pub fn load_clock_offsets(
+	conn: &Connection,
+	peer: &PeerId,
+	from: DateTime<Utc>,
+) -> anyhow::Result<Vec<ClockOffsetRecord>> {
+	let mut stmt = conn.prepare(
+		"SELECT sampled_at, offset_ms, sample_offset_ms, rtt_ms FROM clock_offsets
+		WHERE peer_id = ?1 AND sampled_at >= ?2
+		ORDER BY sampled_at ASC",
+	)?;
+	let rows = stmt.query_map(params![peer.to_string(), from.timestamp()], |row| {
+		Ok(ClockOffsetRecord {
+			sampled_at: DateTime::from_timestamp(row.get(0)?, 0).unwrap_or_default(),
+			offset_ms: row.get(1)?,
+			sample_offset_ms: row.get(2)?,
+			rtt_ms: row.get(3)?,
+		})
+	})?;
+	let mut records = Vec::new();
+	for row in rows {
+		records.push(row?);
+	}
+	Ok(records)
+}
+
+/// Queues a write by `peer` for review. A file that is already queued is
+/// updated in place, and its hash is recomputed when the queue is read.
+pub fn record_pending_review(
+	conn: &Connection,
+	peer: &PeerId,
+	path: &str,
+	size: u64,
+	received_at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+	conn.execute(
+		"INSERT INTO pending_reviews (peer_id, path, size, received_at)
+		VALUES (?1, ?2, ?3, ?4)
+		ON CONFLICT (peer_id, path) DO UPDATE SET
+			hash = NULL, size = excluded.size, received_at = excluded.received_at,
+			status = 'pending'",
+		params![peer.to_string(), path, size as i64, received_at.timestamp()],
+	)?;
+	Ok(())
+}
+
+/// Clears a rejection of `path` by `peer`. Returns whether there was one,
+/// i.e. whether the peer still has to be told.
+pub fn take_rejected_review(conn: &Connection, peer: &PeerId, path: &str) -> anyhow::Result<bool> {
+	Ok(conn.execute(
+		"DELETE FROM pending_reviews WHERE peer_id = ?1 AND path = ?2 AND status = 'rejected'",
+		params![peer.to_string(), path],
+	)? > 0)
+}
+
+const PENDING_REVIEW_COLUMNS: &str = "id, peer_id, path, hash, size, received_at";
+
+fn pending_review_row(row: &Row<'_>) -> rusqlite::Result<PendingReview> {
+	Ok(PendingReview {
+		id: row.get(0)?,
+		peer: row.get(1)?,
+		path: row.get(2)?,
+		hash: row.get(3)?,
+		size: row.get::<_, i64>(4)?.max(0) as u64,
+		received_at: DateTime::from_timestamp(row.get(5)?, 0).unwrap_or_default(),
+	})
+}
+
+/// Writes waiting for a decision, oldest first.
+pub fn load_pending_reviews(conn: &Connection) -> anyhow::Result<Vec<PendingReview>> {
+	let mut stmt = conn.prepare(&format!(
+		"SELECT {PENDING_REVIEW_COLUMNS} FROM pending_reviews
+		WHERE status = 'pending' ORDER BY received_at ASC, id ASC"
+	))?;
+	let rows = stmt.query_map([], pending_review_row)?;
+	let mut reviews = Vec::new();
+	for row in rows {
+		reviews.push(row?);
+	}
+	Ok(reviews)
+}
+
+pub fn count_pending_reviews(conn: &Connection) -> anyhow::Result<u64> {
+	let count: i64 = conn.query_row(
+		"SELECT COUNT(*) FROM pending_reviews WHERE status = 'pending'",
+		[],
+		|row| row.get(0),
+	)?;
+	Ok(count.max(0) as u64)
+}
+
+/// Stores the hash of a queued file unless it was written again meanwhile.
+pub fn set_pending_review_hash(
+	conn: &Connection,
+	id: i64,
+	received_at: DateTime<Utc>,
+	hash: &[u8],
+) -> anyhow::Result<()> {
+	conn.execute(
+		"UPDATE pending_reviews SET hash = ?1 WHERE id = ?2 AND received_at = ?3",
+		params![hash, id, received_at.timestamp()],
+	)?;
+	Ok(())
+}
+
+/// Applies `decision` to the pending reviews in `ids` and counts it towards
+/// each peer's trust. Returns the entries decided; ids that are unknown or
+/// already decided are skipped.
+pub fn decide_reviews(
+	conn: &Connection,
+	ids: &[i64],
+	decision: ReviewDecision,
+) -> anyhow::Result<Vec<PendingReview>> {
+	let tx = conn.unchecked_transaction()?;
+	let mut decided = Vec::new();
+	{
+		let mut select = tx.prepare(&format!(
+			"SELECT {PENDING_REVIEW_COLUMNS} FROM pending_reviews
+			WHERE id = ?1 AND status = 'pending'"
+		))?;
+		let mut update = tx.prepare(match decision {
+			ReviewDecision::Accept => "DELETE FROM pending_reviews WHERE id = ?1",
+			ReviewDecision::Reject => {
+				"UPDATE pending_reviews SET status = 'rejected' WHERE id = ?1"
+			}
+		})?;
+		let mut trust = tx.prepare(match decision {
+			ReviewDecision::Accept => {
+				"INSERT INTO peer_trust (peer_id, accepted) VALUES (?1, 1)
+				ON CONFLICT (peer_id) DO UPDATE SET accepted = accepted + 1"
+			}
+			ReviewDecision::Reject => {
+				"INSERT INTO peer_trust (peer_id, rejected) VALUES (?1, 1)
+				ON CONFLICT (peer_id) DO UPDATE SET rejected = rejected + 1"
+			}
+		})?;
+		for id in ids {
+			let Some(review) = select
+				.query_map(params![id], pending_review_row)?
+				.next()
+				.transpose()?
+			else {
+				continue;
+			};
+			update.execute(params![id])?;
+			trust.execute(params![review.peer])?;
+			decided.push(review);
+		}
+	}
+	tx.commit()?;
+	Ok(decided)
+}
+
+pub fn load_peer_trust(conn: &Connection, peer: &PeerId) -> anyhow::Result<PeerTrust> {
+	let mut stmt = conn.prepare("SELECT accepted, rejected FROM peer_trust WHERE peer_id = ?1")?;
+	let mut rows = stmt.query_map(params![peer.to_string()], |row| {
+		Ok(PeerTrust {
+			accepted: row.get::<_, i64>(0)?.max(0) as u64,
+			rejected: row.get::<_, i64>(1)?.max(0) as u64,
+		})
+	})?;
+	Ok(rows.next().transpose()?.unwrap_or_default())
+}
+
+/// Live files with content `hash` in the index of any node, as
+/// `(node, path)`.
+pub fn live_locations_of_hash(
+	conn: &Connection,
+	hash: &FileHash,
+) -> anyhow::Result<Vec<(NodeID, PathBuf)>> {
+	let mut stmt = conn.prepare(
+		"SELECT node_id, path FROM file_locations
+		WHERE hash = ?1 AND deleted_at IS NULL ORDER BY node_id, path",
+	)?;
+	let rows = stmt.query_map(params![&hash[..]], |row| {
+		Ok((row.get::<_, Vec<u8>>(0)?, path_column(row, 1)?))
+	})?;
+	let mut locations = Vec::new();
+	for row in rows {
+		let (node_id, path) = row?;
+		if let Ok(node_id) = NodeID::try_from(node_id.as_slice()) {
+			locations.push((node_id, path));
+		}
+	}
+	Ok(locations)
+}
+
+/// Records a proposal this node made, with its holders.
+pub fn record_delete_proposal(conn: &Connection, proposal: &DeleteProposal) -> anyhow::Result<()> {
+	let tx = conn.unchecked_transaction()?;
+	tx.execute(
+		"INSERT INTO delete_proposals (id, hash, reason, created_at, expires_at)
+		VALUES (?1, ?2, ?3, ?4, ?5)",
+		params![
+			proposal.id,
+			&proposal.hash[..],
+			proposal.reason,
+			proposal.created_at.timestamp(),
+			proposal.expires_at.timestamp(),
+		],
+	)?;
+	{
+		let mut stmt = tx.prepare(
+			"INSERT INTO delete_proposal_holders
+				(proposal_id, peer_id, paths, status, detail, updated_at)
+			VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+		)?;
+		for holder in &proposal.holders {
+			stmt.execute(params![
+				proposal.id,
+				holder.peer,
+				serde_json::to_string(&holder.paths)?,
+				holder.status.as_str(),
+				holder.detail,
+				holder.updated_at.timestamp(),
+			])?;
+		}
+	}
+	let mut summary = format!(
+		"Proposed deleting {} from {} device(s)",
+		proposal.subject(),
+		proposal.holders.len()
+	);
+	if !proposal.reason.is_empty() {
+		summary.push_str(&format!(": {}", proposal.reason));
+	}
+	record_activity(
+		&tx,
+		&ActivityEntry::new(ActivityEventKind::Deletion, proposal.created_at, summary)
+			.ref_id(&proposal.id),
+	)?;
+	tx.commit()?;
+	Ok(())
+}
+
+fn delete_proposal_holder_row(row: &Row<'_>) -> rusqlite::Result<(String, ProposalHolder)> {
+	let paths: String = row.get(2)?;
+	let status: String = row.get(3)?;
+	Ok((
+		row.get(0)?,
+		ProposalHolder {
+			peer: row.get(1)?,
+			paths: serde_json::from_str(&paths).unwrap_or_default(),
+			status: DeletionStatus::parse(&status).unwrap_or(DeletionStatus::Undelivered),
+			detail: row.get(4)?,
+			updated_at: DateTime::from_timestamp(row.get(5)?, 0).unwrap_or_default(),
+		},
+	))
+}
+
+/// Proposals with the holders matching `holder_filter`, a condition on
+/// `delete_proposal_holders` bound to `holder_params`. Proposals without a
+/// matching holder are left out.
+fn load_delete_proposals_where(
+	conn: &Connection,
+	holder_filter: &str,
+	holder_params: &[&dyn ToSql],
+) -> anyhow::Result<Vec<DeleteProposal>> {
+	let mut holders: HashMap<String, Vec<ProposalHolder>> = HashMap::new();
+	{
+		let mut stmt = conn.prepare(&format!(
+			"SELECT proposal_id, peer_id, paths, status, detail, updated_at
+			FROM delete_proposal_holders WHERE {holder_filter} ORDER BY peer_id"
+		))?;
+		for row in stmt.query_map(holder_params, delete_proposal_holder_row)? {
+			let (id, holder) = row?;
+			holders.entry(id).or_default().push(holder);
+		}
+	}
+	let mut stmt = conn.prepare(
+		"SELECT id, hash, reason, created_at, expires_at FROM delete_proposals
+		ORDER BY created_at DESC, id",
+	)?;
+	let rows = stmt.query_map([], |row| {
+		Ok((
+			row.get::<_, String>(0)?,
+			row.get::<_, Vec<u8>>(1)?,
+			row.get::<_, String>(2)?,
+			row.get::<_, i64>(3)?,
+			row.get::<_, i64>(4)?,
+		))
+	})?;
+	let mut proposals = Vec::new();
+	for row in rows {
+		let (id, hash, reason, created_at, expires_at) = row?;
+		let Some(holders) = holders.remove(&id) else {
+			continue;
+		};
+		let Ok(hash) = FileHash::try_from(hash.as_slice()) else {
+			continue;
+		};
+		proposals.push(DeleteProposal {
+			id,
+			hash,
+			reason,
+			created_at: DateTime::from_timestamp(created_at, 0).unwrap_or_default(),
+			expires_at: DateTime::from_timestamp(expires_at, 0).unwrap_or_default(),
+			holders,
+		});
+	}
+	Ok(proposals)
+}
+
+/// Proposals this node made, newest first.
+pub fn load_delete_proposals(conn: &Connection) -> anyhow::Result<Vec<DeleteProposal>> {
+	load_delete_proposals_where(conn, "1 = 1", &[])
+}
+
+/// Proposals `peer` holds a copy for and hasn't received, with only its
+/// entry among the holders.
+pub fn undelivered_delete_proposals(
+	conn: &Connection,
+	peer: &PeerId,
+) -> anyhow::Result<Vec<DeleteProposal>> {
+	load_delete_proposals_where(
+		conn,
+		"peer_id = ?1 AND status = 'undelivered'",
+		&[&peer.to_string()],
+	)
+}
+
+/// Sets where a proposal stands with `peer` while no decision came yet, so
+/// a late delivery answer can't hide one. Returns whether it changed.
+pub fn set_delete_holder_status(
+	conn: &Connection,
+	id: &str,
+	peer: &PeerId,
+	status: DeletionStatus,
+	detail: Option<&str>,
+	now: DateTime<Utc>,
+) -> anyhow::Result<bool> {
+	Ok(conn.execute(
+		"UPDATE delete_proposal_holders SET status = ?1, detail = ?2, updated_at = ?3
+		WHERE proposal_id = ?4 AND peer_id = ?5 AND status IN ('undelivered', 'pending')",
+		params![
+			status.as_str(),
+			detail,
+			now.timestamp(),
+			id,
+			peer.to_string()
+		],
+	)? > 0)
+}
+
+/// Records what `peer` did with proposal `id`. Returns false when `peer`
+/// holds no copy for such a proposal.
+pub fn record_delete_outcome(
+	conn: &Connection,
+	id: &str,
+	peer: &PeerId,
+	outcome: &DeleteOutcome,
+	now: DateTime<Utc>,
+) -> anyhow::Result<bool> {
+	let tx = conn.unchecked_transaction()?;
+	let holder = {
+		let mut stmt = tx.prepare(
+			"SELECT h.paths, p.hash FROM delete_proposal_holders h
+			JOIN delete_proposals p ON p.id = h.proposal_id
+			WHERE h.proposal_id = ?1 AND h.peer_id = ?2",
+		)?;
+		stmt.query_map(params![id, peer.to_string()], |row| {
+			Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+		})?
+		.next()
+		.transpose()?
+	};
+	let Some((paths, hash)) = holder else {
+		return Ok(false);
+	};
+	// An outcome reported again changes nothing.
+	let changed = tx.execute(
+		"UPDATE delete_proposal_holders SET status = ?1, detail = ?2, updated_at = ?3
+		WHERE proposal_id = ?4 AND peer_id = ?5 AND (status, detail) IS NOT (?1, ?2)",
+		params![
+			outcome.status().as_str(),
+			outcome.detail(),
+			now.timestamp(),
+			id,
+			peer.to_string()
+		],
+	)?;
+	if changed == 0 {
+		return Ok(true);
+	}
+	let paths: Vec<String> = serde_json::from_str(&paths).unwrap_or_default();
+	let subject = crate::deletion::subject(&paths, &FileHash::try_from(hash.as_slice())?);
+	let summary = match outcome {
+		DeleteOutcome::Deleted { removed } => format!("Deleted {subject} ({removed} copies)"),
+		DeleteOutcome::Rejected { reason } if reason.is_empty() => {
+			format!("Rejected deleting {subject}")
+		}
+		DeleteOutcome::Rejected { reason } => format!("Rejected deleting {subject}: {reason}"),
+		DeleteOutcome::Expired => format!("Proposal to delete {subject} expired"),
+		DeleteOutcome::Failed { error } => format!("Failed to delete {subject}: {error}"),
+	};
+	record_activity(
+		&tx,
+		&ActivityEntry::new(ActivityEventKind::Deletion, now, summary)
+			.peer(peer)
+			.ref_id(id),
+	)?;
+	tx.commit()?;
+	Ok(true)
+}
+
+const RECEIVED_DELETE_PROPOSAL_COLUMNS: &str =
+	"id, proposal_id, proposer, hash, paths_hint, reason, received_at, expires_at, outcome";
+
+fn received_delete_proposal_row(row: &Row<'_>) -> rusqlite::Result<ReceivedProposal> {
+	let hash: Vec<u8> = row.get(3)?;
+	let paths_hint: String = row.get(4)?;
+	let outcome: Option<String> = row.get(8)?;
+	Ok(ReceivedProposal {
+		id: row.get(0)?,
+		proposal_id: row.get(1)?,
+		proposer: row.get(2)?,
+		hash: FileHash::try_from(hash.as_slice()).unwrap_or_default(),
+		paths_hint: serde_json::from_str(&paths_hint).unwrap_or_default(),
+		reason: row.get(5)?,
+		received_at: DateTime::from_timestamp(row.get(6)?, 0).unwrap_or_default(),
+		expires_at: DateTime::from_timestamp(row.get(7)?, 0).unwrap_or_default(),
+		outcome: outcome.and_then(|outcome| serde_json::from_str(&outcome).ok()),
+	})
+}
+
+/// Queues a proposal from a peer and records it on the timeline. Returns
+/// its row id.
+pub fn insert_received_delete_proposal(
+	conn: &Connection,
+	proposal: &ReceivedProposal,
+) -> anyhow::Result<i64> {
+	let tx = conn.unchecked_transaction()?;
+	tx.execute(
+		"INSERT INTO received_delete_proposals
+			(proposer, proposal_id, hash, paths_hint, reason, received_at, expires_at)
+		VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+		params![
+			proposal.proposer,
+			proposal.proposal_id,
+			&proposal.hash[..],
+			serde_json::to_string(&proposal.paths_hint)?,
+			proposal.reason,
+			proposal.received_at.timestamp(),
+			proposal.expires_at.timestamp(),
+		],
+	)?;
+	let id = tx.last_insert_rowid();
+	let mut summary = format!("Asked to delete {}", proposal.subject());
+	if !proposal.reason.is_empty() {
+		summary.push_str(&format!(": {}", proposal.reason));
+	}
+	let mut entry =
+		ActivityEntry::new(ActivityEventKind::Deletion, proposal.received_at, summary).ref_id(id);
+	entry.peer = Some(proposal.proposer.clone());
+	record_activity(&tx, &entry)?;
+	tx.commit()?;
+	Ok(id)
+}
+
+pub fn load_received_delete_proposal(
+	conn: &Connection,
+	id: i64,
+) -> anyhow::Result<Option<ReceivedProposal>> {
+	let mut stmt = conn.prepare(&format!(
+		"SELECT {RECEIVED_DELETE_PROPOSAL_COLUMNS} FROM received_delete_proposals WHERE id = ?1"
+	))?;
+	Ok(stmt
+		.query_map(params![id], received_delete_proposal_row)?
+		.next()
+		.transpose()?)
+}
+
+/// The proposal `proposer` sent under `proposal_id`, if it was received.
+pub fn find_received_delete_proposal(
+	conn: &Connection,
+	proposer: &PeerId,
+	proposal_id: &str,
+) -> anyhow::Result<Option<ReceivedProposal>> {
+	let mut stmt = conn.prepare(&format!(
+		"SELECT {RECEIVED_DELETE_PROPOSAL_COLUMNS} FROM received_delete_proposals
+		WHERE proposer = ?1 AND proposal_id = ?2"
+	))?;
+	Ok(stmt
+		.query_map(
+			params![proposer.to_string(), proposal_id],
+			received_delete_proposal_row,
+		)?
+		.next()
+		.transpose()?)
+}
+
+/// Received proposals waiting for the owner, oldest first.
+pub fn load_pending_delete_proposals(conn: &Connection) -> anyhow::Result<Vec<ReceivedProposal>> {
+	let mut stmt = conn.prepare(&format!(
+		"SELECT {RECEIVED_DELETE_PROPOSAL_COLUMNS} FROM received_delete_proposals
+		WHERE status = 'pending' ORDER BY received_at ASC, id ASC"
+	))?;
+	let rows = stmt.query_map([], received_delete_proposal_row)?;
+	let mut proposals = Vec::new();
+	for row in rows {
+		proposals.push(row?);
+	}
+	Ok(proposals)
+}
+
+/// Received proposals waiting for the owner, from `proposer` or anyone.
+pub fn count_pending_delete_proposals(
+	conn: &Connection,
+	proposer: Option<&PeerId>,
+) -> anyhow::Result<u64> {
+	let count: i64 = conn.query_row(
+		"SELECT COUNT(*) FROM received_delete_proposals
+		WHERE status = 'pending' AND (?1 IS NULL OR proposer = ?1)",
+		params![proposer.map(PeerId::to_string)],
+		|row| row.get(0),
+	)?;
+	Ok(count.max(0) as u64)
+}
+
+/// Stores the outcome of a pending received proposal and records it on the
+/// timeline. Returns false when it was no longer pending.
+fn settle_received_delete_proposal(
+	conn: &Connection,
+	proposal: &ReceivedProposal,
+	outcome: &DeleteOutcome,
+	now: DateTime<Utc>,
+) -> anyhow::Result<bool> {
+	let changed = conn.execute(
+		"UPDATE received_delete_proposals SET status = ?1, outcome = ?2, decided_at = ?3
+		WHERE id = ?4 AND status = 'pending'",
+		params![
+			outcome.status().as_str(),
+			serde_json::to_string(outcome)?,
+			now.timestamp(),
+			proposal.id
+		],
+	)?;
+	if changed == 0 {
+		return Ok(false);
+	}
+	let subject = proposal.subject();
+	let summary = match outcome {
+		DeleteOutcome::Deleted { removed } => {
+			format!("Approved deleting {subject}, removed {removed} copies")
+		}
+		DeleteOutcome::Rejected { reason } if reason.is_empty() => {
+			format!("Declined deleting {subject}")
+		}
+		DeleteOutcome::Rejected { reason } => format!("Declined deleting {subject}: {reason}"),
+		DeleteOutcome::Expired => format!("Proposal to delete {subject} expired"),
+		DeleteOutcome::Failed { error } => format!("Failed to delete {subject}: {error}"),
+	};
+	let mut entry =
+		ActivityEntry::new(ActivityEventKind::Deletion, now, summary).ref_id(proposal.id);
+	entry.peer = Some(proposal.proposer.clone());
+	record_activity(conn, &entry)?;
+	Ok(true)
+}
+
+/// Stores the owner's decision on a pending proposal, tombstoning the
+/// copies at `removed` in `node_id`'s index. Returns false when it was no
+/// longer pending.
+pub fn decide_received_delete_proposal(
+	conn: &Connection,
+	proposal: &ReceivedProposal,
+	node_id: &NodeID,
+	removed: &[PathBuf],
+	outcome: &DeleteOutcome,
+	now: DateTime<Utc>,
+) -> anyhow::Result<bool> {
+	let tx = conn.unchecked_transaction()?;
+	if !settle_received_delete_proposal(&tx, proposal, outcome, now)? {
+		return Ok(false);
+	}
+	for path in removed {
+		tx.execute(
+			"UPDATE file_locations SET deleted_at = ?1, deleted_by_run = NULL
+			WHERE node_id = ?2 AND path = ?3 AND deleted_at IS NULL",
+			params![now.timestamp(), &node_id[..], path_to_sql(path)],
+		)?;
+	}
+	tx.commit()?;
+	Ok(true)
+}
+
+/// Outcomes of `proposer`'s proposals it hasn't been told, by its ids.
+pub fn unreported_delete_outcomes(
+	conn: &Connection,
+	proposer: &PeerId,
+) -> anyhow::Result<Vec<(String, DeleteOutcome)>> {
+	let mut stmt = conn.prepare(
+		"SELECT proposal_id, outcome FROM received_delete_proposals
+		WHERE proposer = ?1 AND status != 'pending' AND reported = 0 ORDER BY decided_at, id",
+	)?;
+	let rows = stmt.query_map(params![proposer.to_string()], |row| {
+		Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+	})?;
+	let mut outcomes = Vec::new();
+	for row in rows {
+		let (id, outcome) = row?;
+		if let Some(outcome) = outcome.and_then(|outcome| serde_json::from_str(&outcome).ok()) {
+			outcomes.push((id, outcome));
+		}
+	}
+	Ok(outcomes)
+}
+
+pub fn mark_delete_outcome_reported(
+	conn: &Connection,
+	proposer: &PeerId,
+	proposal_id: &str,
+) -> anyhow::Result<()> {
+	conn.execute(
+		"UPDATE received_delete_proposals SET reported = 1
+		WHERE proposer = ?1 AND proposal_id = ?2 AND status != 'pending'",
+		params![proposer.to_string(), proposal_id],
+	)?;
+	Ok(())
+}
+
+/// Expires proposals past their expiry on both sides: holders of this
+/// node's proposals that didn't decide, and received proposals the owner
+/// didn't decide, whose proposer is then told. Returns how many expired.
+pub fn expire_delete_proposals(conn: &Connection, now: DateTime<Utc>) -> anyhow::Result<usize> {
+	let tx = conn.unchecked_transaction()?;
+	let lapsed = load_delete_proposals_where(
+		&tx,
+		"status IN ('undelivered', 'pending') AND proposal_id IN (
+			SELECT id FROM delete_proposals WHERE expires_at <= ?1
+		)",
+		&[&now.timestamp()],
+	)?;
+	let holders = tx.execute(
+		"UPDATE delete_proposal_holders SET status = 'expired', detail = NULL, updated_at = ?1
+		WHERE status IN ('undelivered', 'pending') AND proposal_id IN (
+			SELECT id FROM delete_proposals WHERE expires_at <= ?1
+		)",
+		params![now.timestamp()],
+	)?;
+	for proposal in &lapsed {
+		record_activity(
+			&tx,
+			&ActivityEntry::new(
+				ActivityEventKind::Deletion,
+				now,
+				format!(
+					"Proposal to delete {} expired without an answer from {} device(s)",
+					proposal.subject(),
+					proposal.holders.len()
+				),
+			)
+			.ref_id(&proposal.id),
+		)?;
+	}
+	let expired = {
+		let mut stmt = tx.prepare(&format!(
+			"SELECT {RECEIVED_DELETE_PROPOSAL_COLUMNS} FROM received_delete_proposals
+			WHERE status = 'pending' AND expires_at <= ?1"
+		))?;
+		let rows = stmt.query_map(params![now.timestamp()], received_delete_proposal_row)?;
+		rows.collect::<rusqlite::Result<Vec<_>>>()?
+	};
+	for proposal in &expired {
+		settle_received_delete_proposal(&tx, proposal, &DeleteOutcome::Expired, now)?;
+	}
+	tx.commit()?;
+	Ok(holders + expired.len())
+}
+
+/// Records the MAC address and network `peer` was last seen with. A MAC
+/// the user set stays; only the network follows the peer.
+pub fn record_wake_target(
+	conn: &Connection,
+	peer: &PeerId,
+	mac: &str,
+	subnet: Option<&str>,
+	now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+	conn.execute(
+		"INSERT INTO peer_wake (peer_id, mac, subnet, updated_at) VALUES (?1, ?2, ?3, ?4)
+		ON CONFLICT(peer_id) DO UPDATE SET
+			mac = CASE WHEN confirmed THEN mac ELSE excluded.mac END,
+			subnet = excluded.subnet,
+			updated_at = excluded.updated_at",
+		params![peer.to_string(), mac, subnet, now.timestamp()],
+	)?;
+	Ok(())
+}
+
+/// Sets the MAC address of `peer` by hand.
+pub fn confirm_wake_mac(
+	conn: &Connection,
+	peer: &PeerId,
+	mac: &str,
+	now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+	conn.execute(
+		"INSERT INTO peer_wake (peer_id, mac, confirmed, updated_at) VALUES (?1, ?2, 1, ?3)
+		ON CONFLICT(peer_id) DO UPDATE SET
+			mac = excluded.mac,
+			confirmed = 1,
+			updated_at = excluded.updated_at",
+		params![peer.to_string(), mac, now.timestamp()],
+	)?;
+	Ok(())
+}
+
+pub fn load_wake_target(conn: &Connection, peer: &PeerId) -> anyhow::Result<Option<WakeTarget>> {
+	let mut stmt = conn.prepare(
+		"SELECT peer_id, mac, subnet, confirmed, updated_at FROM peer_wake WHERE peer_id = ?1",
+	)?;
+	let mut rows = stmt.query_map(params![peer.to_string()], |row| {
+		Ok(WakeTarget {
+			peer: row.get(0)?,
+			mac: row.get(1)?,
+			subnet: row.get(2)?,
+			confirmed: row.get(3)?,
+			updated_at: DateTime::from_timestamp(row.get(4)?, 0).unwrap_or_default(),
+		})
+	})?;
+	Ok(rows.next().transpose()?)
+}
+
+pub fn record_login_attempts(conn: &Connection, attempts: &[LoginAttempt]) -> anyhow::Result<()> {
+	let tx = conn.unchecked_transaction()?;
+	{
+		let mut stmt = tx.prepare(
+			"INSERT INTO login_attempts (attempted_at, username, source_ip, user_agent, outcome)
+			VALUES (?1, ?2, ?3, ?4, ?5)",
+		)?;
+		for attempt in attempts {
+			stmt.execute(params![
+				attempt.at.timestamp(),
+				attempt.username,
+				attempt.source_ip,
+				attempt.user_agent,
+				attempt.outcome.as_str(),
+			])?;
+		}
+	}
+	tx.commit()?;
+	Ok(())
+}
+
+/// Login attempts, newest first, optionally for one username.
+pub fn load_login_history(
+	conn: &Connection,
+	username: Option<&str>,
+	offset: u64,
+	limit: u64,
+) -> anyhow::Result<Vec<LoginAttempt>> {
+	let mut stmt = conn.prepare(
+		"SELECT attempted_at, username, source_ip, user_agent, outcome FROM login_attempts
+		WHERE (?1 IS NULL OR username = ?1)
+		ORDER BY attempted_at DESC, id DESC LIMIT ?2 OFFSET ?3",
+	)?;
+	let rows = stmt.query_map(params![username, limit as i64, offset as i64], |row| {
+		let outcome: String = row.get(4)?;
+		Ok(LoginAttempt {
+			at: DateTime::from_timestamp(row.get(0)?, 0).unwrap_or_default(),
+			username: row.get(1)?,
+			source_ip: row.get(2)?,
+			user_agent: row.get(3)?,
+			outcome: LoginOutcome::parse(&outcome).unwrap_or(LoginOutcome::Failure),
+		})
+	})?;
+	let mut attempts = Vec::new();
+	for row in rows {
+		attempts.push(row?);
+	}
+	Ok(attempts)
+}
+
+/// Failed logins since `since`, grouped by username and source address,
+/// most failures first.
+pub fn failed_logins_since(
+	conn: &Connection,
+	since: DateTime<Utc>,
+) -> anyhow::Result<Vec<FailedLoginGroup>> {
+	let mut stmt = conn.prepare(
+		"SELECT username, source_ip, COUNT(*), MAX(attempted_at) FROM login_attempts
+		WHERE attempted_at >= ?1 AND outcome != 'success'
+		GROUP BY username, source_ip
+		ORDER BY COUNT(*) DESC, MAX(attempted_at) DESC",
+	)?;
+	let rows = stmt.query_map(params![since.timestamp()], |row| {
+		Ok(FailedLoginGroup {
+			username: row.get(0)?,
+			source_ip: row.get(1)?,
+			count: row.get::<_, i64>(2)?.max(0) as u64,
+			last_at: DateTime::from_timestamp(row.get(3)?, 0).unwrap_or_default(),
+		})
+	})?;
+	let mut groups = Vec::new();
+	for row in rows {
+		groups.push(row?);
+	}
+	Ok(groups)
+}
+
+pub fn record_backup_run(conn: &Connection, run: &BackupRun) -> anyhow::Result<()> {
+	conn.execute(
+		"INSERT INTO backup_runs (kind, started_at, finished_at, path, size_bytes, scheduled, error)
+		VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+		params![
+			run.kind.as_str(),
+			run.started_at.timestamp(),
+			run.finished_at.timestamp(),
+			run.path,
+			run.size_bytes.map(|size| size as i64),
+			run.scheduled,
+			run.error,
+		],
+	)?;
+	Ok(())
+}
+
+/// Backups and restores, newest first.
+pub fn load_backup_runs(conn: &Connection, limit: u64) -> anyhow::Result<Vec<BackupRun>> {
+	let mut stmt = conn.prepare(
+		"SELECT kind, started_at, finished_at, path, size_bytes, scheduled, error FROM backup_runs
+		ORDER BY started_at DESC, id DESC LIMIT ?1",
+	)?;
+	let rows = stmt.query_map(params![limit as i64], |row| {
+		let kind: String = row.get(0)?;
+		Ok(BackupRun {
+			kind: BackupKind::parse(&kind).unwrap_or(BackupKind::Backup),
+			started_at: DateTime::from_timestamp(row.get(1)?, 0).unwrap_or_default(),
+			finished_at: DateTime::from_timestamp(row.get(2)?, 0).unwrap_or_default(),
+			path: row.get(3)?,
+			size_bytes: row.get::<_, Option<i64>>(4)?.map(|size| size.max(0) as u64),
+			scheduled: row.get(5)?,
+			error: row.get(6)?,
+		})
+	})?;
+	let mut runs = Vec::new();
+	for row in rows {
+		runs.push(row?);
+	}
+	Ok(runs)
+}
+
+/// Records a transfer, and its timeline event, and prunes the oldest ones
+/// beyond the retention cap.
+pub fn record_transfer(conn: &Connection, transfer: &Transfer) -> anyhow::Result<()> {
+	conn.execute(
+		"INSERT INTO transfers (direction, peer, remote_path, local_path, size, duration_ms,
+			status, error, hash, finished_at, note)
+		VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+		params![
+			transfer.direction.as_str(),
+			transfer.peer,
+			transfer.remote_path,
+			transfer.local_path,
+			transfer.size as i64,
+			transfer.duration_ms as i64,
+			transfer.status.as_str(),
+			transfer.error,
+			transfer.hash,
+			transfer.finished_at.timestamp(),
+			transfer.note,
+		],
+	)?;
+	let id = conn.last_insert_rowid();
+	conn.execute(
+		"DELETE FROM transfers WHERE id <= (SELECT MAX(id) FROM transfers) - ?1",
+		params![TRANSFER_RETENTION as i64],
+	)?;
+	let summary = match (transfer.direction, transfer.status) {
+		(TransferDirection::Download, TransferStatus::Completed) => {
+			format!("Downloaded {}", transfer.remote_path)
+		}
+		(TransferDirection::Upload, TransferStatus::Completed) => {
+			format!("Uploaded {}", transfer.local_path)
+		}
+		(TransferDirection::Download, TransferStatus::Failed) => {
+			format!("Download of {} failed", transfer.remote_path)
+		}
+		(TransferDirection::Upload, TransferStatus::Failed) => {
+			format!("Upload of {} failed", transfer.local_path)
+		}
+	};
+	record_activity(
+		conn,
+		&ActivityEntry::new(ActivityEventKind::Transfer, transfer.finished_at, summary)
+			.peer(&transfer.peer)
+			.ref_id(id),
+	)?;
+	Ok(())
+}
+
+const TRANSFER_COLUMNS: &str = "direction, peer, remote_path, local_path, size, duration_ms, \
+	status, error, hash, finished_at, note";
+
+fn transfer_row(row: &Row<'_>) -> rusqlite::Result<Transfer> {
+	let direction: String = row.get(0)?;
+	let status: String = row.get(6)?;
+	Ok(Transfer {
+		direction: TransferDirection::parse(&direction).unwrap_or(TransferDirection::Download),
+		peer: row.get(1)?,
+		remote_path: row.get(2)?,
+		local_path: row.get(3)?,
+		size: row.get::<_, i64>(4)?.max(0) as u64,
+		duration_ms: row.get::<_, i64>(5)?.max(0) as u64,
+		status: TransferStatus::parse(&status).unwrap_or(TransferStatus::Failed),
+		error: row.get(7)?,
+		hash: row.get(8)?,
+		finished_at: DateTime::from_timestamp(row.get(9)?, 0).unwrap_or_default(),
+		note: row.get(10)?,
+	})
+}
+
+/// Transfers newest first, all of them or those with `peer`.
+pub fn load_transfers(
+	conn: &Connection,
+	peer: Option<&str>,
+	offset: u64,
+	limit: u64,
+) -> anyhow::Result<Vec<Transfer>> {
+	let mut stmt = conn.prepare(&format!(
+		"SELECT {TRANSFER_COLUMNS} FROM transfers WHERE ?1 IS NULL OR peer = ?1
+		ORDER BY id DESC LIMIT ?2 OFFSET ?3"
+	))?;
+	let rows = stmt.query_map(params![peer, limit as i64, offset as i64], transfer_row)?;
+	let mut transfers = Vec::new();
+	for row in rows {
+		transfers.push(row?);
+	}
+	Ok(transfers)
+}
+
+/// The latest completed download of content with `hash`.
+pub fn last_download_of(conn: &Connection, hash: &[u8]) -> anyhow::Result<Option<Transfer>> {
+	let mut stmt = conn.prepare(&format!(
+		"SELECT {TRANSFER_COLUMNS} FROM transfers
+		WHERE hash = ?1 AND direction = 'download' AND status = 'completed'
+		ORDER BY id DESC LIMIT 1"
+	))?;
+	let mut rows = stmt.query_map(params![hash], transfer_row)?;
+	Ok(rows.next().transpose()?)
+}
+
+/// The indexed hash of `path` on `node_id`, if the index knows the file.
+pub fn indexed_hash(
+	conn: &Connection,
+	node_id: &[u8],
+	path: &str,
+) -> anyhow::Result<Option<Vec<u8>>> {
+	let mut stmt = conn.prepare(
+		"SELECT hash FROM file_locations WHERE node_id = ?1 AND path = ?2 AND deleted_at IS NULL",
+	)?;
+	let mut rows = stmt.query_map(params![node_id, path], |row| {
+		row.get::<_, Option<Vec<u8>>>(0)
+	})?;
+	Ok(rows.next().transpose()?.flatten())
+}
+
+/// Saves a new pin and returns its id.
+pub fn insert_pin(
+	conn: &Connection,
+	peer: &PeerId,
+	remote_path: &str,
+	local_dest: &Path,
+	opts: &PinOptions,
+	created_at: DateTime<Utc>,
+) -> anyhow::Result<u64> {
+	conn.execute(
+		"INSERT INTO pins (peer, remote_path, local_dest, interval_secs, bandwidth_limit,
+			delete_removed, wake_if_needed, created_at)
+		VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+		params![
+			peer.to_string(),
+			remote_path,
+			local_dest.to_string_lossy(),
+			opts.interval.as_secs() as i64,
+			opts.bandwidth_limit.map(|limit| limit as i64),
+			opts.delete_removed,
+			opts.wake_if_needed,
+			created_at.timestamp(),
+		],
+	)?;
+	Ok(conn.last_insert_rowid() as u64)
+}
+
+/// All pins, oldest first.
+pub fn load_pins(conn: &Connection) -> anyhow::Result<Vec<PinStatus>> {
+	let mut stmt = conn.prepare(
+		"SELECT id, peer, remote_path, local_dest, interval_secs, bandwidth_limit,
+			delete_removed, paused, created_at, last_sync_at, files_pending,
+			bytes_transferred, last_error, wake_if_needed
+		FROM pins ORDER BY id",
+	)?;
+	let rows = stmt.query_map([], |row| {
+		Ok(PinStatus {
+			id: row.get::<_, i64>(0)? as u64,
+			peer: row.get(1)?,
+			remote_path: row.get(2)?,
+			local_dest: row.get(3)?,
+			interval_secs: row.get::<_, i64>(4)?.max(0) as u64,
+			bandwidth_limit: row
+				.get::<_, Option<i64>>(5)?
+				.map(|limit| limit.max(1) as u64),
+			delete_removed: row.get(6)?,
+			paused: row.get(7)?,
+			syncing: false,
+			created_at: DateTime::from_timestamp(row.get(8)?, 0).unwrap_or_default(),
+			last_sync_at: row
+				.get::<_, Option<i64>>(9)?
+				.and_then(|at| DateTime::from_timestamp(at, 0)),
+			files_pending: row.get::<_, i64>(10)?.max(0) as u64,
+			bytes_transferred: row.get::<_, i64>(11)?.max(0) as u64,
+			last_error: row.get(12)?,
+			wake_if_needed: row.get(13)?,
+		})
+	})?;
+	let mut pins = Vec::new();
+	for row in rows {
+		pins.push(row?);
+	}
+	Ok(pins)
+}
+
+/// Returns whether a pin with `id` exists.
+pub fn set_pin_paused(conn: &Connection, id: u64, paused: bool) -> anyhow::Result<bool> {
+	let changed = conn.execute(
+		"UPDATE pins SET paused = ?2 WHERE id = ?1",
+		params![id as i64, paused],
+	)?;
+	Ok(changed > 0)
+}
+
+/// Forgets a pin and its manifest. Returns whether it existed.
+pub fn delete_pin(conn: &Connection, id: u64) -> anyhow::Result<bool> {
+	conn.execute(
+		"DELETE FROM pin_files WHERE pin_id = ?1",
+		params![id as i64],
+	)?;
+	let changed = conn.execute("DELETE FROM pins WHERE id = ?1", params![id as i64])?;
+	Ok(changed > 0)
+}
+
+/// Records how a sync of pin `id` went.
+pub fn finish_pin_sync(conn: &Connection, id: u64, report: &PinSyncReport) -> anyhow::Result<()> {
+	conn.execute(
+		"UPDATE pins SET last_sync_at = COALESCE(?2, last_sync_at), files_pending = ?3,
+			bytes_transferred = bytes_transferred + ?4, last_error = ?5
+		WHERE id = ?1",
+		params![
+			id as i64,
+			report.finished_at.map(|at| at.timestamp()),
+			report.files_pending as i64,
+			report.bytes as i64,
+			report.error,
+		],
+	)?;
+	Ok(())
+}
+
+/// Saves a new outbox rule and returns its id.
+pub fn insert_outbox_rule(
+	conn: &Connection,
+	rule: &OutboxRule,
+	created_at: DateTime<Utc>,
+) -> anyhow::Result<u64> {
+	conn.execute(
+		"INSERT INTO outbox_rules (watch_path, dest_peer, dest_path, delete_after_send, pattern,
+			created_at)
+		VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+		params![
+			rule.watch_path.to_string_lossy(),
+			rule.dest_peer.to_string(),
+			rule.dest_path,
+			rule.delete_after_send,
+			rule.pattern,
+			created_at.timestamp(),
+		],
+	)?;
+	Ok(conn.last_insert_rowid() as u64)
+}
+
+/// Replaces what outbox rule `id` sends where. Returns whether it exists.
+pub fn update_outbox_rule(conn: &Connection, id: u64, rule: &OutboxRule) -> anyhow::Result<bool> {
+	let changed = conn.execute(
+		"UPDATE outbox_rules SET watch_path = ?2, dest_peer = ?3, dest_path = ?4,
+			delete_after_send = ?5, pattern = ?6, last_error = NULL
+		WHERE id = ?1",
+		params![
+			id as i64,
+			rule.watch_path.to_string_lossy(),
+			rule.dest_peer.to_string(),
+			rule.dest_path,
+			rule.delete_after_send,
+			rule.pattern,
+		],
+	)?;
+	Ok(changed > 0)
+}
+
+/// All outbox rules, oldest first.
+pub fn load_outbox_rules(conn: &Connection) -> anyhow::Result<Vec<OutboxStatus>> {
+	let mut stmt = conn.prepare(
+		"SELECT id, watch_path, dest_peer, dest_path, delete_after_send, pattern, paused,
+			created_at, last_sent_file, last_sent_at, files_sent, last_error
+		FROM outbox_rules ORDER BY id",
+	)?;
+	let rows = stmt.query_map([], |row| {
+		Ok(OutboxStatus {
+			id: row.get::<_, i64>(0)? as u64,
+			watch_path: row.get(1)?,
+			dest_peer: row.get(2)?,
+			dest_path: row.get(3)?,
+			delete_after_send: row.get(4)?,
+			pattern: row.get(5)?,
+			paused: row.get(6)?,
+			created_at: DateTime::from_timestamp(row.get(7)?, 0).unwrap_or_default(),
+			last_sent_file: row.get(8)?,
+			last_sent_at: row
+				.get::<_, Option<i64>>(9)?
+				.and_then(|at| DateTime::from_timestamp(at, 0)),
+			files_sent: row.get::<_, i64>(10)?.max(0) as u64,
+			pending: 0,
+			last_error: row.get(11)?,
+		})
+	})?;
+	let mut rules = Vec::new();
+	for row in rows {
+		rules.push(row?);
+	}
+	Ok(rules)
+}
+
+/// Returns whether an outbox rule with `id` exists.
+pub fn set_outbox_rule_paused(conn: &Connection, id: u64, paused: bool) -> anyhow::Result<bool> {
+	let changed = conn.execute(
+		"UPDATE outbox_rules SET paused = ?2 WHERE id = ?1",
+		params![id as i64, paused],
+	)?;
+	Ok(changed > 0)
+}
+
+/// Forgets an outbox rule. Returns whether it existed.
+pub fn delete_outbox_rule(conn: &Connection, id: u64) -> anyhow::Result<bool> {
+	let changed = conn.execute("DELETE FROM outbox_rules WHERE id = ?1", params![id as i64])?;
+	Ok(changed > 0)
+}
+
+/// Records that `file` of outbox rule `id` arrived on the peer.
+pub fn record_outbox_send(
+	conn: &Connection,
+	id: u64,
+	file: &str,
+	at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+	conn.execute(
+		"UPDATE outbox_rules SET last_sent_file = ?2, last_sent_at = ?3,
+			files_sent = files_sent + 1, last_error = NULL
+		WHERE id = ?1",
+		params![id as i64, file, at.timestamp()],
+	)?;
+	Ok(())
+}
+
+/// Sets or clears what last went wrong with outbox rule `id`.
+pub fn record_outbox_error(conn: &Connection, id: u64, error: Option<&str>) -> anyhow::Result<()> {
+	conn.execute(
+		"UPDATE outbox_rules SET last_error = ?2 WHERE id = ?1",
+		params![id as i64, error],
+	)?;
+	Ok(())
+}
+
+/// The files the mirror of pin `id` holds, by path under the pinned folder.
+pub fn load_pin_files(conn: &Connection, id: u64) -> anyhow::Result<HashMap<String, PinnedFile>> {
+	let mut stmt =
+		conn.prepare("SELECT path, size, modified_at, hash FROM pin_files WHERE pin_id = ?1")?;
+	let rows = stmt.query_map(params![id as i64], |row| {
+		Ok((
+			row.get::<_, String>(0)?,
+			PinnedFile {
+				size: row.get::<_, i64>(1)?.max(0) as u64,
+				modified_at: row.get(2)?,
+				hash: row.get(3)?,
+			},
+		))
+	})?;
+	let mut files = HashMap::new();
+	for row in rows {
+		let (path, file) = row?;
+		files.insert(path, file);
+	}
+	Ok(files)
+}
+
+/// Records a mirrored file, unless the pin was removed meanwhile.
+pub fn save_pin_file(
+	conn: &Connection,
+	id: u64,
+	path: &str,
+	file: &PinnedFile,
+) -> anyhow::Result<()> {
+	conn.execute(
+		"INSERT OR REPLACE INTO pin_files (pin_id, path, size, modified_at, hash)
+		SELECT ?1, ?2, ?3, ?4, ?5 WHERE EXISTS (SELECT 1 FROM pins WHERE id = ?1)",
+		params![
+			id as i64,
+			path,
+			file.size as i64,
+			file.modified_at,
+			file.hash
+		],
+	)?;
+	Ok(())
+}
+
+pub fn remove_pin_file(conn: &Connection, id: u64, path: &str) -> anyhow::Result<()> {
+	conn.execute(
+		"DELETE FROM pin_files WHERE pin_id = ?1 AND path = ?2",
+		params![id as i64, path],
+	)?;
+	Ok(())
+}
+
+pub(crate) const INDEX_GENERATION_SETTING: &str = "index_generation";
+const INDEX_PURGED_THROUGH_SETTING: &str = "index_purged_through";
+
+/// Number of the last change made to `file_entries` or `file_locations`.
+pub fn index_change_seq(conn: &Connection) -> anyhow::Result<u64> {
+	let seq: i64 = conn.query_row("SELECT seq FROM index_changes WHERE id = 1", [], |row| {
+		row.get(0)
+	})?;
+	Ok(seq as u64)
+}
+
+/// Id of this node's index history, made on first use. Replicas holding
+/// another generation are replaced instead of updated.
+pub fn index_generation(conn: &Connection) -> anyhow::Result<String> {
+	match load_setting(conn, INDEX_GENERATION_SETTING)? {
+		Some(generation) => Ok(generation),
+		None => bump_index_generation(conn),
+	}
+}
+
+pub fn bump_index_generation(conn: &Connection) -> anyhow::Result<String> {
+	let generation = uuid::Uuid::new_v4().to_string();
+	save_setting(conn, INDEX_GENERATION_SETTING, &generation)?;
+	// Replicas of the new generation start from scratch, after the purge.
+	delete_setting(conn, INDEX_PURGED_THROUGH_SETTING)?;
+	Ok(generation)
+}
+
+/// Last change of this node's index whose tombstone was purged during the
+/// current generation, or 0. A replica that acknowledged less than this
+/// missed a deletion and has to start over.
+pub fn index_purged_through(conn: &Connection) -> anyhow::Result<u64> {
+	Ok(load_setting(conn, INDEX_PURGED_THROUGH_SETTING)?
+		.and_then(|value| value.parse().ok())
+		.unwrap_or(0))
+}
+
+/// Removes every location of `node_id`, tombstones included, and the
+/// entries no other node has a location for.
+pub fn forget_node_index(conn: &Connection, node_id: &[u8]) -> anyhow::Result<usize> {
+	let tx = conn.unchecked_transaction()?;
+	let removed = delete_node_index(&tx, node_id)?;
+	tx.commit()?;
+	Ok(removed)
+}
+
+fn delete_node_index(conn: &Connection, node_id: &[u8]) -> rusqlite::Result<usize> {
+	conn.execute(
+		"DELETE FROM file_entries WHERE hash IN (SELECT hash FROM file_locations WHERE node_id = ?1)
+		AND NOT EXISTS (
+			SELECT 1 FROM file_locations fl WHERE fl.hash = file_entries.hash AND fl.node_id != ?1
+		)",
+		[node_id],
+	)?;
+	conn.execute("DELETE FROM file_locations WHERE node_id = ?1", [node_id])
+}
+
+const REPLICATED_ENTRY_COLUMNS: &str =
+	"fe.change_seq, fe.hash, fe.size, fe.mime_type, fe.first_datetime, fe.latest_datetime";
+
+fn replicated_entry(row: &Row<'_>) -> rusqlite::Result<(u64, ReplicatedEntry)> {
+	Ok((
+		row.get::<_, i64>(0)? as u64,
+		ReplicatedEntry {
+			hash: row.get(1)?,
+			size: row.get(2)?,
+			mime_type: row.get(3)?,
+			first_datetime: row.get(4)?,
+			latest_datetime: row.get(5)?,
+		},
+	))
+}
+
+fn replicated_location(row: &Row<'_>) -> rusqlite::Result<(u64, ReplicatedLocation)> {
+	let origin: String = row.get(9)?;
+	Ok((
+		row.get::<_, i64>(0)? as u64,
+		ReplicatedLocation {
+			path: WirePath::from_path(&path_column(row, 1)?),
+			hash: row.get(2)?,
+			size: row.get::<_, i64>(3)? as u64,
+			timestamp: row.get(4)?,
+			created_at: row.get(5)?,
+			modified_at: row.get(6)?,
+			accessed_at: row.get(7)?,
+			deleted_at: row.get(8)?,
+			origin: FileOrigin::parse(&origin).unwrap_or(FileOrigin::LocalScan),
+			origin_peer: row.get(10)?,
+			origin_run: row.get(11)?,
+			origin_transfer: row.get(12)?,
+			origin_user: row.get(13)?,
+			introduced_at: row.get(14)?,
+		},
+	))
+}
+
+/// The oldest `limit` changes to `node_id`'s index after change `after`,
+/// with the entry of every location in the batch, changed or not.
+pub(crate) fn load_index_changes(
+	conn: &Connection,
+	node_id: &[u8],
+	after: u64,
+	limit: usize,
+) -> anyhow::Result<IndexChanges> {
+	let mut stmt = conn.prepare(
+		"SELECT change_seq, path, hash, size, timestamp, created_at, modified_at, accessed_at,
+			deleted_at, origin, origin_peer, origin_run, origin_transfer, origin_user, introduced_at
+		FROM file_locations WHERE node_id = ?1 AND change_seq > ?2
+		ORDER BY change_seq LIMIT ?3",
+	)?;
+	let locations = stmt
+		.query_map(
+			params![node_id, after as i64, limit as i64],
+			replicated_location,
+		)?
+		.collect::<rusqlite::Result<Vec<_>>>()?;
+	let mut stmt = conn.prepare(&format!(
+		"SELECT {REPLICATED_ENTRY_COLUMNS} FROM file_entries fe
+		WHERE fe.change_seq > ?2
+			AND EXISTS (SELECT 1 FROM file_locations fl WHERE fl.hash = fe.hash AND fl.node_id = ?1)
+		ORDER BY fe.change_seq LIMIT ?3"
+	))?;
+	let entries = stmt
+		.query_map(
+			params![node_id, after as i64, limit as i64],
+			replicated_entry,
+		)?
+		.collect::<rusqlite::Result<Vec<_>>>()?;
+
+	// Both lists are ordered by change; keep the oldest `limit` of the two.
+	let mut changes = IndexChanges {
+		through: after,
+		..IndexChanges::default()
+	};
+	let (mut entries, mut locations) = (
+		entries.into_iter().peekable(),
+		locations.into_iter().peekable(),
+	);
+	for _ in 0..limit {
+		let entry_first = match (entries.peek(), locations.peek()) {
+			(Some((entry_seq, _)), Some((location_seq, _))) => entry_seq < location_seq,
+			(Some(_), None) => true,
+			(None, Some(_)) => false,
+			(None, None) => break,
+		};
+		let seq = if entry_first {
+			let (seq, entry) = entries.next().unwrap();
+			changes.entries.push(entry);
+			seq
+		} else {
+			let (seq, location) = locations.next().unwrap();
+			changes.locations.push(location);
+			seq
+		};
+		changes.through = seq;
+	}
+	push_location_entries(conn, &mut changes)?;
+	Ok(changes)
+}
+
+/// Adds the entry of every location in `changes` it doesn't hold yet.
+fn push_location_entries(conn: &Connection, changes: &mut IndexChanges) -> anyhow::Result<()> {
+	let mut sent = changes
+		.entries
+		.iter()
+		.map(|entry| entry.hash.clone())
+		.collect::<HashSet<_>>();
+	let mut stmt = conn.prepare(&format!(
+		"SELECT {REPLICATED_ENTRY_COLUMNS} FROM file_entries fe WHERE fe.hash = ?1"
+	))?;
+	for location in &changes.locations {
+		let Some(hash) = &location.hash else {
+			continue;
+		};
+		if !sent.insert(hash.clone()) {
+			continue;
+		}
+		let mut rows = stmt.query_map([hash], replicated_entry)?;
+		if let Some(row) = rows.next() {
+			changes.entries.push(row?.1);
+		}
+	}
+	Ok(())
+}
+
+/// Changes to `node_id`'s index after change `after`.
+pub(crate) fn count_index_changes(
+	conn: &Connection,
+	node_id: &[u8],
+	after: u64,
+) -> anyhow::Result<u64> {
+	let count: i64 = conn.query_row(
+		"SELECT (SELECT COUNT(*) FROM file_locations WHERE node_id = ?1 AND change_seq > ?2)
+			+ (SELECT COUNT(*) FROM file_entries fe WHERE fe.change_seq > ?2
+				AND EXISTS (
+					SELECT 1 FROM file_locations fl WHERE fl.hash = fe.hash AND fl.node_id = ?1
+				))",
+		params![node_id, after as i64],
+		|row| row.get(0),
+	)?;
+	Ok(count as u64)
+}
+
+/// `node_id`'s locations under `path_prefix` after row `after`, tombstones
+/// included, the first `limit` in row order, with the entry of each.
+/// `through` is the last row in the batch.
+pub(crate) fn load_locations_under(
+	conn: &Connection,
+	node_id: &NodeID,
+	path_prefix: &str,
+	after: u64,
+	limit: usize,
+) -> anyhow::Result<IndexChanges> {
+	let args = SearchFilesArgs {
+		node_id: Some(*node_id),
+		path_prefix: Some(path_prefix.to_string()),
+		..Default::default()
+	};
+	let (scope, mut param_values) = location_scope(&args, "fl");
+	param_values.push(Value::Integer(after.min(i64::MAX as u64) as i64));
+	param_values.push(Value::Integer(limit as i64));
+	let mut stmt = conn.prepare(&format!(
+		"SELECT fl.rowid, fl.path, fl.hash, fl.size, fl.timestamp, fl.created_at,
+			fl.modified_at, fl.accessed_at, fl.deleted_at, fl.origin, fl.origin_peer,
+			fl.origin_run, fl.origin_transfer, fl.origin_user, fl.introduced_at
+		FROM file_locations fl
+		WHERE {} AND fl.rowid > ?{}
+		ORDER BY fl.rowid LIMIT ?{}",
+		scope.join(" AND "),
+		param_values.len() - 1,
+		param_values.len()
+	))?;
+	let rows = stmt.query_map(
+		rusqlite::params_from_iter(&param_values),
+		replicated_location,
+	)?;
+	let mut changes = IndexChanges {
+		through: after,
+		..IndexChanges::default()
+	};
+	for row in rows {
+		let (rowid, location) = row?;
+		changes.through = rowid;
+		changes.locations.push(location);
+	}
+	push_location_entries(conn, &mut changes)?;
+	Ok(changes)
+}
+
+/// `node_id`'s locations under `path_prefix` after row `after`, tombstones
+/// included.
+pub(crate) fn count_locations_under(
+	conn: &Connection,
+	node_id: &NodeID,
+	path_prefix: &str,
+	after: u64,
+) -> anyhow::Result<u64> {
+	let args = SearchFilesArgs {
+		node_id: Some(*node_id),
+		path_prefix: Some(path_prefix.to_string()),
+		..Default::default()
+	};
+	let (scope, mut param_values) = location_scope(&args, "fl");
+	param_values.push(Value::Integer(after.min(i64::MAX as u64) as i64));
+	let count: i64 = conn.query_row(
+		&format!(
+			"SELECT COUNT(*) FROM file_locations fl WHERE {} AND fl.rowid > ?{}",
+			scope.join(" AND "),
+			param_values.len()
+		),
+		rusqlite::params_from_iter(&param_values),
+		|row| row.get(0),
+	)?;
+	Ok(count as u64)
+}
+
+/// Writes `delta` into the rows kept for `node_id` and records `replica`,
+/// in one transaction. A delta starting at zero replaces the node's rows.
+/// Entries other nodes share keep the earliest and latest times of both.
+pub(crate) fn apply_index_delta(
+	conn: &Connection,
+	node_id: &[u8],
+	delta: &IndexDelta,
+	replica: &ReplicationStatus,
+) -> anyhow::Result<()> {
+	let tx = conn.unchecked_transaction()?;
+	if delta.after == 0 {
+		delete_node_index(&tx, node_id)?;
+	}
+	upsert_index_rows(&tx, node_id, &delta.entries, &delta.locations)?;
+	save_replication(&tx, replica)?;
+	tx.commit()?;
+	Ok(())
+}
+
+/// Writes index rows another node sent into the rows kept for `node_id`.
+/// Entries other nodes share keep the earliest and latest times of both.
+pub(crate) fn upsert_index_rows(
+	conn: &Connection,
+	node_id: &[u8],
+	entries: &[ReplicatedEntry],
+	locations: &[ReplicatedLocation],
+) -> anyhow::Result<()> {
+	let mut upsert_entry = conn.prepare_cached(
+		"INSERT INTO file_entries (hash, size, mime_type, first_datetime, latest_datetime)
+		VALUES (?1, ?2, ?3, ?4, ?5)
+		ON CONFLICT(hash) DO UPDATE SET
+			mime_type = coalesce(file_entries.mime_type, excluded.mime_type),
+			first_datetime = min(
+				coalesce(file_entries.first_datetime, excluded.first_datetime),
+				coalesce(excluded.first_datetime, file_entries.first_datetime)
+			),
+			latest_datetime = max(
+				coalesce(file_entries.latest_datetime, excluded.latest_datetime),
+				coalesce(excluded.latest_datetime, file_entries.latest_datetime)
+			)",
+	)?;
+	for entry in entries {
+		upsert_entry.execute(params![
+			entry.hash,
+			entry.size,
+			entry.mime_type,
+			entry.first_datetime,
+			entry.latest_datetime
+		])?;
+	}
+	let mut upsert_location = conn.prepare_cached(
+		"INSERT INTO file_locations (node_id, path, hash, size, timestamp, created_at,
+			modified_at, accessed_at, deleted_at, origin, origin_peer, origin_run,
+			origin_transfer, origin_user, introduced_at)
+		VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)
+		ON CONFLICT(node_id, path) DO UPDATE SET hash = excluded.hash, size = excluded.size,
+			timestamp = excluded.timestamp, created_at = excluded.created_at,
+			modified_at = excluded.modified_at, accessed_at = excluded.accessed_at,
+			deleted_at = excluded.deleted_at, deleted_by_run = NULL, origin = excluded.origin,
+			origin_peer = excluded.origin_peer, origin_run = excluded.origin_run,
+			origin_transfer = excluded.origin_transfer, origin_user = excluded.origin_user,
+			introduced_at = excluded.introduced_at",
+	)?;
+	for location in locations {
+		upsert_location.execute(params![
+			node_id,
+			path_to_sql(&location.path.to_path_buf()),
+			location.hash,
+			location.size as i64,
+			location.timestamp,
+			location.created_at,
+			location.modified_at,
+			location.accessed_at,
+			location.deleted_at,
+			location.origin.as_str(),
+			location.origin_peer,
+			location.origin_run,
+			location.origin_transfer,
+			location.origin_user,
+			location.introduced_at
+		])?;
+	}
+	Ok(())
+}
+
+const REPLICATION_COLUMNS: &str =
+	"peer, role, generation, acked_seq, behind, last_sync_at, last_error";
+
+fn replication_row(row: &Row<'_>) -> rusqlite::Result<Option<ReplicationStatus>> {
+	let role: String = row.get(1)?;
+	let Some(role) = ReplicationRole::parse(&role) else {
+		return Ok(None);
+	};
+	Ok(Some(ReplicationStatus {
+		peer: row.get(0)?,
+		role,
+		generation: row.get(2)?,
+		acked_seq: row.get::<_, i64>(3)? as u64,
+		behind: row.get::<_, i64>(4)? as u64,
+		last_sync_at: row
+			.get::<_, Option<i64>>(5)?
+			.and_then(|at| DateTime::from_timestamp(at, 0)),
+		last_error: row.get(6)?,
+		syncing: false,
+	}))
+}
+
+pub fn load_replication(
+	conn: &Connection,
+	peer: &PeerId,
+	role: ReplicationRole,
+) -> anyhow::Result<Option<ReplicationStatus>> {
+	let mut stmt = conn.prepare(&format!(
+		"SELECT {REPLICATION_COLUMNS} FROM replication_state WHERE peer = ?1 AND role = ?2"
+	))?;
+	let mut rows = stmt.query_map(params![peer.to_string(), role.as_str()], replication_row)?;
+	match rows.next() {
+		Some(row) => Ok(row?),
+		None => Ok(None),
+	}
+}
+
+pub fn load_replications(conn: &Connection) -> anyhow::Result<Vec<ReplicationStatus>> {
+	let mut stmt = conn.prepare(&format!(
+		"SELECT {REPLICATION_COLUMNS} FROM replication_state ORDER BY peer, role"
+	))?;
+	let rows = stmt.query_map([], replication_row)?;
+	let mut replications = Vec::new();
+	for row in rows {
+		replications.extend(row?);
+	}
+	Ok(replications)
+}
+
+pub fn save_replication(conn: &Connection, status: &ReplicationStatus) -> anyhow::Result<()> {
+	conn.execute(
+		"INSERT OR REPLACE INTO replication_state
+			(peer, role, generation, acked_seq, behind, last_sync_at, last_error)
+		VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+		params![
+			status.peer,
+			status.role.as_str(),
+			status.generation,
+			status.acked_seq as i64,
+			status.behind as i64,
+			status.last_sync_at.map(|at| at.timestamp()),
+			status.last_error
+		],
+	)?;
+	Ok(())
+}
+
+pub fn delete_replication(
+	conn: &Connection,
+	peer: &PeerId,
+	role: ReplicationRole,
+) -> anyhow::Result<bool> {
+	let removed = conn.execute(
+		"DELETE FROM replication_state WHERE peer = ?1 AND role = ?2",
+		params![peer.to_string(), role.as_str()],
+	)?;
+	Ok(removed > 0)
+}
+
+/// Whether this node pulls `peer`'s index changes when it announces them.
+pub fn is_subscribed_to_index(conn: &Connection, peer: &PeerId) -> anyhow::Result<bool> {
+	let count: i64 = conn.query_row(
+		"SELECT COUNT(*) FROM index_subscriptions WHERE peer = ?1",
+		params![peer.to_string()],
+		|row| row.get(0),
+	)?;
+	Ok(count > 0)
+}
+
+pub fn save_index_subscription(
+	conn: &Connection,
+	peer: &PeerId,
+	subscribed: bool,
+	now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+	if subscribed {
+		conn.execute(
+			"INSERT OR IGNORE INTO index_subscriptions (peer, created_at) VALUES (?1, ?2)",
+			params![peer.to_string(), now.timestamp()],
+		)?;
+	} else {
+		conn.execute(
+			"DELETE FROM index_subscriptions WHERE peer = ?1",
+			params![peer.to_string()],
+		)?;
+	}
+	Ok(())
+}
+
+/// Stores what extraction found for the content with `hash`.
+pub fn save_media_metadata(
+	conn: &Connection,
+	hash: &[u8],
+	metadata: &MediaMetadata,
+) -> anyhow::Result<()> {
+	conn.execute(
+		"INSERT OR REPLACE INTO media_metadata
+			(hash, width, height, duration_ms, codec, error, extracted_at)
+		VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+		params![
+			hash,
+			metadata.width,
+			metadata.height,
+			metadata.duration_ms.map(|ms| ms as i64),
+			metadata.codec,
+			metadata.error,
+			Utc::now().timestamp(),
+		],
+	)?;
+	Ok(())
+}
+
+pub fn load_media_metadata(
+	conn: &Connection,
+	hash: &[u8],
+) -> anyhow::Result<Option<MediaMetadata>> {
+	let mut stmt = conn.prepare(
+		"SELECT width, height, duration_ms, codec, error FROM media_metadata WHERE hash = ?1",
+	)?;
+	let mut rows = stmt.query_map(params![hash], |row| {
+		Ok(MediaMetadata {
+			width: row.get(0)?,
+			height: row.get(1)?,
+			duration_ms: row.get::<_, Option<i64>>(2)?.map(|ms| ms.max(0) as u64),
+			codec: row.get(3)?,
+			error: row.get(4)?,
+		})
+	})?;
+	Ok(rows.next().transpose()?)
+}
+
+/// Files of `node_id`, under `path_prefix` if given, whose content no
+/// longer matches a checksum claimed by a manifest, ordered by path.
+pub fn load_hash_mismatches(
+	conn: &Connection,
+	node_id: &NodeID,
+	path_prefix: Option<&str>,
+) -> anyhow::Result<Vec<HashMismatch>> {
+	let args = SearchFilesArgs {
+		node_id: Some(*node_id),
+		path_prefix: path_prefix.map(str::to_string),
+		..Default::default()
+	};
+	let (mut scope, mut param_values) = location_scope(&args, "ch");
+	param_values.push(Value::Text(ClaimStatus::Mismatch.as_str().to_string()));
+	scope.push(format!("ch.status = ?{}", param_values.len()));
+	let mut stmt = conn.prepare(&format!(
+		"SELECT ch.path, ch.manifest_path, ch.algorithm, ch.claimed, ch.actual, ch.checked_at
+		FROM claimed_hashes ch
+		WHERE {}
+		ORDER BY ch.path, ch.manifest_path",
+		scope.join(" AND ")
+	))?;
+	let rows = stmt.query_map(rusqlite::params_from_iter(&param_values), |row| {
+		let algorithm: String = row.get(2)?;
+		let checked_at: i64 = row.get(5)?;
+		Ok((
+			path_column(row, 0)?,
+			path_column(row, 1)?,
+			algorithm,
+			row.get::<_, Vec<u8>>(3)?,
+			row.get::<_, Option<Vec<u8>>>(4)?,
+			checked_at,
+		))
+	})?;
+	let mut mismatches = Vec::new();
+	for row in rows {
+		let (path, manifest, algorithm, claimed, actual, checked_at) = row?;
+		let Some(algorithm) = ChecksumAlgorithm::parse(&algorithm) else {
+			continue;
+		};
+		mismatches.push(HashMismatch {
+			path,
+			manifest,
+			algorithm,
+			claimed,
+			actual: actual.unwrap_or_default(),
+			checked_at: DateTime::from_timestamp(checked_at, 0).unwrap_or_default(),
+		});
+	}
+	Ok(mismatches)
+}
+
+/// Media files of `node_id`, under `path_prefix` if given, whose content
+/// has no metadata yet, one file per content hash.
+pub fn pending_media_files(
+	conn: &Connection,
+	node_id: &NodeID,
+	path_prefix: Option<&str>,
+) -> anyhow::Result<Vec<PendingMedia>> {
+	let args = SearchFilesArgs {
+		node_id: Some(*node_id),
+		path_prefix: path_prefix.map(str::to_string),
+		..Default::default()
+	};
+	let (scope, param_values) = location_scope(&args, "fl");
+	let mut stmt = conn.prepare(&format!(
+		"SELECT fe.hash, fe.mime_type, fl.path
+		FROM file_locations fl JOIN file_entries fe ON fe.hash = fl.hash
+		WHERE {} AND fl.deleted_at IS NULL AND fe.mime_type IS NOT NULL
+			AND NOT EXISTS (SELECT 1 FROM media_metadata mm WHERE mm.hash = fe.hash)
+		ORDER BY fl.path",
+		scope.join(" AND ")
+	))?;
+	let rows = stmt.query_map(rusqlite::params_from_iter(&param_values), |row| {
+		Ok(PendingMedia {
+			hash: row.get(0)?,
+			mime_type: row.get(1)?,
+			path: path_column(row, 2)?,
+		})
+	})?;
+	let mut seen = std::collections::HashSet::new();
+	let mut pending = Vec::new();
+	for row in rows {
+		let file = row?;
+		if is_media_mime(&file.mime_type) && seen.insert(file.hash.clone()) {
+			pending.push(file);
+		}
+	}
+	Ok(pending)
+}
+
+/// Live images of at least `min_size` bytes the node has indexed under
+/// `path_prefix`, for thumbnails made ahead of browsing.
+pub fn image_files_under(
+	conn: &Connection,
+	node_id: &NodeID,
+	path_prefix: &str,
+	min_size: u64,
+) -> anyhow::Result<Vec<PathBuf>> {
+	let args = SearchFilesArgs {
+		node_id: Some(*node_id),
+		path_prefix: Some(path_prefix.to_string()),
+		..Default::default()
+	};
+	let (mut scope, mut param_values) = location_scope(&args, "fl");
+	param_values.push(Value::Integer(min_size.min(i64::MAX as u64) as i64));
+	scope.push(format!("fl.size >= ?{}", param_values.len()));
+	let mut stmt = conn.prepare(&format!(
+		"SELECT fl.path
+		FROM file_locations fl JOIN file_entries fe ON fe.hash = fl.hash
+		WHERE {} AND fl.deleted_at IS NULL AND fe.mime_type LIKE 'image/%'
+		ORDER BY fl.path",
+		scope.join(" AND ")
+	))?;
+	let rows = stmt.query_map(rusqlite::params_from_iter(&param_values), |row| {
+		path_column(row, 0)
+	})?;
+	let mut paths = Vec::new();
+	for row in rows {
+		paths.push(row?);
+	}
+	Ok(paths)
+}
+
+/// Live files `node_id` has indexed under `path_prefix`, at most `limit`,
+/// in path order, with the mime types of their content.
+pub fn indexed_files_under(
+	conn: &Connection,
+	node_id: &NodeID,
+	path_prefix: &str,
+	limit: u64,
+) -> anyhow::Result<Vec<FileLocation>> {
+	let args = SearchFilesArgs {
+		node_id: Some(*node_id),
+		path_prefix: Some(path_prefix.to_string()),
+		..Default::default()
+	};
+	let (scope, mut param_values) = location_scope(&args, "fl");
+	param_values.push(Value::Integer(limit.min(i64::MAX as u64) as i64));
+	let mut stmt = conn.prepare(&format!(
+		"SELECT fl.path, fl.hash, fl.size, fl.timestamp, fl.created_at, fl.modified_at,
+			fl.accessed_at, fe.mime_type
+		FROM file_locations fl LEFT JOIN file_entries fe ON fe.hash = fl.hash
+		WHERE {} AND fl.deleted_at IS NULL
+		ORDER BY fl.path
+		LIMIT ?{}",
+		scope.join(" AND "),
+		param_values.len()
+	))?;
+	let rows = stmt.query_map(rusqlite::params_from_iter(&param_values), |row| {
+		let hash: Option<Vec<u8>> = row.get(1)?;
+		Ok(FileLocation {
+			path: path_column(row, 0)?,
+			hash: hash.and_then(|hash| hash.as_slice().try_into().ok()),
+			size: row.get::<_, i64>(2)? as u64,
+			mime_type: row.get(7)?,
+			timestamp: row.get(3)?,
+			created_at: row.get(4)?,
+			modified_at: row.get(5)?,
+			accessed_at: row.get(6)?,
+			blocks: Vec::new(),
+		})
+	})?;
+	let mut files = Vec::new();
+	for row in rows {
+		files.push(row?);
+	}
+	Ok(files)
+}
+
+/// Start of the latest backup that succeeded.
+pub fn last_successful_backup(conn: &Connection) -> anyhow::Result<Option<DateTime<Utc>>> {
+	let at: Option<i64> = conn.query_row(
+		"SELECT MAX(started_at) FROM backup_runs WHERE kind = 'backup' AND error IS NULL",
+		[],
+		|row| row.get(0),
+	)?;
+	Ok(at.and_then(|at| DateTime::from_timestamp(at, 0)))
+}
+
+fn disk_sample_from_row(row: &Row<'_>) -> rusqlite::Result<DiskSample> {
+	Ok(DiskSample {
+		disk_id: row.get(0)?,
+		mount_path: row.get(1)?,
+		sampled_at: DateTime::from_timestamp(row.get(2)?, 0).unwrap_or_default(),
+		total_space: row.get::<_, i64>(3)?.max(0) as u64,
+		available_space: row.get::<_, i64>(4)?.max(0) as u64,
+	})
+}
+
+/// Samples of the disk currently mounted at `mount` between `from` and `to`,
+/// oldest first. Only the disk most recently seen there is returned, so a
+/// different drive that used the same mount point earlier does not bend the
+/// history. `mount` may also be a disk id.
+pub fn load_disk_samples(
+	conn: &Connection,
+	mount: &str,
+	from: DateTime<Utc>,
+	to: DateTime<Utc>,
+) -> anyhow::Result<Vec<DiskSample>> {
+	let disk_id: String = {
+		let mut stmt = conn.prepare(
+			"SELECT disk_id FROM disk_samples WHERE mount_path = ?1 OR disk_id = ?1
+			ORDER BY sampled_at DESC LIMIT 1",
+		)?;
+		let mut rows = stmt.query([mount])?;
+		match rows.next()? {
+			Some(row) => row.get(0)?,
+			None => return Ok(Vec::new()),
+		}
+	};
+	let mut stmt = conn.prepare(
+		"SELECT disk_id, mount_path, sampled_at, total_space, available_space FROM disk_samples
+		WHERE disk_id = ?1 AND sampled_at >= ?2 AND sampled_at <= ?3
+		ORDER BY sampled_at ASC",
+	)?;
+	let rows = stmt.query_map(
+		params![disk_id, from.timestamp(), to.timestamp()],
+		disk_sample_from_row,
+	)?;
+	let mut samples = Vec::new();
+	for row in rows {
+		samples.push(row?);
+	}
+	Ok(samples)
+}
+
+/// Samples of every disk between `from` and `to`, by disk and oldest first.
+pub fn load_all_disk_samples(
+	conn: &Connection,
+	from: DateTime<Utc>,
+	to: DateTime<Utc>,
+) -> anyhow::Result<Vec<DiskSample>> {
+	let mut stmt = conn.prepare(
+		"SELECT disk_id, mount_path, sampled_at, total_space, available_space FROM disk_samples
+		WHERE sampled_at >= ?1 AND sampled_at <= ?2
+		ORDER BY disk_id ASC, sampled_at ASC",
+	)?;
+	let rows = stmt.query_map(
+		params![from.timestamp(), to.timestamp()],
+		disk_sample_from_row,
+	)?;
+	let mut samples = Vec::new();
+	for row in rows {
+		samples.push(row?);
+	}
+	Ok(samples)
+}
+
+/// Adds a reference to a stored blob, creating its row on first store.
+/// Returns the new reference count.
+pub fn content_store_add_ref(
+	conn: &Connection,
+	hash: &FileHash,
+	size: u64,
+	stored_at: DateTime<Utc>,
+) -> anyhow::Result<u64> {
+	conn.execute(
+		"INSERT INTO content_store (hash, size, ref_count, stored_at) VALUES (?1, ?2, 1, ?3)
+		ON CONFLICT(hash) DO UPDATE SET ref_count = ref_count + 1",
+		params![hash.to_vec(), size as i64, stored_at.timestamp()],
+	)?;
+	let count: i64 = conn.query_row(
+		"SELECT ref_count FROM content_store WHERE hash = ?1",
+		[hash.to_vec()],
+		|row| row.get(0),
+	)?;
+	Ok(count as u64)
+}
+
+/// Drops one reference. Returns the remaining count, `None` for unknown blobs.
+pub fn content_store_release(conn: &Connection, hash: &FileHash) -> anyhow::Result<Option<u64>> {
+	conn.execute(
+		"UPDATE content_store SET ref_count = MAX(ref_count - 1, 0) WHERE hash = ?1",
+		[hash.to_vec()],
+	)?;
+	let mut stmt = conn.prepare("SELECT ref_count FROM content_store WHERE hash = ?1")?;
+	let mut rows = stmt.query([hash.to_vec()])?;
+	if let Some(row) = rows.next()? {
+		return Ok(Some(row.get::<_, i64>(0)? as u64));
+	}
+	Ok(None)
+}
+
+pub fn save_setting(conn: &Connection, key: &str, value: &str) -> anyhow::Result<()> {
+	conn.execute(
+		"INSERT INTO settings (key, value) VALUES (?1, ?2)
+		 ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+		params![key, value],
+	)?;
+	Ok(())
+}
+
+pub fn load_setting(conn: &Connection, key: &str) -> anyhow::Result<Option<String>> {
+	let mut stmt = conn.prepare("SELECT value FROM settings WHERE key = ?1")?;
+	let mut rows = stmt.query([key])?;
+	match rows.next()? {
+		Some(row) => Ok(Some(row.get(0)?)),
+		None => Ok(None),
+	}
+}
+
+pub fn delete_setting(conn: &Connection, key: &str) -> anyhow::Result<()> {
+	conn.execute("DELETE FROM settings WHERE key = ?1", [key])?;
+	Ok(())
+}
+
+pub fn load_settings(conn: &Connection) -> anyhow::Result<Vec<(String, String)>> {
+	let mut stmt = conn.prepare("SELECT key, value FROM settings ORDER BY key ASC")?;
+	let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+	let mut settings = Vec::new();
+	for row in rows {
+		settings.push(row?);
+	}
+	Ok(settings)
+}
+
+pub fn content_store_contains(conn: &Connection, hash: &[u8]) -> anyhow::Result<bool> {
+	let mut stmt = conn.prepare("SELECT 1 FROM content_store WHERE hash = ?1 LIMIT 1")?;
+	let mut rows = stmt.query([hash])?;
+	Ok(rows.next()?.is_some())
+}
+
+pub fn content_store_unreferenced(conn: &Connection) -> anyhow::Result<Vec<FileHash>> {
+	let mut stmt = conn.prepare("SELECT hash FROM content_store WHERE ref_count = 0")?;
+	let rows = stmt.query_map([], |row| row.get::<_, Vec<u8>>(0))?;
+	let mut hashes = Vec::new();
+	for row in rows {
+		if let Ok(hash) = FileHash::try_from(row?.as_slice()) {
+			hashes.push(hash);
+		}
+	}
+	Ok(hashes)
+}
+
+/// Deletes the row only while it is still unreferenced.
+pub fn content_store_remove_unreferenced(
+	conn: &Connection,
+	hash: &FileHash,
+) -> anyhow::Result<bool> {
+	let removed = conn.execute(
+		"DELETE FROM content_store WHERE hash = ?1 AND ref_count = 0",
+		[hash.to_vec()],
+	)?;
+	Ok(removed > 0)
+}
+
+pub fn save_shared_folder(conn: &Connection, rule: &FolderRule) -> anyhow::Result<()> {
+	conn.execute(
+		"INSERT INTO shared_folders (path, flags, deny_patterns, allow_patterns)
+		VALUES (?1, ?2, ?3, ?4)
+		ON CONFLICT(path) DO UPDATE SET flags = excluded.flags,
+			deny_patterns = excluded.deny_patterns, allow_patterns = excluded.allow_patterns",
+		params![
+			rule.path().to_string_lossy(),
+			rule.flags() as i64,
+			patterns_column(rule.deny_patterns()),
+			patterns_column(rule.allow_patterns()),
+		],
+	)?;
+	Ok(())
+}
+
+pub fn delete_shared_folder(conn: &Connection, path: &Path) -> anyhow::Result<()> {
+	conn.execute(
+		"DELETE FROM shared_folders WHERE path = ?1",
+		params![path.to_string_lossy()],
+	)?;
+	Ok(())
+}
+
+pub fn load_shared_folders(conn: &Connection) -> anyhow::Result<Vec<FolderRule>> {
+	let mut stmt = conn.prepare(
+		"SELECT path, flags, deny_patterns, allow_patterns FROM shared_folders ORDER BY path ASC",
+	)?;
+	let rows = stmt.query_map([], |row| {
+		let path: String = row.get(0)?;
+		let flags: i64 = row.get(1)?;
+		let deny: String = row.get(2)?;
+		let allow: String = row.get(3)?;
+		Ok(FolderRule::new(PathBuf::from(path), flags as u8)
+			.with_patterns(patterns_from_column(&deny), patterns_from_column(&allow)))
+	})?;
+	let mut folders = Vec::new();
+	for row in rows {
+		folders.push(row?);
+	}
+	Ok(folders)
+}
+
+pub fn save_user(conn: &Connection, user: &User) -> anyhow::Result<()> {
+	conn.execute(
+		r#"
+		INSERT INTO users (username, password)
+		VALUES (?1, ?2)
+		ON CONFLICT(username) DO UPDATE SET password = excluded.password
+		"#,
+		params![&user.name, &user.passw],
+	)?;
+	Ok(())
+}
+
+pub fn load_users(conn: &Connection) -> anyhow::Result<Vec<User>> {
+	let mut stmt = conn.prepare("SELECT username, password FROM users ORDER BY username ASC")?;
+	let rows = stmt.query_map([], |row| {
+		Ok(User {
+			name: row.get(0)?,
+			passw: row.get(1)?,
+		})
+	})?;
+	let mut users = Vec::new();
+	for user in rows {
+		users.push(user?);
+	}
+	Ok(users)
+}
+
+pub fn load_user(conn: &Connection, username: &str) -> anyhow::Result<Option<User>> {
+	let mut stmt =
 		conn.prepare("SELECT username, password FROM users WHERE username = ?1 LIMIT 1")?;
 	let mut rows = stmt.query(params![username])?;
 	if let Some(row) = rows.next()? {
@@ -1213,7 +5396,7 @@ pub fn delete_session(conn: &Connection, token_hash: &[u8]) -> anyhow::Result<()
 ///
 /// Returns an `anyhow::Error` if any database operation fails.
 pub fn run_migrations(conn: &mut Connection) -> anyhow::Result<()> {
-	log::info!("running migrations");
+	tracing::info!("running migrations");
 	conn.execute(
 		"CREATE TABLE IF NOT EXISTS migrations (
             id INTEGER PRIMARY KEY,
@@ -1238,7 +5421,7 @@ pub fn run_migrations(conn: &mut Connection) -> anyhow::Result<()> {
 	pending_migrations.sort_by_key(|migration| migration.id);
 	if !pending_migrations.is_empty() {
 		for migration in &pending_migrations {
-			log::info!("applying migration {}: {}", migration.id, migration.name);
+			tracing::info!("applying migration {}: {}", migration.id, migration.name);
 
 			// Begin a transaction for atomicity
 			let tx = conn.transaction()?;
@@ -1255,20 +5438,410 @@ pub fn run_migrations(conn: &mut Connection) -> anyhow::Result<()> {
 			// Commit the transaction
 			tx.commit()?;
 
-			log::info!("migration {} applied successfully.", migration.id);
+			tracing::info!("migration {} applied successfully.", migration.id);
 		}
 	} else {
-		log::info!("No new migrations to apply.");
+		tracing::info!("No new migrations to apply.");
 	}
 
 	Ok(())
 }
 
+/// Where [`db_path`] points, without creating the app directory.
+pub(crate) fn db_location() -> PathBuf {
+	config::startup().db_path.clone()
+}
+
+pub fn db_path() -> PathBuf {
+	let path = db_location();
+	if config::startup_source("db_path") == ConfigSource::Default
+		&& let Some(dir) = path.parent()
+	{
+		std::fs::create_dir_all(dir).unwrap();
+	}
+	path
+}
+
+/// How long a connection waits on another process's lock before failing
+/// with "database is locked".
+pub(crate) const BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Journal settings for every connection to the node database. WAL lets
+/// readers carry on during a scan's write batches, and `NORMAL` syncs at
+/// checkpoints instead of on every commit, which WAL keeps crash safe.
+pub(crate) fn configure_connection(conn: &Connection) -> rusqlite::Result<()> {
+	conn.busy_timeout(BUSY_TIMEOUT)?;
+	conn.query_row("PRAGMA journal_mode = WAL", [], |row| {
+		row.get::<_, String>(0)
+	})?;
+	conn.execute_batch("PRAGMA synchronous = NORMAL;")
+}
+
+pub(crate) fn open_db_at(path: &Path) -> Connection {
+	let conn = Connection::open(path).unwrap();
+	if let Err(err) = configure_connection(&conn) {
+		tracing::warn!("failed to configure database journal: {err}");
+	}
+	conn
+}
+
 pub fn open_db() -> Connection {
-	let db_path = env::var_os("DB").map(PathBuf::from).unwrap_or_else(|| {
-		let path = homedir::my_home().unwrap().unwrap().join(".puppynet");
-		std::fs::create_dir_all(&path).unwrap();
-		path.join("puppynet.db")
-	});
-	Connection::open(db_path).unwrap()
+	open_db_at(&db_path())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util;
+
+	fn insert_location(conn: &Connection, node: u8, path: &str, hash: u8) {
+		conn.execute(
+			"INSERT OR IGNORE INTO file_entries (hash, size, latest_datetime)
+			 VALUES (?1, 1, '2025-03-01 12:00:00')",
+			params![vec![hash; 32]],
+		)
+		.unwrap();
+		conn.execute(
+			"INSERT INTO file_locations (node_id, path, hash, size, timestamp)
+			 VALUES (?1, ?2, ?3, 1, '2025-03-01 12:00:00')",
+			params![vec![node; 16], path, vec![hash; 32]],
+		)
+		.unwrap();
+	}
+
+	fn scoped_paths(conn: &Connection, node: u8, prefix: &str) -> Vec<String> {
+		let args = SearchFilesArgs {
+			node_id: Some([node; 16]),
+			path_prefix: Some(prefix.to_string()),
+			page_size: 50,
+			..Default::default()
+		};
+		let mut paths = search_files(conn, args)
+			.unwrap()
+			.0
+			.rows
+			.into_iter()
+			.map(|result| result.path)
+			.collect::<Vec<_>>();
+		paths.sort();
+		paths
+	}
+
+	#[test]
+	fn scoped_search_matches_folder_on_one_node_with_either_separator() {
+		let conn = test_util::memory_db();
+		insert_location(&conn, 1, "C:\\Users\\ann\\Docs\\cv.pdf", 1);
+		insert_location(&conn, 1, "C:\\Users\\ann\\Docs\\old\\tax.pdf", 2);
+		insert_location(&conn, 1, "C:\\Users\\ann\\Docs2\\notes.txt", 3);
+		insert_location(&conn, 2, "C:\\Users\\ann\\Docs\\other.pdf", 4);
+		// The same file elsewhere on node 1 must still show the scoped path.
+		insert_location(&conn, 1, "/backup/cv.pdf", 1);
+
+		let expected = vec![
+			String::from("C:\\Users\\ann\\Docs\\cv.pdf"),
+			String::from("C:\\Users\\ann\\Docs\\old\\tax.pdf"),
+		];
+		assert_eq!(scoped_paths(&conn, 1, "C:/Users/ann/Docs"), expected);
+		assert_eq!(scoped_paths(&conn, 1, "C:\\Users\\ann\\Docs\\"), expected);
+		assert_eq!(
+			scoped_paths(&conn, 1, "/backup"),
+			vec![String::from("/backup/cv.pdf")]
+		);
+		assert_eq!(scoped_paths(&conn, 1, "/").len(), 3);
+	}
+
+	#[test]
+	fn reviews_update_in_place_and_decisions_feed_peer_trust() {
+		let conn = test_util::memory_db();
+		let peer = PeerId::random();
+		let at = |secs: i64| DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap();
+		record_pending_review(&conn, &peer, "/srv/drop/a.txt", 10, at(0)).unwrap();
+		record_pending_review(&conn, &peer, "/srv/drop/b.txt", 20, at(1)).unwrap();
+		let first = load_pending_reviews(&conn).unwrap();
+		set_pending_review_hash(&conn, first[0].id, at(0), &[7; 32]).unwrap();
+		// Written again while pending: same entry, new size, hash dropped.
+		record_pending_review(&conn, &peer, "/srv/drop/a.txt", 15, at(2)).unwrap();
+
+		let reviews = load_pending_reviews(&conn).unwrap();
+		assert_eq!(count_pending_reviews(&conn).unwrap(), 2);
+		assert_eq!(reviews[0].path, "/srv/drop/b.txt");
+		assert_eq!(reviews[1].id, first[0].id);
+		assert_eq!(reviews[1].size, 15);
+		assert_eq!(reviews[1].hash, None);
+
+		let ids = reviews.iter().map(|review| review.id).collect::<Vec<_>>();
+		let accepted = decide_reviews(&conn, &ids[..1], ReviewDecision::Accept).unwrap();
+		assert_eq!(accepted.len(), 1);
+		let rejected = decide_reviews(&conn, &ids, ReviewDecision::Reject).unwrap();
+		assert_eq!(rejected.len(), 1);
+		assert_eq!(rejected[0].path, "/srv/drop/a.txt");
+		assert_eq!(count_pending_reviews(&conn).unwrap(), 0);
+		assert_eq!(
+			load_peer_trust(&conn, &peer).unwrap(),
+			PeerTrust {
+				accepted: 1,
+				rejected: 1,
+			}
+		);
+
+		assert!(take_rejected_review(&conn, &peer, "/srv/drop/a.txt").unwrap());
+		assert!(!take_rejected_review(&conn, &peer, "/srv/drop/a.txt").unwrap());
+		assert!(!take_rejected_review(&conn, &peer, "/srv/drop/b.txt").unwrap());
+	}
+
+	#[test]
+	fn confirmed_wake_mac_survives_learned_ones() {
+		let conn = test_util::memory_db();
+		let peer = PeerId::random();
+		let at = |secs: i64| DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap();
+		assert_eq!(load_wake_target(&conn, &peer).unwrap(), None);
+
+		record_wake_target(
+			&conn,
+			&peer,
+			"aa:aa:aa:aa:aa:01",
+			Some("10.0.0.5/24"),
+			at(0),
+		)
+		.unwrap();
+		confirm_wake_mac(&conn, &peer, "aa:aa:aa:aa:aa:02", at(1)).unwrap();
+		record_wake_target(
+			&conn,
+			&peer,
+			"aa:aa:aa:aa:aa:03",
+			Some("10.0.1.5/24"),
+			at(2),
+		)
+		.unwrap();
+
+		let target = load_wake_target(&conn, &peer).unwrap().unwrap();
+		assert_eq!(target.mac, "aa:aa:aa:aa:aa:02");
+		assert_eq!(target.subnet.as_deref(), Some("10.0.1.5/24"));
+		assert!(target.confirmed);
+		assert_eq!(target.updated_at, at(2));
+	}
+
+	#[test]
+	fn transfers_are_paged_newest_first_and_pruned_past_retention() {
+		let mut conn = test_util::memory_db();
+		let peer = PeerId::random();
+		let transfer = |n: u64| Transfer {
+			direction: TransferDirection::Download,
+			peer: peer.to_string(),
+			remote_path: format!("/srv/{n}.bin"),
+			local_path: format!("/dl/{n}.bin"),
+			size: n,
+			duration_ms: 1,
+			status: TransferStatus::Completed,
+			error: None,
+			hash: Some(vec![(n % 200) as u8; 32]),
+			finished_at: DateTime::from_timestamp(n as i64, 0).unwrap(),
+			note: None,
+		};
+		let tx = conn.transaction().unwrap();
+		for n in 0..TRANSFER_RETENTION + 3 {
+			record_transfer(&tx, &transfer(n)).unwrap();
+		}
+		tx.commit().unwrap();
+
+		let count: i64 = conn
+			.query_row("SELECT COUNT(*) FROM transfers", [], |row| row.get(0))
+			.unwrap();
+		assert_eq!(count as u64, TRANSFER_RETENTION);
+		let page = load_transfers(&conn, Some(&peer.to_string()), 1, 2).unwrap();
+		assert_eq!(
+			page.iter().map(|t| t.size).collect::<Vec<_>>(),
+			vec![TRANSFER_RETENTION + 1, TRANSFER_RETENTION]
+		);
+		assert!(
+			load_transfers(&conn, Some(&PeerId::random().to_string()), 0, 10)
+				.unwrap()
+				.is_empty()
+		);
+		let last = last_download_of(&conn, &[7; 32]).unwrap().unwrap();
+		let newest_with_hash = (0..TRANSFER_RETENTION + 3).filter(|n| n % 200 == 7).max();
+		assert_eq!(Some(last.size), newest_with_hash);
+
+		insert_location(&conn, 1, "/srv/a.bin", 9);
+		assert_eq!(
+			indexed_hash(&conn, &[1; 16], "/srv/a.bin").unwrap(),
+			Some(vec![9; 32])
+		);
+		assert_eq!(indexed_hash(&conn, &[2; 16], "/srv/a.bin").unwrap(), None);
+	}
+
+	#[test]
+	fn rows_from_before_provenance_read_as_local_scans() {
+		let conn = test_util::memory_db();
+		insert_location(&conn, 1, "/old/file.txt", 1);
+		let provenance = file_provenance(&conn, &[1; 32], Path::new("/old/file.txt"))
+			.unwrap()
+			.unwrap();
+		assert_eq!(provenance.origin, FileOrigin::LocalScan);
+		assert_eq!(
+			provenance.describe("desktop", None),
+			"indexed by a scan on desktop"
+		);
+
+		let written = FileProvenance {
+			user: Some(String::from("anna")),
+			introduced_at: Some(1_718_150_400),
+			..FileProvenance::remote_write(&[1; 16], &PeerId::random(), Utc::now())
+		};
+		assert_eq!(
+			written.describe("desktop", Some("laptop")),
+			"written by peer laptop as user anna, 2024-06-12"
+		);
+		let scanned = FileProvenance {
+			origin: FileOrigin::LocalScan,
+			scan_run: Some(42),
+			peer: None,
+			user: None,
+			introduced_at: Some(1_714_521_600),
+			..written
+		};
+		assert_eq!(
+			scanned.describe("desktop", None),
+			"indexed by scan #42 on desktop, 2024-05-01"
+		);
+	}
+
+	#[test]
+	fn peers_known_before_trust_levels_stay_trusted_and_new_ones_start_untrusted() {
+		let mut conn = test_util::memory_db();
+		conn.execute_batch(
+			"DELETE FROM migrations WHERE id = 20250412;
+			 ALTER TABLE peers DROP COLUMN trust;",
+		)
+		.unwrap();
+		let known = PeerId::random();
+		conn.execute(
+			"INSERT INTO peers (peer_id, name) VALUES (?1, 'laptop')",
+			params![known.to_string()],
+		)
+		.unwrap();
+		run_migrations(&mut conn).unwrap();
+
+		let new = PeerId::random();
+		let peer = |id, name: Option<&str>| Peer {
+			id,
+			name: name.map(String::from),
+			trust: TrustLevel::Untrusted,
+		};
+		save_peer(&conn, &peer(new, None)).unwrap();
+		// Seeing a known peer again doesn't reset its trust.
+		save_peer(&conn, &peer(known, Some("laptop"))).unwrap();
+		let trust_of = |conn: &Connection, id| {
+			load_peers(conn)
+				.unwrap()
+				.into_iter()
+				.find(|peer| peer.id == id)
+				.map(|peer| peer.trust)
+		};
+		assert_eq!(trust_of(&conn, known), Some(TrustLevel::Trusted));
+		assert_eq!(trust_of(&conn, new), Some(TrustLevel::Untrusted));
+
+		save_trust_level(&conn, &new, TrustLevel::Basic).unwrap();
+		let unseen = PeerId::random();
+		save_trust_level(&conn, &unseen, TrustLevel::Owner).unwrap();
+		assert_eq!(trust_of(&conn, new), Some(TrustLevel::Basic));
+		assert_eq!(trust_of(&conn, unseen), Some(TrustLevel::Owner));
+	}
+
+	#[test]
+	fn media_filters_use_extracted_metadata_and_extraction_skips_known_hashes() {
+		let conn = test_util::memory_db();
+		for (path, hash) in [("/m/film.mkv", 1), ("/m/clip.mp4", 2), ("/m/photo.jpg", 3)] {
+			insert_location(&conn, 1, path, hash);
+		}
+		insert_location(&conn, 1, "/m/copy-of-film.mkv", 1);
+		for (hash, mime) in [(1, "video/x-matroska"), (2, "video/mp4"), (3, "image/jpeg")] {
+			conn.execute(
+				"UPDATE file_entries SET mime_type = ?2 WHERE hash = ?1",
+				params![vec![hash as u8; 32], mime],
+			)
+			.unwrap();
+		}
+		let pending = pending_media_files(&conn, &[1; 16], Some("/m")).unwrap();
+		assert_eq!(
+			pending.iter().map(|file| file.hash[0]).collect::<Vec<_>>(),
+			vec![2, 1, 3]
+		);
+
+		let film = MediaMetadata {
+			width: Some(1920),
+			height: Some(1080),
+			duration_ms: Some(2 * 3_600_000),
+			codec: Some(String::from("h264")),
+			error: None,
+		};
+		save_media_metadata(&conn, &[1; 32], &film).unwrap();
+		let clip = MediaMetadata {
+			duration_ms: Some(90_000),
+			..film.clone()
+		};
+		save_media_metadata(&conn, &[2; 32], &clip).unwrap();
+		save_media_metadata(&conn, &[3; 32], &MediaMetadata::failed("truncated")).unwrap();
+		assert!(
+			pending_media_files(&conn, &[1; 16], None)
+				.unwrap()
+				.is_empty()
+		);
+		assert_eq!(load_media_metadata(&conn, &[1; 32]).unwrap(), Some(film));
+
+		let hashes = |args: SearchFilesArgs| {
+			let mut hashes = search_files(&conn, args)
+				.unwrap()
+				.0
+				.rows
+				.into_iter()
+				.map(|row| row.hash[0])
+				.collect::<Vec<_>>();
+			hashes.sort();
+			hashes
+		};
+		let long = hashes(SearchFilesArgs {
+			min_duration: Some(3_600),
+			..Default::default()
+		});
+		assert_eq!(long, vec![1]);
+		let short = hashes(SearchFilesArgs {
+			max_duration: Some(120),
+			min_pixels: Some(2_000_000),
+			..Default::default()
+		});
+		assert_eq!(short, vec![2]);
+		let rows = search_files(&conn, SearchFilesArgs::default())
+			.unwrap()
+			.0
+			.rows;
+		let film_row = rows.iter().find(|row| row.hash[0] == 1).unwrap();
+		assert_eq!(film_row.duration_ms, Some(7_200_000));
+		assert_eq!(film_row.codec.as_deref(), Some("h264"));
+	}
+	#[test]
+	fn protocol_stats_add_up_per_day_and_expire() {
+		let conn = test_util::memory_db();
+		let peer = PeerId::random();
+		let counts = ProtocolCounts {
+			requests: 3,
+			bytes_in: 120,
+			rate_limited: 1,
+			..Default::default()
+		};
+		let row = || vec![(peer.to_string(), "ListDir", counts)];
+		let old = NaiveDate::from_ymd_opt(2026, 1, 1).unwrap();
+		let today = NaiveDate::from_ymd_opt(2026, 6, 1).unwrap();
+		record_protocol_stats(&conn, old, &row()).unwrap();
+		record_protocol_stats(&conn, today, &row()).unwrap();
+		record_protocol_stats(&conn, today, &row()).unwrap();
+
+		let days = load_protocol_stats(&conn, &peer, old).unwrap();
+		assert_eq!(days.len(), 1);
+		assert_eq!(days[0].day, today);
+		assert_eq!(days[0].request, "ListDir");
+		assert_eq!(days[0].counts.requests, 6);
+		assert_eq!(days[0].counts.bytes_in, 240);
+		assert_eq!(days[0].counts.rate_limited, 2);
+	}
 }
@@ -0,0 +1,1017 @@
+//! SQL for index searches. [`SearchQuery`] checks a [`SearchFilesArgs`]
+//! and turns its filters into one parameterized `WHERE` over
+//! `file_entries fe`, which the search page, searches run for peers, the
+//! scan results listing and `/api/search` all share. User text only reaches
+//! the database as a parameter, and name queries escape `LIKE`'s own
+//! wildcards.
+
+use super::{FileOrigin, NodeID, SearchFilesArgs, SearchSortBy};
+use crate::pagination::{PageCursor, order_clause};
+use rusqlite::types::Value;
+use std::fmt;
+
+/// Rows one page may ask for.
+pub const MAX_SEARCH_PAGE_SIZE: usize = 1000;
+/// Rows a numbered page may start after; deeper pages need a cursor.
+pub const MAX_SEARCH_OFFSET: usize = 100_000;
+/// Longest name query, in characters.
+pub const MAX_NAME_QUERY_LEN: usize = 256;
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// A search refused before it reached the database.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SearchQueryError {
+	PageSizeTooLarge {
+		requested: usize,
+	},
+	OffsetTooLarge {
+		offset: usize,
+	},
+	NameQueryTooLong {
+		len: usize,
+	},
+	/// The lower bound of `filter` is above its upper bound.
+	InvertedRange {
+		filter: &'static str,
+		low: String,
+		high: String,
+	},
+}
+
+impl fmt::Display for SearchQueryError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::PageSizeTooLarge { requested } => write!(
+				f,
+				"page size {requested} is above the limit of {MAX_SEARCH_PAGE_SIZE}"
+			),
+			Self::OffsetTooLarge { offset } => write!(
+				f,
+				"cannot skip {offset} results, only {MAX_SEARCH_OFFSET}; continue with a cursor instead"
+			),
+			Self::NameQueryTooLong { len } => write!(
+				f,
+				"name query is {len} characters long, the limit is {MAX_NAME_QUERY_LEN}"
+			),
+			Self::InvertedRange { filter, low, high } => {
+				write!(f, "{filter} range is inverted: {low} is above {high}")
+			}
+		}
+	}
+}
+
+impl std::error::Error for SearchQueryError {}
+
+/// Conditions and the parameters they bind, numbered in binding order.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SqlFilter {
+	conditions: Vec<String>,
+	params: Vec<Value>,
+}
+
+impl SqlFilter {
+	/// Binds `value` and returns its placeholder.
+	fn bind(&mut self, value: Value) -> String {
+		self.params.push(value);
+		format!("?{}", self.params.len())
+	}
+
+	fn push(&mut self, condition: String) {
+		self.conditions.push(condition);
+	}
+
+	/// Narrows to the rows after `cursor` in `(latest_datetime, hash)` order.
+	fn after(&mut self, cursor: Option<&PageCursor>, desc: bool) {
+		if let Some(cursor) = cursor {
+			let (clause, params) = cursor.after_clause(desc, self.params.len() + 1);
+			self.conditions.push(clause);
+			self.params.extend(params);
+		}
+	}
+
+	/// ` WHERE …`, or nothing without conditions.
+	pub(crate) fn where_sql(&self) -> String {
+		if self.conditions.is_empty() {
+			String::new()
+		} else {
+			format!(" WHERE {}", self.conditions.join(" AND "))
+		}
+	}
+
+	pub(crate) fn params(&self) -> &[Value] {
+		&self.params
+	}
+}
+
+/// `min..=max`, either side optional.
+#[derive(Clone, Debug)]
+struct Bounds<T> {
+	min: Option<T>,
+	max: Option<T>,
+}
+
+impl<T: PartialOrd + ToString> Bounds<T> {
+	fn new(filter: &'static str, min: Option<T>, max: Option<T>) -> Result<Self, SearchQueryError> {
+		if let (Some(low), Some(high)) = (&min, &max)
+			&& low > high
+		{
+			return Err(SearchQueryError::InvertedRange {
+				filter,
+				low: low.to_string(),
+				high: high.to_string(),
+			});
+		}
+		Ok(Self { min, max })
+	}
+}
+
+/// Half-open ranges `[low, high)` covering every path under `prefix`, one
+/// per separator style. Range bounds rather than `LIKE` keep the match on
+/// the path index, and the upper bound bumps the trailing separator to the
+/// next byte (`/` to `0`, `\` to `]`).
+fn path_prefix_ranges(prefix: &str) -> Vec<(String, String)> {
+	let trimmed = prefix.trim().trim_end_matches(['/', '\\']);
+	if trimmed.is_empty() {
+		return Vec::new();
+	}
+	[('/', '0'), ('\\', ']')]
+		.into_iter()
+		.map(|(sep, next)| {
+			let dir = trimmed.replace(['/', '\\'], &sep.to_string());
+			(format!("{dir}{sep}"), format!("{dir}{next}"))
+		})
+		.collect()
+}
+
+/// `query` as a `LIKE` pattern for `ESCAPE '\'` matching paths that
+/// contain it. `*` and `?` are the wildcards; `%`, `_` and `\` match
+/// themselves.
+fn name_pattern(query: &str) -> String {
+	let mut pattern = String::from("%");
+	for c in query.chars() {
+		match c {
+			'*' => pattern.push('%'),
+			'?' => pattern.push('_'),
+			'%' | '_' | '\\' => {
+				pattern.push('\\');
+				pattern.push(c);
+			}
+			c => pattern.push(c),
+		}
+	}
+	pattern.push('%');
+	pattern
+}
+
+/// The `file_locations` rows a search is about. A file matches through a
+/// location meeting every condition, and that location is the one shown.
+#[derive(Clone, Debug, Default)]
+struct LocationScope {
+	node_id: Option<NodeID>,
+	path_ranges: Vec<(String, String)>,
+	name: Option<String>,
+	origin: Option<FileOrigin>,
+	introduced_by_peer: Option<String>,
+}
+
+impl LocationScope {
+	fn is_empty(&self) -> bool {
+		self.node_id.is_none()
+			&& self.path_ranges.is_empty()
+			&& self.name.is_none()
+			&& self.origin.is_none()
+			&& self.introduced_by_peer.is_none()
+	}
+
+	/// Conditions on `alias`, a `file_locations` row, binding into `sql`.
+	fn conditions(&self, alias: &str, sql: &mut SqlFilter) -> Vec<String> {
+		let mut conditions = Vec::new();
+		if let Some(node_id) = self.node_id {
+			let node_id = sql.bind(Value::Blob(node_id.to_vec()));
+			conditions.push(format!("{alias}.node_id = {node_id}"));
+		}
+		if !self.path_ranges.is_empty() {
+			let ranges = self
+				.path_ranges
+				.iter()
+				.map(|(low, high)| {
+					let low = sql.bind(Value::Text(low.clone()));
+					let high = sql.bind(Value::Text(high.clone()));
+					format!("({alias}.path >= {low} AND {alias}.path < {high})")
+				})
+				.collect::<Vec<_>>();
+			conditions.push(format!("({})", ranges.join(" OR ")));
+		}
+		if let Some(name) = &self.name {
+			let name = sql.bind(Value::Text(name.clone()));
+			conditions.push(format!("{alias}.path LIKE {name} ESCAPE '\\'"));
+		}
+		if let Some(origin) = self.origin {
+			let origin = sql.bind(Value::Text(origin.as_str().to_string()));
+			conditions.push(format!("{alias}.origin = {origin}"));
+		}
+		if let Some(peer) = &self.introduced_by_peer {
+			let peer = sql.bind(Value::Text(peer.clone()));
+			conditions.push(format!("{alias}.origin_peer = {peer}"));
+		}
+		conditions
+	}
+}
+
+/// Conditions keeping `alias`, a row keyed by `node_id` and `path` like
+/// `file_locations`, to the node, folder and origin of `args`, with the
+/// parameters they bind. For queries over locations rather than entries.
+pub(crate) fn location_scope(args: &SearchFilesArgs, alias: &str) -> (Vec<String>, Vec<Value>) {
+	let scope = LocationScope {
+		node_id: args.node_id,
+		path_ranges: args
+			.path_prefix
+			.as_deref()
+			.map(path_prefix_ranges)
+			.unwrap_or_default(),
+		name: None,
+		origin: args.origin,
+		introduced_by_peer: args.introduced_by_peer.clone(),
+	};
+	let mut sql = SqlFilter::default();
+	let conditions = scope.conditions(alias, &mut sql);
+	(conditions, sql.params)
+}
+
+/// `offset` if a numbered page may start there.
+pub(crate) fn check_offset(offset: usize) -> Result<usize, SearchQueryError> {
+	if offset > MAX_SEARCH_OFFSET {
+		return Err(SearchQueryError::OffsetTooLarge { offset });
+	}
+	Ok(offset)
+}
+
+/// Text filters count only when they hold more than whitespace.
+fn non_blank(value: &Option<String>) -> Option<String> {
+	value.clone().filter(|value| !value.trim().is_empty())
+}
+
+fn to_sql_integer(value: u64) -> Value {
+	Value::Integer(value.min(i64::MAX as u64) as i64)
+}
+
+/// A checked search, ready to become SQL.
+#[derive(Clone, Debug)]
+pub(crate) struct SearchQuery {
+	scope: LocationScope,
+	mime_types: Vec<String>,
+	dates: Bounds<String>,
+	replicas: Bounds<u64>,
+	include_empty: bool,
+	duration_ms: Bounds<u64>,
+	min_pixels: Option<u64>,
+	include_deleted: bool,
+	sort_by: SearchSortBy,
+	sort_desc: bool,
+	page_size: usize,
+	offset: usize,
+}
+
+impl SearchQuery {
+	pub(crate) fn new(args: &SearchFilesArgs) -> Result<Self, SearchQueryError> {
+		let page_size = match args.page_size {
+			0 => DEFAULT_PAGE_SIZE,
+			requested if requested > MAX_SEARCH_PAGE_SIZE => {
+				return Err(SearchQueryError::PageSizeTooLarge { requested });
+			}
+			page_size => page_size,
+		};
+		let offset = check_offset(args.page.saturating_mul(page_size))?;
+		let name = match non_blank(&args.name_query) {
+			Some(query) if query.chars().count() > MAX_NAME_QUERY_LEN => {
+				return Err(SearchQueryError::NameQueryTooLong {
+					len: query.chars().count(),
+				});
+			}
+			query => query.map(|query| name_pattern(query.trim())),
+		};
+		let seconds_to_ms = |secs: Option<u64>| secs.map(|secs| secs.saturating_mul(1000));
+		Ok(Self {
+			scope: LocationScope {
+				node_id: args.node_id,
+				path_ranges: args
+					.path_prefix
+					.as_deref()
+					.map(path_prefix_ranges)
+					.unwrap_or_default(),
+				name,
+				origin: args.origin,
+				introduced_by_peer: args.introduced_by_peer.clone(),
+			},
+			mime_types: args.mime_types.clone(),
+			dates: Bounds::new("date", non_blank(&args.date_from), non_blank(&args.date_to))?,
+			replicas: Bounds::new("replicas", args.replicas_min, args.replicas_max)?,
+			include_empty: args.include_empty,
+			duration_ms: Bounds::new(
+				"duration",
+				seconds_to_ms(args.min_duration),
+				seconds_to_ms(args.max_duration),
+			)?,
+			min_pixels: args.min_pixels,
+			include_deleted: args.include_deleted,
+			sort_by: args.sort_by,
+			sort_desc: args.sort_desc,
+			page_size,
+			offset,
+		})
+	}
+
+	/// Every entry, newest first, `page_size` at a time.
+	pub(crate) fn all(page_size: usize) -> Result<Self, SearchQueryError> {
+		Self::new(&SearchFilesArgs {
+			sort_desc: true,
+			page_size,
+			..Default::default()
+		})
+	}
+
+	pub(crate) fn page_size(&self) -> usize {
+		self.page_size
+	}
+
+	pub(crate) fn sort_by(&self) -> SearchSortBy {
+		self.sort_by
+	}
+
+	pub(crate) fn sort_desc(&self) -> bool {
+		self.sort_desc
+	}
+
+	/// Rows before the numbered page asked for.
+	pub(crate) fn offset(&self) -> usize {
+		self.offset
+	}
+
+	/// Keeps `alias` to live locations, unless deleted ones were asked for.
+	fn live(&self, alias: &str) -> String {
+		if self.include_deleted {
+			String::new()
+		} else {
+			format!(" AND {alias}.deleted_at IS NULL")
+		}
+	}
+
+	/// The filters on `file_entries fe`.
+	pub(crate) fn filter(&self) -> SqlFilter {
+		let mut sql = SqlFilter::default();
+		if !self.scope.is_empty() {
+			let scope = self.scope.conditions("fl4", &mut sql);
+			sql.push(format!(
+				"EXISTS (SELECT 1 FROM file_locations fl4 WHERE fl4.hash = fe.hash AND {}{})",
+				scope.join(" AND "),
+				self.live("fl4")
+			));
+		}
+		if !self.mime_types.is_empty() {
+			let placeholders = self
+				.mime_types
+				.iter()
+				.map(|mime| sql.bind(Value::Text(mime.clone())))
+				.collect::<Vec<_>>();
+			sql.push(format!("fe.mime_type IN ({})", placeholders.join(", ")));
+		}
+		if let Some(from) = &self.dates.min {
+			let from = sql.bind(Value::Text(from.clone()));
+			sql.push(format!("fe.first_datetime >= {from}"));
+		}
+		if let Some(to) = &self.dates.max {
+			let to = sql.bind(Value::Text(to.clone()));
+			sql.push(format!("fe.latest_datetime <= {to}"));
+		}
+		let replicas = "(SELECT COUNT(*) FROM file_locations fl3 \
+			WHERE fl3.hash = fe.hash AND fl3.deleted_at IS NULL)";
+		for (bound, op) in [(self.replicas.min, ">="), (self.replicas.max, "<=")] {
+			if let Some(bound) = bound {
+				let bound = sql.bind(to_sql_integer(bound));
+				sql.push(format!("{replicas} {op} {bound}"));
+			}
+		}
+		let replica_bounds = self.replicas.min.is_some() || self.replicas.max.is_some();
+		if replica_bounds && !self.include_empty {
+			sql.push(String::from("fe.size > 0"));
+		}
+		let media_filters = [
+			(self.duration_ms.min, "mm.duration_ms >="),
+			(self.duration_ms.max, "mm.duration_ms <="),
+			(self.min_pixels, "mm.width * mm.height >="),
+		];
+		for (value, condition) in media_filters {
+			if let Some(value) = value {
+				let value = sql.bind(to_sql_integer(value));
+				sql.push(format!(
+					"EXISTS (SELECT 1 FROM media_metadata mm WHERE mm.hash = fe.hash AND {condition} {value})"
+				));
+			}
+		}
+		sql
+	}
+
+	/// Counts every matching entry.
+	pub(crate) fn count_sql(&self) -> (String, Vec<Value>) {
+		let filter = self.filter();
+		(
+			format!("SELECT COUNT(*) FROM file_entries fe{}", filter.where_sql()),
+			filter.params,
+		)
+	}
+
+	/// `columns` of the page after `cursor`, plus one probe row telling
+	/// whether another page follows.
+	pub(crate) fn select_sql(
+		&self,
+		columns: &str,
+		cursor: Option<&PageCursor>,
+	) -> (String, Vec<Value>) {
+		let mut filter = self.filter();
+		filter.after(cursor, self.sort_desc);
+		(
+			format!(
+				"SELECT {columns} FROM file_entries fe{}{} LIMIT {}",
+				filter.where_sql(),
+				order_clause(self.sort_desc),
+				self.page_size + 1
+			),
+			filter.params,
+		)
+	}
+
+	/// Conditions on `fl2` picking the location shown for an entry: one
+	/// inside the scope, binding into `filter`.
+	fn shown(&self, filter: &mut SqlFilter) -> String {
+		self.scope
+			.conditions("fl2", filter)
+			.into_iter()
+			.map(|condition| format!(" AND {condition}"))
+			.collect::<String>()
+			+ &self.live("fl2")
+	}
+
+	/// The result rows matching `filter`, with the columns
+	/// [`super::search_files_after`] reads, followed by `tail`.
+	fn results_sql(filter: &SqlFilter, shown: &str, tail: &str) -> String {
+		format!(
+			"SELECT
+				fe.hash,
+				COALESCE({}, '') as path,
+				COALESCE({}, X'') as node_id,
+				fe.size,
+				fe.mime_type,
+				(SELECT COUNT(*) FROM file_locations fl3 WHERE fl3.hash = fe.hash AND fl3.deleted_at IS NULL) as replicas,
+				fe.first_datetime,
+				fe.latest_datetime,
+				mm.duration_ms,
+				mm.width,
+				mm.height,
+				mm.codec,
+				{} as deleted_at
+			FROM file_entries fe
+			LEFT JOIN media_metadata mm ON mm.hash = fe.hash{}{tail}",
+			shown_location("path", shown),
+			shown_location("node_id", shown),
+			shown_location("deleted_at", shown),
+			filter.where_sql(),
+		)
+	}
+
+	/// The result page after `cursor`, with the columns
+	/// [`super::search_files_after`] reads. The location shown is one inside
+	/// the scope, a live one over a deleted one.
+	pub(crate) fn page_sql(&self, cursor: Option<&PageCursor>) -> (String, Vec<Value>) {
+		let mut filter = self.filter();
+		let shown = self.shown(&mut filter);
+		filter.after(cursor, self.sort_desc);
+		let tail = format!(
+			"{} LIMIT {}",
+			order_clause(self.sort_desc),
+			self.page_size + 1
+		);
+		(Self::results_sql(&filter, &shown, &tail), filter.params)
+	}
+
+	/// Hash and shown path of every matching entry, for ordering by name,
+	/// which SQLite cannot do naturally.
+	pub(crate) fn names_sql(&self) -> (String, Vec<Value>) {
+		let mut filter = self.filter();
+		let shown = self.shown(&mut filter);
+		(
+			format!(
+				"SELECT fe.hash, COALESCE({}, '') FROM file_entries fe{}",
+				shown_location("path", &shown),
+				filter.where_sql()
+			),
+			filter.params,
+		)
+	}
+
+	/// The result rows of the matching entries among `hashes`, unordered.
+	pub(crate) fn rows_sql(&self, hashes: &[Vec<u8>]) -> (String, Vec<Value>) {
+		let mut filter = self.filter();
+		let shown = self.shown(&mut filter);
+		let placeholders = hashes
+			.iter()
+			.map(|hash| filter.bind(Value::Blob(hash.clone())))
+			.collect::<Vec<_>>();
+		filter.push(format!("fe.hash IN ({})", placeholders.join(", ")));
+		(Self::results_sql(&filter, &shown, ""), filter.params)
+	}
+}
+
+/// `column` of the location shown for `fe`, a live one over a deleted one,
+/// where `shown` holds the conditions from [`SearchQuery::shown`].
+fn shown_location(column: &str, shown: &str) -> String {
+	format!(
+		"(SELECT fl2.{column} FROM file_locations fl2 WHERE fl2.hash = fe.hash{shown} \
+		ORDER BY fl2.deleted_at IS NOT NULL, fl2.deleted_at DESC LIMIT 1)"
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::db::search_files_after;
+	use crate::test_util;
+	use rusqlite::{Connection, params};
+
+	const HERE: NodeID = [1; 16];
+	const THERE: NodeID = [2; 16];
+
+	struct Location {
+		node: NodeID,
+		path: &'static str,
+		deleted: bool,
+		remote: bool,
+	}
+
+	struct Fixture {
+		hash: u8,
+		mime: &'static str,
+		first: Option<&'static str>,
+		latest: Option<&'static str>,
+		duration_ms: Option<u64>,
+		locations: Vec<Location>,
+	}
+
+	fn at(node: NodeID, path: &'static str) -> Location {
+		Location {
+			node,
+			path,
+			deleted: false,
+			remote: false,
+		}
+	}
+
+	fn deleted(location: Location) -> Location {
+		Location {
+			deleted: true,
+			..location
+		}
+	}
+
+	fn remote(location: Location) -> Location {
+		Location {
+			remote: true,
+			..location
+		}
+	}
+
+	fn fixtures() -> Vec<Fixture> {
+		let file = |hash, mime, first, latest, locations| Fixture {
+			hash,
+			mime,
+			first,
+			latest,
+			duration_ms: None,
+			locations,
+		};
+		vec![
+			file(
+				1,
+				"image/jpeg",
+				Some("2024-03-01"),
+				Some("2024-03-02"),
+				vec![
+					at(HERE, "/srv/photos/cat_1.jpg"),
+					at(THERE, "/backup/cat_1.jpg"),
+				],
+			),
+			file(
+				2,
+				"image/jpeg",
+				Some("2023-05-01"),
+				Some("2024-02-01"),
+				vec![remote(at(HERE, "/srv/photos/dog.jpg"))],
+			),
+			Fixture {
+				duration_ms: Some(30_000),
+				..file(
+					3,
+					"video/mp4",
+					Some("2024-06-01"),
+					Some("2024-06-01"),
+					vec![
+						deleted(remote(at(HERE, "/srv/photos/clip_a.mp4"))),
+						at(THERE, "/srv/videos/clip_a.mp4"),
+					],
+				)
+			},
+			Fixture {
+				duration_ms: Some(5_000),
+				..file(
+					4,
+					"video/mp4",
+					Some("2024-07-01"),
+					Some("2025-01-05"),
+					vec![at(THERE, "/srv/photos/short.mp4")],
+				)
+			},
+			file(
+				5,
+				"image/jpeg",
+				Some("2024-08-01"),
+				Some("2024-08-01"),
+				vec![
+					at(HERE, "\\srv\\photos\\win_shot.jpg"),
+					remote(at(HERE, "/srv/other/win_shot.jpg")),
+				],
+			),
+			file(
+				6,
+				"text/plain",
+				Some("2024-02-02"),
+				Some("2024-02-03"),
+				vec![deleted(at(HERE, "/srv/photos/notes_old.txt"))],
+			),
+			file(
+				7,
+				"image/jpeg",
+				Some("2024-04-04"),
+				Some("2024-04-05"),
+				Vec::new(),
+			),
+			Fixture {
+				duration_ms: Some(120_000),
+				..file(
+					8,
+					"audio/mpeg",
+					None,
+					None,
+					vec![
+						remote(at(HERE, "/srv/photos/song_b.mp3")),
+						remote(at(THERE, "/srv/music/song_b.mp3")),
+						deleted(at(THERE, "/srv/x/song_b.mp3")),
+					],
+				)
+			},
+			file(
+				9,
+				"image/jpeg",
+				Some("2024-05-05"),
+				Some("2024-05-05"),
+				vec![at(HERE, "/srv/photos-old/pic_9.jpg")],
+			),
+		]
+	}
+
+	fn fixture_db(fixtures: &[Fixture]) -> Connection {
+		let conn = test_util::memory_db();
+		for fixture in fixtures {
+			let hash = vec![fixture.hash; 32];
+			conn.execute(
+				"INSERT INTO file_entries (hash, size, mime_type, first_datetime, latest_datetime)
+				 VALUES (?1, 1, ?2, ?3, ?4)",
+				params![hash, fixture.mime, fixture.first, fixture.latest],
+			)
+			.unwrap();
+			if let Some(duration_ms) = fixture.duration_ms {
+				conn.execute(
+					"INSERT INTO media_metadata (hash, duration_ms, extracted_at) VALUES (?1, ?2, 0)",
+					params![hash, duration_ms as i64],
+				)
+				.unwrap();
+			}
+			for location in &fixture.locations {
+				let (origin, peer) = if location.remote {
+					("remote_write", Some("12D3KooWpeer"))
+				} else {
+					("local_scan", None)
+				};
+				conn.execute(
+					"INSERT INTO file_locations
+						(node_id, path, hash, size, timestamp, deleted_at, origin, origin_peer)
+					 VALUES (?1, ?2, ?3, 1, '2024-01-01', ?4, ?5, ?6)",
+					params![
+						location.node.to_vec(),
+						location.path,
+						hash,
+						location.deleted.then_some(1_700_000_000i64),
+						origin,
+						peer
+					],
+				)
+				.unwrap();
+			}
+		}
+		conn
+	}
+
+	const FILTERS: [&str; 8] = [
+		"name",
+		"mime",
+		"dates",
+		"replicas",
+		"scope",
+		"media",
+		"provenance",
+		"include_deleted",
+	];
+
+	/// The search with the filters whose bits are set in `mask`.
+	fn combination(mask: usize) -> SearchFilesArgs {
+		let on =
+			|filter: &str| mask & (1 << FILTERS.iter().position(|f| *f == filter).unwrap()) != 0;
+		let mut args = SearchFilesArgs {
+			sort_desc: true,
+			page_size: MAX_SEARCH_PAGE_SIZE,
+			include_deleted: on("include_deleted"),
+			..Default::default()
+		};
+		if on("name") {
+			args.name_query = Some(String::from("_"));
+		}
+		if on("mime") {
+			args.mime_types = vec![String::from("image/jpeg"), String::from("video/mp4")];
+		}
+		if on("dates") {
+			args.date_from = Some(String::from("2024-01-01"));
+			args.date_to = Some(String::from("2024-12-31"));
+		}
+		if on("replicas") {
+			args.replicas_min = Some(2);
+		}
+		if on("scope") {
+			args.node_id = Some(HERE);
+			args.path_prefix = Some(String::from("/srv/photos/"));
+		}
+		if on("media") {
+			args.min_duration = Some(10);
+		}
+		if on("provenance") {
+			args.origin = Some(FileOrigin::RemoteWrite);
+			args.introduced_by_peer = Some(String::from("12D3KooWpeer"));
+		}
+		args
+	}
+
+	/// The same search, done by hand over the fixtures.
+	fn expected(fixtures: &[Fixture], args: &SearchFilesArgs) -> Vec<u8> {
+		let scoped = args.node_id.is_some()
+			|| args.path_prefix.is_some()
+			|| args.name_query.is_some()
+			|| args.origin.is_some();
+		let in_scope =
+			|location: &Location| {
+				args.node_id.is_none_or(|node| location.node == node)
+					&& args.path_prefix.as_ref().is_none_or(|_| {
+						location.path.replace('\\', "/").starts_with("/srv/photos/")
+					}) && args
+					.name_query
+					.as_ref()
+					.is_none_or(|query| location.path.contains(query.as_str()))
+					&& args.origin.is_none_or(|_| location.remote)
+					&& (args.include_deleted || !location.deleted)
+			};
+		let mut matches = fixtures
+			.iter()
+			.filter(|fixture| !scoped || fixture.locations.iter().any(in_scope))
+			.filter(|fixture| {
+				args.mime_types.is_empty()
+					|| args.mime_types.iter().any(|mime| mime == fixture.mime)
+			})
+			.filter(|fixture| {
+				args.date_from
+					.as_deref()
+					.is_none_or(|from| fixture.first.is_some_and(|first| first >= from))
+					&& args
+						.date_to
+						.as_deref()
+						.is_none_or(|to| fixture.latest.is_some_and(|latest| latest <= to))
+			})
+			.filter(|fixture| {
+				let live = fixture.locations.iter().filter(|l| !l.deleted).count() as u64;
+				args.replicas_min.is_none_or(|min| live >= min)
+			})
+			.filter(|fixture| {
+				args.min_duration
+					.is_none_or(|secs| fixture.duration_ms.is_some_and(|ms| ms >= secs * 1000))
+			})
+			.map(|fixture| (fixture.latest.unwrap_or(""), fixture.hash))
+			.collect::<Vec<_>>();
+		matches.sort_by(|a, b| b.cmp(a));
+		matches.into_iter().map(|(_, hash)| hash).collect()
+	}
+
+	fn plan(conn: &Connection, sql: &str, params: &[Value]) -> Vec<(i64, String)> {
+		let mut stmt = conn.prepare(&format!("EXPLAIN QUERY PLAN {sql}")).unwrap();
+		stmt.query_map(rusqlite::params_from_iter(params), |row| {
+			Ok((row.get(1)?, row.get(3)?))
+		})
+		.unwrap()
+		.map(|row| row.unwrap())
+		.collect()
+	}
+
+	#[test]
+	fn every_filter_combination_matches_and_stays_on_indexes() {
+		let fixtures = fixtures();
+		let conn = fixture_db(&fixtures);
+		let mut nonempty = 0;
+		for mask in 0..1 << FILTERS.len() {
+			let args = combination(mask);
+			let on = FILTERS
+				.iter()
+				.enumerate()
+				.filter(|(bit, _)| mask & (1 << bit) != 0)
+				.map(|(_, filter)| *filter)
+				.collect::<Vec<_>>();
+			let want = expected(&fixtures, &args);
+			nonempty += usize::from(!want.is_empty());
+
+			let (page, _, total) = search_files_after(&conn, args.clone(), None).unwrap();
+			let got = page.rows.iter().map(|row| row.hash[0]).collect::<Vec<_>>();
+			assert_eq!(got, want, "filters {on:?}");
+			assert_eq!(total, want.len(), "filters {on:?}");
+			if on.contains(&"scope") {
+				for row in &page.rows {
+					let shown = row.path.replace('\\', "/");
+					assert!(shown.starts_with("/srv/photos/"), "filters {on:?}: {shown}");
+					assert_eq!(row.node_id, HERE.to_vec(), "filters {on:?}");
+				}
+			}
+
+			let query = SearchQuery::new(&args).unwrap();
+			let cursor = PageCursor::new(Some(String::from("2024-06-01")), vec![3; 32]);
+			for (sql, params) in [
+				query.count_sql(),
+				query.page_sql(None),
+				query.select_sql("fe.hash", Some(&cursor)),
+			] {
+				let steps = plan(&conn, &sql, &params);
+				for (parent, detail) in &steps {
+					assert!(
+						!detail.starts_with("SCAN fl") && !detail.starts_with("SCAN mm"),
+						"filters {on:?} scan a whole table: {steps:?}"
+					);
+					assert!(
+						!(*parent == 0 && detail.contains("TEMP B-TREE")),
+						"filters {on:?} sort outside the index: {steps:?}"
+					);
+				}
+			}
+		}
+		// Most combinations are too narrow for nine files; enough are not.
+		assert!(
+			nonempty > 100,
+			"only {nonempty} combinations matched anything"
+		);
+	}
+
+	#[test]
+	fn empty_files_stay_out_of_replica_searches_unless_asked_for() {
+		let fixtures = fixtures();
+		let conn = fixture_db(&fixtures);
+		conn.execute(
+			"UPDATE file_entries SET size = 0 WHERE hash = ?1",
+			[vec![1u8; 32]],
+		)
+		.unwrap();
+		let search = |args: SearchFilesArgs| {
+			let (page, _, total) = search_files_after(&conn, args, None).unwrap();
+			assert_eq!(total, page.rows.len());
+			let mut hashes = page.rows.iter().map(|row| row.hash[0]).collect::<Vec<_>>();
+			hashes.sort();
+			hashes
+		};
+		let replicated = SearchFilesArgs {
+			replicas_min: Some(2),
+			..Default::default()
+		};
+		assert_eq!(search(replicated.clone()), [5, 8]);
+		assert_eq!(
+			search(SearchFilesArgs {
+				include_empty: true,
+				..replicated
+			}),
+			[1, 5, 8]
+		);
+		// Without replica bounds an empty file is found like any other.
+		assert!(search(SearchFilesArgs::default()).contains(&1));
+	}
+
+	#[test]
+	fn percent_and_underscore_in_names_match_literally() {
+		let fixtures = vec![
+			Fixture {
+				hash: 1,
+				mime: "text/plain",
+				first: None,
+				latest: None,
+				duration_ms: None,
+				locations: vec![at(HERE, "/srv/docs/100%_done.txt")],
+			},
+			Fixture {
+				hash: 2,
+				mime: "text/plain",
+				first: None,
+				latest: None,
+				duration_ms: None,
+				locations: vec![at(HERE, "/srv/docs/100x-done.txt")],
+			},
+		];
+		let conn = fixture_db(&fixtures);
+		let search = |query: &str| {
+			let args = SearchFilesArgs {
+				name_query: Some(String::from(query)),
+				..Default::default()
+			};
+			let (page, _, _) = search_files_after(&conn, args, None).unwrap();
+			let mut hashes = page.rows.iter().map(|row| row.hash[0]).collect::<Vec<_>>();
+			hashes.sort();
+			hashes
+		};
+		assert_eq!(search("100%_done"), [1]);
+		assert_eq!(search("0x-d"), [2]);
+		assert_eq!(search("100*done"), [1, 2]);
+		assert_eq!(search("100??done"), [1, 2]);
+		assert_eq!(search("\\"), Vec::<u8>::new());
+	}
+
+	#[test]
+	fn pathological_requests_are_refused() {
+		let refused = |args: SearchFilesArgs| SearchQuery::new(&args).unwrap_err();
+		assert_eq!(
+			refused(SearchFilesArgs {
+				page_size: MAX_SEARCH_PAGE_SIZE + 1,
+				..Default::default()
+			}),
+			SearchQueryError::PageSizeTooLarge {
+				requested: MAX_SEARCH_PAGE_SIZE + 1
+			}
+		);
+		assert!(matches!(
+			refused(SearchFilesArgs {
+				page: usize::MAX,
+				page_size: 50,
+				..Default::default()
+			}),
+			SearchQueryError::OffsetTooLarge { .. }
+		));
+		assert_eq!(
+			refused(SearchFilesArgs {
+				date_from: Some(String::from("2025-01-01")),
+				date_to: Some(String::from("2024-01-01")),
+				..Default::default()
+			}),
+			SearchQueryError::InvertedRange {
+				filter: "date",
+				low: String::from("2025-01-01"),
+				high: String::from("2024-01-01"),
+			}
+		);
+		assert!(matches!(
+			refused(SearchFilesArgs {
+				replicas_min: Some(3),
+				replicas_max: Some(1),
+				..Default::default()
+			}),
+			SearchQueryError::InvertedRange {
+				filter: "replicas",
+				..
+			}
+		));
+		assert!(matches!(
+			refused(SearchFilesArgs {
+				name_query: Some("a".repeat(MAX_NAME_QUERY_LEN + 1)),
+				..Default::default()
+			}),
+			SearchQueryError::NameQueryTooLong { .. }
+		));
+
+		// The error survives the trip through `anyhow` for callers that
+		// answer with a status code.
+		let conn = fixture_db(&[]);
+		let args = SearchFilesArgs {
+			min_duration: Some(60),
+			max_duration: Some(10),
+			..Default::default()
+		};
+		let err = search_files_after(&conn, args.clone(), None).unwrap_err();
+		assert!(err.downcast_ref::<SearchQueryError>().is_some());
+		assert!(args.validate().is_err());
+		assert!(SearchFilesArgs::default().validate().is_ok());
+	}
+}
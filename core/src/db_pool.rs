@@ -0,0 +1,309 @@
+//! The node database as the rest of core shares it: one writer connection
+//! and a small pool of readers over the same WAL file. WAL lets the readers
+//! run while the writer commits, so a long search no longer waits behind a
+//! scan's write batches, and a permission save no longer waits behind a
+//! search.
+//!
+//! Work runs in closures handed a connection, [`Db::read`] or
+//! [`Db::write`], so a transaction never spans two connections. Readers are
+//! opened read-only as reads need them; once all are lent out, further
+//! reads wait for one to come back.
+
+use crate::db::{db_path, open_db};
+use rusqlite::{Connection, OpenFlags};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, LockResult, Mutex, MutexGuard, PoisonError};
+
+/// Readers open at most, besides the writer.
+const READERS: usize = 4;
+
+pub(crate) struct Db {
+	writer: Mutex<Connection>,
+	/// Where readers are opened; `None` reads on the writer.
+	path: Option<PathBuf>,
+	readers: Mutex<Readers>,
+	returned: Condvar,
+}
+
+#[derive(Default)]
+struct Readers {
+	idle: Vec<Connection>,
+	lent: usize,
+	/// Set while the database file is being swapped; reads wait.
+	closed: bool,
+}
+
+/// A reader on loan, given back when dropped, also when the read panics.
+struct Lent<'a> {
+	db: &'a Db,
+	conn: Option<Connection>,
+}
+
+impl Drop for Lent<'_> {
+	fn drop(&mut self) {
+		let mut readers = self.db.readers();
+		readers.lent -= 1;
+		if let Some(conn) = self.conn.take()
+			&& !readers.closed
+		{
+			readers.idle.push(conn);
+		}
+		self.db.returned.notify_all();
+	}
+}
+
+/// Keeps reads waiting until dropped; see [`Db::close_readers`].
+pub(crate) struct ReadersClosed<'a> {
+	db: &'a Db,
+}
+
+impl Drop for ReadersClosed<'_> {
+	fn drop(&mut self) {
+		self.db.readers().closed = false;
+		self.db.returned.notify_all();
+	}
+}
+
+fn open_reader(path: &Path) -> rusqlite::Result<Connection> {
+	let conn = Connection::open_with_flags(
+		path,
+		OpenFlags::SQLITE_OPEN_READ_ONLY
+			| OpenFlags::SQLITE_OPEN_NO_MUTEX
+			| OpenFlags::SQLITE_OPEN_URI,
+	)?;
+	conn.busy_timeout(crate::db::BUSY_TIMEOUT)?;
+	Ok(conn)
+}
+
+impl Db {
+	/// The node database at [`db_path`].
+	pub(crate) fn open() -> Self {
+		Self::pooled(open_db(), db_path())
+	}
+
+	/// `writer`, with readers opened on the file at `path` as reads need
+	/// them.
+	pub(crate) fn pooled(writer: Connection, path: PathBuf) -> Self {
+		Self {
+			writer: Mutex::new(writer),
+			path: Some(path),
+			readers: Mutex::new(Readers::default()),
+			returned: Condvar::new(),
+		}
+	}
+
+	/// Reads and writes on `conn` alone, for in-memory databases no other
+	/// connection can see.
+	pub(crate) fn single(conn: Connection) -> Self {
+		Self {
+			writer: Mutex::new(conn),
+			path: None,
+			readers: Mutex::new(Readers::default()),
+			returned: Condvar::new(),
+		}
+	}
+
+	fn readers(&self) -> MutexGuard<'_, Readers> {
+		self.readers.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	/// An idle reader, a new one while fewer than [`READERS`] are open, or
+	/// else the first one given back.
+	fn lend(&self, path: &Path) -> rusqlite::Result<Lent<'_>> {
+		let mut readers = self.readers();
+		loop {
+			if !readers.closed {
+				let conn = match readers.idle.pop() {
+					Some(conn) => Some(conn),
+					None if readers.lent < READERS => Some(open_reader(path)?),
+					None => None,
+				};
+				if let Some(conn) = conn {
+					readers.lent += 1;
+					return Ok(Lent {
+						db: self,
+						conn: Some(conn),
+					});
+				}
+			}
+			readers = self
+				.returned
+				.wait(readers)
+				.unwrap_or_else(PoisonError::into_inner);
+		}
+	}
+
+	/// Runs `f` on the writer, after any write already running.
+	pub(crate) fn write<T>(&self, f: impl FnOnce(&mut Connection) -> T) -> T {
+		// A write that panicked rolled its transaction back when it
+		// unwound, so the connection is still good.
+		let mut conn = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+		f(&mut conn)
+	}
+
+	/// Runs `f` on a pooled reader, alongside other reads and the writer.
+	/// The connection is read-only; `f` must not call back into this
+	/// handle's writer, which a restore may be holding while it waits for
+	/// the reader to come back.
+	pub(crate) fn read<T>(&self, f: impl FnOnce(&Connection) -> T) -> T {
+		let Some(path) = &self.path else {
+			return self.write(|conn| f(conn));
+		};
+		match self.lend(path) {
+			Ok(lent) => f(lent.conn.as_ref().expect("a lent reader has a connection")),
+			Err(err) => {
+				tracing::warn!("failed to open a database reader, reading on the writer: {err}");
+				self.write(|conn| f(conn))
+			}
+		}
+	}
+
+	/// The writer itself, for code that still holds it across several
+	/// statements; prefer [`Self::write`].
+	pub(crate) fn lock(&self) -> LockResult<MutexGuard<'_, Connection>> {
+		self.writer.lock()
+	}
+
+	/// Closes every reader once those on loan come back, so the database
+	/// file can be replaced. Reads wait until the returned guard drops and
+	/// then open fresh readers on the new file.
+	pub(crate) fn close_readers(&self) -> ReadersClosed<'_> {
+		let mut readers = self.readers();
+		readers.closed = true;
+		while readers.lent > 0 {
+			readers = self
+				.returned
+				.wait(readers)
+				.unwrap_or_else(PoisonError::into_inner);
+		}
+		readers.idle.clear();
+		ReadersClosed { db: self }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::db::{SearchFilesArgs, open_db_at, run_migrations, search_files};
+	use crate::scan;
+	use crate::test_util::test_dir;
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicBool, Ordering};
+	use std::time::{Duration, Instant};
+
+	fn pooled(dir: &Path) -> Db {
+		let path = dir.join("puppynet.db");
+		let mut writer = open_db_at(&path);
+		run_migrations(&mut writer).unwrap();
+		Db::pooled(writer, path)
+	}
+
+	#[test]
+	fn searches_run_while_a_scan_writes() {
+		let dir = test_dir("db-pool-scan");
+		let files = dir.join("files");
+		std::fs::create_dir_all(&files).unwrap();
+		for i in 0..3000 {
+			std::fs::write(files.join(format!("file-{i}.txt")), i.to_string()).unwrap();
+		}
+		let db = Arc::new(pooled(&dir));
+		let node_id = [3u8; 16];
+
+		let scanning = Arc::new(AtomicBool::new(true));
+		let scan = std::thread::spawn({
+			let (db, scanning, files) = (Arc::clone(&db), Arc::clone(&scanning), files.clone());
+			move || {
+				let result = db.write(|conn| scan::scan(&node_id, &files, conn));
+				scanning.store(false, Ordering::SeqCst);
+				result
+			}
+		});
+		let (mut during_scan, mut slowest) = (0, Duration::ZERO);
+		while scanning.load(Ordering::SeqCst) {
+			let started = Instant::now();
+			db.read(|conn| {
+				search_files(
+					conn,
+					SearchFilesArgs {
+						name_query: Some("file".to_string()),
+						page_size: 50,
+						..Default::default()
+					},
+				)
+			})
+			.unwrap();
+			slowest = slowest.max(started.elapsed());
+			during_scan += 1;
+		}
+		let scanned = scan.join().unwrap().unwrap();
+		assert_eq!(scanned.inserted_count, 3000);
+		assert!(during_scan > 1, "searches waited for the scan to finish");
+		assert!(
+			slowest < Duration::from_secs(2),
+			"a search took {slowest:?}"
+		);
+		let (page, _, total) = db
+			.read(|conn| {
+				search_files(
+					conn,
+					SearchFilesArgs {
+						name_query: Some("file".to_string()),
+						page_size: 50,
+						..Default::default()
+					},
+				)
+			})
+			.unwrap();
+		assert_eq!((page.rows.len(), total), (50, 3000));
+		let _ = std::fs::remove_dir_all(dir);
+	}
+
+	#[test]
+	fn reads_queue_once_every_reader_is_lent() {
+		let dir = test_dir("db-pool-queue");
+		let db = Arc::new(pooled(&dir));
+		let (held_tx, held_rx) = std::sync::mpsc::channel();
+		let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+		let release_rx = Arc::new(Mutex::new(release_rx));
+		let holders = (0..READERS)
+			.map(|_| {
+				let (db, held_tx, release_rx) =
+					(Arc::clone(&db), held_tx.clone(), Arc::clone(&release_rx));
+				std::thread::spawn(move || {
+					db.read(|_| {
+						held_tx.send(()).unwrap();
+						release_rx.lock().unwrap().recv().unwrap();
+					})
+				})
+			})
+			.collect::<Vec<_>>();
+		for _ in 0..READERS {
+			held_rx.recv().unwrap();
+		}
+		let waiting = std::thread::spawn({
+			let db = Arc::clone(&db);
+			move || db.read(|conn| conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0)))
+		});
+		std::thread::sleep(Duration::from_millis(100));
+		assert!(!waiting.is_finished());
+		for _ in 0..READERS {
+			release_tx.send(()).unwrap();
+		}
+		assert_eq!(waiting.join().unwrap().unwrap(), 1);
+		for holder in holders {
+			holder.join().unwrap();
+		}
+		assert_eq!(db.readers().idle.len(), READERS);
+		let _ = std::fs::remove_dir_all(dir);
+	}
+
+	#[test]
+	fn readers_are_read_only() {
+		let dir = test_dir("db-pool-read-only");
+		let db = pooled(&dir);
+		let written =
+			db.read(|conn| conn.execute("INSERT INTO settings (key, value) VALUES ('k', 'v')", []));
+		assert!(written.is_err());
+		let _ = std::fs::remove_dir_all(dir);
+	}
+}
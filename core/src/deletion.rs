@@ -0,0 +1,798 @@
+//! Deleting a file everywhere it is replicated, with every holder's
+//! consent. [`crate::PuppyNet::propose_global_delete`] records a proposal
+//! for a content hash and sends it to each node this node's index shows
+//! holding a live copy. A holder never deletes on its own: the proposal
+//! waits in its review queue until the owner approves, which removes the
+//! local copies that still hash to the proposal, or rejects it with a
+//! reason. The outcome goes back to the proposer, which keeps one status
+//! per holder.
+//!
+//! Both sides keep proposals in the database. Proposals and outcomes that
+//! could not be sent because the other node was away are sent when it next
+//! connects, and a proposal nobody decided on expires after
+//! [`DELETE_PROPOSAL_EXPIRY_SETTING`] days.
+
+use crate::config;
+use crate::db::{
+	NodeID, count_pending_delete_proposals, decide_received_delete_proposal,
+	expire_delete_proposals, find_received_delete_proposal, insert_received_delete_proposal,
+	live_locations_of_hash, load_received_delete_proposal, load_setting,
+	undelivered_delete_proposals, unreported_delete_outcomes,
+};
+use crate::db_pool::Db;
+use crate::format::abbrev_hash;
+use crate::p2p::PeerReq;
+use crate::scan::{FileHash, hash_file};
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Days a proposal waits for decisions before it expires.
+pub(crate) const DELETE_PROPOSAL_EXPIRY_SETTING: &str = "delete_proposal_expiry_days";
+pub(crate) const DEFAULT_DELETE_PROPOSAL_EXPIRY_DAYS: u32 = 14;
+/// Paths sent along with a proposal. They only tell the holder's owner
+/// what the file is; the holder finds its copies by hash.
+pub(crate) const MAX_PATHS_HINT: usize = 16;
+/// Longest reason or rejection kept, in bytes.
+pub(crate) const MAX_REASON_LEN: usize = 500;
+/// Undecided proposals one peer may have queued here at once.
+pub(crate) const MAX_PENDING_PER_PEER: u64 = 256;
+
+/// Where a proposal stands with one holder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletionStatus {
+	/// Not received by the holder yet; sent when it next connects.
+	Undelivered,
+	/// Waiting for the holder's owner.
+	Pending,
+	Deleted,
+	Rejected,
+	Expired,
+	/// The owner approved but a copy could not be removed.
+	Failed,
+}
+
+impl DeletionStatus {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			DeletionStatus::Undelivered => "undelivered",
+			DeletionStatus::Pending => "pending",
+			DeletionStatus::Deleted => "deleted",
+			DeletionStatus::Rejected => "rejected",
+			DeletionStatus::Expired => "expired",
+			DeletionStatus::Failed => "failed",
+		}
+	}
+
+	pub fn parse(value: &str) -> Option<Self> {
+		[
+			DeletionStatus::Undelivered,
+			DeletionStatus::Pending,
+			DeletionStatus::Deleted,
+			DeletionStatus::Rejected,
+			DeletionStatus::Expired,
+			DeletionStatus::Failed,
+		]
+		.into_iter()
+		.find(|status| status.as_str() == value)
+	}
+
+	/// Whether a decision may still come.
+	pub fn is_open(&self) -> bool {
+		matches!(self, DeletionStatus::Undelivered | DeletionStatus::Pending)
+	}
+
+	pub fn label(&self) -> &'static str {
+		match self {
+			DeletionStatus::Undelivered => "not delivered yet",
+			DeletionStatus::Pending => "pending owner review",
+			DeletionStatus::Deleted => "deleted",
+			DeletionStatus::Rejected => "rejected",
+			DeletionStatus::Expired => "expired",
+			DeletionStatus::Failed => "failed",
+		}
+	}
+}
+
+/// What a holder did with a proposal, as sent back to the proposer.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeleteOutcome {
+	/// The owner approved and `removed` copies were deleted. Copies that
+	/// changed since they were indexed are kept.
+	Deleted {
+		removed: u64,
+	},
+	Rejected {
+		reason: String,
+	},
+	/// Nobody decided before the proposal expired.
+	Expired,
+	Failed {
+		error: String,
+	},
+}
+
+impl DeleteOutcome {
+	pub fn status(&self) -> DeletionStatus {
+		match self {
+			DeleteOutcome::Deleted { .. } => DeletionStatus::Deleted,
+			DeleteOutcome::Rejected { .. } => DeletionStatus::Rejected,
+			DeleteOutcome::Expired => DeletionStatus::Expired,
+			DeleteOutcome::Failed { .. } => DeletionStatus::Failed,
+		}
+	}
+
+	/// The rejection reason or failure, when there is one.
+	pub fn detail(&self) -> Option<&str> {
+		match self {
+			DeleteOutcome::Rejected { reason } if !reason.is_empty() => Some(reason),
+			DeleteOutcome::Failed { error } => Some(error),
+			_ => None,
+		}
+	}
+}
+
+/// The owner's answer to a received proposal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeleteDecision {
+	Approve,
+	Reject { reason: String },
+}
+
+/// One node holding a copy, as the proposer tracks it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ProposalHolder {
+	pub peer: String,
+	/// Where the proposer's index saw the copies.
+	pub paths: Vec<String>,
+	pub status: DeletionStatus,
+	/// Rejection reason, failure, or why delivery failed.
+	pub detail: Option<String>,
+	pub updated_at: DateTime<Utc>,
+}
+
+impl ProposalHolder {
+	/// `rejected — 'still need this'`
+	pub fn describe(&self) -> String {
+		match (self.status, &self.detail) {
+			(DeletionStatus::Rejected, Some(reason)) => format!("rejected — '{reason}'"),
+			(status, Some(detail)) if status != DeletionStatus::Pending => {
+				format!("{} — {detail}", status.label())
+			}
+			(status, _) => status.label().to_string(),
+		}
+	}
+}
+
+/// A deletion this node proposed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DeleteProposal {
+	pub id: String,
+	#[serde(skip)]
+	pub hash: FileHash,
+	pub reason: String,
+	pub created_at: DateTime<Utc>,
+	pub expires_at: DateTime<Utc>,
+	pub holders: Vec<ProposalHolder>,
+}
+
+impl DeleteProposal {
+	/// File name of the first copy, or the start of the hash.
+	pub fn subject(&self) -> String {
+		subject(
+			self.holders.iter().flat_map(|holder| &holder.paths),
+			&self.hash,
+		)
+	}
+
+	/// `desktop: deleted, NAS: pending owner review`, naming each holder
+	/// with `name`.
+	pub fn describe(&self, name: impl Fn(&str) -> String) -> String {
+		self.holders
+			.iter()
+			.map(|holder| format!("{}: {}", name(&holder.peer), holder.describe()))
+			.collect::<Vec<_>>()
+			.join(", ")
+	}
+
+	/// Whether every holder decided or the proposal expired.
+	pub fn is_settled(&self) -> bool {
+		self.holders.iter().all(|holder| !holder.status.is_open())
+	}
+
+	/// The request delivering this proposal to one of its holders.
+	pub(crate) fn request(&self, holder: &ProposalHolder) -> PeerReq {
+		PeerReq::DeleteProposal {
+			id: self.id.clone(),
+			hash: self.hash,
+			paths_hint: holder.paths.iter().take(MAX_PATHS_HINT).cloned().collect(),
+			reason: self.reason.clone(),
+			expires_at: self.expires_at,
+		}
+	}
+}
+
+/// A deletion a peer proposed to this node.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ReceivedProposal {
+	pub id: i64,
+	/// The proposer's id for it, which outcomes are reported under.
+	pub proposal_id: String,
+	pub proposer: String,
+	#[serde(skip)]
+	pub hash: FileHash,
+	pub paths_hint: Vec<String>,
+	pub reason: String,
+	pub received_at: DateTime<Utc>,
+	pub expires_at: DateTime<Utc>,
+	/// `None` while it waits for the owner.
+	pub outcome: Option<DeleteOutcome>,
+}
+
+impl ReceivedProposal {
+	pub fn subject(&self) -> String {
+		subject(&self.paths_hint, &self.hash)
+	}
+}
+
+/// File name of the first of `paths`, or the start of `hash` without one.
+pub(crate) fn subject<'a>(paths: impl IntoIterator<Item = &'a String>, hash: &FileHash) -> String {
+	paths
+		.into_iter()
+		.find_map(|path| Path::new(path).file_name())
+		.map(|name| name.to_string_lossy().into_owned())
+		.unwrap_or_else(|| abbrev_hash(hash))
+}
+
+/// `reason` without surrounding space, cut to [`MAX_REASON_LEN`] bytes.
+pub(crate) fn clip_reason(reason: &str) -> String {
+	let reason = reason.trim();
+	let mut end = reason.len().min(MAX_REASON_LEN);
+	while !reason.is_char_boundary(end) {
+		end -= 1;
+	}
+	reason[..end].to_string()
+}
+
+/// Days a proposal waits for decisions, as configured under
+/// [`DELETE_PROPOSAL_EXPIRY_SETTING`].
+pub(crate) fn proposal_expiry_days(conn: &Connection) -> u32 {
+	let days = match load_setting(conn, DELETE_PROPOSAL_EXPIRY_SETTING) {
+		Ok(Some(value)) => value.parse().unwrap_or_else(|err| {
+			tracing::warn!("ignoring invalid deletion proposal expiry {value:?}: {err}");
+			config::startup().delete_proposal_expiry_days
+		}),
+		Ok(None) => config::startup().delete_proposal_expiry_days,
+		Err(err) => {
+			tracing::error!("failed to load deletion proposal expiry: {err}");
+			config::startup().delete_proposal_expiry_days
+		}
+	};
+	days.max(1)
+}
+
+pub(crate) fn proposal_expiry(conn: &Connection) -> chrono::Duration {
+	chrono::Duration::days(i64::from(proposal_expiry_days(conn)))
+}
+
+/// Nodes other than `me` whose index this node keeps that hold a live copy
+/// of `hash`, with the paths they hold it at. Nodes that aren't one of
+/// `known` can't be asked and are left out.
+pub(crate) fn replica_holders(
+	conn: &Connection,
+	hash: &FileHash,
+	me: &NodeID,
+	known: &[PeerId],
+) -> Result<Vec<(PeerId, Vec<String>)>> {
+	let mut holders: Vec<(PeerId, Vec<String>)> = Vec::new();
+	for (node_id, path) in live_locations_of_hash(conn, hash)? {
+		if node_id == *me {
+			continue;
+		}
+		let Some(peer) = known
+			.iter()
+			.find(|peer| crate::app::peer_to_node_id(peer) == Some(node_id))
+		else {
+			continue;
+		};
+		let path = path.to_string_lossy().into_owned();
+		match holders.iter_mut().find(|(holder, _)| holder == peer) {
+			Some((_, paths)) => paths.push(path),
+			None => holders.push((*peer, vec![path])),
+		}
+	}
+	Ok(holders)
+}
+
+/// A proposal as it arrives in a `DeleteProposal` request.
+pub(crate) struct ProposalOffer {
+	pub id: String,
+	pub hash: FileHash,
+	pub paths_hint: Vec<String>,
+	pub reason: String,
+	pub expires_at: DateTime<Utc>,
+}
+
+/// Queues a proposal `proposer` sent for the owner's review, expiring at
+/// the proposer's expiry or this node's, whichever comes first. A proposal
+/// delivered again is not queued twice. Returns the queued row and, once
+/// decided, the outcome.
+pub(crate) fn receive(
+	conn: &Connection,
+	proposer: &PeerId,
+	offer: ProposalOffer,
+	now: DateTime<Utc>,
+) -> Result<(i64, Option<DeleteOutcome>)> {
+	if offer.id.is_empty() || offer.id.len() > 64 {
+		bail!("invalid deletion proposal id");
+	}
+	if let Some(existing) = find_received_delete_proposal(conn, proposer, &offer.id)? {
+		return Ok((existing.id, existing.outcome));
+	}
+	if count_pending_delete_proposals(conn, Some(proposer))? >= MAX_PENDING_PER_PEER {
+		bail!("too many deletion proposals wait for review");
+	}
+	let mut paths_hint = offer.paths_hint;
+	paths_hint.truncate(MAX_PATHS_HINT);
+	let proposal = ReceivedProposal {
+		id: 0,
+		proposal_id: offer.id,
+		proposer: proposer.to_string(),
+		hash: offer.hash,
+		paths_hint,
+		reason: clip_reason(&offer.reason),
+		received_at: now,
+		expires_at: offer.expires_at.min(now + proposal_expiry(conn)),
+		outcome: None,
+	};
+	let row = insert_received_delete_proposal(conn, &proposal)?;
+	Ok((row, None))
+}
+
+/// Removes the files at `paths` that still hold `hash`; a file changed
+/// since it was indexed is kept. Returns the removed paths and what
+/// couldn't be removed.
+pub(crate) fn remove_copies(paths: &[PathBuf], hash: &FileHash) -> (Vec<PathBuf>, Vec<String>) {
+	let mut removed = Vec::new();
+	let mut errors = Vec::new();
+	for path in paths {
+		let current =
+			std::fs::File::open(path).and_then(|file| hash_file(std::io::BufReader::new(file)));
+		match current {
+			Ok(current) if current == *hash => match std::fs::remove_file(path) {
+				Ok(()) => {
+					tracing::info!("removed {} as proposed", path.display());
+					removed.push(path.clone());
+				}
+				Err(err) => errors.push(format!("{}: {err}", path.display())),
+			},
+			Ok(_) => tracing::info!("kept {}: it changed since it was indexed", path.display()),
+			Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+			Err(err) => errors.push(format!("{}: {err}", path.display())),
+		}
+	}
+	(removed, errors)
+}
+
+/// Applies the owner's decision on received proposal `id`. Approving
+/// removes this node's copies; files are hashed and removed without
+/// holding the database.
+pub(crate) fn decide(
+	db: &Db,
+	node_id: &NodeID,
+	id: i64,
+	decision: DeleteDecision,
+	now: DateTime<Utc>,
+) -> Result<ReceivedProposal> {
+	let (proposal, paths) = {
+		let conn = db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		expire_delete_proposals(&conn, now)?;
+		let Some(proposal) = load_received_delete_proposal(&conn, id)? else {
+			bail!("no deletion proposal {id}");
+		};
+		if let Some(outcome) = &proposal.outcome {
+			bail!("deletion proposal already {}", outcome.status().label());
+		}
+		let paths = match decision {
+			DeleteDecision::Approve => live_locations_of_hash(&conn, &proposal.hash)?
+				.into_iter()
+				.filter(|(node, _)| node == node_id)
+				.map(|(_, path)| path)
+				.collect(),
+			DeleteDecision::Reject { .. } => Vec::new(),
+		};
+		(proposal, paths)
+	};
+	let (removed, outcome) = match decision {
+		DeleteDecision::Approve => {
+			let (removed, errors) = remove_copies(&paths, &proposal.hash);
+			let outcome = if errors.is_empty() {
+				DeleteOutcome::Deleted {
+					removed: removed.len() as u64,
+				}
+			} else {
+				DeleteOutcome::Failed {
+					error: clip_reason(&errors.join("; ")),
+				}
+			};
+			(removed, outcome)
+		}
+		DeleteDecision::Reject { reason } => (
+			Vec::new(),
+			DeleteOutcome::Rejected {
+				reason: clip_reason(&reason),
+			},
+		),
+	};
+	let conn = db
+		.lock()
+		.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+	if !decide_received_delete_proposal(&conn, &proposal, node_id, &removed, &outcome, now)? {
+		bail!("deletion proposal was decided meanwhile");
+	}
+	Ok(ReceivedProposal {
+		outcome: Some(outcome),
+		..proposal
+	})
+}
+
+/// Requests waiting for `peer` since it was last reached: proposals it
+/// hasn't received and outcomes of its proposals it hasn't been told.
+pub(crate) fn outbox_for(
+	conn: &Connection,
+	peer: &PeerId,
+	now: DateTime<Utc>,
+) -> Result<Vec<PeerReq>> {
+	expire_delete_proposals(conn, now)?;
+	let mut requests = Vec::new();
+	for proposal in undelivered_delete_proposals(conn, peer)? {
+		for holder in &proposal.holders {
+			requests.push(proposal.request(holder));
+		}
+	}
+	for (id, outcome) in unreported_delete_outcomes(conn, peer)? {
+		requests.push(PeerReq::DeleteOutcome { id, outcome });
+	}
+	Ok(requests)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::db::{
+		load_delete_proposals, mark_delete_outcome_reported, record_delete_outcome,
+		record_delete_proposal, set_delete_holder_status,
+	};
+	use crate::test_util::{self, test_dir};
+	use chrono::TimeZone;
+
+	fn conn() -> Connection {
+		test_util::memory_db()
+	}
+
+	fn at(day: u32) -> DateTime<Utc> {
+		Utc.with_ymd_and_hms(2025, 4, day, 12, 0, 0).unwrap()
+	}
+
+	fn index(conn: &Connection, node_id: &NodeID, path: &Path, hash: &FileHash) {
+		conn.execute(
+			"INSERT INTO file_locations (node_id, path, hash, size, timestamp)
+			VALUES (?1, ?2, ?3, 1, 0)",
+			rusqlite::params![&node_id[..], path.to_string_lossy().into_owned(), &hash[..]],
+		)
+		.unwrap();
+	}
+
+	/// A proposer and a holder, each with its own database, the proposer
+	/// knowing from its replica of the holder's index that it holds a copy
+	/// of `content` at `path`.
+	struct Pair {
+		proposer: PeerId,
+		proposer_db: Connection,
+		holder: PeerId,
+		holder_node: NodeID,
+		holder_db: Db,
+		hash: FileHash,
+	}
+
+	impl Pair {
+		fn new(path: &Path, content: &[u8]) -> Self {
+			std::fs::write(path, content).unwrap();
+			let hash = *blake3::hash(content).as_bytes();
+			let proposer = PeerId::random();
+			let holder = PeerId::random();
+			let holder_node = crate::app::peer_to_node_id(&holder).unwrap();
+			let proposer_db = conn();
+			index(&proposer_db, &holder_node, path, &hash);
+			let holder_db = conn();
+			index(&holder_db, &holder_node, path, &hash);
+			Self {
+				proposer,
+				proposer_db,
+				holder,
+				holder_node,
+				holder_db: Db::single(holder_db),
+				hash,
+			}
+		}
+
+		fn propose(&self, reason: &str, now: DateTime<Utc>) -> DeleteProposal {
+			let me = crate::app::peer_to_node_id(&self.proposer).unwrap();
+			let holders =
+				replica_holders(&self.proposer_db, &self.hash, &me, &[self.holder]).unwrap();
+			let proposal = DeleteProposal {
+				id: String::from("proposal-1"),
+				hash: self.hash,
+				reason: reason.to_string(),
+				created_at: now,
+				expires_at: now + chrono::Duration::days(7),
+				holders: holders
+					.into_iter()
+					.map(|(peer, paths)| ProposalHolder {
+						peer: peer.to_string(),
+						paths,
+						status: DeletionStatus::Undelivered,
+						detail: None,
+						updated_at: now,
+					})
+					.collect(),
+			};
+			record_delete_proposal(&self.proposer_db, &proposal).unwrap();
+			proposal
+		}
+
+		/// The holder connected: it is sent what waits for it, and answers
+		/// each request as the app would.
+		fn connect_holder(&self, now: DateTime<Utc>) -> Vec<i64> {
+			let requests = outbox_for(&self.proposer_db, &self.holder, now).unwrap();
+			let mut queued = Vec::new();
+			for request in requests {
+				let PeerReq::DeleteProposal {
+					id,
+					hash,
+					paths_hint,
+					reason,
+					expires_at,
+				} = request
+				else {
+					panic!("unexpected {request:?}");
+				};
+				let offer = ProposalOffer {
+					id: id.clone(),
+					hash,
+					paths_hint,
+					reason,
+					expires_at,
+				};
+				let holder_db = self.holder_db.lock().unwrap();
+				let (row, outcome) = receive(&holder_db, &self.proposer, offer, now).unwrap();
+				match outcome {
+					Some(outcome) => {
+						record_delete_outcome(&self.proposer_db, &id, &self.holder, &outcome, now)
+							.unwrap();
+					}
+					None => {
+						set_delete_holder_status(
+							&self.proposer_db,
+							&id,
+							&self.holder,
+							DeletionStatus::Pending,
+							None,
+							now,
+						)
+						.unwrap();
+					}
+				}
+				queued.push(row);
+			}
+			queued
+		}
+
+		/// The proposer connected: the holder reports its decisions.
+		fn connect_proposer(&self, now: DateTime<Utc>) {
+			let holder_db = self.holder_db.lock().unwrap();
+			for request in outbox_for(&holder_db, &self.proposer, now).unwrap() {
+				let PeerReq::DeleteOutcome { id, outcome } = request else {
+					panic!("unexpected {request:?}");
+				};
+				assert!(
+					record_delete_outcome(&self.proposer_db, &id, &self.holder, &outcome, now)
+						.unwrap()
+				);
+				mark_delete_outcome_reported(&holder_db, &self.proposer, &id).unwrap();
+			}
+		}
+
+		fn holder_status(&self) -> String {
+			let proposals = load_delete_proposals(&self.proposer_db).unwrap();
+			let holder = self.holder.to_string();
+			proposals[0].describe(|peer| {
+				if peer == holder {
+					String::from("desktop")
+				} else {
+					String::from("?")
+				}
+			})
+		}
+	}
+
+	#[test]
+	fn an_approved_proposal_deletes_the_copy_and_reports_back() {
+		let dir = test_dir("delete-approve");
+		let path = dir.join("old.iso");
+		let pair = Pair::new(&path, b"obsolete image");
+		pair.propose("superseded", at(1));
+		assert_eq!(pair.holder_status(), "desktop: not delivered yet");
+
+		let queued = pair.connect_holder(at(1));
+		assert_eq!(pair.holder_status(), "desktop: pending owner review");
+		assert!(path.exists(), "nothing is deleted before the owner decides");
+		assert_eq!(
+			count_pending_delete_proposals(&pair.holder_db.lock().unwrap(), None).unwrap(),
+			1
+		);
+
+		let decided = decide(
+			&pair.holder_db,
+			&pair.holder_node,
+			queued[0],
+			DeleteDecision::Approve,
+			at(2),
+		)
+		.unwrap();
+		assert_eq!(decided.outcome, Some(DeleteOutcome::Deleted { removed: 1 }));
+		assert!(!path.exists());
+		let live = live_locations_of_hash(&pair.holder_db.lock().unwrap(), &pair.hash).unwrap();
+		assert!(live.is_empty(), "the removed copy is tombstoned");
+
+		pair.connect_proposer(at(2));
+		assert_eq!(pair.holder_status(), "desktop: deleted");
+		assert!(load_delete_proposals(&pair.proposer_db).unwrap()[0].is_settled());
+		// Reported once.
+		let holder_db = pair.holder_db.lock().unwrap();
+		assert!(
+			outbox_for(&holder_db, &pair.proposer, at(2))
+				.unwrap()
+				.is_empty()
+		);
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn a_rejection_keeps_the_file_and_carries_its_reason() {
+		let dir = test_dir("delete-reject");
+		let path = dir.join("thesis.pdf");
+		let pair = Pair::new(&path, b"draft");
+		pair.propose("duplicate", at(1));
+		let queued = pair.connect_holder(at(1));
+
+		decide(
+			&pair.holder_db,
+			&pair.holder_node,
+			queued[0],
+			DeleteDecision::Reject {
+				reason: String::from("  still need this "),
+			},
+			at(2),
+		)
+		.unwrap();
+		assert!(path.exists());
+		assert!(
+			decide(
+				&pair.holder_db,
+				&pair.holder_node,
+				queued[0],
+				DeleteDecision::Approve,
+				at(2),
+			)
+			.is_err(),
+			"a decided proposal can't be approved afterwards"
+		);
+		assert!(path.exists());
+
+		pair.connect_proposer(at(3));
+		assert_eq!(
+			pair.holder_status(),
+			"desktop: rejected — 'still need this'"
+		);
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn undecided_proposals_expire_on_both_sides() {
+		let dir = test_dir("delete-expire");
+		let path = dir.join("clip.mov");
+		let pair = Pair::new(&path, b"frames");
+		pair.propose("", at(1));
+		let queued = pair.connect_holder(at(1));
+
+		let later = at(1) + chrono::Duration::days(8);
+		assert!(
+			decide(
+				&pair.holder_db,
+				&pair.holder_node,
+				queued[0],
+				DeleteDecision::Approve,
+				later,
+			)
+			.is_err()
+		);
+		assert!(path.exists());
+		assert_eq!(
+			count_pending_delete_proposals(&pair.holder_db.lock().unwrap(), None).unwrap(),
+			0
+		);
+		assert!(
+			outbox_for(&pair.proposer_db, &pair.holder, later)
+				.unwrap()
+				.is_empty()
+		);
+		assert_eq!(pair.holder_status(), "desktop: expired");
+		// The holder tells the proposer it expired there too.
+		pair.connect_proposer(later);
+		assert_eq!(pair.holder_status(), "desktop: expired");
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn offline_holders_get_the_proposal_once_when_they_connect() {
+		let dir = test_dir("delete-offline");
+		let path = dir.join("notes.txt");
+		let pair = Pair::new(&path, b"old notes");
+		let proposal = pair.propose("moved to the wiki", at(1));
+
+		// Delivery failed: the holder was away.
+		let requests = outbox_for(&pair.proposer_db, &pair.holder, at(1)).unwrap();
+		assert!(matches!(
+			requests.as_slice(),
+			[PeerReq::DeleteProposal { id, paths_hint, .. }]
+				if *id == proposal.id && *paths_hint == proposal.holders[0].paths
+		));
+		set_delete_holder_status(
+			&pair.proposer_db,
+			&proposal.id,
+			&pair.holder,
+			DeletionStatus::Undelivered,
+			Some("peer not connected"),
+			at(1),
+		)
+		.unwrap();
+		assert_eq!(
+			pair.holder_status(),
+			"desktop: not delivered yet — peer not connected"
+		);
+
+		let queued = pair.connect_holder(at(3));
+		assert_eq!(queued.len(), 1);
+		assert_eq!(pair.holder_status(), "desktop: pending owner review");
+		assert!(
+			outbox_for(&pair.proposer_db, &pair.holder, at(3))
+				.unwrap()
+				.is_empty()
+		);
+		// A proposal delivered twice, e.g. after a lost answer, is queued
+		// once.
+		let holder_db = pair.holder_db.lock().unwrap();
+		let offer = ProposalOffer {
+			id: proposal.id.clone(),
+			hash: proposal.hash,
+			paths_hint: proposal.holders[0].paths.clone(),
+			reason: proposal.reason.clone(),
+			expires_at: proposal.expires_at,
+		};
+		let (again, outcome) = receive(&holder_db, &pair.proposer, offer, at(3)).unwrap();
+		assert_eq!((again, outcome), (queued[0], None));
+		assert_eq!(count_pending_delete_proposals(&holder_db, None).unwrap(), 1);
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+}
@@ -0,0 +1,2437 @@
+//! Demo mode: a node with made-up devices and files, for working on the
+//! interfaces, taking screenshots and reproducing UI bugs without a network
+//! or real data. [`crate::PuppyNet::new_demo`], or `PUPPYNET_DEMO=1` at
+//! startup, replaces the swarm with [`DemoApp`], which answers the same
+//! [`Command`]s from a [`DemoFixture`], and the node database with an
+//! in-memory one holding the fixture's index, scan history and users. The
+//! same seed (`PUPPYNET_DEMO_SEED`) gives the same devices and files.
+//!
+//! Nothing is sent over the network or written to disk. New users,
+//! permissions, pairings and scans change the in-memory model only and are
+//! gone when the process exits. Log in as `demo` with password `demo`.
+
+use crate::app::{
+	AccessGrantAck, Command, InboxSlot, MAX_TEMPORARY_GRANT_TTL, ReadFileCmd, RestartAck,
+	peer_to_node_id,
+};
+use crate::auth;
+use crate::contact_sheet::{
+	self, CONTACT_SHEET_TIME_BUDGET, ContactSheetOptions, ContactSheetResult,
+};
+use crate::db::{
+	Node, delete_user, fetch_file_entries_paginated, indexed_files_under, load_permission_revision,
+	record_scan_run, record_transfer, run_migrations, save_node, save_peer,
+	save_peer_permissions_at, save_setting, save_shared_folder, save_trust_level, save_user,
+	search_files,
+};
+use crate::db_pool::Db;
+use crate::discovered::{DEFAULT_DISCOVERED_ADDRESS_TTL, DiscoveredPeers};
+use crate::disk_history::DiskSample;
+use crate::file_read::DEFAULT_READ_CHUNK_SIZE;
+use crate::grant_cache::RemoteGrants;
+use crate::index::storage_files;
+use crate::locations::{FolderKind, WellKnownFolder};
+use crate::mounts::ShareAvailability;
+use crate::nat::{NatMethod, NatStatus};
+use crate::natural_sort::natural_cmp;
+use crate::p2p::{
+	AudioCapability, AudioDevice, AudioDeviceKind, BrowseRoots, CpuInfo, DirEntry, DirListing,
+	DiskInfo, FileWriteAck, InterfaceInfo, LiveSearchArgs, LiveSearchRow, MediaCapability,
+	MediaFrame, MediaOutput, MediaSource, MediaSourceKind, MediaTransport, MimeSource,
+	PeerCapabilities, PeerHealth, PeerInfo, SearchEvent, Thumbnail,
+};
+use crate::pairing::{
+	NODE_NAME_SETTING, Pairing, PairingDirection, PairingStatus, normalize_node_name,
+};
+use crate::password_policy::{PasswordPolicy, check_new_user, hash_new_user};
+use crate::peer_search;
+use crate::reachability::{DialBackOutcome, aggregate};
+use crate::remote_ops::RemoteOps;
+use crate::scan::{self, FileHash, FileLocation, ScanEvent, ScanProgress, ScanResult};
+use crate::share_summary::{
+	SHARE_SUMMARY_MAX_FILES, SHARE_SUMMARY_MAX_ROOTS, ShareSummary, summarize_files,
+};
+use crate::state::{
+	BatchGrantOutcome, Connection, ConnectionDirection, FLAG_PREVIEW, FLAG_READ, FLAG_SEARCH,
+	FLAG_WRITE, FolderRule, Peer, Permission, PermissionSet, Rule, State, TrustLevel, User,
+	merge_folder_grant,
+};
+use crate::types::{FileChunk, ShellOutput};
+use crate::updater::UpdateProgress;
+use crate::version;
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Datelike, Utc};
+use libp2p::swarm::ConnectionId;
+use libp2p::{Multiaddr, PeerId, identity::Keypair, multiaddr::Protocol};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use rusqlite::Connection as SqliteConnection;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+/// Set to `1` to start in demo mode.
+pub(crate) const DEMO_ENV: &str = "PUPPYNET_DEMO";
+/// Seed for the demo fixture; [`DEFAULT_DEMO_SEED`] when unset.
+pub(crate) const DEMO_SEED_ENV: &str = "PUPPYNET_DEMO_SEED";
+pub(crate) const DEFAULT_DEMO_SEED: u64 = 1;
+pub(crate) const DEMO_USERNAME: &str = "demo";
+pub(crate) const DEMO_PASSWORD: &str = "demo";
+/// Files in each device's home folder.
+const FILES_PER_DEVICE: usize = 700;
+/// Share of a remote device's files that are copies of files on this one,
+/// in percent, so the index has replicas.
+const COPIED_PERCENT: usize = 12;
+/// Updates a simulated scan reports before it finishes.
+const SCAN_STEPS: u64 = 20;
+const SCAN_STEP_DELAY: Duration = Duration::from_millis(75);
+/// How long a device takes to accept a pairing.
+const PAIRING_DELAY: Duration = Duration::from_secs(3);
+
+/// The demo seed, when the environment asks for demo mode.
+pub(crate) fn seed_from_env() -> Option<u64> {
+	let enabled =
+		std::env::var(DEMO_ENV).is_ok_and(|value| matches!(value.trim(), "1" | "true" | "yes"));
+	if !enabled {
+		return None;
+	}
+	Some(
+		std::env::var(DEMO_SEED_ENV)
+			.ok()
+			.and_then(|seed| seed.trim().parse().ok())
+			.unwrap_or(DEFAULT_DEMO_SEED),
+	)
+}
+
+/// What the devices in the demo are; this node is the first.
+struct DeviceRole {
+	os: &'static str,
+	kernel: &'static str,
+	home_root: &'static str,
+	root_device: &'static str,
+	root_filesystem: &'static str,
+	interface: &'static str,
+	cores: usize,
+	ghz: f64,
+	memory_gib: u64,
+	root_gib: u64,
+	/// Size of a second disk mounted at `/mnt/media`, if any.
+	media_gib: Option<u64>,
+	webcam: bool,
+}
+
+const ROLES: &[DeviceRole] = &[
+	DeviceRole {
+		os: "Ubuntu 24.04",
+		kernel: "6.8.0-45-generic",
+		home_root: "/home",
+		root_device: "nvme0n1p2",
+		root_filesystem: "ext4",
+		interface: "enp5s0",
+		cores: 16,
+		ghz: 4.2,
+		memory_gib: 32,
+		root_gib: 1000,
+		media_gib: Some(4000),
+		webcam: true,
+	},
+	DeviceRole {
+		os: "macOS 15.1",
+		kernel: "24.1.0",
+		home_root: "/Users",
+		root_device: "disk3s1",
+		root_filesystem: "apfs",
+		interface: "en0",
+		cores: 10,
+		ghz: 3.5,
+		memory_gib: 16,
+		root_gib: 500,
+		media_gib: None,
+		webcam: true,
+	},
+	DeviceRole {
+		os: "Fedora Linux 41",
+		kernel: "6.11.4-301.fc41.x86_64",
+		home_root: "/home",
+		root_device: "nvme0n1p3",
+		root_filesystem: "btrfs",
+		interface: "wlp3s0",
+		cores: 8,
+		ghz: 3.8,
+		memory_gib: 16,
+		root_gib: 512,
+		media_gib: None,
+		webcam: true,
+	},
+	DeviceRole {
+		os: "Debian GNU/Linux 12",
+		kernel: "6.1.0-26-amd64",
+		home_root: "/home",
+		root_device: "sda2",
+		root_filesystem: "ext4",
+		interface: "eth0",
+		cores: 4,
+		ghz: 2.4,
+		memory_gib: 8,
+		root_gib: 250,
+		media_gib: Some(8000),
+		webcam: false,
+	},
+	DeviceRole {
+		os: "Raspberry Pi OS 12",
+		kernel: "6.6.51+rpt-rpi-v8",
+		home_root: "/home",
+		root_device: "mmcblk0p2",
+		root_filesystem: "ext4",
+		interface: "eth0",
+		cores: 4,
+		ghz: 2.4,
+		memory_gib: 4,
+		root_gib: 64,
+		media_gib: None,
+		webcam: false,
+	},
+];
+
+const DEVICE_NAMES: &[&str] = &[
+	"atlas", "biscuit", "comet", "dune", "ember", "fjord", "gizmo", "harbor", "indigo", "juniper",
+	"kestrel", "lumen",
+];
+const USER_NAMES: &[&str] = &[
+	"ana", "ben", "cai", "dana", "eli", "fern", "gus", "hana", "ivo", "jules",
+];
+/// Devices seen on the network that are not paired yet.
+const NEARBY_NAMES: &[&str] = &["living-room-tv", "guest-laptop"];
+const WORDS: &[&str] = &[
+	"beach", "birthday", "budget", "concert", "draft", "family", "garden", "holiday", "invoice",
+	"lecture", "notes", "picnic", "project", "recipe", "report", "roadtrip", "summary", "sunset",
+	"taxes", "wedding",
+];
+const ARTISTS: &[&str] = &[
+	"Aurora Lane",
+	"Night Owls",
+	"Static Bloom",
+	"The Paper Kites",
+	"Velvet Harbor",
+];
+
+/// Kinds of files the fixture makes: folder under the home folder,
+/// extension, mime type, size range in bytes and how common they are.
+struct FileKind {
+	folder: &'static str,
+	extension: &'static str,
+	mime: &'static str,
+	min_size: u64,
+	max_size: u64,
+	weight: u32,
+}
+
+const FILE_KINDS: &[FileKind] = &[
+	FileKind {
+		folder: "Photos",
+		extension: "jpg",
+		mime: "image/jpeg",
+		min_size: 800_000,
+		max_size: 9_000_000,
+		weight: 40,
+	},
+	FileKind {
+		folder: "Photos",
+		extension: "png",
+		mime: "image/png",
+		min_size: 200_000,
+		max_size: 4_000_000,
+		weight: 6,
+	},
+	FileKind {
+		folder: "Videos",
+		extension: "mp4",
+		mime: "video/mp4",
+		min_size: 20_000_000,
+		max_size: 2_000_000_000,
+		weight: 6,
+	},
+	FileKind {
+		folder: "Music",
+		extension: "mp3",
+		mime: "audio/mpeg",
+		min_size: 3_000_000,
+		max_size: 12_000_000,
+		weight: 18,
+	},
+	FileKind {
+		folder: "Music",
+		extension: "flac",
+		mime: "audio/flac",
+		min_size: 15_000_000,
+		max_size: 60_000_000,
+		weight: 4,
+	},
+	FileKind {
+		folder: "Documents",
+		extension: "pdf",
+		mime: "application/pdf",
+		min_size: 40_000,
+		max_size: 8_000_000,
+		weight: 10,
+	},
+	FileKind {
+		folder: "Documents",
+		extension: "txt",
+		mime: "text/plain",
+		min_size: 200,
+		max_size: 60_000,
+		weight: 6,
+	},
+	FileKind {
+		folder: "Documents",
+		extension: "md",
+		mime: "text/markdown",
+		min_size: 300,
+		max_size: 40_000,
+		weight: 4,
+	},
+	FileKind {
+		folder: "Downloads",
+		extension: "zip",
+		mime: "application/zip",
+		min_size: 100_000,
+		max_size: 500_000_000,
+		weight: 6,
+	},
+];
+
+/// A file on a demo device.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct DemoFile {
+	pub(crate) path: String,
+	hash: FileHash,
+	size: u64,
+	mime: &'static str,
+	modified_at: DateTime<Utc>,
+}
+
+impl DemoFile {
+	fn name(&self) -> &str {
+		self.path.rsplit('/').next().unwrap_or(&self.path)
+	}
+
+	fn location(&self, now: DateTime<Utc>) -> FileLocation {
+		FileLocation {
+			path: PathBuf::from(&self.path),
+			hash: Some(self.hash),
+			size: self.size,
+			mime_type: Some(self.mime.to_string()),
+			timestamp: now,
+			created_at: Some(self.modified_at),
+			modified_at: Some(self.modified_at),
+			accessed_at: Some(self.modified_at),
+			blocks: Vec::new(),
+		}
+	}
+
+	fn entry(&self) -> DirEntry {
+		let name = self.name();
+		DirEntry {
+			name: name.to_string(),
+			name_raw: name.as_bytes().to_vec(),
+			is_dir: false,
+			extension: Path::new(name)
+				.extension()
+				.map(|ext| ext.to_string_lossy().into_owned()),
+			mime: Some(self.mime.to_string()),
+			mime_source: MimeSource::Index,
+			size: self.size,
+			disk_size: None,
+			created_at: Some(self.modified_at),
+			modified_at: Some(self.modified_at),
+			accessed_at: Some(self.modified_at),
+			hidden: false,
+		}
+	}
+
+	/// Bytes `offset..offset + length`: repeated lines for text, bytes
+	/// derived from the hash otherwise.
+	fn read(&self, offset: u64, length: u64) -> FileChunk {
+		let end = offset.saturating_add(length).min(self.size);
+		let data = if self.mime.starts_with("text/") {
+			let line = format!("{}: demo text\n", self.name());
+			let line = line.as_bytes();
+			(offset..end)
+				.map(|i| line[(i % line.len() as u64) as usize])
+				.collect()
+		} else {
+			(offset..end)
+				.map(|i| self.hash[(i % 32) as usize] ^ (i as u8).wrapping_mul(31))
+				.collect()
+		};
+		FileChunk {
+			offset,
+			data,
+			eof: end >= self.size,
+			hole: 0,
+		}
+	}
+}
+
+fn dir_entry(name: &str) -> DirEntry {
+	DirEntry {
+		name: name.to_string(),
+		name_raw: name.as_bytes().to_vec(),
+		is_dir: true,
+		extension: None,
+		mime: None,
+		mime_source: MimeSource::default(),
+		size: 0,
+		disk_size: None,
+		created_at: None,
+		modified_at: None,
+		accessed_at: None,
+		hidden: false,
+	}
+}
+
+/// A made-up device.
+#[derive(Clone, Debug)]
+pub(crate) struct DemoPeer {
+	pub(crate) id: PeerId,
+	pub(crate) name: String,
+	os: &'static str,
+	kernel: &'static str,
+	memory_gib: u64,
+	pub(crate) home: String,
+	addr: Multiaddr,
+	/// Uptime when the demo started.
+	uptime_secs: u64,
+	/// Added to every request to the device.
+	latency: Duration,
+	cpus: Vec<CpuInfo>,
+	disks: Vec<DiskInfo>,
+	interfaces: Vec<InterfaceInfo>,
+	audio_devices: Vec<AudioDevice>,
+	media_sources: Vec<MediaSource>,
+	pub(crate) files: Vec<DemoFile>,
+}
+
+impl DemoPeer {
+	fn has_dir(&self, dir: &str) -> bool {
+		let prefix = format!("{}/", dir.trim_end_matches('/'));
+		self.files.iter().any(|file| file.path.starts_with(&prefix))
+	}
+
+	fn file(&self, path: &str) -> Option<&DemoFile> {
+		self.files.iter().find(|file| file.path == path)
+	}
+
+	pub(crate) fn list_dir(&self, dir: &str) -> Result<Vec<DirEntry>> {
+		let prefix = format!("{}/", dir.trim_end_matches('/'));
+		let mut entries = BTreeMap::new();
+		for file in &self.files {
+			let Some(rest) = file.path.strip_prefix(&prefix) else {
+				continue;
+			};
+			let entry = match rest.split_once('/') {
+				Some((sub, _)) => dir_entry(sub),
+				None => file.entry(),
+			};
+			entries.entry(entry.name.clone()).or_insert(entry);
+		}
+		if entries.is_empty() && prefix != "/" && !self.disks.iter().any(|d| d.mount_path == dir) {
+			bail!("Failed to access directory: {dir} does not exist");
+		}
+		Ok(entries.into_values().collect())
+	}
+
+	fn stat(&self, path: &str) -> Result<DirEntry> {
+		if let Some(file) = self.file(path) {
+			return Ok(file.entry());
+		}
+		if self.has_dir(path) || path == "/" {
+			let name = path.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+			return Ok(dir_entry(name));
+		}
+		bail!("Failed to access file: {path} does not exist")
+	}
+
+	fn well_known_folders(&self) -> Vec<WellKnownFolder> {
+		let folder = |kind, label: &str, path: String| WellKnownFolder {
+			kind,
+			label: label.to_string(),
+			path,
+		};
+		let mut folders = vec![folder(FolderKind::Home, "Home", self.home.clone())];
+		for (kind, label, sub) in [
+			(FolderKind::Documents, "Documents", "Documents"),
+			(FolderKind::Downloads, "Downloads", "Downloads"),
+			(FolderKind::Pictures, "Pictures", "Photos"),
+			(FolderKind::Music, "Music", "Music"),
+			(FolderKind::Videos, "Videos", "Videos"),
+		] {
+			folders.push(folder(kind, label, format!("{}/{sub}", self.home)));
+		}
+		for disk in self.disks.iter().filter(|disk| disk.mount_path != "/") {
+			folders.push(folder(
+				FolderKind::Drive,
+				&disk.name,
+				disk.mount_path.clone(),
+			));
+		}
+		folders
+	}
+
+	fn audio_device_mut(
+		&mut self,
+		device_id: Option<&str>,
+		kind: Option<&AudioDeviceKind>,
+	) -> Result<&mut AudioDevice> {
+		self.audio_devices
+			.iter_mut()
+			.find(|device| match device_id {
+				Some(id) => device.id == id,
+				None => device.is_default && kind.is_none_or(|kind| same_kind(&device.kind, kind)),
+			})
+			.ok_or_else(|| anyhow!("audio device not found"))
+	}
+}
+
+fn same_kind(a: &AudioDeviceKind, b: &AudioDeviceKind) -> bool {
+	std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+/// Log-uniform in `min..max`, so small files outnumber big ones.
+fn file_size(rng: &mut StdRng, min: u64, max: u64) -> u64 {
+	let (min, max) = ((min as f64).ln(), (max as f64).ln());
+	rng.gen_range(min..max).exp() as u64
+}
+
+/// Within the last five years, recent dates more likely.
+fn file_date(rng: &mut StdRng, now: DateTime<Utc>) -> DateTime<Utc> {
+	let age_days = rng.r#gen::<f64>().powi(2) * 5.0 * 365.0;
+	now - chrono::Duration::seconds((age_days * 86_400.0) as i64)
+}
+
+fn pick<'a, T>(rng: &mut StdRng, items: &'a [T]) -> &'a T {
+	&items[rng.gen_range(0..items.len())]
+}
+
+fn pick_kind<'a>(rng: &mut StdRng, kinds: &[&'a FileKind]) -> &'a FileKind {
+	let total: u32 = kinds.iter().map(|kind| kind.weight).sum();
+	let mut roll = rng.gen_range(0..total);
+	for kind in kinds {
+		if roll < kind.weight {
+			return kind;
+		}
+		roll -= kind.weight;
+	}
+	kinds[kinds.len() - 1]
+}
+
+/// A file of `kind` in `folder`; `n` keeps the name unique on the device.
+fn make_file(
+	rng: &mut StdRng,
+	folder: &str,
+	kind: &FileKind,
+	n: usize,
+	now: DateTime<Utc>,
+) -> DemoFile {
+	let modified_at = file_date(rng, now);
+	let ext = kind.extension;
+	let path = match kind.folder {
+		"Photos" => format!("{folder}/{}/IMG_{n:04}.{ext}", modified_at.year()),
+		"Music" => format!(
+			"{folder}/{}/{n:03} {} {}.{ext}",
+			pick(rng, ARTISTS),
+			pick(rng, WORDS),
+			pick(rng, WORDS)
+		),
+		"Videos" => format!(
+			"{folder}/{}-{}-{n}.{ext}",
+			pick(rng, WORDS),
+			modified_at.year()
+		),
+		_ => format!(
+			"{folder}/{}-{}-{n}.{ext}",
+			pick(rng, WORDS),
+			pick(rng, WORDS)
+		),
+	};
+	let mut hash = [0u8; 32];
+	rng.fill_bytes(&mut hash);
+	DemoFile {
+		path,
+		hash,
+		size: file_size(rng, kind.min_size, kind.max_size),
+		mime: kind.mime,
+		modified_at,
+	}
+}
+
+fn disk(
+	name: &str,
+	mount_path: &str,
+	filesystem: &str,
+	total_gib: u64,
+	used: f64,
+	kind: &str,
+	id: String,
+) -> DiskInfo {
+	let total_space = total_gib * 1024 * 1024 * 1024;
+	let available_space = (total_space as f64 * (1.0 - used)) as u64;
+	DiskInfo {
+		name: name.to_string(),
+		mount_path: mount_path.to_string(),
+		filesystem: filesystem.to_string(),
+		total_space,
+		available_space,
+		usage_percent: (used * 100.0) as f32,
+		total_read_bytes: total_space / 7,
+		total_written_bytes: total_space / 11,
+		read_only: false,
+		removable: false,
+		kind: kind.to_string(),
+		id,
+	}
+}
+
+fn demo_peer(
+	rng: &mut StdRng,
+	index: usize,
+	role: &DeviceRole,
+	name: &str,
+	user: &str,
+	now: DateTime<Utc>,
+) -> DemoPeer {
+	let mut key = [0u8; 32];
+	rng.fill_bytes(&mut key);
+	let id = Keypair::ed25519_from_bytes(key)
+		.expect("32 bytes are an ed25519 key")
+		.public()
+		.to_peer_id();
+	let host = 10 + index * 7;
+	let addr: Multiaddr = format!("/ip4/192.168.1.{host}/tcp/4001")
+		.parse()
+		.expect("valid multiaddr");
+	let cpus = (0..role.cores)
+		.map(|core| CpuInfo {
+			name: format!("cpu{core}"),
+			usage: rng.gen_range(1.0..45.0),
+			frequency_hz: (role.ghz * 1e9) as u64,
+		})
+		.collect();
+	let mut disks = vec![disk(
+		role.root_device,
+		"/",
+		role.root_filesystem,
+		role.root_gib,
+		rng.gen_range(0.3..0.85),
+		"SSD",
+		format!("{name}-root"),
+	)];
+	if let Some(media_gib) = role.media_gib {
+		disks.push(disk(
+			"sdb1",
+			"/mnt/media",
+			"ext4",
+			media_gib,
+			rng.gen_range(0.5..0.95),
+			"HDD",
+			format!("{name}-media"),
+		));
+	}
+	let mut mac = [0u8; 6];
+	rng.fill_bytes(&mut mac);
+	mac[0] = (mac[0] & 0xfe) | 0x02;
+	let received = rng.gen_range(1u64 << 30..1u64 << 40);
+	let interfaces = vec![
+		InterfaceInfo {
+			name: String::from("lo"),
+			mac: String::from("00:00:00:00:00:00"),
+			ips: vec![String::from("127.0.0.1/8"), String::from("::1/128")],
+			total_received: received / 50,
+			total_transmitted: received / 50,
+			packets_received: received / 50_000,
+			packets_transmitted: received / 50_000,
+			errors_on_received: 0,
+			errors_on_transmitted: 0,
+			mtu: 65536,
+		},
+		InterfaceInfo {
+			name: role.interface.to_string(),
+			mac: mac
+				.iter()
+				.map(|byte| format!("{byte:02x}"))
+				.collect::<Vec<_>>()
+				.join(":"),
+			ips: vec![format!("192.168.1.{host}/24")],
+			total_received: received,
+			total_transmitted: received / 3,
+			packets_received: received / 1200,
+			packets_transmitted: received / 3600,
+			errors_on_received: rng.gen_range(0..3),
+			errors_on_transmitted: 0,
+			mtu: 1500,
+		},
+	];
+	let audio_device = |id: &str, name: &str, kind, is_default| AudioDevice {
+		id: id.to_string(),
+		name: name.to_string(),
+		description: name.to_string(),
+		kind,
+		volume: 65,
+		muted: false,
+		is_default,
+	};
+	let mut audio_devices = vec![
+		audio_device("speakers", "Built-in Speakers", AudioDeviceKind::Sink, true),
+		audio_device("mic", "Built-in Microphone", AudioDeviceKind::Source, true),
+	];
+	if role.media_gib.is_some() {
+		audio_devices.push(audio_device(
+			"hdmi",
+			"HDMI Output",
+			AudioDeviceKind::Sink,
+			false,
+		));
+	}
+	let snapshot = || {
+		vec![MediaOutput {
+			transport: MediaTransport::Snapshot,
+			mime: String::from("image/png"),
+			codec: None,
+		}]
+	};
+	let mut media_sources = vec![MediaSource {
+		id: String::from("screen-0"),
+		name: String::from("Screen 1"),
+		kind: MediaSourceKind::Screen,
+		live: true,
+		outputs: snapshot(),
+	}];
+	if role.webcam {
+		media_sources.push(MediaSource {
+			id: String::from("webcam-0"),
+			name: String::from("Integrated Camera"),
+			kind: MediaSourceKind::Webcam,
+			live: true,
+			outputs: snapshot(),
+		});
+	}
+	let home = format!("{}/{user}", role.home_root);
+	let home_kinds = FILE_KINDS.iter().collect::<Vec<_>>();
+	let mut files = (0..FILES_PER_DEVICE)
+		.map(|n| {
+			let kind = pick_kind(rng, &home_kinds);
+			make_file(rng, &format!("{home}/{}", kind.folder), kind, n, now)
+		})
+		.collect::<Vec<_>>();
+	if role.media_gib.is_some() {
+		let video = FILE_KINDS
+			.iter()
+			.find(|kind| kind.folder == "Videos")
+			.expect("video kind");
+		files.extend(
+			(0..120).map(|n| make_file(rng, "/mnt/media/Videos", video, FILES_PER_DEVICE + n, now)),
+		);
+	}
+	DemoPeer {
+		id,
+		name: name.to_string(),
+		os: role.os,
+		kernel: role.kernel,
+		memory_gib: role.memory_gib,
+		home,
+		addr,
+		uptime_secs: rng.gen_range(3_600..40 * 86_400),
+		latency: if index == 0 {
+			Duration::ZERO
+		} else {
+			Duration::from_millis(rng.gen_range(15..120))
+		},
+		cpus,
+		disks,
+		interfaces,
+		audio_devices,
+		media_sources,
+		files,
+	}
+}
+
+/// Everything demo mode shows, made from one seed.
+pub(crate) struct DemoFixture {
+	pub(crate) seed: u64,
+	/// Start of the day the fixture was made, so the same seed gives the
+	/// same dates all day.
+	pub(crate) now: DateTime<Utc>,
+	/// This node first, then the remote devices.
+	pub(crate) peers: Vec<DemoPeer>,
+	/// Devices seen nearby that are not paired yet.
+	nearby: Vec<(PeerId, String, Multiaddr)>,
+}
+
+impl DemoFixture {
+	pub(crate) fn generate(seed: u64, now: DateTime<Utc>) -> Self {
+		let now = now
+			.date_naive()
+			.and_hms_opt(0, 0, 0)
+			.expect("midnight exists")
+			.and_utc();
+		let mut rng = StdRng::seed_from_u64(seed);
+		let mut names = DEVICE_NAMES.to_vec();
+		let mut users = USER_NAMES.to_vec();
+		let mut peers = Vec::with_capacity(ROLES.len());
+		for (index, role) in ROLES.iter().enumerate() {
+			let name = names.remove(rng.gen_range(0..names.len()));
+			let user = users.remove(rng.gen_range(0..users.len()));
+			peers.push(demo_peer(&mut rng, index, role, name, user, now));
+		}
+		let (local, remotes) = peers.split_first_mut().expect("at least one device");
+		for remote in remotes {
+			let mut paths = remote
+				.files
+				.iter()
+				.map(|file| file.path.clone())
+				.collect::<HashSet<_>>();
+			for _ in 0..FILES_PER_DEVICE * COPIED_PERCENT / 100 {
+				let original = pick(&mut rng, &local.files);
+				let Some(rest) = original.path.strip_prefix(&local.home) else {
+					continue;
+				};
+				let copy = DemoFile {
+					path: format!("{}{rest}", remote.home),
+					..original.clone()
+				};
+				if paths.insert(copy.path.clone()) {
+					remote.files.push(copy);
+				}
+			}
+		}
+		let nearby = NEARBY_NAMES
+			.iter()
+			.enumerate()
+			.map(|(index, name)| {
+				let mut key = [0u8; 32];
+				rng.fill_bytes(&mut key);
+				let id = Keypair::ed25519_from_bytes(key)
+					.expect("32 bytes are an ed25519 key")
+					.public()
+					.to_peer_id();
+				let addr: Multiaddr = format!("/ip4/192.168.1.{}/udp/4001/quic-v1", 200 + index)
+					.parse()
+					.expect("valid multiaddr");
+				(id, name.to_string(), addr)
+			})
+			.collect();
+		Self {
+			seed,
+			now,
+			peers,
+			nearby,
+		}
+	}
+
+	fn local(&self) -> &DemoPeer {
+		&self.peers[0]
+	}
+
+	/// Folders this node shares.
+	fn shared_folders(&self) -> Vec<FolderRule> {
+		let local = self.local();
+		let mut folders = vec![
+			FolderRule::new(
+				PathBuf::from(format!("{}/Photos", local.home)),
+				FLAG_READ | FLAG_SEARCH | FLAG_PREVIEW,
+			),
+			FolderRule::new(
+				PathBuf::from(format!("{}/Music", local.home)),
+				FLAG_READ | FLAG_SEARCH,
+			),
+			FolderRule::new(
+				PathBuf::from(format!("{}/Documents", local.home)),
+				FLAG_READ | FLAG_WRITE | FLAG_SEARCH,
+			),
+		];
+		if local
+			.disks
+			.iter()
+			.any(|disk| disk.mount_path == "/mnt/media")
+		{
+			folders.push(FolderRule::new(
+				PathBuf::from("/mnt/media"),
+				FLAG_READ | FLAG_SEARCH | FLAG_PREVIEW,
+			));
+		}
+		folders
+	}
+
+	/// What this node lets each remote device do.
+	fn grants(&self) -> Vec<(PeerId, Vec<Permission>)> {
+		let home = &self.local().home;
+		let folder = |path: String, flags| {
+			Permission::new(Rule::Folder(FolderRule::new(PathBuf::from(path), flags)))
+		};
+		vec![
+			(self.peers[1].id, vec![Permission::new(Rule::Owner)]),
+			(
+				self.peers[2].id,
+				vec![
+					folder(
+						format!("{home}/Photos"),
+						FLAG_READ | FLAG_SEARCH | FLAG_PREVIEW,
+					),
+					folder(format!("{home}/Documents"), FLAG_READ | FLAG_SEARCH),
+				],
+			),
+			(
+				self.peers[3].id,
+				vec![folder(format!("{home}/Music"), FLAG_READ)],
+			),
+		]
+	}
+
+	/// What each remote device lets this node do.
+	fn remote_permissions(&self) -> HashMap<PeerId, Vec<Permission>> {
+		self.peers[1..]
+			.iter()
+			.enumerate()
+			.map(|(index, peer)| {
+				let permission = if index == 0 {
+					Permission::new(Rule::Owner)
+				} else {
+					Permission::new(Rule::Folder(FolderRule::new(
+						PathBuf::from(&peer.home),
+						FLAG_READ | FLAG_SEARCH | FLAG_PREVIEW,
+					)))
+				};
+				(peer.id, vec![permission])
+			})
+			.collect()
+	}
+
+	fn node(&self, peer: &DemoPeer, you: bool) -> Option<Node> {
+		let created_at = self.now - chrono::Duration::days(400);
+		Some(Node {
+			id: peer_to_node_id(&peer.id)?,
+			name: peer.name.clone(),
+			you,
+			total_memory: peer.memory_gib * 1024 * 1024 * 1024,
+			system_name: peer.os.split(' ').next().unwrap_or(peer.os).to_string(),
+			kernel_version: peer.kernel.to_string(),
+			os_version: peer.os.to_string(),
+			created_at,
+			modified_at: self.now,
+			accessed_at: self.now,
+		})
+	}
+
+	/// Fills a fresh database with the fixture's devices, index, scan
+	/// history, shares, users and permissions.
+	pub(crate) fn populate(&self, conn: &mut SqliteConnection) -> Result<()> {
+		run_migrations(conn)?;
+		let me = self.local().id;
+		let mut rng = StdRng::seed_from_u64(self.seed ^ 0x5eed);
+		for (index, peer) in self.peers.iter().enumerate() {
+			let node = self
+				.node(peer, index == 0)
+				.ok_or_else(|| anyhow!("peer id too short for a node id"))?;
+			save_node(conn, &node)?;
+			if index > 0 {
+				save_peer(
+					conn,
+					&Peer {
+						id: peer.id,
+						name: Some(peer.name.clone()),
+						trust: TrustLevel::Trusted,
+					},
+				)?;
+			}
+			let locations = peer
+				.files
+				.iter()
+				.map(|file| file.location(self.now))
+				.collect();
+			let changes =
+				scan::record_locations(conn, &node.id, locations).map_err(|e| anyhow!(e))?;
+			if index > 0 {
+				continue;
+			}
+			for rule in self.shared_folders() {
+				let path = rule.path().to_string_lossy().into_owned();
+				let mut file_count = 0;
+				for week in (1..=6).rev() {
+					let started_at = self.now - chrono::Duration::weeks(week);
+					let inserted = rng.gen_range(0..12);
+					file_count += inserted;
+					let outcome = if week == 3 && path.ends_with("Music") {
+						Err(String::from("Permission denied (os error 13)"))
+					} else {
+						Ok(ScanResult {
+							updated_count: rng.gen_range(0..4),
+							inserted_count: inserted,
+							removed_count: rng.gen_range(0..2),
+							duration: Duration::from_millis(rng.gen_range(400..9_000)),
+							file_count,
+							checksums: None,
+							run: None,
+							changes: Vec::new(),
+						})
+					};
+					record_scan_run(
+						conn,
+						&path,
+						started_at,
+						Duration::from_secs(3),
+						Some(&me),
+						&outcome,
+						false,
+					)?;
+				}
+				let changes = changes
+					.iter()
+					.filter(|change| change.path.starts_with(rule.path()))
+					.cloned()
+					.collect::<Vec<_>>();
+				let latest = ScanResult {
+					updated_count: 0,
+					inserted_count: changes.len() as u64,
+					removed_count: 0,
+					duration: Duration::from_millis(rng.gen_range(2_000..20_000)),
+					file_count: changes.len() as u64,
+					checksums: None,
+					run: None,
+					changes,
+				};
+				let started_at = self.now - chrono::Duration::hours(2);
+				record_scan_run(
+					conn,
+					&path,
+					started_at,
+					latest.duration,
+					Some(&me),
+					&Ok(latest),
+					false,
+				)?;
+				save_shared_folder(conn, &rule)?;
+			}
+		}
+		save_setting(conn, NODE_NAME_SETTING, &self.local().name)?;
+		let passw = auth::hash_password(DEMO_PASSWORD)?;
+		save_user(
+			conn,
+			&User {
+				name: DEMO_USERNAME.to_string(),
+				passw,
+			},
+		)?;
+		for (peer, permissions) in self.grants() {
+			save_peer_permissions_at(conn, &me, &peer, &permissions, None)?;
+		}
+		Ok(())
+	}
+
+	/// The node state the fixture starts with.
+	pub(crate) fn state(&self) -> State {
+		let mut state = State::default();
+		state.me = self.local().id;
+		state.peers = self
+			.peers
+			.iter()
+			.map(|peer| Peer {
+				id: peer.id,
+				name: Some(peer.name.clone()),
+				trust: TrustLevel::Trusted,
+			})
+			.collect();
+		for (index, peer) in self.peers.iter().enumerate().skip(1) {
+			state.connections.push(Connection {
+				peer_id: peer.id,
+				connection_id: ConnectionId::new_unchecked(index),
+				direction: if index % 2 == 0 {
+					ConnectionDirection::Inbound
+				} else {
+					ConnectionDirection::Outbound
+				},
+				local_addr: None,
+				remote_addr: peer.addr.clone(),
+				connected_at: self.now - chrono::Duration::minutes(17 * index as i64),
+			});
+			state
+				.capabilities
+				.insert(peer.id, PeerCapabilities::local());
+		}
+		for rule in self.shared_folders() {
+			state.share_availability.insert(
+				rule.path().to_path_buf(),
+				ShareAvailability::Available { mount_point: None },
+			);
+			state.add_shared_folder(rule);
+		}
+		for (peer, permissions) in self.grants() {
+			state.set_peer_permissions(peer, permissions);
+		}
+		state.remote_permissions = self.remote_permissions();
+		state
+	}
+}
+
+/// A gradient tinted by `seed`, standing in for photos, screens and camera
+/// frames.
+fn test_pattern(width: u32, height: u32, seed: &[u8]) -> Result<Vec<u8>> {
+	let (width, height) = (width.max(1), height.max(1));
+	let tint = |i: usize| seed.get(i).copied().unwrap_or(128) / 2;
+	let image = image::RgbImage::from_fn(width, height, |x, y| {
+		let fx = (x * 255 / width) as u8 / 2;
+		let fy = (y * 255 / height) as u8 / 2;
+		image::Rgb([tint(0) + fx, tint(1) + fy, tint(2) + (fx ^ fy) / 2])
+	});
+	let mut data = Vec::new();
+	image::DynamicImage::ImageRgb8(image)
+		.write_to(&mut Cursor::new(&mut data), image::ImageFormat::Png)
+		.map_err(|err| anyhow!("failed to encode demo image: {err}"))?;
+	Ok(data)
+}
+
+/// Where a simulated scan reports to.
+enum ScanSink {
+	Local(tokio::sync::mpsc::Sender<ScanEvent>),
+	Remote(UnboundedSender<ScanEvent>),
+}
+
+impl ScanSink {
+	async fn send(&self, event: ScanEvent) -> bool {
+		match self {
+			Self::Local(tx) => tx.send(event).await.is_ok(),
+			Self::Remote(tx) => tx.send(event).is_ok(),
+		}
+	}
+}
+
+enum DemoEvent {
+	Scanned { peer: PeerId, files: Vec<DemoFile> },
+	PairingAccepted { peer: PeerId },
+}
+
+/// Answers [`Command`]s from a [`DemoFixture`] the way `App` answers them
+/// from the swarm.
+pub(crate) struct DemoApp {
+	fixture: DemoFixture,
+	state: State,
+	users: Vec<String>,
+	discovered: DiscoveredPeers,
+	db: Arc<Db>,
+	remote_scans: Arc<RemoteOps<ScanEvent>>,
+	remote_searches: Arc<Mutex<HashMap<u64, mpsc::Sender<SearchEvent>>>>,
+	remote_updates: Arc<RemoteOps<UpdateProgress>>,
+	rx: UnboundedReceiver<Command>,
+	events_tx: UnboundedSender<DemoEvent>,
+	events_rx: UnboundedReceiver<DemoEvent>,
+	rng: StdRng,
+	started_at: Instant,
+	restarted_at: HashMap<PeerId, Instant>,
+	/// Keeps names of files made by simulated scans unique.
+	next_file: usize,
+	frames: u64,
+}
+
+impl DemoApp {
+	pub(crate) fn new(
+		fixture: DemoFixture,
+		db: Arc<Db>,
+		remote_scans: Arc<RemoteOps<ScanEvent>>,
+		remote_searches: Arc<Mutex<HashMap<u64, mpsc::Sender<SearchEvent>>>>,
+		remote_updates: Arc<RemoteOps<UpdateProgress>>,
+	) -> (Self, UnboundedSender<Command>) {
+		let (tx, rx) = unbounded_channel();
+		let (events_tx, events_rx) = unbounded_channel();
+		let mut discovered = DiscoveredPeers::new(DEFAULT_DISCOVERED_ADDRESS_TTL);
+		for peer in &fixture.peers[1..] {
+			discovered.record(peer.id, peer.addr.clone(), fixture.now);
+		}
+		for (peer, _, addr) in &fixture.nearby {
+			discovered.record(*peer, addr.clone(), fixture.now);
+		}
+		let app = Self {
+			state: fixture.state(),
+			users: vec![DEMO_USERNAME.to_string()],
+			discovered,
+			db,
+			remote_scans,
+			remote_searches,
+			remote_updates,
+			rx,
+			events_tx,
+			events_rx,
+			rng: StdRng::seed_from_u64(fixture.seed.rotate_left(17)),
+			started_at: Instant::now(),
+			restarted_at: HashMap::new(),
+			next_file: FILES_PER_DEVICE * 2,
+			frames: 0,
+			fixture,
+		};
+		(app, tx)
+	}
+
+	fn peer(&self, id: &PeerId) -> Result<&DemoPeer> {
+		self.fixture
+			.peers
+			.iter()
+			.find(|peer| peer.id == *id)
+			.ok_or_else(|| anyhow!("peer {id} is not connected"))
+	}
+
+	fn peer_mut(&mut self, id: &PeerId) -> Result<&mut DemoPeer> {
+		self.fixture
+			.peers
+			.iter_mut()
+			.find(|peer| peer.id == *id)
+			.ok_or_else(|| anyhow!("peer {id} is not connected"))
+	}
+
+	/// Waits as long as a request to `peer` would take.
+	async fn round_trip(&self, peer: &PeerId) {
+		if let Ok(peer) = self.peer(peer)
+			&& !peer.latency.is_zero()
+		{
+			tokio::time::sleep(peer.latency).await;
+		}
+	}
+
+	fn uptime_secs(&self, peer: &DemoPeer) -> u64 {
+		match self.restarted_at.get(&peer.id) {
+			Some(at) => at.elapsed().as_secs(),
+			None => peer.uptime_secs + self.started_at.elapsed().as_secs(),
+		}
+	}
+
+	fn thumbnail(
+		&self,
+		peer: &PeerId,
+		path: &str,
+		max_width: u32,
+		max_height: u32,
+	) -> Result<Thumbnail> {
+		let file = self
+			.peer(peer)?
+			.file(path)
+			.ok_or_else(|| anyhow!("{path} does not exist"))?;
+		if !file.mime.starts_with("image/") && !file.mime.starts_with("video/") {
+			bail!("no thumbnail for {}", file.mime);
+		}
+		let width = max_width.min(max_height.saturating_mul(4) / 3).max(1);
+		let height = (width * 3 / 4).max(1);
+		Ok(Thumbnail {
+			data: test_pattern(width, height, &file.hash)?,
+			width,
+			height,
+			mime_type: String::from("image/png"),
+		})
+	}
+
+	/// Contact sheet of the demo images directly in `dir`.
+	async fn contact_sheet(
+		&self,
+		peer: &PeerId,
+		dir: &str,
+		options: ContactSheetOptions,
+	) -> Result<ContactSheetResult> {
+		let options = options.clamped();
+		let mut images = self
+			.peer(peer)?
+			.list_dir(dir)?
+			.into_iter()
+			.filter(|entry| {
+				!entry.is_dir
+					&& entry
+						.mime
+						.as_deref()
+						.is_some_and(|mime| mime.starts_with("image/"))
+			})
+			.map(|entry| entry.name)
+			.collect::<Vec<_>>();
+		images.sort_by(|a, b| natural_cmp(a, b));
+		images.truncate(options.max_items as usize);
+		let dir = dir.trim_end_matches('/');
+		let images = images
+			.into_iter()
+			.map(|name| PathBuf::from(format!("{dir}/{name}")))
+			.collect();
+		let deadline = Instant::now() + CONTACT_SHEET_TIME_BUDGET;
+		let cell = options.cell_size;
+		contact_sheet::build(images, options, deadline, |path| async move {
+			self.thumbnail(peer, &path.to_string_lossy(), cell, cell)
+		})
+		.await
+	}
+
+	fn disk_history(
+		&self,
+		peer: &PeerId,
+		mount: &str,
+		from: DateTime<Utc>,
+		to: DateTime<Utc>,
+	) -> Result<Vec<DiskSample>> {
+		let Some(disk) = self
+			.peer(peer)?
+			.disks
+			.iter()
+			.find(|d| d.mount_path == mount)
+		else {
+			return Ok(Vec::new());
+		};
+		let now = Utc::now();
+		// Free space shrinks by about a percent a week, with some wobble.
+		let per_hour = disk.total_space / 100 / (7 * 24);
+		let mut samples = Vec::new();
+		let mut at = from.max(to - chrono::Duration::days(90));
+		while at <= to && samples.len() < 500 {
+			let hours_ago = (now - at).num_hours().max(0) as u64;
+			let wobble = (at.timestamp() as u64 / 21_600 % 7) * (disk.total_space / 4_000);
+			samples.push(DiskSample {
+				disk_id: disk.id.clone(),
+				mount_path: disk.mount_path.clone(),
+				sampled_at: at,
+				total_space: disk.total_space,
+				available_space: (disk.available_space + hours_ago * per_hour + wobble)
+					.min(disk.total_space),
+			});
+			at += chrono::Duration::hours(6);
+		}
+		Ok(samples)
+	}
+
+	fn read_file(&self, cmd: &ReadFileCmd) -> Result<FileChunk> {
+		let path = cmd.path.to_string();
+		let file = self
+			.peer(&cmd.peer_id)?
+			.file(&path)
+			.ok_or_else(|| anyhow!("Failed to open file: {path} does not exist"))?;
+		let length = cmd
+			.length
+			.unwrap_or(DEFAULT_READ_CHUNK_SIZE)
+			.min(DEFAULT_READ_CHUNK_SIZE);
+		Ok(file.read(cmd.offset, length))
+	}
+
+	/// Summaries of the folders `peer` lets this node search, from its
+	/// files in the demo index.
+	fn share_summaries(&self, peer: &PeerId) -> Result<Vec<ShareSummary>> {
+		let node_id = peer_to_node_id(peer).ok_or_else(|| anyhow!("invalid peer id {peer}"))?;
+		let mut roots = self
+			.state
+			.remote_permissions
+			.get(peer)
+			.into_iter()
+			.flatten()
+			.filter_map(|permission| match permission.rule() {
+				Rule::Folder(folder) if folder.allows(FLAG_SEARCH) => {
+					Some(folder.path().to_path_buf())
+				}
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+		roots.sort();
+		roots.dedup();
+		roots.truncate(SHARE_SUMMARY_MAX_ROOTS);
+		let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+		roots
+			.into_iter()
+			.map(|root| {
+				let files = indexed_files_under(
+					&conn,
+					&node_id,
+					&root.to_string_lossy(),
+					SHARE_SUMMARY_MAX_FILES + 1,
+				)?;
+				Ok(summarize_files(
+					&root,
+					files,
+					SHARE_SUMMARY_MAX_FILES,
+					|_| true,
+				))
+			})
+			.collect()
+	}
+
+	fn live_search(&self, peer: &PeerId, args: &LiveSearchArgs, tx: mpsc::Sender<SearchEvent>) {
+		let files = match self.peer(peer) {
+			Ok(peer) => &peer.files,
+			Err(err) => {
+				let _ = tx.send(SearchEvent::Failed {
+					error: err.to_string(),
+				});
+				return;
+			}
+		};
+		let query = args.name_query.as_deref().unwrap_or("").to_lowercase();
+		let mut matched = files
+			.iter()
+			.filter(|file| file.name().to_lowercase().contains(&query))
+			.filter(|file| {
+				args.mime_types.is_empty() || args.mime_types.iter().any(|m| m == file.mime)
+			})
+			.collect::<Vec<_>>();
+		matched.sort_by_key(|file| file.modified_at);
+		if args.sort_desc {
+			matched.reverse();
+		}
+		let _ = tx.send(SearchEvent::Progress {
+			visited: files.len(),
+			matched: matched.len(),
+		});
+		let rows = matched
+			.iter()
+			.skip(args.page * args.page_size)
+			.take(args.page_size)
+			.map(|file| LiveSearchRow {
+				name: file.name().to_string(),
+				path: file.path.clone(),
+				size: file.size,
+				mime_type: Some(file.mime.to_string()),
+				modified_at: Some(file.modified_at.to_rfc3339()),
+			})
+			.collect();
+		let _ = tx.send(SearchEvent::Rows { rows });
+		let _ = tx.send(SearchEvent::Finished {
+			total: matched.len(),
+			truncated: false,
+		});
+	}
+
+	/// Scans `path` on `peer` without touching the disk: reports progress
+	/// for a while, then adds a few new files to the index.
+	fn start_scan(&mut self, peer: PeerId, path: String, sink: ScanSink, cancel: Arc<AtomicBool>) {
+		let Some(node_id) = self
+			.peer(&peer)
+			.ok()
+			.and_then(|demo| peer_to_node_id(&demo.id))
+		else {
+			tokio::spawn(async move {
+				let outcome = Err(format!("peer {peer} is not connected"));
+				sink.send(ScanEvent::Finished(outcome)).await;
+			});
+			return;
+		};
+		let folder = path.trim_end_matches('/').to_string();
+		let existing = self
+			.peer(&peer)
+			.map(|p| {
+				p.files
+					.iter()
+					.filter(|f| f.path.starts_with(&folder))
+					.count()
+			})
+			.unwrap_or_default();
+		let kinds = FILE_KINDS
+			.iter()
+			.filter(|kind| folder.contains(kind.folder))
+			.collect::<Vec<_>>();
+		let kinds = if kinds.is_empty() {
+			FILE_KINDS.iter().collect()
+		} else {
+			kinds
+		};
+		let added = self.rng.gen_range(3..25);
+		let now = Utc::now();
+		let files = (0..added)
+			.map(|_| {
+				self.next_file += 1;
+				let kind = pick_kind(&mut self.rng, &kinds);
+				let mut file = make_file(&mut self.rng, &folder, kind, self.next_file, now);
+				file.modified_at = now;
+				file
+			})
+			.collect::<Vec<_>>();
+		let total = existing + files.len();
+		let db = self.db.clone();
+		let events = self.events_tx.clone();
+		let initiator = self.state.me;
+		tokio::spawn(async move {
+			let started_at = Utc::now();
+			let clock = Instant::now();
+			for step in 1..=SCAN_STEPS {
+				tokio::time::sleep(SCAN_STEP_DELAY).await;
+				if cancel.load(Ordering::Relaxed) {
+					let outcome = Err(String::from("Scan cancelled"));
+					if let Ok(conn) = db.lock() {
+						let _ = record_scan_run(
+							&conn,
+							&path,
+							started_at,
+							clock.elapsed(),
+							Some(&initiator),
+							&outcome,
+							true,
+						);
+					}
+					sink.send(ScanEvent::Finished(outcome)).await;
+					return;
+				}
+				let progress = ScanProgress {
+					total_files: total,
+					processed_files: total * step as usize / SCAN_STEPS as usize,
+					inserted_count: files.len() as u64 * step / SCAN_STEPS,
+					updated_count: 0,
+					removed_count: 0,
+					..Default::default()
+				};
+				if !sink.send(ScanEvent::Progress(progress)).await {
+					break;
+				}
+			}
+			let outcome = (|| -> Result<ScanResult, String> {
+				let mut conn = db.lock().map_err(|_| String::from("db lock poisoned"))?;
+				let locations = files.iter().map(|file| file.location(now)).collect();
+				let changes = scan::record_locations(&mut conn, &node_id, locations)?;
+				let result = ScanResult {
+					updated_count: 0,
+					inserted_count: changes.len() as u64,
+					removed_count: 0,
+					duration: clock.elapsed(),
+					file_count: total as u64,
+					checksums: None,
+					run: None,
+					changes,
+				};
+				let _ = record_scan_run(
+					&conn,
+					&path,
+					started_at,
+					result.duration,
+					Some(&initiator),
+					&Ok(result.clone()),
+					false,
+				);
+				Ok(result)
+			})();
+			if outcome.is_ok() {
+				let _ = events.send(DemoEvent::Scanned { peer, files });
+			}
+			sink.send(ScanEvent::Finished(outcome)).await;
+		});
+	}
+
+	fn handle_event(&mut self, event: DemoEvent) {
+		match event {
+			DemoEvent::Scanned { peer, files } => {
+				if let Ok(peer) = self.peer_mut(&peer) {
+					peer.files.extend(files);
+				}
+			}
+			DemoEvent::PairingAccepted { peer } => {
+				let name = self
+					.fixture
+					.nearby
+					.iter()
+					.find(|(id, _, _)| *id == peer)
+					.map(|(_, name, _)| name.clone())
+					.unwrap_or_default();
+				if let Some(pairing) = self.state.pairings.get_mut(&peer) {
+					pairing.peer_name = name.clone();
+					pairing.status = PairingStatus::Paired;
+				}
+				if !self.state.peers.iter().any(|known| known.id == peer) {
+					self.state.peers.push(Peer {
+						id: peer,
+						name: Some(name),
+						trust: TrustLevel::Basic,
+					});
+				}
+			}
+		}
+	}
+
+	async fn handle_cmd(&mut self, cmd: Command) {
+		match cmd {
+			Command::PeerInfo { tx, peer_id } => {
+				self.round_trip(&peer_id).await;
+				let result = self.peer(&peer_id).map(|peer| PeerInfo {
+					version: version::version_label(),
+					os: peer.os.to_string(),
+					uptime_seconds: self.uptime_secs(peer),
+				});
+				let _ = tx.send(result);
+			}
+			Command::Connect { peer_id, addr } => {
+				tracing::info!("demo mode does not dial {peer_id} at {addr}");
+			}
+			Command::Request { tx, .. } => {
+				let _ = tx.send(Err(anyhow!(
+					"demo peers only answer the node's own commands"
+				)));
+			}
+			Command::ListDir { peer, path, tx } => {
+				self.round_trip(&peer).await;
+				let path = path.to_string();
+				let listing = self.peer(&peer).and_then(|peer| peer.list_dir(&path));
+				let _ = tx.send(listing.map(|entries| DirListing {
+					entries,
+					free_hint: None,
+					access: None,
+				}));
+			}
+			Command::StatFile { peer, path, tx } => {
+				self.round_trip(&peer).await;
+				let path = path.to_string();
+				let _ = tx.send(self.peer(&peer).and_then(|peer| peer.stat(&path)));
+			}
+			Command::ListCpus { tx, peer_id } => {
+				self.round_trip(&peer_id).await;
+				let rng = &mut self.rng;
+				let result = self
+					.fixture
+					.peers
+					.iter_mut()
+					.find(|peer| peer.id == peer_id)
+					.map(|peer| {
+						for cpu in &mut peer.cpus {
+							cpu.usage = (cpu.usage + rng.gen_range(-6.0..6.0)).clamp(0.5, 100.0);
+						}
+						peer.cpus.clone()
+					})
+					.ok_or_else(|| anyhow!("peer {peer_id} is not connected"));
+				let _ = tx.send(result);
+			}
+			Command::ListDisks { tx, peer_id } => {
+				self.round_trip(&peer_id).await;
+				let _ = tx.send(self.peer(&peer_id).map(|peer| peer.disks.clone()));
+			}
+			Command::DiskHistory {
+				peer_id,
+				mount,
+				from,
+				to,
+				tx,
+			} => {
+				self.round_trip(&peer_id).await;
+				let _ = tx.send(self.disk_history(&peer_id, &mount, from, to));
+			}
+			Command::ListRoots { tx, peer_id } => {
+				self.round_trip(&peer_id).await;
+				let result = self.peer(&peer_id).map(|peer| {
+					let shared = if peer_id == self.state.me {
+						self.state
+							.roots_for_peer(&peer_id, FLAG_READ)
+							.iter()
+							.map(|path| path.to_string_lossy().into_owned())
+							.collect()
+					} else {
+						vec![peer.home.clone()]
+					};
+					BrowseRoots::collect(false, &peer.disks, &shared, |_| true)
+				});
+				let _ = tx.send(result);
+			}
+			Command::WellKnownFolders { tx, peer_id } => {
+				self.round_trip(&peer_id).await;
+				let _ = tx.send(self.peer(&peer_id).map(DemoPeer::well_known_folders));
+			}
+			Command::ListInterfaces { tx, peer_id } => {
+				self.round_trip(&peer_id).await;
+				let _ = tx.send(self.peer(&peer_id).map(|peer| peer.interfaces.clone()));
+			}
+			Command::AudioCapability { tx, peer_id } => {
+				self.round_trip(&peer_id).await;
+				let result = self.peer(&peer_id).map(|_| AudioCapability {
+					supported: true,
+					backend: Some(String::from("demo")),
+					message: String::from("Simulated audio devices"),
+				});
+				let _ = tx.send(result);
+			}
+			Command::ListAudioDevices { tx, peer_id } => {
+				self.round_trip(&peer_id).await;
+				let _ = tx.send(self.peer(&peer_id).map(|peer| peer.audio_devices.clone()));
+			}
+			Command::SetAudioMuted {
+				tx,
+				peer_id,
+				device_id,
+				muted,
+			} => {
+				self.round_trip(&peer_id).await;
+				let result = self.peer_mut(&peer_id).and_then(|peer| {
+					peer.audio_device_mut(device_id.as_deref(), Some(&AudioDeviceKind::Sink))?
+						.muted = muted;
+					Ok(peer.audio_devices.clone())
+				});
+				let _ = tx.send(result);
+			}
+			Command::SetAudioVolume {
+				tx,
+				peer_id,
+				device_id,
+				volume,
+			} => {
+				self.round_trip(&peer_id).await;
+				let result = self.peer_mut(&peer_id).and_then(|peer| {
+					peer.audio_device_mut(device_id.as_deref(), Some(&AudioDeviceKind::Sink))?
+						.volume = volume.min(100);
+					Ok(peer.audio_devices.clone())
+				});
+				let _ = tx.send(result);
+			}
+			Command::SetDefaultAudioDevice {
+				tx,
+				peer_id,
+				device_id,
+			} => {
+				self.round_trip(&peer_id).await;
+				let result = self.peer_mut(&peer_id).and_then(|peer| {
+					let kind = peer.audio_device_mut(Some(&device_id), None)?.kind.clone();
+					for device in &mut peer.audio_devices {
+						if same_kind(&device.kind, &kind) {
+							device.is_default = device.id == device_id;
+						}
+					}
+					Ok(peer.audio_devices.clone())
+				});
+				let _ = tx.send(result);
+			}
+			Command::MediaCapability { tx, peer_id } => {
+				self.round_trip(&peer_id).await;
+				let result = self.peer(&peer_id).map(|_| MediaCapability {
+					supported: true,
+					backend: Some(String::from("demo")),
+					message: String::from("Simulated screens and cameras"),
+				});
+				let _ = tx.send(result);
+			}
+			Command::ListMediaSources { tx, peer_id } => {
+				self.round_trip(&peer_id).await;
+				let _ = tx.send(self.peer(&peer_id).map(|peer| peer.media_sources.clone()));
+			}
+			Command::GetMediaFrame {
+				tx,
+				peer_id,
+				source_id,
+			} => {
+				self.round_trip(&peer_id).await;
+				self.frames += 1;
+				let frame = self.frames;
+				let result = self.peer(&peer_id).and_then(|peer| {
+					if !peer
+						.media_sources
+						.iter()
+						.any(|source| source.id == source_id)
+					{
+						bail!("media source {source_id} not found");
+					}
+					let seed = blake3::hash(format!("{source_id}{}", frame / 10).as_bytes());
+					Ok(MediaFrame {
+						mime: String::from("image/png"),
+						data: test_pattern(320, 180, seed.as_bytes())?,
+					})
+				});
+				let _ = tx.send(result);
+			}
+			Command::ListFileEntries {
+				peer: _,
+				offset,
+				limit,
+				tx,
+			} => {
+				let result = match self.db.lock() {
+					Ok(conn) => fetch_file_entries_paginated(&conn, offset, limit),
+					Err(_) => Err(anyhow!("db lock poisoned")),
+				};
+				let _ = tx.send(result);
+			}
+			Command::ListStorageFiles { tx } => {
+				let result = match self.db.lock() {
+					Ok(conn) => storage_files(&conn),
+					Err(_) => Err(anyhow!("db lock poisoned")),
+				};
+				let _ = tx.send(result);
+			}
+			Command::ListPermissions {
+				peer,
+				force_refresh: _,
+				tx,
+			} => {
+				self.round_trip(&peer).await;
+				let permissions = if peer == self.state.me {
+					self.state.permissions_for_peer(&peer)
+				} else {
+					self.state
+						.remote_permissions
+						.get(&peer)
+						.cloned()
+						.unwrap_or_default()
+				};
+				let _ = tx.send(Ok(RemoteGrants {
+					permissions,
+					fetched_at: Utc::now(),
+					revalidating: false,
+				}));
+			}
+			Command::GrantPermissions {
+				peer,
+				username,
+				permissions,
+				merge: _,
+				tx,
+			} => {
+				self.round_trip(&peer).await;
+				let result = self.peer(&peer).map(|_| AccessGrantAck {
+					username,
+					permissions,
+				});
+				let _ = tx.send(result);
+			}
+			Command::ReadFile(cmd) => {
+				self.round_trip(&cmd.peer_id).await;
+				let result = self.read_file(&cmd);
+				let _ = cmd.tx.send(result);
+			}
+			Command::Scan {
+				path,
+				tx,
+				cancel_flag,
+				extract_metadata: _,
+				limits: _,
+			} => {
+				let me = self.state.me;
+				if !self
+					.state
+					.has_fs_access(me, Path::new(&path.to_string()), FLAG_READ)
+				{
+					let _ = tx.try_send(ScanEvent::Finished(Err(String::from("Access denied"))));
+					return;
+				}
+				self.start_scan(me, path.to_string(), ScanSink::Local(tx), cancel_flag);
+			}
+			Command::RemoteScan {
+				peer,
+				path,
+				scan_id,
+				limits: _,
+			} => {
+				let Some(tx) = self.remote_scans.remove(scan_id) else {
+					return;
+				};
+				self.start_scan(
+					peer,
+					path.to_string(),
+					ScanSink::Remote(tx),
+					Arc::new(AtomicBool::new(false)),
+				);
+			}
+			Command::PullScanResults { tx, .. } => {
+				// Every demo device's scans land in the one demo index.
+				let _ = tx.send(Err(anyhow!("scan results are already in the demo index")));
+			}
+			Command::LiveSearch {
+				peer,
+				args,
+				search_id,
+			} => {
+				let Some(tx) = self.remote_searches.lock().unwrap().remove(&search_id) else {
+					return;
+				};
+				self.round_trip(&peer).await;
+				self.live_search(&peer, &args, tx);
+			}
+			Command::SearchPeerFiles { peer_id, args, tx } => {
+				self.round_trip(&peer_id).await;
+				let args = peer_search::scope_for_peer(args, peer_to_node_id(&peer_id));
+				let result = match self.db.lock() {
+					Ok(conn) => search_files(&conn, args).map(|(page, _, _)| page.rows),
+					Err(_) => Err(anyhow!("db lock poisoned")),
+				};
+				let _ = tx.send(result);
+			}
+			Command::ShareSummaries { peer_id, tx } => {
+				self.round_trip(&peer_id).await;
+				let _ = tx.send(self.share_summaries(&peer_id));
+			}
+			Command::InvalidateDerived { path: _, tx } => {
+				let _ = tx.send(());
+			}
+			Command::GetThumbnail {
+				peer,
+				path,
+				max_width,
+				max_height,
+				tx,
+			} => {
+				self.round_trip(&peer).await;
+				let _ = tx.send(self.thumbnail(&peer, &path.to_string(), max_width, max_height));
+			}
+			Command::ContactSheet {
+				peer,
+				path,
+				options,
+				tx,
+			} => {
+				self.round_trip(&peer).await;
+				let _ = tx.send(self.contact_sheet(&peer, &path.to_string(), options).await);
+			}
+			Command::RestartPeer {
+				peer,
+				delay_secs,
+				tx,
+			} => {
+				self.round_trip(&peer).await;
+				let result = self.peer(&peer).map(|_| RestartAck { delay_secs });
+				if result.is_ok() {
+					self.restarted_at
+						.insert(peer, Instant::now() + Duration::from_secs(delay_secs));
+				}
+				let _ = tx.send(result);
+			}
+			Command::HealthCheck { peer, tx } => {
+				self.round_trip(&peer).await;
+				let result = self.peer(&peer).map(|demo| PeerHealth {
+					version: version::version_label(),
+					uptime_secs: self.uptime_secs(demo),
+					db_ok: true,
+					listen_addrs: vec![demo.addr.to_string()],
+					pending_requests: 0,
+					last_restart_error: None,
+					pending_dials: 0,
+					queued_dials: 0,
+					dials: Vec::new(),
+					churn: Vec::new(),
+				});
+				let _ = tx.send(result);
+			}
+			Command::SendIndexDelta { tx, .. } => {
+				let _ = tx.send(Err(anyhow!(
+					"index replication is not available in demo mode"
+				)));
+			}
+			Command::RemoteUpdate {
+				peer: _,
+				version: _,
+				update_id,
+			} => {
+				if let Some(tx) = self.remote_updates.remove(update_id) {
+					let _ = tx.send(UpdateProgress::failed(&anyhow!(
+						"updates are not available in demo mode"
+					)));
+				}
+			}
+			Command::InjectDiscoveredPeer { peer, addr, tx } => {
+				self.discovered.record(peer, addr, Utc::now());
+				let _ = tx.send(());
+			}
+			Command::GetState { tx } => {
+				let _ = tx.send(self.state.clone());
+			}
+			Command::QueryDiscoveredPeers { filter, limit, tx } => {
+				let _ = tx.send(self.discovered.query(&filter, limit));
+			}
+			Command::QueryUsers { tx } => {
+				let _ = tx.send(self.users.clone());
+			}
+			Command::SetGrantCacheTtl { ttl, tx } => {
+				let result = if ttl <= chrono::Duration::zero() {
+					Err(anyhow!("Cache lifetime must be positive"))
+				} else {
+					Ok(())
+				};
+				let _ = tx.send(result);
+			}
+			Command::SetDiscoveredAddressTtl { ttl, tx } => {
+				let result = if ttl <= chrono::Duration::zero() {
+					Err(anyhow!("Retention must be positive"))
+				} else {
+					self.discovered.set_ttl(ttl);
+					Ok(())
+				};
+				let _ = tx.send(result);
+			}
+			Command::ReloadStoredState { tx } => {
+				let _ = tx.send(Ok(()));
+			}
+			Command::RegisterSharedFolder { path, flags, tx } => {
+				let result = (|| -> anyhow::Result<_> {
+					let rule = FolderRule::new(path, flags);
+					{
+						let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+						save_shared_folder(&conn, &rule)?;
+					}
+					self.state.share_availability.insert(
+						rule.path().to_path_buf(),
+						ShareAvailability::Available { mount_point: None },
+					);
+					Ok(self.state.add_shared_folder(rule))
+				})();
+				let _ = tx.send(result);
+			}
+			Command::CreateUser {
+				username,
+				password,
+				tx,
+			} => {
+				let result = async {
+					let policy = {
+						let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+						PasswordPolicy::load(&conn)
+					};
+					let name = check_new_user(
+						&policy,
+						self.users.iter().map(String::as_str),
+						&username,
+						&password,
+					)?;
+					let user = hash_new_user(name, password).await?;
+					let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+					save_user(&conn, &user)?;
+					drop(conn);
+					self.users.push(user.name);
+					Ok(())
+				}
+				.await;
+				let _ = tx.send(result);
+			}
+			Command::DeleteUser { username, tx } => {
+				let result = (|| -> anyhow::Result<()> {
+					if username.trim().is_empty() {
+						bail!("Username is required");
+					}
+					if self.users.len() <= 1 {
+						bail!("Cannot delete the last user");
+					}
+					if !self.users.contains(&username) {
+						bail!("User not found");
+					}
+					{
+						let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+						delete_user(&conn, &username)?;
+					}
+					self.users.retain(|name| *name != username);
+					Ok(())
+				})();
+				let _ = tx.send(result);
+			}
+			Command::SetPeerPermissions {
+				peer,
+				permissions,
+				expected_revision,
+				tx,
+			} => {
+				let result = (|| -> anyhow::Result<_> {
+					let me = self.state.me;
+					let mut conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+					save_peer_permissions_at(
+						&mut conn,
+						&me,
+						&peer,
+						&permissions,
+						expected_revision,
+					)?;
+					Ok(self.state.set_peer_permissions(peer, permissions))
+				})();
+				let _ = tx.send(result);
+			}
+			Command::GrantFolderToPeers { folder, peers, tx } => {
+				let me = self.state.me;
+				let mut results = Vec::new();
+				for peer in peers {
+					let existing = self.state.permissions_granted_to_peer(&peer);
+					let outcome = match merge_folder_grant(&existing, &folder) {
+						None => BatchGrantOutcome::Unchanged,
+						Some(permissions) => {
+							let saved = self
+								.db
+								.lock()
+								.map_err(|_| anyhow!("db lock poisoned"))
+								.and_then(|mut conn| {
+									save_peer_permissions_at(
+										&mut conn,
+										&me,
+										&peer,
+										&permissions,
+										None,
+									)
+								});
+							match saved {
+								Ok(_) => {
+									self.state.set_peer_permissions(peer, permissions);
+									BatchGrantOutcome::Granted
+								}
+								Err(err) => BatchGrantOutcome::Failed {
+									error: err.to_string(),
+								},
+							}
+						}
+					};
+					results.push((peer, outcome));
+				}
+				let _ = tx.send(Ok(results));
+			}
+			Command::SetRemoteAccessSuspended { suspended, tx } => {
+				self.state.remote_access_suspended = suspended;
+				let _ = tx.send(Ok(()));
+			}
+			Command::SetMaintenance { maintenance, tx } => {
+				self.state.maintenance = maintenance;
+				let _ = tx.send(Ok(()));
+			}
+			Command::SetNatMapping { enabled, tx } => {
+				self.state.nat = if enabled {
+					NatStatus::Mapped {
+						external_ip: [203, 0, 113, 7].into(),
+						tcp_port: Some(4001),
+						quic_port: Some(4001),
+						method: NatMethod::Upnp,
+						renews_at: Utc::now() + chrono::Duration::hours(1),
+					}
+				} else {
+					NatStatus::Disabled
+				};
+				let _ = tx.send(Ok(()));
+			}
+			Command::SetPeerImportant {
+				peer,
+				important,
+				tx,
+			} => {
+				if important {
+					self.state.important_peers.insert(peer);
+				} else {
+					self.state.important_peers.remove(&peer);
+				}
+				let _ = tx.send(Ok(()));
+			}
+			Command::SetTrustLevel { peer, trust, tx } => {
+				let result = (|| -> anyhow::Result<()> {
+					let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+					save_trust_level(&conn, &peer, trust)?;
+					self.state.set_trust_level(peer, trust);
+					Ok(())
+				})();
+				let _ = tx.send(result);
+			}
+			Command::LimitPeerTraffic { tx, .. } => {
+				let _ = tx.send(Ok(()));
+			}
+			Command::SetPowerState { power } => self.state.power = power,
+			Command::NotifyLocal { message } => {
+				let me = self.state.me;
+				self.state.push_notification(me, message);
+			}
+			Command::TestReachability { tx } => {
+				// The LAN address answers; the public one sits behind a
+				// router that forwards nothing.
+				let tester = &self.fixture.peers[1];
+				let now = Utc::now();
+				let public = Multiaddr::empty()
+					.with(Protocol::Ip4([203, 0, 113, 7].into()))
+					.with(Protocol::Tcp(4001));
+				let results = vec![
+					aggregate(
+						&self.fixture.local().addr,
+						&[(
+							tester.id,
+							DialBackOutcome::Reachable {
+								latency_ms: tester.latency.as_millis() as u64,
+							},
+						)],
+						now,
+					),
+					aggregate(
+						&public,
+						&[(
+							tester.id,
+							DialBackOutcome::Unreachable {
+								error: String::from("timed out"),
+							},
+						)],
+						now,
+					),
+				];
+				self.state.reachability = results.clone();
+				let _ = tx.send(results);
+			}
+			Command::ListGrantedPermissions { peer, tx } => {
+				let result = (|| -> anyhow::Result<PermissionSet> {
+					let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+					Ok(PermissionSet {
+						revision: load_permission_revision(&conn, &self.state.me, &peer)?,
+						permissions: self.state.permissions_granted_to_peer(&peer),
+						temporary: self.state.temporary_grants_for(&peer, Utc::now()),
+					})
+				})();
+				let _ = tx.send(result);
+			}
+			Command::GrantTemporary {
+				peer,
+				path,
+				flags,
+				ttl,
+				tx,
+			} => {
+				let result = (|| -> anyhow::Result<_> {
+					if ttl.is_zero() || ttl > MAX_TEMPORARY_GRANT_TTL {
+						bail!("temporary access must last between a second and a week");
+					}
+					if !self.state.has_fs_access(self.state.me, &path, flags) {
+						bail!(
+							"{} is outside the allowed folders for this access",
+							path.display()
+						);
+					}
+					let expires_at = Utc::now() + ttl;
+					Ok(self
+						.state
+						.grant_temporary(peer, FolderRule::new(path, flags), expires_at))
+				})();
+				let _ = tx.send(result);
+			}
+			Command::RevokeTemporary { peer, id, tx } => {
+				let result = if self.state.revoke_temporary(&peer, id) {
+					Ok(())
+				} else {
+					Err(anyhow!("temporary grant {id} not found"))
+				};
+				let _ = tx.send(result);
+			}
+			Command::SetNodeName { name, tx } => {
+				let result = (|| -> anyhow::Result<String> {
+					let name = normalize_node_name(&name)?;
+					let conn = self.db.lock().map_err(|_| anyhow!("db lock poisoned"))?;
+					save_setting(&conn, NODE_NAME_SETTING, &name)?;
+					self.fixture.peers[0].name = name.clone();
+					if let Some(node) = self.fixture.node(self.fixture.local(), true) {
+						save_node(&conn, &node)?;
+					}
+					Ok(name)
+				})();
+				if let Ok(name) = &result {
+					let me = self.state.me;
+					if let Some(peer) = self.state.peers.iter_mut().find(|peer| peer.id == me) {
+						peer.name = Some(name.clone());
+					}
+				}
+				let _ = tx.send(result);
+			}
+			Command::StartPairing { peer, tx } => {
+				if peer == self.state.me {
+					let _ = tx.send(Err(anyhow!("cannot pair this device with itself")));
+					return;
+				}
+				let pairing = Pairing::new(
+					&self.state.me,
+					peer,
+					String::new(),
+					PairingDirection::Outgoing,
+					Utc::now(),
+				);
+				self.state.pairings.insert(peer, pairing.clone());
+				let events = self.events_tx.clone();
+				tokio::spawn(async move {
+					tokio::time::sleep(PAIRING_DELAY).await;
+					let _ = events.send(DemoEvent::PairingAccepted { peer });
+				});
+				let _ = tx.send(Ok(pairing));
+			}
+			Command::AnswerPairing { peer, accept, tx } => {
+				let Some(pairing) = self
+					.state
+					.pairings
+					.get_mut(&peer)
+					.filter(|pairing| pairing.status == PairingStatus::AwaitingYou)
+				else {
+					let _ = tx.send(Err(anyhow!("no pairing request from {peer} is waiting")));
+					return;
+				};
+				pairing.status = if accept {
+					PairingStatus::Paired
+				} else {
+					PairingStatus::Declined
+				};
+				let _ = tx.send(Ok(()));
+			}
+			Command::PairingInfo { tx } => {
+				let _ = tx.send(Err(anyhow!("the demo doesn't issue pairing strings")));
+			}
+			Command::PairWith { tx, .. } => {
+				let _ = tx.send(Err(anyhow!("the demo can't pair over pairing strings")));
+			}
+			Command::GetLocalPeerId { tx } => {
+				let _ = tx.send(self.state.me);
+			}
+			Command::StartShell {
+				peer,
+				session_id,
+				tx,
+			} => {
+				self.round_trip(&peer).await;
+				let _ = tx.send(self.peer(&peer).map(|_| session_id));
+			}
+			Command::ShellInput {
+				peer,
+				session_id: _,
+				data,
+				tx,
+			} => {
+				self.round_trip(&peer).await;
+				let result = self.peer(&peer).map(|demo| {
+					let input = String::from_utf8_lossy(&data);
+					if !input.contains(['\n', '\r']) {
+						return ShellOutput::Output(Vec::new());
+					}
+					let mut output = String::new();
+					for line in input.lines().map(str::trim).filter(|line| !line.is_empty()) {
+						output.push_str(&format!("{line}: not available in demo mode\r\n"));
+					}
+					output.push_str(&format!(
+						"{}@{}:~$ ",
+						demo.home.rsplit('/').next().unwrap_or(""),
+						demo.name
+					));
+					ShellOutput::Output(output.into_bytes())
+				});
+				let _ = tx.send(result);
+			}
+			Command::CloseShell {
+				peer,
+				session_id: _,
+				tx,
+			} => {
+				let _ = tx.send(self.peer(&peer).map(|_| ShellOutput::Exited));
+			}
+			Command::DesktopInput { peer, input: _, tx } => {
+				let _ = tx.send(self.peer(&peer).map(|_| ()));
+			}
+			Command::OpenInbox {
+				peer,
+				name,
+				size: _,
+				tx,
+			} => {
+				self.round_trip(&peer).await;
+				let result = self.peer(&peer).map(|_| InboxSlot { path: name });
+				let _ = tx.send(result);
+			}
+			Command::WriteFile {
+				peer,
+				path: _,
+				offset: _,
+				data,
+				tx,
+			}
+			| Command::WriteInbox {
+				peer,
+				path: _,
+				offset: _,
+				data,
+				tx,
+			} => {
+				self.round_trip(&peer).await;
+				let result = self.peer(&peer).map(|_| FileWriteAck {
+					bytes_written: data.len() as u64,
+				});
+				let _ = tx.send(result);
+			}
+			Command::HaveHashes { tx, .. } | Command::HaveBlocks { tx, .. } => {
+				let _ = tx.send(Err(anyhow!("demo peers don't say which files they have")));
+			}
+			Command::WriteKnownBlock { tx, .. } => {
+				let _ = tx.send(Err(anyhow!("demo peers don't say which files they have")));
+			}
+			Command::RecordTransfer { transfer } => {
+				if let Ok(conn) = self.db.lock()
+					&& let Err(err) = record_transfer(&conn, &transfer)
+				{
+					tracing::error!("failed to record transfer: {err}");
+				}
+			}
+			// Demo peers are never sent deletion proposals.
+			Command::FlushDeleteOutbox { .. } => {}
+		}
+	}
+
+	/// Handles the next command or simulation event.
+	pub(crate) async fn run(&mut self) {
+		tokio::select! {
+			cmd = self.rx.recv() => {
+				if let Some(cmd) = cmd {
+					self.handle_cmd(cmd).await;
+				}
+			}
+			event = self.events_rx.recv() => {
+				if let Some(event) = event {
+					self.handle_event(event);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn now() -> DateTime<Utc> {
+		"2026-03-14T08:30:00Z".parse().unwrap()
+	}
+
+	#[test]
+	fn the_same_seed_makes_the_same_fixture() {
+		let (a, b) = (
+			DemoFixture::generate(7, now()),
+			DemoFixture::generate(7, now()),
+		);
+		assert_eq!(
+			a.peers.iter().map(|peer| peer.id).collect::<Vec<_>>(),
+			b.peers.iter().map(|peer| peer.id).collect::<Vec<_>>()
+		);
+		assert_eq!(a.peers[2].files, b.peers[2].files);
+		let other = DemoFixture::generate(8, now());
+		assert_ne!(a.peers[0].id, other.peers[0].id);
+	}
+
+	#[test]
+	fn remote_devices_hold_copies_of_local_files() {
+		let fixture = DemoFixture::generate(3, now());
+		let local = fixture
+			.local()
+			.files
+			.iter()
+			.map(|file| file.hash)
+			.collect::<HashSet<_>>();
+		for remote in &fixture.peers[1..] {
+			assert!(remote.files.iter().any(|file| local.contains(&file.hash)));
+			let paths = remote
+				.files
+				.iter()
+				.map(|file| &file.path)
+				.collect::<HashSet<_>>();
+			assert_eq!(paths.len(), remote.files.len());
+		}
+	}
+
+	#[test]
+	fn listings_show_folders_and_files_below_a_path() {
+		let fixture = DemoFixture::generate(3, now());
+		let local = fixture.local();
+		let home = local.list_dir(&local.home).unwrap();
+		assert!(
+			home.iter()
+				.any(|entry| entry.is_dir && entry.name == "Photos")
+		);
+		let root = local.list_dir("/").unwrap();
+		assert!(root.iter().any(|entry| entry.is_dir && entry.name == "mnt"));
+		assert!(local.list_dir("/nowhere").is_err());
+		let file = &local.files[0];
+		let chunk = file.read(0, 64);
+		assert_eq!(chunk.data.len() as u64, file.size.min(64));
+		assert_eq!(local.stat(&file.path).unwrap().size, file.size);
+	}
+
+	#[test]
+	fn the_database_holds_the_index_and_the_demo_user() {
+		let fixture = DemoFixture::generate(3, now());
+		let mut conn = SqliteConnection::open_in_memory().unwrap();
+		fixture.populate(&mut conn).unwrap();
+		let user = crate::db::load_user(&conn, DEMO_USERNAME).unwrap().unwrap();
+		assert!(auth::verify_password(DEMO_PASSWORD, &user.passw).unwrap());
+		assert!(!storage_files(&conn).unwrap().is_empty());
+	}
+}
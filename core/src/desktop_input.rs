@@ -80,7 +80,7 @@ fn mouse_move_worker() {
 			continue;
 		};
 		if let Err(err) = run_mouse_move(next.dx, next.dy) {
-			log::warn!("failed to move mouse: {err:#}");
+			tracing::warn!("failed to move mouse: {err:#}");
 		}
 	}
 }
@@ -129,7 +129,7 @@ fn run_mouse_move(dx: i32, dy: i32) -> Result<()> {
 	match uinput_mouse::move_relative(dx, dy) {
 		Ok(()) => Ok(()),
 		Err(err) => {
-			log::warn!("uinput mouse move failed, falling back to xdotool: {err:#}");
+			tracing::warn!("uinput mouse move failed, falling back to xdotool: {err:#}");
 			run_xdotool(["mousemove_relative", "--", &dx.to_string(), &dy.to_string()])
 		}
 	}
@@ -138,7 +138,7 @@ fn run_mouse_move(dx: i32, dy: i32) -> Result<()> {
 fn scroll_mouse(amount: i32) -> Result<()> {
 	match uinput_mouse::scroll(amount) {
 		Ok(()) => return Ok(()),
-		Err(err) => log::warn!("uinput mouse scroll failed, falling back to xdotool: {err:#}"),
+		Err(err) => tracing::warn!("uinput mouse scroll failed, falling back to xdotool: {err:#}"),
 	}
 	let button = if amount < 0 { "4" } else { "5" };
 	for _ in 0..amount.unsigned_abs().min(20) {
@@ -151,7 +151,7 @@ fn click_mouse(button: MouseButton) -> Result<()> {
 	wait_for_mouse_moves();
 	match uinput_mouse::click(button) {
 		Ok(()) => return Ok(()),
-		Err(err) => log::warn!("uinput mouse click failed, falling back to ydotool: {err:#}"),
+		Err(err) => tracing::warn!("uinput mouse click failed, falling back to ydotool: {err:#}"),
 	}
 	match run_ydotool(["click", ydotool_mouse_button_arg(button)]) {
 		Ok(()) => Ok(()),
@@ -167,7 +167,7 @@ fn press_mouse(button: MouseButton) -> Result<()> {
 	match uinput_mouse::press(button) {
 		Ok(()) => Ok(()),
 		Err(err) => {
-			log::warn!("uinput mouse press failed, falling back to xdotool: {err:#}");
+			tracing::warn!("uinput mouse press failed, falling back to xdotool: {err:#}");
 			run_xdotool(["mousedown", xdotool_mouse_button_arg(button)])
 		}
 	}
@@ -178,7 +178,7 @@ fn release_mouse(button: MouseButton) -> Result<()> {
 	match uinput_mouse::release(button) {
 		Ok(()) => Ok(()),
 		Err(err) => {
-			log::warn!("uinput mouse release failed, falling back to xdotool: {err:#}");
+			tracing::warn!("uinput mouse release failed, falling back to xdotool: {err:#}");
 			run_xdotool(["mouseup", xdotool_mouse_button_arg(button)])
 		}
 	}
@@ -190,7 +190,7 @@ fn type_text(text: &str) -> Result<()> {
 	}
 	match uinput_mouse::type_text(text) {
 		Ok(()) => return Ok(()),
-		Err(err) => log::warn!("uinput keyboard type failed, falling back to ydotool: {err:#}"),
+		Err(err) => tracing::warn!("uinput keyboard type failed, falling back to ydotool: {err:#}"),
 	}
 	match run_ydotool_stdin(
 		["type", "--delay", "0", "--key-delay", "0", "--file", "-"],
@@ -211,7 +211,7 @@ fn press_key(key: &str) -> Result<()> {
 	}
 	match uinput_mouse::press_key(key) {
 		Ok(()) => return Ok(()),
-		Err(err) => log::warn!("uinput keyboard key failed, falling back to ydotool: {err:#}"),
+		Err(err) => tracing::warn!("uinput keyboard key failed, falling back to ydotool: {err:#}"),
 	}
 	match ydotool_key_sequence(key) {
 		Some([down, up]) => match run_ydotool(["key", down, up]) {
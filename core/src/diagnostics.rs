@@ -0,0 +1,615 @@
+//! `puppynet doctor`: independent checks of what a node needs from its
+//! environment, each ending in pass, warn or fail with a hint on how to fix
+//! it. Everything is probed read-only: a missing keypair or database is
+//! reported rather than created, and the bind test lets go of the port
+//! right away.
+
+use crate::app::available_space_for;
+use crate::backup::verify_database;
+use crate::clock_skew::{CLOCK_SKEW_WARN_SECS, ClockSample};
+use crate::db::{db_location, load_shared_folders};
+use crate::format::{SizeUnits, abbrev_peer_id, human_duration, human_size};
+use crate::http_proxy::HttpClient;
+use crate::mounts::{MountTable, ShareAvailability};
+use crate::p2p::{is_quic_addr, keypair_path, listen_addrs};
+use crate::state::FolderRule;
+use chrono::{DateTime, Utc};
+use libp2p::identity::Keypair;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The updater talks to the same host.
+const HTTPS_PROBE_URL: &str = "https://api.github.com/";
+const HTTPS_PROBE_HOST: &str = "api.github.com";
+const HTTPS_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+/// Free space below which the database volume is flagged.
+const DB_MIN_FREE_SPACE: u64 = 512 * 1024 * 1024;
+/// Clocks further off than this expire grants and pairings early or late.
+const CLOCK_SKEW_FAIL_SECS: i64 = 300;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticStatus {
+	Pass,
+	Warn,
+	Fail,
+}
+
+impl DiagnosticStatus {
+	pub fn label(self) -> &'static str {
+		match self {
+			Self::Pass => "pass",
+			Self::Warn => "warn",
+			Self::Fail => "fail",
+		}
+	}
+}
+
+/// Outcome of one check.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+	/// What was checked, e.g. `keypair` or `shared folder`.
+	pub check: String,
+	pub status: DiagnosticStatus,
+	pub detail: String,
+	/// One line on how to fix it; `None` on a pass.
+	pub hint: Option<String>,
+}
+
+impl Diagnostic {
+	fn pass(check: &str, detail: impl Into<String>) -> Self {
+		Self {
+			check: check.to_string(),
+			status: DiagnosticStatus::Pass,
+			detail: detail.into(),
+			hint: None,
+		}
+	}
+
+	fn warn(check: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+		Self {
+			check: check.to_string(),
+			status: DiagnosticStatus::Warn,
+			detail: detail.into(),
+			hint: Some(hint.into()),
+		}
+	}
+
+	fn fail(check: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+		Self {
+			check: check.to_string(),
+			status: DiagnosticStatus::Fail,
+			detail: detail.into(),
+			hint: Some(hint.into()),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiagnosticsReport {
+	pub ran_at: DateTime<Utc>,
+	pub checks: Vec<Diagnostic>,
+}
+
+impl DiagnosticsReport {
+	pub fn count(&self, status: DiagnosticStatus) -> usize {
+		self.checks
+			.iter()
+			.filter(|check| check.status == status)
+			.count()
+	}
+
+	pub fn has_failures(&self) -> bool {
+		self.count(DiagnosticStatus::Fail) > 0
+	}
+}
+
+/// Whether this process may write `path`, which has to exist. A read-only
+/// mount counts as not writable.
+#[cfg(target_os = "linux")]
+fn is_writable(path: &Path) -> bool {
+	use std::os::unix::ffi::OsStrExt;
+
+	let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+		return false;
+	};
+	unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_writable(path: &Path) -> bool {
+	std::fs::metadata(path).is_ok_and(|meta| !meta.permissions().readonly())
+}
+
+/// Closest directory above `path` that exists, where a missing file would
+/// be created.
+fn existing_parent(path: &Path) -> PathBuf {
+	path.ancestors()
+		.skip(1)
+		.map(|dir| {
+			if dir.as_os_str().is_empty() {
+				Path::new(".")
+			} else {
+				dir
+			}
+		})
+		.find(|dir| dir.is_dir())
+		.unwrap_or(Path::new("."))
+		.to_path_buf()
+}
+
+fn check_keypair(path: &Path) -> Diagnostic {
+	const CHECK: &str = "keypair";
+	match std::fs::read(path) {
+		Ok(bytes) => match Keypair::from_protobuf_encoding(&bytes) {
+			Ok(key) => {
+				let peer_id = PeerId::from(key.public()).to_string();
+				let mut detail = format!(
+					"{}, fingerprint {}",
+					path.display(),
+					abbrev_peer_id(&peer_id)
+				);
+				if !is_writable(path) {
+					detail.push_str(", read-only");
+				}
+				Diagnostic::pass(CHECK, detail)
+			}
+			Err(err) => Diagnostic::fail(
+				CHECK,
+				format!("{} is not a valid keypair: {err}", path.display()),
+				"Restore the keypair from a backup; removing it gives this node a new identity.",
+			),
+		},
+		Err(err) if err.kind() == ErrorKind::NotFound => {
+			let dir = existing_parent(path);
+			if is_writable(&dir) {
+				Diagnostic::warn(
+					CHECK,
+					format!(
+						"no keypair at {}; a new identity is generated on start",
+						path.display()
+					),
+					"If this node had an identity before, restore its keypair or point KEYPAIR at it.",
+				)
+			} else {
+				Diagnostic::fail(
+					CHECK,
+					format!(
+						"no keypair at {} and {} is not writable",
+						path.display(),
+						dir.display()
+					),
+					"Point KEYPAIR at a writable location.",
+				)
+			}
+		}
+		Err(err) => Diagnostic::fail(
+			CHECK,
+			format!("failed to read {}: {err}", path.display()),
+			"Check the owner and permissions of the keypair file.",
+		),
+	}
+}
+
+fn check_database(path: &Path) -> Diagnostic {
+	const CHECK: &str = "database";
+	let meta = match std::fs::metadata(path) {
+		Ok(meta) => meta,
+		Err(err) if err.kind() == ErrorKind::NotFound => {
+			let dir = existing_parent(path);
+			return if is_writable(&dir) {
+				Diagnostic::warn(
+					CHECK,
+					format!(
+						"no database at {}; an empty one is created on start",
+						path.display()
+					),
+					"If this node had a database before, restore it with `puppynet restore` or point DB at it.",
+				)
+			} else {
+				Diagnostic::fail(
+					CHECK,
+					format!(
+						"no database at {} and {} is not writable",
+						path.display(),
+						dir.display()
+					),
+					"Point DB at a writable location.",
+				)
+			};
+		}
+		Err(err) => {
+			return Diagnostic::fail(
+				CHECK,
+				format!("failed to read {}: {err}", path.display()),
+				"Check the owner and permissions of the database file.",
+			);
+		}
+	};
+	if let Err(err) = verify_database(path) {
+		return Diagnostic::fail(
+			CHECK,
+			format!("{err:#}"),
+			"Restore a backup with `puppynet restore`.",
+		);
+	}
+	let dir = existing_parent(path);
+	if !is_writable(path) || !is_writable(&dir) {
+		return Diagnostic::fail(
+			CHECK,
+			format!(
+				"{} can't be written; its volume is read-only or the permissions forbid it",
+				path.display()
+			),
+			"Fix the permissions, or point DB at a writable location.",
+		);
+	}
+	let mut detail = format!(
+		"{}, {}",
+		path.display(),
+		human_size(meta.len(), SizeUnits::Binary)
+	);
+	let free = std::fs::canonicalize(path)
+		.ok()
+		.and_then(|path| available_space_for(&path));
+	if let Some(free) = free {
+		detail.push_str(&format!(", {} free", human_size(free, SizeUnits::Binary)));
+		if free < DB_MIN_FREE_SPACE {
+			return Diagnostic::warn(
+				CHECK,
+				detail,
+				"Free up space on that volume; scans stop writing once it is full.",
+			);
+		}
+	}
+	Diagnostic::pass(CHECK, detail)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Transport {
+	Tcp,
+	Udp,
+}
+
+/// The socket a listen address binds, if it names an IP and a port.
+fn listen_socket(addr: &Multiaddr) -> Option<(Transport, SocketAddr)> {
+	let mut ip = None;
+	let mut port = None;
+	for protocol in addr.iter() {
+		match protocol {
+			Protocol::Ip4(v4) => ip = Some(IpAddr::V4(v4)),
+			Protocol::Ip6(v6) => ip = Some(IpAddr::V6(v6)),
+			Protocol::Tcp(tcp) => port = Some((Transport::Tcp, tcp)),
+			Protocol::Udp(udp) => port = Some((Transport::Udp, udp)),
+			_ => {}
+		}
+	}
+	let (transport, port) = port?;
+	Some((transport, SocketAddr::new(ip?, port)))
+}
+
+/// Binds the listen address and lets go of it again. Ports a running node
+/// already holds, given in `own`, are fine.
+fn check_listen(addr: &Multiaddr, own: &[Multiaddr]) -> Diagnostic {
+	const CHECK: &str = "listen";
+	let variable = if is_quic_addr(addr) {
+		"PUPPYNET_LISTEN_QUIC"
+	} else {
+		"PUPPYNET_LISTEN_TCP"
+	};
+	let Some((transport, socket)) = listen_socket(addr) else {
+		return Diagnostic::warn(
+			CHECK,
+			format!("{addr} was not tested"),
+			format!("Set {variable} to an /ip4 or /ip6 address with a port."),
+		);
+	};
+	if socket.port() == 0 {
+		return Diagnostic::pass(CHECK, format!("{addr}, any free port"));
+	}
+	let held = own
+		.iter()
+		.filter_map(listen_socket)
+		.any(|(held, bound)| held == transport && bound.port() == socket.port());
+	if held {
+		return Diagnostic::pass(CHECK, format!("{addr}, in use by this node"));
+	}
+	let bound = match transport {
+		Transport::Tcp => TcpListener::bind(socket).map(drop),
+		Transport::Udp => UdpSocket::bind(socket).map(drop),
+	};
+	match bound {
+		Ok(()) => Diagnostic::pass(CHECK, format!("{addr}, port {} is free", socket.port())),
+		Err(err) if err.kind() == ErrorKind::AddrInUse => Diagnostic::fail(
+			CHECK,
+			format!("{addr}: port {} is taken by another program", socket.port()),
+			format!("Stop the other program or pick another port with {variable}."),
+		),
+		Err(err) if err.kind() == ErrorKind::PermissionDenied => Diagnostic::fail(
+			CHECK,
+			format!("{addr}: not allowed to bind port {}", socket.port()),
+			format!("Pick a port above 1023 with {variable}."),
+		),
+		Err(err) if err.kind() == ErrorKind::AddrNotAvailable => Diagnostic::fail(
+			CHECK,
+			format!("{addr}: {} is not an address of this machine", socket.ip()),
+			format!("Use one of this machine's addresses, or 0.0.0.0, in {variable}."),
+		),
+		Err(err) => Diagnostic::fail(
+			CHECK,
+			format!("{addr}: {err}"),
+			format!("Pick another address with {variable}."),
+		),
+	}
+}
+
+/// Joins the mDNS group on a throwaway socket; it fails where no interface
+/// routes multicast.
+fn check_multicast() -> Diagnostic {
+	const CHECK: &str = "multicast";
+	let joined = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).and_then(|socket| {
+		socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+		socket.leave_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)
+	});
+	match joined {
+		Ok(()) => Diagnostic::pass(CHECK, format!("joined the mDNS group {MDNS_GROUP}")),
+		Err(err) => Diagnostic::warn(
+			CHECK,
+			format!("can't join the mDNS group {MDNS_GROUP}: {err}"),
+			"Nearby peers won't be discovered; allow multicast on the network or add peers by address.",
+		),
+	}
+}
+
+/// Requests the updater's host. The response's `Date` header is kept for
+/// [`check_clock`].
+async fn check_https() -> (Diagnostic, Option<ClockSample>) {
+	const CHECK: &str = "https";
+	let sent = Utc::now();
+	let response = match HttpClient::new() {
+		Ok(client) => {
+			client
+				.send(client.get(HTTPS_PROBE_URL).timeout(HTTPS_PROBE_TIMEOUT))
+				.await
+		}
+		Err(err) => Err(err),
+	};
+	let received = Utc::now();
+	match response {
+		Ok(response) => {
+			let sample = response
+				.headers()
+				.get(reqwest::header::DATE)
+				.and_then(|date| date.to_str().ok())
+				.and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+				.map(|date| ClockSample::new(sent, received, date.with_timezone(&Utc)));
+			let detail = format!(
+				"reached {HTTPS_PROBE_HOST} in {} ms (HTTP status: {})",
+				(received - sent).num_milliseconds(),
+				response.status().as_u16()
+			);
+			(Diagnostic::pass(CHECK, detail), sample)
+		}
+		Err(err) => (
+			Diagnostic::warn(
+				CHECK,
+				format!("{err:#}"),
+				"Updates can't be downloaded; check the firewall and the HTTP proxy setting.",
+			),
+			None,
+		),
+	}
+}
+
+fn check_clock(sample: Option<ClockSample>) -> Diagnostic {
+	const CHECK: &str = "clock";
+	const HINT: &str =
+		"Turn on time synchronization (NTP); grants and pairings expire by this clock.";
+	let Some(sample) = sample else {
+		return Diagnostic::warn(
+			CHECK,
+			format!("not checked; no date from {HTTPS_PROBE_HOST}"),
+			HINT,
+		);
+	};
+	// The header only has whole seconds.
+	let skew_secs = -sample.offset_ms / 1000;
+	if skew_secs.abs() < CLOCK_SKEW_WARN_SECS {
+		return Diagnostic::pass(CHECK, format!("within a minute of {HTTPS_PROBE_HOST}"));
+	}
+	let detail = format!(
+		"{} {} {HTTPS_PROBE_HOST}",
+		human_duration(Duration::from_secs(skew_secs.unsigned_abs())),
+		if skew_secs > 0 { "ahead of" } else { "behind" }
+	);
+	if skew_secs.abs() < CLOCK_SKEW_FAIL_SECS {
+		Diagnostic::warn(CHECK, detail, HINT)
+	} else {
+		Diagnostic::fail(CHECK, detail, HINT)
+	}
+}
+
+fn check_shared_folder(folder: &FolderRule, mounts: &MountTable) -> Diagnostic {
+	const CHECK: &str = "shared folder";
+	let path = folder.path();
+	match mounts.availability(path) {
+		ShareAvailability::NotMounted { mount_point } => {
+			return Diagnostic::fail(
+				CHECK,
+				format!(
+					"{}: {} is not mounted",
+					path.display(),
+					mount_point.display()
+				),
+				format!(
+					"Mount {} or stop sharing the folder.",
+					mount_point.display()
+				),
+			);
+		}
+		ShareAvailability::Missing => {
+			return Diagnostic::fail(
+				CHECK,
+				format!("{}: folder is missing", path.display()),
+				"Recreate the folder or stop sharing it.",
+			);
+		}
+		ShareAvailability::Available { .. } => {}
+	}
+	if !path.is_dir() {
+		return Diagnostic::fail(
+			CHECK,
+			format!("{} is not a folder", path.display()),
+			"Share the folder that contains it instead.",
+		);
+	}
+	if let Err(err) = std::fs::read_dir(path) {
+		return Diagnostic::fail(
+			CHECK,
+			format!("failed to list {}: {err}", path.display()),
+			"Give the user running puppynet read access to the folder.",
+		);
+	}
+	if folder.can_write() && !is_writable(path) {
+		return Diagnostic::warn(
+			CHECK,
+			format!("{} is shared for writing but is read-only", path.display()),
+			"Fix the folder's permissions or share it read-only.",
+		);
+	}
+	Diagnostic::pass(CHECK, path.display().to_string())
+}
+
+/// Shared folders as stored in the database at `db`, read without the
+/// node that owns it.
+fn check_shared_folders(db: &Path) -> Vec<Diagnostic> {
+	const CHECK: &str = "shared folder";
+	if !db.exists() {
+		return vec![Diagnostic::pass(CHECK, "no folders shared")];
+	}
+	let folders = Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY)
+		.map_err(anyhow::Error::from)
+		.and_then(|conn| load_shared_folders(&conn));
+	let folders = match folders {
+		Ok(folders) => folders,
+		Err(err) => {
+			return vec![Diagnostic::warn(
+				CHECK,
+				format!("failed to read the shared folders: {err:#}"),
+				"Fix the database first.",
+			)];
+		}
+	};
+	if folders.is_empty() {
+		return vec![Diagnostic::pass(CHECK, "no folders shared")];
+	}
+	let mounts = MountTable::load();
+	folders
+		.iter()
+		.map(|folder| check_shared_folder(folder, &mounts))
+		.collect()
+}
+
+/// Runs every check against the database at `db`. `own_listeners` are the
+/// addresses a running node holds, which the bind test leaves alone.
+pub(crate) async fn run(db: PathBuf, own_listeners: Vec<Multiaddr>) -> DiagnosticsReport {
+	let ran_at = Utc::now();
+	let local = tokio::task::spawn_blocking(move || {
+		let mut checks = vec![check_keypair(&keypair_path()), check_database(&db)];
+		checks.extend(
+			listen_addrs()
+				.iter()
+				.map(|addr| check_listen(addr, &own_listeners)),
+		);
+		checks.push(check_multicast());
+		(checks, check_shared_folders(&db))
+	});
+	let ((https, sample), local) = tokio::join!(check_https(), local);
+	let (mut checks, folders) = local.unwrap_or_else(|err| {
+		let failed = Diagnostic::fail(
+			"diagnostics",
+			format!("local checks did not finish: {err}"),
+			"Run `puppynet doctor` again and report the error if it persists.",
+		);
+		(vec![failed], Vec::new())
+	});
+	checks.push(https);
+	checks.push(check_clock(sample));
+	checks.extend(folders);
+	DiagnosticsReport { ran_at, checks }
+}
+
+/// Checks the environment for a node that isn't running in this process,
+/// e.g. before one is started.
+pub async fn run_diagnostics() -> DiagnosticsReport {
+	run(db_location(), Vec::new()).await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::test_dir;
+
+	#[test]
+	fn keypairs_are_read_and_never_created() {
+		let dir = test_dir("doctor-keypair");
+		let path = dir.join("peer_keypair.bin");
+
+		let missing = check_keypair(&path);
+		assert_eq!(missing.status, DiagnosticStatus::Warn);
+		assert!(!path.exists());
+
+		let key = Keypair::generate_ed25519();
+		std::fs::write(&path, key.to_protobuf_encoding().unwrap()).unwrap();
+		let found = check_keypair(&path);
+		assert_eq!(found.status, DiagnosticStatus::Pass);
+		let fingerprint = abbrev_peer_id(&PeerId::from(key.public()).to_string());
+		assert!(found.detail.contains(&fingerprint), "{}", found.detail);
+
+		std::fs::write(&path, b"not a key").unwrap();
+		assert_eq!(check_keypair(&path).status, DiagnosticStatus::Fail);
+	}
+
+	#[test]
+	fn taken_ports_fail_unless_this_node_holds_them() {
+		let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = taken.local_addr().unwrap().port();
+		let addr: Multiaddr = format!("/ip4/127.0.0.1/tcp/{port}").parse().unwrap();
+
+		let check = check_listen(&addr, &[]);
+		assert_eq!(check.status, DiagnosticStatus::Fail);
+		assert!(check.hint.unwrap().contains("PUPPYNET_LISTEN_TCP"));
+
+		let own: Multiaddr = format!("/ip4/192.168.1.5/tcp/{port}").parse().unwrap();
+		assert_eq!(check_listen(&addr, &[own]).status, DiagnosticStatus::Pass);
+
+		drop(taken);
+		assert_eq!(check_listen(&addr, &[]).status, DiagnosticStatus::Pass);
+		let any: Multiaddr = "/ip4/0.0.0.0/tcp/0".parse().unwrap();
+		assert_eq!(check_listen(&any, &[]).status, DiagnosticStatus::Pass);
+	}
+
+	#[test]
+	fn clock_skew_warns_then_fails() {
+		let server = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+		let sample = |local_offset_secs: i64| {
+			let local = server + chrono::Duration::seconds(local_offset_secs);
+			Some(ClockSample::new(local, local, server))
+		};
+		assert_eq!(check_clock(sample(2)).status, DiagnosticStatus::Pass);
+		let ahead = check_clock(sample(120));
+		assert_eq!(ahead.status, DiagnosticStatus::Warn);
+		assert!(ahead.detail.contains("ahead of"), "{}", ahead.detail);
+		let behind = check_clock(sample(-3600));
+		assert_eq!(behind.status, DiagnosticStatus::Fail);
+		assert!(behind.detail.contains("behind"), "{}", behind.detail);
+		assert_eq!(check_clock(None).status, DiagnosticStatus::Warn);
+	}
+}
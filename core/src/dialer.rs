@@ -0,0 +1,338 @@
+//! Dials to peers found by mDNS. Announcements come in bursts and may
+//! carry addresses this node cannot reach, so addresses are filtered
+//! before dialing, each peer has at most one dial in flight, and no more
+//! than [`MAX_PENDING_DIALS`] run at once while the rest wait in a queue.
+
+use crate::p2p::{AgentBehaviour, dial_opts};
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::DialError;
+use libp2p::{Multiaddr, PeerId, Swarm};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::Ipv6Addr;
+
+pub(crate) const MAX_PENDING_DIALS: usize = 8;
+/// Queued dials beyond this drop the oldest; the peer announces again.
+const DIAL_QUEUE_LIMIT: usize = 64;
+
+/// Outcomes of outgoing dials to one peer since this node started.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerDialStats {
+	pub peer: String,
+	pub succeeded: u64,
+	pub failed: u64,
+	pub last_error: Option<String>,
+}
+
+fn is_link_local_v6(ip: &Ipv6Addr) -> bool {
+	ip.segments()[0] & 0xffc0 == 0xfe80
+}
+
+fn without_peer_id(addr: &Multiaddr) -> Multiaddr {
+	addr.iter()
+		.filter(|protocol| !matches!(protocol, Protocol::P2p(_)))
+		.collect()
+}
+
+/// Whether this node has a transport for `addr` and it points somewhere
+/// other than one of `own` listen addresses. Link-local IPv6 is refused
+/// since it needs a zone id, which the TCP and QUIC transports can't take.
+pub(crate) fn is_dialable(addr: &Multiaddr, own: &[Multiaddr]) -> bool {
+	let mut protocols = addr.iter();
+	let host_ok = match protocols.next() {
+		Some(Protocol::Ip4(ip)) => !ip.is_unspecified() && !ip.is_multicast() && !ip.is_broadcast(),
+		Some(Protocol::Ip6(ip)) => {
+			!ip.is_unspecified() && !ip.is_multicast() && !is_link_local_v6(&ip)
+		}
+		_ => false,
+	};
+	let transport_ok = match protocols.next() {
+		Some(Protocol::Tcp(port)) => port != 0,
+		Some(Protocol::Udp(port)) => {
+			port != 0
+				&& cfg!(feature = "quic")
+				&& matches!(protocols.next(), Some(Protocol::QuicV1 | Protocol::Quic))
+		}
+		_ => false,
+	};
+	if !host_ok || !transport_ok {
+		return false;
+	}
+	let addr = without_peer_id(addr);
+	!own.iter().any(|own| without_peer_id(own) == addr)
+}
+
+#[derive(Default)]
+pub(crate) struct Dialer {
+	pending: HashSet<PeerId>,
+	queue: VecDeque<(PeerId, Vec<Multiaddr>)>,
+	stats: HashMap<PeerId, PeerDialStats>,
+}
+
+impl Dialer {
+	/// Addresses to dial `peer` at right now. `None` when none is
+	/// dialable or the peer is already being dialed, and also when the cap
+	/// is reached, in which case the dial waits in the queue.
+	pub(crate) fn admit(
+		&mut self,
+		peer: PeerId,
+		addrs: Vec<Multiaddr>,
+		own: &[Multiaddr],
+	) -> Option<Vec<Multiaddr>> {
+		let addrs = addrs
+			.into_iter()
+			.filter(|addr| {
+				let dialable = is_dialable(addr, own);
+				if !dialable {
+					tracing::debug!("not dialing {peer} at undialable {addr}");
+				}
+				dialable
+			})
+			.collect::<Vec<_>>();
+		if addrs.is_empty() || self.pending.contains(&peer) {
+			return None;
+		}
+		if let Some((_, queued)) = self.queue.iter_mut().find(|(queued, _)| *queued == peer) {
+			queued.extend(addrs);
+			return None;
+		}
+		if self.pending.len() >= MAX_PENDING_DIALS {
+			if self.queue.len() >= DIAL_QUEUE_LIMIT {
+				self.queue.pop_front();
+			}
+			self.queue.push_back((peer, addrs));
+			return None;
+		}
+		self.pending.insert(peer);
+		Some(addrs)
+	}
+
+	/// The next queued dial, if a slot is free, already counted as pending.
+	fn next_queued(&mut self) -> Option<(PeerId, Vec<Multiaddr>)> {
+		if self.pending.len() >= MAX_PENDING_DIALS {
+			return None;
+		}
+		let (peer, addrs) = self.queue.pop_front()?;
+		self.pending.insert(peer);
+		Some((peer, addrs))
+	}
+
+	fn stats_mut(&mut self, peer: PeerId) -> &mut PeerDialStats {
+		self.stats.entry(peer).or_insert_with(|| PeerDialStats {
+			peer: peer.to_string(),
+			..PeerDialStats::default()
+		})
+	}
+
+	pub(crate) fn succeeded(&mut self, peer: PeerId) -> Option<(PeerId, Vec<Multiaddr>)> {
+		self.pending.remove(&peer);
+		self.stats_mut(peer).succeeded += 1;
+		self.next_queued()
+	}
+
+	pub(crate) fn failed(
+		&mut self,
+		peer: PeerId,
+		error: String,
+	) -> Option<(PeerId, Vec<Multiaddr>)> {
+		self.pending.remove(&peer);
+		let stats = self.stats_mut(peer);
+		stats.failed += 1;
+		stats.last_error = Some(error);
+		self.next_queued()
+	}
+
+	/// A dial that did not start because the peer is already connected or
+	/// being dialed elsewhere. Not counted either way.
+	fn skipped(&mut self, peer: PeerId) -> Option<(PeerId, Vec<Multiaddr>)> {
+		self.pending.remove(&peer);
+		self.next_queued()
+	}
+
+	pub(crate) fn pending(&self) -> usize {
+		self.pending.len()
+	}
+
+	pub(crate) fn queued(&self) -> usize {
+		self.queue.len()
+	}
+
+	/// Per-peer dial counts, by peer id.
+	pub(crate) fn stats(&self) -> Vec<PeerDialStats> {
+		let mut stats = self.stats.values().cloned().collect::<Vec<_>>();
+		stats.sort_by(|a, b| a.peer.cmp(&b.peer));
+		stats
+	}
+}
+
+/// Starts `next` and any queued dials that can start after it fails to.
+fn start_dials(
+	swarm: &mut Swarm<AgentBehaviour>,
+	dialer: &mut Dialer,
+	mut next: Option<(PeerId, Vec<Multiaddr>)>,
+) {
+	while let Some((peer, addrs)) = next.take() {
+		next = match swarm.dial(dial_opts(peer, addrs)) {
+			Ok(()) => None,
+			Err(DialError::DialPeerConditionFalse(_)) => dialer.skipped(peer),
+			Err(err) => {
+				tracing::warn!("failed to dial {peer}: {err}");
+				dialer.failed(peer, err.to_string())
+			}
+		};
+	}
+}
+
+/// Dials a peer mDNS found at `addrs`, subject to the dialer's filters
+/// and cap.
+pub(crate) fn dial_discovered(
+	swarm: &mut Swarm<AgentBehaviour>,
+	dialer: &mut Dialer,
+	peer: PeerId,
+	addrs: Vec<Multiaddr>,
+) {
+	if peer == *swarm.local_peer_id() {
+		return;
+	}
+	let own = swarm.listeners().cloned().collect::<Vec<_>>();
+	let next = dialer.admit(peer, addrs, &own).map(|addrs| (peer, addrs));
+	start_dials(swarm, dialer, next);
+}
+
+/// Records how an outgoing dial to `peer` ended and starts the next
+/// queued one.
+pub(crate) fn dial_finished(
+	swarm: &mut Swarm<AgentBehaviour>,
+	dialer: &mut Dialer,
+	peer: PeerId,
+	result: Result<(), String>,
+) {
+	let next = match result {
+		Ok(()) => dialer.succeeded(peer),
+		Err(err) => dialer.failed(peer, err),
+	};
+	start_dials(swarm, dialer, next);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::keepalive::ConnectionPolicy;
+	use crate::p2p::build_swarm;
+	use futures::StreamExt;
+	use libp2p::identity::Keypair;
+	use libp2p::swarm::SwarmEvent;
+	use std::time::Duration;
+
+	fn addr(value: &str) -> Multiaddr {
+		value.parse().unwrap()
+	}
+
+	fn test_swarm() -> Swarm<AgentBehaviour> {
+		let keys = Keypair::generate_ed25519();
+		let peer_id = keys.public().to_peer_id();
+		build_swarm(keys, peer_id, ConnectionPolicy::default()).unwrap()
+	}
+
+	#[test]
+	fn undialable_addresses_are_filtered_out() {
+		let own = vec![addr("/ip4/192.168.1.2/tcp/4001")];
+		assert!(is_dialable(&addr("/ip4/192.168.1.5/tcp/4001"), &own));
+		assert!(is_dialable(&addr("/ip6/2001:db8::5/tcp/4001"), &own));
+		for undialable in [
+			"/ip4/0.0.0.0/tcp/4001",
+			"/ip6/::/tcp/4001",
+			"/ip6/fe80::1/tcp/4001",
+			"/ip6zone/eth0/ip6/fe80::1/tcp/4001",
+			"/ip4/224.0.0.251/tcp/4001",
+			"/dns4/example.com/tcp/4001",
+			"/ip4/192.168.1.5/tcp/0",
+			"/ip4/192.168.1.5/udp/4001",
+			"/ip4/192.168.1.2/tcp/4001",
+		] {
+			assert!(!is_dialable(&addr(undialable), &own), "{undialable}");
+		}
+		assert_eq!(
+			is_dialable(&addr("/ip4/192.168.1.5/udp/4001/quic-v1"), &own),
+			cfg!(feature = "quic")
+		);
+	}
+
+	#[test]
+	fn bursts_are_deduplicated_and_capped() {
+		let mut dialer = Dialer::default();
+		let first = PeerId::random();
+		let at = || vec![addr("/ip4/192.168.1.5/tcp/4001")];
+		assert!(dialer.admit(first, at(), &[]).is_some());
+		for _ in 0..10 {
+			assert!(dialer.admit(first, at(), &[]).is_none());
+		}
+		assert_eq!((dialer.pending(), dialer.queued()), (1, 0));
+
+		let others = (0..MAX_PENDING_DIALS + 2)
+			.map(|_| PeerId::random())
+			.collect::<Vec<_>>();
+		let started = others
+			.iter()
+			.filter(|peer| dialer.admit(**peer, at(), &[]).is_some())
+			.count();
+		assert_eq!(started, MAX_PENDING_DIALS - 1);
+		assert_eq!(dialer.queued(), 3);
+
+		let next = dialer.failed(first, String::from("refused")).unwrap();
+		assert_eq!(next.0, others[MAX_PENDING_DIALS - 1]);
+		assert!(dialer.succeeded(others[0]).is_some());
+		let stats = dialer.stats();
+		let first_stats = stats.iter().find(|s| s.peer == first.to_string()).unwrap();
+		assert_eq!((first_stats.succeeded, first_stats.failed), (0, 1));
+		assert_eq!(first_stats.last_error.as_deref(), Some("refused"));
+	}
+
+	#[tokio::test]
+	async fn invalid_discovered_address_does_not_stop_later_dials() {
+		let mut server = test_swarm();
+		let server_id = *server.local_peer_id();
+		server.listen_on(addr("/ip4/127.0.0.1/tcp/0")).unwrap();
+		let listening = loop {
+			if let SwarmEvent::NewListenAddr { address, .. } = server.select_next_some().await {
+				break address;
+			}
+		};
+		let mut client = test_swarm();
+		let mut dialer = Dialer::default();
+
+		dial_discovered(
+			&mut client,
+			&mut dialer,
+			server_id,
+			vec![addr("/ip6zone/eth0/ip6/fe80::1/tcp/4001")],
+		);
+		assert_eq!(dialer.pending(), 0);
+		dial_discovered(&mut client, &mut dialer, server_id, vec![listening]);
+		assert_eq!(dialer.pending(), 1);
+
+		tokio::time::timeout(Duration::from_secs(20), async {
+			loop {
+				tokio::select! {
+					_ = server.select_next_some() => {}
+					event = client.select_next_some() => {
+						match event {
+							SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+								dial_finished(&mut client, &mut dialer, peer_id, Ok(()));
+								break;
+							}
+							SwarmEvent::OutgoingConnectionError { error, .. } => {
+								panic!("dial failed: {error}");
+							}
+							_ => {}
+						}
+					}
+				}
+			}
+		})
+		.await
+		.unwrap();
+		assert_eq!(dialer.pending(), 0);
+		assert_eq!(dialer.stats()[0].succeeded, 1);
+	}
+}
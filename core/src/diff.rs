@@ -0,0 +1,450 @@
+//! Comparing two files, each on any peer. Small text files get a unified
+//! line diff; anything else is compared in [`DIFF_BLOCK_SIZE`] blocks read
+//! at the same offsets from both sides, so large files never have to be
+//! fetched whole.
+
+use libp2p::PeerId;
+use serde::Serialize;
+
+/// Size of the ranged reads binary comparisons are made of.
+pub(crate) const DIFF_BLOCK_SIZE: u64 = 64 * 1024;
+
+/// A file on a peer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileRef {
+	pub peer: PeerId,
+	pub path: String,
+}
+
+impl FileRef {
+	pub fn new(peer: PeerId, path: impl Into<String>) -> Self {
+		Self {
+			peer,
+			path: path.into(),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiffOptions {
+	/// Files larger than this are compared block by block, even if text.
+	pub max_text_bytes: u64,
+	/// Unchanged lines shown around each change.
+	pub context_lines: usize,
+	/// Past this many blocks, evenly spaced blocks are sampled instead.
+	pub max_compared_blocks: u64,
+}
+
+impl Default for DiffOptions {
+	fn default() -> Self {
+		Self {
+			max_text_bytes: 1024 * 1024,
+			context_lines: 3,
+			max_compared_blocks: 1024,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffLineKind {
+	Context,
+	Added,
+	Removed,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DiffLine {
+	pub kind: DiffLineKind,
+	pub text: String,
+}
+
+/// One `@@` section of a unified diff. Line numbers are 1-based.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DiffHunk {
+	pub old_start: usize,
+	pub old_lines: usize,
+	pub new_start: usize,
+	pub new_lines: usize,
+	pub lines: Vec<DiffLine>,
+}
+
+impl DiffHunk {
+	pub fn header(&self) -> String {
+		format!(
+			"@@ -{},{} +{},{} @@",
+			self.old_start, self.old_lines, self.new_start, self.new_lines
+		)
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TextDiff {
+	pub added: usize,
+	pub removed: usize,
+	pub hunks: Vec<DiffHunk>,
+	/// The hunks as `diff -u` prints them.
+	pub unified: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BinaryDiff {
+	pub size_a: u64,
+	pub size_b: u64,
+	/// Byte offset of the first difference found, `None` when the
+	/// compared bytes are identical.
+	pub first_difference: Option<u64>,
+	pub compared_blocks: u64,
+	pub differing_blocks: u64,
+	pub differing_percent: f64,
+	/// Why the comparison is not a text diff or not exhaustive.
+	pub note: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FileDiff {
+	Text(TextDiff),
+	Binary(BinaryDiff),
+}
+
+fn as_text(data: &[u8]) -> Option<&str> {
+	if data.contains(&0) {
+		return None;
+	}
+	std::str::from_utf8(data).ok()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Edit {
+	Equal(usize),
+	Removed(usize),
+	Added(usize),
+}
+
+/// Shortest edit script from `a` to `b` (Myers, O((N+M)D)).
+fn edit_script(a: &[&str], b: &[&str]) -> Vec<Edit> {
+	let (n, m) = (a.len() as isize, b.len() as isize);
+	let max = n + m;
+	if max == 0 {
+		return Vec::new();
+	}
+	let at = |k: isize| (k + max) as usize;
+	let mut v = vec![0isize; 2 * max as usize + 2];
+	let mut trace = Vec::new();
+	'search: for d in 0..=max {
+		trace.push(v.clone());
+		for k in (-d..=d).step_by(2) {
+			let mut x = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+				v[at(k + 1)]
+			} else {
+				v[at(k - 1)] + 1
+			};
+			let mut y = x - k;
+			while x < n && y < m && a[x as usize] == b[y as usize] {
+				x += 1;
+				y += 1;
+			}
+			v[at(k)] = x;
+			if x >= n && y >= m {
+				break 'search;
+			}
+		}
+	}
+
+	let mut edits = Vec::new();
+	let (mut x, mut y) = (n, m);
+	for (d, v) in trace.iter().enumerate().rev() {
+		let d = d as isize;
+		let k = x - y;
+		let prev_k = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+			k + 1
+		} else {
+			k - 1
+		};
+		let prev_x = v[at(prev_k)];
+		let prev_y = prev_x - prev_k;
+		while x > prev_x && y > prev_y {
+			x -= 1;
+			y -= 1;
+			edits.push(Edit::Equal(x as usize));
+		}
+		if d > 0 {
+			if x == prev_x {
+				edits.push(Edit::Added(prev_y as usize));
+			} else {
+				edits.push(Edit::Removed(prev_x as usize));
+			}
+		}
+		x = prev_x;
+		y = prev_y;
+	}
+	edits.reverse();
+	edits
+}
+
+/// Groups `edits` into hunks with `context` unchanged lines around each
+/// change, merging changes whose context would overlap.
+fn hunks(edits: &[Edit], a: &[&str], b: &[&str], context: usize) -> Vec<DiffHunk> {
+	let changes = edits
+		.iter()
+		.enumerate()
+		.filter(|(_, edit)| !matches!(edit, Edit::Equal(_)))
+		.map(|(i, _)| i)
+		.collect::<Vec<_>>();
+	let mut groups: Vec<(usize, usize)> = Vec::new();
+	for i in changes {
+		let start = i.saturating_sub(context);
+		let end = (i + context + 1).min(edits.len());
+		match groups.last_mut() {
+			Some(last) if start <= last.1 => last.1 = end,
+			_ => groups.push((start, end)),
+		}
+	}
+	groups
+		.into_iter()
+		.map(|(start, end)| {
+			// Where this hunk begins in each file, counting the lines of
+			// everything before it.
+			let (mut old_start, mut new_start) = (0, 0);
+			for edit in &edits[..start] {
+				match edit {
+					Edit::Equal(_) => {
+						old_start += 1;
+						new_start += 1;
+					}
+					Edit::Removed(_) => old_start += 1,
+					Edit::Added(_) => new_start += 1,
+				}
+			}
+			let mut hunk = DiffHunk {
+				old_start: old_start + 1,
+				old_lines: 0,
+				new_start: new_start + 1,
+				new_lines: 0,
+				lines: Vec::with_capacity(end - start),
+			};
+			for edit in &edits[start..end] {
+				let (kind, text) = match *edit {
+					Edit::Equal(i) => {
+						hunk.old_lines += 1;
+						hunk.new_lines += 1;
+						(DiffLineKind::Context, a[i])
+					}
+					Edit::Removed(i) => {
+						hunk.old_lines += 1;
+						(DiffLineKind::Removed, a[i])
+					}
+					Edit::Added(j) => {
+						hunk.new_lines += 1;
+						(DiffLineKind::Added, b[j])
+					}
+				};
+				hunk.lines.push(DiffLine {
+					kind,
+					text: text.to_string(),
+				});
+			}
+			// diff -u numbers an empty side from the line before it.
+			if hunk.old_lines == 0 {
+				hunk.old_start -= 1;
+			}
+			if hunk.new_lines == 0 {
+				hunk.new_start -= 1;
+			}
+			hunk
+		})
+		.collect()
+}
+
+pub(crate) fn text_diff(a: &str, b: &str, name_a: &str, name_b: &str, context: usize) -> TextDiff {
+	let a = a.lines().collect::<Vec<_>>();
+	let b = b.lines().collect::<Vec<_>>();
+	let edits = edit_script(&a, &b);
+	let hunks = hunks(&edits, &a, &b, context);
+	let mut unified = String::new();
+	if !hunks.is_empty() {
+		unified.push_str(&format!("--- {name_a}\n+++ {name_b}\n"));
+	}
+	for hunk in &hunks {
+		unified.push_str(&hunk.header());
+		unified.push('\n');
+		for line in &hunk.lines {
+			unified.push(match line.kind {
+				DiffLineKind::Context => ' ',
+				DiffLineKind::Added => '+',
+				DiffLineKind::Removed => '-',
+			});
+			unified.push_str(&line.text);
+			unified.push('\n');
+		}
+	}
+	TextDiff {
+		added: edits.iter().filter(|e| matches!(e, Edit::Added(_))).count(),
+		removed: edits
+			.iter()
+			.filter(|e| matches!(e, Edit::Removed(_)))
+			.count(),
+		hunks,
+		unified,
+	}
+}
+
+/// The blocks to compare for files of these sizes: all of them, or
+/// `max_blocks` spread evenly over the longer file.
+pub(crate) fn blocks_to_compare(size_a: u64, size_b: u64, max_blocks: u64) -> Vec<u64> {
+	let total = size_a.max(size_b).div_ceil(DIFF_BLOCK_SIZE);
+	let max_blocks = max_blocks.max(1);
+	if total <= max_blocks {
+		return (0..total).collect();
+	}
+	(0..max_blocks).map(|i| i * total / max_blocks).collect()
+}
+
+/// Running result of a block-by-block comparison.
+#[derive(Debug, Default)]
+pub(crate) struct BlockTally {
+	first_difference: Option<u64>,
+	compared: u64,
+	differing: u64,
+}
+
+impl BlockTally {
+	/// Adds the block at `index`, as read from each file. A block missing
+	/// from the shorter file counts as differing from its start.
+	pub(crate) fn add(&mut self, index: u64, a: &[u8], b: &[u8]) {
+		self.compared += 1;
+		let differs_at = a
+			.iter()
+			.zip(b)
+			.position(|(x, y)| x != y)
+			.or((a.len() != b.len()).then(|| a.len().min(b.len())));
+		if let Some(at) = differs_at {
+			self.differing += 1;
+			if self.first_difference.is_none() {
+				self.first_difference = Some(index * DIFF_BLOCK_SIZE + at as u64);
+			}
+		}
+	}
+
+	pub(crate) fn finish(self, size_a: u64, size_b: u64, note: Option<String>) -> BinaryDiff {
+		let differing_percent = if self.compared == 0 {
+			0.0
+		} else {
+			self.differing as f64 * 100.0 / self.compared as f64
+		};
+		BinaryDiff {
+			size_a,
+			size_b,
+			first_difference: self.first_difference,
+			compared_blocks: self.compared,
+			differing_blocks: self.differing,
+			differing_percent,
+			note,
+		}
+	}
+}
+
+/// Diffs two files held in memory: as text when both are UTF-8 without
+/// NUL bytes, else block by block.
+pub(crate) fn diff_contents(
+	a: &[u8],
+	b: &[u8],
+	name_a: &str,
+	name_b: &str,
+	opts: &DiffOptions,
+) -> FileDiff {
+	if let (Some(text_a), Some(text_b)) = (as_text(a), as_text(b)) {
+		return FileDiff::Text(text_diff(
+			text_a,
+			text_b,
+			name_a,
+			name_b,
+			opts.context_lines,
+		));
+	}
+	let mut tally = BlockTally::default();
+	let block = DIFF_BLOCK_SIZE as usize;
+	let range = |data: &[u8], index: usize| {
+		let start = (index * block).min(data.len());
+		let end = (start + block).min(data.len());
+		(start, end)
+	};
+	for index in blocks_to_compare(a.len() as u64, b.len() as u64, u64::MAX) {
+		let (a_start, a_end) = range(a, index as usize);
+		let (b_start, b_end) = range(b, index as usize);
+		tally.add(index, &a[a_start..a_end], &b[b_start..b_end]);
+	}
+	FileDiff::Binary(tally.finish(a.len() as u64, b.len() as u64, None))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn unified_diff_matches_diff_u() {
+		let a = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n";
+		let b = "one\ntwo\n3\nfour\nfive\nsix\nseven\neight\nnine\nten\neleven\n";
+		let diff = text_diff(a, b, "a/list", "b/list", 2);
+		assert_eq!((diff.added, diff.removed), (2, 1));
+		assert_eq!(diff.hunks.len(), 2);
+		assert_eq!(
+			diff.unified,
+			"--- a/list\n+++ b/list\n\
+			@@ -1,5 +1,5 @@\n one\n two\n-three\n+3\n four\n five\n\
+			@@ -9,2 +9,3 @@\n nine\n ten\n+eleven\n"
+		);
+	}
+
+	#[test]
+	fn nearby_changes_share_a_hunk() {
+		let diff = text_diff("a\nb\nc\nd\n", "a\nB\nc\nD\n", "x", "y", 1);
+		assert_eq!(diff.hunks.len(), 1);
+		assert_eq!(diff.hunks[0].header(), "@@ -1,4 +1,4 @@");
+	}
+
+	#[test]
+	fn empty_sides_and_identical_files() {
+		let added = text_diff("", "new\n", "x", "y", 3);
+		assert_eq!(added.hunks[0].header(), "@@ -0,0 +1,1 @@");
+		let removed = text_diff("old\n", "", "x", "y", 3);
+		assert_eq!(removed.hunks[0].header(), "@@ -1,1 +0,0 @@");
+		let same = text_diff("same\n", "same\n", "x", "y", 3);
+		assert!(same.hunks.is_empty() && same.unified.is_empty());
+	}
+
+	#[test]
+	fn binary_contents_report_offset_and_blocks() {
+		let a = vec![0xffu8; DIFF_BLOCK_SIZE as usize * 4];
+		let mut b = a.clone();
+		b[DIFF_BLOCK_SIZE as usize + 10] = 0;
+		let FileDiff::Binary(diff) = diff_contents(&a, &b, "a", "b", &DiffOptions::default())
+		else {
+			panic!("expected a binary diff");
+		};
+		assert_eq!(diff.first_difference, Some(DIFF_BLOCK_SIZE + 10));
+		assert_eq!((diff.compared_blocks, diff.differing_blocks), (4, 1));
+		assert_eq!(diff.differing_percent, 25.0);
+
+		b.truncate(DIFF_BLOCK_SIZE as usize);
+		b.push(1);
+		let FileDiff::Binary(diff) = diff_contents(&a, &b, "a", "b", &DiffOptions::default())
+		else {
+			panic!("expected a binary diff");
+		};
+		assert_eq!(diff.first_difference, Some(DIFF_BLOCK_SIZE));
+		assert_eq!(diff.differing_blocks, 3);
+	}
+
+	#[test]
+	fn large_files_sample_evenly_spaced_blocks() {
+		let blocks = blocks_to_compare(DIFF_BLOCK_SIZE * 100, 1, 4);
+		assert_eq!(blocks, vec![0, 25, 50, 75]);
+		assert_eq!(
+			blocks_to_compare(DIFF_BLOCK_SIZE * 2 + 1, 0, 4),
+			vec![0, 1, 2]
+		);
+		assert!(blocks_to_compare(0, 0, 4).is_empty());
+	}
+}
@@ -0,0 +1,475 @@
+//! Addresses other peers were seen at, through mDNS, dials and pairing.
+//! Kept out of [`crate::State`] because a busy network keeps adding to it
+//! and the state snapshot is cloned on every UI render; callers ask for
+//! the part they need through [`DiscoveredPeers::query`] instead.
+//!
+//! Retention only applies to memory. The `discovered_peers` table keeps
+//! every address until mDNS reports it expired, and a restart starts from
+//! it again. Changes reach the table in batches, see [`DiscoveryWrites`].
+
+use crate::config;
+use crate::state::DiscoveredPeer;
+use chrono::{DateTime, Utc};
+use libp2p::{Multiaddr, PeerId};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+pub const DISCOVERED_ADDRESS_TTL_SETTING: &str = "discovered_address_ttl_secs";
+pub const DEFAULT_DISCOVERED_ADDRESS_TTL: chrono::Duration = chrono::Duration::days(7);
+pub const DISCOVERY_FLUSH_SETTING: &str = "discovery_flush_secs";
+pub const DEFAULT_DISCOVERY_FLUSH_INTERVAL: chrono::Duration = chrono::Duration::seconds(30);
+/// Addresses kept per peer; the one seen longest ago goes first.
+pub const MAX_ADDRESSES_PER_PEER: usize = 8;
+
+/// TTL stored under [`DISCOVERED_ADDRESS_TTL_SETTING`], in seconds.
+pub fn ttl_from_setting(value: Option<&str>) -> chrono::Duration {
+	value
+		.and_then(|value| value.trim().parse::<i64>().ok())
+		.filter(|secs| *secs > 0)
+		.map(chrono::Duration::seconds)
+		.unwrap_or_else(|| config::startup().discovered_address_ttl)
+}
+
+/// Flush interval stored under [`DISCOVERY_FLUSH_SETTING`], in seconds.
+pub fn flush_interval_from_setting(value: Option<&str>) -> chrono::Duration {
+	value
+		.and_then(|value| value.trim().parse::<i64>().ok())
+		.filter(|secs| *secs > 0)
+		.map(chrono::Duration::seconds)
+		.unwrap_or_else(|| config::startup().discovery_flush_interval)
+}
+
+/// Which discovered peers a query returns.
+#[derive(Clone, Debug, Default)]
+pub struct DiscoveredPeerFilter {
+	pub peer: Option<PeerId>,
+	/// Only addresses seen at or after this time.
+	pub seen_since: Option<DateTime<Utc>>,
+}
+
+/// A discovered peer with its known addresses, most recently seen first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiscoveredPeerInfo {
+	pub peer_id: PeerId,
+	pub addresses: Vec<Multiaddr>,
+	pub last_seen: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug)]
+struct SeenAddress {
+	multiaddr: Multiaddr,
+	last_seen: DateTime<Utc>,
+}
+
+/// Bounded table of discovered peer addresses.
+#[derive(Debug)]
+pub struct DiscoveredPeers {
+	/// Per peer, oldest sighting first.
+	peers: HashMap<PeerId, VecDeque<SeenAddress>>,
+	ttl: chrono::Duration,
+}
+
+impl Default for DiscoveredPeers {
+	fn default() -> Self {
+		Self::new(DEFAULT_DISCOVERED_ADDRESS_TTL)
+	}
+}
+
+impl DiscoveredPeers {
+	pub fn new(ttl: chrono::Duration) -> Self {
+		Self {
+			peers: HashMap::new(),
+			ttl,
+		}
+	}
+
+	pub fn ttl(&self) -> chrono::Duration {
+		self.ttl
+	}
+
+	pub fn set_ttl(&mut self, ttl: chrono::Duration) {
+		self.ttl = ttl;
+	}
+
+	/// Replaces the table with stored addresses. The database keeps no
+	/// sighting times, so they count as seen at `now`.
+	pub fn load(&mut self, stored: Vec<DiscoveredPeer>, now: DateTime<Utc>) {
+		self.peers.clear();
+		for entry in stored {
+			self.record(entry.peer_id, entry.multiaddr, now);
+		}
+	}
+
+	/// Notes `multiaddr` as seen at `now`, dropping the peer's oldest
+	/// address once it has [`MAX_ADDRESSES_PER_PEER`].
+	pub fn record(&mut self, peer_id: PeerId, multiaddr: Multiaddr, now: DateTime<Utc>) {
+		let seen = self.peers.entry(peer_id).or_default();
+		seen.retain(|entry| entry.multiaddr != multiaddr);
+		if seen.len() == MAX_ADDRESSES_PER_PEER {
+			seen.pop_front();
+		}
+		seen.push_back(SeenAddress {
+			multiaddr,
+			last_seen: now,
+		});
+	}
+
+	pub fn remove(&mut self, peer_id: &PeerId, multiaddr: &Multiaddr) {
+		if let Some(seen) = self.peers.get_mut(peer_id) {
+			seen.retain(|entry| entry.multiaddr != *multiaddr);
+			if seen.is_empty() {
+				self.peers.remove(peer_id);
+			}
+		}
+	}
+
+	/// Drops addresses not seen within the TTL. Returns how many went.
+	pub fn expire(&mut self, now: DateTime<Utc>) -> usize {
+		let cutoff = now - self.ttl;
+		let mut expired = 0;
+		self.peers.retain(|_, seen| {
+			let before = seen.len();
+			seen.retain(|entry| entry.last_seen >= cutoff);
+			expired += before - seen.len();
+			!seen.is_empty()
+		});
+		expired
+	}
+
+	/// Known addresses of `peer_id`, most recently seen first.
+	pub fn addresses(&self, peer_id: &PeerId) -> Vec<Multiaddr> {
+		self.peers
+			.get(peer_id)
+			.map(|seen| {
+				seen.iter()
+					.rev()
+					.map(|entry| entry.multiaddr.clone())
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
+	/// Up to `limit` peers matching `filter`, most recently seen first.
+	pub fn query(&self, filter: &DiscoveredPeerFilter, limit: usize) -> Vec<DiscoveredPeerInfo> {
+		let mut matches = self
+			.peers
+			.iter()
+			.filter(|(peer_id, _)| filter.peer.is_none_or(|peer| peer == **peer_id))
+			.filter_map(|(peer_id, seen)| {
+				let addresses = seen
+					.iter()
+					.rev()
+					.filter(|entry| {
+						filter
+							.seen_since
+							.is_none_or(|since| entry.last_seen >= since)
+					})
+					.collect::<Vec<_>>();
+				Some(DiscoveredPeerInfo {
+					peer_id: *peer_id,
+					last_seen: addresses.first()?.last_seen,
+					addresses: addresses
+						.into_iter()
+						.map(|entry| entry.multiaddr.clone())
+						.collect(),
+				})
+			})
+			.collect::<Vec<_>>();
+		matches.sort_by(|a, b| {
+			b.last_seen
+				.cmp(&a.last_seen)
+				.then_with(|| a.peer_id.cmp(&b.peer_id))
+		});
+		matches.truncate(limit);
+		matches
+	}
+
+	/// Number of addresses held across all peers.
+	pub fn address_count(&self) -> usize {
+		self.peers.values().map(VecDeque::len).sum()
+	}
+}
+
+/// Address changes for the `discovered_peers` table, written at most
+/// once per flush interval. A busy network reports the same addresses
+/// over and over, and an address that expires and comes back before the
+/// next flush never reaches the table at all.
+#[derive(Debug)]
+pub struct DiscoveryWrites {
+	/// What the table holds, as of the last flush.
+	stored: HashSet<(PeerId, Multiaddr)>,
+	/// Whether each address that changed since should be in the table.
+	pending: HashMap<(PeerId, Multiaddr), bool>,
+	interval: chrono::Duration,
+	last_flush: DateTime<Utc>,
+}
+
+/// Addresses to add to and remove from the `discovered_peers` table.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiscoveryBatch {
+	pub saved: Vec<DiscoveredPeer>,
+	pub removed: Vec<DiscoveredPeer>,
+}
+
+impl DiscoveryBatch {
+	/// Rows the batch writes.
+	pub fn len(&self) -> usize {
+		self.saved.len() + self.removed.len()
+	}
+}
+
+impl DiscoveryWrites {
+	/// Starting from the addresses the table holds at `now`.
+	pub fn new(stored: &[DiscoveredPeer], interval: chrono::Duration, now: DateTime<Utc>) -> Self {
+		let mut writes = Self {
+			stored: HashSet::new(),
+			pending: HashMap::new(),
+			interval,
+			last_flush: now,
+		};
+		writes.load(stored);
+		writes
+	}
+
+	/// Starts over from `stored`, dropping what hadn't been written, as
+	/// after the database file was replaced.
+	pub fn load(&mut self, stored: &[DiscoveredPeer]) {
+		self.stored = stored
+			.iter()
+			.map(|entry| (entry.peer_id, entry.multiaddr.clone()))
+			.collect();
+		self.pending.clear();
+	}
+
+	pub fn seen(&mut self, peer_id: PeerId, multiaddr: Multiaddr) {
+		self.set((peer_id, multiaddr), true);
+	}
+
+	pub fn expired(&mut self, peer_id: PeerId, multiaddr: Multiaddr) {
+		self.set((peer_id, multiaddr), false);
+	}
+
+	fn set(&mut self, key: (PeerId, Multiaddr), present: bool) {
+		if self.stored.contains(&key) == present {
+			self.pending.remove(&key);
+		} else {
+			self.pending.insert(key, present);
+		}
+	}
+
+	/// Changes waiting to be written.
+	#[cfg(test)]
+	pub fn pending(&self) -> usize {
+		self.pending.len()
+	}
+
+	/// The changes to write once the flush interval has passed since the
+	/// last flush, counting them as written.
+	pub fn take_due(&mut self, now: DateTime<Utc>) -> Option<DiscoveryBatch> {
+		if now - self.last_flush < self.interval {
+			return None;
+		}
+		self.take(now)
+	}
+
+	/// Every change waiting, due or not, as on shutdown. `None` when
+	/// there are none.
+	pub fn take(&mut self, now: DateTime<Utc>) -> Option<DiscoveryBatch> {
+		self.last_flush = now;
+		if self.pending.is_empty() {
+			return None;
+		}
+		let mut batch = DiscoveryBatch::default();
+		for ((peer_id, multiaddr), present) in self.pending.drain() {
+			let entry = DiscoveredPeer {
+				peer_id,
+				multiaddr: multiaddr.clone(),
+			};
+			if present {
+				self.stored.insert((peer_id, multiaddr));
+				batch.saved.push(entry);
+			} else {
+				self.stored.remove(&(peer_id, multiaddr));
+				batch.removed.push(entry);
+			}
+		}
+		Some(batch)
+	}
+
+	/// Puts back a batch that failed to write, behind any change made to
+	/// the same addresses since it was taken.
+	pub fn requeue(&mut self, batch: DiscoveryBatch) {
+		let entries = batch
+			.saved
+			.into_iter()
+			.map(|entry| (entry, true))
+			.chain(batch.removed.into_iter().map(|entry| (entry, false)));
+		for (entry, present) in entries {
+			let key = (entry.peer_id, entry.multiaddr);
+			if present {
+				self.stored.remove(&key);
+			} else {
+				self.stored.insert(key.clone());
+			}
+			let present = self.pending.get(&key).copied().unwrap_or(present);
+			self.set(key, present);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::db::{load_discovered_peers, write_discovered_peers};
+	use crate::test_util;
+	use rusqlite::Connection;
+
+	fn at(secs: i64) -> DateTime<Utc> {
+		DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+	}
+
+	fn addr(port: u16) -> Multiaddr {
+		format!("/ip4/192.168.1.10/tcp/{port}").parse().unwrap()
+	}
+
+	#[test]
+	fn addresses_per_peer_are_capped_oldest_first() {
+		let mut discovered = DiscoveredPeers::default();
+		let peer = PeerId::random();
+		for port in 0..MAX_ADDRESSES_PER_PEER as u16 + 3 {
+			discovered.record(peer, addr(port), at(port.into()));
+		}
+		// Seeing a kept address again moves it to the front.
+		discovered.record(peer, addr(3), at(100));
+		let addresses = discovered.addresses(&peer);
+		assert_eq!(addresses.len(), MAX_ADDRESSES_PER_PEER);
+		assert_eq!(addresses[0], addr(3));
+		assert!(!addresses.contains(&addr(2)));
+		assert_eq!(discovered.address_count(), MAX_ADDRESSES_PER_PEER);
+	}
+
+	#[test]
+	fn expire_drops_addresses_not_seen_within_the_ttl() {
+		let mut discovered = DiscoveredPeers::new(chrono::Duration::seconds(60));
+		let stale = PeerId::random();
+		let fresh = PeerId::random();
+		discovered.record(stale, addr(1), at(0));
+		discovered.record(fresh, addr(1), at(0));
+		discovered.record(fresh, addr(2), at(50));
+		assert_eq!(discovered.expire(at(100)), 2);
+		assert!(discovered.addresses(&stale).is_empty());
+		assert_eq!(discovered.addresses(&fresh), vec![addr(2)]);
+	}
+
+	#[test]
+	fn query_filters_and_limits_newest_first() {
+		let mut discovered = DiscoveredPeers::default();
+		let peers = [PeerId::random(), PeerId::random(), PeerId::random()];
+		for (i, peer) in peers.iter().enumerate() {
+			discovered.record(*peer, addr(1), at(i as i64 * 10));
+		}
+		discovered.record(peers[0], addr(2), at(5));
+
+		let newest = discovered.query(&DiscoveredPeerFilter::default(), 2);
+		assert_eq!(
+			newest.iter().map(|info| info.peer_id).collect::<Vec<_>>(),
+			vec![peers[2], peers[1]]
+		);
+
+		let one = discovered.query(
+			&DiscoveredPeerFilter {
+				peer: Some(peers[0]),
+				seen_since: Some(at(1)),
+			},
+			10,
+		);
+		assert_eq!(one.len(), 1);
+		assert_eq!(one[0].addresses, vec![addr(2)]);
+		assert_eq!(one[0].last_seen, at(5));
+	}
+
+	fn stored(conn: &Connection) -> HashSet<(PeerId, Multiaddr)> {
+		load_discovered_peers(conn)
+			.unwrap()
+			.into_iter()
+			.map(|entry| (entry.peer_id, entry.multiaddr))
+			.collect()
+	}
+
+	#[test]
+	fn flapping_addresses_are_written_in_bounded_batches() {
+		let mut conn = test_util::memory_db();
+		let (flapping, leaving, arriving) = (PeerId::random(), PeerId::random(), PeerId::random());
+		let old = DiscoveredPeer {
+			peer_id: leaving,
+			multiaddr: addr(1),
+		};
+		write_discovered_peers(&mut conn, std::slice::from_ref(&old), &[]).unwrap();
+		let mut writes = DiscoveryWrites::new(&[old], chrono::Duration::seconds(30), at(0));
+
+		let mut written = 0;
+		for second in 0..100 {
+			if second % 2 == 0 {
+				writes.seen(flapping, addr(1));
+				writes.seen(leaving, addr(1));
+			} else {
+				writes.expired(flapping, addr(1));
+				writes.expired(leaving, addr(1));
+			}
+			if second == 50 {
+				writes.seen(arriving, addr(2));
+			}
+			if let Some(batch) = writes.take_due(at(second)) {
+				written += batch.len();
+				write_discovered_peers(&mut conn, &batch.saved, &batch.removed).unwrap();
+			}
+		}
+		writes.seen(flapping, addr(1));
+		if let Some(batch) = writes.take(at(100)) {
+			written += batch.len();
+			write_discovered_peers(&mut conn, &batch.saved, &batch.removed).unwrap();
+		}
+
+		// One row per address that ended up changed, not one per event.
+		assert_eq!(written, 3);
+		assert_eq!(
+			stored(&conn),
+			HashSet::from([(flapping, addr(1)), (arriving, addr(2))])
+		);
+		assert_eq!(writes.pending(), 0);
+	}
+
+	#[test]
+	fn address_back_before_the_flush_writes_nothing() {
+		let peer = PeerId::random();
+		let kept = DiscoveredPeer {
+			peer_id: peer,
+			multiaddr: addr(1),
+		};
+		let mut writes = DiscoveryWrites::new(&[kept], chrono::Duration::seconds(30), at(0));
+		writes.expired(peer, addr(1));
+		writes.seen(peer, addr(1));
+		writes.seen(peer, addr(1));
+		assert_eq!(writes.take(at(40)), None);
+	}
+
+	#[test]
+	fn failed_batch_is_written_again_unless_overtaken() {
+		let peer = PeerId::random();
+		let mut writes = DiscoveryWrites::new(&[], chrono::Duration::seconds(30), at(0));
+		writes.seen(peer, addr(1));
+		writes.seen(peer, addr(2));
+		let batch = writes.take(at(30)).unwrap();
+		writes.expired(peer, addr(2));
+		writes.requeue(batch);
+
+		let batch = writes.take(at(60)).unwrap();
+		assert_eq!(
+			batch.saved,
+			vec![DiscoveredPeer {
+				peer_id: peer,
+				multiaddr: addr(1),
+			}]
+		);
+		assert!(batch.removed.is_empty());
+	}
+}
@@ -0,0 +1,298 @@
+use crate::config;
+use crate::db::load_setting;
+use crate::p2p::DiskInfo;
+use chrono::{DateTime, Utc};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub(crate) const DISK_SAMPLE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+pub(crate) const DISK_SAMPLE_RETENTION_DAYS: i64 = 90;
+/// Most samples one history response carries; longer ranges are thinned.
+pub(crate) const DISK_HISTORY_MAX_SAMPLES: usize = 1000;
+/// Alert when free space drops below this share of a disk, unless the node
+/// configures its own threshold. 0 turns alerts off.
+pub(crate) const DEFAULT_LOW_SPACE_PERCENT: u8 = 10;
+pub(crate) const LOW_SPACE_PERCENT_SETTING: &str = "disk_alert_threshold";
+/// Free space must climb this many points above the threshold before the
+/// same disk can alert again, so a disk hovering at the line stays quiet.
+const ALERT_CLEAR_MARGIN: f64 = 2.0;
+const SECONDS_PER_DAY: f64 = 24.0 * 60.0 * 60.0;
+
+/// Size and free space of one disk at one point in time.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskSample {
+	pub disk_id: String,
+	pub mount_path: String,
+	pub sampled_at: DateTime<Utc>,
+	pub total_space: u64,
+	pub available_space: u64,
+}
+
+/// Filesystem UUIDs and the devices they resolve to, from
+/// `/dev/disk/by-uuid`. Empty where the platform has no such directory.
+pub(crate) fn uuid_links() -> Vec<(String, PathBuf)> {
+	let Ok(entries) = std::fs::read_dir("/dev/disk/by-uuid") else {
+		return Vec::new();
+	};
+	entries
+		.filter_map(|entry| {
+			let entry = entry.ok()?;
+			let device = std::fs::canonicalize(entry.path()).ok()?;
+			Some((entry.file_name().to_string_lossy().into_owned(), device))
+		})
+		.collect()
+}
+
+/// Key that follows a disk rather than its mount point: the filesystem UUID
+/// or device where the platform exposes them, the mount path otherwise.
+pub(crate) fn disk_id(name: &str, mount_path: &str, uuids: &[(String, PathBuf)]) -> String {
+	if name.starts_with("/dev/") {
+		let device = std::fs::canonicalize(name).unwrap_or_else(|_| PathBuf::from(name));
+		if let Some((uuid, _)) = uuids.iter().find(|(_, target)| *target == device) {
+			return format!("uuid:{uuid}");
+		}
+		return format!("dev:{name}");
+	}
+	format!("mount:{mount_path}")
+}
+
+/// Low-space threshold configured on this node, in percent free.
+pub(crate) fn low_space_percent(conn: &Connection) -> u8 {
+	match load_setting(conn, LOW_SPACE_PERCENT_SETTING) {
+		Ok(Some(value)) => value.parse().unwrap_or_else(|err| {
+			tracing::warn!("ignoring invalid disk alert threshold {value:?}: {err}");
+			config::startup().disk_alert_threshold
+		}),
+		Ok(None) => config::startup().disk_alert_threshold,
+		Err(err) => {
+			tracing::error!("failed to load disk alert threshold: {err}");
+			config::startup().disk_alert_threshold
+		}
+	}
+}
+
+fn free_percent(total_space: u64, available_space: u64) -> f64 {
+	available_space as f64 / total_space as f64 * 100.0
+}
+
+/// Keeps every n-th sample so at most `max` remain, always including the
+/// newest.
+pub(crate) fn thin_samples(samples: Vec<DiskSample>, max: usize) -> Vec<DiskSample> {
+	if samples.len() <= max || max == 0 {
+		return samples;
+	}
+	let step = samples.len().div_ceil(max);
+	let last = samples.len() - 1;
+	samples
+		.into_iter()
+		.enumerate()
+		.filter(|(index, _)| (last - index).is_multiple_of(step))
+		.map(|(_, sample)| sample)
+		.collect()
+}
+
+/// The newest sample in `samples` and the bytes per second the used space
+/// of its disk grew by, by a least-squares fit. `None` with fewer than two
+/// samples of that disk.
+fn usage_trend(samples: &[DiskSample]) -> Option<(&DiskSample, f64)> {
+	let latest = samples.iter().max_by_key(|sample| sample.sampled_at)?;
+	let samples = samples
+		.iter()
+		.filter(|sample| sample.disk_id == latest.disk_id)
+		.collect::<Vec<_>>();
+	if samples.len() < 2 {
+		return None;
+	}
+	let origin = samples[0].sampled_at.timestamp() as f64;
+	let points = samples
+		.iter()
+		.map(|sample| {
+			let used = sample.total_space.saturating_sub(sample.available_space);
+			(sample.sampled_at.timestamp() as f64 - origin, used as f64)
+		})
+		.collect::<Vec<_>>();
+	let count = points.len() as f64;
+	let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / count;
+	let mean_used = points.iter().map(|(_, used)| used).sum::<f64>() / count;
+	let (covariance, variance) = points.iter().fold((0.0, 0.0), |(cov, var), (t, used)| {
+		(
+			cov + (t - mean_t) * (used - mean_used),
+			var + (t - mean_t) * (t - mean_t),
+		)
+	});
+	if variance == 0.0 {
+		return None;
+	}
+	Some((latest, covariance / variance))
+}
+
+/// Bytes a day the used space grew by over `samples`; negative when it
+/// shrank.
+pub(crate) fn usage_per_day(samples: &[DiskSample]) -> Option<f64> {
+	usage_trend(samples).map(|(_, bytes_per_second)| bytes_per_second * SECONDS_PER_DAY)
+}
+
+/// Days until the disk is full at the rate its used space grew over
+/// `samples`, by a least-squares fit. `None` when usage is flat or
+/// shrinking, or there is too little history to tell.
+pub(crate) fn days_until_full(samples: &[DiskSample]) -> Option<f64> {
+	days_until_low(samples, 0)
+}
+
+/// Days until less than `threshold_percent` of the disk is free at the
+/// rate of [`days_until_full`]; 0 once it is.
+pub(crate) fn days_until_low(samples: &[DiskSample], threshold_percent: u8) -> Option<f64> {
+	let (latest, bytes_per_second) = usage_trend(samples)?;
+	let low_mark = latest.total_space as f64 * f64::from(threshold_percent) / 100.0;
+	let headroom = latest.available_space as f64 - low_mark;
+	if headroom <= 0.0 {
+		return Some(0.0);
+	}
+	if bytes_per_second <= 0.0 {
+		return None;
+	}
+	Some(headroom / bytes_per_second / SECONDS_PER_DAY)
+}
+
+/// Remembers which disks already alerted so each crossing of the threshold
+/// is reported once. Disks are tracked by [`disk_id`], so a removable drive
+/// that is unplugged and comes back, or a different drive mounted at the
+/// same path, never looks like a disk that shrank.
+#[derive(Default)]
+pub(crate) struct LowSpaceAlerts {
+	alerted: HashSet<String>,
+}
+
+impl LowSpaceAlerts {
+	/// Messages for disks that just fell below `threshold_percent` free.
+	pub(crate) fn check(&mut self, disks: &[DiskInfo], threshold_percent: u8) -> Vec<String> {
+		if threshold_percent == 0 {
+			self.alerted.clear();
+			return Vec::new();
+		}
+		let threshold = f64::from(threshold_percent);
+		let mut alerts = Vec::new();
+		for disk in disks {
+			if disk.total_space == 0 || disk.read_only {
+				continue;
+			}
+			let free = free_percent(disk.total_space, disk.available_space);
+			if free < threshold {
+				if self.alerted.insert(disk.id.clone()) {
+					alerts.push(format!(
+						"{} is low on space: {:.1}% free ({} bytes)",
+						disk.mount_path, free, disk.available_space
+					));
+				}
+			} else if free >= threshold + ALERT_CLEAR_MARGIN {
+				self.alerted.remove(&disk.id);
+			}
+		}
+		alerts
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::TimeZone;
+
+	const GB: u64 = 1024 * 1024 * 1024;
+
+	fn disk(id: &str, mount_path: &str, total_space: u64, available_space: u64) -> DiskInfo {
+		DiskInfo {
+			name: String::new(),
+			mount_path: mount_path.to_string(),
+			filesystem: String::new(),
+			total_space,
+			available_space,
+			usage_percent: 0.0,
+			total_read_bytes: 0,
+			total_written_bytes: 0,
+			read_only: false,
+			removable: true,
+			kind: String::new(),
+			id: id.to_string(),
+		}
+	}
+
+	fn sample(day: u32, available_space: u64) -> DiskSample {
+		DiskSample {
+			disk_id: String::from("uuid:1"),
+			mount_path: String::from("/srv"),
+			sampled_at: Utc.with_ymd_and_hms(2025, 3, day, 12, 0, 0).unwrap(),
+			total_space: 100 * GB,
+			available_space,
+		}
+	}
+
+	#[test]
+	fn steady_growth_projects_when_the_disk_fills() {
+		let samples = (1..=5)
+			.map(|day| sample(day, (50 - 2 * u64::from(day)) * GB))
+			.collect::<Vec<_>>();
+		let days = days_until_full(&samples).unwrap();
+		assert!((days - 20.0).abs() < 0.01, "{days}");
+
+		let flat = vec![sample(1, 40 * GB), sample(2, 40 * GB)];
+		assert_eq!(days_until_full(&flat), None);
+		assert_eq!(days_until_full(&samples[..1]), None);
+	}
+
+	#[test]
+	fn thinning_keeps_the_newest_sample() {
+		let samples = (1..=10)
+			.map(|day| sample(day, u64::from(day)))
+			.collect::<Vec<_>>();
+		let thinned = thin_samples(samples.clone(), 4);
+		assert_eq!(
+			thinned
+				.iter()
+				.map(|sample| sample.available_space)
+				.collect::<Vec<_>>(),
+			vec![1, 4, 7, 10]
+		);
+		assert_eq!(thin_samples(samples.clone(), 10), samples);
+	}
+
+	#[test]
+	fn low_space_alerts_once_per_crossing() {
+		let mut alerts = LowSpaceAlerts::default();
+		let usb = |available| [disk("uuid:usb", "/media/usb", 100 * GB, available)];
+
+		assert!(alerts.check(&usb(50 * GB), 10).is_empty());
+		assert_eq!(alerts.check(&usb(5 * GB), 10).len(), 1);
+		assert!(alerts.check(&usb(4 * GB), 10).is_empty());
+		// Unplugged and plugged back in while still full: no new alert.
+		assert!(alerts.check(&[], 10).is_empty());
+		assert!(alerts.check(&usb(4 * GB), 10).is_empty());
+		// Hovering just above the line does not re-arm the alert.
+		assert!(alerts.check(&usb(11 * GB), 10).is_empty());
+		assert!(alerts.check(&usb(9 * GB), 10).is_empty());
+		assert!(alerts.check(&usb(30 * GB), 10).is_empty());
+		assert_eq!(alerts.check(&usb(9 * GB), 10).len(), 1);
+	}
+
+	#[test]
+	fn another_drive_at_the_same_mount_is_a_different_disk() {
+		let mut alerts = LowSpaceAlerts::default();
+		let small = disk("uuid:small", "/media/usb", 8 * GB, GB / 2);
+		let large = disk("uuid:large", "/media/usb", 1000 * GB, 900 * GB);
+
+		assert_eq!(alerts.check(std::slice::from_ref(&small), 10).len(), 1);
+		assert!(alerts.check(&[large], 10).is_empty());
+		assert!(alerts.check(&[small], 10).is_empty());
+		assert!(alerts.check(&[disk("", "/proc", 0, 0)], 10).is_empty());
+	}
+
+	#[test]
+	fn disks_are_keyed_by_uuid_then_device_then_mount() {
+		let uuids = vec![(String::from("abcd-1234"), PathBuf::from("/dev/sdz9"))];
+		assert_eq!(disk_id("/dev/sdz9", "/media/usb", &uuids), "uuid:abcd-1234");
+		assert_eq!(disk_id("/dev/sdy1", "/media/usb", &uuids), "dev:/dev/sdy1");
+		assert_eq!(disk_id("Data", "D:\\", &uuids), "mount:D:\\");
+	}
+}
@@ -0,0 +1,134 @@
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedSender, error::TrySendError};
+
+/// Events a scan or update consumer may fall behind by. Producers that can
+/// wait (see [`send_blocking`]) pause until there is room; producers that
+/// must not wait go through a [`relay`] or, for events a later one
+/// supersedes, [`send_superseded`].
+pub(crate) const EVENT_CHANNEL_CAPACITY: usize = 256;
+/// A consumer that takes nothing for this long is treated as gone.
+const STALLED_CONSUMER_TIMEOUT: Duration = Duration::from_secs(60);
+const FULL_RETRY_INTERVAL: Duration = Duration::from_millis(5);
+
+pub(crate) fn event_channel<T>() -> (Sender<T>, Receiver<T>) {
+	mpsc::channel(EVENT_CHANNEL_CAPACITY)
+}
+
+/// Sends from a thread that may block, waiting while the channel is full.
+/// Returns false when the receiver is dropped or stalls for longer than
+/// [`STALLED_CONSUMER_TIMEOUT`]; the producer should then stop. Safe to call
+/// from inside a runtime, unlike `Sender::blocking_send`.
+pub(crate) fn send_blocking<T>(tx: &Sender<T>, event: T) -> bool {
+	send_blocking_within(tx, event, STALLED_CONSUMER_TIMEOUT)
+}
+
+/// Sends an event that a later one supersedes, such as cumulative progress,
+/// without ever waiting: while the consumer is behind the event is skipped,
+/// and the last slot stays free for the final event. Returns false only once
+/// the receiver is dropped.
+pub(crate) fn send_superseded<T>(tx: &Sender<T>, event: T) -> bool {
+	if tx.is_closed() {
+		return false;
+	}
+	if tx.capacity() > 1 {
+		let _ = tx.try_send(event);
+	}
+	true
+}
+
+fn send_blocking_within<T>(tx: &Sender<T>, mut event: T, timeout: Duration) -> bool {
+	let deadline = Instant::now() + timeout;
+	loop {
+		match tx.try_send(event) {
+			Ok(()) => return true,
+			Err(TrySendError::Closed(_)) => return false,
+			Err(TrySendError::Full(returned)) => {
+				if Instant::now() >= deadline {
+					tracing::warn!("event consumer stalled; giving up on the stream");
+					return false;
+				}
+				event = returned;
+				std::thread::sleep(FULL_RETRY_INTERVAL);
+			}
+		}
+	}
+}
+
+/// Front for producers that must never wait, such as the network loop or
+/// callbacks running inside async code. Events queue in order behind the
+/// bounded channel until the consumer takes them, and are discarded once
+/// the consumer drops its receiver.
+pub(crate) fn relay<T: Send + 'static>(runtime: &Handle, tx: Sender<T>) -> UnboundedSender<T> {
+	let (relay_tx, mut relay_rx) = mpsc::unbounded_channel();
+	runtime.spawn(async move {
+		while let Some(event) = relay_rx.recv().await {
+			if tx.send(event).await.is_err() {
+				break;
+			}
+		}
+	});
+	relay_tx
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const EVENTS: usize = EVENT_CHANNEL_CAPACITY * 4;
+
+	async fn drain_slowly(mut rx: Receiver<usize>) -> Vec<usize> {
+		let mut received = Vec::new();
+		while let Some(event) = rx.recv().await {
+			received.push(event);
+			if received.len() % 64 == 0 {
+				tokio::time::sleep(Duration::from_millis(5)).await;
+			}
+		}
+		received
+	}
+
+	#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+	async fn blocking_producer_waits_for_a_slow_consumer() {
+		let (tx, rx) = event_channel();
+		let producer =
+			tokio::task::spawn_blocking(move || (0..EVENTS).all(|event| send_blocking(&tx, event)));
+		let received = drain_slowly(rx).await;
+		assert!(producer.await.unwrap());
+		assert_eq!(received, (0..EVENTS).collect::<Vec<_>>());
+	}
+
+	#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+	async fn relay_keeps_every_event_in_order() {
+		let (tx, rx) = event_channel();
+		let relay_tx = relay(&Handle::current(), tx);
+		for event in 0..EVENTS {
+			relay_tx.send(event).unwrap();
+		}
+		drop(relay_tx);
+		assert_eq!(drain_slowly(rx).await, (0..EVENTS).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn superseded_events_leave_room_for_the_final_one() {
+		let (tx, mut rx) = mpsc::channel(4);
+		assert!((0..10).all(|event| send_superseded(&tx, event)));
+		assert!(send_blocking_within(&tx, 99, Duration::from_millis(20)));
+		let mut received = Vec::new();
+		while let Ok(event) = rx.try_recv() {
+			received.push(event);
+		}
+		assert_eq!(received, vec![0, 1, 2, 99]);
+		drop(rx);
+		assert!(!send_superseded(&tx, 100));
+	}
+
+	#[test]
+	fn blocking_producer_gives_up_on_a_gone_or_stalled_consumer() {
+		let (tx, rx) = mpsc::channel(1);
+		assert!(send_blocking_within(&tx, 1, Duration::from_millis(20)));
+		assert!(!send_blocking_within(&tx, 2, Duration::from_millis(20)));
+		drop(rx);
+		assert!(!send_blocking(&tx, 3));
+	}
+}
@@ -0,0 +1,177 @@
+//! Which input the event loop takes next. Local commands go first, then
+//! internal commands (answers from work done off the loop among them),
+//! then swarm events. A source passed over for [`LOOP_BUDGET`] inputs goes
+//! first once, so a peer flooding the swarm can't keep local commands
+//! waiting, and a busy local client can't keep peers waiting either.
+
+use futures::{Stream, StreamExt};
+use std::future::poll_fn;
+use std::task::Poll;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+/// Inputs a waiting source may be passed over for.
+pub(crate) const LOOP_BUDGET: u32 = 16;
+/// Inbound requests answered off the loop at once; more are refused as
+/// busy instead of queueing without bound.
+pub(crate) const MAX_INBOUND_JOBS: usize = 64;
+/// What busy peers are told to wait before asking again.
+pub(crate) const BUSY_RETRY_SECS: u64 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Source {
+	Local,
+	Internal,
+	Swarm,
+}
+
+const PRIORITY: [Source; 3] = [Source::Local, Source::Internal, Source::Swarm];
+
+#[derive(Debug, Default)]
+pub(crate) struct Fairness {
+	/// Inputs taken since each source of [`PRIORITY`] was last taken.
+	passed_over: [u32; 3],
+}
+
+impl Fairness {
+	/// The sources in the order to check them.
+	pub(crate) fn order(&self) -> [Source; 3] {
+		let mut order = PRIORITY;
+		// Stable, so starved sources keep their priority among themselves.
+		order.sort_by_key(|source| self.passed_over[*source as usize] < LOOP_BUDGET);
+		order
+	}
+
+	fn took(&mut self, source: Source) {
+		for (at, passed_over) in self.passed_over.iter_mut().enumerate() {
+			*passed_over = if at == source as usize {
+				0
+			} else {
+				passed_over.saturating_add(1)
+			};
+		}
+	}
+}
+
+pub(crate) enum LoopInput<C, I, E> {
+	Command(C),
+	Internal(I),
+	Swarm(E),
+}
+
+impl<C, I, E> LoopInput<C, I, E> {
+	fn source(&self) -> Source {
+		match self {
+			Self::Command(_) => Source::Local,
+			Self::Internal(_) => Source::Internal,
+			Self::Swarm(_) => Source::Swarm,
+		}
+	}
+}
+
+/// Waits for the next input, taking ready sources in [`Fairness::order`].
+/// Closed channels are skipped.
+pub(crate) async fn next_input<C, I, S>(
+	fairness: &mut Fairness,
+	commands: &mut UnboundedReceiver<C>,
+	internal: &mut UnboundedReceiver<I>,
+	swarm: &mut S,
+) -> LoopInput<C, I, S::Item>
+where
+	S: Stream + Unpin,
+{
+	let order = fairness.order();
+	let input = poll_fn(|cx| {
+		for source in order {
+			let ready = match source {
+				Source::Local => commands
+					.poll_recv(cx)
+					.map(|cmd| cmd.map(LoopInput::Command)),
+				Source::Internal => internal
+					.poll_recv(cx)
+					.map(|cmd| cmd.map(LoopInput::Internal)),
+				Source::Swarm => swarm
+					.poll_next_unpin(cx)
+					.map(|event| event.map(LoopInput::Swarm)),
+			};
+			if let Poll::Ready(Some(input)) = ready {
+				return Poll::Ready(input);
+			}
+		}
+		Poll::Pending
+	})
+	.await;
+	fairness.took(input.source());
+	input
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::{Duration, Instant};
+	use tokio::sync::mpsc::unbounded_channel;
+
+	#[test]
+	fn sources_passed_over_for_the_budget_go_first() {
+		let mut fairness = Fairness::default();
+		assert_eq!(fairness.order(), PRIORITY);
+		for _ in 0..LOOP_BUDGET {
+			fairness.took(Source::Local);
+		}
+		assert_eq!(
+			fairness.order(),
+			[Source::Internal, Source::Swarm, Source::Local]
+		);
+		fairness.took(Source::Internal);
+		assert_eq!(
+			fairness.order(),
+			[Source::Swarm, Source::Local, Source::Internal]
+		);
+		fairness.took(Source::Swarm);
+		assert_eq!(fairness.order(), PRIORITY);
+	}
+
+	#[tokio::test]
+	async fn flooded_sources_still_take_turns() {
+		let (command_tx, mut commands) = unbounded_channel();
+		let (internal_tx, mut internal) = unbounded_channel();
+		let mut swarm = futures::stream::repeat(());
+		for n in 0..100 {
+			command_tx.send(n).unwrap();
+			internal_tx.send(n).unwrap();
+		}
+		let mut fairness = Fairness::default();
+		let mut taken = Vec::new();
+		for _ in 0..(LOOP_BUDGET + 2) * 3 {
+			let input = next_input(&mut fairness, &mut commands, &mut internal, &mut swarm).await;
+			taken.push(input.source());
+		}
+		let budget = LOOP_BUDGET as usize;
+		assert!(
+			taken[..budget]
+				.iter()
+				.all(|source| *source == Source::Local)
+		);
+		assert_eq!(taken[budget..budget + 2], [Source::Internal, Source::Swarm]);
+		for source in PRIORITY {
+			let turns = taken.iter().filter(|taken| **taken == source).count();
+			assert!(turns >= 3, "{source:?} got {turns} turns");
+		}
+	}
+
+	#[tokio::test]
+	async fn local_commands_come_before_a_flooded_swarm() {
+		let (command_tx, mut commands) = unbounded_channel();
+		let (_internal_tx, mut internal) = unbounded_channel::<()>();
+		let mut swarm = futures::stream::repeat(());
+		let mut fairness = Fairness::default();
+		for _ in 0..1000 {
+			let input = next_input(&mut fairness, &mut commands, &mut internal, &mut swarm).await;
+			assert!(matches!(input, LoopInput::Swarm(())));
+		}
+		let sent = Instant::now();
+		command_tx.send(()).unwrap();
+		let input = next_input(&mut fairness, &mut commands, &mut internal, &mut swarm).await;
+		assert!(matches!(input, LoopInput::Command(())));
+		assert!(sent.elapsed() < Duration::from_millis(50));
+	}
+}
@@ -0,0 +1,170 @@
+//! Running the same request against many peers. At most
+//! [`FanOutOptions::concurrency`] requests are in flight, each gets its own
+//! [`FanOutOptions::per_peer_timeout`], and every peer comes back with its
+//! own result, so one slow or dead peer costs its timeout and nothing more.
+
+use anyhow::{Result, anyhow};
+use futures::{Future, StreamExt};
+use libp2p::PeerId;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FanOutOptions {
+	/// Requests in flight at once; at least one.
+	pub concurrency: usize,
+	/// How long each peer gets, counted from when its request starts.
+	pub per_peer_timeout: Duration,
+}
+
+impl Default for FanOutOptions {
+	fn default() -> Self {
+		Self {
+			concurrency: 8,
+			per_peer_timeout: Duration::from_secs(10),
+		}
+	}
+}
+
+/// Runs `op` for each of `peers` and returns their results in the order
+/// of `peers`. A peer that doesn't finish in time gets an error.
+pub async fn fan_out<T, F, Fut>(
+	peers: Vec<PeerId>,
+	op: F,
+	opts: FanOutOptions,
+) -> Vec<(PeerId, Result<T>)>
+where
+	F: Fn(PeerId) -> Fut,
+	Fut: Future<Output = Result<T>>,
+{
+	let timeout = opts.per_peer_timeout;
+	let mut results = futures::stream::iter(peers.into_iter().enumerate())
+		.map(|(idx, peer)| {
+			let request = op(peer);
+			async move {
+				let result = match tokio::time::timeout(timeout, request).await {
+					Ok(result) => result,
+					Err(_) => Err(anyhow!("no answer within {timeout:?}")),
+				};
+				(idx, peer, result)
+			}
+		})
+		.buffer_unordered(opts.concurrency.max(1))
+		.collect::<Vec<_>>()
+		.await;
+	results.sort_by_key(|(idx, _, _)| *idx);
+	results
+		.into_iter()
+		.map(|(_, peer, result)| (peer, result))
+		.collect()
+}
+
+/// How a fan-out went, for a status line and a list of what failed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FanOutSummary {
+	pub total: usize,
+	pub responded: usize,
+	/// Peers that failed or timed out, with why.
+	pub failures: Vec<(PeerId, String)>,
+}
+
+impl FanOutSummary {
+	pub fn of<T>(results: &[(PeerId, Result<T>)]) -> Self {
+		let failures = results
+			.iter()
+			.filter_map(|(peer, result)| {
+				result.as_ref().err().map(|err| (*peer, format!("{err:#}")))
+			})
+			.collect::<Vec<_>>();
+		Self {
+			total: results.len(),
+			responded: results.len() - failures.len(),
+			failures,
+		}
+	}
+
+	/// "3 of 5 peers responded".
+	pub fn describe(&self) -> String {
+		format!("{} of {} peers responded", self.responded, self.total)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::time::Instant;
+
+	#[tokio::test]
+	async fn a_silent_peer_only_costs_its_own_timeout() {
+		let peers = (0..6).map(|_| PeerId::random()).collect::<Vec<_>>();
+		let silent = peers[0];
+		let opts = FanOutOptions {
+			concurrency: 3,
+			per_peer_timeout: Duration::from_millis(300),
+		};
+		let started = Instant::now();
+		let results = fan_out(
+			peers.clone(),
+			|peer| async move {
+				if peer == silent {
+					futures::future::pending::<()>().await;
+				}
+				tokio::time::sleep(Duration::from_millis(50)).await;
+				Ok(peer)
+			},
+			opts,
+		)
+		.await;
+		let elapsed = started.elapsed();
+
+		// The other five share the two remaining slots: three rounds of
+		// 50ms, all while the silent peer waits out its timeout.
+		assert!(elapsed < Duration::from_millis(550), "took {elapsed:?}");
+		assert!(elapsed >= opts.per_peer_timeout, "took {elapsed:?}");
+		assert_eq!(
+			results.iter().map(|(peer, _)| *peer).collect::<Vec<_>>(),
+			peers
+		);
+		let summary = FanOutSummary::of(&results);
+		assert_eq!(summary.describe(), "5 of 6 peers responded");
+		assert_eq!(summary.failures.len(), 1);
+		assert_eq!(summary.failures[0].0, silent);
+		assert!(summary.failures[0].1.contains("no answer within"));
+	}
+
+	#[tokio::test]
+	async fn concurrency_is_capped_and_errors_stay_per_peer() {
+		let peers = (0..5).map(|_| PeerId::random()).collect::<Vec<_>>();
+		let failing = peers[2];
+		let running = Arc::new(AtomicUsize::new(0));
+		let most = Arc::new(AtomicUsize::new(0));
+		let results = fan_out(
+			peers.clone(),
+			|peer| {
+				let running = Arc::clone(&running);
+				let most = Arc::clone(&most);
+				async move {
+					let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+					most.fetch_max(now, Ordering::SeqCst);
+					tokio::time::sleep(Duration::from_millis(20)).await;
+					running.fetch_sub(1, Ordering::SeqCst);
+					if peer == failing {
+						return Err(anyhow!("refused"));
+					}
+					Ok(())
+				}
+			},
+			FanOutOptions {
+				concurrency: 2,
+				per_peer_timeout: Duration::from_secs(5),
+			},
+		)
+		.await;
+
+		assert_eq!(most.load(Ordering::SeqCst), 2);
+		let summary = FanOutSummary::of(&results);
+		assert_eq!(summary.responded, 4);
+		assert_eq!(summary.failures, vec![(failing, String::from("refused"))]);
+	}
+}
@@ -0,0 +1,340 @@
+//! Reading a peer file in chunks. A peer answers each `ReadFile` with at
+//! most the bytes asked for, and may send fewer; [`ChunkReader`] keeps
+//! asking from where the last chunk ended until the file or the requested
+//! range ends. An empty chunk that is not the end of the file is retried
+//! with backoff and then fails with [`NoProgress`], so a reader never sits
+//! on the same offset forever.
+
+use crate::types::FileChunk;
+use anyhow::Result;
+use std::ops::Range;
+use std::time::Duration;
+
+/// Empty chunks in a row a reader tolerates before giving up.
+const NO_PROGRESS_RETRIES: u32 = 4;
+/// Wait before the first retry of an empty chunk; doubles on each retry.
+const NO_PROGRESS_BACKOFF: Duration = Duration::from_millis(50);
+/// Bytes asked for per request unless the caller says otherwise.
+pub const DEFAULT_READ_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// A peer kept answering with empty chunks before the end of the file.
+#[derive(Clone, Debug)]
+pub struct NoProgress {
+	pub offset: u64,
+	pub attempts: u32,
+}
+
+impl std::fmt::Display for NoProgress {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"peer sent no data at offset {} after {} attempts",
+			self.offset, self.attempts
+		)
+	}
+}
+
+impl std::error::Error for NoProgress {}
+
+#[derive(Clone, Debug)]
+pub struct ReadToEndOptions {
+	/// Reading stops after this many bytes and the result is marked
+	/// truncated. `None` reads the whole file.
+	pub max_bytes: Option<u64>,
+	pub chunk_size: u64,
+}
+
+impl Default for ReadToEndOptions {
+	fn default() -> Self {
+		Self {
+			max_bytes: None,
+			chunk_size: DEFAULT_READ_CHUNK_SIZE,
+		}
+	}
+}
+
+/// A file read from its start.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileContents {
+	pub data: Vec<u8>,
+	/// The file goes on past `data`; it was cut at
+	/// [`ReadToEndOptions::max_bytes`].
+	pub truncated: bool,
+}
+
+/// Successive chunks of a file from `offset` up to an optional end.
+pub(crate) struct ChunkReader {
+	offset: u64,
+	end: Option<u64>,
+	chunk_size: u64,
+	done: bool,
+}
+
+impl ChunkReader {
+	pub(crate) fn new(offset: u64, end: Option<u64>, chunk_size: u64) -> Self {
+		Self {
+			offset,
+			end,
+			chunk_size: chunk_size.max(1),
+			done: false,
+		}
+	}
+
+	/// Where the next chunk starts.
+	pub(crate) fn offset(&self) -> u64 {
+		self.offset
+	}
+
+	/// The next non-empty chunk from `fetch(offset, length)`, or `None`
+	/// once the file or the range ended. Chunks longer than asked for are
+	/// cut to the requested length; a hole counts as content.
+	pub(crate) async fn next<F, Fut>(&mut self, fetch: &mut F) -> Result<Option<FileChunk>>
+	where
+		F: FnMut(u64, u64) -> Fut,
+		Fut: Future<Output = Result<FileChunk>>,
+	{
+		let remaining = self.end.map(|end| end.saturating_sub(self.offset));
+		if self.done || remaining == Some(0) {
+			return Ok(None);
+		}
+		let length = remaining.map_or(self.chunk_size, |left| left.min(self.chunk_size));
+		let mut attempts = 0;
+		let mut backoff = NO_PROGRESS_BACKOFF;
+		loop {
+			let mut chunk = fetch(self.offset, length).await?;
+			if chunk.span() == 0 {
+				if chunk.eof {
+					self.done = true;
+					return Ok(None);
+				}
+				attempts += 1;
+				if attempts > NO_PROGRESS_RETRIES {
+					return Err(NoProgress {
+						offset: self.offset,
+						attempts,
+					}
+					.into());
+				}
+				tracing::debug!(
+					"empty chunk at offset {}, retrying in {backoff:?}",
+					self.offset
+				);
+				tokio::time::sleep(backoff).await;
+				backoff *= 2;
+				continue;
+			}
+			chunk.data.truncate(length as usize);
+			chunk.hole = chunk.hole.min(length - chunk.data.len() as u64);
+			chunk.offset = self.offset;
+			self.offset += chunk.span();
+			self.done = chunk.eof;
+			return Ok(Some(chunk));
+		}
+	}
+}
+
+/// Reads from the start of a file until it ends or `opts.max_bytes` were
+/// read, calling `on_progress` with the bytes read so far after every
+/// chunk.
+pub(crate) async fn read_to_end<F, Fut>(
+	mut fetch: F,
+	opts: &ReadToEndOptions,
+	mut on_progress: impl FnMut(u64),
+) -> Result<FileContents>
+where
+	F: FnMut(u64, u64) -> Fut,
+	Fut: Future<Output = Result<FileChunk>>,
+{
+	// One byte past the limit tells a file of exactly `max_bytes` apart
+	// from a longer one.
+	let end = opts.max_bytes.map(|max| max.saturating_add(1));
+	let mut reader = ChunkReader::new(0, end, opts.chunk_size);
+	let mut data = Vec::new();
+	while let Some(chunk) = reader.next(&mut fetch).await? {
+		data.extend_from_slice(&chunk.data);
+		data.resize(data.len() + chunk.hole as usize, 0);
+		on_progress(reader.offset());
+	}
+	let truncated = opts.max_bytes.is_some_and(|max| data.len() as u64 > max);
+	if let Some(max) = opts.max_bytes {
+		data.truncate(max as usize);
+	}
+	Ok(FileContents { data, truncated })
+}
+
+/// Reads `range` of a file. The chunk is shorter than the range only when
+/// the file ends first, and then has `eof` set.
+pub(crate) async fn read_range<F, Fut>(
+	mut fetch: F,
+	range: Range<u64>,
+	chunk_size: u64,
+) -> Result<FileChunk>
+where
+	F: FnMut(u64, u64) -> Fut,
+	Fut: Future<Output = Result<FileChunk>>,
+{
+	let mut reader = ChunkReader::new(range.start, Some(range.end), chunk_size);
+	let mut data = Vec::new();
+	let mut eof = false;
+	while let Some(chunk) = reader.next(&mut fetch).await? {
+		data.extend_from_slice(&chunk.data);
+		data.resize(data.len() + chunk.hole as usize, 0);
+		eof = chunk.eof;
+	}
+	Ok(FileChunk {
+		offset: range.start,
+		eof: eof || (data.len() as u64) < range.end.saturating_sub(range.start),
+		data,
+		hole: 0,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use anyhow::anyhow;
+	use std::collections::VecDeque;
+	use std::sync::Mutex;
+
+	enum Reply {
+		/// Up to this many bytes of the file from the requested offset.
+		Bytes(usize),
+		/// An empty chunk that does not end the file.
+		Stall,
+		Fail,
+	}
+
+	/// A peer serving `file` with a scripted reply to each request; once
+	/// the script runs out it serves whole requests.
+	struct MockPeer {
+		file: Vec<u8>,
+		replies: Mutex<VecDeque<Reply>>,
+		requests: Mutex<Vec<(u64, u64)>>,
+	}
+
+	impl MockPeer {
+		fn new(len: usize, replies: Vec<Reply>) -> Self {
+			Self {
+				file: (0..len).map(|i| i as u8).collect(),
+				replies: Mutex::new(replies.into()),
+				requests: Mutex::new(Vec::new()),
+			}
+		}
+
+		fn fetch(&self, offset: u64, length: u64) -> impl Future<Output = Result<FileChunk>> {
+			self.requests.lock().unwrap().push((offset, length));
+			let reply = self
+				.replies
+				.lock()
+				.unwrap()
+				.pop_front()
+				.unwrap_or(Reply::Bytes(length as usize));
+			let start = (offset as usize).min(self.file.len());
+			let result = match reply {
+				Reply::Bytes(n) => {
+					let end = (start + n.min(length as usize)).min(self.file.len());
+					Ok(FileChunk {
+						offset,
+						data: self.file[start..end].to_vec(),
+						eof: end == self.file.len(),
+						hole: 0,
+					})
+				}
+				Reply::Stall => Ok(FileChunk {
+					offset,
+					data: Vec::new(),
+					eof: false,
+					hole: 0,
+				}),
+				Reply::Fail => Err(anyhow!("peer went away")),
+			};
+			std::future::ready(result)
+		}
+	}
+
+	#[tokio::test]
+	async fn short_and_empty_chunks_continue_from_where_the_last_ended() {
+		let peer = MockPeer::new(10, vec![Reply::Bytes(3), Reply::Stall, Reply::Bytes(2)]);
+		let mut progress = Vec::new();
+		let contents = read_to_end(
+			|offset, length| peer.fetch(offset, length),
+			&ReadToEndOptions {
+				max_bytes: None,
+				chunk_size: 4,
+			},
+			|read| progress.push(read),
+		)
+		.await
+		.unwrap();
+		assert_eq!(contents.data, peer.file);
+		assert!(!contents.truncated);
+		assert_eq!(progress, vec![3, 5, 9, 10]);
+		assert_eq!(
+			peer.requests.lock().unwrap().as_slice(),
+			&[(0, 4), (3, 4), (3, 4), (5, 4), (9, 4)]
+		);
+	}
+
+	#[tokio::test]
+	async fn a_peer_that_never_progresses_fails_instead_of_waiting() {
+		let stalls = (0..=NO_PROGRESS_RETRIES).map(|_| Reply::Stall).collect();
+		let peer = MockPeer::new(10, stalls);
+		let err = read_to_end(
+			|offset, length| peer.fetch(offset, length),
+			&ReadToEndOptions::default(),
+			|_| {},
+		)
+		.await
+		.unwrap_err();
+		let stalled = err.downcast_ref::<NoProgress>().unwrap();
+		assert_eq!(stalled.offset, 0);
+		assert_eq!(stalled.attempts, NO_PROGRESS_RETRIES + 1);
+	}
+
+	#[tokio::test]
+	async fn errors_mid_stream_are_returned() {
+		let peer = MockPeer::new(10, vec![Reply::Bytes(4), Reply::Fail]);
+		let err = read_range(|offset, length| peer.fetch(offset, length), 0..10, 4)
+			.await
+			.unwrap_err();
+		assert_eq!(err.to_string(), "peer went away");
+	}
+
+	#[tokio::test]
+	async fn max_bytes_marks_only_longer_files_truncated() {
+		let opts = ReadToEndOptions {
+			max_bytes: Some(6),
+			chunk_size: 4,
+		};
+		let longer = MockPeer::new(10, Vec::new());
+		let cut = read_to_end(|offset, length| longer.fetch(offset, length), &opts, |_| {})
+			.await
+			.unwrap();
+		assert_eq!(cut.data, longer.file[..6]);
+		assert!(cut.truncated);
+
+		let exact = MockPeer::new(6, Vec::new());
+		let whole = read_to_end(|offset, length| exact.fetch(offset, length), &opts, |_| {})
+			.await
+			.unwrap();
+		assert_eq!(whole.data, exact.file);
+		assert!(!whole.truncated);
+	}
+
+	#[tokio::test]
+	async fn ranges_fill_up_or_stop_at_the_end_of_the_file() {
+		let peer = MockPeer::new(10, vec![Reply::Bytes(1), Reply::Stall]);
+		let chunk = read_range(|offset, length| peer.fetch(offset, length), 2..7, 8)
+			.await
+			.unwrap();
+		assert_eq!(chunk.offset, 2);
+		assert_eq!(chunk.data, peer.file[2..7]);
+		assert!(!chunk.eof);
+
+		let tail = read_range(|offset, length| peer.fetch(offset, length), 8..20, 8)
+			.await
+			.unwrap();
+		assert_eq!(tail.data, peer.file[8..]);
+		assert!(tail.eof);
+	}
+}
@@ -0,0 +1,280 @@
+//! Canonical display formatting shared by every frontend, so the CLI, web UI
+//! and HTTP API describe the same file the same way. Output never depends on
+//! the locale.
+
+use chrono::{DateTime, Utc};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Unit system for [`human_size`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SizeUnits {
+	/// Powers of 1024 labelled KiB, MiB, ...
+	Binary,
+	/// Powers of 1000 labelled kB, MB, ...
+	Decimal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampStyle {
+	/// "3 minutes ago", "in 2 hours".
+	Relative,
+	/// `YYYY-MM-DD HH:MM` in UTC.
+	Absolute,
+}
+
+const PEER_ID_EDGE: usize = 10;
+const HASH_ABBREV_BYTES: usize = 6;
+
+pub fn human_size(bytes: u64, units: SizeUnits) -> String {
+	let (base, labels) = match units {
+		SizeUnits::Binary => (1024.0, ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"]),
+		SizeUnits::Decimal => (1000.0, ["B", "kB", "MB", "GB", "TB", "PB", "EB"]),
+	};
+	if (bytes as f64) < base {
+		return format!("{bytes} B");
+	}
+	let mut size = bytes as f64;
+	let mut unit = 0;
+	while size >= base && unit < labels.len() - 1 {
+		size /= base;
+		unit += 1;
+	}
+	format!("{size:.2} {}", labels[unit])
+}
+
+/// Largest two units of `duration`, e.g. `3m 05s` or `2d 4h`. Durations
+/// under a second are shown in milliseconds.
+pub fn human_duration(duration: Duration) -> String {
+	let secs = duration.as_secs();
+	if secs == 0 {
+		return format!("{}ms", duration.subsec_millis());
+	}
+	let (days, hours, minutes, seconds) = (
+		secs / 86_400,
+		secs % 86_400 / 3_600,
+		secs % 3_600 / 60,
+		secs % 60,
+	);
+	if days > 0 {
+		format!("{days}d {hours}h")
+	} else if hours > 0 {
+		format!("{hours}h {minutes:02}m")
+	} else if minutes > 0 {
+		format!("{minutes}m {seconds:02}s")
+	} else {
+		format!("{seconds}s")
+	}
+}
+
+/// `12.00 MiB/s, 3m 05s left`, or `stalled` for a rate of zero. `None`
+/// while there is no rate to show yet.
+pub fn human_rate(
+	bytes_per_sec: Option<u64>,
+	eta_secs: Option<u64>,
+	units: SizeUnits,
+) -> Option<String> {
+	let rate = match bytes_per_sec? {
+		0 => return Some(String::from("stalled")),
+		rate => format!("{}/s", human_size(rate, units)),
+	};
+	Some(match eta_secs {
+		Some(eta) if eta > 0 => {
+			format!("{rate}, {} left", human_duration(Duration::from_secs(eta)))
+		}
+		_ => rate,
+	})
+}
+
+fn plural(count: i64, unit: &str) -> String {
+	if count == 1 {
+		format!("1 {unit}")
+	} else {
+		format!("{count} {unit}s")
+	}
+}
+
+/// `at` relative to `now`, in the largest whole unit: "just now" within a
+/// minute, then minutes, hours, days, months (30 days) and years (365 days).
+pub fn relative_time(at: DateTime<Utc>, now: DateTime<Utc>) -> String {
+	let delta = (now - at).num_seconds();
+	let secs = delta.abs();
+	if secs < 60 {
+		return String::from("just now");
+	}
+	let span = match secs {
+		s if s < 3_600 => plural(s / 60, "minute"),
+		s if s < 86_400 => plural(s / 3_600, "hour"),
+		s if s < 30 * 86_400 => plural(s / 86_400, "day"),
+		s if s < 365 * 86_400 => plural(s / (30 * 86_400), "month"),
+		s => plural(s / (365 * 86_400), "year"),
+	};
+	if delta > 0 {
+		format!("{span} ago")
+	} else {
+		format!("in {span}")
+	}
+}
+
+pub fn human_timestamp(at: DateTime<Utc>, style: TimestampStyle) -> String {
+	match style {
+		TimestampStyle::Relative => relative_time(at, Utc::now()),
+		TimestampStyle::Absolute => at.format("%Y-%m-%d %H:%M").to_string(),
+	}
+}
+
+/// `1,204`: a count with its thousands separated by commas.
+pub fn group_digits(n: u64) -> String {
+	let digits = n.to_string();
+	let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+	for (idx, digit) in digits.chars().enumerate() {
+		if idx > 0 && (digits.len() - idx).is_multiple_of(3) {
+			out.push(',');
+		}
+		out.push(digit);
+	}
+	out
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+	let mut out = String::with_capacity(bytes.len() * 2);
+	for byte in bytes {
+		let _ = write!(out, "{byte:02x}");
+	}
+	out
+}
+
+/// First bytes of a content hash in hex, enough to tell files apart at a
+/// glance.
+pub fn abbrev_hash(hash: &[u8]) -> String {
+	hex(&hash[..hash.len().min(HASH_ABBREV_BYTES)])
+}
+
+/// `12D3KooWAb...xYz0123456`: both ends of a peer id, which is where ids of
+/// the same key type differ.
+pub fn abbrev_peer_id(peer_id: &str) -> String {
+	let chars = peer_id.chars().collect::<Vec<_>>();
+	if chars.len() <= PEER_ID_EDGE * 2 + 1 {
+		return peer_id.to_string();
+	}
+	let start = chars[..PEER_ID_EDGE].iter().collect::<String>();
+	let end = chars[chars.len() - PEER_ID_EDGE..]
+		.iter()
+		.collect::<String>();
+	format!("{start}...{end}")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::TimeZone;
+
+	#[test]
+	fn sizes_use_the_requested_units() {
+		assert_eq!(human_size(0, SizeUnits::Binary), "0 B");
+		assert_eq!(human_size(1023, SizeUnits::Binary), "1023 B");
+		assert_eq!(human_size(1024, SizeUnits::Binary), "1.00 KiB");
+		assert_eq!(human_size(1536, SizeUnits::Binary), "1.50 KiB");
+		assert_eq!(human_size(999, SizeUnits::Decimal), "999 B");
+		assert_eq!(human_size(1500, SizeUnits::Decimal), "1.50 kB");
+		assert_eq!(
+			human_size(5 * 1024 * 1024 * 1024, SizeUnits::Binary),
+			"5.00 GiB"
+		);
+		assert_eq!(human_size(u64::MAX, SizeUnits::Binary), "16.00 EiB");
+		assert_eq!(human_size(u64::MAX, SizeUnits::Decimal), "18.45 EB");
+	}
+
+	#[test]
+	fn durations_show_the_two_largest_units() {
+		assert_eq!(human_duration(Duration::ZERO), "0ms");
+		assert_eq!(human_duration(Duration::from_millis(250)), "250ms");
+		assert_eq!(human_duration(Duration::from_millis(1999)), "1s");
+		assert_eq!(human_duration(Duration::from_secs(185)), "3m 05s");
+		assert_eq!(human_duration(Duration::from_secs(3_720)), "1h 02m");
+		assert_eq!(
+			human_duration(Duration::from_secs(2 * 86_400 + 4 * 3_600)),
+			"2d 4h"
+		);
+		assert_eq!(
+			human_duration(Duration::from_secs(u64::MAX)),
+			"213503982334601d 7h"
+		);
+	}
+
+	#[test]
+	fn rates_show_time_left_or_a_stall() {
+		assert_eq!(human_rate(None, Some(5), SizeUnits::Binary), None);
+		assert_eq!(
+			human_rate(Some(0), Some(5), SizeUnits::Binary).as_deref(),
+			Some("stalled")
+		);
+		assert_eq!(
+			human_rate(Some(1536), None, SizeUnits::Binary).as_deref(),
+			Some("1.50 KiB/s")
+		);
+		assert_eq!(
+			human_rate(Some(12_000_000), Some(185), SizeUnits::Decimal).as_deref(),
+			Some("12.00 MB/s, 3m 05s left")
+		);
+		assert_eq!(
+			human_rate(Some(512), Some(0), SizeUnits::Binary).as_deref(),
+			Some("512 B/s")
+		);
+	}
+
+	#[test]
+	fn relative_times_cover_past_and_future() {
+		let now = Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap();
+		let ago = |secs| relative_time(now - chrono::Duration::seconds(secs), now);
+		assert_eq!(ago(0), "just now");
+		assert_eq!(ago(59), "just now");
+		assert_eq!(ago(-30), "just now");
+		assert_eq!(ago(60), "1 minute ago");
+		assert_eq!(ago(3 * 60 + 59), "3 minutes ago");
+		assert_eq!(ago(2 * 3_600), "2 hours ago");
+		assert_eq!(ago(86_400), "1 day ago");
+		assert_eq!(ago(45 * 86_400), "1 month ago");
+		assert_eq!(ago(800 * 86_400), "2 years ago");
+		assert_eq!(ago(-5 * 60), "in 5 minutes");
+		assert_eq!(ago(-3 * 86_400), "in 3 days");
+	}
+
+	#[test]
+	fn absolute_timestamps_are_utc_minutes() {
+		let at = Utc.with_ymd_and_hms(2025, 3, 1, 7, 5, 59).unwrap();
+		assert_eq!(
+			human_timestamp(at, TimestampStyle::Absolute),
+			"2025-03-01 07:05"
+		);
+	}
+
+	#[test]
+	fn counts_group_thousands() {
+		assert_eq!(group_digits(0), "0");
+		assert_eq!(group_digits(999), "999");
+		assert_eq!(group_digits(1204), "1,204");
+		assert_eq!(group_digits(100_000), "100,000");
+		assert_eq!(group_digits(u64::MAX), "18,446,744,073,709,551,615");
+	}
+
+	#[test]
+	fn hashes_and_peer_ids_abbreviate_stably() {
+		assert_eq!(hex(&[0x00, 0xab, 0xff]), "00abff");
+		assert_eq!(hex(&[]), "");
+		assert_eq!(
+			abbrev_hash(&[0xde, 0xad, 0xbe, 0xef, 1, 2, 3, 4]),
+			"deadbeef0102"
+		);
+		assert_eq!(abbrev_hash(&[0x01]), "01");
+		assert_eq!(
+			abbrev_peer_id("12D3KooWAbCdEfGhIjKlMnOpQrStUvWxYz0123456789"),
+			"12D3KooWAb...0123456789"
+		);
+		assert_eq!(abbrev_peer_id("short"), "short");
+		assert_eq!(
+			abbrev_peer_id("ääääääääääöööööööööö"),
+			"ääääääääääöööööööööö"
+		);
+	}
+}
@@ -0,0 +1,165 @@
+//! How much a peer can still write under a folder, sent with listings of
+//! folders it may write to. The value is a hint: other writers can use up
+//! the space between the listing and an upload, so it only warns about
+//! uploads that obviously won't fit and never stands in for the write
+//! errors themselves.
+
+use crate::format::{SizeUnits, human_size};
+use crate::p2p::DiskInfo;
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How long disk space read for one listing answers the next ones.
+pub const DISK_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Available space of the disk holding `path`: the one with the longest
+/// mount path it lies under.
+fn volume_available(disks: &[DiskInfo], path: &Path) -> Option<u64> {
+	disks
+		.iter()
+		.filter(|disk| !disk.mount_path.is_empty() && path.starts_with(&disk.mount_path))
+		.max_by_key(|disk| disk.mount_path.len())
+		.map(|disk| disk.available_space)
+}
+
+/// Disks read at most once per [`DISK_CACHE_TTL`], so listing many folders
+/// doesn't query the system each time.
+#[derive(Default)]
+pub(crate) struct DiskCache {
+	disks: Vec<DiskInfo>,
+	refreshed_at: Option<Instant>,
+}
+
+impl DiskCache {
+	pub(crate) fn is_stale(&self, now: Instant) -> bool {
+		self.refreshed_at
+			.is_none_or(|refreshed_at| now.duration_since(refreshed_at) >= DISK_CACHE_TTL)
+	}
+
+	pub(crate) fn refresh(&mut self, disks: Vec<DiskInfo>, now: Instant) {
+		self.disks = disks;
+		self.refreshed_at = Some(now);
+	}
+
+	pub(crate) fn available_for(&self, path: &Path) -> Option<u64> {
+		volume_available(&self.disks, path)
+	}
+}
+
+/// The space left to a peer under a folder: the disk's available space,
+/// capped by what the peer's quota there still allows when it has one.
+pub(crate) fn free_hint(volume_available: Option<u64>, quota_left: Option<u64>) -> Option<u64> {
+	match (volume_available, quota_left) {
+		(Some(volume), Some(quota)) => Some(volume.min(quota)),
+		(volume, quota) => volume.or(quota),
+	}
+}
+
+/// "~34.00 GB available to you here", for the header of a listing.
+pub fn describe_free_hint(bytes: u64) -> String {
+	format!(
+		"~{} available to you here",
+		human_size(bytes, SizeUnits::Decimal)
+	)
+}
+
+/// An upload larger than the space the folder's listing reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WontFit {
+	pub size: u64,
+	pub free_hint: u64,
+}
+
+impl fmt::Display for WontFit {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{} won't fit: only about {} is available there",
+			human_size(self.size, SizeUnits::Decimal),
+			human_size(self.free_hint, SizeUnits::Decimal)
+		)
+	}
+}
+
+impl std::error::Error for WontFit {}
+
+/// Refuses an upload of `size` bytes into a folder whose listing hinted
+/// less room. Without a hint there is nothing to check against.
+pub fn check_fits(size: u64, free_hint: Option<u64>) -> Result<(), WontFit> {
+	match free_hint {
+		Some(free_hint) if size > free_hint => Err(WontFit { size, free_hint }),
+		_ => Ok(()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const GB: u64 = 1_000_000_000;
+
+	fn disk(mount_path: &str, available_space: u64) -> DiskInfo {
+		DiskInfo {
+			name: String::new(),
+			mount_path: mount_path.to_string(),
+			filesystem: String::from("ext4"),
+			total_space: 1000 * GB,
+			available_space,
+			usage_percent: 0.0,
+			total_read_bytes: 0,
+			total_written_bytes: 0,
+			read_only: false,
+			removable: false,
+			kind: String::from("SSD"),
+			id: String::new(),
+		}
+	}
+
+	#[test]
+	fn the_quota_caps_a_roomy_disk() {
+		let mut cache = DiskCache::default();
+		cache.refresh(vec![disk("/", 500 * GB)], Instant::now());
+		let volume = cache.available_for(Path::new("/srv/share"));
+		assert_eq!(free_hint(volume, Some(34 * GB)), Some(34 * GB));
+		assert_eq!(
+			check_fits(40 * GB, free_hint(volume, Some(34 * GB))),
+			Err(WontFit {
+				size: 40 * GB,
+				free_hint: 34 * GB,
+			})
+		);
+	}
+
+	#[test]
+	fn a_full_disk_caps_the_quota() {
+		let mut cache = DiskCache::default();
+		cache.refresh(
+			vec![disk("/", 500 * GB), disk("/media/usb", 2 * GB)],
+			Instant::now(),
+		);
+		let volume = cache.available_for(Path::new("/media/usb/photos"));
+		assert_eq!(volume, Some(2 * GB));
+		assert_eq!(free_hint(volume, Some(34 * GB)), Some(2 * GB));
+		assert_eq!(free_hint(volume, None), Some(2 * GB));
+		assert_eq!(describe_free_hint(2 * GB), "~2.00 GB available to you here");
+	}
+
+	#[test]
+	fn without_a_hint_every_upload_is_let_through() {
+		let cache = DiskCache::default();
+		assert_eq!(cache.available_for(Path::new("/srv/share")), None);
+		assert_eq!(free_hint(None, None), None);
+		assert_eq!(check_fits(u64::MAX, None), Ok(()));
+	}
+
+	#[test]
+	fn disks_are_read_again_only_once_stale() {
+		let start = Instant::now();
+		let mut cache = DiskCache::default();
+		assert!(cache.is_stale(start));
+		cache.refresh(vec![disk("/", GB)], start);
+		assert!(!cache.is_stale(start + Duration::from_secs(5)));
+		assert!(cache.is_stale(start + DISK_CACHE_TTL));
+	}
+}
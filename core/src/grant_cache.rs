@@ -0,0 +1,265 @@
+//! What other peers allow this node, remembered so that opening a peer
+//! doesn't have to wait for `ListPermissions` every time. An entry younger
+//! than the TTL is answered as is; an older one is still answered, while a
+//! refetch runs in the background. A `PermissionsChanged` push replaces
+//! the entry, and an "Access denied" for a path the entry allowed drops it,
+//! since the peer changed its grants without telling us.
+
+use crate::config;
+use crate::p2p::PeerReq;
+use crate::state::{FLAG_PREVIEW, FLAG_READ, FLAG_WRITE, Permission, Rule};
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const GRANT_CACHE_TTL_SETTING: &str = "grant_cache_ttl_secs";
+pub const DEFAULT_GRANT_CACHE_TTL: chrono::Duration = chrono::Duration::minutes(10);
+
+/// TTL stored under [`GRANT_CACHE_TTL_SETTING`], in seconds.
+pub fn ttl_from_setting(value: Option<&str>) -> chrono::Duration {
+	value
+		.and_then(|value| value.trim().parse::<i64>().ok())
+		.filter(|secs| *secs > 0)
+		.map(chrono::Duration::seconds)
+		.unwrap_or_else(|| config::startup().grant_cache_ttl)
+}
+
+/// Grants a peer gave this node, as last fetched or pushed.
+#[derive(Clone, Debug)]
+pub struct RemoteGrants {
+	pub permissions: Vec<Permission>,
+	pub fetched_at: DateTime<Utc>,
+	/// Older than the TTL; a refetch is on its way.
+	pub revalidating: bool,
+}
+
+/// The path and the access a filesystem request needs from the peer;
+/// any of the flags in the mask will do.
+pub(crate) fn requested_access(request: &PeerReq) -> Option<(PathBuf, u8)> {
+	match request {
+		PeerReq::ListDir { path } | PeerReq::StatFile { path } => {
+			Some((path.to_path_buf(), FLAG_READ | FLAG_PREVIEW))
+		}
+		PeerReq::ReadFile { path, .. } => Some((path.to_path_buf(), FLAG_READ)),
+		PeerReq::WriteFile { path, .. } => Some((path.to_path_buf(), FLAG_WRITE)),
+		PeerReq::GetThumbnail { path, .. } | PeerReq::ContactSheet { path, .. } => {
+			Some((PathBuf::from(path), FLAG_READ | FLAG_PREVIEW))
+		}
+		PeerReq::Traced { request, .. } => requested_access(request),
+		_ => None,
+	}
+}
+
+/// Answer to a lookup in the [`GrantCache`].
+#[derive(Clone, Debug)]
+pub(crate) enum Lookup {
+	Fresh(RemoteGrants),
+	/// Worth answering with, but due for a refetch.
+	Stale(RemoteGrants),
+	Missing,
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+	permissions: Vec<Permission>,
+	fetched_at: DateTime<Utc>,
+}
+
+impl Entry {
+	/// True when a folder grant covers `path` with any of `flags`.
+	fn allows(&self, path: &Path, flags: u8) -> bool {
+		self.permissions
+			.iter()
+			.any(|permission| match permission.rule() {
+				Rule::Owner => true,
+				Rule::Folder(folder) => {
+					folder.flags() & flags != 0 && path.starts_with(folder.path())
+				}
+				_ => false,
+			})
+	}
+}
+
+/// Grants per peer, mirrored in the `remote_grants` table.
+#[derive(Debug)]
+pub(crate) struct GrantCache {
+	entries: HashMap<PeerId, Entry>,
+	ttl: chrono::Duration,
+}
+
+impl GrantCache {
+	pub(crate) fn new(ttl: chrono::Duration) -> Self {
+		Self {
+			entries: HashMap::new(),
+			ttl,
+		}
+	}
+
+	pub(crate) fn set_ttl(&mut self, ttl: chrono::Duration) {
+		self.ttl = ttl;
+	}
+
+	/// Replaces the cache with stored entries, fetched at unix seconds.
+	pub(crate) fn load(&mut self, stored: Vec<(PeerId, Vec<Permission>, i64)>) {
+		self.entries = stored
+			.into_iter()
+			.filter_map(|(peer, permissions, fetched_at)| {
+				let fetched_at = DateTime::from_timestamp(fetched_at, 0)?;
+				Some((
+					peer,
+					Entry {
+						permissions,
+						fetched_at,
+					},
+				))
+			})
+			.collect();
+	}
+
+	pub(crate) fn contains(&self, peer: &PeerId) -> bool {
+		self.entries.contains_key(peer)
+	}
+
+	pub(crate) fn lookup(&self, peer: &PeerId, now: DateTime<Utc>) -> Lookup {
+		let Some(entry) = self.entries.get(peer) else {
+			return Lookup::Missing;
+		};
+		let stale = now - entry.fetched_at >= self.ttl;
+		let grants = RemoteGrants {
+			permissions: entry.permissions.clone(),
+			fetched_at: entry.fetched_at,
+			revalidating: stale,
+		};
+		if stale {
+			Lookup::Stale(grants)
+		} else {
+			Lookup::Fresh(grants)
+		}
+	}
+
+	pub(crate) fn store(&mut self, peer: PeerId, permissions: Vec<Permission>, now: DateTime<Utc>) {
+		self.entries.insert(
+			peer,
+			Entry {
+				permissions,
+				fetched_at: now,
+			},
+		);
+	}
+
+	/// Drops the entry for `peer` when it claimed `path` was open to any of
+	/// `flags`, which the peer just refused. True when it did.
+	pub(crate) fn refuted(&mut self, peer: &PeerId, path: &Path, flags: u8) -> bool {
+		let refuted = self
+			.entries
+			.get(peer)
+			.is_some_and(|entry| entry.allows(path, flags));
+		if refuted {
+			self.entries.remove(peer);
+		}
+		refuted
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::state::{FLAG_SEARCH, FolderRule};
+
+	fn folder(path: &str, flags: u8) -> Permission {
+		Permission::new(Rule::Folder(FolderRule::new(path.into(), flags)))
+	}
+
+	fn rules(grants: &RemoteGrants) -> Vec<Rule> {
+		grants
+			.permissions
+			.iter()
+			.map(|permission| permission.rule().clone())
+			.collect()
+	}
+
+	fn at(secs: i64) -> DateTime<Utc> {
+		DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+	}
+
+	#[test]
+	fn entries_are_fresh_until_the_ttl_runs_out() {
+		let peer = PeerId::random();
+		let mut cache = GrantCache::new(chrono::Duration::minutes(10));
+		assert!(matches!(cache.lookup(&peer, at(0)), Lookup::Missing));
+
+		let photos = folder("/srv/photos", FLAG_READ | FLAG_SEARCH);
+		cache.store(peer, vec![photos.clone()], at(0));
+		let Lookup::Fresh(hit) = cache.lookup(&peer, at(599)) else {
+			panic!("expected a fresh entry");
+		};
+		assert_eq!(rules(&hit), [photos.rule().clone()]);
+		assert!(!hit.revalidating);
+
+		let Lookup::Stale(stale) = cache.lookup(&peer, at(600)) else {
+			panic!("expected a stale entry");
+		};
+		assert!(stale.revalidating);
+		assert_eq!(stale.fetched_at, at(0));
+	}
+
+	#[test]
+	fn pushed_grants_replace_the_entry() {
+		let peer = PeerId::random();
+		let mut cache = GrantCache::new(chrono::Duration::minutes(10));
+		cache.load(vec![(
+			peer,
+			vec![folder("/srv/photos", FLAG_READ)],
+			at(0).timestamp(),
+		)]);
+		assert!(matches!(cache.lookup(&peer, at(900)), Lookup::Stale(_)));
+
+		let music = folder("/srv/music", FLAG_READ);
+		cache.store(peer, vec![music.clone()], at(900));
+		let Lookup::Fresh(hit) = cache.lookup(&peer, at(901)) else {
+			panic!("expected a fresh entry");
+		};
+		assert_eq!(rules(&hit), [music.rule().clone()]);
+	}
+
+	#[test]
+	fn denials_drop_entries_that_allowed_the_path() {
+		let peer = PeerId::random();
+		let mut cache = GrantCache::new(chrono::Duration::minutes(10));
+		cache.store(peer, vec![folder("/srv/photos", FLAG_READ)], at(0));
+
+		// Not covered by the cache, so the denial is no news.
+		assert!(!cache.refuted(&peer, Path::new("/srv/music/a.mp3"), FLAG_READ));
+		assert!(!cache.refuted(&peer, Path::new("/srv/photos/cat.jpg"), FLAG_WRITE));
+		assert!(matches!(cache.lookup(&peer, at(1)), Lookup::Fresh(_)));
+
+		assert!(cache.refuted(&peer, Path::new("/srv/photos/cat.jpg"), FLAG_READ));
+		assert!(matches!(cache.lookup(&peer, at(1)), Lookup::Missing));
+	}
+
+	#[test]
+	fn filesystem_requests_name_the_access_they_need() {
+		let read = PeerReq::ReadFile {
+			path: "/srv/photos/cat.jpg".into(),
+			offset: 0,
+			length: None,
+			sparse: false,
+		};
+		assert_eq!(
+			requested_access(&read),
+			Some((PathBuf::from("/srv/photos/cat.jpg"), FLAG_READ))
+		);
+		let traced = PeerReq::Traced {
+			corr: 1,
+			request: Box::new(PeerReq::ListDir {
+				path: "/srv".into(),
+			}),
+		};
+		assert_eq!(
+			requested_access(&traced),
+			Some((PathBuf::from("/srv"), FLAG_READ | FLAG_PREVIEW))
+		);
+		assert_eq!(requested_access(&PeerReq::ListPermissions), None);
+	}
+}
@@ -1,134 +1,750 @@
+use crate::activity_log::{
+	ActivityEvent, ActivityEventKind, ActivityFilter, DEFAULT_ACTIVITY_PAGE, MAX_ACTIVITY_PAGE,
+};
+use crate::activity_window::ActivityWindow;
 use crate::auth;
-use crate::puppynet::PuppyNet;
+use crate::backup::{BackupRun, BackupSettings};
+use crate::config;
+use crate::contact_sheet::{ContactSheetOptions, ContactSheetResult};
+use crate::cors::CorsSettings;
+use crate::diff::{DiffOptions, FileRef};
+use crate::discovered::DiscoveredPeerFilter;
+use crate::format::hex;
+use crate::image_decode::{ImageTooLarge, decoded_size, header_dimensions};
+use crate::login_guard::LoginSource;
+use crate::maintenance::Maintenance;
+use crate::openapi::{ApiRoute, ApiSchema, DOCS_HTML, api_struct, document};
+use crate::p2p::{CpuInfo, DirEntry, DiskInfo, InterfaceInfo, ListingAccess, WirePath};
+use crate::pagination::PageCursor;
+use crate::password_policy::{CreateUserError, UserFieldError};
+use crate::path::SafePath;
+use crate::pins::{PinOptions, PinStatus};
+use crate::power::{PowerPolicy, PowerState};
+use crate::preview::{
+	FilePreview, PREVIEW_MAX_IMAGE_SIZE, PREVIEW_MAX_PAGE_SIZE, PREVIEW_PAGE_SIZE, PreviewKind,
+	build_preview,
+};
+use crate::puppynet::{LoginResult, PuppyNet};
+use crate::readahead::Readahead;
+use crate::request_trace::{REQUEST_TRACE_CAPACITY, RequestTrace, slowest};
 use crate::scan::ScanEvent;
+use crate::scan_limits::ScanOverrides;
+use crate::secrets::JWT_SECRET;
+use crate::state::{
+	ConnectionDirection, FLAG_PREVIEW, FLAG_READ, FLAG_SEARCH, FLAG_WRITE, PermissionConflict,
+	TemporaryGrant, TrustLevel, effective_permission_rules, permission_overlaps,
+};
 use crate::updater::UpdateProgress;
-use crate::{Permission, SearchFilesArgs};
+use crate::{
+	AddressReachability, BatchGrantOutcome, DraftRejected, FieldError, FileChunk, FileDiff,
+	FileOrigin, FileSearchResult, FolderRule, GrowthReport, IdKind, IdentityMismatch,
+	PROJECTION_DAYS, PeerSearch, PeerSearchHit, Permission, PermissionDraft, ReadToEndOptions,
+	RuleDraft, ScanDiffEntry, ScanResultRow, ScanRun, ScanTrend, SearchFilesArgs, SearchSortBy,
+	ShellOutput, StorageUsageFile,
+};
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use futures::stream::unfold;
 use hyper::body::{Buf, Bytes};
 use hyper::header::{
-	ACCEPT_RANGES, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
-	ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_LENGTH, CONTENT_RANGE,
-	CONTENT_TYPE, HeaderValue, ORIGIN, RANGE, SET_COOKIE,
+	ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, HeaderValue, ORIGIN, RANGE,
+	RETRY_AFTER, SET_COOKIE, USER_AGENT,
 };
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server, StatusCode};
 use libp2p::PeerId;
-use log::warn;
 use mime_guess::from_path;
-use rand::RngCore;
-use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::collections::HashMap;
 use std::convert::Infallible;
-use std::env;
-use std::fmt::Write;
 use std::io::{ErrorKind, SeekFrom};
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::net::TcpListener;
+use tokio::sync::mpsc::error::TryRecvError;
 use tokio::{signal, task};
 use url::form_urlencoded;
 
 const CT_JSON: &str = "application/json";
 const SESSION_COOKIE: &str = "sid";
 const SESSION_TTL_SECS: i64 = 60 * 60 * 24 * 7;
+const DISK_HISTORY_DEFAULT_DAYS: i64 = 7;
+/// Longest period a storage growth report covers.
+const MAX_GROWTH_DAYS: i64 = 3650;
+const PROTOCOL_HISTORY_DEFAULT_DAYS: u64 = 7;
+/// Most recently seen discovered peers listed by `/api/state`.
+const STATE_DISCOVERED_LIMIT: usize = 200;
+
+api_struct! {
+	#[derive(Deserialize)]
+	struct CreateUserRequest {
+		username: String,
+		password: String,
+	}
+}
+
+api_struct! {
+	#[derive(Deserialize)]
+	struct LoginRequest {
+		username: String,
+		password: String,
+		set_cookie: Option<bool>,
+	}
+}
+
+api_struct! {
+	#[derive(Deserialize)]
+	struct PermissionsRequest {
+		permissions: Vec<Permission>,
+		merge: Option<bool>,
+	}
+}
+
+api_struct! {
+	#[derive(Deserialize)]
+	struct SetPermissionsRequest {
+		permissions: Vec<Permission>,
+		/// Revision the client edited; omit to overwrite unconditionally.
+		#[serde(default)]
+		expected_revision: Option<u64>,
+	}
+}
+
+api_struct! {
+	#[derive(Deserialize)]
+	struct TemporaryGrantRequest {
+		path: String,
+		/// Defaults to read and search.
+		#[serde(default)]
+		write: bool,
+		/// Listings and capped thumbnails instead of read access.
+		#[serde(default)]
+		preview_only: bool,
+		ttl_secs: u64,
+	}
+}
+
+api_struct! {
+	#[derive(Deserialize)]
+	struct BatchGrantRequest {
+		path: String,
+		/// Defaults to read and search.
+		#[serde(default)]
+		write: bool,
+		/// Listings and capped thumbnails instead of read access.
+		#[serde(default)]
+		preview_only: bool,
+		/// Names the peers never see under the folder.
+		#[serde(default)]
+		deny_patterns: Vec<String>,
+		peers: Vec<String>,
+	}
+}
+
+api_struct! {
+	#[derive(Deserialize, Default)]
+	struct BackupRequest {
+		#[serde(default)]
+		dir: Option<String>,
+	}
+}
+
+api_struct! {
+	#[derive(Deserialize)]
+	struct MaintenanceRequest {
+		reason: String,
+		/// How long the maintenance is expected to take.
+		#[serde(default)]
+		expected_secs: Option<u64>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct MaintenanceResponse {
+		maintenance: Option<Maintenance>,
+		/// Mutating work running now.
+		running: Vec<String>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct MaintenanceReportResponse {
+		maintenance: Maintenance,
+		/// Mutating work still running when the wait ended.
+		pending: Vec<String>,
+		checkpointed: bool,
+		safe_to_copy: bool,
+		summary: String,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct BackupRunResponse {
+		run: BackupRun,
+	}
+}
+
+api_struct! {
+	#[derive(Deserialize)]
+	struct RestoreRequest {
+		path: String,
+		#[serde(default)]
+		force: bool,
+	}
+}
+
+api_struct! {
+	#[derive(Deserialize)]
+	struct PinRequest {
+		peer: String,
+		remote_path: String,
+		local_dest: String,
+		#[serde(default)]
+		interval_secs: Option<u64>,
+		#[serde(default)]
+		bandwidth_limit: Option<u64>,
+		#[serde(default)]
+		delete_removed: Option<bool>,
+		#[serde(default)]
+		wake_if_needed: Option<bool>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct PinsResponse {
+		pins: Vec<PinStatus>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct PinResponse {
+		pin: PinStatus,
+	}
+}
+
+api_struct! {
+	#[derive(Deserialize)]
+	struct ScanStartRequest {
+		path: String,
+		#[serde(default)]
+		override_window: bool,
+		#[serde(default)]
+		extract_metadata: bool,
+		/// In place of the node's scan limits for this scan.
+		#[serde(default)]
+		limits: ScanOverrides,
+	}
+}
+
+api_struct! {
+	#[derive(Deserialize)]
+	struct UpdateStartRequest {
+		version: Option<String>,
+		#[serde(default)]
+		override_window: bool,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct ShellStartResponse {
+		id: u64,
+	}
+}
+
+api_struct! {
+	#[derive(Deserialize)]
+	struct ShellInputRequest {
+		id: u64,
+		data: Vec<u8>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct ShellOutputResponse {
+		data: Vec<u8>,
+		/// The shell exited; the session takes no more input.
+		exited: bool,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct StateResponse {
+		me: String,
+		peers: Vec<PeerSummary>,
+		discovered: Vec<DiscoveredSummary>,
+		users: Vec<UserSummary>,
+		shared_folders: Vec<SharedFolderSummary>,
+		connections: Vec<ConnectionSummary>,
+		remote_access_suspended: bool,
+		/// Set while the node refuses every change.
+		maintenance: Option<Maintenance>,
+		identity_mismatch: Option<IdentityMismatch>,
+		/// Last dial-back self-test of this node's addresses.
+		reachability: Vec<AddressReachability>,
+		/// Power supply and whether background work waits for it.
+		power: PowerState,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct ConnectionSummary {
+		peer_id: String,
+		direction: ConnectionDirection,
+		transport: &'static str,
+		local_addr: Option<String>,
+		remote_addr: String,
+		connected_at: DateTime<Utc>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct PeerSummary {
+		id: String,
+		name: Option<String>,
+		node_id: Option<String>,
+		/// Connections that closed within the last hour.
+		recent_drops: usize,
+		/// Estimated offset of the peer's clock from ours, once measured.
+		clock_offset_ms: Option<i64>,
+		/// "peer clock is ~4m ahead" when the offset is worth a warning.
+		clock_skew: Option<String>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct DiscoveredSummary {
+		peer_id: String,
+		multiaddr: String,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct UserSummary {
+		name: String,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct SharedFolderSummary {
+		path: String,
+		flags: u8,
+	}
+}
+
+/// What went wrong, derived from the response status.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ApiErrorKind {
+	BadRequest,
+	Unauthorized,
+	NotFound,
+	Conflict,
+	RateLimited,
+	Internal,
+}
+
+impl ApiErrorKind {
+	fn from_status(status: StatusCode) -> Self {
+		match status {
+			StatusCode::UNAUTHORIZED => Self::Unauthorized,
+			StatusCode::NOT_FOUND => Self::NotFound,
+			StatusCode::CONFLICT => Self::Conflict,
+			StatusCode::TOO_MANY_REQUESTS => Self::RateLimited,
+			status if status.is_server_error() => Self::Internal,
+			_ => Self::BadRequest,
+		}
+	}
+}
+
+impl ApiSchema for ApiErrorKind {
+	fn schema() -> serde_json::Value {
+		json!({
+			"type": "string",
+			"enum": ["bad_request", "unauthorized", "not_found", "conflict", "rate_limited", "internal"],
+		})
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct ApiErrorBody {
+		kind: ApiErrorKind,
+		message: String,
+	}
+}
+
+api_struct! {
+	/// Body of every error response.
+	#[derive(Serialize)]
+	struct ApiError {
+		error: ApiErrorBody,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct LoginResponse {
+		access_token: String,
+	}
+}
+
+api_struct! {
+	/// `429` answer to a login while the source is throttled or locked out.
+	#[derive(Serialize)]
+	struct LoginThrottledResponse {
+		error: ApiErrorBody,
+		retry_after_secs: u64,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct MeResponse {
+		user: String,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct UsersResponse {
+		users: Vec<String>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct CreateUserResponse {
+		username: String,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct PeerListResponse {
+		peers: Vec<PeerListEntry>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct PeerListEntry {
+		id: String,
+		name: Option<String>,
+		/// Which kinds of request the peer may send this node.
+		trust: TrustLevel,
+	}
+}
+
+api_struct! {
+	#[derive(Deserialize)]
+	struct PeerUpdateRequest {
+		trust: TrustLevel,
+	}
+}
+
+api_struct! {
+	/// Raw grants plus the merged folder rules and overlap warnings, so clients
+	/// can show which rule decides access.
+	#[derive(Serialize)]
+	struct PermissionsResponse {
+		permissions: Vec<Permission>,
+		effective: Vec<FolderRule>,
+		warnings: Vec<String>,
+		/// Only for the permissions this node granted.
+		#[serde(skip_serializing_if = "Option::is_none")]
+		revision: Option<u64>,
+		#[serde(skip_serializing_if = "Option::is_none")]
+		temporary: Option<Vec<TemporaryGrantResponse>>,
+		/// Only for the permissions a peer granted us: when they were
+		/// fetched, and whether a refetch is running.
+		#[serde(skip_serializing_if = "Option::is_none")]
+		fetched_at: Option<DateTime<Utc>>,
+		#[serde(skip_serializing_if = "Option::is_none")]
+		revalidating: Option<bool>,
+	}
+}
+
+api_struct! {
+	/// `409` answer when the grants changed since `expected_revision`; carries
+	/// the latest set so the client can redo its edit.
+	#[derive(Serialize)]
+	struct PermissionConflictResponse {
+		error: ApiErrorBody,
+		permissions: Vec<Permission>,
+		effective: Vec<FolderRule>,
+		warnings: Vec<String>,
+		revision: u64,
+	}
+}
+
+api_struct! {
+	/// Rules granted to the peer as an editor shows them, one row each.
+	#[derive(Serialize)]
+	struct PermissionDraftResponse {
+		rules: Vec<RuleDraft>,
+		warnings: Vec<String>,
+		revision: u64,
+	}
+}
+
+api_struct! {
+	#[derive(Deserialize)]
+	struct SavePermissionDraftRequest {
+		rules: Vec<RuleDraft>,
+		/// Add the rules to those the peer has instead of replacing them.
+		#[serde(default)]
+		merge: Option<bool>,
+		/// Revision the client edited; omit to overwrite unconditionally.
+		#[serde(default)]
+		expected_revision: Option<u64>,
+	}
+}
+
+api_struct! {
+	/// `400` answer to a draft that doesn't validate, one entry per field to
+	/// fix.
+	#[derive(Serialize)]
+	struct DraftErrorsResponse {
+		error: ApiErrorBody,
+		errors: Vec<FieldError>,
+	}
+}
+
+api_struct! {
+	/// `400` answer to a user the password policy refuses, one entry per
+	/// field to fix. A taken username is a `409` instead.
+	#[derive(Serialize)]
+	struct UserErrorsResponse {
+		error: ApiErrorBody,
+		errors: Vec<UserFieldError>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct PermissionListResponse {
+		permissions: Vec<Permission>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct WarningsResponse {
+		warnings: Vec<String>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct BatchGrantResponse {
+		results: Vec<BatchGrantEntry>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct BatchGrantEntry {
+		peer_id: String,
+		/// `granted`, `unchanged` or `failed`.
+		outcome: &'static str,
+		error: Option<String>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct TemporaryGrantResponse {
+		id: u64,
+		rule: FolderRule,
+		expires_at: DateTime<Utc>,
+		remaining_secs: u64,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct TemporaryGrantsResponse {
+		temporary: Vec<TemporaryGrantResponse>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct DirResponse {
+		entries: Vec<DirEntry>,
+		/// Bytes the peer says this node may still write in the folder. A
+		/// hint; absent for folders it can't write to and from older peers.
+		#[serde(skip_serializing_if = "Option::is_none")]
+		free_hint: Option<u64>,
+		/// The access flags the peer says this node holds in the folder;
+		/// absent from older peers.
+		#[serde(skip_serializing_if = "Option::is_none")]
+		access: Option<ListingAccess>,
+	}
+}
 
-#[derive(Deserialize)]
-struct CreateUserRequest {
-	username: String,
-	password: String,
+api_struct! {
+	#[derive(Serialize)]
+	struct DisksResponse {
+		disks: Vec<DiskInfo>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct InterfacesResponse {
+		interfaces: Vec<InterfaceInfo>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct CpusResponse {
+		cpus: Vec<CpuInfo>,
+	}
+}
+
+api_struct! {
+	#[derive(Serialize)]
+	struct RequestTracesResponse {
+		/// Slowest first.
+		requests: Vec<RequestTrace>,
+	}
 }
 
-#[derive(Deserialize)]
-struct LoginRequest {
-	username: String,
-	password: String,
-	set_cookie: Option<bool>,
+api_struct! {
+	#[derive(Serialize)]
+	struct RemoteAccessResponse {
+		suspended: bool,
+	}
 }
 
-#[derive(Deserialize)]
-struct PermissionsRequest {
-	permissions: Vec<Permission>,
-	merge: Option<bool>,
+api_struct! {
+	#[derive(Serialize)]
+	struct StorageResponse {
+		files: Vec<StorageUsageFile>,
+	}
 }
 
-#[derive(Deserialize)]
-struct SetPermissionsRequest {
-	permissions: Vec<Permission>,
+api_struct! {
+	#[derive(Serialize)]
+	struct DiffResponse {
+		diff: FileDiff,
+	}
 }
 
-#[derive(Deserialize)]
-struct ScanStartRequest {
-	path: String,
+api_struct! {
+	#[derive(Serialize)]
+	struct ScanStartResponse {
+		scan_id: u64,
+	}
 }
 
-#[derive(Deserialize)]
-struct UpdateStartRequest {
-	version: Option<String>,
+api_struct! {
+	#[derive(Serialize)]
+	struct ScanEventsResponse {
+		events: Vec<ScanEvent>,
+	}
 }
 
-#[derive(Serialize)]
-struct ShellStartResponse {
-	id: u64,
+api_struct! {
+	#[derive(Serialize)]
+	struct ScanResultsResponse {
+		rows: Vec<ScanResultRow>,
+		total: usize,
+		page: usize,
+		page_size: usize,
+		next_cursor: Option<String>,
+	}
 }
 
-#[derive(Deserialize)]
-struct ShellInputRequest {
-	id: u64,
-	data: Vec<u8>,
+api_struct! {
+	#[derive(Serialize)]
+	struct ScanHistoryResponse {
+		runs: Vec<ScanRun>,
+		/// Only when filtering by `path`.
+		trend: Option<ScanTrend>,
+		page: u64,
+	}
 }
 
-#[derive(Serialize)]
-struct ShellOutputResponse {
-	data: Vec<u8>,
+api_struct! {
+	#[derive(Serialize)]
+	struct ActivityResponse {
+		events: Vec<ActivityEvent>,
+		next_cursor: Option<String>,
+	}
 }
 
-#[derive(Serialize)]
-struct StateResponse {
-	me: String,
-	peers: Vec<PeerSummary>,
-	discovered: Vec<DiscoveredSummary>,
-	users: Vec<UserSummary>,
-	shared_folders: Vec<SharedFolderSummary>,
+api_struct! {
+	#[derive(Serialize)]
+	struct ScanDiffResponse {
+		entries: Vec<ScanDiffEntry>,
+		page: u64,
+	}
 }
 
-#[derive(Serialize)]
-struct PeerSummary {
-	id: String,
-	name: Option<String>,
-	node_id: Option<String>,
+api_struct! {
+	#[derive(Serialize)]
+	struct SearchResponse {
+		results: Vec<FileSearchResult>,
+		mime_types: Vec<String>,
+		total: usize,
+		next_cursor: Option<String>,
+		/// Peers left out of a `peer=all` search, as `peer: reason`.
+		#[serde(skip_serializing_if = "Vec::is_empty")]
+		skipped: Vec<String>,
+	}
 }
 
-#[derive(Serialize)]
-struct DiscoveredSummary {
-	peer_id: String,
-	multiaddr: String,
+api_struct! {
+	#[derive(Serialize)]
+	struct MimeTypesResponse {
+		mime_types: Vec<String>,
+	}
 }
 
-#[derive(Serialize)]
-struct UserSummary {
-	name: String,
+api_struct! {
+	#[derive(Serialize)]
+	struct UpdateStartResponse {
+		update_id: u64,
+	}
 }
 
-#[derive(Serialize)]
-struct SharedFolderSummary {
-	path: String,
-	flags: u8,
+api_struct! {
+	#[derive(Serialize)]
+	struct UpdateEventsResponse {
+		events: Vec<UpdateProgress>,
+	}
 }
 
 struct ApiState {
 	puppy: Arc<PuppyNet>,
 	scans: Mutex<HashMap<u64, crate::puppynet::ScanHandle>>,
-	next_scan_id: AtomicU64,
-	updates: Mutex<HashMap<u64, Arc<Mutex<std::sync::mpsc::Receiver<UpdateProgress>>>>>,
-	next_update_id: AtomicU64,
+	updates: Mutex<HashMap<u64, tokio::sync::mpsc::Receiver<UpdateProgress>>>,
 	jwt_secret: String,
+	readahead: Readahead,
+	local_peer: OnceLock<PeerId>,
 }
 
 impl ApiState {
@@ -136,15 +752,48 @@ impl ApiState {
 		Self {
 			puppy,
 			scans: Mutex::new(HashMap::new()),
-			next_scan_id: AtomicU64::new(1),
 			updates: Mutex::new(HashMap::new()),
-			next_update_id: AtomicU64::new(1),
 			jwt_secret,
+			readahead: Readahead::default(),
+			local_peer: OnceLock::new(),
+		}
+	}
+
+	async fn is_local_peer(&self, peer: PeerId) -> bool {
+		if let Some(me) = self.local_peer.get() {
+			return *me == peer;
+		}
+		match self.puppy.state_snapshot().await {
+			Some(snapshot) => *self.local_peer.get_or_init(|| snapshot.me) == peer,
+			None => false,
+		}
+	}
+
+	/// Ranged read for media streaming. Files on other peers go through the
+	/// readahead buffer so sequential ranges do not each cost a round trip.
+	async fn read_range(
+		&self,
+		peer: PeerId,
+		path: &str,
+		start: u64,
+		length: u64,
+	) -> Result<FileChunk> {
+		if self.is_local_peer(peer).await {
+			return self.puppy.read_file(peer, path, start, Some(length)).await;
 		}
+		let puppy = self.puppy.clone();
+		let fetch = move |offset, length| {
+			let puppy = puppy.clone();
+			let path = path.to_string();
+			async move { puppy.read_file(peer, path, offset, Some(length)).await }
+		};
+		self.readahead
+			.read(peer, path, start, Some(length), fetch)
+			.await
 	}
 
 	fn insert_scan(&self, handle: crate::puppynet::ScanHandle) -> u64 {
-		let id = self.next_scan_id.fetch_add(1, Ordering::SeqCst);
+		let id = self.puppy.next_id(IdKind::Scan);
 		self.scans.lock().unwrap().insert(id, handle);
 		id
 	}
@@ -153,16 +802,35 @@ impl ApiState {
 		let mut scans = self.scans.lock().unwrap();
 		let handle = scans.get(&id)?;
 		let receiver = handle.receiver();
-		let mut rx = receiver.lock().unwrap();
+		// HTTP scans are only read here, under the `scans` lock.
+		let Ok(mut rx) = receiver.try_lock() else {
+			return Some(Vec::new());
+		};
 		let mut events = Vec::new();
-		while let Ok(event) = rx.try_recv() {
-			let is_done = matches!(event, ScanEvent::Finished(_));
-			events.push(event);
-			if is_done {
-				scans.remove(&id);
-				break;
+		let mut done = false;
+		loop {
+			match rx.try_recv() {
+				Ok(event) => {
+					done = matches!(event, ScanEvent::Finished(_));
+					events.push(event);
+					if done {
+						break;
+					}
+				}
+				Err(TryRecvError::Empty) => break,
+				// The producer went away without finishing, so no more
+				// events can come.
+				Err(TryRecvError::Disconnected) => {
+					events.push(ScanEvent::Finished(Err(String::from("Scan stream closed"))));
+					done = true;
+					break;
+				}
 			}
 		}
+		drop(rx);
+		if done {
+			scans.remove(&id);
+		}
 		Some(events)
 	}
 
@@ -175,32 +843,45 @@ impl ApiState {
 		false
 	}
 
-	fn insert_update(&self, rx: Arc<Mutex<std::sync::mpsc::Receiver<UpdateProgress>>>) -> u64 {
-		let id = self.next_update_id.fetch_add(1, Ordering::SeqCst);
+	fn insert_update(&self, rx: tokio::sync::mpsc::Receiver<UpdateProgress>) -> u64 {
+		let id = self.puppy.next_id(IdKind::Update);
 		self.updates.lock().unwrap().insert(id, rx);
 		id
 	}
 
 	fn poll_update(&self, id: u64) -> Option<Vec<UpdateProgress>> {
 		let mut updates = self.updates.lock().unwrap();
-		let Some(rx) = updates.get(&id) else {
+		let Some(rx) = updates.get_mut(&id) else {
 			return None;
 		};
-		let guard = rx.lock().unwrap();
 		let mut events = Vec::new();
 		let mut should_remove = false;
-		for progress in guard.try_iter() {
-			if matches!(
-				progress,
-				UpdateProgress::Completed { .. }
-					| UpdateProgress::Failed { .. }
-					| UpdateProgress::AlreadyUpToDate { .. }
-			) {
-				should_remove = true;
+		loop {
+			match rx.try_recv() {
+				Ok(progress) => {
+					if matches!(
+						progress,
+						UpdateProgress::Completed { .. }
+							| UpdateProgress::Failed { .. }
+							| UpdateProgress::AlreadyUpToDate { .. }
+					) {
+						should_remove = true;
+					}
+					events.push(progress);
+				}
+				Err(TryRecvError::Empty) => break,
+				Err(TryRecvError::Disconnected) => {
+					if !should_remove {
+						events.push(UpdateProgress::Failed {
+							error: String::from("Update stream closed"),
+							kind: None,
+						});
+					}
+					should_remove = true;
+					break;
+				}
 			}
-			events.push(progress);
 		}
-		drop(guard);
 		if should_remove {
 			updates.remove(&id);
 		}
@@ -216,8 +897,24 @@ fn json_response(status: StatusCode, value: serde_json::Value) -> Response<Body>
 		.unwrap()
 }
 
+fn error_body(status: StatusCode, message: impl Into<String>) -> ApiErrorBody {
+	ApiErrorBody {
+		kind: ApiErrorKind::from_status(status),
+		message: message.into(),
+	}
+}
+
+fn error_response(status: StatusCode, message: impl Into<String>) -> Response<Body> {
+	json_response(
+		status,
+		json!(ApiError {
+			error: error_body(status, message),
+		}),
+	)
+}
+
 fn bad_request(msg: impl Into<String>) -> Response<Body> {
-	json_response(StatusCode::BAD_REQUEST, json!({ "error": msg.into() }))
+	error_response(StatusCode::BAD_REQUEST, msg)
 }
 
 fn parse_query(req: &Request<Body>) -> HashMap<String, String> {
@@ -226,6 +923,43 @@ fn parse_query(req: &Request<Body>) -> HashMap<String, String> {
 		.collect()
 }
 
+/// A `path` parameter naming a file on a peer, checked before any request
+/// is sent; the peer checks it again against its own platform's rules.
+fn parse_peer_path(path: &str) -> Result<SafePath, String> {
+	SafePath::remote(&WirePath::from(path)).map_err(|err| err.to_string())
+}
+
+/// Accepts unix seconds or RFC 3339.
+fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+	if let Ok(secs) = value.parse::<i64>() {
+		return DateTime::from_timestamp(secs, 0).ok_or_else(|| format!("invalid time: {value}"));
+	}
+	DateTime::parse_from_rfc3339(value)
+		.map(|time| time.with_timezone(&Utc))
+		.map_err(|e| format!("invalid time {value}: {e}"))
+}
+
+/// Filter of `GET /api/activity`: `kinds` is a comma separated list.
+fn parse_activity_filter(query: &HashMap<String, String>) -> Result<ActivityFilter, String> {
+	let kinds = match query.get("kinds") {
+		Some(kinds) => kinds
+			.split(',')
+			.map(str::trim)
+			.filter(|kind| !kind.is_empty())
+			.map(|kind| {
+				ActivityEventKind::parse(kind).ok_or_else(|| format!("unknown kind: {kind}"))
+			})
+			.collect::<Result<_, _>>()?,
+		None => Vec::new(),
+	};
+	Ok(ActivityFilter {
+		kinds,
+		peer: query.get("peer").cloned(),
+		since: query.get("since").map(|v| parse_time(v)).transpose()?,
+		text: query.get("text").cloned(),
+	})
+}
+
 fn parse_peer_id(id: &str) -> Result<PeerId, String> {
 	PeerId::from_str(id).map_err(|e| format!("invalid peer id: {e}"))
 }
@@ -285,36 +1019,6 @@ fn authenticate(req: &Request<Body>, state: &Arc<ApiState>) -> Option<String> {
 	None
 }
 
-fn with_cors(mut resp: Response<Body>, origin: Option<&str>) -> Response<Body> {
-	let origin_value: HeaderValue = origin
-		.unwrap_or("*")
-		.parse()
-		.unwrap_or_else(|_| HeaderValue::from_static("*"));
-	resp.headers_mut()
-		.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin_value);
-	resp.headers_mut().insert(
-		ACCESS_CONTROL_ALLOW_HEADERS,
-		"content-type,authorization".parse().unwrap(),
-	);
-	resp.headers_mut().insert(
-		ACCESS_CONTROL_ALLOW_METHODS,
-		"GET,POST,PUT,DELETE,OPTIONS".parse().unwrap(),
-	);
-	resp.headers_mut().insert(
-		ACCESS_CONTROL_ALLOW_CREDENTIALS,
-		HeaderValue::from_static("true"),
-	);
-	resp
-}
-
-fn bytes_to_hex(bytes: &[u8]) -> String {
-	let mut buf = String::with_capacity(bytes.len() * 2);
-	for byte in bytes {
-		write!(buf, "{:02x}", byte).ok();
-	}
-	buf
-}
-
 fn peer_to_node_id(peer: &PeerId) -> Option<[u8; 16]> {
 	let mut node_id = [0u8; 16];
 	let bytes = peer.to_bytes();
@@ -496,122 +1200,614 @@ fn parse_peer_range_header(
 	Ok((start, Some(end)))
 }
 
-async fn infer_peer_file_size(state: &ApiState, peer: PeerId, path: &str) -> Option<u64> {
-	let normalized = path.replace('\\', "/").trim_end_matches('/').to_string();
-	if normalized.is_empty() {
-		return None;
-	}
-	let (parent, name) = match normalized.rsplit_once('/') {
-		Some((parent, name)) => {
-			let parent = if parent.is_empty() { "/" } else { parent };
-			(parent.to_string(), name.to_string())
-		}
-		None => ("/".to_string(), normalized.clone()),
-	};
-	let entries = state.puppy.list_dir(peer, parent).await.ok()?;
+async fn infer_peer_file_size(state: &ApiState, peer: PeerId, path: &SafePath) -> Option<u64> {
+	let name = path.file_name()?;
+	let entries = state.puppy.list_dir(peer, path.parent()?).await.ok()?;
 	let entry = entries
 		.into_iter()
-		.find(|entry| !entry.is_dir && entry.name == name)?;
+		.find(|entry| !entry.is_dir && entry.name.as_bytes() == name)?;
 	Some(entry.size)
 }
 
-fn load_jwt_secret() -> String {
-	if let Ok(value) = env::var("JWT_SECRET") {
-		let trimmed = value.trim();
-		if !trimmed.is_empty() {
-			return trimmed.to_string();
+/// Reads one page of a peer file and classifies it for the preview endpoint.
+async fn file_preview(
+	state: &ApiState,
+	peer: PeerId,
+	peer_id: &str,
+	path: &str,
+	offset: u64,
+	length: u64,
+) -> Result<FilePreview> {
+	let chunk = state
+		.puppy
+		.read_file_range(
+			peer,
+			path.to_string(),
+			offset..offset.saturating_add(length),
+		)
+		.await?;
+	let mut preview = build_preview(path, offset, &chunk.data, chunk.eof);
+	if preview.kind == PreviewKind::Image {
+		let size = match parse_peer_path(path) {
+			Ok(path) => infer_peer_file_size(state, peer, &path).await,
+			Err(_) => None,
+		};
+		let decodes_too_large = header_dimensions(&chunk.data).is_some_and(|(width, height)| {
+			decoded_size(width, height) > state.puppy.thumbnail_decode_budget()
+		});
+		if decodes_too_large
+			|| size
+				.map(|size| size > PREVIEW_MAX_IMAGE_SIZE)
+				.unwrap_or(false)
+		{
+			preview.kind = PreviewKind::TooLarge;
+		} else {
+			let query = form_urlencoded::Serializer::new(String::new())
+				.append_pair("path", path)
+				.finish();
+			preview.content = format!("/api/peers/{peer_id}/thumbnail?{query}");
 		}
 	}
-	let mut bytes = [0u8; 32];
-	OsRng.fill_bytes(&mut bytes);
-	let fallback: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
-	warn!("JWT_SECRET not set; using ephemeral secret");
-	fallback
+	Ok(preview)
 }
 
-async fn handle_request(
-	req: Request<Body>,
-	state: Arc<ApiState>,
-) -> Result<Response<Body>, Infallible> {
-	let origin = req
-		.headers()
-		.get(ORIGIN)
-		.and_then(|v| v.to_str().ok())
-		.map(|v| v.to_string());
-	let origin_ref = origin.as_deref();
-	let segments: Vec<&str> = req
-		.uri()
-		.path()
-		.split('/')
-		.filter(|s| !s.is_empty())
-		.collect();
-	let is_protected = matches!(segments.as_slice(), ["api", ..]);
-	let auth_user = if is_protected || matches!(segments.as_slice(), ["auth", "me"]) {
-		authenticate(&req, &state)
+/// Read and search, or preview and search; write on top when asked.
+fn grant_flags(write: bool, preview_only: bool) -> u8 {
+	let flags = if preview_only {
+		FLAG_PREVIEW | FLAG_SEARCH
 	} else {
-		None
+		FLAG_READ | FLAG_SEARCH
 	};
-	if is_protected && req.method() != Method::OPTIONS && auth_user.is_none() {
-		let resp = json_response(
-			StatusCode::UNAUTHORIZED,
-			json!({ "error": "not authenticated" }),
-		);
-		return Ok(with_cors(resp, origin_ref));
-	}
+	if write { flags | FLAG_WRITE } else { flags }
+}
 
-	let response = match (req.method(), segments.as_slice()) {
-		(&Method::OPTIONS, _) => Response::builder()
-			.status(StatusCode::NO_CONTENT)
-			.body(Body::empty())
-			.unwrap(),
+fn batch_grant_response(results: &[(PeerId, BatchGrantOutcome)]) -> BatchGrantResponse {
+	let results = results
+		.iter()
+		.map(|(peer, outcome)| {
+			let (outcome, error) = match outcome {
+				BatchGrantOutcome::Granted => ("granted", None),
+				BatchGrantOutcome::Unchanged => ("unchanged", None),
+				BatchGrantOutcome::Failed { error } => ("failed", Some(error.clone())),
+			};
+			BatchGrantEntry {
+				peer_id: peer.to_string(),
+				outcome,
+				error,
+			}
+		})
+		.collect();
+	BatchGrantResponse { results }
+}
+
+fn temporary_grant_response(grant: &TemporaryGrant, now: DateTime<Utc>) -> TemporaryGrantResponse {
+	TemporaryGrantResponse {
+		id: grant.id,
+		rule: grant.rule.clone(),
+		expires_at: grant.expires_at,
+		remaining_secs: grant.remaining(now).as_secs(),
+	}
+}
+
+fn temporary_grants_response(grants: &[TemporaryGrant]) -> Vec<TemporaryGrantResponse> {
+	let now = Utc::now();
+	grants
+		.iter()
+		.map(|grant| temporary_grant_response(grant, now))
+		.collect()
+}
+
+fn overlap_warnings(permissions: &[Permission]) -> Vec<String> {
+	permission_overlaps(permissions)
+		.iter()
+		.map(|overlap| overlap.describe())
+		.collect()
+}
+
+fn permissions_response(permissions: &[Permission]) -> PermissionsResponse {
+	PermissionsResponse {
+		permissions: permissions.to_vec(),
+		effective: effective_permission_rules(permissions),
+		warnings: overlap_warnings(permissions),
+		revision: None,
+		temporary: None,
+		fetched_at: None,
+		revalidating: None,
+	}
+}
+
+fn permission_conflict_response(conflict: &PermissionConflict) -> Response<Body> {
+	let latest = &conflict.latest.permissions;
+	let body = PermissionConflictResponse {
+		error: error_body(StatusCode::CONFLICT, conflict.to_string()),
+		permissions: latest.clone(),
+		effective: effective_permission_rules(latest),
+		warnings: overlap_warnings(latest),
+		revision: conflict.latest.revision,
+	};
+	json_response(StatusCode::CONFLICT, json!(body))
+}
+
+fn draft_errors_response(rejected: &DraftRejected) -> Response<Body> {
+	let body = DraftErrorsResponse {
+		error: error_body(StatusCode::BAD_REQUEST, rejected.to_string()),
+		errors: rejected.errors.clone(),
+	};
+	json_response(StatusCode::BAD_REQUEST, json!(body))
+}
+
+fn create_user_error_response(err: anyhow::Error) -> Response<Body> {
+	match err.downcast_ref::<CreateUserError>() {
+		Some(CreateUserError::Invalid(errors)) => {
+			let body = UserErrorsResponse {
+				error: error_body(StatusCode::BAD_REQUEST, err.to_string()),
+				errors: errors.clone(),
+			};
+			json_response(StatusCode::BAD_REQUEST, json!(body))
+		}
+		Some(CreateUserError::AlreadyExists { .. }) => {
+			error_response(StatusCode::CONFLICT, err.to_string())
+		}
+		None => bad_request(err.to_string()),
+	}
+}
+
+/// Every route `handle_request` answers, with the types it takes and
+/// returns. Published at `/api/openapi.json`; keep it next to the match
+/// arms when adding routes.
+fn api_routes() -> Vec<ApiRoute> {
+	const BYTES: &str = "application/octet-stream";
+	vec![
+		ApiRoute::new("get", "/health", "Liveness probe")
+			.raw("text/plain")
+			.public(),
+		ApiRoute::new("post", "/auth/login", "Log in and get a bearer token")
+			.takes::<LoginRequest>()
+			.returns::<LoginResponse>(200)
+			.public(),
+		ApiRoute::new("post", "/auth/logout", "Drop the session cookie")
+			.no_content()
+			.public(),
+		ApiRoute::new("get", "/auth/me", "Authenticated user").returns::<MeResponse>(200),
+		ApiRoute::new("get", "/users", "List users")
+			.returns::<UsersResponse>(200)
+			.public(),
+		ApiRoute::new("post", "/users", "Create a user")
+			.takes::<CreateUserRequest>()
+			.returns::<CreateUserResponse>(201)
+			.public(),
+		ApiRoute::new("get", "/api/state", "Node state").returns::<StateResponse>(200),
+		ApiRoute::new("get", "/api/peers", "Known peers").returns::<PeerListResponse>(200),
+		ApiRoute::new(
+			"get",
+			"/api/peers/{peer_id}",
+			"A known peer and its trust level",
+		)
+		.returns::<PeerListEntry>(200),
+		ApiRoute::new(
+			"put",
+			"/api/peers/{peer_id}",
+			"Set the peer's trust level, which applies from its next request",
+		)
+		.takes::<PeerUpdateRequest>()
+		.no_content(),
+		ApiRoute::new(
+			"get",
+			"/api/peers/{peer_id}/permissions",
+			"Permissions the peer granted us, cached; refresh=true asks the peer",
+		)
+		.query(&["refresh"])
+		.returns::<PermissionsResponse>(200),
+		ApiRoute::new(
+			"put",
+			"/api/peers/{peer_id}/permissions",
+			"Replace what the peer may access; 204 when no rules overlap",
+		)
+		.takes::<SetPermissionsRequest>()
+		.returns::<WarningsResponse>(200),
+		ApiRoute::new(
+			"delete",
+			"/api/peers/{peer_id}/permissions",
+			"Revoke everything granted to the peer",
+		)
+		.no_content(),
+		ApiRoute::new(
+			"get",
+			"/api/peers/{peer_id}/permissions/drafts",
+			"Permissions granted to the peer, one editable rule each",
+		)
+		.returns::<PermissionDraftResponse>(200),
+		ApiRoute::new(
+			"put",
+			"/api/peers/{peer_id}/permissions/drafts",
+			"Save edited rules for the peer; 400 names each field to fix, 204 when no rules overlap",
+		)
+		.takes::<SavePermissionDraftRequest>()
+		.returns::<WarningsResponse>(200),
+		ApiRoute::new(
+			"get",
+			"/api/peers/{peer_id}/permissions/granted",
+			"Permissions granted to the peer",
+		)
+		.returns::<PermissionsResponse>(200),
+		ApiRoute::new(
+			"get",
+			"/api/peers/{peer_id}/permissions/temporary",
+			"Temporary grants of the peer",
+		)
+		.returns::<TemporaryGrantsResponse>(200),
+		ApiRoute::new(
+			"post",
+			"/api/peers/{peer_id}/permissions/temporary",
+			"Grant a folder for a while",
+		)
+		.takes::<TemporaryGrantRequest>()
+		.returns::<TemporaryGrantResponse>(201),
+		ApiRoute::new(
+			"delete",
+			"/api/peers/{peer_id}/permissions/temporary/{grant_id}",
+			"Revoke a temporary grant",
+		)
+		.no_content(),
+		ApiRoute::new(
+			"post",
+			"/api/peers/{peer_id}/permissions/request",
+			"Ask the peer for permissions",
+		)
+		.takes::<PermissionsRequest>()
+		.returns::<PermissionListResponse>(200),
+		ApiRoute::new(
+			"post",
+			"/api/permissions/batch",
+			"Grant one folder to several peers, adding to what each has",
+		)
+		.takes::<BatchGrantRequest>()
+		.returns::<BatchGrantResponse>(200),
+		ApiRoute::new(
+			"get",
+			"/api/metrics",
+			"Readahead statistics and remote scans and updates in flight",
+		),
+		ApiRoute::new(
+			"get",
+			"/api/stats/protocol",
+			"Requests exchanged with peers over the last minute by request type; with peer, also daily totals",
+		),
+		ApiRoute::new(
+			"get",
+			"/api/activity-window",
+			"Activity window and deferred work",
+		),
+		ApiRoute::new("put", "/api/activity-window", "Set the activity window").no_content(),
+		ApiRoute::new(
+			"get",
+			"/api/power",
+			"Power supply, whether background work waits for it, and the policy",
+		),
+		ApiRoute::new("put", "/api/power/policy", "Set the power policy").no_content(),
+		ApiRoute::new(
+			"post",
+			"/api/power/run-anyway",
+			"Let background work held for power run until the hold next ends",
+		)
+		.no_content(),
+		ApiRoute::new(
+			"get",
+			"/api/config",
+			"Every configuration value in effect and where it came from, secrets masked",
+		),
+		ApiRoute::new("get", "/api/cors", "CORS settings"),
+		ApiRoute::new("put", "/api/cors", "Set the CORS settings").no_content(),
+		ApiRoute::new("get", "/api/backups", "Backup settings and recent runs"),
+		ApiRoute::new("post", "/api/backups", "Back up the database now")
+			.takes::<BackupRequest>()
+			.returns::<BackupRunResponse>(201),
+		ApiRoute::new("put", "/api/backups/settings", "Set the backup settings").no_content(),
+		ApiRoute::new(
+			"post",
+			"/api/backups/restore",
+			"Restore the database from a backup",
+		)
+		.takes::<RestoreRequest>()
+		.returns::<BackupRunResponse>(200),
+		ApiRoute::new(
+			"post",
+			"/api/identity/adopt",
+			"Adopt the previous node identity",
+		)
+		.no_content(),
+		ApiRoute::new(
+			"post",
+			"/api/identity/fresh",
+			"Start over with the current identity",
+		)
+		.no_content(),
+		ApiRoute::new(
+			"post",
+			"/api/remote-access/suspend",
+			"Suspend remote access",
+		)
+		.returns::<RemoteAccessResponse>(200),
+		ApiRoute::new("post", "/api/remote-access/resume", "Resume remote access")
+			.returns::<RemoteAccessResponse>(200),
+		ApiRoute::new(
+			"get",
+			"/api/maintenance",
+			"Maintenance mode and the mutating work running now",
+		)
+		.returns::<MaintenanceResponse>(200),
+		ApiRoute::new(
+			"post",
+			"/api/maintenance/enter",
+			"Refuse every change, wait for running work and checkpoint the database",
+		)
+		.takes::<MaintenanceRequest>()
+		.returns::<MaintenanceReportResponse>(200),
+		ApiRoute::new("post", "/api/maintenance/exit", "Leave maintenance mode").no_content(),
+		ApiRoute::new("get", "/api/peers/{peer_id}/dir", "List a directory")
+			.query(&["path"])
+			.returns::<DirResponse>(200),
+		ApiRoute::new("get", "/api/peers/{peer_id}/disks", "Disks of the peer")
+			.returns::<DisksResponse>(200),
+		ApiRoute::new(
+			"get",
+			"/api/peers/{peer_id}/disks/history",
+			"Disk usage samples",
+		)
+		.query(&["mount", "from", "to"]),
+		ApiRoute::new(
+			"get",
+			"/api/peers/{peer_id}/roots",
+			"Browse roots of the peer",
+		),
+		ApiRoute::new(
+			"get",
+			"/api/peers/{peer_id}/interfaces",
+			"Network interfaces of the peer",
+		)
+		.returns::<InterfacesResponse>(200),
+		ApiRoute::new("get", "/api/peers/{peer_id}/cpus", "CPUs of the peer")
+			.returns::<CpusResponse>(200),
+		ApiRoute::new(
+			"get",
+			"/api/peers/{peer_id}/file",
+			"File bytes, honouring `Range`. `offset`, `length` or `Accept: application/json` \
+			 return a chunk as JSON, `preview=true` a preview page",
+		)
+		.query(&["path", "offset", "length", "preview"])
+		.raw(BYTES)
+		.or_json(json!({ "oneOf": [FileChunk::schema(), FilePreview::schema()] })),
+		ApiRoute::new("get", "/api/peers/{peer_id}/thumbnail", "Thumbnail image")
+			.query(&["path", "max_width", "max_height"])
+			.raw("image/*"),
+		ApiRoute::new(
+			"get",
+			"/api/peers/{peer_id}/contact-sheet",
+			"Contact sheet of a folder's images, or 204 when it has none",
+		)
+		.query(&["path", "columns", "cell_size", "max_items"])
+		.raw("image/jpeg"),
+		ApiRoute::new(
+			"post",
+			"/api/peers/{peer_id}/shell/start",
+			"Start a shell session",
+		)
+		.returns::<ShellStartResponse>(200),
+		ApiRoute::new(
+			"post",
+			"/api/peers/{peer_id}/shell/input",
+			"Send shell input and read output",
+		)
+		.takes::<ShellInputRequest>()
+		.returns::<ShellOutputResponse>(200),
+		ApiRoute::new("get", "/api/storage", "Storage usage by file")
+			.returns::<StorageResponse>(200),
+		ApiRoute::new(
+			"get",
+			"/api/storage/growth",
+			"Growth of the scanned folders over the last `days` and when each disk runs low",
+		)
+		.query(&["days"])
+		.returns::<GrowthReport>(200),
+		ApiRoute::new(
+			"get",
+			"/api/file/hash",
+			"Local file by content hash, honouring `Range`",
+		)
+		.query(&["hash"])
+		.raw(BYTES),
+		ApiRoute::new("get", "/api/scans/results", "Indexed files")
+			.query(&["page", "page_size", "cursor"])
+			.returns::<ScanResultsResponse>(200),
+		ApiRoute::new("get", "/api/scans/history", "Recorded scan runs")
+			.query(&["page", "path"])
+			.returns::<ScanHistoryResponse>(200),
+		ApiRoute::new("get", "/api/transfers", "Transfer history").query(&["page", "peer"]),
+		ApiRoute::new("get", "/api/activity", "Activity timeline, newest first")
+			.query(&["kinds", "peer", "since", "text", "cursor", "limit"])
+			.returns::<ActivityResponse>(200),
+		ApiRoute::new("get", "/api/debug/requests", "Slowest recent peer requests")
+			.query(&["limit"])
+			.returns::<RequestTracesResponse>(200),
+		ApiRoute::new("get", "/api/pins", "Pinned remote folders").returns::<PinsResponse>(200),
+		ApiRoute::new("post", "/api/pins", "Pin a remote folder")
+			.takes::<PinRequest>()
+			.returns::<PinResponse>(201),
+		ApiRoute::new("post", "/api/pins/{pin_id}/pause", "Pause a pin").no_content(),
+		ApiRoute::new("post", "/api/pins/{pin_id}/resume", "Resume a pin").no_content(),
+		ApiRoute::new("delete", "/api/pins/{pin_id}", "Unpin a folder").no_content(),
+		ApiRoute::new("get", "/api/scans/diff", "Changes between two scan runs")
+			.query(&["a", "b", "page"])
+			.returns::<ScanDiffResponse>(200),
+		ApiRoute::new("get", "/api/diff", "Compare two files across peers")
+			.query(&["peer_a", "path_a", "peer_b", "path_b", "context"])
+			.returns::<DiffResponse>(200),
+		ApiRoute::new("post", "/api/scans", "Start a scan")
+			.takes::<ScanStartRequest>()
+			.returns::<ScanStartResponse>(201),
+		ApiRoute::new(
+			"get",
+			"/api/scans/{scan_id}/events",
+			"Scan events since the last poll",
+		)
+		.returns::<ScanEventsResponse>(200),
+		ApiRoute::new("post", "/api/scans/{scan_id}/cancel", "Cancel a scan").no_content(),
+		ApiRoute::new("get", "/api/search", "Search indexed files")
+			.query(&[
+				"name_query",
+				"content_query",
+				"date_from",
+				"date_to",
+				"replicas_min",
+				"replicas_max",
+				"include_empty",
+				"mime_types",
+				"mime_type",
+				"peer_id",
+				"peer",
+				"path_prefix",
+				"min_duration",
+				"max_duration",
+				"min_pixels",
+				"include_deleted",
+				"origin",
+				"introduced_by_peer",
+				"sort_by",
+				"sort_desc",
+				"page",
+				"page_size",
+				"cursor",
+			])
+			.returns::<SearchResponse>(200),
+		ApiRoute::new("get", "/api/mime-types", "Indexed MIME types")
+			.returns::<MimeTypesResponse>(200),
+		ApiRoute::new("post", "/api/updates/{peer_id}", "Update a peer")
+			.takes::<UpdateStartRequest>()
+			.returns::<UpdateStartResponse>(201),
+		ApiRoute::new(
+			"get",
+			"/api/updates/{update_id}/events",
+			"Update progress since the last poll",
+		)
+		.returns::<UpdateEventsResponse>(200),
+		ApiRoute::new("get", "/api/openapi.json", "This document").public(),
+		ApiRoute::new("get", "/api/docs", "Rendered API docs")
+			.raw("text/html")
+			.public(),
+	]
+}
+
+async fn handle_request(
+	req: Request<Body>,
+	state: Arc<ApiState>,
+	remote_addr: SocketAddr,
+) -> Result<Response<Body>, Infallible> {
+	let origin = req
+		.headers()
+		.get(ORIGIN)
+		.and_then(|v| v.to_str().ok())
+		.map(|v| v.to_string());
+	let origin_ref = origin.as_deref();
+	let cors = state.puppy.cors_settings();
+	if let Some(resp) = cors.preflight(&req) {
+		return Ok(resp);
+	}
+	let segments: Vec<&str> = req
+		.uri()
+		.path()
+		.split('/')
+		.filter(|s| !s.is_empty())
+		.collect();
+	let is_protected = matches!(segments.as_slice(), ["api", ..])
+		&& !matches!(segments.as_slice(), ["api", "openapi.json" | "docs"]);
+	let auth_user = if is_protected || matches!(segments.as_slice(), ["auth", "me"]) {
+		authenticate(&req, &state)
+	} else {
+		None
+	};
+	if is_protected && auth_user.is_none() {
+		let resp = error_response(StatusCode::UNAUTHORIZED, "not authenticated");
+		return Ok(cors.apply(resp, origin_ref));
+	}
+
+	let response = match (req.method(), segments.as_slice()) {
 		(&Method::GET, ["health"]) => Response::new(Body::from("ok")),
+		(&Method::GET, ["api", "openapi.json"]) => {
+			json_response(StatusCode::OK, document::<ApiError>(&api_routes()))
+		}
+		(&Method::GET, ["api", "docs"]) => Response::builder()
+			.header(CONTENT_TYPE, "text/html; charset=utf-8")
+			.body(Body::from(DOCS_HTML))
+			.unwrap(),
 		(&Method::POST, ["auth", "login"]) => {
+			let source = LoginSource {
+				ip: remote_addr.ip().to_string(),
+				user_agent: req
+					.headers()
+					.get(USER_AGENT)
+					.and_then(|v| v.to_str().ok())
+					.unwrap_or_default()
+					.to_string(),
+			};
 			let body = hyper::body::aggregate(req.into_body()).await;
 			let Ok(buf) = body else {
-				return Ok(with_cors(bad_request("failed to read body"), origin_ref));
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
 			};
 			let parsed: Result<LoginRequest, _> = serde_json::from_reader(buf.reader());
 			match parsed {
 				Ok(payload) => {
-					let creds_ok = match state
-						.puppy
-						.verify_user_credentials(&payload.username, &payload.password)
-					{
-						Ok(valid) => valid,
+					let result = match state.puppy.attempt_login(
+						&payload.username,
+						&payload.password,
+						&source,
+					) {
+						Ok(result) => result,
 						Err(err) => {
-							return Ok(with_cors(
-								json_response(
-									StatusCode::INTERNAL_SERVER_ERROR,
-									json!({ "error": err.to_string() }),
-								),
+							return Ok(cors.apply(
+								error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
 								origin_ref,
 							));
 						}
 					};
-					if !creds_ok {
-						return Ok(with_cors(
-							json_response(
-								StatusCode::UNAUTHORIZED,
-								json!({ "error": "invalid credentials" }),
-							),
-							origin_ref,
-						));
+					let retry_after = match result {
+						LoginResult::Success => None,
+						LoginResult::InvalidCredentials => {
+							return Ok(cors.apply(
+								error_response(StatusCode::UNAUTHORIZED, "invalid credentials"),
+								origin_ref,
+							));
+						}
+						LoginResult::Throttled { retry_after_secs }
+						| LoginResult::LockedOut { retry_after_secs } => Some(retry_after_secs),
+					};
+					if let Some(retry_after_secs) = retry_after {
+						let status = StatusCode::TOO_MANY_REQUESTS;
+						let mut resp = json_response(
+							status,
+							json!(LoginThrottledResponse {
+								error: error_body(status, "too many login attempts"),
+								retry_after_secs,
+							}),
+						);
+						resp.headers_mut()
+							.insert(RETRY_AFTER, HeaderValue::from(retry_after_secs));
+						return Ok(cors.apply(resp, origin_ref));
 					}
 					let access_token =
 						match auth::issue_jwt(&payload.username, state.jwt_secret.as_bytes()) {
 							Ok(token) => token,
 							Err(err) => {
-								return Ok(with_cors(
-									json_response(
+								return Ok(cors.apply(
+									error_response(
 										StatusCode::INTERNAL_SERVER_ERROR,
-										json!({ "error": err.to_string() }),
+										err.to_string(),
 									),
 									origin_ref,
 								));
 							}
 						};
 					let mut resp =
-						json_response(StatusCode::OK, json!({ "access_token": access_token }));
+						json_response(StatusCode::OK, json!(LoginResponse { access_token }));
 					if payload.set_cookie.unwrap_or(false) {
 						let (token, hash) = auth::generate_session_token();
 						if let Err(err) =
@@ -619,11 +1815,8 @@ async fn handle_request(
 								.puppy
 								.save_session(&hash, &payload.username, SESSION_TTL_SECS)
 						{
-							return Ok(with_cors(
-								json_response(
-									StatusCode::INTERNAL_SERVER_ERROR,
-									json!({ "error": err.to_string() }),
-								),
+							return Ok(cors.apply(
+								error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
 								origin_ref,
 							));
 						}
@@ -650,31 +1843,32 @@ async fn handle_request(
 			resp
 		}
 		(&Method::GET, ["auth", "me"]) => match auth_user {
-			Some(user) => json_response(StatusCode::OK, json!({ "user": user })),
-			None => json_response(
-				StatusCode::UNAUTHORIZED,
-				json!({ "error": "not authenticated" }),
-			),
+			Some(user) => json_response(StatusCode::OK, json!(MeResponse { user })),
+			None => error_response(StatusCode::UNAUTHORIZED, "not authenticated"),
 		},
 		(&Method::GET, ["users"]) => match state.puppy.list_users_db() {
-			Ok(list) => json_response(StatusCode::OK, json!({ "users": list })),
-			Err(err) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({ "error": err })),
+			Ok(users) => json_response(StatusCode::OK, json!(UsersResponse { users })),
+			Err(err) => error_response(StatusCode::INTERNAL_SERVER_ERROR, err),
 		},
 		(&Method::POST, ["users"]) => {
 			let body = hyper::body::aggregate(req.into_body()).await;
 			let Ok(buf) = body else {
-				return Ok(with_cors(bad_request("failed to read body"), origin_ref));
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
 			};
 			let parsed: Result<CreateUserRequest, _> = serde_json::from_reader(buf.reader());
 			match parsed {
 				Ok(payload) => match state
 					.puppy
-					.create_user(payload.username.clone(), payload.password)
+					.create_user_async(payload.username.clone(), payload.password)
+					.await
 				{
-					Ok(()) => {
-						json_response(StatusCode::CREATED, json!({ "username": payload.username }))
-					}
-					Err(err) => bad_request(err.to_string()),
+					Ok(()) => json_response(
+						StatusCode::CREATED,
+						json!(CreateUserResponse {
+							username: payload.username.trim().to_string(),
+						}),
+					),
+					Err(err) => create_user_error_response(err),
 				},
 				Err(err) => bad_request(format!("invalid json: {err}")),
 			}
@@ -697,34 +1891,62 @@ async fn handle_request(
 						.collect()
 				})
 				.unwrap_or_default();
+			let connections = snapshot
+				.as_ref()
+				.map(|s| {
+					s.connections
+						.iter()
+						.map(|c| ConnectionSummary {
+							peer_id: c.peer_id.to_string(),
+							direction: c.direction,
+							transport: c.transport(),
+							local_addr: c.local_addr.as_ref().map(|addr| addr.to_string()),
+							remote_addr: c.remote_addr.to_string(),
+							connected_at: c.connected_at,
+						})
+						.collect()
+				})
+				.unwrap_or_default();
 			let peers = state
 				.puppy
 				.list_peers_db()
 				.unwrap_or_default()
 				.into_iter()
 				.map(|p| {
-					let node_id = peer_to_node_id(&p.id).map(|id| bytes_to_hex(&id));
+					let node_id = peer_to_node_id(&p.id).map(|id| hex(&id));
 					PeerSummary {
 						id: p.id.to_string(),
 						name: p.name,
 						node_id,
+						recent_drops: snapshot
+							.as_ref()
+							.map_or(0, |s| s.recent_drops(&p.id, Utc::now())),
+						clock_offset_ms: snapshot
+							.as_ref()
+							.and_then(|s| s.clock_offsets.get(&p.id))
+							.and_then(|offset| offset.offset_ms()),
+						clock_skew: snapshot.as_ref().and_then(|s| s.clock_skew(&p.id)),
 					}
 				})
 				.collect();
 			let discovered = state
 				.puppy
-				.list_discovered_peers_db()
-				.unwrap_or_default()
+				.query_discovered_peers(DiscoveredPeerFilter::default(), STATE_DISCOVERED_LIMIT)
+				.await
 				.into_iter()
-				.map(|d| DiscoveredSummary {
-					peer_id: d.peer_id.to_string(),
-					multiaddr: d.multiaddr.to_string(),
+				.flat_map(|d| {
+					d.addresses
+						.into_iter()
+						.map(move |multiaddr| DiscoveredSummary {
+							peer_id: d.peer_id.to_string(),
+							multiaddr: multiaddr.to_string(),
+						})
 				})
 				.collect();
 			let users = state
 				.puppy
-				.list_users_db()
-				.unwrap_or_default()
+				.query_users()
+				.await
 				.into_iter()
 				.map(|name| UserSummary { name })
 				.collect();
@@ -735,7 +1957,16 @@ async fn handle_request(
 					peers,
 					discovered,
 					users,
-					shared_folders
+					shared_folders,
+					connections,
+					remote_access_suspended: state.puppy.remote_access_suspended().await,
+					maintenance: state.puppy.maintenance(),
+					reachability: snapshot
+						.as_ref()
+						.map(|s| s.reachability.clone())
+						.unwrap_or_default(),
+					identity_mismatch: snapshot.and_then(|s| s.identity_mismatch),
+					power: state.puppy.power_state(),
 				}),
 			)
 		}
@@ -745,42 +1976,474 @@ async fn handle_request(
 				.list_peers_db()
 				.unwrap_or_default()
 				.into_iter()
-				.map(|p| json!({ "id": p.id.to_string(), "name": p.name }))
-				.collect::<Vec<_>>();
-			json_response(StatusCode::OK, json!({ "peers": peers }))
+				.map(|p| PeerListEntry {
+					id: p.id.to_string(),
+					name: p.name,
+					trust: p.trust,
+				})
+				.collect();
+			json_response(StatusCode::OK, json!(PeerListResponse { peers }))
+		}
+		(&Method::GET, ["api", "peers", peer_id]) => {
+			let peer = match parse_peer_id(peer_id) {
+				Ok(p) => p,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			let known = state
+				.puppy
+				.list_peers_db()
+				.unwrap_or_default()
+				.into_iter()
+				.find(|p| p.id == peer);
+			match known {
+				Some(p) => json_response(
+					StatusCode::OK,
+					json!(PeerListEntry {
+						id: p.id.to_string(),
+						name: p.name,
+						trust: p.trust,
+					}),
+				),
+				None => error_response(StatusCode::NOT_FOUND, "unknown peer"),
+			}
+		}
+		(&Method::PUT, ["api", "peers", peer_id]) => {
+			let peer = match parse_peer_id(peer_id) {
+				Ok(p) => p,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			let body = hyper::body::aggregate(req.into_body()).await;
+			let Ok(buf) = body else {
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
+			};
+			let parsed: Result<PeerUpdateRequest, _> = serde_json::from_reader(buf.reader());
+			match parsed {
+				Ok(update) => match state.puppy.set_trust_level(peer, update.trust) {
+					Ok(()) => Response::builder()
+						.status(StatusCode::NO_CONTENT)
+						.body(Body::empty())
+						.unwrap(),
+					Err(err) => bad_request(err.to_string()),
+				},
+				Err(err) => bad_request(format!("invalid json: {err}")),
+			}
 		}
 		(&Method::GET, ["api", "peers", peer_id, "permissions"]) => {
 			let peer = match parse_peer_id(peer_id) {
 				Ok(p) => p,
-				Err(err) => return Ok(with_cors(bad_request(err), origin_ref)),
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
 			};
-			match state.puppy.list_permissions(peer).await {
-				Ok(perms) => json_response(StatusCode::OK, json!({ "permissions": perms })),
+			let force_refresh = parse_query(&req)
+				.get("refresh")
+				.is_some_and(|value| value == "true" || value == "1");
+			match state.puppy.list_permissions(peer, force_refresh).await {
+				Ok(grants) => {
+					let body = PermissionsResponse {
+						fetched_at: Some(grants.fetched_at),
+						revalidating: Some(grants.revalidating),
+						..permissions_response(&grants.permissions)
+					};
+					json_response(StatusCode::OK, json!(body))
+				}
 				Err(err) => bad_request(err.to_string()),
 			}
 		}
 		(&Method::GET, ["api", "peers", peer_id, "permissions", "granted"]) => {
 			let peer = match parse_peer_id(peer_id) {
 				Ok(p) => p,
-				Err(err) => return Ok(with_cors(bad_request(err), origin_ref)),
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
 			};
-			match state.puppy.list_granted_permissions(peer) {
-				Ok(perms) => json_response(StatusCode::OK, json!({ "permissions": perms })),
+			match state.puppy.granted_permission_set(peer) {
+				Ok(set) => {
+					let body = PermissionsResponse {
+						revision: Some(set.revision),
+						temporary: Some(temporary_grants_response(&set.temporary)),
+						..permissions_response(&set.permissions)
+					};
+					json_response(StatusCode::OK, json!(body))
+				}
 				Err(err) => bad_request(err.to_string()),
 			}
 		}
 		(&Method::PUT, ["api", "peers", peer_id, "permissions"]) => {
 			let peer = match parse_peer_id(peer_id) {
 				Ok(p) => p,
-				Err(err) => return Ok(with_cors(bad_request(err), origin_ref)),
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
 			};
 			let body = hyper::body::aggregate(req.into_body()).await;
 			let Ok(buf) = body else {
-				return Ok(with_cors(bad_request("failed to read body"), origin_ref));
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
 			};
 			let parsed: Result<SetPermissionsRequest, _> = serde_json::from_reader(buf.reader());
 			match parsed {
-				Ok(payload) => match state.puppy.set_peer_permissions(peer, payload.permissions) {
+				Ok(payload) => match state.puppy.set_peer_permissions_at(
+					peer,
+					payload.permissions,
+					payload.expected_revision,
+				) {
+					Ok(overlaps) if overlaps.is_empty() => Response::builder()
+						.status(StatusCode::NO_CONTENT)
+						.body(Body::empty())
+						.unwrap(),
+					Ok(overlaps) => {
+						let warnings = overlaps
+							.iter()
+							.map(|overlap| overlap.describe())
+							.collect::<Vec<_>>();
+						json_response(StatusCode::OK, json!(WarningsResponse { warnings }))
+					}
+					Err(err) => match err.downcast_ref::<PermissionConflict>() {
+						Some(conflict) => permission_conflict_response(conflict),
+						None => bad_request(err.to_string()),
+					},
+				},
+				Err(err) => bad_request(format!("invalid json: {err}")),
+			}
+		}
+		(&Method::GET, ["api", "peers", peer_id, "permissions", "drafts"]) => {
+			let peer = match parse_peer_id(peer_id) {
+				Ok(p) => p,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			match state.puppy.granted_permission_set(peer) {
+				Ok(set) => {
+					let body = PermissionDraftResponse {
+						rules: PermissionDraft::from_permissions(&set.permissions).rules,
+						warnings: overlap_warnings(&set.permissions),
+						revision: set.revision,
+					};
+					json_response(StatusCode::OK, json!(body))
+				}
+				Err(err) => bad_request(err.to_string()),
+			}
+		}
+		(&Method::PUT, ["api", "peers", peer_id, "permissions", "drafts"]) => {
+			let peer = match parse_peer_id(peer_id) {
+				Ok(p) => p,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			let body = hyper::body::aggregate(req.into_body()).await;
+			let Ok(buf) = body else {
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
+			};
+			let parsed: Result<SavePermissionDraftRequest, _> =
+				serde_json::from_reader(buf.reader());
+			match parsed {
+				Ok(payload) => match state.puppy.save_permission_draft(
+					peer,
+					&PermissionDraft {
+						rules: payload.rules,
+					},
+					payload.merge.unwrap_or(false),
+					payload.expected_revision,
+				) {
+					Ok(overlaps) if overlaps.is_empty() => Response::builder()
+						.status(StatusCode::NO_CONTENT)
+						.body(Body::empty())
+						.unwrap(),
+					Ok(overlaps) => {
+						let warnings = overlaps
+							.iter()
+							.map(|overlap| overlap.describe())
+							.collect::<Vec<_>>();
+						json_response(StatusCode::OK, json!(WarningsResponse { warnings }))
+					}
+					Err(err) => {
+						if let Some(rejected) = err.downcast_ref::<DraftRejected>() {
+							draft_errors_response(rejected)
+						} else if let Some(conflict) = err.downcast_ref::<PermissionConflict>() {
+							permission_conflict_response(conflict)
+						} else {
+							bad_request(err.to_string())
+						}
+					}
+				},
+				Err(err) => bad_request(format!("invalid json: {err}")),
+			}
+		}
+		(&Method::DELETE, ["api", "peers", peer_id, "permissions"]) => {
+			let peer = match parse_peer_id(peer_id) {
+				Ok(p) => p,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			match state.puppy.revoke_all_permissions(peer) {
+				Ok(()) => Response::builder()
+					.status(StatusCode::NO_CONTENT)
+					.body(Body::empty())
+					.unwrap(),
+				Err(err) => bad_request(err.to_string()),
+			}
+		}
+		(&Method::GET, ["api", "peers", peer_id, "permissions", "temporary"]) => {
+			let peer = match parse_peer_id(peer_id) {
+				Ok(p) => p,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			match state.puppy.list_temporary_grants(peer) {
+				Ok(grants) => json_response(
+					StatusCode::OK,
+					json!(TemporaryGrantsResponse {
+						temporary: temporary_grants_response(&grants),
+					}),
+				),
+				Err(err) => bad_request(err.to_string()),
+			}
+		}
+		(&Method::POST, ["api", "peers", peer_id, "permissions", "temporary"]) => {
+			let peer = match parse_peer_id(peer_id) {
+				Ok(p) => p,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			let body = hyper::body::aggregate(req.into_body()).await;
+			let Ok(buf) = body else {
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
+			};
+			let parsed: Result<TemporaryGrantRequest, _> = serde_json::from_reader(buf.reader());
+			match parsed {
+				Ok(payload) => {
+					match state.puppy.grant_temporary(
+						peer,
+						payload.path,
+						grant_flags(payload.write, payload.preview_only),
+						Duration::from_secs(payload.ttl_secs),
+					) {
+						Ok(grant) => json_response(
+							StatusCode::CREATED,
+							json!(temporary_grant_response(&grant, Utc::now())),
+						),
+						Err(err) => bad_request(err.to_string()),
+					}
+				}
+				Err(err) => bad_request(format!("invalid json: {err}")),
+			}
+		}
+		(
+			&Method::DELETE,
+			[
+				"api",
+				"peers",
+				peer_id,
+				"permissions",
+				"temporary",
+				grant_id,
+			],
+		) => {
+			let peer = match parse_peer_id(peer_id) {
+				Ok(p) => p,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			let Ok(id) = grant_id.parse::<u64>() else {
+				return Ok(cors.apply(bad_request("invalid grant id"), origin_ref));
+			};
+			match state.puppy.revoke_temporary(peer, id) {
+				Ok(()) => Response::builder()
+					.status(StatusCode::NO_CONTENT)
+					.body(Body::empty())
+					.unwrap(),
+				Err(err) => error_response(StatusCode::NOT_FOUND, err.to_string()),
+			}
+		}
+		(&Method::POST, ["api", "permissions", "batch"]) => {
+			let body = hyper::body::aggregate(req.into_body()).await;
+			let Ok(buf) = body else {
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
+			};
+			let parsed: Result<BatchGrantRequest, _> = serde_json::from_reader(buf.reader());
+			match parsed {
+				Ok(payload) => {
+					let peers = payload
+						.peers
+						.iter()
+						.map(String::as_str)
+						.map(parse_peer_id)
+						.collect::<Result<Vec<_>, _>>();
+					match peers {
+						Ok(peers) => {
+							let folder = FolderRule::new(
+								PathBuf::from(payload.path),
+								grant_flags(payload.write, payload.preview_only),
+							)
+							.with_patterns(payload.deny_patterns, Vec::new());
+							match state.puppy.grant_folder_to_peers(folder, peers) {
+								Ok(results) => json_response(
+									StatusCode::OK,
+									json!(batch_grant_response(&results)),
+								),
+								Err(err) => bad_request(err.to_string()),
+							}
+						}
+						Err(err) => bad_request(err),
+					}
+				}
+				Err(err) => bad_request(format!("invalid json: {err}")),
+			}
+		}
+		(&Method::GET, ["api", "metrics"]) => json_response(
+			StatusCode::OK,
+			json!({
+				"readahead": state.readahead.stats(),
+				"remote_ops": state.puppy.remote_op_stats(),
+			}),
+		),
+		(&Method::GET, ["api", "stats", "protocol"]) => {
+			let query = parse_query(&req);
+			match query.get("peer") {
+				Some(peer_id) => {
+					let peer = match parse_peer_id(peer_id) {
+						Ok(p) => p,
+						Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+					};
+					let days = match query.get("days").map(|v| v.parse::<u64>()).transpose() {
+						Ok(days) => days.unwrap_or(PROTOCOL_HISTORY_DEFAULT_DAYS),
+						Err(_) => return Ok(cors.apply(bad_request("invalid days"), origin_ref)),
+					};
+					match state.puppy.peer_protocol_history(&peer, days) {
+						Ok(history) => json_response(
+							StatusCode::OK,
+							json!({
+								"limits": state.puppy.protocol_limits(),
+								"last_minute": state.puppy.peer_protocol_stats(&peer),
+								"history": history,
+							}),
+						),
+						Err(err) => {
+							error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+						}
+					}
+				}
+				None => json_response(
+					StatusCode::OK,
+					json!({
+						"limits": state.puppy.protocol_limits(),
+						"last_minute": state.puppy.protocol_stats(),
+					}),
+				),
+			}
+		}
+		(&Method::GET, ["api", "activity-window"]) => json_response(
+			StatusCode::OK,
+			json!({
+				"window": state.puppy.activity_window(),
+				"deferred": state.puppy.deferred_activities(),
+			}),
+		),
+		(&Method::PUT, ["api", "activity-window"]) => {
+			let body = hyper::body::aggregate(req.into_body()).await;
+			let Ok(buf) = body else {
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
+			};
+			let parsed: Result<ActivityWindow, _> = serde_json::from_reader(buf.reader());
+			match parsed {
+				Ok(window) => match state.puppy.set_activity_window(window) {
+					Ok(()) => Response::builder()
+						.status(StatusCode::NO_CONTENT)
+						.body(Body::empty())
+						.unwrap(),
+					Err(err) => bad_request(err.to_string()),
+				},
+				Err(err) => bad_request(format!("invalid json: {err}")),
+			}
+		}
+		(&Method::GET, ["api", "power"]) => json_response(
+			StatusCode::OK,
+			json!({
+				"state": state.puppy.power_state(),
+				"policy": state.puppy.power_policy(),
+			}),
+		),
+		(&Method::PUT, ["api", "power", "policy"]) => {
+			let body = hyper::body::aggregate(req.into_body()).await;
+			let Ok(buf) = body else {
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
+			};
+			let parsed: Result<PowerPolicy, _> = serde_json::from_reader(buf.reader());
+			match parsed {
+				Ok(policy) => match state.puppy.set_power_policy(policy) {
+					Ok(()) => Response::builder()
+						.status(StatusCode::NO_CONTENT)
+						.body(Body::empty())
+						.unwrap(),
+					Err(err) => bad_request(err.to_string()),
+				},
+				Err(err) => bad_request(format!("invalid json: {err}")),
+			}
+		}
+		(&Method::POST, ["api", "power", "run-anyway"]) => {
+			if state.puppy.run_background_anyway() {
+				Response::builder()
+					.status(StatusCode::NO_CONTENT)
+					.body(Body::empty())
+					.unwrap()
+			} else {
+				error_response(StatusCode::CONFLICT, "no background work is held for power")
+			}
+		}
+		(&Method::GET, ["api", "config"]) => match state.puppy.effective_config() {
+			Ok(config) => json_response(StatusCode::OK, json!(config.redacted())),
+			Err(err) => error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")),
+		},
+		(&Method::GET, ["api", "cors"]) => json_response(StatusCode::OK, json!(cors)),
+		(&Method::PUT, ["api", "cors"]) => {
+			let body = hyper::body::aggregate(req.into_body()).await;
+			let Ok(buf) = body else {
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
+			};
+			let parsed: Result<CorsSettings, _> = serde_json::from_reader(buf.reader());
+			match parsed {
+				Ok(settings) => match state.puppy.set_cors_settings(settings) {
+					Ok(()) => Response::builder()
+						.status(StatusCode::NO_CONTENT)
+						.body(Body::empty())
+						.unwrap(),
+					Err(err) => bad_request(err.to_string()),
+				},
+				Err(err) => bad_request(format!("invalid json: {err}")),
+			}
+		}
+		(&Method::GET, ["api", "backups"]) => match state.puppy.backup_runs(50) {
+			Ok(runs) => json_response(
+				StatusCode::OK,
+				json!({
+					"settings": state.puppy.backup_settings(),
+					"runs": runs,
+				}),
+			),
+			Err(err) => error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+		},
+		(&Method::POST, ["api", "backups"]) => {
+			let body = hyper::body::aggregate(req.into_body()).await;
+			let Ok(buf) = body else {
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
+			};
+			let payload = if buf.has_remaining() {
+				match serde_json::from_reader::<_, BackupRequest>(buf.reader()) {
+					Ok(payload) => payload,
+					Err(err) => {
+						return Ok(
+							cors.apply(bad_request(format!("invalid json: {err}")), origin_ref)
+						);
+					}
+				}
+			} else {
+				BackupRequest::default()
+			};
+			let result = match payload.dir {
+				Some(dir) => state.puppy.backup_database(dir).await,
+				None => state.puppy.backup_now().await,
+			};
+			match result {
+				Ok(run) => json_response(StatusCode::CREATED, json!(BackupRunResponse { run })),
+				Err(err) => error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")),
+			}
+		}
+		(&Method::PUT, ["api", "backups", "settings"]) => {
+			let body = hyper::body::aggregate(req.into_body()).await;
+			let Ok(buf) = body else {
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
+			};
+			let parsed: Result<BackupSettings, _> = serde_json::from_reader(buf.reader());
+			match parsed {
+				Ok(settings) => match state.puppy.set_backup_settings(settings) {
 					Ok(()) => Response::builder()
 						.status(StatusCode::NO_CONTENT)
 						.body(Body::empty())
@@ -790,14 +2453,114 @@ async fn handle_request(
 				Err(err) => bad_request(format!("invalid json: {err}")),
 			}
 		}
+		(&Method::POST, ["api", "backups", "restore"]) => {
+			let body = hyper::body::aggregate(req.into_body()).await;
+			let Ok(buf) = body else {
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
+			};
+			let parsed: Result<RestoreRequest, _> = serde_json::from_reader(buf.reader());
+			match parsed {
+				Ok(payload) => match state
+					.puppy
+					.restore_database(payload.path, payload.force)
+					.await
+				{
+					Ok(run) => json_response(StatusCode::OK, json!(BackupRunResponse { run })),
+					Err(err) => error_response(StatusCode::CONFLICT, format!("{err:#}")),
+				},
+				Err(err) => bad_request(format!("invalid json: {err}")),
+			}
+		}
+		(&Method::POST, ["api", "identity", "adopt"]) => {
+			match state.puppy.adopt_previous_identity().await {
+				Ok(()) => Response::builder()
+					.status(StatusCode::NO_CONTENT)
+					.body(Body::empty())
+					.unwrap(),
+				Err(err) => error_response(StatusCode::CONFLICT, format!("{err:#}")),
+			}
+		}
+		(&Method::POST, ["api", "identity", "fresh"]) => {
+			match state.puppy.start_fresh_identity().await {
+				Ok(()) => Response::builder()
+					.status(StatusCode::NO_CONTENT)
+					.body(Body::empty())
+					.unwrap(),
+				Err(err) => error_response(StatusCode::CONFLICT, format!("{err:#}")),
+			}
+		}
+		(&Method::POST, ["api", "remote-access", "suspend"]) => {
+			match state.puppy.suspend_remote_access() {
+				Ok(()) => json_response(
+					StatusCode::OK,
+					json!(RemoteAccessResponse { suspended: true }),
+				),
+				Err(err) => bad_request(err.to_string()),
+			}
+		}
+		(&Method::POST, ["api", "remote-access", "resume"]) => {
+			match state.puppy.resume_remote_access() {
+				Ok(()) => json_response(
+					StatusCode::OK,
+					json!(RemoteAccessResponse { suspended: false }),
+				),
+				Err(err) => bad_request(err.to_string()),
+			}
+		}
+		(&Method::GET, ["api", "maintenance"]) => json_response(
+			StatusCode::OK,
+			json!(MaintenanceResponse {
+				maintenance: state.puppy.maintenance(),
+				running: state.puppy.mutating_work(),
+			}),
+		),
+		(&Method::POST, ["api", "maintenance", "enter"]) => {
+			let body = hyper::body::aggregate(req.into_body()).await;
+			let Ok(buf) = body else {
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
+			};
+			let parsed: Result<MaintenanceRequest, _> = serde_json::from_reader(buf.reader());
+			match parsed {
+				Ok(payload) => match state
+					.puppy
+					.enter_maintenance(
+						&payload.reason,
+						payload.expected_secs.map(Duration::from_secs),
+					)
+					.await
+				{
+					Ok(report) => json_response(
+						StatusCode::OK,
+						json!(MaintenanceReportResponse {
+							safe_to_copy: report.safe_to_copy(),
+							summary: report.summary(),
+							maintenance: report.maintenance,
+							pending: report.pending,
+							checkpointed: report.checkpointed,
+						}),
+					),
+					Err(err) => bad_request(format!("{err:#}")),
+				},
+				Err(err) => bad_request(format!("invalid json: {err}")),
+			}
+		}
+		(&Method::POST, ["api", "maintenance", "exit"]) => {
+			match state.puppy.exit_maintenance().await {
+				Ok(()) => Response::builder()
+					.status(StatusCode::NO_CONTENT)
+					.body(Body::empty())
+					.unwrap(),
+				Err(err) => error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")),
+			}
+		}
 		(&Method::POST, ["api", "peers", peer_id, "permissions", "request"]) => {
 			let peer = match parse_peer_id(peer_id) {
 				Ok(p) => p,
-				Err(err) => return Ok(with_cors(bad_request(err), origin_ref)),
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
 			};
 			let body = hyper::body::aggregate(req.into_body()).await;
 			let Ok(buf) = body else {
-				return Ok(with_cors(bad_request("failed to read body"), origin_ref));
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
 			};
 			let parsed: Result<PermissionsRequest, _> = serde_json::from_reader(buf.reader());
 			match parsed {
@@ -806,7 +2569,10 @@ async fn handle_request(
 					.request_permissions(peer, payload.permissions, payload.merge.unwrap_or(true))
 					.await
 				{
-					Ok(ack) => json_response(StatusCode::OK, json!({ "permissions": ack })),
+					Ok(permissions) => json_response(
+						StatusCode::OK,
+						json!(PermissionListResponse { permissions }),
+					),
 					Err(err) => bad_request(err.to_string()),
 				},
 				Err(err) => bad_request(format!("invalid json: {err}")),
@@ -815,33 +2581,77 @@ async fn handle_request(
 		(&Method::GET, ["api", "peers", peer_id, "dir"]) => {
 			let peer = match parse_peer_id(peer_id) {
 				Ok(p) => p,
-				Err(err) => return Ok(with_cors(bad_request(err), origin_ref)),
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
 			};
 			let query = parse_query(&req);
-			let path = query.get("path").cloned().unwrap_or_else(|| "/".into());
-			match state.puppy.list_dir(peer, path).await {
-				Ok(entries) => json_response(StatusCode::OK, json!({ "entries": entries })),
+			let path = match parse_peer_path(query.get("path").map_or("/", String::as_str)) {
+				Ok(path) => path,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			match state.puppy.list_dir_with_hint(peer, path).await {
+				Ok(listing) => json_response(
+					StatusCode::OK,
+					json!(DirResponse {
+						entries: listing.entries,
+						free_hint: listing.free_hint,
+						access: listing.access,
+					}),
+				),
 				Err(err) => bad_request(err.to_string()),
 			}
 		}
 		(&Method::GET, ["api", "peers", peer_id, "disks"]) => {
 			let peer = match parse_peer_id(peer_id) {
 				Ok(p) => p,
-				Err(err) => return Ok(with_cors(bad_request(err), origin_ref)),
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
 			};
 			match state.puppy.list_disks(peer).await {
-				Ok(disks) => json_response(StatusCode::OK, json!({ "disks": disks })),
+				Ok(disks) => json_response(StatusCode::OK, json!(DisksResponse { disks })),
+				Err(err) => bad_request(err.to_string()),
+			}
+		}
+		(&Method::GET, ["api", "peers", peer_id, "disks", "history"]) => {
+			let peer = match parse_peer_id(peer_id) {
+				Ok(p) => p,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			let query = parse_query(&req);
+			let Some(mount) = query.get("mount") else {
+				return Ok(cors.apply(bad_request("missing mount"), origin_ref));
+			};
+			let to = match query.get("to").map(|v| parse_time(v)).transpose() {
+				Ok(to) => to.unwrap_or_else(Utc::now),
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			let from = match query.get("from").map(|v| parse_time(v)).transpose() {
+				Ok(from) => {
+					from.unwrap_or_else(|| to - chrono::Duration::days(DISK_HISTORY_DEFAULT_DAYS))
+				}
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			match state.puppy.disk_history(peer, mount, from..to).await {
+				Ok(samples) => json_response(StatusCode::OK, json!({ "samples": samples })),
+				Err(err) => bad_request(err.to_string()),
+			}
+		}
+		(&Method::GET, ["api", "peers", peer_id, "roots"]) => {
+			let peer = match parse_peer_id(peer_id) {
+				Ok(p) => p,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			match state.puppy.list_roots(peer).await {
+				Ok(roots) => json_response(StatusCode::OK, json!(roots)),
 				Err(err) => bad_request(err.to_string()),
 			}
 		}
 		(&Method::GET, ["api", "peers", peer_id, "interfaces"]) => {
 			let peer = match parse_peer_id(peer_id) {
 				Ok(p) => p,
-				Err(err) => return Ok(with_cors(bad_request(err), origin_ref)),
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
 			};
 			match state.puppy.list_interfaces(peer).await {
 				Ok(interfaces) => {
-					json_response(StatusCode::OK, json!({ "interfaces": interfaces }))
+					json_response(StatusCode::OK, json!(InterfacesResponse { interfaces }))
 				}
 				Err(err) => bad_request(err.to_string()),
 			}
@@ -849,22 +2659,27 @@ async fn handle_request(
 		(&Method::GET, ["api", "peers", peer_id, "cpus"]) => {
 			let peer = match parse_peer_id(peer_id) {
 				Ok(p) => p,
-				Err(err) => return Ok(with_cors(bad_request(err), origin_ref)),
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
 			};
 			match state.puppy.list_cpus(peer).await {
-				Ok(cpus) => json_response(StatusCode::OK, json!({ "cpus": cpus })),
+				Ok(cpus) => json_response(StatusCode::OK, json!(CpusResponse { cpus })),
 				Err(err) => bad_request(err.to_string()),
 			}
 		}
 		(&Method::GET, ["api", "peers", peer_id, "file"]) => {
 			let peer = match parse_peer_id(peer_id) {
 				Ok(p) => p,
-				Err(err) => return Ok(with_cors(bad_request(err), origin_ref)),
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
 			};
 			let query = parse_query(&req);
 			let Some(path) = query.get("path") else {
-				return Ok(with_cors(bad_request("missing path"), origin_ref));
+				return Ok(cors.apply(bad_request("missing path"), origin_ref));
 			};
+			let safe_path = match parse_peer_path(path) {
+				Ok(path) => path,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			let path = &safe_path.to_string();
 			let accept_json = req
 				.headers()
 				.get(hyper::header::ACCEPT)
@@ -879,7 +2694,15 @@ async fn handle_request(
 			let wants_json =
 				accept_json || query.contains_key("offset") || query.contains_key("length");
 
-			if wants_json {
+			if query.get("preview").map(|v| v == "true").unwrap_or(false) {
+				let length = length
+					.unwrap_or(PREVIEW_PAGE_SIZE)
+					.min(PREVIEW_MAX_PAGE_SIZE);
+				match file_preview(&state, peer, peer_id, path, offset, length).await {
+					Ok(preview) => json_response(StatusCode::OK, json!(preview)),
+					Err(err) => bad_request(err.to_string()),
+				}
+			} else if wants_json {
 				match state
 					.puppy
 					.read_file(peer, path.clone(), offset, length)
@@ -895,20 +2718,20 @@ async fn handle_request(
 					.to_string();
 				let range_header = req.headers().get(RANGE).cloned();
 				let (chunk, status, content_range) = if let Some(range_value) = range_header {
-					let total_len = infer_peer_file_size(&state, peer, path).await;
+					let total_len = infer_peer_file_size(&state, peer, &safe_path).await;
 					let header_value = match range_value.to_str() {
 						Ok(value) => value,
 						Err(_) => {
-							return Ok(with_cors(bad_request("invalid range header"), origin_ref));
+							return Ok(cors.apply(bad_request("invalid range header"), origin_ref));
 						}
 					};
 					let (start, end_opt) = match parse_peer_range_header(header_value, total_len) {
 						Ok(value) => value,
 						Err(RangeParseError::Invalid) => {
-							return Ok(with_cors(bad_request("invalid range header"), origin_ref));
+							return Ok(cors.apply(bad_request("invalid range header"), origin_ref));
 						}
 						Err(RangeParseError::Unsatisfiable) => {
-							return Ok(with_cors(
+							return Ok(cors.apply(
 								range_not_satisfiable_response(total_len.unwrap_or(0)),
 								origin_ref,
 							));
@@ -917,18 +2740,14 @@ async fn handle_request(
 					let desired_len = end_opt
 						.map(|end| end.saturating_sub(start).saturating_add(1))
 						.unwrap_or(READ_CHUNK_SIZE as u64);
-					let chunk = match state
-						.puppy
-						.read_file(peer, path.clone(), start, Some(desired_len))
-						.await
-					{
+					let chunk = match state.read_range(peer, path, start, desired_len).await {
 						Ok(chunk) => chunk,
 						Err(err) => {
-							return Ok(with_cors(bad_request(err.to_string()), origin_ref));
+							return Ok(cors.apply(bad_request(err.to_string()), origin_ref));
 						}
 					};
 					if chunk.data.is_empty() {
-						return Ok(with_cors(
+						return Ok(cors.apply(
 							range_not_satisfiable_response(total_len.unwrap_or(0)),
 							origin_ref,
 						));
@@ -942,12 +2761,22 @@ async fn handle_request(
 					let range_value = format!("bytes {}-{}/{}", start, actual_end, total_str);
 					(chunk, StatusCode::PARTIAL_CONTENT, Some(range_value))
 				} else {
-					let chunk = match state.puppy.read_file(peer, path.clone(), 0, None).await {
-						Ok(chunk) => chunk,
+					let contents = match state
+						.puppy
+						.read_file_to_end(peer, path.clone(), ReadToEndOptions::default(), |_| {})
+						.await
+					{
+						Ok(contents) => contents,
 						Err(err) => {
-							return Ok(with_cors(bad_request(err.to_string()), origin_ref));
+							return Ok(cors.apply(bad_request(err.to_string()), origin_ref));
 						}
 					};
+					let chunk = FileChunk {
+						offset: 0,
+						data: contents.data,
+						eof: true,
+						hole: 0,
+					};
 					(chunk, StatusCode::OK, None)
 				};
 
@@ -965,11 +2794,15 @@ async fn handle_request(
 		(&Method::GET, ["api", "peers", peer_id, "thumbnail"]) => {
 			let peer = match parse_peer_id(peer_id) {
 				Ok(p) => p,
-				Err(err) => return Ok(with_cors(bad_request(err), origin_ref)),
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
 			};
 			let query = parse_query(&req);
 			let Some(path) = query.get("path") else {
-				return Ok(with_cors(bad_request("missing path"), origin_ref));
+				return Ok(cors.apply(bad_request("missing path"), origin_ref));
+			};
+			let path = match parse_peer_path(path) {
+				Ok(path) => path,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
 			};
 			let max_width = query
 				.get("max_width")
@@ -981,7 +2814,7 @@ async fn handle_request(
 				.unwrap_or(128);
 			match state
 				.puppy
-				.get_thumbnail(peer, path.clone(), max_width, max_height)
+				.get_thumbnail(peer, path, max_width, max_height)
 				.await
 			{
 				Ok(thumb) => Response::builder()
@@ -989,17 +2822,59 @@ async fn handle_request(
 					.header(hyper::header::CONTENT_TYPE, thumb.mime_type)
 					.body(Body::from(thumb.data))
 					.unwrap(),
+				Err(err) if err.is::<ImageTooLarge>() => {
+					error_response(StatusCode::UNPROCESSABLE_ENTITY, err.to_string())
+				}
+				Err(err) => bad_request(err.to_string()),
+			}
+		}
+		(&Method::GET, ["api", "peers", peer_id, "contact-sheet"]) => {
+			let peer = match parse_peer_id(peer_id) {
+				Ok(p) => p,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			let query = parse_query(&req);
+			let Some(path) = query.get("path") else {
+				return Ok(cors.apply(bad_request("missing path"), origin_ref));
+			};
+			let path = match parse_peer_path(path) {
+				Ok(path) => path,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			let defaults = ContactSheetOptions::default();
+			let number = |name: &str, default: u32| {
+				query
+					.get(name)
+					.and_then(|v| v.parse::<u32>().ok())
+					.unwrap_or(default)
+			};
+			let options = ContactSheetOptions {
+				columns: number("columns", defaults.columns),
+				cell_size: number("cell_size", defaults.cell_size),
+				max_items: number("max_items", defaults.max_items),
+			};
+			match state.puppy.contact_sheet(peer, path, options).await {
+				Ok(ContactSheetResult::Sheet(sheet)) => Response::builder()
+					.status(StatusCode::OK)
+					.header(hyper::header::CONTENT_TYPE, sheet.image.mime_type)
+					.header("x-contact-sheet-cells", sheet.cells)
+					.header("x-contact-sheet-images", sheet.images)
+					.header("x-contact-sheet-partial", sheet.partial.to_string())
+					.body(Body::from(sheet.image.data))
+					.unwrap(),
+				Ok(ContactSheetResult::Empty) => Response::builder()
+					.status(StatusCode::NO_CONTENT)
+					.body(Body::empty())
+					.unwrap(),
 				Err(err) => bad_request(err.to_string()),
 			}
 		}
 		(&Method::POST, ["api", "peers", peer_id, "shell", "start"]) => {
 			let peer = match parse_peer_id(peer_id) {
 				Ok(p) => p,
-				Err(err) => return Ok(with_cors(bad_request(err), origin_ref)),
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
 			};
-			let mut id_bytes = [0u8; 8];
-			OsRng.fill_bytes(&mut id_bytes);
-			let session_id = u64::from_le_bytes(id_bytes);
+			let session_id = state.puppy.next_id(IdKind::Shell);
 			match state.puppy.start_shell(peer, session_id).await {
 				Ok(id) => json_response(StatusCode::OK, json!(ShellStartResponse { id })),
 				Err(err) => bad_request(err.to_string()),
@@ -1008,11 +2883,11 @@ async fn handle_request(
 		(&Method::POST, ["api", "peers", peer_id, "shell", "input"]) => {
 			let peer = match parse_peer_id(peer_id) {
 				Ok(p) => p,
-				Err(err) => return Ok(with_cors(bad_request(err), origin_ref)),
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
 			};
 			let body = hyper::body::aggregate(req.into_body()).await;
 			let Ok(buf) = body else {
-				return Ok(with_cors(bad_request("failed to read body"), origin_ref));
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
 			};
 			let parsed: Result<ShellInputRequest, _> = serde_json::from_reader(buf.reader());
 			match parsed {
@@ -1021,39 +2896,67 @@ async fn handle_request(
 					.shell_input(peer, payload.id, payload.data)
 					.await
 				{
-					Ok(data) => json_response(StatusCode::OK, json!(ShellOutputResponse { data })),
+					Ok(output) => {
+						let (data, exited) = match output {
+							ShellOutput::Output(data) => (data, false),
+							ShellOutput::Exited => (Vec::new(), true),
+						};
+						json_response(StatusCode::OK, json!(ShellOutputResponse { data, exited }))
+					}
 					Err(err) => bad_request(err.to_string()),
 				},
 				Err(err) => bad_request(format!("invalid json: {err}")),
 			}
 		}
 		(&Method::GET, ["api", "storage"]) => match state.puppy.list_storage_files().await {
-			Ok(files) => json_response(StatusCode::OK, json!({ "files": files })),
+			Ok(files) => json_response(StatusCode::OK, json!(StorageResponse { files })),
 			Err(err) => bad_request(err.to_string()),
 		},
+		(&Method::GET, ["api", "storage", "growth"]) => {
+			let query = parse_query(&req);
+			let days = match query.get("days").map(|v| v.parse::<i64>()) {
+				None => PROJECTION_DAYS,
+				Some(Ok(days)) if (1..=MAX_GROWTH_DAYS).contains(&days) => days,
+				Some(_) => return Ok(cors.apply(bad_request("invalid days"), origin_ref)),
+			};
+			let node = match state.puppy.local_peer().await {
+				Ok(node) => node,
+				Err(err) => {
+					return Ok(cors.apply(
+						error_response(StatusCode::INTERNAL_SERVER_ERROR, err),
+						origin_ref,
+					));
+				}
+			};
+			match state
+				.puppy
+				.growth_report(node, chrono::Duration::days(days))
+				.await
+			{
+				Ok(report) => json_response(StatusCode::OK, json!(report)),
+				Err(err) => error_response(StatusCode::INTERNAL_SERVER_ERROR, err),
+			}
+		}
 		(&Method::GET, ["api", "file", "hash"]) => {
 			let query = parse_query(&req);
 			let Some(raw_hash) = query.get("hash") else {
-				return Ok(with_cors(bad_request("missing hash parameter"), origin_ref));
+				return Ok(cors.apply(bad_request("missing hash parameter"), origin_ref));
 			};
 			let hash_bytes = match parse_hash_param(raw_hash) {
 				Ok(value) => value,
-				Err(err) => return Ok(with_cors(bad_request(err), origin_ref)),
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
 			};
 			let (path, entry) = match state.puppy.resolve_local_file_by_hash(&hash_bytes) {
 				Ok(Some(result)) => result,
 				Ok(None) => {
-					return Ok(with_cors(
-						json_response(
-							StatusCode::NOT_FOUND,
-							json!({ "error": "file hash not found locally" }),
-						),
+					return Ok(cors.apply(
+						error_response(StatusCode::NOT_FOUND, "file hash not found locally"),
 						origin_ref,
 					));
 				}
 				Err(err) => {
-					return Ok(with_cors(
-						json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({ "error": err })),
+					return Ok(cors.apply(
+						error_response(StatusCode::INTERNAL_SERVER_ERROR, err),
 						origin_ref,
 					));
 				}
@@ -1062,27 +2965,19 @@ async fn handle_request(
 				Ok(file) => file,
 				Err(err) => {
 					let response = if matches!(err.kind(), ErrorKind::NotFound) {
-						json_response(
-							StatusCode::NOT_FOUND,
-							json!({ "error": "file missing on disk" }),
-						)
+						error_response(StatusCode::NOT_FOUND, "file missing on disk")
 					} else {
-						json_response(
-							StatusCode::INTERNAL_SERVER_ERROR,
-							json!({ "error": err.to_string() }),
-						)
+						error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
 					};
-					return Ok(with_cors(response, origin_ref));
+					return Ok(cors.apply(response, origin_ref));
 				}
 			};
 			let metadata = match file.metadata().await {
 				Ok(metadata) => metadata,
 				Err(err) => {
-					let response = json_response(
-						StatusCode::INTERNAL_SERVER_ERROR,
-						json!({ "error": err.to_string() }),
-					);
-					return Ok(with_cors(response, origin_ref));
+					let response =
+						error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+					return Ok(cors.apply(response, origin_ref));
 				}
 			};
 			let total_len = metadata.len();
@@ -1095,10 +2990,7 @@ async fn handle_request(
 			let range_header = req.headers().get(RANGE).cloned();
 			if total_len == 0 {
 				if range_header.is_some() {
-					return Ok(with_cors(
-						range_not_satisfiable_response(total_len),
-						origin_ref,
-					));
+					return Ok(cors.apply(range_not_satisfiable_response(total_len), origin_ref));
 				}
 				let resp = Response::builder()
 					.status(StatusCode::OK)
@@ -1107,25 +2999,24 @@ async fn handle_request(
 					.header(ACCEPT_RANGES, HeaderValue::from_static("bytes"))
 					.body(Body::empty())
 					.unwrap();
-				return Ok(with_cors(resp, origin_ref));
+				return Ok(cors.apply(resp, origin_ref));
 			}
 			let (start, end, status) = if let Some(range_value) = range_header {
 				let header_value = match range_value.to_str() {
 					Ok(value) => value,
 					Err(_) => {
-						return Ok(with_cors(bad_request("invalid range header"), origin_ref));
+						return Ok(cors.apply(bad_request("invalid range header"), origin_ref));
 					}
 				};
 				match parse_range_header(header_value, total_len) {
 					Ok((start, end)) => (start, end, StatusCode::PARTIAL_CONTENT),
 					Err(RangeParseError::Invalid) => {
-						return Ok(with_cors(bad_request("invalid range header"), origin_ref));
+						return Ok(cors.apply(bad_request("invalid range header"), origin_ref));
 					}
 					Err(RangeParseError::Unsatisfiable) => {
-						return Ok(with_cors(
-							range_not_satisfiable_response(total_len),
-							origin_ref,
-						));
+						return Ok(
+							cors.apply(range_not_satisfiable_response(total_len), origin_ref)
+						);
 					}
 				}
 			} else {
@@ -1133,11 +3024,9 @@ async fn handle_request(
 			};
 			if start > 0 {
 				if let Err(err) = file.seek(SeekFrom::Start(start)).await {
-					let response = json_response(
-						StatusCode::INTERNAL_SERVER_ERROR,
-						json!({ "error": err.to_string() }),
-					);
-					return Ok(with_cors(response, origin_ref));
+					let response =
+						error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+					return Ok(cors.apply(response, origin_ref));
 				}
 			}
 			let chunk_len = end - start + 1;
@@ -1169,7 +3058,7 @@ async fn handle_request(
 				);
 			}
 			let resp = builder.body(Body::wrap_stream(stream)).unwrap();
-			with_cors(resp, origin_ref)
+			cors.apply(resp, origin_ref)
 		}
 		(&Method::GET, ["api", "scans", "results"]) => {
 			let query = parse_query(&req);
@@ -1181,25 +3070,237 @@ async fn handle_request(
 				.get("page_size")
 				.and_then(|v| v.parse::<usize>().ok())
 				.unwrap_or(25);
-			match state.puppy.fetch_scan_results_page(page, page_size) {
-				Ok((rows, total)) => json_response(
+			let cursor = match query.get("cursor").map(|raw| PageCursor::decode(raw)) {
+				Some(Ok(cursor)) => Some(cursor),
+				Some(Err(err)) => return Ok(cors.apply(bad_request(err.to_string()), origin_ref)),
+				None => None,
+			};
+			let result = match cursor {
+				Some(cursor) => state
+					.puppy
+					.fetch_scan_results_after(Some(&cursor), page_size),
+				None => state.puppy.fetch_scan_results_page(page, page_size),
+			};
+			match result {
+				Ok((results, total)) => json_response(
+					StatusCode::OK,
+					json!(ScanResultsResponse {
+						rows: results.rows,
+						total,
+						page,
+						page_size,
+						next_cursor: results.next_cursor.map(|cursor| cursor.encode()),
+					}),
+				),
+				Err(err) => bad_request(err),
+			}
+		}
+		(&Method::GET, ["api", "scans", "history"]) => {
+			let query = parse_query(&req);
+			let page = query
+				.get("page")
+				.and_then(|v| v.parse::<u64>().ok())
+				.unwrap_or(0);
+			let path = query.get("path").cloned();
+			match state.puppy.scan_history(path.clone(), page) {
+				Ok(runs) => {
+					let trend = match path.as_deref() {
+						Some(path) => state.puppy.scan_trend(path).ok().flatten(),
+						None => None,
+					};
+					json_response(
+						StatusCode::OK,
+						json!(ScanHistoryResponse { runs, trend, page }),
+					)
+				}
+				Err(err) => error_response(StatusCode::INTERNAL_SERVER_ERROR, err),
+			}
+		}
+		(&Method::GET, ["api", "transfers"]) => {
+			let query = parse_query(&req);
+			let page = query
+				.get("page")
+				.and_then(|v| v.parse::<u64>().ok())
+				.unwrap_or(0);
+			let peer = match query.get("peer").map(|peer| parse_peer_id(peer)) {
+				Some(Ok(peer)) => Some(peer),
+				Some(Err(err)) => return Ok(cors.apply(bad_request(err), origin_ref)),
+				None => None,
+			};
+			match state.puppy.transfer_history(peer, page) {
+				Ok(transfers) => json_response(
 					StatusCode::OK,
-					json!({ "rows": rows, "total": total, "page": page, "page_size": page_size }),
+					json!({ "transfers": transfers, "page": page }),
 				),
+				Err(err) => error_response(StatusCode::INTERNAL_SERVER_ERROR, err),
+			}
+		}
+		(&Method::GET, ["api", "activity"]) => {
+			let query = parse_query(&req);
+			let filter = match parse_activity_filter(&query) {
+				Ok(filter) => filter,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			let cursor = match query.get("cursor").map(|raw| PageCursor::decode(raw)) {
+				Some(Ok(cursor)) => Some(cursor),
+				Some(Err(err)) => return Ok(cors.apply(bad_request(err.to_string()), origin_ref)),
+				None => None,
+			};
+			let limit = query
+				.get("limit")
+				.and_then(|v| v.parse::<u64>().ok())
+				.unwrap_or(DEFAULT_ACTIVITY_PAGE)
+				.min(MAX_ACTIVITY_PAGE);
+			match state.puppy.activity(&filter, cursor.as_ref(), limit) {
+				Ok(page) => json_response(
+					StatusCode::OK,
+					json!(ActivityResponse {
+						events: page.rows,
+						next_cursor: page.next_cursor.map(|cursor| cursor.encode()),
+					}),
+				),
+				Err(err) => error_response(StatusCode::INTERNAL_SERVER_ERROR, err),
+			}
+		}
+		(&Method::GET, ["api", "debug", "requests"]) => {
+			let limit = parse_query(&req)
+				.get("limit")
+				.and_then(|v| v.parse::<usize>().ok())
+				.unwrap_or(50)
+				.min(REQUEST_TRACE_CAPACITY);
+			let requests = slowest(state.puppy.recent_requests(), limit);
+			json_response(StatusCode::OK, json!(RequestTracesResponse { requests }))
+		}
+		(&Method::GET, ["api", "pins"]) => match state.puppy.list_pins() {
+			Ok(pins) => json_response(StatusCode::OK, json!(PinsResponse { pins })),
+			Err(err) => error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+		},
+		(&Method::POST, ["api", "pins"]) => {
+			let body = hyper::body::aggregate(req.into_body()).await;
+			let Ok(buf) = body else {
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
+			};
+			let payload = match serde_json::from_reader::<_, PinRequest>(buf.reader()) {
+				Ok(payload) => payload,
+				Err(err) => {
+					return Ok(cors.apply(bad_request(format!("invalid json: {err}")), origin_ref));
+				}
+			};
+			let peer = match parse_peer_id(&payload.peer) {
+				Ok(peer) => peer,
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+			};
+			let defaults = PinOptions::default();
+			let opts = PinOptions {
+				interval: payload
+					.interval_secs
+					.map(Duration::from_secs)
+					.unwrap_or(defaults.interval),
+				bandwidth_limit: payload.bandwidth_limit,
+				delete_removed: payload.delete_removed.unwrap_or(defaults.delete_removed),
+				wake_if_needed: payload.wake_if_needed.unwrap_or(defaults.wake_if_needed),
+			};
+			match state.puppy.pin_remote_folder(
+				peer,
+				&payload.remote_path,
+				&payload.local_dest,
+				opts,
+			) {
+				Ok(pin) => json_response(StatusCode::CREATED, json!(PinResponse { pin })),
+				Err(err) => bad_request(format!("{err:#}")),
+			}
+		}
+		(&Method::POST, ["api", "pins", pin_id, action @ ("pause" | "resume")]) => {
+			let Ok(id) = pin_id.parse::<u64>() else {
+				return Ok(cors.apply(bad_request("invalid pin id"), origin_ref));
+			};
+			let result = if *action == "pause" {
+				state.puppy.pause_pin(id)
+			} else {
+				state.puppy.resume_pin(id)
+			};
+			match result {
+				Ok(()) => Response::builder()
+					.status(StatusCode::NO_CONTENT)
+					.body(Body::empty())
+					.unwrap(),
+				Err(err) => error_response(StatusCode::NOT_FOUND, err.to_string()),
+			}
+		}
+		(&Method::DELETE, ["api", "pins", pin_id]) => {
+			let Ok(id) = pin_id.parse::<u64>() else {
+				return Ok(cors.apply(bad_request("invalid pin id"), origin_ref));
+			};
+			match state.puppy.unpin(id) {
+				Ok(()) => Response::builder()
+					.status(StatusCode::NO_CONTENT)
+					.body(Body::empty())
+					.unwrap(),
+				Err(err) => error_response(StatusCode::NOT_FOUND, err.to_string()),
+			}
+		}
+		(&Method::GET, ["api", "scans", "diff"]) => {
+			let query = parse_query(&req);
+			let run_id = |key: &str| query.get(key).and_then(|v| v.parse::<i64>().ok());
+			let (Some(run_a), Some(run_b)) = (run_id("a"), run_id("b")) else {
+				return Ok(cors.apply(bad_request("missing scan run ids"), origin_ref));
+			};
+			let page = query
+				.get("page")
+				.and_then(|v| v.parse::<u64>().ok())
+				.unwrap_or(0);
+			match state.puppy.scan_diff(run_a, run_b, page) {
+				Ok(entries) => {
+					json_response(StatusCode::OK, json!(ScanDiffResponse { entries, page }))
+				}
 				Err(err) => bad_request(err),
 			}
 		}
+		(&Method::GET, ["api", "diff"]) => {
+			let query = parse_query(&req);
+			let file = |side: &str| -> Result<FileRef, String> {
+				let peer = query
+					.get(&format!("peer_{side}"))
+					.ok_or_else(|| format!("missing peer_{side}"))?;
+				let path = query
+					.get(&format!("path_{side}"))
+					.ok_or_else(|| format!("missing path_{side}"))?;
+				Ok(FileRef::new(parse_peer_id(peer)?, path.clone()))
+			};
+			let (a, b) = match (file("a"), file("b")) {
+				(Ok(a), Ok(b)) => (a, b),
+				(Err(err), _) | (_, Err(err)) => {
+					return Ok(cors.apply(bad_request(err), origin_ref));
+				}
+			};
+			let mut opts = DiffOptions::default();
+			if let Some(context) = query.get("context").and_then(|v| v.parse().ok()) {
+				opts.context_lines = context;
+			}
+			match state.puppy.diff_files(a, b, opts).await {
+				Ok(diff) => json_response(StatusCode::OK, json!(DiffResponse { diff })),
+				Err(err) => bad_request(err.to_string()),
+			}
+		}
 		(&Method::POST, ["api", "scans"]) => {
 			let body = hyper::body::aggregate(req.into_body()).await;
 			let Ok(buf) = body else {
-				return Ok(with_cors(bad_request("failed to read body"), origin_ref));
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
 			};
 			let parsed: Result<ScanStartRequest, _> = serde_json::from_reader(buf.reader());
 			match parsed {
-				Ok(payload) => match state.puppy.scan_folder(payload.path) {
+				Ok(payload) => match state.puppy.scan_folder_with_options(
+					payload.path,
+					payload.override_window,
+					payload.extract_metadata,
+					payload.limits,
+				) {
 					Ok(handle) => {
 						let id = state.insert_scan(handle);
-						json_response(StatusCode::CREATED, json!({ "scan_id": id }))
+						json_response(
+							StatusCode::CREATED,
+							json!(ScanStartResponse { scan_id: id }),
+						)
 					}
 					Err(err) => bad_request(err),
 				},
@@ -1208,16 +3309,16 @@ async fn handle_request(
 		}
 		(&Method::GET, ["api", "scans", scan_id, "events"]) => {
 			let Ok(id) = scan_id.parse::<u64>() else {
-				return Ok(with_cors(bad_request("invalid scan id"), origin_ref));
+				return Ok(cors.apply(bad_request("invalid scan id"), origin_ref));
 			};
 			match state.poll_scan(id) {
-				Some(events) => json_response(StatusCode::OK, json!({ "events": events })),
-				None => json_response(StatusCode::NOT_FOUND, json!({ "error": "scan not found" })),
+				Some(events) => json_response(StatusCode::OK, json!(ScanEventsResponse { events })),
+				None => error_response(StatusCode::NOT_FOUND, "scan not found"),
 			}
 		}
 		(&Method::POST, ["api", "scans", scan_id, "cancel"]) => {
 			let Ok(id) = scan_id.parse::<u64>() else {
-				return Ok(with_cors(bad_request("invalid scan id"), origin_ref));
+				return Ok(cors.apply(bad_request("invalid scan id"), origin_ref));
 			};
 			if state.cancel_scan(id) {
 				Response::builder()
@@ -1225,7 +3326,7 @@ async fn handle_request(
 					.body(Body::empty())
 					.unwrap()
 			} else {
-				json_response(StatusCode::NOT_FOUND, json!({ "error": "scan not found" }))
+				error_response(StatusCode::NOT_FOUND, "scan not found")
 			}
 		}
 		(&Method::GET, ["api", "search"]) => {
@@ -1246,6 +3347,33 @@ async fn handle_request(
 					}
 				}
 			}
+			let node_id = match q.get("peer_id").map(|raw| parse_peer_id(raw)) {
+				Some(Ok(peer)) => peer_to_node_id(&peer),
+				Some(Err(err)) => return Ok(cors.apply(bad_request(err), origin_ref)),
+				None => None,
+			};
+			let file_origin = match q.get("origin").map(|raw| (raw, FileOrigin::parse(raw))) {
+				Some((_, Some(file_origin))) => Some(file_origin),
+				Some((raw, None)) => {
+					return Ok(cors.apply(bad_request(format!("unknown origin {raw}")), origin_ref));
+				}
+				None => None,
+			};
+			let introduced_by_peer = match q.get("introduced_by_peer").map(|raw| parse_peer_id(raw))
+			{
+				Some(Ok(peer)) => Some(peer.to_string()),
+				Some(Err(err)) => return Ok(cors.apply(bad_request(err), origin_ref)),
+				None => None,
+			};
+			let sort_by = match q.get("sort_by").map(String::as_str) {
+				None | Some("date") => SearchSortBy::Date,
+				Some("name") => SearchSortBy::Name,
+				Some(raw) => {
+					return Ok(
+						cors.apply(bad_request(format!("unknown sort_by {raw}")), origin_ref)
+					);
+				}
+			};
 			let args = SearchFilesArgs {
 				name_query: q.get("name_query").cloned(),
 				content_query: q.get("content_query").cloned(),
@@ -1253,7 +3381,21 @@ async fn handle_request(
 				date_to: q.get("date_to").cloned(),
 				replicas_min: q.get("replicas_min").and_then(|v| v.parse::<u64>().ok()),
 				replicas_max: q.get("replicas_max").and_then(|v| v.parse::<u64>().ok()),
+				include_empty: q
+					.get("include_empty")
+					.is_some_and(|v| v == "true" || v == "1"),
 				mime_types,
+				node_id,
+				path_prefix: q.get("path_prefix").cloned(),
+				min_duration: q.get("min_duration").and_then(|v| v.parse::<u64>().ok()),
+				max_duration: q.get("max_duration").and_then(|v| v.parse::<u64>().ok()),
+				min_pixels: q.get("min_pixels").and_then(|v| v.parse::<u64>().ok()),
+				include_deleted: q
+					.get("include_deleted")
+					.is_some_and(|v| v == "true" || v == "1"),
+				origin: file_origin,
+				introduced_by_peer,
+				sort_by,
 				sort_desc: q
 					.get("sort_desc")
 					.map(|v| v == "true" || v == "1")
@@ -1267,11 +3409,70 @@ async fn handle_request(
 					.and_then(|v| v.parse::<usize>().ok())
 					.unwrap_or(50),
 			};
+			if let Err(err) = args.validate() {
+				return Ok(cors.apply(bad_request(err.to_string()), origin_ref));
+			}
+			// `peer` runs the search on that peer's own index, or on every
+			// connected peer with `all`, instead of the local one.
+			if let Some(target) = q.get("peer") {
+				let search = if target == "all" {
+					state.puppy.search_connected_peers(args).await
+				} else {
+					let peer = match parse_peer_id(target) {
+						Ok(peer) => peer,
+						Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
+					};
+					match state.puppy.search_peer_files(peer, args).await {
+						Ok(results) => PeerSearch {
+							hits: results
+								.into_iter()
+								.map(|result| PeerSearchHit { peer, result })
+								.collect(),
+							skipped: Vec::new(),
+						},
+						Err(err) => return Ok(cors.apply(bad_request(err.to_string()), origin_ref)),
+					}
+				};
+				let results: Vec<FileSearchResult> =
+					search.hits.into_iter().map(|hit| hit.result).collect();
+				return Ok(cors.apply(
+					json_response(
+						StatusCode::OK,
+						json!(SearchResponse {
+							total: results.len(),
+							results,
+							mime_types: Vec::new(),
+							next_cursor: None,
+							skipped: search
+								.skipped
+								.iter()
+								.map(|skipped| format!("{}: {}", skipped.peer, skipped.reason))
+								.collect(),
+						}),
+					),
+					origin_ref,
+				));
+			}
+			let cursor = match q.get("cursor").map(|raw| PageCursor::decode(raw)) {
+				Some(Ok(cursor)) => Some(cursor),
+				Some(Err(err)) => return Ok(cors.apply(bad_request(err.to_string()), origin_ref)),
+				None => None,
+			};
 			let puppy = Arc::clone(&state.puppy);
-			match task::spawn_blocking(move || puppy.search_files(args)).await {
-				Ok(Ok((results, mimes, total))) => json_response(
+			let search = move || match cursor {
+				Some(cursor) => puppy.search_files_after(args, Some(&cursor)),
+				None => puppy.search_files(args),
+			};
+			match task::spawn_blocking(search).await {
+				Ok(Ok((results, mime_types, total))) => json_response(
 					StatusCode::OK,
-					json!({ "results": results, "mime_types": mimes, "total": total }),
+					json!(SearchResponse {
+						results: results.rows,
+						mime_types,
+						total,
+						next_cursor: results.next_cursor.map(|cursor| cursor.encode()),
+						skipped: Vec::new(),
+					}),
 				),
 				Ok(Err(err)) => bad_request(err),
 				Err(err) => bad_request(err.to_string()),
@@ -1280,7 +3481,9 @@ async fn handle_request(
 		(&Method::GET, ["api", "mime-types"]) => {
 			let puppy = Arc::clone(&state.puppy);
 			match task::spawn_blocking(move || puppy.get_mime_types()).await {
-				Ok(Ok(mimes)) => json_response(StatusCode::OK, json!({ "mime_types": mimes })),
+				Ok(Ok(mime_types)) => {
+					json_response(StatusCode::OK, json!(MimeTypesResponse { mime_types }))
+				}
 				Ok(Err(err)) => bad_request(err),
 				Err(err) => bad_request(err.to_string()),
 			}
@@ -1288,18 +3491,25 @@ async fn handle_request(
 		(&Method::POST, ["api", "updates", peer_id]) => {
 			let peer = match parse_peer_id(peer_id) {
 				Ok(p) => p,
-				Err(err) => return Ok(with_cors(bad_request(err), origin_ref)),
+				Err(err) => return Ok(cors.apply(bad_request(err), origin_ref)),
 			};
 			let body = hyper::body::aggregate(req.into_body()).await;
 			let Ok(buf) = body else {
-				return Ok(with_cors(bad_request("failed to read body"), origin_ref));
+				return Ok(cors.apply(bad_request("failed to read body"), origin_ref));
 			};
 			let parsed: Result<UpdateStartRequest, _> = serde_json::from_reader(buf.reader());
 			match parsed {
-				Ok(payload) => match state.puppy.update_remote_peer(peer, payload.version) {
+				Ok(payload) => match state.puppy.update_remote_peer_with_override(
+					peer,
+					payload.version,
+					payload.override_window,
+				) {
 					Ok(rx) => {
 						let id = state.insert_update(rx);
-						json_response(StatusCode::CREATED, json!({ "update_id": id }))
+						json_response(
+							StatusCode::CREATED,
+							json!(UpdateStartResponse { update_id: id }),
+						)
 					}
 					Err(err) => bad_request(err),
 				},
@@ -1308,32 +3518,35 @@ async fn handle_request(
 		}
 		(&Method::GET, ["api", "updates", update_id, "events"]) => {
 			let Ok(id) = update_id.parse::<u64>() else {
-				return Ok(with_cors(bad_request("invalid update id"), origin_ref));
+				return Ok(cors.apply(bad_request("invalid update id"), origin_ref));
 			};
 			match state.poll_update(id) {
-				Some(events) => json_response(StatusCode::OK, json!({ "events": events })),
-				None => json_response(
-					StatusCode::NOT_FOUND,
-					json!({ "error": "update not found" }),
-				),
+				Some(events) => {
+					json_response(StatusCode::OK, json!(UpdateEventsResponse { events }))
+				}
+				None => error_response(StatusCode::NOT_FOUND, "update not found"),
 			}
 		}
-		_ => json_response(StatusCode::NOT_FOUND, json!({ "error": "not found" })),
+		_ => error_response(StatusCode::NOT_FOUND, "not found"),
 	};
 
-	Ok(with_cors(response, origin_ref))
+	Ok(cors.apply(response, origin_ref))
 }
 
 /// Start a simple HTTP server exposing a small API surface on top of PuppyNet.
 pub async fn serve(puppy: Arc<PuppyNet>, addr: SocketAddr) -> Result<()> {
-	let jwt_secret = load_jwt_secret();
+	let jwt_secret = config::startup()
+		.jwt_secret
+		.clone()
+		.unwrap_or_else(|| puppy.secrets().get_or_generate(JWT_SECRET));
 	let state = Arc::new(ApiState::new(puppy, jwt_secret));
-	let make_svc = make_service_fn(move |_| {
+	let make_svc = make_service_fn(move |conn: &AddrStream| {
 		let state = Arc::clone(&state);
+		let remote_addr = conn.remote_addr();
 		async move {
 			Ok::<_, Infallible>(service_fn(move |req| {
 				let state = Arc::clone(&state);
-				handle_request(req, state)
+				handle_request(req, state, remote_addr)
 			}))
 		}
 	});
@@ -1345,7 +3558,533 @@ pub async fn serve(puppy: Arc<PuppyNet>, addr: SocketAddr) -> Result<()> {
 		.with_graceful_shutdown(async {
 			let _ = signal::ctrl_c().await;
 		});
-	log::info!("HTTP API listening on {}", addr);
+	tracing::info!("HTTP API listening on {}", addr);
 	server.await?;
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::demo::DemoFixture;
+	use crate::openapi::{API_VERSION, response_schema, validate};
+	use crate::state::Rule;
+
+	fn folder(path: &str, flags: u8) -> Permission {
+		Permission::new(Rule::Folder(FolderRule::new(path.into(), flags)))
+	}
+
+	fn assert_matches_spec(
+		doc: &serde_json::Value,
+		method: &str,
+		path: &str,
+		status: u16,
+		body: serde_json::Value,
+	) {
+		let schema = response_schema(doc, method, path, status);
+		if let Err(err) = validate(&schema, &body, "$") {
+			panic!("{method} {path}: {err}\n{body:#}");
+		}
+	}
+
+	async fn body_json(resp: Response<Body>) -> serde_json::Value {
+		let bytes = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+		serde_json::from_slice(&bytes).unwrap()
+	}
+
+	const TEST_JWT_SECRET: &str = "test-secret";
+
+	/// The API of a demo node, for driving [`handle_request`].
+	fn demo_api() -> Arc<ApiState> {
+		let puppy = Arc::new(PuppyNet::new_demo(1));
+		Arc::new(ApiState::new(puppy, String::from(TEST_JWT_SECRET)))
+	}
+
+	/// A request signed in as `admin`, sent from a page at `origin`.
+	fn signed_in(
+		method: Method,
+		uri: &str,
+		origin: &str,
+		body: Option<serde_json::Value>,
+	) -> Request<Body> {
+		let token = auth::issue_jwt("admin", TEST_JWT_SECRET.as_bytes()).unwrap();
+		Request::builder()
+			.method(method)
+			.uri(uri)
+			.header(ORIGIN, origin)
+			.header(hyper::header::AUTHORIZATION, format!("Bearer {token}"))
+			.body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+			.unwrap()
+	}
+
+	async fn send(state: &Arc<ApiState>, req: Request<Body>) -> Response<Body> {
+		let remote_addr = SocketAddr::from(([127, 0, 0, 1], 40000));
+		handle_request(req, Arc::clone(state), remote_addr)
+			.await
+			.unwrap()
+	}
+
+	fn header(resp: &Response<Body>, name: impl hyper::header::AsHeaderName) -> Option<&str> {
+		resp.headers().get(name).and_then(|v| v.to_str().ok())
+	}
+
+	#[test]
+	fn openapi_document_lists_each_route_once() {
+		let routes = api_routes();
+		let doc = document::<ApiError>(&routes);
+		assert_eq!(doc["info"]["version"], json!(API_VERSION));
+		let operations = doc["paths"]
+			.as_object()
+			.unwrap()
+			.values()
+			.map(|item| item.as_object().unwrap().len())
+			.sum::<usize>();
+		assert_eq!(operations, routes.len());
+		let public = &doc["paths"]["/api/openapi.json"]["get"];
+		assert_eq!(public["security"], json!([]));
+		let scan =
+			&doc["paths"]["/api/scans"]["post"]["requestBody"]["content"]["application/json"];
+		let required = scan["schema"]["required"].as_array().unwrap();
+		assert_eq!(required, &vec![json!("path")]);
+	}
+
+	#[test]
+	fn permission_responses_match_the_spec() {
+		let doc = document::<ApiError>(&api_routes());
+		let perms = vec![
+			folder("/srv", FLAG_READ),
+			folder("/srv/media", FLAG_READ | FLAG_WRITE),
+			Permission::new(Rule::Owner),
+		];
+		let listed = json!(PermissionsResponse {
+			fetched_at: Some(Utc::now()),
+			revalidating: Some(true),
+			..permissions_response(&perms)
+		});
+		assert_eq!(listed["warnings"].as_array().unwrap().len(), 1);
+		assert!(listed.get("revision").is_none());
+		assert_eq!(listed["revalidating"], json!(true));
+		assert_matches_spec(&doc, "get", "/api/peers/{peer_id}/permissions", 200, listed);
+
+		let grant = TemporaryGrant {
+			id: 7,
+			rule: FolderRule::new("/srv/photos".into(), FLAG_READ | FLAG_SEARCH),
+			expires_at: Utc::now() + chrono::Duration::minutes(5),
+		};
+		let granted = PermissionsResponse {
+			revision: Some(3),
+			temporary: Some(temporary_grants_response(std::slice::from_ref(&grant))),
+			..permissions_response(&perms)
+		};
+		let path = "/api/peers/{peer_id}/permissions/granted";
+		assert_matches_spec(&doc, "get", path, 200, json!(granted));
+		let path = "/api/peers/{peer_id}/permissions/temporary";
+		let created = json!(temporary_grant_response(&grant, Utc::now()));
+		assert_matches_spec(&doc, "post", path, 201, created);
+		let warnings = WarningsResponse {
+			warnings: overlap_warnings(&perms),
+		};
+		let path = "/api/peers/{peer_id}/permissions";
+		assert_matches_spec(&doc, "put", path, 200, json!(warnings));
+		let drafts = PermissionDraftResponse {
+			rules: PermissionDraft::from_permissions(&perms).rules,
+			warnings: overlap_warnings(&perms),
+			revision: 3,
+		};
+		let path = "/api/peers/{peer_id}/permissions/drafts";
+		assert_matches_spec(&doc, "get", path, 200, json!(drafts));
+		assert_matches_spec(&doc, "put", path, 200, json!(warnings));
+		let peer = PeerListEntry {
+			id: PeerId::random().to_string(),
+			name: None,
+			trust: TrustLevel::Basic,
+		};
+		assert_matches_spec(&doc, "get", "/api/peers/{peer_id}", 200, json!(peer));
+		let batch = batch_grant_response(&[
+			(PeerId::random(), BatchGrantOutcome::Granted),
+			(PeerId::random(), BatchGrantOutcome::Unchanged),
+			(
+				PeerId::random(),
+				BatchGrantOutcome::Failed {
+					error: String::from("db lock poisoned"),
+				},
+			),
+		]);
+		let path = "/api/permissions/batch";
+		assert_matches_spec(&doc, "post", path, 200, json!(batch));
+	}
+
+	#[tokio::test]
+	async fn cors_headers_follow_the_stored_settings() {
+		use hyper::header::{
+			ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_METHODS,
+			ACCESS_CONTROL_ALLOW_ORIGIN, VARY,
+		};
+		const APP: &str = "https://app.example.com";
+		const EVIL: &str = "https://evil.example.com";
+		let state = demo_api();
+		let put_cors =
+			|settings: serde_json::Value| signed_in(Method::PUT, "/api/cors", APP, Some(settings));
+
+		// Same origin only by default.
+		let resp = send(&state, signed_in(Method::GET, "/api/cors", APP, None)).await;
+		assert_eq!(resp.status(), StatusCode::OK);
+		assert_eq!(header(&resp, ACCESS_CONTROL_ALLOW_ORIGIN), None);
+		assert_eq!(header(&resp, VARY), Some("Origin"));
+
+		let resp = send(
+			&state,
+			put_cors(json!({ "allowed_origins": "any", "allow_credentials": true })),
+		)
+		.await;
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+		let resp = send(
+			&state,
+			put_cors(json!({ "allowed_origins": { "list": [APP] } })),
+		)
+		.await;
+		assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+		let preflight = Request::builder()
+			.method(Method::OPTIONS)
+			.uri("/api/scan")
+			.header(ORIGIN, APP)
+			.header("access-control-request-method", "POST")
+			.body(Body::empty())
+			.unwrap();
+		let resp = send(&state, preflight).await;
+		assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+		assert_eq!(header(&resp, ACCESS_CONTROL_ALLOW_ORIGIN), Some(APP));
+		assert_eq!(
+			header(&resp, ACCESS_CONTROL_ALLOW_METHODS),
+			Some("GET,POST,PUT,DELETE,OPTIONS")
+		);
+		let resp = send(&state, signed_in(Method::GET, "/api/cors", APP, None)).await;
+		assert_eq!(header(&resp, ACCESS_CONTROL_ALLOW_ORIGIN), Some(APP));
+		assert_eq!(
+			header(&resp, ACCESS_CONTROL_ALLOW_CREDENTIALS),
+			Some("true")
+		);
+		let resp = send(&state, signed_in(Method::GET, "/api/cors", EVIL, None)).await;
+		assert_eq!(header(&resp, ACCESS_CONTROL_ALLOW_ORIGIN), None);
+		assert_eq!(header(&resp, ACCESS_CONTROL_ALLOW_CREDENTIALS), None);
+
+		let resp = send(
+			&state,
+			put_cors(json!({ "allowed_origins": "any", "allow_credentials": false })),
+		)
+		.await;
+		assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+		let resp = send(&state, signed_in(Method::GET, "/api/cors", EVIL, None)).await;
+		assert_eq!(header(&resp, ACCESS_CONTROL_ALLOW_ORIGIN), Some("*"));
+		assert_eq!(header(&resp, ACCESS_CONTROL_ALLOW_CREDENTIALS), None);
+	}
+
+	#[tokio::test]
+	async fn drafts_are_validated_alike_from_the_web_ui_and_the_api() {
+		let doc = document::<ApiError>(&api_routes());
+		let schema = &doc["paths"]["/api/peers/{peer_id}/permissions/drafts"]["put"]["responses"]["default"]
+			["content"]["application/json"]["schema"];
+		let dir = std::env::temp_dir().display().to_string();
+		let inputs = [
+			(dir.clone(), "read"),
+			(dir.clone(), "write"),
+			(format!("  {dir}/./ "), "write"),
+			(String::from("   "), "read"),
+			(String::from("relative/folder"), "read"),
+			(format!("{dir}/../{dir}"), "read"),
+			(format!("{dir}/puppynet-no-such-folder"), "write"),
+		];
+		for (path, access) in inputs {
+			let from_ui = PermissionDraft {
+				rules: vec![RuleDraft::with_access(&path, access)],
+			}
+			.validate();
+			// What a client sends for the same choice, leaving the other
+			// fields to their defaults.
+			let body = json!({
+				"rules": [{
+					"path": path,
+					"read": true,
+					"write": access == "write",
+					"search": true,
+				}],
+			});
+			let request: SavePermissionDraftRequest = serde_json::from_value(body).unwrap();
+			let from_api = PermissionDraft {
+				rules: request.rules,
+			}
+			.validate();
+			assert_eq!(from_ui, from_api, "{path:?} with {access} access");
+			if let Err(errors) = from_api {
+				let resp = draft_errors_response(&DraftRejected { errors });
+				assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+				let body = body_json(resp).await;
+				validate(schema, &body, "$").unwrap();
+				assert_eq!(body["error"]["kind"], json!("bad_request"));
+				assert_eq!(body["errors"][0]["index"], json!(0));
+				assert_eq!(body["errors"][0]["field"], json!("path"));
+			}
+		}
+	}
+
+	/// A signed-in request for `path` with `query` encoded.
+	fn api_request(
+		method: Method,
+		path: &str,
+		query: &[(&str, &str)],
+		body: Option<serde_json::Value>,
+	) -> Request<Body> {
+		let mut uri = path.to_string();
+		if !query.is_empty() {
+			let encoded = form_urlencoded::Serializer::new(String::new())
+				.extend_pairs(query)
+				.finish();
+			uri = format!("{uri}?{encoded}");
+		}
+		signed_in(method, &uri, "https://app.example.com", body)
+	}
+
+	/// Sends `req` to `state` and checks that the answer has `status` and
+	/// matches what the spec of `route` says it returns.
+	async fn answer_matching_spec(
+		state: &Arc<ApiState>,
+		doc: &serde_json::Value,
+		route: &str,
+		req: Request<Body>,
+		status: u16,
+	) -> serde_json::Value {
+		let method = req.method().as_str().to_ascii_lowercase();
+		let uri = req.uri().to_string();
+		let resp = send(state, req).await;
+		let actual = resp.status().as_u16();
+		let body = body_json(resp).await;
+		assert_eq!(actual, status, "{method} {uri}: {body:#}");
+		assert_matches_spec(doc, &method, route, status, body.clone());
+		body
+	}
+
+	#[tokio::test]
+	async fn file_and_scan_responses_match_the_spec() {
+		let doc = document::<ApiError>(&api_routes());
+		let state = demo_api();
+		let fixture = DemoFixture::generate(1, Utc::now());
+		let (local, remote) = (&fixture.peers[0], &fixture.peers[1]);
+		let peer = remote.id.to_string();
+		let file = &remote.files[0].path;
+		let (dir, _) = file.rsplit_once('/').unwrap();
+
+		let route = "/api/peers/{peer_id}/dir";
+		let uri = format!("/api/peers/{peer}/dir");
+		let req = api_request(Method::GET, &uri, &[("path", dir)], None);
+		let listing = answer_matching_spec(&state, &doc, route, req, 200).await;
+		assert!(!listing["entries"].as_array().unwrap().is_empty());
+
+		let route = "/api/peers/{peer_id}/file";
+		let uri = format!("/api/peers/{peer}/file");
+		let query = [("path", file.as_str()), ("offset", "0"), ("length", "16")];
+		let req = api_request(Method::GET, &uri, &query, None);
+		answer_matching_spec(&state, &doc, route, req, 200).await;
+		let query = [("path", file.as_str()), ("preview", "true")];
+		let req = api_request(Method::GET, &uri, &query, None);
+		answer_matching_spec(&state, &doc, route, req, 200).await;
+
+		let query = [
+			("peer_a", peer.as_str()),
+			("path_a", file.as_str()),
+			("peer_b", peer.as_str()),
+			("path_b", file.as_str()),
+		];
+		let req = api_request(Method::GET, "/api/diff", &query, None);
+		answer_matching_spec(&state, &doc, "/api/diff", req, 200).await;
+
+		let folder = format!("{}/Photos", local.home);
+		let req = api_request(
+			Method::POST,
+			"/api/scans",
+			&[],
+			Some(json!({ "path": folder })),
+		);
+		let started = answer_matching_spec(&state, &doc, "/api/scans", req, 201).await;
+		let events = format!("/api/scans/{}/events", started["scan_id"]);
+		let mut finished = false;
+		for _ in 0..100 {
+			let req = api_request(Method::GET, &events, &[], None);
+			let route = "/api/scans/{scan_id}/events";
+			let polled = answer_matching_spec(&state, &doc, route, req, 200).await;
+			let polled = polled["events"].as_array().unwrap();
+			if polled.iter().any(|event| event.get("Finished").is_some()) {
+				finished = true;
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(50)).await;
+		}
+		assert!(finished, "the demo scan never finished");
+
+		let route = "/api/scans/results";
+		let req = api_request(Method::GET, route, &[("page_size", "5")], None);
+		let results = answer_matching_spec(&state, &doc, route, req, 200).await;
+		assert!(!results["rows"].as_array().unwrap().is_empty());
+		let route = "/api/scans/history";
+		let req = api_request(Method::GET, route, &[("path", &folder)], None);
+		let history = answer_matching_spec(&state, &doc, route, req, 200).await;
+		assert!(!history["runs"].as_array().unwrap().is_empty());
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn search_and_update_responses_match_the_spec() {
+		let doc = document::<ApiError>(&api_routes());
+		let state = demo_api();
+		let fixture = DemoFixture::generate(1, Utc::now());
+		let remote = &fixture.peers[1];
+		let (_, name) = remote.files[0].path.rsplit_once('/').unwrap();
+
+		let req = api_request(Method::GET, "/api/search", &[("name_query", name)], None);
+		let found = answer_matching_spec(&state, &doc, "/api/search", req, 200).await;
+		assert!(!found["results"].as_array().unwrap().is_empty());
+
+		let uri = format!("/api/updates/{}", remote.id);
+		let req = api_request(Method::POST, &uri, &[], Some(json!({ "version": null })));
+		let route = "/api/updates/{peer_id}";
+		let started = answer_matching_spec(&state, &doc, route, req, 201).await;
+		let events = format!("/api/updates/{}/events", started["update_id"]);
+		let mut polled = Vec::new();
+		for _ in 0..100 {
+			let req = api_request(Method::GET, &events, &[], None);
+			let route = "/api/updates/{update_id}/events";
+			let body = answer_matching_spec(&state, &doc, route, req, 200).await;
+			polled.extend(body["events"].as_array().unwrap().iter().cloned());
+			if !polled.is_empty() {
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(50)).await;
+		}
+		assert!(polled[0].get("Failed").is_some(), "{polled:?}");
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn node_responses_match_the_spec() {
+		let doc = document::<ApiError>(&api_routes());
+		let state = demo_api();
+		let fixture = DemoFixture::generate(1, Utc::now());
+		let peer = fixture.peers[1].id.to_string();
+
+		for (route, field) in [
+			("/api/peers/{peer_id}/disks", "disks"),
+			("/api/peers/{peer_id}/cpus", "cpus"),
+			("/api/peers/{peer_id}/interfaces", "interfaces"),
+		] {
+			let uri = route.replace("{peer_id}", &peer);
+			let req = api_request(Method::GET, &uri, &[], None);
+			let body = answer_matching_spec(&state, &doc, route, req, 200).await;
+			assert!(!body[field].as_array().unwrap().is_empty(), "{route}");
+		}
+
+		let route = "/api/debug/requests";
+		let req = api_request(Method::GET, route, &[("limit", "5")], None);
+		answer_matching_spec(&state, &doc, route, req, 200).await;
+
+		let route = "/api/remote-access/suspend";
+		let req = api_request(Method::POST, route, &[], None);
+		let body = answer_matching_spec(&state, &doc, route, req, 200).await;
+		assert_eq!(body["suspended"], json!(true));
+		let route = "/api/remote-access/resume";
+		let req = api_request(Method::POST, route, &[], None);
+		let body = answer_matching_spec(&state, &doc, route, req, 200).await;
+		assert_eq!(body["suspended"], json!(false));
+
+		let dest = std::env::temp_dir().join(format!("puppynet-api-pin-{}", std::process::id()));
+		let pin = json!({
+			"peer": peer,
+			"remote_path": fixture.peers[1].home,
+			"local_dest": dest,
+		});
+		let req = api_request(Method::POST, "/api/pins", &[], Some(pin));
+		answer_matching_spec(&state, &doc, "/api/pins", req, 201).await;
+		let req = api_request(Method::GET, "/api/pins", &[], None);
+		let pins = answer_matching_spec(&state, &doc, "/api/pins", req, 200).await;
+		assert_eq!(pins["pins"].as_array().unwrap().len(), 1);
+
+		let _ = std::fs::remove_dir_all(&dest);
+	}
+
+	#[tokio::test]
+	async fn errors_share_one_envelope() {
+		let doc = document::<ApiError>(&api_routes());
+		let schema = &doc["paths"]["/api/search"]["get"]["responses"]["default"]["content"]["application/json"]
+			["schema"];
+		for (resp, kind) in [
+			(bad_request("missing path"), "bad_request"),
+			(
+				error_response(StatusCode::NOT_FOUND, "scan not found"),
+				"not_found",
+			),
+			(
+				error_response(StatusCode::INTERNAL_SERVER_ERROR, "disk full"),
+				"internal",
+			),
+		] {
+			let body = body_json(resp).await;
+			validate(schema, &body, "$").unwrap();
+			assert_eq!(body["error"]["kind"], json!(kind));
+		}
+	}
+
+	#[tokio::test]
+	async fn users_created_over_http_are_hashed_and_refused_by_field() {
+		let state = demo_api();
+		let create = |username: &str, password: &str| {
+			signed_in(
+				Method::POST,
+				"/users",
+				"https://app.example.com",
+				Some(json!({ "username": username, "password": password })),
+			)
+		};
+
+		let resp = send(&state, create(" carol ", "long enough")).await;
+		assert_eq!(resp.status(), StatusCode::CREATED);
+		assert_eq!(body_json(resp).await, json!({ "username": "carol" }));
+		assert!(
+			state
+				.puppy
+				.list_users_db()
+				.unwrap()
+				.contains(&String::from("carol"))
+		);
+
+		let resp = send(&state, create("carol", "long enough")).await;
+		assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+		let resp = send(&state, create("dave", "short")).await;
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+		let body = body_json(resp).await;
+		assert_eq!(body["errors"][0]["field"], json!("password"));
+	}
+
+	#[tokio::test]
+	async fn refused_users_name_the_field_to_fix() {
+		let doc = document::<ApiError>(&api_routes());
+		let schema = &doc["paths"]["/users"]["post"]["responses"]["default"]["content"]["application/json"]
+			["schema"];
+		let refused = CreateUserError::Invalid(vec![UserFieldError {
+			field: String::from("password"),
+			message: String::from("Password must be at least 8 characters, not 1"),
+		}]);
+		let resp = create_user_error_response(refused.into());
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+		let body = body_json(resp).await;
+		validate(schema, &body, "$").unwrap();
+		validate(&UserErrorsResponse::schema(), &body, "$").unwrap();
+		assert_eq!(body["errors"][0]["field"], json!("password"));
+
+		let taken = CreateUserError::AlreadyExists {
+			username: String::from("ana"),
+		};
+		let resp = create_user_error_response(taken.into());
+		assert_eq!(resp.status(), StatusCode::CONFLICT);
+		let body = body_json(resp).await;
+		validate(schema, &body, "$").unwrap();
+		assert_eq!(body["error"]["kind"], json!("conflict"));
+	}
+}
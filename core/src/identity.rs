@@ -0,0 +1,219 @@
+//! Agreement between the keypair and the database about which node this is.
+//! Copying the database to another machine without the keypair, or the
+//! other way around, leaves a `you` row for a node id other than the one
+//! derived from the current peer id. Until the user picks a remedy the
+//! mismatch is reported and no second `you` row is written.
+//!
+//! Adopting the previous identity moves that node's rows to the current
+//! node id one table at a time. The pending adoption is stored in settings
+//! first, so an interrupted run is finished on the next start.
+
+use crate::db::{NodeID, delete_setting, demote_node, load_setting, save_setting};
+use crate::format::hex;
+use anyhow::Result;
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+
+pub(crate) const IDENTITY_ADOPTION_SETTING: &str = "identity_adoption";
+
+/// Tables whose rows belong to a node through their `node_id` column.
+const NODE_TABLES: [&str; 8] = [
+	"file_locations",
+	"claimed_hashes",
+	"integrity_checks",
+	"connections",
+	"cpus",
+	"disks",
+	"interfaces",
+	"temperatures",
+];
+
+/// The database says this machine is `previous_node_id` while the keypair
+/// says it is `current_node_id`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct IdentityMismatch {
+	pub previous_node_id: String,
+	pub current_node_id: String,
+	pub explanation: String,
+}
+
+impl IdentityMismatch {
+	pub(crate) fn new(previous: &NodeID, current: &NodeID) -> Self {
+		Self {
+			previous_node_id: hex(previous),
+			current_node_id: hex(current),
+			explanation: String::from(
+				"The database belongs to a different keypair than the one this node started with, \
+				 usually because only one of them was copied from another machine. New scans are \
+				 recorded under the current identity. Adopt the previous identity to move its files \
+				 and device info to this one, or start fresh to keep them as another node's.",
+			),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+struct PendingAdoption {
+	from: NodeID,
+	to: NodeID,
+}
+
+fn finish_adoption(conn: &mut Connection, from: &NodeID, to: &NodeID) -> Result<()> {
+	for table in NODE_TABLES {
+		// Rows the current identity already has win over the previous ones.
+		let tx = conn.transaction()?;
+		tx.execute(
+			&format!("UPDATE OR IGNORE {table} SET node_id = ?1 WHERE node_id = ?2"),
+			params![&to[..], &from[..]],
+		)?;
+		tx.execute(
+			&format!("DELETE FROM {table} WHERE node_id = ?1"),
+			params![&from[..]],
+		)?;
+		tx.commit()?;
+	}
+	let tx = conn.transaction()?;
+	demote_node(&tx, from)?;
+	delete_setting(&tx, IDENTITY_ADOPTION_SETTING)?;
+	tx.commit()?;
+	Ok(())
+}
+
+/// Moves everything recorded for `from` to `to` and demotes the `from`
+/// node row.
+pub(crate) fn adopt_node_identity(conn: &mut Connection, from: &NodeID, to: &NodeID) -> Result<()> {
+	let pending = serde_json::to_string(&PendingAdoption {
+		from: *from,
+		to: *to,
+	})?;
+	save_setting(conn, IDENTITY_ADOPTION_SETTING, &pending)?;
+	finish_adoption(conn, from, to)
+}
+
+/// Finishes an adoption that was interrupted. Returns whether there was one.
+pub(crate) fn resume_identity_adoption(conn: &mut Connection) -> Result<bool> {
+	let Some(pending) = load_setting(conn, IDENTITY_ADOPTION_SETTING)? else {
+		return Ok(false);
+	};
+	let pending: PendingAdoption = serde_json::from_str(&pending)?;
+	finish_adoption(conn, &pending.from, &pending.to)?;
+	Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::db::find_previous_local_node;
+	use crate::test_util;
+
+	const OLD: NodeID = [1; 16];
+	const NEW: NodeID = [2; 16];
+
+	/// A database copied from another machine: its `you` row is `OLD`, and
+	/// this node already scanned a file under `NEW` before anyone noticed.
+	fn mismatched_db() -> Connection {
+		let conn = test_util::memory_db();
+		conn.execute(
+			"INSERT INTO nodes (id, name, you, total_memory, system_name, kernel_version, os_version, created_at, modified_at, accessed_at)
+			 VALUES (?1, 'old', 1, 0, 'linux', '', '', '2025-03-01 12:00:00', '2025-03-01 12:00:00', '2025-03-01 12:00:00')",
+			params![&OLD[..]],
+		)
+		.unwrap();
+		for (node, path, size) in [
+			(OLD, "/data/a.txt", 1),
+			(OLD, "/data/b.txt", 1),
+			(NEW, "/data/b.txt", 2),
+		] {
+			conn.execute(
+				"INSERT INTO file_locations (node_id, path, size, timestamp)
+				 VALUES (?1, ?2, ?3, '2025-03-01 12:00:00')",
+				params![&node[..], path, size],
+			)
+			.unwrap();
+		}
+		conn.execute(
+			"INSERT INTO cpus (node_id, name, usage, frequency, created_at, modified_at)
+			 VALUES (?1, 'cpu0', 0.5, 1000, '2025-03-01 12:00:00', '2025-03-01 12:00:00')",
+			params![&OLD[..]],
+		)
+		.unwrap();
+		conn.execute(
+			"INSERT INTO claimed_hashes (node_id, path, manifest_path, algorithm, claimed, status,
+				checked_at)
+			 VALUES (?1, '/data/a.txt', '/data/SHA256SUMS', 'sha256', x'00', 'match', 0)",
+			params![&OLD[..]],
+		)
+		.unwrap();
+		conn.execute(
+			"INSERT INTO integrity_checks (node_id, path, expected, status, last_verified_at)
+			 VALUES (?1, '/data/a.txt', x'00', 'ok', 0)",
+			params![&OLD[..]],
+		)
+		.unwrap();
+		conn
+	}
+
+	fn rows(conn: &Connection, table: &str, node: &NodeID) -> i64 {
+		conn.query_row(
+			&format!("SELECT count(*) FROM {table} WHERE node_id = ?1"),
+			params![&node[..]],
+			|row| row.get(0),
+		)
+		.unwrap()
+	}
+
+	fn locations(conn: &Connection, node: &NodeID) -> Vec<(String, i64)> {
+		let mut stmt = conn
+			.prepare("SELECT path, size FROM file_locations WHERE node_id = ?1 ORDER BY path")
+			.unwrap();
+		stmt.query_map(params![&node[..]], |row| Ok((row.get(0)?, row.get(1)?)))
+			.unwrap()
+			.collect::<Result<_, _>>()
+			.unwrap()
+	}
+
+	#[test]
+	fn adopting_moves_rows_and_clears_the_mismatch() {
+		let mut conn = mismatched_db();
+		assert_eq!(find_previous_local_node(&conn, &NEW).unwrap(), Some(OLD));
+
+		adopt_node_identity(&mut conn, &OLD, &NEW).unwrap();
+
+		assert_eq!(find_previous_local_node(&conn, &NEW).unwrap(), None);
+		assert!(locations(&conn, &OLD).is_empty());
+		assert_eq!(
+			locations(&conn, &NEW),
+			vec![
+				(String::from("/data/a.txt"), 1),
+				(String::from("/data/b.txt"), 2)
+			]
+		);
+		assert_eq!(rows(&conn, "cpus", &NEW), 1);
+		assert_eq!(rows(&conn, "claimed_hashes", &NEW), 1);
+		assert_eq!(rows(&conn, "claimed_hashes", &OLD), 0);
+		assert_eq!(rows(&conn, "integrity_checks", &NEW), 1);
+		assert_eq!(
+			load_setting(&conn, IDENTITY_ADOPTION_SETTING).unwrap(),
+			None
+		);
+	}
+
+	#[test]
+	fn interrupted_adoption_is_finished_on_resume() {
+		let mut conn = mismatched_db();
+		// Stopped after recording the adoption and moving file_locations.
+		let pending = serde_json::to_string(&PendingAdoption { from: OLD, to: NEW }).unwrap();
+		save_setting(&conn, IDENTITY_ADOPTION_SETTING, &pending).unwrap();
+		conn.execute(
+			"UPDATE OR IGNORE file_locations SET node_id = ?1 WHERE node_id = ?2",
+			params![&NEW[..], &OLD[..]],
+		)
+		.unwrap();
+
+		assert!(resume_identity_adoption(&mut conn).unwrap());
+		assert!(locations(&conn, &OLD).is_empty());
+		assert_eq!(locations(&conn, &NEW).len(), 2);
+		assert_eq!(find_previous_local_node(&conn, &NEW).unwrap(), None);
+		assert!(!resume_identity_adoption(&mut conn).unwrap());
+	}
+}
@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Categories of identifiers handed out by [`IdAllocator`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdKind {
+	Scan,
+	Search,
+	Update,
+	Shell,
+	Job,
+}
+
+/// Hands out scan, search, update, shell and job ids for the whole process.
+///
+/// Every frontend asks the same allocator, so concurrent clients never pick
+/// the same id and an id is never handed out twice.
+pub struct IdAllocator {
+	scan: AtomicU64,
+	search: AtomicU64,
+	update: AtomicU64,
+	shell: AtomicU64,
+	job: AtomicU64,
+}
+
+impl IdAllocator {
+	pub fn new() -> Self {
+		Self {
+			scan: AtomicU64::new(1),
+			search: AtomicU64::new(1),
+			update: AtomicU64::new(1),
+			shell: AtomicU64::new(1),
+			job: AtomicU64::new(1),
+		}
+	}
+
+	fn counter(&self, kind: IdKind) -> &AtomicU64 {
+		match kind {
+			IdKind::Scan => &self.scan,
+			IdKind::Search => &self.search,
+			IdKind::Update => &self.update,
+			IdKind::Shell => &self.shell,
+			IdKind::Job => &self.job,
+		}
+	}
+
+	pub fn next(&self, kind: IdKind) -> u64 {
+		self.counter(kind)
+			.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |id| id.checked_add(1))
+			.unwrap_or_else(|_| panic!("{kind:?} id space exhausted"))
+	}
+}
+
+impl Default for IdAllocator {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashSet;
+	use std::sync::Arc;
+
+	#[test]
+	fn concurrent_shell_starts_get_distinct_ids() {
+		let ids = Arc::new(IdAllocator::new());
+		let handles = (0..2)
+			.map(|_| {
+				let ids = Arc::clone(&ids);
+				std::thread::spawn(move || {
+					(0..1000)
+						.map(|_| ids.next(IdKind::Shell))
+						.collect::<Vec<_>>()
+				})
+			})
+			.collect::<Vec<_>>();
+
+		let mut seen = HashSet::new();
+		for handle in handles {
+			for id in handle.join().unwrap() {
+				assert!(seen.insert(id), "shell id {id} was handed out twice");
+			}
+		}
+		assert_eq!(seen.len(), 2000);
+	}
+
+	#[test]
+	fn categories_count_independently() {
+		let ids = IdAllocator::new();
+
+		assert_eq!(ids.next(IdKind::Scan), 1);
+		assert_eq!(ids.next(IdKind::Scan), 2);
+		assert_eq!(ids.next(IdKind::Update), 1);
+		assert_eq!(ids.next(IdKind::Shell), 1);
+		assert_eq!(ids.next(IdKind::Scan), 3);
+	}
+}
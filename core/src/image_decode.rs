@@ -0,0 +1,322 @@
+//! Safeguards around decoding images for thumbnails. The header is read
+//! first, so an image whose pixels would not fit the decode budget is
+//! refused with its dimensions before memory for them is allocated, and
+//! decodes share permits sized from the memory the machine has free. None
+//! of the decoders built in can decode at a reduced scale, so an image over
+//! the budget is refused rather than downsampled.
+
+use crate::config;
+use crate::format::{SizeUnits, human_size};
+use anyhow::anyhow;
+use image::{DynamicImage, ImageDecoder, ImageReader, Limits};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use std::sync::Mutex;
+use sysinfo::System;
+use tokio::sync::{AcquireError, Semaphore, SemaphorePermit, TryAcquireError};
+
+pub(crate) const THUMBNAIL_DECODE_BUDGET_SETTING: &str = "thumbnail_decode_budget_mib";
+/// Memory, in MiB, one decoded image may take. A 100-megapixel RGB
+/// panorama decodes to about 300 MiB.
+pub(crate) const DEFAULT_THUMBNAIL_DECODE_BUDGET_MIB: u64 = 128;
+
+/// Budget stored under [`THUMBNAIL_DECODE_BUDGET_SETTING`], in bytes.
+pub(crate) fn budget_from_setting(value: Option<&str>) -> u64 {
+	value
+		.and_then(|value| value.trim().parse::<u64>().ok())
+		.filter(|mib| *mib > 0)
+		.unwrap_or_else(|| config::startup().thumbnail_decode_budget_mib)
+		.saturating_mul(1024 * 1024)
+}
+
+/// An image refused because its decoded pixels would not fit the decode
+/// budget of the node holding it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageTooLarge {
+	pub width: u32,
+	pub height: u32,
+	/// Bytes the decoded pixels would take.
+	pub decoded_bytes: u64,
+	pub budget: u64,
+}
+
+impl std::fmt::Display for ImageTooLarge {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"image too large to preview: {}x{} decodes to {}, over the {} limit",
+			self.width,
+			self.height,
+			human_size(self.decoded_bytes, SizeUnits::Binary),
+			human_size(self.budget, SizeUnits::Binary)
+		)
+	}
+}
+
+impl std::error::Error for ImageTooLarge {}
+
+/// Width and height from the header in `data`, which may be only the start
+/// of the file.
+pub(crate) fn header_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+	let mut reader = ImageReader::new(Cursor::new(data))
+		.with_guessed_format()
+		.ok()?;
+	reader.no_limits();
+	reader.into_dimensions().ok()
+}
+
+/// Bytes a `width` by `height` image takes decoded as RGBA, the most any of
+/// the supported formats decodes to.
+pub(crate) fn decoded_size(width: u32, height: u32) -> u64 {
+	u64::from(width) * u64::from(height) * 4
+}
+
+/// Decodes to run at once: `concurrency`, or as many budgets as fit in the
+/// `available` memory when that is fewer, but at least one.
+pub(crate) fn decode_slots(concurrency: usize, budget: u64, available: u64) -> usize {
+	let fit = usize::try_from(available / budget.max(1)).unwrap_or(usize::MAX);
+	fit.clamp(1, concurrency.max(1))
+}
+
+#[derive(Default)]
+struct DecodeUsage {
+	running: usize,
+	bytes: u64,
+	peak_running: usize,
+	peak_bytes: u64,
+}
+
+/// The permits and budget every thumbnail decode goes through, and the
+/// memory decodes held at their peak.
+pub(crate) struct DecodeLimits {
+	permits: Semaphore,
+	budget: u64,
+	usage: Mutex<DecodeUsage>,
+}
+
+impl DecodeLimits {
+	pub(crate) fn new(slots: usize, budget: u64) -> Self {
+		Self {
+			permits: Semaphore::new(slots),
+			budget,
+			usage: Mutex::new(DecodeUsage::default()),
+		}
+	}
+
+	/// At most `concurrency` decodes, fewer when the memory available now
+	/// holds fewer budgets.
+	pub(crate) fn for_this_machine(concurrency: usize, budget: u64) -> Self {
+		let mut system = System::new();
+		system.refresh_memory();
+		let available = system.available_memory();
+		let slots = decode_slots(concurrency, budget, available);
+		if slots < concurrency {
+			tracing::info!(
+				"decoding {slots} thumbnails at once instead of {concurrency}: {} of memory available",
+				human_size(available, SizeUnits::Binary)
+			);
+		}
+		Self::new(slots, budget)
+	}
+
+	pub(crate) async fn acquire(&self) -> Result<SemaphorePermit<'_>, AcquireError> {
+		self.permits.acquire().await
+	}
+
+	pub(crate) fn try_acquire(&self) -> Result<SemaphorePermit<'_>, TryAcquireError> {
+		self.permits.try_acquire()
+	}
+
+	/// Bytes one decode may take.
+	pub(crate) fn budget(&self) -> u64 {
+		self.budget
+	}
+
+	/// Most decodes that ran at once, and most bytes decodes held together.
+	pub(crate) fn peak(&self) -> (usize, u64) {
+		let usage = self.usage.lock().unwrap();
+		(usage.peak_running, usage.peak_bytes)
+	}
+
+	fn start(&self, bytes: u64) {
+		let mut usage = self.usage.lock().unwrap();
+		usage.running += 1;
+		usage.bytes += bytes;
+		usage.peak_running = usage.peak_running.max(usage.running);
+		usage.peak_bytes = usage.peak_bytes.max(usage.bytes);
+	}
+
+	fn finish(&self, bytes: u64) {
+		let mut usage = self.usage.lock().unwrap();
+		usage.running -= 1;
+		usage.bytes -= bytes;
+	}
+
+	/// Decodes `data` unless its pixels would not fit the budget, which is
+	/// refused with [`ImageTooLarge`] after reading only the header. Run
+	/// while holding a permit.
+	pub(crate) fn decode(&self, data: &[u8]) -> anyhow::Result<DynamicImage> {
+		let mut reader = ImageReader::new(Cursor::new(data))
+			.with_guessed_format()
+			.map_err(|e| anyhow!("failed to guess image format: {}", e))?;
+		// The default limits refuse large images without saying how large;
+		// the budget is checked against the header instead.
+		reader.no_limits();
+		let mut decoder = reader
+			.into_decoder()
+			.map_err(|e| anyhow!("failed to decode image: {}", e))?;
+		let (width, height) = decoder.dimensions();
+		let decoded_bytes = decoder.total_bytes();
+		if decoded_bytes > self.budget {
+			return Err(ImageTooLarge {
+				width,
+				height,
+				decoded_bytes,
+				budget: self.budget,
+			}
+			.into());
+		}
+		let mut limits = Limits::default();
+		limits.max_alloc = Some(self.budget);
+		decoder
+			.set_limits(limits)
+			.map_err(|e| anyhow!("failed to decode image: {}", e))?;
+		self.start(decoded_bytes);
+		let image = DynamicImage::from_decoder(decoder);
+		let (peak_running, peak_bytes) = self.peak();
+		self.finish(decoded_bytes);
+		let image = image.map_err(|e| anyhow!("failed to decode image: {}", e))?;
+		tracing::debug!(
+			"decoded {width}x{height} image into {}; decodes peaked at {} held by {peak_running}",
+			human_size(image.as_bytes().len() as u64, SizeUnits::Binary),
+			human_size(peak_bytes, SizeUnits::Binary)
+		);
+		Ok(image)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Arc;
+	use std::time::{Duration, Instant};
+
+	const BUDGET: u64 = 64 * 1024 * 1024;
+
+	fn crc32(bytes: &[u8]) -> u32 {
+		let mut crc = !0u32;
+		for &byte in bytes {
+			crc ^= u32::from(byte);
+			for _ in 0..8 {
+				crc = if crc & 1 == 1 {
+					(crc >> 1) ^ 0xedb8_8320
+				} else {
+					crc >> 1
+				};
+			}
+		}
+		!crc
+	}
+
+	fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+		png.extend((data.len() as u32).to_be_bytes());
+		let start = png.len();
+		png.extend(kind);
+		png.extend(data);
+		let crc = crc32(&png[start..]);
+		png.extend(crc.to_be_bytes());
+	}
+
+	/// A PNG whose header claims `width` by `height` RGBA pixels, with no
+	/// pixel data behind it.
+	fn png_header_only(width: u32, height: u32) -> Vec<u8> {
+		let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+		let mut ihdr = Vec::new();
+		ihdr.extend(width.to_be_bytes());
+		ihdr.extend(height.to_be_bytes());
+		ihdr.extend([8, 6, 0, 0, 0]);
+		png_chunk(&mut png, b"IHDR", &ihdr);
+		png_chunk(
+			&mut png,
+			b"IDAT",
+			&[0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01],
+		);
+		png_chunk(&mut png, b"IEND", &[]);
+		png
+	}
+
+	fn encoded_png(width: u32, height: u32) -> Vec<u8> {
+		let image = image::RgbImage::from_fn(width, height, |x, y| {
+			image::Rgb([x as u8, y as u8, (x ^ y) as u8])
+		});
+		let mut png = Vec::new();
+		image
+			.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+			.unwrap();
+		png
+	}
+
+	#[test]
+	fn a_huge_image_is_refused_from_its_header() {
+		let png = png_header_only(60_000, 40_000);
+		assert_eq!(header_dimensions(&png), Some((60_000, 40_000)));
+		let limits = DecodeLimits::new(1, BUDGET);
+
+		let started = Instant::now();
+		let err = limits.decode(&png).unwrap_err();
+		assert!(started.elapsed() < Duration::from_secs(1));
+		assert_eq!(
+			err.downcast_ref::<ImageTooLarge>(),
+			Some(&ImageTooLarge {
+				width: 60_000,
+				height: 40_000,
+				decoded_bytes: decoded_size(60_000, 40_000),
+				budget: BUDGET,
+			})
+		);
+		assert_eq!(limits.peak(), (0, 0));
+	}
+
+	#[test]
+	fn images_within_the_budget_decode() {
+		let limits = DecodeLimits::new(1, BUDGET);
+		let image = limits.decode(&encoded_png(300, 200)).unwrap();
+		assert_eq!((image.width(), image.height()), (300, 200));
+		assert_eq!(limits.peak(), (1, 300 * 200 * 3));
+	}
+
+	#[test]
+	fn slots_shrink_to_the_budgets_that_fit_in_memory() {
+		let mib = 1024 * 1024;
+		assert_eq!(decode_slots(4, 128 * mib, 8 * 1024 * mib), 4);
+		assert_eq!(decode_slots(4, 128 * mib, 300 * mib), 2);
+		assert_eq!(decode_slots(4, 128 * mib, 10 * mib), 1);
+	}
+
+	#[tokio::test]
+	async fn permits_cap_simultaneous_decodes() {
+		let limits = Arc::new(DecodeLimits::new(2, BUDGET));
+		let png = Arc::new(encoded_png(1200, 900));
+		let decodes = (0..8)
+			.map(|_| {
+				let limits = Arc::clone(&limits);
+				let png = Arc::clone(&png);
+				tokio::spawn(async move {
+					let _permit = limits.acquire().await.unwrap();
+					tokio::task::spawn_blocking({
+						let limits = Arc::clone(&limits);
+						move || limits.decode(&png).map(|image| image.width())
+					})
+					.await
+					.unwrap()
+				})
+			})
+			.collect::<Vec<_>>();
+		for decode in decodes {
+			assert_eq!(decode.await.unwrap().unwrap(), 1200);
+		}
+		let (peak_running, peak_bytes) = limits.peak();
+		assert!((1..=2).contains(&peak_running), "{peak_running} at once");
+		assert!(peak_bytes <= 2 * 1200 * 900 * 3);
+	}
+}
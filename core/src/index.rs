@@ -0,0 +1,496 @@
+//! The local file index on its own: scanning folders into a database,
+//! searching it and adding up storage, with no peer-to-peer networking.
+//! `App` runs the same functions on its shared connection, and programs
+//! embedding puppynet-core can use [`FileIndex`] without starting a node.
+//! What another peer may see of the index is asked of a [`PeerAccess`],
+//! which the node answers from its grants.
+
+use crate::db::{
+	FileProvenance, FileSearchResult, Node, NodeID, SearchFilesArgs, StorageUsageFile,
+	attribute_additions, attribute_tombstones, configure_connection, file_provenance,
+	get_your_node, path_column, pending_media_files, purge_tombstones, record_scan_run,
+	run_migrations, save_media_metadata, save_node, search_files, search_files_after,
+};
+use crate::db_pool::Db;
+use crate::media_metadata::{MediaExtractReport, extract_pending};
+use crate::pagination::{CursorPage, PageCursor};
+use crate::peer_search;
+use crate::scan::{
+	ScanChangeKind, ScanProgress, ScanResult, scan_limited, tombstone_retention_days,
+};
+use crate::scan_limits::ScanThrottle;
+use crate::share_summary::{self, ShareSummary};
+use crate::state::{HiddenNames, State};
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use rusqlite::{Connection, OptionalExtension, params};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// What another peer may see of this node's files, asked when the index
+/// answers it. [`State`] answers from the node's grants and shared
+/// folders; a program embedding [`FileIndex`] can answer from its own.
+pub trait PeerAccess {
+	/// Whether `peer` may `access` (a mask of the `FLAG_*` bits) `path`
+	/// as of `now`.
+	fn may_access(&self, peer: PeerId, path: &Path, access: u8, now: DateTime<Utc>) -> bool;
+
+	/// The folders `peer` may `access`, the roots its searches start at.
+	fn roots(&self, peer: PeerId, access: u8) -> Vec<PathBuf>;
+
+	/// The names deny and allow patterns hide from `peer` as of `now`.
+	fn hidden(&self, _peer: PeerId, _now: DateTime<Utc>) -> HiddenNames {
+		HiddenNames::default()
+	}
+}
+
+impl PeerAccess for State {
+	fn may_access(&self, peer: PeerId, path: &Path, access: u8, now: DateTime<Utc>) -> bool {
+		self.has_fs_access_at(peer, path, access, now)
+	}
+
+	fn roots(&self, peer: PeerId, access: u8) -> Vec<PathBuf> {
+		self.roots_for_peer(&peer, access)
+	}
+
+	fn hidden(&self, peer: PeerId, now: DateTime<Utc>) -> HiddenNames {
+		self.hidden_names(peer, now)
+	}
+}
+
+/// Drops deleted files older than the configured retention.
+fn purge_expired_tombstones(conn: &Connection, now: DateTime<Utc>) {
+	let days = tombstone_retention_days(conn);
+	if days == 0 {
+		return;
+	}
+	let before = now - chrono::Duration::days(days.into());
+	match purge_tombstones(conn, before) {
+		Ok(0) => {}
+		Ok(purged) => tracing::info!("purged {purged} deleted files from the index"),
+		Err(err) => tracing::warn!("failed to purge deleted files: {err}"),
+	}
+}
+
+/// What [`scan_and_record`] scans and who the run is recorded for.
+pub(crate) struct ScanRecordOptions<'a> {
+	pub node_id: &'a NodeID,
+	pub path: &'a str,
+	pub started_at: DateTime<Utc>,
+	/// The peer that asked for the scan, or `None` for this node.
+	pub initiated_by: Option<&'a PeerId>,
+	pub throttle: &'a ScanThrottle,
+}
+
+/// Scans `options.path` for `options.node_id` and records the run in the
+/// scan history, naming it in the result. `should_cancel` is asked once more
+/// after the scan so a cancel that raced the last file is still recorded as
+/// one.
+pub(crate) fn scan_and_record<F, C>(
+	conn: &mut Connection,
+	options: ScanRecordOptions<'_>,
+	progress: F,
+	mut should_cancel: C,
+) -> Result<ScanResult, String>
+where
+	F: FnMut(ScanProgress),
+	C: FnMut() -> bool,
+{
+	let ScanRecordOptions {
+		node_id,
+		path,
+		started_at,
+		initiated_by,
+		throttle,
+	} = options;
+	let started = std::time::Instant::now();
+	let mut result = scan_limited(node_id, path, conn, throttle, progress, &mut should_cancel);
+	match record_scan_run(
+		conn,
+		path,
+		started_at,
+		started.elapsed(),
+		initiated_by,
+		&result,
+		should_cancel(),
+	) {
+		Ok(run_id) => {
+			if let Ok(scan) = &mut result {
+				scan.run = Some(run_id);
+				let removed = scan
+					.changes
+					.iter()
+					.filter(|change| change.kind == ScanChangeKind::Removed)
+					.map(|change| change.path.as_path())
+					.collect::<Vec<_>>();
+				if let Err(err) = attribute_tombstones(conn, node_id, run_id, &removed) {
+					tracing::warn!("failed to link deleted files to scan run: {err}");
+				}
+				let added = scan
+					.changes
+					.iter()
+					.filter(|change| change.kind == ScanChangeKind::Added)
+					.map(|change| change.path.as_path())
+					.collect::<Vec<_>>();
+				if let Err(err) = attribute_additions(conn, node_id, run_id, &added) {
+					tracing::warn!("failed to link added files to scan run: {err}");
+				}
+			}
+		}
+		Err(err) => tracing::warn!("failed to record scan run: {err}"),
+	}
+	purge_expired_tombstones(conn, started_at);
+	result
+}
+
+/// Extracts media metadata for `node_id`'s files under `path_prefix` that
+/// have none yet. The lock is only held to list and to save, never while a
+/// file is being read.
+pub(crate) fn extract_media_metadata<C>(
+	db: &Db,
+	node_id: &NodeID,
+	path_prefix: Option<&str>,
+	should_cancel: C,
+) -> MediaExtractReport
+where
+	C: FnMut() -> bool,
+{
+	let pending = db
+		.lock()
+		.map_err(|err| anyhow!("db lock poisoned: {err}"))
+		.and_then(|conn| pending_media_files(&conn, node_id, path_prefix));
+	let pending = match pending {
+		Ok(pending) => pending,
+		Err(err) => {
+			tracing::warn!("failed to list files for media metadata: {err}");
+			return MediaExtractReport::default();
+		}
+	};
+	extract_pending(
+		pending,
+		|hash, metadata| {
+			let conn = db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			save_media_metadata(&conn, hash, metadata)
+		},
+		should_cancel,
+	)
+}
+
+fn storage_files_for_node(
+	conn: &Connection,
+	node_id: &[u8],
+	node_name: &str,
+) -> Result<Vec<StorageUsageFile>> {
+	let mut stmt = conn
+		.prepare(
+			"SELECT path, size, timestamp, modified_at \
+			FROM file_locations WHERE node_id = ?1 AND deleted_at IS NULL",
+		)
+		.map_err(|err| anyhow!("failed to prepare file_locations query: {err}"))?;
+	let rows = stmt
+		.query_map(params![node_id], |row| {
+			let path = path_column(row, 0)?.to_string_lossy().into_owned();
+			let size = row.get::<_, i64>(1)?.max(0) as u64;
+			let timestamp: Option<DateTime<Utc>> = row.get(2)?;
+			let modified: Option<DateTime<Utc>> = row.get(3)?;
+			Ok(StorageUsageFile {
+				node_id: node_id.to_vec(),
+				node_name: node_name.to_string(),
+				path,
+				size,
+				last_changed: modified.or(timestamp),
+			})
+		})
+		.map_err(|err| anyhow!("failed to query file_locations: {err}"))?;
+	let mut files = Vec::new();
+	for row in rows {
+		files.push(row.map_err(|err| anyhow!("failed to read file row: {err}"))?);
+	}
+	Ok(files)
+}
+
+/// The indexed files of `node_id`, or `None` for a node this one doesn't
+/// know.
+pub(crate) fn storage_files_of(
+	conn: &Connection,
+	node_id: &NodeID,
+) -> Result<Option<Vec<StorageUsageFile>>> {
+	let name = conn
+		.query_row(
+			"SELECT name FROM nodes WHERE id = ?1",
+			params![node_id.as_slice()],
+			|row| row.get::<_, String>(0),
+		)
+		.optional()
+		.map_err(|err| anyhow!("failed to load node: {err}"))?;
+	name.map(|name| storage_files_for_node(conn, node_id, &name))
+		.transpose()
+}
+
+/// Every indexed file of every known node, for the storage usage views.
+pub(crate) fn storage_files(conn: &Connection) -> Result<Vec<StorageUsageFile>> {
+	let mut stmt = conn
+		.prepare("SELECT id, name FROM nodes")
+		.map_err(|err| anyhow!("failed to prepare nodes query: {err}"))?;
+	let rows = stmt
+		.query_map([], |row| {
+			let id: Vec<u8> = row.get(0)?;
+			let name: String = row.get(1)?;
+			Ok((id, name))
+		})
+		.map_err(|err| anyhow!("failed to query nodes: {err}"))?;
+	let mut files = Vec::new();
+	for row in rows {
+		let (node_id, node_name) = row.map_err(|err| anyhow!("failed to read node row: {err}"))?;
+		files.extend(storage_files_for_node(conn, &node_id, &node_name)?);
+	}
+	Ok(files)
+}
+
+/// One page of the index search for `peer`: files of `node_id` under
+/// folders `access` lets it search that no pattern hides. Pages can come
+/// back short.
+pub(crate) fn search_for_peer(
+	conn: &Connection,
+	access: &impl PeerAccess,
+	peer: PeerId,
+	node_id: NodeID,
+	args: SearchFilesArgs,
+	now: DateTime<Utc>,
+) -> Result<Vec<FileSearchResult>> {
+	let args = peer_search::scope_for_peer(args, Some(node_id));
+	let (page, _, _) = search_files(conn, args)?;
+	Ok(peer_search::visible_results(access, peer, page.rows, now))
+}
+
+/// A file index over a database of its own, or over a running node's.
+///
+/// ```
+/// use puppynet_core::index::FileIndex;
+/// use puppynet_core::SearchFilesArgs;
+///
+/// let dir = std::env::temp_dir().join("puppynet-file-index-doc");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// std::fs::write(dir.join("notes.txt"), "hello").unwrap();
+///
+/// let index = FileIndex::open_in_memory().unwrap();
+/// let scanned = index.scan(&dir).unwrap();
+/// assert!(scanned.file_count >= 1);
+///
+/// let (page, _mime_types, total) = index
+///     .search(SearchFilesArgs {
+///         name_query: Some(String::from("notes")),
+///         ..Default::default()
+///     })
+///     .unwrap();
+/// assert!(total >= 1);
+/// assert!(page.rows.iter().any(|file| file.name == "notes.txt"));
+/// ```
+pub struct FileIndex {
+	db: Arc<Db>,
+	node_id: NodeID,
+}
+
+/// Readies `conn` for an index: configured, migrated and with a node of
+/// its own, whose id is returned. A new database gets a fresh node id.
+fn prepare(conn: &mut Connection) -> Result<NodeID> {
+	configure_connection(conn)?;
+	run_migrations(conn)?;
+	if let Some(id) = get_your_node(conn)? {
+		return Ok(id);
+	}
+	let id = *uuid::Uuid::new_v4().as_bytes();
+	let now = Utc::now();
+	save_node(
+		conn,
+		&Node {
+			id,
+			name: String::from("local"),
+			you: true,
+			total_memory: 0,
+			system_name: String::new(),
+			kernel_version: String::new(),
+			os_version: String::new(),
+			created_at: now,
+			modified_at: now,
+			accessed_at: now,
+		},
+	)?;
+	Ok(id)
+}
+
+impl FileIndex {
+	/// Opens the database at `path`, creating and migrating it as needed.
+	/// Scans are recorded under the database's own node; a new database
+	/// gets a fresh node id.
+	pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+		let path = path.as_ref().to_path_buf();
+		let mut conn = Connection::open(&path)?;
+		let node_id = prepare(&mut conn)?;
+		Ok(Self::shared(Arc::new(Db::pooled(conn, path)), node_id))
+	}
+
+	pub fn open_in_memory() -> Result<Self> {
+		let mut conn = Connection::open_in_memory()?;
+		let node_id = prepare(&mut conn)?;
+		Ok(Self::shared(Arc::new(Db::single(conn)), node_id))
+	}
+
+	/// The index in `db`, already migrated, with scans recorded under
+	/// `node_id`. A node shares its database with its index this way.
+	pub(crate) fn shared(db: Arc<Db>, node_id: NodeID) -> Self {
+		Self { db, node_id }
+	}
+
+	/// The node scans are recorded under.
+	pub fn node_id(&self) -> NodeID {
+		self.node_id
+	}
+
+	pub fn scan(&self, path: impl AsRef<Path>) -> Result<ScanResult, String> {
+		self.scan_with_progress(path, |_| {})
+	}
+
+	pub fn scan_with_progress<F>(
+		&self,
+		path: impl AsRef<Path>,
+		progress: F,
+	) -> Result<ScanResult, String>
+	where
+		F: FnMut(ScanProgress),
+	{
+		let path = std::fs::canonicalize(path.as_ref())
+			.map_err(|err| format!("failed to access path: {err}"))?;
+		let path = path.to_string_lossy().to_string();
+		self.db.write(|conn| {
+			let throttle = ScanThrottle::stored(conn);
+			scan_and_record(
+				conn,
+				ScanRecordOptions {
+					node_id: &self.node_id,
+					path: &path,
+					started_at: Utc::now(),
+					initiated_by: None,
+					throttle: &throttle,
+				},
+				progress,
+				|| false,
+			)
+		})
+	}
+
+	/// Extracts media metadata for indexed files under `path`, or
+	/// everywhere, that have none yet.
+	pub fn extract_metadata(&self, path: Option<&str>) -> Result<MediaExtractReport> {
+		let pending = self
+			.db
+			.read(|conn| pending_media_files(conn, &self.node_id, path))?;
+		Ok(extract_pending(
+			pending,
+			|hash, metadata| {
+				self.db
+					.write(|conn| save_media_metadata(conn, hash, metadata))
+			},
+			|| false,
+		))
+	}
+
+	/// Returns (page, mime_types, total_count) like the node's own search.
+	pub fn search(
+		&self,
+		args: SearchFilesArgs,
+	) -> Result<(CursorPage<FileSearchResult>, Vec<String>, usize)> {
+		self.db.read(|conn| search_files(conn, args))
+	}
+
+	pub fn search_after(
+		&self,
+		args: SearchFilesArgs,
+		cursor: Option<&PageCursor>,
+	) -> Result<(CursorPage<FileSearchResult>, Vec<String>, usize)> {
+		self.db.read(|conn| search_files_after(conn, args, cursor))
+	}
+
+	/// One page of the search `peer` asked for, answered as the node
+	/// answers a peer's `SearchFiles`: only this index's own files that
+	/// `access` lets the peer search.
+	///
+	/// ```
+	/// use chrono::{DateTime, Utc};
+	/// use puppynet_core::index::{FileIndex, PeerAccess};
+	/// use puppynet_core::{FLAG_SEARCH, PeerId, SearchFilesArgs};
+	/// use std::path::{Path, PathBuf};
+	///
+	/// /// Lets every peer search one folder.
+	/// struct OneFolder(PathBuf);
+	///
+	/// impl PeerAccess for OneFolder {
+	///     fn may_access(&self, _: PeerId, path: &Path, access: u8, _: DateTime<Utc>) -> bool {
+	///         access == FLAG_SEARCH && path.starts_with(&self.0)
+	///     }
+	///
+	///     fn roots(&self, _: PeerId, _: u8) -> Vec<PathBuf> {
+	///         vec![self.0.clone()]
+	///     }
+	/// }
+	///
+	/// let dir = std::env::temp_dir().join("puppynet-peer-search-doc");
+	/// std::fs::create_dir_all(dir.join("shared")).unwrap();
+	/// std::fs::write(dir.join("shared").join("a.txt"), "a").unwrap();
+	/// std::fs::write(dir.join("b.txt"), "b").unwrap();
+	///
+	/// let index = FileIndex::open_in_memory().unwrap();
+	/// index.scan(&dir).unwrap();
+	/// let shared = OneFolder(std::fs::canonicalize(dir.join("shared")).unwrap());
+	/// let peer = PeerId::random();
+	/// let found = index
+	///     .search_for_peer(&shared, peer, SearchFilesArgs::default())
+	///     .unwrap();
+	/// assert_eq!(found.len(), 1);
+	/// assert_eq!(found[0].name, "a.txt");
+	/// ```
+	pub fn search_for_peer(
+		&self,
+		access: &impl PeerAccess,
+		peer: PeerId,
+		args: SearchFilesArgs,
+	) -> Result<Vec<FileSearchResult>> {
+		self.db
+			.read(|conn| search_for_peer(conn, access, peer, self.node_id, args, Utc::now()))
+	}
+
+	/// Summaries of the folders `access` lets `peer` search, from this
+	/// index's own files.
+	pub fn share_summaries(
+		&self,
+		access: &impl PeerAccess,
+		peer: PeerId,
+	) -> Result<Vec<ShareSummary>> {
+		self.db
+			.read(|conn| share_summary::summarize(conn, access, peer, &self.node_id, Utc::now()))
+	}
+
+	pub fn storage_files(&self) -> Result<Vec<StorageUsageFile>> {
+		self.db.read(storage_files)
+	}
+
+	/// Who introduced the location of `hash` at `path`.
+	pub fn file_provenance(
+		&self,
+		hash: &[u8],
+		path: impl AsRef<Path>,
+	) -> Result<Option<FileProvenance>> {
+		self.db
+			.read(|conn| file_provenance(conn, hash, path.as_ref()))
+	}
+
+	/// Forgets files deleted longer than `older_than` ago. Returns how many
+	/// were dropped.
+	pub fn purge_tombstones(&self, older_than: chrono::Duration) -> Result<usize> {
+		self.db
+			.write(|conn| purge_tombstones(conn, Utc::now() - older_than))
+	}
+}
@@ -0,0 +1,448 @@
+//! Index news between peers. Once a scan commits a batch, this node tells
+//! the connected peers it shares with how many rows changed and its latest
+//! change number, never the rows themselves, with the bursts of a long
+//! scan coalesced into one announcement per [`ANNOUNCE_INTERVAL`].
+//!
+//! A peer keeping a replica of the announcing node's index, or subscribed
+//! to its updates, pulls the changes after a random delay, so nodes
+//! finishing scans at the same time don't all pull at once, and only a few
+//! pulls run at a time. Every other peer just marks what it knows about
+//! that index as stale, which search results from the node show.
+
+use crate::db::{is_subscribed_to_index, load_replication};
+use crate::format::{group_digits, relative_time};
+use crate::replication::ReplicationRole;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use rusqlite::Connection;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// Shortest time between two announcements of this node's index.
+pub(crate) const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(10);
+/// How often changes held back by [`ANNOUNCE_INTERVAL`] are checked.
+pub(crate) const ANNOUNCE_FLUSH_INTERVAL: Duration = Duration::from_secs(2);
+/// Pulls wait at least this long after an announcement...
+const PULL_DELAY_MIN: Duration = Duration::from_secs(1);
+/// ...plus a random part of this.
+const PULL_JITTER: Duration = Duration::from_secs(20);
+/// Pulls running at once, from all peers together.
+pub(crate) const MAX_RUNNING_PULLS: usize = 2;
+/// Deltas one pull takes before it stops until the next announcement.
+const MAX_PULL_BATCHES: u32 = 50;
+
+/// Index rows a scan added, updated and removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexChangeCounts {
+	pub added: u64,
+	pub updated: u64,
+	pub removed: u64,
+}
+
+impl IndexChangeCounts {
+	pub fn is_empty(&self) -> bool {
+		self.added == 0 && self.updated == 0 && self.removed == 0
+	}
+
+	fn add(&mut self, other: Self) {
+		self.added += other.added;
+		self.updated += other.updated;
+		self.removed += other.removed;
+	}
+
+	/// "1,204 added, 3 removed".
+	fn describe(&self) -> String {
+		[
+			(self.added, "added"),
+			(self.updated, "updated"),
+			(self.removed, "removed"),
+		]
+		.into_iter()
+		.filter(|(count, _)| *count > 0)
+		.map(|(count, what)| format!("{} {what}", group_digits(count)))
+		.collect::<Vec<_>>()
+		.join(", ")
+	}
+}
+
+/// Turns the running totals a scan reports into the changes since its
+/// previous report.
+#[derive(Debug, Default)]
+pub(crate) struct ScanTally {
+	seen: IndexChangeCounts,
+}
+
+impl ScanTally {
+	pub(crate) fn advance(
+		&mut self,
+		inserted: u64,
+		updated: u64,
+		removed: u64,
+	) -> IndexChangeCounts {
+		let change = IndexChangeCounts {
+			added: inserted.saturating_sub(self.seen.added),
+			updated: updated.saturating_sub(self.seen.updated),
+			removed: removed.saturating_sub(self.seen.removed),
+		};
+		self.seen = IndexChangeCounts {
+			added: inserted,
+			updated,
+			removed,
+		};
+		change
+	}
+}
+
+/// Changes to this node's index not announced yet.
+#[derive(Debug, Default)]
+pub(crate) struct AnnounceCoalescer {
+	pending: IndexChangeCounts,
+	last_sent: Option<DateTime<Utc>>,
+}
+
+impl AnnounceCoalescer {
+	pub(crate) fn record(&mut self, change: IndexChangeCounts) {
+		self.pending.add(change);
+	}
+
+	/// The changes to announce now: everything recorded since the last
+	/// announcement, once [`ANNOUNCE_INTERVAL`] has passed since it.
+	pub(crate) fn take_due(&mut self, now: DateTime<Utc>) -> Option<IndexChangeCounts> {
+		if self.pending.is_empty() {
+			return None;
+		}
+		let interval = chrono::Duration::from_std(ANNOUNCE_INTERVAL).unwrap_or_default();
+		if self.last_sent.is_some_and(|at| now - at < interval) {
+			return None;
+		}
+		self.last_sent = Some(now);
+		Some(std::mem::take(&mut self.pending))
+	}
+}
+
+/// What a peer announced about its index since this node last caught up
+/// with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexFreshness {
+	/// Changes announced since the last pull caught up.
+	pub changes: IndexChangeCounts,
+	/// Latest change number the peer announced.
+	pub high_water_mark: u64,
+	pub announced_at: DateTime<Utc>,
+	/// Change number the last pull caught up to; `None` before one did.
+	pub synced_seq: Option<u64>,
+}
+
+impl IndexFreshness {
+	pub(crate) fn new(now: DateTime<Utc>) -> Self {
+		Self {
+			changes: IndexChangeCounts::default(),
+			high_water_mark: 0,
+			announced_at: now,
+			synced_seq: None,
+		}
+	}
+
+	pub fn is_stale(&self) -> bool {
+		self.synced_seq.is_none_or(|seq| seq < self.high_water_mark)
+	}
+
+	pub(crate) fn announce(
+		&mut self,
+		changes: IndexChangeCounts,
+		high_water_mark: u64,
+		now: DateTime<Utc>,
+	) {
+		if !self.is_stale() {
+			self.changes = IndexChangeCounts::default();
+		}
+		self.changes.add(changes);
+		self.high_water_mark = high_water_mark;
+		self.announced_at = now;
+	}
+
+	pub(crate) fn synced(&mut self, seq: u64) {
+		self.synced_seq = Some(seq);
+		if !self.is_stale() {
+			self.changes = IndexChangeCounts::default();
+		}
+	}
+
+	/// "index changed 5 minutes ago (120 added), results may be out of
+	/// date" while changes haven't been pulled.
+	pub fn describe(&self, now: DateTime<Utc>) -> Option<String> {
+		if !self.is_stale() {
+			return None;
+		}
+		let changes = self.changes.describe();
+		let changes = if changes.is_empty() {
+			String::new()
+		} else {
+			format!(" ({changes})")
+		};
+		Some(format!(
+			"index changed {}{changes}, results may be out of date",
+			relative_time(self.announced_at, now)
+		))
+	}
+}
+
+/// Whether an announcement from `peer` is answered with a pull: this node
+/// keeps a replica of its index or subscribed to its updates.
+pub(crate) fn wants_pull(conn: &Connection, peer: &PeerId) -> Result<bool> {
+	Ok(is_subscribed_to_index(conn, peer)?
+		|| load_replication(conn, peer, ReplicationRole::Replica)?.is_some())
+}
+
+/// Delay before pulling after an announcement, for `unit` drawn from
+/// `0.0..1.0`.
+pub(crate) fn pull_delay(unit: f64) -> Duration {
+	PULL_DELAY_MIN + PULL_JITTER.mul_f64(unit.clamp(0.0, 1.0))
+}
+
+/// Pulls waiting for their delay and running, which only live as long as
+/// the process.
+#[derive(Debug, Default)]
+pub(crate) struct IndexPulls {
+	scheduled: HashSet<PeerId>,
+	/// Running pulls, with the change number each catches up to and the
+	/// deltas it took.
+	running: HashMap<PeerId, (u64, u32)>,
+	/// Peers that announced again while being pulled.
+	again: HashSet<PeerId>,
+}
+
+impl IndexPulls {
+	/// Whether a pull of `peer` should be scheduled; false while one is
+	/// waiting or running already, which then covers the announcement.
+	pub(crate) fn schedule(&mut self, peer: PeerId) -> bool {
+		if self.running.contains_key(&peer) {
+			self.again.insert(peer);
+			return false;
+		}
+		self.scheduled.insert(peer)
+	}
+
+	/// Starts the scheduled pull of `peer` unless [`MAX_RUNNING_PULLS`]
+	/// are running, in which case it stays scheduled.
+	pub(crate) fn start(&mut self, peer: PeerId, target: u64) -> bool {
+		if self.running.len() >= MAX_RUNNING_PULLS {
+			return false;
+		}
+		self.scheduled.remove(&peer);
+		self.running.insert(peer, (target, 0));
+		true
+	}
+
+	/// The change number the running pull of `peer` catches up to.
+	pub(crate) fn target(&self, peer: &PeerId) -> Option<u64> {
+		self.running.get(peer).map(|(target, _)| *target)
+	}
+
+	/// Counts a delta of the pull of `peer`; false once it took
+	/// [`MAX_PULL_BATCHES`].
+	pub(crate) fn next_batch(&mut self, peer: &PeerId) -> bool {
+		let Some((_, batches)) = self.running.get_mut(peer) else {
+			return false;
+		};
+		*batches += 1;
+		*batches < MAX_PULL_BATCHES
+	}
+
+	/// Drops a scheduled pull that can't run, e.g. once `peer` is gone.
+	pub(crate) fn cancel(&mut self, peer: &PeerId) {
+		self.scheduled.remove(peer);
+	}
+
+	/// Ends the pull of `peer`; true when it announced again meanwhile
+	/// and is due another.
+	pub(crate) fn finish(&mut self, peer: &PeerId) -> bool {
+		self.running.remove(peer);
+		self.again.remove(peer)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::app::peer_to_node_id;
+	use crate::db::{NodeID, save_index_subscription};
+	use crate::replication::{pull_delta, receive_delta};
+	use crate::test_util;
+	use rusqlite::params;
+
+	fn open() -> Connection {
+		test_util::memory_db()
+	}
+
+	fn add_file(conn: &Connection, node_id: &NodeID, path: &str) {
+		let hash = blake3::hash(path.as_bytes()).as_bytes().to_vec();
+		conn.execute(
+			"INSERT INTO file_entries (hash, size, mime_type) VALUES (?1, ?2, 'text/plain')",
+			params![hash, path.len() as i64],
+		)
+		.unwrap();
+		conn.execute(
+			"INSERT INTO file_locations (node_id, path, hash, size, timestamp)
+			VALUES (?1, ?2, ?3, ?4, '2024-05-01 10:00:00+00:00')",
+			params![node_id.as_slice(), path, hash, path.len() as i64],
+		)
+		.unwrap();
+	}
+
+	fn change_seq(conn: &Connection) -> u64 {
+		conn.query_row("SELECT seq FROM index_changes WHERE id = 1", [], |row| {
+			row.get::<_, i64>(0)
+		})
+		.unwrap() as u64
+	}
+
+	fn at(secs: i64) -> DateTime<Utc> {
+		DateTime::from_timestamp(1_714_557_600 + secs, 0).unwrap()
+	}
+
+	#[test]
+	fn a_long_scan_announces_at_most_once_per_interval() {
+		let mut tally = ScanTally::default();
+		let mut coalescer = AnnounceCoalescer::default();
+		assert_eq!(coalescer.take_due(at(0)), None);
+
+		let mut sent = Vec::new();
+		// A batch of 100 new files committed every second for a minute.
+		for second in 0..60 {
+			let inserted = 100 * (second as u64 + 1);
+			coalescer.record(tally.advance(inserted, second as u64, 0));
+			sent.extend(coalescer.take_due(at(second)));
+		}
+		coalescer.record(tally.advance(6_000, 59, 12));
+		sent.extend(coalescer.take_due(at(59)));
+		assert_eq!(sent.len(), 6);
+		sent.extend(coalescer.take_due(at(70)));
+		assert_eq!(sent.len(), 7);
+		assert_eq!(coalescer.take_due(at(90)), None);
+
+		let mut total = IndexChangeCounts::default();
+		sent.iter().for_each(|change| total.add(*change));
+		assert_eq!(
+			total,
+			IndexChangeCounts {
+				added: 6_000,
+				updated: 59,
+				removed: 12,
+			}
+		);
+	}
+
+	#[test]
+	fn a_subscriber_pulls_the_announced_changes() {
+		let (source, receiver) = (open(), open());
+		let source_peer = PeerId::random();
+		let source_node = peer_to_node_id(&source_peer).unwrap();
+		for path in ["/photos/a.jpg", "/photos/b.jpg", "/photos/c.jpg"] {
+			add_file(&source, &source_node, path);
+		}
+
+		assert!(!wants_pull(&receiver, &source_peer).unwrap());
+		save_index_subscription(&receiver, &source_peer, true, at(0)).unwrap();
+		assert!(wants_pull(&receiver, &source_peer).unwrap());
+
+		let high_water_mark = change_seq(&source);
+		let mut freshness = IndexFreshness::new(at(0));
+		let added = IndexChangeCounts {
+			added: 3,
+			..Default::default()
+		};
+		freshness.announce(added, high_water_mark, at(0));
+		assert!(freshness.describe(at(30)).is_some());
+
+		let (mut generation, mut after) = (String::new(), 0);
+		loop {
+			let delta = pull_delta(&source, &source_node, &generation, after).unwrap();
+			let ack = receive_delta(&receiver, &source_peer, &delta, at(5)).unwrap();
+			(generation, after) = (ack.generation, ack.acked);
+			if delta.remaining == 0 {
+				break;
+			}
+		}
+		freshness.synced(after.max(high_water_mark));
+		assert_eq!(freshness.describe(at(30)), None);
+
+		let pulled: i64 = receiver
+			.query_row(
+				"SELECT COUNT(*) FROM file_locations WHERE node_id = ?1",
+				[source_node.as_slice()],
+				|row| row.get(0),
+			)
+			.unwrap();
+		assert_eq!(pulled, 3);
+		// A pull with nothing new continues what the receiver holds.
+		let delta = pull_delta(&source, &source_node, &generation, after).unwrap();
+		assert!(delta.entries.is_empty() && delta.locations.is_empty());
+		assert_eq!(
+			receive_delta(&receiver, &source_peer, &delta, at(6))
+				.unwrap()
+				.acked,
+			after
+		);
+
+		save_index_subscription(&receiver, &source_peer, false, at(10)).unwrap();
+		// The rows pulled are a replica, which keeps the pulls going.
+		assert!(wants_pull(&receiver, &source_peer).unwrap());
+	}
+
+	#[test]
+	fn a_non_subscriber_only_marks_the_index_stale() {
+		let receiver = open();
+		let peer = PeerId::random();
+		assert!(!wants_pull(&receiver, &peer).unwrap());
+
+		let mut freshness = IndexFreshness::new(at(0));
+		let change = IndexChangeCounts {
+			added: 1_204,
+			removed: 3,
+			..Default::default()
+		};
+		freshness.announce(change, 1_300, at(0));
+		freshness.announce(change, 1_400, at(60));
+		assert!(freshness.is_stale());
+		assert_eq!(
+			freshness.describe(at(360)).as_deref(),
+			Some(
+				"index changed 5 minutes ago (2,408 added, 6 removed), results may be out of date"
+			)
+		);
+
+		freshness.synced(1_400);
+		assert_eq!(freshness.describe(at(360)), None);
+		freshness.announce(change, 1_500, at(400));
+		assert_eq!(freshness.changes, change);
+	}
+
+	#[test]
+	fn pulls_are_jittered_and_bounded() {
+		assert_eq!(pull_delay(0.0), PULL_DELAY_MIN);
+		assert_eq!(pull_delay(1.0), PULL_DELAY_MIN + PULL_JITTER);
+		assert!(pull_delay(0.5) > pull_delay(0.25));
+
+		let mut pulls = IndexPulls::default();
+		let peers = (0..4).map(|_| PeerId::random()).collect::<Vec<_>>();
+		assert!(pulls.schedule(peers[0]));
+		assert!(!pulls.schedule(peers[0]));
+		for peer in &peers[1..] {
+			assert!(pulls.schedule(*peer));
+		}
+		let started = peers.iter().filter(|peer| pulls.start(**peer, 10)).count();
+		assert_eq!(started, MAX_RUNNING_PULLS);
+
+		assert!(!pulls.schedule(peers[0]));
+		assert!(pulls.finish(&peers[0]));
+		assert!(!pulls.finish(&peers[1]));
+		assert!(pulls.start(peers[2], 10));
+		assert_eq!(pulls.target(&peers[2]), Some(10));
+		let batches = std::iter::from_fn(|| Some(pulls.next_batch(&peers[2])))
+			.take_while(|more| *more)
+			.count();
+		assert_eq!(batches as u32, MAX_PULL_BATCHES - 1);
+	}
+}
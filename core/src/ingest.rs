@@ -0,0 +1,865 @@
+//! Ingest from removable drives. A camera card or USB stick that shows up is
+//! announced once, and an ingest copies its photos and videos into a dated
+//! folder and the index in one go. Files the node already holds anywhere are
+//! skipped by hash. Each copy is written next to its destination under a
+//! temporary name and only renamed into place and indexed once it matches
+//! the original, so a card pulled mid-copy leaves neither a partial file nor
+//! a half-indexed entry; ingesting the same card again finds the files done
+//! so far already held and carries on with the rest.
+
+use crate::app::reserve_inbox_file;
+use crate::checksum_manifest::matches_pattern;
+use crate::content_negotiation::hashes_held;
+use crate::db::record_ingested_file;
+use crate::db_pool::Db;
+use crate::format::{SizeUnits, human_size};
+use crate::p2p::DiskInfo;
+use crate::scan::{FileHash, hash_file};
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use walkdir::WalkDir;
+
+pub(crate) const INGEST_SETTINGS_SETTING: &str = "ingest_settings";
+/// How often the local disks are looked at for drives coming and going.
+pub(crate) const DRIVE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Changes kept for the interface to pick up; older ones are dropped.
+const MAX_PENDING_CHANGES: usize = 32;
+/// Ending of a copy still being written.
+const PARTIAL_SUFFIX: &str = ".puppynet-partial";
+
+/// Lists the local disks: the system's, or made-up ones in tests.
+pub(crate) trait DiskSource: Send + Sync {
+	fn disks(&self) -> Vec<DiskInfo>;
+}
+
+impl<F> DiskSource for F
+where
+	F: Fn() -> Vec<DiskInfo> + Send + Sync,
+{
+	fn disks(&self) -> Vec<DiskInfo> {
+		self()
+	}
+}
+
+/// The name a drive goes by: its mount folder, which is the volume label
+/// on most systems.
+pub fn drive_label(drive: &DiskInfo) -> String {
+	Path::new(&drive.mount_path)
+		.file_name()
+		.map(|name| name.to_string_lossy().into_owned())
+		.filter(|name| !name.is_empty())
+		.unwrap_or_else(|| drive.name.clone())
+}
+
+fn drive_kind(drive: &DiskInfo) -> &'static str {
+	// Card readers show up as MMC block devices on Linux.
+	if drive.name.contains("mmcblk") {
+		"SD card"
+	} else {
+		"Drive"
+	}
+}
+
+fn used_space(drive: &DiskInfo) -> String {
+	let used = drive.total_space.saturating_sub(drive.available_space);
+	human_size(used, SizeUnits::Decimal)
+}
+
+/// "SD card 'EOS_DIGITAL', 12.00 GB used".
+pub fn describe_drive(drive: &DiskInfo) -> String {
+	format!(
+		"{} '{}', {} used",
+		drive_kind(drive),
+		drive_label(drive),
+		used_space(drive)
+	)
+}
+
+/// A removable drive that came or went between two looks.
+#[derive(Clone, Debug)]
+pub enum DriveChange {
+	Attached {
+		drive: DiskInfo,
+		/// Its last ingest stopped when it was removed.
+		interrupted: bool,
+	},
+	Detached(DiskInfo),
+}
+
+impl DriveChange {
+	/// "SD card 'EOS_DIGITAL' attached — 12.00 GB used".
+	pub fn message(&self) -> String {
+		match self {
+			DriveChange::Attached { drive, interrupted } => {
+				let mut message = format!(
+					"{} '{}' attached — {} used",
+					drive_kind(drive),
+					drive_label(drive),
+					used_space(drive)
+				);
+				if *interrupted {
+					message.push_str("; its last ingest was interrupted and can be resumed");
+				}
+				message
+			}
+			DriveChange::Detached(drive) => {
+				format!("{} '{}' removed", drive_kind(drive), drive_label(drive))
+			}
+		}
+	}
+}
+
+/// A removable drive attached now.
+#[derive(Clone, Debug)]
+pub struct RemovableDrive {
+	pub disk: DiskInfo,
+	/// Its last ingest stopped when it was removed.
+	pub interrupted: bool,
+}
+
+/// The removable drives seen at the last look and what changed since the
+/// changes were last taken.
+#[derive(Default)]
+pub(crate) struct RemovableDrives {
+	attached: Vec<DiskInfo>,
+	/// Drives already there at the first look are listed but not announced.
+	primed: bool,
+	changes: Vec<DriveChange>,
+	/// Ids of drives whose last ingest stopped because they went away.
+	interrupted: HashSet<String>,
+}
+
+impl RemovableDrives {
+	pub(crate) fn refresh(&mut self, disks: Vec<DiskInfo>) {
+		let current = disks
+			.into_iter()
+			.filter(|disk| disk.removable)
+			.collect::<Vec<_>>();
+		if self.primed {
+			for drive in &current {
+				if !self.attached.iter().any(|known| known.id == drive.id) {
+					self.changes.push(DriveChange::Attached {
+						drive: drive.clone(),
+						interrupted: self.interrupted.contains(&drive.id),
+					});
+				}
+			}
+			for drive in &self.attached {
+				if !current.iter().any(|disk| disk.id == drive.id) {
+					self.changes.push(DriveChange::Detached(drive.clone()));
+				}
+			}
+			let excess = self.changes.len().saturating_sub(MAX_PENDING_CHANGES);
+			self.changes.drain(..excess);
+		}
+		self.primed = true;
+		self.attached = current;
+	}
+
+	pub(crate) fn attached(&self) -> Vec<RemovableDrive> {
+		self.attached
+			.iter()
+			.map(|disk| RemovableDrive {
+				disk: disk.clone(),
+				interrupted: self.interrupted.contains(&disk.id),
+			})
+			.collect()
+	}
+
+	pub(crate) fn find(&self, id: &str) -> Option<DiskInfo> {
+		self.attached.iter().find(|disk| disk.id == id).cloned()
+	}
+
+	pub(crate) fn take_changes(&mut self) -> Vec<DriveChange> {
+		std::mem::take(&mut self.changes)
+	}
+
+	pub(crate) fn set_interrupted(&mut self, id: &str, interrupted: bool) {
+		if interrupted {
+			self.interrupted.insert(id.to_string());
+		} else {
+			self.interrupted.remove(id);
+		}
+	}
+}
+
+/// What an ingest copies and where to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IngestSettings {
+	/// Folder the copies go to. A leading `~` is the home folder; `{date}`,
+	/// `{year}` and `{month}` come from each file's modification time and
+	/// `{drive}` is the drive's label.
+	pub destination: String,
+	/// File name patterns like `*.CR3`, or media type patterns like
+	/// `image/*` when they contain a `/`.
+	pub patterns: Vec<String>,
+	/// Remove each original from the drive once its copy reads back the
+	/// same.
+	pub delete_originals: bool,
+	/// Flush and release the drive after an ingest that got through it.
+	pub eject_when_done: bool,
+}
+
+impl Default for IngestSettings {
+	fn default() -> Self {
+		Self {
+			destination: String::from("~/Pictures/Ingest/{date}"),
+			patterns: vec![String::from("image/*"), String::from("video/*")],
+			delete_originals: false,
+			eject_when_done: false,
+		}
+	}
+}
+
+impl IngestSettings {
+	pub fn validate(&self) -> Result<()> {
+		if self.destination.trim().is_empty() {
+			bail!("choose a destination folder");
+		}
+		if self
+			.patterns
+			.iter()
+			.all(|pattern| pattern.trim().is_empty())
+		{
+			bail!("give at least one pattern of files to ingest");
+		}
+		Ok(())
+	}
+
+	pub fn matches(&self, path: &Path) -> bool {
+		let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+			return false;
+		};
+		// Skips the `._` files macOS leaves next to everything on a card.
+		if name.starts_with('.') {
+			return false;
+		}
+		let mime = mime_guess::from_path(path).first_raw();
+		self.patterns
+			.iter()
+			.map(|pattern| pattern.trim())
+			.filter(|pattern| !pattern.is_empty())
+			.any(|pattern| {
+				if pattern.contains('/') {
+					mime.is_some_and(|mime| matches_pattern(mime, pattern))
+				} else {
+					matches_pattern(name, pattern)
+				}
+			})
+	}
+
+	fn destination_dir(
+		&self,
+		drive: &str,
+		modified: DateTime<Local>,
+		home: Option<&Path>,
+	) -> Result<PathBuf> {
+		let dir = self
+			.destination
+			.trim()
+			.replace("{date}", &modified.format("%Y-%m-%d").to_string())
+			.replace("{year}", &modified.format("%Y").to_string())
+			.replace("{month}", &modified.format("%m").to_string())
+			.replace("{drive}", drive);
+		match dir.strip_prefix('~') {
+			Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => {
+				let home = home.ok_or_else(|| anyhow!("no home folder to expand ~ in"))?;
+				Ok(home.join(rest.trim_start_matches(['/', '\\'])))
+			}
+			_ => Ok(PathBuf::from(dir)),
+		}
+	}
+}
+
+/// What became of one file of an ingest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IngestFileStatus {
+	/// Copied to this path and indexed.
+	Copied(PathBuf),
+	/// The node already held the same content.
+	Duplicate,
+	Failed(String),
+}
+
+/// Sent after each file of an ingest.
+#[derive(Clone, Debug)]
+pub struct IngestProgress {
+	pub done: usize,
+	pub total: usize,
+	pub source: PathBuf,
+	pub status: IngestFileStatus,
+}
+
+impl IngestProgress {
+	/// "3/40 IMG_0003.JPG: copied".
+	pub fn line(&self) -> String {
+		let name = self
+			.source
+			.file_name()
+			.map(|name| name.to_string_lossy().into_owned())
+			.unwrap_or_default();
+		let status = match &self.status {
+			IngestFileStatus::Copied(_) => String::from("copied"),
+			IngestFileStatus::Duplicate => String::from("already indexed"),
+			IngestFileStatus::Failed(err) => format!("failed: {err}"),
+		};
+		format!("{}/{} {name}: {status}", self.done, self.total)
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IngestReport {
+	/// Where each copy went.
+	pub copied: Vec<PathBuf>,
+	/// Files on the drive whose content the node already held.
+	pub duplicates: Vec<PathBuf>,
+	pub failed: Vec<(PathBuf, String)>,
+	/// Originals removed from the drive after their copy checked out.
+	pub deleted: usize,
+	/// The drive went away before every file was done.
+	pub interrupted: bool,
+	pub cancelled: bool,
+	/// How ejecting went, when it was asked for.
+	pub eject: Option<Result<(), String>>,
+}
+
+impl IngestReport {
+	/// "12 copied, 3 duplicates skipped, 1 failed".
+	pub fn summary(&self) -> String {
+		let mut summary = format!(
+			"{} copied, {} duplicates skipped, {} failed",
+			self.copied.len(),
+			self.duplicates.len(),
+			self.failed.len()
+		);
+		if self.deleted > 0 {
+			summary.push_str(&format!(", {} originals deleted", self.deleted));
+		}
+		if self.interrupted {
+			summary.push_str("; the drive was removed, attach it again to resume");
+		} else if self.cancelled {
+			summary.push_str("; cancelled");
+		}
+		match &self.eject {
+			Some(Ok(())) => summary.push_str("; drive ejected"),
+			Some(Err(err)) => summary.push_str(&format!("; could not eject: {err}")),
+			None => {}
+		}
+		summary
+	}
+}
+
+enum Ingested {
+	Copied { dest: PathBuf, deleted: bool },
+	Duplicate,
+}
+
+fn still_attached(disks: &dyn DiskSource, drive: &DiskInfo) -> bool {
+	disks.disks().iter().any(|disk| disk.id == drive.id) && Path::new(&drive.mount_path).is_dir()
+}
+
+/// Copies `source` to `dest` and flushes it to disk, returning the hash and
+/// length of what was read.
+fn copy_hashing(source: &Path, dest: &Path) -> io::Result<(FileHash, u64)> {
+	let mut reader = File::open(source)?;
+	let mut writer = File::create(dest)?;
+	let mut hasher = blake3::Hasher::new();
+	let mut buffer = vec![0u8; 64 * 1024];
+	let mut len = 0u64;
+	loop {
+		let count = reader.read(&mut buffer)?;
+		if count == 0 {
+			break;
+		}
+		hasher.update(&buffer[..count]);
+		writer.write_all(&buffer[..count])?;
+		len += count as u64;
+	}
+	writer.sync_all()?;
+	Ok((*hasher.finalize().as_bytes(), len))
+}
+
+/// Copies and checks `source` into a temporary file in `dir` and renames it
+/// into place, removing whatever it left behind when it fails.
+fn place_copy(
+	source: &Path,
+	dir: &Path,
+	name: &str,
+	hash: &FileHash,
+	verify: bool,
+) -> Result<(PathBuf, u64)> {
+	let partial = dir.join(format!(".{name}{PARTIAL_SUFFIX}"));
+	let placed = (|| -> Result<(PathBuf, u64)> {
+		let (copied, size) = copy_hashing(source, &partial)
+			.with_context(|| format!("failed to copy {}", source.display()))?;
+		if copied != *hash {
+			bail!("{} changed while it was copied", source.display());
+		}
+		if verify && hash_file(File::open(&partial)?)? != *hash {
+			bail!(
+				"the copy of {} does not read back the same",
+				source.display()
+			);
+		}
+		let dest = reserve_inbox_file(dir, name)?;
+		if let Err(err) = fs::rename(&partial, &dest) {
+			let _ = fs::remove_file(&dest);
+			return Err(err.into());
+		}
+		Ok((dest, size))
+	})();
+	if placed.is_err() {
+		let _ = fs::remove_file(&partial);
+	}
+	placed
+}
+
+fn ingest_file(
+	db: &Db,
+	node_id: &[u8],
+	source: &Path,
+	drive: &str,
+	settings: &IngestSettings,
+	home: Option<&Path>,
+) -> Result<Ingested> {
+	let hash = File::open(source)
+		.and_then(hash_file)
+		.with_context(|| format!("failed to read {}", source.display()))?;
+	{
+		let conn = db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		let held = hashes_held(&conn, node_id, &[hash], |_| true)?;
+		if held.first().is_some_and(|bits| bits & 1 != 0) {
+			return Ok(Ingested::Duplicate);
+		}
+	}
+	let modified = fs::metadata(source)?
+		.modified()
+		.ok()
+		.map(DateTime::<Utc>::from);
+	let dir = settings.destination_dir(
+		drive,
+		modified.unwrap_or_else(Utc::now).with_timezone(&Local),
+		home,
+	)?;
+	fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+	let name = source
+		.file_name()
+		.and_then(|name| name.to_str())
+		.ok_or_else(|| anyhow!("{} has no usable name", source.display()))?;
+	let (dest, size) = place_copy(source, &dir, name, &hash, settings.delete_originals)?;
+	let mime_type = mime_guess::from_path(&dest)
+		.first_raw()
+		.map(|mime| mime.to_string());
+	let indexed = db
+		.lock()
+		.map_err(|err| anyhow!("db lock poisoned: {err}"))
+		.and_then(|conn| {
+			record_ingested_file(
+				&conn,
+				node_id,
+				&dest,
+				&hash,
+				size,
+				mime_type.as_deref(),
+				modified,
+			)
+		});
+	if let Err(err) = indexed {
+		let _ = fs::remove_file(&dest);
+		return Err(err.context("failed to index the copy"));
+	}
+	let deleted = settings.delete_originals
+		&& match fs::remove_file(source) {
+			Ok(()) => true,
+			Err(err) => {
+				tracing::warn!("failed to delete ingested {}: {err}", source.display());
+				false
+			}
+		};
+	Ok(Ingested::Copied { dest, deleted })
+}
+
+/// Copies the files on `drive` that `settings` picks into their destination
+/// and the index of `node_id`, reporting each file as it goes. Stops with
+/// `interrupted` set once `disks` no longer lists the drive.
+pub(crate) fn ingest(
+	db: &Db,
+	node_id: &[u8],
+	drive: &DiskInfo,
+	disks: &dyn DiskSource,
+	settings: &IngestSettings,
+	cancel: &AtomicBool,
+	mut progress: impl FnMut(&IngestProgress),
+) -> Result<IngestReport> {
+	settings.validate()?;
+	let home = homedir::my_home().ok().flatten();
+	let label = drive_label(drive);
+	let mut files = WalkDir::new(&drive.mount_path)
+		.follow_links(false)
+		.into_iter()
+		.filter_map(|entry| entry.ok())
+		.filter(|entry| entry.file_type().is_file() && settings.matches(entry.path()))
+		.map(|entry| entry.into_path())
+		.collect::<Vec<_>>();
+	files.sort();
+	let mut report = IngestReport::default();
+	for (index, source) in files.iter().enumerate() {
+		if cancel.load(Ordering::SeqCst) {
+			report.cancelled = true;
+			break;
+		}
+		let status = match ingest_file(db, node_id, source, &label, settings, home.as_deref()) {
+			Ok(Ingested::Copied { dest, deleted }) => {
+				report.copied.push(dest.clone());
+				report.deleted += usize::from(deleted);
+				IngestFileStatus::Copied(dest)
+			}
+			Ok(Ingested::Duplicate) => {
+				report.duplicates.push(source.clone());
+				IngestFileStatus::Duplicate
+			}
+			Err(err) if !still_attached(disks, drive) => {
+				tracing::info!("{label} went away during an ingest: {err:#}");
+				report.interrupted = true;
+				break;
+			}
+			Err(err) => {
+				let err = format!("{err:#}");
+				report.failed.push((source.clone(), err.clone()));
+				IngestFileStatus::Failed(err)
+			}
+		};
+		progress(&IngestProgress {
+			done: index + 1,
+			total: files.len(),
+			source: source.clone(),
+			status,
+		});
+	}
+	Ok(report)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn run(command: &mut Command) -> Result<String> {
+	let program = command.get_program().to_string_lossy().into_owned();
+	let output = command
+		.output()
+		.with_context(|| format!("failed to run {program}"))?;
+	if !output.status.success() {
+		bail!(
+			"{program} failed: {}",
+			String::from_utf8_lossy(&output.stderr).trim()
+		);
+	}
+	Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Flushes pending writes and releases the drive mounted at `mount_path` so
+/// it can be pulled.
+#[cfg(target_os = "linux")]
+pub(crate) fn eject(mount_path: &str) -> Result<()> {
+	run(&mut Command::new("sync"))?;
+	let device = run(Command::new("findmnt").args(["-n", "-o", "SOURCE", "--target", mount_path]))?;
+	if device.is_empty() {
+		bail!("{mount_path} is not mounted");
+	}
+	run(Command::new("udisksctl").args(["unmount", "-b", &device]))?;
+	// Not every reader can be powered off; unmounted is safe to pull.
+	if let Err(err) = run(Command::new("udisksctl").args(["power-off", "-b", &device])) {
+		tracing::debug!("did not power off {device}: {err:#}");
+	}
+	Ok(())
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn eject(mount_path: &str) -> Result<()> {
+	run(&mut Command::new("sync"))?;
+	run(Command::new("diskutil").args(["eject", mount_path]))?;
+	Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub(crate) fn eject(_mount_path: &str) -> Result<()> {
+	bail!("ejecting drives is not supported on this platform")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::{self, test_dir};
+	use chrono::TimeZone;
+	use std::sync::Arc;
+
+	fn card(mount_path: &Path) -> DiskInfo {
+		DiskInfo {
+			name: String::from("/dev/mmcblk0p1"),
+			mount_path: mount_path.display().to_string(),
+			filesystem: String::from("exfat"),
+			total_space: 64_000_000_000,
+			available_space: 52_000_000_000,
+			usage_percent: 18.75,
+			total_read_bytes: 0,
+			total_written_bytes: 0,
+			read_only: false,
+			removable: true,
+			kind: String::from("Unknown(-1)"),
+			id: String::from("uuid:1234-ABCD"),
+		}
+	}
+
+	/// A card reader whose card can be pulled and put back.
+	fn reader(drive: &DiskInfo) -> (Arc<AtomicBool>, impl DiskSource) {
+		let inserted = Arc::new(AtomicBool::new(true));
+		let source = {
+			let (inserted, drive) = (Arc::clone(&inserted), drive.clone());
+			move || {
+				if inserted.load(Ordering::SeqCst) {
+					vec![drive.clone()]
+				} else {
+					Vec::new()
+				}
+			}
+		};
+		(inserted, source)
+	}
+
+	fn open_db() -> Db {
+		let conn = test_util::memory_db();
+		Db::single(conn)
+	}
+
+	fn indexed_paths(db: &Db) -> Vec<String> {
+		let conn = db.lock().unwrap();
+		let mut stmt = conn
+			.prepare("SELECT path FROM file_locations WHERE hash IS NOT NULL ORDER BY path")
+			.unwrap();
+		stmt.query_map([], |row| row.get::<_, String>(0))
+			.unwrap()
+			.map(Result::unwrap)
+			.collect()
+	}
+
+	fn partial_files(dir: &Path) -> usize {
+		WalkDir::new(dir)
+			.into_iter()
+			.filter_map(|entry| entry.ok())
+			.filter(|entry| {
+				entry
+					.file_name()
+					.to_string_lossy()
+					.ends_with(PARTIAL_SUFFIX)
+			})
+			.count()
+	}
+
+	fn settings(dest: &Path) -> IngestSettings {
+		IngestSettings {
+			destination: dest.join("{drive}").display().to_string(),
+			..IngestSettings::default()
+		}
+	}
+
+	#[test]
+	fn settings_pick_media_and_fill_in_the_destination() {
+		let settings = IngestSettings::default();
+		assert!(settings.matches(Path::new("/card/DCIM/IMG_0001.JPG")));
+		assert!(settings.matches(Path::new("/card/DCIM/MVI_0002.MP4")));
+		assert!(!settings.matches(Path::new("/card/MISC/notes.txt")));
+		assert!(!settings.matches(Path::new("/card/DCIM/._IMG_0001.JPG")));
+		let raw = IngestSettings {
+			patterns: vec![String::from("*.cr3"), String::new()],
+			..IngestSettings::default()
+		};
+		assert!(raw.matches(Path::new("/card/IMG_0003.CR3")));
+		assert!(!raw.matches(Path::new("/card/IMG_0003.JPG")));
+		let empty = IngestSettings {
+			patterns: vec![String::from(" ")],
+			..IngestSettings::default()
+		};
+		assert!(empty.validate().is_err());
+
+		let modified = Local.with_ymd_and_hms(2026, 3, 7, 12, 0, 0).unwrap();
+		let home = Path::new("/home/anna");
+		assert_eq!(
+			settings
+				.destination_dir("EOS_DIGITAL", modified, Some(home))
+				.unwrap(),
+			home.join("Pictures/Ingest/2026-03-07")
+		);
+		let by_drive = IngestSettings {
+			destination: String::from("/photos/{year}/{month}/{drive}"),
+			..IngestSettings::default()
+		};
+		assert_eq!(
+			by_drive
+				.destination_dir("EOS_DIGITAL", modified, None)
+				.unwrap(),
+			PathBuf::from("/photos/2026/03/EOS_DIGITAL")
+		);
+		assert!(settings.destination_dir("card", modified, None).is_err());
+	}
+
+	#[test]
+	fn drives_attached_after_the_first_look_are_announced() {
+		let drive = card(Path::new("/media/anna/EOS_DIGITAL"));
+		let fixed = DiskInfo {
+			removable: false,
+			id: String::from("uuid:root"),
+			..card(Path::new("/"))
+		};
+		let mut drives = RemovableDrives::default();
+		drives.refresh(vec![fixed.clone(), drive.clone()]);
+		assert!(drives.take_changes().is_empty());
+		assert_eq!(drives.attached().len(), 1);
+
+		drives.refresh(vec![fixed.clone()]);
+		let changes = drives.take_changes();
+		assert!(matches!(&changes[..], [DriveChange::Detached(_)]));
+		assert_eq!(changes[0].message(), "SD card 'EOS_DIGITAL' removed");
+
+		drives.set_interrupted(&drive.id, true);
+		drives.refresh(vec![fixed, drive.clone()]);
+		let changes = drives.take_changes();
+		assert_eq!(
+			changes[0].message(),
+			"SD card 'EOS_DIGITAL' attached — 12.00 GB used; \
+			its last ingest was interrupted and can be resumed"
+		);
+		assert!(drives.attached()[0].interrupted);
+		assert_eq!(drives.find(&drive.id).unwrap().mount_path, drive.mount_path);
+	}
+
+	#[test]
+	fn ingest_skips_what_the_index_holds_and_deletes_verified_originals() {
+		let dir = test_dir("ingest-dedup");
+		let mount = dir.join("EOS_DIGITAL");
+		fs::create_dir_all(mount.join("DCIM/100CANON")).unwrap();
+		fs::write(mount.join("DCIM/100CANON/IMG_0001.JPG"), b"first photo").unwrap();
+		fs::write(mount.join("DCIM/100CANON/IMG_0002.JPG"), b"known photo").unwrap();
+		fs::write(mount.join("DCIM/100CANON/IMG_0003.JPG"), b"first photo").unwrap();
+		fs::write(mount.join("readme.txt"), b"not media").unwrap();
+		let db = open_db();
+		let node_id = [1u8; 16];
+		let known = *blake3::hash(b"known photo").as_bytes();
+		record_ingested_file(
+			&db.lock().unwrap(),
+			&node_id,
+			&dir.join("library/known.jpg"),
+			&known,
+			11,
+			Some("image/jpeg"),
+			None,
+		)
+		.unwrap();
+		let drive = card(&mount);
+		let (_inserted, disks) = reader(&drive);
+		let dest = dir.join("dest");
+		let settings = IngestSettings {
+			delete_originals: true,
+			..settings(&dest)
+		};
+		let mut lines = Vec::new();
+		let report = ingest(
+			&db,
+			&node_id,
+			&drive,
+			&disks,
+			&settings,
+			&AtomicBool::new(false),
+			|progress| lines.push(progress.line()),
+		)
+		.unwrap();
+
+		let copy = dest.join("EOS_DIGITAL/IMG_0001.JPG");
+		assert_eq!(report.copied, vec![copy.clone()]);
+		assert_eq!(report.duplicates.len(), 2);
+		assert_eq!(report.deleted, 1);
+		assert_eq!(
+			report.summary(),
+			"1 copied, 2 duplicates skipped, 0 failed, 1 originals deleted"
+		);
+		assert_eq!(lines[0], "1/3 IMG_0001.JPG: copied");
+		assert_eq!(lines[1], "2/3 IMG_0002.JPG: already indexed");
+		assert_eq!(fs::read(&copy).unwrap(), b"first photo");
+		assert!(!mount.join("DCIM/100CANON/IMG_0001.JPG").exists());
+		assert!(mount.join("DCIM/100CANON/IMG_0002.JPG").exists());
+		assert!(mount.join("readme.txt").exists());
+		assert_eq!(indexed_paths(&db).len(), 2);
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn a_card_pulled_mid_ingest_resumes_when_it_is_back() {
+		let dir = test_dir("ingest-resume");
+		let mount = dir.join("EOS_DIGITAL");
+		fs::create_dir_all(mount.join("DCIM")).unwrap();
+		for n in 1..=3 {
+			fs::write(
+				mount.join(format!("DCIM/IMG_000{n}.JPG")),
+				format!("photo {n}"),
+			)
+			.unwrap();
+		}
+		let db = open_db();
+		let node_id = [1u8; 16];
+		let drive = card(&mount);
+		let (inserted, disks) = reader(&drive);
+		let dest = dir.join("dest");
+		let settings = settings(&dest);
+		let pulled = dir.join("pulled");
+		let report = ingest(
+			&db,
+			&node_id,
+			&drive,
+			&disks,
+			&settings,
+			&AtomicBool::new(false),
+			|progress| {
+				if progress.done == 1 {
+					inserted.store(false, Ordering::SeqCst);
+					fs::rename(&mount, &pulled).unwrap();
+				}
+			},
+		)
+		.unwrap();
+		assert!(report.interrupted);
+		assert_eq!(report.copied.len(), 1);
+		assert!(report.failed.is_empty());
+		assert_eq!(partial_files(&dest), 0);
+		assert_eq!(indexed_paths(&db).len(), 1);
+
+		fs::rename(&pulled, &mount).unwrap();
+		inserted.store(true, Ordering::SeqCst);
+		let report = ingest(
+			&db,
+			&node_id,
+			&drive,
+			&disks,
+			&settings,
+			&AtomicBool::new(false),
+			|_| {},
+		)
+		.unwrap();
+		assert!(!report.interrupted);
+		assert_eq!(report.duplicates.len(), 1);
+		assert_eq!(report.copied.len(), 2);
+		assert_eq!(indexed_paths(&db).len(), 3);
+		assert_eq!(
+			fs::read(dest.join("EOS_DIGITAL/IMG_0003.JPG")).unwrap(),
+			b"photo 3"
+		);
+		fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -0,0 +1,735 @@
+//! Background integrity sampling. Rather than a full verify run over every
+//! indexed byte, the node re-hashes a few of its own files each cycle and
+//! compares them with the hash the index holds. Files never checked, or
+//! checked long ago, are picked first; larger and older files, which have
+//! more to lose and sit untouched the longest, are favoured among them.
+//!
+//! A cycle only runs while the node is otherwise quiet, the activity window
+//! is open for scans and there is no power hold, and its reads are held
+//! under a rate cap so they never crowd out foreground reads. Files on a
+//! share that is not mounted, and files changed since they were indexed,
+//! are skipped without an outcome: only a file that reads back different
+//! under an unchanged size and modification time is a mismatch. Each
+//! outcome is kept per file in `integrity_checks`.
+
+use crate::db::{NodeID, get_your_node, path_column, path_to_sql};
+use crate::db_pool::Db;
+use crate::mounts::MountTable;
+use crate::pins::throttle_delay;
+use crate::scan::FileHash;
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub(crate) const INTEGRITY_SETTINGS_SETTING: &str = "integrity_sampling";
+/// How often a sampling cycle is attempted.
+pub(crate) const INTEGRITY_CYCLE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// A check older than this no longer counts towards coverage.
+pub const COVERAGE_WINDOW_DAYS: i64 = 90;
+/// Random local files a cycle weighs before picking its sample.
+const CANDIDATE_POOL: usize = 256;
+pub const MAX_FILES_PER_CYCLE: u32 = 100;
+/// Slowest read rate allowed, so a cycle still ends.
+pub const MIN_READ_RATE: u64 = 64 * 1024;
+/// Other copies listed with a finding.
+const MAX_REPLICAS: usize = 8;
+const READ_CHUNK: usize = 256 * 1024;
+const STATUS_OK: &str = "ok";
+const STATUS_MISMATCH: &str = "mismatch";
+
+/// How much sampling does, stored under [`INTEGRITY_SETTINGS_SETTING`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IntegritySettings {
+	pub enabled: bool,
+	/// Files re-hashed per cycle.
+	pub files_per_cycle: u32,
+	/// Cap on the sampler's reads, in bytes per second.
+	pub max_read_rate: u64,
+}
+
+impl Default for IntegritySettings {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			files_per_cycle: 2,
+			max_read_rate: 4 * 1024 * 1024,
+		}
+	}
+}
+
+impl IntegritySettings {
+	pub fn validate(&self) -> Result<()> {
+		if !(1..=MAX_FILES_PER_CYCLE).contains(&self.files_per_cycle) {
+			bail!("files per cycle must be between 1 and {MAX_FILES_PER_CYCLE}");
+		}
+		if self.max_read_rate < MIN_READ_RATE {
+			bail!(
+				"the read rate cap must be at least {} KiB/s",
+				MIN_READ_RATE / 1024
+			);
+		}
+		Ok(())
+	}
+}
+
+/// How much of the index was checked recently.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct IntegrityStatus {
+	pub enabled: bool,
+	pub indexed_files: u64,
+	pub indexed_bytes: u64,
+	/// Files that read back as indexed within [`COVERAGE_WINDOW_DAYS`].
+	pub verified_files: u64,
+	pub verified_bytes: u64,
+	/// Files whose last check found different content.
+	pub mismatches: u64,
+}
+
+impl IntegrityStatus {
+	/// Share of indexed bytes verified in the coverage window, rounded down.
+	pub fn coverage_percent(&self) -> u64 {
+		if self.indexed_bytes == 0 {
+			return 0;
+		}
+		(self.verified_bytes as u128 * 100 / self.indexed_bytes as u128) as u64
+	}
+
+	/// "31% of indexed bytes verified in the last 90 days, 0 mismatches".
+	pub fn summary(&self) -> String {
+		let mut line = if self.indexed_files == 0 {
+			String::from("Nothing indexed to verify yet")
+		} else {
+			format!(
+				"{}% of indexed bytes verified in the last {COVERAGE_WINDOW_DAYS} days, {} {}",
+				self.coverage_percent(),
+				self.mismatches,
+				if self.mismatches == 1 {
+					"mismatch"
+				} else {
+					"mismatches"
+				}
+			)
+		};
+		if !self.enabled {
+			line.push_str(" (sampling turned off)");
+		}
+		line
+	}
+}
+
+/// Another indexed copy of a file's expected content.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct IntegrityReplica {
+	pub node_id: NodeID,
+	/// Empty for nodes the index has no name for.
+	pub node_name: String,
+	pub path: PathBuf,
+}
+
+/// A local file that no longer reads back as indexed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct IntegrityFinding {
+	pub path: PathBuf,
+	pub expected: Vec<u8>,
+	pub actual: Vec<u8>,
+	pub checked_at: DateTime<Utc>,
+	/// Copies to recover from, this node's own first.
+	pub replicas: Vec<IntegrityReplica>,
+}
+
+/// A local file a cycle may pick.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Candidate {
+	pub(crate) path: PathBuf,
+	pub(crate) hash: Vec<u8>,
+	pub(crate) size: u64,
+	pub(crate) modified_at: Option<DateTime<Utc>>,
+	pub(crate) last_verified_at: Option<DateTime<Utc>>,
+}
+
+/// How much a file is owed a check. Never checked counts as checked two
+/// coverage windows ago; size and age scale that up.
+pub(crate) fn weight(candidate: &Candidate, now: DateTime<Utc>) -> f64 {
+	let days = |at: DateTime<Utc>| (now - at).num_seconds().max(0) as f64 / 86_400.0;
+	let stale_cap = COVERAGE_WINDOW_DAYS as f64 * 2.0;
+	let staleness = candidate
+		.last_verified_at
+		.map_or(stale_cap, |at| days(at).clamp(0.01, stale_cap));
+	let mebibytes = candidate.size as f64 / (1024.0 * 1024.0);
+	let size = 1.0 + (1.0 + mebibytes).log2();
+	let years = candidate.modified_at.map_or(0.0, |at| days(at) / 365.0);
+	let age = 1.0 + years.min(10.0) / 2.0;
+	staleness * size * age
+}
+
+/// Up to `count` of `candidates`, drawn without replacement with chances
+/// following [`weight`].
+pub(crate) fn pick(
+	candidates: Vec<Candidate>,
+	count: usize,
+	now: DateTime<Utc>,
+	rng: &mut impl Rng,
+) -> Vec<Candidate> {
+	// Each candidate draws ln(u)/w; the largest keys form a weighted
+	// sample (Efraimidis and Spirakis).
+	let mut keyed = candidates
+		.into_iter()
+		.map(|candidate| {
+			let weight = weight(&candidate, now).max(f64::MIN_POSITIVE);
+			(rng.r#gen::<f64>().ln() / weight, candidate)
+		})
+		.collect::<Vec<_>>();
+	keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+	keyed
+		.into_iter()
+		.take(count)
+		.map(|(_, candidate)| candidate)
+		.collect()
+}
+
+/// Live local files with a hash, a random `limit` of them. A check of
+/// content the file was since rescanned away from counts as none.
+fn candidates(conn: &Connection, node_id: &NodeID, limit: usize) -> Result<Vec<Candidate>> {
+	let mut stmt = conn.prepare(
+		"SELECT fl.path, fl.hash, fl.size, fl.modified_at, ic.last_verified_at
+		FROM file_locations fl
+		LEFT JOIN integrity_checks ic
+			ON ic.node_id = fl.node_id AND ic.path = fl.path AND ic.expected = fl.hash
+		WHERE fl.node_id = ?1 AND fl.deleted_at IS NULL AND fl.hash IS NOT NULL
+		ORDER BY random()
+		LIMIT ?2",
+	)?;
+	let rows = stmt.query_map(params![node_id.as_slice(), limit as i64], |row| {
+		Ok(Candidate {
+			path: path_column(row, 0)?,
+			hash: row.get(1)?,
+			size: row.get::<_, i64>(2)?.max(0) as u64,
+			modified_at: row.get::<_, Option<DateTime<Utc>>>(3).unwrap_or(None),
+			last_verified_at: row
+				.get::<_, Option<i64>>(4)?
+				.and_then(|at| DateTime::from_timestamp(at, 0)),
+		})
+	})?;
+	Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+/// Why a picked file was left without an outcome.
+#[derive(Debug)]
+enum Skipped {
+	/// Its share is not mounted or the file is gone; the next scan of the
+	/// folder sorts that out.
+	Unavailable,
+	/// Size or modification time differ from the index, so a different
+	/// hash is an edit, not damage.
+	Changed,
+	Unreadable(io::Error),
+}
+
+fn same_second(a: Option<DateTime<Utc>>, b: Option<DateTime<Utc>>) -> bool {
+	match (a, b) {
+		(Some(a), Some(b)) => a.timestamp() == b.timestamp(),
+		_ => true,
+	}
+}
+
+/// Hashes `path`, sleeping between chunks to stay under `max_rate` bytes
+/// per second.
+fn hash_throttled(path: &Path, max_rate: u64) -> io::Result<FileHash> {
+	let mut file = File::open(path)?;
+	let mut hasher = blake3::Hasher::new();
+	let mut buffer = vec![0u8; READ_CHUNK];
+	let started = Instant::now();
+	let mut read = 0u64;
+	loop {
+		let count = match file.read(&mut buffer) {
+			Ok(0) => break,
+			Ok(count) => count,
+			Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+			Err(err) => return Err(err),
+		};
+		hasher.update(&buffer[..count]);
+		read += count as u64;
+		std::thread::sleep(throttle_delay(read, max_rate, started.elapsed()));
+	}
+	Ok(*hasher.finalize().as_bytes())
+}
+
+fn check_file(
+	candidate: &Candidate,
+	mounts: &MountTable,
+	max_rate: u64,
+) -> Result<FileHash, Skipped> {
+	if !mounts.is_present(&candidate.path) {
+		return Err(Skipped::Unavailable);
+	}
+	let metadata = match std::fs::metadata(&candidate.path) {
+		Ok(metadata) => metadata,
+		Err(err) if err.kind() == io::ErrorKind::NotFound => return Err(Skipped::Unavailable),
+		Err(err) => return Err(Skipped::Unreadable(err)),
+	};
+	let modified_at = metadata.modified().ok().map(DateTime::<Utc>::from);
+	if metadata.len() != candidate.size || !same_second(modified_at, candidate.modified_at) {
+		return Err(Skipped::Changed);
+	}
+	hash_throttled(&candidate.path, max_rate).map_err(|err| match err.kind() {
+		io::ErrorKind::NotFound => Skipped::Unavailable,
+		_ => Skipped::Unreadable(err),
+	})
+}
+
+/// Stores the outcome of checking `path`. True when it is a mismatch that
+/// was not known already.
+fn record_check(
+	conn: &Connection,
+	node_id: &NodeID,
+	path: &Path,
+	expected: &[u8],
+	actual: &[u8],
+	at: DateTime<Utc>,
+) -> Result<bool> {
+	let status = if expected == actual {
+		STATUS_OK
+	} else {
+		STATUS_MISMATCH
+	};
+	let previous = conn
+		.query_row(
+			"SELECT status, expected FROM integrity_checks WHERE node_id = ?1 AND path = ?2",
+			params![node_id.as_slice(), path_to_sql(path)],
+			|row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?)),
+		)
+		.optional()?;
+	conn.execute(
+		"INSERT INTO integrity_checks (node_id, path, expected, actual, status, last_verified_at)
+		VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+		ON CONFLICT(node_id, path) DO UPDATE SET
+			expected = excluded.expected,
+			actual = excluded.actual,
+			status = excluded.status,
+			last_verified_at = excluded.last_verified_at",
+		params![
+			node_id.as_slice(),
+			path_to_sql(path),
+			expected,
+			(status == STATUS_MISMATCH).then_some(actual),
+			status,
+			at.timestamp(),
+		],
+	)?;
+	let known = previous
+		.is_some_and(|(status, previous)| status == STATUS_MISMATCH && previous == expected);
+	Ok(status == STATUS_MISMATCH && !known)
+}
+
+/// What one cycle did.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct CycleReport {
+	pub(crate) verified: usize,
+	pub(crate) skipped: usize,
+	/// Files found damaged that were not known to be.
+	pub(crate) new_mismatches: Vec<PathBuf>,
+}
+
+/// Re-hashes a weighted sample of this node's files and records each
+/// outcome. `keep_going` is asked before every file, so a cycle stops as
+/// soon as the node gets busy.
+pub(crate) fn run_cycle(
+	db: &Db,
+	settings: &IntegritySettings,
+	mounts: &MountTable,
+	rng: &mut impl Rng,
+	keep_going: impl Fn() -> bool,
+) -> Result<CycleReport> {
+	let mut report = CycleReport::default();
+	let Some(node_id) = db.read(get_your_node)? else {
+		return Ok(report);
+	};
+	let pool = db.read(|conn| candidates(conn, &node_id, CANDIDATE_POOL))?;
+	let sample = pick(pool, settings.files_per_cycle as usize, Utc::now(), rng);
+	for candidate in sample {
+		if !keep_going() {
+			break;
+		}
+		let actual = match check_file(&candidate, mounts, settings.max_read_rate) {
+			Ok(actual) => actual,
+			Err(Skipped::Unreadable(err)) => {
+				tracing::warn!(
+					"integrity check of {} skipped, unreadable: {err}",
+					candidate.path.display()
+				);
+				report.skipped += 1;
+				continue;
+			}
+			Err(skipped) => {
+				tracing::debug!(
+					"integrity check of {} skipped: {skipped:?}",
+					candidate.path.display()
+				);
+				report.skipped += 1;
+				continue;
+			}
+		};
+		let new_mismatch = db.write(|conn| {
+			record_check(
+				conn,
+				&node_id,
+				&candidate.path,
+				&candidate.hash,
+				&actual,
+				Utc::now(),
+			)
+		})?;
+		report.verified += 1;
+		if new_mismatch {
+			tracing::warn!(
+				"{} no longer matches its indexed hash",
+				candidate.path.display()
+			);
+			report.new_mismatches.push(candidate.path);
+		}
+	}
+	Ok(report)
+}
+
+/// Coverage of `node_id`'s live files as of `now`. Checks made against a
+/// hash the file has since been rescanned away from don't count.
+pub(crate) fn integrity_status(
+	conn: &Connection,
+	node_id: &NodeID,
+	now: DateTime<Utc>,
+) -> Result<IntegrityStatus> {
+	let since = (now - chrono::Duration::days(COVERAGE_WINDOW_DAYS)).timestamp();
+	let status = conn.query_row(
+		"SELECT count(*), coalesce(sum(fl.size), 0),
+			coalesce(sum(ic.status = ?2 AND ic.last_verified_at >= ?3), 0),
+			coalesce(sum(CASE WHEN ic.status = ?2 AND ic.last_verified_at >= ?3
+				THEN fl.size ELSE 0 END), 0),
+			coalesce(sum(ic.status = ?4), 0)
+		FROM file_locations fl
+		LEFT JOIN integrity_checks ic
+			ON ic.node_id = fl.node_id AND ic.path = fl.path AND ic.expected = fl.hash
+		WHERE fl.node_id = ?1 AND fl.deleted_at IS NULL AND fl.hash IS NOT NULL",
+		params![node_id.as_slice(), STATUS_OK, since, STATUS_MISMATCH],
+		|row| {
+			let count = |idx| row.get::<_, i64>(idx).map(|value| value.max(0) as u64);
+			Ok(IntegrityStatus {
+				enabled: false,
+				indexed_files: count(0)?,
+				indexed_bytes: count(1)?,
+				verified_files: count(2)?,
+				verified_bytes: count(3)?,
+				mismatches: count(4)?,
+			})
+		},
+	)?;
+	Ok(status)
+}
+
+fn replicas(
+	conn: &Connection,
+	node_id: &NodeID,
+	path: &Path,
+	hash: &[u8],
+) -> Result<Vec<IntegrityReplica>> {
+	let mut stmt = conn.prepare(
+		"SELECT fl.node_id, coalesce(n.name, ''), fl.path
+		FROM file_locations fl
+		LEFT JOIN nodes n ON n.id = fl.node_id
+		WHERE fl.hash = ?1 AND fl.deleted_at IS NULL
+			AND NOT (fl.node_id = ?2 AND fl.path = ?3)
+			AND NOT EXISTS (
+				SELECT 1 FROM integrity_checks ic
+				WHERE ic.node_id = fl.node_id AND ic.path = fl.path
+					AND ic.expected = fl.hash AND ic.status = ?4
+			)
+		ORDER BY fl.node_id = ?2 DESC, n.name, fl.path
+		LIMIT ?5",
+	)?;
+	let rows = stmt.query_map(
+		params![
+			hash,
+			node_id.as_slice(),
+			path_to_sql(path),
+			STATUS_MISMATCH,
+			MAX_REPLICAS as i64,
+		],
+		|row| {
+			let node_id: Vec<u8> = row.get(0)?;
+			Ok((node_id, row.get::<_, String>(1)?, path_column(row, 2)?))
+		},
+	)?;
+	let mut replicas = Vec::new();
+	for row in rows {
+		let (node_id, node_name, path) = row?;
+		let Ok(node_id) = NodeID::try_from(node_id.as_slice()) else {
+			continue;
+		};
+		replicas.push(IntegrityReplica {
+			node_id,
+			node_name,
+			path,
+		});
+	}
+	Ok(replicas)
+}
+
+/// Live files of `node_id` whose last check found different content,
+/// newest first, each with the copies it can be recovered from.
+pub(crate) fn integrity_findings(
+	conn: &Connection,
+	node_id: &NodeID,
+) -> Result<Vec<IntegrityFinding>> {
+	let mut stmt = conn.prepare(
+		"SELECT ic.path, ic.expected, ic.actual, ic.last_verified_at
+		FROM integrity_checks ic
+		JOIN file_locations fl ON fl.node_id = ic.node_id AND fl.path = ic.path
+			AND fl.hash = ic.expected AND fl.deleted_at IS NULL
+		WHERE ic.node_id = ?1 AND ic.status = ?2
+		ORDER BY ic.last_verified_at DESC, ic.path",
+	)?;
+	let rows = stmt.query_map(params![node_id.as_slice(), STATUS_MISMATCH], |row| {
+		Ok((
+			path_column(row, 0)?,
+			row.get::<_, Vec<u8>>(1)?,
+			row.get::<_, Option<Vec<u8>>>(2)?,
+			row.get::<_, i64>(3)?,
+		))
+	})?;
+	let mut findings = Vec::new();
+	for row in rows {
+		let (path, expected, actual, checked_at) = row?;
+		findings.push(IntegrityFinding {
+			replicas: replicas(conn, node_id, &path, &expected)?,
+			path,
+			expected,
+			actual: actual.unwrap_or_default(),
+			checked_at: DateTime::from_timestamp(checked_at, 0).unwrap_or_default(),
+		});
+	}
+	Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::scan::hash_file;
+	use crate::test_util::{self, test_dir};
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+	use std::fs;
+
+	const ME: NodeID = [1; 16];
+	const LAPTOP: NodeID = [2; 16];
+
+	fn open_db() -> Db {
+		let conn = test_util::memory_db();
+		conn.execute(
+			"INSERT INTO nodes (id, name, you, total_memory, system_name, kernel_version,
+				os_version, created_at, modified_at, accessed_at)
+			VALUES (?1, 'desk', 1, 0, '', '', '', 0, 0, 0), (?2, 'laptop', 0, 0, '', '', '', 0, 0, 0)",
+			params![ME.as_slice(), LAPTOP.as_slice()],
+		)
+		.unwrap();
+		Db::single(conn)
+	}
+
+	fn index(db: &Db, node_id: &NodeID, path: &Path, hash: &[u8], size: u64) {
+		let modified_at = fs::metadata(path)
+			.ok()
+			.and_then(|metadata| metadata.modified().ok())
+			.map(DateTime::<Utc>::from);
+		db.write(|conn| {
+			conn.execute(
+				"INSERT INTO file_locations (node_id, path, hash, size, timestamp, modified_at)
+				VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+				params![
+					node_id.as_slice(),
+					path_to_sql(path),
+					hash,
+					size as i64,
+					Utc::now(),
+					modified_at,
+				],
+			)
+			.unwrap();
+		});
+	}
+
+	fn candidate(name: &str, last_verified_at: Option<DateTime<Utc>>) -> Candidate {
+		Candidate {
+			path: PathBuf::from(format!("/photos/{name}.jpg")),
+			hash: vec![0; 32],
+			size: 4 * 1024 * 1024,
+			modified_at: None,
+			last_verified_at,
+		}
+	}
+
+	#[test]
+	fn weighting_prefers_never_verified_files() {
+		let now = Utc::now();
+		let yesterday = Some(now - chrono::Duration::days(1));
+		let mut candidates = (0..50)
+			.map(|i| candidate(&format!("checked-{i}"), yesterday))
+			.collect::<Vec<_>>();
+		candidates.extend((0..50).map(|i| candidate(&format!("new-{i}"), None)));
+		assert!(weight(&candidates[50], now) > weight(&candidates[0], now) * 100.0);
+
+		let mut rng = StdRng::seed_from_u64(7);
+		let sample = pick(candidates, 10, now, &mut rng);
+		assert_eq!(sample.len(), 10);
+		let never_verified = sample
+			.iter()
+			.filter(|candidate| candidate.last_verified_at.is_none())
+			.count();
+		assert!(never_verified >= 9, "{never_verified} of 10 never verified");
+	}
+
+	#[test]
+	fn larger_and_older_files_weigh_more() {
+		let now = Utc::now();
+		let small = candidate("small", None);
+		let large = Candidate {
+			size: 4 * 1024 * 1024 * 1024,
+			..small.clone()
+		};
+		let old = Candidate {
+			modified_at: Some(now - chrono::Duration::days(5 * 365)),
+			..small.clone()
+		};
+		assert!(weight(&large, now) > weight(&small, now));
+		assert!(weight(&old, now) > weight(&small, now));
+	}
+
+	#[test]
+	fn a_corrupted_file_is_caught_and_reported_with_its_replicas() {
+		let dir = test_dir("integrity");
+		let path = dir.join("photo.jpg");
+		let original = b"the original photo".repeat(100);
+		fs::write(&path, &original).unwrap();
+		let hash = hash_file(original.as_slice()).unwrap();
+		let db = open_db();
+		index(&db, &ME, &path, &hash, original.len() as u64);
+		index(
+			&db,
+			&LAPTOP,
+			Path::new("/home/me/photo.jpg"),
+			&hash,
+			original.len() as u64,
+		);
+		let settings = IntegritySettings::default();
+		let mut rng = StdRng::seed_from_u64(1);
+
+		let report = run_cycle(&db, &settings, &MountTable::default(), &mut rng, || true).unwrap();
+		assert_eq!(report.verified, 1);
+		assert!(report.new_mismatches.is_empty());
+
+		// Flip one byte the way bit rot would: same size, same timestamp.
+		let modified = fs::metadata(&path).unwrap().modified().unwrap();
+		let mut damaged = original.clone();
+		damaged[10] ^= 0x01;
+		fs::write(&path, &damaged).unwrap();
+		File::options()
+			.write(true)
+			.open(&path)
+			.unwrap()
+			.set_modified(modified)
+			.unwrap();
+
+		let report = run_cycle(&db, &settings, &MountTable::default(), &mut rng, || true).unwrap();
+		assert_eq!(report.new_mismatches, vec![path.clone()]);
+		// Known damage is not reported as new again.
+		let report = run_cycle(&db, &settings, &MountTable::default(), &mut rng, || true).unwrap();
+		assert_eq!(report.verified, 1);
+		assert!(report.new_mismatches.is_empty());
+
+		let (status, findings) = db.read(|conn| {
+			(
+				integrity_status(conn, &ME, Utc::now()).unwrap(),
+				integrity_findings(conn, &ME).unwrap(),
+			)
+		});
+		assert_eq!(status.mismatches, 1);
+		assert_eq!(status.verified_files, 0);
+		assert_eq!(findings.len(), 1);
+		assert_eq!(findings[0].path, path);
+		assert_eq!(findings[0].expected, hash.to_vec());
+		assert_eq!(
+			findings[0].actual,
+			hash_file(damaged.as_slice()).unwrap().to_vec()
+		);
+		assert_eq!(
+			findings[0].replicas,
+			vec![IntegrityReplica {
+				node_id: LAPTOP,
+				node_name: String::from("laptop"),
+				path: PathBuf::from("/home/me/photo.jpg"),
+			}]
+		);
+		let _ = fs::remove_dir_all(dir);
+	}
+
+	#[test]
+	fn missing_and_edited_files_are_skipped_without_an_outcome() {
+		let dir = test_dir("integrity-skip");
+		let edited = dir.join("edited.txt");
+		fs::write(&edited, b"first draft").unwrap();
+		let db = open_db();
+		index(&db, &ME, &edited, &[7; 32], 11);
+		index(&db, &ME, &dir.join("gone.txt"), &[8; 32], 4);
+		fs::write(&edited, b"second draft, longer").unwrap();
+		let settings = IntegritySettings {
+			files_per_cycle: 10,
+			..Default::default()
+		};
+		let mut rng = StdRng::seed_from_u64(3);
+
+		let report = run_cycle(&db, &settings, &MountTable::default(), &mut rng, || true).unwrap();
+		assert_eq!(report.verified, 0);
+		assert_eq!(report.skipped, 2);
+		let status = db.read(|conn| integrity_status(conn, &ME, Utc::now()).unwrap());
+		assert_eq!(
+			status,
+			IntegrityStatus {
+				enabled: false,
+				indexed_files: 2,
+				indexed_bytes: 15,
+				verified_files: 0,
+				verified_bytes: 0,
+				mismatches: 0,
+			}
+		);
+		let _ = fs::remove_dir_all(dir);
+	}
+
+	#[test]
+	fn the_summary_reports_coverage_and_mismatches() {
+		let status = IntegrityStatus {
+			enabled: true,
+			indexed_files: 10,
+			indexed_bytes: 1000,
+			verified_files: 3,
+			verified_bytes: 319,
+			mismatches: 0,
+		};
+		assert_eq!(
+			status.summary(),
+			"31% of indexed bytes verified in the last 90 days, 0 mismatches"
+		);
+		assert!(
+			IntegritySettings {
+				files_per_cycle: 0,
+				..Default::default()
+			}
+			.validate()
+			.is_err()
+		);
+	}
+}
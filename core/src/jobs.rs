@@ -0,0 +1,416 @@
+use crate::format::{SizeUnits, human_rate};
+use crate::throughput::Throughput;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, Instant};
+
+/// Finished jobs kept for the jobs panel; older ones are dropped first.
+pub(crate) const JOB_HISTORY_LIMIT: usize = 20;
+/// Progress lines kept per job.
+const JOB_LOG_LIMIT: usize = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum JobProgress {
+	Indeterminate,
+	Determinate { done: u64, total: u64 },
+}
+
+impl JobProgress {
+	/// Completion in percent, `None` when the total is unknown.
+	pub(crate) fn percent(&self) -> Option<u8> {
+		match *self {
+			Self::Indeterminate => None,
+			Self::Determinate { total: 0, .. } => Some(0),
+			Self::Determinate { done, total } => Some((done.min(total) * 100 / total) as u8),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum JobStatus {
+	Running,
+	Succeeded,
+	Failed(String),
+	Cancelled,
+}
+
+/// A long-running operation shown in the jobs panel.
+#[derive(Clone, Debug)]
+pub(crate) struct Job {
+	pub id: u64,
+	pub label: String,
+	pub progress: JobProgress,
+	pub status: JobStatus,
+	/// Latest progress or result line.
+	pub detail: String,
+	pub log: Vec<String>,
+	/// Rate and time left from the latest progress, if it had them.
+	pub throughput: Throughput,
+	pub progressed_at: Instant,
+	pub started_at: Instant,
+	pub finished_at: Option<Instant>,
+	cancel: Option<Arc<AtomicBool>>,
+}
+
+impl Job {
+	pub(crate) fn is_active(&self) -> bool {
+		self.status == JobStatus::Running
+	}
+
+	pub(crate) fn can_cancel(&self) -> bool {
+		self.is_active() && self.cancel.is_some()
+	}
+
+	pub(crate) fn elapsed(&self) -> Duration {
+		self.finished_at
+			.unwrap_or_else(Instant::now)
+			.duration_since(self.started_at)
+	}
+
+	/// `3.20 MiB/s, 1m 05s left` while running, or `stalled` once the job
+	/// went quiet for the stall period.
+	pub(crate) fn rate(&self) -> Option<String> {
+		if !self.is_active() {
+			return None;
+		}
+		let throughput = self.throughput.after_quiet(self.progressed_at.elapsed());
+		human_rate(
+			throughput.bytes_per_sec,
+			throughput.eta_secs,
+			SizeUnits::Binary,
+		)
+	}
+
+	fn push_log(&mut self, line: String) {
+		if line.is_empty() || self.log.last() == Some(&line) {
+			return;
+		}
+		if self.log.len() == JOB_LOG_LIMIT {
+			self.log.remove(0);
+		}
+		self.log.push(line);
+	}
+}
+
+pub(crate) enum JobMessage {
+	Progressed {
+		id: u64,
+		progress: JobProgress,
+		throughput: Throughput,
+		detail: String,
+	},
+	Finished {
+		id: u64,
+		result: Result<String, String>,
+	},
+}
+
+impl JobMessage {
+	fn id(&self) -> u64 {
+		match self {
+			Self::Progressed { id, .. } | Self::Finished { id, .. } => *id,
+		}
+	}
+}
+
+/// Handed to the code doing the work; reports into the manager's channel.
+#[derive(Clone)]
+pub(crate) struct JobReporter {
+	id: u64,
+	tx: mpsc::Sender<JobMessage>,
+}
+
+impl JobReporter {
+	pub(crate) fn id(&self) -> u64 {
+		self.id
+	}
+
+	pub(crate) fn progress(&self, progress: JobProgress, detail: impl Into<String>) {
+		self.progress_at(progress, Throughput::default(), detail);
+	}
+
+	/// Progress of work that measures its rate, like a scan or download.
+	pub(crate) fn progress_at(
+		&self,
+		progress: JobProgress,
+		throughput: Throughput,
+		detail: impl Into<String>,
+	) {
+		let _ = self.tx.send(JobMessage::Progressed {
+			id: self.id,
+			progress,
+			throughput,
+			detail: detail.into(),
+		});
+	}
+
+	pub(crate) fn finish(&self, result: Result<String, String>) {
+		let _ = self.tx.send(JobMessage::Finished {
+			id: self.id,
+			result,
+		});
+	}
+}
+
+/// Registry of long-running UI operations. Every job reports through one
+/// channel that a single dispatcher thread drains, so jobs outlive the page
+/// that started them and new kinds of work only translate their own events.
+pub(crate) struct JobManager {
+	jobs: Mutex<Vec<Job>>,
+	tx: mpsc::Sender<JobMessage>,
+	rx: Mutex<Option<mpsc::Receiver<JobMessage>>>,
+}
+
+impl JobManager {
+	pub(crate) fn new() -> Self {
+		let (tx, rx) = mpsc::channel();
+		Self {
+			jobs: Mutex::new(Vec::new()),
+			tx,
+			rx: Mutex::new(Some(rx)),
+		}
+	}
+
+	/// Registers a running job. A `cancel` flag makes the job cancellable.
+	pub(crate) fn start(
+		&self,
+		id: u64,
+		label: impl Into<String>,
+		cancel: Option<Arc<AtomicBool>>,
+	) -> JobReporter {
+		self.jobs.lock().unwrap().push(Job {
+			id,
+			label: label.into(),
+			progress: JobProgress::Indeterminate,
+			status: JobStatus::Running,
+			detail: String::from("Starting"),
+			log: Vec::new(),
+			throughput: Throughput::default(),
+			progressed_at: Instant::now(),
+			started_at: Instant::now(),
+			finished_at: None,
+			cancel,
+		});
+		JobReporter {
+			id,
+			tx: self.tx.clone(),
+		}
+	}
+
+	/// Requests cancellation; the job finishes once the work notices.
+	pub(crate) fn cancel(&self, id: u64) -> bool {
+		let jobs = self.jobs.lock().unwrap();
+		let Some(flag) = jobs
+			.iter()
+			.find(|job| job.id == id && job.is_active())
+			.and_then(|job| job.cancel.as_ref())
+		else {
+			return false;
+		};
+		flag.store(true, Ordering::SeqCst);
+		true
+	}
+
+	pub(crate) fn job(&self, id: u64) -> Option<Job> {
+		self.jobs
+			.lock()
+			.unwrap()
+			.iter()
+			.find(|job| job.id == id)
+			.cloned()
+	}
+
+	/// Active jobs first, then finished ones, newest first within each group.
+	pub(crate) fn jobs(&self) -> Vec<Job> {
+		let mut jobs = self.jobs.lock().unwrap().clone();
+		jobs.sort_by(|a, b| {
+			b.is_active()
+				.cmp(&a.is_active())
+				.then(b.started_at.cmp(&a.started_at))
+		});
+		jobs
+	}
+
+	pub(crate) fn active_count(&self) -> usize {
+		self.jobs
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|job| job.is_active())
+			.count()
+	}
+
+	fn apply(&self, message: JobMessage) {
+		let mut jobs = self.jobs.lock().unwrap();
+		let id = message.id();
+		let Some(job) = jobs.iter_mut().find(|job| job.id == id && job.is_active()) else {
+			return;
+		};
+		match message {
+			JobMessage::Progressed {
+				progress,
+				throughput,
+				detail,
+				..
+			} => {
+				job.progress = progress;
+				job.throughput = throughput;
+				job.progressed_at = Instant::now();
+				job.push_log(detail.clone());
+				job.detail = detail;
+			}
+			JobMessage::Finished { result, .. } => {
+				let cancelled = job
+					.cancel
+					.as_ref()
+					.is_some_and(|flag| flag.load(Ordering::SeqCst));
+				job.finished_at = Some(Instant::now());
+				match result {
+					Ok(detail) => {
+						job.status = JobStatus::Succeeded;
+						job.push_log(detail.clone());
+						job.detail = detail;
+					}
+					Err(_) if cancelled => {
+						job.status = JobStatus::Cancelled;
+						job.detail = String::from("Cancelled");
+					}
+					Err(err) => {
+						job.push_log(err.clone());
+						job.detail = err.clone();
+						job.status = JobStatus::Failed(err);
+					}
+				}
+				let mut finished = jobs
+					.iter()
+					.filter(|job| !job.is_active())
+					.map(|job| (job.finished_at, job.id))
+					.collect::<Vec<_>>();
+				if finished.len() > JOB_HISTORY_LIMIT {
+					finished.sort();
+					let expired = finished[..finished.len() - JOB_HISTORY_LIMIT]
+						.iter()
+						.map(|(_, id)| *id)
+						.collect::<Vec<_>>();
+					jobs.retain(|job| !expired.contains(&job.id));
+				}
+			}
+		}
+	}
+
+	/// Starts the dispatcher thread. `on_change` runs after every applied
+	/// message, at most every `throttle` per job except for final messages.
+	pub(crate) fn run_dispatcher(
+		self: &Arc<Self>,
+		throttle: Duration,
+		on_change: impl Fn(u64) + Send + 'static,
+	) {
+		let Some(rx) = self.rx.lock().unwrap().take() else {
+			return;
+		};
+		let manager = Arc::clone(self);
+		std::thread::spawn(move || {
+			let mut last_notified: Vec<(u64, Instant)> = Vec::new();
+			while let Ok(message) = rx.recv() {
+				let id = message.id();
+				let finished = matches!(message, JobMessage::Finished { .. });
+				manager.apply(message);
+				let now = Instant::now();
+				let due = match last_notified.iter_mut().find(|(job, _)| *job == id) {
+					Some((_, at)) if !finished && now.duration_since(*at) < throttle => false,
+					Some((_, at)) => {
+						*at = now;
+						true
+					}
+					None => {
+						last_notified.push((id, now));
+						true
+					}
+				};
+				if finished {
+					last_notified.retain(|(job, _)| *job != id);
+				}
+				if due {
+					on_change(id);
+				}
+			}
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn drain(manager: &JobManager) {
+		let rx = manager.rx.lock().unwrap().take().unwrap();
+		while let Ok(message) = rx.try_recv() {
+			manager.apply(message);
+		}
+		*manager.rx.lock().unwrap() = Some(rx);
+	}
+
+	#[test]
+	fn jobs_report_progress_and_cancellation() {
+		let manager = JobManager::new();
+		let cancel = Arc::new(AtomicBool::new(false));
+		let scan = manager.start(1, "Scan /data", Some(Arc::clone(&cancel)));
+		let update = manager.start(2, "Update peer", None);
+
+		scan.progress(
+			JobProgress::Determinate { done: 5, total: 20 },
+			"5/20 files",
+		);
+		update.progress(JobProgress::Indeterminate, "Downloading");
+		drain(&manager);
+
+		assert_eq!(manager.job(1).unwrap().progress.percent(), Some(25));
+		assert_eq!(manager.job(1).unwrap().rate(), None);
+		update.progress_at(
+			JobProgress::Indeterminate,
+			Throughput {
+				bytes_per_sec: Some(2048),
+				eta_secs: Some(65),
+			},
+			"Downloading",
+		);
+		drain(&manager);
+		assert_eq!(
+			manager.job(2).unwrap().rate().as_deref(),
+			Some("2.00 KiB/s, 1m 05s left")
+		);
+		assert!(!manager.cancel(2));
+		assert!(manager.cancel(1));
+		assert!(cancel.load(Ordering::SeqCst));
+
+		scan.finish(Err(String::from("Scan cancelled")));
+		update.finish(Err(String::from("checksum mismatch")));
+		drain(&manager);
+
+		assert_eq!(manager.job(1).unwrap().status, JobStatus::Cancelled);
+		assert_eq!(
+			manager.job(2).unwrap().status,
+			JobStatus::Failed(String::from("checksum mismatch"))
+		);
+		assert_eq!(manager.active_count(), 0);
+	}
+
+	#[test]
+	fn finished_history_is_capped() {
+		let manager = JobManager::new();
+		let running = manager.start(0, "Running", None);
+		for id in 1..=(JOB_HISTORY_LIMIT as u64 + 5) {
+			manager
+				.start(id, format!("Job {id}"), None)
+				.finish(Ok(String::new()));
+			drain(&manager);
+		}
+		running.progress(JobProgress::Indeterminate, "still going");
+		drain(&manager);
+
+		let jobs = manager.jobs();
+		assert_eq!(jobs.len(), JOB_HISTORY_LIMIT + 1);
+		assert_eq!(jobs[0].id, 0);
+		assert!(manager.job(1).is_none());
+	}
+}
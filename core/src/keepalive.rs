@@ -0,0 +1,285 @@
+//! How long connections live. The swarm closes a connection once it has
+//! carried no request for the idle timeout; pings keep NAT mappings open and
+//! notice dead links but don't count as use. Connections to peers this node
+//! shares with get a cheap request every half timeout, so they last through
+//! quiet periods instead of closing and being redialed on next use. Peers
+//! marked important are redialed with backoff whenever their last
+//! connection drops. Reconnects and closes are counted per peer for the
+//! health check, so churn shows up as a number.
+
+use crate::config;
+use crate::db::load_setting;
+use crate::state::DisconnectReason;
+use libp2p::PeerId;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+pub(crate) const IDLE_CONNECTION_TIMEOUT_SETTING: &str = "idle_connection_timeout_secs";
+pub(crate) const DEFAULT_IDLE_CONNECTION_TIMEOUT_SECS: u64 = 10 * 60;
+pub(crate) const PING_INTERVAL_SETTING: &str = "ping_interval_secs";
+pub(crate) const DEFAULT_PING_INTERVAL_SECS: u64 = 15;
+/// JSON list of the ids of the peers kept connected.
+pub(crate) const IMPORTANT_PEERS_SETTING: &str = "important_peers";
+/// How often due redials of important peers are looked for.
+pub(crate) const REDIAL_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const MIN_REDIAL_DELAY: Duration = Duration::from_secs(5);
+const MAX_REDIAL_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Timeouts the swarm is built with. Changes take effect on restart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionPolicy {
+	/// Seconds a connection may carry no request before it closes.
+	pub idle_timeout_secs: u64,
+	/// Seconds between pings on every connection.
+	pub ping_interval_secs: u64,
+}
+
+impl Default for ConnectionPolicy {
+	fn default() -> Self {
+		Self {
+			idle_timeout_secs: DEFAULT_IDLE_CONNECTION_TIMEOUT_SECS,
+			ping_interval_secs: DEFAULT_PING_INTERVAL_SECS,
+		}
+	}
+}
+
+fn secs_from_setting(conn: &Connection, setting: &str, fallback: u64) -> u64 {
+	match load_setting(conn, setting) {
+		Ok(value) => value
+			.and_then(|value| value.trim().parse::<u64>().ok())
+			.filter(|secs| *secs > 0)
+			.unwrap_or(fallback),
+		Err(err) => {
+			tracing::error!("failed to load {setting}: {err}");
+			fallback
+		}
+	}
+}
+
+impl ConnectionPolicy {
+	/// The policy in `conn`'s settings, over the startup configuration.
+	pub(crate) fn load(conn: &Connection) -> Self {
+		let startup = config::startup();
+		Self {
+			idle_timeout_secs: secs_from_setting(
+				conn,
+				IDLE_CONNECTION_TIMEOUT_SETTING,
+				startup.idle_connection_timeout_secs,
+			),
+			ping_interval_secs: secs_from_setting(
+				conn,
+				PING_INTERVAL_SETTING,
+				startup.ping_interval_secs,
+			),
+		}
+	}
+
+	pub fn idle_timeout(&self) -> Duration {
+		Duration::from_secs(self.idle_timeout_secs.max(1))
+	}
+
+	pub fn ping_interval(&self) -> Duration {
+		Duration::from_secs(self.ping_interval_secs.max(1))
+	}
+
+	/// How often connections worth keeping get a request: half the idle
+	/// timeout, so one late tick doesn't let them lapse.
+	pub(crate) fn keepalive_interval(&self) -> Duration {
+		Duration::from_secs((self.idle_timeout_secs / 2).max(1))
+	}
+}
+
+/// The important peers stored in `conn`. Ids that don't parse are skipped.
+pub(crate) fn load_important_peers(conn: &Connection) -> HashSet<PeerId> {
+	let stored = match load_setting(conn, IMPORTANT_PEERS_SETTING) {
+		Ok(stored) => stored,
+		Err(err) => {
+			tracing::error!("failed to load important peers: {err}");
+			None
+		}
+	};
+	stored
+		.and_then(|json| serde_json::from_str::<Vec<String>>(&json).ok())
+		.unwrap_or_default()
+		.iter()
+		.filter_map(|peer| peer.parse().ok())
+		.collect()
+}
+
+pub(crate) fn important_peers_json(peers: &HashSet<PeerId>) -> String {
+	let mut ids = peers.iter().map(PeerId::to_string).collect::<Vec<_>>();
+	ids.sort();
+	serde_json::to_string(&ids).unwrap_or_else(|_| String::from("[]"))
+}
+
+/// Connection churn with one peer since this node started.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerChurn {
+	pub peer: String,
+	/// Connections established after an earlier one closed.
+	pub reconnects: u64,
+	/// Connections closed after carrying no requests for the idle timeout.
+	pub idle_closes: u64,
+	/// Connections lost to errors or closed by the peer.
+	pub lost: u64,
+	/// Connections this node closed on purpose.
+	pub closed: u64,
+}
+
+/// Delay before redial number `attempts`, doubling up to a cap.
+fn redial_delay(attempts: u32) -> Duration {
+	MIN_REDIAL_DELAY
+		.saturating_mul(1 << attempts.min(16))
+		.min(MAX_REDIAL_DELAY)
+}
+
+struct Redial {
+	attempts: u32,
+	due: Instant,
+}
+
+/// Churn counts and pending redials, owned by the swarm loop.
+#[derive(Default)]
+pub(crate) struct ConnectionKeeper {
+	churn: HashMap<PeerId, PeerChurn>,
+	/// Peers whose last connection closed, so the next one is a reconnect.
+	disconnected: HashSet<PeerId>,
+	redials: HashMap<PeerId, Redial>,
+}
+
+impl ConnectionKeeper {
+	fn churn_mut(&mut self, peer: PeerId) -> &mut PeerChurn {
+		self.churn.entry(peer).or_insert_with(|| PeerChurn {
+			peer: peer.to_string(),
+			..PeerChurn::default()
+		})
+	}
+
+	pub(crate) fn connected(&mut self, peer: PeerId) {
+		self.redials.remove(&peer);
+		if self.disconnected.remove(&peer) {
+			self.churn_mut(peer).reconnects += 1;
+		}
+	}
+
+	/// Counts a closed connection. Once the last one to an important peer
+	/// is gone, other than by this node closing it, a redial is scheduled.
+	pub(crate) fn disconnected(
+		&mut self,
+		peer: PeerId,
+		reason: DisconnectReason,
+		still_connected: bool,
+		important: bool,
+		now: Instant,
+	) {
+		let churn = self.churn_mut(peer);
+		match reason {
+			DisconnectReason::Idle => churn.idle_closes += 1,
+			DisconnectReason::Lost => churn.lost += 1,
+			DisconnectReason::Deliberate => churn.closed += 1,
+		}
+		if still_connected {
+			return;
+		}
+		self.disconnected.insert(peer);
+		if important && reason != DisconnectReason::Deliberate {
+			self.schedule_redial(peer, now);
+		}
+	}
+
+	/// Dials `peer` at the next redial check, unless one is already due.
+	pub(crate) fn redial_soon(&mut self, peer: PeerId, now: Instant) {
+		self.redials.entry(peer).or_insert(Redial {
+			attempts: 0,
+			due: now,
+		});
+	}
+
+	fn schedule_redial(&mut self, peer: PeerId, now: Instant) {
+		let attempts = self.redials.get(&peer).map_or(0, |redial| redial.attempts);
+		self.redials.insert(
+			peer,
+			Redial {
+				attempts: attempts + 1,
+				due: now + redial_delay(attempts),
+			},
+		);
+	}
+
+	pub(crate) fn forget_redial(&mut self, peer: &PeerId) {
+		self.redials.remove(peer);
+	}
+
+	/// Peers whose redial is due. Each is pushed back by the next delay in
+	/// case this attempt fails; connecting clears it.
+	pub(crate) fn due_redials(&mut self, now: Instant) -> Vec<PeerId> {
+		let mut due = Vec::new();
+		for (peer, redial) in &mut self.redials {
+			if redial.due <= now {
+				due.push(*peer);
+				redial.due = now + redial_delay(redial.attempts);
+				redial.attempts += 1;
+			}
+		}
+		due.sort();
+		due
+	}
+
+	/// Churn per peer, by peer id.
+	pub(crate) fn churn(&self) -> Vec<PeerChurn> {
+		let mut churn = self.churn.values().cloned().collect::<Vec<_>>();
+		churn.sort_by(|a, b| a.peer.cmp(&b.peer));
+		churn
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn important_peers_are_redialed_with_backoff_until_they_connect() {
+		let mut keeper = ConnectionKeeper::default();
+		let peer = PeerId::random();
+		let start = Instant::now();
+
+		keeper.connected(peer);
+		keeper.disconnected(peer, DisconnectReason::Lost, false, true, start);
+		assert!(keeper.due_redials(start).is_empty());
+		let first = start + MIN_REDIAL_DELAY;
+		assert_eq!(keeper.due_redials(first), vec![peer]);
+		// The dial failed: the next one waits twice as long.
+		assert!(keeper.due_redials(first + MIN_REDIAL_DELAY).is_empty());
+		assert_eq!(keeper.due_redials(first + MIN_REDIAL_DELAY * 2), vec![peer]);
+
+		keeper.connected(peer);
+		assert!(keeper.due_redials(first + MAX_REDIAL_DELAY).is_empty());
+		let churn = keeper.churn();
+		assert_eq!(churn[0].reconnects, 1);
+		assert_eq!(churn[0].lost, 1);
+	}
+
+	#[test]
+	fn only_dropped_important_peers_are_redialed() {
+		let mut keeper = ConnectionKeeper::default();
+		let important = PeerId::random();
+		let other = PeerId::random();
+		let now = Instant::now();
+
+		keeper.disconnected(important, DisconnectReason::Lost, true, true, now);
+		keeper.disconnected(important, DisconnectReason::Deliberate, false, true, now);
+		keeper.disconnected(other, DisconnectReason::Idle, false, false, now);
+		assert!(keeper.due_redials(now + MAX_REDIAL_DELAY).is_empty());
+
+		let counts = keeper
+			.churn()
+			.into_iter()
+			.map(|churn| (churn.peer.clone(), churn))
+			.collect::<HashMap<_, _>>();
+		assert_eq!(counts[&important.to_string()].closed, 1);
+		assert_eq!(counts[&important.to_string()].lost, 1);
+		assert_eq!(counts[&other.to_string()].idle_closes, 1);
+	}
+}
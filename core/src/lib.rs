@@ -1,26 +1,231 @@
+mod access_volume;
+mod activity_log;
+pub mod activity_window;
 mod app;
 mod audio;
 pub mod auth;
+mod backup;
+mod checksum_manifest;
+pub mod client;
+mod clock;
+mod clock_skew;
+pub mod config;
+mod config_snapshot;
+mod contact_sheet;
+mod content_negotiation;
+mod content_store;
+mod cors;
 #[cfg(target_os = "linux")]
 mod cosmic_capture;
 mod db;
+mod db_pool;
+mod deletion;
+mod demo;
 mod desktop_input;
+mod diagnostics;
+mod dialer;
+mod diff;
+mod discovered;
+mod disk_history;
+mod event_channel;
+mod event_loop;
+mod fan_out;
+mod file_read;
+pub mod format;
+mod free_space;
+mod grant_cache;
+#[cfg(feature = "http-api")]
 pub mod http_api;
+mod http_proxy;
+mod identity;
+mod ids;
+mod image_decode;
+pub mod index;
+mod index_announce;
+mod ingest;
+mod integrity;
+#[cfg(feature = "web-ui")]
+mod jobs;
+mod keepalive;
+mod locations;
+mod login_guard;
+mod maintenance;
+mod media_metadata;
+#[cfg(feature = "web-ui")]
 mod media_webrtc;
+mod mime_hint;
+mod mounts;
+mod nat;
+mod natural_sort;
+#[cfg(feature = "http-api")]
+mod openapi;
+mod outbox;
 pub mod p2p;
+mod pagination;
+mod pairing;
+mod pairing_invite;
+mod password_policy;
+pub mod path;
+mod peer_search;
+mod permission_draft;
+mod pins;
+mod power;
+mod preview;
+mod protocol_stats;
 mod puppynet;
+mod reachability;
+mod readahead;
+mod remote_ops;
+mod replication;
+mod request_trace;
+mod review;
+mod safe_open;
 pub mod scan;
+mod scan_limits;
+mod scan_results;
+mod secrets;
+mod share_summary;
+mod sparse;
 mod state;
+mod storage_growth;
+mod storage_tree;
+#[cfg(test)]
+mod test_util;
+mod throughput;
+mod thumbnail_cache;
+mod thumbnail_pregen;
+mod transfers;
 mod types;
+#[cfg(feature = "web-ui")]
 pub mod ui;
+#[cfg(feature = "web-ui")]
+mod ui_focus;
+#[cfg(feature = "web-ui")]
+mod ui_notifications;
+#[cfg(feature = "web-ui")]
+mod ui_prefs;
+#[cfg(feature = "web-ui")]
+mod ui_terminal;
+#[cfg(feature = "web-ui")]
+mod ui_window;
 pub mod updater;
 mod version;
+mod wake;
 mod webcam;
+mod wire;
+pub use access_volume::{AccessAlert, AccessAlertLimits, AccessVolume};
+pub use activity_log::{
+	ActivityEvent, ActivityEventKind, ActivityFilter, DEFAULT_ACTIVITY_PAGE, MAX_ACTIVITY_PAGE,
+};
+pub use backup::{BackupKind, BackupRun, BackupSettings};
+pub use checksum_manifest::{ChecksumAlgorithm, ChecksumSummary, HashMismatch};
+pub use clock::{Clock, SystemClock};
+pub use clock_skew::{ClockOffset, ClockOffsetRecord, ClockSample};
+pub use config_snapshot::{
+	ConfigRollback, ConfigSnapshotInfo, SnapshotReferencesMissing, UNDO_WINDOW, UndoableChange,
+};
+pub use contact_sheet::{ContactSheet, ContactSheetOptions, ContactSheetResult};
+pub use content_negotiation::{SendSavings, Upload};
+pub use content_store::{ContentRef, StoreGcReport, StoreMode};
+pub use cors::{AllowedOrigins, CorsSettings};
+pub use deletion::{
+	DeleteDecision, DeleteOutcome, DeleteProposal, DeletionStatus, ProposalHolder, ReceivedProposal,
+};
+pub use diagnostics::{Diagnostic, DiagnosticStatus, DiagnosticsReport, run_diagnostics};
+pub use dialer::PeerDialStats;
+pub use diff::{
+	BinaryDiff, DiffHunk, DiffLine, DiffLineKind, DiffOptions, FileDiff, FileRef, TextDiff,
+};
+pub use discovered::{DiscoveredPeerFilter, DiscoveredPeerInfo, DiscoveredPeers};
+pub use disk_history::DiskSample;
+pub use fan_out::{FanOutOptions, FanOutSummary, fan_out};
+pub use file_read::{FileContents, NoProgress, ReadToEndOptions};
+pub use free_space::{WontFit, check_fits, describe_free_hint};
+pub use grant_cache::{DEFAULT_GRANT_CACHE_TTL, RemoteGrants};
+pub use http_proxy::{HttpProxySettings, ProxyCredentials};
+pub use identity::IdentityMismatch;
+pub use ids::{IdAllocator, IdKind};
+pub use image_decode::ImageTooLarge;
+pub use index_announce::{IndexChangeCounts, IndexFreshness};
+pub use ingest::{
+	DriveChange, IngestFileStatus, IngestProgress, IngestReport, IngestSettings, RemovableDrive,
+	describe_drive, drive_label,
+};
+pub use integrity::{
+	COVERAGE_WINDOW_DAYS, IntegrityFinding, IntegrityReplica, IntegritySettings, IntegrityStatus,
+	MAX_FILES_PER_CYCLE, MIN_READ_RATE,
+};
+pub use keepalive::{ConnectionPolicy, PeerChurn};
 pub use libp2p::PeerId;
-pub use state::{FLAG_READ, FLAG_SEARCH, FLAG_WRITE, FolderRule, Permission, Rule, State};
-pub use types::FileChunk;
+pub use locations::{FolderKind, WellKnownFolder};
+pub use login_guard::{FailedLoginGroup, LoginAttempt, LoginLimits, LoginOutcome, LoginSource};
+pub use maintenance::{Maintenance, MaintenanceMode, MaintenanceReport};
+pub use media_metadata::{MediaExtractReport, MediaMetadata, media_duration};
+pub use mounts::{ShareAvailability, ShareChange, ShareUnavailable};
+pub use nat::{NatMethod, NatStatus};
+pub use natural_sort::natural_cmp;
+#[cfg(feature = "http-api")]
+pub use openapi::API_VERSION;
+pub use outbox::{OUTBOX_SENT_FOLDER, OutboxRule, OutboxStatus};
+pub use pagination::{CursorPage, PageCursor};
+pub use pairing::{Pairing, PairingDirection, PairingStatus};
+pub use pairing_invite::{
+	DEFAULT_PAIRING_SECRET_TTL, InvalidPairingString, PairedWith, PairingInfo, PairingInvite,
+	PairingRejected,
+};
+pub use password_policy::{
+	CreateUserError, DEFAULT_PASSWORD_MIN_LENGTH, PasswordPolicy, UserFieldError,
+};
+pub use peer_search::{PEER_SEARCH_TIMEOUT, PeerSearch, PeerSearchHit, SkippedPeer};
+pub use permission_draft::{
+	DraftKind, DraftRejected, FieldError, PermissionDraft, RuleDraft, parse_patterns,
+};
+pub use pins::{PinOptions, PinStatus};
+pub use power::{PowerPolicy, PowerReading, PowerState};
+pub use protocol_stats::{ProtocolCounts, ProtocolDay, ProtocolLimits, ProtocolRate, RateLimited};
+pub use reachability::{AddressReachability, Reachability, port_mapping_worthwhile};
+pub use remote_ops::{RemoteOpStats, RemoteOpsStats, TooManyRemoteOps};
+pub use replication::{
+	IndexDelta, IndexDeltaAck, ReplicatedEntry, ReplicatedLocation, ReplicationRole,
+	ReplicationStatus,
+};
+pub use request_trace::{RequestDirection, RequestTrace};
+pub use review::{PeerTrust, PendingReview, ReviewDecision};
+pub use scan_limits::{MAX_SCAN_READS, ScanLimits, ScanOverrides};
+pub use scan_results::{SCAN_RESULTS_PULL_CAP, ScanResults, ScanResultsPull};
+pub use secrets::{SecretBackend, SecretInfo, SecretStore, Secrets};
+pub use share_summary::{MimeCategory, ShareSummary};
+pub use state::{
+	AccessExplanation, BatchGrantOutcome, Connection, ConnectionDirection, Disconnect,
+	DisconnectReason, Disconnected, DiscoveredPeer, FLAG_PAIRED, FLAG_PREVIEW, FLAG_QUARANTINE,
+	FLAG_READ, FLAG_SEARCH, FLAG_WRITE, FolderRule, FullStateSnapshot, HiddenNames, LapsedAccess,
+	Notification, Permission, PermissionConflict, PermissionSet, Rule, RuleOverlap,
+	SUGGESTED_DENY_PATTERNS, State, TemporaryGrant, TrustLevel, TrustRefusal, pattern_error,
+};
+pub use storage_growth::{DirectoryGrowth, DiskProjection, GrowthReport, PROJECTION_DAYS};
+pub use storage_tree::{
+	NodeStorageTree, STORAGE_ROOT, StorageEntry, StorageExportFormat, StorageTree, StorageTreeRow,
+	TREEMAP_MAX_RECTS, TreemapRect, lay_out_treemap, read_storage_export, trees_by_node,
+	write_storage_export,
+};
+pub use throughput::{RATE_WINDOW, Throughput};
+pub use thumbnail_pregen::{PregenPause, ThumbnailQueueStatus};
+pub use transfers::{
+	DownloadOutcome, Transfer, TransferDirection, TransferProgress, TransferStatus,
+};
+pub use types::{FileChunk, ShellOutput};
+pub use wake::{DidNotWake, WAKE_TIMEOUT, WakeTarget};
 pub mod wait_group;
-pub use db::{FileEntry, FileSearchResult, SearchFilesArgs, StorageUsageFile};
-pub use p2p::Thumbnail;
-pub use puppynet::{LiveSearchPeerEvent, PuppyNet, ScanHandle, ScanResultRow};
-pub use updater::{UpdateProgress, UpdateResult};
+pub use db::{
+	FileEntry, FileOrigin, FileProvenance, FileSearchResult, MAX_SEARCH_PAGE_SIZE, ScanDiffEntry,
+	ScanRun, ScanRunStatus, ScanTrend, SearchFilesArgs, SearchQueryError, SearchSortBy,
+	StorageUsageFile,
+};
+pub use p2p::{PeerHealth, RequestScope, Thumbnail};
+pub use puppynet::{
+	LOGIN_HISTORY_PAGE_SIZE, LiveSearchPeerEvent, LoginResult, PuppyNet, SCAN_HISTORY_PAGE_SIZE,
+	ScanHandle, ScanResultRow, TRANSFER_PAGE_SIZE,
+};
+pub use updater::{
+	UpdateErrorKind, UpdateProgress, UpdateResult, UpdateRetryPolicy, classify_update_error,
+};
@@ -0,0 +1,343 @@
+use crate::p2p::DiskInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FolderKind {
+	Home,
+	Desktop,
+	Documents,
+	Downloads,
+	Pictures,
+	Music,
+	Videos,
+	CloudDrive,
+	Drive,
+}
+
+/// A standard folder on a node, with its path in that node's own syntax.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WellKnownFolder {
+	pub kind: FolderKind,
+	pub label: String,
+	pub path: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Platform {
+	Linux,
+	Windows,
+	MacOs,
+}
+
+impl Platform {
+	fn current() -> Self {
+		if cfg!(target_os = "windows") {
+			Self::Windows
+		} else if cfg!(target_os = "macos") {
+			Self::MacOs
+		} else {
+			Self::Linux
+		}
+	}
+}
+
+/// Everything folder discovery reads from the OS, so tests can supply it.
+pub(crate) struct LocationEnv {
+	pub platform: Platform,
+	pub home: Option<String>,
+	pub vars: HashMap<String, String>,
+	/// Contents of `~/.config/user-dirs.dirs` on Linux.
+	pub xdg_user_dirs: Option<String>,
+}
+
+impl LocationEnv {
+	pub(crate) fn current() -> Self {
+		let platform = Platform::current();
+		let home = homedir::my_home()
+			.ok()
+			.flatten()
+			.map(|home| home.to_string_lossy().into_owned());
+		let vars = std::env::vars().collect::<HashMap<_, _>>();
+		let xdg_user_dirs = (platform == Platform::Linux)
+			.then(|| {
+				let config = vars
+					.get("XDG_CONFIG_HOME")
+					.map(PathBuf::from)
+					.or_else(|| home.as_ref().map(|home| Path::new(home).join(".config")))?;
+				std::fs::read_to_string(config.join("user-dirs.dirs")).ok()
+			})
+			.flatten();
+		Self {
+			platform,
+			home,
+			vars,
+			xdg_user_dirs,
+		}
+	}
+
+	fn join(&self, base: &str, child: &str) -> String {
+		let separator = if self.platform == Platform::Windows {
+			'\\'
+		} else {
+			'/'
+		};
+		if base.ends_with(separator) {
+			format!("{base}{child}")
+		} else {
+			format!("{base}{separator}{child}")
+		}
+	}
+
+	fn home(&self) -> Option<String> {
+		match self.platform {
+			Platform::Windows => self
+				.vars
+				.get("USERPROFILE")
+				.cloned()
+				.or_else(|| self.home.clone()),
+			_ => self.vars.get("HOME").cloned().or_else(|| self.home.clone()),
+		}
+	}
+
+	/// Reads `XDG_<NAME>_DIR`, from the environment first and then from
+	/// `user-dirs.dirs`. `$HOME` is expanded; a dir pointing at home itself
+	/// means the folder is disabled.
+	fn xdg_dir(&self, name: &str, home: &str) -> Option<Option<String>> {
+		let key = format!("XDG_{name}_DIR");
+		let value = self.vars.get(&key).cloned().or_else(|| {
+			self.xdg_user_dirs.as_deref()?.lines().find_map(|line| {
+				let (line_key, value) = line.trim().split_once('=')?;
+				(line_key.trim() == key).then(|| value.trim().trim_matches('"').to_string())
+			})
+		})?;
+		let value = match value.strip_prefix("$HOME") {
+			Some(rest) => format!("{home}{rest}"),
+			None => value,
+		};
+		if !value.starts_with('/') {
+			return None;
+		}
+		let value = value.trim_end_matches('/').to_string();
+		Some((value != home.trim_end_matches('/')).then_some(value))
+	}
+}
+
+fn standard_folders(platform: Platform) -> &'static [(FolderKind, &'static str, &'static str)] {
+	// (kind, label, folder name under home)
+	match platform {
+		Platform::MacOs => &[
+			(FolderKind::Desktop, "Desktop", "Desktop"),
+			(FolderKind::Documents, "Documents", "Documents"),
+			(FolderKind::Downloads, "Downloads", "Downloads"),
+			(FolderKind::Pictures, "Pictures", "Pictures"),
+			(FolderKind::Music, "Music", "Music"),
+			(FolderKind::Videos, "Movies", "Movies"),
+		],
+		Platform::Windows | Platform::Linux => &[
+			(FolderKind::Desktop, "Desktop", "Desktop"),
+			(FolderKind::Documents, "Documents", "Documents"),
+			(FolderKind::Downloads, "Downloads", "Downloads"),
+			(FolderKind::Pictures, "Pictures", "Pictures"),
+			(FolderKind::Music, "Music", "Music"),
+			(FolderKind::Videos, "Videos", "Videos"),
+		],
+	}
+}
+
+fn xdg_key(kind: FolderKind) -> &'static str {
+	match kind {
+		FolderKind::Desktop => "DESKTOP",
+		FolderKind::Documents => "DOCUMENTS",
+		FolderKind::Downloads => "DOWNLOAD",
+		FolderKind::Pictures => "PICTURES",
+		FolderKind::Music => "MUSIC",
+		FolderKind::Videos => "VIDEOS",
+		FolderKind::Home | FolderKind::CloudDrive | FolderKind::Drive => "",
+	}
+}
+
+/// Home, the platform's standard user folders and mounted drives, in that
+/// order. Callers filter out paths that don't exist.
+pub(crate) fn well_known_folders(env: &LocationEnv, disks: &[DiskInfo]) -> Vec<WellKnownFolder> {
+	let mut folders = Vec::new();
+	if let Some(home) = env.home() {
+		folders.push(WellKnownFolder {
+			kind: FolderKind::Home,
+			label: String::from("Home"),
+			path: home.clone(),
+		});
+		for (kind, label, name) in standard_folders(env.platform) {
+			let path = if env.platform == Platform::Linux {
+				match env.xdg_dir(xdg_key(*kind), &home) {
+					Some(Some(path)) => path,
+					Some(None) => continue,
+					None => env.join(&home, name),
+				}
+			} else {
+				env.join(&home, name)
+			};
+			folders.push(WellKnownFolder {
+				kind: *kind,
+				label: label.to_string(),
+				path,
+			});
+		}
+		let cloud = match env.platform {
+			Platform::MacOs => {
+				Some(env.join(&home, "Library/Mobile Documents/com~apple~CloudDocs"))
+			}
+			Platform::Windows => env.vars.get("OneDrive").cloned(),
+			Platform::Linux => None,
+		};
+		if let Some(path) = cloud {
+			folders.push(WellKnownFolder {
+				kind: FolderKind::CloudDrive,
+				label: String::from(if env.platform == Platform::MacOs {
+					"iCloud Drive"
+				} else {
+					"OneDrive"
+				}),
+				path,
+			});
+		}
+	}
+	for disk in disks {
+		if disk.mount_path.is_empty() || folders.iter().any(|folder| folder.path == disk.mount_path)
+		{
+			continue;
+		}
+		folders.push(WellKnownFolder {
+			kind: FolderKind::Drive,
+			label: if disk.name.is_empty() || disk.name == disk.mount_path {
+				disk.mount_path.clone()
+			} else {
+				format!("{} ({})", disk.mount_path, disk.name)
+			},
+			path: disk.mount_path.clone(),
+		});
+	}
+	folders
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn env(platform: Platform, home: &str, vars: &[(&str, &str)]) -> LocationEnv {
+		LocationEnv {
+			platform,
+			home: Some(home.to_string()),
+			vars: vars
+				.iter()
+				.map(|(key, value)| (key.to_string(), value.to_string()))
+				.collect(),
+			xdg_user_dirs: None,
+		}
+	}
+
+	fn disk(name: &str, mount_path: &str) -> DiskInfo {
+		DiskInfo {
+			name: name.to_string(),
+			mount_path: mount_path.to_string(),
+			filesystem: String::new(),
+			total_space: 0,
+			available_space: 0,
+			usage_percent: 0.0,
+			total_read_bytes: 0,
+			total_written_bytes: 0,
+			read_only: false,
+			removable: false,
+			kind: String::new(),
+			id: String::new(),
+		}
+	}
+
+	fn path_of(folders: &[WellKnownFolder], kind: FolderKind) -> Option<&str> {
+		folders
+			.iter()
+			.find(|folder| folder.kind == kind)
+			.map(|folder| folder.path.as_str())
+	}
+
+	#[test]
+	fn linux_folders_follow_xdg_user_dirs() {
+		let mut linux = env(
+			Platform::Linux,
+			"/home/ada",
+			&[("XDG_MUSIC_DIR", "/srv/music")],
+		);
+		linux.xdg_user_dirs = Some(String::from(
+			"# generated\nXDG_DOWNLOAD_DIR=\"$HOME/Lataukset\"\nXDG_DESKTOP_DIR=\"$HOME/\"\n",
+		));
+		let folders = well_known_folders(&linux, &[disk("", "/"), disk("usb", "/media/usb")]);
+
+		assert_eq!(path_of(&folders, FolderKind::Home), Some("/home/ada"));
+		assert_eq!(
+			path_of(&folders, FolderKind::Downloads),
+			Some("/home/ada/Lataukset")
+		);
+		assert_eq!(path_of(&folders, FolderKind::Music), Some("/srv/music"));
+		assert_eq!(
+			path_of(&folders, FolderKind::Documents),
+			Some("/home/ada/Documents")
+		);
+		assert_eq!(path_of(&folders, FolderKind::Desktop), None);
+		let drives = folders
+			.iter()
+			.filter(|folder| folder.kind == FolderKind::Drive)
+			.map(|folder| folder.label.as_str())
+			.collect::<Vec<_>>();
+		assert_eq!(drives, vec!["/", "/media/usb (usb)"]);
+	}
+
+	#[test]
+	fn windows_folders_use_profile_and_backslashes() {
+		let windows = env(
+			Platform::Windows,
+			"C:\\fallback",
+			&[
+				("USERPROFILE", "C:\\Users\\Ada"),
+				("OneDrive", "C:\\Users\\Ada\\OneDrive"),
+			],
+		);
+		let folders = well_known_folders(&windows, &[disk("System", "C:\\")]);
+
+		assert_eq!(path_of(&folders, FolderKind::Home), Some("C:\\Users\\Ada"));
+		assert_eq!(
+			path_of(&folders, FolderKind::Downloads),
+			Some("C:\\Users\\Ada\\Downloads")
+		);
+		assert_eq!(
+			path_of(&folders, FolderKind::CloudDrive),
+			Some("C:\\Users\\Ada\\OneDrive")
+		);
+		assert_eq!(path_of(&folders, FolderKind::Drive), Some("C:\\"));
+	}
+
+	#[test]
+	fn macos_folders_include_movies_and_icloud() {
+		let mac = env(Platform::MacOs, "/Users/ada", &[("HOME", "/Users/ada")]);
+		let folders = well_known_folders(&mac, &[]);
+
+		assert_eq!(
+			path_of(&folders, FolderKind::Videos),
+			Some("/Users/ada/Movies")
+		);
+		assert_eq!(
+			path_of(&folders, FolderKind::CloudDrive),
+			Some("/Users/ada/Library/Mobile Documents/com~apple~CloudDocs")
+		);
+	}
+
+	#[test]
+	fn without_home_only_drives_are_listed() {
+		let mut linux = env(Platform::Linux, "", &[]);
+		linux.home = None;
+		let folders = well_known_folders(&linux, &[disk("", "/")]);
+		assert_eq!(folders.len(), 1);
+		assert_eq!(folders[0].kind, FolderKind::Drive);
+	}
+}
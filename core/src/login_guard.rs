@@ -0,0 +1,428 @@
+//! Throttling for password logins. Every attempt spends a token from a
+//! bucket for its source address and one for its username, and repeated
+//! failures for a username lock it out for a while, doubling each time. All
+//! of this lives in memory; only the audit rows reach the database, in
+//! batches.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub(crate) const LOGIN_LIMITS_SETTING: &str = "login_limits";
+pub(crate) const LOGIN_AUDIT_FLUSH_INTERVAL: std::time::Duration =
+	std::time::Duration::from_secs(10);
+/// Buckets and failure counters kept before idle ones are dropped.
+const MAX_TRACKED_KEYS: usize = 10_000;
+/// Longest username, address or user agent kept for an attempt.
+const MAX_AUDIT_FIELD_CHARS: usize = 256;
+
+/// How many login attempts are let through. Buckets hold `*_burst` tokens
+/// and regain one every `*_refill_secs`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoginLimits {
+	pub ip_burst: u32,
+	pub ip_refill_secs: u64,
+	pub user_burst: u32,
+	pub user_refill_secs: u64,
+	/// Consecutive failures for one username before it is locked out.
+	pub lockout_after: u32,
+	/// First lockout; each further failure doubles it up to the cap.
+	pub lockout_base_secs: u64,
+	pub lockout_max_secs: u64,
+}
+
+impl Default for LoginLimits {
+	fn default() -> Self {
+		Self {
+			ip_burst: 20,
+			ip_refill_secs: 6,
+			user_burst: 10,
+			user_refill_secs: 12,
+			lockout_after: 5,
+			lockout_base_secs: 60,
+			lockout_max_secs: 60 * 60,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginOutcome {
+	Success,
+	Failure,
+	Throttled,
+	LockedOut,
+}
+
+impl LoginOutcome {
+	pub(crate) fn as_str(&self) -> &'static str {
+		match self {
+			LoginOutcome::Success => "success",
+			LoginOutcome::Failure => "failure",
+			LoginOutcome::Throttled => "throttled",
+			LoginOutcome::LockedOut => "locked_out",
+		}
+	}
+
+	pub(crate) fn parse(value: &str) -> Option<Self> {
+		match value {
+			"success" => Some(LoginOutcome::Success),
+			"failure" => Some(LoginOutcome::Failure),
+			"throttled" => Some(LoginOutcome::Throttled),
+			"locked_out" => Some(LoginOutcome::LockedOut),
+			_ => None,
+		}
+	}
+}
+
+/// One row of the login audit trail.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LoginAttempt {
+	pub at: DateTime<Utc>,
+	pub username: String,
+	pub source_ip: String,
+	pub user_agent: String,
+	pub outcome: LoginOutcome,
+}
+
+/// Where a login attempt came from.
+#[derive(Clone, Debug, Default)]
+pub struct LoginSource {
+	pub ip: String,
+	pub user_agent: String,
+}
+
+/// Failed logins for one username from one address, for the Users page.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FailedLoginGroup {
+	pub username: String,
+	pub source_ip: String,
+	pub count: u64,
+	pub last_at: DateTime<Utc>,
+}
+
+/// What a login attempt is allowed to do before its password is checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LoginGate {
+	Open,
+	Throttled { retry_after: Duration },
+	LockedOut { retry_after: Duration },
+}
+
+#[derive(Clone, Debug)]
+struct Bucket {
+	tokens: f64,
+	updated: DateTime<Utc>,
+}
+
+impl Bucket {
+	fn full(burst: u32, now: DateTime<Utc>) -> Self {
+		Self {
+			tokens: burst as f64,
+			updated: now,
+		}
+	}
+
+	fn refill(&mut self, burst: u32, refill_secs: u64, now: DateTime<Utc>) {
+		let elapsed = (now - self.updated).num_milliseconds().max(0) as f64 / 1000.0;
+		self.tokens = (self.tokens + elapsed / refill_secs.max(1) as f64).min(burst as f64);
+		self.updated = now;
+	}
+
+	/// Time until a token is available, or `None` if one is now.
+	fn wait(&self, refill_secs: u64) -> Option<Duration> {
+		(self.tokens < 1.0).then(|| {
+			Duration::milliseconds(
+				((1.0 - self.tokens) * refill_secs.max(1) as f64 * 1000.0).ceil() as i64,
+			)
+		})
+	}
+}
+
+#[derive(Clone, Debug)]
+struct Failures {
+	count: u32,
+	last_at: DateTime<Utc>,
+	locked_until: Option<DateTime<Utc>>,
+}
+
+pub(crate) struct LoginGuard {
+	limits: LoginLimits,
+	ips: HashMap<String, Bucket>,
+	users: HashMap<String, Bucket>,
+	failures: HashMap<String, Failures>,
+	pending: Vec<LoginAttempt>,
+}
+
+pub(crate) fn clip_audit_field(value: &str) -> String {
+	value.chars().take(MAX_AUDIT_FIELD_CHARS).collect()
+}
+
+/// Lockout after the `count`th consecutive failure.
+fn lockout_for(limits: &LoginLimits, count: u32) -> Option<Duration> {
+	if limits.lockout_after == 0 || count < limits.lockout_after {
+		return None;
+	}
+	let doublings = (count - limits.lockout_after).min(32);
+	let secs = limits
+		.lockout_base_secs
+		.saturating_mul(1u64 << doublings)
+		.min(limits.lockout_max_secs);
+	Some(Duration::seconds(secs as i64))
+}
+
+impl LoginGuard {
+	pub(crate) fn new(limits: LoginLimits) -> Self {
+		Self {
+			limits,
+			ips: HashMap::new(),
+			users: HashMap::new(),
+			failures: HashMap::new(),
+			pending: Vec::new(),
+		}
+	}
+
+	pub(crate) fn limits(&self) -> &LoginLimits {
+		&self.limits
+	}
+
+	pub(crate) fn set_limits(&mut self, limits: LoginLimits) {
+		self.limits = limits;
+	}
+
+	/// Checks the lockout and both buckets, spending a token from each when
+	/// the attempt may go ahead. An attempt on a locked username still
+	/// spends its address token, so guessing at locked names is throttled
+	/// like any other.
+	pub(crate) fn admit(&mut self, username: &str, ip: &str, now: DateTime<Utc>) -> LoginGate {
+		let limits = &self.limits;
+		let ip_bucket = self
+			.ips
+			.entry(ip.to_string())
+			.or_insert_with(|| Bucket::full(limits.ip_burst, now));
+		ip_bucket.refill(limits.ip_burst, limits.ip_refill_secs, now);
+		if let Some(until) = self
+			.failures
+			.get(username)
+			.and_then(|failures| failures.locked_until)
+			.filter(|until| *until > now)
+		{
+			if let Some(retry_after) = ip_bucket.wait(limits.ip_refill_secs) {
+				return LoginGate::Throttled { retry_after };
+			}
+			ip_bucket.tokens -= 1.0;
+			return LoginGate::LockedOut {
+				retry_after: until - now,
+			};
+		}
+		let user_bucket = self
+			.users
+			.entry(username.to_string())
+			.or_insert_with(|| Bucket::full(limits.user_burst, now));
+		user_bucket.refill(limits.user_burst, limits.user_refill_secs, now);
+		let wait = ip_bucket
+			.wait(limits.ip_refill_secs)
+			.max(user_bucket.wait(limits.user_refill_secs));
+		if let Some(retry_after) = wait {
+			return LoginGate::Throttled { retry_after };
+		}
+		ip_bucket.tokens -= 1.0;
+		user_bucket.tokens -= 1.0;
+		LoginGate::Open
+	}
+
+	/// Queues `attempt` for the audit trail. A success clears the failure
+	/// count for its username; a failure may start or extend a lockout.
+	pub(crate) fn record(&mut self, attempt: LoginAttempt) {
+		match attempt.outcome {
+			LoginOutcome::Success => {
+				self.failures.remove(&attempt.username);
+			}
+			LoginOutcome::Failure => {
+				let failures = self
+					.failures
+					.entry(attempt.username.clone())
+					.or_insert(Failures {
+						count: 0,
+						last_at: attempt.at,
+						locked_until: None,
+					});
+				failures.count += 1;
+				failures.last_at = attempt.at;
+				if let Some(lockout) = lockout_for(&self.limits, failures.count) {
+					failures.locked_until = Some(attempt.at + lockout);
+				}
+			}
+			LoginOutcome::Throttled | LoginOutcome::LockedOut => {}
+		}
+		self.pending.push(attempt);
+	}
+
+	/// Audit rows not yet written. Also forgets idle state once it grows
+	/// past [`MAX_TRACKED_KEYS`], so a flood of made-up names cannot grow
+	/// memory without bound. Failure counts are only forgotten once the
+	/// longest lockout has passed since the last failure, so pruning never
+	/// gives a username its attempts back early.
+	pub(crate) fn take_pending(&mut self, now: DateTime<Utc>) -> Vec<LoginAttempt> {
+		if self.ips.len() + self.users.len() + self.failures.len() > MAX_TRACKED_KEYS {
+			let limits = self.limits.clone();
+			self.ips.retain(|_, bucket| {
+				bucket.refill(limits.ip_burst, limits.ip_refill_secs, now);
+				bucket.tokens < limits.ip_burst as f64
+			});
+			self.users.retain(|_, bucket| {
+				bucket.refill(limits.user_burst, limits.user_refill_secs, now);
+				bucket.tokens < limits.user_burst as f64
+			});
+			let max_lockout = Duration::seconds(limits.lockout_max_secs as i64);
+			self.failures.retain(|_, failures| {
+				failures.last_at + max_lockout > now
+					|| failures
+						.locked_until
+						.is_some_and(|until| until + max_lockout > now)
+			});
+		}
+		std::mem::take(&mut self.pending)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn attempt(username: &str, outcome: LoginOutcome, at: DateTime<Utc>) -> LoginAttempt {
+		LoginAttempt {
+			at,
+			username: username.to_string(),
+			source_ip: String::from("10.0.0.7"),
+			user_agent: String::new(),
+			outcome,
+		}
+	}
+
+	#[test]
+	fn buckets_throttle_bursts_and_refill() {
+		let limits = LoginLimits {
+			ip_burst: 3,
+			ip_refill_secs: 10,
+			..LoginLimits::default()
+		};
+		let mut guard = LoginGuard::new(limits);
+		let now = Utc::now();
+		for user in ["a", "b", "c"] {
+			assert_eq!(guard.admit(user, "10.0.0.7", now), LoginGate::Open);
+		}
+		assert_eq!(
+			guard.admit("d", "10.0.0.7", now),
+			LoginGate::Throttled {
+				retry_after: Duration::seconds(10)
+			}
+		);
+		// Another address has its own bucket.
+		assert_eq!(guard.admit("d", "10.0.0.8", now), LoginGate::Open);
+		assert_eq!(
+			guard.admit("d", "10.0.0.7", now + Duration::seconds(10)),
+			LoginGate::Open
+		);
+	}
+
+	#[test]
+	fn failures_lock_out_with_doubling_until_a_success() {
+		let limits = LoginLimits {
+			lockout_after: 2,
+			lockout_base_secs: 60,
+			lockout_max_secs: 150,
+			..LoginLimits::default()
+		};
+		let mut guard = LoginGuard::new(limits);
+		let start = Utc::now();
+		guard.record(attempt("ann", LoginOutcome::Failure, start));
+		assert_eq!(guard.admit("ann", "ip", start), LoginGate::Open);
+		guard.record(attempt("ann", LoginOutcome::Failure, start));
+		assert_eq!(
+			guard.admit("ann", "ip", start + Duration::seconds(30)),
+			LoginGate::LockedOut {
+				retry_after: Duration::seconds(30)
+			}
+		);
+
+		let later = start + Duration::seconds(61);
+		assert_eq!(guard.admit("ann", "ip", later), LoginGate::Open);
+		guard.record(attempt("ann", LoginOutcome::Failure, later));
+		assert!(matches!(
+			guard.admit("ann", "ip", later + Duration::seconds(119)),
+			LoginGate::LockedOut { .. }
+		));
+		let capped = later + Duration::seconds(121);
+		guard.record(attempt("ann", LoginOutcome::Failure, capped));
+		assert_eq!(
+			guard.admit("ann", "ip", capped),
+			LoginGate::LockedOut {
+				retry_after: Duration::seconds(150)
+			}
+		);
+
+		guard.record(attempt("ann", LoginOutcome::Success, capped));
+		assert_eq!(guard.admit("ann", "ip", capped), LoginGate::Open);
+		assert_eq!(guard.take_pending(capped).len(), 5);
+		assert!(guard.take_pending(capped).is_empty());
+	}
+
+	#[test]
+	fn locked_out_attempts_spend_address_tokens() {
+		let limits = LoginLimits {
+			ip_burst: 2,
+			ip_refill_secs: 10,
+			lockout_after: 1,
+			..LoginLimits::default()
+		};
+		let mut guard = LoginGuard::new(limits);
+		let now = Utc::now();
+		guard.record(attempt("ann", LoginOutcome::Failure, now));
+		for _ in 0..2 {
+			assert!(matches!(
+				guard.admit("ann", "ip", now),
+				LoginGate::LockedOut { .. }
+			));
+		}
+		assert!(matches!(
+			guard.admit("ann", "ip", now),
+			LoginGate::Throttled { .. }
+		));
+		assert!(matches!(
+			guard.admit("bob", "ip", now),
+			LoginGate::Throttled { .. }
+		));
+	}
+
+	#[test]
+	fn pruning_keeps_failure_counts_until_they_expire() {
+		let limits = LoginLimits {
+			lockout_after: 3,
+			lockout_max_secs: 600,
+			..LoginLimits::default()
+		};
+		let mut guard = LoginGuard::new(limits);
+		let flood = |guard: &mut LoginGuard, at: DateTime<Utc>| {
+			for n in 0..MAX_TRACKED_KEYS {
+				guard.admit(&format!("user{n}"), "ip", at);
+			}
+			guard.take_pending(at);
+		};
+		let now = Utc::now();
+		for _ in 0..2 {
+			guard.record(attempt("ann", LoginOutcome::Failure, now));
+		}
+		flood(&mut guard, now + Duration::seconds(30));
+		let later = now + Duration::seconds(60);
+		guard.record(attempt("ann", LoginOutcome::Failure, later));
+		assert!(matches!(
+			guard.admit("ann", "other", later),
+			LoginGate::LockedOut { .. }
+		));
+
+		flood(&mut guard, later + Duration::seconds(1300));
+		assert!(!guard.failures.contains_key("ann"));
+	}
+}
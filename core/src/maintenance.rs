@@ -0,0 +1,342 @@
+//! Maintenance mode: the node keeps serving reads but refuses everything
+//! that would write to its database or shared folders, so the data
+//! directory can be copied or migrated while it runs. Entering waits a
+//! bounded time for mutating work already running, asks what is left to
+//! stop at its next checkpoint, then folds the SQLite WAL into the database
+//! file. The mode is stored as a setting, so a node restarted during
+//! maintenance stays in it until told to exit.
+
+use crate::format::human_duration;
+use chrono::{DateTime, Utc};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+pub(crate) const MAINTENANCE_SETTING: &str = "maintenance";
+/// How long entering maintenance waits for running work to finish.
+pub(crate) const MAINTENANCE_SETTLE: Duration = Duration::from_secs(30);
+/// How long work still running after that gets to reach its next
+/// checkpoint.
+pub(crate) const MAINTENANCE_CHECKPOINT_GRACE: Duration = Duration::from_secs(10);
+
+/// Why this node is in maintenance and since when.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Maintenance {
+	pub reason: String,
+	/// How long the operator expected it to take, in seconds.
+	#[serde(default)]
+	pub expected_secs: Option<u64>,
+	pub since: DateTime<Utc>,
+}
+
+impl Maintenance {
+	pub fn new(
+		reason: impl Into<String>,
+		expected: Option<Duration>,
+		since: DateTime<Utc>,
+	) -> Self {
+		Self {
+			reason: reason.into(),
+			expected_secs: expected.map(|expected| expected.as_secs()),
+			since,
+		}
+	}
+
+	pub fn expected(&self) -> Option<Duration> {
+		self.expected_secs.map(Duration::from_secs)
+	}
+
+	/// The refusal mutating operations get while this lasts.
+	pub fn refusal(&self) -> MaintenanceMode {
+		MaintenanceMode {
+			reason: self.reason.clone(),
+			expected: self.expected(),
+		}
+	}
+}
+
+/// A mutating operation refused because the node is in maintenance mode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MaintenanceMode {
+	pub reason: String,
+	pub expected: Option<Duration>,
+}
+
+impl fmt::Display for MaintenanceMode {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "node is in maintenance mode: {}", self.reason)?;
+		if let Some(expected) = self.expected {
+			write!(f, " (expected to take {})", human_duration(expected))?;
+		}
+		write!(f, "; changes are refused until it ends")
+	}
+}
+
+impl std::error::Error for MaintenanceMode {}
+
+/// What entering maintenance found once the wait was over.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MaintenanceReport {
+	pub maintenance: Maintenance,
+	/// Mutating work still running when the wait ended.
+	pub pending: Vec<String>,
+	/// Whether the WAL was folded into the database file.
+	pub checkpointed: bool,
+}
+
+impl MaintenanceReport {
+	/// Whether the data directory can be copied as it is on disk.
+	pub fn safe_to_copy(&self) -> bool {
+		self.pending.is_empty() && self.checkpointed
+	}
+
+	pub fn summary(&self) -> String {
+		if !self.pending.is_empty() {
+			format!(
+				"not safe to copy yet; still running: {}",
+				self.pending.join(", ")
+			)
+		} else if !self.checkpointed {
+			String::from("not safe to copy yet; the database journal could not be checkpointed")
+		} else {
+			String::from("safe to copy")
+		}
+	}
+}
+
+struct Running {
+	label: String,
+	cancel: Option<Arc<AtomicBool>>,
+}
+
+#[derive(Default)]
+struct GateState {
+	active: Option<Maintenance>,
+	running: BTreeMap<u64, Running>,
+	next_id: u64,
+}
+
+/// Whether maintenance is on, and the mutating work it waits for.
+#[derive(Default)]
+pub(crate) struct MaintenanceGate {
+	state: Mutex<GateState>,
+	finished: Condvar,
+}
+
+impl MaintenanceGate {
+	pub(crate) fn new(active: Option<Maintenance>) -> Self {
+		Self {
+			state: Mutex::new(GateState {
+				active,
+				..GateState::default()
+			}),
+			finished: Condvar::new(),
+		}
+	}
+
+	pub(crate) fn active(&self) -> Option<Maintenance> {
+		self.state.lock().unwrap().active.clone()
+	}
+
+	pub(crate) fn is_active(&self) -> bool {
+		self.state.lock().unwrap().active.is_some()
+	}
+
+	/// Refuses while maintenance lasts.
+	pub(crate) fn check(&self) -> Result<(), MaintenanceMode> {
+		match &self.state.lock().unwrap().active {
+			Some(maintenance) => Err(maintenance.refusal()),
+			None => Ok(()),
+		}
+	}
+
+	pub(crate) fn set(&self, active: Option<Maintenance>) {
+		self.state.lock().unwrap().active = active;
+	}
+
+	/// Registers mutating work until the returned guard drops, unless
+	/// maintenance is on. Setting `cancel` stops the work at its next
+	/// checkpoint.
+	pub(crate) fn begin(
+		self: &Arc<Self>,
+		label: impl Into<String>,
+		cancel: Option<Arc<AtomicBool>>,
+	) -> Result<MutatingWork, MaintenanceMode> {
+		let mut state = self.state.lock().unwrap();
+		if let Some(maintenance) = &state.active {
+			return Err(maintenance.refusal());
+		}
+		let id = state.next_id;
+		state.next_id += 1;
+		state.running.insert(
+			id,
+			Running {
+				label: label.into(),
+				cancel,
+			},
+		);
+		Ok(MutatingWork {
+			gate: Arc::clone(self),
+			id,
+		})
+	}
+
+	/// Labels of the mutating work running now.
+	pub(crate) fn running(&self) -> Vec<String> {
+		labels(&self.state.lock().unwrap())
+	}
+
+	fn wait_idle<'a>(
+		&self,
+		mut state: MutexGuard<'a, GateState>,
+		timeout: Duration,
+	) -> MutexGuard<'a, GateState> {
+		let deadline = Instant::now() + timeout;
+		while !state.running.is_empty() {
+			let left = deadline.saturating_duration_since(Instant::now());
+			if left.is_zero() {
+				break;
+			}
+			state = self.finished.wait_timeout(state, left).unwrap().0;
+		}
+		state
+	}
+
+	/// Waits up to `settle` for the running work to finish, then asks what
+	/// still runs to stop at its next checkpoint and waits up to `grace`
+	/// more. Returns what is still running after that.
+	pub(crate) fn settle(&self, settle: Duration, grace: Duration) -> Vec<String> {
+		let state = self.wait_idle(self.state.lock().unwrap(), settle);
+		for running in state.running.values() {
+			if let Some(cancel) = &running.cancel {
+				tracing::info!("asking {} to stop for maintenance", running.label);
+				cancel.store(true, Ordering::SeqCst);
+			}
+		}
+		labels(&self.wait_idle(state, grace))
+	}
+}
+
+fn labels(state: &GateState) -> Vec<String> {
+	state
+		.running
+		.values()
+		.map(|running| running.label.clone())
+		.collect()
+}
+
+/// Mutating work registered with [`MaintenanceGate::begin`].
+pub(crate) struct MutatingWork {
+	gate: Arc<MaintenanceGate>,
+	id: u64,
+}
+
+impl Drop for MutatingWork {
+	fn drop(&mut self) {
+		self.gate.state.lock().unwrap().running.remove(&self.id);
+		self.gate.finished.notify_all();
+	}
+}
+
+/// Folds the WAL into the database file and truncates it, so the file
+/// alone holds every commit. False when a connection kept part of it busy.
+pub(crate) fn checkpoint_wal(conn: &Connection) -> rusqlite::Result<bool> {
+	conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| {
+		row.get::<_, i64>(0)
+	})
+	.map(|busy| busy == 0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::scan::scan_with_progress_cancelable;
+	use crate::test_util::{self, test_dir};
+
+	fn backup() -> Maintenance {
+		Maintenance::new(
+			"copying the data directory",
+			Some(Duration::from_secs(600)),
+			Utc::now(),
+		)
+	}
+
+	#[test]
+	fn a_scan_started_before_entry_finishes_or_checkpoints() {
+		let dir = test_dir("maintenance-scan");
+		for i in 0..200 {
+			std::fs::write(dir.join(format!("{i}.txt")), format!("file {i}")).unwrap();
+		}
+		let gate = Arc::new(MaintenanceGate::default());
+		let cancel = Arc::new(AtomicBool::new(false));
+		let work = gate
+			.begin(format!("scan of {}", dir.display()), Some(cancel.clone()))
+			.unwrap();
+		let scan = std::thread::spawn({
+			let dir = dir.clone();
+			move || {
+				let _work = work;
+				let mut conn = test_util::memory_db();
+				scan_with_progress_cancelable(
+					&[7u8; 16],
+					&dir,
+					&mut conn,
+					|_| {},
+					|| cancel.load(Ordering::SeqCst),
+				)
+			}
+		});
+
+		gate.set(Some(backup()));
+		let pending = gate.settle(Duration::from_secs(10), Duration::from_secs(10));
+		assert!(pending.is_empty(), "still running: {pending:?}");
+		match scan.join().unwrap() {
+			Ok(result) => assert_eq!(result.inserted_count, 200),
+			Err(err) => assert_eq!(err, "Scan cancelled"),
+		}
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn a_scan_attempted_during_maintenance_is_refused() {
+		let gate = Arc::new(MaintenanceGate::new(Some(backup())));
+		let refused = gate.begin("scan of /photos", None).err().unwrap();
+		assert_eq!(refused.reason, "copying the data directory");
+		assert_eq!(refused.expected, Some(Duration::from_secs(600)));
+		assert_eq!(
+			refused.to_string(),
+			"node is in maintenance mode: copying the data directory (expected to take 10m 00s); \
+			 changes are refused until it ends"
+		);
+		assert!(gate.running().is_empty());
+
+		gate.set(None);
+		let work = gate.begin("scan of /photos", None).unwrap();
+		assert_eq!(gate.running(), ["scan of /photos"]);
+		drop(work);
+		assert!(gate.running().is_empty());
+	}
+
+	#[test]
+	fn work_that_ignores_the_checkpoint_request_is_reported() {
+		let gate = Arc::new(MaintenanceGate::default());
+		let _work = gate.begin("sync of pin 3", None).unwrap();
+		gate.set(Some(backup()));
+		let pending = gate.settle(Duration::from_millis(10), Duration::from_millis(10));
+		assert_eq!(pending, ["sync of pin 3"]);
+		let report = MaintenanceReport {
+			maintenance: backup(),
+			pending,
+			checkpointed: true,
+		};
+		assert!(!report.safe_to_copy());
+		assert_eq!(
+			report.summary(),
+			"not safe to copy yet; still running: sync of pin 3"
+		);
+	}
+}
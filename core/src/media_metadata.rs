@@ -0,0 +1,338 @@
+//! Dimensions, duration and codec of indexed media, stored per content
+//! hash. Images are measured from their headers through `image` without
+//! decoding; audio and video go through `ffprobe` when it is installed.
+//! A failed extraction is stored too, so a hash is only ever tried once.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+/// What extraction found for one piece of content.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaMetadata {
+	pub width: Option<u32>,
+	pub height: Option<u32>,
+	pub duration_ms: Option<u64>,
+	/// Video codec for video, audio codec for audio.
+	pub codec: Option<String>,
+	/// Why extraction failed; the other fields are empty then.
+	pub error: Option<String>,
+}
+
+impl MediaMetadata {
+	pub fn failed(error: impl Into<String>) -> Self {
+		Self {
+			error: Some(error.into()),
+			..Default::default()
+		}
+	}
+
+	pub fn pixels(&self) -> Option<u64> {
+		Some(u64::from(self.width?) * u64::from(self.height?))
+	}
+
+	pub fn duration(&self) -> Option<Duration> {
+		self.duration_ms.map(Duration::from_millis)
+	}
+
+	/// One line for headers, e.g. `1920x1080 h264, 42 min`. `None` when
+	/// nothing is known.
+	pub fn describe(&self) -> Option<String> {
+		let size = match (self.width, self.height) {
+			(Some(width), Some(height)) => Some(format!("{width}x{height}")),
+			_ => None,
+		};
+		let format = [size, self.codec.clone()]
+			.into_iter()
+			.flatten()
+			.collect::<Vec<_>>()
+			.join(" ");
+		let parts = [Some(format), self.duration().map(media_duration)]
+			.into_iter()
+			.flatten()
+			.filter(|part| !part.is_empty())
+			.collect::<Vec<_>>();
+		(!parts.is_empty()).then(|| parts.join(", "))
+	}
+}
+
+/// Play length the way players show it: `35 s`, `42 min`, `1 h 05 min`.
+pub fn media_duration(duration: Duration) -> String {
+	let secs = duration.as_secs();
+	if secs < 60 {
+		format!("{secs} s")
+	} else if secs < 3_600 {
+		format!("{} min", secs / 60)
+	} else {
+		format!("{} h {:02} min", secs / 3_600, secs % 3_600 / 60)
+	}
+}
+
+/// Whether content of `mime` has metadata worth extracting.
+pub(crate) fn is_media_mime(mime: &str) -> bool {
+	["image/", "audio/", "video/"]
+		.iter()
+		.any(|prefix| mime.starts_with(prefix))
+}
+
+/// An indexed local file whose content has no metadata yet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PendingMedia {
+	pub(crate) hash: Vec<u8>,
+	pub(crate) mime_type: String,
+	pub(crate) path: PathBuf,
+}
+
+/// Counts from one extraction pass.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaExtractReport {
+	pub extracted: u64,
+	pub failed: u64,
+}
+
+/// Metadata of the file at `path`. Never fails; problems end up in
+/// `error`.
+pub(crate) fn extract(path: &Path, mime_type: &str) -> MediaMetadata {
+	let result = if mime_type.starts_with("image/") {
+		image_dimensions(path)
+	} else {
+		probe(path)
+	};
+	result.unwrap_or_else(MediaMetadata::failed)
+}
+
+fn image_dimensions(path: &Path) -> Result<MediaMetadata, String> {
+	let (width, height) = image::ImageReader::open(path)
+		.map_err(|err| format!("failed to open image: {err}"))?
+		.with_guessed_format()
+		.map_err(|err| format!("failed to read image header: {err}"))?
+		.into_dimensions()
+		.map_err(|err| format!("failed to read image dimensions: {err}"))?;
+	Ok(MediaMetadata {
+		width: Some(width),
+		height: Some(height),
+		..Default::default()
+	})
+}
+
+fn probe(path: &Path) -> Result<MediaMetadata, String> {
+	let output = Command::new("ffprobe")
+		.args([
+			"-v",
+			"error",
+			"-print_format",
+			"json",
+			"-show_format",
+			"-show_streams",
+		])
+		.arg(path)
+		.output()
+		.map_err(|err| match err.kind() {
+			std::io::ErrorKind::NotFound => String::from("ffprobe is not installed"),
+			_ => format!("failed to run ffprobe: {err}"),
+		})?;
+	if !output.status.success() {
+		let stderr = String::from_utf8_lossy(&output.stderr);
+		return Err(format!("ffprobe failed: {}", stderr.trim()));
+	}
+	parse_ffprobe(&output.stdout)
+}
+
+#[derive(Deserialize)]
+struct ProbeOutput {
+	#[serde(default)]
+	format: Option<ProbeFormat>,
+	#[serde(default)]
+	streams: Vec<ProbeStream>,
+}
+
+#[derive(Deserialize)]
+struct ProbeFormat {
+	duration: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+	codec_type: Option<String>,
+	codec_name: Option<String>,
+	width: Option<u32>,
+	height: Option<u32>,
+	duration: Option<String>,
+	/// Set on cover art, which ffprobe lists as a video stream.
+	#[serde(default)]
+	disposition: Option<ProbeDisposition>,
+}
+
+#[derive(Deserialize)]
+struct ProbeDisposition {
+	#[serde(default)]
+	attached_pic: u8,
+}
+
+fn parse_seconds(value: Option<&str>) -> Option<u64> {
+	let secs = value?.trim().parse::<f64>().ok()?;
+	(secs.is_finite() && secs >= 0.0).then(|| (secs * 1000.0).round() as u64)
+}
+
+/// Reads `ffprobe -print_format json -show_format -show_streams` output.
+/// The first real video stream wins over audio; cover art is skipped.
+pub(crate) fn parse_ffprobe(json: &[u8]) -> Result<MediaMetadata, String> {
+	let output: ProbeOutput =
+		serde_json::from_slice(json).map_err(|err| format!("invalid ffprobe output: {err}"))?;
+	let is = |stream: &&ProbeStream, kind: &str| stream.codec_type.as_deref() == Some(kind);
+	let video = output.streams.iter().find(|stream| {
+		is(stream, "video")
+			&& stream
+				.disposition
+				.as_ref()
+				.is_none_or(|disposition| disposition.attached_pic == 0)
+	});
+	let stream = video.or_else(|| output.streams.iter().find(|stream| is(stream, "audio")));
+	let Some(stream) = stream else {
+		return Err(String::from("no audio or video stream"));
+	};
+	let duration_ms = output
+		.format
+		.as_ref()
+		.and_then(|format| parse_seconds(format.duration.as_deref()))
+		.or_else(|| parse_seconds(stream.duration.as_deref()));
+	Ok(MediaMetadata {
+		width: video.and_then(|video| video.width),
+		height: video.and_then(|video| video.height),
+		duration_ms,
+		codec: stream.codec_name.clone(),
+		error: None,
+	})
+}
+
+/// Extracts every file in `pending`, handing each result to `save`.
+/// Stops between files once `should_cancel` says so.
+pub(crate) fn extract_pending<S, C>(
+	pending: Vec<PendingMedia>,
+	mut save: S,
+	mut should_cancel: C,
+) -> MediaExtractReport
+where
+	S: FnMut(&[u8], &MediaMetadata) -> anyhow::Result<()>,
+	C: FnMut() -> bool,
+{
+	let mut report = MediaExtractReport::default();
+	for file in pending {
+		if should_cancel() {
+			break;
+		}
+		let metadata = extract(&file.path, &file.mime_type);
+		if let Some(err) = &metadata.error {
+			tracing::info!("no media metadata for {}: {err}", file.path.display());
+		}
+		if let Err(err) = save(&file.hash, &metadata) {
+			tracing::warn!(
+				"failed to store media metadata for {}: {err}",
+				file.path.display()
+			);
+			continue;
+		}
+		if metadata.error.is_some() {
+			report.failed += 1;
+		} else {
+			report.extracted += 1;
+		}
+	}
+	report
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn ffprobe_output_prefers_video_over_cover_art_and_audio() {
+		let json = br#"{
+			"streams": [
+				{"codec_type": "audio", "codec_name": "aac", "duration": "2519.9"},
+				{"codec_type": "video", "codec_name": "mjpeg", "width": 600, "height": 600,
+					"disposition": {"attached_pic": 1}},
+				{"codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080,
+					"disposition": {"attached_pic": 0}}
+			],
+			"format": {"duration": "2520.040000"}
+		}"#;
+		let metadata = parse_ffprobe(json).unwrap();
+		assert_eq!(metadata.codec.as_deref(), Some("h264"));
+		assert_eq!(metadata.pixels(), Some(1920 * 1080));
+		assert_eq!(metadata.duration_ms, Some(2_520_040));
+		assert_eq!(
+			metadata.describe().as_deref(),
+			Some("1920x1080 h264, 42 min")
+		);
+	}
+
+	#[test]
+	fn audio_only_files_have_no_dimensions() {
+		let json = br#"{
+			"streams": [
+				{"codec_type": "audio", "codec_name": "flac", "duration": "215.5"},
+				{"codec_type": "video", "codec_name": "png", "width": 500, "height": 500,
+					"disposition": {"attached_pic": 1}}
+			],
+			"format": {}
+		}"#;
+		let metadata = parse_ffprobe(json).unwrap();
+		assert_eq!(metadata.codec.as_deref(), Some("flac"));
+		assert_eq!(metadata.pixels(), None);
+		assert_eq!(metadata.duration_ms, Some(215_500));
+		assert_eq!(metadata.describe().as_deref(), Some("flac, 3 min"));
+		assert!(parse_ffprobe(br#"{"streams": []}"#).is_err());
+		assert!(parse_ffprobe(b"not json").is_err());
+	}
+
+	#[test]
+	fn images_are_measured_and_broken_files_are_recorded() {
+		let dir = std::env::temp_dir().join(format!("puppynet-media-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let photo = dir.join("photo.png");
+		image::RgbImage::new(40, 20).save(&photo).unwrap();
+		let broken = dir.join("broken.png");
+		std::fs::write(&broken, b"not a png").unwrap();
+
+		let pending = vec![
+			PendingMedia {
+				hash: vec![1; 32],
+				mime_type: String::from("image/png"),
+				path: photo,
+			},
+			PendingMedia {
+				hash: vec![2; 32],
+				mime_type: String::from("image/png"),
+				path: broken,
+			},
+		];
+		let mut saved = Vec::new();
+		let report = extract_pending(
+			pending,
+			|hash, metadata| {
+				saved.push((hash.to_vec(), metadata.clone()));
+				Ok(())
+			},
+			|| false,
+		);
+		assert_eq!(
+			report,
+			MediaExtractReport {
+				extracted: 1,
+				failed: 1
+			}
+		);
+		assert_eq!(saved[0].1.describe().as_deref(), Some("40x20"));
+		assert!(saved[1].1.error.is_some());
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn durations_read_like_a_player() {
+		assert_eq!(media_duration(Duration::from_secs(35)), "35 s");
+		assert_eq!(media_duration(Duration::from_secs(42 * 60 + 5)), "42 min");
+		assert_eq!(media_duration(Duration::from_secs(3_900)), "1 h 05 min");
+	}
+}
@@ -145,7 +145,7 @@ where
 		let _ = samples.try_send(downmix_samples(data, channels));
 	};
 	let error_callback = |error: cpal::Error| {
-		log::warn!("microphone WebRTC capture error: {error}");
+		tracing::warn!("microphone WebRTC capture error: {error}");
 	};
 	Ok(device.build_input_stream(*config, data_callback, error_callback, None)?)
 }
@@ -208,13 +208,13 @@ fn run_microphone_producer(
 			Ok(encoder) => encoder,
 			Err(error) => {
 				let _ = ready.send(Err(format!("failed to initialize Opus encoder: {error}")));
-				log::warn!("failed to initialize Opus encoder: {error}");
+				tracing::warn!("failed to initialize Opus encoder: {error}");
 				return;
 			}
 		};
 	if let Err(error) = encoder.set_bitrate(Bitrate::Bits(AUDIO_BITRATE)) {
 		let _ = ready.send(Err(format!("failed to configure Opus encoder: {error}")));
-		log::warn!("failed to configure Opus encoder: {error}");
+		tracing::warn!("failed to configure Opus encoder: {error}");
 		return;
 	}
 	let mut resampler = match Async::<f32>::new_poly(
@@ -230,7 +230,7 @@ fn run_microphone_producer(
 			let _ = ready.send(Err(format!(
 				"failed to initialize microphone resampler: {error}"
 			)));
-			log::warn!("failed to initialize microphone resampler: {error}");
+			tracing::warn!("failed to initialize microphone resampler: {error}");
 			return;
 		}
 	};
@@ -244,7 +244,7 @@ fn run_microphone_producer(
 				if let Err(error) =
 					write_opus_frames(&track, &handle, &mut encoder, &mut resampler, &mut input)
 				{
-					log::warn!("microphone WebRTC producer stopped: {error}");
+					tracing::warn!("microphone WebRTC producer stopped: {error}");
 					return;
 				}
 			}
@@ -322,7 +322,7 @@ async fn run_video_producer(
 	let (encoder, fps) = match video_encoder(&kind) {
 		Ok(result) => result,
 		Err(error) => {
-			log::warn!("failed to initialize H.264 encoder: {error}");
+			tracing::warn!("failed to initialize H.264 encoder: {error}");
 			return;
 		}
 	};
@@ -331,7 +331,7 @@ async fn run_video_producer(
 	while !stop.load(Ordering::Relaxed) {
 		let elapsed = epoch_millis().saturating_sub(last_rtcp.load(Ordering::Relaxed));
 		if Duration::from_millis(elapsed) > VIDEO_RTCP_TIMEOUT {
-			log::info!("video WebRTC producer stopping: no receiver RTCP for {elapsed}ms");
+			tracing::info!("video WebRTC producer stopping: no receiver RTCP for {elapsed}ms");
 			stop.store(true, Ordering::Relaxed);
 			break;
 		}
@@ -357,15 +357,15 @@ async fn run_video_producer(
 							})
 							.await
 						{
-							log::warn!("video WebRTC producer failed: {error}");
+							tracing::warn!("video WebRTC producer failed: {error}");
 						}
 					}
 					Ok(Ok(_)) => {}
-					Ok(Err(error)) => log::warn!("failed to encode video frame: {error}"),
-					Err(error) => log::warn!("video encoder task failed: {error}"),
+					Ok(Err(error)) => tracing::warn!("failed to encode video frame: {error}"),
+					Err(error) => tracing::warn!("video encoder task failed: {error}"),
 				}
 			}
-			Err(error) => log::warn!("failed to capture video frame: {error}"),
+			Err(error) => tracing::warn!("failed to capture video frame: {error}"),
 		}
 		if let Some(delay) = frame_duration.checked_sub(started.elapsed()) {
 			tokio::time::sleep(delay).await;
@@ -557,7 +557,7 @@ impl MediaSessionManager {
 			return false;
 		};
 		if let Err(error) = session.peer_connection.close().await {
-			log::warn!("failed to close WebRTC media session {session_id}: {error}");
+			tracing::warn!("failed to close WebRTC media session {session_id}: {error}");
 		}
 		self.release_producer(&session.source_id).await;
 		true
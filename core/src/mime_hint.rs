@@ -0,0 +1,72 @@
+//! Mime types for directory listings beyond the extension guess. A type an
+//! earlier scan sniffed for the same file wins over the extension; files
+//! neither knows about get their leading bytes sniffed, within a budget so
+//! listing a huge directory stays quick.
+
+use crate::p2p::{DirEntry, MimeSource, path_from_bytes};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+
+/// Leading bytes read to sniff a file's type.
+const SNIFF_BYTES: usize = 512;
+/// Files sniffed per listing at most.
+const SNIFF_MAX_FILES: usize = 64;
+/// Sniffing stops once a listing has spent this long on it.
+const SNIFF_BUDGET: Duration = Duration::from_millis(100);
+
+fn entry_path(dir: &Path, entry: &DirEntry) -> PathBuf {
+	if entry.name_raw.is_empty() {
+		dir.join(&entry.name)
+	} else {
+		dir.join(path_from_bytes(&entry.name_raw))
+	}
+}
+
+/// Full paths and sizes of the files among `entries`, listed from `dir`,
+/// to look up in the index.
+pub(crate) fn indexable_files(dir: &Path, entries: &[DirEntry]) -> Vec<(PathBuf, u64)> {
+	entries
+		.iter()
+		.filter(|entry| !entry.is_dir)
+		.map(|entry| (entry_path(dir, entry), entry.size))
+		.collect()
+}
+
+async fn sniff_file(path: &Path) -> Option<String> {
+	let file = tokio::fs::File::open(path).await.ok()?;
+	let mut buffer = Vec::with_capacity(SNIFF_BYTES);
+	file.take(SNIFF_BYTES as u64)
+		.read_to_end(&mut buffer)
+		.await
+		.ok()?;
+	infer::get(&buffer).map(|kind| kind.mime_type().to_string())
+}
+
+/// Applies the `indexed` types (see [`crate::db::load_indexed_mimes`]) to
+/// `entries`, then sniffs files that still have none.
+pub(crate) async fn refine(
+	dir: &Path,
+	entries: &mut [DirEntry],
+	indexed: &HashMap<PathBuf, String>,
+) {
+	let started = Instant::now();
+	let mut sniffed = 0;
+	for entry in entries.iter_mut().filter(|entry| !entry.is_dir) {
+		let path = entry_path(dir, entry);
+		if let Some(mime) = indexed.get(&path) {
+			entry.mime = Some(mime.clone());
+			entry.mime_source = MimeSource::Index;
+			continue;
+		}
+		if entry.mime.is_some() || sniffed == SNIFF_MAX_FILES || started.elapsed() >= SNIFF_BUDGET {
+			continue;
+		}
+		sniffed += 1;
+		if let Some(mime) = sniff_file(&path).await {
+			entry.mime = Some(mime);
+			entry.mime_source = MimeSource::Sniffed;
+		}
+	}
+}
@@ -0,0 +1,256 @@
+//! Shared folders on mounts that come and go, like NFS or SMB shares and
+//! USB disks. A share is unavailable while its folder is missing or while
+//! the mount point it lives under is configured but nothing is mounted
+//! there. The empty directory an absent mount leaves behind must not read
+//! as "every file was deleted", so scans refuse unavailable shares and
+//! peers get [`ShareUnavailable`] instead of a plain IO error.
+//!
+//! Mount points come from the live mount table and fstab where the system
+//! has them; a folder whose device differs from its parent's counts as a
+//! mount point too.
+
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const MOUNTS_FILE: &str = "/proc/self/mounts";
+const FSTAB_FILE: &str = "/etc/fstab";
+/// How often shared folders are checked for mounts that left or came back.
+pub(crate) const MOUNT_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Whether a shared folder can be read right now.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum ShareAvailability {
+	/// On a local disk or on a mount that is present; `mount_point` is the
+	/// mount below `/` the folder is on, if any.
+	Available { mount_point: Option<PathBuf> },
+	/// `mount_point` is configured but nothing is mounted there.
+	NotMounted { mount_point: PathBuf },
+	/// The folder does not exist.
+	Missing,
+}
+
+impl ShareAvailability {
+	pub fn is_available(&self) -> bool {
+		matches!(self, Self::Available { .. })
+	}
+
+	/// Shown next to the share, e.g. "currently unavailable (/mnt/nas is
+	/// not mounted)".
+	pub fn describe(&self) -> String {
+		match self {
+			Self::Available {
+				mount_point: Some(mount_point),
+			} => format!("available (mounted at {})", mount_point.display()),
+			Self::Available { mount_point: None } => String::from("available"),
+			Self::NotMounted { mount_point } => format!(
+				"currently unavailable ({} is not mounted)",
+				mount_point.display()
+			),
+			Self::Missing => String::from("currently unavailable (folder is missing)"),
+		}
+	}
+}
+
+/// A share whose availability changed between two checks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShareChange {
+	pub share: PathBuf,
+	pub available: bool,
+}
+
+/// A request reached a shared folder that is offline. Returned apart from
+/// other errors so callers can say "try later" rather than "not found".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShareUnavailable {
+	pub share: String,
+}
+
+impl std::fmt::Display for ShareUnavailable {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "the share {} is offline; try again later", self.share)
+	}
+}
+
+impl std::error::Error for ShareUnavailable {}
+
+/// Undoes the octal escapes mount tables use for spaces and the like.
+fn unescape_mount_point(field: &str) -> String {
+	let bytes = field.as_bytes();
+	let mut out = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while i < bytes.len() {
+		let escaped = bytes.get(i + 1..i + 4).filter(|digits| {
+			bytes[i] == b'\\' && digits.iter().all(|digit| (b'0'..=b'7').contains(digit))
+		});
+		match escaped
+			.and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok())
+		{
+			Some(byte) => {
+				out.push(byte);
+				i += 4;
+			}
+			None => {
+				out.push(bytes[i]);
+				i += 1;
+			}
+		}
+	}
+	String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Mount points listed in an fstab or `/proc/mounts` style table.
+fn parse_mount_points(table: &str) -> Vec<PathBuf> {
+	table
+		.lines()
+		.map(str::trim)
+		.filter(|line| !line.is_empty() && !line.starts_with('#'))
+		.filter_map(|line| {
+			let mut fields = line.split_whitespace();
+			let mount_point = fields.nth(1)?;
+			let fs_type = fields.next().unwrap_or_default();
+			(mount_point.starts_with('/') && fs_type != "swap")
+				.then(|| PathBuf::from(unescape_mount_point(mount_point)))
+		})
+		.collect()
+}
+
+/// True when `path` is on another device than its parent.
+#[cfg(unix)]
+fn is_mount_point(path: &Path) -> bool {
+	use std::os::unix::fs::MetadataExt;
+	let Some(parent) = path.parent() else {
+		return false;
+	};
+	match (std::fs::metadata(path), std::fs::metadata(parent)) {
+		(Ok(own), Ok(parent)) => own.dev() != parent.dev(),
+		_ => false,
+	}
+}
+
+#[cfg(not(unix))]
+fn is_mount_point(_path: &Path) -> bool {
+	false
+}
+
+/// Mount points known to the system.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MountTable {
+	mounted: Vec<PathBuf>,
+	configured: Vec<PathBuf>,
+}
+
+impl MountTable {
+	/// The live mount table and fstab. Either is empty where the system has
+	/// no such file.
+	pub(crate) fn load() -> Self {
+		let read = |file| std::fs::read_to_string(file).unwrap_or_default();
+		Self {
+			mounted: parse_mount_points(&read(MOUNTS_FILE)),
+			configured: parse_mount_points(&read(FSTAB_FILE)),
+		}
+	}
+
+	/// Deepest known mount point below `/` that `path` lies on.
+	fn mount_point_of(&self, path: &Path) -> Option<&Path> {
+		self.mounted
+			.iter()
+			.chain(&self.configured)
+			.filter(|mount_point| mount_point.parent().is_some() && path.starts_with(mount_point))
+			.max_by_key(|mount_point| mount_point.components().count())
+			.map(PathBuf::as_path)
+	}
+
+	/// False while `path` lies under a configured mount point that has
+	/// nothing mounted.
+	pub(crate) fn is_present(&self, path: &Path) -> bool {
+		self.mount_point_of(path)
+			.is_none_or(|mount_point| self.mounted.iter().any(|mounted| mounted == mount_point))
+	}
+
+	/// Reads the file system, which can block on a hung network mount.
+	pub(crate) fn availability(&self, share: &Path) -> ShareAvailability {
+		if !self.is_present(share)
+			&& let Some(mount_point) = self.mount_point_of(share)
+		{
+			return ShareAvailability::NotMounted {
+				mount_point: mount_point.to_path_buf(),
+			};
+		}
+		if std::fs::metadata(share).is_err() {
+			return ShareAvailability::Missing;
+		}
+		let mount_point = self
+			.mount_point_of(share)
+			.map(Path::to_path_buf)
+			.or_else(|| is_mount_point(share).then(|| share.to_path_buf()));
+		ShareAvailability::Available { mount_point }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const FSTAB: &str = "\
+# <file system> <mount point> <type> <options> <dump> <pass>
+UUID=1234 / ext4 defaults 0 1
+UUID=5678 none swap sw 0 0
+nas:/media /mnt/media nfs defaults,noauto 0 0
+//nas/backup /mnt/my\\040backup cifs credentials=/etc/nas 0 0
+";
+
+	const MOUNTS: &str = "\
+/dev/sda1 / ext4 rw,relatime 0 0
+proc /proc proc rw 0 0
+//nas/backup /mnt/my\\040backup cifs rw 0 0
+";
+
+	fn table() -> MountTable {
+		MountTable {
+			mounted: parse_mount_points(MOUNTS),
+			configured: parse_mount_points(FSTAB),
+		}
+	}
+
+	#[test]
+	fn tables_list_mount_points_with_escapes_undone() {
+		assert_eq!(
+			parse_mount_points(FSTAB),
+			[
+				PathBuf::from("/"),
+				PathBuf::from("/mnt/media"),
+				PathBuf::from("/mnt/my backup"),
+			]
+		);
+	}
+
+	#[test]
+	fn shares_under_an_absent_mount_are_not_mounted() {
+		let table = table();
+		assert!(!table.is_present(Path::new("/mnt/media/photos/cat.jpg")));
+		assert_eq!(
+			table.availability(Path::new("/mnt/media/photos")),
+			ShareAvailability::NotMounted {
+				mount_point: PathBuf::from("/mnt/media"),
+			}
+		);
+		assert!(table.is_present(Path::new("/mnt/my backup/2025")));
+		assert!(table.is_present(Path::new("/mnt/mediaserver")));
+		assert!(table.is_present(Path::new("/home/ana")));
+	}
+
+	#[test]
+	fn missing_folders_are_unavailable() {
+		let table = MountTable::default();
+		assert_eq!(
+			table.availability(Path::new("/definitely/not/a/puppynet/share")),
+			ShareAvailability::Missing
+		);
+		assert!(
+			table
+				.availability(Path::new(env!("CARGO_MANIFEST_DIR")))
+				.is_available()
+		);
+	}
+}
@@ -0,0 +1,625 @@
+use crate::format::human_duration;
+use chrono::{DateTime, Utc};
+use igd_next::PortMappingProtocol;
+use igd_next::aio::Gateway;
+use igd_next::aio::tokio::{Tokio, search_gateway};
+use libp2p::Multiaddr;
+use libp2p::multiaddr::Protocol;
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, timeout};
+
+/// Port mapping changes how reachable the node is, so it stays off until
+/// enabled in settings.
+pub(crate) const NAT_MAPPING_SETTING: &str = "nat_port_mapping";
+const MAPPING_DESCRIPTION: &str = "puppynet";
+const UPNP_LEASE_SECS: u32 = 60 * 60;
+const NATPMP_LIFETIME_SECS: u32 = 60 * 60;
+const NATPMP_PORT: u16 = 5351;
+const NATPMP_ATTEMPTS: u32 = 4;
+const NATPMP_FIRST_TIMEOUT: Duration = Duration::from_millis(250);
+const GATEWAY_SEARCH_TIMEOUT: Duration = Duration::from_secs(5);
+const RETRY_MIN: Duration = Duration::from_secs(30);
+const RETRY_MAX: Duration = Duration::from_secs(30 * 60);
+/// How long shutdown waits for the router to drop the mapping.
+const TEARDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NatMethod {
+	Upnp,
+	NatPmp,
+}
+
+impl NatMethod {
+	pub fn label(&self) -> &'static str {
+		match self {
+			Self::Upnp => "UPnP",
+			Self::NatPmp => "NAT-PMP",
+		}
+	}
+}
+
+/// Outcome of the router port mapping, for display.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum NatStatus {
+	#[default]
+	Disabled,
+	Mapping,
+	Mapped {
+		external_ip: IpAddr,
+		tcp_port: Option<u16>,
+		quic_port: Option<u16>,
+		method: NatMethod,
+		renews_at: DateTime<Utc>,
+	},
+	Failed {
+		reason: String,
+		retry_at: DateTime<Utc>,
+	},
+}
+
+impl NatStatus {
+	pub fn describe(&self, now: DateTime<Utc>) -> String {
+		let until = |at: DateTime<Utc>| human_duration((at - now).to_std().unwrap_or_default());
+		match self {
+			Self::Disabled => String::from("Port mapping is off"),
+			Self::Mapping => String::from("Looking for a UPnP or NAT-PMP router..."),
+			Self::Mapped {
+				external_ip,
+				tcp_port,
+				quic_port,
+				method,
+				renews_at,
+			} => {
+				let address = match (tcp_port, quic_port) {
+					(Some(tcp), Some(quic)) => {
+						format!(
+							"{} (QUIC on udp {quic})",
+							SocketAddr::new(*external_ip, *tcp)
+						)
+					}
+					(Some(port), None) | (None, Some(port)) => {
+						SocketAddr::new(*external_ip, *port).to_string()
+					}
+					(None, None) => external_ip.to_string(),
+				};
+				format!(
+					"External address {address} mapped via {}, lease renews in {}",
+					method.label(),
+					until(*renews_at)
+				)
+			}
+			Self::Failed { reason, retry_at } => {
+				format!(
+					"Port mapping failed: {reason}; retrying in {}",
+					until(*retry_at)
+				)
+			}
+		}
+	}
+}
+
+/// Local ports to forward, learnt from the swarm's listeners.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct NatPorts {
+	pub tcp: Option<u16>,
+	pub quic: Option<u16>,
+}
+
+impl NatPorts {
+	fn is_empty(&self) -> bool {
+		self.tcp.is_none() && self.quic.is_none()
+	}
+
+	fn entries(&self) -> Vec<(PortMappingProtocol, u16)> {
+		self.tcp
+			.map(|port| (PortMappingProtocol::TCP, port))
+			.into_iter()
+			.chain(self.quic.map(|port| (PortMappingProtocol::UDP, port)))
+			.collect()
+	}
+}
+
+enum MappedGateway {
+	Upnp(Gateway<Tokio>),
+	NatPmp(Ipv4Addr),
+}
+
+struct Mapping {
+	gateway: MappedGateway,
+	external_ip: IpAddr,
+	local: NatPorts,
+	external: NatPorts,
+	lease: Duration,
+}
+
+impl Mapping {
+	fn method(&self) -> NatMethod {
+		match self.gateway {
+			MappedGateway::Upnp(_) => NatMethod::Upnp,
+			MappedGateway::NatPmp(_) => NatMethod::NatPmp,
+		}
+	}
+
+	fn external_addrs(&self) -> Vec<Multiaddr> {
+		let ip = match self.external_ip {
+			IpAddr::V4(ip) => Protocol::Ip4(ip),
+			IpAddr::V6(ip) => Protocol::Ip6(ip),
+		};
+		let mut addrs = Vec::new();
+		if let Some(port) = self.external.tcp {
+			addrs.push(
+				Multiaddr::empty()
+					.with(ip.clone())
+					.with(Protocol::Tcp(port)),
+			);
+		}
+		if let Some(port) = self.external.quic {
+			addrs.push(
+				Multiaddr::empty()
+					.with(ip)
+					.with(Protocol::Udp(port))
+					.with(Protocol::QuicV1),
+			);
+		}
+		addrs
+	}
+}
+
+/// Addresses that can't be reached from the internet. A router reporting
+/// one of these as its external address sits behind another NAT.
+pub(crate) fn is_non_routable(ip: IpAddr) -> bool {
+	match ip {
+		IpAddr::V4(ip) => {
+			let [a, b, ..] = ip.octets();
+			ip.is_private()
+				|| ip.is_loopback()
+				|| ip.is_link_local()
+				|| ip.is_unspecified()
+				// Carrier-grade NAT, 100.64.0.0/10.
+				|| (a == 100 && (64..128).contains(&b))
+		}
+		IpAddr::V6(ip) => ip.is_loopback() || ip.is_unspecified(),
+	}
+}
+
+/// Default IPv4 gateway from the contents of `/proc/net/route`.
+fn parse_default_gateway(route_table: &str) -> Option<Ipv4Addr> {
+	route_table.lines().skip(1).find_map(|line| {
+		let fields = line.split_whitespace().collect::<Vec<_>>();
+		if fields.get(1) != Some(&"00000000") {
+			return None;
+		}
+		let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+		(gateway != 0).then(|| Ipv4Addr::from(gateway.to_le_bytes()))
+	})
+}
+
+fn default_gateway() -> Option<Ipv4Addr> {
+	parse_default_gateway(&std::fs::read_to_string("/proc/net/route").ok()?)
+}
+
+/// Address of this host on the interface that reaches `gateway`. Connecting
+/// a UDP socket sends nothing; it only picks the route.
+fn local_ip_towards(gateway: SocketAddr) -> Result<IpAddr, String> {
+	let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+		.map_err(|e| format!("failed to open socket: {e}"))?;
+	socket
+		.connect(gateway)
+		.map_err(|e| format!("no route to gateway {gateway}: {e}"))?;
+	socket
+		.local_addr()
+		.map(|addr| addr.ip())
+		.map_err(|e| format!("failed to read local address: {e}"))
+}
+
+fn natpmp_result(code: u16) -> Result<(), String> {
+	match code {
+		0 => Ok(()),
+		1 => Err(String::from("router does not support this NAT-PMP version")),
+		2 => Err(String::from("port mapping is disabled on the router")),
+		3 => Err(String::from("router has no external address")),
+		4 => Err(String::from("router is out of mapping resources")),
+		5 => Err(String::from("router does not support this request")),
+		code => Err(format!("router returned NAT-PMP error {code}")),
+	}
+}
+
+fn natpmp_opcode(protocol: PortMappingProtocol) -> u8 {
+	match protocol {
+		PortMappingProtocol::UDP => 1,
+		PortMappingProtocol::TCP => 2,
+	}
+}
+
+fn natpmp_map_request(opcode: u8, internal: u16, external: u16, lifetime: u32) -> [u8; 12] {
+	let mut request = [0u8; 12];
+	request[1] = opcode;
+	request[4..6].copy_from_slice(&internal.to_be_bytes());
+	request[6..8].copy_from_slice(&external.to_be_bytes());
+	request[8..12].copy_from_slice(&lifetime.to_be_bytes());
+	request
+}
+
+fn parse_natpmp_external_ip(response: &[u8]) -> Result<Ipv4Addr, String> {
+	if response.len() < 12 || response[0] != 0 || response[1] != 128 {
+		return Err(String::from("malformed NAT-PMP response"));
+	}
+	natpmp_result(u16::from_be_bytes([response[2], response[3]]))?;
+	Ok(Ipv4Addr::new(
+		response[8],
+		response[9],
+		response[10],
+		response[11],
+	))
+}
+
+/// External port and lifetime granted for a mapping request.
+fn parse_natpmp_mapping(response: &[u8], opcode: u8) -> Result<(u16, u32), String> {
+	if response.len() < 16 || response[0] != 0 || response[1] != opcode + 128 {
+		return Err(String::from("malformed NAT-PMP response"));
+	}
+	natpmp_result(u16::from_be_bytes([response[2], response[3]]))?;
+	let external = u16::from_be_bytes([response[10], response[11]]);
+	let lifetime = u32::from_be_bytes([response[12], response[13], response[14], response[15]]);
+	Ok((external, lifetime))
+}
+
+/// Sends `request` to the gateway, retrying with doubling timeouts as
+/// NAT-PMP asks clients to.
+async fn natpmp_call(gateway: Ipv4Addr, request: &[u8]) -> Result<Vec<u8>, String> {
+	let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+		.await
+		.map_err(|e| format!("failed to open socket: {e}"))?;
+	socket
+		.connect((gateway, NATPMP_PORT))
+		.await
+		.map_err(|e| format!("no route to gateway {gateway}: {e}"))?;
+	let mut wait = NATPMP_FIRST_TIMEOUT;
+	let mut buf = [0u8; 16];
+	for _ in 0..NATPMP_ATTEMPTS {
+		socket
+			.send(request)
+			.await
+			.map_err(|e| format!("failed to reach gateway {gateway}: {e}"))?;
+		match timeout(wait, socket.recv(&mut buf)).await {
+			Ok(Ok(len)) => return Ok(buf[..len].to_vec()),
+			Ok(Err(err)) => return Err(format!("gateway {gateway} refused NAT-PMP: {err}")),
+			Err(_) => wait *= 2,
+		}
+	}
+	Err(format!("gateway {gateway} did not answer NAT-PMP"))
+}
+
+async fn natpmp_map_port(
+	gateway: Ipv4Addr,
+	protocol: PortMappingProtocol,
+	internal: u16,
+	external: u16,
+	lifetime: u32,
+) -> Result<(u16, u32), String> {
+	let opcode = natpmp_opcode(protocol);
+	let request = natpmp_map_request(opcode, internal, external, lifetime);
+	parse_natpmp_mapping(&natpmp_call(gateway, &request).await?, opcode)
+}
+
+async fn map_upnp(ports: NatPorts) -> Result<Mapping, String> {
+	let options = igd_next::SearchOptions {
+		timeout: Some(GATEWAY_SEARCH_TIMEOUT),
+		..Default::default()
+	};
+	let gateway = search_gateway(options)
+		.await
+		.map_err(|e| format!("no UPnP router found ({e})"))?;
+	let local_ip = local_ip_towards(gateway.addr)?;
+	let external_ip = gateway
+		.get_external_ip()
+		.await
+		.map_err(|e| format!("UPnP router did not report its external address ({e})"))?;
+	let mut mapped = Vec::new();
+	for (protocol, port) in ports.entries() {
+		let local = SocketAddr::new(local_ip, port);
+		if let Err(err) = gateway
+			.add_port(protocol, port, local, UPNP_LEASE_SECS, MAPPING_DESCRIPTION)
+			.await
+		{
+			for (protocol, port) in mapped {
+				let _ = gateway.remove_port(protocol, port).await;
+			}
+			return Err(format!(
+				"UPnP router refused to forward {protocol} port {port} ({err})"
+			));
+		}
+		mapped.push((protocol, port));
+	}
+	Ok(Mapping {
+		gateway: MappedGateway::Upnp(gateway),
+		external_ip,
+		local: ports,
+		external: ports,
+		lease: Duration::from_secs(u64::from(UPNP_LEASE_SECS)),
+	})
+}
+
+async fn map_natpmp(ports: NatPorts) -> Result<Mapping, String> {
+	let gateway = default_gateway().ok_or_else(|| String::from("default gateway unknown"))?;
+	let external_ip = parse_natpmp_external_ip(&natpmp_call(gateway, &[0, 0]).await?)?;
+	let mut external = NatPorts::default();
+	let mut lease = u32::MAX;
+	for (protocol, port) in ports.entries() {
+		match natpmp_map_port(gateway, protocol, port, port, NATPMP_LIFETIME_SECS).await {
+			Ok((external_port, lifetime)) => {
+				match protocol {
+					PortMappingProtocol::TCP => external.tcp = Some(external_port),
+					PortMappingProtocol::UDP => external.quic = Some(external_port),
+				}
+				lease = lease.min(lifetime);
+			}
+			Err(err) => {
+				for (protocol, port) in ports.entries() {
+					let _ = natpmp_map_port(gateway, protocol, port, 0, 0).await;
+				}
+				return Err(err);
+			}
+		}
+	}
+	Ok(Mapping {
+		gateway: MappedGateway::NatPmp(gateway),
+		external_ip: IpAddr::V4(external_ip),
+		local: ports,
+		external,
+		lease: Duration::from_secs(u64::from(lease)),
+	})
+}
+
+async fn map_ports(ports: NatPorts) -> Result<Mapping, String> {
+	let upnp_err = match map_upnp(ports).await {
+		Ok(mapping) => return Ok(mapping),
+		Err(err) => err,
+	};
+	match map_natpmp(ports).await {
+		Ok(mapping) => Ok(mapping),
+		Err(natpmp_err) => Err(format!("UPnP: {upnp_err}; NAT-PMP: {natpmp_err}")),
+	}
+}
+
+async fn unmap(mapping: &Mapping) {
+	for (protocol, port) in mapping.local.entries() {
+		let result = match &mapping.gateway {
+			MappedGateway::Upnp(gateway) => {
+				let external = match protocol {
+					PortMappingProtocol::TCP => mapping.external.tcp,
+					PortMappingProtocol::UDP => mapping.external.quic,
+				};
+				gateway
+					.remove_port(protocol, external.unwrap_or(port))
+					.await
+					.map_err(|e| e.to_string())
+			}
+			MappedGateway::NatPmp(gateway) => natpmp_map_port(*gateway, protocol, port, 0, 0)
+				.await
+				.map(|_| ()),
+		};
+		if let Err(err) = result {
+			tracing::warn!("failed to remove {protocol} port mapping for {port}: {err}");
+		}
+	}
+}
+
+/// Keeps the swarm's ports forwarded on the local router, renewing leases
+/// and retrying with backoff. `report` receives every status change with
+/// the external addresses that currently reach this node.
+async fn run_mapper(
+	mut ports_rx: watch::Receiver<NatPorts>,
+	mut stop_rx: oneshot::Receiver<()>,
+	report: impl Fn(NatStatus, Vec<Multiaddr>),
+) {
+	let mut current: Option<Mapping> = None;
+	let mut backoff = RETRY_MIN;
+	loop {
+		let ports = *ports_rx.borrow_and_update();
+		if let Some(mapping) = current.take_if(|mapping| mapping.local != ports) {
+			unmap(&mapping).await;
+		}
+		let wait = if ports.is_empty() {
+			None
+		} else {
+			if current.is_none() {
+				report(NatStatus::Mapping, Vec::new());
+			}
+			match map_ports(ports).await {
+				Ok(mapping) if is_non_routable(mapping.external_ip) => {
+					let reason = format!(
+						"the router's external address {} is itself private, so this network is behind another NAT (double NAT) and a mapping would not make this node reachable",
+						mapping.external_ip
+					);
+					unmap(&mapping).await;
+					current = None;
+					report(
+						NatStatus::Failed {
+							reason,
+							retry_at: Utc::now() + RETRY_MAX,
+						},
+						Vec::new(),
+					);
+					Some(RETRY_MAX)
+				}
+				Ok(mapping) => {
+					backoff = RETRY_MIN;
+					let renew = mapping.lease / 2;
+					tracing::info!(
+						"mapped {:?} via {} to {}",
+						mapping.local,
+						mapping.method().label(),
+						mapping.external_ip
+					);
+					report(
+						NatStatus::Mapped {
+							external_ip: mapping.external_ip,
+							tcp_port: mapping.external.tcp,
+							quic_port: mapping.external.quic,
+							method: mapping.method(),
+							renews_at: Utc::now() + renew,
+						},
+						mapping.external_addrs(),
+					);
+					current = Some(mapping);
+					Some(renew)
+				}
+				Err(reason) => {
+					tracing::warn!("port mapping failed: {reason}");
+					current = None;
+					let wait = backoff;
+					backoff = (backoff * 2).min(RETRY_MAX);
+					report(
+						NatStatus::Failed {
+							reason,
+							retry_at: Utc::now() + wait,
+						},
+						Vec::new(),
+					);
+					Some(wait)
+				}
+			}
+		};
+		let sleep = async {
+			match wait {
+				Some(wait) => tokio::time::sleep(wait).await,
+				None => std::future::pending().await,
+			}
+		};
+		tokio::select! {
+			_ = &mut stop_rx => break,
+			changed = ports_rx.changed() => {
+				if changed.is_err() {
+					break;
+				}
+			}
+			_ = sleep => {}
+		}
+	}
+	if let Some(mapping) = current {
+		unmap(&mapping).await;
+	}
+}
+
+/// Handle to the background port mapper.
+pub(crate) struct NatMapper {
+	ports: watch::Sender<NatPorts>,
+	stop: oneshot::Sender<()>,
+	task: JoinHandle<()>,
+}
+
+impl NatMapper {
+	pub(crate) fn start(
+		ports: NatPorts,
+		report: impl Fn(NatStatus, Vec<Multiaddr>) + Send + 'static,
+	) -> Self {
+		let (ports_tx, ports_rx) = watch::channel(ports);
+		let (stop_tx, stop_rx) = oneshot::channel();
+		let task = tokio::spawn(run_mapper(ports_rx, stop_rx, report));
+		Self {
+			ports: ports_tx,
+			stop: stop_tx,
+			task,
+		}
+	}
+
+	pub(crate) fn set_ports(&self, ports: NatPorts) {
+		self.ports.send_if_modified(|current| {
+			let changed = *current != ports;
+			*current = ports;
+			changed
+		});
+	}
+
+	/// Stops renewing and removes the mapping from the router.
+	pub(crate) async fn stop(self) {
+		let _ = self.stop.send(());
+		let mut task = self.task;
+		if timeout(TEARDOWN_TIMEOUT, &mut task).await.is_err() {
+			tracing::warn!("router did not confirm removal of the port mapping in time");
+			task.abort();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn default_gateway_comes_from_the_route_table() {
+		let table = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\n\
+			eth0\t0000A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\n\
+			eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\n";
+		assert_eq!(
+			parse_default_gateway(table),
+			Some(Ipv4Addr::new(192, 168, 1, 1))
+		);
+		assert_eq!(parse_default_gateway("Iface\tDestination\tGateway\n"), None);
+	}
+
+	#[test]
+	fn natpmp_messages_round_trip() {
+		let request = natpmp_map_request(2, 8336, 8336, 3600);
+		assert_eq!(
+			request,
+			[0, 2, 0, 0, 0x20, 0x90, 0x20, 0x90, 0, 0, 0x0e, 0x10]
+		);
+
+		let external = [0, 128, 0, 0, 0, 0, 0, 1, 203, 0, 113, 7];
+		assert_eq!(
+			parse_natpmp_external_ip(&external),
+			Ok(Ipv4Addr::new(203, 0, 113, 7))
+		);
+		let mapping = [
+			0, 130, 0, 0, 0, 0, 0, 1, 0x20, 0x90, 0x20, 0x91, 0, 0, 0x0e, 0x10,
+		];
+		assert_eq!(parse_natpmp_mapping(&mapping, 2), Ok((8337, 3600)));
+		let refused = [0, 130, 0, 2, 0, 0, 0, 1, 0x20, 0x90, 0, 0, 0, 0, 0, 0];
+		assert_eq!(
+			parse_natpmp_mapping(&refused, 2),
+			Err(String::from("port mapping is disabled on the router"))
+		);
+		assert!(parse_natpmp_mapping(&mapping, 1).is_err());
+	}
+
+	#[test]
+	fn private_external_addresses_mean_double_nat() {
+		assert!(is_non_routable("192.168.0.10".parse().unwrap()));
+		assert!(is_non_routable("10.1.2.3".parse().unwrap()));
+		assert!(is_non_routable("100.72.0.1".parse().unwrap()));
+		assert!(!is_non_routable("100.128.0.1".parse().unwrap()));
+		assert!(!is_non_routable("203.0.113.7".parse().unwrap()));
+	}
+
+	#[test]
+	fn status_describes_the_mapping() {
+		let now = Utc::now();
+		let mapped = NatStatus::Mapped {
+			external_ip: "203.0.113.7".parse().unwrap(),
+			tcp_port: Some(8336),
+			quic_port: None,
+			method: NatMethod::Upnp,
+			renews_at: now + Duration::from_secs(20 * 60),
+		};
+		assert_eq!(
+			mapped.describe(now),
+			"External address 203.0.113.7:8336 mapped via UPnP, lease renews in 20m 00s"
+		);
+		let failed = NatStatus::Failed {
+			reason: String::from("no UPnP router found"),
+			retry_at: now + Duration::from_secs(30),
+		};
+		assert_eq!(
+			failed.describe(now),
+			"Port mapping failed: no UPnP router found; retrying in 30s"
+		);
+	}
+}
@@ -0,0 +1,361 @@
+//! Orders file names the way people read them. Runs of digits compare by
+//! their value, so "IMG_2" comes before "IMG_10", and letters compare
+//! without case or accents, so "Émile" sits with the other names starting
+//! with "e" instead of after "z". Accents, leading zeros and case only
+//! break ties, in that order, and the raw text after them, so the order is
+//! total and the same on every run.
+//!
+//! With the `locale-collation` feature names are compared by the Unicode
+//! collation rules of the locale in `LC_ALL`, `LC_COLLATE` or `LANG`
+//! instead, numbers still by value.
+//!
+//! Whoever shows a listing decides its order. Peers send directory
+//! listings sorted with [`natural_cmp`], but ones from before it sort by
+//! lowercased name, so the GUI sorts what it receives again. Index
+//! searches sorted by name are ordered by the node answering them, since
+//! they page through more rows than one answer holds.
+
+use std::cmp::Ordering;
+
+/// One piece of a name: a run of ASCII digits or one letter, folded.
+#[derive(Clone, Copy, Debug)]
+enum Unit<'a> {
+	Number {
+		/// The run without its leading zeros.
+		digits: &'a str,
+		zeros: usize,
+	},
+	Letter {
+		base: char,
+		accented: bool,
+		upper: bool,
+	},
+}
+
+impl Unit<'_> {
+	/// What orders a number against a letter: its first digit.
+	fn lead(&self) -> char {
+		match self {
+			Unit::Number { digits, .. } => digits.chars().next().unwrap_or('0'),
+			Unit::Letter { base, .. } => *base,
+		}
+	}
+}
+
+fn is_combining_mark(c: char) -> bool {
+	matches!(
+		c,
+		'\u{0300}'..='\u{036F}'
+			| '\u{1AB0}'..='\u{1AFF}'
+			| '\u{1DC0}'..='\u{1DFF}'
+			| '\u{20D0}'..='\u{20FF}'
+			| '\u{FE20}'..='\u{FE2F}'
+	)
+}
+
+/// The unaccented letter of a lowercase Latin letter, and whether it had
+/// an accent. Decomposed accents are combining marks and handled apart.
+fn strip_accent(c: char) -> (char, bool) {
+	let base = match c {
+		'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => 'a',
+		'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => 'c',
+		'ď' | 'đ' => 'd',
+		'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => 'e',
+		'ĝ' | 'ğ' | 'ġ' | 'ģ' => 'g',
+		'ĥ' | 'ħ' => 'h',
+		'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => 'i',
+		'ĵ' => 'j',
+		'ķ' => 'k',
+		'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => 'l',
+		'ñ' | 'ń' | 'ņ' | 'ň' => 'n',
+		'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => 'o',
+		'ŕ' | 'ŗ' | 'ř' => 'r',
+		'ś' | 'ŝ' | 'ş' | 'š' => 's',
+		'ţ' | 'ť' | 'ŧ' => 't',
+		'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => 'u',
+		'ŵ' => 'w',
+		'ý' | 'ÿ' | 'ŷ' => 'y',
+		'ź' | 'ż' | 'ž' => 'z',
+		c => return (c, false),
+	};
+	(base, true)
+}
+
+fn units(name: &str) -> Vec<Unit<'_>> {
+	let mut units = Vec::with_capacity(name.len());
+	let mut chars = name.char_indices().peekable();
+	while let Some((start, c)) = chars.next() {
+		if c.is_ascii_digit() {
+			let mut end = start + 1;
+			while let Some((_, digit)) = chars.next_if(|(_, next)| next.is_ascii_digit()) {
+				end += digit.len_utf8();
+			}
+			let run = &name[start..end];
+			let digits = run.trim_start_matches('0');
+			units.push(Unit::Number {
+				digits,
+				zeros: run.len() - digits.len(),
+			});
+			continue;
+		}
+		let upper = c.is_uppercase();
+		for lower in c.to_lowercase() {
+			if is_combining_mark(lower)
+				&& let Some(Unit::Letter { accented, .. }) = units.last_mut()
+			{
+				*accented = true;
+				continue;
+			}
+			let (base, accented) = strip_accent(lower);
+			units.push(Unit::Letter {
+				base,
+				accented,
+				upper,
+			});
+		}
+	}
+	units
+}
+
+/// Natural order of two names; see the module docs.
+#[cfg(not(feature = "locale-collation"))]
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+	folded_cmp(a, b)
+}
+
+/// Natural order of two names by the locale's collation rules, or without
+/// them when the locale has no collation data.
+#[cfg(feature = "locale-collation")]
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+	match locale::collator() {
+		Some(collator) => collator.compare(a, b).then_with(|| a.cmp(b)),
+		None => folded_cmp(a, b),
+	}
+}
+
+fn folded_cmp(a: &str, b: &str) -> Ordering {
+	let (left, right) = (units(a), units(b));
+	let mut accents = Ordering::Equal;
+	let mut zeros = Ordering::Equal;
+	let mut case = Ordering::Equal;
+	for (x, y) in left.iter().zip(&right) {
+		match (x, y) {
+			(
+				Unit::Number {
+					digits: x_digits,
+					zeros: x_zeros,
+				},
+				Unit::Number {
+					digits: y_digits,
+					zeros: y_zeros,
+				},
+			) => {
+				let by_value = x_digits
+					.len()
+					.cmp(&y_digits.len())
+					.then_with(|| x_digits.cmp(y_digits));
+				if by_value != Ordering::Equal {
+					return by_value;
+				}
+				zeros = zeros.then(x_zeros.cmp(y_zeros));
+			}
+			(
+				Unit::Letter {
+					base: x_base,
+					accented: x_accented,
+					upper: x_upper,
+				},
+				Unit::Letter {
+					base: y_base,
+					accented: y_accented,
+					upper: y_upper,
+				},
+			) => {
+				if x_base != y_base {
+					return x_base.cmp(y_base);
+				}
+				accents = accents.then(x_accented.cmp(y_accented));
+				case = case.then(x_upper.cmp(y_upper));
+			}
+			// Digits come before a letter of the same code point, which
+			// only a digit could be.
+			(Unit::Number { .. }, Unit::Letter { .. }) => {
+				return x.lead().cmp(&y.lead()).then(Ordering::Less);
+			}
+			(Unit::Letter { .. }, Unit::Number { .. }) => {
+				return x.lead().cmp(&y.lead()).then(Ordering::Greater);
+			}
+		}
+	}
+	left.len()
+		.cmp(&right.len())
+		.then(accents)
+		.then(zeros)
+		.then(case)
+		.then_with(|| a.cmp(b))
+}
+
+#[cfg(feature = "locale-collation")]
+mod locale {
+	use icu_collator::options::CollatorOptions;
+	use icu_collator::preferences::CollationNumericOrdering;
+	use icu_collator::{Collator, CollatorBorrowed, CollatorPreferences};
+	use icu_locale_core::Locale;
+	use std::sync::OnceLock;
+
+	/// `de_DE.UTF-8` as `de-DE`; `None` for the C locale or garbage.
+	fn env_locale() -> Option<Locale> {
+		let value = ["LC_ALL", "LC_COLLATE", "LANG"]
+			.iter()
+			.find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))?;
+		let tag = value
+			.split(['.', '@'])
+			.next()
+			.unwrap_or_default()
+			.replace('_', "-");
+		if tag == "C" || tag == "POSIX" {
+			return None;
+		}
+		Locale::try_from_str(&tag).ok()
+	}
+
+	pub(super) fn collator() -> Option<&'static CollatorBorrowed<'static>> {
+		static COLLATOR: OnceLock<Option<CollatorBorrowed<'static>>> = OnceLock::new();
+		COLLATOR
+			.get_or_init(|| {
+				let mut prefs = env_locale()
+					.map(|locale| CollatorPreferences::from(&locale))
+					.unwrap_or_default();
+				prefs.numeric_ordering = Some(CollationNumericOrdering::True);
+				Collator::try_new(prefs, CollatorOptions::default())
+					.inspect_err(|err| tracing::warn!("no collation for the locale: {err}"))
+					.ok()
+			})
+			.as_ref()
+	}
+}
+
+#[cfg(all(test, not(feature = "locale-collation")))]
+mod tests {
+	use super::*;
+
+	fn sorted(names: &[&str]) -> Vec<String> {
+		let mut names = names
+			.iter()
+			.map(|name| name.to_string())
+			.collect::<Vec<_>>();
+		names.sort_by(|a, b| natural_cmp(a, b));
+		names
+	}
+
+	#[test]
+	fn numbers_compare_by_value() {
+		assert_eq!(
+			sorted(&["File10", "File2", "File1", "file3", "File20"]),
+			["File1", "File2", "file3", "File10", "File20"]
+		);
+		assert_eq!(
+			sorted(&["IMG_0010.jpg", "IMG_9.jpg", "IMG_0100.jpg", "IMG_11.jpg"]),
+			["IMG_9.jpg", "IMG_0010.jpg", "IMG_11.jpg", "IMG_0100.jpg"]
+		);
+		// Longer than any integer type.
+		assert_eq!(
+			sorted(&["x100000000000000000000000", "x99999999999999999999999"]),
+			["x99999999999999999999999", "x100000000000000000000000"]
+		);
+	}
+
+	#[test]
+	fn leading_zeros_only_break_ties() {
+		assert_eq!(
+			sorted(&["007", "7", "07", "8", "0"]),
+			["0", "7", "07", "007", "8"]
+		);
+		assert_eq!(sorted(&["a01b", "a1c", "a1b"]), ["a1b", "a01b", "a1c"]);
+		assert_eq!(sorted(&["000", "00", "0", "1"]), ["0", "00", "000", "1"]);
+	}
+
+	#[test]
+	fn digits_and_letters_mix() {
+		assert_eq!(
+			sorted(&["v1.10", "v1.9", "v1.9a", "v1.9.1", "v10", "v2"]),
+			["v1.9", "v1.9.1", "v1.9a", "v1.10", "v2", "v10"]
+		);
+		assert_eq!(sorted(&["b", "2", "a", "10"]), ["2", "10", "a", "b"]);
+		assert_eq!(
+			sorted(&["track 2 live", "track 2", "track 12"]),
+			["track 2", "track 2 live", "track 12"]
+		);
+	}
+
+	#[test]
+	fn accents_group_with_their_letter() {
+		assert_eq!(
+			sorted(&["Zoe", "Émile", "Eva", "emile", "Ölaf", "Oscar"]),
+			["emile", "Émile", "Eva", "Ölaf", "Oscar", "Zoe"]
+		);
+		// A precomposed and a decomposed é order the same, and only the raw
+		// text tells them apart.
+		let precomposed = "caf\u{e9}";
+		let decomposed = "cafe\u{301}";
+		assert_eq!(
+			sorted(&[decomposed, "cafe", "cafes", precomposed]),
+			["cafe", decomposed, precomposed, "cafes"]
+		);
+		assert_eq!(
+			sorted(&["a\u{301}b", "ac", "aa"]),
+			["aa", "a\u{301}b", "ac"]
+		);
+	}
+
+	#[test]
+	fn case_ties_are_broken_the_same_way_every_time() {
+		let names = ["Readme", "README", "readme", "ReadMe"];
+		let expected = ["readme", "Readme", "ReadMe", "README"];
+		assert_eq!(sorted(&names), expected);
+		let mut reversed = names;
+		reversed.reverse();
+		assert_eq!(sorted(&reversed), expected);
+		assert_eq!(sorted(&["B", "a", "A", "b"]), ["a", "A", "b", "B"]);
+		assert_eq!(natural_cmp("Straße", "STRASSE"), Ordering::Greater);
+	}
+
+	#[test]
+	fn only_identical_names_are_equal() {
+		let names = [
+			"",
+			"0",
+			"00",
+			"a",
+			"A",
+			"á",
+			"a\u{301}",
+			"a1",
+			"a01",
+			"a 1",
+			"a-1",
+			"file10",
+			"File10",
+			"file010",
+			"ǅ",
+			"ǆ",
+			"İstanbul",
+			"istanbul",
+		];
+		for a in names {
+			for b in names {
+				assert_eq!(natural_cmp(a, b) == Ordering::Equal, a == b, "{a:?} {b:?}");
+				assert_eq!(
+					natural_cmp(a, b),
+					natural_cmp(b, a).reverse(),
+					"{a:?} {b:?}"
+				);
+				for c in names {
+					if natural_cmp(a, b).is_le() && natural_cmp(b, c).is_le() {
+						assert!(natural_cmp(a, c).is_le(), "{a:?} {b:?} {c:?}");
+					}
+				}
+			}
+		}
+	}
+}
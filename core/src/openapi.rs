@@ -0,0 +1,937 @@
+//! OpenAPI 3 description of the HTTP API. Request and response types are
+//! declared with [`api_struct!`], which emits the struct together with its
+//! schema so the two cannot drift apart; the route table in `http_api`
+//! names the type each endpoint takes and returns.
+
+use crate::activity_log::{ActivityEvent, ActivityEventKind};
+use crate::backup::{BackupKind, BackupRun};
+use crate::checksum_manifest::ChecksumSummary;
+use crate::db::{
+	FileSearchResult, ScanDiffEntry, ScanRun, ScanRunStatus, ScanTrend, StorageUsageFile,
+};
+use crate::diff::FileDiff;
+use crate::identity::IdentityMismatch;
+use crate::maintenance::Maintenance;
+use crate::p2p::{
+	CpuInfo, DirEntry, DiskInfo, EntryAccess, InterfaceInfo, ListingAccess, MimeSource,
+};
+use crate::password_policy::UserFieldError;
+use crate::permission_draft::{DraftKind, FieldError, RuleDraft};
+use crate::pins::PinStatus;
+use crate::power::{PowerReading, PowerState};
+use crate::preview::{FilePreview, PreviewKind};
+use crate::puppynet::ScanResultRow;
+use crate::reachability::{AddressReachability, Reachability};
+use crate::request_trace::{RequestDirection, RequestTrace};
+use crate::scan::{ScanChangeKind, ScanEvent, ScanProgress, ScanResult};
+use crate::scan_limits::{ScanLimits, ScanOverrides};
+use crate::state::{ConnectionDirection, FolderRule, Permission, Rule, TrustLevel};
+use crate::storage_growth::{DirectoryGrowth, DiskProjection, GrowthReport};
+use crate::types::FileChunk;
+use crate::updater::{UpdateErrorKind, UpdateProgress};
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value, json};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Version of the HTTP API, published as the document's `info.version`.
+/// Bump the major part whenever a request or response changes shape in a
+/// way existing clients cannot ignore.
+pub const API_VERSION: &str = "1.0.0";
+
+/// JSON schema of a type as it is serialized.
+pub(crate) trait ApiSchema {
+	fn schema() -> Value;
+
+	/// Whether an object field of this type is always present.
+	fn required() -> bool {
+		true
+	}
+}
+
+/// Object schema from `(name, schema, required)` fields.
+pub(crate) fn object_schema(fields: &[(&str, Value, bool)]) -> Value {
+	let mut properties = Map::new();
+	let mut required = Vec::new();
+	for (name, schema, is_required) in fields {
+		properties.insert(name.to_string(), schema.clone());
+		if *is_required {
+			required.push(Value::from(*name));
+		}
+	}
+	let mut schema = json!({ "type": "object", "properties": properties });
+	if !required.is_empty() {
+		schema["required"] = Value::from(required);
+	}
+	schema
+}
+
+fn string_enum(values: &[&str]) -> Value {
+	json!({ "type": "string", "enum": values })
+}
+
+/// Whether field attributes let serde skip the field, so clients may omit
+/// it in requests or not find it in responses.
+pub(crate) fn may_be_absent(attributes: &str) -> bool {
+	let attributes = attributes.split_whitespace().collect::<String>();
+	attributes.contains("serde(default") || attributes.contains("skip_serializing_if")
+}
+
+/// Externally tagged enum variant, `{ "<name>": <schema> }`.
+fn variant(name: &str, schema: Value) -> Value {
+	object_schema(&[(name, schema, true)])
+}
+
+/// Implements [`ApiSchema`] for a struct that is defined elsewhere, listing
+/// its serialized fields.
+macro_rules! impl_api_schema {
+	($ty:ty { $($field:ident: $field_ty:ty),* $(,)? }) => {
+		impl $crate::openapi::ApiSchema for $ty {
+			fn schema() -> serde_json::Value {
+				$crate::openapi::object_schema(&[$((
+					stringify!($field),
+					<$field_ty as $crate::openapi::ApiSchema>::schema(),
+					<$field_ty as $crate::openapi::ApiSchema>::required(),
+				)),*])
+			}
+		}
+	};
+}
+
+/// Declares a request or response struct and its schema in one go.
+macro_rules! api_struct {
+	(
+		$(#[$meta:meta])*
+		struct $name:ident {
+			$($(#[$field_meta:meta])* $field:ident: $field_ty:ty),* $(,)?
+		}
+	) => {
+		$(#[$meta])*
+		struct $name {
+			$($(#[$field_meta])* $field: $field_ty,)*
+		}
+
+		impl $crate::openapi::ApiSchema for $name {
+			fn schema() -> serde_json::Value {
+				$crate::openapi::object_schema(&[$((
+					stringify!($field),
+					<$field_ty as $crate::openapi::ApiSchema>::schema(),
+					<$field_ty as $crate::openapi::ApiSchema>::required()
+						&& !$crate::openapi::may_be_absent(stringify!($(#[$field_meta])*)),
+				)),*])
+			}
+		}
+	};
+}
+
+pub(crate) use api_struct;
+
+macro_rules! impl_primitive {
+	($($ty:ty => $schema:expr),* $(,)?) => {
+		$(impl ApiSchema for $ty {
+			fn schema() -> Value {
+				$schema
+			}
+		})*
+	};
+}
+
+impl_primitive! {
+	bool => json!({ "type": "boolean" }),
+	u8 => json!({ "type": "integer", "minimum": 0 }),
+	u32 => json!({ "type": "integer", "minimum": 0 }),
+	u64 => json!({ "type": "integer", "minimum": 0 }),
+	usize => json!({ "type": "integer", "minimum": 0 }),
+	i64 => json!({ "type": "integer" }),
+	f32 => json!({ "type": "number" }),
+	f64 => json!({ "type": "number" }),
+	String => json!({ "type": "string" }),
+	&'static str => json!({ "type": "string" }),
+	PathBuf => json!({ "type": "string" }),
+	DateTime<Utc> => json!({ "type": "string", "format": "date-time" }),
+	Value => json!({}),
+}
+
+impl<T: ApiSchema> ApiSchema for Option<T> {
+	fn schema() -> Value {
+		let mut schema = T::schema();
+		if let Some(object) = schema.as_object_mut() {
+			object.insert(String::from("nullable"), Value::Bool(true));
+		}
+		schema
+	}
+
+	fn required() -> bool {
+		false
+	}
+}
+
+impl<T: ApiSchema> ApiSchema for Vec<T> {
+	fn schema() -> Value {
+		json!({ "type": "array", "items": T::schema() })
+	}
+}
+
+impl ApiSchema for Duration {
+	fn schema() -> Value {
+		object_schema(&[
+			("secs", u64::schema(), true),
+			("nanos", u32::schema(), true),
+		])
+	}
+}
+
+impl ApiSchema for ActivityEventKind {
+	fn schema() -> Value {
+		string_enum(&["scan", "transfer", "permission", "peer", "deletion"])
+	}
+}
+
+impl ApiSchema for ConnectionDirection {
+	fn schema() -> Value {
+		string_enum(&["outbound", "inbound"])
+	}
+}
+
+impl ApiSchema for TrustLevel {
+	fn schema() -> Value {
+		string_enum(&["untrusted", "basic", "trusted", "owner"])
+	}
+}
+
+impl ApiSchema for MimeSource {
+	fn schema() -> Value {
+		string_enum(&["extension", "index", "sniffed"])
+	}
+}
+
+impl ApiSchema for PreviewKind {
+	fn schema() -> Value {
+		string_enum(&["text", "binary", "image", "too_large"])
+	}
+}
+
+impl ApiSchema for ScanRunStatus {
+	fn schema() -> Value {
+		string_enum(&["completed", "failed", "cancelled"])
+	}
+}
+
+impl ApiSchema for ScanChangeKind {
+	fn schema() -> Value {
+		string_enum(&["added", "changed", "removed"])
+	}
+}
+
+impl ApiSchema for Reachability {
+	fn schema() -> Value {
+		string_enum(&["reachable", "unreachable", "untested"])
+	}
+}
+
+impl ApiSchema for UpdateErrorKind {
+	fn schema() -> Value {
+		string_enum(&["transient", "permanent"])
+	}
+}
+
+impl ApiSchema for RequestDirection {
+	fn schema() -> Value {
+		string_enum(&["outbound", "inbound"])
+	}
+}
+
+impl ApiSchema for BackupKind {
+	fn schema() -> Value {
+		string_enum(&["backup", "restore"])
+	}
+}
+
+impl ApiSchema for DraftKind {
+	fn schema() -> Value {
+		string_enum(&["folder", "owner", "inbox"])
+	}
+}
+
+impl ApiSchema for Rule {
+	fn schema() -> Value {
+		json!({ "oneOf": [
+			string_enum(&["Owner", "Inbox"]),
+			variant("Folder", FolderRule::schema()),
+		] })
+	}
+}
+
+impl ApiSchema for ScanEvent {
+	fn schema() -> Value {
+		let finished = json!({ "oneOf": [
+			variant("Ok", ScanResult::schema()),
+			variant("Err", String::schema()),
+		] });
+		json!({ "oneOf": [
+			variant("Deferred", object_schema(&[("until", String::schema(), true)])),
+			variant("Progress", ScanProgress::schema()),
+			variant("Finished", finished),
+		] })
+	}
+}
+
+impl ApiSchema for UpdateProgress {
+	fn schema() -> Value {
+		json!({ "oneOf": [
+			string_enum(&["FetchingRelease", "Unpacking", "Verifying", "Installing"]),
+			variant("Deferred", object_schema(&[("until", String::schema(), true)])),
+			variant("Downloading", object_schema(&[
+				("filename", String::schema(), true),
+				("received", Option::<u64>::schema(), false),
+				("total", Option::<u64>::schema(), false),
+				("bytes_per_sec", Option::<u64>::schema(), false),
+				("eta_secs", Option::<u64>::schema(), false),
+			])),
+			variant("Completed", object_schema(&[("version", String::schema(), true)])),
+			variant("Failed", object_schema(&[
+				("error", String::schema(), true),
+				("kind", Option::<UpdateErrorKind>::schema(), false),
+			])),
+			variant("Retrying", object_schema(&[
+				("attempt", u32::schema(), true),
+				("max_attempts", u32::schema(), true),
+				("delay_secs", u64::schema(), true),
+				("error", String::schema(), true),
+			])),
+			variant("AlreadyUpToDate", object_schema(&[("current_version", u32::schema(), true)])),
+		] })
+	}
+}
+
+impl ApiSchema for FileDiff {
+	fn schema() -> Value {
+		// The text and binary bodies sit next to `kind`; see `diff.rs`.
+		object_schema(&[("kind", string_enum(&["text", "binary"]), true)])
+	}
+}
+
+impl_api_schema!(FolderRule {
+	path: PathBuf,
+	flags: u8,
+	deny_patterns: Vec<String>,
+	allow_patterns: Vec<String>,
+});
+impl_api_schema!(Permission { rule: Rule, expires_at: Option<i64> });
+impl_api_schema!(RuleDraft {
+	kind: DraftKind,
+	path: String,
+	read: bool,
+	write: bool,
+	search: bool,
+	execute: bool,
+	preview: bool,
+	quarantine: bool,
+	paired: bool,
+	deny_patterns: Vec<String>,
+	allow_patterns: Vec<String>,
+	expires_at: Option<i64>,
+});
+impl_api_schema!(FieldError {
+	index: usize,
+	field: &'static str,
+	message: String,
+});
+impl_api_schema!(UserFieldError {
+	field: String,
+	message: String,
+});
+impl_api_schema!(IdentityMismatch {
+	previous_node_id: String,
+	current_node_id: String,
+	explanation: String,
+});
+impl_api_schema!(FileChunk {
+	offset: u64,
+	data: Vec<u8>,
+	eof: bool,
+	hole: u64,
+});
+impl_api_schema!(AddressReachability {
+	addr: String,
+	status: Reachability,
+	tested_at: Option<DateTime<Utc>>,
+	tested_by: Option<String>,
+	latency_ms: Option<u64>,
+	error: Option<String>,
+});
+impl_api_schema!(PowerReading {
+	on_battery: bool,
+	battery_percent: Option<u8>,
+});
+impl_api_schema!(PowerState {
+	reading: Option<PowerReading>,
+	background_paused: bool,
+	run_anyway: bool,
+});
+impl_api_schema!(Maintenance {
+	reason: String,
+	expected_secs: Option<u64>,
+	since: DateTime<Utc>,
+});
+impl_api_schema!(ActivityEvent {
+	id: i64,
+	kind: ActivityEventKind,
+	at: DateTime<Utc>,
+	first_at: DateTime<Utc>,
+	peer: Option<String>,
+	summary: String,
+	ref_id: Option<String>,
+	count: u64,
+});
+impl_api_schema!(FilePreview {
+	kind: PreviewKind,
+	content: String,
+	truncated: bool,
+	detected_mime: String,
+	line_count: usize,
+	offset: u64,
+	next_offset: Option<u64>,
+});
+impl_api_schema!(DirEntry {
+	name: String,
+	name_raw: Vec<u8>,
+	is_dir: bool,
+	extension: Option<String>,
+	mime: Option<String>,
+	mime_source: MimeSource,
+	size: u64,
+	disk_size: Option<u64>,
+	created_at: Option<DateTime<Utc>>,
+	modified_at: Option<DateTime<Utc>>,
+	accessed_at: Option<DateTime<Utc>>,
+	hidden: bool,
+});
+impl_api_schema!(ListingAccess {
+	flags: u8,
+	entries: Vec<EntryAccess>,
+});
+impl_api_schema!(EntryAccess {
+	name: String,
+	rule: String,
+	flags: u8,
+});
+impl_api_schema!(StorageUsageFile {
+	node_id: Vec<u8>,
+	node_name: String,
+	path: String,
+	size: u64,
+	last_changed: Option<DateTime<Utc>>,
+});
+impl_api_schema!(FileSearchResult {
+	hash: Vec<u8>,
+	name: String,
+	path: String,
+	node_id: Vec<u8>,
+	size: u64,
+	mime_type: Option<String>,
+	replicas: u64,
+	first_datetime: Option<String>,
+	latest_datetime: Option<String>,
+	duration_ms: Option<u64>,
+	width: Option<u32>,
+	height: Option<u32>,
+	codec: Option<String>,
+	deleted_at: Option<i64>,
+});
+impl_api_schema!(ScanResultRow {
+	hash: Vec<u8>,
+	size: u64,
+	mime_type: Option<String>,
+	first_datetime: Option<String>,
+	latest_datetime: Option<String>,
+});
+impl_api_schema!(ScanProgress {
+	total_files: usize,
+	processed_files: usize,
+	inserted_count: u64,
+	updated_count: u64,
+	removed_count: u64,
+	bytes_per_sec: Option<u64>,
+	eta_secs: Option<u64>,
+	limits: Option<ScanLimits>,
+});
+impl_api_schema!(ScanLimits {
+	max_reads: u32,
+	max_read_mib: u32,
+	low_priority: bool,
+});
+impl_api_schema!(ScanOverrides {
+	max_reads: Option<u32>,
+	max_read_mib: Option<u32>,
+	low_priority: Option<bool>,
+});
+impl_api_schema!(ChecksumSummary {
+	manifests: u64,
+	claims: u64,
+	matched: u64,
+	mismatched: u64,
+	unverified: u64,
+	missing: u64,
+	malformed_lines: u64,
+});
+impl_api_schema!(ScanResult {
+	updated_count: u64,
+	inserted_count: u64,
+	removed_count: u64,
+	duration: Duration,
+	file_count: u64,
+	checksums: Option<ChecksumSummary>,
+});
+impl_api_schema!(ScanRun {
+	id: i64,
+	path: String,
+	started_at: DateTime<Utc>,
+	duration_ms: u64,
+	inserted_count: u64,
+	updated_count: u64,
+	removed_count: u64,
+	file_count: Option<u64>,
+	initiated_by: Option<String>,
+	status: ScanRunStatus,
+	error: Option<String>,
+	changes_truncated: bool,
+});
+impl_api_schema!(ScanTrend {
+	path: String,
+	file_count: u64,
+	baseline_file_count: u64,
+	baseline_started_at: DateTime<Utc>,
+});
+impl_api_schema!(ScanDiffEntry {
+	path: String,
+	change: ScanChangeKind,
+	old_hash: Option<Vec<u8>>,
+	new_hash: Option<Vec<u8>>,
+});
+impl_api_schema!(DirectoryGrowth {
+	path: String,
+	bytes_added: u64,
+	bytes_removed: u64,
+	net_bytes: i64,
+});
+impl_api_schema!(DiskProjection {
+	disk_id: String,
+	mount_path: String,
+	total_space: u64,
+	available_space: u64,
+	samples: u64,
+	bytes_per_day: Option<f64>,
+	days_until_low: Option<f64>,
+});
+impl_api_schema!(GrowthReport {
+	since: DateTime<Utc>,
+	until: DateTime<Utc>,
+	scans: u64,
+	insufficient_data: Option<String>,
+	directories: Vec<DirectoryGrowth>,
+	fastest_growing: Vec<DirectoryGrowth>,
+	low_space_percent: u8,
+	projection_days: i64,
+	disks: Vec<DiskProjection>,
+});
+impl_api_schema!(DiskInfo {
+	name: String,
+	mount_path: String,
+	filesystem: String,
+	total_space: u64,
+	available_space: u64,
+	usage_percent: f32,
+	total_read_bytes: u64,
+	total_written_bytes: u64,
+	read_only: bool,
+	removable: bool,
+	kind: String,
+	id: String,
+});
+impl_api_schema!(CpuInfo {
+	name: String,
+	usage: f32,
+	frequency_hz: u64,
+});
+impl_api_schema!(InterfaceInfo {
+	name: String,
+	mac: String,
+	ips: Vec<String>,
+	total_received: u64,
+	total_transmitted: u64,
+	packets_received: u64,
+	packets_transmitted: u64,
+	errors_on_received: u64,
+	errors_on_transmitted: u64,
+	mtu: u64,
+});
+impl_api_schema!(RequestTrace {
+	corr: u64,
+	direction: RequestDirection,
+	peer: String,
+	request: String,
+	at: DateTime<Utc>,
+	queue_ms: Option<u64>,
+	handler_ms: Option<u64>,
+	round_trip_ms: Option<u64>,
+	error: Option<String>,
+});
+impl_api_schema!(PinStatus {
+	id: u64,
+	peer: String,
+	remote_path: String,
+	local_dest: String,
+	interval_secs: u64,
+	bandwidth_limit: Option<u64>,
+	delete_removed: bool,
+	wake_if_needed: bool,
+	paused: bool,
+	syncing: bool,
+	created_at: DateTime<Utc>,
+	last_sync_at: Option<DateTime<Utc>>,
+	files_pending: u64,
+	bytes_transferred: u64,
+	last_error: Option<String>,
+});
+impl_api_schema!(BackupRun {
+	kind: BackupKind,
+	started_at: DateTime<Utc>,
+	finished_at: DateTime<Utc>,
+	path: String,
+	size_bytes: Option<u64>,
+	scheduled: bool,
+	error: Option<String>,
+});
+
+const JSON: &str = "application/json";
+
+/// One endpoint of the HTTP API. Path parameters are written `{name}`.
+pub(crate) struct ApiRoute {
+	method: &'static str,
+	path: &'static str,
+	summary: &'static str,
+	query: &'static [&'static str],
+	request: Option<Value>,
+	status: u16,
+	/// Schema by content type; empty for responses without a body.
+	content: Vec<(&'static str, Value)>,
+	public: bool,
+}
+
+impl ApiRoute {
+	/// A route answering 200 with a JSON body of unspecified shape.
+	pub(crate) fn new(method: &'static str, path: &'static str, summary: &'static str) -> Self {
+		Self {
+			method,
+			path,
+			summary,
+			query: &[],
+			request: None,
+			status: 200,
+			content: vec![(JSON, json!({ "type": "object" }))],
+			public: false,
+		}
+	}
+
+	pub(crate) fn query(mut self, names: &'static [&'static str]) -> Self {
+		self.query = names;
+		self
+	}
+
+	pub(crate) fn takes<T: ApiSchema>(mut self) -> Self {
+		self.request = Some(T::schema());
+		self
+	}
+
+	pub(crate) fn returns<T: ApiSchema>(mut self, status: u16) -> Self {
+		self.status = status;
+		self.content = vec![(JSON, T::schema())];
+		self
+	}
+
+	pub(crate) fn no_content(mut self) -> Self {
+		self.status = 204;
+		self.content.clear();
+		self
+	}
+
+	/// Answers with bytes of `content_type` instead of JSON.
+	pub(crate) fn raw(mut self, content_type: &'static str) -> Self {
+		let binary = json!({ "type": "string", "format": "binary" });
+		self.content = vec![(content_type, binary)];
+		self
+	}
+
+	/// JSON answer the client can ask for instead of the raw bytes.
+	pub(crate) fn or_json(mut self, schema: Value) -> Self {
+		self.content.push((JSON, schema));
+		self
+	}
+
+	pub(crate) fn public(mut self) -> Self {
+		self.public = true;
+		self
+	}
+
+	fn operation(&self, error: &Value) -> Value {
+		let mut parameters = self
+			.path
+			.split('/')
+			.filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+			.map(
+				|name| json!({ "name": name, "in": "path", "required": true, "schema": String::schema() }),
+			)
+			.collect::<Vec<_>>();
+		parameters.extend(self.query.iter().map(
+			|name| json!({ "name": name, "in": "query", "required": false, "schema": String::schema() }),
+		));
+		let mut success = json!({ "description": self.summary });
+		if !self.content.is_empty() {
+			let content = self
+				.content
+				.iter()
+				.map(|(content_type, schema)| {
+					(content_type.to_string(), json!({ "schema": schema }))
+				})
+				.collect::<Map<_, _>>();
+			success["content"] = Value::Object(content);
+		}
+		let mut operation = json!({
+			"summary": self.summary,
+			"parameters": parameters,
+			"responses": {
+				(self.status.to_string()): success,
+				"default": {
+					"description": "Error",
+					"content": { JSON: { "schema": error } },
+				},
+			},
+		});
+		if let Some(schema) = &self.request {
+			operation["requestBody"] = json!({
+				"required": true,
+				"content": { JSON: { "schema": schema } },
+			});
+		}
+		if self.public {
+			operation["security"] = json!([]);
+		}
+		operation
+	}
+}
+
+/// The OpenAPI document for `routes`; `E` is the error envelope every
+/// route may answer with.
+pub(crate) fn document<E: ApiSchema>(routes: &[ApiRoute]) -> Value {
+	let error = E::schema();
+	let mut paths = Map::new();
+	for route in routes {
+		let item = paths
+			.entry(route.path)
+			.or_insert_with(|| Value::Object(Map::new()));
+		item[route.method] = route.operation(&error);
+	}
+	json!({
+		"openapi": "3.0.3",
+		"info": {
+			"title": "PuppyNet HTTP API",
+			"version": API_VERSION,
+		},
+		"paths": paths,
+		"components": {
+			"securitySchemes": {
+				"bearer": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" },
+				"session": { "type": "apiKey", "in": "cookie", "name": "sid" },
+			},
+		},
+		"security": [{ "bearer": [] }, { "session": [] }],
+	})
+}
+
+/// Page at `/api/docs` that lists the operations of `/api/openapi.json`.
+pub(crate) const DOCS_HTML: &str = r#"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>PuppyNet HTTP API</title>
+<style>
+body { font-family: sans-serif; margin: 2em; max-width: 60em; }
+details { border-bottom: 1px solid #ccc; padding: 0.4em 0; }
+code { font-weight: bold; }
+pre { background: #f4f4f4; padding: 0.6em; overflow: auto; }
+</style>
+</head>
+<body>
+<h1 id="title">PuppyNet HTTP API</h1>
+<div id="operations"></div>
+<script>
+fetch("/api/openapi.json").then((r) => r.json()).then((spec) => {
+	document.getElementById("title").textContent = `${spec.info.title} ${spec.info.version}`;
+	const root = document.getElementById("operations");
+	for (const [path, item] of Object.entries(spec.paths)) {
+		for (const [method, op] of Object.entries(item)) {
+			const details = document.createElement("details");
+			const summary = document.createElement("summary");
+			summary.innerHTML = `<code>${method.toUpperCase()} ${path}</code> `;
+			summary.append(op.summary);
+			const body = document.createElement("pre");
+			body.textContent = JSON.stringify(op, null, 2);
+			details.append(summary, body);
+			root.append(details);
+		}
+	}
+});
+</script>
+</body>
+</html>
+"#;
+
+/// Checks `value` against the subset of JSON schema this module emits.
+#[cfg(test)]
+pub(crate) fn validate(schema: &Value, value: &Value, at: &str) -> Result<(), String> {
+	if value.is_null() {
+		if schema["nullable"] == json!(true) || schema.get("type").is_none() {
+			return Ok(());
+		}
+		return Err(format!("{at}: unexpected null"));
+	}
+	if let Some(options) = schema["oneOf"].as_array() {
+		let matches = options
+			.iter()
+			.filter(|option| validate(option, value, at).is_ok())
+			.count();
+		if matches != 1 {
+			return Err(format!("{at}: {value} matches {matches} of oneOf"));
+		}
+		return Ok(());
+	}
+	if let Some(allowed) = schema["enum"].as_array()
+		&& !allowed.contains(value)
+	{
+		return Err(format!("{at}: {value} is not one of {allowed:?}"));
+	}
+	let type_ok = match schema["type"].as_str() {
+		None => true,
+		Some("object") => value.is_object(),
+		Some("array") => value.is_array(),
+		Some("string") => value.is_string(),
+		Some("integer") => value.is_i64() || value.is_u64(),
+		Some("number") => value.is_number(),
+		Some("boolean") => value.is_boolean(),
+		Some(other) => return Err(format!("{at}: unknown schema type {other}")),
+	};
+	if !type_ok {
+		return Err(format!("{at}: {value} is not {}", schema["type"]));
+	}
+	if schema["minimum"] == json!(0) && value.as_i64().is_some_and(|n| n < 0) {
+		return Err(format!("{at}: {value} is negative"));
+	}
+	if let Some(object) = value.as_object() {
+		for name in schema["required"].as_array().into_iter().flatten() {
+			let name = name.as_str().unwrap_or_default();
+			if !object.contains_key(name) {
+				return Err(format!("{at}: missing {name}"));
+			}
+		}
+		if let Some(properties) = schema["properties"].as_object() {
+			for (name, property) in properties {
+				if let Some(field) = object.get(name) {
+					validate(property, field, &format!("{at}.{name}"))?;
+				}
+			}
+		}
+	}
+	if let (Some(items), Some(values)) = (schema.get("items"), value.as_array()) {
+		for (i, item) in values.iter().enumerate() {
+			validate(items, item, &format!("{at}[{i}]"))?;
+		}
+	}
+	Ok(())
+}
+
+/// Schema of the `status` response of `method path` in `doc`.
+#[cfg(test)]
+pub(crate) fn response_schema(doc: &Value, method: &str, path: &str, status: u16) -> Value {
+	let response = &doc["paths"][path][method]["responses"][status.to_string()];
+	assert!(
+		!response.is_null(),
+		"no {status} response for {method} {path}"
+	);
+	response["content"]["application/json"]["schema"].clone()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::state::FLAG_READ;
+
+	#[test]
+	fn nested_schemas_validate_serialized_values() {
+		let permission = Permission::new(Rule::Folder(FolderRule::new("/srv".into(), FLAG_READ)));
+		let schema = Vec::<Permission>::schema();
+		validate(
+			&schema,
+			&json!([permission, Permission::new(Rule::Owner)]),
+			"$",
+		)
+		.unwrap();
+		assert!(validate(&schema, &json!([{ "rule": "Folder" }]), "$").is_err());
+		assert!(validate(&schema, &json!([{ "expires_at": 1 }]), "$").is_err());
+
+		let progress = UpdateProgress::Failed {
+			error: String::from("bad signature"),
+			kind: Some(UpdateErrorKind::Permanent),
+		};
+		validate(&UpdateProgress::schema(), &json!(progress), "$").unwrap();
+		validate(
+			&UpdateProgress::schema(),
+			&json!(UpdateProgress::Verifying),
+			"$",
+		)
+		.unwrap();
+
+		let finished = ScanEvent::Finished(Err(String::from("Scan cancelled")));
+		validate(&ScanEvent::schema(), &json!(finished), "$").unwrap();
+		assert!(validate(&ScanEvent::schema(), &json!({ "Finished": 3 }), "$").is_err());
+	}
+
+	#[test]
+	fn options_are_nullable_and_optional() {
+		let schema = ScanTrend::schema();
+		assert_eq!(schema["required"].as_array().unwrap().len(), 4);
+		let run = ScanRun::schema();
+		let required = run["required"].as_array().unwrap();
+		assert!(!required.contains(&json!("file_count")));
+		assert_eq!(run["properties"]["file_count"]["nullable"], json!(true));
+	}
+
+	#[test]
+	fn document_declares_path_parameters_and_public_routes() {
+		let routes = [
+			ApiRoute::new("get", "/api/peers/{peer_id}/dir", "List a directory")
+				.query(&["path"])
+				.returns::<Vec<DirEntry>>(200),
+			ApiRoute::new("get", "/health", "Liveness probe")
+				.raw("text/plain")
+				.public(),
+		];
+		let doc = document::<String>(&routes);
+		assert_eq!(doc["info"]["version"], json!(API_VERSION));
+		let list = &doc["paths"]["/api/peers/{peer_id}/dir"]["get"];
+		let names = list["parameters"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|param| {
+				(
+					param["name"].as_str().unwrap(),
+					param["in"].as_str().unwrap(),
+				)
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(names, vec![("peer_id", "path"), ("path", "query")]);
+		assert!(list.get("security").is_none());
+		assert_eq!(doc["paths"]["/health"]["get"]["security"], json!([]));
+	}
+}
@@ -0,0 +1,817 @@
+//! Local folders whose new files go to a peer on their own, like the
+//! folder a scanner or camera drops its files in. Every few seconds the
+//! files directly inside each watched folder are listed; one that kept its
+//! size and modification time for [`OUTBOX_QUIET_PERIOD`] is written into
+//! the rule's folder on the peer through the write path. The copy is then
+//! read back and compared by hash, and only a verified copy lets the
+//! original be deleted; otherwise, or without `delete_after_send`, it is
+//! moved into the `sent` folder next to it.
+//!
+//! Nothing the rule itself produces is matched again: the `sent` folder is
+//! not listed, and when the peer is this node, neither is the destination.
+//! A failed file waits with a growing delay before it is tried again, and
+//! files wait without losing their place while the peer is away.
+
+use crate::activity_window::{ActivityKind, ActivityWindow};
+use crate::app::Command;
+use crate::checksum_manifest::matches_pattern;
+use crate::db::{load_outbox_rules, record_outbox_error, record_outbox_send};
+use crate::db_pool::Db;
+use crate::maintenance::MaintenanceGate;
+use crate::p2p::{DirEntry, WirePath};
+use crate::path::SafePath;
+use crate::pins::remote_child;
+use crate::power::PowerGate;
+use crate::transfers::{Transfer, TransferDirection};
+use crate::types::FileChunk;
+use anyhow::{Result, anyhow, bail};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+/// How often watched folders are listed.
+pub(crate) const OUTBOX_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How long a file must stay the same size and age before it is sent.
+pub(crate) const OUTBOX_QUIET_PERIOD: Duration = Duration::from_secs(10);
+/// Folder inside the watched one that sent originals are moved to.
+pub const OUTBOX_SENT_FOLDER: &str = "sent";
+const OUTBOX_CHUNK_SIZE: u64 = 1024 * 1024;
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30 * 60);
+/// Names tried before a file that exists on the peer is given up on.
+const MAX_NAME_ATTEMPTS: u32 = 100;
+
+/// What a watched folder sends where.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutboxRule {
+	pub watch_path: PathBuf,
+	pub dest_peer: PeerId,
+	/// Folder on the peer the files are written to.
+	pub dest_path: String,
+	/// Delete the original once the copy on the peer matches it, instead
+	/// of moving it to the `sent` folder.
+	pub delete_after_send: bool,
+	/// File names to send, `*` and `?` standing for any characters; all
+	/// files when empty.
+	pub pattern: String,
+}
+
+/// A rule with how its sending went.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct OutboxStatus {
+	pub id: u64,
+	pub watch_path: String,
+	pub dest_peer: String,
+	pub dest_path: String,
+	pub delete_after_send: bool,
+	pub pattern: String,
+	pub paused: bool,
+	pub created_at: DateTime<Utc>,
+	/// Name of the last file that arrived on the peer.
+	pub last_sent_file: Option<String>,
+	pub last_sent_at: Option<DateTime<Utc>>,
+	pub files_sent: u64,
+	/// Matching files waiting in the folder, as of the last look.
+	pub pending: u64,
+	pub last_error: Option<String>,
+}
+
+impl OutboxStatus {
+	fn matches(&self, name: &str) -> bool {
+		let pattern = self.pattern.trim();
+		pattern.is_empty() || matches_pattern(name, pattern)
+	}
+}
+
+/// Time to wait before trying a file again after `failures` failures in a
+/// row.
+pub(crate) fn retry_delay(failures: u32) -> Duration {
+	MIN_RETRY_DELAY
+		.saturating_mul(1 << failures.saturating_sub(1).min(16))
+		.min(MAX_RETRY_DELAY)
+}
+
+/// Peer access sending needs. Implemented over the command channel; tests
+/// use an in-memory peer.
+#[async_trait]
+pub(crate) trait OutboxTarget: Send + Sync {
+	async fn local_peer(&self) -> Option<PeerId>;
+	async fn is_connected(&self, peer: PeerId) -> bool;
+	async fn stat(&self, peer: PeerId, path: &str) -> Result<DirEntry>;
+	async fn write(&self, peer: PeerId, path: &str, offset: u64, data: Vec<u8>) -> Result<()>;
+	async fn read_file(
+		&self,
+		peer: PeerId,
+		path: &str,
+		offset: u64,
+		length: u64,
+	) -> Result<FileChunk>;
+	fn record_transfer(&self, transfer: Transfer);
+}
+
+#[async_trait]
+impl OutboxTarget for UnboundedSender<Command> {
+	async fn local_peer(&self) -> Option<PeerId> {
+		let (tx, rx) = oneshot::channel();
+		self.send(Command::GetLocalPeerId { tx }).ok()?;
+		rx.await.ok()
+	}
+
+	async fn is_connected(&self, peer: PeerId) -> bool {
+		let (tx, rx) = oneshot::channel();
+		if self.send(Command::GetState { tx }).is_err() {
+			return false;
+		}
+		rx.await.is_ok_and(|state| {
+			state.me == peer || state.connections.iter().any(|conn| conn.peer_id == peer)
+		})
+	}
+
+	async fn stat(&self, peer: PeerId, path: &str) -> Result<DirEntry> {
+		let (tx, rx) = oneshot::channel();
+		self.send(Command::StatFile {
+			peer,
+			path: SafePath::remote(&WirePath::from(path))?,
+			tx,
+		})
+		.map_err(|e| anyhow!("failed to send StatFile command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("StatFile response channel closed: {e}"))?
+	}
+
+	async fn write(&self, peer: PeerId, path: &str, offset: u64, data: Vec<u8>) -> Result<()> {
+		let (tx, rx) = oneshot::channel();
+		self.send(Command::WriteFile {
+			peer,
+			path: SafePath::remote(&WirePath::from(path))?,
+			offset,
+			data,
+			tx,
+		})
+		.map_err(|e| anyhow!("failed to send WriteFile command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("WriteFile response channel closed: {e}"))?
+			.map(|_| ())
+	}
+
+	async fn read_file(
+		&self,
+		peer: PeerId,
+		path: &str,
+		offset: u64,
+		length: u64,
+	) -> Result<FileChunk> {
+		let (tx, rx) = oneshot::channel();
+		self.send(Command::ReadFile(crate::app::ReadFileCmd {
+			peer_id: peer,
+			path: SafePath::remote(&WirePath::from(path))?,
+			offset,
+			length: Some(length),
+			sparse: false,
+			tx,
+		}))
+		.map_err(|e| anyhow!("failed to send ReadFile command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ReadFile response channel closed: {e}"))?
+	}
+
+	fn record_transfer(&self, transfer: Transfer) {
+		if let Err(err) = self.send(Command::RecordTransfer { transfer }) {
+			tracing::warn!("failed to queue transfer record: {err}");
+		}
+	}
+}
+
+/// A file seen in a watched folder.
+struct WatchedFile {
+	size: u64,
+	modified: Option<SystemTime>,
+	/// When the file was last seen to change.
+	changed_at: Instant,
+	failures: u32,
+	retry_at: Option<Instant>,
+}
+
+/// Rules being worked on and the files they are waiting on, which only
+/// live as long as the process.
+#[derive(Default)]
+pub(crate) struct OutboxRuns {
+	running: Mutex<HashSet<u64>>,
+	files: Mutex<HashMap<(u64, PathBuf), WatchedFile>>,
+	pending: Mutex<HashMap<u64, u64>>,
+}
+
+impl OutboxRuns {
+	/// Notes the size and age `path` has at `now` and returns whether it
+	/// is ready to send: unchanged for the quiet period and not waiting
+	/// to be retried.
+	pub(crate) fn observe(
+		&self,
+		rule: u64,
+		path: &Path,
+		size: u64,
+		modified: Option<SystemTime>,
+		now: Instant,
+	) -> bool {
+		let mut files = self.files.lock().unwrap();
+		let file = files
+			.entry((rule, path.to_path_buf()))
+			.or_insert_with(|| WatchedFile {
+				size,
+				modified,
+				changed_at: now,
+				failures: 0,
+				retry_at: None,
+			});
+		if file.size != size || file.modified != modified {
+			file.size = size;
+			file.modified = modified;
+			file.changed_at = now;
+		}
+		now.duration_since(file.changed_at) >= OUTBOX_QUIET_PERIOD
+			&& file.retry_at.is_none_or(|at| now >= at)
+	}
+
+	fn failed(&self, rule: u64, path: &Path, now: Instant) {
+		if let Some(file) = self
+			.files
+			.lock()
+			.unwrap()
+			.get_mut(&(rule, path.to_path_buf()))
+		{
+			file.failures += 1;
+			file.retry_at = Some(now + retry_delay(file.failures));
+		}
+	}
+
+	/// Drops what is known about files of `rule` that are no longer in
+	/// its folder.
+	fn retain(&self, rule: u64, present: &HashSet<PathBuf>) {
+		self.files
+			.lock()
+			.unwrap()
+			.retain(|(id, path), _| *id != rule || present.contains(path));
+		self.pending
+			.lock()
+			.unwrap()
+			.insert(rule, present.len() as u64);
+	}
+
+	fn begin(&self, rule: u64) -> bool {
+		self.running.lock().unwrap().insert(rule)
+	}
+
+	fn end(&self, rule: u64) {
+		self.running.lock().unwrap().remove(&rule);
+	}
+
+	/// Makes every file of `rule` ready to be tried again and forgets
+	/// its failures.
+	pub(crate) fn forget(&self, rule: u64) {
+		self.files.lock().unwrap().retain(|(id, _), _| *id != rule);
+		self.pending.lock().unwrap().remove(&rule);
+	}
+
+	/// Fills in the live parts of `status`.
+	pub(crate) fn apply(&self, status: &mut OutboxStatus) {
+		if let Some(pending) = self.pending.lock().unwrap().get(&status.id) {
+			status.pending = *pending;
+		}
+	}
+}
+
+/// A matching file in a watched folder.
+struct Candidate {
+	path: PathBuf,
+	name: String,
+	size: u64,
+	modified: Option<SystemTime>,
+}
+
+/// Files directly inside the folder of `rule` that it sends. Folders,
+/// symlinks and names that aren't valid UTF-8 are left alone, and so is
+/// the destination when it is in the watched folder on this node.
+fn candidates(rule: &OutboxStatus, local: bool) -> Result<Vec<Candidate>> {
+	let watch = Path::new(&rule.watch_path);
+	let dest = Path::new(&rule.dest_path);
+	let mut files = Vec::new();
+	for entry in std::fs::read_dir(watch)? {
+		let entry = entry?;
+		let path = entry.path();
+		let meta = entry.metadata()?;
+		if !meta.file_type().is_file() || (local && path.starts_with(dest)) {
+			continue;
+		}
+		let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+			continue;
+		};
+		if !rule.matches(name) {
+			continue;
+		}
+		files.push(Candidate {
+			name: name.to_string(),
+			size: meta.len(),
+			modified: meta.modified().ok(),
+			path,
+		});
+	}
+	files.sort_by(|a, b| a.modified.cmp(&b.modified).then(a.name.cmp(&b.name)));
+	Ok(files)
+}
+
+/// `name` with ` (n)` before its extension.
+fn numbered_name(name: &str, n: u32) -> String {
+	match name.rsplit_once('.') {
+		Some((stem, ext)) if !stem.is_empty() => format!("{stem} ({n}).{ext}"),
+		_ => format!("{name} ({n})"),
+	}
+}
+
+/// A path in `dest` on `peer` for `name` that holds no file yet, so
+/// nothing there is overwritten.
+async fn free_remote_path<T: OutboxTarget + ?Sized>(
+	target: &T,
+	peer: PeerId,
+	dest: &str,
+	name: &str,
+) -> Result<String> {
+	for n in 1..=MAX_NAME_ATTEMPTS {
+		let candidate = if n == 1 {
+			name.to_string()
+		} else {
+			numbered_name(name, n)
+		};
+		let path = remote_child(dest, &candidate);
+		if target.stat(peer, &path).await.is_err() {
+			return Ok(path);
+		}
+	}
+	bail!("{dest} on the peer already holds {MAX_NAME_ATTEMPTS} files named like {name}")
+}
+
+/// Writes `local` to `remote` on `peer` and returns its hash and size.
+async fn write_file<T: OutboxTarget + ?Sized>(
+	target: &T,
+	peer: PeerId,
+	local: &Path,
+	remote: &str,
+) -> Result<(blake3::Hash, u64)> {
+	let mut file = tokio::fs::File::open(local).await?;
+	let mut hasher = blake3::Hasher::new();
+	let mut buf = vec![0u8; OUTBOX_CHUNK_SIZE as usize];
+	let mut offset = 0u64;
+	loop {
+		let read = file.read(&mut buf).await?;
+		if read == 0 {
+			break;
+		}
+		hasher.update(&buf[..read]);
+		target
+			.write(peer, remote, offset, buf[..read].to_vec())
+			.await?;
+		offset += read as u64;
+	}
+	if offset == 0 {
+		// An empty file still has to exist on the peer.
+		target.write(peer, remote, 0, Vec::new()).await?;
+	}
+	Ok((hasher.finalize(), offset))
+}
+
+/// Hash of `remote` on `peer`, read back in full.
+async fn remote_hash<T: OutboxTarget + ?Sized>(
+	target: &T,
+	peer: PeerId,
+	remote: &str,
+) -> Result<blake3::Hash> {
+	let mut hasher = blake3::Hasher::new();
+	let mut offset = 0u64;
+	loop {
+		let chunk = target
+			.read_file(peer, remote, offset, OUTBOX_CHUNK_SIZE)
+			.await?;
+		hasher.update(&chunk.data);
+		offset += chunk.data.len() as u64;
+		if chunk.eof || chunk.data.is_empty() {
+			return Ok(hasher.finalize());
+		}
+	}
+}
+
+/// Moves `path` into the `sent` folder next to it, under a free name.
+fn move_to_sent(path: &Path) -> Result<PathBuf> {
+	let parent = path
+		.parent()
+		.ok_or_else(|| anyhow!("{} has no folder", path.display()))?;
+	let name = path
+		.file_name()
+		.and_then(|name| name.to_str())
+		.ok_or_else(|| anyhow!("invalid file name: {}", path.display()))?;
+	let sent = parent.join(OUTBOX_SENT_FOLDER);
+	std::fs::create_dir_all(&sent)?;
+	for n in 1..=MAX_NAME_ATTEMPTS {
+		let dest = if n == 1 {
+			sent.join(name)
+		} else {
+			sent.join(numbered_name(name, n))
+		};
+		if !dest.exists() {
+			std::fs::rename(path, &dest)?;
+			return Ok(dest);
+		}
+	}
+	bail!("{} already holds files named like {name}", sent.display())
+}
+
+/// What became of the original after its copy arrived.
+#[derive(Debug, PartialEq, Eq)]
+enum Sent {
+	Deleted,
+	Moved(PathBuf),
+}
+
+/// Sends one file of `rule` and puts the original away.
+async fn send_one<T: OutboxTarget + ?Sized>(
+	target: &T,
+	peer: PeerId,
+	rule: &OutboxStatus,
+	file: &Candidate,
+) -> Result<(String, u64, Sent, bool)> {
+	let remote = free_remote_path(target, peer, &rule.dest_path, &file.name).await?;
+	let (hash, size) = write_file(target, peer, &file.path, &remote).await?;
+	let verified = match remote_hash(target, peer, &remote).await {
+		Ok(copy) if copy == hash => true,
+		Ok(_) => bail!(
+			"the copy at {remote} does not match {}",
+			file.path.display()
+		),
+		Err(err) => {
+			tracing::debug!("could not read back {remote} to verify it: {err:#}");
+			false
+		}
+	};
+	// Deleting needs proof that the peer has the same bytes.
+	let sent = if rule.delete_after_send && verified {
+		std::fs::remove_file(&file.path)?;
+		Sent::Deleted
+	} else {
+		Sent::Moved(move_to_sent(&file.path)?)
+	};
+	Ok((remote, size, sent, verified))
+}
+
+fn lock(db: &Db) -> Result<std::sync::MutexGuard<'_, Connection>> {
+	db.lock().map_err(|err| anyhow!("db lock poisoned: {err}"))
+}
+
+/// Sends the files of `rule` that are ready at `now`, oldest first. Files
+/// still changing wait; while the peer is away nothing is tried.
+pub(crate) async fn process_rule<T: OutboxTarget + ?Sized>(
+	target: &T,
+	db: &Db,
+	runs: &OutboxRuns,
+	rule: &OutboxStatus,
+	now: Instant,
+) -> Result<()> {
+	let peer: PeerId = rule
+		.dest_peer
+		.parse()
+		.map_err(|err| anyhow!("invalid peer id {}: {err}", rule.dest_peer))?;
+	let local = target.local_peer().await == Some(peer);
+	let files = candidates(rule, local)
+		.map_err(|err| anyhow!("failed to list {}: {err}", rule.watch_path))?;
+	runs.retain(
+		rule.id,
+		&files.iter().map(|file| file.path.clone()).collect(),
+	);
+	let ready = files
+		.iter()
+		.filter(|file| runs.observe(rule.id, &file.path, file.size, file.modified, now))
+		.collect::<Vec<_>>();
+	if ready.is_empty() {
+		return Ok(());
+	}
+	if !target.is_connected(peer).await {
+		let waiting = format!(
+			"waiting for the peer to connect; {} file{} queued",
+			files.len(),
+			if files.len() == 1 { "" } else { "s" }
+		);
+		record_outbox_error(&*lock(db)?, rule.id, Some(&waiting))?;
+		return Ok(());
+	}
+	for file in ready {
+		let started = Instant::now();
+		let result = send_one(target, peer, rule, file).await;
+		let local_path = file.path.to_string_lossy();
+		match result {
+			Ok((remote, size, sent, verified)) => {
+				let mut transfer = Transfer::new(
+					TransferDirection::Upload,
+					peer,
+					remote,
+					local_path,
+					started.elapsed(),
+				);
+				transfer.size = size;
+				transfer.note = match (&sent, verified) {
+					(_, false) => Some("not verified; original kept in sent".to_string()),
+					(Sent::Deleted, true) => Some("verified; original deleted".to_string()),
+					(Sent::Moved(_), true) => None,
+				};
+				target.record_transfer(transfer);
+				record_outbox_send(&*lock(db)?, rule.id, &file.name, Utc::now())?;
+			}
+			Err(err) => {
+				tracing::warn!("outbox {}: failed to send {}: {err:#}", rule.id, file.name);
+				runs.failed(rule.id, &file.path, now);
+				target.record_transfer(
+					Transfer::new(
+						TransferDirection::Upload,
+						peer,
+						remote_child(&rule.dest_path, &file.name),
+						local_path,
+						started.elapsed(),
+					)
+					.failed(&err),
+				);
+				let error = format!("failed to send {}: {err:#}", file.name);
+				record_outbox_error(&*lock(db)?, rule.id, Some(&error))?;
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Looks at every rule that isn't paused or already being worked on, while
+/// the activity window is open for syncs and there is neither a power hold
+/// nor maintenance.
+pub(crate) async fn start_due_outboxes(
+	target: &Arc<UnboundedSender<Command>>,
+	db: &Arc<Db>,
+	window: &Arc<Mutex<ActivityWindow>>,
+	power: &Arc<Mutex<PowerGate>>,
+	maintenance: &Arc<MaintenanceGate>,
+	runs: &Arc<OutboxRuns>,
+) {
+	if !window
+		.lock()
+		.unwrap()
+		.allows(ActivityKind::Sync, Utc::now())
+		|| power.lock().unwrap().holds()
+		|| maintenance.is_active()
+	{
+		return;
+	}
+	let rules = match db.lock() {
+		Ok(conn) => load_outbox_rules(&conn).unwrap_or_else(|err| {
+			tracing::error!("failed to load outbox rules: {err}");
+			Vec::new()
+		}),
+		Err(err) => {
+			tracing::error!("db lock poisoned while loading outbox rules: {err}");
+			return;
+		}
+	};
+	for rule in rules {
+		if rule.paused || !runs.begin(rule.id) {
+			continue;
+		}
+		let Ok(work) = maintenance.begin(format!("outbox {}", rule.id), None) else {
+			runs.end(rule.id);
+			return;
+		};
+		let (target, db, runs) = (target.clone(), db.clone(), runs.clone());
+		tokio::spawn(async move {
+			let _work = work;
+			if let Err(err) = process_rule(&*target, &db, &runs, &rule, Instant::now()).await {
+				tracing::warn!("outbox {}: {err:#}", rule.id);
+				match db.lock() {
+					Ok(conn) => {
+						let error = format!("{err:#}");
+						if let Err(err) = record_outbox_error(&conn, rule.id, Some(&error)) {
+							tracing::error!("failed to record outbox error: {err}");
+						}
+					}
+					Err(err) => tracing::error!("db lock poisoned while recording outbox: {err}"),
+				}
+			}
+			runs.end(rule.id);
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::db::insert_outbox_rule;
+	use crate::p2p::MimeSource;
+	use crate::test_util::{self, test_dir};
+	use std::collections::BTreeMap;
+	use std::sync::atomic::{AtomicBool, Ordering};
+
+	/// A peer keeping written files in memory.
+	struct FakePeer {
+		files: Mutex<BTreeMap<String, Vec<u8>>>,
+		connected: AtomicBool,
+		/// Reads return other bytes than were written.
+		corrupt: AtomicBool,
+		transfers: Mutex<Vec<Transfer>>,
+	}
+
+	impl FakePeer {
+		fn new(connected: bool) -> Self {
+			Self {
+				files: Mutex::new(BTreeMap::new()),
+				connected: AtomicBool::new(connected),
+				corrupt: AtomicBool::new(false),
+				transfers: Mutex::new(Vec::new()),
+			}
+		}
+	}
+
+	#[async_trait]
+	impl OutboxTarget for FakePeer {
+		async fn local_peer(&self) -> Option<PeerId> {
+			None
+		}
+
+		async fn is_connected(&self, _peer: PeerId) -> bool {
+			self.connected.load(Ordering::SeqCst)
+		}
+
+		async fn stat(&self, _peer: PeerId, path: &str) -> Result<DirEntry> {
+			let files = self.files.lock().unwrap();
+			let data = files.get(path).ok_or_else(|| anyhow!("not found"))?;
+			Ok(DirEntry {
+				name: path.to_string(),
+				name_raw: Vec::new(),
+				is_dir: false,
+				extension: None,
+				mime: None,
+				mime_source: MimeSource::Extension,
+				size: data.len() as u64,
+				disk_size: None,
+				created_at: None,
+				modified_at: None,
+				accessed_at: None,
+				hidden: false,
+			})
+		}
+
+		async fn write(&self, _peer: PeerId, path: &str, offset: u64, data: Vec<u8>) -> Result<()> {
+			let mut files = self.files.lock().unwrap();
+			let file = files.entry(path.to_string()).or_default();
+			let end = offset as usize + data.len();
+			if file.len() < end {
+				file.resize(end, 0);
+			}
+			file[offset as usize..end].copy_from_slice(&data);
+			Ok(())
+		}
+
+		async fn read_file(
+			&self,
+			_peer: PeerId,
+			path: &str,
+			offset: u64,
+			length: u64,
+		) -> Result<FileChunk> {
+			let files = self.files.lock().unwrap();
+			let mut data = files.get(path).ok_or_else(|| anyhow!("not found"))?.clone();
+			if self.corrupt.load(Ordering::SeqCst) {
+				data.reverse();
+			}
+			let start = (offset as usize).min(data.len());
+			let end = (start + length as usize).min(data.len());
+			Ok(FileChunk {
+				offset,
+				data: data[start..end].to_vec(),
+				eof: end == data.len(),
+				hole: 0,
+			})
+		}
+
+		fn record_transfer(&self, transfer: Transfer) {
+			self.transfers.lock().unwrap().push(transfer);
+		}
+	}
+
+	fn setup(watch: &Path, delete_after_send: bool) -> (Db, OutboxStatus) {
+		let conn = test_util::memory_db();
+		let rule = OutboxRule {
+			watch_path: watch.to_path_buf(),
+			dest_peer: PeerId::random(),
+			dest_path: "/nas/scans".to_string(),
+			delete_after_send,
+			pattern: "*.pdf".to_string(),
+		};
+		insert_outbox_rule(&conn, &rule, Utc::now()).unwrap();
+		let status = load_outbox_rules(&conn).unwrap().remove(0);
+		(Db::single(conn), status)
+	}
+
+	#[test]
+	fn a_file_is_ready_once_it_stops_changing() {
+		let runs = OutboxRuns::default();
+		let path = Path::new("/scans/a.pdf");
+		let start = Instant::now();
+		assert!(!runs.observe(1, path, 100, None, start));
+		let later = start + OUTBOX_QUIET_PERIOD / 2;
+		assert!(!runs.observe(1, path, 200, None, later));
+		// Still growing, so the quiet period starts over.
+		assert!(!runs.observe(1, path, 200, None, start + OUTBOX_QUIET_PERIOD));
+		assert!(runs.observe(1, path, 200, None, later + OUTBOX_QUIET_PERIOD));
+	}
+
+	#[tokio::test]
+	async fn files_wait_while_the_peer_is_away() {
+		let watch = test_dir("outbox-offline");
+		std::fs::write(watch.join("a.pdf"), b"scan").unwrap();
+		std::fs::write(watch.join("notes.txt"), b"skip").unwrap();
+		let (db, rule) = setup(&watch, false);
+		let peer = FakePeer::new(false);
+		let runs = OutboxRuns::default();
+		let start = Instant::now();
+
+		process_rule(&peer, &db, &runs, &rule, start).await.unwrap();
+		let stable = start + OUTBOX_QUIET_PERIOD;
+		process_rule(&peer, &db, &runs, &rule, stable)
+			.await
+			.unwrap();
+		assert!(peer.files.lock().unwrap().is_empty());
+		assert!(watch.join("a.pdf").exists());
+		let status = load_outbox_rules(&db.lock().unwrap()).unwrap().remove(0);
+		assert!(status.last_error.unwrap().contains("1 file queued"));
+
+		peer.connected.store(true, Ordering::SeqCst);
+		process_rule(&peer, &db, &runs, &rule, stable)
+			.await
+			.unwrap();
+		assert_eq!(
+			peer.files.lock().unwrap().get("/nas/scans/a.pdf").unwrap(),
+			b"scan"
+		);
+		assert!(!watch.join("a.pdf").exists());
+		assert!(watch.join(OUTBOX_SENT_FOLDER).join("a.pdf").exists());
+		assert!(watch.join("notes.txt").exists());
+		let status = load_outbox_rules(&db.lock().unwrap()).unwrap().remove(0);
+		assert_eq!(status.last_sent_file.as_deref(), Some("a.pdf"));
+		assert_eq!(status.last_error, None);
+
+		// The sent folder is not looked at again.
+		process_rule(&peer, &db, &runs, &rule, stable + OUTBOX_QUIET_PERIOD)
+			.await
+			.unwrap();
+		assert_eq!(peer.files.lock().unwrap().len(), 1);
+		std::fs::remove_dir_all(watch).unwrap();
+	}
+
+	#[tokio::test]
+	async fn originals_are_only_deleted_when_the_copy_matches() {
+		let watch = test_dir("outbox-verify");
+		std::fs::write(watch.join("a.pdf"), b"first scan").unwrap();
+		let (db, rule) = setup(&watch, true);
+		let peer = FakePeer::new(true);
+		peer.corrupt.store(true, Ordering::SeqCst);
+		let runs = OutboxRuns::default();
+		let start = Instant::now();
+		let stable = start + OUTBOX_QUIET_PERIOD;
+
+		process_rule(&peer, &db, &runs, &rule, start).await.unwrap();
+		process_rule(&peer, &db, &runs, &rule, stable)
+			.await
+			.unwrap();
+		assert!(watch.join("a.pdf").exists());
+		assert!(peer.transfers.lock().unwrap()[0].error.is_some());
+		// Failed files wait before they are tried again.
+		peer.corrupt.store(false, Ordering::SeqCst);
+		process_rule(&peer, &db, &runs, &rule, stable)
+			.await
+			.unwrap();
+		assert_eq!(peer.transfers.lock().unwrap().len(), 1);
+
+		process_rule(&peer, &db, &runs, &rule, stable + retry_delay(1))
+			.await
+			.unwrap();
+		assert!(!watch.join("a.pdf").exists());
+		assert!(!watch.join(OUTBOX_SENT_FOLDER).exists());
+		// The mismatched copy is left alone rather than overwritten.
+		assert!(
+			peer.files
+				.lock()
+				.unwrap()
+				.contains_key("/nas/scans/a (2).pdf")
+		);
+		std::fs::remove_dir_all(watch).unwrap();
+	}
+}
@@ -9,7 +9,10 @@ use libp2p::request_response::{
 };
 use libp2p::{
 	Multiaddr, PeerId, StreamProtocol, Swarm, SwarmBuilder, identity, noise,
-	swarm::{NetworkBehaviour, SwarmEvent},
+	swarm::{
+		NetworkBehaviour, SwarmEvent,
+		dial_opts::{DialOpts, PeerCondition},
+	},
 	tcp, yamux,
 };
 use libp2p::{mdns, ping};
@@ -18,49 +21,370 @@ use std::collections::{HashMap, HashSet};
 use std::fs::{self, File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::net::{IpAddr, UdpSocket};
+use std::num::NonZeroU8;
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::time::{SystemTime, UNIX_EPOCH};
 use sysinfo::{Disks, Networks, System};
 use tokio::sync::Mutex;
 use tokio::time::{Duration, interval};
 use uuid::Uuid;
 
-use crate::db::FileEntry;
+use crate::config;
+use crate::contact_sheet::ContactSheetResult;
+use crate::db::{FileEntry, FileSearchResult, SearchFilesArgs};
+use crate::deletion::DeleteOutcome;
+use crate::dialer::PeerDialStats;
+use crate::disk_history::DiskSample;
+use crate::image_decode::ImageTooLarge;
+use crate::keepalive::{ConnectionPolicy, PeerChurn};
+use crate::locations::WellKnownFolder;
+use crate::pairing_invite::PairingRejected;
+use crate::password_policy::CreateUserError;
+use crate::replication::{IndexDelta, IndexDeltaAck};
 use crate::scan::{ScanEvent, ScanResult};
-use crate::state::{FLAG_READ, FLAG_SEARCH, FLAG_WRITE, FolderRule, Permission, Rule};
+use crate::scan_limits::ScanOverrides;
+use crate::scan_results::ScanResults;
+use crate::share_summary::ShareSummary;
+use crate::state::{
+	AccessExplanation, FLAG_PREVIEW, FLAG_READ, FLAG_SEARCH, FLAG_WRITE, FolderRule, Permission,
+	Rule,
+};
 use crate::types::FileChunk;
 use crate::updater::UpdateProgress;
 use crate::wait_group::WaitGroupGuard;
+use crate::wire::{TolerantEnum, deserialize_tolerant, variant_names};
 
 const PUPPYNET_PROTOCOL: &str = "/puppynet/0.0.1";
+/// Version announced in the `Hello` handshake. Peers that predate the
+/// handshake are recorded as [`LEGACY_PROTOCOL_VERSION`]. Version 3 peers
+/// answer requests they don't know with [`PeerRes::Unsupported`].
+pub const PROTOCOL_VERSION: u32 = 3;
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
+// Feature names are lowercase, dot-separated identifiers. Features defined by
+// this project use the `puppynet.` prefix; forks must put their own features
+// under a prefix they control (e.g. `org.example.relay`) and ignore any
+// feature string they don't recognise.
+pub const FEATURE_FILES: &str = "puppynet.files";
+pub const FEATURE_THUMBNAILS: &str = "puppynet.thumbnails";
+pub const FEATURE_SHELL: &str = "puppynet.shell";
+pub const FEATURE_UPDATE: &str = "puppynet.update";
+pub const FEATURE_DESKTOP_INPUT: &str = "puppynet.desktop-input";
+pub const FEATURE_PERMISSION_PUSH: &str = "puppynet.permission-push";
+pub const FEATURE_INBOX: &str = "puppynet.inbox";
+pub const FEATURE_RAW_PATHS: &str = "puppynet.raw-paths";
+pub const FEATURE_LIST_ROOTS: &str = "puppynet.list-roots";
+pub const FEATURE_WELL_KNOWN_FOLDERS: &str = "puppynet.well-known-folders";
+pub const FEATURE_RESTART: &str = "puppynet.restart";
+pub const FEATURE_HEALTH_CHECK: &str = "puppynet.health-check";
+pub const FEATURE_DISK_HISTORY: &str = "puppynet.disk-history";
+pub const FEATURE_PAIRING: &str = "puppynet.pairing";
+pub const FEATURE_TRACING: &str = "puppynet.tracing";
+pub const FEATURE_SEARCH_FILES: &str = "puppynet.search-files";
+pub const FEATURE_DIAL_BACK: &str = "puppynet.dial-back";
+pub const FEATURE_ACCESS_EXPLAIN: &str = "puppynet.access-explain";
+pub const FEATURE_INDEX_REPLICATION: &str = "puppynet.index-replication";
+pub const FEATURE_INDEX_ANNOUNCE: &str = "puppynet.index-announce";
+pub const FEATURE_HAVE_HASHES: &str = "puppynet.have-hashes";
+pub const FEATURE_FREE_HINT: &str = "puppynet.free-hint";
+pub const FEATURE_RATE_LIMIT: &str = "puppynet.rate-limit";
+pub const FEATURE_IMAGE_TOO_LARGE: &str = "puppynet.image-too-large";
+pub const FEATURE_SHARE_SUMMARY: &str = "puppynet.share-summary";
+pub const FEATURE_DELETE_PROPOSALS: &str = "puppynet.delete-proposals";
+pub const FEATURE_CONTACT_SHEET: &str = "puppynet.contact-sheet";
+pub const FEATURE_USER_REFUSED: &str = "puppynet.user-refused";
+
+const LOCAL_FEATURES: &[&str] = &[
+	FEATURE_FILES,
+	FEATURE_THUMBNAILS,
+	FEATURE_SHELL,
+	FEATURE_UPDATE,
+	FEATURE_DESKTOP_INPUT,
+	FEATURE_PERMISSION_PUSH,
+	FEATURE_INBOX,
+	FEATURE_RAW_PATHS,
+	FEATURE_LIST_ROOTS,
+	FEATURE_WELL_KNOWN_FOLDERS,
+	FEATURE_RESTART,
+	FEATURE_HEALTH_CHECK,
+	FEATURE_DISK_HISTORY,
+	FEATURE_PAIRING,
+	FEATURE_TRACING,
+	FEATURE_SEARCH_FILES,
+	FEATURE_DIAL_BACK,
+	FEATURE_ACCESS_EXPLAIN,
+	FEATURE_INDEX_REPLICATION,
+	FEATURE_INDEX_ANNOUNCE,
+	FEATURE_HAVE_HASHES,
+	FEATURE_FREE_HINT,
+	FEATURE_RATE_LIMIT,
+	FEATURE_IMAGE_TOO_LARGE,
+	FEATURE_SHARE_SUMMARY,
+	FEATURE_DELETE_PROPOSALS,
+	FEATURE_CONTACT_SHEET,
+	FEATURE_USER_REFUSED,
+];
+
+/// Whether this build has the code behind `feature`; the thumbnail, shell
+/// and self-update subsystems are cargo features of this crate.
+fn feature_compiled_in(feature: &str) -> bool {
+	match feature {
+		FEATURE_THUMBNAILS | FEATURE_IMAGE_TOO_LARGE | FEATURE_CONTACT_SHEET => {
+			cfg!(feature = "thumbnails")
+		}
+		FEATURE_SHELL => cfg!(feature = "shell"),
+		FEATURE_UPDATE => cfg!(feature = "self-update"),
+		_ => true,
+	}
+}
+
+/// Conservative feature set assumed for peers that don't answer `Hello`.
+const LEGACY_FEATURES: &[&str] = &[FEATURE_FILES, FEATURE_THUMBNAILS];
 const MAX_FILE_CHUNK: u64 = 4 * 1024 * 1024; // 4 MiB per transfer chunk
 const OWNER_ROLE: &str = "owner";
 const VIEWER_ROLE: &str = "viewer";
 const DEFAULT_SESSION_TTL: u64 = 60 * 60; // 1 hour sessions for credential auth
 
+/// Returns the exact bytes of a path. Unix paths are returned as-is; Windows
+/// paths are encoded as WTF-8 so unpaired surrogates survive.
+#[cfg(unix)]
+pub fn path_bytes(path: &Path) -> Vec<u8> {
+	use std::os::unix::ffi::OsStrExt;
+	path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(windows)]
+pub fn path_bytes(path: &Path) -> Vec<u8> {
+	use std::os::windows::ffi::OsStrExt;
+	let mut bytes = Vec::new();
+	for unit in char::decode_utf16(path.as_os_str().encode_wide()) {
+		match unit {
+			Ok(ch) => {
+				let mut buf = [0u8; 4];
+				bytes.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+			}
+			Err(err) => {
+				let surrogate = err.unpaired_surrogate() as u32;
+				bytes.extend_from_slice(&[
+					0xE0 | (surrogate >> 12) as u8,
+					0x80 | ((surrogate >> 6) & 0x3F) as u8,
+					0x80 | (surrogate & 0x3F) as u8,
+				]);
+			}
+		}
+	}
+	bytes
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn path_bytes(path: &Path) -> Vec<u8> {
+	path.to_string_lossy().into_owned().into_bytes()
+}
+
+/// Inverse of [`path_bytes`].
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+	use std::os::unix::ffi::OsStrExt;
+	PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(windows)]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+	use std::os::windows::ffi::OsStringExt;
+	let mut units = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while i < bytes.len() {
+		let lead = bytes[i];
+		let len = match lead {
+			0x00..=0x7F => 1,
+			0xC0..=0xDF => 2,
+			0xE0..=0xEF => 3,
+			0xF0..=0xF7 => 4,
+			_ => 0,
+		};
+		let continuation = bytes
+			.get(i + 1..i + len.max(1))
+			.filter(|rest| len > 0 && rest.iter().all(|b| b & 0xC0 == 0x80));
+		let Some(rest) = continuation else {
+			units.push(0xFFFD);
+			i += 1;
+			continue;
+		};
+		let mut code = match len {
+			1 => lead as u32,
+			2 => (lead & 0x1F) as u32,
+			3 => (lead & 0x0F) as u32,
+			_ => (lead & 0x07) as u32,
+		};
+		for b in rest {
+			code = (code << 6) | (b & 0x3F) as u32;
+		}
+		if code > 0xFFFF {
+			let code = code - 0x10000;
+			units.push(0xD800 | (code >> 10) as u16);
+			units.push(0xDC00 | (code & 0x3FF) as u16);
+		} else {
+			units.push(code as u16);
+		}
+		i += len;
+	}
+	PathBuf::from(std::ffi::OsString::from_wide(&units))
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+	PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerCapabilities {
+	pub protocol_version: u32,
+	pub features: Vec<String>,
+	/// The peer never answered `Hello`; `features` is the legacy default.
+	pub legacy: bool,
+}
+
+impl PeerCapabilities {
+	pub fn local() -> Self {
+		Self {
+			protocol_version: PROTOCOL_VERSION,
+			features: LOCAL_FEATURES
+				.iter()
+				.filter(|f| feature_compiled_in(f))
+				.map(|f| f.to_string())
+				.collect(),
+			legacy: false,
+		}
+	}
+
+	pub fn legacy() -> Self {
+		Self {
+			protocol_version: LEGACY_PROTOCOL_VERSION,
+			features: LEGACY_FEATURES.iter().map(|f| f.to_string()).collect(),
+			legacy: true,
+		}
+	}
+
+	pub fn supports(&self, feature: &str) -> bool {
+		self.features.iter().any(|f| f == feature)
+	}
+}
+
+/// Path carried by file requests. Valid UTF-8 paths travel as plain strings,
+/// anything else as the bytes from [`path_bytes`] so the round trip is
+/// lossless.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum WirePath {
+	Display(String),
+	Raw(Vec<u8>),
+}
+
+impl WirePath {
+	pub fn from_path(path: &Path) -> Self {
+		match path.to_str() {
+			Some(path) => Self::Display(path.to_string()),
+			None => Self::Raw(path_bytes(path)),
+		}
+	}
+
+	pub fn to_path_buf(&self) -> PathBuf {
+		match self {
+			Self::Display(path) => PathBuf::from(path),
+			Self::Raw(bytes) => path_from_bytes(bytes),
+		}
+	}
+}
+
+impl From<String> for WirePath {
+	fn from(path: String) -> Self {
+		Self::Display(path)
+	}
+}
+
+impl From<&str> for WirePath {
+	fn from(path: &str) -> Self {
+		Self::Display(path.to_string())
+	}
+}
+
+impl std::fmt::Display for WirePath {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Display(path) => f.write_str(path),
+			Self::Raw(bytes) => f.write_str(&String::from_utf8_lossy(bytes)),
+		}
+	}
+}
+
+/// Refusal, or start of one, sent for filesystem requests the sender has
+/// no grant for.
+pub const ACCESS_DENIED: &str = "Access denied";
+
+/// Refusal sent for filesystem requests while the owner has suspended
+/// remote access.
+pub const REMOTE_ACCESS_SUSPENDED: &str = "Access temporarily suspended by owner";
+
+/// Refusal sent once to a peer whose write to a path was rejected during
+/// review; the rejected file has been removed.
+pub const WRITE_REJECTED: &str = "Write rejected by owner during review";
+
+/// What a [`PeerReq`] reaches on the receiving node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestScope {
+	/// Handshakes, pairing and answers to this node's own requests.
+	Exchange,
+	/// Files and the index, decided by folder rules.
+	Files,
+	/// The machine itself: hardware, network, devices, users and control.
+	System,
+}
+
+/// Requests between peers, sent as externally tagged JSON.
+///
+/// Nodes of different versions talk to each other, so the encoding is a
+/// compatibility contract, guarded by the golden fixtures in
+/// `tests/fixtures/peer_protocol`. Wire-safe changes:
+/// - adding a variant; older nodes decode it as `Unknown` and answer
+///   [`PeerRes::Unsupported`]
+/// - adding a field with `#[serde(default)]`, or an `Option` field
+/// - reordering variants or fields, which JSON doesn't encode
+///
+/// Breaking changes, which need a new variant instead:
+/// - renaming or removing a variant or field
+/// - changing a field's type, or adding a field without a default
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub enum PeerReq {
 	PeerInfo,
 	ListDir {
-		path: String,
+		path: WirePath,
 	},
 	StatFile {
-		path: String,
+		path: WirePath,
 	},
 	ReadFile {
-		path: String,
+		path: WirePath,
 		offset: u64,
 		length: Option<u64>,
+		/// Answer holes with their length instead of their zeros. Peers
+		/// that predate it send every byte.
+		#[serde(default)]
+		sparse: bool,
 	},
 	WriteFile {
-		path: String,
+		path: WirePath,
 		offset: u64,
 		data: Vec<u8>,
 	},
 	ListCpus,
 	ListDisks,
+	/// Where a file browser should start on this peer.
+	ListRoots,
+	/// Home, user folders and drives this peer may read.
+	WellKnownFolders,
 	ListInterfaces,
 	AudioCapability,
 	ListAudioDevices,
@@ -83,6 +407,10 @@ pub enum PeerReq {
 	StartScan {
 		id: u64,
 		path: String,
+		/// Limits the requester would like, held to the serving node's
+		/// own. Peers that predate it scan under those alone.
+		#[serde(default)]
+		limits: ScanOverrides,
 	},
 	FileEntries {
 		offset: u64,
@@ -155,13 +483,424 @@ pub enum PeerReq {
 		id: u64,
 		data: Vec<u8>,
 	},
+	/// End a shell session, killing the shell if it still runs.
+	CloseShell {
+		id: u64,
+	},
 	/// Send desktop mouse or keyboard input to this peer.
 	DesktopInput {
 		input: DesktopInput,
 	},
+	/// Notify the peer that the permissions granted to it have changed.
+	PermissionsChanged {
+		permissions: Vec<Permission>,
+	},
+	/// Reserve a file in the peer's inbox before streaming it with `WriteInbox`.
+	OpenInbox {
+		name: String,
+		size: u64,
+	},
+	/// Capability handshake sent right after a connection is established.
+	Hello {
+		protocol_version: u32,
+		features: Vec<String>,
+	},
+	/// Restart the puppynet process after `delay_secs`. Owner only.
+	Restart {
+		delay_secs: u64,
+	},
+	/// Cheap liveness snapshot of the peer.
+	HealthCheck,
+	/// Recorded free space of the disk mounted at `mount` (or with that disk
+	/// id) between `from` and `to`.
+	DiskHistory {
+		mount: String,
+		from: DateTime<Utc>,
+		to: DateTime<Utc>,
+	},
+	/// Ask to pair. The receiver shows the pairing code to its user and
+	/// answers with `PairResponse` once they compared it.
+	PairRequest {
+		node_name: String,
+	},
+	/// The user compared pairing codes and accepted or declined.
+	PairResponse {
+		accepted: bool,
+	},
+	/// Pair with the secret of the receiver's pairing string, which stands
+	/// in for comparing codes. `requested_permissions` narrows the folders
+	/// asked for; empty asks for the default pairing access.
+	Pair {
+		secret: Vec<u8>,
+		requested_permissions: Vec<Permission>,
+	},
+	/// `request` under the sender's correlation id, which the receiver logs
+	/// its handling under. Only sent to peers announcing
+	/// [`FEATURE_TRACING`].
+	Traced {
+		corr: u64,
+		request: Box<PeerReq>,
+	},
+	/// Search the receiver's own index. Only files on the receiver under
+	/// folders the sender may search come back, one page of at most
+	/// [`crate::peer_search::PEER_SEARCH_MAX_PAGE_SIZE`].
+	SearchFiles {
+		args: SearchFilesArgs,
+	},
+	/// Open a fresh connection to `addr`, one of the sender's own
+	/// addresses, and report whether it worked. Only sent to peers
+	/// announcing [`FEATURE_DIAL_BACK`].
+	DialBack {
+		addr: String,
+	},
+	/// Changes to the sender's index for the replica the receiver keeps of
+	/// it. Only sent to peers announcing [`FEATURE_INDEX_REPLICATION`].
+	IndexDelta {
+		delta: IndexDelta,
+	},
+	/// The sender's index changed: counts of the rows since its previous
+	/// announcement and its latest change number, not the rows. Only sent
+	/// to peers announcing [`FEATURE_INDEX_ANNOUNCE`].
+	IndexAnnounce {
+		node_id: String,
+		added: u64,
+		updated: u64,
+		removed: u64,
+		high_water_mark: u64,
+	},
+	/// Changes to the receiver's index after `after` of `generation`,
+	/// answered with an `IndexDelta` response. Only sent to peers
+	/// announcing [`FEATURE_INDEX_ANNOUNCE`].
+	IndexPull {
+		generation: String,
+		after: u64,
+	},
+	/// Which of `hashes` the receiver holds as live files of its own,
+	/// answered with a `Have` bitmap. Only the asker's hashes are
+	/// confirmed, never paths. Batches are capped; only peers that may
+	/// send into the inbox may ask.
+	HaveHashes {
+		hashes: Vec<[u8; 32]>,
+	},
+	/// Which of `blocks`, the hashes of the `block_size` blocks of the file
+	/// with content `hash`, the receiver holds in files of its own,
+	/// answered with a `Have` bitmap.
+	HaveBlocks {
+		hash: [u8; 32],
+		block_size: u64,
+		blocks: Vec<[u8; 32]>,
+	},
+	/// Write the receiver's own copy of the block hashing to `block_hash`
+	/// at `offset` of the inbox upload at `path`, relative to the inbox,
+	/// instead of sending it with `WriteInbox`. Answered with a `WriteAck`.
+	WriteKnownBlock {
+		path: String,
+		offset: u64,
+		block_hash: [u8; 32],
+	},
+	/// Write `data` at `offset` of the inbox upload at `path`, relative to
+	/// the inbox as `InboxOpened` gave it. Answered with a `WriteAck`.
+	WriteInbox {
+		path: String,
+		offset: u64,
+		data: Vec<u8>,
+	},
+	/// What the folders the sender may search hold, answered with
+	/// `ShareSummaries`. Capped at
+	/// [`crate::share_summary::SHARE_SUMMARY_MAX_ROOTS`] folders.
+	ShareSummary,
+	/// Asks the receiver's owner to delete their copies of the file with
+	/// content `hash`, answered with `DeleteProposalReceived`. Nothing is
+	/// deleted until the owner approves it; `paths_hint` are where the
+	/// sender's index last saw the copies. Only sent to peers announcing
+	/// [`FEATURE_DELETE_PROPOSALS`].
+	DeleteProposal {
+		id: String,
+		hash: [u8; 32],
+		paths_hint: Vec<String>,
+		reason: String,
+		expires_at: DateTime<Utc>,
+	},
+	/// How the receiver's proposal `id` was settled here, answered with
+	/// `DeleteOutcomeAck`.
+	DeleteOutcome {
+		id: String,
+		outcome: DeleteOutcome,
+	},
+	/// The images directly in the folder at `path` as one grid of
+	/// `columns` cells of `cell_size` pixels, the first `max_items` by name,
+	/// answered with `ContactSheet`. Images the sender may not preview are
+	/// left out. Only sent to peers announcing [`FEATURE_CONTACT_SHEET`].
+	ContactSheet {
+		path: String,
+		columns: u32,
+		cell_size: u32,
+		max_items: u32,
+	},
+	/// The locations the receiver's scan run `run`, which the sender asked
+	/// for, left under its folder after row `cursor`, answered with
+	/// `ScanResults`.
+	ScanResultsPull {
+		run: i64,
+		cursor: u64,
+	},
+	/// A request from a newer node that this one doesn't know, by variant
+	/// name. Never sent.
+	#[serde(skip)]
+	Unknown(String),
+}
+
+impl Serialize for PeerReq {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		PeerReq::serialize(self, serializer)
+	}
 }
 
+impl<'de> Deserialize<'de> for PeerReq {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		deserialize_tolerant(deserializer)
+	}
+}
+
+impl TolerantEnum for PeerReq {
+	fn deserialize_known<'de, D: serde::Deserializer<'de>>(
+		deserializer: D,
+	) -> Result<Self, D::Error> {
+		PeerReq::deserialize(deserializer)
+	}
+
+	fn unknown(name: String) -> Self {
+		PeerReq::Unknown(name)
+	}
+
+	fn variants() -> &'static [&'static str] {
+		static VARIANTS: OnceLock<&'static [&'static str]> = OnceLock::new();
+		VARIANTS.get_or_init(variant_names::<Self>)
+	}
+}
+
+impl PeerReq {
+	/// Variant name, for logs and request traces.
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::PeerInfo => "PeerInfo",
+			Self::ListDir { .. } => "ListDir",
+			Self::StatFile { .. } => "StatFile",
+			Self::ReadFile { .. } => "ReadFile",
+			Self::WriteFile { .. } => "WriteFile",
+			Self::ListCpus => "ListCpus",
+			Self::ListDisks => "ListDisks",
+			Self::ListRoots => "ListRoots",
+			Self::WellKnownFolders => "WellKnownFolders",
+			Self::ListInterfaces => "ListInterfaces",
+			Self::AudioCapability => "AudioCapability",
+			Self::ListAudioDevices => "ListAudioDevices",
+			Self::SetAudioMuted { .. } => "SetAudioMuted",
+			Self::SetAudioVolume { .. } => "SetAudioVolume",
+			Self::SetDefaultAudioDevice { .. } => "SetDefaultAudioDevice",
+			Self::MediaCapability => "MediaCapability",
+			Self::ListMediaSources => "ListMediaSources",
+			Self::GetMediaFrame { .. } => "GetMediaFrame",
+			Self::StartScan { .. } => "StartScan",
+			Self::FileEntries { .. } => "FileEntries",
+			Self::StartSearch { .. } => "StartSearch",
+			Self::SearchEvent { .. } => "SearchEvent",
+			Self::ScanEvent { .. } => "ScanEvent",
+			Self::Authenticate { .. } => "Authenticate",
+			Self::CreateUser { .. } => "CreateUser",
+			Self::CreateToken { .. } => "CreateToken",
+			Self::GrantAccess { .. } => "GrantAccess",
+			Self::ListUsers => "ListUsers",
+			Self::ListTokens { .. } => "ListTokens",
+			Self::RevokeToken { .. } => "RevokeToken",
+			Self::RevokeUser { .. } => "RevokeUser",
+			Self::ListPermissions => "ListPermissions",
+			Self::GetThumbnail { .. } => "GetThumbnail",
+			Self::UpdateSelf { .. } => "UpdateSelf",
+			Self::UpdateEvent { .. } => "UpdateEvent",
+			Self::StartShell { .. } => "StartShell",
+			Self::ShellInput { .. } => "ShellInput",
+			Self::CloseShell { .. } => "CloseShell",
+			Self::DesktopInput { .. } => "DesktopInput",
+			Self::PermissionsChanged { .. } => "PermissionsChanged",
+			Self::OpenInbox { .. } => "OpenInbox",
+			Self::Hello { .. } => "Hello",
+			Self::Restart { .. } => "Restart",
+			Self::HealthCheck => "HealthCheck",
+			Self::DiskHistory { .. } => "DiskHistory",
+			Self::PairRequest { .. } => "PairRequest",
+			Self::PairResponse { .. } => "PairResponse",
+			Self::Pair { .. } => "Pair",
+			Self::Traced { .. } => "Traced",
+			Self::SearchFiles { .. } => "SearchFiles",
+			Self::DialBack { .. } => "DialBack",
+			Self::IndexDelta { .. } => "IndexDelta",
+			Self::IndexAnnounce { .. } => "IndexAnnounce",
+			Self::IndexPull { .. } => "IndexPull",
+			Self::HaveHashes { .. } => "HaveHashes",
+			Self::HaveBlocks { .. } => "HaveBlocks",
+			Self::WriteKnownBlock { .. } => "WriteKnownBlock",
+			Self::WriteInbox { .. } => "WriteInbox",
+			Self::ShareSummary => "ShareSummary",
+			Self::DeleteProposal { .. } => "DeleteProposal",
+			Self::DeleteOutcome { .. } => "DeleteOutcome",
+			Self::ContactSheet { .. } => "ContactSheet",
+			Self::ScanResultsPull { .. } => "ScanResultsPull",
+			Self::Unknown(_) => "Unknown",
+		}
+	}
+
+	/// The correlation id and the request a `Traced` one wraps.
+	pub fn untraced(self) -> (Option<u64>, PeerReq) {
+		match self {
+			Self::Traced { corr, request } => (Some(corr), *request),
+			request => (None, request),
+		}
+	}
+
+	/// Requests that read or change the local filesystem or file index,
+	/// including through a shell, desktop input or a deletion proposal.
+	/// Closing a shell is left out so sessions can still be ended.
+	pub fn touches_filesystem(&self) -> bool {
+		matches!(
+			self,
+			Self::ListDir { .. }
+				| Self::StatFile { .. }
+				| Self::ReadFile { .. }
+				| Self::WriteFile { .. }
+				| Self::ListRoots
+				| Self::WellKnownFolders
+				| Self::StartScan { .. }
+				| Self::FileEntries { .. }
+				| Self::StartSearch { .. }
+				| Self::GetThumbnail { .. }
+				| Self::OpenInbox { .. }
+				| Self::SearchFiles { .. }
+				| Self::IndexDelta { .. }
+				| Self::IndexPull { .. }
+				| Self::HaveHashes { .. }
+				| Self::HaveBlocks { .. }
+				| Self::WriteKnownBlock { .. }
+				| Self::WriteInbox { .. }
+				| Self::ShareSummary
+				| Self::ContactSheet { .. }
+				| Self::ScanResultsPull { .. }
+				| Self::StartShell { .. }
+				| Self::ShellInput { .. }
+				| Self::DesktopInput { .. }
+				| Self::DeleteProposal { .. }
+		)
+	}
+
+	/// Requests that change files, the index, users or grants here, which
+	/// maintenance mode refuses.
+	pub fn mutates(&self) -> bool {
+		matches!(
+			self,
+			Self::WriteFile { .. }
+				| Self::WriteKnownBlock { .. }
+				| Self::WriteInbox { .. }
+				| Self::OpenInbox { .. }
+				| Self::StartScan { .. }
+				| Self::IndexDelta { .. }
+				| Self::CreateUser { .. }
+				| Self::CreateToken { .. }
+				| Self::GrantAccess { .. }
+				| Self::RevokeToken { .. }
+				| Self::RevokeUser { .. }
+				| Self::DeleteProposal { .. }
+		)
+	}
+
+	/// What the request reaches on this node, which the sender's trust
+	/// level is checked against.
+	pub fn scope(&self) -> RequestScope {
+		match self {
+			Self::PeerInfo
+			| Self::Hello { .. }
+			| Self::Authenticate { .. }
+			| Self::PairRequest { .. }
+			| Self::PairResponse { .. }
+			| Self::Pair { .. }
+			| Self::PermissionsChanged { .. }
+			| Self::SearchEvent { .. }
+			| Self::ScanEvent { .. }
+			| Self::UpdateEvent { .. }
+			| Self::IndexAnnounce { .. }
+			| Self::DeleteOutcome { .. }
+			| Self::Unknown(_) => RequestScope::Exchange,
+			Self::ListDir { .. }
+			| Self::StatFile { .. }
+			| Self::ReadFile { .. }
+			| Self::WriteFile { .. }
+			| Self::ListRoots
+			| Self::WellKnownFolders
+			| Self::StartScan { .. }
+			| Self::StartSearch { .. }
+			| Self::ListPermissions
+			| Self::GetThumbnail { .. }
+			| Self::OpenInbox { .. }
+			| Self::SearchFiles { .. }
+			| Self::IndexDelta { .. }
+			| Self::IndexPull { .. }
+			| Self::HaveHashes { .. }
+			| Self::HaveBlocks { .. }
+			| Self::WriteKnownBlock { .. }
+			| Self::WriteInbox { .. }
+			| Self::ShareSummary
+			| Self::DeleteProposal { .. }
+			| Self::ContactSheet { .. }
+			| Self::ScanResultsPull { .. } => RequestScope::Files,
+			Self::ListCpus
+			| Self::ListDisks
+			| Self::ListInterfaces
+			| Self::AudioCapability
+			| Self::ListAudioDevices
+			| Self::SetAudioMuted { .. }
+			| Self::SetAudioVolume { .. }
+			| Self::SetDefaultAudioDevice { .. }
+			| Self::MediaCapability
+			| Self::ListMediaSources
+			| Self::GetMediaFrame { .. }
+			| Self::FileEntries { .. }
+			| Self::CreateUser { .. }
+			| Self::CreateToken { .. }
+			| Self::GrantAccess { .. }
+			| Self::ListUsers
+			| Self::ListTokens { .. }
+			| Self::RevokeToken { .. }
+			| Self::RevokeUser { .. }
+			| Self::UpdateSelf { .. }
+			| Self::StartShell { .. }
+			| Self::ShellInput { .. }
+			| Self::CloseShell { .. }
+			| Self::DesktopInput { .. }
+			| Self::Restart { .. }
+			| Self::HealthCheck
+			| Self::DiskHistory { .. }
+			| Self::DialBack { .. } => RequestScope::System,
+			Self::Traced { request, .. } => request.scope(),
+		}
+	}
+
+	/// Whether this build can answer the request at all. Requests for a
+	/// subsystem left out at compile time are answered
+	/// [`PeerRes::Unsupported`], as if this node predated them.
+	pub fn compiled_in(&self) -> bool {
+		match self {
+			Self::GetThumbnail { .. } | Self::ContactSheet { .. } => cfg!(feature = "thumbnails"),
+			Self::StartShell { .. } | Self::ShellInput { .. } | Self::CloseShell { .. } => {
+				cfg!(feature = "shell")
+			}
+			Self::UpdateSelf { .. } => cfg!(feature = "self-update"),
+			_ => true,
+		}
+	}
+}
+
+/// Responses to [`PeerReq`], under the same compatibility rules.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub enum PeerRes {
 	PeerInfo(PeerInfo),
 	DirEntries(Vec<DirEntry>),
@@ -170,6 +909,8 @@ pub enum PeerRes {
 	WriteAck(FileWriteAck),
 	Cpus(Vec<CpuInfo>),
 	Disks(Vec<DiskInfo>),
+	Roots(BrowseRoots),
+	WellKnownFolders(Vec<WellKnownFolder>),
 	Interfaces(Vec<InterfaceInfo>),
 	AudioCapability(AudioCapability),
 	AudioDevices(Vec<AudioDevice>),
@@ -231,6 +972,137 @@ pub enum PeerRes {
 	},
 	/// Acknowledgment for desktop mouse or keyboard input.
 	DesktopInputAck(Result<(), String>),
+	/// Acknowledgment for a permission change notification.
+	PermissionsChangedAck,
+	/// Inbox file reserved for an upload; `path` is where it lies relative
+	/// to the inbox, for `WriteInbox` and `WriteKnownBlock` to address.
+	InboxOpened {
+		path: String,
+	},
+	/// Reply to `Hello` carrying the responder's version and features.
+	Hello {
+		protocol_version: u32,
+		features: Vec<String>,
+		/// Responder's clock when answering, for skew estimates. Missing
+		/// from peers that predate it.
+		#[serde(default)]
+		time: Option<DateTime<Utc>>,
+	},
+	/// The peer will restart after `delay_secs`.
+	RestartScheduled {
+		delay_secs: u64,
+	},
+	Health(PeerHealth),
+	DiskHistory(Vec<DiskSample>),
+	/// The pairing request waits for the responder's user.
+	PairPending {
+		node_name: String,
+	},
+	PairResponseAck,
+	/// The secret of a `Pair` was good; `granted` is what pairing added.
+	Paired {
+		node_name: String,
+		granted: Vec<Permission>,
+	},
+	PairRejected {
+		reason: PairingRejected,
+	},
+	SearchResults(Vec<FileSearchResult>),
+	/// Outcome of a `DialBack`; `latency_ms` is how long connecting took.
+	DialBack {
+		reachable: bool,
+		latency_ms: Option<u64>,
+		error: Option<String>,
+	},
+	/// The `DialBack` was refused, e.g. for an address the sender isn't
+	/// connected from or too many requests.
+	DialBackDeclined {
+		reason: String,
+	},
+	/// The path is in a shared folder that is offline, such as a network
+	/// mount that is not mounted; `share` is that folder.
+	Unavailable {
+		share: String,
+	},
+	/// A filesystem request was refused, with the rule that decided it.
+	/// Sent instead of an [`ACCESS_DENIED`] error to peers announcing
+	/// [`FEATURE_ACCESS_EXPLAIN`].
+	AccessDenied(AccessExplanation),
+	/// Where the replica stands after an `IndexDelta`.
+	IndexDeltaAck(IndexDeltaAck),
+	IndexAnnounceAck,
+	/// Changes answering an `IndexPull`.
+	IndexDelta(IndexDelta),
+	/// One bit per hash asked about by `HaveHashes` or `HaveBlocks`, set
+	/// when the responder holds it. Bit `i` is `bitmap[i / 8] >> (i % 8)`.
+	Have {
+		bitmap: Vec<u8>,
+	},
+	/// A `ListDir` answer with room for more than the entries. Sent instead
+	/// of `DirEntries` to peers announcing [`FEATURE_FREE_HINT`].
+	DirListing(DirListing),
+	/// The sender asks too often; the request was not handled. Sent instead
+	/// of an error to peers announcing [`FEATURE_RATE_LIMIT`].
+	RateLimited {
+		retry_after_secs: u64,
+	},
+	/// The image asked for a thumbnail of would not fit the responder's
+	/// decode budget. Sent instead of an error to peers announcing
+	/// [`FEATURE_IMAGE_TOO_LARGE`].
+	ImageTooLarge(ImageTooLarge),
+	/// Answer to `ShareSummary`.
+	ShareSummaries(Vec<ShareSummary>),
+	/// Answer to `DeleteProposal`: `None` while it waits for the owner, or
+	/// how it was settled when it already was.
+	DeleteProposalReceived {
+		outcome: Option<DeleteOutcome>,
+	},
+	/// Acknowledgment for a `DeleteOutcome`.
+	DeleteOutcomeAck,
+	/// Answer to `ContactSheet`.
+	ContactSheet(ContactSheetResult),
+	/// Answer to `ScanResultsPull`.
+	ScanResults(ScanResults),
+	/// `CreateUser` was refused, naming what to fix. Sent instead of an
+	/// error to peers announcing [`FEATURE_USER_REFUSED`].
+	UserRefused(CreateUserError),
+	/// This node doesn't know the `request` variant it was sent.
+	Unsupported {
+		request: String,
+	},
+	/// A response from a newer node that this one doesn't know, by variant
+	/// name. Never sent.
+	#[serde(skip)]
+	Unknown(String),
+}
+
+impl Serialize for PeerRes {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		PeerRes::serialize(self, serializer)
+	}
+}
+
+impl<'de> Deserialize<'de> for PeerRes {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		deserialize_tolerant(deserializer)
+	}
+}
+
+impl TolerantEnum for PeerRes {
+	fn deserialize_known<'de, D: serde::Deserializer<'de>>(
+		deserializer: D,
+	) -> Result<Self, D::Error> {
+		PeerRes::deserialize(deserializer)
+	}
+
+	fn unknown(name: String) -> Self {
+		PeerRes::Unknown(name)
+	}
+
+	fn variants() -> &'static [&'static str] {
+		static VARIANTS: OnceLock<&'static [&'static str]> = OnceLock::new();
+		VARIANTS.get_or_init(variant_names::<Self>)
+	}
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -289,6 +1161,28 @@ pub struct PeerInfo {
 	pub uptime_seconds: u64,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerHealth {
+	pub version: String,
+	/// Seconds since the puppynet process started.
+	pub uptime_secs: u64,
+	pub db_ok: bool,
+	pub listen_addrs: Vec<String>,
+	pub pending_requests: usize,
+	/// Why the last requested restart did not happen, if it failed.
+	pub last_restart_error: Option<String>,
+	#[serde(default)]
+	pub pending_dials: usize,
+	#[serde(default)]
+	pub queued_dials: usize,
+	/// Outgoing dial outcomes per peer since the process started.
+	#[serde(default)]
+	pub dials: Vec<PeerDialStats>,
+	/// Reconnects and closed connections per peer since the process started.
+	#[serde(default)]
+	pub churn: Vec<PeerChurn>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Thumbnail {
 	pub data: Vec<u8>,
@@ -315,16 +1209,108 @@ pub enum DesktopInput {
 	KeyboardKey { key: String },
 }
 
+/// Where a [`DirEntry`]'s mime type came from, from least to most certain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MimeSource {
+	/// Guessed from the file name's extension.
+	#[default]
+	Extension,
+	/// Content-sniffed by an earlier scan of the same file.
+	Index,
+	/// Sniffed from the leading bytes while listing.
+	Sniffed,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DirEntry {
 	pub name: String,
+	/// Original bytes of the name (see [`path_bytes`]); `name` is lossy when
+	/// the file name is not valid UTF-8.
+	#[serde(default)]
+	pub name_raw: Vec<u8>,
 	pub is_dir: bool,
 	pub extension: Option<String>,
 	pub mime: Option<String>,
+	#[serde(default)]
+	pub mime_source: MimeSource,
 	pub size: u64,
+	/// Bytes the file takes on disk, well under `size` when it is sparse.
+	/// Missing where the peer can't tell.
+	#[serde(default)]
+	pub disk_size: Option<u64>,
 	pub created_at: Option<DateTime<Utc>>,
 	pub modified_at: Option<DateTime<Utc>>,
 	pub accessed_at: Option<DateTime<Utc>>,
+	/// Marked hidden by the file system, as Windows does; dotfiles are
+	/// told apart by their name instead.
+	#[serde(default)]
+	pub hidden: bool,
+}
+
+impl DirEntry {
+	/// Whether `name` lost information when decoded for display.
+	pub fn has_undecodable_name(&self) -> bool {
+		!self.name_raw.is_empty() && std::str::from_utf8(&self.name_raw).is_err()
+	}
+
+	/// Whether file browsers hide the entry unless asked to show hidden
+	/// files.
+	pub fn is_hidden(&self) -> bool {
+		self.hidden || self.name.starts_with('.')
+	}
+}
+
+/// A directory's entries, with how much the requester may still write
+/// there when it may write at all.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DirListing {
+	pub entries: Vec<DirEntry>,
+	/// Bytes the requester can still write under the directory, as the
+	/// serving peer saw it when listing. Only a hint, since other writers
+	/// share the space; `None` when the peer doesn't say.
+	#[serde(default)]
+	pub free_hint: Option<u64>,
+	/// What the requester may do in the directory, as the serving peer
+	/// enforces it. `None` from peers that predate it.
+	#[serde(default)]
+	pub access: Option<ListingAccess>,
+}
+
+/// The access flags the requester holds on a listed directory, and the
+/// entries a rule of their own governs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListingAccess {
+	pub flags: u8,
+	#[serde(default)]
+	pub entries: Vec<EntryAccess>,
+}
+
+impl ListingAccess {
+	/// Keeps the rules naming one of `entries`, under the entry's name.
+	/// Names compare like the serving peer's file system does.
+	pub(crate) fn keep_listed(&mut self, entries: &[DirEntry]) {
+		self.entries.retain_mut(|access| {
+			let listed = entries.iter().find(|entry| {
+				entry.name == access.name
+					|| (cfg!(target_os = "windows")
+						&& entry.name.eq_ignore_ascii_case(&access.name))
+			});
+			if let Some(entry) = listed {
+				access.name = entry.name.clone();
+			}
+			listed.is_some()
+		});
+	}
+}
+
+/// An entry named by a rule narrower than its directory's.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryAccess {
+	pub name: String,
+	/// Path of the rule, as the serving peer stores it.
+	pub rule: String,
+	pub flags: u8,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -352,6 +1338,96 @@ pub struct DiskInfo {
 	pub read_only: bool,
 	pub removable: bool,
 	pub kind: String,
+	/// Stable key for the disk: filesystem UUID or device where known,
+	/// otherwise the mount path. Empty from peers that predate it.
+	#[serde(default)]
+	pub id: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BrowseRootKind {
+	Disk,
+	SharedFolder,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BrowseRoot {
+	pub label: String,
+	pub path: String,
+	pub kind: BrowseRootKind,
+	/// Access flags the requester holds on the root. `None` from peers
+	/// that predate it.
+	#[serde(default)]
+	pub flags: Option<u8>,
+}
+
+/// Starting points for browsing a peer, plus whether its paths are Windows
+/// paths. Path handling follows the peer, not the machine showing it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BrowseRoots {
+	pub windows: bool,
+	pub roots: Vec<BrowseRoot>,
+}
+
+impl BrowseRoots {
+	/// Readable disk mounts (drive letters on Windows, `/` and mounted
+	/// volumes elsewhere) followed by shared folders that aren't already a
+	/// disk root.
+	pub(crate) fn collect(
+		windows: bool,
+		disks: &[DiskInfo],
+		shared_folders: &[String],
+		can_read: impl Fn(&str) -> bool,
+	) -> Self {
+		let mut roots = disks
+			.iter()
+			.filter(|disk| !disk.mount_path.is_empty() && can_read(&disk.mount_path))
+			.map(|disk| BrowseRoot {
+				label: if disk.name.is_empty() || disk.name == disk.mount_path {
+					disk.mount_path.clone()
+				} else {
+					format!("{} ({})", disk.mount_path, disk.name)
+				},
+				path: disk.mount_path.clone(),
+				kind: BrowseRootKind::Disk,
+				flags: None,
+			})
+			.collect::<Vec<_>>();
+		roots.sort_by(|a, b| a.path.cmp(&b.path));
+		roots.dedup_by(|a, b| a.path == b.path);
+		let mut shares = shared_folders
+			.iter()
+			.filter(|path| !roots.iter().any(|root| &root.path == *path))
+			.map(|path| BrowseRoot {
+				label: path.clone(),
+				path: path.clone(),
+				kind: BrowseRootKind::SharedFolder,
+				flags: None,
+			})
+			.collect::<Vec<_>>();
+		shares.sort_by(|a, b| a.path.cmp(&b.path));
+		shares.dedup_by(|a, b| a.path == b.path);
+		roots.extend(shares);
+		Self { windows, roots }
+	}
+
+	/// Best guess for peers that predate `ListRoots`, from their OS label.
+	pub(crate) fn legacy(os: &str, disks: &[DiskInfo]) -> Self {
+		let windows = os.to_ascii_lowercase().contains("windows");
+		if windows {
+			Self::collect(true, disks, &[], |_| true)
+		} else {
+			Self {
+				windows: false,
+				roots: vec![BrowseRoot {
+					label: String::from("/"),
+					path: String::from("/"),
+					kind: BrowseRootKind::Disk,
+					flags: None,
+				}],
+			}
+		}
+	}
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -479,6 +1555,7 @@ pub enum PermissionGrant {
 	Owner,
 	Viewer,
 	Files { path: String, access: FileAccess },
+	Inbox,
 	SystemInfo,
 	DiskInfo,
 	NetworkInfo,
@@ -488,6 +1565,8 @@ pub enum PermissionGrant {
 pub enum FileAccess {
 	Read,
 	ReadWrite,
+	/// Listings and capped thumbnails only; the files cannot be read.
+	Preview,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -554,7 +1633,7 @@ impl From<mdns::Event> for AgentEvent {
 }
 
 impl AgentBehaviour {
-	fn new(local_peer_id: PeerId) -> Self {
+	fn new(local_peer_id: PeerId, policy: ConnectionPolicy) -> Self {
 		let puppynet_protocol = std::iter::once((
 			StreamProtocol::new(PUPPYNET_PROTOCOL),
 			ProtocolSupport::Full,
@@ -568,7 +1647,7 @@ impl AgentBehaviour {
 		let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id)
 			.expect("mDNS init failed");
 		Self {
-			ping: ping::Behaviour::default(),
+			ping: ping::Behaviour::new(ping::Config::new().with_interval(policy.ping_interval())),
 			puppynet,
 			mdns,
 		}
@@ -597,6 +1676,7 @@ fn read_file_chunk(path: &str, offset: u64, length: Option<u64>) -> Result<FileC
 		offset,
 		data: buffer,
 		eof,
+		hole: 0,
 	})
 }
 
@@ -660,12 +1740,15 @@ impl PermissionGrant {
 			PermissionGrant::Owner => true,
 			PermissionGrant::Viewer => capability.is_read_only(),
 			PermissionGrant::Files { path, access } => match capability {
-				Capability::FileRead(request_path) => path_matches(path, request_path),
+				Capability::FileRead(request_path) => {
+					!matches!(access, FileAccess::Preview) && path_matches(path, request_path)
+				}
 				Capability::FileWrite(request_path) => {
 					matches!(access, FileAccess::ReadWrite) && path_matches(path, request_path)
 				}
 				Capability::System | Capability::Disks | Capability::Network => false,
 			},
+			PermissionGrant::Inbox => false,
 			PermissionGrant::SystemInfo => matches!(capability, Capability::System),
 			PermissionGrant::DiskInfo => matches!(capability, Capability::Disks),
 			PermissionGrant::NetworkInfo => matches!(capability, Capability::Network),
@@ -754,15 +1837,17 @@ pub(crate) fn permission_from_grant(grant: &PermissionGrant) -> Option<Permissio
 		)))),
 		PermissionGrant::Files { path, access } => {
 			let normalized = normalize_path(path);
-			let mut flags = FLAG_READ | FLAG_SEARCH;
-			if matches!(access, FileAccess::ReadWrite) {
-				flags |= FLAG_WRITE;
-			}
+			let flags = match access {
+				FileAccess::Read => FLAG_READ | FLAG_SEARCH,
+				FileAccess::ReadWrite => FLAG_READ | FLAG_WRITE | FLAG_SEARCH,
+				FileAccess::Preview => FLAG_PREVIEW | FLAG_SEARCH,
+			};
 			Some(Permission::new(Rule::Folder(FolderRule::new(
 				PathBuf::from(normalized),
 				flags,
 			))))
 		}
+		PermissionGrant::Inbox => Some(Permission::new(Rule::Inbox)),
 		PermissionGrant::SystemInfo | PermissionGrant::DiskInfo | PermissionGrant::NetworkInfo => {
 			None
 		}
@@ -772,11 +1857,14 @@ pub(crate) fn permission_from_grant(grant: &PermissionGrant) -> Option<Permissio
 pub(crate) fn grant_from_permission(permission: &Permission) -> Option<PermissionGrant> {
 	match permission.rule() {
 		Rule::Owner => Some(PermissionGrant::Owner),
+		Rule::Inbox => Some(PermissionGrant::Inbox),
 		Rule::Folder(rule) => {
 			let access = if rule.can_write() {
 				FileAccess::ReadWrite
 			} else if rule.can_read() {
 				FileAccess::Read
+			} else if rule.can_preview() {
+				FileAccess::Preview
 			} else {
 				return None;
 			};
@@ -787,12 +1875,18 @@ pub(crate) fn grant_from_permission(permission: &Permission) -> Option<Permissio
 }
 
 /// Load or generate an Ed25519 keypair and persist it to disk.
+/// Keypair file from `KEYPAIR`, relative to the working directory unless
+/// absolute.
+pub(crate) fn keypair_path() -> PathBuf {
+	config::startup().keypair_path.clone()
+}
+
 pub fn load_or_generate_keypair(path: &Path) -> Result<identity::Keypair> {
 	// Ensure parent directory exists if a directory component was provided.
 	if let Some(parent) = path.parent() {
 		if !parent.as_os_str().is_empty() && !parent.exists() {
 			std::fs::create_dir_all(parent)?;
-			log::info!("created key directory {}", parent.display());
+			tracing::info!("created key directory {}", parent.display());
 		}
 	}
 	if path.exists() {
@@ -807,6 +1901,26 @@ pub fn load_or_generate_keypair(path: &Path) -> Result<identity::Keypair> {
 	}
 }
 
+/// The keypair at [`keypair_path`], or an ephemeral one when it can't be
+/// loaded or saved.
+pub(crate) fn node_keypair() -> identity::Keypair {
+	let key_path = keypair_path();
+	let key_path = key_path.as_path();
+	if !key_path.exists() {
+		tracing::warn!(
+			"keypair file {} does not exist, generating new keypair",
+			key_path.display()
+		);
+	}
+	load_or_generate_keypair(key_path).unwrap_or_else(|err| {
+		tracing::warn!(
+			"failed to load persisted keypair at {}: {err}; using ephemeral keypair",
+			key_path.display()
+		);
+		identity::Keypair::generate_ed25519()
+	})
+}
+
 fn libp2p_multiaddr(address: &Multiaddr, local_ip: IpAddr, peer_id: &PeerId) -> Multiaddr {
 	let mut reachable = Multiaddr::empty();
 	for protocol in address.iter() {
@@ -826,23 +1940,132 @@ fn libp2p_multiaddr(address: &Multiaddr, local_ip: IpAddr, peer_id: &PeerId) ->
 	reachable
 }
 
-pub fn build_swarm(id_keys: identity::Keypair, peer_id: PeerId) -> Result<Swarm<AgentBehaviour>> {
-	let swarm = SwarmBuilder::with_existing_identity(id_keys)
+pub(crate) const DEFAULT_TCP_LISTEN: &str = "/ip4/0.0.0.0/tcp/0";
+pub(crate) const DEFAULT_QUIC_LISTEN: &str = "/ip4/0.0.0.0/udp/0/quic-v1";
+
+pub fn build_swarm(
+	id_keys: identity::Keypair,
+	peer_id: PeerId,
+	policy: ConnectionPolicy,
+) -> Result<Swarm<AgentBehaviour>> {
+	let builder = SwarmBuilder::with_existing_identity(id_keys)
 		.with_tokio()
 		.with_tcp(
 			tcp::Config::default(),
 			noise::Config::new,
 			yamux::Config::default,
-		)?
-		.with_behaviour(|_| AgentBehaviour::new(peer_id))?
-		.with_swarm_config(|cfg| cfg.with_idle_connection_timeout(Duration::from_secs(u64::MAX)))
+		)?;
+	#[cfg(feature = "quic")]
+	let builder = builder.with_quic();
+	let swarm = builder
+		.with_behaviour(|_| AgentBehaviour::new(peer_id, policy))?
+		.with_swarm_config(|cfg| cfg.with_idle_connection_timeout(policy.idle_timeout()))
 		.build();
 	Ok(swarm)
 }
 
+/// `value` as a listen address: unset means `default`, `off` disables the
+/// transport.
+pub(crate) fn listen_addr_from(value: Option<&str>, default: &str) -> Option<Multiaddr> {
+	let value = value.map(str::trim).unwrap_or(default);
+	if value.eq_ignore_ascii_case("off") {
+		return None;
+	}
+	match value.parse() {
+		Ok(addr) => Some(addr),
+		Err(err) => {
+			tracing::warn!("ignoring invalid listen address {value}: {err}");
+			None
+		}
+	}
+}
+
+/// Swarm listen addresses from the `listen_tcp` and `listen_quic` knobs,
+/// `PUPPYNET_LISTEN_TCP` and `PUPPYNET_LISTEN_QUIC` in the environment.
+/// Each takes a multiaddr or `off`; QUIC is only available when built with
+/// the `quic` feature.
+pub(crate) fn listen_addrs() -> Vec<Multiaddr> {
+	let config = config::startup();
+	let mut addrs = config.listen_tcp.iter().cloned().collect::<Vec<_>>();
+	if cfg!(feature = "quic") {
+		addrs.extend(config.listen_quic.clone());
+	}
+	addrs
+}
+
+pub(crate) fn is_quic_addr(addr: &Multiaddr) -> bool {
+	addr.iter()
+		.any(|protocol| matches!(protocol, Protocol::QuicV1 | Protocol::Quic))
+}
+
+/// Addresses to try for a peer, QUIC first so it wins when both work. QUIC
+/// addresses are dropped when this build can't dial them.
+pub(crate) fn dial_order(addrs: impl IntoIterator<Item = Multiaddr>) -> Vec<Multiaddr> {
+	let mut seen = HashSet::new();
+	let mut addrs = addrs
+		.into_iter()
+		.filter(|addr| cfg!(feature = "quic") || !is_quic_addr(addr))
+		.filter(|addr| seen.insert(addr.clone()))
+		.collect::<Vec<_>>();
+	addrs.sort_by_key(|addr| !is_quic_addr(addr));
+	addrs
+}
+
+/// Dials `peer` one address at a time in [`dial_order`], so TCP is only
+/// tried once QUIC failed.
+pub(crate) fn dial_opts(peer: PeerId, addrs: impl IntoIterator<Item = Multiaddr>) -> DialOpts {
+	DialOpts::peer_id(peer)
+		.addresses(dial_order(addrs))
+		.condition(PeerCondition::DisconnectedAndNotDialing)
+		.override_dial_concurrency_factor(NonZeroU8::MIN)
+		.build()
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
+	#[cfg(feature = "quic")]
+	use std::sync::atomic::{AtomicBool, Ordering};
+
+	#[test]
+	fn wire_path_stays_compatible_with_string_paths() {
+		let display = WirePath::from("/srv/share/report.pdf");
+
+		let json = serde_json::to_value(&display).unwrap();
+		let raw: WirePath = serde_json::from_value(serde_json::json!([99, 97, 102, 233])).unwrap();
+
+		assert_eq!(json, "/srv/share/report.pdf");
+		assert_eq!(serde_json::from_value::<WirePath>(json).unwrap(), display);
+		assert_eq!(raw, WirePath::Raw(vec![99, 97, 102, 233]));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn raw_wire_path_round_trips_latin1_names() {
+		use std::os::unix::ffi::OsStrExt;
+		let path = Path::new(std::ffi::OsStr::from_bytes(b"/archive/caf\xe9.txt"));
+
+		let wire = WirePath::from_path(path);
+		let json = serde_json::to_string(&wire).unwrap();
+		let decoded: WirePath = serde_json::from_str(&json).unwrap();
+
+		assert!(matches!(wire, WirePath::Raw(_)));
+		assert_eq!(decoded.to_path_buf(), path);
+	}
+
+	#[cfg(windows)]
+	#[test]
+	fn raw_wire_path_round_trips_unpaired_surrogates() {
+		use std::os::windows::ffi::OsStringExt;
+		let path = PathBuf::from(std::ffi::OsString::from_wide(&[
+			0x43, 0x3A, 0x5C, 0x61, 0xD800, 0x62,
+		]));
+
+		let wire = WirePath::from_path(&path);
+
+		assert!(matches!(wire, WirePath::Raw(_)));
+		assert_eq!(wire.to_path_buf(), path);
+	}
 
 	#[test]
 	fn media_frame_data_serializes_as_base64_string() {
@@ -871,4 +2094,332 @@ mod tests {
 		assert_eq!(decoded.mime, "image/jpeg");
 		assert_eq!(decoded.data, vec![1, 2, 3, 255]);
 	}
+
+	fn disk(name: &str, mount_path: &str) -> DiskInfo {
+		DiskInfo {
+			name: name.to_string(),
+			mount_path: mount_path.to_string(),
+			filesystem: String::new(),
+			total_space: 0,
+			available_space: 0,
+			usage_percent: 0.0,
+			total_read_bytes: 0,
+			total_written_bytes: 0,
+			read_only: false,
+			removable: false,
+			kind: String::new(),
+			id: String::new(),
+		}
+	}
+
+	fn root_paths(roots: &BrowseRoots) -> Vec<&str> {
+		roots.roots.iter().map(|root| root.path.as_str()).collect()
+	}
+
+	#[test]
+	fn shells_desktop_input_and_delete_proposals_touch_the_filesystem() {
+		let touching = [
+			PeerReq::StartShell { id: 1 },
+			PeerReq::ShellInput {
+				id: 1,
+				data: b"ls\n".to_vec(),
+			},
+			PeerReq::DesktopInput {
+				input: DesktopInput::KeyboardText {
+					text: String::from("rm -rf ~"),
+				},
+			},
+			PeerReq::DeleteProposal {
+				id: String::from("p1"),
+				hash: [0; 32],
+				paths_hint: Vec::new(),
+				reason: String::new(),
+				expires_at: Utc::now(),
+			},
+		];
+		for req in &touching {
+			assert!(req.touches_filesystem(), "{} should be refused", req.name());
+		}
+		assert!(!PeerReq::CloseShell { id: 1 }.touches_filesystem());
+	}
+
+	#[test]
+	fn browse_roots_follow_the_remote_platform() {
+		let windows = BrowseRoots::collect(
+			true,
+			&[
+				disk("Data", "D:\\"),
+				disk("Local Disk", "C:\\"),
+				disk("", "E:\\"),
+			],
+			&[String::from("D:\\"), String::from("C:\\Users\\bob\\Share")],
+			|mount| mount != "E:\\",
+		);
+		assert!(windows.windows);
+		assert_eq!(
+			root_paths(&windows),
+			vec!["C:\\", "D:\\", "C:\\Users\\bob\\Share"]
+		);
+		assert_eq!(windows.roots[0].label, "C:\\ (Local Disk)");
+		assert_eq!(windows.roots[2].kind, BrowseRootKind::SharedFolder);
+
+		let unix = BrowseRoots::collect(
+			false,
+			&[disk("/dev/sda1", "/"), disk("/dev/sdb1", "/mnt/usb")],
+			&[String::from("/srv/share")],
+			|mount| mount == "/mnt/usb",
+		);
+		assert!(!unix.windows);
+		assert_eq!(root_paths(&unix), vec!["/mnt/usb", "/srv/share"]);
+
+		let legacy_windows = BrowseRoots::legacy("Windows 11 (26100)", &[disk("", "C:\\")]);
+		assert!(legacy_windows.windows);
+		assert_eq!(root_paths(&legacy_windows), vec!["C:\\"]);
+		let legacy_linux = BrowseRoots::legacy("Ubuntu 24.04", &[disk("", "/boot")]);
+		assert!(!legacy_linux.windows);
+		assert_eq!(root_paths(&legacy_linux), vec!["/"]);
+	}
+
+	#[test]
+	fn dial_order_prefers_quic_and_listen_addrs_can_be_disabled() {
+		let tcp: Multiaddr = "/ip4/192.168.1.5/tcp/4001".parse().unwrap();
+		let quic: Multiaddr = "/ip4/192.168.1.5/udp/4001/quic-v1".parse().unwrap();
+		let ordered = dial_order([tcp.clone(), quic.clone(), tcp.clone()]);
+		if cfg!(feature = "quic") {
+			assert_eq!(ordered, vec![quic, tcp]);
+		} else {
+			assert_eq!(ordered, vec![tcp]);
+		}
+
+		assert_eq!(listen_addr_from(Some("off"), DEFAULT_TCP_LISTEN), None);
+		assert_eq!(
+			listen_addr_from(None, DEFAULT_QUIC_LISTEN),
+			Some(DEFAULT_QUIC_LISTEN.parse().unwrap())
+		);
+		assert_eq!(
+			listen_addr_from(Some("not an addr"), DEFAULT_TCP_LISTEN),
+			None
+		);
+	}
+
+	fn test_swarm_with(policy: ConnectionPolicy) -> (Swarm<AgentBehaviour>, PeerId) {
+		let keys = identity::Keypair::generate_ed25519();
+		let peer_id = keys.public().to_peer_id();
+		(build_swarm(keys, peer_id, policy).unwrap(), peer_id)
+	}
+
+	fn test_swarm() -> (Swarm<AgentBehaviour>, PeerId) {
+		test_swarm_with(ConnectionPolicy::default())
+	}
+
+	/// Serves one ListDir from a node listening on `listen` to a node
+	/// dialing `extra` addresses plus the listener's. Returns the address
+	/// the client connected over.
+	async fn list_dir_round_trip(listen: &str, extra: Vec<Multiaddr>) -> Multiaddr {
+		use futures::StreamExt;
+		let (mut server, server_id) = test_swarm();
+		let (mut client, _) = test_swarm();
+		server.listen_on(listen.parse().unwrap()).unwrap();
+		let listening = loop {
+			if let SwarmEvent::NewListenAddr { address, .. } = server.select_next_some().await {
+				break address;
+			}
+		};
+		let mut addrs = extra;
+		addrs.push(listening);
+		client.dial(dial_opts(server_id, addrs)).unwrap();
+		client.behaviour_mut().puppynet.send_request(
+			&server_id,
+			PeerReq::ListDir {
+				path: WirePath::from("/srv"),
+			},
+		);
+
+		let mut connected = None;
+		tokio::time::timeout(Duration::from_secs(20), async {
+			loop {
+				tokio::select! {
+					event = server.select_next_some() => {
+						if let SwarmEvent::Behaviour(AgentEvent::PuppyNet(
+							RequestResponseEvent::Message {
+								message: RequestResponseMessage::Request { request, channel, .. },
+								..
+							},
+						)) = event
+						{
+							assert!(matches!(request, PeerReq::ListDir { .. }));
+							server
+								.behaviour_mut()
+								.puppynet
+								.send_response(channel, PeerRes::DirEntries(Vec::new()))
+								.unwrap();
+						}
+					}
+					event = client.select_next_some() => match event {
+						SwarmEvent::ConnectionEstablished { endpoint, .. } => {
+							connected = Some(endpoint.get_remote_address().clone());
+						}
+						SwarmEvent::Behaviour(AgentEvent::PuppyNet(RequestResponseEvent::Message {
+							message: RequestResponseMessage::Response { response, .. },
+							..
+						})) => {
+							assert!(matches!(response, PeerRes::DirEntries(_)));
+							break;
+						}
+						_ => {}
+					},
+				}
+			}
+		})
+		.await
+		.expect("ListDir round trip timed out");
+		connected.expect("client never connected")
+	}
+
+	#[cfg(feature = "quic")]
+	#[tokio::test]
+	async fn list_dir_round_trips_over_quic_only() {
+		let addr = list_dir_round_trip("/ip4/127.0.0.1/udp/0/quic-v1", Vec::new()).await;
+		assert!(is_quic_addr(&addr));
+	}
+
+	/// Answers every QUIC packet on `socket` with a version negotiation
+	/// offering only a reserved version, which no client speaks, so their
+	/// handshakes fail as soon as the reply arrives. Sets `refused` once it
+	/// has answered.
+	#[cfg(feature = "quic")]
+	async fn refuse_quic(socket: tokio::net::UdpSocket, refused: Arc<AtomicBool>) {
+		let mut buf = [0u8; 1500];
+		loop {
+			let Ok((len, from)) = socket.recv_from(&mut buf).await else {
+				return;
+			};
+			// A long header: flags, version and both connection ids, which
+			// the reply swaps.
+			let packet = &buf[..len];
+			if len < 7 || packet[0] & 0x80 == 0 {
+				continue;
+			}
+			let dcid_end = 6 + usize::from(packet[5]);
+			let Some(&scid_len) = packet.get(dcid_end) else {
+				continue;
+			};
+			let scid_end = dcid_end + 1 + usize::from(scid_len);
+			let (Some(dcid), Some(scid)) =
+				(packet.get(6..dcid_end), packet.get(dcid_end + 1..scid_end))
+			else {
+				continue;
+			};
+			let mut reply = vec![0x80, 0, 0, 0, 0];
+			reply.push(scid_len);
+			reply.extend_from_slice(scid);
+			reply.push(packet[5]);
+			reply.extend_from_slice(dcid);
+			reply.extend_from_slice(&[0x0a, 0x1a, 0x2a, 0x3a]);
+			if socket.send_to(&reply, from).await.is_ok() {
+				refused.store(true, Ordering::SeqCst);
+			}
+		}
+	}
+
+	#[cfg(feature = "quic")]
+	#[tokio::test]
+	async fn peers_without_quic_fall_back_to_tcp() {
+		// The peer predates QUIC: only its TCP listener accepts, and the
+		// QUIC address tried first refuses every version, so the dial moves
+		// on without waiting out a handshake timeout.
+		let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		let port = socket.local_addr().unwrap().port();
+		let refused = Arc::new(AtomicBool::new(false));
+		let refuser = tokio::spawn(refuse_quic(socket, Arc::clone(&refused)));
+		let refusing_quic = format!("/ip4/127.0.0.1/udp/{port}/quic-v1")
+			.parse()
+			.unwrap();
+		let addr = list_dir_round_trip("/ip4/127.0.0.1/tcp/0", vec![refusing_quic]).await;
+		refuser.abort();
+		assert!(refused.load(Ordering::SeqCst), "QUIC was never tried");
+		assert!(!is_quic_addr(&addr));
+	}
+
+	#[tokio::test]
+	async fn keepalive_requests_outlast_the_idle_timeout() {
+		use crate::state::DisconnectReason;
+		use futures::StreamExt;
+		// The client's timer runs out first, so it is the side that sees the
+		// idle close once the keepalives stop.
+		let client_policy = ConnectionPolicy {
+			idle_timeout_secs: 2,
+			ping_interval_secs: 1,
+		};
+		let (mut server, server_id) = test_swarm_with(ConnectionPolicy {
+			idle_timeout_secs: 4,
+			ping_interval_secs: 1,
+		});
+		let (mut client, _) = test_swarm_with(client_policy);
+		server
+			.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+			.unwrap();
+		let listening = loop {
+			if let SwarmEvent::NewListenAddr { address, .. } = server.select_next_some().await {
+				break address;
+			}
+		};
+		client.dial(dial_opts(server_id, vec![listening])).unwrap();
+
+		let mut keepalive = interval(client_policy.keepalive_interval());
+		let quiet = tokio::time::sleep(Duration::from_secs(6));
+		tokio::pin!(quiet);
+		let mut connected = false;
+		loop {
+			tokio::select! {
+				_ = &mut quiet => break,
+				_ = keepalive.tick(), if connected => {
+					client
+						.behaviour_mut()
+						.puppynet
+						.send_request(&server_id, PeerReq::HealthCheck);
+				}
+				event = server.select_next_some() => match event {
+					SwarmEvent::Behaviour(AgentEvent::PuppyNet(RequestResponseEvent::Message {
+						message: RequestResponseMessage::Request { channel, .. },
+						..
+					})) => {
+						let _ = server
+							.behaviour_mut()
+							.puppynet
+							.send_response(channel, PeerRes::Error(String::new()));
+					}
+					SwarmEvent::ConnectionClosed { cause, .. } => {
+						panic!("server closed the connection while quiet: {cause:?}");
+					}
+					_ => {}
+				},
+				event = client.select_next_some() => match event {
+					SwarmEvent::ConnectionEstablished { .. } => connected = true,
+					SwarmEvent::ConnectionClosed { cause, .. } => {
+						panic!("client closed the connection while quiet: {cause:?}");
+					}
+					_ => {}
+				},
+			}
+		}
+		assert!(connected && client.is_connected(&server_id));
+
+		// Without keepalives it lapses, and is told apart from a lost one.
+		let reason = tokio::time::timeout(Duration::from_secs(20), async {
+			loop {
+				tokio::select! {
+					_ = server.select_next_some() => {}
+					event = client.select_next_some() => {
+						if let SwarmEvent::ConnectionClosed { cause, .. } = event {
+							break DisconnectReason::from_cause(cause.as_ref());
+						}
+					}
+				}
+			}
+		})
+		.await
+		.expect("idle connection never closed");
+		assert_eq!(reason, DisconnectReason::Idle);
+	}
 }
@@ -27,6 +27,14 @@ impl FilesController {
 		self.core().logout();
 	}
 
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
 	pub fn refresh_files(&mut self) {
 		self.core().refresh_files();
 	}
@@ -50,6 +58,30 @@ impl FilesController {
 	pub fn load_file_preview(&mut self) {
 		self.core().load_file_preview();
 	}
+
+	pub fn next_file_preview_page(&mut self) {
+		self.core().next_file_preview_page();
+	}
+
+	pub fn previous_file_preview_page(&mut self) {
+		self.core().previous_file_preview_page();
+	}
+
+	pub fn download_preview_file(&mut self) {
+		self.core().download_preview_file();
+	}
+
+	pub fn download_preview_file_again(&mut self) {
+		self.core().download_preview_file_again();
+	}
+
+	pub fn open_previous_download(&mut self) {
+		self.core().open_previous_download();
+	}
+
+	pub fn preview_large_image(&mut self) {
+		self.core().preview_large_image();
+	}
 }
 
 #[async_trait]
@@ -26,6 +26,14 @@ impl HomeController {
 	pub fn logout(&mut self) {
 		self.core().logout();
 	}
+
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
 }
 
 #[async_trait]
@@ -41,6 +49,13 @@ impl Component for HomeController {
 		if let Some(result) = super::redirect_unauthenticated(&ctx) {
 			return result;
 		}
+		let onboarding_done = ctx.state.prefs.lock().unwrap().onboarding_done;
+		if !onboarding_done && ctx.state.server.needs_onboarding().await {
+			return MountResult::Redirect("/welcome".to_string());
+		}
+		if let Some(route) = UiControllerCore::new(&ctx).take_resumed_route() {
+			return MountResult::Redirect(route);
+		}
 		MountResult::Ready(Self { ctx })
 	}
 
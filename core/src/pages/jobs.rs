@@ -0,0 +1,128 @@
+use super::{UiContext, UiControllerCore, UiViewState};
+use async_trait::async_trait;
+use std::sync::Arc;
+use wgui::wui::runtime::{Component, Ctx, MountResult, RouteContext};
+
+pub(in super::super) struct JobsController {
+	ctx: Arc<Ctx<UiContext, ()>>,
+}
+
+impl JobsController {
+	fn core(&self) -> UiControllerCore<'_> {
+		UiControllerCore::new(&self.ctx)
+	}
+}
+
+#[wgui::wgui_controller]
+impl JobsController {
+	pub fn state(&self) -> UiViewState {
+		self.core().jobs_state()
+	}
+
+	pub fn title(&self) -> String {
+		String::from("Jobs - PuppyNet UI")
+	}
+
+	pub fn logout(&mut self) {
+		self.core().logout();
+	}
+
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
+	pub fn cancel_job(&mut self, id: u64) {
+		self.core().cancel_job(id);
+	}
+
+	pub fn enable_thumbnail_pregeneration(&mut self) {
+		self.core().enable_thumbnail_pregeneration();
+	}
+
+	pub fn disable_thumbnail_pregeneration(&mut self) {
+		self.core().disable_thumbnail_pregeneration();
+	}
+
+	pub fn pause_pin(&mut self, idx: u32) {
+		self.core().pause_pin(idx);
+	}
+
+	pub fn resume_pin(&mut self, idx: u32) {
+		self.core().resume_pin(idx);
+	}
+
+	pub fn unpin(&mut self, idx: u32) {
+		self.core().unpin(idx);
+	}
+
+	pub fn edit_outbox_watch_path(&mut self, value: String) {
+		self.core().edit_outbox_watch_path(value);
+	}
+
+	pub fn select_outbox_peer(&mut self, value: String) {
+		self.core().select_outbox_peer(value);
+	}
+
+	pub fn edit_outbox_dest_path(&mut self, value: String) {
+		self.core().edit_outbox_dest_path(value);
+	}
+
+	pub fn edit_outbox_pattern(&mut self, value: String) {
+		self.core().edit_outbox_pattern(value);
+	}
+
+	pub fn toggle_outbox_delete_after_send(&mut self) {
+		self.core().toggle_outbox_delete_after_send();
+	}
+
+	pub fn save_outbox_rule(&mut self) {
+		self.core().save_outbox_rule();
+	}
+
+	pub fn cancel_outbox_edit(&mut self) {
+		self.core().cancel_outbox_edit();
+	}
+
+	pub fn edit_outbox_rule(&mut self, idx: u32) {
+		self.core().edit_outbox_rule(idx);
+	}
+
+	pub fn pause_outbox_rule(&mut self, idx: u32) {
+		self.core().pause_outbox_rule(idx);
+	}
+
+	pub fn resume_outbox_rule(&mut self, idx: u32) {
+		self.core().resume_outbox_rule(idx);
+	}
+
+	pub fn remove_outbox_rule(&mut self, idx: u32) {
+		self.core().remove_outbox_rule(idx);
+	}
+}
+
+#[async_trait]
+impl Component for JobsController {
+	type Context = UiContext;
+	type Db = ();
+	type Model = UiViewState;
+
+	async fn mount(
+		ctx: Arc<Ctx<Self::Context, Self::Db>>,
+		_route: RouteContext,
+	) -> MountResult<Self> {
+		if let Some(result) = super::redirect_unauthenticated(&ctx) {
+			return result;
+		}
+		MountResult::Ready(Self { ctx })
+	}
+
+	fn render(&self, _ctx: &Ctx<Self::Context, Self::Db>) -> Self::Model {
+		self.state()
+	}
+
+	fn unmount(self, _ctx: Arc<Ctx<Self::Context, Self::Db>>) {}
+}
@@ -1,3 +1,21 @@
+//! One module per page of the web UI. A page module holds:
+//!
+//! - the page controller: the `#[wgui_controller]` methods the page's
+//!   template calls, which forward to [`UiControllerCore`], and its
+//!   `Component` impl;
+//! - for pages whose state outlives a request (a running search, results
+//!   shown while browsing), a session struct that `UiClientSession` keeps
+//!   one field of, a message enum and an `update(&mut self, msg)` that is
+//!   the only place that state changes.
+//!
+//! Messages that arrive late, like events of a search that was replaced,
+//! are dropped inside `update` (see [`SearchSession::update`]) rather than
+//! by guards in every handler. Accessors such as
+//! [`PeerFilesSession::search_for`] only hand out state that belongs to
+//! what the page currently shows. A new page adds its module here with its
+//! own session struct and messages instead of more fields and branches in
+//! `ui.rs`; `search` and `peer_files` are the examples to follow.
+
 use super::{UiAction, UiContext, UiControllerCore, UiViewState};
 use std::sync::Arc;
 use wgui::wui::runtime::{Ctx, MountResult};
@@ -12,30 +30,52 @@ fn redirect_unauthenticated<C>(ctx: &Arc<Ctx<UiContext, ()>>) -> Option<MountRes
 
 mod files;
 mod home;
+mod jobs;
 mod login;
 mod not_found;
+mod notifications;
 mod peer;
 mod peer_control;
 mod peer_files;
+mod peer_permissions;
+mod peer_shell;
 mod peer_webcams;
 mod peers;
+mod review;
 mod search;
 mod settings;
 mod storage;
+mod timeline;
 mod updates;
 mod users;
+mod welcome;
 
 pub(super) use files::FilesController;
 pub(super) use home::HomeController;
+pub(super) use jobs::JobsController;
 pub(super) use login::LoginController;
 pub(super) use not_found::NotFoundController;
+pub(super) use notifications::NotificationsController;
 pub(super) use peer::PeerController;
 pub(super) use peer_control::PeerControlController;
-pub(super) use peer_files::PeerFilesController;
+pub(super) use peer_files::{
+	PeerFilesController, PeerFilesMsg, PeerFilesSession, ScopedSearch, ThumbnailFetch, ThumbnailMsg,
+};
+pub(super) use peer_permissions::{
+	DraftFlag, PeerPermissionsController, PermissionEditorMsg, PermissionEditorSession,
+	pattern_text,
+};
+pub(super) use peer_shell::{PeerShellController, PeerShellMsg, PeerShellSession, ShellPoll};
 pub(super) use peer_webcams::PeerWebcamsController;
 pub(super) use peers::PeersController;
-pub(super) use search::SearchController;
-pub(super) use settings::SettingsController;
-pub(super) use storage::StorageController;
+pub(super) use review::{ReviewController, ReviewMsg, ReviewSession};
+pub(super) use search::{SearchController, SearchMsg, SearchSession, SearchStream};
+pub(super) use settings::{ProtocolColumn, ProtocolMsg, ProtocolSession, SettingsController};
+pub(super) use storage::{
+	GrowthColumn, GrowthMsg, GrowthSession, StorageController, TREEMAP_DEPTHS, TREEMAP_HEIGHT,
+	TREEMAP_WIDTH, TreemapMsg, TreemapSession, TreemapTile,
+};
+pub(super) use timeline::{TimelineController, TimelineMsg, TimelineSession};
 pub(super) use updates::UpdatesController;
 pub(super) use users::UsersController;
+pub(super) use welcome::WelcomeController;
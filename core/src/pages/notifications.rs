@@ -0,0 +1,64 @@
+use super::{UiContext, UiControllerCore, UiViewState};
+use async_trait::async_trait;
+use std::sync::Arc;
+use wgui::wui::runtime::{Component, Ctx, MountResult, RouteContext};
+
+pub(in super::super) struct NotificationsController {
+	ctx: Arc<Ctx<UiContext, ()>>,
+}
+
+impl NotificationsController {
+	fn core(&self) -> UiControllerCore<'_> {
+		UiControllerCore::new(&self.ctx)
+	}
+}
+
+#[wgui::wgui_controller]
+impl NotificationsController {
+	pub fn state(&self) -> UiViewState {
+		self.core().notifications_state()
+	}
+
+	pub fn title(&self) -> String {
+		String::from("Notifications - PuppyNet UI")
+	}
+
+	pub fn logout(&mut self) {
+		self.core().logout();
+	}
+
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
+	pub fn clear_notifications(&mut self) {
+		self.core().clear_notifications();
+	}
+}
+
+#[async_trait]
+impl Component for NotificationsController {
+	type Context = UiContext;
+	type Db = ();
+	type Model = UiViewState;
+
+	async fn mount(
+		ctx: Arc<Ctx<Self::Context, Self::Db>>,
+		_route: RouteContext,
+	) -> MountResult<Self> {
+		if let Some(result) = super::redirect_unauthenticated(&ctx) {
+			return result;
+		}
+		MountResult::Ready(Self { ctx })
+	}
+
+	fn render(&self, _ctx: &Ctx<Self::Context, Self::Db>) -> Self::Model {
+		self.state()
+	}
+
+	fn unmount(self, _ctx: Arc<Ctx<Self::Context, Self::Db>>) {}
+}
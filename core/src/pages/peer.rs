@@ -31,20 +31,32 @@ impl PeerController {
 		self.core().logout();
 	}
 
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
 	pub fn peer_back(&mut self) {
 		self.core().peer_back();
 	}
 
-	pub fn edit_shell_input(&mut self, value: String) {
-		self.core().edit_shell_input(value);
+	pub fn search_shared_folder(&mut self, idx: u32) {
+		self.core().search_shared_folder(idx);
 	}
 
-	pub fn start_shell(&mut self) {
-		self.core().start_shell();
+	pub fn pin_shared_folder(&mut self, idx: u32) {
+		self.core().pin_shared_folder(idx);
 	}
 
-	pub fn send_shell_input(&mut self) {
-		self.core().send_shell_input();
+	pub fn edit_send_file_path(&mut self, value: String) {
+		self.core().edit_send_file_path(value);
+	}
+
+	pub fn send_file(&mut self) {
+		self.core().send_file();
 	}
 
 	pub fn refresh_audio(&mut self) {
@@ -71,6 +83,10 @@ impl PeerController {
 		self.core().edit_shared_folder_path(value);
 	}
 
+	pub fn pick_shared_folder(&mut self, idx: u32) {
+		self.core().pick_shared_folder(idx);
+	}
+
 	pub fn select_shared_folder_access(&mut self, value: String) {
 		self.core().select_shared_folder_access(value);
 	}
@@ -79,6 +95,94 @@ impl PeerController {
 		self.core().add_shared_folder();
 	}
 
+	pub fn edit_peer_download_dir(&mut self, value: String) {
+		self.core().edit_peer_download_dir(value);
+	}
+
+	pub fn save_peer_download_dir(&mut self) {
+		self.core().save_peer_download_dir();
+	}
+
+	pub fn edit_wake_mac(&mut self, value: String) {
+		self.core().edit_wake_mac(value);
+	}
+
+	pub fn save_wake_mac(&mut self) {
+		self.core().save_wake_mac();
+	}
+
+	pub fn wake_peer(&mut self) {
+		self.core().wake_peer();
+	}
+
+	pub fn toggle_keep_connected(&mut self) {
+		self.core().toggle_keep_connected();
+	}
+
+	pub fn revoke_peer_access(&mut self) {
+		self.core().revoke_peer_access();
+	}
+
+	pub fn limit_peer_traffic(&mut self) {
+		self.core().limit_peer_traffic();
+	}
+
+	pub fn suspend_peer(&mut self) {
+		self.core().suspend_peer();
+	}
+
+	pub fn replicate_index(&mut self) {
+		self.core().replicate_index();
+	}
+
+	pub fn stop_index_replication(&mut self) {
+		self.core().stop_index_replication();
+	}
+
+	pub fn toggle_index_updates(&mut self) {
+		self.core().toggle_index_updates();
+	}
+
+	pub fn edit_remote_scan_path(&mut self, value: String) {
+		self.core().edit_remote_scan_path(value);
+	}
+
+	pub fn edit_scan_read_limit(&mut self, value: String) {
+		self.core().edit_scan_read_limit(value);
+	}
+
+	pub fn toggle_scan_low_priority(&mut self) {
+		self.core().toggle_scan_low_priority();
+	}
+
+	pub fn start_remote_scan(&mut self) {
+		self.core().start_remote_scan();
+	}
+
+	pub fn continue_scan_results(&mut self, idx: u32) {
+		self.core().continue_scan_results(idx);
+	}
+
+	pub fn browse_scan_results(&mut self, idx: u32) {
+		self.core().browse_scan_results(idx);
+	}
+
+	pub fn edit_temporary_grant_path(&mut self, value: String) {
+		self.core().edit_temporary_grant_path(value);
+	}
+
+	pub fn select_temporary_grant_access(&mut self, value: String) {
+		self.core().select_temporary_grant_access(value);
+	}
+
+	pub fn grant_temporary_access(&mut self, idx: u32) {
+		self.core().grant_temporary_access(idx);
+	}
+
+	pub fn revoke_temporary_grant(&mut self, id: u64) {
+		self.core().revoke_temporary_grant(id);
+	}
+
 	pub fn edit_update_version(&mut self, value: String) {
 		self.core().edit_update_version(value);
 	}
@@ -86,11 +190,21 @@ impl PeerController {
 	pub fn start_peer_update(&mut self) {
 		self.core().start_peer_update();
 	}
-}
 
-impl PeerController {
-	pub fn poll_peer_update(&mut self) {
-		self.core().poll_peer_update();
+	pub fn toggle_restart_after_update(&mut self) {
+		self.core().toggle_restart_after_update();
+	}
+
+	pub fn request_peer_restart(&mut self) {
+		self.core().request_peer_restart();
+	}
+
+	pub fn cancel_peer_restart(&mut self) {
+		self.core().cancel_peer_restart();
+	}
+
+	pub fn confirm_peer_restart(&mut self) {
+		self.core().confirm_peer_restart();
 	}
 }
 
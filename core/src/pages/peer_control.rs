@@ -31,6 +31,14 @@ impl PeerControlController {
 		self.core().logout();
 	}
 
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
 	pub fn move_peer_mouse(&mut self, payload: wgui::serde_json::Value) {
 		self.core().move_peer_mouse(payload);
 	}
@@ -1,8 +1,330 @@
+use super::super::UiSearchRow;
 use super::{UiContext, UiControllerCore, UiViewState};
+use crate::natural_sort::natural_cmp;
+use crate::p2p::DirEntry;
+use crate::ui_prefs::{DirSort, DirView};
+use crate::ui_window::RowWindow;
 use async_trait::async_trait;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Range;
 use std::sync::Arc;
 use wgui::wui::runtime::{Component, Ctx, MountResult, RouteContext};
 
+/// An index search under one folder of one device.
+#[derive(Clone)]
+pub(in super::super) struct ScopedSearch {
+	pub(in super::super) peer_id: String,
+	pub(in super::super) path: String,
+	pub(in super::super) results: Vec<UiSearchRow>,
+	pub(in super::super) status: String,
+}
+
+pub(in super::super) enum PeerFilesMsg {
+	QueryEdited(String),
+	Searched(ScopedSearch),
+	Cleared,
+	/// Flips whether searches include files the index saw deleted.
+	DeletedToggled,
+	/// Flips whether sparse files also show the space they take on disk.
+	DiskSizesToggled,
+	/// Shows or hides the request for access the browsed folder refused.
+	AccessRequestToggled,
+	/// Shows or hides where the search result at the index came from.
+	ProvenanceToggled(usize),
+	/// Marks the file the browser opens after following a search result.
+	Highlighted(String),
+	/// Another folder was listed; its rows render from the top again.
+	FolderOpened,
+	/// The list was scrolled to the end of the rendered rows.
+	ShowMore,
+	/// Keyboard focus moved to the row at the index.
+	Focused(usize),
+}
+
+/// The `listed` entries of a folder as `view` shows them: folders first,
+/// each group in the chosen order with names breaking ties. Sorting here
+/// means a peer's own order never shows, and changing the order needs no
+/// listing.
+pub(in super::super) fn shown_entries(listed: &[DirEntry], view: DirView) -> Vec<DirEntry> {
+	let mut entries = listed
+		.iter()
+		.filter(|entry| view.show_hidden || !entry.is_hidden())
+		.cloned()
+		.collect::<Vec<_>>();
+	entries.sort_by(|left, right| {
+		right
+			.is_dir
+			.cmp(&left.is_dir)
+			.then_with(|| match view.sort {
+				// Folder sizes and types say nothing about their contents.
+				_ if left.is_dir => Ordering::Equal,
+				DirSort::Name => Ordering::Equal,
+				DirSort::Size => right.size.cmp(&left.size),
+				DirSort::Modified => right.modified_at.cmp(&left.modified_at),
+				DirSort::Type => {
+					let extension =
+						|entry: &DirEntry| entry.extension.as_deref().map(str::to_ascii_lowercase);
+					extension(left).cmp(&extension(right))
+				}
+			})
+			.then_with(|| natural_cmp(&left.name, &right.name))
+	});
+	entries
+}
+
+/// Thumbnail fetches a client runs at once while browsing a folder.
+pub(in super::super) const THUMBNAIL_CONCURRENCY: usize = 4;
+
+/// A thumbnail to fetch for the folder of batch `generation`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(in super::super) struct ThumbnailFetch {
+	pub(in super::super) generation: u64,
+	pub(in super::super) peer_id: String,
+	pub(in super::super) name: String,
+	pub(in super::super) path: String,
+}
+
+/// Everything that changes the thumbnails of a browsed folder. Results name
+/// the batch they were fetched for, so ones for a folder that was left are
+/// dropped in [`ThumbnailBatch::update`].
+pub(in super::super) enum ThumbnailMsg {
+	/// A folder was listed; `files` are `(name, path)` of its rendered
+	/// images, in the order shown.
+	Opened {
+		peer_id: String,
+		dir: String,
+		files: Vec<(String, String)>,
+	},
+	/// A fetch finished; `thumbnail` is a data URL, or `None` if it failed.
+	/// `too_large` when it failed because the image would not fit the
+	/// decode budget of the device holding it.
+	Loaded {
+		generation: u64,
+		name: String,
+		thumbnail: Option<String>,
+		too_large: bool,
+	},
+	/// The client browsed away from the folder.
+	Left,
+}
+
+/// Thumbnails of the folder a client is browsing, fetched top of the list
+/// first with at most [`THUMBNAIL_CONCURRENCY`] in flight.
+#[derive(Clone, Default)]
+pub(in super::super) struct ThumbnailBatch {
+	generation: u64,
+	peer_id: String,
+	dir: String,
+	queue: VecDeque<(String, String)>,
+	/// Every file queued for this folder, so rows rendered again aren't.
+	queued: HashSet<String>,
+	in_flight: HashSet<String>,
+	loaded: HashMap<String, String>,
+	too_large: HashSet<String>,
+	total: usize,
+	done: usize,
+}
+
+impl ThumbnailBatch {
+	/// Data URL of the thumbnail of `name`, once loaded.
+	pub(in super::super) fn thumbnail(&self, name: &str) -> Option<&str> {
+		self.loaded.get(name).map(String::as_str)
+	}
+
+	pub(in super::super) fn is_loading(&self, name: &str) -> bool {
+		self.in_flight.contains(name)
+	}
+
+	/// The thumbnail of `name` was refused as too large to decode.
+	pub(in super::super) fn is_too_large(&self, name: &str) -> bool {
+		self.too_large.contains(name)
+	}
+
+	/// "thumbnails 42/300" while the batch runs, empty otherwise.
+	pub(in super::super) fn progress(&self) -> String {
+		if self.done < self.total {
+			format!("thumbnails {}/{}", self.done, self.total)
+		} else {
+			String::new()
+		}
+	}
+
+	fn reset(&mut self) {
+		self.generation += 1;
+		self.queue.clear();
+		self.queued.clear();
+		self.in_flight.clear();
+		self.loaded.clear();
+		self.too_large.clear();
+		self.total = 0;
+		self.done = 0;
+	}
+
+	/// Moves queued files in flight up to the cap.
+	fn fill(&mut self) -> Vec<ThumbnailFetch> {
+		let mut fetches = Vec::new();
+		while self.in_flight.len() < THUMBNAIL_CONCURRENCY {
+			let Some((name, path)) = self.queue.pop_front() else {
+				break;
+			};
+			self.in_flight.insert(name.clone());
+			fetches.push(ThumbnailFetch {
+				generation: self.generation,
+				peer_id: self.peer_id.clone(),
+				name,
+				path,
+			});
+		}
+		fetches
+	}
+
+	/// Applies `msg` and returns the fetches to start now, or `None` when
+	/// it was a result for a folder that was left, so nothing re-renders.
+	pub(in super::super) fn update(&mut self, msg: ThumbnailMsg) -> Option<Vec<ThumbnailFetch>> {
+		match msg {
+			ThumbnailMsg::Opened {
+				peer_id,
+				dir,
+				files,
+			} => {
+				// Rendering the same folder again keeps the batch running
+				// and only adds the rows rendered since.
+				if self.peer_id != peer_id || self.dir != dir {
+					self.reset();
+					self.peer_id = peer_id;
+					self.dir = dir;
+				}
+				// Rows no longer rendered, hidden or sorted out of view,
+				// leave the queue before they are fetched.
+				let rendered = files
+					.iter()
+					.map(|(name, _)| name.as_str())
+					.collect::<HashSet<_>>();
+				let before = self.queue.len();
+				let queued = &mut self.queued;
+				self.queue.retain(|(name, _)| {
+					let keep = rendered.contains(name.as_str());
+					if !keep {
+						queued.remove(name);
+					}
+					keep
+				});
+				self.total -= before - self.queue.len();
+				for (name, path) in files {
+					if self.queued.insert(name.clone()) {
+						self.queue.push_back((name, path));
+						self.total += 1;
+					}
+				}
+				Some(self.fill())
+			}
+			ThumbnailMsg::Loaded {
+				generation,
+				name,
+				thumbnail,
+				too_large,
+			} => {
+				if generation != self.generation || !self.in_flight.remove(&name) {
+					return None;
+				}
+				self.done += 1;
+				if let Some(thumbnail) = thumbnail {
+					self.loaded.insert(name, thumbnail);
+				} else if too_large {
+					self.too_large.insert(name);
+				}
+				Some(self.fill())
+			}
+			ThumbnailMsg::Left => {
+				self.reset();
+				self.peer_id.clear();
+				self.dir.clear();
+				Some(Vec::new())
+			}
+		}
+	}
+}
+
+/// File browser state of one client.
+#[derive(Clone, Default)]
+pub(in super::super) struct PeerFilesSession {
+	pub(in super::super) query: String,
+	pub(in super::super) include_deleted: bool,
+	pub(in super::super) disk_sizes: bool,
+	pub(in super::super) access_request_open: bool,
+	search: Option<ScopedSearch>,
+	/// File the browser scrolls to after opening a search result.
+	pub(in super::super) highlight: String,
+	pub(in super::super) thumbnails: ThumbnailBatch,
+	/// Rows of the listed folder that are rendered.
+	pub(in super::super) window: RowWindow,
+}
+
+impl PeerFilesSession {
+	/// The last search, if it was rooted on `peer_id`. Browsing another
+	/// device hides it instead of showing its results there.
+	pub(in super::super) fn search_for(&self, peer_id: &str) -> Option<&ScopedSearch> {
+		self.search
+			.as_ref()
+			.filter(|search| search.peer_id == peer_id)
+	}
+
+	pub(in super::super) fn search_active(&self) -> bool {
+		self.search.is_some()
+	}
+
+	fn highlighted(&self, entries: &[DirEntry]) -> Option<usize> {
+		if self.highlight.is_empty() {
+			return None;
+		}
+		entries
+			.iter()
+			.position(|entry| !entry.is_dir && entry.name == self.highlight)
+	}
+
+	/// Rows of the listed `entries` to render: the window, stretched to
+	/// the highlighted file wherever it is.
+	pub(in super::super) fn rendered(&self, entries: &[DirEntry]) -> Range<usize> {
+		self.window.range(entries.len(), self.highlighted(entries))
+	}
+
+	/// "Show 200 more (1,800 not shown)" while rows are left out.
+	pub(in super::super) fn more_label(&self, entries: &[DirEntry]) -> String {
+		self.window
+			.more_label(entries.len(), self.highlighted(entries))
+	}
+
+	pub(in super::super) fn update(&mut self, msg: PeerFilesMsg) {
+		match msg {
+			PeerFilesMsg::QueryEdited(query) => self.query = query,
+			PeerFilesMsg::Searched(search) => self.search = Some(search),
+			PeerFilesMsg::Cleared => {
+				self.search = None;
+				self.highlight.clear();
+			}
+			PeerFilesMsg::DeletedToggled => self.include_deleted = !self.include_deleted,
+			PeerFilesMsg::DiskSizesToggled => self.disk_sizes = !self.disk_sizes,
+			PeerFilesMsg::AccessRequestToggled => {
+				self.access_request_open = !self.access_request_open;
+			}
+			PeerFilesMsg::ProvenanceToggled(idx) => {
+				if let Some(row) = self
+					.search
+					.as_mut()
+					.and_then(|search| search.results.get_mut(idx))
+				{
+					row.provenance_open = !row.provenance_open;
+				}
+			}
+			PeerFilesMsg::Highlighted(name) => self.highlight = name,
+			PeerFilesMsg::FolderOpened => self.window = RowWindow::default(),
+			PeerFilesMsg::ShowMore => self.window.grow(),
+			PeerFilesMsg::Focused(idx) => self.window.reveal(idx),
+		}
+	}
+}
+
 pub(in super::super) struct PeerFilesController {
 	ctx: Arc<Ctx<UiContext, ()>>,
 }
@@ -28,7 +350,7 @@ impl PeerFilesController {
 	}
 
 	fn path(&self) -> String {
-		Self::decode_path(self.ctx.query("path").unwrap_or_else(|| String::from("/")))
+		Self::decode_path(self.ctx.query("path").unwrap_or_default())
 	}
 
 	fn peer_id(&self) -> String {
@@ -50,14 +372,83 @@ impl PeerFilesController {
 		self.core().logout();
 	}
 
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
 	pub fn refresh_peer_files(&mut self) {
 		self.core().refresh_peer_files();
 	}
 
+	pub fn contact_sheet_peer_files(&mut self) {
+		self.core().contact_sheet_peer_files();
+	}
+
 	pub fn preview_peer_file(&mut self, idx: u32) {
 		self.core().preview_peer_file(idx);
 	}
 
+	pub fn pin_peer_folder(&mut self, idx: u32) {
+		self.core().pin_peer_folder(idx);
+	}
+
+	pub fn edit_peer_files_search(&mut self, value: String) {
+		self.core().edit_peer_files_search(value);
+	}
+
+	pub fn run_peer_files_search(&mut self) {
+		self.core().run_peer_files_search();
+	}
+
+	pub fn toggle_peer_files_access_request(&mut self) {
+		self.core().toggle_peer_files_access_request();
+	}
+
+	pub fn toggle_peer_files_deleted(&mut self) {
+		self.core().toggle_peer_files_deleted();
+	}
+
+	pub fn toggle_peer_files_disk_sizes(&mut self) {
+		self.core().toggle_peer_files_disk_sizes();
+	}
+
+	pub fn select_peer_files_sort(&mut self, value: String) {
+		self.core().select_peer_files_sort(value);
+	}
+
+	pub fn toggle_peer_files_hidden(&mut self) {
+		self.core().toggle_peer_files_hidden();
+	}
+
+	pub fn toggle_peer_files_thumbnails(&mut self) {
+		self.core().toggle_peer_files_thumbnails();
+	}
+
+	pub fn toggle_peer_files_provenance(&mut self, idx: u32) {
+		self.core().toggle_peer_files_provenance(idx);
+	}
+
+	pub fn clear_peer_files_search(&mut self) {
+		self.core().clear_peer_files_search();
+	}
+
+	pub fn open_peer_files_search_result(&mut self, idx: u32) {
+		self.core()
+			.open_peer_files_search_result(&self.peer_id(), idx);
+	}
+
+	pub fn show_more_peer_files(&mut self) {
+		self.core().show_more_peer_files();
+	}
+
+	pub fn peer_files_key(&mut self, payload: wgui::serde_json::Value) {
+		self.core().peer_files_key(payload);
+	}
+
 	pub fn close_file_preview_modal(&mut self) {
 		self.core().close_file_preview_modal();
 	}
@@ -73,6 +464,30 @@ impl PeerFilesController {
 	pub fn load_file_preview(&mut self) {
 		self.core().load_file_preview();
 	}
+
+	pub fn next_file_preview_page(&mut self) {
+		self.core().next_file_preview_page();
+	}
+
+	pub fn previous_file_preview_page(&mut self) {
+		self.core().previous_file_preview_page();
+	}
+
+	pub fn download_preview_file(&mut self) {
+		self.core().download_preview_file();
+	}
+
+	pub fn download_preview_file_again(&mut self) {
+		self.core().download_preview_file_again();
+	}
+
+	pub fn open_previous_download(&mut self) {
+		self.core().open_previous_download();
+	}
+
+	pub fn preview_large_image(&mut self) {
+		self.core().preview_large_image();
+	}
 }
 
 #[async_trait]
@@ -95,5 +510,316 @@ impl Component for PeerFilesController {
 		self.state()
 	}
 
-	fn unmount(self, _ctx: Arc<Ctx<Self::Context, Self::Db>>) {}
+	fn unmount(self, _ctx: Arc<Ctx<Self::Context, Self::Db>>) {
+		self.core().leave_peer_files();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn search(peer_id: &str, names: &[&str]) -> ScopedSearch {
+		ScopedSearch {
+			peer_id: peer_id.to_string(),
+			path: String::from("/srv"),
+			results: names
+				.iter()
+				.map(|name| UiSearchRow {
+					name: name.to_string(),
+					path: format!("/srv/{name}"),
+					size: String::new(),
+					replicas: String::new(),
+					peer_id: peer_id.to_string(),
+					device: String::new(),
+					mime_type: String::new(),
+					modified_at: String::new(),
+					clock_warning: String::new(),
+					index_stale: String::new(),
+					duration: String::new(),
+					deleted: String::new(),
+					provenance: String::new(),
+					provenance_open: false,
+					focused: false,
+				})
+				.collect(),
+			status: format!("{} result(s)", names.len()),
+		}
+	}
+
+	#[test]
+	fn search_only_shows_on_the_device_it_was_rooted_on() {
+		let mut session = PeerFilesSession::default();
+		session.update(PeerFilesMsg::Searched(search("peer-a", &["a.txt"])));
+		assert_eq!(session.search_for("peer-a").unwrap().results.len(), 1);
+		assert!(session.search_for("peer-b").is_none());
+
+		session.update(PeerFilesMsg::Searched(search(
+			"peer-b",
+			&["b.txt", "c.txt"],
+		)));
+		assert!(session.search_for("peer-a").is_none());
+		assert_eq!(session.search_for("peer-b").unwrap().status, "2 result(s)");
+	}
+
+	#[test]
+	fn clearing_forgets_the_search_and_the_highlight() {
+		let mut session = PeerFilesSession::default();
+		session.update(PeerFilesMsg::QueryEdited(String::from("*.txt")));
+		session.update(PeerFilesMsg::Searched(search("peer-a", &["a.txt"])));
+		session.update(PeerFilesMsg::Highlighted(String::from("a.txt")));
+		assert_eq!(session.highlight, "a.txt");
+
+		session.update(PeerFilesMsg::Cleared);
+		assert!(session.search_for("peer-a").is_none());
+		assert!(session.highlight.is_empty());
+		assert_eq!(session.query, "*.txt");
+	}
+
+	#[test]
+	fn provenance_opens_per_result() {
+		let mut session = PeerFilesSession::default();
+		session.update(PeerFilesMsg::Searched(search(
+			"peer-a",
+			&["a.txt", "b.txt"],
+		)));
+		session.update(PeerFilesMsg::ProvenanceToggled(1));
+		session.update(PeerFilesMsg::ProvenanceToggled(7));
+		let open = |session: &PeerFilesSession| {
+			session
+				.search_for("peer-a")
+				.unwrap()
+				.results
+				.iter()
+				.map(|row| row.provenance_open)
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(open(&session), [false, true]);
+		session.update(PeerFilesMsg::ProvenanceToggled(1));
+		assert_eq!(open(&session), [false, false]);
+	}
+
+	fn open(batch: &mut ThumbnailBatch, dir: &str, count: usize) -> Vec<ThumbnailFetch> {
+		batch
+			.update(ThumbnailMsg::Opened {
+				peer_id: String::from("peer-a"),
+				dir: dir.to_string(),
+				files: (0..count)
+					.map(|i| (format!("{i}.jpg"), format!("{dir}/{i}.jpg")))
+					.collect(),
+			})
+			.unwrap()
+	}
+
+	fn loaded(fetch: &ThumbnailFetch) -> ThumbnailMsg {
+		ThumbnailMsg::Loaded {
+			generation: fetch.generation,
+			name: fetch.name.clone(),
+			thumbnail: Some(format!("data:image/jpeg;base64,{}", fetch.name)),
+			too_large: false,
+		}
+	}
+
+	#[test]
+	fn thumbnails_load_top_first_with_a_capped_number_in_flight() {
+		let mut batch = ThumbnailBatch::default();
+		let first = open(&mut batch, "/srv/photos", 10);
+		assert_eq!(
+			first.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(),
+			["0.jpg", "1.jpg", "2.jpg", "3.jpg"]
+		);
+		assert!(batch.is_loading("0.jpg"));
+		assert!(!batch.is_loading("4.jpg"));
+		assert!(open(&mut batch, "/srv/photos", 10).is_empty());
+
+		let next = batch.update(loaded(&first[0])).unwrap();
+		assert_eq!(
+			next.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(),
+			["4.jpg"]
+		);
+		assert_eq!(batch.in_flight.len(), THUMBNAIL_CONCURRENCY);
+		assert_eq!(
+			batch.thumbnail("0.jpg"),
+			Some("data:image/jpeg;base64,0.jpg")
+		);
+		assert_eq!(batch.progress(), "thumbnails 1/10");
+		// A repeated result does not free a second slot.
+		assert!(batch.update(loaded(&first[0])).is_none());
+		assert_eq!(batch.progress(), "thumbnails 1/10");
+	}
+
+	#[test]
+	fn images_too_large_to_decode_are_told_apart_from_failures() {
+		let mut batch = ThumbnailBatch::default();
+		let fetches = open(&mut batch, "/srv/panoramas", 2);
+		for (fetch, too_large) in fetches.iter().zip([true, false]) {
+			batch.update(ThumbnailMsg::Loaded {
+				generation: fetch.generation,
+				name: fetch.name.clone(),
+				thumbnail: None,
+				too_large,
+			});
+		}
+		assert!(batch.is_too_large("0.jpg"));
+		assert!(!batch.is_too_large("1.jpg"));
+		assert!(batch.thumbnail("0.jpg").is_none());
+
+		open(&mut batch, "/srv/photos", 1);
+		assert!(!batch.is_too_large("0.jpg"));
+	}
+
+	#[test]
+	fn thumbnails_of_a_left_folder_are_dropped() {
+		let mut batch = ThumbnailBatch::default();
+		let old = open(&mut batch, "/srv/photos", 10);
+		let current = open(&mut batch, "/srv/trip", 2);
+		assert_eq!(current.len(), 2);
+
+		assert!(batch.update(loaded(&old[0])).is_none());
+		assert!(batch.thumbnail("0.jpg").is_none());
+		assert_eq!(batch.progress(), "thumbnails 0/2");
+
+		batch.update(ThumbnailMsg::Left);
+		assert!(batch.update(loaded(&current[0])).is_none());
+		assert!(batch.thumbnail("0.jpg").is_none());
+		assert!(batch.progress().is_empty());
+	}
+
+	#[test]
+	fn rows_rendered_later_queue_only_their_own_thumbnails() {
+		let mut batch = ThumbnailBatch::default();
+		let first = open(&mut batch, "/srv/photos", 2);
+		assert_eq!(batch.progress(), "thumbnails 0/2");
+		for fetch in &first {
+			batch.update(loaded(fetch));
+		}
+		assert!(batch.progress().is_empty());
+
+		// The window grew from two rows to five.
+		let more = open(&mut batch, "/srv/photos", 5);
+		assert_eq!(
+			more.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(),
+			["2.jpg", "3.jpg", "4.jpg"]
+		);
+		assert_eq!(more[0].generation, first[0].generation);
+		assert_eq!(batch.progress(), "thumbnails 2/5");
+		assert!(batch.thumbnail("1.jpg").is_some());
+	}
+
+	#[test]
+	fn rows_no_longer_rendered_leave_the_queue() {
+		let mut batch = ThumbnailBatch::default();
+		let first = open(&mut batch, "/srv/photos", 10);
+		assert_eq!(batch.progress(), "thumbnails 0/10");
+
+		// Hiding files left two rows rendered; both are in flight already.
+		assert!(open(&mut batch, "/srv/photos", 2).is_empty());
+		assert_eq!(batch.progress(), "thumbnails 0/4");
+		for fetch in &first {
+			assert_eq!(batch.update(loaded(fetch)), Some(Vec::new()));
+		}
+		assert!(batch.progress().is_empty());
+
+		// Showing them again queues the ones never fetched.
+		let again = open(&mut batch, "/srv/photos", 10);
+		assert_eq!(again.len(), THUMBNAIL_CONCURRENCY);
+		assert_eq!(again[0].name, "4.jpg");
+	}
+
+	fn entry(name: &str, is_dir: bool, size: u64, modified: i64) -> DirEntry {
+		DirEntry {
+			name: name.to_string(),
+			name_raw: Vec::new(),
+			is_dir,
+			extension: name.rsplit_once('.').map(|(_, ext)| ext.to_string()),
+			mime: None,
+			mime_source: Default::default(),
+			size,
+			disk_size: None,
+			created_at: None,
+			modified_at: chrono::DateTime::from_timestamp(modified, 0),
+			accessed_at: None,
+			hidden: false,
+		}
+	}
+
+	#[test]
+	fn folders_sort_first_in_the_chosen_order() {
+		let listed = [
+			entry("file10.txt", false, 5, 300),
+			entry("b", true, 4096, 100),
+			entry("file2.txt", false, 50, 100),
+			entry("A", true, 0, 200),
+			entry("clip.MP4", false, 500, 200),
+		];
+		let names = |sort| {
+			let view = DirView {
+				sort,
+				..DirView::default()
+			};
+			shown_entries(&listed, view)
+				.into_iter()
+				.map(|entry| entry.name)
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(
+			names(DirSort::Name),
+			["A", "b", "clip.MP4", "file2.txt", "file10.txt"]
+		);
+		assert_eq!(
+			names(DirSort::Size),
+			["A", "b", "clip.MP4", "file2.txt", "file10.txt"]
+		);
+		assert_eq!(
+			names(DirSort::Modified),
+			["A", "b", "file10.txt", "clip.MP4", "file2.txt"]
+		);
+		assert_eq!(
+			names(DirSort::Type),
+			["A", "b", "clip.MP4", "file2.txt", "file10.txt"]
+		);
+	}
+
+	#[test]
+	fn hidden_files_show_only_when_asked() {
+		let marked = DirEntry {
+			hidden: true,
+			..entry("desktop.ini", false, 1, 0)
+		};
+		let listed = [
+			entry(".git", true, 0, 0),
+			marked,
+			entry("notes.txt", false, 1, 0),
+		];
+		let hidden = DirView {
+			show_hidden: false,
+			..DirView::default()
+		};
+		let shown = shown_entries(&listed, hidden);
+		assert_eq!(shown.len(), 1);
+		assert_eq!(shown[0].name, "notes.txt");
+		assert_eq!(shown_entries(&listed, DirView::default()).len(), 3);
+	}
+
+	#[test]
+	fn the_window_starts_over_in_another_folder() {
+		let mut session = PeerFilesSession::default();
+		session.update(PeerFilesMsg::ShowMore);
+		session.update(PeerFilesMsg::Focused(700));
+		assert_eq!(session.window.range(50_000, None).end, 800);
+		session.update(PeerFilesMsg::FolderOpened);
+		assert_eq!(session.window, RowWindow::default());
+	}
+
+	#[test]
+	fn encoded_paths_are_decoded() {
+		assert_eq!(
+			PeerFilesController::decode_path(String::from("%2Fsrv%2Fmy+files")),
+			"/srv/my files"
+		);
+		assert_eq!(
+			PeerFilesController::decode_path(String::from("/srv")),
+			"/srv"
+		);
+	}
 }
@@ -0,0 +1,512 @@
+use super::{UiContext, UiControllerCore, UiViewState};
+use crate::permission_draft::{DraftKind, FieldError, PermissionDraft, RuleDraft, parse_patterns};
+use crate::state::TrustLevel;
+use async_trait::async_trait;
+use std::sync::Arc;
+use wgui::wui::runtime::{Component, Ctx, MountResult, RouteContext};
+
+/// A checkbox of a folder rule, in the order the editor shows them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(in super::super) enum DraftFlag {
+	Read,
+	Write,
+	Search,
+	Execute,
+	Preview,
+	Quarantine,
+}
+
+impl DraftFlag {
+	pub(in super::super) const ALL: [Self; 6] = [
+		Self::Read,
+		Self::Write,
+		Self::Search,
+		Self::Execute,
+		Self::Preview,
+		Self::Quarantine,
+	];
+
+	pub(in super::super) fn label(self) -> &'static str {
+		match self {
+			Self::Read => "Read",
+			Self::Write => "Write",
+			Self::Search => "Search",
+			Self::Execute => "Execute",
+			Self::Preview => "Preview",
+			Self::Quarantine => "Review writes",
+		}
+	}
+
+	fn slot(self, rule: &mut RuleDraft) -> &mut bool {
+		match self {
+			Self::Read => &mut rule.read,
+			Self::Write => &mut rule.write,
+			Self::Search => &mut rule.search,
+			Self::Execute => &mut rule.execute,
+			Self::Preview => &mut rule.preview,
+			Self::Quarantine => &mut rule.quarantine,
+		}
+	}
+
+	pub(in super::super) fn is_set(self, rule: &RuleDraft) -> bool {
+		match self {
+			Self::Read => rule.read,
+			Self::Write => rule.write,
+			Self::Search => rule.search,
+			Self::Execute => rule.execute,
+			Self::Preview => rule.preview,
+			Self::Quarantine => rule.quarantine,
+		}
+	}
+}
+
+/// Patterns as typed, split on commas with the spaces kept, so the field
+/// shows back exactly what was typed.
+fn split_patterns(value: &str) -> Vec<String> {
+	if value.is_empty() {
+		Vec::new()
+	} else {
+		value.split(',').map(String::from).collect()
+	}
+}
+
+/// The text of a pattern field, see [`split_patterns`].
+pub(in super::super) fn pattern_text(patterns: &[String]) -> String {
+	patterns.join(",")
+}
+
+pub(in super::super) enum PermissionEditorMsg {
+	/// What `peer_id` is granted, as stored at `revision`, and how far it
+	/// is trusted.
+	Loaded {
+		peer_id: String,
+		draft: PermissionDraft,
+		revision: u64,
+		trust: TrustLevel,
+	},
+	/// The trust level was changed; the rules are left as edited.
+	TrustSet(TrustLevel),
+	Selected(usize),
+	/// The path of the selected rule.
+	PathEdited(String),
+	/// A flag of the selected rule.
+	FlagToggled(DraftFlag),
+	/// The deny patterns of the selected rule, as typed.
+	DenyPatternsEdited(String),
+	/// The allow patterns of the selected rule, as typed.
+	AllowPatternsEdited(String),
+	/// An empty folder rule, selected for editing.
+	RuleAdded,
+	RuleRemoved(usize),
+	MergeToggled,
+	/// The save was refused; the fields to fix.
+	Rejected(Vec<FieldError>),
+	/// The save went through; the rules are reloaded for the new revision.
+	Saved(String),
+	Failed(String),
+	Left,
+}
+
+/// Permissions editor state of one client: the rules of one peer as edited
+/// so far, the revision they were loaded at, and the errors of the last
+/// save, kept next to the rows and fields they are about until those are
+/// edited.
+#[derive(Clone, Default)]
+pub(in super::super) struct PermissionEditorSession {
+	peer_id: String,
+	draft: PermissionDraft,
+	revision: Option<u64>,
+	trust: TrustLevel,
+	selected: Option<usize>,
+	errors: Vec<FieldError>,
+	merge: bool,
+	loaded: bool,
+	pub(in super::super) status: String,
+}
+
+impl PermissionEditorSession {
+	pub(in super::super) fn needs_load(&self, peer_id: &str) -> bool {
+		!self.loaded || self.peer_id != peer_id
+	}
+
+	pub(in super::super) fn peer_id(&self) -> &str {
+		&self.peer_id
+	}
+
+	pub(in super::super) fn draft(&self) -> &PermissionDraft {
+		&self.draft
+	}
+
+	pub(in super::super) fn revision(&self) -> Option<u64> {
+		self.revision
+	}
+
+	pub(in super::super) fn merge(&self) -> bool {
+		self.merge
+	}
+
+	pub(in super::super) fn trust(&self) -> TrustLevel {
+		self.trust
+	}
+
+	pub(in super::super) fn selected(&self) -> Option<(usize, &RuleDraft)> {
+		let idx = self.selected?;
+		self.draft.rules.get(idx).map(|rule| (idx, rule))
+	}
+
+	/// Messages of row `idx` about `fields`, joined into one line.
+	pub(in super::super) fn errors_for(&self, idx: usize, fields: &[&str]) -> String {
+		self.errors
+			.iter()
+			.filter(|error| error.index == idx && fields.contains(&error.field))
+			.map(|error| error.message.as_str())
+			.collect::<Vec<_>>()
+			.join("; ")
+	}
+
+	fn clear_errors(&mut self, idx: usize, fields: &[&str]) {
+		self.errors
+			.retain(|error| error.index != idx || !fields.contains(&error.field));
+	}
+
+	/// Replaces the errors of pattern `field` on row `idx` with what is
+	/// wrong with `patterns` now, so a bad pattern shows while typing.
+	fn check_patterns(&mut self, idx: usize, field: &'static str, patterns: &[String]) {
+		self.clear_errors(idx, &[field]);
+		if let Err(message) = parse_patterns(patterns) {
+			self.errors.push(FieldError {
+				index: idx,
+				field,
+				message,
+			});
+		}
+	}
+
+	fn selected_folder(&mut self) -> Option<(usize, &mut RuleDraft)> {
+		let idx = self.selected?;
+		self.draft
+			.rules
+			.get_mut(idx)
+			.filter(|rule| rule.kind == DraftKind::Folder)
+			.map(|rule| (idx, rule))
+	}
+
+	pub(in super::super) fn update(&mut self, msg: PermissionEditorMsg) {
+		match msg {
+			PermissionEditorMsg::Loaded {
+				peer_id,
+				draft,
+				revision,
+				trust,
+			} => {
+				if peer_id != self.peer_id {
+					self.status.clear();
+					self.merge = false;
+				}
+				self.peer_id = peer_id;
+				self.draft = draft;
+				self.revision = Some(revision);
+				self.trust = trust;
+				self.selected = None;
+				self.errors.clear();
+				self.loaded = true;
+			}
+			PermissionEditorMsg::Selected(idx) => {
+				if idx < self.draft.rules.len() {
+					self.selected = Some(idx);
+				}
+			}
+			PermissionEditorMsg::PathEdited(path) => {
+				if let Some((idx, rule)) = self.selected_folder() {
+					rule.path = path;
+					self.clear_errors(idx, &["path"]);
+				}
+			}
+			PermissionEditorMsg::FlagToggled(flag) => {
+				if let Some((idx, rule)) = self.selected_folder() {
+					let slot = flag.slot(rule);
+					*slot = !*slot;
+					self.clear_errors(idx, &["read", "quarantine"]);
+				}
+			}
+			PermissionEditorMsg::DenyPatternsEdited(value) => {
+				if let Some((idx, rule)) = self.selected_folder() {
+					rule.deny_patterns = split_patterns(&value);
+					let patterns = rule.deny_patterns.clone();
+					self.check_patterns(idx, "deny_patterns", &patterns);
+				}
+			}
+			PermissionEditorMsg::AllowPatternsEdited(value) => {
+				if let Some((idx, rule)) = self.selected_folder() {
+					rule.allow_patterns = split_patterns(&value);
+					let patterns = rule.allow_patterns.clone();
+					self.check_patterns(idx, "allow_patterns", &patterns);
+				}
+			}
+			PermissionEditorMsg::RuleAdded => {
+				self.draft.rules.push(RuleDraft::with_access("", "read"));
+				self.selected = Some(self.draft.rules.len() - 1);
+			}
+			PermissionEditorMsg::RuleRemoved(idx) => {
+				if idx >= self.draft.rules.len() {
+					return;
+				}
+				self.draft.rules.remove(idx);
+				self.errors.retain(|error| error.index != idx);
+				for error in &mut self.errors {
+					if error.index > idx {
+						error.index -= 1;
+					}
+				}
+				self.selected = match self.selected {
+					Some(selected) if selected == idx => None,
+					Some(selected) if selected > idx => Some(selected - 1),
+					selected => selected,
+				};
+			}
+			PermissionEditorMsg::MergeToggled => self.merge = !self.merge,
+			PermissionEditorMsg::TrustSet(trust) => {
+				self.trust = trust;
+				self.status = format!("Trust level set to {}", trust.label());
+			}
+			PermissionEditorMsg::Rejected(errors) => {
+				self.selected = errors.first().map(|error| error.index);
+				self.status = String::from("Fix the marked fields and save again");
+				self.errors = errors;
+			}
+			PermissionEditorMsg::Saved(status) => {
+				self.errors.clear();
+				self.loaded = false;
+				self.status = status;
+			}
+			PermissionEditorMsg::Failed(status) => self.status = status,
+			PermissionEditorMsg::Left => *self = Self::default(),
+		}
+	}
+}
+
+pub(in super::super) struct PeerPermissionsController {
+	ctx: Arc<Ctx<UiContext, ()>>,
+}
+
+impl PeerPermissionsController {
+	fn core(&self) -> UiControllerCore<'_> {
+		UiControllerCore::new(&self.ctx)
+	}
+
+	fn peer_id(&self) -> String {
+		self.ctx.param("peer_id").unwrap_or_default()
+	}
+}
+
+#[wgui::wgui_controller]
+impl PeerPermissionsController {
+	pub fn state(&self) -> UiViewState {
+		self.core().peer_permissions_state(self.peer_id())
+	}
+
+	pub fn title(&self) -> String {
+		String::from("Device Permissions - PuppyNet UI")
+	}
+
+	pub fn logout(&mut self) {
+		self.core().logout();
+	}
+
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
+	pub fn select_permission_rule(&mut self, idx: u32) {
+		self.core().select_permission_rule(idx);
+	}
+
+	pub fn edit_permission_path(&mut self, value: String) {
+		self.core().edit_permission_path(value);
+	}
+
+	pub fn toggle_permission_flag(&mut self, idx: u32) {
+		self.core().toggle_permission_flag(idx);
+	}
+
+	pub fn edit_permission_deny_patterns(&mut self, value: String) {
+		self.core().edit_permission_deny_patterns(value);
+	}
+
+	pub fn edit_permission_allow_patterns(&mut self, value: String) {
+		self.core().edit_permission_allow_patterns(value);
+	}
+
+	pub fn add_permission_rule(&mut self) {
+		self.core().add_permission_rule();
+	}
+
+	pub fn remove_permission_rule(&mut self, idx: u32) {
+		self.core().remove_permission_rule(idx);
+	}
+
+	pub fn toggle_permission_merge(&mut self) {
+		self.core().toggle_permission_merge();
+	}
+
+	pub fn select_permission_trust(&mut self, value: String) {
+		self.core().select_permission_trust(value);
+	}
+
+	pub fn save_permissions(&mut self) {
+		self.core().save_permissions();
+	}
+
+	pub fn reload_permissions(&mut self) {
+		self.core().reload_permissions(self.peer_id());
+	}
+}
+
+#[async_trait]
+impl Component for PeerPermissionsController {
+	type Context = UiContext;
+	type Db = ();
+	type Model = UiViewState;
+
+	async fn mount(
+		ctx: Arc<Ctx<Self::Context, Self::Db>>,
+		_route: RouteContext,
+	) -> MountResult<Self> {
+		if let Some(result) = super::redirect_unauthenticated(&ctx) {
+			return result;
+		}
+		MountResult::Ready(Self { ctx })
+	}
+
+	fn render(&self, _ctx: &Ctx<Self::Context, Self::Db>) -> Self::Model {
+		self.state()
+	}
+
+	fn unmount(self, _ctx: Arc<Ctx<Self::Context, Self::Db>>) {
+		self.core().leave_permission_editor();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn folder(path: &str) -> RuleDraft {
+		RuleDraft::with_access(path, "read")
+	}
+
+	fn loaded(rules: Vec<RuleDraft>) -> PermissionEditorSession {
+		let mut session = PermissionEditorSession::default();
+		session.update(PermissionEditorMsg::Loaded {
+			peer_id: String::from("laptop"),
+			draft: PermissionDraft { rules },
+			revision: 4,
+			trust: TrustLevel::Trusted,
+		});
+		session
+	}
+
+	fn error(index: usize, field: &'static str) -> FieldError {
+		FieldError {
+			index,
+			field,
+			message: format!("bad {field}"),
+		}
+	}
+
+	#[test]
+	fn errors_stay_on_their_fields_until_those_are_edited() {
+		let mut session = loaded(vec![folder("/srv"), folder("")]);
+		session.update(PermissionEditorMsg::Rejected(vec![
+			error(1, "path"),
+			error(1, "read"),
+		]));
+		assert_eq!(session.selected().map(|(idx, _)| idx), Some(1));
+		assert_eq!(session.errors_for(1, &["path"]), "bad path");
+
+		session.update(PermissionEditorMsg::FlagToggled(DraftFlag::Write));
+		assert_eq!(session.errors_for(1, &["read"]), "");
+		assert_eq!(session.errors_for(1, &["path"]), "bad path");
+		session.update(PermissionEditorMsg::PathEdited(String::from("/srv/media")));
+		assert_eq!(session.errors_for(1, &["path"]), "");
+		let (_, rule) = session.selected().unwrap();
+		assert!(rule.write && rule.path == "/srv/media");
+	}
+
+	#[test]
+	fn bad_patterns_show_while_typing_and_keep_the_text() {
+		let mut session = loaded(vec![folder("/srv")]);
+		session.update(PermissionEditorMsg::Selected(0));
+		session.update(PermissionEditorMsg::DenyPatternsEdited(String::from(
+			"*.key, /etc/*",
+		)));
+		assert_eq!(
+			session.errors_for(0, &["deny_patterns"]),
+			"/etc/*: patterns are relative to the folder"
+		);
+		session.update(PermissionEditorMsg::DenyPatternsEdited(String::from(
+			"*.key, id_rsa*, ",
+		)));
+		assert_eq!(session.errors_for(0, &["deny_patterns"]), "");
+		let (_, rule) = session.selected().unwrap();
+		assert_eq!(pattern_text(&rule.deny_patterns), "*.key, id_rsa*, ");
+		assert_eq!(
+			parse_patterns(&rule.deny_patterns).unwrap(),
+			vec!["*.key", "id_rsa*"]
+		);
+	}
+
+	#[test]
+	fn removing_a_row_moves_later_errors_up() {
+		let mut session = loaded(vec![folder("/a"), folder("/b"), folder("/c")]);
+		session.update(PermissionEditorMsg::Rejected(vec![
+			error(0, "path"),
+			error(2, "path"),
+		]));
+		session.update(PermissionEditorMsg::Selected(2));
+		session.update(PermissionEditorMsg::RuleRemoved(0));
+		assert_eq!(session.errors_for(0, &["path"]), "");
+		assert_eq!(session.errors_for(1, &["path"]), "bad path");
+		assert_eq!(
+			session
+				.selected()
+				.map(|(idx, rule)| (idx, rule.path.as_str())),
+			Some((1, "/c"))
+		);
+	}
+
+	#[test]
+	fn a_save_reloads_and_another_peer_starts_over() {
+		let mut session = loaded(vec![folder("/srv")]);
+		session.update(PermissionEditorMsg::MergeToggled);
+		session.update(PermissionEditorMsg::Saved(String::from("Saved")));
+		assert!(session.needs_load("laptop"));
+		assert!(session.merge());
+
+		session.update(PermissionEditorMsg::Loaded {
+			peer_id: String::from("desktop"),
+			draft: PermissionDraft::default(),
+			revision: 1,
+			trust: TrustLevel::Untrusted,
+		});
+		assert!(!session.needs_load("desktop") && session.needs_load("laptop"));
+		assert!(!session.merge() && session.status.is_empty());
+		assert_eq!(session.revision(), Some(1));
+		assert_eq!(session.trust(), TrustLevel::Untrusted);
+	}
+
+	#[test]
+	fn a_trust_change_keeps_unsaved_rules() {
+		let mut session = loaded(vec![folder("/srv")]);
+		session.update(PermissionEditorMsg::RuleAdded);
+		session.update(PermissionEditorMsg::TrustSet(TrustLevel::Basic));
+		assert_eq!(session.trust(), TrustLevel::Basic);
+		assert_eq!(session.draft().rules.len(), 2);
+		assert_eq!(session.selected().map(|(idx, _)| idx), Some(1));
+		assert_eq!(session.status, "Trust level set to Basic");
+	}
+}
@@ -0,0 +1,400 @@
+use super::{UiContext, UiControllerCore, UiViewState};
+use crate::ShellOutput;
+use crate::ui_terminal::{FEED_BATCH, Span, Terminal};
+use async_trait::async_trait;
+use std::sync::Arc;
+use wgui::wui::runtime::{Component, Ctx, MountResult, RouteContext};
+
+/// Output waiting to be shown past which the shell is not read from until
+/// the page caught up, so a flood of output backs up on the peer instead of
+/// in memory here.
+const MAX_BACKLOG: usize = 4 * FEED_BATCH;
+
+pub(in super::super) enum PeerShellMsg {
+	/// A shell is being started on `peer_id`.
+	Starting {
+		peer_id: String,
+	},
+	/// The shell asked for last is up as `session_id`.
+	Started {
+		session_id: u64,
+	},
+	Output {
+		session_id: u64,
+		data: Vec<u8>,
+	},
+	/// The shell of `session_id` exited or its session was closed.
+	Exited {
+		session_id: u64,
+	},
+	/// The shell of `session_id` could not be reached.
+	Lost {
+		session_id: u64,
+		error: String,
+	},
+	/// The shell could not be started.
+	Failed(String),
+	InputEdited(String),
+	/// The input line went to the shell.
+	Sent,
+	/// A render is about to show the terminal; one batch of the output
+	/// waiting is fed to it.
+	Frame,
+	Left,
+}
+
+impl PeerShellMsg {
+	pub(in super::super) fn from_output(session_id: u64, output: ShellOutput) -> Self {
+		match output {
+			ShellOutput::Output(data) => Self::Output { session_id, data },
+			ShellOutput::Exited => Self::Exited { session_id },
+		}
+	}
+}
+
+/// What the output poller of a session does next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(in super::super) enum ShellPoll {
+	/// Ask the shell for output, then render.
+	Read,
+	/// Only render: output is waiting to be shown.
+	Render,
+	/// The session ended or was replaced, and everything it printed is shown.
+	Stop,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+enum Connection {
+	#[default]
+	Idle,
+	Starting,
+	Running(u64),
+	Exited(u64),
+	Failed(String),
+}
+
+/// Terminal of one client: the shell it runs on one peer and what that
+/// shell printed so far.
+#[derive(Clone, Default)]
+pub(in super::super) struct PeerShellSession {
+	peer_id: String,
+	connection: Connection,
+	terminal: Terminal,
+	pub(in super::super) input: String,
+}
+
+impl PeerShellSession {
+	/// No shell was asked for on `peer_id` yet.
+	pub(in super::super) fn needs_start(&self, peer_id: &str) -> bool {
+		self.peer_id != peer_id || self.connection == Connection::Idle
+	}
+
+	/// The peer and session of the shell input can go to.
+	pub(in super::super) fn live(&self) -> Option<(&str, u64)> {
+		match self.connection {
+			Connection::Running(session_id) => Some((&self.peer_id, session_id)),
+			_ => None,
+		}
+	}
+
+	/// The shell can be started again: it exited, failed to start or was lost.
+	pub(in super::super) fn ended(&self) -> bool {
+		matches!(
+			self.connection,
+			Connection::Exited(_) | Connection::Failed(_)
+		)
+	}
+
+	pub(in super::super) fn status(&self) -> String {
+		match &self.connection {
+			Connection::Idle => String::from("Not started"),
+			Connection::Starting => String::from("Connecting..."),
+			Connection::Running(session_id) => format!("Connected, session {session_id}"),
+			Connection::Exited(session_id) => format!("Session {session_id} exited"),
+			Connection::Failed(err) => err.clone(),
+		}
+	}
+
+	pub(in super::super) fn spans(&self) -> Vec<Span> {
+		self.terminal.spans()
+	}
+
+	/// Output is waiting to be shown by the next render.
+	pub(in super::super) fn waiting(&self) -> bool {
+		self.terminal.has_queued()
+	}
+
+	pub(in super::super) fn poll(&self, session_id: u64) -> ShellPoll {
+		let current = match self.connection {
+			Connection::Running(id) | Connection::Exited(id) => id == session_id,
+			_ => false,
+		};
+		if !current {
+			ShellPoll::Stop
+		} else if self.live().is_some() && self.terminal.queued() <= MAX_BACKLOG {
+			ShellPoll::Read
+		} else if self.terminal.has_queued() {
+			ShellPoll::Render
+		} else {
+			ShellPoll::Stop
+		}
+	}
+
+	fn is_current(&self, session_id: u64) -> bool {
+		self.connection == Connection::Running(session_id)
+	}
+
+	pub(in super::super) fn update(&mut self, msg: PeerShellMsg) {
+		match msg {
+			PeerShellMsg::Starting { peer_id } => {
+				if peer_id != self.peer_id {
+					*self = Self::default();
+					self.peer_id = peer_id;
+				}
+				self.connection = Connection::Starting;
+			}
+			PeerShellMsg::Started { session_id } => {
+				if self.connection == Connection::Starting {
+					self.connection = Connection::Running(session_id);
+				}
+			}
+			PeerShellMsg::Output { session_id, data } => {
+				if self.is_current(session_id) {
+					self.terminal.push(&data);
+				}
+			}
+			PeerShellMsg::Exited { session_id } => {
+				if self.is_current(session_id) {
+					self.connection = Connection::Exited(session_id);
+				}
+			}
+			PeerShellMsg::Lost { session_id, error } => {
+				if self.is_current(session_id) {
+					self.connection = Connection::Failed(error);
+				}
+			}
+			PeerShellMsg::Failed(err) => self.connection = Connection::Failed(err),
+			PeerShellMsg::InputEdited(input) => self.input = input,
+			PeerShellMsg::Sent => self.input.clear(),
+			PeerShellMsg::Frame => self.terminal.feed_batch(),
+			PeerShellMsg::Left => *self = Self::default(),
+		}
+	}
+}
+
+pub(in super::super) struct PeerShellController {
+	ctx: Arc<Ctx<UiContext, ()>>,
+}
+
+impl PeerShellController {
+	fn core(&self) -> UiControllerCore<'_> {
+		UiControllerCore::new(&self.ctx)
+	}
+
+	fn peer_id(&self) -> String {
+		self.ctx.param("peer_id").unwrap_or_default()
+	}
+}
+
+#[wgui::wgui_controller]
+impl PeerShellController {
+	pub fn state(&self) -> UiViewState {
+		self.core().peer_shell_state(self.peer_id())
+	}
+
+	pub fn title(&self) -> String {
+		String::from("Device Shell - PuppyNet UI")
+	}
+
+	pub fn logout(&mut self) {
+		self.core().logout();
+	}
+
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
+	pub fn edit_peer_shell_input(&mut self, value: String) {
+		self.core().edit_peer_shell_input(value);
+	}
+
+	pub fn send_peer_shell_input(&mut self) {
+		self.core().send_peer_shell_input();
+	}
+
+	pub fn interrupt_peer_shell(&mut self) {
+		self.core().interrupt_peer_shell();
+	}
+
+	pub fn restart_peer_shell(&mut self) {
+		self.core().restart_peer_shell(self.peer_id());
+	}
+}
+
+#[async_trait]
+impl Component for PeerShellController {
+	type Context = UiContext;
+	type Db = ();
+	type Model = UiViewState;
+
+	async fn mount(
+		ctx: Arc<Ctx<Self::Context, Self::Db>>,
+		_route: RouteContext,
+	) -> MountResult<Self> {
+		if let Some(result) = super::redirect_unauthenticated(&ctx) {
+			return result;
+		}
+		MountResult::Ready(Self { ctx })
+	}
+
+	fn render(&self, _ctx: &Ctx<Self::Context, Self::Db>) -> Self::Model {
+		self.state()
+	}
+
+	fn unmount(self, _ctx: Arc<Ctx<Self::Context, Self::Db>>) {
+		self.core().leave_peer_shell();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn running(session_id: u64) -> PeerShellSession {
+		let mut session = PeerShellSession::default();
+		session.update(PeerShellMsg::Starting {
+			peer_id: String::from("peer-a"),
+		});
+		session.update(PeerShellMsg::Started { session_id });
+		session
+	}
+
+	fn shown(session: &mut PeerShellSession) -> String {
+		while session.terminal.has_queued() {
+			session.update(PeerShellMsg::Frame);
+		}
+		session.terminal.text()
+	}
+
+	#[test]
+	fn a_started_shell_takes_input_and_shows_its_output() {
+		let mut session = PeerShellSession::default();
+		assert!(session.needs_start("peer-a"));
+		session.update(PeerShellMsg::Starting {
+			peer_id: String::from("peer-a"),
+		});
+		assert!(!session.needs_start("peer-a"));
+		assert_eq!(session.live(), None);
+		assert_eq!(session.status(), "Connecting...");
+
+		session.update(PeerShellMsg::Started { session_id: 7 });
+		assert_eq!(session.live(), Some(("peer-a", 7)));
+		assert_eq!(session.status(), "Connected, session 7");
+		assert_eq!(session.poll(7), ShellPoll::Read);
+
+		session.update(PeerShellMsg::InputEdited(String::from("ls")));
+		session.update(PeerShellMsg::Sent);
+		assert!(session.input.is_empty());
+		session.update(PeerShellMsg::Output {
+			session_id: 7,
+			data: b"\x1b[32mnotes.txt\x1b[0m\n$ ".to_vec(),
+		});
+		assert_eq!(shown(&mut session), "notes.txt\n$ ");
+	}
+
+	#[test]
+	fn the_peer_ending_the_shell_mid_use_stops_input_and_keeps_the_output() {
+		let mut session = running(7);
+		session.update(PeerShellMsg::Output {
+			session_id: 7,
+			data: b"bye\n".to_vec(),
+		});
+		session.update(PeerShellMsg::Exited { session_id: 7 });
+
+		assert_eq!(session.live(), None);
+		assert!(session.ended());
+		assert_eq!(session.status(), "Session 7 exited");
+		assert_eq!(session.poll(7), ShellPoll::Render);
+		assert_eq!(shown(&mut session), "bye\n");
+		assert_eq!(session.poll(7), ShellPoll::Stop);
+		assert!(!session.needs_start("peer-a"));
+	}
+
+	#[test]
+	fn output_of_a_replaced_or_left_session_is_dropped() {
+		let mut session = running(7);
+		session.update(PeerShellMsg::Exited { session_id: 7 });
+		session.update(PeerShellMsg::Starting {
+			peer_id: String::from("peer-a"),
+		});
+		session.update(PeerShellMsg::Started { session_id: 8 });
+		session.update(PeerShellMsg::Output {
+			session_id: 7,
+			data: b"stale".to_vec(),
+		});
+		session.update(PeerShellMsg::Exited { session_id: 7 });
+
+		assert_eq!(session.live(), Some(("peer-a", 8)));
+		assert_eq!(session.poll(7), ShellPoll::Stop);
+		assert_eq!(shown(&mut session), "");
+
+		session.update(PeerShellMsg::Lost {
+			session_id: 7,
+			error: String::from("shell session not found"),
+		});
+		assert_eq!(session.live(), Some(("peer-a", 8)));
+
+		session.update(PeerShellMsg::Left);
+		assert_eq!(session.poll(8), ShellPoll::Stop);
+		assert!(session.needs_start("peer-a"));
+	}
+
+	#[test]
+	fn a_failed_start_can_be_retried_and_another_peer_starts_clean() {
+		let mut session = PeerShellSession::default();
+		session.update(PeerShellMsg::Starting {
+			peer_id: String::from("peer-a"),
+		});
+		session.update(PeerShellMsg::Failed(String::from(
+			"Failed to start shell: peer offline",
+		)));
+		assert!(session.ended());
+		assert_eq!(session.status(), "Failed to start shell: peer offline");
+
+		let mut session = running(7);
+		session.update(PeerShellMsg::Lost {
+			session_id: 7,
+			error: String::from("Shell input failed: peer disconnected"),
+		});
+		assert_eq!(session.live(), None);
+		assert!(session.ended());
+
+		let mut session = running(7);
+		session.update(PeerShellMsg::Output {
+			session_id: 7,
+			data: b"old".to_vec(),
+		});
+		session.update(PeerShellMsg::Starting {
+			peer_id: String::from("peer-b"),
+		});
+		assert!(!session.terminal.has_queued());
+		assert!(session.needs_start("peer-a"));
+	}
+
+	#[test]
+	fn a_flood_of_output_pauses_reading_until_it_is_shown() {
+		let mut session = running(7);
+		session.update(PeerShellMsg::Output {
+			session_id: 7,
+			data: vec![b'x'; MAX_BACKLOG + 1],
+		});
+
+		assert_eq!(session.poll(7), ShellPoll::Render);
+		session.update(PeerShellMsg::Frame);
+		assert_eq!(session.poll(7), ShellPoll::Read);
+	}
+}
@@ -31,6 +31,14 @@ impl PeerWebcamsController {
 		self.core().logout();
 	}
 
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
 	pub fn refresh_webcams(&mut self) {
 		self.core().refresh_webcams();
 	}
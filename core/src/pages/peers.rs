@@ -27,6 +27,22 @@ impl PeersController {
 		self.core().logout();
 	}
 
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
+	pub fn toggle_peer_failures(&mut self) {
+		self.core().toggle_peer_failures();
+	}
+
+	pub fn update_outdated_peers(&mut self) {
+		self.core().update_outdated_peers();
+	}
+
 	pub fn refresh_peers(&mut self) {
 		self.core().refresh_peers();
 	}
@@ -34,23 +50,67 @@ impl PeersController {
 	pub fn peer_row(&mut self, idx: u32) {
 		self.core().peer_row(idx);
 	}
-}
 
-impl PeersController {
-	pub fn peer_back(&mut self) {
-		self.core().peer_back();
+	pub fn peers_key(&mut self, payload: wgui::serde_json::Value) {
+		self.core().peers_key(payload);
+	}
+
+	pub fn open_share_wizard(&mut self) {
+		self.core().open_share_wizard();
+	}
+
+	pub fn close_share_wizard(&mut self) {
+		self.core().close_share_wizard();
+	}
+
+	pub fn edit_share_wizard_path(&mut self, value: String) {
+		self.core().edit_share_wizard_path(value);
+	}
+
+	pub fn pick_share_wizard_folder(&mut self, idx: u32) {
+		self.core().pick_share_wizard_folder(idx);
 	}
 
-	pub fn edit_shell_input(&mut self, value: String) {
-		self.core().edit_shell_input(value);
+	pub fn toggle_share_wizard_write(&mut self) {
+		self.core().toggle_share_wizard_write();
 	}
 
-	pub fn start_shell(&mut self) {
-		self.core().start_shell();
+	pub fn toggle_share_wizard_preview_only(&mut self) {
+		self.core().toggle_share_wizard_preview_only();
 	}
 
-	pub fn send_shell_input(&mut self) {
-		self.core().send_shell_input();
+	pub fn edit_share_wizard_deny_patterns(&mut self, value: String) {
+		self.core().edit_share_wizard_deny_patterns(value);
+	}
+
+	pub fn suggest_share_wizard_patterns(&mut self) {
+		self.core().suggest_share_wizard_patterns();
+	}
+
+	pub fn show_pairing_string(&mut self) {
+		self.core().show_pairing_string();
+	}
+
+	pub fn edit_join_pairing(&mut self, value: String) {
+		self.core().edit_join_pairing(value);
+	}
+
+	pub fn join_pairing(&mut self) {
+		self.core().join_pairing();
+	}
+
+	pub fn toggle_share_wizard_peer(&mut self, idx: u32) {
+		self.core().toggle_share_wizard_peer(idx);
+	}
+
+	pub fn run_share_wizard(&mut self) {
+		self.core().run_share_wizard();
+	}
+}
+
+impl PeersController {
+	pub fn peer_back(&mut self) {
+		self.core().peer_back();
 	}
 
 	pub fn edit_update_version(&mut self, value: String) {
@@ -60,10 +120,6 @@ impl PeersController {
 	pub fn start_peer_update(&mut self) {
 		self.core().start_peer_update();
 	}
-
-	pub fn poll_peer_update(&mut self) {
-		self.core().poll_peer_update();
-	}
 }
 
 #[async_trait]
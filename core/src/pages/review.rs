@@ -0,0 +1,222 @@
+use super::{UiContext, UiControllerCore, UiViewState};
+use crate::review::PendingReview;
+use async_trait::async_trait;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use wgui::wui::runtime::{Component, Ctx, MountResult, RouteContext};
+
+pub(in super::super) enum ReviewMsg {
+	Toggled(i64),
+	/// Selects all of `ids`, or clears the selection when all of them are
+	/// already selected.
+	AllToggled(Vec<i64>),
+	Decided {
+		ids: Vec<i64>,
+		status: String,
+	},
+	/// The reason a deletion proposal is rejected with.
+	ReasonEdited(String),
+	/// A deletion proposal from a peer was approved or rejected.
+	ProposalDecided(String),
+	Failed(String),
+}
+
+/// Review queue state of one client: which entries are ticked for a bulk
+/// decision, and the reason typed for rejecting a deletion proposal.
+#[derive(Clone, Default)]
+pub(in super::super) struct ReviewSession {
+	selected: BTreeSet<i64>,
+	pub(in super::super) reject_reason: String,
+	pub(in super::super) status: String,
+}
+
+impl ReviewSession {
+	pub(in super::super) fn is_selected(&self, id: i64) -> bool {
+		self.selected.contains(&id)
+	}
+
+	/// Ticked entries that are still pending. Entries decided in another
+	/// session drop out instead of being decided twice.
+	pub(in super::super) fn selection(&self, pending: &[PendingReview]) -> Vec<i64> {
+		pending
+			.iter()
+			.map(|review| review.id)
+			.filter(|id| self.selected.contains(id))
+			.collect()
+	}
+
+	pub(in super::super) fn update(&mut self, msg: ReviewMsg) {
+		match msg {
+			ReviewMsg::Toggled(id) => {
+				if !self.selected.remove(&id) {
+					self.selected.insert(id);
+				}
+				self.status.clear();
+			}
+			ReviewMsg::AllToggled(ids) => {
+				if ids.iter().all(|id| self.selected.contains(id)) {
+					self.selected.clear();
+				} else {
+					self.selected.extend(ids);
+				}
+				self.status.clear();
+			}
+			ReviewMsg::Decided { ids, status } => {
+				for id in &ids {
+					self.selected.remove(id);
+				}
+				self.status = status;
+			}
+			ReviewMsg::ReasonEdited(reason) => self.reject_reason = reason,
+			ReviewMsg::ProposalDecided(status) => {
+				self.reject_reason.clear();
+				self.status = status;
+			}
+			ReviewMsg::Failed(status) => self.status = status,
+		}
+	}
+}
+
+pub(in super::super) struct ReviewController {
+	ctx: Arc<Ctx<UiContext, ()>>,
+}
+
+impl ReviewController {
+	fn core(&self) -> UiControllerCore<'_> {
+		UiControllerCore::new(&self.ctx)
+	}
+}
+
+#[wgui::wgui_controller]
+impl ReviewController {
+	pub fn state(&self) -> UiViewState {
+		self.core().review_state()
+	}
+
+	pub fn title(&self) -> String {
+		String::from("Review - PuppyNet UI")
+	}
+
+	pub fn logout(&mut self) {
+		self.core().logout();
+	}
+
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
+	pub fn toggle_review(&mut self, idx: u32) {
+		self.core().toggle_review(idx);
+	}
+
+	pub fn toggle_all_reviews(&mut self) {
+		self.core().toggle_all_reviews();
+	}
+
+	pub fn accept_review(&mut self, idx: u32) {
+		self.core().accept_review(idx);
+	}
+
+	pub fn reject_review(&mut self, idx: u32) {
+		self.core().reject_review(idx);
+	}
+
+	pub fn accept_selected_reviews(&mut self) {
+		self.core().accept_selected_reviews();
+	}
+
+	pub fn reject_selected_reviews(&mut self) {
+		self.core().reject_selected_reviews();
+	}
+
+	pub fn approve_delete_request(&mut self, idx: u32) {
+		self.core().approve_delete_request(idx);
+	}
+
+	pub fn reject_delete_request(&mut self, idx: u32) {
+		self.core().reject_delete_request(idx);
+	}
+
+	pub fn edit_delete_reject_reason(&mut self, value: String) {
+		self.core().edit_delete_reject_reason(value);
+	}
+}
+
+#[async_trait]
+impl Component for ReviewController {
+	type Context = UiContext;
+	type Db = ();
+	type Model = UiViewState;
+
+	async fn mount(
+		ctx: Arc<Ctx<Self::Context, Self::Db>>,
+		_route: RouteContext,
+	) -> MountResult<Self> {
+		if let Some(result) = super::redirect_unauthenticated(&ctx) {
+			return result;
+		}
+		MountResult::Ready(Self { ctx })
+	}
+
+	fn render(&self, _ctx: &Ctx<Self::Context, Self::Db>) -> Self::Model {
+		self.state()
+	}
+
+	fn unmount(self, _ctx: Arc<Ctx<Self::Context, Self::Db>>) {}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::Utc;
+
+	fn pending(id: i64) -> PendingReview {
+		PendingReview {
+			id,
+			peer: String::from("peer"),
+			path: format!("/srv/drop/{id}"),
+			hash: None,
+			size: 1,
+			received_at: Utc::now(),
+		}
+	}
+
+	#[test]
+	fn toggling_all_selects_then_clears() {
+		let mut session = ReviewSession::default();
+		session.update(ReviewMsg::Toggled(2));
+		session.update(ReviewMsg::AllToggled(vec![1, 2, 3]));
+		assert!((1..=3).all(|id| session.is_selected(id)));
+		session.update(ReviewMsg::AllToggled(vec![1, 2, 3]));
+		assert!(!(1..=3).any(|id| session.is_selected(id)));
+	}
+
+	#[test]
+	fn selection_skips_entries_decided_elsewhere() {
+		let mut session = ReviewSession::default();
+		session.update(ReviewMsg::AllToggled(vec![1, 2, 3]));
+		assert_eq!(session.selection(&[pending(1), pending(3)]), vec![1, 3]);
+		session.update(ReviewMsg::Decided {
+			ids: vec![1],
+			status: String::from("Accepted 1 file"),
+		});
+		assert_eq!(session.selection(&[pending(2), pending(3)]), vec![2, 3]);
+		assert_eq!(session.status, "Accepted 1 file");
+	}
+
+	#[test]
+	fn deciding_a_proposal_clears_the_reason() {
+		let mut session = ReviewSession::default();
+		session.update(ReviewMsg::ReasonEdited(String::from("still need this")));
+		session.update(ReviewMsg::Failed(String::from("Failed to decide deletion")));
+		assert_eq!(session.reject_reason, "still need this");
+		session.update(ReviewMsg::ProposalDecided(String::from(
+			"Declined deleting a.jpg",
+		)));
+		assert!(session.reject_reason.is_empty());
+	}
+}
@@ -1,8 +1,264 @@
+use super::super::{UiSearchRawRow, UiSearchRow, search_page_size, search_row_to_ui, search_sort};
 use super::{UiAction, UiContext, UiControllerCore, UiViewState};
+use crate::LiveSearchPeerEvent;
+use crate::natural_sort::natural_cmp;
+use crate::p2p::{SearchEvent, SearchSort};
 use async_trait::async_trait;
-use std::sync::Arc;
+use std::sync::{Arc, mpsc};
 use wgui::wui::runtime::{Component, Ctx, MountResult, RouteContext};
 
+/// Events of one live search, shared with the thread that watches it.
+pub(in super::super) type SearchStream = Arc<std::sync::Mutex<mpsc::Receiver<LiveSearchPeerEvent>>>;
+
+/// Everything that changes a client's search. Stream messages name the
+/// stream they came from, so results of a search that was replaced or
+/// stopped meanwhile are dropped in [`SearchSession::update`].
+pub(in super::super) enum SearchMsg {
+	QueryEdited(String),
+	MimeToggled(String),
+	MimesCleared,
+	TargetSelected(String),
+	SortSelected(String),
+	PageSizeSelected(String),
+	LoadMore,
+	Started {
+		target: String,
+		page_size: usize,
+		total_peers: usize,
+		stream: SearchStream,
+	},
+	/// The search could not start; `status` says why.
+	Stopped {
+		status: String,
+	},
+	Event {
+		stream: SearchStream,
+		peer: String,
+		event: SearchEvent,
+		/// Known durations of the rows of a `Rows` event, in order.
+		durations: Vec<Option<u64>>,
+	},
+	/// Every device finished or the stream was dropped.
+	StreamClosed {
+		stream: SearchStream,
+	},
+	StreamFailed {
+		stream: SearchStream,
+		error: String,
+	},
+}
+
+impl SearchMsg {
+	fn stream(&self) -> Option<&SearchStream> {
+		match self {
+			Self::Event { stream, .. }
+			| Self::StreamClosed { stream }
+			| Self::StreamFailed { stream, .. } => Some(stream),
+			_ => None,
+		}
+	}
+}
+
+/// Search page state of one client.
+#[derive(Clone, Default)]
+pub(in super::super) struct SearchSession {
+	pub(in super::super) name_query: String,
+	pub(in super::super) target: String,
+	pub(in super::super) sort: String,
+	pub(in super::super) page_size: String,
+	pub(in super::super) selected_mimes: Vec<String>,
+	/// All rows received so far, unsorted.
+	pub(in super::super) raw_rows: Vec<UiSearchRawRow>,
+	/// The sorted rows shown, `visible_count` at most.
+	pub(in super::super) results: Vec<UiSearchRow>,
+	pub(in super::super) status: String,
+	pub(in super::super) in_progress: bool,
+	visible_count: usize,
+	total_peers: usize,
+	done_peers: usize,
+	truncated: bool,
+	stream: Option<SearchStream>,
+}
+
+impl SearchSession {
+	fn is_current(&self, stream: &SearchStream) -> bool {
+		self.stream
+			.as_ref()
+			.is_some_and(|current| Arc::ptr_eq(current, stream))
+	}
+
+	fn rebuild(&mut self) {
+		let page_size = search_page_size(&self.page_size);
+		let visible_count = self.visible_count.max(page_size);
+		let mut rows = self.raw_rows.clone();
+		match search_sort(&self.sort) {
+			SearchSort::Name => rows.sort_by(|left, right| natural_cmp(&left.name, &right.name)),
+			SearchSort::Size => rows.sort_by_key(|row| std::cmp::Reverse(row.size)),
+			SearchSort::Latest => rows.sort_by(|left, right| {
+				right
+					.modified_at
+					.cmp(&left.modified_at)
+					.then_with(|| left.name.cmp(&right.name))
+			}),
+		}
+		self.visible_count = visible_count;
+		self.results = rows
+			.into_iter()
+			.take(visible_count)
+			.map(search_row_to_ui)
+			.collect();
+	}
+
+	fn reset_visible_count(&mut self) {
+		self.visible_count = search_page_size(&self.page_size);
+	}
+
+	fn stop(&mut self) {
+		self.in_progress = false;
+		self.stream = None;
+	}
+
+	fn found_status(&self) -> String {
+		if self.truncated {
+			format!(
+				"Found {} result(s); result set was truncated",
+				self.raw_rows.len()
+			)
+		} else {
+			format!("Found {} result(s)", self.raw_rows.len())
+		}
+	}
+
+	fn apply_event(&mut self, peer: String, event: SearchEvent, durations: Vec<Option<u64>>) {
+		match event {
+			SearchEvent::Rows { rows } => {
+				self.raw_rows
+					.extend(rows.into_iter().zip(durations).map(|(row, duration_ms)| {
+						UiSearchRawRow {
+							name: row.name,
+							path: row.path,
+							size: row.size,
+							mime_type: row.mime_type,
+							modified_at: row.modified_at,
+							peer_id: peer.clone(),
+							duration_ms,
+						}
+					}));
+				self.rebuild();
+				self.status = format!("Searching... {} result(s)", self.raw_rows.len());
+			}
+			SearchEvent::Progress { visited, matched } => {
+				self.status = format!("Searching... visited {visited}, matched {matched}");
+			}
+			SearchEvent::Finished { total, truncated } => {
+				self.done_peers = self.done_peers.saturating_add(1);
+				self.truncated |= truncated;
+				self.rebuild();
+				if self.done_peers >= self.total_peers {
+					self.stop();
+					self.status = self.found_status();
+				} else {
+					self.status = format!(
+						"Device finished with {total} result(s); {}/{} done",
+						self.done_peers, self.total_peers
+					);
+				}
+			}
+			SearchEvent::Failed { error } => {
+				self.done_peers = self.done_peers.saturating_add(1);
+				self.rebuild();
+				if self.done_peers >= self.total_peers {
+					self.stop();
+				}
+				self.status = format!("Search failed on a device: {error}");
+			}
+		}
+	}
+
+	/// Applies `msg`. Returns false when it came from a stream that is no
+	/// longer this session's search, so its watcher can stop.
+	pub(in super::super) fn update(&mut self, msg: SearchMsg) -> bool {
+		if let Some(stream) = msg.stream()
+			&& !self.is_current(stream)
+		{
+			return false;
+		}
+		match msg {
+			SearchMsg::QueryEdited(query) => self.name_query = query,
+			SearchMsg::MimeToggled(mime) => {
+				match self.selected_mimes.iter().position(|item| item == &mime) {
+					Some(pos) => {
+						self.selected_mimes.remove(pos);
+					}
+					None => self.selected_mimes.push(mime),
+				}
+			}
+			SearchMsg::MimesCleared => self.selected_mimes.clear(),
+			SearchMsg::TargetSelected(target) => {
+				self.target = target;
+				self.reset_visible_count();
+				self.rebuild();
+			}
+			SearchMsg::SortSelected(sort) => {
+				self.sort = sort;
+				self.reset_visible_count();
+				self.rebuild();
+			}
+			SearchMsg::PageSizeSelected(page_size) => {
+				self.page_size = page_size;
+				self.reset_visible_count();
+				self.rebuild();
+			}
+			SearchMsg::LoadMore => {
+				let page_size = search_page_size(&self.page_size);
+				self.visible_count = self.visible_count.max(page_size).saturating_add(page_size);
+				self.rebuild();
+			}
+			SearchMsg::Started {
+				target,
+				page_size,
+				total_peers,
+				stream,
+			} => {
+				self.target = target;
+				self.visible_count = page_size;
+				self.raw_rows.clear();
+				self.results.clear();
+				self.status = String::from("Search started");
+				self.in_progress = true;
+				self.total_peers = total_peers;
+				self.done_peers = 0;
+				self.truncated = false;
+				self.stream = Some(stream);
+			}
+			SearchMsg::Stopped { status } => {
+				self.raw_rows.clear();
+				self.results.clear();
+				self.status = status;
+				self.stop();
+			}
+			SearchMsg::Event {
+				stream: _,
+				peer,
+				event,
+				durations,
+			} => self.apply_event(peer, event, durations),
+			SearchMsg::StreamClosed { stream: _ } => {
+				self.stop();
+				self.rebuild();
+				if self.status == "Search started" {
+					self.status = format!("Found {} result(s)", self.raw_rows.len());
+				}
+			}
+			SearchMsg::StreamFailed { stream: _, error } => {
+				self.status = format!("Search stream lock failed: {error}");
+				self.stop();
+			}
+		}
+		true
+	}
+}
+
 pub(in super::super) struct SearchController {
 	ctx: Arc<Ctx<UiContext, ()>>,
 }
@@ -27,6 +283,14 @@ impl SearchController {
 		self.core().logout();
 	}
 
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
 	pub fn edit_search_name_query(&mut self, value: String) {
 		self.core().edit_search_name_query(value);
 	}
@@ -67,6 +331,22 @@ impl SearchController {
 		self.core().search_preview(idx);
 	}
 
+	pub fn search_compare(&mut self, idx: u32) {
+		self.core().search_compare(idx);
+	}
+
+	pub fn cancel_search_compare(&mut self) {
+		self.core().cancel_search_compare();
+	}
+
+	pub fn close_compare_modal(&mut self) {
+		self.core().close_compare_modal();
+	}
+
+	pub fn search_key(&mut self, payload: wgui::serde_json::Value) {
+		self.core().search_key(payload);
+	}
+
 	pub fn close_file_preview_modal(&mut self) {
 		self.core().close_file_preview_modal();
 	}
@@ -82,6 +362,30 @@ impl SearchController {
 	pub fn load_file_preview(&mut self) {
 		self.core().load_file_preview();
 	}
+
+	pub fn next_file_preview_page(&mut self) {
+		self.core().next_file_preview_page();
+	}
+
+	pub fn previous_file_preview_page(&mut self) {
+		self.core().previous_file_preview_page();
+	}
+
+	pub fn download_preview_file(&mut self) {
+		self.core().download_preview_file();
+	}
+
+	pub fn download_preview_file_again(&mut self) {
+		self.core().download_preview_file_again();
+	}
+
+	pub fn open_previous_download(&mut self) {
+		self.core().open_previous_download();
+	}
+
+	pub fn preview_large_image(&mut self) {
+		self.core().preview_large_image();
+	}
 }
 
 #[async_trait]
@@ -110,3 +414,135 @@ impl Component for SearchController {
 
 	fn unmount(self, _ctx: Arc<Ctx<Self::Context, Self::Db>>) {}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::p2p::LiveSearchRow;
+
+	fn stream() -> SearchStream {
+		Arc::new(std::sync::Mutex::new(mpsc::channel().1))
+	}
+
+	fn row(name: &str, size: u64, modified_at: &str) -> LiveSearchRow {
+		LiveSearchRow {
+			name: name.to_string(),
+			path: format!("/srv/{name}"),
+			size,
+			mime_type: None,
+			modified_at: Some(modified_at.to_string()),
+		}
+	}
+
+	fn rows_event(stream: &SearchStream, rows: Vec<LiveSearchRow>) -> SearchMsg {
+		SearchMsg::Event {
+			stream: Arc::clone(stream),
+			peer: String::from("peer-a"),
+			durations: vec![None; rows.len()],
+			event: SearchEvent::Rows { rows },
+		}
+	}
+
+	fn start(session: &mut SearchSession, total_peers: usize) -> SearchStream {
+		let stream = stream();
+		assert!(session.update(SearchMsg::Started {
+			target: String::from("__all__"),
+			page_size: 2,
+			total_peers,
+			stream: Arc::clone(&stream),
+		}));
+		stream
+	}
+
+	#[test]
+	fn events_of_a_replaced_search_are_dropped() {
+		let mut session = SearchSession::default();
+		let old = start(&mut session, 1);
+		let current = start(&mut session, 1);
+
+		assert!(!session.update(rows_event(&old, vec![row("old.txt", 1, "2025-01-01")])));
+		assert!(!session.update(SearchMsg::StreamClosed { stream: old }));
+		assert!(session.in_progress);
+		assert!(session.raw_rows.is_empty());
+
+		assert!(session.update(rows_event(&current, vec![row("new.txt", 1, "2025-01-01")])));
+		assert_eq!(session.results.len(), 1);
+		assert_eq!(session.results[0].name, "new.txt");
+	}
+
+	#[test]
+	fn search_finishes_once_every_device_reports() {
+		let mut session = SearchSession::default();
+		let stream = start(&mut session, 2);
+		session.update(rows_event(&stream, vec![row("a", 1, "2025-01-01")]));
+		session.update(SearchMsg::Event {
+			stream: Arc::clone(&stream),
+			peer: String::from("peer-a"),
+			event: SearchEvent::Finished {
+				total: 1,
+				truncated: true,
+			},
+			durations: Vec::new(),
+		});
+		assert!(session.in_progress);
+		assert_eq!(session.status, "Device finished with 1 result(s); 1/2 done");
+
+		session.update(SearchMsg::Event {
+			stream: Arc::clone(&stream),
+			peer: String::from("peer-b"),
+			event: SearchEvent::Failed {
+				error: String::from("offline"),
+			},
+			durations: Vec::new(),
+		});
+		assert!(!session.in_progress);
+		assert_eq!(session.status, "Search failed on a device: offline");
+		// The search is over, so a late event from it no longer applies.
+		assert!(!session.update(rows_event(&stream, vec![row("late", 1, "2025-01-01")])));
+	}
+
+	#[test]
+	fn sorting_and_paging_rebuild_the_visible_rows() {
+		let mut session = SearchSession {
+			page_size: String::from("2"),
+			..Default::default()
+		};
+		let stream = start(&mut session, 1);
+		session.update(rows_event(
+			&stream,
+			vec![
+				row("b", 30, "2025-01-02"),
+				row("a", 10, "2025-01-03"),
+				row("c", 20, "2025-01-01"),
+			],
+		));
+		let names = |session: &SearchSession| {
+			session
+				.results
+				.iter()
+				.map(|row| row.name.clone())
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(names(&session), ["a", "b"]);
+
+		session.update(SearchMsg::SortSelected(String::from("size")));
+		assert_eq!(names(&session), ["b", "c"]);
+		session.update(SearchMsg::LoadMore);
+		assert_eq!(names(&session), ["b", "c", "a"]);
+		session.update(SearchMsg::SortSelected(String::from("name")));
+		assert_eq!(names(&session), ["a", "b"]);
+	}
+
+	#[test]
+	fn a_search_that_cannot_start_clears_old_results() {
+		let mut session = SearchSession::default();
+		let stream = start(&mut session, 1);
+		session.update(rows_event(&stream, vec![row("a", 1, "2025-01-01")]));
+		session.update(SearchMsg::Stopped {
+			status: String::from("No target devices available"),
+		});
+		assert!(session.results.is_empty());
+		assert!(!session.in_progress);
+		assert!(!session.update(SearchMsg::StreamClosed { stream }));
+	}
+}
@@ -1,10 +1,116 @@
 use super::super::redirect_response;
 use super::{UiContext, UiControllerCore, UiViewState};
+use crate::natural_sort::natural_cmp;
+use crate::protocol_stats::ProtocolRate;
 use async_trait::async_trait;
+use std::cmp::Ordering;
 use std::sync::Arc;
 use wgui::wui::runtime::{Component, Ctx, MountResult, RouteContext};
 use wgui::{FormData, HttpResponse};
 
+/// Column the protocol usage table is sorted by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(in super::super) enum ProtocolColumn {
+	Peer,
+	Request,
+	#[default]
+	Rate,
+	Errors,
+	Bytes,
+}
+
+impl ProtocolColumn {
+	fn compare(self, left: &ProtocolRate, right: &ProtocolRate) -> Ordering {
+		match self {
+			Self::Peer => natural_cmp(&left.peer, &right.peer),
+			Self::Request => natural_cmp(&left.request, &right.request),
+			Self::Rate => left.last_minute.requests.cmp(&right.last_minute.requests),
+			Self::Errors => {
+				let failed = |rate: &ProtocolRate| {
+					rate.last_minute.errors
+						+ rate.last_minute.denials
+						+ rate.last_minute.rate_limited
+				};
+				failed(left).cmp(&failed(right))
+			}
+			Self::Bytes => {
+				let bytes =
+					|rate: &ProtocolRate| rate.last_minute.bytes_in + rate.last_minute.bytes_out;
+				bytes(left).cmp(&bytes(right))
+			}
+		}
+	}
+
+	/// Numbers read best largest first, names from A.
+	fn descends_first(self) -> bool {
+		!matches!(self, Self::Peer | Self::Request)
+	}
+}
+
+pub(in super::super) enum ProtocolMsg {
+	/// Sorts by the column, or flips the order when it already is.
+	SortedBy(ProtocolColumn),
+	AlertEdited(String),
+	Saved(String),
+}
+
+/// Protocol usage section of one client: how the table is sorted and the
+/// alert rate being edited.
+#[derive(Clone)]
+pub(in super::super) struct ProtocolSession {
+	column: ProtocolColumn,
+	descending: bool,
+	pub(in super::super) alert_draft: Option<String>,
+	pub(in super::super) status: String,
+}
+
+impl Default for ProtocolSession {
+	fn default() -> Self {
+		Self {
+			column: ProtocolColumn::default(),
+			descending: ProtocolColumn::default().descends_first(),
+			alert_draft: None,
+			status: String::new(),
+		}
+	}
+}
+
+impl ProtocolSession {
+	/// `rates` in the order the table shows them.
+	pub(in super::super) fn sorted(&self, mut rates: Vec<ProtocolRate>) -> Vec<ProtocolRate> {
+		rates.sort_by(|left, right| {
+			let order = self.column.compare(left, right);
+			if self.descending {
+				order.reverse()
+			} else {
+				order
+			}
+		});
+		rates
+	}
+
+	pub(in super::super) fn update(&mut self, msg: ProtocolMsg) {
+		match msg {
+			ProtocolMsg::SortedBy(column) => {
+				if self.column == column {
+					self.descending = !self.descending;
+				} else {
+					self.column = column;
+					self.descending = column.descends_first();
+				}
+			}
+			ProtocolMsg::AlertEdited(value) => {
+				self.alert_draft = Some(value);
+				self.status.clear();
+			}
+			ProtocolMsg::Saved(status) => {
+				self.alert_draft = None;
+				self.status = status;
+			}
+		}
+	}
+}
+
 pub(in super::super) struct SettingsController {
 	ctx: Arc<Ctx<UiContext, ()>>,
 }
@@ -29,6 +135,14 @@ impl SettingsController {
 		self.core().logout();
 	}
 
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
 	pub fn edit_current_password(&mut self, value: String) {
 		self.core().edit_current_password(value);
 	}
@@ -45,6 +159,222 @@ impl SettingsController {
 		self.core().change_password();
 	}
 
+	pub fn select_theme(&mut self, value: String) {
+		self.core().select_theme(value);
+	}
+
+	pub fn select_font_scale(&mut self, value: String) {
+		self.core().select_font_scale(value);
+	}
+
+	pub fn select_default_page_size(&mut self, value: String) {
+		self.core().select_default_page_size(value);
+	}
+
+	pub fn select_refresh_interval(&mut self, value: String) {
+		self.core().select_refresh_interval(value);
+	}
+
+	pub fn suspend_remote_access(&mut self) {
+		self.core().suspend_remote_access();
+	}
+
+	pub fn resume_remote_access(&mut self) {
+		self.core().resume_remote_access();
+	}
+
+	pub fn adopt_previous_identity(&mut self) {
+		self.core().adopt_previous_identity();
+	}
+
+	pub fn start_fresh_identity(&mut self) {
+		self.core().start_fresh_identity();
+	}
+
+	pub fn enable_nat_mapping(&mut self) {
+		self.core().enable_nat_mapping();
+	}
+
+	pub fn disable_nat_mapping(&mut self) {
+		self.core().disable_nat_mapping();
+	}
+
+	pub fn test_reachability(&mut self) {
+		self.core().test_reachability();
+	}
+
+	pub fn run_diagnostics(&mut self) {
+		self.core().run_diagnostics();
+	}
+
+	pub fn sort_protocol_by_peer(&mut self) {
+		self.core().sort_protocol(ProtocolColumn::Peer);
+	}
+
+	pub fn sort_protocol_by_request(&mut self) {
+		self.core().sort_protocol(ProtocolColumn::Request);
+	}
+
+	pub fn sort_protocol_by_rate(&mut self) {
+		self.core().sort_protocol(ProtocolColumn::Rate);
+	}
+
+	pub fn sort_protocol_by_errors(&mut self) {
+		self.core().sort_protocol(ProtocolColumn::Errors);
+	}
+
+	pub fn sort_protocol_by_bytes(&mut self) {
+		self.core().sort_protocol(ProtocolColumn::Bytes);
+	}
+
+	pub fn edit_protocol_alert(&mut self, value: String) {
+		self.core().edit_protocol_alert(value);
+	}
+
+	pub fn save_protocol_alert(&mut self) {
+		self.core().save_protocol_alert();
+	}
+
+	pub fn toggle_protocol_limiter(&mut self) {
+		self.core().toggle_protocol_limiter();
+	}
+
+	pub fn edit_activity_ranges(&mut self, value: String) {
+		self.core().edit_activity_ranges(value);
+	}
+
+	pub fn edit_activity_utc_offset(&mut self, value: String) {
+		self.core().edit_activity_utc_offset(value);
+	}
+
+	pub fn toggle_activity_scans(&mut self) {
+		self.core().toggle_activity_scans();
+	}
+
+	pub fn toggle_activity_updates(&mut self) {
+		self.core().toggle_activity_updates();
+	}
+
+	pub fn toggle_activity_syncs(&mut self) {
+		self.core().toggle_activity_syncs();
+	}
+
+	pub fn toggle_activity_hard_stop(&mut self) {
+		self.core().toggle_activity_hard_stop();
+	}
+
+	pub fn save_activity_window(&mut self) {
+		self.core().save_activity_window();
+	}
+
+	pub fn edit_disk_alert_threshold(&mut self, value: String) {
+		self.core().edit_disk_alert_threshold(value);
+	}
+
+	pub fn save_disk_alert_threshold(&mut self) {
+		self.core().save_disk_alert_threshold();
+	}
+
+	pub fn edit_idle_timeout(&mut self, value: String) {
+		self.core().edit_idle_timeout(value);
+	}
+
+	pub fn edit_ping_interval(&mut self, value: String) {
+		self.core().edit_ping_interval(value);
+	}
+
+	pub fn save_connection_policy(&mut self) {
+		self.core().save_connection_policy();
+	}
+
+	pub fn toggle_mirror_remote_scans(&mut self) {
+		self.core().toggle_mirror_remote_scans();
+	}
+
+	pub fn select_power_mode(&mut self, value: String) {
+		self.core().select_power_mode(value);
+	}
+
+	pub fn edit_power_percent(&mut self, value: String) {
+		self.core().edit_power_percent(value);
+	}
+
+	pub fn save_power_policy(&mut self) {
+		self.core().save_power_policy();
+	}
+
+	pub fn edit_config_snapshot_label(&mut self, value: String) {
+		self.core().edit_config_snapshot_label(value);
+	}
+
+	pub fn toggle_config_snapshot_users(&mut self) {
+		self.core().toggle_config_snapshot_users();
+	}
+
+	pub fn snapshot_config(&mut self) {
+		self.core().snapshot_config();
+	}
+
+	pub fn roll_back_to_snapshot(&mut self, idx: u32) {
+		self.core().roll_back_to_snapshot(idx);
+	}
+
+	pub fn force_roll_back_to_snapshot(&mut self, idx: u32) {
+		self.core().force_roll_back_to_snapshot(idx);
+	}
+
+	pub fn toggle_backup_schedule(&mut self) {
+		self.core().toggle_backup_schedule();
+	}
+
+	pub fn edit_backup_hour(&mut self, value: String) {
+		self.core().edit_backup_hour(value);
+	}
+
+	pub fn edit_backup_keep(&mut self, value: String) {
+		self.core().edit_backup_keep(value);
+	}
+
+	pub fn edit_backup_dir(&mut self, value: String) {
+		self.core().edit_backup_dir(value);
+	}
+
+	pub fn save_backup_settings(&mut self) {
+		self.core().save_backup_settings();
+	}
+
+	pub fn run_backup_now(&mut self) {
+		self.core().run_backup_now();
+	}
+
+	pub fn edit_download_dir(&mut self, value: String) {
+		self.core().edit_download_dir(value);
+	}
+
+	pub fn save_download_dir(&mut self) {
+		self.core().save_download_dir();
+	}
+
+	pub fn edit_proxy_url(&mut self, value: String) {
+		self.core().edit_proxy_url(value);
+	}
+
+	pub fn edit_proxy_username(&mut self, value: String) {
+		self.core().edit_proxy_username(value);
+	}
+
+	pub fn edit_proxy_password(&mut self, value: String) {
+		self.core().edit_proxy_password(value);
+	}
+
+	pub fn save_http_proxy(&mut self) {
+		self.core().save_http_proxy();
+	}
+
+	pub fn test_http_proxy(&mut self) {
+		self.core().test_http_proxy();
+	}
+
 	#[wgui_post("/settings/password")]
 	pub fn change_password_post(&mut self, form: FormData) -> HttpResponse {
 		let current_password = form.get("current_password").unwrap_or_default().to_string();
@@ -83,3 +413,57 @@ impl Component for SettingsController {
 
 	fn unmount(self, _ctx: Arc<Ctx<Self::Context, Self::Db>>) {}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::protocol_stats::ProtocolCounts;
+
+	fn rate(peer: &str, request: &str, requests: u64) -> ProtocolRate {
+		ProtocolRate {
+			peer: peer.to_string(),
+			request: request.to_string(),
+			last_minute: ProtocolCounts {
+				requests,
+				..Default::default()
+			},
+			limited: false,
+		}
+	}
+
+	fn order(session: &ProtocolSession, rates: &[ProtocolRate]) -> Vec<String> {
+		session
+			.sorted(rates.to_vec())
+			.into_iter()
+			.map(|rate| format!("{}/{}", rate.peer, rate.request))
+			.collect()
+	}
+
+	#[test]
+	fn protocol_table_sorts_by_the_clicked_column_and_flips_on_a_second_click() {
+		let rates = [
+			rate("peer2", "ListDir", 5),
+			rate("peer10", "ReadFile", 40),
+			rate("peer1", "StatFile", 12),
+		];
+		let mut session = ProtocolSession::default();
+		assert_eq!(
+			order(&session, &rates),
+			["peer10/ReadFile", "peer1/StatFile", "peer2/ListDir"]
+		);
+
+		session.update(ProtocolMsg::SortedBy(ProtocolColumn::Peer));
+		assert_eq!(
+			order(&session, &rates),
+			["peer1/StatFile", "peer2/ListDir", "peer10/ReadFile"]
+		);
+		session.update(ProtocolMsg::SortedBy(ProtocolColumn::Peer));
+		assert_eq!(
+			order(&session, &rates),
+			["peer10/ReadFile", "peer2/ListDir", "peer1/StatFile"]
+		);
+
+		session.update(ProtocolMsg::SortedBy(ProtocolColumn::Rate));
+		assert_eq!(order(&session, &rates)[0], "peer10/ReadFile");
+	}
+}
@@ -1,8 +1,259 @@
 use super::{UiAction, UiContext, UiControllerCore, UiViewState};
+use crate::format::hex;
+use crate::natural_sort::natural_cmp;
+use crate::{
+	DirectoryGrowth, NodeStorageTree, STORAGE_ROOT, TREEMAP_MAX_RECTS, TreemapRect, lay_out_treemap,
+};
 use async_trait::async_trait;
+use std::cmp::Ordering;
 use std::sync::Arc;
 use wgui::wui::runtime::{Component, Ctx, MountResult, RouteContext};
 
+/// Column the storage growth table is sorted by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(in super::super) enum GrowthColumn {
+	Folder,
+	Added,
+	Removed,
+	#[default]
+	Net,
+}
+
+impl GrowthColumn {
+	fn compare(self, left: &DirectoryGrowth, right: &DirectoryGrowth) -> Ordering {
+		match self {
+			Self::Folder => natural_cmp(&left.path, &right.path),
+			Self::Added => left.bytes_added.cmp(&right.bytes_added),
+			Self::Removed => left.bytes_removed.cmp(&right.bytes_removed),
+			Self::Net => left.net_bytes.cmp(&right.net_bytes),
+		}
+	}
+
+	/// Sizes read best largest first, folders from A.
+	fn descends_first(self) -> bool {
+		self != Self::Folder
+	}
+}
+
+pub(in super::super) enum GrowthMsg {
+	/// Sorts by the column, or flips the order when it already is.
+	SortedBy(GrowthColumn),
+}
+
+/// Storage growth section of one client: how the table is sorted.
+#[derive(Clone)]
+pub(in super::super) struct GrowthSession {
+	column: GrowthColumn,
+	descending: bool,
+}
+
+impl Default for GrowthSession {
+	fn default() -> Self {
+		Self {
+			column: GrowthColumn::default(),
+			descending: GrowthColumn::default().descends_first(),
+		}
+	}
+}
+
+impl GrowthSession {
+	/// `directories` in the order the table shows them.
+	pub(in super::super) fn sorted(
+		&self,
+		mut directories: Vec<DirectoryGrowth>,
+	) -> Vec<DirectoryGrowth> {
+		directories.sort_by(|left, right| {
+			let order = self.column.compare(left, right);
+			if self.descending {
+				order.reverse()
+			} else {
+				order
+			}
+		});
+		directories
+	}
+
+	pub(in super::super) fn update(&mut self, msg: GrowthMsg) {
+		match msg {
+			GrowthMsg::SortedBy(column) => {
+				if self.column == column {
+					self.descending = !self.descending;
+				} else {
+					self.column = column;
+					self.descending = column.descends_first();
+				}
+			}
+		}
+	}
+}
+
+/// Folder levels the treemap can show below the one it starts at.
+pub(in super::super) const TREEMAP_DEPTHS: [u32; 5] = [1, 2, 3, 4, 5];
+/// The area the treemap is laid out in; the page scales it to fit.
+pub(in super::super) const TREEMAP_WIDTH: f64 = 1000.0;
+pub(in super::super) const TREEMAP_HEIGHT: f64 = 600.0;
+
+pub(in super::super) enum TreemapMsg {
+	/// Shows the node with this hex id from its root.
+	NodeSelected(String),
+	DepthSelected(u32),
+	/// Starts the treemap at the entry with this path; empty for the root.
+	ZoomedTo(String),
+	LaidOut(Arc<TreemapLayout>),
+}
+
+/// One rectangle of a laid out treemap with the entry it stands for.
+#[derive(Debug, Clone)]
+pub(in super::super) struct TreemapTile {
+	pub(in super::super) rect: TreemapRect,
+	/// Empty for the rectangle of the entries too small to draw.
+	pub(in super::super) path: String,
+	pub(in super::super) name: String,
+	/// Where clicking it starts the treemap: the entry itself when it has
+	/// children, otherwise its parent unless that is shown already.
+	pub(in super::super) zoom_to: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub(in super::super) struct TreemapLayout {
+	/// Hex id of the node laid out; `None` when there is no storage data.
+	pub(in super::super) node: Option<String>,
+	/// Names and paths from the node's root down to where the treemap starts.
+	pub(in super::super) trail: Vec<(String, String)>,
+	pub(in super::super) tiles: Vec<TreemapTile>,
+	/// Why the treemap is empty or starts elsewhere than asked.
+	pub(in super::super) note: String,
+}
+
+/// Treemap section of one client: which node, from where and how deep.
+#[derive(Clone)]
+pub(in super::super) struct TreemapSession {
+	node: Option<String>,
+	depth: u32,
+	root: String,
+	layout: Arc<TreemapLayout>,
+}
+
+impl Default for TreemapSession {
+	fn default() -> Self {
+		Self {
+			node: None,
+			depth: 3,
+			root: String::new(),
+			layout: Arc::default(),
+		}
+	}
+}
+
+impl TreemapSession {
+	pub(in super::super) fn depth(&self) -> u32 {
+		self.depth
+	}
+
+	pub(in super::super) fn layout(&self) -> &TreemapLayout {
+		&self.layout
+	}
+
+	/// Lays out what the session shows from `trees`. A node or folder no
+	/// longer there falls back to the first node or the node's root.
+	pub(in super::super) fn lay_out(&self, trees: &[NodeStorageTree]) -> TreemapLayout {
+		let Some(node) = self
+			.node
+			.as_ref()
+			.and_then(|id| trees.iter().find(|tree| hex(&tree.node_id) == *id))
+			.or(trees.first())
+		else {
+			return TreemapLayout {
+				note: String::from("No storage data captured yet."),
+				..TreemapLayout::default()
+			};
+		};
+		let tree = &node.tree;
+		let (root, mut note) = match tree.find(&self.root) {
+			Some(idx) => (idx, String::new()),
+			None => (
+				STORAGE_ROOT,
+				format!(
+					"{} is no longer indexed; showing all of {}",
+					self.root, node.node_name
+				),
+			),
+		};
+		let rects = lay_out_treemap(
+			tree,
+			root,
+			TREEMAP_WIDTH,
+			TREEMAP_HEIGHT,
+			self.depth,
+			TREEMAP_MAX_RECTS,
+		);
+		if rects.is_empty() && note.is_empty() {
+			note = String::from("No file sizes indexed here.");
+		}
+		let tiles = rects
+			.into_iter()
+			.map(|rect| match rect.entry {
+				Some(idx) => {
+					let entry = tree.entry(idx);
+					let zoom_to = if entry.children.is_empty() {
+						tree.ancestors(idx)
+							.into_iter()
+							.rev()
+							.nth(1)
+							.filter(|parent| *parent != root)
+							.map(|parent| tree.entry(parent).path.clone())
+					} else {
+						Some(entry.path.clone())
+					};
+					TreemapTile {
+						path: entry.path.clone(),
+						name: entry.name.clone(),
+						zoom_to,
+						rect,
+					}
+				}
+				None => TreemapTile {
+					path: String::new(),
+					name: format!("{} other items", rect.items),
+					zoom_to: None,
+					rect,
+				},
+			})
+			.collect();
+		let trail = tree
+			.ancestors(root)
+			.into_iter()
+			.map(|idx| {
+				let entry = tree.entry(idx);
+				let name = if idx == STORAGE_ROOT {
+					node.node_name.clone()
+				} else {
+					entry.name.clone()
+				};
+				(name, entry.path.clone())
+			})
+			.collect();
+		TreemapLayout {
+			node: Some(hex(&node.node_id)),
+			trail,
+			tiles,
+			note,
+		}
+	}
+
+	pub(in super::super) fn update(&mut self, msg: TreemapMsg) {
+		match msg {
+			TreemapMsg::NodeSelected(node) => {
+				self.node = Some(node);
+				self.root.clear();
+			}
+			TreemapMsg::DepthSelected(depth) => self.depth = depth,
+			TreemapMsg::ZoomedTo(path) => self.root = path,
+			TreemapMsg::LaidOut(layout) => self.layout = layout,
+		}
+	}
+}
+
 pub(in super::super) struct StorageController {
 	ctx: Arc<Ctx<UiContext, ()>>,
 }
@@ -27,9 +278,117 @@ impl StorageController {
 		self.core().logout();
 	}
 
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
 	pub fn refresh_storage(&mut self) {
 		self.core().refresh_storage();
 	}
+
+	pub fn edit_scan_path(&mut self, value: String) {
+		self.core().edit_scan_path(value);
+	}
+
+	pub fn edit_scan_read_limit(&mut self, value: String) {
+		self.core().edit_scan_read_limit(value);
+	}
+
+	pub fn toggle_scan_low_priority(&mut self) {
+		self.core().toggle_scan_low_priority();
+	}
+
+	pub fn pick_scan_folder(&mut self, idx: u32) {
+		self.core().pick_scan_folder(idx);
+	}
+
+	pub fn start_scan(&mut self) {
+		self.core().start_scan();
+	}
+
+	pub fn gc_store(&mut self) {
+		self.core().gc_store();
+	}
+
+	pub fn edit_ingest_destination(&mut self, value: String) {
+		self.core().edit_ingest_destination(value);
+	}
+
+	pub fn edit_ingest_patterns(&mut self, value: String) {
+		self.core().edit_ingest_patterns(value);
+	}
+
+	pub fn toggle_ingest_delete_originals(&mut self) {
+		self.core().toggle_ingest_delete_originals();
+	}
+
+	pub fn toggle_ingest_eject(&mut self) {
+		self.core().toggle_ingest_eject();
+	}
+
+	pub fn ingest_drive(&mut self, idx: u32) {
+		self.core().ingest_drive(idx);
+	}
+
+	pub fn toggle_integrity_sampling(&mut self) {
+		self.core().toggle_integrity_sampling();
+	}
+
+	pub fn edit_integrity_files_per_cycle(&mut self, value: String) {
+		self.core().edit_integrity_files_per_cycle(value);
+	}
+
+	pub fn edit_integrity_read_rate(&mut self, value: String) {
+		self.core().edit_integrity_read_rate(value);
+	}
+
+	pub fn save_integrity_settings(&mut self) {
+		self.core().save_integrity_settings();
+	}
+
+	pub fn open_integrity_replica(&mut self, idx: u32) {
+		self.core().open_integrity_replica(idx);
+	}
+
+	pub fn select_scan_run(&mut self, idx: u32) {
+		self.core().select_scan_run(idx);
+	}
+
+	pub fn sort_growth_by_folder(&mut self) {
+		self.core().sort_growth(GrowthColumn::Folder);
+	}
+
+	pub fn sort_growth_by_added(&mut self) {
+		self.core().sort_growth(GrowthColumn::Added);
+	}
+
+	pub fn sort_growth_by_removed(&mut self) {
+		self.core().sort_growth(GrowthColumn::Removed);
+	}
+
+	pub fn sort_growth_by_net(&mut self) {
+		self.core().sort_growth(GrowthColumn::Net);
+	}
+
+	pub fn select_treemap_node(&mut self, value: String) {
+		self.core().update_treemap(TreemapMsg::NodeSelected(value));
+	}
+
+	pub fn select_treemap_depth(&mut self, value: String) {
+		self.core().select_treemap_depth(value);
+	}
+
+	pub fn zoom_treemap(&mut self, payload: wgui::serde_json::Value) {
+		self.core().zoom_treemap(payload);
+	}
+
+	pub fn zoom_treemap_out(&mut self, idx: u32) {
+		self.core().zoom_treemap_out(idx);
+	}
 }
 
 #[async_trait]
@@ -49,6 +408,7 @@ impl Component for StorageController {
 			.server
 			.handle_action(UiAction::RefreshStorage)
 			.await;
+		UiControllerCore::new(&ctx).lay_out_treemap().await;
 		MountResult::Ready(Self { ctx })
 	}
 
@@ -58,3 +418,106 @@ impl Component for StorageController {
 
 	fn unmount(self, _ctx: Arc<Ctx<Self::Context, Self::Db>>) {}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{StorageUsageFile, trees_by_node};
+
+	fn growth(path: &str, bytes_added: u64, bytes_removed: u64) -> DirectoryGrowth {
+		DirectoryGrowth {
+			path: path.to_string(),
+			bytes_added,
+			bytes_removed,
+			net_bytes: bytes_added as i64 - bytes_removed as i64,
+		}
+	}
+
+	fn order(session: &GrowthSession, directories: &[DirectoryGrowth]) -> Vec<String> {
+		session
+			.sorted(directories.to_vec())
+			.into_iter()
+			.map(|growth| growth.path)
+			.collect()
+	}
+
+	#[test]
+	fn growth_table_sorts_by_the_clicked_column_and_flips_on_a_second_click() {
+		let directories = [
+			growth("/data/photos", 500, 100),
+			growth("/data/music10", 50, 0),
+			growth("/data/music2", 0, 300),
+		];
+		let mut session = GrowthSession::default();
+		assert_eq!(
+			order(&session, &directories),
+			["/data/photos", "/data/music10", "/data/music2"]
+		);
+
+		session.update(GrowthMsg::SortedBy(GrowthColumn::Folder));
+		assert_eq!(
+			order(&session, &directories),
+			["/data/music2", "/data/music10", "/data/photos"]
+		);
+
+		session.update(GrowthMsg::SortedBy(GrowthColumn::Removed));
+		assert_eq!(order(&session, &directories)[0], "/data/music2");
+		session.update(GrowthMsg::SortedBy(GrowthColumn::Removed));
+		assert_eq!(order(&session, &directories)[0], "/data/music10");
+	}
+
+	fn file(node: u8, path: &str, size: u64) -> StorageUsageFile {
+		StorageUsageFile {
+			node_id: vec![node],
+			node_name: format!("node{node}"),
+			path: path.to_string(),
+			size,
+			last_changed: None,
+		}
+	}
+
+	#[test]
+	fn treemap_zooms_into_folders_and_falls_back_when_they_are_gone() {
+		let trees = trees_by_node(&[
+			file(1, "/data/photos/a.jpg", 600),
+			file(1, "/data/photos/b.jpg", 200),
+			file(1, "/data/notes.txt", 100),
+			file(2, "/srv/backup.tar", 900),
+		]);
+		let mut session = TreemapSession::default();
+		let layout = session.lay_out(&trees);
+		assert_eq!(layout.node.as_deref(), Some("01"));
+		assert_eq!(layout.trail, [(String::from("node1"), String::new())]);
+		let notes = layout
+			.tiles
+			.iter()
+			.find(|tile| tile.path == "/data/notes.txt")
+			.unwrap();
+		assert_eq!(notes.zoom_to.as_deref(), Some("/data"));
+
+		session.update(TreemapMsg::ZoomedTo(String::from("/data/photos")));
+		let layout = session.lay_out(&trees);
+		assert_eq!(
+			layout
+				.trail
+				.iter()
+				.map(|(name, _)| name.as_str())
+				.collect::<Vec<_>>(),
+			["node1", "data", "photos"]
+		);
+		assert_eq!(layout.tiles.len(), 2);
+		assert!(layout.tiles.iter().all(|tile| tile.zoom_to.is_none()));
+
+		session.update(TreemapMsg::NodeSelected(String::from("02")));
+		let layout = session.lay_out(&trees);
+		assert_eq!(layout.trail[0].0, "node2");
+		assert!(layout.note.is_empty());
+
+		session.update(TreemapMsg::ZoomedTo(String::from("/srv/gone")));
+		let layout = session.lay_out(&trees);
+		assert_eq!(layout.trail.len(), 1);
+		assert!(layout.note.contains("/srv/gone is no longer indexed"));
+
+		assert!(!session.lay_out(&[]).note.is_empty());
+	}
+}
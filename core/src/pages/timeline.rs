@@ -0,0 +1,262 @@
+use super::{UiContext, UiControllerCore, UiViewState};
+use crate::activity_log::{ActivityEvent, ActivityEventKind, ActivityFilter};
+use crate::pagination::{CursorPage, PageCursor};
+use async_trait::async_trait;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use wgui::wui::runtime::{Component, Ctx, MountResult, RouteContext};
+
+pub(in super::super) enum TimelineMsg {
+	KindToggled(ActivityEventKind),
+	/// Shows only `peer`, or every peer again when it is already the one
+	/// shown.
+	PeerToggled(String),
+	TextEdited(String),
+	/// A page of events, following the loaded ones when `append` is set.
+	Loaded {
+		page: CursorPage<ActivityEvent>,
+		append: bool,
+	},
+	Failed(String),
+	/// The page was left; its events are reloaded on the next visit.
+	Left,
+}
+
+/// Timeline state of one client: the filter chips it picked and the events
+/// loaded so far. Changing the filter drops the loaded events so the next
+/// render starts again from the newest.
+#[derive(Clone, Default)]
+pub(in super::super) struct TimelineSession {
+	kinds: BTreeSet<ActivityEventKind>,
+	peer: Option<String>,
+	pub(in super::super) text: String,
+	events: Vec<ActivityEvent>,
+	next_cursor: Option<PageCursor>,
+	loaded: bool,
+	pub(in super::super) status: String,
+}
+
+impl TimelineSession {
+	pub(in super::super) fn filter(&self) -> ActivityFilter {
+		let text = self.text.trim();
+		ActivityFilter {
+			kinds: self.kinds.iter().copied().collect(),
+			peer: self.peer.clone(),
+			since: None,
+			text: (!text.is_empty()).then(|| text.to_string()),
+		}
+	}
+
+	pub(in super::super) fn shows_kind(&self, kind: ActivityEventKind) -> bool {
+		self.kinds.contains(&kind)
+	}
+
+	pub(in super::super) fn shows_peer(&self, peer: &str) -> bool {
+		self.peer.as_deref() == Some(peer)
+	}
+
+	pub(in super::super) fn needs_load(&self) -> bool {
+		!self.loaded
+	}
+
+	pub(in super::super) fn events(&self) -> &[ActivityEvent] {
+		&self.events
+	}
+
+	pub(in super::super) fn next_cursor(&self) -> Option<&PageCursor> {
+		self.next_cursor.as_ref()
+	}
+
+	fn reset(&mut self) {
+		self.events.clear();
+		self.next_cursor = None;
+		self.loaded = false;
+	}
+
+	pub(in super::super) fn update(&mut self, msg: TimelineMsg) {
+		match msg {
+			TimelineMsg::KindToggled(kind) => {
+				if !self.kinds.remove(&kind) {
+					self.kinds.insert(kind);
+				}
+				self.reset();
+			}
+			TimelineMsg::PeerToggled(peer) => {
+				self.peer = if self.peer.as_ref() == Some(&peer) {
+					None
+				} else {
+					Some(peer)
+				};
+				self.reset();
+			}
+			TimelineMsg::TextEdited(text) => {
+				self.text = text;
+				self.reset();
+			}
+			TimelineMsg::Loaded { page, append } => {
+				if !append {
+					self.events.clear();
+				}
+				self.events.extend(page.rows);
+				self.next_cursor = page.next_cursor;
+				self.loaded = true;
+				self.status.clear();
+			}
+			TimelineMsg::Failed(status) => {
+				self.loaded = true;
+				self.status = status;
+			}
+			TimelineMsg::Left => self.reset(),
+		}
+	}
+}
+
+pub(in super::super) struct TimelineController {
+	ctx: Arc<Ctx<UiContext, ()>>,
+}
+
+impl TimelineController {
+	fn core(&self) -> UiControllerCore<'_> {
+		UiControllerCore::new(&self.ctx)
+	}
+}
+
+#[wgui::wgui_controller]
+impl TimelineController {
+	pub fn state(&self) -> UiViewState {
+		self.core().timeline_state()
+	}
+
+	pub fn title(&self) -> String {
+		String::from("Timeline - PuppyNet UI")
+	}
+
+	pub fn logout(&mut self) {
+		self.core().logout();
+	}
+
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
+	pub fn toggle_timeline_kind(&mut self, idx: u32) {
+		self.core().toggle_timeline_kind(idx);
+	}
+
+	pub fn toggle_timeline_peer(&mut self, idx: u32) {
+		self.core().toggle_timeline_peer(idx);
+	}
+
+	pub fn edit_timeline_text(&mut self, value: String) {
+		self.core().edit_timeline_text(value);
+	}
+
+	pub fn load_more_activity(&mut self) {
+		self.core().load_more_activity();
+	}
+
+	pub fn refresh_timeline(&mut self) {
+		self.core().refresh_timeline();
+	}
+
+	pub fn open_activity(&mut self, idx: u32) {
+		self.core().open_activity(idx);
+	}
+}
+
+#[async_trait]
+impl Component for TimelineController {
+	type Context = UiContext;
+	type Db = ();
+	type Model = UiViewState;
+
+	async fn mount(
+		ctx: Arc<Ctx<Self::Context, Self::Db>>,
+		_route: RouteContext,
+	) -> MountResult<Self> {
+		if let Some(result) = super::redirect_unauthenticated(&ctx) {
+			return result;
+		}
+		MountResult::Ready(Self { ctx })
+	}
+
+	fn render(&self, _ctx: &Ctx<Self::Context, Self::Db>) -> Self::Model {
+		self.state()
+	}
+
+	fn unmount(self, _ctx: Arc<Ctx<Self::Context, Self::Db>>) {
+		self.core().leave_timeline();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::Utc;
+
+	fn page(ids: &[i64], more: bool) -> CursorPage<ActivityEvent> {
+		let rows = ids
+			.iter()
+			.map(|&id| ActivityEvent {
+				id,
+				kind: ActivityEventKind::Peer,
+				at: Utc::now(),
+				first_at: Utc::now(),
+				peer: None,
+				summary: String::from("Connected"),
+				ref_id: None,
+				count: 1,
+			})
+			.collect();
+		CursorPage {
+			rows,
+			next_cursor: more.then(|| PageCursor::new(None, Vec::new())),
+		}
+	}
+
+	fn ids(session: &TimelineSession) -> Vec<i64> {
+		session.events().iter().map(|event| event.id).collect()
+	}
+
+	#[test]
+	fn more_pages_append_until_the_filter_changes() {
+		let mut session = TimelineSession::default();
+		assert!(session.needs_load());
+		session.update(TimelineMsg::Loaded {
+			page: page(&[3, 2], true),
+			append: false,
+		});
+		session.update(TimelineMsg::Loaded {
+			page: page(&[1], false),
+			append: true,
+		});
+		assert_eq!(ids(&session), [3, 2, 1]);
+		assert!(session.next_cursor().is_none() && !session.needs_load());
+
+		session.update(TimelineMsg::KindToggled(ActivityEventKind::Scan));
+		assert!(session.events().is_empty() && session.needs_load());
+		assert_eq!(session.filter().kinds, [ActivityEventKind::Scan]);
+	}
+
+	#[test]
+	fn picking_the_shown_peer_again_shows_every_peer() {
+		let mut session = TimelineSession::default();
+		session.update(TimelineMsg::PeerToggled(String::from("laptop")));
+		session.update(TimelineMsg::TextEdited(String::from("  photos ")));
+		assert!(session.shows_peer("laptop"));
+		assert_eq!(
+			session.filter(),
+			ActivityFilter {
+				peer: Some(String::from("laptop")),
+				text: Some(String::from("photos")),
+				..Default::default()
+			}
+		);
+		session.update(TimelineMsg::PeerToggled(String::from("laptop")));
+		assert_eq!(session.filter().peer, None);
+	}
+}
@@ -26,6 +26,14 @@ impl UpdatesController {
 	pub fn logout(&mut self) {
 		self.core().logout();
 	}
+
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
 }
 
 #[async_trait]
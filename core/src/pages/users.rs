@@ -29,6 +29,14 @@ impl UsersController {
 		self.core().logout();
 	}
 
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
 	pub fn refresh_users(&mut self) {
 		self.core().refresh_users();
 	}
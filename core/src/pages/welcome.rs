@@ -0,0 +1,124 @@
+use super::{UiAction, UiContext, UiControllerCore, UiViewState};
+use async_trait::async_trait;
+use std::sync::Arc;
+use wgui::wui::runtime::{Component, Ctx, MountResult, RouteContext};
+
+pub(in super::super) struct WelcomeController {
+	ctx: Arc<Ctx<UiContext, ()>>,
+}
+
+impl WelcomeController {
+	fn core(&self) -> UiControllerCore<'_> {
+		UiControllerCore::new(&self.ctx)
+	}
+}
+
+#[wgui::wgui_controller]
+impl WelcomeController {
+	pub fn state(&self) -> UiViewState {
+		self.core().welcome_state()
+	}
+
+	pub fn title(&self) -> String {
+		String::from("Setup - PuppyNet UI")
+	}
+
+	pub fn logout(&mut self) {
+		self.core().logout();
+	}
+
+	pub fn undo_last_change(&mut self) {
+		self.core().undo_last_change();
+	}
+
+	pub fn run_background_anyway(&mut self) {
+		self.core().run_background_anyway();
+	}
+
+	pub fn onboarding_next(&mut self) {
+		self.core().onboarding_next();
+	}
+
+	pub fn onboarding_back(&mut self) {
+		self.core().onboarding_back();
+	}
+
+	pub fn finish_onboarding(&mut self) {
+		self.core().finish_onboarding();
+	}
+
+	pub fn edit_node_name(&mut self, value: String) {
+		self.core().edit_node_name(value);
+	}
+
+	pub fn save_node_name(&mut self) {
+		self.core().save_node_name();
+	}
+
+	pub fn edit_shared_folder_path(&mut self, value: String) {
+		self.core().edit_shared_folder_path(value);
+	}
+
+	pub fn pick_shared_folder(&mut self, idx: u32) {
+		self.core().pick_shared_folder(idx);
+	}
+
+	pub fn select_shared_folder_access(&mut self, value: String) {
+		self.core().select_shared_folder_access(value);
+	}
+
+	pub fn add_shared_folder(&mut self) {
+		self.core().add_shared_folder();
+	}
+
+	pub fn pair_nearby_peer(&mut self, idx: u32) {
+		self.core().pair_nearby_peer(idx);
+	}
+
+	pub fn confirm_pairing(&mut self, idx: u32) {
+		self.core().confirm_pairing(idx);
+	}
+
+	pub fn decline_pairing(&mut self, idx: u32) {
+		self.core().decline_pairing(idx);
+	}
+
+	pub fn show_pairing_string(&mut self) {
+		self.core().show_pairing_string();
+	}
+
+	pub fn edit_join_pairing(&mut self, value: String) {
+		self.core().edit_join_pairing(value);
+	}
+
+	pub fn join_pairing(&mut self) {
+		self.core().join_pairing();
+	}
+}
+
+#[async_trait]
+impl Component for WelcomeController {
+	type Context = UiContext;
+	type Db = ();
+	type Model = UiViewState;
+
+	async fn mount(
+		ctx: Arc<Ctx<Self::Context, Self::Db>>,
+		_route: RouteContext,
+	) -> MountResult<Self> {
+		if let Some(result) = super::redirect_unauthenticated(&ctx) {
+			return result;
+		}
+		ctx.state
+			.server
+			.handle_action(UiAction::RefreshOnboarding)
+			.await;
+		MountResult::Ready(Self { ctx })
+	}
+
+	fn render(&self, _ctx: &Ctx<Self::Context, Self::Db>) -> Self::Model {
+		self.state()
+	}
+
+	fn unmount(self, _ctx: Arc<Ctx<Self::Context, Self::Db>>) {}
+}
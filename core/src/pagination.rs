@@ -0,0 +1,262 @@
+//! Keyset pagination over `file_entries`. Rows are ordered by
+//! `(latest_datetime, hash)`, which is unique, and a cursor names the last
+//! row the client has seen. Rows inserted or removed between requests never
+//! shift the rows still to come, unlike `OFFSET`.
+
+use anyhow::{anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use rusqlite::types::Value;
+
+/// Sort key expression for `file_entries fe`. Rows without a timestamp sort
+/// as the empty string, before every dated row.
+pub(crate) const SORT_KEY_SQL: &str = "COALESCE(fe.latest_datetime, '')";
+
+/// Position just after one row in `(latest_datetime, hash)` order, or in
+/// `(name, hash)` order for searches sorted by name. Clients treat the
+/// encoded form as opaque.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageCursor {
+	/// The row's timestamp, or its name.
+	key: String,
+	hash: Vec<u8>,
+}
+
+/// One page of rows and the cursor continuing after it, `None` once the
+/// last row has been returned.
+#[derive(Clone, Debug)]
+pub struct CursorPage<T> {
+	pub rows: Vec<T>,
+	pub next_cursor: Option<PageCursor>,
+}
+
+impl PageCursor {
+	pub(crate) fn new(key: Option<String>, hash: Vec<u8>) -> Self {
+		Self {
+			key: key.unwrap_or_default(),
+			hash,
+		}
+	}
+
+	pub(crate) fn key(&self) -> &str {
+		&self.key
+	}
+
+	pub(crate) fn hash(&self) -> &[u8] {
+		&self.hash
+	}
+
+	pub fn encode(&self) -> String {
+		let mut raw = Vec::with_capacity(1 + self.hash.len() + self.key.len());
+		raw.push(self.hash.len() as u8);
+		raw.extend_from_slice(&self.hash);
+		raw.extend_from_slice(self.key.as_bytes());
+		URL_SAFE_NO_PAD.encode(raw)
+	}
+
+	pub fn decode(cursor: &str) -> anyhow::Result<Self> {
+		let raw = URL_SAFE_NO_PAD
+			.decode(cursor)
+			.map_err(|err| anyhow!("invalid cursor: {err}"))?;
+		let Some((&hash_len, rest)) = raw.split_first() else {
+			bail!("invalid cursor: empty");
+		};
+		if rest.len() < usize::from(hash_len) {
+			bail!("invalid cursor: truncated");
+		}
+		let (hash, key) = rest.split_at(usize::from(hash_len));
+		Ok(Self {
+			key: String::from_utf8(key.to_vec()).map_err(|_| anyhow!("invalid cursor: bad key"))?,
+			hash: hash.to_vec(),
+		})
+	}
+
+	/// Condition matching the rows after this cursor, with its two
+	/// parameters bound from `?{first_param}`.
+	pub(crate) fn after_clause(&self, desc: bool, first_param: usize) -> (String, [Value; 2]) {
+		let op = if desc { "<" } else { ">" };
+		let (key, hash) = (first_param, first_param + 1);
+		(
+			format!(
+				"({SORT_KEY_SQL} {op} ?{key} OR ({SORT_KEY_SQL} = ?{key} AND fe.hash {op} ?{hash}))"
+			),
+			[
+				Value::Text(self.key.clone()),
+				Value::Blob(self.hash.clone()),
+			],
+		)
+	}
+}
+
+pub(crate) fn order_clause(desc: bool) -> String {
+	let dir = if desc { "DESC" } else { "ASC" };
+	format!(" ORDER BY {SORT_KEY_SQL} {dir}, fe.hash {dir}")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::db::{
+		SearchFilesArgs, SearchSortBy, fetch_file_entries_after, search_files, search_files_after,
+	};
+	use crate::test_util;
+	use rusqlite::{Connection, params};
+	use std::collections::BTreeSet;
+
+	fn insert(conn: &Connection, hash: u8, latest: &str) {
+		conn.execute(
+			"INSERT INTO file_entries (hash, size, mime_type, first_datetime, latest_datetime)
+			 VALUES (?1, 1, 'text/plain', ?2, ?2)",
+			params![vec![hash; 32], latest],
+		)
+		.unwrap();
+	}
+
+	fn all_hashes(conn: &Connection) -> BTreeSet<u8> {
+		let mut stmt = conn.prepare("SELECT hash FROM file_entries").unwrap();
+		stmt.query_map([], |row| row.get::<_, Vec<u8>>(0))
+			.unwrap()
+			.map(|hash| hash.unwrap()[0])
+			.collect()
+	}
+
+	#[test]
+	fn cursors_round_trip_and_reject_garbage() {
+		let cursor = PageCursor::new(Some(String::from("2025-03-01 12:00:00")), vec![0, 1, 255]);
+		assert_eq!(PageCursor::decode(&cursor.encode()).unwrap(), cursor);
+		let undated = PageCursor::new(None, vec![7; 32]);
+		assert_eq!(PageCursor::decode(&undated.encode()).unwrap(), undated);
+		assert!(PageCursor::decode("not a cursor!").is_err());
+		assert!(PageCursor::decode("").is_err());
+		assert!(PageCursor::decode(&URL_SAFE_NO_PAD.encode([9, 1, 2])).is_err());
+	}
+
+	#[test]
+	fn pages_neither_skip_nor_repeat_rows_while_inserts_interleave() {
+		let conn = test_util::memory_db();
+		// Several rows share a timestamp so the hash tiebreak matters.
+		for hash in 0..40u8 {
+			insert(&conn, hash, &format!("2025-03-01 12:00:{:02}", hash / 4));
+		}
+		let initial = (0..40u8).collect::<BTreeSet<_>>();
+
+		let mut seen = Vec::new();
+		let mut cursor = None;
+		let mut inserted = 100u8;
+		loop {
+			let page = fetch_file_entries_after(&conn, cursor.as_ref(), 7).unwrap();
+			seen.extend(page.rows.iter().map(|entry| entry.hash[0]));
+			// A scan keeps adding newer files while the client pages.
+			insert(&conn, inserted, "2025-03-02 08:00:00");
+			inserted += 1;
+			match page.next_cursor {
+				Some(next) => cursor = Some(PageCursor::decode(&next.encode()).unwrap()),
+				None => break,
+			}
+		}
+		assert_eq!(seen.len(), initial.len(), "{seen:?}");
+		assert_eq!(seen.iter().copied().collect::<BTreeSet<_>>(), initial);
+
+		// Newly scanned rows sort before the cursor in ascending order too
+		// when they carry an older timestamp; they must not leak in either.
+		let before_search = all_hashes(&conn);
+		let mut seen = Vec::new();
+		let mut cursor = None;
+		loop {
+			let args = SearchFilesArgs {
+				sort_desc: false,
+				page_size: 9,
+				..Default::default()
+			};
+			let (page, _, _) = search_files_after(&conn, args, cursor.as_ref()).unwrap();
+			seen.extend(page.rows.iter().map(|result| result.hash[0]));
+			insert(&conn, inserted, "2025-02-01 00:00:00");
+			inserted += 1;
+			match page.next_cursor {
+				Some(next) => cursor = Some(next),
+				None => break,
+			}
+		}
+		assert_eq!(seen.len(), before_search.len(), "{seen:?}");
+		assert_eq!(seen.into_iter().collect::<BTreeSet<_>>(), before_search);
+	}
+
+	#[test]
+	fn name_sorted_pages_follow_natural_order_across_cursors_and_page_numbers() {
+		let conn = test_util::memory_db();
+		let names = [
+			"IMG_10.jpg",
+			"img_2.jpg",
+			"IMG_1.jpg",
+			"IMG_002.jpg",
+			"b.jpg",
+			"a.jpg",
+		];
+		for (hash, name) in names.iter().enumerate() {
+			let hash = hash as u8;
+			insert(&conn, hash, "2025-03-01 12:00:00");
+			conn.execute(
+				"INSERT INTO file_locations (node_id, path, hash, size, timestamp)
+				 VALUES (?1, ?2, ?3, 1, '2025-03-01')",
+				params![vec![1u8; 16], format!("/photos/{name}"), vec![hash; 32]],
+			)
+			.unwrap();
+		}
+		// Two files with the same name are told apart by hash.
+		insert(&conn, 50, "2025-03-01 12:00:00");
+		conn.execute(
+			"INSERT INTO file_locations (node_id, path, hash, size, timestamp)
+			 VALUES (?1, '/other/a.jpg', ?2, 1, '2025-03-01')",
+			params![vec![1u8; 16], vec![50u8; 32]],
+		)
+		.unwrap();
+		let expected = [
+			"a.jpg",
+			"a.jpg",
+			"b.jpg",
+			"IMG_1.jpg",
+			"img_2.jpg",
+			"IMG_002.jpg",
+			"IMG_10.jpg",
+		];
+		let args = |sort_desc: bool, page: usize| SearchFilesArgs {
+			sort_by: SearchSortBy::Name,
+			sort_desc,
+			page,
+			page_size: 3,
+			..Default::default()
+		};
+
+		for sort_desc in [false, true] {
+			let mut seen = Vec::new();
+			let mut cursor = None;
+			loop {
+				let (page, _, total) =
+					search_files_after(&conn, args(sort_desc, 0), cursor.as_ref()).unwrap();
+				assert_eq!(total, expected.len());
+				seen.extend(page.rows.iter().map(|result| result.name.clone()));
+				match page.next_cursor {
+					Some(next) => cursor = Some(PageCursor::decode(&next.encode()).unwrap()),
+					None => break,
+				}
+			}
+			let mut want = expected.to_vec();
+			if sort_desc {
+				want.reverse();
+			}
+			assert_eq!(seen, want);
+		}
+
+		let numbered = (0..3)
+			.flat_map(|page| {
+				search_files(&conn, args(false, page))
+					.unwrap()
+					.0
+					.rows
+					.into_iter()
+					.map(|result| result.name)
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(numbered, expected);
+	}
+}
@@ -0,0 +1,278 @@
+//! Pairing two nodes from the setup wizard. Both screens show a short code
+//! derived from the two peer ids; the user on each side confirms the codes
+//! match before any access is granted, so pairing with the wrong machine,
+//! or through a man in the middle, shows up as a mismatch.
+
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use sha2::{Digest, Sha256};
+
+use crate::state::{FLAG_PAIRED, FLAG_READ, FLAG_SEARCH, FolderRule, Permission, Rule};
+use std::collections::HashMap;
+use std::path::Component;
+
+pub(crate) const NODE_NAME_SETTING: &str = "node_name";
+pub(crate) const MAX_NODE_NAME_LEN: usize = 64;
+/// Access each side of a pairing grants the other on its shared folders.
+pub(crate) const PAIRING_FLAGS: u8 = FLAG_READ | FLAG_SEARCH;
+const CODE_DIGITS: u32 = 6;
+/// How long a pairing waits for the users to compare codes. An answer
+/// after that grants nothing.
+pub(crate) const PAIRING_TTL: chrono::Duration = chrono::Duration::minutes(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PairingDirection {
+	/// This node asked the peer to pair.
+	Outgoing,
+	/// The peer asked this node to pair.
+	Incoming,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PairingStatus {
+	/// Waiting for the user on the other node to compare codes.
+	AwaitingPeer,
+	/// Waiting for the user on this node to compare codes.
+	AwaitingYou,
+	Paired,
+	Declined,
+	Failed(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Pairing {
+	pub peer: PeerId,
+	/// Name the peer gave itself, empty until it answers.
+	pub peer_name: String,
+	pub code: String,
+	pub direction: PairingDirection,
+	pub status: PairingStatus,
+	pub started_at: DateTime<Utc>,
+}
+
+impl Pairing {
+	pub(crate) fn new(
+		me: &PeerId,
+		peer: PeerId,
+		peer_name: String,
+		direction: PairingDirection,
+		now: DateTime<Utc>,
+	) -> Self {
+		Self {
+			code: pairing_code(me, &peer),
+			peer,
+			peer_name,
+			direction,
+			status: match direction {
+				PairingDirection::Outgoing => PairingStatus::AwaitingPeer,
+				PairingDirection::Incoming => PairingStatus::AwaitingYou,
+			},
+			started_at: now,
+		}
+	}
+
+	pub fn is_pending(&self) -> bool {
+		matches!(
+			self.status,
+			PairingStatus::AwaitingPeer | PairingStatus::AwaitingYou
+		)
+	}
+
+	/// Whether the pairing is still waiting after [`PAIRING_TTL`].
+	pub(crate) fn is_expired(&self, now: DateTime<Utc>) -> bool {
+		self.is_pending() && now - self.started_at > PAIRING_TTL
+	}
+
+	pub fn describe(&self) -> String {
+		match &self.status {
+			PairingStatus::AwaitingPeer => {
+				String::from("Waiting for the other device to confirm the code")
+			}
+			PairingStatus::AwaitingYou => {
+				String::from("Confirm if the other device shows the same code")
+			}
+			PairingStatus::Paired => String::from("Paired"),
+			PairingStatus::Declined => String::from("Declined"),
+			PairingStatus::Failed(reason) => format!("Failed: {reason}"),
+		}
+	}
+}
+
+/// Fails the pairings that [expired](Pairing::is_expired) by `now` and
+/// returns their peers.
+pub(crate) fn expire_pairings(
+	pairings: &mut HashMap<PeerId, Pairing>,
+	now: DateTime<Utc>,
+) -> Vec<PeerId> {
+	let mut expired = Vec::new();
+	for pairing in pairings.values_mut() {
+		if pairing.is_expired(now) {
+			pairing.status = PairingStatus::Failed(String::from("the code expired"));
+			expired.push(pairing.peer);
+		}
+	}
+	expired
+}
+
+/// Six digits, grouped `123 456`, that both nodes compute alike no matter
+/// which one started the pairing.
+pub fn pairing_code(a: &PeerId, b: &PeerId) -> String {
+	let (a, b) = (a.to_bytes(), b.to_bytes());
+	let (low, high) = if a <= b { (a, b) } else { (b, a) };
+	let mut hasher = Sha256::new();
+	hasher.update(b"puppynet-pairing");
+	hasher.update((low.len() as u32).to_be_bytes());
+	hasher.update(&low);
+	hasher.update(&high);
+	let digest = hasher.finalize();
+	let value =
+		u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) % 10u32.pow(CODE_DIGITS);
+	let digits = format!("{value:06}");
+	format!("{} {}", &digits[..3], &digits[3..])
+}
+
+/// Trims `name` and checks it fits the nodes table and the pairing prompt.
+pub(crate) fn normalize_node_name(name: &str) -> anyhow::Result<String> {
+	let name = name.trim();
+	if name.is_empty() {
+		anyhow::bail!("node name is required");
+	}
+	if name.chars().count() > MAX_NODE_NAME_LEN {
+		anyhow::bail!("node name must be at most {MAX_NODE_NAME_LEN} characters");
+	}
+	if name.chars().any(char::is_control) {
+		anyhow::bail!("node name must not contain control characters");
+	}
+	Ok(name.to_string())
+}
+
+/// Name a peer sent about itself, stripped of control characters and cut
+/// to the length a local name may have.
+pub(crate) fn peer_node_name(name: &str) -> String {
+	name.chars()
+		.filter(|c| !c.is_control())
+		.take(MAX_NODE_NAME_LEN)
+		.collect()
+}
+
+/// Folder rules a pairing grants on top of `existing`: read and search on
+/// every folder of `offered` not already granted, so pairing twice adds
+/// nothing. The rules are marked as granted by pairing.
+pub(crate) fn missing_pairing_rules(
+	offered: &[FolderRule],
+	existing: &[FolderRule],
+) -> Vec<FolderRule> {
+	offered
+		.iter()
+		.filter(|shared| {
+			!existing
+				.iter()
+				.any(|rule| rule.path() == shared.path() && rule.allows(PAIRING_FLAGS))
+		})
+		.map(|shared| FolderRule::new(shared.path().to_path_buf(), PAIRING_FLAGS | FLAG_PAIRED))
+		.collect()
+}
+
+/// Folders a device pairing with a pairing string is offered: those of
+/// `requested` inside a shared folder, or every shared folder when it asks
+/// for none. Only folders count; the access is always [`PAIRING_FLAGS`].
+pub(crate) fn requested_pairing_folders(
+	shared_folders: &[FolderRule],
+	requested: &[Permission],
+) -> Vec<FolderRule> {
+	let asked = requested
+		.iter()
+		.filter_map(|permission| match permission.rule() {
+			Rule::Folder(rule) => Some(rule.path()),
+			Rule::Owner | Rule::Inbox => None,
+		})
+		.collect::<Vec<_>>();
+	if asked.is_empty() {
+		return shared_folders.to_vec();
+	}
+	let mut folders: Vec<FolderRule> = Vec::new();
+	for path in asked {
+		let shared = !path.components().any(|c| c == Component::ParentDir)
+			&& shared_folders
+				.iter()
+				.any(|shared| path.starts_with(shared.path()));
+		if shared && !folders.iter().any(|folder| folder.path() == path) {
+			folders.push(FolderRule::new(path.to_path_buf(), PAIRING_FLAGS));
+		}
+	}
+	folders
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::state::FLAG_WRITE;
+	use std::path::PathBuf;
+
+	#[test]
+	fn both_sides_derive_the_same_code() {
+		let (alice, bob, mallory) = (PeerId::random(), PeerId::random(), PeerId::random());
+		let code = pairing_code(&alice, &bob);
+		assert_eq!(code, pairing_code(&bob, &alice));
+		assert_eq!(code.len(), 7);
+		assert!(
+			code.chars()
+				.filter(|c| *c != ' ')
+				.all(|c| c.is_ascii_digit())
+		);
+		// A man in the middle pairs with each side under its own id, so the
+		// two screens show codes for different pairs of ids.
+		assert_ne!(pairing_code(&alice, &mallory), pairing_code(&mallory, &bob));
+	}
+
+	#[test]
+	fn pairing_again_grants_nothing_new() {
+		let photos = FolderRule::new(PathBuf::from("/srv/photos"), FLAG_READ | FLAG_WRITE);
+		let music = FolderRule::new(PathBuf::from("/srv/music"), FLAG_READ);
+		let shared = vec![photos, music];
+
+		let first = missing_pairing_rules(&shared, &[]);
+		assert_eq!(first.len(), 2);
+		assert!(
+			first
+				.iter()
+				.all(|rule| rule.flags() == PAIRING_FLAGS | FLAG_PAIRED)
+		);
+		assert!(missing_pairing_rules(&shared, &first).is_empty());
+		// A read-only grant without search still needs topping up.
+		let partial = vec![FolderRule::new(PathBuf::from("/srv/photos"), FLAG_READ)];
+		assert_eq!(missing_pairing_rules(&shared, &partial).len(), 2);
+	}
+
+	#[test]
+	fn pairing_strings_only_get_shared_folders() {
+		let shared = vec![FolderRule::new(PathBuf::from("/srv/photos"), FLAG_READ)];
+		let folder = |path: &str| Permission::new(Rule::Folder(FolderRule::new(path.into(), 0)));
+		assert_eq!(requested_pairing_folders(&shared, &[]).len(), 1);
+		let offered = requested_pairing_folders(
+			&shared,
+			&[
+				Permission::new(Rule::Owner),
+				folder("/srv/photos/2025"),
+				folder("/srv/photos/../secrets"),
+				folder("/home"),
+			],
+		);
+		assert_eq!(
+			offered.iter().map(FolderRule::path).collect::<Vec<_>>(),
+			vec![PathBuf::from("/srv/photos/2025")]
+		);
+		// Asking only for what isn't shared gets nothing, not everything.
+		assert!(requested_pairing_folders(&shared, &[folder("/home")]).is_empty());
+	}
+
+	#[test]
+	fn node_names_are_trimmed_and_bounded() {
+		assert_eq!(normalize_node_name("  attic nas ").unwrap(), "attic nas");
+		assert!(normalize_node_name("   ").is_err());
+		assert!(normalize_node_name("a\nb").is_err());
+		assert!(normalize_node_name(&"x".repeat(MAX_NODE_NAME_LEN + 1)).is_err());
+		assert_eq!(peer_node_name("nas\u{1b}[2J"), "nas[2J");
+		assert_eq!(peer_node_name(&"y".repeat(100)).len(), MAX_NODE_NAME_LEN);
+	}
+}
@@ -0,0 +1,476 @@
+//! Pairing across networks with a pairing string. The string carries this
+//! node's peer id, the addresses it is most likely reachable at and a
+//! secret that is good for one pairing within a short window; a device
+//! that presents the secret is granted the default pairing access without
+//! anyone confirming a code, so the string is as good as a key until it
+//! expires or is used.
+//!
+//! The string is `puppy1` followed by lowercase base32 of a versioned
+//! binary blob with a CRC32 at the end, so a mistyped string is refused
+//! instead of dialing the wrong node.
+
+use chrono::{DateTime, Utc};
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::db::load_setting;
+use crate::nat::is_non_routable;
+use crate::reachability::{AddressReachability, Reachability, testable_addrs};
+use crate::state::Permission;
+
+pub const PAIRING_SECRET_TTL_SETTING: &str = "pairing_secret_ttl_secs";
+pub const DEFAULT_PAIRING_SECRET_TTL: chrono::Duration = chrono::Duration::minutes(10);
+const PREFIX: &str = "puppy1";
+const VERSION: u8 = 1;
+const SECRET_LEN: usize = 16;
+const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+/// Addresses a pairing string carries at most, so it stays short enough
+/// for a QR code.
+pub(crate) const MAX_INVITE_ADDRS: usize = 4;
+/// Secrets waiting to be used at once; issuing another drops the oldest.
+const MAX_OPEN_SECRETS: usize = 16;
+/// How long a used or expired secret is remembered, so presenting it again
+/// is told apart from a wrong one.
+const SPENT_MEMORY: chrono::Duration = chrono::Duration::hours(24);
+
+/// Secret lifetime stored under [`PAIRING_SECRET_TTL_SETTING`], in seconds.
+pub(crate) fn secret_ttl(conn: &Connection) -> chrono::Duration {
+	let value = match load_setting(conn, PAIRING_SECRET_TTL_SETTING) {
+		Ok(value) => value,
+		Err(err) => {
+			tracing::error!("failed to load pairing secret lifetime: {err}");
+			None
+		}
+	};
+	value
+		.and_then(|value| value.trim().parse::<i64>().ok())
+		.filter(|secs| *secs > 0)
+		.map(chrono::Duration::seconds)
+		.unwrap_or_else(|| config::startup().pairing_secret_ttl)
+}
+
+/// Why a node refused a `Pair` request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PairingRejected {
+	/// The secret was never issued by the node.
+	WrongSecret,
+	Expired,
+	AlreadyUsed,
+}
+
+impl std::fmt::Display for PairingRejected {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Self::WrongSecret => "the pairing string is not valid for that device",
+			Self::Expired => "the pairing string has expired; show a new one",
+			Self::AlreadyUsed => "the pairing string was already used; show a new one",
+		})
+	}
+}
+
+impl std::error::Error for PairingRejected {}
+
+/// Text that does not decode as a pairing string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidPairingString(String);
+
+impl std::fmt::Display for InvalidPairingString {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "not a pairing string: {}", self.0)
+	}
+}
+
+impl std::error::Error for InvalidPairingString {}
+
+fn invalid(reason: impl Into<String>) -> InvalidPairingString {
+	InvalidPairingString(reason.into())
+}
+
+/// What a pairing string says.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PairingInvite {
+	pub peer_id: PeerId,
+	/// Best first, without a `/p2p` part.
+	pub addrs: Vec<Multiaddr>,
+	pub secret: [u8; SECRET_LEN],
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+	let mut out = String::with_capacity(bytes.len().div_ceil(5) * 8);
+	let (mut buffer, mut bits) = (0u32, 0u32);
+	for byte in bytes {
+		buffer = (buffer << 8) | u32::from(*byte);
+		bits += 8;
+		while bits >= 5 {
+			bits -= 5;
+			out.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+		}
+	}
+	if bits > 0 {
+		out.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+	}
+	out
+}
+
+fn base32_decode(text: &str) -> Result<Vec<u8>, InvalidPairingString> {
+	let mut out = Vec::with_capacity(text.len() * 5 / 8);
+	let (mut buffer, mut bits) = (0u32, 0u32);
+	for c in text.chars() {
+		let value = ALPHABET
+			.iter()
+			.position(|a| *a as char == c)
+			.ok_or_else(|| invalid(format!("unexpected character {c:?}")))?;
+		buffer = (buffer << 5) | value as u32;
+		bits += 5;
+		if bits >= 8 {
+			bits -= 8;
+			out.push((buffer >> bits) as u8);
+		}
+	}
+	Ok(out)
+}
+
+/// Reads the length-prefixed pieces of the blob in order.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+	fn take(&mut self, len: usize) -> Result<&'a [u8], InvalidPairingString> {
+		if self.0.len() < len {
+			return Err(invalid("it is cut short"));
+		}
+		let (head, rest) = self.0.split_at(len);
+		self.0 = rest;
+		Ok(head)
+	}
+
+	fn byte(&mut self) -> Result<u8, InvalidPairingString> {
+		Ok(self.take(1)?[0])
+	}
+
+	fn prefixed(&mut self) -> Result<&'a [u8], InvalidPairingString> {
+		let len = self.byte()?;
+		self.take(len as usize)
+	}
+}
+
+impl PairingInvite {
+	pub fn encode(&self) -> String {
+		let mut blob = vec![VERSION];
+		blob.extend_from_slice(&self.secret);
+		let peer = self.peer_id.to_bytes();
+		blob.push(peer.len() as u8);
+		blob.extend_from_slice(&peer);
+		let addrs = self
+			.addrs
+			.iter()
+			.map(Multiaddr::to_vec)
+			.filter(|addr| addr.len() <= u8::MAX as usize)
+			.take(MAX_INVITE_ADDRS)
+			.collect::<Vec<_>>();
+		blob.push(addrs.len() as u8);
+		for addr in addrs {
+			blob.push(addr.len() as u8);
+			blob.extend_from_slice(&addr);
+		}
+		blob.extend_from_slice(&crc32fast::hash(&blob).to_be_bytes());
+		format!("{PREFIX}{}", base32_encode(&blob))
+	}
+
+	/// Reads a pairing string as typed or pasted: case and whitespace,
+	/// such as line breaks from a chat message, don't matter.
+	pub fn decode(text: &str) -> Result<Self, InvalidPairingString> {
+		let text = text
+			.chars()
+			.filter(|c| !c.is_whitespace())
+			.collect::<String>()
+			.to_ascii_lowercase();
+		let body = text
+			.strip_prefix(PREFIX)
+			.ok_or_else(|| invalid(format!("it does not start with {PREFIX}")))?;
+		let blob = base32_decode(body)?;
+		let Some((data, checksum)) = blob.split_last_chunk::<4>() else {
+			return Err(invalid("it is cut short"));
+		};
+		if crc32fast::hash(data) != u32::from_be_bytes(*checksum) {
+			return Err(invalid("it has a typo or is cut short"));
+		}
+		let mut reader = Reader(data);
+		let version = reader.byte()?;
+		if version != VERSION {
+			return Err(invalid(format!(
+				"version {version} is not supported; update PuppyNet"
+			)));
+		}
+		let secret = reader
+			.take(SECRET_LEN)?
+			.try_into()
+			.expect("take returns the length asked for");
+		let peer_id = PeerId::from_bytes(reader.prefixed()?)
+			.map_err(|err| invalid(format!("bad peer id: {err}")))?;
+		let count = reader.byte()?;
+		let mut addrs = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			let addr = Multiaddr::try_from(reader.prefixed()?.to_vec())
+				.map_err(|err| invalid(format!("bad address: {err}")))?;
+			addrs.push(addr);
+		}
+		Ok(Self {
+			peer_id,
+			addrs,
+			secret,
+		})
+	}
+}
+
+/// A pairing string of this node, for showing to the user.
+#[derive(Clone, Debug, Serialize)]
+pub struct PairingInfo {
+	pub peer_id: String,
+	pub addrs: Vec<String>,
+	pub expires_at: DateTime<Utc>,
+	pub pairing_string: String,
+}
+
+/// What the node behind a pairing string answered.
+#[derive(Clone, Debug)]
+pub struct PairedWith {
+	pub peer_id: PeerId,
+	pub node_name: String,
+	/// What it granted this node, only what the pairing added.
+	pub granted: Vec<Permission>,
+}
+
+fn ip_of(addr: &Multiaddr) -> Option<std::net::IpAddr> {
+	match addr.iter().next()? {
+		Protocol::Ip4(ip) => Some(ip.into()),
+		Protocol::Ip6(ip) => Some(ip.into()),
+		_ => None,
+	}
+}
+
+/// Addresses for a pairing string, best first: public ones the last
+/// self-test reached, then the router mapping and other external ones the
+/// self-test didn't find unreachable, then the listen addresses for a
+/// device on the same network.
+pub(crate) fn invite_addrs(
+	reachability: &[AddressReachability],
+	external: impl IntoIterator<Item = Multiaddr>,
+	listeners: impl IntoIterator<Item = Multiaddr>,
+) -> Vec<Multiaddr> {
+	let status = |addr: &Multiaddr| {
+		reachability
+			.iter()
+			.find(|result| result.addr.parse::<Multiaddr>().ok().as_ref() == Some(addr))
+			.map(|result| result.status)
+	};
+	let public = |addr: &Multiaddr| ip_of(addr).is_some_and(|ip| !is_non_routable(ip));
+	let candidates = testable_addrs(
+		reachability
+			.iter()
+			.filter_map(|result| result.addr.parse::<Multiaddr>().ok())
+			.chain(external)
+			.chain(listeners),
+	);
+	let reached = candidates
+		.iter()
+		.filter(|addr| public(addr) && status(addr) == Some(Reachability::Reachable));
+	let external = candidates
+		.iter()
+		.filter(|addr| public(addr) && status(addr) != Some(Reachability::Unreachable));
+	let local = candidates.iter().filter(|addr| !public(addr));
+	let mut addrs = Vec::new();
+	for addr in reached.chain(external).chain(local) {
+		if !addrs.contains(addr) && addrs.len() < MAX_INVITE_ADDRS {
+			addrs.push(addr.clone());
+		}
+	}
+	addrs
+}
+
+struct IssuedSecret {
+	secret: [u8; SECRET_LEN],
+	expires_at: DateTime<Utc>,
+	used: bool,
+}
+
+/// Pairing secrets this node handed out, kept in memory only: a restart
+/// invalidates every pairing string shown before it.
+#[derive(Default)]
+pub(crate) struct PairingSecrets {
+	issued: Vec<IssuedSecret>,
+}
+
+/// Compares without returning early, so the time taken says nothing about
+/// how much of a guess was right.
+fn same_secret(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl PairingSecrets {
+	fn forget_old(&mut self, now: DateTime<Utc>) {
+		self.issued
+			.retain(|issued| issued.expires_at + SPENT_MEMORY > now);
+	}
+
+	/// A new secret, valid until the returned time.
+	pub(crate) fn issue(
+		&mut self,
+		now: DateTime<Utc>,
+		ttl: chrono::Duration,
+	) -> ([u8; SECRET_LEN], DateTime<Utc>) {
+		self.forget_old(now);
+		let open = |issued: &IssuedSecret| !issued.used && issued.expires_at > now;
+		if self.issued.iter().filter(|issued| open(issued)).count() >= MAX_OPEN_SECRETS
+			&& let Some(oldest) = self.issued.iter().position(open)
+		{
+			self.issued.remove(oldest);
+		}
+		let mut secret = [0u8; SECRET_LEN];
+		OsRng.fill_bytes(&mut secret);
+		let expires_at = now + ttl;
+		self.issued.push(IssuedSecret {
+			secret,
+			expires_at,
+			used: false,
+		});
+		(secret, expires_at)
+	}
+
+	/// Spends `secret`, which then can't be used again.
+	pub(crate) fn redeem(
+		&mut self,
+		secret: &[u8],
+		now: DateTime<Utc>,
+	) -> Result<(), PairingRejected> {
+		self.forget_old(now);
+		let issued = self
+			.issued
+			.iter_mut()
+			.find(|issued| same_secret(&issued.secret, secret))
+			.ok_or(PairingRejected::WrongSecret)?;
+		if issued.used {
+			return Err(PairingRejected::AlreadyUsed);
+		}
+		if issued.expires_at <= now {
+			return Err(PairingRejected::Expired);
+		}
+		issued.used = true;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn invite() -> PairingInvite {
+		PairingInvite {
+			peer_id: PeerId::random(),
+			addrs: vec![
+				"/ip4/203.0.113.7/tcp/8336".parse().unwrap(),
+				"/ip4/192.168.1.20/udp/8336/quic-v1".parse().unwrap(),
+			],
+			secret: [7; SECRET_LEN],
+		}
+	}
+
+	fn at(secs: i64) -> DateTime<Utc> {
+		DateTime::from_timestamp(1_800_000_000 + secs, 0).unwrap()
+	}
+
+	#[test]
+	fn pairing_strings_round_trip_as_pasted() {
+		let invite = invite();
+		let text = invite.encode();
+		assert!(text.starts_with(PREFIX));
+		assert!(text.chars().all(|c| c.is_ascii_alphanumeric()));
+		assert_eq!(PairingInvite::decode(&text).unwrap(), invite);
+		let (head, tail) = text.split_at(20);
+		let pasted = format!(" {}\n{} ", head.to_uppercase(), tail);
+		assert_eq!(PairingInvite::decode(&pasted).unwrap(), invite);
+	}
+
+	#[test]
+	fn typos_and_other_text_are_refused() {
+		let text = invite().encode();
+		let mut typo = text.clone().into_bytes();
+		let last = typo.len() - 5;
+		typo[last] = if typo[last] == b'a' { b'b' } else { b'a' };
+		assert!(PairingInvite::decode(std::str::from_utf8(&typo).unwrap()).is_err());
+		assert!(PairingInvite::decode(&text[..text.len() - 8]).is_err());
+		assert!(PairingInvite::decode("12D3KooWSomePeerId").is_err());
+		assert!(PairingInvite::decode("puppy1!!").is_err());
+	}
+
+	#[test]
+	fn secrets_expire() {
+		let mut secrets = PairingSecrets::default();
+		let (secret, expires_at) = secrets.issue(at(0), chrono::Duration::minutes(10));
+		assert_eq!(expires_at, at(600));
+		assert_eq!(
+			secrets.redeem(&secret, at(600)),
+			Err(PairingRejected::Expired)
+		);
+		let (secret, _) = secrets.issue(at(0), chrono::Duration::minutes(10));
+		assert_eq!(secrets.redeem(&secret, at(599)), Ok(()));
+	}
+
+	#[test]
+	fn secrets_work_once() {
+		let mut secrets = PairingSecrets::default();
+		let (secret, _) = secrets.issue(at(0), chrono::Duration::minutes(10));
+		assert_eq!(secrets.redeem(&secret, at(1)), Ok(()));
+		assert_eq!(
+			secrets.redeem(&secret, at(2)),
+			Err(PairingRejected::AlreadyUsed)
+		);
+	}
+
+	#[test]
+	fn wrong_secrets_are_refused_without_spending_the_right_one() {
+		let mut secrets = PairingSecrets::default();
+		let (secret, _) = secrets.issue(at(0), chrono::Duration::minutes(10));
+		let mut wrong = secret;
+		wrong[0] ^= 1;
+		assert_eq!(
+			secrets.redeem(&wrong, at(1)),
+			Err(PairingRejected::WrongSecret)
+		);
+		assert_eq!(
+			secrets.redeem(&secret[..8], at(1)),
+			Err(PairingRejected::WrongSecret)
+		);
+		assert_eq!(secrets.redeem(&secret, at(2)), Ok(()));
+	}
+
+	#[test]
+	fn reached_public_addresses_come_first() {
+		let lan: Multiaddr = "/ip4/192.168.1.20/tcp/8336".parse().unwrap();
+		let mapped: Multiaddr = "/ip4/203.0.113.7/tcp/40000".parse().unwrap();
+		let blocked: Multiaddr = "/ip4/203.0.113.7/tcp/8336".parse().unwrap();
+		let reached: Multiaddr = "/ip4/198.51.100.3/udp/8336/quic-v1".parse().unwrap();
+		let result = |addr: &Multiaddr, status| AddressReachability {
+			addr: addr.to_string(),
+			status,
+			tested_at: None,
+			tested_by: None,
+			latency_ms: None,
+			error: None,
+		};
+		let addrs = invite_addrs(
+			&[
+				result(&blocked, Reachability::Unreachable),
+				result(&reached, Reachability::Reachable),
+			],
+			vec![mapped.clone()],
+			vec![lan.clone(), "/ip4/127.0.0.1/tcp/8336".parse().unwrap()],
+		);
+		assert_eq!(addrs, vec![reached, mapped, lan]);
+	}
+}
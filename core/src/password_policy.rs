@@ -0,0 +1,259 @@
+//! What a new user's name and password must look like. Every way of
+//! creating a user (the daemon's control socket behind the CLI, the HTTP
+//! API, the web UI and peers sending `CreateUser`) ends up in
+//! [`check_new_user`], so they all accept and refuse the same things, and a
+//! refusal names the field to fix.
+
+use crate::auth;
+use crate::db::load_setting;
+use crate::state::User;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+pub(crate) const PASSWORD_POLICY_SETTING: &str = "password_policy";
+pub const DEFAULT_PASSWORD_MIN_LENGTH: usize = 8;
+
+/// Rules for new passwords, stored as JSON under
+/// [`PASSWORD_POLICY_SETTING`]. A password that spells the username is
+/// always refused.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasswordPolicy {
+	/// Characters, not bytes.
+	pub min_length: usize,
+	/// Passwords need both a letter and a digit.
+	pub require_letters_and_digits: bool,
+	/// Passwords need a character that is neither a letter nor a digit.
+	pub require_symbol: bool,
+}
+
+impl Default for PasswordPolicy {
+	fn default() -> Self {
+		Self {
+			min_length: DEFAULT_PASSWORD_MIN_LENGTH,
+			require_letters_and_digits: false,
+			require_symbol: false,
+		}
+	}
+}
+
+impl PasswordPolicy {
+	/// The policy stored in `conn`, or the default when none is stored or
+	/// it can't be read.
+	pub(crate) fn load(conn: &Connection) -> Self {
+		match load_setting(conn, PASSWORD_POLICY_SETTING) {
+			Ok(Some(value)) => serde_json::from_str(&value).unwrap_or_else(|err| {
+				tracing::warn!("ignoring invalid password policy: {err}");
+				Self::default()
+			}),
+			Ok(None) => Self::default(),
+			Err(err) => {
+				tracing::error!("failed to load password policy: {err}");
+				Self::default()
+			}
+		}
+	}
+
+	/// What is wrong with `password` for `username`, first problem first.
+	pub fn check(&self, username: &str, password: &str) -> Option<String> {
+		if password.is_empty() {
+			return Some(String::from("Password is required"));
+		}
+		let length = password.chars().count();
+		if length < self.min_length {
+			return Some(format!(
+				"Password must be at least {} characters, not {length}",
+				self.min_length
+			));
+		}
+		if !username.is_empty() && password.trim().eq_ignore_ascii_case(username.trim()) {
+			return Some(String::from("Password can't be the username"));
+		}
+		if self.require_letters_and_digits
+			&& !(password.chars().any(char::is_alphabetic)
+				&& password.chars().any(|c| c.is_ascii_digit()))
+		{
+			return Some(String::from("Password needs both letters and digits"));
+		}
+		if self.require_symbol && password.chars().all(char::is_alphanumeric) {
+			return Some(String::from(
+				"Password needs a character that is not a letter or digit",
+			));
+		}
+		None
+	}
+
+	/// The rules in a sentence, to show next to a password field.
+	pub fn describe(&self) -> String {
+		let mut rules = vec![format!("at least {} characters", self.min_length)];
+		if self.require_letters_and_digits {
+			rules.push(String::from("letters and digits"));
+		}
+		if self.require_symbol {
+			rules.push(String::from("a symbol"));
+		}
+		format!(
+			"Passwords need {}, and can't be the username.",
+			rules.join(", ")
+		)
+	}
+}
+
+/// One field of a new user to fix.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserFieldError {
+	/// `username` or `password`.
+	pub field: String,
+	pub message: String,
+}
+
+/// Why a user was not created.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CreateUserError {
+	AlreadyExists { username: String },
+	Invalid(Vec<UserFieldError>),
+}
+
+impl CreateUserError {
+	/// The message for `field`, if it is the one at fault.
+	pub fn field(&self, field: &str) -> Option<&str> {
+		match self {
+			Self::AlreadyExists { .. } => (field == "username").then_some("Username is taken"),
+			Self::Invalid(errors) => errors
+				.iter()
+				.find(|error| error.field == field)
+				.map(|error| error.message.as_str()),
+		}
+	}
+}
+
+impl fmt::Display for CreateUserError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::AlreadyExists { username } => write!(f, "user {username} already exists"),
+			Self::Invalid(errors) => {
+				let messages = errors
+					.iter()
+					.map(|error| error.message.as_str())
+					.collect::<Vec<_>>();
+				write!(f, "{}", messages.join("; "))
+			}
+		}
+	}
+}
+
+impl std::error::Error for CreateUserError {}
+
+/// The trimmed name to add for `username` and `password`, when `policy`
+/// allows them and no user in `existing` has the name. Nothing is hashed,
+/// so this is cheap enough to run anywhere.
+pub(crate) fn check_new_user<'a>(
+	policy: &PasswordPolicy,
+	existing: impl IntoIterator<Item = &'a str>,
+	username: &str,
+	password: &str,
+) -> Result<String, CreateUserError> {
+	let username = username.trim();
+	let mut errors = Vec::new();
+	if username.is_empty() {
+		errors.push(UserFieldError {
+			field: String::from("username"),
+			message: String::from("Username is required"),
+		});
+	}
+	if let Some(message) = policy.check(username, password) {
+		errors.push(UserFieldError {
+			field: String::from("password"),
+			message,
+		});
+	}
+	if !errors.is_empty() {
+		return Err(CreateUserError::Invalid(errors));
+	}
+	if existing.into_iter().any(|name| name == username) {
+		return Err(CreateUserError::AlreadyExists {
+			username: username.to_string(),
+		});
+	}
+	Ok(username.to_string())
+}
+
+/// The user `name` with `password` hashed, on a blocking thread: Argon2 is
+/// slow on purpose, too slow for the event loop or under the database lock.
+pub(crate) async fn hash_new_user(name: String, password: String) -> anyhow::Result<User> {
+	let passw = tokio::task::spawn_blocking(move || auth::hash_password(&password)).await??;
+	Ok(User { name, passw })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::db::{load_users, save_setting, save_user};
+	use crate::test_util;
+
+	fn refusal(policy: &PasswordPolicy, username: &str, password: &str) -> CreateUserError {
+		check_new_user(policy, ["ana"], username, password).unwrap_err()
+	}
+
+	#[test]
+	fn policy_names_the_field_to_fix() {
+		let policy = PasswordPolicy::default();
+		let short = refusal(&policy, "bo", "a");
+		assert_eq!(
+			short.field("password"),
+			Some("Password must be at least 8 characters, not 1")
+		);
+		assert_eq!(short.field("username"), None);
+		let both = refusal(&policy, "  ", "");
+		assert_eq!(both.field("username"), Some("Username is required"));
+		assert_eq!(both.field("password"), Some("Password is required"));
+		assert_eq!(
+			refusal(&policy, "Robert", "robert").field("password"),
+			Some("Password must be at least 8 characters, not 6")
+		);
+		assert_eq!(
+			refusal(&policy, "roberto1", "ROBERTO1").field("password"),
+			Some("Password can't be the username")
+		);
+		assert_eq!(
+			refusal(&policy, " ana ", "long enough"),
+			CreateUserError::AlreadyExists {
+				username: String::from("ana")
+			}
+		);
+	}
+
+	#[test]
+	fn complexity_checks_are_optional() {
+		let strict = PasswordPolicy {
+			min_length: 4,
+			require_letters_and_digits: true,
+			require_symbol: true,
+		};
+		assert!(strict.check("bo", "abcdef").is_some());
+		assert!(strict.check("bo", "abc123").is_some());
+		assert_eq!(strict.check("bo", "abc-123"), None);
+		assert_eq!(PasswordPolicy::default().check("bo", "abcdefgh"), None);
+	}
+
+	#[tokio::test]
+	async fn stored_policy_applies_and_created_user_can_log_in() {
+		let conn = test_util::memory_db();
+		assert_eq!(PasswordPolicy::load(&conn), PasswordPolicy::default());
+		save_setting(&conn, PASSWORD_POLICY_SETTING, r#"{"min_length": 12}"#).unwrap();
+		let policy = PasswordPolicy::load(&conn);
+		assert_eq!(policy.min_length, 12);
+		assert!(check_new_user(&policy, [], "bo", "eleven char").is_err());
+
+		let name = check_new_user(&policy, [], " bo ", "twelve chars").unwrap();
+		let user = hash_new_user(name, String::from("twelve chars"))
+			.await
+			.unwrap();
+		save_user(&conn, &user).unwrap();
+		let stored = load_users(&conn).unwrap();
+		assert_eq!(stored.len(), 1);
+		assert_eq!(stored[0].name, "bo");
+		assert!(auth::verify_password("twelve chars", &stored[0].passw).unwrap());
+	}
+}
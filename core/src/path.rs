@@ -0,0 +1,628 @@
+//! Validation of every path that arrives from outside: peer requests, the
+//! HTTP API, commands and the GUI's path fields. A [`SafePath`] is absolute,
+//! has no `..` segments, NUL bytes or names the target platform refuses,
+//! and is within the length limits; `.` segments, repeated separators and
+//! trailing separators are dropped, and on Windows `/` becomes `\`. A path
+//! already in that form comes out byte for byte as it went in.
+
+use crate::p2p::{WirePath, path_bytes, path_from_bytes};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Longest accepted path, in bytes.
+pub const MAX_PATH_BYTES: usize = 4096;
+
+/// Longest accepted file or folder name, in bytes.
+pub const MAX_COMPONENT_BYTES: usize = 255;
+
+/// Names Windows reserves for devices, with or without an extension.
+const WINDOWS_DEVICE_NAMES: [&str; 22] = [
+	"CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+	"COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters Windows refuses in names, besides control characters.
+const WINDOWS_INVALID_CHARS: &[u8] = b"<>:\"|?*";
+
+/// The path rules of the filesystem a path is meant for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PathStyle {
+	/// `/` separates names; `\` is an ordinary character.
+	Unix,
+	/// `\` or `/` separates names below a drive (`C:\`) or share
+	/// (`\\server\share`).
+	Windows,
+}
+
+impl PathStyle {
+	pub fn native() -> Self {
+		if cfg!(windows) {
+			Self::Windows
+		} else {
+			Self::Unix
+		}
+	}
+
+	/// The style of a path on another node: Windows when it starts with a
+	/// drive letter or `\\`, Unix otherwise. The node it is sent to checks
+	/// it again against its own rules.
+	pub fn detect(path: &[u8]) -> Self {
+		let drive = path.len() >= 2 && path[0].is_ascii_alphabetic() && path[1] == b':';
+		if drive || path.starts_with(b"\\\\") {
+			Self::Windows
+		} else {
+			Self::Unix
+		}
+	}
+
+	fn separator(self) -> u8 {
+		match self {
+			Self::Unix => b'/',
+			Self::Windows => b'\\',
+		}
+	}
+
+	fn is_separator(self, byte: u8) -> bool {
+		byte == b'/' || (self == Self::Windows && byte == b'\\')
+	}
+}
+
+/// The rule a rejected path broke.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathError {
+	Empty,
+	NulByte,
+	TooLong {
+		len: usize,
+	},
+	ComponentTooLong {
+		len: usize,
+	},
+	NotAbsolute,
+	ParentSegment,
+	/// `\\?\` and `\\.\` paths, which skip Windows' own checks.
+	DevicePath,
+	ReservedName(String),
+	InvalidCharacter {
+		name: String,
+		ch: char,
+	},
+	TrailingDotOrSpace(String),
+	/// A name to join that is empty, `.`, or holds a separator.
+	NotAName(String),
+}
+
+impl fmt::Display for PathError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Empty => write!(f, "path is empty"),
+			Self::NulByte => write!(f, "path contains a NUL byte"),
+			Self::TooLong { len } => write!(
+				f,
+				"path is {len} bytes long; at most {MAX_PATH_BYTES} are allowed"
+			),
+			Self::ComponentTooLong { len } => write!(
+				f,
+				"path has a {len} byte name; at most {MAX_COMPONENT_BYTES} are allowed"
+			),
+			Self::NotAbsolute => write!(f, "path is not absolute"),
+			Self::ParentSegment => write!(f, "path contains a `..` segment"),
+			Self::DevicePath => write!(f, "device paths (`\\\\?\\`, `\\\\.\\`) are not allowed"),
+			Self::ReservedName(name) => {
+				write!(f, "path names `{name}`, a reserved device name on Windows")
+			}
+			Self::InvalidCharacter { name, ch } => write!(
+				f,
+				"path name `{name}` contains {ch:?}, which Windows does not allow"
+			),
+			Self::TrailingDotOrSpace(name) => write!(
+				f,
+				"path name `{name}` ends with a dot or space, which Windows drops"
+			),
+			Self::NotAName(name) => write!(f, "`{name}` is not a single file or folder name"),
+		}
+	}
+}
+
+impl std::error::Error for PathError {}
+
+fn check_name(name: &[u8], style: PathStyle) -> Result<(), PathError> {
+	if name.len() > MAX_COMPONENT_BYTES {
+		return Err(PathError::ComponentTooLong { len: name.len() });
+	}
+	if style == PathStyle::Unix {
+		return Ok(());
+	}
+	let text = || String::from_utf8_lossy(name).into_owned();
+	if let Some(&byte) = name.iter().find(|byte| {
+		(byte.is_ascii_control() && **byte != 0x7F) || WINDOWS_INVALID_CHARS.contains(byte)
+	}) {
+		return Err(PathError::InvalidCharacter {
+			name: text(),
+			ch: char::from(byte),
+		});
+	}
+	if name.ends_with(b".") || name.ends_with(b" ") {
+		return Err(PathError::TrailingDotOrSpace(text()));
+	}
+	let stem = name.split(|byte| *byte == b'.').next().unwrap_or_default();
+	let stem = stem.trim_ascii_end();
+	if WINDOWS_DEVICE_NAMES
+		.iter()
+		.any(|device| stem.eq_ignore_ascii_case(device.as_bytes()))
+	{
+		return Err(PathError::ReservedName(text()));
+	}
+	Ok(())
+}
+
+/// Splits off the part naming the filesystem root, returned in normalized
+/// form, from the names below it.
+fn split_root(path: &[u8], style: PathStyle) -> Result<(Vec<u8>, &[u8]), PathError> {
+	match style {
+		PathStyle::Unix => match path.strip_prefix(b"/") {
+			Some(rest) => Ok((b"/".to_vec(), rest)),
+			None => Err(PathError::NotAbsolute),
+		},
+		PathStyle::Windows => {
+			let sep = |byte: &u8| style.is_separator(*byte);
+			if path.len() >= 2 && sep(&path[0]) && sep(&path[1]) {
+				let rest = &path[2..];
+				if rest.first().is_some_and(|byte| matches!(byte, b'?' | b'.'))
+					&& rest.get(1).is_some_and(sep)
+				{
+					return Err(PathError::DevicePath);
+				}
+				let mut parts = rest.splitn(3, sep);
+				let server = parts.next().unwrap_or_default();
+				let share = parts.next().unwrap_or_default();
+				if server.is_empty() || share.is_empty() {
+					return Err(PathError::NotAbsolute);
+				}
+				check_name(server, style)?;
+				check_name(share, style)?;
+				let mut root = b"\\\\".to_vec();
+				root.extend_from_slice(server);
+				root.push(b'\\');
+				root.extend_from_slice(share);
+				return Ok((root, parts.next().unwrap_or_default()));
+			}
+			if path.len() >= 2 && path[0].is_ascii_alphabetic() && path[1] == b':' {
+				let rest = &path[2..];
+				if !rest.is_empty() && !rest.first().is_some_and(sep) {
+					// `C:name` is relative to the drive's current folder.
+					return Err(PathError::NotAbsolute);
+				}
+				return Ok((vec![path[0], b':', b'\\'], rest));
+			}
+			Err(PathError::NotAbsolute)
+		}
+	}
+}
+
+/// An absolute path that passed validation for `style`; see the module
+/// docs for the rules.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SafePath {
+	bytes: Vec<u8>,
+	style: PathStyle,
+}
+
+impl SafePath {
+	/// Checks and normalizes `path` under `style`. The bytes are UTF-8, or
+	/// the encoding of [`path_bytes`].
+	pub fn from_bytes(path: &[u8], style: PathStyle) -> Result<Self, PathError> {
+		if path.is_empty() {
+			return Err(PathError::Empty);
+		}
+		if path.contains(&0) {
+			return Err(PathError::NulByte);
+		}
+		if path.len() > MAX_PATH_BYTES {
+			return Err(PathError::TooLong { len: path.len() });
+		}
+		let (mut bytes, rest) = split_root(path, style)?;
+		let mut first = true;
+		for name in rest.split(|byte| style.is_separator(*byte)) {
+			match name {
+				b"" | b"." => continue,
+				b".." => return Err(PathError::ParentSegment),
+				_ => check_name(name, style)?,
+			}
+			if !first || !bytes.ends_with(&[style.separator()]) {
+				bytes.push(style.separator());
+			}
+			bytes.extend_from_slice(name);
+			first = false;
+		}
+		if bytes.len() > MAX_PATH_BYTES {
+			return Err(PathError::TooLong { len: bytes.len() });
+		}
+		Ok(Self { bytes, style })
+	}
+
+	/// A path on this node's filesystem.
+	pub fn new(path: impl AsRef<Path>) -> Result<Self, PathError> {
+		Self::from_bytes(&path_bytes(path.as_ref()), PathStyle::native())
+	}
+
+	/// A path a peer asked this node about.
+	pub fn from_wire(path: &WirePath) -> Result<Self, PathError> {
+		Self::from_wire_with_style(path, PathStyle::native())
+	}
+
+	/// A path on another node, whose style is read from the path itself.
+	pub fn remote(path: &WirePath) -> Result<Self, PathError> {
+		let bytes = wire_bytes(path);
+		Self::from_bytes(bytes, PathStyle::detect(bytes))
+	}
+
+	pub fn from_wire_with_style(path: &WirePath, style: PathStyle) -> Result<Self, PathError> {
+		Self::from_bytes(wire_bytes(path), style)
+	}
+
+	/// A path typed by a user, ignoring surrounding whitespace.
+	pub fn parse(text: &str, style: PathStyle) -> Result<Self, PathError> {
+		Self::from_bytes(text.trim().as_bytes(), style)
+	}
+
+	pub fn style(&self) -> PathStyle {
+		self.style
+	}
+
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.bytes
+	}
+
+	/// The path on this node's filesystem. Paths written for another
+	/// platform fail the rule they break here.
+	pub fn to_local(&self) -> Result<PathBuf, PathError> {
+		if self.style != PathStyle::native() {
+			Self::from_bytes(&self.bytes, PathStyle::native())?;
+		}
+		Ok(path_from_bytes(&self.bytes))
+	}
+
+	pub fn to_wire(&self) -> WirePath {
+		match std::str::from_utf8(&self.bytes) {
+			Ok(text) => WirePath::Display(text.to_string()),
+			Err(_) => WirePath::Raw(self.bytes.clone()),
+		}
+	}
+
+	/// `/`, a drive (`C:\`) or a share (`\\server\share`).
+	pub fn is_root(&self) -> bool {
+		self.parent().is_none()
+	}
+
+	/// The last name, `None` for a root.
+	pub fn file_name(&self) -> Option<&[u8]> {
+		if self.is_root() {
+			return None;
+		}
+		let split = self
+			.bytes
+			.iter()
+			.rposition(|byte| *byte == self.style.separator())?;
+		Some(&self.bytes[split + 1..])
+	}
+
+	/// The enclosing folder, `None` for a root.
+	pub fn parent(&self) -> Option<Self> {
+		let (root, rest) = split_root(&self.bytes, self.style).ok()?;
+		if rest.iter().all(|byte| self.style.is_separator(*byte)) {
+			return None;
+		}
+		let split = self
+			.bytes
+			.iter()
+			.rposition(|byte| *byte == self.style.separator())?;
+		let bytes = if split < root.len() {
+			root
+		} else {
+			self.bytes[..split].to_vec()
+		};
+		Some(Self {
+			bytes,
+			style: self.style,
+		})
+	}
+
+	/// `name` inside this folder. `name` must be a single name.
+	pub fn join(&self, name: &str) -> Result<Self, PathError> {
+		let name_bytes = name.as_bytes();
+		if matches!(name, "" | "." | "..")
+			|| name_bytes.iter().any(|byte| self.style.is_separator(*byte))
+		{
+			return Err(PathError::NotAName(name.to_string()));
+		}
+		if name_bytes.contains(&0) {
+			return Err(PathError::NulByte);
+		}
+		check_name(name_bytes, self.style)?;
+		let mut bytes = self.bytes.clone();
+		if !bytes.ends_with(&[self.style.separator()]) {
+			bytes.push(self.style.separator());
+		}
+		bytes.extend_from_slice(name_bytes);
+		if bytes.len() > MAX_PATH_BYTES {
+			return Err(PathError::TooLong { len: bytes.len() });
+		}
+		Ok(Self {
+			bytes,
+			style: self.style,
+		})
+	}
+}
+
+fn wire_bytes(path: &WirePath) -> &[u8] {
+	match path {
+		WirePath::Display(text) => text.as_bytes(),
+		WirePath::Raw(bytes) => bytes,
+	}
+}
+
+impl fmt::Display for SafePath {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&String::from_utf8_lossy(&self.bytes))
+	}
+}
+
+impl From<SafePath> for WirePath {
+	fn from(path: SafePath) -> Self {
+		path.to_wire()
+	}
+}
+
+impl From<&SafePath> for WirePath {
+	fn from(path: &SafePath) -> Self {
+		path.to_wire()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::state::{FLAG_READ, FLAG_SEARCH, FolderRule, State};
+
+	fn unix(path: &str) -> Result<String, PathError> {
+		SafePath::from_bytes(path.as_bytes(), PathStyle::Unix).map(|path| path.to_string())
+	}
+
+	fn windows(path: &str) -> Result<String, PathError> {
+		SafePath::from_bytes(path.as_bytes(), PathStyle::Windows).map(|path| path.to_string())
+	}
+
+	#[test]
+	fn valid_paths_round_trip_unchanged() {
+		for path in [
+			"/",
+			"/home/bob",
+			"/tmp/a\\b",
+			"/srv/ünïcode/ファイル",
+			"/a b/c.",
+		] {
+			assert_eq!(unix(path).as_deref(), Ok(path));
+		}
+		for path in [
+			"C:\\",
+			"C:\\Users\\bob",
+			"d:\\x.txt",
+			"\\\\nas\\media",
+			"\\\\nas\\media\\films",
+		] {
+			assert_eq!(windows(path).as_deref(), Ok(path));
+		}
+		let raw = b"/tmp/\xff\xfe".to_vec();
+		let path = SafePath::from_wire_with_style(&WirePath::Raw(raw.clone()), PathStyle::Unix);
+		assert_eq!(path.unwrap().to_wire(), WirePath::Raw(raw));
+	}
+
+	#[test]
+	fn separators_and_dot_segments_are_normalized() {
+		assert_eq!(unix("//home///bob/./").as_deref(), Ok("/home/bob"));
+		assert_eq!(windows("C:/Users/bob/").as_deref(), Ok("C:\\Users\\bob"));
+		assert_eq!(windows("C:\\Users/./bob").as_deref(), Ok("C:\\Users\\bob"));
+		assert_eq!(windows("C:").as_deref(), Ok("C:\\"));
+		assert_eq!(windows("//nas/media/").as_deref(), Ok("\\\\nas\\media"));
+		assert_eq!(
+			SafePath::parse("  /home/bob \n", PathStyle::Unix).map(|p| p.to_string()),
+			Ok(String::from("/home/bob"))
+		);
+	}
+
+	#[test]
+	fn traversal_is_rejected_before_any_lookup() {
+		for path in [
+			"/srv/share/..",
+			"/srv/share/../etc",
+			"/..",
+			"/srv/./../x",
+			"/srv/share/a/../../b",
+		] {
+			assert_eq!(unix(path), Err(PathError::ParentSegment), "{path}");
+		}
+		for path in [
+			"C:\\share\\..\\Windows",
+			"C:/share/../x",
+			"C:\\share/..\\..",
+			"\\\\nas\\media\\..",
+		] {
+			assert_eq!(windows(path), Err(PathError::ParentSegment), "{path}");
+		}
+		// On Unix a backslash is part of the name, so this is one odd name.
+		assert_eq!(unix("/srv/..\\etc").as_deref(), Ok("/srv/..\\etc"));
+	}
+
+	#[test]
+	fn malformed_input_names_the_rule_it_broke() {
+		assert_eq!(unix(""), Err(PathError::Empty));
+		assert_eq!(unix("/tmp/a\0b"), Err(PathError::NulByte));
+		assert_eq!(unix("relative/path"), Err(PathError::NotAbsolute));
+		assert_eq!(unix("C:\\Users"), Err(PathError::NotAbsolute));
+		assert_eq!(windows("/home/bob"), Err(PathError::NotAbsolute));
+		assert_eq!(windows("C:Users"), Err(PathError::NotAbsolute));
+		assert_eq!(windows("\\\\nas"), Err(PathError::NotAbsolute));
+		assert_eq!(windows("\\\\?\\C:\\Windows"), Err(PathError::DevicePath));
+		assert_eq!(windows("//./PhysicalDrive0"), Err(PathError::DevicePath));
+
+		let long_name = "a".repeat(MAX_COMPONENT_BYTES + 1);
+		assert_eq!(
+			unix(&format!("/tmp/{long_name}")),
+			Err(PathError::ComponentTooLong {
+				len: MAX_COMPONENT_BYTES + 1
+			})
+		);
+		let long_path = "/a".repeat(MAX_PATH_BYTES / 2 + 1);
+		assert_eq!(
+			unix(&long_path),
+			Err(PathError::TooLong {
+				len: long_path.len()
+			})
+		);
+		assert_eq!(
+			PathError::ParentSegment.to_string(),
+			"path contains a `..` segment"
+		);
+		assert_eq!(
+			PathError::ReservedName(String::from("con.txt")).to_string(),
+			"path names `con.txt`, a reserved device name on Windows"
+		);
+	}
+
+	#[test]
+	fn windows_refuses_names_it_cannot_store() {
+		for name in ["CON", "con", "Nul.txt", "COM1", "lpt9.tar.gz", "AUX .log"] {
+			assert_eq!(
+				windows(&format!("C:\\share\\{name}")),
+				Err(PathError::ReservedName(name.to_string())),
+				"{name}"
+			);
+		}
+		assert_eq!(
+			windows("C:\\share\\CONSOLE").as_deref(),
+			Ok("C:\\share\\CONSOLE")
+		);
+		assert_eq!(
+			windows("C:\\share\\notes."),
+			Err(PathError::TrailingDotOrSpace(String::from("notes.")))
+		);
+		assert_eq!(
+			windows("C:\\share\\notes "),
+			Err(PathError::TrailingDotOrSpace(String::from("notes ")))
+		);
+		assert_eq!(
+			windows("C:\\share\\a:b"),
+			Err(PathError::InvalidCharacter {
+				name: String::from("a:b"),
+				ch: ':'
+			})
+		);
+		assert_eq!(
+			windows("C:\\share\\a\tb"),
+			Err(PathError::InvalidCharacter {
+				name: String::from("a\tb"),
+				ch: '\t'
+			})
+		);
+		// The same names are fine on Unix.
+		assert_eq!(
+			unix("/share/CON/notes./a:b").as_deref(),
+			Ok("/share/CON/notes./a:b")
+		);
+	}
+
+	#[test]
+	fn join_and_parent_follow_the_path_style() {
+		let win = SafePath::from_bytes(b"C:\\", PathStyle::Windows).unwrap();
+		let users = win.join("Users").unwrap();
+		assert_eq!(users.to_string(), "C:\\Users");
+		assert_eq!(users.parent(), Some(win.clone()));
+		assert!(win.is_root());
+		assert_eq!(
+			users.join("a\\b"),
+			Err(PathError::NotAName(String::from("a\\b")))
+		);
+		assert_eq!(
+			users.join(".."),
+			Err(PathError::NotAName(String::from("..")))
+		);
+		assert_eq!(
+			users.join("CON"),
+			Err(PathError::ReservedName(String::from("CON")))
+		);
+
+		let share = SafePath::from_bytes(b"\\\\nas\\media\\films", PathStyle::Windows).unwrap();
+		let root = share.parent().unwrap();
+		assert_eq!(root.to_string(), "\\\\nas\\media");
+		assert!(root.is_root());
+
+		let home = SafePath::from_bytes(b"/home", PathStyle::Unix).unwrap();
+		assert_eq!(home.join("a\\b").unwrap().to_string(), "/home/a\\b");
+		assert_eq!(home.parent().unwrap().to_string(), "/");
+		assert_eq!(
+			home.join("a/b"),
+			Err(PathError::NotAName(String::from("a/b")))
+		);
+	}
+
+	#[test]
+	fn remote_paths_take_the_style_they_are_written_in() {
+		let win = SafePath::remote(&WirePath::from("C:/Users")).unwrap();
+		assert_eq!(
+			(win.style(), win.to_string().as_str()),
+			(PathStyle::Windows, "C:\\Users")
+		);
+		let unix = SafePath::remote(&WirePath::from("/home/bob/")).unwrap();
+		assert_eq!(
+			(unix.style(), unix.to_string().as_str()),
+			(PathStyle::Unix, "/home/bob")
+		);
+		assert_eq!(
+			SafePath::remote(&WirePath::from("\\\\?\\C:\\x")),
+			Err(PathError::DevicePath)
+		);
+	}
+
+	#[test]
+	fn adversarial_paths_never_reach_the_permission_check() {
+		let mut state = State::default();
+		state.add_shared_folder(FolderRule::new(
+			PathBuf::from("/srv/share"),
+			FLAG_READ | FLAG_SEARCH,
+		));
+		let allowed = |input: &str| {
+			SafePath::from_bytes(input.as_bytes(), PathStyle::Unix).is_ok_and(|path| {
+				state.has_fs_access(state.me, &path.to_local().unwrap(), FLAG_READ)
+			})
+		};
+
+		// Read component by component these sit under the share, but they
+		// resolve outside it.
+		for input in [
+			"/srv/share/../secret",
+			"/srv/share/./../../etc/passwd",
+			"/srv/share/a/../../secret",
+		] {
+			assert!(state.has_fs_access(state.me, Path::new(input), FLAG_READ));
+			assert!(!allowed(input), "{input}");
+		}
+		for input in [
+			"/srv/share\\..\\secret",
+			"/srv/share/\0/../secret",
+			"/srv/shared/x",
+			"/srv",
+			"srv/share/x",
+		] {
+			assert!(!allowed(input), "{input}");
+		}
+		for input in [
+			"/srv/share",
+			"/srv/share/",
+			"//srv//share/./photos/",
+			"/srv/share/..hidden",
+		] {
+			assert!(allowed(input), "{input}");
+		}
+	}
+}
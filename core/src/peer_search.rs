@@ -0,0 +1,266 @@
+//! Index searches run by other peers. A peer answering `SearchFiles`
+//! searches only the files on itself and drops every result outside the
+//! folders the asker may search, so no result reveals a path that is not
+//! shared. Counts and the mime type list stay out of the answer for the
+//! same reason, which means a page can come back shorter than asked.
+//!
+//! Searching all connected peers asks each for the same page, waits at most
+//! [`PEER_SEARCH_TIMEOUT`] for each, and merges the pages in an order that
+//! does not depend on which peer answered first.
+
+use crate::db::{FileSearchResult, NodeID, SearchFilesArgs, SearchSortBy};
+use crate::index::PeerAccess;
+use crate::natural_sort::natural_cmp;
+use crate::state::FLAG_SEARCH;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use std::cmp::Ordering;
+use std::path::Path;
+use std::time::Duration;
+
+/// Results a peer returns per page at most, whatever it was asked for.
+pub const PEER_SEARCH_MAX_PAGE_SIZE: usize = 200;
+/// How long a search of all connected peers waits for each of them.
+pub const PEER_SEARCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A search result and the peer whose index it came from.
+#[derive(Clone, Debug)]
+pub struct PeerSearchHit {
+	pub peer: PeerId,
+	pub result: FileSearchResult,
+}
+
+/// A peer left out of a search, and why.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedPeer {
+	pub peer: PeerId,
+	pub reason: String,
+}
+
+/// Merged results of searching several peers.
+#[derive(Clone, Debug, Default)]
+pub struct PeerSearch {
+	pub hits: Vec<PeerSearchHit>,
+	/// Peers that failed, timed out or do not support the search.
+	pub skipped: Vec<SkippedPeer>,
+}
+
+/// `args` as this node runs them for another peer: only files on this node
+/// (`local`), and no more than [`PEER_SEARCH_MAX_PAGE_SIZE`] per page.
+pub(crate) fn scope_for_peer(mut args: SearchFilesArgs, local: Option<NodeID>) -> SearchFilesArgs {
+	args.node_id = local;
+	args.page_size = args.page_size.min(PEER_SEARCH_MAX_PAGE_SIZE);
+	args
+}
+
+/// The results `peer` may see as of `now`: those under a folder it may
+/// search that no pattern hides.
+pub(crate) fn visible_results(
+	access: &impl PeerAccess,
+	peer: PeerId,
+	results: Vec<FileSearchResult>,
+	now: DateTime<Utc>,
+) -> Vec<FileSearchResult> {
+	let hidden = access.hidden(peer, now);
+	results
+		.into_iter()
+		.filter(|result| {
+			!result.path.is_empty()
+				&& access.may_access(peer, Path::new(&result.path), FLAG_SEARCH, now)
+				&& !hidden.hides(Path::new(&result.path), false)
+		})
+		.collect()
+}
+
+/// Order of merged results: the index order, `(latest_datetime, hash)` or
+/// `(name, hash)`, then the peer.
+fn hit_order(
+	a: &PeerSearchHit,
+	b: &PeerSearchHit,
+	sort_by: SearchSortBy,
+	sort_desc: bool,
+) -> Ordering {
+	let by_key = match sort_by {
+		SearchSortBy::Date => a.result.latest_datetime.cmp(&b.result.latest_datetime),
+		SearchSortBy::Name => natural_cmp(&a.result.name, &b.result.name),
+	};
+	let by_index = by_key.then_with(|| a.result.hash.cmp(&b.result.hash));
+	let by_index = if sort_desc {
+		by_index.reverse()
+	} else {
+		by_index
+	};
+	by_index.then_with(|| a.peer.cmp(&b.peer))
+}
+
+/// Merges one page from each peer into a single page.
+pub(crate) fn merge(
+	pages: Vec<(PeerId, Result<Vec<FileSearchResult>>)>,
+	sort_by: SearchSortBy,
+	sort_desc: bool,
+) -> PeerSearch {
+	let mut search = PeerSearch::default();
+	for (peer, page) in pages {
+		match page {
+			Ok(results) => search.hits.extend(
+				results
+					.into_iter()
+					.map(|result| PeerSearchHit { peer, result }),
+			),
+			Err(err) => search.skipped.push(SkippedPeer {
+				peer,
+				reason: err.to_string(),
+			}),
+		}
+	}
+	search
+		.hits
+		.sort_by(|a, b| hit_order(a, b, sort_by, sort_desc));
+	search.skipped.sort_by_key(|skipped| skipped.peer);
+	search
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::state::{FLAG_READ, FolderRule, Permission, Rule, State};
+	use anyhow::anyhow;
+	use std::path::PathBuf;
+
+	fn result(path: &str, hash: u8, latest: &str) -> FileSearchResult {
+		FileSearchResult {
+			hash: vec![hash],
+			name: path.rsplit('/').next().unwrap_or(path).to_string(),
+			path: path.to_string(),
+			node_id: vec![0; 16],
+			size: 1,
+			mime_type: None,
+			replicas: 1,
+			first_datetime: None,
+			latest_datetime: Some(latest.to_string()),
+			duration_ms: None,
+			width: None,
+			height: None,
+			codec: None,
+			deleted_at: None,
+		}
+	}
+
+	#[test]
+	fn results_outside_searchable_folders_are_omitted() {
+		let mut state = State::default();
+		let peer = PeerId::random();
+		state.add_shared_folder(FolderRule::new(
+			PathBuf::from("/srv"),
+			FLAG_READ | FLAG_SEARCH,
+		));
+		state.set_peer_permissions(
+			peer,
+			vec![Permission::new(Rule::Folder(FolderRule::new(
+				PathBuf::from("/srv/photos"),
+				FLAG_SEARCH,
+			)))],
+		);
+		let visible = visible_results(
+			&state,
+			peer,
+			vec![
+				result("/srv/photos/cat.jpg", 1, "2025-01-01"),
+				result("/srv/private/notes.txt", 2, "2025-01-01"),
+				result("/etc/passwd", 3, "2025-01-01"),
+				result("", 4, "2025-01-01"),
+			],
+			Utc::now(),
+		);
+		assert_eq!(
+			visible.iter().map(|r| r.path.as_str()).collect::<Vec<_>>(),
+			vec!["/srv/photos/cat.jpg"]
+		);
+	}
+
+	#[test]
+	fn peer_searches_stay_on_the_answering_node_and_under_the_page_cap() {
+		let args = SearchFilesArgs {
+			node_id: Some([9; 16]),
+			page_size: 10_000,
+			..Default::default()
+		};
+		let scoped = scope_for_peer(args, Some([1; 16]));
+		assert_eq!(scoped.node_id, Some([1; 16]));
+		assert_eq!(scoped.page_size, PEER_SEARCH_MAX_PAGE_SIZE);
+	}
+
+	#[test]
+	fn merged_order_does_not_depend_on_answer_order() {
+		let (a, b, c) = (PeerId::random(), PeerId::random(), PeerId::random());
+		let pages = || {
+			vec![
+				(
+					a,
+					Ok(vec![
+						result("/a/new", 1, "2025-03-01"),
+						result("/a/old", 2, "2025-01-01"),
+					]),
+				),
+				(b, Ok(vec![result("/b/mid", 3, "2025-02-01")])),
+				(
+					c,
+					Err(anyhow!("peer does not support the SearchFiles request")),
+				),
+				(b, Ok(vec![result("/b/same", 1, "2025-03-01")])),
+			]
+		};
+		let forward = merge(pages(), SearchSortBy::Date, true);
+		let backward = merge(
+			pages().into_iter().rev().collect(),
+			SearchSortBy::Date,
+			true,
+		);
+		let paths = |search: &PeerSearch| {
+			search
+				.hits
+				.iter()
+				.map(|hit| (hit.peer, hit.result.path.clone()))
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(paths(&forward), paths(&backward));
+		assert_eq!(
+			forward
+				.hits
+				.iter()
+				.map(|hit| hit.result.latest_datetime.as_deref().unwrap())
+				.collect::<Vec<_>>(),
+			vec!["2025-03-01", "2025-03-01", "2025-02-01", "2025-01-01"]
+		);
+		assert_eq!(forward.skipped.len(), 1);
+		assert_eq!(forward.skipped[0].peer, c);
+	}
+
+	#[test]
+	fn name_sorted_pages_merge_in_natural_order() {
+		let (a, b) = (PeerId::random(), PeerId::random());
+		let search = merge(
+			vec![
+				(
+					a,
+					Ok(vec![
+						result("/a/track 2.flac", 1, "2025-03-01"),
+						result("/a/track 10.flac", 2, "2025-01-01"),
+					]),
+				),
+				(b, Ok(vec![result("/b/Track 3.flac", 3, "2025-02-01")])),
+			],
+			SearchSortBy::Name,
+			false,
+		);
+		assert_eq!(
+			search
+				.hits
+				.iter()
+				.map(|hit| hit.result.name.as_str())
+				.collect::<Vec<_>>(),
+			vec!["track 2.flac", "Track 3.flac", "track 10.flac"]
+		);
+	}
+}
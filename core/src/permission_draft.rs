@@ -0,0 +1,401 @@
+//! Peer permissions as an editor holds them: one [`RuleDraft`] per row, with
+//! the flags as checkboxes and the folder as typed. The web UI and the HTTP
+//! API both turn drafts into permissions through [`PermissionDraft::validate`],
+//! so whatever one of them accepts the other accepts too, and a refused draft
+//! names each row and field to fix rather than failing as a whole.
+
+use crate::state::{
+	FLAG_EXECUTE, FLAG_PAIRED, FLAG_PREVIEW, FLAG_QUARANTINE, FLAG_READ, FLAG_SEARCH, FLAG_WRITE,
+	FolderRule, Permission, Rule, pattern_error,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DraftKind {
+	#[default]
+	Folder,
+	Owner,
+	Inbox,
+}
+
+/// One rule being edited. Only folder rules use the path and the flags.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuleDraft {
+	pub kind: DraftKind,
+	pub path: String,
+	pub read: bool,
+	pub write: bool,
+	pub search: bool,
+	pub execute: bool,
+	pub preview: bool,
+	/// Writes wait in the review queue until the owner accepts them.
+	pub quarantine: bool,
+	/// Granted by pairing rather than by the owner. Kept through edits so
+	/// the rule stays marked.
+	pub paired: bool,
+	/// Names the peer never sees under the folder, see
+	/// [`FolderRule::hides`]. Blank entries are dropped.
+	pub deny_patterns: Vec<String>,
+	/// When set, the only files the peer sees under the folder.
+	pub allow_patterns: Vec<String>,
+	/// Unix seconds after which the rule lapses.
+	pub expires_at: Option<i64>,
+}
+
+impl RuleDraft {
+	/// A folder rule as the shared folder picker offers them: `"write"` for
+	/// read and write, `"preview"` for previews only, anything else for
+	/// read only. All of them allow search.
+	pub fn with_access(path: impl Into<String>, access: &str) -> Self {
+		let preview = access == "preview";
+		Self {
+			path: path.into(),
+			read: !preview,
+			write: access == "write",
+			search: true,
+			preview,
+			..Self::default()
+		}
+	}
+
+	pub fn from_permission(permission: &Permission) -> Self {
+		let draft = Self {
+			expires_at: permission.expires_at(),
+			..Self::default()
+		};
+		match permission.rule() {
+			Rule::Owner => Self {
+				kind: DraftKind::Owner,
+				..draft
+			},
+			Rule::Inbox => Self {
+				kind: DraftKind::Inbox,
+				..draft
+			},
+			Rule::Folder(rule) => Self {
+				kind: DraftKind::Folder,
+				path: rule.path().display().to_string(),
+				read: rule.can_read(),
+				write: rule.can_write(),
+				search: rule.can_search(),
+				execute: rule.can_execute(),
+				preview: rule.flags() & FLAG_PREVIEW != 0,
+				quarantine: rule.quarantines(),
+				paired: rule.flags() & FLAG_PAIRED != 0,
+				deny_patterns: rule.deny_patterns().to_vec(),
+				allow_patterns: rule.allow_patterns().to_vec(),
+				..draft
+			},
+		}
+	}
+
+	fn flags(&self) -> u8 {
+		[
+			(self.read, FLAG_READ),
+			(self.write, FLAG_WRITE),
+			(self.search, FLAG_SEARCH),
+			(self.execute, FLAG_EXECUTE),
+			(self.preview, FLAG_PREVIEW),
+			(self.quarantine, FLAG_QUARANTINE),
+			(self.paired, FLAG_PAIRED),
+		]
+		.into_iter()
+		.filter(|(set, _)| *set)
+		.fold(0, |flags, (_, flag)| flags | flag)
+	}
+}
+
+/// What is wrong with one field of one row of a draft.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+	/// Row of the rule, counted from zero.
+	pub index: usize,
+	pub field: &'static str,
+	pub message: String,
+}
+
+impl FieldError {
+	fn new(index: usize, field: &'static str, message: impl Into<String>) -> Self {
+		Self {
+			index,
+			field,
+			message: message.into(),
+		}
+	}
+}
+
+/// A draft that failed validation, for callers that pass it on as an error.
+#[derive(Clone, Debug)]
+pub struct DraftRejected {
+	pub errors: Vec<FieldError>,
+}
+
+impl std::fmt::Display for DraftRejected {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self.errors.as_slice() {
+			[] => write!(f, "the permissions are not valid"),
+			[only] => write!(f, "{}", only.message),
+			[first, rest @ ..] => write!(f, "{} (and {} more)", first.message, rest.len()),
+		}
+	}
+}
+
+impl std::error::Error for DraftRejected {}
+
+/// A peer's permissions while they are edited.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PermissionDraft {
+	pub rules: Vec<RuleDraft>,
+}
+
+/// The patterns of `raw` trimmed and without blanks, or what is wrong with
+/// the first bad one.
+pub fn parse_patterns(raw: &[String]) -> Result<Vec<String>, String> {
+	let mut patterns = Vec::new();
+	for pattern in raw.iter().map(|pattern| pattern.trim()) {
+		if pattern.is_empty() {
+			continue;
+		}
+		if let Some(problem) = pattern_error(pattern) {
+			return Err(format!("{pattern}: {problem}"));
+		}
+		patterns.push(pattern.to_string());
+	}
+	Ok(patterns)
+}
+
+/// The folder `raw` names, made absolute and without `.` components.
+fn folder_path(raw: &str) -> Result<PathBuf, String> {
+	let trimmed = raw.trim();
+	if trimmed.is_empty() {
+		return Err("Path is required".to_string());
+	}
+	let path = Path::new(trimmed);
+	if !path.is_absolute() {
+		return Err(format!("{trimmed} is not an absolute path"));
+	}
+	if path.components().any(|c| c == Component::ParentDir) {
+		return Err(format!("{trimmed} must not go up with .."));
+	}
+	let normalized: PathBuf = path.components().collect();
+	if !normalized.is_dir() {
+		return Err(format!("{trimmed} is not a folder on this device"));
+	}
+	Ok(normalized)
+}
+
+impl PermissionDraft {
+	pub fn from_permissions(permissions: &[Permission]) -> Self {
+		Self {
+			rules: permissions.iter().map(RuleDraft::from_permission).collect(),
+		}
+	}
+
+	/// The permissions the draft stands for, or every problem found in it,
+	/// in row order.
+	pub fn validate(&self) -> Result<Vec<Permission>, Vec<FieldError>> {
+		let now = Utc::now().timestamp();
+		let mut permissions: Vec<Permission> = Vec::with_capacity(self.rules.len());
+		let mut errors = Vec::new();
+		for (index, draft) in self.rules.iter().enumerate() {
+			let row_errors = errors.len();
+			if draft.expires_at.is_some_and(|at| at <= now) {
+				errors.push(FieldError::new(
+					index,
+					"expires_at",
+					"Expiry is in the past",
+				));
+			}
+			let rule = match draft.kind {
+				DraftKind::Owner => Rule::Owner,
+				DraftKind::Inbox => Rule::Inbox,
+				DraftKind::Folder => {
+					let path = folder_path(&draft.path).unwrap_or_else(|message| {
+						errors.push(FieldError::new(index, "path", message));
+						PathBuf::new()
+					});
+					if !(draft.read || draft.write || draft.preview) {
+						errors.push(FieldError::new(
+							index,
+							"read",
+							"Allow at least reading, writing or previews",
+						));
+					}
+					if draft.quarantine && !draft.write {
+						errors.push(FieldError::new(
+							index,
+							"quarantine",
+							"Reviewing writes needs write access",
+						));
+					}
+					let mut pattern_field = |field, raw| {
+						parse_patterns(raw).unwrap_or_else(|message| {
+							errors.push(FieldError::new(index, field, message));
+							Vec::new()
+						})
+					};
+					let deny = pattern_field("deny_patterns", &draft.deny_patterns);
+					let allow = pattern_field("allow_patterns", &draft.allow_patterns);
+					Rule::Folder(FolderRule::new(path, draft.flags()).with_patterns(deny, allow))
+				}
+			};
+			if errors.len() > row_errors {
+				continue;
+			}
+			let listed = permissions.iter().any(|p| match (p.rule(), &rule) {
+				(Rule::Folder(a), Rule::Folder(b)) => a.path() == b.path(),
+				(a, b) => a == b,
+			});
+			if listed {
+				let field = match draft.kind {
+					DraftKind::Folder => "path",
+					DraftKind::Owner | DraftKind::Inbox => "kind",
+				};
+				errors.push(FieldError::new(index, field, "Already listed above"));
+				continue;
+			}
+			permissions.push(Permission::with_expiration(rule, draft.expires_at));
+		}
+		if errors.is_empty() {
+			Ok(permissions)
+		} else {
+			Err(errors)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn folder(path: &Path, read: bool, write: bool) -> RuleDraft {
+		RuleDraft {
+			path: path.display().to_string(),
+			read,
+			write,
+			..RuleDraft::default()
+		}
+	}
+
+	fn fields(draft: &PermissionDraft) -> Vec<(usize, &'static str)> {
+		draft
+			.validate()
+			.unwrap_err()
+			.into_iter()
+			.map(|error| (error.index, error.field))
+			.collect()
+	}
+
+	#[test]
+	fn drafts_round_trip_through_permissions() {
+		let dir = std::env::temp_dir();
+		let permissions = vec![
+			Permission::new(Rule::Owner),
+			Permission::with_expiration(
+				Rule::Folder(
+					FolderRule::new(
+						dir.clone(),
+						FLAG_READ | FLAG_WRITE | FLAG_QUARANTINE | FLAG_PAIRED,
+					)
+					.with_patterns(vec![String::from("*.key")], vec![String::from("*.rs")]),
+				),
+				Some(Utc::now().timestamp() + 3600),
+			),
+			Permission::new(Rule::Inbox),
+		];
+		let draft = PermissionDraft::from_permissions(&permissions);
+		assert_eq!(draft.validate().unwrap(), permissions);
+	}
+
+	#[test]
+	fn the_picker_offers_preview_only_folders() {
+		let dir = std::env::temp_dir();
+		let permissions = PermissionDraft {
+			rules: vec![RuleDraft::with_access(dir.display().to_string(), "preview")],
+		}
+		.validate()
+		.unwrap();
+		let Rule::Folder(rule) = permissions[0].rule() else {
+			panic!("expected a folder rule");
+		};
+		assert_eq!(rule.flags(), FLAG_PREVIEW | FLAG_SEARCH);
+	}
+
+	#[test]
+	fn paths_are_trimmed_and_normalized() {
+		let dir = std::env::temp_dir();
+		let draft = PermissionDraft {
+			rules: vec![RuleDraft {
+				path: format!("  {}/./  ", dir.display()),
+				read: true,
+				..RuleDraft::default()
+			}],
+		};
+		let permissions = draft.validate().unwrap();
+		let Rule::Folder(rule) = permissions[0].rule() else {
+			panic!("expected a folder rule");
+		};
+		assert_eq!(rule.path(), dir.components().collect::<PathBuf>());
+	}
+
+	#[test]
+	fn every_bad_row_is_reported_by_field() {
+		let dir = std::env::temp_dir();
+		let draft = PermissionDraft {
+			rules: vec![
+				folder(&dir, true, false),
+				folder(Path::new("  "), true, false),
+				folder(Path::new("relative/folder"), true, false),
+				folder(&dir.join("..").join("x"), true, false),
+				folder(&dir.join("puppynet-no-such-folder"), true, false),
+				folder(&dir, false, false),
+				RuleDraft {
+					quarantine: true,
+					..folder(&dir, true, false)
+				},
+				folder(&dir, false, true),
+				RuleDraft {
+					deny_patterns: vec![String::from("*.pem"), String::from("/etc/*")],
+					allow_patterns: vec![String::from("[ab].txt")],
+					..folder(&dir, true, false)
+				},
+				RuleDraft {
+					kind: DraftKind::Owner,
+					expires_at: Some(1),
+					..RuleDraft::default()
+				},
+			],
+		};
+		assert_eq!(
+			fields(&draft),
+			vec![
+				(1, "path"),
+				(2, "path"),
+				(3, "path"),
+				(4, "path"),
+				(5, "read"),
+				(6, "quarantine"),
+				(7, "path"),
+				(8, "deny_patterns"),
+				(8, "allow_patterns"),
+				(9, "expires_at"),
+			]
+		);
+	}
+
+	#[test]
+	fn owner_and_inbox_may_be_listed_once() {
+		let owner = RuleDraft {
+			kind: DraftKind::Owner,
+			..RuleDraft::default()
+		};
+		let draft = PermissionDraft {
+			rules: vec![owner.clone(), owner],
+		};
+		assert_eq!(fields(&draft), vec![(1, "kind")]);
+	}
+}
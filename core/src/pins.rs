@@ -0,0 +1,1120 @@
+//! Remote folders pinned for offline use. A pin mirrors a folder on a peer
+//! into a local folder: while the peer is connected and the pin's interval
+//! has passed, the remote tree is listed, new and changed files are fetched
+//! through the read path and, unless turned off, files removed on the peer
+//! are removed locally. Each fetched file goes into the pin's manifest as
+//! soon as it is in place, so an interrupted sync does not fetch it again,
+//! and a half-fetched file resumes from its `.puppynet-part`.
+//!
+//! Names in the listing come from the peer. Each must be one plain path
+//! component, and nothing is written through a symlink inside the mirror,
+//! so a sync never touches files outside the local folder.
+
+use crate::activity_window::{ActivityKind, ActivityWindow};
+use crate::app::{Command, ReadFileCmd, peer_to_node_id};
+use crate::db::{
+	finish_pin_sync, indexed_hash, load_pin_files, load_pins, remove_pin_file, save_pin_file,
+};
+use crate::db_pool::Db;
+use crate::file_read::ChunkReader;
+use crate::maintenance::MaintenanceGate;
+use crate::p2p::{DirEntry, WirePath};
+use crate::path::SafePath;
+use crate::power::PowerGate;
+use crate::types::FileChunk;
+use crate::wake::{self, WAKE_TIMEOUT, wait_until_connected};
+use anyhow::{Result, anyhow, bail};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+/// How often pins are checked for a due sync.
+pub(crate) const PIN_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+pub(crate) const MIN_PIN_INTERVAL: Duration = Duration::from_secs(60);
+const PIN_CHUNK_SIZE: u64 = 1024 * 1024;
+/// Folders nested deeper than this under the pinned one are not mirrored.
+const MAX_PIN_DEPTH: usize = 64;
+const PART_SUFFIX: &str = ".puppynet-part";
+
+/// How a pinned folder is kept in sync.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PinOptions {
+	/// Time between syncs while the peer stays connected.
+	pub interval: Duration,
+	/// Bytes per second; unlimited when unset.
+	pub bandwidth_limit: Option<u64>,
+	/// Remove local copies of files that were removed on the peer.
+	pub delete_removed: bool,
+	/// Wake the peer over the LAN when a sync is due and it is away,
+	/// instead of waiting for it to connect.
+	pub wake_if_needed: bool,
+}
+
+impl Default for PinOptions {
+	fn default() -> Self {
+		Self {
+			interval: Duration::from_secs(15 * 60),
+			bandwidth_limit: None,
+			delete_removed: true,
+			wake_if_needed: false,
+		}
+	}
+}
+
+/// A pinned remote folder and how its syncing went.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PinStatus {
+	pub id: u64,
+	pub peer: String,
+	pub remote_path: String,
+	pub local_dest: String,
+	pub interval_secs: u64,
+	pub bandwidth_limit: Option<u64>,
+	pub delete_removed: bool,
+	pub wake_if_needed: bool,
+	pub paused: bool,
+	/// A sync is running right now.
+	pub syncing: bool,
+	pub created_at: DateTime<Utc>,
+	/// End of the last sync that got through the whole remote tree.
+	pub last_sync_at: Option<DateTime<Utc>>,
+	/// Files left to fetch, as of the running or last sync.
+	pub files_pending: u64,
+	/// Bytes fetched since the folder was pinned.
+	pub bytes_transferred: u64,
+	pub last_error: Option<String>,
+}
+
+impl PinStatus {
+	pub fn options(&self) -> PinOptions {
+		PinOptions {
+			interval: Duration::from_secs(self.interval_secs),
+			bandwidth_limit: self.bandwidth_limit,
+			delete_removed: self.delete_removed,
+			wake_if_needed: self.wake_if_needed,
+		}
+	}
+}
+
+/// A file the mirror holds, as it was on the peer when fetched.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PinnedFile {
+	pub(crate) size: u64,
+	/// Remote modification time, in seconds.
+	pub(crate) modified_at: Option<i64>,
+	/// Blake3 hash of the content, as the index stores it.
+	pub(crate) hash: Vec<u8>,
+}
+
+/// `name` if it is a single plain path component here. Separators of
+/// either style are refused everywhere so the mirror reads the same on
+/// every platform.
+pub(crate) fn safe_entry_name(name: &str) -> Option<&str> {
+	if name.contains(['/', '\\', '\0']) {
+		return None;
+	}
+	let mut components = Path::new(name).components();
+	match (components.next(), components.next()) {
+		(Some(Component::Normal(_)), None) => Some(name),
+		_ => None,
+	}
+}
+
+fn is_symlink(path: &Path) -> bool {
+	std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_symlink())
+}
+
+/// Where the mirror in `dest` keeps `rel`, a `/`-separated path of entry
+/// names. `None` when a name is not a plain component or something on the
+/// way is a symlink, which could lead outside `dest`.
+pub(crate) fn mirror_path(dest: &Path, rel: &str) -> Option<PathBuf> {
+	let mut path = dest.to_path_buf();
+	for name in rel.split('/') {
+		path.push(safe_entry_name(name)?);
+		if is_symlink(&path) {
+			return None;
+		}
+	}
+	Some(path)
+}
+
+/// `name` inside the peer folder `parent`, in the separator style `parent`
+/// uses.
+pub(crate) fn remote_child(parent: &str, name: &str) -> String {
+	let separator = if parent.contains('\\') && !parent.contains('/') {
+		'\\'
+	} else {
+		'/'
+	};
+	format!("{}{separator}{name}", parent.trim_end_matches(separator))
+}
+
+fn part_path(local: &Path) -> PathBuf {
+	let mut part = local.as_os_str().to_os_string();
+	part.push(PART_SUFFIX);
+	PathBuf::from(part)
+}
+
+/// How long to wait after fetching `bytes` in `elapsed` to stay under
+/// `limit` bytes per second.
+pub(crate) fn throttle_delay(bytes: u64, limit: u64, elapsed: Duration) -> Duration {
+	Duration::from_secs_f64(bytes as f64 / limit.max(1) as f64).saturating_sub(elapsed)
+}
+
+/// Peer access a sync needs. Implemented over the command channel; tests
+/// use an in-memory peer.
+#[async_trait]
+pub(crate) trait PinSource: Send + Sync {
+	async fn is_connected(&self, peer: PeerId) -> bool;
+	async fn list_dir(&self, peer: PeerId, path: &str) -> Result<Vec<DirEntry>>;
+	async fn read_file(
+		&self,
+		peer: PeerId,
+		path: &str,
+		offset: u64,
+		length: u64,
+	) -> Result<FileChunk>;
+
+	/// How far `peer`'s timestamps may be from ours for the same moment.
+	async fn clock_tolerance(&self, _peer: PeerId) -> chrono::Duration {
+		chrono::Duration::zero()
+	}
+
+	/// True while `path` is in a shared folder whose mount is away; a
+	/// sync into it waits until the mount is back.
+	async fn share_offline(&self, _path: &Path) -> bool {
+		false
+	}
+}
+
+#[async_trait]
+impl PinSource for UnboundedSender<Command> {
+	async fn is_connected(&self, peer: PeerId) -> bool {
+		let (tx, rx) = oneshot::channel();
+		if self.send(Command::GetState { tx }).is_err() {
+			return false;
+		}
+		rx.await
+			.is_ok_and(|state| state.connections.iter().any(|conn| conn.peer_id == peer))
+	}
+
+	async fn list_dir(&self, peer: PeerId, path: &str) -> Result<Vec<DirEntry>> {
+		let (tx, rx) = oneshot::channel();
+		self.send(Command::ListDir {
+			peer,
+			path: SafePath::remote(&WirePath::from(path))?,
+			tx,
+		})
+		.map_err(|e| anyhow!("failed to send ListDir command: {e}"))?;
+		let listing = rx
+			.await
+			.map_err(|e| anyhow!("ListDir response channel closed: {e}"))??;
+		Ok(listing.entries)
+	}
+
+	async fn read_file(
+		&self,
+		peer: PeerId,
+		path: &str,
+		offset: u64,
+		length: u64,
+	) -> Result<FileChunk> {
+		let (tx, rx) = oneshot::channel();
+		self.send(Command::ReadFile(ReadFileCmd {
+			peer_id: peer,
+			path: SafePath::remote(&WirePath::from(path))?,
+			offset,
+			length: Some(length),
+			sparse: false,
+			tx,
+		}))
+		.map_err(|e| anyhow!("failed to send ReadFile command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ReadFile response channel closed: {e}"))?
+	}
+
+	async fn clock_tolerance(&self, peer: PeerId) -> chrono::Duration {
+		let (tx, rx) = oneshot::channel();
+		if self.send(Command::GetState { tx }).is_err() {
+			return chrono::Duration::zero();
+		}
+		rx.await
+			.ok()
+			.and_then(|state| {
+				state
+					.clock_offsets
+					.get(&peer)
+					.map(|offset| offset.tolerance())
+			})
+			.unwrap_or_else(chrono::Duration::zero)
+	}
+
+	async fn share_offline(&self, path: &Path) -> bool {
+		let (tx, rx) = oneshot::channel();
+		if self.send(Command::GetState { tx }).is_err() {
+			return false;
+		}
+		rx.await
+			.is_ok_and(|state| state.unavailable_share(path).is_some())
+	}
+}
+
+struct RunningSync {
+	cancel: Arc<AtomicBool>,
+	files_pending: u64,
+}
+
+/// Syncs in flight and when each pin was last tried, which only lives as
+/// long as the process.
+#[derive(Default)]
+pub(crate) struct PinRuns {
+	running: Mutex<HashMap<u64, RunningSync>>,
+	attempted: Mutex<HashMap<u64, Instant>>,
+}
+
+impl PinRuns {
+	fn due(&self, pin: &PinStatus, now: Instant) -> bool {
+		!pin.paused
+			&& !self.running.lock().unwrap().contains_key(&pin.id)
+			&& self
+				.attempted
+				.lock()
+				.unwrap()
+				.get(&pin.id)
+				.is_none_or(|at| now.duration_since(*at) >= pin.options().interval)
+	}
+
+	/// Marks `id` as syncing, unless it already is.
+	fn begin(&self, id: u64, now: Instant) -> Option<Arc<AtomicBool>> {
+		let mut running = self.running.lock().unwrap();
+		if running.contains_key(&id) {
+			return None;
+		}
+		let cancel = Arc::new(AtomicBool::new(false));
+		running.insert(
+			id,
+			RunningSync {
+				cancel: cancel.clone(),
+				files_pending: 0,
+			},
+		);
+		self.attempted.lock().unwrap().insert(id, now);
+		Some(cancel)
+	}
+
+	fn set_pending(&self, id: u64, files_pending: u64) {
+		if let Some(run) = self.running.lock().unwrap().get_mut(&id) {
+			run.files_pending = files_pending;
+		}
+	}
+
+	fn end(&self, id: u64) {
+		self.running.lock().unwrap().remove(&id);
+	}
+
+	/// Stops a running sync of `id` after its current chunk.
+	pub(crate) fn cancel(&self, id: u64) {
+		if let Some(run) = self.running.lock().unwrap().get(&id) {
+			run.cancel.store(true, Ordering::SeqCst);
+		}
+	}
+
+	/// Makes `id` due at the next check.
+	pub(crate) fn forget(&self, id: u64) {
+		self.attempted.lock().unwrap().remove(&id);
+	}
+
+	/// Fills in the live parts of `pin`.
+	pub(crate) fn apply(&self, pin: &mut PinStatus) {
+		if let Some(run) = self.running.lock().unwrap().get(&pin.id) {
+			pin.syncing = true;
+			pin.files_pending = run.files_pending;
+		}
+	}
+}
+
+struct RemoteFile {
+	/// `/`-separated path under the pinned folder.
+	rel: String,
+	path: String,
+	size: u64,
+	modified_at: Option<i64>,
+}
+
+/// Files under `root` on `peer`. Entries with names that aren't safe to
+/// mirror are left out and reported in the second list.
+async fn list_tree<S: PinSource + ?Sized>(
+	source: &S,
+	peer: PeerId,
+	root: &str,
+	cancel: &AtomicBool,
+) -> Result<(Vec<RemoteFile>, Vec<String>)> {
+	let mut files = Vec::new();
+	let mut skipped = Vec::new();
+	let mut folders = vec![(String::new(), root.to_string(), 0)];
+	while let Some((rel, path, depth)) = folders.pop() {
+		if cancel.load(Ordering::SeqCst) {
+			bail!("sync stopped");
+		}
+		for entry in source.list_dir(peer, &path).await? {
+			let name = Some(entry.name.as_str())
+				.filter(|_| !entry.has_undecodable_name())
+				.and_then(safe_entry_name);
+			let Some(name) = name else {
+				skipped.push(remote_child(&path, &entry.name));
+				continue;
+			};
+			let child_rel = if rel.is_empty() {
+				name.to_string()
+			} else {
+				format!("{rel}/{name}")
+			};
+			let child = remote_child(&path, name);
+			if !entry.is_dir {
+				files.push(RemoteFile {
+					rel: child_rel,
+					path: child,
+					size: entry.size,
+					modified_at: entry.modified_at.map(|at| at.timestamp()),
+				});
+			} else if depth + 1 < MAX_PIN_DEPTH {
+				folders.push((child_rel, child, depth + 1));
+			} else {
+				skipped.push(child);
+			}
+		}
+	}
+	Ok((files, skipped))
+}
+
+/// Whether the copy recorded as `known` is still at `local` and matches
+/// `remote` by size and either modification time or indexed hash.
+fn is_current(
+	remote: &RemoteFile,
+	known: Option<&PinnedFile>,
+	local: &Path,
+	remote_hash: Option<&[u8]>,
+) -> bool {
+	let Some(known) = known else {
+		return false;
+	};
+	let on_disk = std::fs::symlink_metadata(local)
+		.is_ok_and(|meta| meta.is_file() && meta.len() == known.size);
+	on_disk
+		&& known.size == remote.size
+		&& (known.modified_at == remote.modified_at || remote_hash == Some(known.hash.as_slice()))
+}
+
+/// Length and hash of what an earlier sync left in `part`, if it can be
+/// resumed: no longer than the remote file and written after the remote
+/// file last changed. The remote time comes from the peer's clock, so it
+/// only counts as later when it is by more than `tolerance`.
+async fn resumable_part(
+	part: &Path,
+	remote: &RemoteFile,
+	tolerance: chrono::Duration,
+) -> Option<(u64, blake3::Hasher)> {
+	let meta = tokio::fs::symlink_metadata(part).await.ok()?;
+	let written_at = DateTime::<Utc>::from(meta.modified().ok()?).timestamp();
+	if !meta.is_file()
+		|| meta.len() > remote.size
+		|| remote
+			.modified_at
+			.is_none_or(|at| at > written_at + tolerance.num_seconds())
+	{
+		return None;
+	}
+	let mut file = tokio::fs::File::open(part).await.ok()?;
+	let mut hasher = blake3::Hasher::new();
+	let mut buf = vec![0u8; PIN_CHUNK_SIZE as usize];
+	let mut len = 0u64;
+	loop {
+		let read = file.read(&mut buf).await.ok()?;
+		if read == 0 {
+			return Some((len, hasher));
+		}
+		hasher.update(&buf[..read]);
+		len += read as u64;
+	}
+}
+
+/// Fetches `remote` to `local` through a `.puppynet-part` file, calling
+/// `fetched` with the size of every chunk. Returns the content hash.
+async fn fetch_file<S: PinSource + ?Sized>(
+	source: &S,
+	peer: PeerId,
+	remote: &RemoteFile,
+	local: &Path,
+	bandwidth_limit: Option<u64>,
+	cancel: &AtomicBool,
+	mut fetched: impl FnMut(u64),
+) -> Result<Vec<u8>> {
+	if let Some(parent) = local.parent() {
+		tokio::fs::create_dir_all(parent).await?;
+	}
+	let part = part_path(local);
+	let tolerance = source.clock_tolerance(peer).await;
+	let (offset, mut hasher, mut file) = match resumable_part(&part, remote, tolerance).await {
+		Some((len, hasher)) => {
+			let file = tokio::fs::OpenOptions::new()
+				.append(true)
+				.open(&part)
+				.await?;
+			(len, hasher, file)
+		}
+		None => (
+			0,
+			blake3::Hasher::new(),
+			tokio::fs::File::create(&part).await?,
+		),
+	};
+	let started = Instant::now();
+	let mut sent = 0u64;
+	let path = remote.path.as_str();
+	let mut fetch = move |offset, length| source.read_file(peer, path, offset, length);
+	let mut reader = ChunkReader::new(offset, None, PIN_CHUNK_SIZE);
+	loop {
+		if cancel.load(Ordering::SeqCst) {
+			bail!("sync stopped");
+		}
+		let Some(chunk) = reader.next(&mut fetch).await? else {
+			break;
+		};
+		file.write_all(&chunk.data).await?;
+		hasher.update(&chunk.data);
+		sent += chunk.data.len() as u64;
+		fetched(chunk.data.len() as u64);
+		if chunk.eof {
+			break;
+		}
+		if let Some(limit) = bandwidth_limit {
+			tokio::time::sleep(throttle_delay(sent, limit, started.elapsed())).await;
+		}
+	}
+	file.flush().await?;
+	drop(file);
+	if is_symlink(local) {
+		bail!("refusing to replace symlink {}", local.display());
+	}
+	tokio::fs::rename(&part, local).await?;
+	Ok(hasher.finalize().as_bytes().to_vec())
+}
+
+/// What one sync did, for the pin's row.
+#[derive(Debug, Default)]
+pub(crate) struct PinSyncReport {
+	/// Set when the whole tree was listed and every file attempted.
+	pub(crate) finished_at: Option<DateTime<Utc>>,
+	pub(crate) files_pending: u64,
+	pub(crate) bytes: u64,
+	pub(crate) error: Option<String>,
+}
+
+fn lock(db: &Db) -> Result<std::sync::MutexGuard<'_, Connection>> {
+	db.lock().map_err(|err| anyhow!("db lock poisoned: {err}"))
+}
+
+/// Brings the mirror of `pin` up to date. Stops early when `cancel` is set,
+/// when the activity window closes in hard-stop mode, or when the power
+/// hold starts; files fetched so far are kept and the next run goes on.
+pub(crate) async fn sync_pin<S: PinSource + ?Sized>(
+	source: &S,
+	db: &Db,
+	window: &Mutex<ActivityWindow>,
+	power: &Mutex<PowerGate>,
+	runs: &PinRuns,
+	pin: &PinStatus,
+	cancel: &AtomicBool,
+) -> PinSyncReport {
+	let mut report = PinSyncReport::default();
+	let held = || {
+		{
+			let window = window.lock().unwrap();
+			if window.hard_stop && !window.allows(ActivityKind::Sync, Utc::now()) {
+				tracing::info!(
+					"activity window closed; stopping sync of {}",
+					pin.remote_path
+				);
+				return true;
+			}
+		}
+		if power.lock().unwrap().holds() {
+			tracing::info!("on battery; pausing sync of {}", pin.remote_path);
+			return true;
+		}
+		false
+	};
+	if let Err(err) = sync_files(source, db, runs, pin, cancel, &held, &mut report).await
+		&& !cancel.load(Ordering::SeqCst)
+	{
+		report.error = Some(format!("{err:#}"));
+	}
+	report
+}
+
+async fn sync_files<S: PinSource + ?Sized>(
+	source: &S,
+	db: &Db,
+	runs: &PinRuns,
+	pin: &PinStatus,
+	cancel: &AtomicBool,
+	held: &(dyn Fn() -> bool + Sync),
+	report: &mut PinSyncReport,
+) -> Result<()> {
+	let peer: PeerId = pin
+		.peer
+		.parse()
+		.map_err(|err| anyhow!("invalid peer id {}: {err}", pin.peer))?;
+	let dest = Path::new(&pin.local_dest);
+	tokio::fs::create_dir_all(dest).await?;
+	let (remote_files, skipped) = list_tree(source, peer, &pin.remote_path, cancel).await?;
+	let mut manifest = load_pin_files(&*lock(db)?, pin.id)?;
+	let node_id = peer_to_node_id(&peer);
+
+	let mut to_fetch = Vec::new();
+	for remote in &remote_files {
+		let Some(local) = mirror_path(dest, &remote.rel) else {
+			report.error = Some(format!("not mirroring {}: unsafe local path", remote.path));
+			continue;
+		};
+		let remote_hash = match &node_id {
+			Some(node_id) => indexed_hash(&*lock(db)?, node_id, &remote.path)?,
+			None => None,
+		};
+		let known = manifest.get(&remote.rel);
+		if !is_current(remote, known, &local, remote_hash.as_deref()) {
+			to_fetch.push((remote, local));
+		} else if let Some(known) = known
+			&& known.modified_at != remote.modified_at
+		{
+			// Touched on the peer but the content is the same.
+			let touched = PinnedFile {
+				modified_at: remote.modified_at,
+				..known.clone()
+			};
+			save_pin_file(&*lock(db)?, pin.id, &remote.rel, &touched)?;
+		}
+	}
+	report.files_pending = to_fetch.len() as u64;
+	runs.set_pending(pin.id, report.files_pending);
+
+	for (remote, local) in to_fetch {
+		if held() {
+			return Ok(());
+		}
+		let mut bytes = 0;
+		let fetched = fetch_file(
+			source,
+			peer,
+			remote,
+			&local,
+			pin.bandwidth_limit,
+			cancel,
+			|len| bytes += len,
+		)
+		.await;
+		report.bytes += bytes;
+		match fetched {
+			Ok(hash) => {
+				let file = PinnedFile {
+					size: remote.size,
+					modified_at: remote.modified_at,
+					hash,
+				};
+				save_pin_file(&*lock(db)?, pin.id, &remote.rel, &file)?;
+				manifest.insert(remote.rel.clone(), file);
+				report.files_pending -= 1;
+				runs.set_pending(pin.id, report.files_pending);
+			}
+			Err(_) if cancel.load(Ordering::SeqCst) => return Ok(()),
+			Err(err) => {
+				tracing::warn!("pin {}: failed to fetch {}: {err:#}", pin.id, remote.path);
+				report.error = Some(format!("failed to fetch {}: {err:#}", remote.path));
+			}
+		}
+	}
+
+	if pin.delete_removed {
+		let present = remote_files
+			.iter()
+			.map(|remote| remote.rel.as_str())
+			.collect::<HashSet<_>>();
+		let removed = manifest
+			.keys()
+			.filter(|rel| !present.contains(rel.as_str()))
+			.cloned()
+			.collect::<Vec<_>>();
+		for rel in removed {
+			if let Some(local) = mirror_path(dest, &rel) {
+				match tokio::fs::remove_file(&local).await {
+					Ok(()) => {}
+					Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+					Err(err) => {
+						report.error = Some(format!("failed to remove {}: {err}", local.display()));
+						continue;
+					}
+				}
+			}
+			remove_pin_file(&*lock(db)?, pin.id, &rel)?;
+		}
+	}
+
+	if report.error.is_none()
+		&& let Some(first) = skipped.first()
+	{
+		report.error = Some(format!(
+			"skipped {} entr{} with names that can't be mirrored, e.g. {first}",
+			skipped.len(),
+			if skipped.len() == 1 { "y" } else { "ies" }
+		));
+	}
+	report.finished_at = Some(Utc::now());
+	Ok(())
+}
+
+/// Wakes `peer` over the LAN and waits for it to connect.
+async fn wake_for_sync<S: PinSource + ?Sized>(source: &S, db: &Db, peer: PeerId) -> Result<()> {
+	let target = wake::send_wake(db, peer)?;
+	tracing::info!("sent wake packet to {peer} ({}) for a pin sync", target.mac);
+	wait_until_connected(peer, WAKE_TIMEOUT, move || source.is_connected(peer)).await?;
+	Ok(())
+}
+
+/// Starts a sync for every pin that is due: not paused or already
+/// syncing, its interval passed since the last try, its peer connected, or
+/// woken first for pins that ask for it, the activity window open for
+/// syncs, no power hold and no maintenance.
+pub(crate) async fn start_due_syncs(
+	source: &Arc<UnboundedSender<Command>>,
+	db: &Arc<Db>,
+	window: &Arc<Mutex<ActivityWindow>>,
+	power: &Arc<Mutex<PowerGate>>,
+	maintenance: &Arc<MaintenanceGate>,
+	runs: &Arc<PinRuns>,
+) {
+	if !window
+		.lock()
+		.unwrap()
+		.allows(ActivityKind::Sync, Utc::now())
+		|| power.lock().unwrap().holds()
+		|| maintenance.is_active()
+	{
+		return;
+	}
+	let pins = match db.lock() {
+		Ok(conn) => load_pins(&conn).unwrap_or_else(|err| {
+			tracing::error!("failed to load pins: {err}");
+			Vec::new()
+		}),
+		Err(err) => {
+			tracing::error!("db lock poisoned while loading pins: {err}");
+			return;
+		}
+	};
+	let now = Instant::now();
+	for pin in pins {
+		if !runs.due(&pin, now) {
+			continue;
+		}
+		let Ok(peer) = pin.peer.parse::<PeerId>() else {
+			continue;
+		};
+		let connected = source.is_connected(peer).await;
+		if !connected && !pin.wake_if_needed {
+			continue;
+		}
+		if source.share_offline(Path::new(&pin.local_dest)).await {
+			tracing::debug!("pin {} waits for {} to be mounted", pin.id, pin.local_dest);
+			continue;
+		}
+		let Some(cancel) = runs.begin(pin.id, now) else {
+			continue;
+		};
+		let Ok(work) = maintenance.begin(format!("sync of pin {}", pin.id), Some(cancel.clone()))
+		else {
+			runs.end(pin.id);
+			return;
+		};
+		let (source, db, window, power, runs) = (
+			source.clone(),
+			db.clone(),
+			window.clone(),
+			power.clone(),
+			runs.clone(),
+		);
+		tokio::spawn(async move {
+			let _work = work;
+			let woken = if connected {
+				Ok(())
+			} else {
+				wake_for_sync(&*source, &db, peer).await
+			};
+			let report = match woken {
+				Ok(()) => {
+					tracing::info!("syncing pin {} ({})", pin.id, pin.remote_path);
+					sync_pin(&*source, &db, &window, &power, &runs, &pin, &cancel).await
+				}
+				Err(err) => PinSyncReport {
+					files_pending: pin.files_pending,
+					error: Some(format!("{err:#}")),
+					..PinSyncReport::default()
+				},
+			};
+			if let Some(err) = &report.error {
+				tracing::warn!("pin {} synced with errors: {err}", pin.id);
+			}
+			match db.lock() {
+				Ok(conn) => {
+					if let Err(err) = finish_pin_sync(&conn, pin.id, &report) {
+						tracing::error!("failed to record sync of pin {}: {err}", pin.id);
+					}
+				}
+				Err(err) => tracing::error!("db lock poisoned while recording pin sync: {err}"),
+			}
+			runs.end(pin.id);
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::db::insert_pin;
+	use crate::p2p::MimeSource;
+	use crate::power::PowerReading;
+	use crate::test_util;
+	use std::collections::BTreeMap;
+
+	/// A peer serving files from memory, keyed by `/`-separated path.
+	struct FakePeer {
+		files: Mutex<BTreeMap<String, (Vec<u8>, i64)>>,
+		reads: Mutex<Vec<String>>,
+		/// Reads of this path fail.
+		broken: Mutex<Option<String>>,
+	}
+
+	impl FakePeer {
+		fn new(files: &[(&str, &str)]) -> Self {
+			Self {
+				files: Mutex::new(
+					files
+						.iter()
+						.map(|(path, data)| (path.to_string(), (data.as_bytes().to_vec(), 1000)))
+						.collect(),
+				),
+				reads: Mutex::new(Vec::new()),
+				broken: Mutex::new(None),
+			}
+		}
+
+		fn entry(name: &str, is_dir: bool, size: u64, modified: i64) -> DirEntry {
+			DirEntry {
+				name: name.to_string(),
+				name_raw: Vec::new(),
+				is_dir,
+				extension: None,
+				mime: None,
+				mime_source: MimeSource::Extension,
+				size,
+				disk_size: None,
+				created_at: None,
+				modified_at: DateTime::from_timestamp(modified, 0),
+				accessed_at: None,
+				hidden: false,
+			}
+		}
+	}
+
+	#[async_trait]
+	impl PinSource for FakePeer {
+		async fn is_connected(&self, _peer: PeerId) -> bool {
+			true
+		}
+
+		async fn list_dir(&self, _peer: PeerId, path: &str) -> Result<Vec<DirEntry>> {
+			let prefix = format!("{}/", path.trim_end_matches('/'));
+			let mut entries = BTreeMap::new();
+			for (file, (data, modified)) in self.files.lock().unwrap().iter() {
+				let Some(rest) = file.strip_prefix(&prefix) else {
+					continue;
+				};
+				let entry = match rest.split_once('/') {
+					Some((dir, _)) => Self::entry(dir, true, 0, 0),
+					None => Self::entry(rest, false, data.len() as u64, *modified),
+				};
+				entries.insert(entry.name.clone(), entry);
+			}
+			Ok(entries.into_values().collect())
+		}
+
+		async fn read_file(
+			&self,
+			_peer: PeerId,
+			path: &str,
+			offset: u64,
+			length: u64,
+		) -> Result<FileChunk> {
+			if self.broken.lock().unwrap().as_deref() == Some(path) {
+				bail!("connection reset");
+			}
+			self.reads.lock().unwrap().push(path.to_string());
+			let files = self.files.lock().unwrap();
+			let (data, _) = files.get(path).ok_or_else(|| anyhow!("no such file"))?;
+			let start = (offset as usize).min(data.len());
+			let end = (start + length as usize).min(data.len());
+			Ok(FileChunk {
+				offset,
+				data: data[start..end].to_vec(),
+				eof: end == data.len(),
+				hole: 0,
+			})
+		}
+	}
+
+	struct Fixture {
+		dir: PathBuf,
+		db: Db,
+		window: Mutex<ActivityWindow>,
+		power: Mutex<PowerGate>,
+		runs: PinRuns,
+		pin: PinStatus,
+	}
+
+	impl Fixture {
+		fn new(name: &str) -> Self {
+			let dir = std::env::temp_dir().join(format!(
+				"puppynet-pins-{name}-{}-{}",
+				std::process::id(),
+				Utc::now().timestamp_nanos_opt().unwrap_or_default()
+			));
+			std::fs::create_dir_all(&dir).unwrap();
+			let conn = test_util::memory_db();
+			let dest = dir.join("mirror");
+			let id = insert_pin(
+				&conn,
+				&PeerId::random(),
+				"/home/ana/papers",
+				&dest,
+				&PinOptions::default(),
+				Utc::now(),
+			)
+			.unwrap();
+			let pin = load_pins(&conn)
+				.unwrap()
+				.into_iter()
+				.find(|pin| pin.id == id)
+				.unwrap();
+			Self {
+				dir,
+				db: Db::single(conn),
+				window: Mutex::new(ActivityWindow::default()),
+				power: Mutex::new(PowerGate::default()),
+				runs: PinRuns::default(),
+				pin,
+			}
+		}
+
+		async fn sync(&self, peer: &FakePeer) -> PinSyncReport {
+			sync_pin(
+				peer,
+				&self.db,
+				&self.window,
+				&self.power,
+				&self.runs,
+				&self.pin,
+				&AtomicBool::new(false),
+			)
+			.await
+		}
+
+		fn local(&self, rel: &str) -> PathBuf {
+			Path::new(&self.pin.local_dest).join(rel)
+		}
+	}
+
+	impl Drop for Fixture {
+		fn drop(&mut self) {
+			let _ = std::fs::remove_dir_all(&self.dir);
+		}
+	}
+
+	#[test]
+	fn peer_names_must_be_single_components() {
+		for name in ["notes.txt", "10:30 call.md", "..hidden", "a b"] {
+			assert_eq!(safe_entry_name(name), Some(name), "{name}");
+		}
+		for name in ["", ".", "..", "../x", "a/b", r"..\x", "/etc", "a\0b"] {
+			assert_eq!(safe_entry_name(name), None, "{name:?}");
+		}
+		let dest = Path::new("/tmp/mirror");
+		assert_eq!(
+			mirror_path(dest, "a/b.txt"),
+			Some(PathBuf::from("/tmp/mirror/a/b.txt"))
+		);
+		assert_eq!(mirror_path(dest, "a/../../etc/passwd"), None);
+		assert_eq!(mirror_path(dest, "a//b"), None);
+		assert_eq!(remote_child("/home/ana/", "a"), "/home/ana/a");
+		assert_eq!(remote_child(r"C:\Users\ana", "a"), r"C:\Users\ana\a");
+		assert_eq!(remote_child("/", "etc"), "/etc");
+	}
+
+	#[test]
+	fn throttle_waits_for_the_bytes_to_fit_the_limit() {
+		assert_eq!(
+			throttle_delay(2048, 1024, Duration::from_millis(500)),
+			Duration::from_millis(1500)
+		);
+		assert_eq!(
+			throttle_delay(1024, 1024, Duration::from_secs(3)),
+			Duration::ZERO
+		);
+	}
+
+	#[tokio::test]
+	async fn mirrors_changes_and_removals_without_refetching() {
+		let fixture = Fixture::new("mirror");
+		let peer = FakePeer::new(&[
+			("/home/ana/papers/a.pdf", "alpha"),
+			("/home/ana/papers/2024/b.pdf", "beta"),
+		]);
+		let report = fixture.sync(&peer).await;
+		assert_eq!(report.error, None);
+		assert!(report.finished_at.is_some());
+		assert_eq!((report.files_pending, report.bytes), (0, 9));
+		assert_eq!(std::fs::read(fixture.local("2024/b.pdf")).unwrap(), b"beta");
+
+		peer.reads.lock().unwrap().clear();
+		{
+			let mut files = peer.files.lock().unwrap();
+			files.insert(
+				"/home/ana/papers/a.pdf".into(),
+				(b"alpha v2".to_vec(), 2000),
+			);
+			files.remove("/home/ana/papers/2024/b.pdf");
+		}
+		let report = fixture.sync(&peer).await;
+		assert_eq!(report.error, None);
+		assert_eq!(*peer.reads.lock().unwrap(), ["/home/ana/papers/a.pdf"]);
+		assert_eq!(std::fs::read(fixture.local("a.pdf")).unwrap(), b"alpha v2");
+		assert!(!fixture.local("2024/b.pdf").exists());
+	}
+
+	#[tokio::test]
+	async fn interrupted_sync_resumes_without_refetching_completed_files() {
+		let fixture = Fixture::new("resume");
+		let peer = FakePeer::new(&[
+			("/home/ana/papers/a.pdf", "alpha"),
+			("/home/ana/papers/b.pdf", "beta"),
+		]);
+		*peer.broken.lock().unwrap() = Some("/home/ana/papers/b.pdf".into());
+		let report = fixture.sync(&peer).await;
+		assert_eq!(report.files_pending, 1);
+		assert!(report.error.unwrap().contains("b.pdf"));
+		assert!(fixture.local("a.pdf").is_file());
+
+		// A part left by a dropped connection is continued, not restarted.
+		std::fs::write(part_path(&fixture.local("b.pdf")), b"be").unwrap();
+		*peer.broken.lock().unwrap() = None;
+		peer.reads.lock().unwrap().clear();
+		let report = fixture.sync(&peer).await;
+		assert_eq!(report.error, None);
+		assert_eq!(report.bytes, 2);
+		assert_eq!(*peer.reads.lock().unwrap(), ["/home/ana/papers/b.pdf"]);
+		assert_eq!(std::fs::read(fixture.local("b.pdf")).unwrap(), b"beta");
+		let manifest = load_pin_files(&fixture.db.lock().unwrap(), fixture.pin.id).unwrap();
+		assert_eq!(
+			manifest["b.pdf"].hash,
+			blake3::hash(b"beta").as_bytes().to_vec()
+		);
+	}
+
+	#[tokio::test]
+	async fn a_power_hold_pauses_the_sync_until_it_ends() {
+		let fixture = Fixture::new("power");
+		let peer = FakePeer::new(&[("/home/ana/papers/a.pdf", "alpha")]);
+		let battery = Some(PowerReading {
+			on_battery: true,
+			battery_percent: Some(40),
+		});
+		fixture
+			.power
+			.lock()
+			.unwrap()
+			.observe(battery, Instant::now());
+		let report = fixture.sync(&peer).await;
+		assert_eq!((report.error, report.files_pending), (None, 1));
+		assert!(peer.reads.lock().unwrap().is_empty());
+
+		fixture.power.lock().unwrap().run_anyway();
+		let report = fixture.sync(&peer).await;
+		assert_eq!(report.error, None);
+		assert_eq!(std::fs::read(fixture.local("a.pdf")).unwrap(), b"alpha");
+	}
+
+	#[tokio::test]
+	async fn hostile_names_never_leave_the_mirror() {
+		let fixture = Fixture::new("traversal");
+		let peer = FakePeer::new(&[("/home/ana/papers/ok.txt", "ok")]);
+		struct Hostile(FakePeer);
+		#[async_trait]
+		impl PinSource for Hostile {
+			async fn is_connected(&self, _peer: PeerId) -> bool {
+				true
+			}
+			async fn list_dir(&self, peer: PeerId, path: &str) -> Result<Vec<DirEntry>> {
+				let mut entries = self.0.list_dir(peer, path).await?;
+				entries.push(FakePeer::entry("../escape.txt", false, 4, 1000));
+				entries.push(FakePeer::entry("..", true, 0, 0));
+				Ok(entries)
+			}
+			async fn read_file(
+				&self,
+				peer: PeerId,
+				path: &str,
+				offset: u64,
+				length: u64,
+			) -> Result<FileChunk> {
+				self.0.read_file(peer, path, offset, length).await
+			}
+		}
+		let hostile = Hostile(peer);
+		let report = sync_pin(
+			&hostile,
+			&fixture.db,
+			&fixture.window,
+			&fixture.power,
+			&fixture.runs,
+			&fixture.pin,
+			&AtomicBool::new(false),
+		)
+		.await;
+		assert!(report.error.unwrap().contains("skipped 2 entries"));
+		assert!(fixture.local("ok.txt").is_file());
+		assert!(!fixture.dir.join("escape.txt").exists());
+		assert_eq!(
+			*hostile.0.reads.lock().unwrap(),
+			["/home/ana/papers/ok.txt"]
+		);
+
+		#[cfg(unix)]
+		{
+			let outside = fixture.dir.join("outside");
+			std::fs::create_dir_all(&outside).unwrap();
+			std::os::unix::fs::symlink(&outside, fixture.local("link")).unwrap();
+			assert_eq!(
+				mirror_path(Path::new(&fixture.pin.local_dest), "link/x.txt"),
+				None
+			);
+		}
+	}
+}
@@ -0,0 +1,391 @@
+//! Whether deferrable background work may run on the power this machine is
+//! on. The platform is asked every few seconds whether it runs on battery
+//! and how full the battery is; a policy turns that into a hold on
+//! scheduled backups, thumbnail pregeneration, pin syncs, outboxes,
+//! replication and update downloads. Scans and reads someone asked for
+//! never wait on it. A changed reading has to last for the debounce before
+//! the hold starts or ends, so a cable wiggle doesn't stop and start
+//! workers. The user can let held work run anyway until the hold next ends.
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+pub(crate) const POWER_POLICY_SETTING: &str = "power_policy";
+/// How often the power source is read.
+pub(crate) const POWER_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// How long a changed reading has to last before the hold follows it.
+const POWER_DEBOUNCE: Duration = Duration::from_secs(30);
+
+/// What the platform reports about the power supply.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowerReading {
+	pub on_battery: bool,
+	/// Charge of the battery, when there is one and it says.
+	pub battery_percent: Option<u8>,
+}
+
+/// Reads the power supply. `None` when the platform doesn't tell, which
+/// never holds work back.
+pub(crate) trait PowerSource: Send + Sync {
+	fn read(&self) -> Option<PowerReading>;
+}
+
+/// The power supply as the operating system reports it.
+pub(crate) struct SystemPower;
+
+#[cfg(target_os = "linux")]
+impl PowerSource for SystemPower {
+	fn read(&self) -> Option<PowerReading> {
+		read_power_supply(std::path::Path::new("/sys/class/power_supply"))
+	}
+}
+
+#[cfg(target_os = "macos")]
+impl PowerSource for SystemPower {
+	fn read(&self) -> Option<PowerReading> {
+		let output = std::process::Command::new("pmset")
+			.args(["-g", "batt"])
+			.output()
+			.ok()?;
+		if !output.status.success() {
+			return None;
+		}
+		parse_pmset(&String::from_utf8_lossy(&output.stdout))
+	}
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+impl PowerSource for SystemPower {
+	fn read(&self) -> Option<PowerReading> {
+		None
+	}
+}
+
+/// Reads the supplies under `/sys/class/power_supply`. Batteries of mice
+/// and other devices are skipped; without a system battery the machine
+/// counts as plugged in.
+#[cfg(target_os = "linux")]
+fn read_power_supply(dir: &std::path::Path) -> Option<PowerReading> {
+	let read = |path: std::path::PathBuf| {
+		std::fs::read_to_string(path)
+			.ok()
+			.map(|value| value.trim().to_string())
+	};
+	let mut mains_online = None;
+	let mut discharging = false;
+	let mut capacities = Vec::new();
+	for entry in std::fs::read_dir(dir).ok()?.flatten() {
+		let supply = entry.path();
+		match read(supply.join("type")).as_deref() {
+			Some("Battery") => {
+				if read(supply.join("scope")).as_deref() == Some("Device") {
+					continue;
+				}
+				discharging |= read(supply.join("status")).as_deref() == Some("Discharging");
+				if let Some(capacity) = read(supply.join("capacity")).and_then(|v| v.parse().ok()) {
+					capacities.push(capacity);
+				}
+			}
+			Some("Mains" | "USB" | "USB_C" | "USB_PD") => {
+				let online = read(supply.join("online")).as_deref() == Some("1");
+				mains_online = Some(mains_online.unwrap_or(false) || online);
+			}
+			_ => {}
+		}
+	}
+	if capacities.is_empty() && !discharging {
+		return None;
+	}
+	Some(PowerReading {
+		on_battery: mains_online.map_or(discharging, |online| !online),
+		battery_percent: capacities.iter().min().copied(),
+	})
+}
+
+/// Parses `pmset -g batt`: "Now drawing from 'Battery Power'" and a line
+/// with the charge like "-InternalBattery-0 (id=…) 81%; discharging".
+#[cfg(any(target_os = "macos", test))]
+fn parse_pmset(output: &str) -> Option<PowerReading> {
+	let source = output.lines().next()?;
+	let on_battery = if source.contains("'Battery Power'") {
+		true
+	} else if source.contains("'AC Power'") {
+		false
+	} else {
+		return None;
+	};
+	let battery_percent = output.lines().skip(1).find_map(|line| {
+		let (before, _) = line.split_once('%')?;
+		before
+			.rsplit(|c: char| !c.is_ascii_digit())
+			.next()?
+			.parse()
+			.ok()
+	});
+	Some(PowerReading {
+		on_battery,
+		battery_percent,
+	})
+}
+
+/// When background work waits for power.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum PowerPolicy {
+	/// Never hold work back.
+	Always,
+	#[default]
+	PauseOnBattery,
+	/// Hold work back on battery once the charge drops below `percent`.
+	PauseBelow { percent: u8 },
+}
+
+impl PowerPolicy {
+	pub fn holds(&self, reading: Option<PowerReading>) -> bool {
+		let Some(reading) = reading.filter(|reading| reading.on_battery) else {
+			return false;
+		};
+		match self {
+			Self::Always => false,
+			Self::PauseOnBattery => true,
+			Self::PauseBelow { percent } => reading
+				.battery_percent
+				.is_some_and(|charge| charge < *percent),
+		}
+	}
+}
+
+/// The power supply and whether background work waits for it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowerState {
+	/// `None` when the platform doesn't report the power supply.
+	pub reading: Option<PowerReading>,
+	pub background_paused: bool,
+	/// Held work was let run until the hold next ends.
+	pub run_anyway: bool,
+}
+
+impl PowerState {
+	/// "on battery, 14%" or "plugged in", for notices.
+	pub fn label(&self) -> String {
+		match self.reading {
+			Some(PowerReading {
+				on_battery: true,
+				battery_percent: Some(percent),
+			}) => format!("on battery, {percent}%"),
+			Some(PowerReading {
+				on_battery: true, ..
+			}) => String::from("on battery"),
+			Some(PowerReading {
+				battery_percent: Some(percent),
+				..
+			}) => format!("plugged in, {percent}%"),
+			Some(_) => String::from("plugged in"),
+			None => String::from("unknown"),
+		}
+	}
+}
+
+/// The debounced hold, shared by the monitor that feeds it readings and
+/// the workers that check it.
+#[derive(Debug, Default)]
+pub(crate) struct PowerGate {
+	policy: PowerPolicy,
+	reading: Option<PowerReading>,
+	held: bool,
+	/// Since when readings disagree with `held`.
+	changing_since: Option<Instant>,
+	/// The first reading applies at once; there is nothing to debounce.
+	observed: bool,
+	run_anyway: bool,
+}
+
+impl PowerGate {
+	pub(crate) fn new(policy: PowerPolicy) -> Self {
+		Self {
+			policy,
+			..Self::default()
+		}
+	}
+
+	pub(crate) fn policy(&self) -> PowerPolicy {
+		self.policy
+	}
+
+	/// A new policy applies to the last reading right away.
+	pub(crate) fn set_policy(&mut self, policy: PowerPolicy) {
+		self.policy = policy;
+		self.changing_since = None;
+		self.set_held(policy.holds(self.reading));
+	}
+
+	fn set_held(&mut self, held: bool) {
+		self.held = held;
+		if !held {
+			self.run_anyway = false;
+		}
+	}
+
+	/// Takes a reading. Returns true when the hold started or ended.
+	pub(crate) fn observe(&mut self, reading: Option<PowerReading>, now: Instant) -> bool {
+		self.reading = reading;
+		let wants = self.policy.holds(reading);
+		if wants == self.held {
+			self.changing_since = None;
+			self.observed = true;
+			return false;
+		}
+		let since = *self.changing_since.get_or_insert(now);
+		if self.observed && now.duration_since(since) < POWER_DEBOUNCE {
+			return false;
+		}
+		self.observed = true;
+		self.changing_since = None;
+		self.set_held(wants);
+		true
+	}
+
+	/// Whether background work should wait.
+	pub(crate) fn holds(&self) -> bool {
+		self.held && !self.run_anyway
+	}
+
+	/// Lets held work run until the hold next ends. False when nothing was
+	/// held.
+	pub(crate) fn run_anyway(&mut self) -> bool {
+		if !self.held {
+			return false;
+		}
+		self.run_anyway = true;
+		true
+	}
+
+	pub(crate) fn state(&self) -> PowerState {
+		PowerState {
+			reading: self.reading,
+			background_paused: self.holds(),
+			run_anyway: self.held && self.run_anyway,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Mutex;
+
+	/// Reports whatever the test last plugged in.
+	struct FakePower(Mutex<Option<PowerReading>>);
+
+	impl FakePower {
+		fn set(&self, on_battery: bool, percent: u8) {
+			*self.0.lock().unwrap() = Some(PowerReading {
+				on_battery,
+				battery_percent: Some(percent),
+			});
+		}
+	}
+
+	impl PowerSource for FakePower {
+		fn read(&self) -> Option<PowerReading> {
+			*self.0.lock().unwrap()
+		}
+	}
+
+	#[test]
+	fn brief_unplugs_do_not_pause_work() {
+		let power = FakePower(Mutex::new(None));
+		let mut gate = PowerGate::new(PowerPolicy::PauseOnBattery);
+		let start = Instant::now();
+		assert!(!gate.observe(power.read(), start));
+		assert!(!gate.holds());
+
+		power.set(false, 80);
+		assert!(!gate.observe(power.read(), start));
+		power.set(true, 80);
+		assert!(!gate.observe(power.read(), start + Duration::from_secs(10)));
+		power.set(false, 80);
+		assert!(!gate.observe(power.read(), start + Duration::from_secs(20)));
+		power.set(true, 79);
+		assert!(!gate.observe(power.read(), start + POWER_DEBOUNCE));
+		assert!(!gate.holds());
+		assert!(gate.observe(power.read(), start + POWER_DEBOUNCE * 2));
+		assert!(gate.holds());
+		assert_eq!(gate.state().reading.unwrap().battery_percent, Some(79));
+
+		power.set(false, 79);
+		let back = start + POWER_DEBOUNCE * 3;
+		assert!(!gate.observe(power.read(), back));
+		assert!(gate.holds());
+		assert!(gate.observe(power.read(), back + POWER_DEBOUNCE));
+		assert!(!gate.holds());
+	}
+
+	#[test]
+	fn a_threshold_holds_only_a_low_battery() {
+		let power = FakePower(Mutex::new(None));
+		let mut gate = PowerGate::new(PowerPolicy::PauseBelow { percent: 20 });
+		let start = Instant::now();
+		power.set(true, 50);
+		gate.observe(power.read(), start);
+		assert!(!gate.holds());
+		power.set(true, 19);
+		gate.observe(power.read(), start);
+		assert!(gate.observe(power.read(), start + POWER_DEBOUNCE));
+		assert!(gate.holds());
+		// A low battery that is charging is fine.
+		power.set(false, 19);
+		gate.observe(power.read(), start + POWER_DEBOUNCE);
+		assert!(gate.observe(power.read(), start + POWER_DEBOUNCE * 2));
+		assert!(!gate.holds());
+
+		gate.set_policy(PowerPolicy::Always);
+		power.set(true, 5);
+		gate.observe(power.read(), start);
+		assert!(!gate.observe(power.read(), start + POWER_DEBOUNCE * 3));
+		assert!(!gate.holds());
+	}
+
+	#[test]
+	fn running_anyway_lasts_until_the_hold_ends() {
+		let power = FakePower(Mutex::new(None));
+		let mut gate = PowerGate::new(PowerPolicy::PauseOnBattery);
+		let start = Instant::now();
+		assert!(!gate.run_anyway());
+		power.set(true, 60);
+		// The first reading needs no debounce.
+		assert!(gate.observe(power.read(), start));
+		assert!(gate.holds());
+		assert!(gate.run_anyway());
+		assert!(!gate.holds());
+		assert!(gate.state().run_anyway);
+
+		power.set(false, 60);
+		gate.observe(power.read(), start);
+		gate.observe(power.read(), start + POWER_DEBOUNCE);
+		power.set(true, 60);
+		gate.observe(power.read(), start + POWER_DEBOUNCE);
+		gate.observe(power.read(), start + POWER_DEBOUNCE * 2);
+		assert!(gate.holds());
+	}
+
+	#[test]
+	fn pmset_output_is_parsed() {
+		let battery = "Now drawing from 'Battery Power'\n -InternalBattery-0 (id=4653155)\t81%; discharging; 5:12 remaining present: true\n";
+		assert_eq!(
+			parse_pmset(battery),
+			Some(PowerReading {
+				on_battery: true,
+				battery_percent: Some(81),
+			})
+		);
+		let desktop = "Now drawing from 'AC Power'\n";
+		assert_eq!(
+			parse_pmset(desktop),
+			Some(PowerReading {
+				on_battery: false,
+				battery_percent: None,
+			})
+		);
+	}
+}
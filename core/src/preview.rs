@@ -0,0 +1,220 @@
+use serde::Serialize;
+
+/// Bytes read per preview page; the UI pages through files in these steps.
+pub(crate) const PREVIEW_PAGE_SIZE: u64 = 64 * 1024;
+/// Largest page a client may request through the HTTP preview endpoint.
+pub(crate) const PREVIEW_MAX_PAGE_SIZE: u64 = 256 * 1024;
+/// Images above this size are reported as `too_large` instead of thumbnailed.
+pub(crate) const PREVIEW_MAX_IMAGE_SIZE: u64 = 32 * 1024 * 1024;
+const HEX_ROW_WIDTH: usize = 16;
+
+const MAGIC_NUMBERS: &[(&[u8], &str)] = &[
+	(b"\x89PNG\r\n\x1a\n", "image/png"),
+	(b"\xff\xd8\xff", "image/jpeg"),
+	(b"GIF87a", "image/gif"),
+	(b"GIF89a", "image/gif"),
+	(b"%PDF-", "application/pdf"),
+	(b"PK\x03\x04", "application/zip"),
+	(b"\x1f\x8b", "application/gzip"),
+	(b"\x7fELF", "application/x-elf"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PreviewKind {
+	Text,
+	Binary,
+	Image,
+	TooLarge,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct FilePreview {
+	pub kind: PreviewKind,
+	/// Decoded text for `text`, a hex dump for `binary`, empty otherwise.
+	pub content: String,
+	/// More data follows this page.
+	pub truncated: bool,
+	pub detected_mime: String,
+	pub line_count: usize,
+	pub offset: u64,
+	pub next_offset: Option<u64>,
+}
+
+/// Length of the valid UTF-8 prefix, tolerating a multi-byte sequence cut off
+/// at the end of the chunk. `None` when the data is not UTF-8.
+fn utf8_prefix_len(data: &[u8]) -> Option<usize> {
+	match std::str::from_utf8(data) {
+		Ok(_) => Some(data.len()),
+		Err(err) if err.error_len().is_none() && data.len() - err.valid_up_to() < 4 => {
+			Some(err.valid_up_to())
+		}
+		Err(_) => None,
+	}
+}
+
+fn looks_like_text(data: &[u8]) -> bool {
+	let Some(len) = utf8_prefix_len(data) else {
+		return false;
+	};
+	!data[..len]
+		.iter()
+		.any(|&b| b == 0 || (b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)))
+}
+
+/// Detects the mime type from the leading bytes, falling back to the path
+/// extension only when the content is not recognisable.
+pub(crate) fn sniff_mime(data: &[u8], path: &str) -> String {
+	if let Some((_, mime)) = MAGIC_NUMBERS
+		.iter()
+		.find(|(magic, _)| data.starts_with(magic))
+	{
+		return mime.to_string();
+	}
+	if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+		return String::from("image/webp");
+	}
+	let guessed = mime_guess::from_path(path).first_raw();
+	if looks_like_text(data) {
+		return match guessed {
+			Some(mime) if mime.starts_with("text/") || mime.ends_with("json") => mime.to_string(),
+			Some("image/svg+xml") => String::from("image/svg+xml"),
+			_ => String::from("text/plain"),
+		};
+	}
+	guessed.unwrap_or("application/octet-stream").to_string()
+}
+
+/// Renders `data` as `offset  hex bytes  |ascii|` rows.
+pub(crate) fn hex_dump(data: &[u8], base_offset: u64) -> String {
+	let mut out = String::new();
+	for (row, chunk) in data.chunks(HEX_ROW_WIDTH).enumerate() {
+		let offset = base_offset + (row * HEX_ROW_WIDTH) as u64;
+		out.push_str(&format!("{offset:08x}  "));
+		for idx in 0..HEX_ROW_WIDTH {
+			match chunk.get(idx) {
+				Some(byte) => out.push_str(&format!("{byte:02x} ")),
+				None => out.push_str("   "),
+			}
+			if idx == HEX_ROW_WIDTH / 2 - 1 {
+				out.push(' ');
+			}
+		}
+		out.push('|');
+		out.extend(chunk.iter().map(|&b| {
+			if b.is_ascii_graphic() || b == b' ' {
+				b as char
+			} else {
+				'.'
+			}
+		}));
+		out.push_str("|\n");
+	}
+	out.pop();
+	out
+}
+
+/// Prefixes every line with its number, starting at `first_line`.
+pub(crate) fn number_lines(text: &str, first_line: usize) -> String {
+	let count = text.lines().count().max(1);
+	let width = (first_line + count - 1).to_string().len();
+	text.lines()
+		.enumerate()
+		.map(|(idx, line)| format!("{:>width$}  {line}", first_line + idx))
+		.collect::<Vec<_>>()
+		.join("\n")
+}
+
+/// Classifies one page of a file read at `offset` and prepares its content.
+pub(crate) fn build_preview(path: &str, offset: u64, data: &[u8], eof: bool) -> FilePreview {
+	let detected_mime = sniff_mime(data, path);
+	if detected_mime.starts_with("image/") && detected_mime != "image/svg+xml" {
+		return FilePreview {
+			kind: PreviewKind::Image,
+			content: String::new(),
+			truncated: false,
+			detected_mime,
+			line_count: 0,
+			offset,
+			next_offset: None,
+		};
+	}
+	if looks_like_text(data) {
+		let len = utf8_prefix_len(data).unwrap_or(0);
+		if len > 0 || eof {
+			let content = String::from_utf8_lossy(&data[..len]).into_owned();
+			let truncated = !eof || len < data.len();
+			return FilePreview {
+				kind: PreviewKind::Text,
+				line_count: content.lines().count(),
+				content,
+				truncated,
+				detected_mime,
+				offset,
+				next_offset: truncated.then_some(offset + len as u64),
+			};
+		}
+	}
+	FilePreview {
+		kind: PreviewKind::Binary,
+		content: hex_dump(data, offset),
+		truncated: !eof,
+		detected_mime,
+		line_count: 0,
+		offset,
+		next_offset: (!eof).then_some(offset + data.len() as u64),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn extensionless_config_is_sniffed_as_text() {
+		let preview = build_preview("/etc/hostname", 0, b"puppy\n", true);
+
+		assert_eq!(preview.kind, PreviewKind::Text);
+		assert_eq!(preview.detected_mime, "text/plain");
+		assert_eq!(preview.line_count, 1);
+		assert_eq!(preview.next_offset, None);
+	}
+
+	#[test]
+	fn content_wins_over_misleading_extension() {
+		let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+		assert_eq!(sniff_mime(png, "/tmp/notes.txt"), "image/png");
+		assert_eq!(
+			build_preview("/tmp/notes.txt", 0, png, true).kind,
+			PreviewKind::Image
+		);
+	}
+
+	#[test]
+	fn text_pages_do_not_split_multibyte_characters() {
+		let mut cut = "héllo w".as_bytes().to_vec();
+		cut.push("ö".as_bytes()[0]);
+
+		let preview = build_preview("/var/log/app.log", 100, &cut, false);
+
+		assert_eq!(preview.kind, PreviewKind::Text);
+		assert_eq!(preview.content, "héllo w");
+		assert_eq!(preview.next_offset, Some(100 + "héllo w".len() as u64));
+	}
+
+	#[test]
+	fn binary_pages_render_offset_hex_and_ascii() {
+		let preview = build_preview("/bin/tool", 0x20, b"\x00\x01AB", false);
+
+		assert_eq!(preview.kind, PreviewKind::Binary);
+		assert!(preview.content.starts_with("00000020  00 01 41 42 "));
+		assert!(preview.content.ends_with("|..AB|"));
+		assert_eq!(preview.next_offset, Some(0x24));
+	}
+
+	#[test]
+	fn line_numbers_continue_from_previous_page() {
+		assert_eq!(number_lines("a\nb", 9), " 9  a\n10  b");
+	}
+}
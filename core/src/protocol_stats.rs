@@ -0,0 +1,743 @@
+//! How much each peer asks of this node, by request type. Handlers bump
+//! atomic counters of the peer they answer, from whichever task answers;
+//! the event loop rolls them into per-minute rates on a timer, raises an
+//! alert when a peer goes over the configured rate and, when the limiter
+//! is on, answers that peer with [`PeerRes::RateLimited`] until its rate
+//! drops again. A peer can also be held to a byte rate for a while, and is
+//! answered the same way while over it. Rolled-up counts are written to a
+//! daily history table; what each peer read and wrote goes on to
+//! [`crate::access_volume`].
+
+use crate::access_volume::{AccessSample, AccessVolume};
+use crate::db::load_setting;
+use crate::p2p::{PeerReq, PeerRes};
+use crate::wire::TolerantEnum;
+use chrono::NaiveDate;
+use libp2p::PeerId;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+/// Requests a minute from one peer that raise the alert; `0` turns the
+/// alert and the limiter off.
+pub const PROTOCOL_ALERT_SETTING: &str = "protocol_alert_per_min";
+pub const DEFAULT_PROTOCOL_ALERT_PER_MIN: u64 = 600;
+/// `"true"` answers peers over the alert rate with `RateLimited`.
+pub const PROTOCOL_LIMITER_SETTING: &str = "protocol_rate_limiter";
+/// How often counters are rolled into rates.
+pub(crate) const PROTOCOL_STATS_TICK: Duration = Duration::from_secs(10);
+/// Ticks summed into a per-minute rate.
+const RATE_TICKS: usize = 6;
+/// Ticks between writes of the daily history.
+pub(crate) const HISTORY_FLUSH_TICKS: u32 = 6;
+/// How long a limited peer is told to wait before asking again.
+pub const RATE_LIMIT_RETRY_SECS: u64 = 10;
+/// Days of history kept.
+pub(crate) const PROTOCOL_STATS_RETENTION_DAYS: u64 = 90;
+/// Request name counted for variants this node doesn't know.
+const OTHER_REQUEST: &str = "Unknown";
+
+fn kind_names() -> &'static [&'static str] {
+	<PeerReq as TolerantEnum>::variants()
+}
+
+/// Slot of the request variant called `name`; unknown names share the
+/// last one.
+pub(crate) fn kind_index(name: &str) -> usize {
+	static INDEX: OnceLock<HashMap<&'static str, usize>> = OnceLock::new();
+	let index = INDEX.get_or_init(|| {
+		kind_names()
+			.iter()
+			.enumerate()
+			.map(|(slot, name)| (*name, slot))
+			.collect()
+	});
+	index.get(name).copied().unwrap_or(kind_names().len())
+}
+
+fn kind_name(slot: usize) -> &'static str {
+	kind_names().get(slot).copied().unwrap_or(OTHER_REQUEST)
+}
+
+/// The alert rate stored under [`PROTOCOL_ALERT_SETTING`].
+pub(crate) fn alert_from_setting(value: Option<&str>) -> u64 {
+	value
+		.and_then(|value| value.trim().parse::<u64>().ok())
+		.unwrap_or(DEFAULT_PROTOCOL_ALERT_PER_MIN)
+}
+
+/// Alert rate and limiter switch as stored.
+pub(crate) fn load_limits(conn: &Connection) -> ProtocolLimits {
+	let load = |key| match load_setting(conn, key) {
+		Ok(value) => value,
+		Err(err) => {
+			tracing::error!("failed to load {key}: {err}");
+			None
+		}
+	};
+	ProtocolLimits {
+		alert_per_min: alert_from_setting(load(PROTOCOL_ALERT_SETTING).as_deref()),
+		limiter: load(PROTOCOL_LIMITER_SETTING).as_deref() == Some("true"),
+	}
+}
+
+/// When a peer's request rate raises the alert and whether the limiter
+/// then refuses it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolLimits {
+	/// Requests a minute from one peer; `0` turns checks off.
+	pub alert_per_min: u64,
+	pub limiter: bool,
+}
+
+impl Default for ProtocolLimits {
+	fn default() -> Self {
+		Self {
+			alert_per_min: DEFAULT_PROTOCOL_ALERT_PER_MIN,
+			limiter: false,
+		}
+	}
+}
+
+/// Traffic of one request type with one peer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolCounts {
+	/// Requests the peer sent, including refused ones.
+	pub requests: u64,
+	/// Requests this node sent the peer.
+	pub sent: u64,
+	/// Serialized bytes of the peer's requests and of its answers to ours.
+	pub bytes_in: u64,
+	/// Serialized bytes of our answers and of our requests.
+	pub bytes_out: u64,
+	/// Requests answered with an error.
+	pub errors: u64,
+	/// Requests refused for lack of access.
+	pub denials: u64,
+	/// Requests refused by the rate limiter.
+	pub rate_limited: u64,
+}
+
+impl ProtocolCounts {
+	pub(crate) fn add(&mut self, other: &ProtocolCounts) {
+		self.requests += other.requests;
+		self.sent += other.sent;
+		self.bytes_in += other.bytes_in;
+		self.bytes_out += other.bytes_out;
+		self.errors += other.errors;
+		self.denials += other.denials;
+		self.rate_limited += other.rate_limited;
+	}
+
+	fn is_empty(&self) -> bool {
+		*self == Self::default()
+	}
+}
+
+/// How a request was answered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Answer {
+	Ok,
+	Error,
+	Denied,
+	RateLimited,
+}
+
+impl Answer {
+	pub(crate) fn of(response: &PeerRes) -> Self {
+		match response {
+			PeerRes::Error(_)
+			| PeerRes::Unavailable { .. }
+			| PeerRes::Unsupported { .. }
+			| PeerRes::ImageTooLarge(_) => Self::Error,
+			PeerRes::AccessDenied(_) => Self::Denied,
+			PeerRes::RateLimited { .. } => Self::RateLimited,
+			_ => Self::Ok,
+		}
+	}
+}
+
+/// Bytes `value` takes as JSON, without keeping them.
+pub(crate) fn wire_len<T: Serialize>(value: &T) -> u64 {
+	struct Count(u64);
+	impl std::io::Write for Count {
+		fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+			self.0 += buf.len() as u64;
+			Ok(buf.len())
+		}
+
+		fn flush(&mut self) -> std::io::Result<()> {
+			Ok(())
+		}
+	}
+	let mut count = Count(0);
+	match serde_json::to_writer(&mut count, value) {
+		Ok(()) => count.0,
+		Err(_) => 0,
+	}
+}
+
+#[derive(Default)]
+struct KindCounters {
+	requests: AtomicU64,
+	sent: AtomicU64,
+	bytes_in: AtomicU64,
+	bytes_out: AtomicU64,
+	errors: AtomicU64,
+	denials: AtomicU64,
+	rate_limited: AtomicU64,
+}
+
+impl KindCounters {
+	fn take(&self) -> ProtocolCounts {
+		let take = |counter: &AtomicU64| counter.swap(0, Ordering::Relaxed);
+		ProtocolCounts {
+			requests: take(&self.requests),
+			sent: take(&self.sent),
+			bytes_in: take(&self.bytes_in),
+			bytes_out: take(&self.bytes_out),
+			errors: take(&self.errors),
+			denials: take(&self.denials),
+			rate_limited: take(&self.rate_limited),
+		}
+	}
+}
+
+/// Counters of one peer since the last tick, one slot per request type.
+pub(crate) struct PeerCounters {
+	kinds: Box<[KindCounters]>,
+	/// Bytes of our answers to the peer's requests.
+	read: AtomicU64,
+	/// Bytes of the peer's requests.
+	written: AtomicU64,
+	/// Bytes of both by the shared folder the request was in.
+	folders: Mutex<HashMap<String, u64>>,
+}
+
+impl PeerCounters {
+	fn new() -> Self {
+		Self {
+			kinds: (0..=kind_names().len())
+				.map(|_| KindCounters::default())
+				.collect(),
+			read: AtomicU64::new(0),
+			written: AtomicU64::new(0),
+			folders: Mutex::new(HashMap::new()),
+		}
+	}
+
+	fn slot(&self, kind: usize) -> &KindCounters {
+		&self.kinds[kind.min(self.kinds.len() - 1)]
+	}
+
+	/// The peer sent a request of `bytes`.
+	pub(crate) fn received(&self, kind: usize, bytes: u64) {
+		let slot = self.slot(kind);
+		slot.requests.fetch_add(1, Ordering::Relaxed);
+		slot.bytes_in.fetch_add(bytes, Ordering::Relaxed);
+		self.written.fetch_add(bytes, Ordering::Relaxed);
+	}
+
+	/// This node answered the peer's request with `bytes`.
+	pub(crate) fn answered(&self, kind: usize, bytes: u64, answer: Answer) {
+		let slot = self.slot(kind);
+		slot.bytes_out.fetch_add(bytes, Ordering::Relaxed);
+		self.read.fetch_add(bytes, Ordering::Relaxed);
+		let counter = match answer {
+			Answer::Ok => return,
+			Answer::Error => &slot.errors,
+			Answer::Denied => &slot.denials,
+			Answer::RateLimited => &slot.rate_limited,
+		};
+		counter.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// This node sent the peer a request of `bytes`.
+	pub(crate) fn sent(&self, kind: usize, bytes: u64) {
+		let slot = self.slot(kind);
+		slot.sent.fetch_add(1, Ordering::Relaxed);
+		slot.bytes_out.fetch_add(bytes, Ordering::Relaxed);
+	}
+
+	/// The peer answered one of our requests with `bytes`.
+	pub(crate) fn response(&self, kind: usize, bytes: u64) {
+		self.slot(kind).bytes_in.fetch_add(bytes, Ordering::Relaxed);
+	}
+
+	/// `bytes` of a request of the peer's, or of our answer, fell in the
+	/// shared folder `folder`.
+	pub(crate) fn accessed(&self, folder: &str, bytes: u64) {
+		let mut folders = self.folders.lock().unwrap();
+		match folders.get_mut(folder) {
+			Some(total) => *total += bytes,
+			None => {
+				folders.insert(folder.to_string(), bytes);
+			}
+		}
+	}
+
+	fn take_access(&self, requests: u64) -> AccessSample {
+		AccessSample {
+			volume: AccessVolume {
+				read: self.read.swap(0, Ordering::Relaxed),
+				written: self.written.swap(0, Ordering::Relaxed),
+				requests,
+			},
+			folders: std::mem::take(&mut *self.folders.lock().unwrap()),
+		}
+	}
+
+	fn take(&self) -> Vec<(usize, ProtocolCounts)> {
+		self.kinds
+			.iter()
+			.enumerate()
+			.map(|(kind, counters)| (kind, counters.take()))
+			.filter(|(_, counts)| !counts.is_empty())
+			.collect()
+	}
+}
+
+/// Traffic of one request type with one peer over one day.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolDay {
+	pub day: NaiveDate,
+	pub request: String,
+	pub counts: ProtocolCounts,
+}
+
+/// Traffic of one request type with one peer over the last minute.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolRate {
+	pub peer: String,
+	pub request: String,
+	pub last_minute: ProtocolCounts,
+	/// Whether the limiter refuses the peer right now.
+	pub limited: bool,
+}
+
+/// A peer refused a request because this node asked it too often. Returned
+/// apart from other errors so callers can back off and retry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RateLimited {
+	pub retry_after: Duration,
+}
+
+impl std::fmt::Display for RateLimited {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"the peer is refusing requests for now; try again in {}s",
+			self.retry_after.as_secs()
+		)
+	}
+}
+
+impl std::error::Error for RateLimited {}
+
+/// Raised or cleared when a peer crosses the alert rate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ProtocolAlert {
+	Raised {
+		peer: PeerId,
+		per_min: u64,
+		/// Request type the peer sent most of.
+		dominant: &'static str,
+		limited: bool,
+	},
+	Cleared {
+		peer: PeerId,
+		per_min: u64,
+	},
+}
+
+impl ProtocolAlert {
+	pub(crate) fn peer(&self) -> PeerId {
+		match self {
+			Self::Raised { peer, .. } | Self::Cleared { peer, .. } => *peer,
+		}
+	}
+
+	pub(crate) fn message(&self) -> String {
+		match self {
+			Self::Raised {
+				peer,
+				per_min,
+				dominant,
+				limited,
+			} => {
+				let action = if *limited {
+					"; refusing its requests until it slows down"
+				} else {
+					""
+				};
+				format!(
+					"{peer} sent {per_min} requests in the last minute, mostly {dominant}{action}"
+				)
+			}
+			Self::Cleared { peer, per_min } => {
+				format!("{peer} is back to {per_min} requests a minute")
+			}
+		}
+	}
+}
+
+/// A byte rate a peer is held to for a while.
+struct TrafficCap {
+	bytes_per_min: u64,
+	ticks_left: u64,
+}
+
+struct PeerWindow {
+	counters: Arc<PeerCounters>,
+	/// Counts of the last [`RATE_TICKS`] ticks, newest last.
+	ticks: VecDeque<Vec<(usize, ProtocolCounts)>>,
+	alerted: bool,
+	limited: bool,
+}
+
+impl PeerWindow {
+	fn last_minute(&self) -> HashMap<usize, ProtocolCounts> {
+		let mut sums = HashMap::<usize, ProtocolCounts>::new();
+		for (kind, counts) in self.ticks.iter().flatten() {
+			sums.entry(*kind).or_default().add(counts);
+		}
+		sums
+	}
+
+	fn bytes_last_minute(&self) -> u64 {
+		self.ticks
+			.iter()
+			.flatten()
+			.map(|(_, counts)| counts.bytes_in + counts.bytes_out)
+			.sum()
+	}
+
+	fn is_idle(&self) -> bool {
+		!self.alerted && self.ticks.iter().all(Vec::is_empty)
+	}
+}
+
+/// Rates, alerts and the limiter, owned by the event loop. Only the
+/// counters are shared with the tasks answering requests.
+#[derive(Default)]
+pub(crate) struct ProtocolMonitor {
+	peers: HashMap<PeerId, PeerWindow>,
+	/// Counts not yet written to the daily history.
+	unflushed: HashMap<(PeerId, usize), ProtocolCounts>,
+	/// What each peer read and wrote since [`Self::take_access`].
+	access: HashMap<PeerId, AccessSample>,
+	caps: HashMap<PeerId, TrafficCap>,
+	ticks: u32,
+}
+
+impl ProtocolMonitor {
+	/// Counters of `peer`, created on its first request.
+	pub(crate) fn counters(&mut self, peer: PeerId) -> Arc<PeerCounters> {
+		let window = self.peers.entry(peer).or_insert_with(|| PeerWindow {
+			counters: Arc::new(PeerCounters::new()),
+			ticks: VecDeque::new(),
+			alerted: false,
+			limited: false,
+		});
+		Arc::clone(&window.counters)
+	}
+
+	pub(crate) fn is_limited(&self, peer: &PeerId) -> bool {
+		self.peers.get(peer).is_some_and(|window| window.limited)
+	}
+
+	/// Refuses `peer` while its traffic over the last minute is above
+	/// `bytes_per_sec`, for the next `duration`.
+	pub(crate) fn cap(&mut self, peer: PeerId, bytes_per_sec: u64, duration: Duration) {
+		let ticks = duration.as_secs().div_ceil(PROTOCOL_STATS_TICK.as_secs());
+		self.caps.insert(
+			peer,
+			TrafficCap {
+				bytes_per_min: bytes_per_sec.saturating_mul(60),
+				ticks_left: ticks.max(1),
+			},
+		);
+	}
+
+	/// Rolls the counters since the last tick into the per-minute rates and
+	/// checks them against `limits`.
+	pub(crate) fn tick(&mut self, limits: ProtocolLimits) -> Vec<ProtocolAlert> {
+		self.ticks = self.ticks.wrapping_add(1);
+		self.caps.retain(|_, cap| {
+			cap.ticks_left -= 1;
+			cap.ticks_left > 0
+		});
+		let mut alerts = Vec::new();
+		for (peer, window) in &mut self.peers {
+			let counts = window.counters.take();
+			for (kind, counts) in &counts {
+				self.unflushed
+					.entry((*peer, *kind))
+					.or_default()
+					.add(counts);
+			}
+			let requests = counts.iter().map(|(_, counts)| counts.requests).sum();
+			let access = window.counters.take_access(requests);
+			if access.volume != AccessVolume::default() {
+				self.access.entry(*peer).or_default().add(access);
+			}
+			window.ticks.push_back(counts);
+			while window.ticks.len() > RATE_TICKS {
+				window.ticks.pop_front();
+			}
+			let last_minute = window.last_minute();
+			let per_min = last_minute.values().map(|counts| counts.requests).sum();
+			let over = limits.alert_per_min > 0 && per_min >= limits.alert_per_min;
+			if over && !window.alerted {
+				let dominant = last_minute
+					.iter()
+					.max_by_key(|(kind, counts)| (counts.requests, std::cmp::Reverse(**kind)))
+					.map(|(kind, _)| kind_name(*kind))
+					.unwrap_or(OTHER_REQUEST);
+				alerts.push(ProtocolAlert::Raised {
+					peer: *peer,
+					per_min,
+					dominant,
+					limited: limits.limiter,
+				});
+			} else if !over && window.alerted {
+				alerts.push(ProtocolAlert::Cleared {
+					peer: *peer,
+					per_min,
+				});
+			}
+			let capped = self
+				.caps
+				.get(peer)
+				.is_some_and(|cap| window.bytes_last_minute() > cap.bytes_per_min);
+			window.alerted = over;
+			window.limited = (over && limits.limiter) || capped;
+		}
+		self.peers
+			.retain(|_, window| !window.is_idle() || Arc::strong_count(&window.counters) > 1);
+		alerts
+	}
+
+	/// What each peer read and wrote since the last call.
+	pub(crate) fn take_access(&mut self) -> Vec<(PeerId, AccessSample)> {
+		self.access.drain().collect()
+	}
+
+	/// Whether this tick is one that writes the history.
+	pub(crate) fn history_due(&self) -> bool {
+		self.ticks.is_multiple_of(HISTORY_FLUSH_TICKS)
+	}
+
+	/// Counts since the last write of the history, by peer and request.
+	pub(crate) fn take_unflushed(&mut self) -> Vec<(String, &'static str, ProtocolCounts)> {
+		self.unflushed
+			.drain()
+			.map(|((peer, kind), counts)| (peer.to_string(), kind_name(kind), counts))
+			.collect()
+	}
+
+	/// Traffic of the last minute, busiest peer and request first.
+	pub(crate) fn rates(&self) -> Vec<ProtocolRate> {
+		let mut rates = self
+			.peers
+			.iter()
+			.flat_map(|(peer, window)| {
+				window
+					.last_minute()
+					.into_iter()
+					.map(move |(kind, last_minute)| ProtocolRate {
+						peer: peer.to_string(),
+						request: kind_name(kind).to_string(),
+						last_minute,
+						limited: window.limited,
+					})
+			})
+			.collect::<Vec<_>>();
+		rates.sort_by(|a, b| {
+			b.last_minute
+				.requests
+				.cmp(&a.last_minute.requests)
+				.then_with(|| a.peer.cmp(&b.peer))
+				.then_with(|| a.request.cmp(&b.request))
+		});
+		rates
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn burst(counters: &PeerCounters, kind: &str, count: u64) {
+		let kind = kind_index(kind);
+		for _ in 0..count {
+			counters.received(kind, 40);
+			counters.answered(kind, 200, Answer::Ok);
+		}
+	}
+
+	#[test]
+	fn a_burst_raises_the_alert_and_engages_the_limiter_until_it_stops() {
+		let mut monitor = ProtocolMonitor::default();
+		let peer = PeerId::random();
+		let limits = ProtocolLimits {
+			alert_per_min: 100,
+			limiter: true,
+		};
+		let counters = monitor.counters(peer);
+
+		burst(&counters, "ListDir", 30);
+		assert!(monitor.tick(limits).is_empty());
+		assert!(!monitor.is_limited(&peer));
+
+		burst(&counters, "ListDir", 80);
+		burst(&counters, "PeerInfo", 5);
+		assert_eq!(
+			monitor.tick(limits),
+			vec![ProtocolAlert::Raised {
+				peer,
+				per_min: 115,
+				dominant: "ListDir",
+				limited: true,
+			}]
+		);
+		assert!(monitor.is_limited(&peer));
+
+		// Refusals are counted and keep the rate up while the peer keeps
+		// asking.
+		let list_dir = kind_index("ListDir");
+		counters.received(list_dir, 40);
+		counters.answered(list_dir, 30, Answer::RateLimited);
+		assert!(monitor.tick(limits).is_empty());
+		assert!(monitor.is_limited(&peer));
+		let rates = monitor.rates();
+		assert_eq!(rates[0].request, "ListDir");
+		assert_eq!(rates[0].last_minute.requests, 111);
+		assert_eq!(rates[0].last_minute.rate_limited, 1);
+		assert!(rates[0].limited);
+
+		// Once the burst falls out of the minute, both let go.
+		let mut cleared = Vec::new();
+		for _ in 0..RATE_TICKS {
+			cleared.extend(monitor.tick(limits));
+		}
+		assert_eq!(cleared, vec![ProtocolAlert::Cleared { peer, per_min: 86 }]);
+		assert!(!monitor.is_limited(&peer));
+	}
+
+	#[test]
+	fn without_the_limiter_only_the_alert_engages() {
+		let mut monitor = ProtocolMonitor::default();
+		let peer = PeerId::random();
+		let limits = ProtocolLimits {
+			alert_per_min: 10,
+			limiter: false,
+		};
+		burst(&monitor.counters(peer), "ReadFile", 10);
+		let alerts = monitor.tick(limits);
+		assert!(matches!(
+			alerts.as_slice(),
+			[ProtocolAlert::Raised {
+				dominant: "ReadFile",
+				limited: false,
+				..
+			}]
+		));
+		assert!(alerts[0].message().contains("mostly ReadFile"));
+		assert!(!monitor.is_limited(&peer));
+
+		// A threshold of 0 turns checks off.
+		let off = ProtocolLimits {
+			alert_per_min: 0,
+			limiter: true,
+		};
+		burst(&monitor.counters(peer), "ReadFile", 1000);
+		assert!(matches!(
+			monitor.tick(off).as_slice(),
+			[ProtocolAlert::Cleared { .. }]
+		));
+		assert!(!monitor.is_limited(&peer));
+	}
+
+	#[test]
+	fn history_gets_each_count_once() {
+		let mut monitor = ProtocolMonitor::default();
+		let peer = PeerId::random();
+		let counters = monitor.counters(peer);
+		burst(&counters, "ListDir", 3);
+		counters.sent(kind_index("PeerInfo"), 12);
+		monitor.tick(ProtocolLimits::default());
+		burst(&counters, "ListDir", 2);
+		monitor.tick(ProtocolLimits::default());
+
+		let mut rows = monitor.take_unflushed();
+		rows.sort_by_key(|(_, request, _)| *request);
+		assert_eq!(rows.len(), 2);
+		assert_eq!(rows[0].1, "ListDir");
+		assert_eq!(rows[0].2.requests, 5);
+		assert_eq!(rows[0].2.bytes_in, 5 * 40);
+		assert_eq!(rows[0].2.bytes_out, 5 * 200);
+		assert_eq!(rows[1].1, "PeerInfo");
+		assert_eq!(rows[1].2.sent, 1);
+		assert!(monitor.take_unflushed().is_empty());
+	}
+
+	#[test]
+	fn access_counts_only_the_peers_own_requests() {
+		let mut monitor = ProtocolMonitor::default();
+		let peer = PeerId::random();
+		let counters = monitor.counters(peer);
+		burst(&counters, "ReadFile", 3);
+		counters.accessed("/srv/photos", 3 * 240);
+		// Our own request and its answer aren't the peer's access.
+		counters.sent(kind_index("ReadFile"), 50);
+		counters.response(kind_index("ReadFile"), 5000);
+		monitor.tick(ProtocolLimits::default());
+
+		let access = monitor.take_access();
+		assert_eq!(access.len(), 1);
+		let (accessed_by, sample) = &access[0];
+		assert_eq!(*accessed_by, peer);
+		assert_eq!(
+			sample.volume,
+			AccessVolume {
+				read: 3 * 200,
+				written: 3 * 40,
+				requests: 3,
+			}
+		);
+		assert_eq!(sample.folders["/srv/photos"], 3 * 240);
+		monitor.tick(ProtocolLimits::default());
+		assert!(monitor.take_access().is_empty());
+	}
+
+	#[test]
+	fn a_cap_refuses_the_peer_while_over_it_until_it_runs_out() {
+		let mut monitor = ProtocolMonitor::default();
+		let peer = PeerId::random();
+		let limits = ProtocolLimits::default();
+		// 10 bytes a second is 600 a minute; each request in a burst is 240.
+		monitor.cap(peer, 10, PROTOCOL_STATS_TICK * 3);
+		let counters = monitor.counters(peer);
+		burst(&counters, "ReadFile", 2);
+		monitor.tick(limits);
+		assert!(!monitor.is_limited(&peer));
+		burst(&counters, "ReadFile", 1);
+		monitor.tick(limits);
+		assert!(monitor.is_limited(&peer));
+		assert!(monitor.rates()[0].limited);
+		monitor.tick(limits);
+		assert!(!monitor.is_limited(&peer), "the cap ran out");
+	}
+
+	#[test]
+	fn unknown_requests_share_one_slot() {
+		assert_eq!(kind_index("NoSuchRequest"), kind_names().len());
+		assert_eq!(kind_name(kind_index("NoSuchRequest")), OTHER_REQUEST);
+		assert_eq!(kind_name(kind_index("ListDir")), "ListDir");
+		assert_eq!(wire_len(&"abc"), 5);
+	}
+}
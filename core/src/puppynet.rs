@@ -1,31 +1,194 @@
-use crate::FileChunk;
-use crate::app::{App, Command, ReadFileCmd};
+use crate::access_volume::{ACCESS_ALERT_SETTING, AccessAlertLimits};
+use crate::activity_log::{
+	self, ACTIVITY_RETENTION_SETTING, ActivityEvent, ActivityFilter, ActivityLog, MAX_ACTIVITY_PAGE,
+};
+use crate::activity_window::{ActivityKind, ActivityWindow, DeferredActivity};
+use crate::app::{App, AppDeps, Command, ReadFileCmd, inbox_dir, peer_to_node_id};
 use crate::auth;
+use crate::backup::{
+	BACKUP_CHECK_INTERVAL, BACKUP_SETTINGS_SETTING, BackupKind, BackupRun, BackupSettings,
+	scheduled_backup_due, swap_in_database, verify_database, write_backup,
+};
+use crate::checksum_manifest::{HashMismatch, MANIFEST_PATTERNS_SETTING, patterns_from_setting};
+use crate::client::{NodeTransport, PeerTransport};
+use crate::clock::SystemClock;
+use crate::clock_skew::{ClockOffset, ClockOffsetRecord};
+use crate::config::{self, EffectiveConfig};
+use crate::config_snapshot::{
+	self, ConfigRollback, ConfigSnapshot, ConfigSnapshotInfo, ConfigState, UndoableChange,
+};
+use crate::contact_sheet::{ContactSheetOptions, ContactSheetResult};
+use crate::content_negotiation::{self, SendSavings, Upload};
+use crate::content_store::{ContentRef, ContentStore, StoreGcReport, StoreMode};
+use crate::cors::{CORS_SETTING, CorsSettings};
 use crate::db::{
-	FileEntry, StorageUsageFile, delete_session, get_file_entry, get_file_location, get_your_node,
-	load_discovered_peers, load_peers, load_user, load_users, lookup_session_username, open_db,
-	run_migrations, save_session, save_user,
+	FILE_ENTRY_COLUMNS, FileEntry, NodeID, ScanDiffEntry, ScanRun, ScanTrend, SearchQuery,
+	StorageUsageFile, bump_index_generation, check_offset, configure_connection, confirm_wake_mac,
+	count_index_changes, count_pending_delete_proposals, count_pending_reviews, cursor_page,
+	db_path, decide_reviews, delete_outbox_rule, delete_pin, delete_replication, delete_session,
+	delete_setting, demote_node, expire_delete_proposals, failed_logins_since,
+	find_previous_local_node, forget_node_index, get_file_entry, get_file_location, get_your_node,
+	index_generation, indexed_hash, insert_outbox_rule, insert_pin, is_subscribed_to_index,
+	last_download_of, last_successful_backup, load_activity, load_backup_runs, load_clock_offsets,
+	load_config_snapshot, load_config_snapshots, load_delete_proposals, load_discovered_peers,
+	load_hash_mismatches, load_local_node_name, load_login_history, load_media_metadata,
+	load_outbox_rules, load_peer_trust, load_peers, load_pending_delete_proposals,
+	load_pending_reviews, load_pins, load_protocol_stats, load_replication, load_replications,
+	load_scan_history, load_setting, load_transfers, load_user, load_users, load_wake_target,
+	lookup_session_username, record_backup_run, record_delete_proposal, record_login_attempts,
+	run_migrations, save_index_subscription, save_replication, save_session, save_setting,
+	save_user, scan_diff, scan_trend, set_outbox_rule_paused, set_pending_review_hash,
+	set_pin_paused, skip_cursor, update_outbox_rule,
+};
+use crate::db_pool::Db;
+use crate::deletion::{
+	self, DELETE_PROPOSAL_EXPIRY_SETTING, DeleteDecision, DeleteProposal, DeletionStatus,
+	ProposalHolder, ReceivedProposal,
+};
+use crate::demo::{self, DemoApp, DemoFixture};
+use crate::diagnostics::{self, DiagnosticsReport};
+use crate::diff::{
+	BlockTally, DIFF_BLOCK_SIZE, DiffOptions, FileDiff, FileRef, blocks_to_compare, diff_contents,
+};
+use crate::discovered::{DISCOVERED_ADDRESS_TTL_SETTING, DiscoveredPeerFilter, DiscoveredPeerInfo};
+use crate::disk_history::{self, DiskSample, LOW_SPACE_PERCENT_SETTING};
+use crate::event_channel::{event_channel, relay, send_blocking};
+use crate::fan_out::{FanOutOptions, fan_out};
+use crate::file_read::{
+	self, ChunkReader, DEFAULT_READ_CHUNK_SIZE, FileContents, ReadToEndOptions,
+};
+use crate::format::{SizeUnits, abbrev_peer_id, human_size};
+use crate::free_space::check_fits;
+use crate::grant_cache::{GRANT_CACHE_TTL_SETTING, RemoteGrants};
+use crate::http_proxy::{
+	self, HTTP_PROXY_SETTING, HttpClient, HttpProxySettings, ProxyCredentials, normalize_proxy_url,
+};
+use crate::identity::{IdentityMismatch, adopt_node_identity};
+use crate::ids::{IdAllocator, IdKind};
+use crate::image_decode::{THUMBNAIL_DECODE_BUDGET_SETTING, budget_from_setting};
+use crate::index::{FileIndex, extract_media_metadata, storage_files_of};
+use crate::ingest::{
+	self, DRIVE_POLL_INTERVAL, DiskSource, DriveChange, INGEST_SETTINGS_SETTING, IngestProgress,
+	IngestReport, IngestSettings, RemovableDrive, RemovableDrives,
+};
+use crate::integrity::{
+	self, CycleReport, INTEGRITY_CYCLE_INTERVAL, INTEGRITY_SETTINGS_SETTING, IntegrityFinding,
+	IntegritySettings, IntegrityStatus,
+};
+use crate::keepalive::{ConnectionPolicy, IDLE_CONNECTION_TIMEOUT_SETTING, PING_INTERVAL_SETTING};
+use crate::locations::{FolderKind, LocationEnv, WellKnownFolder};
+use crate::login_guard::{
+	FailedLoginGroup, LOGIN_AUDIT_FLUSH_INTERVAL, LOGIN_LIMITS_SETTING, LoginAttempt, LoginGate,
+	LoginGuard, LoginLimits, LoginOutcome, LoginSource, clip_audit_field,
+};
+use crate::maintenance::{
+	MAINTENANCE_CHECKPOINT_GRACE, MAINTENANCE_SETTING, MAINTENANCE_SETTLE, Maintenance,
+	MaintenanceGate, MaintenanceReport, checkpoint_wal,
+};
+use crate::media_metadata::{MediaExtractReport, MediaMetadata};
+use crate::mounts::{MountTable, ShareChange};
+use crate::nat::NatStatus;
+use crate::outbox::{
+	OUTBOX_CHECK_INTERVAL, OutboxRule, OutboxRuns, OutboxStatus, start_due_outboxes,
 };
 use crate::p2p::{
-	AudioCapability, AudioDevice, CpuInfo, DesktopInput, DirEntry, DiskInfo, InterfaceInfo,
-	LiveSearchArgs, MediaCapability, MediaFrame, MediaSource, PeerInfo, PermissionGrant,
-	SearchEvent, Thumbnail, grant_from_permission, permission_from_grant,
+	AudioCapability, AudioDevice, BrowseRootKind, BrowseRoots, CpuInfo, DesktopInput, DirEntry,
+	DirListing, DiskInfo, FEATURE_CONTACT_SHEET, FEATURE_DISK_HISTORY, FEATURE_LIST_ROOTS,
+	FEATURE_PAIRING, FEATURE_RESTART, FEATURE_SEARCH_FILES, FEATURE_SHARE_SUMMARY,
+	FEATURE_WELL_KNOWN_FOLDERS, InterfaceInfo, LiveSearchArgs, MediaCapability, MediaFrame,
+	MediaSource, PeerCapabilities, PeerHealth, PeerInfo, PermissionGrant, SearchEvent, Thumbnail,
+	WirePath, grant_from_permission, node_keypair, permission_from_grant,
+};
+use crate::pagination::{CursorPage, PageCursor};
+use crate::pairing::Pairing;
+use crate::pairing_invite::{PairedWith, PairingInfo, PairingInvite};
+use crate::password_policy::{
+	CreateUserError, PASSWORD_POLICY_SETTING, PasswordPolicy, UserFieldError,
+};
+use crate::path::SafePath;
+use crate::peer_search::{self, PEER_SEARCH_TIMEOUT, PeerSearch};
+use crate::permission_draft::{DraftRejected, PermissionDraft};
+use crate::pins::{
+	MIN_PIN_INTERVAL, PIN_CHECK_INTERVAL, PinOptions, PinRuns, PinStatus, start_due_syncs,
+};
+use crate::power::{
+	POWER_CHECK_INTERVAL, POWER_POLICY_SETTING, PowerGate, PowerPolicy, PowerSource, PowerState,
+	SystemPower,
+};
+use crate::protocol_stats::{
+	self, PROTOCOL_ALERT_SETTING, PROTOCOL_LIMITER_SETTING, ProtocolDay, ProtocolLimits,
+	ProtocolRate,
 };
-use crate::scan::ScanEvent;
-use crate::state::{FLAG_READ, FLAG_SEARCH, FLAG_WRITE, Peer, Permission, State};
-use crate::updater::{self, UpdateProgress};
+use crate::reachability::AddressReachability;
+use crate::remote_ops::{RemoteOps, RemoteOpsStats};
+use crate::replication::{
+	REPLICATION_CHECK_INTERVAL, ReplicationRole, ReplicationRuns, ReplicationStatus,
+	start_due_replications,
+};
+use crate::request_trace::{RequestLog, RequestTrace};
+use crate::review::{PeerTrust, PendingReview, ReviewDecision};
+use crate::scan::{self, FileHash, ScanEvent, TOMBSTONE_RETENTION_SETTING};
+use crate::scan_limits::{
+	NodeScanLimits, SCAN_LOW_PRIORITY_SETTING, SCAN_MAX_READ_MIB_SETTING, SCAN_MAX_READS_SETTING,
+	ScanOverrides,
+};
+use crate::scan_results::{
+	self, MIRROR_REMOTE_SCANS_SETTING, SCAN_RESULTS_PULL_CAP, ScanResultsPull,
+	load_scan_result_pull, load_scan_result_pulls, receive_scan_results, save_scan_result_pull,
+};
+use crate::secrets::{HTTP_PROXY_SECRET, MemorySecretStore, SecretBackend, Secrets};
+use crate::share_summary::{ShareSummary, ShareSummaryCache};
+use crate::sparse::SparseSink;
+use crate::state::{
+	BatchGrantOutcome, Connection, DiscoveredPeer, FLAG_PREVIEW, FLAG_READ, FLAG_SEARCH,
+	FLAG_WRITE, FolderRule, FullStateSnapshot, Peer, Permission, PermissionSet, Rule, RuleOverlap,
+	State, TemporaryGrant, TrustLevel,
+};
+use crate::storage_growth::{self, GrowthReport};
+use crate::storage_tree::{StorageExportFormat, StorageTree, write_storage_export};
+use crate::throughput::RateEstimator;
+use crate::thumbnail_cache::{PREVIEW_MAX_DIMENSION_SETTING, preview_max_from_setting};
+use crate::thumbnail_pregen::{
+	self as thumbnail_pregen, THUMBNAIL_PREGEN_SETTING, ThumbnailQueue, ThumbnailQueueStatus,
+};
+use crate::transfers::{
+	DOWNLOAD_DIR_SETTING, DownloadOutcome, Transfer, TransferDirection, TransferProgress,
+	peer_download_dir_setting, remote_file_name, resolve_download_dir, unique_destination,
+};
+use crate::updater::{self, UpdateProgress, UpdateRetryPolicy, classify_update_error};
 use crate::version;
+use crate::wake::{self, DidNotWake, WakeTarget, format_mac, parse_mac, wait_until_connected};
+use crate::{FileChunk, ShellOutput};
 use anyhow::{Result, anyhow, bail};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use futures::executor::block_on;
 use libp2p::PeerId;
-use rusqlite::{Connection as SqliteConnection, params};
-use std::collections::HashMap;
+use rusqlite::Connection as SqliteConnection;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, mpsc};
-use tokio::sync::{mpsc::UnboundedSender, oneshot};
+use std::time::Duration;
+use tokio::sync::mpsc::{Receiver, UnboundedSender};
+use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+const SEND_FILE_CHUNK_SIZE: usize = 1024 * 1024;
+pub const SCAN_HISTORY_PAGE_SIZE: u64 = 50;
+pub const LOGIN_HISTORY_PAGE_SIZE: u64 = 50;
+pub const TRANSFER_PAGE_SIZE: u64 = 50;
+const ACTIVITY_WINDOW_SETTING: &str = "activity_window";
+/// Longest sleep between activity window checks, so edits to the window
+/// apply to waiting work without much delay.
+const ACTIVITY_WINDOW_POLL: Duration = Duration::from_secs(60);
+/// Longest deferred work waits to notice it was cancelled.
+const DEFERRED_CANCEL_POLL: Duration = Duration::from_secs(1);
+/// Delay before a requested restart, leaving time for the acknowledgement.
+const RESTART_DELAY_SECS: u64 = 2;
+const RESTART_RECONNECT_POLL: Duration = Duration::from_secs(2);
+const RESTART_RECONNECT_TIMEOUT: Duration = Duration::from_secs(120);
 
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct ScanResultRow {
@@ -36,20 +199,28 @@ pub struct ScanResultRow {
 	pub latest_datetime: Option<String>,
 }
 
+/// A running scan. Events arrive on a bounded channel; a local scan skips
+/// progress while its consumer falls behind, always delivers its final
+/// event, and is cancelled only once the handle and its receiver are
+/// dropped.
 #[derive(Clone)]
 pub struct ScanHandle {
-	receiver: Arc<Mutex<mpsc::Receiver<ScanEvent>>>,
+	receiver: Arc<tokio::sync::Mutex<Receiver<ScanEvent>>>,
 	cancel_flag: Arc<AtomicBool>,
 }
 
 impl ScanHandle {
-	pub fn receiver(&self) -> Arc<Mutex<mpsc::Receiver<ScanEvent>>> {
+	pub fn receiver(&self) -> Arc<tokio::sync::Mutex<Receiver<ScanEvent>>> {
 		Arc::clone(&self.receiver)
 	}
 
 	pub fn cancel(&self) {
 		self.cancel_flag.store(true, Ordering::SeqCst);
 	}
+
+	pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+		Arc::clone(&self.cancel_flag)
+	}
 }
 
 #[derive(Clone, Debug)]
@@ -62,43 +233,745 @@ pub struct PuppyNet {
 	shutdown_tx: Option<oneshot::Sender<()>>,
 	handle: JoinHandle<()>,
 	cmd_tx: UnboundedSender<Command>,
-	db: Arc<Mutex<SqliteConnection>>,
-	remote_scans: Arc<Mutex<HashMap<u64, mpsc::Sender<ScanEvent>>>>,
-	remote_scan_counter: AtomicU64,
+	db: Arc<Db>,
+	/// The index half of the node, over `db`; searches go through it.
+	index: FileIndex,
+	remote_scans: Arc<RemoteOps<ScanEvent>>,
 	remote_searches: Arc<Mutex<HashMap<u64, mpsc::Sender<SearchEvent>>>>,
-	remote_search_counter: AtomicU64,
-	remote_updates: Arc<Mutex<HashMap<u64, mpsc::Sender<UpdateProgress>>>>,
-	remote_update_counter: AtomicU64,
+	remote_updates: Arc<RemoteOps<UpdateProgress>>,
+	store: Arc<ContentStore>,
+	runtime: tokio::runtime::Handle,
+	ids: IdAllocator,
+	activity_window: Arc<Mutex<ActivityWindow>>,
+	/// Holds background work back on battery.
+	power: Arc<Mutex<PowerGate>>,
+	/// Refuses changes while maintenance lasts and knows the work it waits
+	/// for.
+	maintenance: Arc<MaintenanceGate>,
+	deferred: Arc<Mutex<DeferredWork>>,
+	login_guard: Arc<Mutex<LoginGuard>>,
+	backup_settings: Arc<Mutex<BackupSettings>>,
+	cors_settings: Mutex<CorsSettings>,
+	/// Share summaries fetched from peers.
+	share_summaries: Mutex<ShareSummaryCache>,
+	pins: Arc<PinRuns>,
+	pin_wake: Arc<tokio::sync::Notify>,
+	outboxes: Arc<OutboxRuns>,
+	outbox_wake: Arc<tokio::sync::Notify>,
+	replications: Arc<ReplicationRuns>,
+	replication_wake: Arc<tokio::sync::Notify>,
+	request_log: Arc<RequestLog>,
+	secrets: Secrets,
+	thumbnail_queue: Arc<ThumbnailQueue>,
+	/// Request rates by peer as of the last rollup.
+	protocol_rates: Arc<Mutex<Vec<ProtocolRate>>>,
+	/// The last bulk change, offered for undo while it is recent.
+	last_change: Mutex<Option<UndoableChange>>,
+	/// Removable drives as of the last look, every [`DRIVE_POLL_INTERVAL`].
+	drives: Arc<Mutex<RemovableDrives>>,
+	disk_source: Arc<dyn DiskSource>,
+	/// Read by the sampler before every cycle.
+	integrity_settings: Arc<Mutex<IntegritySettings>>,
+	/// Read by running scans as they go.
+	scan_limits: Arc<NodeScanLimits>,
+	/// Made-up peers and data instead of the network.
+	demo: bool,
+}
+
+/// What a password login came to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoginResult {
+	Success,
+	InvalidCredentials,
+	/// Too many attempts from this address or for this username.
+	Throttled {
+		retry_after_secs: u64,
+	},
+	/// Too many failures for this username in a row.
+	LockedOut {
+		retry_after_secs: u64,
+	},
+}
+
+/// A setting stored as JSON, or the default when it is missing or invalid.
+fn load_json_setting<T: serde::de::DeserializeOwned + Default>(
+	conn: &SqliteConnection,
+	key: &str,
+	what: &str,
+) -> T {
+	match load_setting(conn, key) {
+		Ok(Some(value)) => serde_json::from_str(&value).unwrap_or_else(|err| {
+			tracing::warn!("ignoring invalid {what}: {err}");
+			T::default()
+		}),
+		Ok(None) => T::default(),
+		Err(err) => {
+			tracing::error!("failed to load {what}: {err}");
+			T::default()
+		}
+	}
+}
+
+/// The stored HTTP proxy with its credentials from the secret store.
+fn load_http_proxy(conn: &SqliteConnection, secrets: &Secrets) -> HttpProxySettings {
+	let mut settings: HttpProxySettings =
+		load_json_setting(conn, HTTP_PROXY_SETTING, "HTTP proxy setting");
+	settings.credentials = match secrets.get(HTTP_PROXY_SECRET) {
+		Ok(Some(value)) => serde_json::from_str(&value)
+			.map_err(|err| tracing::warn!("ignoring invalid HTTP proxy credentials: {err}"))
+			.ok(),
+		Ok(None) => None,
+		Err(err) => {
+			tracing::error!("failed to load HTTP proxy credentials: {err:#}");
+			None
+		}
+	};
+	settings
+}
+
+/// Writes queued login audit rows. Rows are dropped if the write fails, so
+/// a broken database cannot grow the queue.
+fn flush_login_audit(guard: &Mutex<LoginGuard>, db: &Db) {
+	let attempts = guard.lock().unwrap().take_pending(Utc::now());
+	if attempts.is_empty() {
+		return;
+	}
+	match db.lock() {
+		Ok(conn) => {
+			if let Err(err) = record_login_attempts(&conn, &attempts) {
+				tracing::error!(
+					"failed to record {} login attempt(s): {err}",
+					attempts.len()
+				);
+			}
+		}
+		Err(err) => tracing::error!("db lock poisoned while recording login attempts: {err}"),
+	}
+}
+
+/// Writes a backup into `dest_dir` and records the run. Blocks for the
+/// length of the copy.
+fn run_backup(db: &Db, dest_dir: &Path, keep: usize, scheduled: bool) -> BackupRun {
+	let started_at = Utc::now();
+	let result = write_backup(&db_path(), dest_dir, keep, started_at);
+	let run = BackupRun {
+		kind: BackupKind::Backup,
+		started_at,
+		finished_at: Utc::now(),
+		path: match &result {
+			Ok(path) => path.display().to_string(),
+			Err(_) => dest_dir.display().to_string(),
+		},
+		size_bytes: result
+			.as_ref()
+			.ok()
+			.and_then(|path| std::fs::metadata(path).ok())
+			.map(|meta| meta.len()),
+		scheduled,
+		error: result.err().map(|err| format!("{err:#}")),
+	};
+	match db.lock() {
+		Ok(conn) => {
+			if let Err(err) = record_backup_run(&conn, &run) {
+				tracing::error!("failed to record backup run: {err}");
+			}
+		}
+		Err(err) => tracing::error!("db lock poisoned while recording backup run: {err}"),
+	}
+	run
+}
+
+/// Takes the daily backup when it is due, the activity window allows
+/// scans, the closest kind of heavy disk work, and there is no power hold.
+fn run_scheduled_backup(
+	db: &Db,
+	settings: &Mutex<BackupSettings>,
+	window: &Mutex<ActivityWindow>,
+	power: &Mutex<PowerGate>,
+) {
+	let settings = settings.lock().unwrap().clone();
+	let now = Utc::now();
+	if !settings.scheduled
+		|| !window.lock().unwrap().allows(ActivityKind::Scan, now)
+		|| power.lock().unwrap().holds()
+	{
+		return;
+	}
+	let last_success = match db.lock() {
+		Ok(conn) => last_successful_backup(&conn).unwrap_or_else(|err| {
+			tracing::error!("failed to read last backup: {err}");
+			None
+		}),
+		Err(err) => {
+			tracing::error!("db lock poisoned while checking backups: {err}");
+			return;
+		}
+	};
+	if !scheduled_backup_due(&settings, last_success, now.with_timezone(&chrono::Local)) {
+		return;
+	}
+	let run = run_backup(db, &settings.backup_dir(), settings.keep, true);
+	match run.error {
+		Some(err) => tracing::error!("scheduled backup failed: {err}"),
+		None => tracing::info!("scheduled backup written to {}", run.path),
+	}
+}
+
+/// Work waiting for its activity window, keyed by kind and id because scan
+/// and update ids are allocated separately and repeat across kinds.
+type DeferredWork = HashMap<(ActivityKind, u64), DeferredEntry>;
+
+/// Deferred work and the flag that cancels it while it waits.
+struct DeferredEntry {
+	activity: DeferredActivity,
+	cancel_flag: Arc<AtomicBool>,
+}
+
+/// Blocks until `kind` may run, re-reading the window on every check.
+/// Returns false when `cancel_flag` is set while waiting.
+fn wait_for_activity_window(
+	window: &Mutex<ActivityWindow>,
+	kind: ActivityKind,
+	cancel_flag: &AtomicBool,
+) -> bool {
+	loop {
+		if cancel_flag.load(Ordering::SeqCst) {
+			return false;
+		}
+		let now = Utc::now();
+		let Some(opening) = window.lock().unwrap().next_opening(kind, now) else {
+			return true;
+		};
+		let wait = (opening - now).to_std().unwrap_or_default();
+		std::thread::sleep(wait.min(DEFERRED_CANCEL_POLL));
+	}
+}
+
+/// Blocks while the power hold lasts.
+fn wait_for_power(power: &Mutex<PowerGate>) {
+	while power.lock().unwrap().holds() {
+		std::thread::sleep(POWER_CHECK_INTERVAL);
+	}
+}
+
+/// In hard-stop mode, sets `cancel_flag` once the window closes for `kind`.
+fn cancel_when_window_closes(
+	window: Arc<Mutex<ActivityWindow>>,
+	kind: ActivityKind,
+	cancel_flag: Arc<AtomicBool>,
+) {
+	std::thread::spawn(move || {
+		loop {
+			if cancel_flag.load(Ordering::SeqCst) {
+				return;
+			}
+			let now = Utc::now();
+			let closing = {
+				let window = window.lock().unwrap();
+				if !window.hard_stop || !window.governs(kind) {
+					return;
+				}
+				if !window.allows(kind, now) {
+					tracing::info!("activity window closed; stopping {}", kind.label());
+					cancel_flag.store(true, Ordering::SeqCst);
+					return;
+				}
+				window.next_closing(kind, now)
+			};
+			let wait = closing
+				.and_then(|closing| (closing - now).to_std().ok())
+				.unwrap_or(ACTIVITY_WINDOW_POLL);
+			std::thread::sleep(wait.min(ACTIVITY_WINDOW_POLL));
+		}
+	});
+}
+
+fn start_update(
+	is_self: bool,
+	peer: PeerId,
+	version: Option<String>,
+	update_id: u64,
+	tx: UnboundedSender<UpdateProgress>,
+	remote_updates: &RemoteOps<UpdateProgress>,
+	cmd_tx: &UnboundedSender<Command>,
+) -> Result<(), String> {
+	if is_self {
+		// Perform local self-update
+		let current_version = version::version_number();
+
+		std::thread::spawn(move || {
+			let rt = tokio::runtime::Runtime::new().unwrap();
+			rt.block_on(async move {
+				let progress_tx = tx.clone();
+				let result = updater::update_with_progress(
+					version.as_deref(),
+					current_version,
+					move |progress| {
+						let _ = progress_tx.send(progress);
+					},
+				)
+				.await;
+
+				if let Err(e) = result {
+					let _ = tx.send(UpdateProgress::failed(&e));
+				}
+			});
+		});
+	} else {
+		// Remote update - send command to dial peer
+		remote_updates
+			.insert(update_id, tx, Utc::now())
+			.map_err(|err| err.to_string())?;
+		cmd_tx
+			.send(Command::RemoteUpdate {
+				peer,
+				version,
+				update_id,
+			})
+			.map_err(|e| {
+				remote_updates.remove(update_id);
+				format!("failed to send RemoteUpdate command: {e}")
+			})?;
+	}
+	Ok(())
 }
 
 impl PuppyNet {
 	pub fn new() -> Self {
-		let state = State::default();
-		let db = Arc::new(Mutex::new(open_db()));
+		if let Some(seed) = demo::seed_from_env() {
+			tracing::warn!(
+				"{} is set; running in demo mode with seed {seed}",
+				demo::DEMO_ENV
+			);
+			return Self::new_demo(seed);
+		}
+		let mut state = State::default();
+		let db = Arc::new(Db::open());
 		{
 			let mut conn = db.lock().unwrap();
 			if let Err(err) = run_migrations(&mut conn) {
-				log::error!("failed to run database migrations: {err}");
+				tracing::error!("failed to run database migrations: {err}");
 			}
 		}
 		// channel to request shutdown
 		let (shutdown_tx, shutdown_rx) = oneshot::channel();
-		let remote_scans = Arc::new(Mutex::new(HashMap::new()));
+		let remote_scans = Arc::new(RemoteOps::new("scan"));
 		let remote_searches = Arc::new(Mutex::new(HashMap::new()));
-		let remote_updates = Arc::new(Mutex::new(HashMap::new()));
+		let remote_updates = Arc::new(RemoteOps::new("update"));
+		let store = Arc::new(ContentStore::new(ContentStore::default_dir(), db.clone()));
+		let activity_window: ActivityWindow = load_json_setting(
+			&db.lock().unwrap(),
+			ACTIVITY_WINDOW_SETTING,
+			"activity window",
+		);
+		let power_policy: PowerPolicy =
+			load_json_setting(&db.lock().unwrap(), POWER_POLICY_SETTING, "power policy");
+		let maintenance: Option<Maintenance> =
+			load_json_setting(&db.lock().unwrap(), MAINTENANCE_SETTING, "maintenance");
+		if let Some(maintenance) = &maintenance {
+			tracing::warn!(
+				"still in maintenance since {}: {}",
+				maintenance.since,
+				maintenance.reason
+			);
+		}
+		let login_limits: LoginLimits =
+			load_json_setting(&db.lock().unwrap(), LOGIN_LIMITS_SETTING, "login limits");
+		let backup_settings: BackupSettings = load_json_setting(
+			&db.lock().unwrap(),
+			BACKUP_SETTINGS_SETTING,
+			"backup settings",
+		);
+		let integrity_settings: IntegritySettings = load_json_setting(
+			&db.lock().unwrap(),
+			INTEGRITY_SETTINGS_SETTING,
+			"integrity settings",
+		);
+		let cors_settings =
+			load_json_setting::<CorsSettings>(&db.lock().unwrap(), CORS_SETTING, "CORS settings")
+				.with_env();
+		let secrets = Secrets::open(&db.lock().unwrap());
+		http_proxy::apply(load_http_proxy(&db.lock().unwrap(), &secrets));
+		let login_guard = Arc::new(Mutex::new(LoginGuard::new(login_limits)));
+		{
+			let guard = Arc::downgrade(&login_guard);
+			let db = db.clone();
+			tokio::spawn(async move {
+				let mut interval = tokio::time::interval(LOGIN_AUDIT_FLUSH_INTERVAL);
+				loop {
+					interval.tick().await;
+					let Some(guard) = guard.upgrade() else {
+						break;
+					};
+					flush_login_audit(&guard, &db);
+				}
+			});
+		}
+		let activity_window = Arc::new(Mutex::new(activity_window));
+		let power = Arc::new(Mutex::new(PowerGate::new(power_policy)));
+		let maintenance = Arc::new(MaintenanceGate::new(maintenance));
+		let backup_settings = Arc::new(Mutex::new(backup_settings));
+		{
+			let settings = Arc::downgrade(&backup_settings);
+			let window = Arc::clone(&activity_window);
+			let power = Arc::clone(&power);
+			let maintenance = Arc::clone(&maintenance);
+			let db = db.clone();
+			tokio::spawn(async move {
+				let mut interval = tokio::time::interval(BACKUP_CHECK_INTERVAL);
+				loop {
+					interval.tick().await;
+					let Some(settings) = settings.upgrade() else {
+						break;
+					};
+					if maintenance.is_active() {
+						continue;
+					}
+					let (db, window, power) = (db.clone(), window.clone(), power.clone());
+					let _ = tokio::task::spawn_blocking(move || {
+						run_scheduled_backup(&db, &settings, &window, &power)
+					})
+					.await;
+				}
+			});
+		}
+		let drives = Arc::new(Mutex::new(RemovableDrives::default()));
+		let disk_source: Arc<dyn DiskSource> = Arc::new(App::collect_disk_info);
+		{
+			let drives = Arc::downgrade(&drives);
+			let disk_source = Arc::clone(&disk_source);
+			tokio::spawn(async move {
+				let mut interval = tokio::time::interval(DRIVE_POLL_INTERVAL);
+				loop {
+					interval.tick().await;
+					let Some(drives) = drives.upgrade() else {
+						break;
+					};
+					let disk_source = Arc::clone(&disk_source);
+					match tokio::task::spawn_blocking(move || disk_source.disks()).await {
+						Ok(disks) => drives.lock().unwrap().refresh(disks),
+						Err(err) => tracing::warn!("failed to list disks: {err}"),
+					}
+				}
+			});
+		}
+		let request_log = Arc::new(RequestLog::default());
+		let thumbnail_pregeneration = {
+			let conn = db.lock().unwrap();
+			match load_setting(&conn, THUMBNAIL_PREGEN_SETTING) {
+				Ok(value) => thumbnail_pregen::enabled_from_setting(value.as_deref()),
+				Err(err) => {
+					tracing::error!("failed to load thumbnail pregeneration setting: {err}");
+					thumbnail_pregen::enabled_from_setting(None)
+				}
+			}
+		};
+		let thumbnail_queue = Arc::new(ThumbnailQueue::new(
+			thumbnail_pregeneration,
+			Arc::clone(&activity_window),
+			Arc::clone(&power),
+		));
+		let protocol_rates = Arc::new(Mutex::new(Vec::new()));
+		let activity = ActivityLog::spawn(db.clone());
+		state.inbox = inbox_dir();
+		let keypair = node_keypair();
+		let node_id = peer_to_node_id(&keypair.public().to_peer_id()).unwrap_or_default();
+		let index = FileIndex::shared(db.clone(), node_id);
 		let (mut app, cmd_tx) = App::new(
+			keypair,
 			state,
+			AppDeps {
+				db: db.clone(),
+				remote_scans: remote_scans.clone(),
+				remote_searches: remote_searches.clone(),
+				remote_updates: remote_updates.clone(),
+				store: store.clone(),
+				clock: Arc::new(SystemClock),
+				request_log: request_log.clone(),
+				thumbnail_queue: thumbnail_queue.clone(),
+				maintenance: maintenance.clone(),
+				protocol_rates: protocol_rates.clone(),
+				activity,
+			},
+		);
+		let scan_limits = app.scan_limits();
+		let pins = Arc::new(PinRuns::default());
+		let pin_wake = Arc::new(tokio::sync::Notify::new());
+		{
+			// Pins into a share that was remounted resume right away.
+			let mut share_changes = app.subscribe_share_changes();
+			let runs = Arc::downgrade(&pins);
+			let wake = pin_wake.clone();
+			let source = Arc::new(cmd_tx.clone());
+			let (db, window, power, maintenance) = (
+				db.clone(),
+				activity_window.clone(),
+				power.clone(),
+				maintenance.clone(),
+			);
+			tokio::spawn(async move {
+				let mut interval = tokio::time::interval(PIN_CHECK_INTERVAL);
+				loop {
+					tokio::select! {
+						_ = interval.tick() => {}
+						_ = wake.notified() => {}
+						Ok(ShareChange { available: true, .. }) = share_changes.recv() => {}
+					}
+					let Some(runs) = runs.upgrade() else {
+						break;
+					};
+					start_due_syncs(&source, &db, &window, &power, &maintenance, &runs).await;
+				}
+			});
+		}
+		let outboxes = Arc::new(OutboxRuns::default());
+		let outbox_wake = Arc::new(tokio::sync::Notify::new());
+		{
+			let runs = Arc::downgrade(&outboxes);
+			let wake = outbox_wake.clone();
+			let target = Arc::new(cmd_tx.clone());
+			let (db, window, power, maintenance) = (
+				db.clone(),
+				activity_window.clone(),
+				power.clone(),
+				maintenance.clone(),
+			);
+			tokio::spawn(async move {
+				let mut interval = tokio::time::interval(OUTBOX_CHECK_INTERVAL);
+				loop {
+					tokio::select! {
+						_ = interval.tick() => {}
+						_ = wake.notified() => {}
+					}
+					let Some(runs) = runs.upgrade() else {
+						break;
+					};
+					start_due_outboxes(&target, &db, &window, &power, &maintenance, &runs).await;
+				}
+			});
+		}
+		let replications = Arc::new(ReplicationRuns::default());
+		let replication_wake = Arc::new(tokio::sync::Notify::new());
+		{
+			let runs = Arc::downgrade(&replications);
+			let wake = replication_wake.clone();
+			let link = Arc::new(cmd_tx.clone());
+			let (db, window, power, maintenance) = (
+				db.clone(),
+				activity_window.clone(),
+				power.clone(),
+				maintenance.clone(),
+			);
+			tokio::spawn(async move {
+				let mut interval = tokio::time::interval(REPLICATION_CHECK_INTERVAL);
+				loop {
+					tokio::select! {
+						_ = interval.tick() => {}
+						_ = wake.notified() => {}
+					}
+					let Some(runs) = runs.upgrade() else {
+						break;
+					};
+					start_due_replications(&link, &db, &window, &power, &maintenance, &runs).await;
+				}
+			});
+		}
+		let integrity_settings = Arc::new(Mutex::new(integrity_settings));
+		{
+			let settings = Arc::downgrade(&integrity_settings);
+			let (db, window, power, maintenance, queue) = (
+				db.clone(),
+				activity_window.clone(),
+				power.clone(),
+				maintenance.clone(),
+				thumbnail_queue.clone(),
+			);
+			let cmd_tx = cmd_tx.clone();
+			tokio::spawn(async move {
+				let mut interval = tokio::time::interval(INTEGRITY_CYCLE_INTERVAL);
+				loop {
+					interval.tick().await;
+					let Some(settings) = settings.upgrade() else {
+						break;
+					};
+					let settings = settings.lock().unwrap().clone();
+					if !settings.enabled || maintenance.is_active() {
+						continue;
+					}
+					let (db, window, power, queue) =
+						(db.clone(), window.clone(), power.clone(), queue.clone());
+					let cycle = tokio::task::spawn_blocking(move || {
+						// Checked again before every file, so a cycle stops
+						// as soon as the node gets busy.
+						let idle = || {
+							queue.is_quiet(std::time::Instant::now())
+								&& window
+									.lock()
+									.unwrap()
+									.allows(ActivityKind::Scan, Utc::now())
+								&& !power.lock().unwrap().holds()
+						};
+						if !idle() {
+							return Ok(CycleReport::default());
+						}
+						integrity::run_cycle(
+							&db,
+							&settings,
+							&MountTable::load(),
+							&mut rand::thread_rng(),
+							idle,
+						)
+					})
+					.await;
+					match cycle {
+						Ok(Ok(report)) => {
+							for path in report.new_mismatches {
+								let message = format!(
+									"{} no longer matches its indexed hash; see integrity findings under Storage",
+									path.display()
+								);
+								let _ = cmd_tx.send(Command::NotifyLocal { message });
+							}
+						}
+						Ok(Err(err)) => tracing::error!("integrity sampling failed: {err:#}"),
+						Err(err) => tracing::error!("integrity sampling stopped: {err}"),
+					}
+				}
+			});
+		}
+		{
+			let gate = Arc::downgrade(&power);
+			let wakes = [
+				pin_wake.clone(),
+				outbox_wake.clone(),
+				replication_wake.clone(),
+			];
+			let cmd_tx = cmd_tx.clone();
+			tokio::spawn(async move {
+				let mut interval = tokio::time::interval(POWER_CHECK_INTERVAL);
+				let mut published = None;
+				loop {
+					interval.tick().await;
+					let Some(gate) = gate.upgrade() else {
+						break;
+					};
+					let reading = tokio::task::spawn_blocking(|| SystemPower.read())
+						.await
+						.unwrap_or_default();
+					let (changed, power) = {
+						let mut gate = gate.lock().unwrap();
+						(
+							gate.observe(reading, std::time::Instant::now()),
+							gate.state(),
+						)
+					};
+					if changed {
+						tracing::info!(
+							"power {}; background work {}",
+							power.label(),
+							if power.background_paused {
+								"paused"
+							} else {
+								"resumed"
+							}
+						);
+						if !power.background_paused {
+							wakes.iter().for_each(|wake| wake.notify_one());
+						}
+					}
+					if published.as_ref() != Some(&power) {
+						published = Some(power.clone());
+						let _ = cmd_tx.send(Command::SetPowerState { power });
+					}
+				}
+			});
+		}
+		let mut shutdown_rx = shutdown_rx;
+		let handle = tokio::spawn(async move {
+			loop {
+				tokio::select! {
+					_ = &mut shutdown_rx => {
+						tracing::info!("PuppyNet shutting down");
+						break;
+					}
+					_ = app.run() => {}
+				}
+			}
+			app.shutdown().await;
+		});
+
+		PuppyNet {
+			shutdown_tx: Some(shutdown_tx),
+			handle,
+			cmd_tx,
+			db,
+			index,
+			remote_scans,
+			remote_searches,
+			remote_updates,
+			store,
+			runtime: tokio::runtime::Handle::current(),
+			ids: IdAllocator::new(),
+			activity_window,
+			power,
+			maintenance,
+			deferred: Arc::new(Mutex::new(HashMap::new())),
+			login_guard,
+			backup_settings,
+			cors_settings: Mutex::new(cors_settings),
+			share_summaries: Mutex::new(ShareSummaryCache::default()),
+			pins,
+			pin_wake,
+			outboxes,
+			outbox_wake,
+			replications,
+			replication_wake,
+			request_log,
+			secrets,
+			thumbnail_queue,
+			protocol_rates,
+			last_change: Mutex::new(None),
+			drives,
+			disk_source,
+			integrity_settings,
+			scan_limits,
+			demo: false,
+		}
+	}
+
+	/// A node that shows made-up peers, files and history instead of the
+	/// network and the database, for working on the interfaces. The same
+	/// `seed` gives the same fixture.
+	pub fn new_demo(seed: u64) -> Self {
+		let fixture = DemoFixture::generate(seed, Utc::now());
+		let mut conn = SqliteConnection::open_in_memory().expect("in-memory database");
+		if let Err(err) = fixture.populate(&mut conn) {
+			tracing::error!("failed to fill the demo database: {err}");
+		}
+		let db = Arc::new(Db::single(conn));
+		let node_id = peer_to_node_id(&fixture.peers[0].id).unwrap_or_default();
+		let index = FileIndex::shared(db.clone(), node_id);
+		let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+		let remote_scans = Arc::new(RemoteOps::new("scan"));
+		let remote_searches = Arc::new(Mutex::new(HashMap::new()));
+		let remote_updates = Arc::new(RemoteOps::new("update"));
+		let store = Arc::new(ContentStore::new(
+			std::env::temp_dir().join("puppynet-demo-store"),
+			db.clone(),
+		));
+		let scan_limits = Arc::new(NodeScanLimits::load(&db.lock().unwrap()));
+		let (mut app, cmd_tx) = DemoApp::new(
+			fixture,
 			db.clone(),
 			remote_scans.clone(),
 			remote_searches.clone(),
 			remote_updates.clone(),
 		);
-		let mut shutdown_rx = shutdown_rx;
 		let handle = tokio::spawn(async move {
 			loop {
 				tokio::select! {
 					_ = &mut shutdown_rx => {
-						log::info!("PuppyNet shutting down");
+						tracing::info!("PuppyNet demo shutting down");
 						break;
 					}
 					_ = app.run() => {}
@@ -106,18 +979,193 @@ impl PuppyNet {
 			}
 		});
 
+		let activity_window = Arc::new(Mutex::new(ActivityWindow::default()));
+		let power = Arc::new(Mutex::new(PowerGate::default()));
 		PuppyNet {
 			shutdown_tx: Some(shutdown_tx),
 			handle,
 			cmd_tx,
 			db,
+			index,
 			remote_scans,
-			remote_scan_counter: AtomicU64::new(1),
 			remote_searches,
-			remote_search_counter: AtomicU64::new(1),
 			remote_updates,
-			remote_update_counter: AtomicU64::new(1),
+			store,
+			runtime: tokio::runtime::Handle::current(),
+			ids: IdAllocator::new(),
+			thumbnail_queue: Arc::new(ThumbnailQueue::new(
+				false,
+				Arc::clone(&activity_window),
+				Arc::clone(&power),
+			)),
+			activity_window,
+			power,
+			maintenance: Arc::new(MaintenanceGate::default()),
+			deferred: Arc::new(Mutex::new(HashMap::new())),
+			login_guard: Arc::new(Mutex::new(LoginGuard::new(LoginLimits::default()))),
+			backup_settings: Arc::new(Mutex::new(BackupSettings::default())),
+			cors_settings: Mutex::new(CorsSettings::default()),
+			share_summaries: Mutex::new(ShareSummaryCache::default()),
+			pins: Arc::new(PinRuns::default()),
+			pin_wake: Arc::new(tokio::sync::Notify::new()),
+			outboxes: Arc::new(OutboxRuns::default()),
+			outbox_wake: Arc::new(tokio::sync::Notify::new()),
+			replications: Arc::new(ReplicationRuns::default()),
+			replication_wake: Arc::new(tokio::sync::Notify::new()),
+			request_log: Arc::new(RequestLog::default()),
+			secrets: Secrets::new(Box::new(MemorySecretStore::default())),
+			protocol_rates: Arc::new(Mutex::new(Vec::new())),
+			last_change: Mutex::new(None),
+			drives: Arc::new(Mutex::new(RemovableDrives::default())),
+			disk_source: Arc::new(Vec::<DiskInfo>::new),
+			integrity_settings: Arc::new(Mutex::new(IntegritySettings::default())),
+			scan_limits,
+			demo: true,
+		}
+	}
+
+	/// True when this node runs on made-up data; see [`PuppyNet::new_demo`].
+	pub fn is_demo(&self) -> bool {
+		self.demo
+	}
+
+	/// Peer requests this node sent or handled recently, newest first, with
+	/// their correlation ids and timings. Recording starts with the first
+	/// call and stops ten minutes after the last one, so the first call
+	/// usually returns nothing.
+	pub fn recent_requests(&self) -> Vec<RequestTrace> {
+		self.request_log.recent()
+	}
+
+	/// Allocate a process-unique id; frontends use this for scan, update and
+	/// shell session handles instead of generating their own.
+	pub fn next_id(&self, kind: IdKind) -> u64 {
+		self.ids.next(kind)
+	}
+
+	/// Scans and updates other peers are running for this node.
+	pub fn remote_op_stats(&self) -> RemoteOpsStats {
+		let now = Utc::now();
+		RemoteOpsStats {
+			scans: self.remote_scans.stats(now),
+			updates: self.remote_updates.stats(now),
+		}
+	}
+
+	/// Depth and progress of the thumbnails made ahead of browsing for
+	/// scanned images.
+	pub fn thumbnail_queue_status(&self) -> ThumbnailQueueStatus {
+		self.thumbnail_queue.status()
+	}
+
+	/// Persists whether scanned images get thumbnails ahead of browsing.
+	/// Turning it off drops what is queued.
+	pub fn set_thumbnail_pregeneration(&self, enabled: bool) -> anyhow::Result<()> {
+		{
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			save_setting(
+				&conn,
+				THUMBNAIL_PREGEN_SETTING,
+				if enabled { "true" } else { "false" },
+			)?;
 		}
+		self.thumbnail_queue.set_enabled(enabled);
+		Ok(())
+	}
+
+	/// Requests exchanged with every peer over the last minute, by request
+	/// type, busiest first.
+	pub fn protocol_stats(&self) -> Vec<ProtocolRate> {
+		self.protocol_rates.lock().unwrap().clone()
+	}
+
+	/// Requests exchanged with `peer` over the last minute, by request type.
+	pub fn peer_protocol_stats(&self, peer: &PeerId) -> Vec<ProtocolRate> {
+		let peer = peer.to_string();
+		self.protocol_stats()
+			.into_iter()
+			.filter(|rate| rate.peer == peer)
+			.collect()
+	}
+
+	/// Daily totals of the requests exchanged with `peer` over the last
+	/// `days` days, newest first.
+	pub fn peer_protocol_history(
+		&self,
+		peer: &PeerId,
+		days: u64,
+	) -> anyhow::Result<Vec<ProtocolDay>> {
+		let since = Utc::now().date_naive() - chrono::Days::new(days);
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		load_protocol_stats(&conn, peer, since)
+	}
+
+	/// The request rate that raises an alert and whether the limiter then
+	/// refuses the peer.
+	pub fn protocol_limits(&self) -> ProtocolLimits {
+		let conn = self.db.lock().unwrap();
+		protocol_stats::load_limits(&conn)
+	}
+
+	/// Persists the alert rate and the limiter switch; the next rollup uses
+	/// them.
+	pub fn set_protocol_limits(&self, limits: ProtocolLimits) -> anyhow::Result<()> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		save_setting(
+			&conn,
+			PROTOCOL_ALERT_SETTING,
+			&limits.alert_per_min.to_string(),
+		)?;
+		save_setting(
+			&conn,
+			PROTOCOL_LIMITER_SETTING,
+			if limits.limiter { "true" } else { "false" },
+		)
+	}
+
+	/// When a peer's day of reads and writes raises an alert, and the
+	/// temporary limit the alert offers.
+	pub fn access_alert_limits(&self) -> AccessAlertLimits {
+		let conn = self.db.lock().unwrap();
+		AccessAlertLimits::load(&conn)
+	}
+
+	/// Persists the access alert thresholds; the next rollup uses them.
+	pub fn set_access_alert_limits(&self, limits: &AccessAlertLimits) -> anyhow::Result<()> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		save_setting(&conn, ACCESS_ALERT_SETTING, &serde_json::to_string(limits)?)
+	}
+
+	/// Refuses `peer` with `RateLimited` while its traffic is over
+	/// `bytes_per_sec`, for the next `duration`. Not kept across restarts.
+	pub fn limit_peer_traffic(
+		&self,
+		peer: PeerId,
+		bytes_per_sec: u64,
+		duration: Duration,
+	) -> Result<()> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::LimitPeerTraffic {
+				peer,
+				bytes_per_sec,
+				duration,
+				tx,
+			})
+			.map_err(|e| anyhow!("failed to send LimitPeerTraffic command: {e}"))?;
+		block_on(rx).map_err(|e| anyhow!("LimitPeerTraffic response channel closed: {e}"))?
 	}
 
 	fn local_peer_id(&self) -> Result<PeerId, String> {
@@ -140,7 +1188,7 @@ impl PuppyNet {
 		block_on(rx).map_err(|e| format!("InjectDiscoveredPeer response channel closed: {e}"))
 	}
 
-	fn register_shared_folder(&self, path: PathBuf, flags: u8) -> anyhow::Result<()> {
+	fn register_shared_folder(&self, path: PathBuf, flags: u8) -> anyhow::Result<Vec<RuleOverlap>> {
 		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
 			.send(Command::RegisterSharedFolder { path, flags, tx })
@@ -148,7 +1196,11 @@ impl PuppyNet {
 		block_on(rx).map_err(|e| anyhow!("RegisterSharedFolder response channel closed: {e}"))?
 	}
 
-	async fn register_shared_folder_async(&self, path: PathBuf, flags: u8) -> anyhow::Result<()> {
+	async fn register_shared_folder_async(
+		&self,
+		path: PathBuf,
+		flags: u8,
+	) -> anyhow::Result<Vec<RuleOverlap>> {
 		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
 			.send(Command::RegisterSharedFolder { path, flags, tx })
@@ -157,19 +1209,30 @@ impl PuppyNet {
 			.map_err(|e| anyhow!("RegisterSharedFolder response channel closed: {e}"))?
 	}
 
-	pub fn share_read_only_folder(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+	/// Shares `path` read-only. Returns the overlaps with other shared folders;
+	/// the most specific folder decides access.
+	pub fn share_read_only_folder(
+		&self,
+		path: impl AsRef<Path>,
+	) -> anyhow::Result<Vec<RuleOverlap>> {
 		let canonical = std::fs::canonicalize(path.as_ref())
 			.map_err(|err| anyhow!("failed to canonicalize path: {err}"))?;
 		self.register_shared_folder(canonical, FLAG_READ | FLAG_SEARCH)
 	}
 
-	pub fn share_read_write_folder(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+	pub fn share_read_write_folder(
+		&self,
+		path: impl AsRef<Path>,
+	) -> anyhow::Result<Vec<RuleOverlap>> {
 		let canonical = std::fs::canonicalize(path.as_ref())
 			.map_err(|err| anyhow!("failed to canonicalize path: {err}"))?;
 		self.register_shared_folder(canonical, FLAG_READ | FLAG_WRITE | FLAG_SEARCH)
 	}
 
-	pub async fn share_read_only_folder_async(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+	pub async fn share_read_only_folder_async(
+		&self,
+		path: impl AsRef<Path>,
+	) -> anyhow::Result<Vec<RuleOverlap>> {
 		let canonical = tokio::fs::canonicalize(path.as_ref())
 			.await
 			.map_err(|err| anyhow!("failed to canonicalize path: {err}"))?;
@@ -180,7 +1243,7 @@ impl PuppyNet {
 	pub async fn share_read_write_folder_async(
 		&self,
 		path: impl AsRef<Path>,
-	) -> anyhow::Result<()> {
+	) -> anyhow::Result<Vec<RuleOverlap>> {
 		let canonical = tokio::fs::canonicalize(path.as_ref())
 			.await
 			.map_err(|err| anyhow!("failed to canonicalize path: {err}"))?;
@@ -188,6 +1251,18 @@ impl PuppyNet {
 			.await
 	}
 
+	/// Shares `path` for previews and search only; its files can't be read.
+	pub async fn share_preview_folder_async(
+		&self,
+		path: impl AsRef<Path>,
+	) -> anyhow::Result<Vec<RuleOverlap>> {
+		let canonical = tokio::fs::canonicalize(path.as_ref())
+			.await
+			.map_err(|err| anyhow!("failed to canonicalize path: {err}"))?;
+		self.register_shared_folder_async(canonical, FLAG_PREVIEW | FLAG_SEARCH)
+			.await
+	}
+
 	pub fn create_user(&self, username: String, password: String) -> anyhow::Result<()> {
 		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
@@ -250,625 +1325,3915 @@ impl PuppyNet {
 		if !auth::verify_password(&current_password, &user.passw)? {
 			bail!("Current password is incorrect");
 		}
+		if let Some(problem) = PasswordPolicy::load(&conn).check(&username, &new_password) {
+			bail!(CreateUserError::Invalid(vec![UserFieldError {
+				field: String::from("password"),
+				message: problem,
+			}]));
+		}
 		user.passw = auth::hash_password(&new_password)?;
 		save_user(&conn, &user)?;
 		Ok(())
 	}
 
+	/// Replaces the grants for `peer`, returning overlapping folder grants.
+	/// The last writer wins; see [`Self::set_peer_permissions_at`].
 	pub fn set_peer_permissions(
 		&self,
 		peer: PeerId,
 		permissions: Vec<Permission>,
-	) -> anyhow::Result<()> {
+	) -> anyhow::Result<Vec<RuleOverlap>> {
+		self.set_peer_permissions_at(peer, permissions, None)
+	}
+
+	/// Like [`Self::set_peer_permissions`], but with `expected_revision` set
+	/// the update fails with a [`PermissionConflict`] if the grants changed
+	/// since that revision was read.
+	///
+	/// [`PermissionConflict`]: crate::PermissionConflict
+	pub fn set_peer_permissions_at(
+		&self,
+		peer: PeerId,
+		permissions: Vec<Permission>,
+		expected_revision: Option<u64>,
+	) -> anyhow::Result<Vec<RuleOverlap>> {
 		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
 			.send(Command::SetPeerPermissions {
 				peer,
 				permissions,
+				expected_revision,
 				tx,
 			})
 			.map_err(|e| anyhow!("failed to send SetPeerPermissions command: {e}"))?;
 		block_on(rx).map_err(|e| anyhow!("SetPeerPermissions response channel closed: {e}"))?
 	}
 
-	pub async fn request_permissions(
+	/// Saves an edited `draft` as the grants of `peer`, or with `merge` adds
+	/// its rules to those the peer already has. A draft that doesn't
+	/// validate fails with [`DraftRejected`] naming each row and field to
+	/// fix; see [`Self::set_peer_permissions_at`] for `expected_revision`.
+	/// A merge without one is checked against the grants it merged into.
+	pub fn save_permission_draft(
 		&self,
 		peer: PeerId,
-		permissions: Vec<Permission>,
+		draft: &PermissionDraft,
 		merge: bool,
-	) -> anyhow::Result<Vec<Permission>> {
-		let grants: Vec<PermissionGrant> = permissions
-			.iter()
-			.filter_map(grant_from_permission)
-			.collect();
-		if grants.is_empty() {
-			bail!("no permissions to request");
+		expected_revision: Option<u64>,
+	) -> anyhow::Result<Vec<RuleOverlap>> {
+		let permissions = draft
+			.validate()
+			.map_err(|errors| DraftRejected { errors })?;
+		if !merge {
+			return self.set_peer_permissions_at(peer, permissions, expected_revision);
 		}
-		let (tx, rx) = oneshot::channel();
-		self.cmd_tx
-			.send(Command::GrantPermissions {
-				peer,
-				username: String::from("gui"),
-				permissions: grants,
-				merge,
-				tx,
-			})
-			.map_err(|e| anyhow!("failed to send GrantPermissions command: {e}"))?;
-		let ack = rx
-			.await
-			.map_err(|e| anyhow!("GrantPermissions response channel closed: {e}"))??;
-		Ok(ack
-			.permissions
-			.into_iter()
-			.filter_map(|grant| permission_from_grant(&grant))
-			.collect())
+		let current = self.granted_permission_set(peer)?;
+		let mut merged = current.permissions;
+		for permission in permissions {
+			if !merged.contains(&permission) {
+				merged.push(permission);
+			}
+		}
+		self.set_peer_permissions_at(peer, merged, expected_revision.or(Some(current.revision)))
 	}
 
-	pub async fn state_snapshot(&self) -> Option<State> {
+	/// Adds `folder` to the grants of each of `peers`, keeping what they
+	/// already have. Peers that already had it come back unchanged; one
+	/// peer failing doesn't stop the rest.
+	pub fn grant_folder_to_peers(
+		&self,
+		folder: FolderRule,
+		peers: Vec<PeerId>,
+	) -> anyhow::Result<Vec<(PeerId, BatchGrantOutcome)>> {
 		let (tx, rx) = oneshot::channel();
-		if self.cmd_tx.send(Command::GetState { tx }).is_err() {
-			return None;
-		}
-		rx.await.ok()
+		self.cmd_tx
+			.send(Command::GrantFolderToPeers { folder, peers, tx })
+			.map_err(|e| anyhow!("failed to send GrantFolderToPeers command: {e}"))?;
+		block_on(rx).map_err(|e| anyhow!("GrantFolderToPeers response channel closed: {e}"))?
 	}
 
-	pub fn list_users_db(&self) -> Result<Vec<String>, String> {
-		let conn = self
-			.db
-			.lock()
-			.map_err(|err| format!("db lock poisoned: {err}"))?;
-		load_users(&conn)
-			.map(|users| users.into_iter().map(|u| u.name).collect())
-			.map_err(|err| format!("failed to load users: {err}"))
+	/// Clears every grant `peer` has on this node, in state and storage.
+	/// The grants are snapshotted first, so the change can be undone.
+	pub fn revoke_all_permissions(&self, peer: PeerId) -> anyhow::Result<()> {
+		self.snapshot_before(format!(
+			"before revoking every grant of {}",
+			abbrev_peer_id(&peer.to_string())
+		))?;
+		self.set_peer_permissions(peer, Vec::new()).map(|_| ())
 	}
 
-	pub fn list_peers_db(&self) -> Result<Vec<Peer>, String> {
-		let conn = self
-			.db
-			.lock()
-			.map_err(|err| format!("db lock poisoned: {err}"))?;
-		load_peers(&conn).map_err(|err| format!("failed to load peers: {err}"))
+	fn set_remote_access_suspended(&self, suspended: bool) -> anyhow::Result<()> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::SetRemoteAccessSuspended { suspended, tx })
+			.map_err(|e| anyhow!("failed to send SetRemoteAccessSuspended command: {e}"))?;
+		block_on(rx)
+			.map_err(|e| anyhow!("SetRemoteAccessSuspended response channel closed: {e}"))?
 	}
 
-	pub fn list_discovered_peers_db(&self) -> Result<Vec<crate::state::DiscoveredPeer>, String> {
-		let conn = self
-			.db
-			.lock()
-			.map_err(|err| format!("db lock poisoned: {err}"))?;
-		load_discovered_peers(&conn)
-			.map_err(|err| format!("failed to load discovered peers: {err}"))
+	/// Refuses every remote filesystem request until resumed. Stored grants
+	/// are left as they are, so resuming restores them exactly.
+	pub fn suspend_remote_access(&self) -> anyhow::Result<()> {
+		self.snapshot_before("before suspending remote access".to_string())?;
+		self.set_remote_access_suspended(true)
 	}
 
-	pub fn verify_user_credentials(&self, username: &str, password: &str) -> anyhow::Result<bool> {
-		let conn = self
-			.db
-			.lock()
-			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
-		let Some(user) = load_user(&conn, username)? else {
-			return Ok(false);
-		};
-		auth::verify_password(password, &user.passw)
+	pub fn resume_remote_access(&self) -> anyhow::Result<()> {
+		self.snapshot_before("before resuming remote access".to_string())?;
+		self.set_remote_access_suspended(false)
 	}
 
-	pub fn save_session(
+	async fn set_maintenance(&self, maintenance: Option<Maintenance>) -> anyhow::Result<()> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::SetMaintenance { maintenance, tx })
+			.map_err(|e| anyhow!("failed to send SetMaintenance command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("SetMaintenance response channel closed: {e}"))?
+	}
+
+	/// Refuses every change to this node, across restarts, until
+	/// [`Self::exit_maintenance`]; reads keep working. Scans, syncs and
+	/// replication runs already going get a bounded wait and are then asked
+	/// to stop at their next checkpoint. Once nothing runs the database
+	/// journal is checkpointed, and the report says whether the data
+	/// directory is safe to copy.
+	pub async fn enter_maintenance(
 		&self,
-		token_hash: &[u8],
-		username: &str,
-		ttl_secs: i64,
-	) -> anyhow::Result<()> {
-		let now = Utc::now().timestamp();
-		let expires_at = now.saturating_add(ttl_secs);
-		let mut conn = self
-			.db
-			.lock()
-			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
-		save_session(&mut *conn, token_hash, username, now, expires_at)?;
+		reason: &str,
+		expected: Option<Duration>,
+	) -> Result<MaintenanceReport> {
+		let reason = reason.trim();
+		if reason.is_empty() {
+			bail!("maintenance needs a reason");
+		}
+		let maintenance = Maintenance::new(reason, expected, Utc::now());
+		self.set_maintenance(Some(maintenance.clone())).await?;
+		let gate = Arc::clone(&self.maintenance);
+		let db = self.db.clone();
+		let report = tokio::task::spawn_blocking(move || {
+			let pending = gate.settle(MAINTENANCE_SETTLE, MAINTENANCE_CHECKPOINT_GRACE);
+			let checkpointed = pending.is_empty()
+				&& match db.lock() {
+					Ok(conn) => checkpoint_wal(&conn).unwrap_or_else(|err| {
+						tracing::error!("failed to checkpoint the database journal: {err}");
+						false
+					}),
+					Err(err) => {
+						tracing::error!("db lock poisoned while checkpointing: {err}");
+						false
+					}
+				};
+			MaintenanceReport {
+				maintenance,
+				pending,
+				checkpointed,
+			}
+		})
+		.await
+		.map_err(|err| anyhow!("maintenance task failed: {err}"))?;
+		tracing::info!("maintenance entered; {}", report.summary());
+		Ok(report)
+	}
+
+	/// Lets changes through again and starts the background work that
+	/// waited.
+	pub async fn exit_maintenance(&self) -> Result<()> {
+		self.set_maintenance(None).await?;
+		self.pin_wake.notify_one();
+		self.outbox_wake.notify_one();
+		self.replication_wake.notify_one();
 		Ok(())
 	}
 
-	pub fn http_me(&self, token_hash: &[u8]) -> anyhow::Result<Option<String>> {
+	/// Set while this node is in maintenance mode.
+	pub fn maintenance(&self) -> Option<Maintenance> {
+		self.maintenance.active()
+	}
+
+	/// Mutating work still running, what entering maintenance waits for.
+	pub fn mutating_work(&self) -> Vec<String> {
+		self.maintenance.running()
+	}
+
+	/// Stores the grants, shared folders, settings and, with
+	/// `include_users`, the users as they are now under `label`. Labeled
+	/// snapshots are kept until deleted.
+	pub fn snapshot_config(
+		&self,
+		label: &str,
+		include_users: bool,
+	) -> anyhow::Result<ConfigSnapshotInfo> {
+		let label = label.trim();
+		if label.is_empty() {
+			bail!("name the snapshot");
+		}
+		let me = self.local_peer_id().map_err(|err| anyhow!(err))?;
 		let conn = self
 			.db
 			.lock()
 			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
-		lookup_session_username(&conn, token_hash, Utc::now().timestamp())
+		let config = ConfigState::capture(&conn, &me, include_users)?;
+		let snapshot = config_snapshot::store(
+			&conn,
+			Some(label.to_string()),
+			"taken on request".to_string(),
+			config,
+		)?;
+		Ok(snapshot.info())
 	}
 
-	pub fn drop_session(&self, token_hash: &[u8]) -> anyhow::Result<()> {
-		let mut conn = self
+	/// Every stored snapshot, newest first.
+	pub fn list_config_snapshots(&self) -> anyhow::Result<Vec<ConfigSnapshotInfo>> {
+		let conn = self
 			.db
 			.lock()
 			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
-		delete_session(&mut *conn, token_hash)?;
+		Ok(load_config_snapshots(&conn)?
+			.iter()
+			.map(ConfigSnapshot::info)
+			.collect())
+	}
+
+	/// Takes an unlabeled snapshot and offers it for undo.
+	fn snapshot_before(&self, reason: String) -> anyhow::Result<()> {
+		let me = self.local_peer_id().map_err(|err| anyhow!(err))?;
+		let snapshot = {
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			let config = ConfigState::capture(&conn, &me, false)?;
+			config_snapshot::store(&conn, None, reason, config)?
+		};
+		*self.last_change.lock().unwrap() = Some(UndoableChange {
+			snapshot_id: snapshot.id,
+			reason: snapshot.reason,
+			taken_at: snapshot.taken_at,
+		});
 		Ok(())
 	}
 
-	pub async fn list_dir(&self, peer: PeerId, path: impl Into<String>) -> Result<Vec<DirEntry>> {
-		let path = path.into();
+	/// Rereads the settings this handle keeps in memory, after they were
+	/// written behind its back.
+	fn reload_cached_settings(&self) {
+		let conn = self.db.lock().unwrap();
+		*self.activity_window.lock().unwrap() =
+			load_json_setting(&conn, ACTIVITY_WINDOW_SETTING, "activity window");
+		*self.backup_settings.lock().unwrap() =
+			load_json_setting(&conn, BACKUP_SETTINGS_SETTING, "backup settings");
+		*self.cors_settings.lock().unwrap() =
+			load_json_setting::<CorsSettings>(&conn, CORS_SETTING, "CORS settings").with_env();
+		self.login_guard
+			.lock()
+			.unwrap()
+			.set_limits(load_json_setting(
+				&conn,
+				LOGIN_LIMITS_SETTING,
+				"login limits",
+			));
+		let pregeneration = load_setting(&conn, THUMBNAIL_PREGEN_SETTING).unwrap_or_else(|err| {
+			tracing::error!("failed to load thumbnail pregeneration setting: {err}");
+			None
+		});
+		self.thumbnail_queue
+			.set_enabled(thumbnail_pregen::enabled_from_setting(
+				pregeneration.as_deref(),
+			));
+		*self.integrity_settings.lock().unwrap() =
+			load_json_setting(&conn, INTEGRITY_SETTINGS_SETTING, "integrity settings");
+		http_proxy::apply(load_http_proxy(&conn, &self.secrets));
+		self.power.lock().unwrap().set_policy(load_json_setting(
+			&conn,
+			POWER_POLICY_SETTING,
+			"power policy",
+		));
+		drop(conn);
+		self.power_changed();
+	}
+
+	/// Writes snapshot `id` back: grants, shared folders, settings and, if
+	/// it holds them, users. A snapshot of the configuration it replaces is
+	/// taken first and offered for undo. Refuses with
+	/// [`SnapshotReferencesMissing`](crate::SnapshotReferencesMissing) when the snapshot names peers or users
+	/// that are gone, unless `force` leaves them out. Settings read only at
+	/// start, like the HTTP proxy and port mapping, apply after a restart.
+	pub fn rollback_to_snapshot(&self, id: i64, force: bool) -> anyhow::Result<ConfigRollback> {
+		let me = self.local_peer_id().map_err(|err| anyhow!(err))?;
+		let (mut rollback, previous) = {
+			let mut conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			let snapshot =
+				load_config_snapshot(&conn, id)?.ok_or_else(|| anyhow!("no snapshot {id}"))?;
+			let current = ConfigState::capture(&conn, &me, snapshot.config.includes_users())?;
+			let rollback = config_snapshot::roll_back(&mut conn, &me, &snapshot, force)?;
+			let reason = format!("before rolling back to snapshot {id}");
+			let previous = config_snapshot::store(&conn, None, reason, current)?;
+			(rollback, previous)
+		};
+		rollback.previous_id = Some(previous.id);
+		*self.last_change.lock().unwrap() = Some(UndoableChange {
+			snapshot_id: previous.id,
+			reason: previous.reason,
+			taken_at: previous.taken_at,
+		});
+
 		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
-			.send(Command::ListDir { peer, path, tx })
-			.map_err(|e| anyhow!("failed to send ListDir command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("ListDir response channel closed: {e}"))?
+			.send(Command::ReloadStoredState { tx })
+			.map_err(|e| anyhow!("failed to send ReloadStoredState command: {e}"))?;
+		block_on(rx).map_err(|e| anyhow!("ReloadStoredState response channel closed: {e}"))??;
+		self.reload_cached_settings();
+		Ok(rollback)
 	}
 
-	pub fn list_dir_blocking(
-		&self,
-		peer: PeerId,
-		path: impl Into<String>,
-	) -> Result<Vec<DirEntry>> {
-		block_on(self.list_dir(peer, path))
+	/// The last bulk change, while [`UNDO_WINDOW`](crate::UNDO_WINDOW) has not passed.
+	pub fn undoable_change(&self) -> Option<UndoableChange> {
+		self.last_change
+			.lock()
+			.unwrap()
+			.clone()
+			.filter(|change| change.is_current(Utc::now()))
 	}
 
-	pub async fn list_cpus(&self, peer_id: PeerId) -> Result<Vec<CpuInfo>> {
-		let (tx, rx) = oneshot::channel();
-		self.cmd_tx
-			.send(Command::ListCpus { tx, peer_id })
-			.map_err(|e| anyhow!("failed to send ListCpus command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("ListCpus response channel closed: {e}"))?
+	/// Rolls back to the snapshot taken right before the last bulk change.
+	pub fn undo_last_change(&self) -> anyhow::Result<ConfigRollback> {
+		let Some(change) = self.undoable_change() else {
+			bail!("there is no recent change to undo");
+		};
+		self.rollback_to_snapshot(change.snapshot_id, false)
 	}
 
-	pub fn list_cpus_blocking(&self, peer_id: PeerId) -> Result<Vec<CpuInfo>> {
-		block_on(self.list_cpus(peer_id))
+	/// Name this node shows to peers and in the device list.
+	pub fn node_name(&self) -> Result<Option<String>> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		load_local_node_name(&conn)
 	}
 
-	pub async fn list_disks(&self, peer_id: PeerId) -> Result<Vec<DiskInfo>> {
+	/// Renames this node. Returns the name as stored, trimmed.
+	pub fn set_node_name(&self, name: impl Into<String>) -> Result<String> {
 		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
-			.send(Command::ListDisks { tx, peer_id })
-			.map_err(|e| anyhow!("failed to send ListDisks command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("ListDisks response channel closed: {e}"))?
+			.send(Command::SetNodeName {
+				name: name.into(),
+				tx,
+			})
+			.map_err(|e| anyhow!("failed to send SetNodeName command: {e}"))?;
+		block_on(rx).map_err(|e| anyhow!("SetNodeName response channel closed: {e}"))?
 	}
 
-	pub async fn list_interfaces(&self, peer_id: PeerId) -> Result<Vec<InterfaceInfo>> {
+	/// Asks `peer` to pair. Both users compare the returned code; once the
+	/// peer's user confirms, it grants this node read and search on its
+	/// shared folders, and this node grants the same once its own user
+	/// confirms with [`Self::answer_pairing`]. Pairings nobody answers
+	/// fail after ten minutes.
+	pub async fn start_pairing(&self, peer: PeerId) -> Result<Pairing> {
+		let supported = self
+			.peer_capabilities(peer)
+			.await
+			.is_none_or(|capabilities| capabilities.supports(FEATURE_PAIRING));
+		if !supported {
+			bail!("peer does not support pairing");
+		}
 		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
-			.send(Command::ListInterfaces { tx, peer_id })
-			.map_err(|e| anyhow!("failed to send ListInterfaces command: {e}"))?;
+			.send(Command::StartPairing { peer, tx })
+			.map_err(|e| anyhow!("failed to send StartPairing command: {e}"))?;
 		rx.await
-			.map_err(|e| anyhow!("ListInterfaces response channel closed: {e}"))?
+			.map_err(|e| anyhow!("StartPairing response channel closed: {e}"))?
 	}
 
-	pub async fn list_audio_devices(&self, peer_id: PeerId) -> Result<Vec<AudioDevice>> {
+	/// Confirms or declines the pairing `peer` asked for, or the one this
+	/// node asked for once the peer confirmed it.
+	pub fn answer_pairing(&self, peer: PeerId, accept: bool) -> Result<()> {
 		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
-			.send(Command::ListAudioDevices { tx, peer_id })
-			.map_err(|e| anyhow!("failed to send ListAudioDevices command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("ListAudioDevices response channel closed: {e}"))?
+			.send(Command::AnswerPairing { peer, accept, tx })
+			.map_err(|e| anyhow!("failed to send AnswerPairing command: {e}"))?;
+		block_on(rx).map_err(|e| anyhow!("AnswerPairing response channel closed: {e}"))?
 	}
 
-	pub async fn audio_capability(&self, peer_id: PeerId) -> Result<AudioCapability> {
+	/// A pairing string for this device: its peer id, the addresses it is
+	/// likely reachable at and a fresh secret that pairs one device until
+	/// it expires, after `pairing_secret_ttl_secs`.
+	pub async fn pairing_info(&self) -> Result<PairingInfo> {
 		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
-			.send(Command::AudioCapability { tx, peer_id })
-			.map_err(|e| anyhow!("failed to send AudioCapability command: {e}"))?;
+			.send(Command::PairingInfo { tx })
+			.map_err(|e| anyhow!("failed to send PairingInfo command: {e}"))?;
 		rx.await
-			.map_err(|e| anyhow!("AudioCapability response channel closed: {e}"))?
+			.map_err(|e| anyhow!("PairingInfo response channel closed: {e}"))?
 	}
 
-	pub async fn set_audio_muted(
-		&self,
-		peer_id: PeerId,
-		device_id: Option<String>,
-		muted: bool,
-	) -> Result<Vec<AudioDevice>> {
+	/// Pairs with the device that showed `pairing_string`. It grants this
+	/// device the default pairing access without its user confirming a
+	/// code. A string that expired or was used already fails with
+	/// [`crate::PairingRejected`], one that doesn't decode with
+	/// [`crate::InvalidPairingString`].
+	pub async fn pair_with(&self, pairing_string: &str) -> Result<PairedWith> {
+		let invite = PairingInvite::decode(pairing_string)?;
 		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
-			.send(Command::SetAudioMuted {
-				tx,
-				peer_id,
-				device_id,
-				muted,
-			})
-			.map_err(|e| anyhow!("failed to send SetAudioMuted command: {e}"))?;
+			.send(Command::PairWith { invite, tx })
+			.map_err(|e| anyhow!("failed to send PairWith command: {e}"))?;
 		rx.await
-			.map_err(|e| anyhow!("SetAudioMuted response channel closed: {e}"))?
+			.map_err(|e| anyhow!("PairWith response channel closed: {e}"))?
 	}
 
-	pub async fn set_audio_volume(
-		&self,
-		peer_id: PeerId,
-		device_id: Option<String>,
-		volume: u8,
-	) -> Result<Vec<AudioDevice>> {
+	/// Turns UPnP/NAT-PMP port mapping on the local router on or off. The
+	/// choice is stored and applies on the next start too.
+	pub fn set_nat_port_mapping(&self, enabled: bool) -> anyhow::Result<()> {
 		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
-			.send(Command::SetAudioVolume {
-				tx,
-				peer_id,
-				device_id,
-				volume,
-			})
-			.map_err(|e| anyhow!("failed to send SetAudioVolume command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("SetAudioVolume response channel closed: {e}"))?
+			.send(Command::SetNatMapping { enabled, tx })
+			.map_err(|e| anyhow!("failed to send SetNatMapping command: {e}"))?;
+		block_on(rx).map_err(|e| anyhow!("SetNatMapping response channel closed: {e}"))?
 	}
 
-	pub async fn set_default_audio_device(
-		&self,
-		peer_id: PeerId,
-		device_id: String,
-	) -> Result<Vec<AudioDevice>> {
-		let (tx, rx) = oneshot::channel();
-		self.cmd_tx
-			.send(Command::SetDefaultAudioDevice {
-				tx,
-				peer_id,
-				device_id,
-			})
-			.map_err(|e| anyhow!("failed to send SetDefaultAudioDevice command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("SetDefaultAudioDevice response channel closed: {e}"))?
+	/// Idle timeout and ping interval the swarm uses, as stored. Changes
+	/// take effect on the next start.
+	pub fn connection_policy(&self) -> ConnectionPolicy {
+		let conn = self.db.lock().unwrap();
+		ConnectionPolicy::load(&conn)
 	}
 
-	pub async fn media_capability(&self, peer_id: PeerId) -> Result<MediaCapability> {
-		let (tx, rx) = oneshot::channel();
-		self.cmd_tx
-			.send(Command::MediaCapability { tx, peer_id })
-			.map_err(|e| anyhow!("failed to send MediaCapability command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("MediaCapability response channel closed: {e}"))?
+	pub fn set_connection_policy(&self, policy: ConnectionPolicy) -> Result<()> {
+		if policy.idle_timeout_secs == 0 || policy.ping_interval_secs == 0 {
+			bail!("idle timeout and ping interval must be at least 1 second");
+		}
+		if policy.ping_interval_secs >= policy.idle_timeout_secs {
+			bail!("pings must come more often than the idle timeout");
+		}
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		save_setting(
+			&conn,
+			IDLE_CONNECTION_TIMEOUT_SETTING,
+			&policy.idle_timeout_secs.to_string(),
+		)?;
+		save_setting(
+			&conn,
+			PING_INTERVAL_SETTING,
+			&policy.ping_interval_secs.to_string(),
+		)
 	}
 
-	pub async fn peer_info(&self, peer_id: PeerId) -> Result<PeerInfo> {
+	/// Keeps `peer` connected, redialing it with backoff whenever its
+	/// connection drops, or stops doing so.
+	pub fn set_peer_important(&self, peer: PeerId, important: bool) -> Result<()> {
 		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
-			.send(Command::PeerInfo { tx, peer_id })
-			.map_err(|e| anyhow!("failed to send PeerInfo command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("PeerInfo response channel closed: {e}"))?
+			.send(Command::SetPeerImportant {
+				peer,
+				important,
+				tx,
+			})
+			.map_err(|e| anyhow!("failed to send SetPeerImportant command: {e}"))?;
+		block_on(rx).map_err(|e| anyhow!("SetPeerImportant response channel closed: {e}"))?
 	}
 
-	pub async fn list_media_sources(&self, peer_id: PeerId) -> Result<Vec<MediaSource>> {
-		let (tx, rx) = oneshot::channel();
-		self.cmd_tx
-			.send(Command::ListMediaSources { tx, peer_id })
-			.map_err(|e| anyhow!("failed to send ListMediaSources command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("ListMediaSources response channel closed: {e}"))?
+	/// Trust level of `peer` as stored. Peers never seen are untrusted.
+	pub fn trust_level(&self, peer: PeerId) -> Result<TrustLevel> {
+		let peers = self.db.read(load_peers)?;
+		Ok(peers
+			.into_iter()
+			.find(|known| known.id == peer)
+			.map_or(TrustLevel::Untrusted, |known| known.trust))
 	}
 
-	pub async fn get_media_frame(&self, peer_id: PeerId, source_id: String) -> Result<MediaFrame> {
+	/// Sets which kinds of request `peer` may send, from its next one on.
+	pub fn set_trust_level(&self, peer: PeerId, trust: TrustLevel) -> Result<()> {
 		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
-			.send(Command::GetMediaFrame {
-				tx,
-				peer_id,
-				source_id,
-			})
-			.map_err(|e| anyhow!("failed to send GetMediaFrame command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("GetMediaFrame response channel closed: {e}"))?
+			.send(Command::SetTrustLevel { peer, trust, tx })
+			.map_err(|e| anyhow!("failed to send SetTrustLevel command: {e}"))?;
+		block_on(rx).map_err(|e| anyhow!("SetTrustLevel response channel closed: {e}"))?
 	}
 
-	pub fn scan_remote_peer(
+	pub async fn request_permissions(
 		&self,
 		peer: PeerId,
-		path: impl Into<String>,
-	) -> Result<ScanHandle, String> {
-		let path = path.into();
-		if self.local_peer_id()? == peer {
-			return self.scan_folder(path);
+		permissions: Vec<Permission>,
+		merge: bool,
+	) -> anyhow::Result<Vec<Permission>> {
+		let grants: Vec<PermissionGrant> = permissions
+			.iter()
+			.filter_map(grant_from_permission)
+			.collect();
+		if grants.is_empty() {
+			bail!("no permissions to request");
 		}
-		let (tx, rx) = mpsc::channel();
-		let scan_id = self.remote_scan_counter.fetch_add(1, Ordering::SeqCst);
-		self.remote_scans
-			.lock()
-			.unwrap()
-			.insert(scan_id, tx.clone());
+		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
-			.send(Command::RemoteScan {
+			.send(Command::GrantPermissions {
 				peer,
-				path,
-				scan_id,
+				username: String::from("gui"),
+				permissions: grants,
+				merge,
+				tx,
 			})
-			.map_err(|e| {
-				self.remote_scans.lock().unwrap().remove(&scan_id);
-				format!("failed to send RemoteScan command: {e}")
-			})?;
-		Ok(ScanHandle {
-			receiver: Arc::new(Mutex::new(rx)),
-			cancel_flag: Arc::new(AtomicBool::new(false)),
-		})
+			.map_err(|e| anyhow!("failed to send GrantPermissions command: {e}"))?;
+		let ack = rx
+			.await
+			.map_err(|e| anyhow!("GrantPermissions response channel closed: {e}"))??;
+		Ok(ack
+			.permissions
+			.into_iter()
+			.filter_map(|grant| permission_from_grant(&grant))
+			.collect())
 	}
 
-	pub async fn list_file_entries(
+	pub async fn state_snapshot(&self) -> Option<State> {
+		let (tx, rx) = oneshot::channel();
+		if self.cmd_tx.send(Command::GetState { tx }).is_err() {
+			return None;
+		}
+		rx.await.ok()
+	}
+
+	/// Up to `limit` discovered peers matching `filter`, most recently seen
+	/// first.
+	pub async fn query_discovered_peers(
 		&self,
-		peer: PeerId,
-		offset: u64,
-		limit: u64,
-	) -> Result<Vec<FileEntry>> {
+		filter: DiscoveredPeerFilter,
+		limit: usize,
+	) -> Vec<DiscoveredPeerInfo> {
 		let (tx, rx) = oneshot::channel();
-		self.cmd_tx
-			.send(Command::ListFileEntries {
-				peer,
-				offset,
-				limit,
-				tx,
+		if self
+			.cmd_tx
+			.send(Command::QueryDiscoveredPeers { filter, limit, tx })
+			.is_err()
+		{
+			return Vec::new();
+		}
+		rx.await.unwrap_or_default()
+	}
+
+	/// Names of the users that can log in to this node.
+	pub async fn query_users(&self) -> Vec<String> {
+		let (tx, rx) = oneshot::channel();
+		if self.cmd_tx.send(Command::QueryUsers { tx }).is_err() {
+			return Vec::new();
+		}
+		rx.await.unwrap_or_default()
+	}
+
+	/// State as it was before discovered peers and users moved out of it.
+	#[deprecated(note = "use state_snapshot with query_discovered_peers and query_users")]
+	pub async fn full_state_snapshot(&self) -> Option<FullStateSnapshot> {
+		let state = self.state_snapshot().await?;
+		let discovered_peers = self
+			.query_discovered_peers(DiscoveredPeerFilter::default(), usize::MAX)
+			.await
+			.into_iter()
+			.flat_map(|info| {
+				info.addresses
+					.into_iter()
+					.map(move |multiaddr| DiscoveredPeer {
+						peer_id: info.peer_id,
+						multiaddr,
+					})
 			})
-			.map_err(|e| anyhow!("failed to send ListFileEntries command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("ListFileEntries response channel closed: {e}"))?
+			.collect();
+		Some(FullStateSnapshot {
+			state,
+			discovered_peers,
+			users: self.query_users().await,
+		})
 	}
 
-	pub async fn list_storage_files(&self) -> Result<Vec<StorageUsageFile>> {
+	/// How long a discovered address is kept in memory without being seen
+	/// again. Stored addresses are unaffected.
+	pub fn set_discovered_address_ttl(&self, ttl: chrono::Duration) -> Result<()> {
+		self.maintenance.check()?;
 		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
-			.send(Command::ListStorageFiles { tx })
-			.map_err(|e| anyhow!("failed to send ListStorageFiles command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("ListStorageFiles response channel closed: {e}"))?
+			.send(Command::SetDiscoveredAddressTtl { ttl, tx })
+			.map_err(|e| anyhow!("failed to send SetDiscoveredAddressTtl command: {e}"))?;
+		block_on(rx).map_err(|e| anyhow!("SetDiscoveredAddressTtl response channel closed: {e}"))?
 	}
 
-	pub fn resolve_local_file_by_hash(
-		&self,
-		hash: &[u8],
-	) -> Result<Option<(PathBuf, FileEntry)>, String> {
+	/// How long grants fetched from a peer are used without asking again.
+	pub fn set_grant_cache_ttl(&self, ttl: chrono::Duration) -> Result<()> {
+		self.maintenance.check()?;
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::SetGrantCacheTtl { ttl, tx })
+			.map_err(|e| anyhow!("failed to send SetGrantCacheTtl command: {e}"))?;
+		block_on(rx).map_err(|e| anyhow!("SetGrantCacheTtl response channel closed: {e}"))?
+	}
+
+	/// Every configuration knob with the value in effect and where it came
+	/// from. Settings changed while running show up right away.
+	pub fn effective_config(&self) -> Result<EffectiveConfig> {
 		let conn = self
 			.db
 			.lock()
-			.map_err(|err| format!("db lock poisoned: {err}"))?;
-		let node_id = match get_your_node(&conn)
-			.map_err(|err| format!("failed to load local node id: {err}"))?
-		{
-			Some(id) => id,
-			None => return Ok(None),
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		Ok(config::effective(config::runtime_layer(&conn)?))
+	}
+
+	/// Changes the knob `key` for this node, over whatever the config file,
+	/// environment or command line say. Knobs read at startup can't be
+	/// changed this way.
+	pub fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+		self.maintenance.check()?;
+		let Some(knob) = config::knob(key) else {
+			bail!("unknown setting {key}");
 		};
-		let file_entry = get_file_entry(&conn, hash)
-			.map_err(|err| format!("failed to load file entry: {err}"))?;
-		let entry = match file_entry {
-			Some(entry) => entry,
-			None => return Ok(None),
+		let Some(setting) = knob.setting else {
+			match knob.env.first() {
+				Some(env) => bail!("{key} is read at startup; set it in the config file or {env}"),
+				None => bail!("{key} is read at startup; set it in the config file"),
+			}
 		};
-		let location = get_file_location(&conn, &node_id, hash)
-			.map_err(|err| format!("failed to load file location: {err}"))?;
-		let location = match location {
-			Some(location) => location,
-			None => return Ok(None),
+		let value = value.trim();
+		knob.kind
+			.check(value)
+			.map_err(|err| anyhow!("{key} {err}"))?;
+		match setting {
+			DISCOVERED_ADDRESS_TTL_SETTING => {
+				self.set_discovered_address_ttl(chrono::Duration::seconds(value.parse()?))
+			}
+			GRANT_CACHE_TTL_SETTING => {
+				self.set_grant_cache_ttl(chrono::Duration::seconds(value.parse()?))
+			}
+			SCAN_MAX_READS_SETTING | SCAN_MAX_READ_MIB_SETTING | SCAN_LOW_PRIORITY_SETTING => {
+				// A running scan holds the writer until it ends, so it takes
+				// the new limit here and the value is saved behind it.
+				self.scan_limits.set(setting, value)?;
+				let db = self.db.clone();
+				let value = value.to_string();
+				self.runtime.spawn_blocking(move || {
+					if let Err(err) = db.write(|conn| save_setting(conn, setting, &value)) {
+						tracing::error!("failed to save {setting}: {err}");
+					}
+				});
+				Ok(())
+			}
+			_ => {
+				let conn = self
+					.db
+					.lock()
+					.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+				save_setting(&conn, setting, value)
+			}
+		}
+	}
+
+	/// Location of the SQLite database this node uses.
+	pub fn db_path(&self) -> PathBuf {
+		db_path()
+	}
+
+	/// Checks this node's environment, like `puppynet doctor`. The ports
+	/// this node listens on count as bindable.
+	pub async fn run_diagnostics(&self) -> DiagnosticsReport {
+		let own_listeners = match self.state_snapshot().await {
+			Some(state) => match self.health_check(state.me).await {
+				Ok(health) => health
+					.listen_addrs
+					.iter()
+					.filter_map(|addr| addr.parse().ok())
+					.collect(),
+				Err(err) => {
+					tracing::warn!("failed to read listen addresses: {err:?}");
+					Vec::new()
+				}
+			},
+			None => Vec::new(),
 		};
-		Ok(Some((location.path, entry)))
+		diagnostics::run(self.db_path(), own_listeners).await
 	}
 
-	pub fn list_granted_permissions(&self, peer: PeerId) -> Result<Vec<Permission>> {
-		let (tx, rx) = oneshot::channel();
-		self.cmd_tx
-			.send(Command::ListGrantedPermissions { peer, tx })
-			.map_err(|e| anyhow!("failed to send ListGrantedPermissions command: {e}"))?;
-		block_on(rx).map_err(|e| anyhow!("ListGrantedPermissions response channel closed: {e}"))?
+	/// Sends requests through this node's swarm and decodes the answers
+	/// as [`PeerClient`](crate::client::PeerClient) does, for requests
+	/// this handle has no method of its own for.
+	///
+	/// ```no_run
+	/// use puppynet_core::client::PeerTransport;
+	/// use puppynet_core::p2p::{PeerInfo, PeerReq};
+	/// use puppynet_core::{PeerId, PuppyNet};
+	///
+	/// # async fn run(node: &PuppyNet, peer: PeerId) -> anyhow::Result<()> {
+	/// let mut client = node.client();
+	/// let info: PeerInfo = client.request(peer, PeerReq::PeerInfo).await?;
+	/// println!("{} on {}", info.version, info.os);
+	/// # Ok(())
+	/// # }
+	/// ```
+	pub fn client(&self) -> impl PeerTransport + use<> {
+		NodeTransport(self.cmd_tx.clone())
+	}
+
+	/// Protocol version and features advertised by `peer`, or `None` until
+	/// the handshake has completed.
+	pub async fn peer_capabilities(&self, peer: PeerId) -> Option<PeerCapabilities> {
+		self.state_snapshot()
+			.await
+			.and_then(|state| state.peer_capabilities(&peer))
+	}
+
+	/// Estimated offset of `peer`'s clock from ours, once it has answered
+	/// a timestamped `Hello`.
+	pub async fn clock_offset(&self, peer: PeerId) -> Option<ClockOffset> {
+		self.state_snapshot()
+			.await
+			.and_then(|state| state.clock_offsets.get(&peer).cloned())
+	}
+
+	/// Recorded clock offset estimates of `peer` since `from`, oldest first.
+	pub fn clock_offset_history(
+		&self,
+		peer: PeerId,
+		from: DateTime<Utc>,
+	) -> Result<Vec<ClockOffsetRecord>> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		load_clock_offsets(&conn, &peer, from)
+	}
+
+	/// Live connections, possibly several per peer.
+	pub async fn connections(&self) -> Vec<Connection> {
+		self.state_snapshot()
+			.await
+			.map(|state| state.connections)
+			.unwrap_or_default()
+	}
+
+	pub async fn remote_access_suspended(&self) -> bool {
+		self.state_snapshot()
+			.await
+			.is_some_and(|state| state.remote_access_suspended)
+	}
+
+	pub async fn nat_status(&self) -> NatStatus {
+		self.state_snapshot()
+			.await
+			.map(|state| state.nat)
+			.unwrap_or_default()
+	}
+
+	/// Last reachability self-test of this node's addresses.
+	pub async fn reachability(&self) -> Vec<AddressReachability> {
+		self.state_snapshot()
+			.await
+			.map(|state| state.reachability)
+			.unwrap_or_default()
 	}
 
-	pub async fn list_permissions(&self, peer: PeerId) -> Result<Vec<Permission>> {
+	/// Asks connected peers to dial each listen and external address back.
+	/// Addresses no peer could test come back untested.
+	pub async fn test_reachability(&self) -> Result<Vec<AddressReachability>> {
 		let (tx, rx) = oneshot::channel();
 		self.cmd_tx
-			.send(Command::ListPermissions { peer, tx })
-			.map_err(|e| anyhow!("failed to send ListPermissions command: {e}"))?;
+			.send(Command::TestReachability { tx })
+			.map_err(|e| anyhow!("failed to send TestReachability command: {e}"))?;
 		rx.await
-			.map_err(|e| anyhow!("ListPermissions response channel closed: {e}"))?
+			.map_err(|e| anyhow!("TestReachability response channel closed: {e}"))
 	}
 
-	pub fn scan_folder(&self, path: impl Into<String>) -> Result<ScanHandle, String> {
-		let path = path.into();
-		let (tx, rx) = mpsc::channel();
-		let cancel_flag = Arc::new(AtomicBool::new(false));
-		self.cmd_tx
-			.send(Command::Scan {
-				path,
-				tx,
-				cancel_flag: Arc::clone(&cancel_flag),
+	pub fn list_users_db(&self) -> Result<Vec<String>, String> {
+		self.db
+			.read(load_users)
+			.map(|users| users.into_iter().map(|u| u.name).collect())
+			.map_err(|err| format!("failed to load users: {err}"))
+	}
+
+	pub fn list_peers_db(&self) -> Result<Vec<Peer>, String> {
+		self.db
+			.read(load_peers)
+			.map_err(|err| format!("failed to load peers: {err}"))
+	}
+
+	pub fn list_discovered_peers_db(&self) -> Result<Vec<DiscoveredPeer>, String> {
+		self.db
+			.read(load_discovered_peers)
+			.map_err(|err| format!("failed to load discovered peers: {err}"))
+	}
+
+	/// Checks a password. Unknown usernames are checked against a dummy
+	/// hash so they take as long to reject as a wrong password.
+	pub fn verify_user_credentials(&self, username: &str, password: &str) -> anyhow::Result<bool> {
+		let user = self.db.read(|conn| load_user(conn, username))?;
+		match user {
+			Some(user) => auth::verify_password(password, &user.passw),
+			None => {
+				auth::verify_password(password, auth::dummy_password_hash())?;
+				Ok(false)
+			}
+		}
+	}
+
+	/// Password login through the rate limiter, recorded in the login
+	/// history. Prefer this over [`Self::verify_user_credentials`] for
+	/// anything a client can call.
+	pub fn attempt_login(
+		&self,
+		username: &str,
+		password: &str,
+		source: &LoginSource,
+	) -> anyhow::Result<LoginResult> {
+		let username = clip_audit_field(username);
+		let now = Utc::now();
+		let gate = self
+			.login_guard
+			.lock()
+			.unwrap()
+			.admit(&username, &source.ip, now);
+		let (outcome, result) = match gate {
+			LoginGate::Open => {
+				if self.verify_user_credentials(&username, password)? {
+					(LoginOutcome::Success, LoginResult::Success)
+				} else {
+					(LoginOutcome::Failure, LoginResult::InvalidCredentials)
+				}
+			}
+			// The answer already says the username is locked, so there is
+			// nothing to hide by hashing the password anyway.
+			LoginGate::LockedOut { retry_after } => (
+				LoginOutcome::LockedOut,
+				LoginResult::LockedOut {
+					retry_after_secs: retry_after.num_seconds().max(1) as u64,
+				},
+			),
+			LoginGate::Throttled { retry_after } => (
+				LoginOutcome::Throttled,
+				LoginResult::Throttled {
+					retry_after_secs: retry_after.num_seconds().max(1) as u64,
+				},
+			),
+		};
+		self.login_guard.lock().unwrap().record(LoginAttempt {
+			at: now,
+			username,
+			source_ip: clip_audit_field(&source.ip),
+			user_agent: clip_audit_field(&source.user_agent),
+			outcome,
+		});
+		Ok(result)
+	}
+
+	/// Recorded login attempts, newest first, `LOGIN_HISTORY_PAGE_SIZE` per
+	/// page, for one username or all of them.
+	pub fn login_history(
+		&self,
+		username: Option<&str>,
+		page: u64,
+	) -> Result<Vec<LoginAttempt>, String> {
+		flush_login_audit(&self.login_guard, &self.db);
+		self.db
+			.read(|conn| {
+				load_login_history(
+					conn,
+					username,
+					page * LOGIN_HISTORY_PAGE_SIZE,
+					LOGIN_HISTORY_PAGE_SIZE,
+				)
 			})
-			.map_err(|e| format!("failed to send Scan command: {e}"))?;
-		Ok(ScanHandle {
-			receiver: Arc::new(Mutex::new(rx)),
-			cancel_flag,
+			.map_err(|err| format!("failed to load login history: {err}"))
+	}
+
+	/// Failed logins since `since`, grouped by username and address.
+	pub fn failed_logins_since(
+		&self,
+		since: DateTime<Utc>,
+	) -> Result<Vec<FailedLoginGroup>, String> {
+		flush_login_audit(&self.login_guard, &self.db);
+		self.db
+			.read(|conn| failed_logins_since(conn, since))
+			.map_err(|err| format!("failed to load failed logins: {err}"))
+	}
+
+	pub fn login_limits(&self) -> LoginLimits {
+		self.login_guard.lock().unwrap().limits().clone()
+	}
+
+	pub fn set_login_limits(&self, limits: LoginLimits) -> anyhow::Result<()> {
+		self.maintenance.check()?;
+		let value = serde_json::to_string(&limits)?;
+		{
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			save_setting(&conn, LOGIN_LIMITS_SETTING, &value)?;
+		}
+		self.login_guard.lock().unwrap().set_limits(limits);
+		Ok(())
+	}
+
+	pub fn password_policy(&self) -> PasswordPolicy {
+		self.db.read(PasswordPolicy::load)
+	}
+
+	/// Applies to users created and passwords changed from now on;
+	/// existing passwords keep working.
+	pub fn set_password_policy(&self, policy: PasswordPolicy) -> anyhow::Result<()> {
+		self.maintenance.check()?;
+		if policy.min_length == 0 {
+			bail!("passwords need at least one character");
+		}
+		let value = serde_json::to_string(&policy)?;
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		save_setting(&conn, PASSWORD_POLICY_SETTING, &value)
+	}
+
+	pub fn backup_settings(&self) -> BackupSettings {
+		self.backup_settings.lock().unwrap().clone()
+	}
+
+	pub fn set_backup_settings(&self, settings: BackupSettings) -> anyhow::Result<()> {
+		self.maintenance.check()?;
+		if settings.hour > 23 {
+			bail!("backup hour must be between 0 and 23");
+		}
+		if settings.keep == 0 {
+			bail!("keep at least one backup");
+		}
+		let value = serde_json::to_string(&settings)?;
+		{
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			save_setting(&conn, BACKUP_SETTINGS_SETTING, &value)?;
+		}
+		*self.backup_settings.lock().unwrap() = settings;
+		Ok(())
+	}
+
+	/// Removable drives attached now, as of the last look.
+	pub fn removable_drives(&self) -> Vec<RemovableDrive> {
+		self.drives.lock().unwrap().attached()
+	}
+
+	/// Removable drives that came or went since this was last called.
+	pub fn take_drive_changes(&self) -> Vec<DriveChange> {
+		self.drives.lock().unwrap().take_changes()
+	}
+
+	pub fn ingest_settings(&self) -> IngestSettings {
+		let conn = self.db.lock().unwrap();
+		load_json_setting(&conn, INGEST_SETTINGS_SETTING, "ingest settings")
+	}
+
+	pub fn set_ingest_settings(&self, settings: IngestSettings) -> anyhow::Result<()> {
+		self.maintenance.check()?;
+		settings.validate()?;
+		let value = serde_json::to_string(&settings)?;
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		save_setting(&conn, INGEST_SETTINGS_SETTING, &value)?;
+		Ok(())
+	}
+
+	/// Copies the media on removable drive `drive_id` into the index as
+	/// `settings` says, then ejects the drive if asked to and the ingest got
+	/// through it. A drive removed part way is remembered so its next attach
+	/// offers to resume.
+	pub async fn ingest_drive(
+		&self,
+		drive_id: &str,
+		settings: IngestSettings,
+		cancel: Arc<AtomicBool>,
+		progress: impl FnMut(&IngestProgress) + Send + 'static,
+	) -> anyhow::Result<IngestReport> {
+		self.maintenance.check()?;
+		let drive = self
+			.drives
+			.lock()
+			.unwrap()
+			.find(drive_id)
+			.ok_or_else(|| anyhow!("the drive is no longer attached"))?;
+		let node_id = {
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			get_your_node(&conn)?.ok_or_else(|| anyhow!("this node has no identity yet"))?
+		};
+		let mount_path = drive.mount_path.clone();
+		let eject_when_done = settings.eject_when_done;
+		let (db, disk_source) = (Arc::clone(&self.db), Arc::clone(&self.disk_source));
+		let mut report = tokio::task::spawn_blocking(move || {
+			ingest::ingest(
+				&db,
+				&node_id,
+				&drive,
+				disk_source.as_ref(),
+				&settings,
+				&cancel,
+				progress,
+			)
 		})
+		.await??;
+		self.drives
+			.lock()
+			.unwrap()
+			.set_interrupted(drive_id, report.interrupted);
+		if eject_when_done && !report.interrupted && !report.cancelled {
+			let ejected = tokio::task::spawn_blocking(move || ingest::eject(&mount_path)).await?;
+			report.eject = Some(ejected.map_err(|err| format!("{err:#}")));
+		}
+		Ok(report)
+	}
+
+	pub fn cors_settings(&self) -> CorsSettings {
+		self.cors_settings.lock().unwrap().clone()
+	}
+
+	/// Stores and applies new CORS settings for the HTTP API. They replace
+	/// `PUPPYNET_CORS_ORIGINS` until the next start.
+	pub fn set_cors_settings(&self, settings: CorsSettings) -> anyhow::Result<()> {
+		self.maintenance.check()?;
+		settings.validate()?;
+		let value = serde_json::to_string(&settings)?;
+		{
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			save_setting(&conn, CORS_SETTING, &value)?;
+		}
+		*self.cors_settings.lock().unwrap() = settings;
+		Ok(())
+	}
+
+	/// The proxy used for updater and other outbound HTTP calls. The
+	/// password, if any, is not included.
+	pub fn http_proxy(&self) -> HttpProxySettings {
+		let mut settings = http_proxy::current();
+		if let Some(credentials) = &mut settings.credentials {
+			credentials.password.clear();
+		}
+		settings
+	}
+
+	/// Sends outbound HTTP through `url`, or through the proxy from the
+	/// environment when `None`. The URL is stored in the settings and
+	/// `credentials` in the secret store; an empty password keeps the one
+	/// already set for the same user. Applies to the next request.
+	pub fn set_http_proxy(
+		&self,
+		url: Option<String>,
+		credentials: Option<ProxyCredentials>,
+	) -> anyhow::Result<()> {
+		self.maintenance.check()?;
+		let url = url
+			.filter(|url| !url.trim().is_empty())
+			.map(|url| normalize_proxy_url(&url))
+			.transpose()?;
+		let credentials = credentials.map(|mut credentials| {
+			if credentials.password.is_empty()
+				&& let Some(current) = http_proxy::current().credentials
+				&& current.username == credentials.username
+			{
+				credentials.password = current.password;
+			}
+			credentials
+		});
+		match &credentials {
+			Some(credentials) => self
+				.secrets
+				.set(HTTP_PROXY_SECRET, &serde_json::to_string(credentials)?)?,
+			None => {
+				self.secrets.delete(HTTP_PROXY_SECRET)?;
+			}
+		}
+		let settings = HttpProxySettings { url, credentials };
+		let value = serde_json::to_string(&settings)?;
+		{
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			save_setting(&conn, HTTP_PROXY_SETTING, &value)?;
+		}
+		http_proxy::apply(settings);
+		Ok(())
+	}
+
+	/// Requests a known endpoint through the current proxy settings and
+	/// describes how it went.
+	pub async fn test_http_proxy(&self) -> anyhow::Result<String> {
+		HttpClient::new()?.test_connection().await
+	}
+
+	/// The node's secrets, like the JWT signing key.
+	pub fn secrets(&self) -> &Secrets {
+		&self.secrets
+	}
+
+	/// Keeps secrets in `backend` from the next start on, copying the ones
+	/// stored now over to it. Returns how many were copied.
+	pub fn set_secret_backend(&self, backend: SecretBackend) -> anyhow::Result<usize> {
+		if self.demo {
+			bail!("the secret store can't be changed in demo mode");
+		}
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		self.secrets.switch_backend(&conn, backend)
+	}
+
+	/// Writes a consistent copy of the database into `dest_dir` while the
+	/// node keeps running, keeping the configured number of backups there.
+	pub async fn backup_database(&self, dest_dir: impl AsRef<Path>) -> anyhow::Result<BackupRun> {
+		let dest_dir = dest_dir.as_ref().to_path_buf();
+		let keep = self.backup_settings().keep;
+		let db = self.db.clone();
+		let run = tokio::task::spawn_blocking(move || run_backup(&db, &dest_dir, keep, false))
+			.await
+			.map_err(|err| anyhow!("backup task failed: {err}"))?;
+		match &run.error {
+			Some(err) => bail!("backup failed: {err}"),
+			None => Ok(run),
+		}
+	}
+
+	/// Backs up into the folder from the backup settings.
+	pub async fn backup_now(&self) -> anyhow::Result<BackupRun> {
+		let dir = self.backup_settings().backup_dir();
+		self.backup_database(dir).await
+	}
+
+	/// Replaces the database with the backup at `src` and reloads state and
+	/// settings from it. The current database is kept as
+	/// `puppynet.db.pre-restore`. Changes are refused while it runs. Refuses
+	/// while a scan or other mutating work runs unless `force` is set, in
+	/// which case that work gets the same wait and stop request as entering
+	/// maintenance.
+	pub async fn restore_database(
+		&self,
+		src: impl AsRef<Path>,
+		force: bool,
+	) -> anyhow::Result<BackupRun> {
+		let src = src.as_ref().to_path_buf();
+		let started_at = Utc::now();
+		verify_database(&src)?;
+		let previous_mode = self.maintenance.active();
+		if previous_mode.is_none() {
+			self.maintenance.set(Some(Maintenance::new(
+				"restoring a backup",
+				None,
+				started_at,
+			)));
+		}
+		let gate = Arc::clone(&self.maintenance);
+		let db = self.db.clone();
+		let restored = src.clone();
+		let swapped = tokio::task::spawn_blocking(move || -> anyhow::Result<PathBuf> {
+			let running = match force {
+				true => gate.settle(MAINTENANCE_SETTLE, MAINTENANCE_CHECKPOINT_GRACE),
+				false => gate.running(),
+			};
+			if !running.is_empty() {
+				bail!(
+					"the database is busy with {}; stop it or force the restore",
+					running.join(", ")
+				);
+			}
+			let mut conn = db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			let _readers = db.close_readers();
+			let path = db_path();
+			let live = std::mem::replace(&mut *conn, SqliteConnection::open_in_memory()?);
+			if let Err((_, err)) = live.close() {
+				*conn = SqliteConnection::open(&path)?;
+				configure_connection(&conn)?;
+				bail!("failed to close the database: {err}");
+			}
+			let swapped = swap_in_database(&path, &restored);
+			*conn = SqliteConnection::open(&path)?;
+			configure_connection(&conn)?;
+			let previous = swapped?;
+			run_migrations(&mut conn)?;
+			Ok(previous)
+		})
+		.await
+		.map_err(|err| anyhow!("restore task failed: {err}"));
+		if let Err(err) = swapped.and_then(|swapped| swapped) {
+			self.maintenance.set(previous_mode);
+			return Err(err);
+		}
+
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ReloadStoredState { tx })
+			.map_err(|e| anyhow!("failed to send ReloadStoredState command: {e}"))?;
+		let reloaded = rx
+			.await
+			.map_err(|e| anyhow!("ReloadStoredState response channel closed: {e}"))
+			.and_then(|reloaded| reloaded);
+		self.reload_cached_settings();
+		// Keeps the mode the node was in, in the restored database too.
+		let kept = self.set_maintenance(previous_mode.clone()).await;
+		if kept.is_err() {
+			self.maintenance.set(previous_mode.clone());
+		}
+		kept?;
+		reloaded?;
+		if previous_mode.is_none() {
+			self.pin_wake.notify_one();
+			self.outbox_wake.notify_one();
+			self.replication_wake.notify_one();
+		}
+
+		let run = BackupRun {
+			kind: BackupKind::Restore,
+			started_at,
+			finished_at: Utc::now(),
+			path: src.display().to_string(),
+			size_bytes: std::fs::metadata(&src).ok().map(|meta| meta.len()),
+			scheduled: false,
+			error: None,
+		};
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		record_backup_run(&conn, &run)?;
+		Ok(run)
+	}
+
+	/// Recent backups and restores, newest first.
+	pub fn backup_runs(&self, limit: u64) -> anyhow::Result<Vec<BackupRun>> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		load_backup_runs(&conn, limit)
+	}
+
+	/// Set while the database's own node differs from the keypair's.
+	pub async fn identity_mismatch(&self) -> Option<IdentityMismatch> {
+		self.state_snapshot().await?.identity_mismatch
+	}
+
+	/// Runs `resolve` with the database's previous node id and the current
+	/// one, then reloads state so the mismatch is checked again.
+	async fn resolve_identity<F>(&self, resolve: F) -> anyhow::Result<()>
+	where
+		F: FnOnce(&mut SqliteConnection, &NodeID, &NodeID) -> anyhow::Result<()> + Send + 'static,
+	{
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::GetLocalPeerId { tx })
+			.map_err(|e| anyhow!("failed to send GetLocalPeerId command: {e}"))?;
+		let peer = rx
+			.await
+			.map_err(|e| anyhow!("GetLocalPeerId response channel closed: {e}"))?;
+		let current =
+			peer_to_node_id(&peer).ok_or_else(|| anyhow!("invalid local peer id {peer}"))?;
+		let db = self.db.clone();
+		tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+			let mut conn = db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			let Some(previous) = find_previous_local_node(&conn, &current)? else {
+				bail!("the database already belongs to this node");
+			};
+			resolve(&mut conn, &previous, &current)
+		})
+		.await
+		.map_err(|err| anyhow!("identity task failed: {err}"))??;
+
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ReloadStoredState { tx })
+			.map_err(|e| anyhow!("failed to send ReloadStoredState command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ReloadStoredState response channel closed: {e}"))?
+	}
+
+	/// Moves the files and device info recorded under the database's
+	/// previous node id to this node's id.
+	pub async fn adopt_previous_identity(&self) -> anyhow::Result<()> {
+		self.resolve_identity(|conn, previous, current| {
+			adopt_node_identity(conn, previous, current)
+		})
+		.await
+	}
+
+	/// Keeps the database's previous node as another node and starts this
+	/// node's records from scratch.
+	pub async fn start_fresh_identity(&self) -> anyhow::Result<()> {
+		self.resolve_identity(|conn, previous, _| demote_node(conn, previous))
+			.await
+	}
+
+	pub fn save_session(
+		&self,
+		token_hash: &[u8],
+		username: &str,
+		ttl_secs: i64,
+	) -> anyhow::Result<()> {
+		let now = Utc::now().timestamp();
+		let expires_at = now.saturating_add(ttl_secs);
+		let mut conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		save_session(&mut *conn, token_hash, username, now, expires_at)?;
+		Ok(())
+	}
+
+	pub fn http_me(&self, token_hash: &[u8]) -> anyhow::Result<Option<String>> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		lookup_session_username(&conn, token_hash, Utc::now().timestamp())
+	}
+
+	pub fn drop_session(&self, token_hash: &[u8]) -> anyhow::Result<()> {
+		let mut conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		delete_session(&mut *conn, token_hash)?;
+		Ok(())
+	}
+
+	/// The entries of a directory on `peer`, and how much this node may
+	/// still write there when the peer says.
+	pub async fn list_dir_with_hint(
+		&self,
+		peer: PeerId,
+		path: impl Into<WirePath>,
+	) -> Result<DirListing> {
+		let path = SafePath::remote(&path.into())?;
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ListDir { peer, path, tx })
+			.map_err(|e| anyhow!("failed to send ListDir command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ListDir response channel closed: {e}"))?
+	}
+
+	pub async fn list_dir(&self, peer: PeerId, path: impl Into<WirePath>) -> Result<Vec<DirEntry>> {
+		Ok(self.list_dir_with_hint(peer, path).await?.entries)
+	}
+
+	/// Fails with [`crate::WontFit`] when `peer` hints less room under `dir` than
+	/// an upload of `size` bytes needs, so it can be refused before any of
+	/// it is sent. Passing says nothing about whether the writes succeed.
+	pub async fn check_room(
+		&self,
+		peer: PeerId,
+		dir: impl Into<WirePath>,
+		size: u64,
+	) -> Result<()> {
+		let listing = self.list_dir_with_hint(peer, dir).await?;
+		check_fits(size, listing.free_hint)?;
+		Ok(())
+	}
+
+	pub fn list_dir_blocking(
+		&self,
+		peer: PeerId,
+		path: impl Into<WirePath>,
+	) -> Result<Vec<DirEntry>> {
+		block_on(self.list_dir(peer, path))
+	}
+
+	pub async fn stat_file(&self, peer: PeerId, path: impl Into<WirePath>) -> Result<DirEntry> {
+		let path = SafePath::remote(&path.into())?;
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::StatFile { peer, path, tx })
+			.map_err(|e| anyhow!("failed to send StatFile command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("StatFile response channel closed: {e}"))?
+	}
+
+	pub async fn list_cpus(&self, peer_id: PeerId) -> Result<Vec<CpuInfo>> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ListCpus { tx, peer_id })
+			.map_err(|e| anyhow!("failed to send ListCpus command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ListCpus response channel closed: {e}"))?
+	}
+
+	pub fn list_cpus_blocking(&self, peer_id: PeerId) -> Result<Vec<CpuInfo>> {
+		block_on(self.list_cpus(peer_id))
+	}
+
+	pub async fn list_disks(&self, peer_id: PeerId) -> Result<Vec<DiskInfo>> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ListDisks { tx, peer_id })
+			.map_err(|e| anyhow!("failed to send ListDisks command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ListDisks response channel closed: {e}"))?
+	}
+
+	/// Recorded free space of the disk mounted at `mount` on `peer_id`,
+	/// oldest first. Empty for peers that don't keep disk history.
+	pub async fn disk_history(
+		&self,
+		peer_id: PeerId,
+		mount: &str,
+		range: Range<DateTime<Utc>>,
+	) -> Result<Vec<DiskSample>> {
+		let supported = self
+			.peer_capabilities(peer_id)
+			.await
+			.is_none_or(|capabilities| capabilities.supports(FEATURE_DISK_HISTORY));
+		if !supported {
+			return Ok(Vec::new());
+		}
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::DiskHistory {
+				peer_id,
+				mount: mount.to_string(),
+				from: range.start,
+				to: range.end,
+				tx,
+			})
+			.map_err(|e| anyhow!("failed to send DiskHistory command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("DiskHistory response channel closed: {e}"))?
+	}
+
+	/// Where to start browsing `peer_id`. Peers without `ListRoots` get a
+	/// guess based on the OS they report.
+	pub async fn list_roots(&self, peer_id: PeerId) -> Result<BrowseRoots> {
+		let supported = self
+			.peer_capabilities(peer_id)
+			.await
+			.is_none_or(|capabilities| capabilities.supports(FEATURE_LIST_ROOTS));
+		if !supported {
+			let info = self.peer_info(peer_id).await?;
+			let disks = self.list_disks(peer_id).await.unwrap_or_default();
+			return Ok(BrowseRoots::legacy(&info.os, &disks));
+		}
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ListRoots { tx, peer_id })
+			.map_err(|e| anyhow!("failed to send ListRoots command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ListRoots response channel closed: {e}"))?
+	}
+
+	/// Home, standard user folders and drives on the peer that this node may
+	/// read. Peers without the feature only report their drives.
+	pub async fn well_known_folders(&self, peer_id: PeerId) -> Result<Vec<WellKnownFolder>> {
+		let supported = self
+			.peer_capabilities(peer_id)
+			.await
+			.is_none_or(|capabilities| capabilities.supports(FEATURE_WELL_KNOWN_FOLDERS));
+		if !supported {
+			let roots = self.list_roots(peer_id).await?;
+			return Ok(roots
+				.roots
+				.into_iter()
+				.filter(|root| root.kind == BrowseRootKind::Disk)
+				.map(|root| WellKnownFolder {
+					kind: FolderKind::Drive,
+					label: root.label,
+					path: root.path,
+				})
+				.collect());
+		}
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::WellKnownFolders { tx, peer_id })
+			.map_err(|e| anyhow!("failed to send WellKnownFolders command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("WellKnownFolders response channel closed: {e}"))?
+	}
+
+	pub async fn list_interfaces(&self, peer_id: PeerId) -> Result<Vec<InterfaceInfo>> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ListInterfaces { tx, peer_id })
+			.map_err(|e| anyhow!("failed to send ListInterfaces command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ListInterfaces response channel closed: {e}"))?
+	}
+
+	pub async fn list_audio_devices(&self, peer_id: PeerId) -> Result<Vec<AudioDevice>> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ListAudioDevices { tx, peer_id })
+			.map_err(|e| anyhow!("failed to send ListAudioDevices command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ListAudioDevices response channel closed: {e}"))?
+	}
+
+	pub async fn audio_capability(&self, peer_id: PeerId) -> Result<AudioCapability> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::AudioCapability { tx, peer_id })
+			.map_err(|e| anyhow!("failed to send AudioCapability command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("AudioCapability response channel closed: {e}"))?
+	}
+
+	pub async fn set_audio_muted(
+		&self,
+		peer_id: PeerId,
+		device_id: Option<String>,
+		muted: bool,
+	) -> Result<Vec<AudioDevice>> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::SetAudioMuted {
+				tx,
+				peer_id,
+				device_id,
+				muted,
+			})
+			.map_err(|e| anyhow!("failed to send SetAudioMuted command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("SetAudioMuted response channel closed: {e}"))?
+	}
+
+	pub async fn set_audio_volume(
+		&self,
+		peer_id: PeerId,
+		device_id: Option<String>,
+		volume: u8,
+	) -> Result<Vec<AudioDevice>> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::SetAudioVolume {
+				tx,
+				peer_id,
+				device_id,
+				volume,
+			})
+			.map_err(|e| anyhow!("failed to send SetAudioVolume command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("SetAudioVolume response channel closed: {e}"))?
+	}
+
+	pub async fn set_default_audio_device(
+		&self,
+		peer_id: PeerId,
+		device_id: String,
+	) -> Result<Vec<AudioDevice>> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::SetDefaultAudioDevice {
+				tx,
+				peer_id,
+				device_id,
+			})
+			.map_err(|e| anyhow!("failed to send SetDefaultAudioDevice command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("SetDefaultAudioDevice response channel closed: {e}"))?
+	}
+
+	pub async fn media_capability(&self, peer_id: PeerId) -> Result<MediaCapability> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::MediaCapability { tx, peer_id })
+			.map_err(|e| anyhow!("failed to send MediaCapability command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("MediaCapability response channel closed: {e}"))?
+	}
+
+	pub async fn peer_info(&self, peer_id: PeerId) -> Result<PeerInfo> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::PeerInfo { tx, peer_id })
+			.map_err(|e| anyhow!("failed to send PeerInfo command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("PeerInfo response channel closed: {e}"))?
+	}
+
+	pub async fn health_check(&self, peer: PeerId) -> Result<PeerHealth> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::HealthCheck { peer, tx })
+			.map_err(|e| anyhow!("failed to send HealthCheck command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("HealthCheck response channel closed: {e}"))?
+	}
+
+	/// Asks `peer` to restart its puppynet process. Requires an `Owner`
+	/// grant on the peer. Returns the delay before it goes down.
+	pub async fn restart_peer(&self, peer: PeerId) -> Result<u64> {
+		let supported = self
+			.peer_capabilities(peer)
+			.await
+			.is_none_or(|capabilities| capabilities.supports(FEATURE_RESTART));
+		if !supported {
+			bail!("peer does not support remote restart");
+		}
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::RestartPeer {
+				peer,
+				delay_secs: RESTART_DELAY_SECS,
+				tx,
+			})
+			.map_err(|e| anyhow!("failed to send RestartPeer command: {e}"))?;
+		let ack = rx
+			.await
+			.map_err(|e| anyhow!("RestartPeer response channel closed: {e}"))??;
+		Ok(ack.delay_secs)
+	}
+
+	/// Polls `peer` until a process started after `since` answers a health
+	/// check, returning how long that took. Fails when the peer reports
+	/// that the restart did not happen or it stays away too long.
+	pub async fn wait_for_restart(
+		&self,
+		peer: PeerId,
+		since: std::time::Instant,
+	) -> Result<Duration> {
+		loop {
+			let elapsed = since.elapsed();
+			if elapsed > RESTART_RECONNECT_TIMEOUT {
+				bail!(
+					"peer did not come back within {}s",
+					RESTART_RECONNECT_TIMEOUT.as_secs()
+				);
+			}
+			if let Ok(health) = self.health_check(peer).await {
+				if let Some(error) = health.last_restart_error {
+					bail!("restart failed: {error}");
+				}
+				if health.uptime_secs <= since.elapsed().as_secs() {
+					return Ok(since.elapsed());
+				}
+			}
+			tokio::time::sleep(RESTART_RECONNECT_POLL).await;
+		}
+	}
+
+	/// Where a magic packet for `peer` would go, if its MAC address is
+	/// known.
+	pub fn wake_target(&self, peer: PeerId) -> Result<Option<WakeTarget>> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		load_wake_target(&conn, &peer)
+	}
+
+	/// Sets the MAC address to wake `peer` with. Addresses learned when the
+	/// peer connects no longer replace it.
+	pub fn set_wake_mac(&self, peer: PeerId, mac: &str) -> Result<WakeTarget> {
+		self.maintenance.check()?;
+		let mac = format_mac(&parse_mac(mac)?);
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		confirm_wake_mac(&conn, &peer, &mac, Utc::now())?;
+		load_wake_target(&conn, &peer)?.ok_or_else(|| anyhow!("wake target of {peer} disappeared"))
+	}
+
+	/// Broadcasts a Wake-on-LAN magic packet for `peer`. Fails when no MAC
+	/// address is known or the network refuses the broadcast.
+	pub fn wake_peer(&self, peer: PeerId) -> Result<WakeTarget> {
+		wake::send_wake(&self.db, peer)
+	}
+
+	/// Waits up to `timeout` for `peer` to connect and returns how long
+	/// that took.
+	pub async fn wait_for_peer(
+		&self,
+		peer: PeerId,
+		timeout: Duration,
+	) -> Result<Duration, DidNotWake> {
+		wait_until_connected(peer, timeout, || async move {
+			self.connections()
+				.await
+				.iter()
+				.any(|connection| connection.peer_id == peer)
+		})
+		.await
+	}
+
+	/// Returns right away when `peer` is connected. Otherwise wakes it and
+	/// waits up to [`wake::WAKE_TIMEOUT`]; a peer that stays away fails
+	/// with [`DidNotWake`].
+	pub async fn wake_if_needed(&self, peer: PeerId) -> Result<()> {
+		if self.wait_for_peer(peer, Duration::ZERO).await.is_ok() {
+			return Ok(());
+		}
+		self.wake_peer(peer)?;
+		self.wait_for_peer(peer, wake::WAKE_TIMEOUT).await?;
+		Ok(())
+	}
+
+	pub async fn list_media_sources(&self, peer_id: PeerId) -> Result<Vec<MediaSource>> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ListMediaSources { tx, peer_id })
+			.map_err(|e| anyhow!("failed to send ListMediaSources command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ListMediaSources response channel closed: {e}"))?
+	}
+
+	pub async fn get_media_frame(&self, peer_id: PeerId, source_id: String) -> Result<MediaFrame> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::GetMediaFrame {
+				tx,
+				peer_id,
+				source_id,
+			})
+			.map_err(|e| anyhow!("failed to send GetMediaFrame command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("GetMediaFrame response channel closed: {e}"))?
+	}
+
+	/// Asks `peer` to scan `path`, under `limits` as far as its own allow.
+	pub fn scan_remote_peer(
+		&self,
+		peer: PeerId,
+		path: impl Into<WirePath>,
+		limits: ScanOverrides,
+	) -> Result<ScanHandle, String> {
+		limits.validate().map_err(|err| err.to_string())?;
+		let path = SafePath::remote(&path.into()).map_err(|err| err.to_string())?;
+		if self.local_peer_id()? == peer {
+			return self.scan_folder_with_options(path.to_string(), false, false, limits);
+		}
+		let (tx, rx) = event_channel();
+		let scan_id = self.next_id(IdKind::Scan);
+		self.remote_scans
+			.insert(scan_id, relay(&self.runtime, tx), Utc::now())
+			.map_err(|err| err.to_string())?;
+		self.cmd_tx
+			.send(Command::RemoteScan {
+				peer,
+				path,
+				scan_id,
+				limits,
+			})
+			.map_err(|e| {
+				self.remote_scans.remove(scan_id);
+				format!("failed to send RemoteScan command: {e}")
+			})?;
+		Ok(ScanHandle {
+			receiver: Arc::new(tokio::sync::Mutex::new(rx)),
+			cancel_flag: Arc::new(AtomicBool::new(false)),
+		})
+	}
+
+	/// Whether the results of scans this node asks peers for are pulled
+	/// into its index.
+	pub fn mirror_remote_scans(&self) -> bool {
+		let value = self
+			.db
+			.read(|conn| load_setting(conn, MIRROR_REMOTE_SCANS_SETTING))
+			.unwrap_or_else(|err| {
+				tracing::error!("failed to load remote scan mirroring setting: {err}");
+				None
+			});
+		scan_results::mirror_enabled_from_setting(value.as_deref())
+	}
+
+	pub fn set_mirror_remote_scans(&self, enabled: bool) -> Result<()> {
+		self.maintenance.check()?;
+		self.db.write(|conn| {
+			save_setting(
+				conn,
+				MIRROR_REMOTE_SCANS_SETTING,
+				if enabled { "true" } else { "false" },
+			)
+		})
+	}
+
+	/// Pulls the results of `peer`'s scan run `run`, one this node asked
+	/// for, into its index. Continues where an earlier pull of the run
+	/// stopped, until it has them all or fetched [`SCAN_RESULTS_PULL_CAP`]
+	/// more; call again to continue. `progress` sees every batch kept.
+	pub async fn pull_scan_results(
+		&self,
+		peer: PeerId,
+		run: i64,
+		progress: impl Fn(&ScanResultsPull),
+	) -> Result<ScanResultsPull> {
+		let mut pull = self
+			.db
+			.read(|conn| load_scan_result_pull(conn, &peer, run))?
+			.unwrap_or_else(|| ScanResultsPull::new(&peer, run, Utc::now()));
+		let mut pulled = 0;
+		while !pull.is_done() && pulled < SCAN_RESULTS_PULL_CAP {
+			let (tx, rx) = oneshot::channel();
+			self.cmd_tx
+				.send(Command::PullScanResults {
+					peer,
+					run,
+					cursor: pull.cursor,
+					tx,
+				})
+				.map_err(|e| anyhow!("failed to send PullScanResults command: {e}"))?;
+			let batch = rx
+				.await
+				.map_err(|e| anyhow!("PullScanResults response channel closed: {e}"))?;
+			let kept = batch.and_then(|results| {
+				pulled += results.locations.len() as u64;
+				self.db
+					.write(|conn| receive_scan_results(conn, &peer, &mut pull, results, Utc::now()))
+			});
+			if let Err(err) = kept {
+				pull.error = Some(format!("{err:#}"));
+				pull.updated_at = Utc::now();
+				if let Err(save_err) = self.db.write(|conn| save_scan_result_pull(conn, &pull)) {
+					tracing::warn!("failed to record scan results pull of {peer}: {save_err}");
+				}
+				return Err(err);
+			}
+			progress(&pull);
+		}
+		Ok(pull)
+	}
+
+	/// Pulls of `peer`'s scan results this node started, latest first.
+	pub fn scan_result_pulls(&self, peer: &PeerId) -> Result<Vec<ScanResultsPull>> {
+		self.db.read(|conn| load_scan_result_pulls(conn, peer))
+	}
+
+	pub async fn list_file_entries(
+		&self,
+		peer: PeerId,
+		offset: u64,
+		limit: u64,
+	) -> Result<Vec<FileEntry>> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ListFileEntries {
+				peer,
+				offset,
+				limit,
+				tx,
+			})
+			.map_err(|e| anyhow!("failed to send ListFileEntries command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ListFileEntries response channel closed: {e}"))?
+	}
+
+	pub async fn list_storage_files(&self) -> Result<Vec<StorageUsageFile>> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ListStorageFiles { tx })
+			.map_err(|e| anyhow!("failed to send ListStorageFiles command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ListStorageFiles response channel closed: {e}"))?
+	}
+
+	/// Writes the folder tree of what `node` (this node when `None`) has
+	/// indexed to `dest`, down to `max_depth` levels, and returns the rows
+	/// written.
+	pub fn export_storage_tree(
+		&self,
+		node: Option<NodeID>,
+		max_depth: Option<u32>,
+		dest: &Path,
+		format: StorageExportFormat,
+	) -> Result<u64> {
+		let files = self.db.read(|conn| {
+			let node = match node {
+				Some(node) => node,
+				None => {
+					get_your_node(conn)?.ok_or_else(|| anyhow!("this node has no identity yet"))?
+				}
+			};
+			storage_files_of(conn, &node)?.ok_or_else(|| anyhow!("unknown node"))
+		})?;
+		let tree = StorageTree::build(&files);
+		let file = std::fs::File::create(dest)
+			.map_err(|err| anyhow!("failed to create {}: {err}", dest.display()))?;
+		let mut out = std::io::BufWriter::new(file);
+		let rows = write_storage_export(&tree, max_depth, format, &mut out)?;
+		std::io::Write::flush(&mut out)?;
+		Ok(rows)
+	}
+
+	pub fn resolve_local_file_by_hash(
+		&self,
+		hash: &[u8],
+	) -> Result<Option<(PathBuf, FileEntry)>, String> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| format!("db lock poisoned: {err}"))?;
+		let node_id = match get_your_node(&conn)
+			.map_err(|err| format!("failed to load local node id: {err}"))?
+		{
+			Some(id) => id,
+			None => return Ok(None),
+		};
+		let file_entry = get_file_entry(&conn, hash)
+			.map_err(|err| format!("failed to load file entry: {err}"))?;
+		let entry = match file_entry {
+			Some(entry) => entry,
+			None => return Ok(None),
+		};
+		let location = get_file_location(&conn, &node_id, hash)
+			.map_err(|err| format!("failed to load file location: {err}"))?;
+		let location = match location {
+			Some(location) => location,
+			None => return Ok(None),
+		};
+		Ok(Some((location.path, entry)))
+	}
+
+	/// Copies or moves `source` into the content store and takes a reference
+	/// on it; identical content is only kept once.
+	pub fn store_file(&self, source: impl AsRef<Path>, mode: StoreMode) -> Result<ContentRef> {
+		self.store.store_file(source.as_ref(), mode, Utc::now())
+	}
+
+	/// Links or copies stored content out to `dest`.
+	pub fn materialize(&self, content: &ContentRef, dest: impl AsRef<Path>) -> Result<()> {
+		self.store.materialize(content, dest.as_ref())
+	}
+
+	/// Drops a reference taken by `store_file`; returns the remaining count.
+	pub fn release_content(&self, content: &ContentRef) -> Result<u64> {
+		self.store.release(&content.hash)
+	}
+
+	/// Whether content with this hash is already in the local store, in which
+	/// case there is nothing to transfer.
+	pub fn has_stored_content(&self, hash: &[u8]) -> Result<bool> {
+		self.store.contains(hash)
+	}
+
+	/// Which of `hashes` the content store holds, checked in one go.
+	pub fn stored_content(&self, hashes: &[FileHash]) -> Result<HashSet<FileHash>> {
+		self.store.contained(hashes)
+	}
+
+	pub fn gc_store(&self) -> Result<StoreGcReport> {
+		self.store.gc()
+	}
+
+	/// Grants `peer` has on this node and the revision they are at.
+	pub fn granted_permission_set(&self, peer: PeerId) -> Result<PermissionSet> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ListGrantedPermissions { peer, tx })
+			.map_err(|e| anyhow!("failed to send ListGrantedPermissions command: {e}"))?;
+		block_on(rx).map_err(|e| anyhow!("ListGrantedPermissions response channel closed: {e}"))?
+	}
+
+	pub fn list_granted_permissions(&self, peer: PeerId) -> Result<Vec<Permission>> {
+		self.granted_permission_set(peer).map(|set| set.permissions)
+	}
+
+	/// Lets `peer` access `path` with `flags` for `ttl`. The grant is kept
+	/// in memory only: it lapses on its own and is gone after a restart.
+	pub fn grant_temporary(
+		&self,
+		peer: PeerId,
+		path: impl Into<PathBuf>,
+		flags: u8,
+		ttl: Duration,
+	) -> Result<TemporaryGrant> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::GrantTemporary {
+				peer,
+				path: path.into(),
+				flags,
+				ttl,
+				tx,
+			})
+			.map_err(|e| anyhow!("failed to send GrantTemporary command: {e}"))?;
+		block_on(rx).map_err(|e| anyhow!("GrantTemporary response channel closed: {e}"))?
+	}
+
+	pub fn revoke_temporary(&self, peer: PeerId, id: u64) -> Result<()> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::RevokeTemporary { peer, id, tx })
+			.map_err(|e| anyhow!("failed to send RevokeTemporary command: {e}"))?;
+		block_on(rx).map_err(|e| anyhow!("RevokeTemporary response channel closed: {e}"))?
+	}
+
+	pub fn list_temporary_grants(&self, peer: PeerId) -> Result<Vec<TemporaryGrant>> {
+		self.granted_permission_set(peer).map(|set| set.temporary)
+	}
+
+	/// Grants `peer` gave this node. Cached grants younger than the grant
+	/// cache TTL come back without asking the peer; older ones come back
+	/// marked as revalidating while a refetch runs in the background.
+	/// `force_refresh` always asks the peer, for callers that must not act
+	/// on stale grants.
+	pub async fn list_permissions(
+		&self,
+		peer: PeerId,
+		force_refresh: bool,
+	) -> Result<RemoteGrants> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ListPermissions {
+				peer,
+				force_refresh,
+				tx,
+			})
+			.map_err(|e| anyhow!("failed to send ListPermissions command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ListPermissions response channel closed: {e}"))?
+	}
+
+	/// What the folders `peer` lets this node search hold. Summaries
+	/// fetched within [`crate::share_summary::SHARE_SUMMARY_TTL`] come back
+	/// without asking the peer unless `force_refresh` is set. Fails for
+	/// peers that don't summarize their shares, which callers show as the
+	/// granted folders.
+	pub async fn share_summaries(
+		&self,
+		peer: PeerId,
+		force_refresh: bool,
+	) -> Result<Vec<ShareSummary>> {
+		let supported = self
+			.peer_capabilities(peer)
+			.await
+			.is_none_or(|capabilities| capabilities.supports(FEATURE_SHARE_SUMMARY));
+		if !supported {
+			bail!("peer does not summarize its shares");
+		}
+		if !force_refresh
+			&& let Some(summaries) = self.share_summaries.lock().unwrap().get(&peer, Utc::now())
+		{
+			return Ok(summaries);
+		}
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ShareSummaries { peer_id: peer, tx })
+			.map_err(|e| anyhow!("failed to send ShareSummaries command: {e}"))?;
+		let summaries = rx
+			.await
+			.map_err(|e| anyhow!("ShareSummaries response channel closed: {e}"))??;
+		self.share_summaries
+			.lock()
+			.unwrap()
+			.store(peer, summaries.clone(), Utc::now());
+		Ok(summaries)
+	}
+
+	pub fn scan_folder(&self, path: impl Into<String>) -> Result<ScanHandle, String> {
+		self.scan_folder_with_override(path, false)
+	}
+
+	/// Starts a scan, or defers it until the activity window opens unless
+	/// `override_window` is set. Scans started inside the window are
+	/// cancelled when it closes if the window is in hard-stop mode.
+	pub fn scan_folder_with_override(
+		&self,
+		path: impl Into<String>,
+		override_window: bool,
+	) -> Result<ScanHandle, String> {
+		self.scan_folder_with_options(path, override_window, false, ScanOverrides::default())
+	}
+
+	/// Like [`Self::scan_folder_with_override`]; with `extract_metadata`
+	/// the scan also reads dimensions and durations of new media before
+	/// it reports finished. `limits` replace the node's scan limits for
+	/// this scan.
+	pub fn scan_folder_with_options(
+		&self,
+		path: impl Into<String>,
+		override_window: bool,
+		extract_metadata: bool,
+		limits: ScanOverrides,
+	) -> Result<ScanHandle, String> {
+		self.maintenance.check().map_err(|err| err.to_string())?;
+		limits.validate().map_err(|err| err.to_string())?;
+		let path: String = path.into();
+		let path = SafePath::new(&path).map_err(|err| err.to_string())?;
+		let (tx, rx) = event_channel();
+		let cancel_flag = Arc::new(AtomicBool::new(false));
+		let handle = ScanHandle {
+			receiver: Arc::new(tokio::sync::Mutex::new(rx)),
+			cancel_flag: Arc::clone(&cancel_flag),
+		};
+		let window = self.activity_window.lock().unwrap().clone();
+		let opening = window.next_opening(ActivityKind::Scan, Utc::now());
+		let Some(opening) = opening.filter(|_| !override_window) else {
+			self.cmd_tx
+				.send(Command::Scan {
+					path,
+					tx,
+					cancel_flag: Arc::clone(&cancel_flag),
+					extract_metadata,
+					limits,
+				})
+				.map_err(|e| format!("failed to send Scan command: {e}"))?;
+			if !override_window {
+				cancel_when_window_closes(
+					Arc::clone(&self.activity_window),
+					ActivityKind::Scan,
+					cancel_flag,
+				);
+			}
+			return Ok(handle);
+		};
+
+		let until = window.local_time_label(opening);
+		let _ = tx.try_send(ScanEvent::Deferred {
+			until: until.clone(),
+		});
+		let id = self.next_id(IdKind::Scan);
+		self.deferred.lock().unwrap().insert(
+			(ActivityKind::Scan, id),
+			DeferredEntry {
+				activity: DeferredActivity {
+					id,
+					kind: ActivityKind::Scan,
+					label: format!("Scan {path}"),
+					until,
+				},
+				cancel_flag: Arc::clone(&cancel_flag),
+			},
+		);
+		let window = Arc::clone(&self.activity_window);
+		let deferred = Arc::clone(&self.deferred);
+		let cmd_tx = self.cmd_tx.clone();
+		std::thread::spawn(move || {
+			let ready = wait_for_activity_window(&window, ActivityKind::Scan, &cancel_flag);
+			deferred.lock().unwrap().remove(&(ActivityKind::Scan, id));
+			if !ready {
+				send_blocking(
+					&tx,
+					ScanEvent::Finished(Err(String::from("Scan cancelled before it started"))),
+				);
+				return;
+			}
+			let scan_cancel_flag = Arc::clone(&cancel_flag);
+			if cmd_tx
+				.send(Command::Scan {
+					path,
+					tx,
+					cancel_flag: scan_cancel_flag,
+					extract_metadata,
+					limits,
+				})
+				.is_ok()
+			{
+				cancel_when_window_closes(window, ActivityKind::Scan, cancel_flag);
+			}
+		});
+		Ok(handle)
+	}
+
+	/// Extracts media metadata for this node's indexed files under `path`,
+	/// or everywhere, that have none yet.
+	pub async fn extract_media_metadata(&self, path: Option<String>) -> Result<MediaExtractReport> {
+		let path = match path {
+			Some(path) => Some(
+				tokio::fs::canonicalize(&path)
+					.await
+					.map_err(|err| anyhow!("failed to access {path}: {err}"))?
+					.to_string_lossy()
+					.into_owned(),
+			),
+			None => None,
+		};
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::GetLocalPeerId { tx })
+			.map_err(|e| anyhow!("failed to send GetLocalPeerId command: {e}"))?;
+		let peer = rx
+			.await
+			.map_err(|e| anyhow!("GetLocalPeerId response channel closed: {e}"))?;
+		let node_id =
+			peer_to_node_id(&peer).ok_or_else(|| anyhow!("invalid local peer id {peer}"))?;
+		let db = self.db.clone();
+		tokio::task::spawn_blocking(move || {
+			extract_media_metadata(&db, &node_id, path.as_deref(), || false)
+		})
+		.await
+		.map_err(|err| anyhow!("metadata task failed: {err}"))
+	}
+
+	/// Whether background sampling re-hashes files, how many per cycle and
+	/// how fast it may read.
+	pub fn integrity_settings(&self) -> IntegritySettings {
+		self.integrity_settings.lock().unwrap().clone()
+	}
+
+	pub fn set_integrity_settings(&self, settings: IntegritySettings) -> Result<()> {
+		self.maintenance.check()?;
+		settings.validate()?;
+		let value = serde_json::to_string(&settings)?;
+		self.db
+			.write(|conn| save_setting(conn, INTEGRITY_SETTINGS_SETTING, &value))?;
+		*self.integrity_settings.lock().unwrap() = settings;
+		Ok(())
+	}
+
+	/// How much of this node's index background sampling verified lately.
+	pub fn integrity_status(&self) -> Result<IntegrityStatus> {
+		let enabled = self.integrity_settings.lock().unwrap().enabled;
+		let status = self.db.read(|conn| match get_your_node(conn)? {
+			Some(node_id) => integrity::integrity_status(conn, &node_id, Utc::now()),
+			None => Ok(IntegrityStatus::default()),
+		})?;
+		Ok(IntegrityStatus { enabled, ..status })
+	}
+
+	/// Files sampling found damaged, each with the copies it can be
+	/// recovered from.
+	pub fn integrity_findings(&self) -> Result<Vec<IntegrityFinding>> {
+		self.db.read(|conn| match get_your_node(conn)? {
+			Some(node_id) => integrity::integrity_findings(conn, &node_id),
+			None => Ok(Vec::new()),
+		})
+	}
+
+	/// Files of this node, under `path_prefix` if given, whose content no
+	/// longer matches a checksum manifest found while scanning.
+	pub fn hash_mismatches(&self, path_prefix: Option<&str>) -> Result<Vec<HashMismatch>> {
+		let peer = self.local_peer_id().map_err(|err| anyhow!(err))?;
+		let node_id =
+			peer_to_node_id(&peer).ok_or_else(|| anyhow!("invalid local peer id {peer}"))?;
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		load_hash_mismatches(&conn, &node_id, path_prefix)
+	}
+
+	/// Metadata of the content the index knows at `path` on `peer`.
+	pub fn media_metadata_at(&self, peer: PeerId, path: &str) -> Result<Option<MediaMetadata>> {
+		let Some(node_id) = peer_to_node_id(&peer) else {
+			return Ok(None);
+		};
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		let Some(hash) = indexed_hash(&conn, &node_id, path)? else {
+			return Ok(None);
+		};
+		load_media_metadata(&conn, &hash)
+	}
+
+	pub fn activity_window(&self) -> ActivityWindow {
+		self.activity_window.lock().unwrap().clone()
+	}
+
+	/// Persists the window and applies it to new and waiting work.
+	pub fn set_activity_window(&self, window: ActivityWindow) -> anyhow::Result<()> {
+		self.maintenance.check()?;
+		let value = serde_json::to_string(&window)?;
+		{
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			save_setting(&conn, ACTIVITY_WINDOW_SETTING, &value)?;
+		}
+		*self.activity_window.lock().unwrap() = window;
+		Ok(())
+	}
+
+	/// The power supply and whether background work waits for it.
+	pub fn power_state(&self) -> PowerState {
+		self.power.lock().unwrap().state()
+	}
+
+	pub fn power_policy(&self) -> PowerPolicy {
+		self.power.lock().unwrap().policy()
+	}
+
+	/// Records the power state and, once work may run, starts what was due.
+	fn power_changed(&self) {
+		let power = self.power_state();
+		if !power.background_paused {
+			self.pin_wake.notify_one();
+			self.outbox_wake.notify_one();
+			self.replication_wake.notify_one();
+		}
+		let _ = self.cmd_tx.send(Command::SetPowerState { power });
+	}
+
+	/// Persists the policy and applies it to the last power reading.
+	pub fn set_power_policy(&self, policy: PowerPolicy) -> anyhow::Result<()> {
+		self.maintenance.check()?;
+		if let PowerPolicy::PauseBelow { percent } = policy
+			&& !(1..=100).contains(&percent)
+		{
+			bail!("battery level must be between 1 and 100");
+		}
+		let value = serde_json::to_string(&policy)?;
+		{
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			save_setting(&conn, POWER_POLICY_SETTING, &value)?;
+		}
+		self.power.lock().unwrap().set_policy(policy);
+		self.power_changed();
+		Ok(())
+	}
+
+	/// Lets background work held for power run until the hold next ends.
+	/// False when nothing was held.
+	pub fn run_background_anyway(&self) -> bool {
+		let released = self.power.lock().unwrap().run_anyway();
+		if released {
+			tracing::info!("running background work on battery this time");
+			self.power_changed();
+		}
+		released
+	}
+
+	/// Free space, in percent, below which this node raises a low-space
+	/// notification for a disk. 0 means alerts are off.
+	pub fn disk_alert_threshold(&self) -> u8 {
+		let conn = self.db.lock().unwrap();
+		disk_history::low_space_percent(&conn)
+	}
+
+	pub fn set_disk_alert_threshold(&self, percent: u8) -> anyhow::Result<()> {
+		self.maintenance.check()?;
+		if percent > 100 {
+			bail!("disk alert threshold must be between 0 and 100");
+		}
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		save_setting(&conn, LOW_SPACE_PERCENT_SETTING, &percent.to_string())
+	}
+
+	/// Days deleted files stay searchable before scans purge them. 0 keeps
+	/// them forever.
+	pub fn tombstone_retention_days(&self) -> u32 {
+		let conn = self.db.lock().unwrap();
+		scan::tombstone_retention_days(&conn)
+	}
+
+	pub fn set_tombstone_retention_days(&self, days: u32) -> anyhow::Result<()> {
+		self.maintenance.check()?;
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		save_setting(&conn, TOMBSTONE_RETENTION_SETTING, &days.to_string())
+	}
+
+	/// Forgets files deleted longer than `older_than` ago, so they no longer
+	/// show up in searches that include deleted files. Returns how many were
+	/// dropped.
+	pub fn purge_tombstones(&self, older_than: chrono::Duration) -> anyhow::Result<usize> {
+		self.maintenance.check()?;
+		self.index.purge_tombstones(older_than)
+	}
+
+	/// Longest edge, in pixels, of thumbnails served to peers that may
+	/// only preview a folder.
+	pub fn preview_max_dimension(&self) -> u32 {
+		let conn = self.db.lock().unwrap();
+		let value = load_setting(&conn, PREVIEW_MAX_DIMENSION_SETTING).unwrap_or_else(|err| {
+			tracing::warn!("failed to load preview size limit: {err}");
+			None
+		});
+		preview_max_from_setting(value.as_deref())
+	}
+
+	/// Bytes one image may take decoded for a thumbnail on this node.
+	pub fn thumbnail_decode_budget(&self) -> u64 {
+		let conn = self.db.lock().unwrap();
+		let value = load_setting(&conn, THUMBNAIL_DECODE_BUDGET_SETTING).unwrap_or_else(|err| {
+			tracing::warn!("failed to load thumbnail decode budget: {err}");
+			None
+		});
+		budget_from_setting(value.as_deref())
+	}
+
+	pub fn set_preview_max_dimension(&self, px: u32) -> anyhow::Result<()> {
+		self.maintenance.check()?;
+		if px == 0 {
+			bail!("preview size limit must be at least 1 pixel");
+		}
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		save_setting(&conn, PREVIEW_MAX_DIMENSION_SETTING, &px.to_string())
+	}
+
+	/// File name patterns scans treat as checksum manifests.
+	pub fn manifest_patterns(&self) -> Vec<String> {
+		let conn = self.db.lock().unwrap();
+		let value = load_setting(&conn, MANIFEST_PATTERNS_SETTING).unwrap_or_else(|err| {
+			tracing::warn!("failed to load manifest patterns: {err}");
+			None
+		});
+		patterns_from_setting(value.as_deref())
+	}
+
+	/// Replaces the manifest patterns; an empty list restores the defaults.
+	pub fn set_manifest_patterns(&self, patterns: &[String]) -> anyhow::Result<()> {
+		self.maintenance.check()?;
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		save_setting(&conn, MANIFEST_PATTERNS_SETTING, &patterns.join(","))
+	}
+
+	/// Work waiting for its activity window to open, soonest first.
+	pub fn deferred_activities(&self) -> Vec<DeferredActivity> {
+		let mut activities = self
+			.deferred
+			.lock()
+			.unwrap()
+			.values()
+			.map(|entry| entry.activity.clone())
+			.collect::<Vec<_>>();
+		activities.sort_by(|a, b| a.until.cmp(&b.until).then(a.id.cmp(&b.id)));
+		activities
+	}
+
+	/// Cancels deferred work of `kind` before it starts. A scan finishes
+	/// with an error and an update reports `Failed`. Returns false when
+	/// nothing of that kind and id is waiting.
+	pub fn cancel_deferred(&self, kind: ActivityKind, id: u64) -> bool {
+		let Some(entry) = self.deferred.lock().unwrap().remove(&(kind, id)) else {
+			return false;
+		};
+		entry.cancel_flag.store(true, Ordering::SeqCst);
+		true
+	}
+
+	/// Recorded scan runs, newest first, `SCAN_HISTORY_PAGE_SIZE` per page.
+	pub fn scan_history(&self, path: Option<String>, page: u64) -> Result<Vec<ScanRun>, String> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| format!("db lock poisoned: {err}"))?;
+		load_scan_history(
+			&conn,
+			path.as_deref(),
+			page * SCAN_HISTORY_PAGE_SIZE,
+			SCAN_HISTORY_PAGE_SIZE,
+		)
+		.map_err(|err| format!("failed to load scan history: {err}"))
+	}
+
+	/// Files added, removed or changed between two completed runs of a path.
+	pub fn scan_diff(
+		&self,
+		run_a: i64,
+		run_b: i64,
+		page: u64,
+	) -> Result<Vec<ScanDiffEntry>, String> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| format!("db lock poisoned: {err}"))?;
+		scan_diff(
+			&conn,
+			run_a,
+			run_b,
+			page * SCAN_HISTORY_PAGE_SIZE,
+			SCAN_HISTORY_PAGE_SIZE,
+		)
+		.map_err(|err| format!("failed to diff scan runs: {err}"))
+	}
+
+	/// File count growth of `path` over the last 30 days of completed runs.
+	pub fn scan_trend(&self, path: &str) -> Result<Option<ScanTrend>, String> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| format!("db lock poisoned: {err}"))?;
+		scan_trend(&conn, path, Utc::now() - chrono::Duration::days(30))
+			.map_err(|err| format!("failed to load scan trend: {err}"))
+	}
+
+	/// This node's peer id.
+	pub async fn local_peer(&self) -> Result<PeerId, String> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::GetLocalPeerId { tx })
+			.map_err(|e| format!("failed to send GetLocalPeerId command: {e}"))?;
+		rx.await
+			.map_err(|e| format!("GetLocalPeerId response channel closed: {e}"))
+	}
+
+	/// How much the folders `node` scans grew over the last `period`, and
+	/// when its disks run low. Only a node's own scan history and disk
+	/// samples are kept, so `node` has to be this node.
+	pub async fn growth_report(
+		&self,
+		node: PeerId,
+		period: chrono::Duration,
+	) -> Result<GrowthReport, String> {
+		if node != self.local_peer().await? {
+			return Err(format!("no storage history of {node} is kept here"));
+		}
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| format!("db lock poisoned: {err}"))?;
+		let now = Utc::now();
+		storage_growth::growth_report(
+			&conn,
+			now - period,
+			now,
+			disk_history::low_space_percent(&conn),
+		)
+		.map_err(|err| format!("failed to build growth report: {err}"))
+	}
+
+	pub fn live_search_peers(
+		&self,
+		peers: Vec<PeerId>,
+		args: LiveSearchArgs,
+	) -> Result<Arc<Mutex<mpsc::Receiver<LiveSearchPeerEvent>>>, String> {
+		let (events_tx, events_rx) = mpsc::channel();
+		for peer in peers {
+			let (tx, rx) = mpsc::channel();
+			let search_id = self.next_id(IdKind::Search);
+			self.remote_searches
+				.lock()
+				.unwrap()
+				.insert(search_id, tx.clone());
+
+			let forward_tx = events_tx.clone();
+			std::thread::spawn(move || {
+				while let Ok(event) = rx.recv() {
+					let terminal = matches!(
+						event,
+						SearchEvent::Finished { .. } | SearchEvent::Failed { .. }
+					);
+					let _ = forward_tx.send(LiveSearchPeerEvent { peer, event });
+					if terminal {
+						break;
+					}
+				}
+			});
+
+			if let Err(err) = self.cmd_tx.send(Command::LiveSearch {
+				peer,
+				args: args.clone(),
+				search_id,
+			}) {
+				self.remote_searches.lock().unwrap().remove(&search_id);
+				let _ = tx.send(SearchEvent::Failed {
+					error: format!("failed to send LiveSearch command: {err}"),
+				});
+			}
+		}
+		drop(events_tx);
+		Ok(Arc::new(Mutex::new(events_rx)))
+	}
+
+	/// Newest scan results first, continuing after `cursor`.
+	/// Returns (page, total_count)
+	pub fn fetch_scan_results_after(
+		&self,
+		cursor: Option<&PageCursor>,
+		limit: usize,
+	) -> Result<(CursorPage<ScanResultRow>, usize), String> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| format!("db lock poisoned: {}", err))?;
+		let query = SearchQuery::all(limit).map_err(|err| err.to_string())?;
+		let (count_sql, count_params) = query.count_sql();
+		let total_entries: i64 = conn
+			.query_row(
+				&count_sql,
+				rusqlite::params_from_iter(&count_params),
+				|row| row.get(0),
+			)
+			.map_err(|err| format!("failed to count scan results: {err}"))?;
+		let (sql, params) = query.select_sql(FILE_ENTRY_COLUMNS, cursor);
+		let mut stmt = conn
+			.prepare(&sql)
+			.map_err(|err| format!("failed to prepare scan results query: {err}"))?;
+		let rows = stmt
+			.query_map(rusqlite::params_from_iter(&params), |row| {
+				let hash: Vec<u8> = row.get(0)?;
+				let size = row.get::<_, i64>(1)?.max(0) as u64;
+				let mime_type = row.get(2)?;
+				let first = row.get(3)?;
+				let latest = row.get(4)?;
+				Ok(ScanResultRow {
+					hash,
+					size,
+					mime_type,
+					first_datetime: first,
+					latest_datetime: latest,
+				})
+			})
+			.map_err(|err| format!("failed to query scan results: {err}"))?;
+		let mut entries = Vec::new();
+		for entry in rows {
+			entries.push(entry.map_err(|err| format!("error reading scan row: {err}"))?);
+		}
+		let page = cursor_page(entries, query.page_size(), |row| {
+			PageCursor::new(row.latest_datetime.clone(), row.hash.clone())
+		});
+		Ok((page, total_entries.max(0) as usize))
+	}
+
+	pub fn fetch_scan_results_page(
+		&self,
+		page: usize,
+		page_size: usize,
+	) -> Result<(CursorPage<ScanResultRow>, usize), String> {
+		let cursor = {
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| format!("db lock poisoned: {}", err))?;
+			let skip =
+				check_offset(page.saturating_mul(page_size)).map_err(|err| err.to_string())?;
+			skip_cursor(&conn, "", &[], true, skip)
+				.map_err(|err| format!("failed to query scan results: {err}"))?
+		};
+		self.fetch_scan_results_after(cursor.as_ref(), page_size)
+	}
+
+	/// Search files continuing after `cursor`; `args.page` is ignored.
+	/// Returns (page, mime_types, total_count)
+	pub fn search_files_after(
+		&self,
+		args: crate::db::SearchFilesArgs,
+		cursor: Option<&PageCursor>,
+	) -> Result<(CursorPage<crate::db::FileSearchResult>, Vec<String>, usize), String> {
+		self.index
+			.search_after(args, cursor)
+			.map_err(|err| format!("search failed: {err}"))
+	}
+
+	/// Search files using file_entries and file_locations tables
+	/// Returns (page, mime_types, total_count)
+	pub fn search_files(
+		&self,
+		args: crate::db::SearchFilesArgs,
+	) -> Result<(CursorPage<crate::db::FileSearchResult>, Vec<String>, usize), String> {
+		self.index
+			.search(args)
+			.map_err(|err| format!("search failed: {err}"))
+	}
+
+	/// One page of `peer`'s own index search. The peer answers only with
+	/// files on itself under folders this node may search, and without
+	/// counts or mime types.
+	pub async fn search_peer_files(
+		&self,
+		peer: PeerId,
+		args: crate::db::SearchFilesArgs,
+	) -> Result<Vec<crate::db::FileSearchResult>> {
+		let supported = self
+			.peer_capabilities(peer)
+			.await
+			.is_none_or(|capabilities| capabilities.supports(FEATURE_SEARCH_FILES));
+		if !supported {
+			bail!("peer does not support index search");
+		}
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::SearchPeerFiles {
+				peer_id: peer,
+				args,
+				tx,
+			})
+			.map_err(|e| anyhow!("failed to send SearchPeerFiles command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("SearchPeerFiles response channel closed: {e}"))?
+	}
+
+	/// Runs the same index search on every connected peer at once and
+	/// merges the pages. Peers that fail or do not answer within
+	/// [`PEER_SEARCH_TIMEOUT`] are listed as skipped.
+	pub async fn search_connected_peers(&self, args: crate::db::SearchFilesArgs) -> PeerSearch {
+		let Some(state) = self.state_snapshot().await else {
+			return PeerSearch::default();
+		};
+		let mut peers: Vec<PeerId> = state
+			.connections
+			.iter()
+			.map(|connection| connection.peer_id)
+			.filter(|peer| *peer != state.me)
+			.collect();
+		peers.sort();
+		peers.dedup();
+		let (sort_by, sort_desc) = (args.sort_by, args.sort_desc);
+		let pages = fan_out(
+			peers,
+			|peer| self.search_peer_files(peer, args.clone()),
+			FanOutOptions {
+				per_peer_timeout: PEER_SEARCH_TIMEOUT,
+				..FanOutOptions::default()
+			},
+		)
+		.await;
+		peer_search::merge(pages, sort_by, sort_desc)
+	}
+
+	/// When the local index last recorded a change from `peer`.
+	pub fn index_updated_at(&self, peer: PeerId) -> Result<Option<DateTime<Utc>>, String> {
+		let node_id = peer_to_node_id(&peer).ok_or_else(|| format!("invalid peer id {peer}"))?;
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| format!("db lock poisoned: {err}"))?;
+		crate::db::node_index_updated_at(&conn, &node_id)
+			.map_err(|err| format!("failed to read index age: {err}"))
+	}
+
+	/// Who introduced the indexed location of `hash` at `path`: a scan, a
+	/// peer's write, an import or another node's index.
+	pub fn file_provenance(
+		&self,
+		hash: &[u8],
+		path: &str,
+	) -> Result<Option<crate::db::FileProvenance>, String> {
+		self.index
+			.file_provenance(hash, path)
+			.map_err(|err| format!("failed to read provenance: {err}"))
+	}
+
+	/// Get all available mime types from file_entries
+	pub fn get_mime_types(&self) -> Result<Vec<String>, String> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| format!("db lock poisoned: {err}"))?;
+		let mut stmt = conn
+			.prepare("SELECT DISTINCT mime_type FROM file_entries WHERE mime_type IS NOT NULL")
+			.map_err(|err| format!("failed to prepare mime types query: {err}"))?;
+		let rows = stmt
+			.query_map((), |row| row.get::<_, String>(0))
+			.map_err(|err| format!("failed to query mime types: {err}"))?;
+		let mut mime_types = Vec::new();
+		for mime in rows {
+			mime_types.push(mime.map_err(|err| format!("failed to read mime type: {err}"))?);
+		}
+		Ok(mime_types)
+	}
+
+	async fn send_read_file(
+		&self,
+		peer: libp2p::PeerId,
+		path: WirePath,
+		offset: u64,
+		length: Option<u64>,
+		sparse: bool,
+	) -> Result<FileChunk> {
+		let path = SafePath::remote(&path)?;
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ReadFile(ReadFileCmd {
+				peer_id: peer,
+				path,
+				offset,
+				length,
+				sparse,
+				tx,
+			}))
+			.map_err(|e| anyhow!("failed to send ReadFile command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ReadFile response channel closed: {e}"))?
+	}
+
+	pub async fn read_file(
+		&self,
+		peer: libp2p::PeerId,
+		path: impl Into<WirePath>,
+		offset: u64,
+		length: Option<u64>,
+	) -> Result<FileChunk> {
+		self.send_read_file(peer, path.into(), offset, length, false)
+			.await
+	}
+
+	/// Like [`Self::read_file`], but holes of a sparse file come back as
+	/// [`FileChunk::hole`] from peers that can tell them apart, instead of
+	/// as zeros.
+	pub async fn read_file_sparse(
+		&self,
+		peer: libp2p::PeerId,
+		path: impl Into<WirePath>,
+		offset: u64,
+		length: Option<u64>,
+	) -> Result<FileChunk> {
+		self.send_read_file(peer, path.into(), offset, length, true)
+			.await
+	}
+
+	/// Reads a whole file, or its first `opts.max_bytes`, however many
+	/// chunks the peer needs to send it. `on_progress` gets the bytes read
+	/// so far after every chunk.
+	pub async fn read_file_to_end(
+		&self,
+		peer: libp2p::PeerId,
+		path: impl Into<WirePath>,
+		opts: ReadToEndOptions,
+		on_progress: impl FnMut(u64),
+	) -> Result<FileContents> {
+		let path = path.into();
+		let fetch = move |offset, length| self.read_file(peer, path.clone(), offset, Some(length));
+		file_read::read_to_end(fetch, &opts, on_progress).await
+	}
+
+	/// Reads `range` of a file in full; the chunk only comes back shorter,
+	/// with `eof` set, when the file ends first.
+	pub async fn read_file_range(
+		&self,
+		peer: libp2p::PeerId,
+		path: impl Into<WirePath>,
+		range: Range<u64>,
+	) -> Result<FileChunk> {
+		let path = path.into();
+		let fetch = move |offset, length| self.read_file(peer, path.clone(), offset, Some(length));
+		file_read::read_range(fetch, range, DEFAULT_READ_CHUNK_SIZE).await
+	}
+
+	pub async fn get_thumbnail(
+		&self,
+		peer: libp2p::PeerId,
+		path: impl Into<WirePath>,
+		max_width: u32,
+		max_height: u32,
+	) -> Result<Thumbnail> {
+		let path = SafePath::remote(&path.into())?;
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::GetThumbnail {
+				peer,
+				path,
+				max_width,
+				max_height,
+				tx,
+			})
+			.map_err(|e| anyhow!("failed to send GetThumbnail command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("GetThumbnail response channel closed: {e}"))?
+	}
+
+	/// The images in the folder `path` of `peer` as one grid, for seeing what
+	/// a folder holds without opening each file. The peer leaves out images
+	/// this node may not preview and answers with a partial sheet when time
+	/// runs out; a folder without images is [`ContactSheetResult::Empty`].
+	pub async fn contact_sheet(
+		&self,
+		peer: libp2p::PeerId,
+		path: impl Into<WirePath>,
+		options: ContactSheetOptions,
+	) -> Result<ContactSheetResult> {
+		let supported = self
+			.peer_capabilities(peer)
+			.await
+			.is_none_or(|capabilities| capabilities.supports(FEATURE_CONTACT_SHEET));
+		if !supported {
+			bail!("peer does not make contact sheets");
+		}
+		let path = SafePath::remote(&path.into())?;
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ContactSheet {
+				peer,
+				path,
+				options,
+				tx,
+			})
+			.map_err(|e| anyhow!("failed to send ContactSheet command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ContactSheet response channel closed: {e}"))?
+	}
+
+	/// Forgets thumbnails generated from `path` (or anything under it), for
+	/// when a file was changed behind puppynet's back.
+	pub fn invalidate_derived(&self, path: impl Into<PathBuf>) -> Result<()> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::InvalidateDerived {
+				path: path.into(),
+				tx,
+			})
+			.map_err(|e| anyhow!("failed to send InvalidateDerived command: {e}"))?;
+		block_on(rx).map_err(|e| anyhow!("InvalidateDerived response channel closed: {e}"))
+	}
+
+	pub async fn start_shell(&self, peer: PeerId, session_id: u64) -> Result<u64> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::StartShell {
+				peer,
+				session_id,
+				tx,
+			})
+			.map_err(|e| anyhow!("failed to send StartShell command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("StartShell response channel closed: {e}"))?
+	}
+
+	pub async fn shell_input(
+		&self,
+		peer: PeerId,
+		session_id: u64,
+		data: Vec<u8>,
+	) -> Result<ShellOutput> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::ShellInput {
+				peer,
+				session_id,
+				data,
+				tx,
+			})
+			.map_err(|e| anyhow!("failed to send ShellInput command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("ShellInput response channel closed: {e}"))?
+	}
+
+	/// Ends a shell session, killing the shell on `peer` if it still runs.
+	pub async fn close_shell(&self, peer: PeerId, session_id: u64) -> Result<()> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::CloseShell {
+				peer,
+				session_id,
+				tx,
+			})
+			.map_err(|e| anyhow!("failed to send CloseShell command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("CloseShell response channel closed: {e}"))?
+			.map(|_| ())
+	}
+
+	pub async fn desktop_input(&self, peer: PeerId, input: DesktopInput) -> Result<()> {
+		let (tx, rx) = oneshot::channel();
+		self.cmd_tx
+			.send(Command::DesktopInput { peer, input, tx })
+			.map_err(|e| anyhow!("failed to send DesktopInput command: {e}"))?;
+		rx.await
+			.map_err(|e| anyhow!("DesktopInput response channel closed: {e}"))?
+	}
+
+	fn record_transfer(&self, transfer: Transfer) {
+		if let Err(err) = self.cmd_tx.send(Command::RecordTransfer { transfer }) {
+			tracing::warn!("failed to queue transfer record: {err}");
+		}
+	}
+
+	/// Streams a local file into the peer's inbox, reporting its progress
+	/// after every chunk. A file the peer already has isn't sent, and
+	/// blocks of it the peer has are copied on its side; the returned
+	/// [`Upload`] and the transfer history say what was saved.
+	pub async fn send_file_with_progress(
+		&self,
+		peer: PeerId,
+		local_path: impl AsRef<Path>,
+		mut progress: impl FnMut(TransferProgress),
+	) -> Result<Upload> {
+		let local_path = local_path.as_ref();
+		let started = std::time::Instant::now();
+		let mut done = 0;
+		let mut rate = RateEstimator::new();
+		let result =
+			content_negotiation::send_file(&self.cmd_tx, peer, local_path, |sent, total| {
+				done = sent;
+				progress(TransferProgress::measure(&mut rate, sent, Some(total)));
+			})
+			.await;
+		let mut transfer = Transfer::new(
+			TransferDirection::Upload,
+			peer,
+			result
+				.as_ref()
+				.ok()
+				.and_then(Upload::remote_path)
+				.unwrap_or_default(),
+			local_path.to_string_lossy(),
+			started.elapsed(),
+		);
+		transfer.size = match &result {
+			Ok(upload) => upload.sent(),
+			Err(_) => done,
+		};
+		self.record_transfer(match &result {
+			Ok(upload) => {
+				let mut savings = SendSavings::default();
+				savings.add(upload);
+				transfer.note = savings.describe();
+				transfer
+			}
+			Err(err) => transfer.failed(err),
+		});
+		result
+	}
+
+	/// The folder downloads from `peer` are saved in: the peer's own
+	/// folder, else the global default, else the user's Downloads.
+	pub fn download_dir(&self, peer: Option<PeerId>) -> Result<PathBuf> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		let peer_dir = match peer {
+			Some(peer) => load_setting(&conn, &peer_download_dir_setting(&peer))?,
+			None => None,
+		};
+		let global_dir = load_setting(&conn, DOWNLOAD_DIR_SETTING)?
+			.or_else(|| config::startup().download_dir.clone());
+		Ok(resolve_download_dir(
+			peer_dir,
+			global_dir,
+			&LocationEnv::current(),
+		))
+	}
+
+	/// The download folder set for `peer`, or the global default for
+	/// `None`, without falling back to anything else.
+	pub fn configured_download_dir(&self, peer: Option<PeerId>) -> Result<Option<String>> {
+		let key = match peer {
+			Some(peer) => peer_download_dir_setting(&peer),
+			None => DOWNLOAD_DIR_SETTING.to_string(),
+		};
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		load_setting(&conn, &key)
+	}
+
+	/// Sets the download folder for `peer`, or the global default for
+	/// `None`. An empty `dir` clears it so the fallback applies again.
+	pub fn set_download_dir(&self, peer: Option<PeerId>, dir: Option<String>) -> Result<()> {
+		self.maintenance.check()?;
+		let key = match peer {
+			Some(peer) => peer_download_dir_setting(&peer),
+			None => DOWNLOAD_DIR_SETTING.to_string(),
+		};
+		let dir = dir
+			.map(|dir| dir.trim().to_string())
+			.filter(|dir| !dir.is_empty());
+		if let Some(dir) = &dir
+			&& !Path::new(dir).is_absolute()
+		{
+			bail!("download folder must be an absolute path");
+		}
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		match dir {
+			Some(dir) => save_setting(&conn, &key, &dir),
+			None => delete_setting(&conn, &key),
+		}
+	}
+
+	/// Scans, transfers, permission changes and peer connections matching
+	/// `filter`, newest first, continuing after `cursor`. `limit` is capped
+	/// at [`MAX_ACTIVITY_PAGE`].
+	pub fn activity(
+		&self,
+		filter: &ActivityFilter,
+		cursor: Option<&PageCursor>,
+		limit: u64,
+	) -> Result<CursorPage<ActivityEvent>, String> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| format!("db lock poisoned: {err}"))?;
+		load_activity(&conn, filter, cursor, limit.clamp(1, MAX_ACTIVITY_PAGE))
+			.map_err(|err| format!("failed to load activity: {err}"))
+	}
+
+	/// Days events stay on the activity timeline. 0 keeps them forever.
+	pub fn activity_retention_days(&self) -> u32 {
+		let conn = self.db.lock().unwrap();
+		activity_log::activity_retention_days(&conn)
+	}
+
+	pub fn set_activity_retention_days(&self, days: u32) -> anyhow::Result<()> {
+		self.maintenance.check()?;
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		save_setting(&conn, ACTIVITY_RETENTION_SETTING, &days.to_string())
+	}
+
+	/// Downloads and uploads, newest first, `TRANSFER_PAGE_SIZE` per page,
+	/// with one peer or all of them.
+	pub fn transfer_history(
+		&self,
+		peer: Option<PeerId>,
+		page: u64,
+	) -> Result<Vec<Transfer>, String> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| format!("db lock poisoned: {err}"))?;
+		load_transfers(
+			&conn,
+			peer.map(|peer| peer.to_string()).as_deref(),
+			page * TRANSFER_PAGE_SIZE,
+			TRANSFER_PAGE_SIZE,
+		)
+		.map_err(|err| format!("failed to load transfers: {err}"))
+	}
+
+	/// Files written by peers through quarantined grants that wait for a
+	/// decision, oldest first. Hashes missing since the last write are
+	/// computed here, so this reads every such file.
+	pub fn pending_reviews(&self) -> Result<Vec<PendingReview>> {
+		let mut reviews = {
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			load_pending_reviews(&conn)?
+		};
+		for review in reviews.iter_mut().filter(|review| review.hash.is_none()) {
+			let hash = match std::fs::File::open(&review.path)
+				.and_then(|file| scan::hash_file(std::io::BufReader::new(file)))
+			{
+				Ok(hash) => hash,
+				Err(err) => {
+					tracing::warn!("failed to hash {} for review: {err}", review.path);
+					continue;
+				}
+			};
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			set_pending_review_hash(&conn, review.id, review.received_at, &hash)?;
+			review.hash = Some(hash.to_vec());
+		}
+		Ok(reviews)
+	}
+
+	pub fn pending_review_count(&self) -> Result<u64> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		count_pending_reviews(&conn)
+	}
+
+	/// Accepts or rejects the pending reviews in `ids` at once. Rejected
+	/// files are removed and their peer is refused its next write to the
+	/// path with [`crate::p2p::WRITE_REJECTED`]. Returns how many entries
+	/// were still pending.
+	pub fn decide_reviews(&self, ids: &[i64], decision: ReviewDecision) -> Result<usize> {
+		let decided = {
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			decide_reviews(&conn, ids, decision)?
+		};
+		if decision == ReviewDecision::Reject {
+			for review in &decided {
+				match std::fs::remove_file(&review.path) {
+					Ok(()) => tracing::info!("removed rejected file {}", review.path),
+					Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+					Err(err) => tracing::warn!("failed to remove rejected {}: {err}", review.path),
+				}
+			}
+		}
+		Ok(decided.len())
+	}
+
+	/// How reviewed writes from `peer` were decided so far.
+	pub fn peer_trust(&self, peer: PeerId) -> Result<PeerTrust> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		load_peer_trust(&conn, &peer)
+	}
+
+	/// Proposes deleting the file with content `hash` from every other node
+	/// the index shows holding a live copy. Nothing is deleted until each
+	/// holder's owner approves; holders that are away get the proposal when
+	/// they next connect.
+	pub fn propose_global_delete(&self, hash: FileHash, reason: &str) -> Result<DeleteProposal> {
+		self.maintenance.check()?;
+		let me = self.local_peer_id().map_err(|err| anyhow!(err))?;
+		let node_id = peer_to_node_id(&me).ok_or_else(|| anyhow!("invalid local peer id {me}"))?;
+		let proposal = {
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			let known = load_peers(&conn)?
+				.into_iter()
+				.map(|peer| peer.id)
+				.filter(|peer| *peer != me)
+				.collect::<Vec<_>>();
+			let holders = deletion::replica_holders(&conn, &hash, &node_id, &known)?;
+			if holders.is_empty() {
+				bail!("no other device holds a copy of this file");
+			}
+			let now = Utc::now();
+			let proposal = DeleteProposal {
+				id: Uuid::new_v4().to_string(),
+				hash,
+				reason: deletion::clip_reason(reason),
+				created_at: now,
+				expires_at: now + deletion::proposal_expiry(&conn),
+				holders: holders
+					.into_iter()
+					.map(|(peer, paths)| ProposalHolder {
+						peer: peer.to_string(),
+						paths,
+						status: DeletionStatus::Undelivered,
+						detail: None,
+						updated_at: now,
+					})
+					.collect(),
+			};
+			record_delete_proposal(&conn, &proposal)?;
+			proposal
+		};
+		for holder in &proposal.holders {
+			if let Ok(peer) = holder.peer.parse() {
+				let _ = self.cmd_tx.send(Command::FlushDeleteOutbox { peer });
+			}
+		}
+		Ok(proposal)
+	}
+
+	/// Deletion proposals made here with each holder's status, newest
+	/// first.
+	pub fn delete_proposals(&self) -> Result<Vec<DeleteProposal>> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		expire_delete_proposals(&conn, Utc::now())?;
+		load_delete_proposals(&conn)
+	}
+
+	/// Deletion proposals from peers that wait for the owner, oldest first.
+	pub fn pending_delete_proposals(&self) -> Result<Vec<ReceivedProposal>> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		expire_delete_proposals(&conn, Utc::now())?;
+		load_pending_delete_proposals(&conn)
+	}
+
+	pub fn pending_delete_proposal_count(&self) -> Result<u64> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		expire_delete_proposals(&conn, Utc::now())?;
+		count_pending_delete_proposals(&conn, None)
+	}
+
+	/// Approves or rejects the deletion proposal `id` from a peer and sends
+	/// the outcome back. Approving deletes this node's copies that still
+	/// hold the proposed content.
+	pub fn decide_delete_proposal(
+		&self,
+		id: i64,
+		decision: DeleteDecision,
+	) -> Result<ReceivedProposal> {
+		self.maintenance.check()?;
+		let me = self.local_peer_id().map_err(|err| anyhow!(err))?;
+		let node_id = peer_to_node_id(&me).ok_or_else(|| anyhow!("invalid local peer id {me}"))?;
+		let proposal = deletion::decide(&self.db, &node_id, id, decision, Utc::now())?;
+		if let Ok(peer) = proposal.proposer.parse() {
+			let _ = self.cmd_tx.send(Command::FlushDeleteOutbox { peer });
+		}
+		Ok(proposal)
+	}
+
+	/// Days a deletion proposal waits for each holder's owner before it
+	/// expires.
+	pub fn delete_proposal_expiry_days(&self) -> u32 {
+		let conn = self.db.lock().unwrap();
+		deletion::proposal_expiry_days(&conn)
+	}
+
+	pub fn set_delete_proposal_expiry_days(&self, days: u32) -> anyhow::Result<()> {
+		self.maintenance.check()?;
+		if days == 0 {
+			bail!("deletion proposals must wait at least a day");
+		}
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		save_setting(&conn, DELETE_PROPOSAL_EXPIRY_SETTING, &days.to_string())
+	}
+
+	/// The earlier download of the content the index knows at
+	/// `remote_path` on `peer`, if its copy is still on disk.
+	fn previous_download(&self, peer: PeerId, remote_path: &str) -> Result<Option<Transfer>> {
+		let Some(node_id) = peer_to_node_id(&peer) else {
+			return Ok(None);
+		};
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		let Some(hash) = indexed_hash(&conn, &node_id, remote_path)? else {
+			return Ok(None);
+		};
+		Ok(last_download_of(&conn, &hash)?
+			.filter(|transfer| Path::new(&transfer.local_path).is_file()))
+	}
+
+	async fn fetch_to_file(
+		&self,
+		peer: PeerId,
+		remote_path: &str,
+		dest: &Path,
+		progress: &mut impl FnMut(u64),
+	) -> Result<Vec<u8>> {
+		let mut sink = SparseSink::new(tokio::fs::File::create(dest).await?);
+		let mut fetch =
+			move |offset, length| self.read_file_sparse(peer, remote_path, offset, Some(length));
+		let mut reader = ChunkReader::new(0, None, SEND_FILE_CHUNK_SIZE as u64);
+		while let Some(chunk) = reader.next(&mut fetch).await? {
+			sink.write(&chunk).await?;
+			progress(reader.offset());
+		}
+		Ok(sink.finish().await?)
+	}
+
+	/// Copies a peer's file into its download folder, reporting its
+	/// progress after every chunk. Unless `force` is set, content the index
+	/// says was downloaded before and is still on disk is not copied again;
+	/// the earlier transfer comes back as `AlreadyDownloaded` instead.
+	pub async fn download_file_with_progress(
+		&self,
+		peer: PeerId,
+		remote_path: &str,
+		force: bool,
+		mut progress: impl FnMut(TransferProgress),
+	) -> Result<DownloadOutcome> {
+		if !force && let Some(previous) = self.previous_download(peer, remote_path)? {
+			return Ok(DownloadOutcome::AlreadyDownloaded(previous));
+		}
+		let name = remote_file_name(remote_path)
+			.ok_or_else(|| anyhow!("not a file path: {remote_path}"))?;
+		let dir = self.download_dir(Some(peer))?;
+		tokio::fs::create_dir_all(&dir).await?;
+		let dest = unique_destination(&dir, name);
+		let mut part = dest.clone().into_os_string();
+		part.push(".part");
+		let part = PathBuf::from(part);
+		// Only for the time left; the download goes ahead without it.
+		let total = self
+			.stat_file(peer, remote_path)
+			.await
+			.ok()
+			.map(|entry| entry.size);
+		let started = std::time::Instant::now();
+		let mut received = 0;
+		let mut rate = RateEstimator::new();
+		let fetched = self
+			.fetch_to_file(peer, remote_path, &part, &mut |done| {
+				received = done;
+				progress(TransferProgress::measure(&mut rate, done, total));
+			})
+			.await;
+		let result = match fetched {
+			Ok(hash) => tokio::fs::rename(&part, &dest)
+				.await
+				.map(|_| hash)
+				.map_err(|err| anyhow!("failed to move download into place: {err}")),
+			Err(err) => Err(err),
+		};
+		let mut transfer = Transfer::new(
+			TransferDirection::Download,
+			peer,
+			remote_path,
+			dest.to_string_lossy(),
+			started.elapsed(),
+		);
+		transfer.size = received;
+		match result {
+			Ok(hash) => {
+				transfer.hash = Some(hash);
+				self.record_transfer(transfer.clone());
+				Ok(DownloadOutcome::Downloaded(transfer))
+			}
+			Err(err) => {
+				let _ = tokio::fs::remove_file(&part).await;
+				self.record_transfer(transfer.failed(&err));
+				Err(err)
+			}
+		}
+	}
+
+	pub async fn download_file(
+		&self,
+		peer: PeerId,
+		remote_path: &str,
+		force: bool,
+	) -> Result<DownloadOutcome> {
+		self.download_file_with_progress(peer, remote_path, force, |_| {})
+			.await
+	}
+
+	/// Keeps a copy of `remote_path` on `peer` in `local_dest`, synced in
+	/// the background whenever the peer is connected and `opts.interval`
+	/// has passed.
+	pub fn pin_remote_folder(
+		&self,
+		peer: PeerId,
+		remote_path: &str,
+		local_dest: impl AsRef<Path>,
+		opts: PinOptions,
+	) -> Result<PinStatus> {
+		self.maintenance.check()?;
+		let remote_path = remote_path.trim();
+		let local_dest = local_dest.as_ref();
+		if remote_path.is_empty() {
+			bail!("remote folder is required");
+		}
+		if !local_dest.is_absolute() {
+			bail!("local folder must be an absolute path");
+		}
+		if opts.interval < MIN_PIN_INTERVAL {
+			bail!(
+				"sync interval must be at least {} seconds",
+				MIN_PIN_INTERVAL.as_secs()
+			);
+		}
+		if opts.bandwidth_limit == Some(0) {
+			bail!("bandwidth limit must be above zero");
+		}
+		let id = {
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			let pins = load_pins(&conn)?;
+			let peer_str = peer.to_string();
+			if pins
+				.iter()
+				.any(|pin| pin.peer == peer_str && pin.remote_path == remote_path)
+			{
+				bail!("{remote_path} is already pinned");
+			}
+			if pins
+				.iter()
+				.any(|pin| Path::new(&pin.local_dest) == local_dest)
+			{
+				bail!("{} already holds a pinned folder", local_dest.display());
+			}
+			std::fs::create_dir_all(local_dest)
+				.map_err(|err| anyhow!("failed to create {}: {err}", local_dest.display()))?;
+			insert_pin(&conn, &peer, remote_path, local_dest, &opts, Utc::now())?
+		};
+		self.pin_wake.notify_one();
+		self.list_pins()?
+			.into_iter()
+			.find(|pin| pin.id == id)
+			.ok_or_else(|| anyhow!("pin {id} disappeared"))
+	}
+
+	/// Pinned folders, oldest first, with the state of running syncs.
+	pub fn list_pins(&self) -> Result<Vec<PinStatus>> {
+		let mut pins = {
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			load_pins(&conn)?
+		};
+		for pin in &mut pins {
+			self.pins.apply(pin);
+		}
+		Ok(pins)
+	}
+
+	/// Stops syncing pin `id`, including a sync in progress, until resumed.
+	pub fn pause_pin(&self, id: u64) -> Result<()> {
+		self.set_pin_paused(id, true)?;
+		self.pins.cancel(id);
+		Ok(())
+	}
+
+	/// Syncs pin `id` again, starting right away if its peer is connected.
+	pub fn resume_pin(&self, id: u64) -> Result<()> {
+		self.set_pin_paused(id, false)?;
+		self.pins.forget(id);
+		self.pin_wake.notify_one();
+		Ok(())
+	}
+
+	fn set_pin_paused(&self, id: u64, paused: bool) -> Result<()> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		if !set_pin_paused(&conn, id, paused)? {
+			bail!("no pin with id {id}");
+		}
+		Ok(())
+	}
+
+	/// Stops mirroring pin `id`. Files already copied stay where they are.
+	pub fn unpin(&self, id: u64) -> Result<()> {
+		self.pins.cancel(id);
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		if !delete_pin(&conn, id)? {
+			bail!("no pin with id {id}");
+		}
+		self.pins.forget(id);
+		Ok(())
+	}
+
+	fn check_outbox_rule(&self, rule: &OutboxRule, except: Option<u64>) -> Result<()> {
+		if !rule.watch_path.is_absolute() {
+			bail!("watched folder must be an absolute path");
+		}
+		if rule.dest_path.trim().is_empty() {
+			bail!("destination folder is required");
+		}
+		if self.local_peer_id().ok() == Some(rule.dest_peer)
+			&& Path::new(rule.dest_path.trim()).starts_with(&rule.watch_path)
+		{
+			bail!("the destination can't be inside the watched folder");
+		}
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		if load_outbox_rules(&conn)?.iter().any(|other| {
+			Some(other.id) != except && Path::new(&other.watch_path) == rule.watch_path
+		}) {
+			bail!("{} is already watched", rule.watch_path.display());
+		}
+		Ok(())
+	}
+
+	/// Watches `rule.watch_path` and sends new files matching the rule to
+	/// its peer. Each file is sent once it stops changing, then deleted or
+	/// moved to the `sent` folder inside the watched one.
+	pub fn add_outbox_rule(&self, mut rule: OutboxRule) -> Result<OutboxStatus> {
+		self.maintenance.check()?;
+		rule.dest_path = rule.dest_path.trim().to_string();
+		rule.pattern = rule.pattern.trim().to_string();
+		self.check_outbox_rule(&rule, None)?;
+		std::fs::create_dir_all(&rule.watch_path)
+			.map_err(|err| anyhow!("failed to create {}: {err}", rule.watch_path.display()))?;
+		let id = {
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			insert_outbox_rule(&conn, &rule, Utc::now())?
+		};
+		self.outbox_wake.notify_one();
+		self.list_outbox_rules()?
+			.into_iter()
+			.find(|status| status.id == id)
+			.ok_or_else(|| anyhow!("outbox rule {id} disappeared"))
+	}
+
+	/// Outbox rules, oldest first, with how many files each has waiting.
+	pub fn list_outbox_rules(&self) -> Result<Vec<OutboxStatus>> {
+		let mut rules = {
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			load_outbox_rules(&conn)?
+		};
+		for rule in &mut rules {
+			self.outboxes.apply(rule);
+		}
+		Ok(rules)
+	}
+
+	/// Replaces what outbox rule `id` sends where. Files that failed are
+	/// tried again right away.
+	pub fn update_outbox_rule(&self, id: u64, mut rule: OutboxRule) -> Result<()> {
+		self.maintenance.check()?;
+		rule.dest_path = rule.dest_path.trim().to_string();
+		rule.pattern = rule.pattern.trim().to_string();
+		self.check_outbox_rule(&rule, Some(id))?;
+		{
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			if !update_outbox_rule(&conn, id, &rule)? {
+				bail!("no outbox rule with id {id}");
+			}
+		}
+		self.outboxes.forget(id);
+		self.outbox_wake.notify_one();
+		Ok(())
+	}
+
+	pub fn pause_outbox_rule(&self, id: u64) -> Result<()> {
+		self.set_outbox_rule_paused(id, true)
+	}
+
+	/// Sends again, retrying failed files right away.
+	pub fn resume_outbox_rule(&self, id: u64) -> Result<()> {
+		self.set_outbox_rule_paused(id, false)?;
+		self.outboxes.forget(id);
+		self.outbox_wake.notify_one();
+		Ok(())
+	}
+
+	fn set_outbox_rule_paused(&self, id: u64, paused: bool) -> Result<()> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		if !set_outbox_rule_paused(&conn, id, paused)? {
+			bail!("no outbox rule with id {id}");
+		}
+		Ok(())
 	}
 
-	pub fn live_search_peers(
-		&self,
-		peers: Vec<PeerId>,
-		args: LiveSearchArgs,
-	) -> Result<Arc<Mutex<mpsc::Receiver<LiveSearchPeerEvent>>>, String> {
-		let (events_tx, events_rx) = mpsc::channel();
-		for peer in peers {
-			let (tx, rx) = mpsc::channel();
-			let search_id = self.remote_search_counter.fetch_add(1, Ordering::SeqCst);
-			self.remote_searches
-				.lock()
-				.unwrap()
-				.insert(search_id, tx.clone());
+	/// Stops watching for outbox rule `id`. Files in the folder stay.
+	pub fn remove_outbox_rule(&self, id: u64) -> Result<()> {
+		self.maintenance.check()?;
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		if !delete_outbox_rule(&conn, id)? {
+			bail!("no outbox rule with id {id}");
+		}
+		self.outboxes.forget(id);
+		Ok(())
+	}
 
-			let forward_tx = events_tx.clone();
-			std::thread::spawn(move || {
-				while let Ok(event) = rx.recv() {
-					let terminal = matches!(
-						event,
-						SearchEvent::Finished { .. } | SearchEvent::Failed { .. }
-					);
-					let _ = forward_tx.send(LiveSearchPeerEvent { peer, event });
-					if terminal {
-						break;
-					}
+	/// Keeps a warm standby of this node's index on `peer`, which needs
+	/// owner access here and takes the changes only once it subscribed to
+	/// this node's index updates. Changes go out while it is connected, and
+	/// an earlier replication to it picks up where it stopped.
+	pub async fn replicate_index_to(&self, peer: PeerId) -> Result<ReplicationStatus> {
+		self.maintenance.check()?;
+		let state = self
+			.state_snapshot()
+			.await
+			.ok_or_else(|| anyhow!("puppynet is not running"))?;
+		if peer == state.me {
+			bail!("the index can't be replicated to this device");
+		}
+		if !state.is_owner(&peer) {
+			bail!(
+				"{} needs owner access to keep a replica of this device's index",
+				state.peer_label(&peer)
+			);
+		}
+		let status = {
+			let conn = self
+				.db
+				.lock()
+				.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+			match load_replication(&conn, &peer, ReplicationRole::Source)? {
+				Some(status) => status,
+				None => {
+					let status = ReplicationStatus {
+						peer: peer.to_string(),
+						role: ReplicationRole::Source,
+						generation: String::new(),
+						acked_seq: 0,
+						behind: 0,
+						last_sync_at: None,
+						last_error: None,
+						syncing: false,
+					};
+					save_replication(&conn, &status)?;
+					status
 				}
-			});
-
-			if let Err(err) = self.cmd_tx.send(Command::LiveSearch {
-				peer,
-				args: args.clone(),
-				search_id,
-			}) {
-				self.remote_searches.lock().unwrap().remove(&search_id);
-				let _ = tx.send(SearchEvent::Failed {
-					error: format!("failed to send LiveSearch command: {err}"),
-				});
 			}
+		};
+		self.replication_wake.notify_one();
+		Ok(status)
+	}
+
+	/// Stops sending index changes to `peer`. What it already holds stays
+	/// there.
+	pub fn stop_replicating_index_to(&self, peer: PeerId) -> Result<()> {
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		if !delete_replication(&conn, &peer, ReplicationRole::Source)? {
+			bail!("the index is not replicated to {peer}");
 		}
-		drop(events_tx);
-		Ok(Arc::new(Mutex::new(events_rx)))
+		Ok(())
 	}
 
-	pub fn fetch_scan_results_page(
-		&self,
-		page: usize,
-		page_size: usize,
-	) -> Result<(Vec<ScanResultRow>, usize), String> {
-		let offset = page.saturating_mul(page_size);
+	/// Index replications between this node and `peer`, either way round.
+	/// How far behind a replica of this node is gets counted now, so it
+	/// keeps growing while the replica is away.
+	pub fn index_replications(&self, peer: PeerId) -> Result<Vec<ReplicationStatus>> {
+		let local = self.local_peer_id().map_err(|err| anyhow!(err))?;
+		let node_id =
+			peer_to_node_id(&local).ok_or_else(|| anyhow!("invalid local peer id {local}"))?;
 		let conn = self
 			.db
 			.lock()
-			.map_err(|err| format!("db lock poisoned: {}", err))?;
-		let total_entries: i64 = conn
-			.query_row("SELECT COUNT(*) FROM file_entries", [], |row| row.get(0))
-			.map_err(|err| format!("failed to count scan results: {err}"))?;
-		let mut stmt = conn
-			.prepare(
-				"SELECT hash, size, mime_type, first_datetime, latest_datetime \
-				FROM file_entries \
-				ORDER BY latest_datetime DESC \
-				LIMIT ? OFFSET ?",
-			)
-			.map_err(|err| format!("failed to prepare scan results query: {err}"))?;
-		let rows = stmt
-			.query_map(params![page_size as i64, offset as i64], |row| {
-				let hash: Vec<u8> = row.get(0)?;
-				let size = row.get::<_, i64>(1)?.max(0) as u64;
-				let mime_type = row.get(2)?;
-				let first = row.get(3)?;
-				let latest = row.get(4)?;
-				Ok(ScanResultRow {
-					hash,
-					size,
-					mime_type,
-					first_datetime: first,
-					latest_datetime: latest,
-				})
-			})
-			.map_err(|err| format!("failed to query scan results: {err}"))?;
-		let mut entries = Vec::new();
-		for entry in rows {
-			entries.push(entry.map_err(|err| format!("error reading scan row: {err}"))?);
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		let generation = index_generation(&conn)?;
+		let mut replications = load_replications(&conn)?;
+		replications.retain(|status| status.peer == peer.to_string());
+		for status in &mut replications {
+			if status.role != ReplicationRole::Source {
+				continue;
+			}
+			status.syncing = self.replications.is_running(&peer);
+			if status.generation == generation {
+				status.behind = count_index_changes(&conn, &node_id, status.acked_seq)?;
+			}
 		}
-		Ok((entries, total_entries.max(0) as usize))
+		Ok(replications)
 	}
 
-	/// Search files using file_entries and file_locations tables
-	/// Returns (results, mime_types, total_count)
-	pub fn search_files(
-		&self,
-		args: crate::db::SearchFilesArgs,
-	) -> Result<(Vec<crate::db::FileSearchResult>, Vec<String>, usize), String> {
+	/// Whether this node pulls `peer`'s index changes as soon as it
+	/// announces them, rather than only marking what it knows as stale.
+	/// Pulling needs owner access on `peer`; the rows pulled are kept like
+	/// a replica of its index. Only subscribed peers may also send their
+	/// changes here as a replica.
+	pub fn subscribe_index_updates(&self, peer: PeerId, subscribed: bool) -> Result<()> {
 		let conn = self
 			.db
 			.lock()
-			.map_err(|err| format!("db lock poisoned: {err}"))?;
-		crate::db::search_files(&conn, args).map_err(|err| format!("search failed: {err}"))
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		save_index_subscription(&conn, &peer, subscribed, Utc::now())
 	}
 
-	/// Get all available mime types from file_entries
-	pub fn get_mime_types(&self) -> Result<Vec<String>, String> {
+	pub fn is_subscribed_to_index_updates(&self, peer: PeerId) -> Result<bool> {
 		let conn = self
 			.db
 			.lock()
-			.map_err(|err| format!("db lock poisoned: {err}"))?;
-		let mut stmt = conn
-			.prepare("SELECT DISTINCT mime_type FROM file_entries WHERE mime_type IS NOT NULL")
-			.map_err(|err| format!("failed to prepare mime types query: {err}"))?;
-		let rows = stmt
-			.query_map((), |row| row.get::<_, String>(0))
-			.map_err(|err| format!("failed to query mime types: {err}"))?;
-		let mut mime_types = Vec::new();
-		for mime in rows {
-			mime_types.push(mime.map_err(|err| format!("failed to read mime type: {err}"))?);
-		}
-		Ok(mime_types)
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		is_subscribed_to_index(&conn, &peer)
 	}
 
-	pub async fn read_file(
-		&self,
-		peer: libp2p::PeerId,
-		path: impl Into<String>,
-		offset: u64,
-		length: Option<u64>,
-	) -> Result<FileChunk> {
-		let path = path.into();
-		let (tx, rx) = oneshot::channel();
-		self.cmd_tx
-			.send(Command::ReadFile(ReadFileCmd {
-				peer_id: peer,
-				path,
-				offset,
-				length,
-				tx,
-			}))
-			.map_err(|e| anyhow!("failed to send ReadFile command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("ReadFile response channel closed: {e}"))?
+	/// Drops this node's own index and starts a new generation of it, so
+	/// the next scans rebuild it from scratch and replicas start over
+	/// instead of keeping files that are gone.
+	pub fn reset_local_index(&self) -> Result<usize> {
+		self.maintenance.check()?;
+		let local = self.local_peer_id().map_err(|err| anyhow!(err))?;
+		let node_id =
+			peer_to_node_id(&local).ok_or_else(|| anyhow!("invalid local peer id {local}"))?;
+		let conn = self
+			.db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		let removed = forget_node_index(&conn, &node_id)?;
+		bump_index_generation(&conn)?;
+		drop(conn);
+		self.replication_wake.notify_one();
+		Ok(removed)
 	}
 
-	pub async fn get_thumbnail(
-		&self,
-		peer: libp2p::PeerId,
-		path: impl Into<String>,
-		max_width: u32,
-		max_height: u32,
-	) -> Result<Thumbnail> {
-		let path = path.into();
-		let (tx, rx) = oneshot::channel();
-		self.cmd_tx
-			.send(Command::GetThumbnail {
-				peer,
-				path,
-				max_width,
-				max_height,
-				tx,
-			})
-			.map_err(|e| anyhow!("failed to send GetThumbnail command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("GetThumbnail response channel closed: {e}"))?
+	/// Reads all of `file`; callers bound its size first.
+	async fn read_whole(&self, file: &FileRef) -> Result<Vec<u8>> {
+		let contents = self
+			.read_file_to_end(
+				file.peer,
+				file.path.as_str(),
+				ReadToEndOptions::default(),
+				|_| {},
+			)
+			.await?;
+		Ok(contents.data)
 	}
 
-	pub async fn start_shell(&self, peer: PeerId, session_id: u64) -> Result<u64> {
-		let (tx, rx) = oneshot::channel();
-		self.cmd_tx
-			.send(Command::StartShell {
-				peer,
-				session_id,
-				tx,
-			})
-			.map_err(|e| anyhow!("failed to send StartShell command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("StartShell response channel closed: {e}"))?
+	/// The diff block at `offset` of a file `size` bytes long, empty past
+	/// its end.
+	async fn read_diff_block(&self, file: &FileRef, offset: u64, size: u64) -> Result<Vec<u8>> {
+		if offset >= size {
+			return Ok(Vec::new());
+		}
+		let end = offset.saturating_add(DIFF_BLOCK_SIZE).min(size);
+		let chunk = self
+			.read_file_range(file.peer, file.path.as_str(), offset..end)
+			.await?;
+		Ok(chunk.data)
 	}
 
-	pub async fn shell_input(
-		&self,
-		peer: PeerId,
-		session_id: u64,
-		data: Vec<u8>,
-	) -> Result<Vec<u8>> {
-		let (tx, rx) = oneshot::channel();
-		self.cmd_tx
-			.send(Command::ShellInput {
-				peer,
-				session_id,
-				data,
-				tx,
+	/// Compares two files, each on any peer. Both at most
+	/// `opts.max_text_bytes` and valid UTF-8 gives a line diff; otherwise
+	/// the files are compared block by block from ranged reads. Errors name
+	/// the peer that could not serve its file.
+	pub async fn diff_files(&self, a: FileRef, b: FileRef, opts: DiffOptions) -> Result<FileDiff> {
+		let unavailable = |file: &FileRef, err: anyhow::Error| {
+			anyhow!("peer {} could not serve {}: {err}", file.peer, file.path)
+		};
+		let (stat_a, stat_b) = futures::join!(
+			self.stat_file(a.peer, a.path.as_str()),
+			self.stat_file(b.peer, b.path.as_str())
+		);
+		let stat_a = stat_a.map_err(|err| unavailable(&a, err))?;
+		let stat_b = stat_b.map_err(|err| unavailable(&b, err))?;
+		if stat_a.is_dir || stat_b.is_dir {
+			bail!("only files can be compared");
+		}
+		let (size_a, size_b) = (stat_a.size, stat_b.size);
+
+		if size_a <= opts.max_text_bytes && size_b <= opts.max_text_bytes {
+			let (data_a, data_b) = futures::join!(self.read_whole(&a), self.read_whole(&b));
+			let data_a = data_a.map_err(|err| unavailable(&a, err))?;
+			let data_b = data_b.map_err(|err| unavailable(&b, err))?;
+			let name_a = format!("{}:{}", a.peer, a.path);
+			let name_b = format!("{}:{}", b.peer, b.path);
+			return tokio::task::spawn_blocking(move || {
+				diff_contents(&data_a, &data_b, &name_a, &name_b, &opts)
 			})
-			.map_err(|e| anyhow!("failed to send ShellInput command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("ShellInput response channel closed: {e}"))?
+			.await
+			.map_err(|err| anyhow!("diff task failed: {err}"));
+		}
+
+		let total = size_a.max(size_b).div_ceil(DIFF_BLOCK_SIZE);
+		let blocks = blocks_to_compare(size_a, size_b, opts.max_compared_blocks);
+		let mut note = format!(
+			"Larger than {}, so compared in {} blocks instead of line by line.",
+			human_size(opts.max_text_bytes, SizeUnits::Binary),
+			human_size(DIFF_BLOCK_SIZE, SizeUnits::Binary)
+		);
+		if (blocks.len() as u64) < total {
+			note.push_str(&format!(
+				" Sampled {} of {total} blocks, so differences may be missed.",
+				blocks.len()
+			));
+		}
+		let mut tally = BlockTally::default();
+		for index in blocks {
+			let offset = index * DIFF_BLOCK_SIZE;
+			let (block_a, block_b) = futures::join!(
+				self.read_diff_block(&a, offset, size_a),
+				self.read_diff_block(&b, offset, size_b)
+			);
+			let block_a = block_a.map_err(|err| unavailable(&a, err))?;
+			let block_b = block_b.map_err(|err| unavailable(&b, err))?;
+			tally.add(index, &block_a, &block_b);
+		}
+		Ok(FileDiff::Binary(tally.finish(size_a, size_b, Some(note))))
 	}
 
-	pub async fn desktop_input(&self, peer: PeerId, input: DesktopInput) -> Result<()> {
-		let (tx, rx) = oneshot::channel();
-		self.cmd_tx
-			.send(Command::DesktopInput { peer, input, tx })
-			.map_err(|e| anyhow!("failed to send DesktopInput command: {e}"))?;
-		rx.await
-			.map_err(|e| anyhow!("DesktopInput response channel closed: {e}"))?
+	pub async fn send_file(&self, peer: PeerId, local_path: impl AsRef<Path>) -> Result<Upload> {
+		self.send_file_with_progress(peer, local_path, |_| {}).await
+	}
+
+	/// Allows `peer` to send files into this node's inbox.
+	pub fn grant_inbox(&self, peer: PeerId) -> Result<()> {
+		let PermissionSet {
+			revision,
+			mut permissions,
+			..
+		} = self.granted_permission_set(peer)?;
+		if permissions
+			.iter()
+			.any(|permission| matches!(permission.rule(), Rule::Inbox))
+		{
+			return Ok(());
+		}
+		permissions.push(Permission::new(Rule::Inbox));
+		self.set_peer_permissions_at(peer, permissions, Some(revision))
+			.map(|_| ())
+	}
+
+	pub fn revoke_inbox(&self, peer: PeerId) -> Result<()> {
+		let PermissionSet {
+			revision,
+			mut permissions,
+			..
+		} = self.granted_permission_set(peer)?;
+		permissions.retain(|permission| !matches!(permission.rule(), Rule::Inbox));
+		self.set_peer_permissions_at(peer, permissions, Some(revision))
+			.map(|_| ())
 	}
 
 	/// Request a remote peer to update itself.
@@ -878,72 +5243,257 @@ impl PuppyNet {
 		&self,
 		peer: PeerId,
 		version: Option<String>,
-	) -> Result<Arc<Mutex<mpsc::Receiver<UpdateProgress>>>, String> {
-		let (tx, rx) = mpsc::channel();
-		let update_id = self.remote_update_counter.fetch_add(1, Ordering::SeqCst);
+	) -> Result<Receiver<UpdateProgress>, String> {
+		self.update_remote_peer_with_override(peer, version, false)
+	}
+
+	/// Starts an update, or defers it until the activity window opens and,
+	/// for this node, until it is plugged in, unless `override_window` is
+	/// set.
+	pub fn update_remote_peer_with_override(
+		&self,
+		peer: PeerId,
+		version: Option<String>,
+		override_window: bool,
+	) -> Result<Receiver<UpdateProgress>, String> {
+		let (tx, rx) = event_channel();
+		// Progress comes from the network loop and from callbacks inside the
+		// updater, neither of which may wait for the consumer.
+		let tx = relay(&self.runtime, tx);
+		let update_id = self.next_id(IdKind::Update);
 
 		// Check if the target peer is self - if so, perform a local update
 		let is_self = self.local_peer_id()? == peer;
 
-		if is_self {
-			// Perform local self-update
-			let tx_clone = tx.clone();
-			let version_clone = version.clone();
-			let current_version = version::version_number();
+		let window = self.activity_window.lock().unwrap().clone();
+		let opening = window
+			.next_opening(ActivityKind::Update, Utc::now())
+			.filter(|_| !override_window);
+		// Only an update of this node downloads on this machine's power.
+		let wait_for_ac = is_self && !override_window;
+		let on_battery = wait_for_ac && self.power.lock().unwrap().holds();
+		if opening.is_none() && !on_battery {
+			start_update(
+				is_self,
+				peer,
+				version,
+				update_id,
+				tx,
+				&self.remote_updates,
+				&self.cmd_tx,
+			)?;
+			return Ok(rx);
+		}
 
-			std::thread::spawn(move || {
-				let rt = tokio::runtime::Runtime::new().unwrap();
-				rt.block_on(async move {
-					let result = updater::update_with_progress(
-						version_clone.as_deref(),
-						current_version,
-						move |progress| {
-							let _ = tx_clone.send(progress);
+		let cancel_flag = Arc::new(AtomicBool::new(false));
+		match opening {
+			Some(opening) => {
+				let until = window.local_time_label(opening);
+				let _ = tx.send(UpdateProgress::Deferred {
+					until: until.clone(),
+				});
+				self.deferred.lock().unwrap().insert(
+					(ActivityKind::Update, update_id),
+					DeferredEntry {
+						activity: DeferredActivity {
+							id: update_id,
+							kind: ActivityKind::Update,
+							label: format!("Update {peer}"),
+							until,
 						},
-					)
-					.await;
-
-					if let Err(e) = result {
-						let _ = tx.send(UpdateProgress::Failed {
-							error: e.to_string(),
-						});
-					}
+						cancel_flag: Arc::clone(&cancel_flag),
+					},
+				);
+			}
+			None => {
+				let _ = tx.send(UpdateProgress::Deferred {
+					until: String::from("plugged in"),
 				});
-			});
-		} else {
-			// Remote update - send command to dial peer
-			self.remote_updates
+			}
+		}
+		let window = Arc::clone(&self.activity_window);
+		let power = Arc::clone(&self.power);
+		let deferred = Arc::clone(&self.deferred);
+		let remote_updates = Arc::clone(&self.remote_updates);
+		let cmd_tx = self.cmd_tx.clone();
+		std::thread::spawn(move || {
+			let ready = wait_for_activity_window(&window, ActivityKind::Update, &cancel_flag);
+			deferred
 				.lock()
 				.unwrap()
-				.insert(update_id, tx.clone());
-			self.cmd_tx
-				.send(Command::RemoteUpdate {
+				.remove(&(ActivityKind::Update, update_id));
+			if !ready {
+				let _ = tx.send(UpdateProgress::Failed {
+					error: String::from("Update cancelled before it started"),
+					kind: None,
+				});
+				return;
+			}
+			if wait_for_ac {
+				wait_for_power(&power);
+			}
+			let failed_tx = tx.clone();
+			if let Err(error) = start_update(
+				is_self,
+				peer,
+				version,
+				update_id,
+				tx,
+				&remote_updates,
+				&cmd_tx,
+			) {
+				let _ = failed_tx.send(UpdateProgress::Failed { error, kind: None });
+			}
+		});
+
+		Ok(rx)
+	}
+
+	/// Like [`Self::update_remote_peer`], but a transient failure re-issues
+	/// the update after a backoff, up to `policy.max_attempts` in total. Each
+	/// retry is reported as [`UpdateProgress::Retrying`], and the peer picks
+	/// its partial download back up instead of starting from byte zero.
+	pub fn update_remote_peer_with_retry(
+		&self,
+		peer: PeerId,
+		version: Option<String>,
+		policy: UpdateRetryPolicy,
+	) -> Result<Receiver<UpdateProgress>, String> {
+		let is_self = self.local_peer_id()? == peer;
+		let mut attempt_rx = self.update_remote_peer(peer, version.clone())?;
+		let retry_ids = (1..policy.max_attempts)
+			.map(|_| self.next_id(IdKind::Update))
+			.collect::<Vec<_>>();
+		let remote_updates = Arc::clone(&self.remote_updates);
+		let cmd_tx = self.cmd_tx.clone();
+		let (tx, rx) = event_channel();
+		self.runtime.spawn(async move {
+			let mut retry_ids = retry_ids.into_iter();
+			let mut attempt = 1;
+			while let Some(event) = attempt_rx.recv().await {
+				let UpdateProgress::Failed { error, kind } = event else {
+					if tx.send(event).await.is_err() {
+						return;
+					}
+					continue;
+				};
+				let kind = kind.unwrap_or_else(|| classify_update_error(&error));
+				let (Some(delay), Some(update_id)) =
+					(policy.retry_after(attempt, kind), retry_ids.next())
+				else {
+					let _ = tx
+						.send(UpdateProgress::Failed {
+							error,
+							kind: Some(kind),
+						})
+						.await;
+					return;
+				};
+				attempt += 1;
+				tracing::info!("retrying update of {peer} in {delay:?}: {error}");
+				let retrying = UpdateProgress::Retrying {
+					attempt,
+					max_attempts: policy.max_attempts,
+					delay_secs: delay.as_secs(),
+					error,
+				};
+				if tx.send(retrying).await.is_err() {
+					return;
+				}
+				tokio::time::sleep(delay).await;
+				let (attempt_tx, next_rx) = event_channel();
+				let attempt_tx = relay(&tokio::runtime::Handle::current(), attempt_tx);
+				if let Err(error) = start_update(
+					is_self,
 					peer,
-					version,
+					version.clone(),
 					update_id,
-				})
-				.map_err(|e| {
-					self.remote_updates.lock().unwrap().remove(&update_id);
-					format!("failed to send RemoteUpdate command: {e}")
-				})?;
-		}
-
-		Ok(Arc::new(Mutex::new(rx)))
+					attempt_tx,
+					&remote_updates,
+					&cmd_tx,
+				) {
+					let _ = tx.send(UpdateProgress::Failed { error, kind: None }).await;
+					return;
+				}
+				attempt_rx = next_rx;
+			}
+		});
+		Ok(rx)
 	}
 
 	/// Wait for the peer until Ctrl+C (SIGINT) then perform a graceful shutdown.
 	pub async fn wait(mut self) {
 		// Wait for Ctrl+C
 		if let Err(e) = tokio::signal::ctrl_c().await {
-			log::error!("failed to listen for ctrl_c: {e}");
+			tracing::error!("failed to listen for ctrl_c: {e}");
 		}
-		log::info!("interrupt received, shutting down");
+		tracing::info!("interrupt received, shutting down");
 		if let Some(tx) = self.shutdown_tx.take() {
 			let _ = tx.send(());
 		}
 		// Await the background task
 		if let Err(e) = self.handle.await {
-			log::error!("task join error: {e}");
+			tracing::error!("task join error: {e}");
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::activity_window::TimeRange;
+	use chrono::Timelike;
+
+	/// A one minute window six hours from now, for scans and updates.
+	fn closed_window() -> ActivityWindow {
+		let now = Utc::now();
+		let start = ((now.hour() * 60 + now.minute() + 6 * 60) % (24 * 60)) as u16;
+		ActivityWindow {
+			categories: vec![ActivityKind::Scan, ActivityKind::Update],
+			allowed: vec![TimeRange {
+				start,
+				end: (start + 1) % (24 * 60),
+			}],
+			utc_offset_minutes: 0,
+			hard_stop: false,
 		}
 	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn cancelling_a_deferred_update_leaves_the_scan_with_its_id() {
+		let puppy = PuppyNet::new_demo(demo::DEFAULT_DEMO_SEED);
+		puppy.set_activity_window(closed_window()).unwrap();
+		let scan = puppy.scan_folder("/").unwrap();
+		let mut updates = puppy.update_remote_peer(PeerId::random(), None).unwrap();
+		let deferred = puppy.deferred_activities();
+		assert_eq!(deferred.len(), 2);
+		assert_eq!(deferred[0].id, deferred[1].id);
+
+		assert!(puppy.cancel_deferred(ActivityKind::Update, deferred[0].id));
+		assert!(!puppy.cancel_deferred(ActivityKind::Update, deferred[0].id));
+		let left = puppy.deferred_activities();
+		assert_eq!(left.len(), 1);
+		assert_eq!(left[0].kind, ActivityKind::Scan);
+		assert!(matches!(
+			updates.recv().await,
+			Some(UpdateProgress::Deferred { .. })
+		));
+		assert!(matches!(
+			updates.recv().await,
+			Some(UpdateProgress::Failed { .. })
+		));
+
+		assert!(puppy.cancel_deferred(ActivityKind::Scan, left[0].id));
+		let receiver = scan.receiver();
+		let mut events = receiver.lock().await;
+		let finished = loop {
+			match events.recv().await {
+				Some(ScanEvent::Finished(result)) => break result,
+				Some(_) => continue,
+				None => panic!("scan ended without finishing"),
+			}
+		};
+		assert!(finished.is_err());
+		assert!(puppy.deferred_activities().is_empty());
+	}
 }
@@ -0,0 +1,377 @@
+//! Dial-back self-test of this node's listen and external addresses.
+//! Connected peers announcing [`crate::p2p::FEATURE_DIAL_BACK`] are asked to
+//! open a fresh connection to each address and report how it went. A peer
+//! only dials addresses on the IP the requester is connected from, so it
+//! can't be used to probe third parties.
+
+use crate::nat::is_non_routable;
+use chrono::{DateTime, Utc};
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Last results as JSON, so they survive restarts.
+pub(crate) const REACHABILITY_SETTING: &str = "reachability_results";
+/// How long a peer tries to reach an address before calling it unreachable.
+pub(crate) const DIAL_BACK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the requester waits for a peer's answer.
+pub(crate) const DIAL_BACK_ANSWER_TIMEOUT: Duration = Duration::from_secs(20);
+/// Longest address a `DialBack` request may carry.
+pub(crate) const MAX_DIAL_BACK_ADDR_LEN: usize = 256;
+/// Peers asked to test each address.
+pub(crate) const MAX_TESTERS: usize = 3;
+const DIAL_BACK_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// Dial-backs each peer may ask for per [`DIAL_BACK_WINDOW`].
+const DIAL_BACKS_PER_WINDOW: usize = 16;
+/// Dial-backs running at once, across all peers.
+const MAX_CONCURRENT_DIAL_BACKS: usize = 4;
+pub(crate) const NO_TESTER: &str = "no peer available to test";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reachability {
+	Reachable,
+	Unreachable,
+	Untested,
+}
+
+impl Reachability {
+	pub fn label(&self) -> &'static str {
+		match self {
+			Self::Reachable => "reachable",
+			Self::Unreachable => "unreachable",
+			Self::Untested => "untested",
+		}
+	}
+}
+
+/// Outcome of the last self-test for one of this node's addresses.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressReachability {
+	pub addr: String,
+	pub status: Reachability,
+	pub tested_at: Option<DateTime<Utc>>,
+	/// The peer whose answer decided `status`.
+	pub tested_by: Option<String>,
+	pub latency_ms: Option<u64>,
+	/// Why the address is unreachable or untested.
+	pub error: Option<String>,
+}
+
+/// False once an address outside the local network was reached, so
+/// asking the router to forward ports would not help.
+pub fn port_mapping_worthwhile(results: &[AddressReachability]) -> bool {
+	!results.iter().any(|result| {
+		result.status == Reachability::Reachable
+			&& result
+				.addr
+				.parse::<Multiaddr>()
+				.ok()
+				.and_then(|addr| addr_ip(&addr))
+				.is_some_and(|ip| !is_non_routable(ip))
+	})
+}
+
+/// One peer's answer to a `DialBack` request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum DialBackOutcome {
+	Reachable { latency_ms: u64 },
+	Unreachable { error: String },
+	Declined { reason: String },
+}
+
+fn addr_ip(addr: &Multiaddr) -> Option<IpAddr> {
+	match addr.iter().next()? {
+		Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+		Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+		_ => None,
+	}
+}
+
+/// Addresses worth testing: what the swarm listens on and advertises,
+/// without loopback or wildcard ones, each once.
+pub(crate) fn testable_addrs(addrs: impl IntoIterator<Item = Multiaddr>) -> Vec<Multiaddr> {
+	let mut testable = Vec::new();
+	for addr in addrs {
+		let testable_ip =
+			addr_ip(&addr).is_some_and(|ip| !ip.is_loopback() && !ip.is_unspecified());
+		if testable_ip && !testable.contains(&addr) {
+			testable.push(addr);
+		}
+	}
+	testable
+}
+
+/// The address `requester` asked to be dialed at, when it may be: a plain
+/// IP with a TCP or QUIC port, on an IP the requester is connected from,
+/// and naming no other peer. Returned without its `/p2p` part.
+pub(crate) fn check_dial_back(
+	addr: &str,
+	requester: PeerId,
+	requester_ips: &[IpAddr],
+) -> Result<Multiaddr, String> {
+	if addr.len() > MAX_DIAL_BACK_ADDR_LEN {
+		return Err(format!(
+			"address is longer than {MAX_DIAL_BACK_ADDR_LEN} bytes"
+		));
+	}
+	let parsed = addr
+		.parse::<Multiaddr>()
+		.map_err(|err| format!("invalid address: {err}"))?;
+	let mut protocols = parsed.iter();
+	let ip = match protocols.next() {
+		Some(Protocol::Ip4(ip)) => IpAddr::V4(ip),
+		Some(Protocol::Ip6(ip)) => IpAddr::V6(ip),
+		_ => return Err(String::from("only IP addresses are dialed back")),
+	};
+	let mut dial = Multiaddr::empty().with(ip.into());
+	match protocols.next() {
+		Some(Protocol::Tcp(port)) if port != 0 => dial.push(Protocol::Tcp(port)),
+		Some(Protocol::Udp(port)) if port != 0 && cfg!(feature = "quic") => {
+			if protocols.next() != Some(Protocol::QuicV1) {
+				return Err(String::from("only QUIC is dialed back over UDP"));
+			}
+			dial.push(Protocol::Udp(port));
+			dial.push(Protocol::QuicV1);
+		}
+		_ => return Err(String::from("only TCP and QUIC ports are dialed back")),
+	}
+	match protocols.next() {
+		None => {}
+		Some(Protocol::P2p(peer)) if peer == requester && protocols.next().is_none() => {}
+		Some(Protocol::P2p(_)) => return Err(String::from("address names another peer")),
+		Some(_) => return Err(String::from("unsupported address")),
+	}
+	if !requester_ips.contains(&ip) {
+		return Err(String::from(
+			"only addresses on the IP you are connected from are dialed back",
+		));
+	}
+	Ok(dial)
+}
+
+/// Bounds how many dial-backs each peer gets and how many run at once.
+#[derive(Debug, Default)]
+pub(crate) struct DialBackLimiter {
+	recent: HashMap<PeerId, VecDeque<Instant>>,
+}
+
+impl DialBackLimiter {
+	/// Counts a dial-back for `peer` unless it asked for too many lately or
+	/// `running` are already in progress.
+	pub(crate) fn admit(
+		&mut self,
+		peer: PeerId,
+		running: usize,
+		now: Instant,
+	) -> Result<(), String> {
+		if running >= MAX_CONCURRENT_DIAL_BACKS {
+			return Err(String::from("too many dial-backs in progress"));
+		}
+		self.recent.retain(|_, started| {
+			while started
+				.front()
+				.is_some_and(|at| now.duration_since(*at) >= DIAL_BACK_WINDOW)
+			{
+				started.pop_front();
+			}
+			!started.is_empty()
+		});
+		let started = self.recent.entry(peer).or_default();
+		if started.len() >= DIAL_BACKS_PER_WINDOW {
+			return Err(String::from(
+				"too many dial-backs requested; try again later",
+			));
+		}
+		started.push_back(now);
+		Ok(())
+	}
+}
+
+/// Folds the answers for `addr` into one result. Any peer reaching it
+/// makes it reachable, the fastest one counting; otherwise any peer
+/// failing makes it unreachable. Without either it stays untested.
+pub(crate) fn aggregate(
+	addr: &Multiaddr,
+	answers: &[(PeerId, DialBackOutcome)],
+	now: DateTime<Utc>,
+) -> AddressReachability {
+	let result = |status, tester: Option<&PeerId>, latency_ms, error| AddressReachability {
+		addr: addr.to_string(),
+		status,
+		tested_at: Some(now),
+		tested_by: tester.map(PeerId::to_string),
+		latency_ms,
+		error,
+	};
+	let fastest = answers
+		.iter()
+		.filter_map(|(tester, outcome)| match outcome {
+			DialBackOutcome::Reachable { latency_ms } => Some((tester, *latency_ms)),
+			_ => None,
+		})
+		.min_by_key(|(_, latency_ms)| *latency_ms);
+	if let Some((tester, latency_ms)) = fastest {
+		return result(
+			Reachability::Reachable,
+			Some(tester),
+			Some(latency_ms),
+			None,
+		);
+	}
+	let failed = answers.iter().find_map(|(tester, outcome)| match outcome {
+		DialBackOutcome::Unreachable { error } => Some((tester, error)),
+		_ => None,
+	});
+	match failed {
+		Some((tester, error)) => result(
+			Reachability::Unreachable,
+			Some(tester),
+			None,
+			Some(error.clone()),
+		),
+		None => result(
+			Reachability::Untested,
+			None,
+			None,
+			Some(String::from(NO_TESTER)),
+		),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn addr(value: &str) -> Multiaddr {
+		value.parse().unwrap()
+	}
+
+	#[test]
+	fn only_the_requesters_own_ip_is_dialed_back() {
+		let requester = PeerId::random();
+		let ips = ["203.0.113.7".parse().unwrap()];
+		assert_eq!(
+			check_dial_back("/ip4/203.0.113.7/tcp/4001", requester, &ips),
+			Ok(addr("/ip4/203.0.113.7/tcp/4001"))
+		);
+		assert_eq!(
+			check_dial_back(
+				&format!("/ip4/203.0.113.7/tcp/4001/p2p/{requester}"),
+				requester,
+				&ips
+			),
+			Ok(addr("/ip4/203.0.113.7/tcp/4001"))
+		);
+		for refused in [
+			String::from("/ip4/198.51.100.1/tcp/22"),
+			String::from("/dns4/example.com/tcp/4001"),
+			String::from("/ip4/203.0.113.7/tcp/0"),
+			String::from("/ip4/203.0.113.7/udp/53"),
+			format!("/ip4/203.0.113.7/tcp/4001/p2p/{}", PeerId::random()),
+			format!(
+				"/ip4/203.0.113.7/tcp/{}",
+				"1".repeat(MAX_DIAL_BACK_ADDR_LEN)
+			),
+			String::from("not an address"),
+		] {
+			assert!(
+				check_dial_back(&refused, requester, &ips).is_err(),
+				"{refused}"
+			);
+		}
+	}
+
+	#[test]
+	fn dial_backs_are_rate_limited() {
+		let mut limiter = DialBackLimiter::default();
+		let peer = PeerId::random();
+		let start = Instant::now();
+		for _ in 0..DIAL_BACKS_PER_WINDOW {
+			assert!(limiter.admit(peer, 0, start).is_ok());
+		}
+		assert!(limiter.admit(peer, 0, start).is_err());
+		assert!(limiter.admit(PeerId::random(), 0, start).is_ok());
+		assert!(
+			limiter
+				.admit(PeerId::random(), MAX_CONCURRENT_DIAL_BACKS, start)
+				.is_err()
+		);
+		assert!(limiter.admit(peer, 0, start + DIAL_BACK_WINDOW).is_ok());
+	}
+
+	#[test]
+	fn answers_fold_into_one_status_per_address() {
+		let now = Utc::now();
+		let target = addr("/ip4/203.0.113.7/tcp/4001");
+		let (slow, fast, failing) = (PeerId::random(), PeerId::random(), PeerId::random());
+		let reached = aggregate(
+			&target,
+			&[
+				(
+					failing,
+					DialBackOutcome::Unreachable {
+						error: String::from("connection refused"),
+					},
+				),
+				(slow, DialBackOutcome::Reachable { latency_ms: 80 }),
+				(fast, DialBackOutcome::Reachable { latency_ms: 12 }),
+			],
+			now,
+		);
+		assert_eq!(reached.status, Reachability::Reachable);
+		assert_eq!(reached.tested_by, Some(fast.to_string()));
+		assert_eq!(reached.latency_ms, Some(12));
+
+		let refused = aggregate(
+			&target,
+			&[(
+				failing,
+				DialBackOutcome::Unreachable {
+					error: String::from("connection refused"),
+				},
+			)],
+			now,
+		);
+		assert_eq!(refused.status, Reachability::Unreachable);
+		assert_eq!(refused.error.as_deref(), Some("connection refused"));
+
+		let declined = aggregate(
+			&target,
+			&[(
+				slow,
+				DialBackOutcome::Declined {
+					reason: String::from("too many dial-backs in progress"),
+				},
+			)],
+			now,
+		);
+		assert_eq!(declined.status, Reachability::Untested);
+		assert_eq!(declined.error.as_deref(), Some(NO_TESTER));
+		assert_eq!(aggregate(&target, &[], now), declined);
+	}
+
+	#[test]
+	fn public_reachable_addresses_make_port_mapping_pointless() {
+		let now = Utc::now();
+		let tester = PeerId::random();
+		let reachable = |value: &str| {
+			aggregate(
+				&addr(value),
+				&[(tester, DialBackOutcome::Reachable { latency_ms: 5 })],
+				now,
+			)
+		};
+		assert!(port_mapping_worthwhile(&[]));
+		assert!(port_mapping_worthwhile(&[reachable(
+			"/ip4/192.168.1.2/tcp/4001"
+		)]));
+		assert!(!port_mapping_worthwhile(&[
+			reachable("/ip4/192.168.1.2/tcp/4001"),
+			reachable("/ip4/203.0.113.7/tcp/4001"),
+		]));
+	}
+}
@@ -0,0 +1,321 @@
+//! Readahead for ranged reads of files on remote peers. Each read fetches a
+//! whole window from the peer and starts fetching the following window in
+//! the background, so a player asking for the next range finds it in memory
+//! instead of waiting on another round trip. A read outside the buffered
+//! windows counts as a seek: the file's buffer is dropped and prefetch
+//! restarts from the new position.
+
+use crate::FileChunk;
+use futures::FutureExt;
+use futures::future::{BoxFuture, Shared};
+use libp2p::PeerId;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Bytes fetched from the peer per round trip.
+pub(crate) const READAHEAD_WINDOW: u64 = 4 * 1024 * 1024;
+/// Windows one file may hold: the one being played and the one after it.
+const MAX_WINDOWS_PER_FILE: usize = 2;
+/// Windows across all files before the least recently read files are
+/// dropped.
+const MAX_WINDOWS_TOTAL: usize = 16;
+
+type Fetch = Shared<BoxFuture<'static, Result<Arc<FileChunk>, String>>>;
+
+struct Window {
+	start: u64,
+	fetch: Fetch,
+}
+
+#[derive(Default)]
+struct Buffer {
+	windows: Vec<Window>,
+	last_used: u64,
+}
+
+#[derive(Default)]
+struct Buffers {
+	files: HashMap<(PeerId, String), Buffer>,
+	tick: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ReadaheadStats {
+	/// Reads served from a buffered or already requested window.
+	pub hits: u64,
+	/// Reads that had to wait for a new round trip.
+	pub misses: u64,
+	/// Round trips to peers, prefetches included.
+	pub fetches: u64,
+	pub buffered_files: usize,
+}
+
+#[derive(Default)]
+pub(crate) struct Readahead {
+	buffers: Mutex<Buffers>,
+	hits: AtomicU64,
+	misses: AtomicU64,
+	fetches: AtomicU64,
+}
+
+/// The part of `chunk` from `offset`, at most `length` bytes.
+fn slice_chunk(chunk: &FileChunk, offset: u64, length: Option<u64>) -> FileChunk {
+	let from = (offset - chunk.offset) as usize;
+	let available = chunk.data.len() - from;
+	let take = length.map_or(available, |length| (length as usize).min(available));
+	FileChunk {
+		offset,
+		data: chunk.data[from..from + take].to_vec(),
+		eof: chunk.eof && from + take == chunk.data.len(),
+		hole: 0,
+	}
+}
+
+fn covers(chunk: &FileChunk, offset: u64) -> bool {
+	offset >= chunk.offset && (offset - chunk.offset) < chunk.data.len() as u64
+}
+
+impl Readahead {
+	pub(crate) fn stats(&self) -> ReadaheadStats {
+		ReadaheadStats {
+			hits: self.hits.load(Ordering::Relaxed),
+			misses: self.misses.load(Ordering::Relaxed),
+			fetches: self.fetches.load(Ordering::Relaxed),
+			buffered_files: self.buffers.lock().unwrap().files.len(),
+		}
+	}
+
+	/// Starts fetching one window in its own task so it progresses whether
+	/// or not anyone is waiting on it yet.
+	fn spawn_fetch<F, Fut>(&self, fetch: &F, start: u64) -> Fetch
+	where
+		F: Fn(u64, u64) -> Fut,
+		Fut: Future<Output = anyhow::Result<FileChunk>> + Send + 'static,
+	{
+		self.fetches.fetch_add(1, Ordering::Relaxed);
+		let task = tokio::spawn(fetch(start, READAHEAD_WINDOW));
+		async move {
+			match task.await {
+				Ok(Ok(chunk)) => Ok(Arc::new(chunk)),
+				Ok(Err(err)) => Err(err.to_string()),
+				Err(err) => Err(format!("readahead task failed: {err}")),
+			}
+		}
+		.boxed()
+		.shared()
+	}
+
+	/// Newest window of `key` starting at or before `offset`.
+	fn window_before(&self, key: &(PeerId, String), offset: u64) -> Option<Fetch> {
+		let mut buffers = self.buffers.lock().unwrap();
+		buffers.tick += 1;
+		let tick = buffers.tick;
+		let buffer = buffers.files.get_mut(key)?;
+		buffer.last_used = tick;
+		buffer
+			.windows
+			.iter()
+			.filter(|window| window.start <= offset)
+			.max_by_key(|window| window.start)
+			.map(|window| window.fetch.clone())
+	}
+
+	/// Replaces the buffer of `key` with a single window at `offset`.
+	fn restart_at<F, Fut>(&self, key: &(PeerId, String), offset: u64, fetch: &F) -> Fetch
+	where
+		F: Fn(u64, u64) -> Fut,
+		Fut: Future<Output = anyhow::Result<FileChunk>> + Send + 'static,
+	{
+		let window = self.spawn_fetch(fetch, offset);
+		let mut buffers = self.buffers.lock().unwrap();
+		buffers.tick += 1;
+		let tick = buffers.tick;
+		let buffer = buffers.files.entry(key.clone()).or_default();
+		buffer.last_used = tick;
+		buffer.windows = vec![Window {
+			start: offset,
+			fetch: window.clone(),
+		}];
+		window
+	}
+
+	/// Drops windows behind `current` and queues the window after it, then
+	/// drops the least recently read files while over the global cap.
+	fn prefetch_after<F, Fut>(&self, key: &(PeerId, String), current: &FileChunk, fetch: &F)
+	where
+		F: Fn(u64, u64) -> Fut,
+		Fut: Future<Output = anyhow::Result<FileChunk>> + Send + 'static,
+	{
+		let next = current.offset + current.data.len() as u64;
+		let mut buffers = self.buffers.lock().unwrap();
+		let Some(buffer) = buffers.files.get_mut(key) else {
+			return;
+		};
+		buffer
+			.windows
+			.retain(|window| window.start >= current.offset);
+		let queued = buffer.windows.iter().any(|window| window.start == next);
+		if !current.eof && !current.data.is_empty() && !queued {
+			if buffer.windows.len() >= MAX_WINDOWS_PER_FILE {
+				return;
+			}
+			let window = self.spawn_fetch(fetch, next);
+			buffer.windows.push(Window {
+				start: next,
+				fetch: window,
+			});
+		}
+		let mut total = buffers
+			.files
+			.values()
+			.map(|buffer| buffer.windows.len())
+			.sum::<usize>();
+		while total > MAX_WINDOWS_TOTAL {
+			let Some(oldest) = buffers
+				.files
+				.iter()
+				.filter(|(other, _)| *other != key)
+				.min_by_key(|(_, buffer)| buffer.last_used)
+				.map(|(other, _)| other.clone())
+			else {
+				break;
+			};
+			if let Some(evicted) = buffers.files.remove(&oldest) {
+				total -= evicted.windows.len();
+			}
+		}
+	}
+
+	/// Reads up to `length` bytes of `path` on `peer` from `offset`, or to
+	/// the end of the buffered window when `length` is `None`. `fetch`
+	/// performs one round trip for a byte range.
+	pub(crate) async fn read<F, Fut>(
+		&self,
+		peer: PeerId,
+		path: &str,
+		offset: u64,
+		length: Option<u64>,
+		fetch: F,
+	) -> anyhow::Result<FileChunk>
+	where
+		F: Fn(u64, u64) -> Fut,
+		Fut: Future<Output = anyhow::Result<FileChunk>> + Send + 'static,
+	{
+		let key = (peer, path.to_string());
+		if let Some(window) = self.window_before(&key, offset)
+			&& let Ok(chunk) = window.await
+			&& covers(&chunk, offset)
+		{
+			self.hits.fetch_add(1, Ordering::Relaxed);
+			self.prefetch_after(&key, &chunk, &fetch);
+			return Ok(slice_chunk(&chunk, offset, length));
+		}
+		self.misses.fetch_add(1, Ordering::Relaxed);
+		let window = self.restart_at(&key, offset, &fetch);
+		let chunk = match window.await {
+			Ok(chunk) => chunk,
+			Err(err) => {
+				self.buffers.lock().unwrap().files.remove(&key);
+				anyhow::bail!(err);
+			}
+		};
+		if chunk.data.is_empty() {
+			return Ok(FileChunk {
+				offset,
+				data: Vec::new(),
+				eof: chunk.eof,
+				hole: 0,
+			});
+		}
+		self.prefetch_after(&key, &chunk, &fetch);
+		Ok(slice_chunk(&chunk, offset, length))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Serves a file of `len` bytes, counting round trips.
+	fn remote_file(
+		len: u64,
+		trips: Arc<AtomicU64>,
+	) -> impl Fn(u64, u64) -> BoxFuture<'static, anyhow::Result<FileChunk>> {
+		move |offset, length| {
+			trips.fetch_add(1, Ordering::SeqCst);
+			let end = (offset + length).min(len);
+			let data = (offset..end).map(|at| at as u8).collect::<Vec<_>>();
+			async move {
+				Ok(FileChunk {
+					offset,
+					data,
+					eof: end >= len,
+					hole: 0,
+				})
+			}
+			.boxed()
+		}
+	}
+
+	#[tokio::test]
+	async fn sequential_reads_cost_one_round_trip_per_window() {
+		let cache = Readahead::default();
+		let peer = PeerId::random();
+		let trips = Arc::new(AtomicU64::new(0));
+		let windows = 6;
+		let len = READAHEAD_WINDOW * windows;
+		let range = 256 * 1024;
+
+		let mut offset = 0;
+		while offset < len {
+			let chunk = cache
+				.read(
+					peer,
+					"movie.mkv",
+					offset,
+					Some(range),
+					remote_file(len, trips.clone()),
+				)
+				.await
+				.unwrap();
+			assert_eq!(chunk.offset, offset);
+			assert_eq!(chunk.data[0], offset as u8);
+			offset += chunk.data.len() as u64;
+		}
+		assert_eq!(trips.load(Ordering::SeqCst), windows);
+		let stats = cache.stats();
+		assert_eq!(stats.misses, 1);
+		assert_eq!(stats.fetches, windows);
+	}
+
+	#[tokio::test]
+	async fn seeking_restarts_the_buffer_at_the_new_position() {
+		let cache = Readahead::default();
+		let peer = PeerId::random();
+		let trips = Arc::new(AtomicU64::new(0));
+		let len = READAHEAD_WINDOW * 10;
+		let file = || remote_file(len, trips.clone());
+
+		cache.read(peer, "a", 0, Some(1024), file()).await.unwrap();
+		let far = READAHEAD_WINDOW * 7 + 5;
+		let chunk = cache
+			.read(peer, "a", far, Some(1024), file())
+			.await
+			.unwrap();
+		assert_eq!(chunk.offset, far);
+		assert_eq!(chunk.data[0], far as u8);
+		assert_eq!(cache.stats().misses, 2);
+		// The window after the seek point is already on its way.
+		cache
+			.read(peer, "a", far + READAHEAD_WINDOW, Some(1024), file())
+			.await
+			.unwrap();
+		assert_eq!(cache.stats().misses, 2);
+		let buffers = cache.buffers.lock().unwrap();
+		let windows = &buffers.files[&(peer, String::from("a"))].windows;
+		assert!(windows.iter().all(|window| window.start >= far));
+	}
+}
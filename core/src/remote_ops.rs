@@ -0,0 +1,320 @@
+//! Scans and updates running on other peers, tracked until the peer reports
+//! the last event. A peer that crashes or drops off the network midway
+//! never sends it, so an operation it has not reported on for the idle
+//! timeout is ended here with a failure of its own and forgotten, and the
+//! consumer gets a terminal event instead of waiting forever.
+
+use crate::config;
+use crate::db::load_setting;
+use crate::scan::ScanEvent;
+use crate::updater::{UpdateErrorKind, UpdateProgress};
+use chrono::{DateTime, Utc};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub const REMOTE_OP_IDLE_SETTING: &str = "remote_op_idle_secs";
+pub const DEFAULT_REMOTE_OP_IDLE: chrono::Duration = chrono::Duration::minutes(10);
+/// Operations of one kind tracked at once; starting another fails with
+/// [`TooManyRemoteOps`].
+pub const MAX_REMOTE_OPS: usize = 256;
+/// Error of the failure an abandoned operation ends with.
+pub const STOPPED_REPORTING: &str = "peer stopped reporting";
+
+/// Idle timeout stored under [`REMOTE_OP_IDLE_SETTING`], in seconds.
+pub(crate) fn idle_timeout(conn: &Connection) -> chrono::Duration {
+	let value = match load_setting(conn, REMOTE_OP_IDLE_SETTING) {
+		Ok(value) => value,
+		Err(err) => {
+			tracing::error!("failed to load remote operation idle timeout: {err}");
+			None
+		}
+	};
+	value
+		.and_then(|value| value.trim().parse::<i64>().ok())
+		.filter(|secs| *secs > 0)
+		.map(chrono::Duration::seconds)
+		.unwrap_or_else(|| config::startup().remote_op_idle)
+}
+
+/// Events a peer streams back about an operation it runs for us.
+pub(crate) trait RemoteEvent: Sized {
+	/// Whether the peer sends nothing after this event.
+	fn is_terminal(&self) -> bool;
+	/// Sent in place of the terminal event of an abandoned operation.
+	fn stopped_reporting() -> Self;
+}
+
+impl RemoteEvent for ScanEvent {
+	fn is_terminal(&self) -> bool {
+		matches!(self, ScanEvent::Finished(_))
+	}
+
+	fn stopped_reporting() -> Self {
+		ScanEvent::Finished(Err(STOPPED_REPORTING.to_string()))
+	}
+}
+
+impl RemoteEvent for UpdateProgress {
+	fn is_terminal(&self) -> bool {
+		matches!(
+			self,
+			UpdateProgress::Completed { .. }
+				| UpdateProgress::Failed { .. }
+				| UpdateProgress::AlreadyUpToDate { .. }
+		)
+	}
+
+	/// Transient, since the peer may only have restarted or dropped off
+	/// for a while.
+	fn stopped_reporting() -> Self {
+		UpdateProgress::Failed {
+			error: STOPPED_REPORTING.to_string(),
+			kind: Some(UpdateErrorKind::Transient),
+		}
+	}
+}
+
+/// Returned when starting an operation while [`MAX_REMOTE_OPS`] of its
+/// kind are already tracked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TooManyRemoteOps {
+	pub kind: &'static str,
+	pub limit: usize,
+}
+
+impl fmt::Display for TooManyRemoteOps {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"already waiting on {} remote {}s; try again when one finishes",
+			self.limit, self.kind
+		)
+	}
+}
+
+impl std::error::Error for TooManyRemoteOps {}
+
+/// How many operations of one kind are tracked and for how long.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RemoteOpStats {
+	pub tracked: usize,
+	/// Age of the longest tracked operation.
+	pub oldest_age_secs: Option<u64>,
+}
+
+/// [`RemoteOpStats`] of remote scans and remote updates.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RemoteOpsStats {
+	pub scans: RemoteOpStats,
+	pub updates: RemoteOpStats,
+}
+
+struct Tracked<E> {
+	tx: UnboundedSender<E>,
+	created_at: DateTime<Utc>,
+	last_event_at: DateTime<Utc>,
+}
+
+/// Consumers of the operations of one kind other peers run for us, by the
+/// id the peer reports under.
+pub(crate) struct RemoteOps<E> {
+	kind: &'static str,
+	limit: usize,
+	entries: Mutex<HashMap<u64, Tracked<E>>>,
+}
+
+impl<E: RemoteEvent> RemoteOps<E> {
+	pub(crate) fn new(kind: &'static str) -> Self {
+		Self::with_limit(kind, MAX_REMOTE_OPS)
+	}
+
+	pub(crate) fn with_limit(kind: &'static str, limit: usize) -> Self {
+		Self {
+			kind,
+			limit,
+			entries: Mutex::new(HashMap::new()),
+		}
+	}
+
+	pub(crate) fn insert(
+		&self,
+		id: u64,
+		tx: UnboundedSender<E>,
+		now: DateTime<Utc>,
+	) -> Result<(), TooManyRemoteOps> {
+		let mut entries = self.entries.lock().unwrap();
+		if entries.len() >= self.limit && !entries.contains_key(&id) {
+			return Err(TooManyRemoteOps {
+				kind: self.kind,
+				limit: self.limit,
+			});
+		}
+		entries.insert(
+			id,
+			Tracked {
+				tx,
+				created_at: now,
+				last_event_at: now,
+			},
+		);
+		Ok(())
+	}
+
+	pub(crate) fn remove(&self, id: u64) -> Option<UnboundedSender<E>> {
+		self.entries
+			.lock()
+			.unwrap()
+			.remove(&id)
+			.map(|entry| entry.tx)
+	}
+
+	/// Passes on an event the peer reported, forgetting the operation after
+	/// its terminal event or once nobody listens. False for unknown ids.
+	pub(crate) fn deliver(&self, id: u64, event: E, now: DateTime<Utc>) -> bool {
+		let mut entries = self.entries.lock().unwrap();
+		let Some(entry) = entries.get_mut(&id) else {
+			return false;
+		};
+		entry.last_event_at = now;
+		let terminal = event.is_terminal();
+		if entry.tx.send(event).is_err() || terminal {
+			entries.remove(&id);
+		}
+		true
+	}
+
+	/// Ends every operation not reported on for `idle` with
+	/// [`RemoteEvent::stopped_reporting`]. Returns their ids.
+	pub(crate) fn expire(&self, now: DateTime<Utc>, idle: chrono::Duration) -> Vec<u64> {
+		let mut entries = self.entries.lock().unwrap();
+		let expired = entries
+			.iter()
+			.filter(|(_, entry)| now - entry.last_event_at >= idle)
+			.map(|(id, _)| *id)
+			.collect::<Vec<_>>();
+		for id in &expired {
+			if let Some(entry) = entries.remove(id) {
+				let _ = entry.tx.send(E::stopped_reporting());
+			}
+		}
+		expired
+	}
+
+	pub(crate) fn stats(&self, now: DateTime<Utc>) -> RemoteOpStats {
+		let entries = self.entries.lock().unwrap();
+		RemoteOpStats {
+			tracked: entries.len(),
+			oldest_age_secs: entries
+				.values()
+				.map(|entry| entry.created_at)
+				.min()
+				.map(|created_at| (now - created_at).num_seconds().max(0) as u64),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::scan::ScanProgress;
+	use tokio::sync::mpsc::unbounded_channel;
+
+	fn progress(processed_files: usize) -> ScanEvent {
+		ScanEvent::Progress(ScanProgress {
+			total_files: 10,
+			processed_files,
+			..Default::default()
+		})
+	}
+
+	#[test]
+	fn a_peer_going_silent_mid_scan_ends_the_scan() {
+		let scans = RemoteOps::<ScanEvent>::new("scan");
+		let start = Utc::now();
+		let idle = chrono::Duration::minutes(10);
+		let (tx, mut rx) = unbounded_channel();
+		scans.insert(7, tx, start).unwrap();
+		assert!(scans.deliver(7, progress(3), start + chrono::Duration::minutes(4)));
+
+		// Idle counts from the last event, not the start.
+		assert!(
+			scans
+				.expire(start + chrono::Duration::minutes(12), idle)
+				.is_empty()
+		);
+		assert_eq!(
+			scans.stats(start + chrono::Duration::minutes(12)),
+			RemoteOpStats {
+				tracked: 1,
+				oldest_age_secs: Some(12 * 60),
+			}
+		);
+
+		let expired = scans.expire(start + chrono::Duration::minutes(14), idle);
+		assert_eq!(expired, vec![7]);
+		assert!(matches!(rx.try_recv(), Ok(ScanEvent::Progress(_))));
+		match rx.try_recv() {
+			Ok(ScanEvent::Finished(Err(error))) => assert_eq!(error, STOPPED_REPORTING),
+			other => panic!("expected the scan to fail, got {other:?}"),
+		}
+		// The sender is gone, so the stream ends after the failure.
+		assert!(rx.try_recv().is_err());
+		assert_eq!(scans.stats(start), RemoteOpStats::default());
+		assert!(!scans.deliver(7, progress(4), start + chrono::Duration::minutes(15)));
+	}
+
+	#[test]
+	fn silent_updates_fail_as_transient() {
+		let updates = RemoteOps::<UpdateProgress>::new("update");
+		let start = Utc::now();
+		let (tx, mut rx) = unbounded_channel();
+		updates.insert(1, tx, start).unwrap();
+		updates.expire(start + chrono::Duration::hours(1), DEFAULT_REMOTE_OP_IDLE);
+		match rx.try_recv() {
+			Ok(UpdateProgress::Failed { error, kind }) => {
+				assert_eq!(error, STOPPED_REPORTING);
+				assert_eq!(kind, Some(UpdateErrorKind::Transient));
+			}
+			other => panic!("expected the update to fail, got {other:?}"),
+		}
+		assert_eq!(updates.stats(start).tracked, 0);
+	}
+
+	#[test]
+	fn terminal_events_and_gone_consumers_are_forgotten() {
+		let scans = RemoteOps::<ScanEvent>::new("scan");
+		let now = Utc::now();
+		let (tx, mut rx) = unbounded_channel();
+		scans.insert(1, tx, now).unwrap();
+		scans.deliver(1, ScanEvent::Finished(Err("denied".to_string())), now);
+		assert!(matches!(rx.try_recv(), Ok(ScanEvent::Finished(_))));
+
+		let (tx, rx) = unbounded_channel();
+		scans.insert(2, tx, now).unwrap();
+		drop(rx);
+		scans.deliver(2, progress(1), now);
+		assert_eq!(scans.stats(now).tracked, 0);
+	}
+
+	#[test]
+	fn starting_past_the_limit_is_refused() {
+		let scans = RemoteOps::<ScanEvent>::with_limit("scan", 2);
+		let now = Utc::now();
+		let (tx, _rx) = unbounded_channel();
+		scans.insert(1, tx.clone(), now).unwrap();
+		scans.insert(2, tx.clone(), now).unwrap();
+		assert_eq!(
+			scans.insert(3, tx.clone(), now),
+			Err(TooManyRemoteOps {
+				kind: "scan",
+				limit: 2,
+			})
+		);
+		scans.remove(1);
+		scans.insert(3, tx, now).unwrap();
+	}
+}
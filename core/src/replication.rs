@@ -0,0 +1,823 @@
+//! A warm standby of this node's index on a trusted peer. Every change to
+//! `file_entries` and `file_locations` gets a number from one counter (the
+//! `index_changes` triggers), so the source streams the rows changed since
+//! the last number the replica acknowledged, a batch at a time, and a
+//! replication cut off halfway resumes where the replica's last ack left
+//! it. The replica keeps the rows under the source's node id, apart from
+//! its own scans.
+//!
+//! Rows removed outright instead of tombstoned are not changes the replica
+//! can see. The source starts a new generation when its index is rebuilt
+//! from scratch, and a delta of a new generation starting at zero makes the
+//! replica drop everything it held for the source first. Tombstones past
+//! their retention are purged on both sides on their own; a replica that
+//! had not yet seen a purged tombstone is sent a new generation.
+
+use crate::activity_window::{ActivityKind, ActivityWindow};
+use crate::app::{Command, peer_to_node_id};
+use crate::db::{
+	FileOrigin, NodeID, apply_index_delta, bump_index_generation, count_index_changes,
+	index_change_seq, index_generation, index_purged_through, load_index_changes, load_replication,
+	load_replications, save_replication,
+};
+use crate::db_pool::Db;
+use crate::format::group_digits;
+use crate::maintenance::MaintenanceGate;
+use crate::p2p::WirePath;
+use crate::power::PowerGate;
+use anyhow::{Result, anyhow, bail};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+/// How often replicas are checked for changes to send.
+pub(crate) const REPLICATION_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Changed rows per delta, which keeps a delta well under the request
+/// size limit even with long paths.
+pub(crate) const INDEX_DELTA_BATCH: usize = 200;
+/// Rows a replica takes in one delta: a batch, plus the entry of every
+/// location in it.
+const MAX_DELTA_ROWS: usize = 2 * INDEX_DELTA_BATCH;
+/// Refused deltas in a row before a run gives up until the next check.
+const MAX_REFUSALS: u32 = 3;
+pub(crate) const DELTA_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A `file_entries` row as it travels to a replica.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicatedEntry {
+	pub hash: Vec<u8>,
+	pub size: i64,
+	pub mime_type: Option<String>,
+	pub first_datetime: Option<String>,
+	pub latest_datetime: Option<String>,
+}
+
+/// A `file_locations` row of the source node, tombstones included.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicatedLocation {
+	pub path: WirePath,
+	pub hash: Option<Vec<u8>>,
+	pub size: u64,
+	pub timestamp: DateTime<Utc>,
+	pub created_at: Option<DateTime<Utc>>,
+	pub modified_at: Option<DateTime<Utc>>,
+	pub accessed_at: Option<DateTime<Utc>>,
+	/// Unix seconds.
+	pub deleted_at: Option<i64>,
+	pub origin: FileOrigin,
+	pub origin_peer: Option<String>,
+	pub origin_run: Option<i64>,
+	pub origin_transfer: Option<String>,
+	pub origin_user: Option<String>,
+	pub introduced_at: Option<i64>,
+}
+
+/// Index rows of the sender changed after `after` up to `through`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexDelta {
+	/// Id of the sender's index history; a new one starts at `after` 0.
+	pub generation: String,
+	/// Last change the sender believes the replica has; 0 sends
+	/// everything and replaces what the replica held.
+	pub after: u64,
+	pub through: u64,
+	pub entries: Vec<ReplicatedEntry>,
+	pub locations: Vec<ReplicatedLocation>,
+	/// Changes left after this delta.
+	#[serde(default)]
+	pub remaining: u64,
+}
+
+/// Where the replica stands after a delta. When it didn't take the delta,
+/// this is what it holds, so the sender can continue from there.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexDeltaAck {
+	pub generation: String,
+	pub acked: u64,
+}
+
+/// Which end of a replication this node is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplicationRole {
+	/// This node's index is replicated to the peer.
+	Source,
+	/// This node keeps a replica of the peer's index.
+	Replica,
+}
+
+impl ReplicationRole {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Source => "source",
+			Self::Replica => "replica",
+		}
+	}
+
+	pub fn parse(value: &str) -> Option<Self> {
+		match value {
+			"source" => Some(Self::Source),
+			"replica" => Some(Self::Replica),
+			_ => None,
+		}
+	}
+}
+
+/// A replication with one peer, from the `replication_state` table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReplicationStatus {
+	pub peer: String,
+	pub role: ReplicationRole,
+	/// Generation the acked changes belong to; empty before the first ack.
+	pub generation: String,
+	/// Last change the replica acknowledged.
+	pub acked_seq: u64,
+	/// Changes the replica doesn't have yet, as of the last sync.
+	pub behind: u64,
+	pub last_sync_at: Option<DateTime<Utc>>,
+	pub last_error: Option<String>,
+	/// Changes are being sent right now.
+	pub syncing: bool,
+}
+
+impl ReplicationStatus {
+	/// "replica 1,204 changes behind" on the source, "keeping a replica of
+	/// this device's index" on the replica.
+	pub fn describe(&self) -> String {
+		let mut text = match self.role {
+			ReplicationRole::Source if self.generation.is_empty() => {
+				String::from("replica not synced yet")
+			}
+			ReplicationRole::Source if self.behind == 0 => String::from("replica up to date"),
+			ReplicationRole::Source => format!(
+				"replica {} change{} behind",
+				group_digits(self.behind),
+				if self.behind == 1 { "" } else { "s" }
+			),
+			ReplicationRole::Replica if self.behind == 0 => {
+				String::from("keeping a replica of this device's index")
+			}
+			ReplicationRole::Replica => format!(
+				"keeping a replica of this device's index, {} changes to go",
+				group_digits(self.behind)
+			),
+		};
+		if self.syncing {
+			text.push_str(", syncing");
+		}
+		if let Some(err) = &self.last_error {
+			text.push_str(&format!(" (last attempt failed: {err})"));
+		}
+		text
+	}
+}
+
+/// Changed rows read for one delta.
+#[derive(Debug, Default)]
+pub(crate) struct IndexChanges {
+	pub(crate) entries: Vec<ReplicatedEntry>,
+	pub(crate) locations: Vec<ReplicatedLocation>,
+	/// Last change in the batch.
+	pub(crate) through: u64,
+}
+
+impl IndexChanges {
+	pub(crate) fn is_empty(&self) -> bool {
+		self.entries.is_empty() && self.locations.is_empty()
+	}
+}
+
+/// Peer access replication needs. Implemented over the command channel;
+/// tests hand deltas straight to a second database.
+#[async_trait]
+pub(crate) trait ReplicaLink: Send + Sync {
+	async fn local_node(&self) -> Option<NodeID>;
+	async fn is_connected(&self, peer: PeerId) -> bool;
+	/// True while this node grants `peer` owner access.
+	async fn is_owner(&self, peer: PeerId) -> bool;
+	async fn send_delta(&self, peer: PeerId, delta: IndexDelta) -> Result<IndexDeltaAck>;
+}
+
+#[async_trait]
+impl ReplicaLink for UnboundedSender<Command> {
+	async fn local_node(&self) -> Option<NodeID> {
+		let (tx, rx) = oneshot::channel();
+		self.send(Command::GetLocalPeerId { tx }).ok()?;
+		peer_to_node_id(&rx.await.ok()?)
+	}
+
+	async fn is_connected(&self, peer: PeerId) -> bool {
+		let (tx, rx) = oneshot::channel();
+		if self.send(Command::GetState { tx }).is_err() {
+			return false;
+		}
+		rx.await
+			.is_ok_and(|state| state.connections.iter().any(|conn| conn.peer_id == peer))
+	}
+
+	async fn is_owner(&self, peer: PeerId) -> bool {
+		let (tx, rx) = oneshot::channel();
+		if self.send(Command::GetState { tx }).is_err() {
+			return false;
+		}
+		rx.await.is_ok_and(|state| state.is_owner(&peer))
+	}
+
+	async fn send_delta(&self, peer: PeerId, delta: IndexDelta) -> Result<IndexDeltaAck> {
+		let (tx, rx) = oneshot::channel();
+		self.send(Command::SendIndexDelta { peer, delta, tx })
+			.map_err(|e| anyhow!("failed to send SendIndexDelta command: {e}"))?;
+		match tokio::time::timeout(DELTA_TIMEOUT, rx).await {
+			Ok(ack) => ack.map_err(|e| anyhow!("SendIndexDelta response channel closed: {e}"))?,
+			Err(_) => bail!("no answer to the index delta within {DELTA_TIMEOUT:?}"),
+		}
+	}
+}
+
+/// Peers with a replication running, which only lives as long as the
+/// process.
+#[derive(Default)]
+pub(crate) struct ReplicationRuns {
+	running: Mutex<HashSet<PeerId>>,
+}
+
+impl ReplicationRuns {
+	fn begin(&self, peer: PeerId) -> bool {
+		self.running.lock().unwrap().insert(peer)
+	}
+
+	fn end(&self, peer: &PeerId) {
+		self.running.lock().unwrap().remove(peer);
+	}
+
+	pub(crate) fn is_running(&self, peer: &PeerId) -> bool {
+		self.running.lock().unwrap().contains(peer)
+	}
+}
+
+fn lock(db: &Db) -> Result<std::sync::MutexGuard<'_, Connection>> {
+	db.lock().map_err(|err| anyhow!("db lock poisoned: {err}"))
+}
+
+/// Applies `delta` from `source` to the replica in `conn` when it
+/// continues what the replica holds, or starts over at zero. Anything else
+/// is answered with where the replica stands, without applying it.
+pub(crate) fn receive_delta(
+	conn: &Connection,
+	source: &PeerId,
+	delta: &IndexDelta,
+	now: DateTime<Utc>,
+) -> Result<IndexDeltaAck> {
+	let node_id = peer_to_node_id(source).ok_or_else(|| anyhow!("invalid peer id {source}"))?;
+	if delta.through < delta.after {
+		bail!("index delta ends before it starts");
+	}
+	if delta.entries.len() + delta.locations.len() > MAX_DELTA_ROWS {
+		bail!("index delta holds more than {MAX_DELTA_ROWS} rows");
+	}
+	let held = load_replication(conn, source, ReplicationRole::Replica)?;
+	let continues = held
+		.as_ref()
+		.is_some_and(|held| held.generation == delta.generation && held.acked_seq == delta.after);
+	if delta.after != 0 && !continues {
+		return Ok(match held {
+			Some(held) => IndexDeltaAck {
+				generation: held.generation,
+				acked: held.acked_seq,
+			},
+			None => IndexDeltaAck {
+				generation: String::new(),
+				acked: 0,
+			},
+		});
+	}
+	let status = ReplicationStatus {
+		peer: source.to_string(),
+		role: ReplicationRole::Replica,
+		generation: delta.generation.clone(),
+		acked_seq: delta.through,
+		behind: delta.remaining,
+		last_sync_at: Some(now),
+		last_error: None,
+		syncing: false,
+	};
+	apply_index_delta(conn, &node_id, delta, &status)?;
+	Ok(IndexDeltaAck {
+		generation: delta.generation.clone(),
+		acked: delta.through,
+	})
+}
+
+/// The next batch of changes to `node_id`'s index for a peer holding
+/// `generation` up to `acked`, or `None` when it has them all.
+fn next_delta(
+	conn: &Connection,
+	node_id: &NodeID,
+	generation: &str,
+	acked: u64,
+) -> Result<Option<IndexDelta>> {
+	let mut current = index_generation(conn)?;
+	if generation == current && acked > index_change_seq(conn)? {
+		// The database went back in time, e.g. restored from a backup, and
+		// the peer holds changes it no longer knows.
+		current = bump_index_generation(conn)?;
+	} else if generation == current && acked != 0 && acked < index_purged_through(conn)? {
+		// A deletion the peer never saw was purged since; it can only be
+		// told by starting over. Peers up to date with the old generation
+		// start over as well.
+		current = bump_index_generation(conn)?;
+	}
+	let after = if generation == current { acked } else { 0 };
+	let changes = load_index_changes(conn, node_id, after, INDEX_DELTA_BATCH)?;
+	if changes.is_empty() && generation == current {
+		return Ok(None);
+	}
+	Ok(Some(IndexDelta {
+		remaining: count_index_changes(conn, node_id, changes.through)?,
+		generation: current,
+		after,
+		through: changes.through,
+		entries: changes.entries,
+		locations: changes.locations,
+	}))
+}
+
+/// Answers an `IndexPull` from a peer holding `generation` up to `after`:
+/// the next batch of changes, or an empty delta continuing what it holds
+/// when it is up to date.
+pub(crate) fn pull_delta(
+	conn: &Connection,
+	node_id: &NodeID,
+	generation: &str,
+	after: u64,
+) -> Result<IndexDelta> {
+	Ok(
+		next_delta(conn, node_id, generation, after)?.unwrap_or_else(|| IndexDelta {
+			generation: generation.to_string(),
+			after,
+			through: after,
+			entries: Vec::new(),
+			locations: Vec::new(),
+			remaining: 0,
+		}),
+	)
+}
+
+/// Sends `peer` the changes to `node_id`'s index it hasn't acknowledged,
+/// a batch at a time, until it has them all. Progress is stored after
+/// every ack, so an interrupted run resumes from the last one.
+pub(crate) async fn replicate_index<L: ReplicaLink + ?Sized>(
+	link: &L,
+	db: &Db,
+	peer: PeerId,
+	node_id: &NodeID,
+) -> Result<()> {
+	let mut refusals = 0;
+	loop {
+		let delta = {
+			let conn = lock(db)?;
+			let mut state = load_replication(&conn, &peer, ReplicationRole::Source)?
+				.ok_or_else(|| anyhow!("index is not replicated to {peer}"))?;
+			match next_delta(&conn, node_id, &state.generation, state.acked_seq)? {
+				Some(delta) => delta,
+				None => {
+					state.behind = 0;
+					state.last_sync_at = Some(Utc::now());
+					state.last_error = None;
+					save_replication(&conn, &state)?;
+					return Ok(());
+				}
+			}
+		};
+		let (generation, through) = (delta.generation.clone(), delta.through);
+		let ack = link.send_delta(peer, delta).await?;
+
+		let conn = lock(db)?;
+		let mut state = load_replication(&conn, &peer, ReplicationRole::Source)?
+			.ok_or_else(|| anyhow!("index is not replicated to {peer}"))?;
+		if ack.generation == generation && ack.acked <= index_change_seq(&conn)? {
+			if ack.acked == through {
+				refusals = 0;
+			} else {
+				refusals += 1;
+			}
+			state.generation = ack.generation;
+			state.acked_seq = ack.acked;
+			state.behind = count_index_changes(&conn, node_id, ack.acked)?;
+		} else {
+			// The replica holds another history; the next delta replaces it.
+			refusals += 1;
+			state.generation = String::new();
+			state.acked_seq = 0;
+		}
+		state.last_sync_at = Some(Utc::now());
+		save_replication(&conn, &state)?;
+		if refusals >= MAX_REFUSALS {
+			bail!("replica refused {MAX_REFUSALS} deltas in a row");
+		}
+	}
+}
+
+/// Starts a replication run for every peer this node replicates its index
+/// to that is connected, still has owner access and isn't being synced
+/// already, while the activity window is open for syncs and there is
+/// neither a power hold nor maintenance.
+pub(crate) async fn start_due_replications<L: ReplicaLink + 'static>(
+	link: &Arc<L>,
+	db: &Arc<Db>,
+	window: &Arc<Mutex<ActivityWindow>>,
+	power: &Arc<Mutex<PowerGate>>,
+	maintenance: &Arc<MaintenanceGate>,
+	runs: &Arc<ReplicationRuns>,
+) {
+	if !window
+		.lock()
+		.unwrap()
+		.allows(ActivityKind::Sync, Utc::now())
+		|| power.lock().unwrap().holds()
+		|| maintenance.is_active()
+	{
+		return;
+	}
+	let replications = match db.lock() {
+		Ok(conn) => load_replications(&conn).unwrap_or_else(|err| {
+			tracing::error!("failed to load index replications: {err}");
+			Vec::new()
+		}),
+		Err(err) => {
+			tracing::error!("db lock poisoned while loading index replications: {err}");
+			return;
+		}
+	};
+	let Some(node_id) = link.local_node().await else {
+		return;
+	};
+	for replication in replications {
+		if replication.role != ReplicationRole::Source {
+			continue;
+		}
+		let Ok(peer) = replication.peer.parse::<PeerId>() else {
+			continue;
+		};
+		if !link.is_connected(peer).await || !link.is_owner(peer).await || !runs.begin(peer) {
+			continue;
+		}
+		let Ok(work) = maintenance.begin(format!("index replication to {peer}"), None) else {
+			runs.end(&peer);
+			return;
+		};
+		let (link, db, runs) = (link.clone(), db.clone(), runs.clone());
+		tokio::spawn(async move {
+			let _work = work;
+			if let Err(err) = replicate_index(&*link, &db, peer, &node_id).await {
+				tracing::warn!("index replication to {peer} failed: {err:#}");
+				match db.lock() {
+					Ok(conn) => {
+						let recorded = load_replication(&conn, &peer, ReplicationRole::Source)
+							.and_then(|state| match state {
+								Some(mut state) => {
+									state.last_error = Some(format!("{err:#}"));
+									save_replication(&conn, &state)
+								}
+								None => Ok(()),
+							});
+						if let Err(err) = recorded {
+							tracing::error!("failed to record replication to {peer}: {err}");
+						}
+					}
+					Err(err) => {
+						tracing::error!("db lock poisoned while recording replication: {err}")
+					}
+				}
+			}
+			runs.end(&peer);
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::db::{forget_node_index, purge_tombstones};
+	use crate::test_util;
+	use rusqlite::params;
+	use std::collections::BTreeSet;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	fn open() -> Connection {
+		test_util::memory_db()
+	}
+
+	fn add_file(conn: &Connection, node_id: &NodeID, path: &str, content: &str) {
+		let hash = blake3::hash(content.as_bytes()).as_bytes().to_vec();
+		conn.execute(
+			"INSERT INTO file_entries (hash, size, mime_type, first_datetime, latest_datetime)
+			VALUES (?1, ?2, 'text/plain', '2024-05-01 10:00:00+00:00', '2024-05-01 10:00:00+00:00')
+			ON CONFLICT(hash) DO NOTHING",
+			params![hash, content.len() as i64],
+		)
+		.unwrap();
+		conn.execute(
+			"INSERT INTO file_locations (node_id, path, hash, size, timestamp, introduced_at)
+			VALUES (?1, ?2, ?3, ?4, '2024-05-01 10:00:00+00:00', 1714557600)",
+			params![node_id.as_slice(), path, hash, content.len() as i64],
+		)
+		.unwrap();
+	}
+
+	fn paths(conn: &Connection, node_id: &NodeID) -> BTreeSet<String> {
+		let mut stmt = conn
+			.prepare("SELECT path FROM file_locations WHERE node_id = ?1")
+			.unwrap();
+		stmt.query_map([node_id.as_slice()], |row| row.get(0))
+			.unwrap()
+			.map(Result::unwrap)
+			.collect()
+	}
+
+	fn entries_without_location(conn: &Connection) -> u64 {
+		conn.query_row(
+			"SELECT COUNT(*) FROM file_entries fe
+			WHERE NOT EXISTS (SELECT 1 FROM file_locations fl WHERE fl.hash = fe.hash)",
+			[],
+			|row| row.get(0),
+		)
+		.unwrap()
+	}
+
+	/// A replica applying deltas to its own database. After `drop_ack_of`
+	/// deltas it applies one more and then loses the connection before the
+	/// ack gets back.
+	struct FakeReplica {
+		source: PeerId,
+		conn: Mutex<Connection>,
+		sent: Mutex<Vec<(u64, u64)>>,
+		drop_ack_of: Option<usize>,
+		calls: AtomicUsize,
+	}
+
+	impl FakeReplica {
+		fn new(source: PeerId, conn: Connection, drop_ack_of: Option<usize>) -> Self {
+			Self {
+				source,
+				conn: Mutex::new(conn),
+				sent: Mutex::new(Vec::new()),
+				drop_ack_of,
+				calls: AtomicUsize::new(0),
+			}
+		}
+	}
+
+	#[async_trait]
+	impl ReplicaLink for FakeReplica {
+		async fn local_node(&self) -> Option<NodeID> {
+			peer_to_node_id(&self.source)
+		}
+
+		async fn is_connected(&self, _peer: PeerId) -> bool {
+			true
+		}
+
+		async fn is_owner(&self, _peer: PeerId) -> bool {
+			true
+		}
+
+		async fn send_delta(&self, _peer: PeerId, delta: IndexDelta) -> Result<IndexDeltaAck> {
+			self.sent.lock().unwrap().push((delta.after, delta.through));
+			let ack = receive_delta(&self.conn.lock().unwrap(), &self.source, &delta, Utc::now())?;
+			if Some(self.calls.fetch_add(1, Ordering::SeqCst)) == self.drop_ack_of {
+				bail!("connection lost");
+			}
+			Ok(ack)
+		}
+	}
+
+	fn start_replication(conn: &Connection, replica: &PeerId) {
+		save_replication(
+			conn,
+			&ReplicationStatus {
+				peer: replica.to_string(),
+				role: ReplicationRole::Source,
+				generation: String::new(),
+				acked_seq: 0,
+				behind: 0,
+				last_sync_at: None,
+				last_error: None,
+				syncing: false,
+			},
+		)
+		.unwrap();
+	}
+
+	#[tokio::test]
+	async fn an_interrupted_replication_resumes_from_the_last_ack() {
+		let (source_peer, replica_peer) = (PeerId::random(), PeerId::random());
+		let source_node = peer_to_node_id(&source_peer).unwrap();
+		let replica_node = peer_to_node_id(&replica_peer).unwrap();
+		let source = open();
+		for i in 0..700 {
+			add_file(
+				&source,
+				&source_node,
+				&format!("/srv/docs/{i}.txt"),
+				&format!("doc {i}"),
+			);
+		}
+		start_replication(&source, &replica_peer);
+		let replica = open();
+		add_file(&replica, &replica_node, "/home/me/own.txt", "mine");
+		let source = Db::single(source);
+
+		// The second delta is applied, but its ack never arrives.
+		let link = FakeReplica::new(source_peer, replica, Some(1));
+		let err = replicate_index(&link, &source, replica_peer, &source_node)
+			.await
+			.unwrap_err();
+		assert!(err.to_string().contains("connection lost"));
+		let first = link.sent.lock().unwrap()[0];
+		let state = load_replication(
+			&source.lock().unwrap(),
+			&replica_peer,
+			ReplicationRole::Source,
+		)
+		.unwrap()
+		.unwrap();
+		assert_eq!(state.acked_seq, first.1);
+		assert!(state.behind > 0);
+
+		let link = FakeReplica {
+			drop_ack_of: None,
+			sent: Mutex::new(Vec::new()),
+			..link
+		};
+		replicate_index(&link, &source, replica_peer, &source_node)
+			.await
+			.unwrap();
+		let sent = link.sent.lock().unwrap().clone();
+		// The delta whose ack was lost is offered again and refused, and the
+		// run continues from what the replica holds, never from zero.
+		assert_eq!(sent[0].0, first.1);
+		assert!(sent[1].0 > first.1);
+		assert!(sent.iter().all(|(after, _)| *after >= first.1));
+
+		let source = source.lock().unwrap();
+		let replica = link.conn.lock().unwrap();
+		assert_eq!(paths(&replica, &source_node), paths(&source, &source_node));
+		assert_eq!(paths(&replica, &source_node).len(), 700);
+		assert_eq!(entries_without_location(&replica), 0);
+		assert_eq!(
+			paths(&replica, &replica_node),
+			BTreeSet::from([String::from("/home/me/own.txt")])
+		);
+		let state = load_replication(&source, &replica_peer, ReplicationRole::Source)
+			.unwrap()
+			.unwrap();
+		assert_eq!(state.behind, 0);
+		assert_eq!(state.describe(), "replica up to date");
+		let held = load_replication(&replica, &source_peer, ReplicationRole::Replica)
+			.unwrap()
+			.unwrap();
+		assert_eq!(held.acked_seq, state.acked_seq);
+	}
+
+	#[tokio::test]
+	async fn a_replica_that_missed_a_purged_deletion_starts_over() {
+		let (source_peer, replica_peer) = (PeerId::random(), PeerId::random());
+		let source_node = peer_to_node_id(&source_peer).unwrap();
+		let source = open();
+		source
+			.execute(
+				"INSERT INTO nodes (id, name, you, total_memory, system_name, kernel_version,
+					os_version, created_at, modified_at, accessed_at)
+				VALUES (?1, 'source', 1, 0, '', '', '', 0, 0, 0)",
+				[source_node.as_slice()],
+			)
+			.unwrap();
+		for name in ["kept", "gone"] {
+			add_file(&source, &source_node, &format!("/srv/{name}.txt"), name);
+		}
+		start_replication(&source, &replica_peer);
+		let source = Db::single(source);
+		let link = FakeReplica::new(source_peer, open(), None);
+		replicate_index(&link, &source, replica_peer, &source_node)
+			.await
+			.unwrap();
+		let old_generation = index_generation(&source.lock().unwrap()).unwrap();
+
+		// Deleted and purged before the replica heard of the deletion.
+		{
+			let conn = source.lock().unwrap();
+			conn.execute(
+				"UPDATE file_locations SET deleted_at = 1 WHERE path = '/srv/gone.txt'",
+				[],
+			)
+			.unwrap();
+			assert_eq!(purge_tombstones(&conn, Utc::now()).unwrap(), 1);
+		}
+		replicate_index(&link, &source, replica_peer, &source_node)
+			.await
+			.unwrap();
+
+		let source = source.lock().unwrap();
+		let replica = link.conn.lock().unwrap();
+		assert_eq!(
+			paths(&replica, &source_node),
+			BTreeSet::from([String::from("/srv/kept.txt")])
+		);
+		assert_ne!(index_generation(&source).unwrap(), old_generation);
+		assert_eq!(index_purged_through(&source).unwrap(), 0);
+	}
+
+	#[tokio::test]
+	async fn a_new_generation_replaces_the_replica() {
+		let (source_peer, replica_peer) = (PeerId::random(), PeerId::random());
+		let source_node = peer_to_node_id(&source_peer).unwrap();
+		let replica_node = peer_to_node_id(&replica_peer).unwrap();
+		let source = open();
+		for i in 0..20 {
+			add_file(
+				&source,
+				&source_node,
+				&format!("/srv/old/{i}.txt"),
+				&format!("old {i}"),
+			);
+		}
+		start_replication(&source, &replica_peer);
+		let replica = open();
+		add_file(&replica, &replica_node, "/home/me/own.txt", "mine");
+		let source = Db::single(source);
+		let link = FakeReplica::new(source_peer, replica, None);
+		replicate_index(&link, &source, replica_peer, &source_node)
+			.await
+			.unwrap();
+		let old_generation = index_generation(&source.lock().unwrap()).unwrap();
+
+		// A rescan from scratch: the old rows go without a change the
+		// replica could see, and the new ones start a new generation.
+		{
+			let conn = source.lock().unwrap();
+			forget_node_index(&conn, &source_node).unwrap();
+			bump_index_generation(&conn).unwrap();
+			for i in 0..5 {
+				add_file(
+					&conn,
+					&source_node,
+					&format!("/srv/new/{i}.txt"),
+					&format!("new {i}"),
+				);
+			}
+		}
+		link.sent.lock().unwrap().clear();
+		replicate_index(&link, &source, replica_peer, &source_node)
+			.await
+			.unwrap();
+		assert_eq!(link.sent.lock().unwrap()[0].0, 0);
+
+		let source = source.lock().unwrap();
+		let replica = link.conn.lock().unwrap();
+		assert_eq!(paths(&replica, &source_node), paths(&source, &source_node));
+		assert_eq!(paths(&replica, &source_node).len(), 5);
+		assert_eq!(entries_without_location(&replica), 0);
+		assert_eq!(paths(&replica, &replica_node).len(), 1);
+		let held = load_replication(&replica, &source_peer, ReplicationRole::Replica)
+			.unwrap()
+			.unwrap();
+		assert_ne!(held.generation, old_generation);
+		assert_eq!(held.generation, index_generation(&source).unwrap());
+
+		// A delta of the old generation no longer fits.
+		let stale = IndexDelta {
+			generation: old_generation,
+			after: held.acked_seq,
+			through: held.acked_seq + 1,
+			entries: Vec::new(),
+			locations: Vec::new(),
+			remaining: 0,
+		};
+		let ack = receive_delta(&replica, &source_peer, &stale, Utc::now()).unwrap();
+		assert_eq!(ack.generation, held.generation);
+		assert_eq!(ack.acked, held.acked_seq);
+	}
+
+	#[test]
+	fn lag_reads_with_grouped_digits() {
+		let status = ReplicationStatus {
+			peer: PeerId::random().to_string(),
+			role: ReplicationRole::Source,
+			generation: String::from("g"),
+			acked_seq: 10,
+			behind: 1204,
+			last_sync_at: None,
+			last_error: None,
+			syncing: false,
+		};
+		assert_eq!(status.describe(), "replica 1,204 changes behind");
+	}
+}
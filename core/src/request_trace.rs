@@ -0,0 +1,193 @@
+//! Correlation ids and timings of peer requests. Every request gets an id
+//! that both sides log it under; timings are kept in a small ring buffer
+//! for finding the slow ones. Recording only runs for a while after the
+//! buffer was last read, so an idle node pays a couple of atomic ops per
+//! request and never takes the lock.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Requests kept in the ring buffer.
+pub const REQUEST_TRACE_CAPACITY: usize = 512;
+/// How long recording stays on after the buffer was read.
+const RECORDING_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestDirection {
+	/// Sent by this node.
+	Outbound,
+	/// Handled by this node.
+	Inbound,
+}
+
+/// One finished request.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestTrace {
+	pub corr: u64,
+	pub direction: RequestDirection,
+	pub peer: String,
+	/// Variant name, e.g. `ListDir`.
+	pub request: String,
+	pub at: DateTime<Utc>,
+	/// Inbound: from arrival until the handler started.
+	pub queue_ms: Option<u64>,
+	/// Inbound: time spent in the handler.
+	pub handler_ms: Option<u64>,
+	/// Outbound: from sending until the answer or failure arrived.
+	pub round_trip_ms: Option<u64>,
+	pub error: Option<String>,
+}
+
+impl RequestTrace {
+	/// Wall time the request took on this node.
+	pub fn total_ms(&self) -> u64 {
+		self.round_trip_ms
+			.unwrap_or_else(|| self.queue_ms.unwrap_or(0) + self.handler_ms.unwrap_or(0))
+	}
+}
+
+pub(crate) fn millis(duration: Duration) -> u64 {
+	duration.as_millis().min(u128::from(u64::MAX)) as u64
+}
+
+pub(crate) struct RequestLog {
+	next_corr: AtomicU64,
+	/// Set while someone read the buffer within [`RECORDING_WINDOW`].
+	recording: AtomicBool,
+	read_at: Mutex<Option<Instant>>,
+	traces: Mutex<VecDeque<RequestTrace>>,
+}
+
+impl Default for RequestLog {
+	fn default() -> Self {
+		Self {
+			next_corr: AtomicU64::new(1),
+			recording: AtomicBool::new(false),
+			read_at: Mutex::new(None),
+			traces: Mutex::new(VecDeque::new()),
+		}
+	}
+}
+
+impl RequestLog {
+	pub(crate) fn next_corr(&self) -> u64 {
+		self.next_corr.fetch_add(1, Ordering::Relaxed)
+	}
+
+	/// Start time for a request to be recorded, `None` while nobody reads
+	/// the buffer.
+	pub(crate) fn start(&self) -> Option<Instant> {
+		self.recording.load(Ordering::Relaxed).then(Instant::now)
+	}
+
+	pub(crate) fn record(&self, trace: RequestTrace) {
+		if !self.recording.load(Ordering::Relaxed) {
+			return;
+		}
+		let expired = self
+			.read_at
+			.lock()
+			.unwrap()
+			.is_none_or(|read_at| read_at.elapsed() > RECORDING_WINDOW);
+		if expired {
+			self.recording.store(false, Ordering::Relaxed);
+			return;
+		}
+		let mut traces = self.traces.lock().unwrap();
+		if traces.len() == REQUEST_TRACE_CAPACITY {
+			traces.pop_front();
+		}
+		traces.push_back(trace);
+	}
+
+	/// Recorded requests, newest first. Reading turns recording on for
+	/// the next [`RECORDING_WINDOW`].
+	pub(crate) fn recent(&self) -> Vec<RequestTrace> {
+		*self.read_at.lock().unwrap() = Some(Instant::now());
+		self.recording.store(true, Ordering::Relaxed);
+		self.traces.lock().unwrap().iter().rev().cloned().collect()
+	}
+}
+
+/// `traces` slowest first, at most `limit` of them.
+pub fn slowest(mut traces: Vec<RequestTrace>, limit: usize) -> Vec<RequestTrace> {
+	traces.sort_by_key(|trace| std::cmp::Reverse(trace.total_ms()));
+	traces.truncate(limit);
+	traces
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn trace(corr: u64, round_trip_ms: u64) -> RequestTrace {
+		RequestTrace {
+			corr,
+			direction: RequestDirection::Outbound,
+			peer: String::from("peer"),
+			request: String::from("ListDir"),
+			at: Utc::now(),
+			queue_ms: None,
+			handler_ms: None,
+			round_trip_ms: Some(round_trip_ms),
+			error: None,
+		}
+	}
+
+	#[test]
+	fn records_only_after_a_read_and_keeps_the_newest() {
+		let log = RequestLog::default();
+		assert_eq!(log.start(), None);
+		log.record(trace(log.next_corr(), 5));
+		assert!(log.recent().is_empty());
+
+		assert!(log.start().is_some());
+		for _ in 0..REQUEST_TRACE_CAPACITY + 3 {
+			log.record(trace(log.next_corr(), 5));
+		}
+		let recent = log.recent();
+		assert_eq!(recent.len(), REQUEST_TRACE_CAPACITY);
+		assert_eq!(recent[0].corr, REQUEST_TRACE_CAPACITY as u64 + 4);
+	}
+
+	#[test]
+	fn slowest_sorts_by_total_time() {
+		let inbound = RequestTrace {
+			direction: RequestDirection::Inbound,
+			queue_ms: Some(3),
+			handler_ms: Some(40),
+			round_trip_ms: None,
+			..trace(3, 0)
+		};
+		let traces = vec![trace(1, 10), trace(2, 90), inbound];
+		let corrs = slowest(traces, 2)
+			.iter()
+			.map(|trace| trace.corr)
+			.collect::<Vec<_>>();
+		assert_eq!(corrs, vec![2, 3]);
+	}
+
+	/// Idle cost of tracing one request: a correlation id plus the checks
+	/// that find recording off. Run with `--ignored --nocapture`.
+	#[test]
+	#[ignore = "benchmark"]
+	fn idle_overhead_per_request() {
+		let log = RequestLog::default();
+		let rounds = 10_000_000u64;
+		let started = Instant::now();
+		for _ in 0..rounds {
+			let corr = std::hint::black_box(log.next_corr());
+			if let Some(start) = log.start() {
+				log.record(trace(corr, millis(start.elapsed())));
+			}
+		}
+		let per_request = started.elapsed().as_nanos() / u128::from(rounds);
+		println!("idle request tracing: {per_request} ns per request");
+		assert!(per_request < 100, "{per_request} ns per request");
+	}
+}
@@ -0,0 +1,65 @@
+//! Review queue for files written by peers through a quarantined folder
+//! grant ([`crate::FLAG_QUARANTINE`]). The write lands as usual and leaves
+//! a row in the `pending_reviews` table until the owner decides: accepting
+//! clears the row, rejecting removes the file and keeps the row as
+//! `rejected` until the peer touches the path again and is told so.
+//! Writes by this node or by owners never queue.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewDecision {
+	Accept,
+	Reject,
+}
+
+/// A file written by a peer that waits for a decision.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PendingReview {
+	pub id: i64,
+	pub peer: String,
+	pub path: String,
+	/// Blake3 hash of the content, computed when the queue is read since
+	/// the peer may still be writing. `None` until then.
+	#[serde(skip)]
+	pub hash: Option<Vec<u8>>,
+	pub size: u64,
+	/// Last write to the file; later writes update the same entry.
+	pub received_at: DateTime<Utc>,
+}
+
+/// How the owner decided on a peer's reviewed writes so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct PeerTrust {
+	pub accepted: u64,
+	pub rejected: u64,
+}
+
+impl PeerTrust {
+	pub fn describe(&self) -> String {
+		if self.accepted == 0 && self.rejected == 0 {
+			return String::from("No reviewed writes yet");
+		}
+		format!("{} accepted, {} rejected", self.accepted, self.rejected)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn trust_reads_as_counts() {
+		assert_eq!(PeerTrust::default().describe(), "No reviewed writes yet");
+		assert_eq!(
+			PeerTrust {
+				accepted: 4,
+				rejected: 1,
+			}
+			.describe(),
+			"4 accepted, 1 rejected"
+		);
+	}
+}
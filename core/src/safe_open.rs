@@ -0,0 +1,402 @@
+//! Opening the paths peers name so the permission check is about what was
+//! opened. Canonicalizing a path, checking it and then opening it again
+//! leaves a gap in which a folder on the way can be swapped for a symlink,
+//! by a local user or a rename racing a remote write, and the open lands
+//! somewhere the check never approved. Here the path is opened first and
+//! the canonical path to check is read back from the handle. Reads, writes
+//! and listings then go through the handle and never resolve the path
+//! again. Symlinks are still followed: where one leads is what is checked.
+//!
+//! On Linux the handle's path comes from `/proc/self/fd`, and folders are
+//! listed and files created in them through that link, which closes the
+//! gap. Elsewhere the path is canonicalized again right after opening and
+//! has to lead to the file the handle holds: the same device and inode on
+//! other Unixes. std has no stable file id on Windows, so there the file
+//! found only has to match the handle's kind, size and modification time,
+//! and a swap to a look-alike file between the open and the check passes.
+
+use std::ffi::OsString;
+use std::fs::{File, Metadata, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// `FILE_FLAG_BACKUP_SEMANTICS`, without which Windows opens no folder.
+#[cfg(windows)]
+const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+
+/// A file or folder opened for a peer, with the canonical path it was
+/// found at.
+#[derive(Debug)]
+pub(crate) struct OpenedPath {
+	file: File,
+	canonical: PathBuf,
+	/// Reaches the handle itself, where the platform has such a path.
+	through_handle: Option<PathBuf>,
+}
+
+impl OpenedPath {
+	fn new(file: File, requested: &Path) -> io::Result<Self> {
+		let through_handle = handle_link(&file);
+		let canonical = match through_handle
+			.as_deref()
+			.and_then(|link| std::fs::read_link(link).ok())
+		{
+			Some(path) if path.is_absolute() => path,
+			_ => std::fs::canonicalize(requested)?,
+		};
+		if !same_file(&file.metadata()?, &std::fs::metadata(&canonical)?) {
+			return Err(moved(requested));
+		}
+		Ok(Self {
+			file,
+			canonical,
+			through_handle,
+		})
+	}
+
+	/// Path the permission check is made against.
+	pub(crate) fn canonical(&self) -> &Path {
+		&self.canonical
+	}
+
+	pub(crate) fn metadata(&self) -> io::Result<Metadata> {
+		self.file.metadata()
+	}
+
+	/// A path to the opened folder that goes through its handle, or its
+	/// canonical path where the platform has none.
+	pub(crate) fn through_handle(&self) -> &Path {
+		self.through_handle.as_deref().unwrap_or(&self.canonical)
+	}
+
+	/// Fails unless the canonical path still leads to the opened file, for
+	/// work that has to go by path after all.
+	pub(crate) fn verify(&self) -> io::Result<()> {
+		if same_file(&self.file.metadata()?, &std::fs::metadata(&self.canonical)?) {
+			Ok(())
+		} else {
+			Err(moved(&self.canonical))
+		}
+	}
+
+	/// Everything in a file opened with [`open_read`].
+	pub(crate) async fn read_all(&self) -> io::Result<Vec<u8>> {
+		let mut file = self.file.try_clone()?;
+		blocking(move || {
+			file.seek(SeekFrom::Start(0))?;
+			let mut data = Vec::new();
+			file.read_to_end(&mut data)?;
+			Ok(data)
+		})
+		.await
+	}
+
+	pub(crate) fn into_file(self) -> File {
+		self.file
+	}
+}
+
+/// Where a peer's write goes: the file already there, or the folder a new
+/// file is created in once the write is allowed.
+#[derive(Debug)]
+pub(crate) enum WriteTarget {
+	Existing(OpenedPath),
+	New { dir: OpenedPath, name: OsString },
+}
+
+impl WriteTarget {
+	pub(crate) async fn open(path: &Path) -> io::Result<Self> {
+		let path = path.to_path_buf();
+		blocking(move || Self::open_blocking(&path)).await
+	}
+
+	fn open_blocking(path: &Path) -> io::Result<Self> {
+		match open_with(content_options().read(true).write(true), path) {
+			Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+			opened => return opened.map(Self::Existing),
+		}
+		let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"invalid file name",
+			));
+		};
+		Ok(Self::New {
+			dir: open_with(&mut look_options(), parent)?,
+			name: name.to_os_string(),
+		})
+	}
+
+	/// Canonical path the write lands at.
+	pub(crate) fn canonical(&self) -> PathBuf {
+		match self {
+			Self::Existing(file) => file.canonical.clone(),
+			Self::New { dir, name } => dir.canonical.join(name),
+		}
+	}
+
+	/// The file to write, created now when new. It has to turn up in the
+	/// folder that was checked, or nothing is written to it.
+	pub(crate) async fn into_file(self) -> io::Result<File> {
+		blocking(move || self.into_file_blocking()).await
+	}
+
+	fn into_file_blocking(self) -> io::Result<File> {
+		match self {
+			Self::Existing(opened) => Ok(opened.file),
+			Self::New { dir, name } => {
+				let created = open_with(
+					content_options().read(true).write(true).create_new(true),
+					&dir.through_handle().join(&name),
+				)?;
+				if created.canonical != dir.canonical.join(&name) {
+					return Err(moved(&dir.canonical.join(&name)));
+				}
+				Ok(created.file)
+			}
+		}
+	}
+}
+
+/// Opens `path` to look at or list it.
+pub(crate) async fn open(path: &Path) -> io::Result<OpenedPath> {
+	let path = path.to_path_buf();
+	blocking(move || open_with(&mut look_options(), &path)).await
+}
+
+/// Opens `path` to read it.
+pub(crate) async fn open_read(path: &Path) -> io::Result<OpenedPath> {
+	let path = path.to_path_buf();
+	blocking(move || open_read_blocking(&path)).await
+}
+
+/// [`open_read`] for callers already off the async runtime.
+pub(crate) fn open_read_blocking(path: &Path) -> io::Result<OpenedPath> {
+	open_with(content_options().read(true), path)
+}
+
+fn open_with(options: &mut OpenOptions, path: &Path) -> io::Result<OpenedPath> {
+	refuse_pipe(path)?;
+	OpenedPath::new(options.open(path)?, path)
+}
+
+/// Options that open a file or a folder without reading it: `O_PATH` on
+/// Linux, which needs no read permission and doesn't wait on a pipe.
+fn look_options() -> OpenOptions {
+	let mut options = OpenOptions::new();
+	options.read(true);
+	#[cfg(target_os = "linux")]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.custom_flags(libc::O_PATH);
+	}
+	#[cfg(windows)]
+	{
+		use std::os::windows::fs::OpenOptionsExt;
+		options.custom_flags(FILE_FLAG_BACKUP_SEMANTICS);
+	}
+	options
+}
+
+/// Options for reading or writing contents, which on Linux don't wait on
+/// a pipe nobody writes to.
+fn content_options() -> OpenOptions {
+	#[allow(unused_mut)]
+	let mut options = OpenOptions::new();
+	#[cfg(target_os = "linux")]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.custom_flags(libc::O_NONBLOCK);
+	}
+	options
+}
+
+/// Opening a pipe waits for its other end; without `O_PATH` or
+/// `O_NONBLOCK` at hand one is refused by its type instead.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn refuse_pipe(path: &Path) -> io::Result<()> {
+	use std::os::unix::fs::FileTypeExt;
+	match std::fs::metadata(path) {
+		Ok(metadata) if metadata.file_type().is_fifo() => Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"path is a pipe",
+		)),
+		_ => Ok(()),
+	}
+}
+
+#[cfg(not(all(unix, not(target_os = "linux"))))]
+fn refuse_pipe(_path: &Path) -> io::Result<()> {
+	Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn handle_link(file: &File) -> Option<PathBuf> {
+	use std::os::fd::AsRawFd;
+	let link = PathBuf::from(format!("/proc/self/fd/{}", file.as_raw_fd()));
+	link.exists().then_some(link)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn handle_link(_file: &File) -> Option<PathBuf> {
+	None
+}
+
+#[cfg(unix)]
+fn same_file(opened: &Metadata, found: &Metadata) -> bool {
+	use std::os::unix::fs::MetadataExt;
+	opened.dev() == found.dev() && opened.ino() == found.ino()
+}
+
+#[cfg(not(unix))]
+fn same_file(opened: &Metadata, found: &Metadata) -> bool {
+	opened.file_type() == found.file_type()
+		&& opened.len() == found.len()
+		&& opened.modified().ok() == found.modified().ok()
+}
+
+fn moved(path: &Path) -> io::Error {
+	io::Error::other(format!("{} changed while it was opened", path.display()))
+}
+
+async fn blocking<T: Send + 'static>(
+	work: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> io::Result<T> {
+	tokio::task::spawn_blocking(work)
+		.await
+		.unwrap_or_else(|err| Err(io::Error::other(err)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util;
+	use std::io::Write;
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicBool, Ordering};
+
+	fn test_dir(name: &str) -> PathBuf {
+		std::fs::canonicalize(test_util::test_dir(name)).unwrap()
+	}
+
+	/// `share/real` and `outside`, each holding `data.txt`, with
+	/// `share/link` pointing at `share/real`.
+	#[cfg(unix)]
+	fn share_with_link(name: &str) -> (PathBuf, PathBuf, PathBuf) {
+		let base = test_dir(name);
+		let share = base.join("share");
+		let outside = base.join("outside");
+		std::fs::create_dir_all(share.join("real")).unwrap();
+		std::fs::create_dir_all(&outside).unwrap();
+		std::fs::write(share.join("real/data.txt"), "shared").unwrap();
+		std::fs::write(outside.join("data.txt"), "secret").unwrap();
+		std::os::unix::fs::symlink(share.join("real"), share.join("link")).unwrap();
+		(base, share, outside)
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn a_symlink_swapped_during_reads_never_leaks_what_is_outside_the_share() {
+		let (base, share, outside) = share_with_link("open-race");
+		let stop = Arc::new(AtomicBool::new(false));
+		let swapper = std::thread::spawn({
+			let (share, stop) = (share.clone(), Arc::clone(&stop));
+			move || {
+				let targets = [share.join("real"), outside];
+				let mut i = 0;
+				while !stop.load(Ordering::Relaxed) {
+					let next = share.join("link.next");
+					let _ = std::fs::remove_file(&next);
+					std::os::unix::fs::symlink(&targets[i % 2], &next).unwrap();
+					std::fs::rename(&next, share.join("link")).unwrap();
+					i += 1;
+				}
+			}
+		});
+
+		let mut allowed = 0;
+		for _ in 0..2000 {
+			let Ok(opened) = open_read(&share.join("link/data.txt")).await else {
+				continue;
+			};
+			if !opened.canonical().starts_with(&share) {
+				continue;
+			}
+			allowed += 1;
+			assert_eq!(opened.read_all().await.unwrap(), b"shared");
+		}
+		stop.store(true, Ordering::Relaxed);
+		swapper.join().unwrap();
+
+		assert!(allowed > 0);
+		let _ = std::fs::remove_dir_all(&base);
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn a_swap_after_the_check_changes_nothing() {
+		let (base, share, outside) = share_with_link("open-swap");
+		let read = open_read(&share.join("link/data.txt")).await.unwrap();
+		assert_eq!(read.canonical(), share.join("real/data.txt"));
+		let target = WriteTarget::open(&share.join("link/new.txt"))
+			.await
+			.unwrap();
+		assert_eq!(target.canonical(), share.join("real/new.txt"));
+
+		std::fs::remove_file(share.join("link")).unwrap();
+		std::os::unix::fs::symlink(&outside, share.join("link")).unwrap();
+		assert_eq!(read.read_all().await.unwrap(), b"shared");
+		target
+			.into_file()
+			.await
+			.unwrap()
+			.write_all(b"written")
+			.unwrap();
+
+		assert_eq!(
+			std::fs::read(share.join("real/new.txt")).unwrap(),
+			b"written"
+		);
+		assert!(!outside.join("new.txt").exists());
+		let _ = std::fs::remove_dir_all(&base);
+	}
+
+	#[tokio::test]
+	async fn files_and_folders_open_as_before() {
+		let dir = test_dir("open-plain");
+		std::fs::create_dir_all(dir.join("photos")).unwrap();
+		std::fs::write(dir.join("photos/a.txt"), "alpha").unwrap();
+
+		let folder = open(&dir.join("photos")).await.unwrap();
+		assert_eq!(folder.canonical(), dir.join("photos"));
+		assert!(folder.metadata().unwrap().is_dir());
+		let names = std::fs::read_dir(folder.through_handle())
+			.unwrap()
+			.map(|entry| entry.unwrap().file_name())
+			.collect::<Vec<_>>();
+		assert_eq!(names, ["a.txt"]);
+		folder.verify().unwrap();
+
+		let file = open_read(&dir.join("photos/../photos/a.txt"))
+			.await
+			.unwrap();
+		assert_eq!(file.canonical(), dir.join("photos/a.txt"));
+		assert_eq!(file.read_all().await.unwrap(), b"alpha");
+
+		let existing = WriteTarget::open(&dir.join("photos/a.txt")).await.unwrap();
+		assert!(matches!(existing, WriteTarget::Existing(_)));
+		let created = WriteTarget::open(&dir.join("photos/b.txt")).await.unwrap();
+		assert_eq!(created.canonical(), dir.join("photos/b.txt"));
+		created.into_file().await.unwrap();
+		assert!(dir.join("photos/b.txt").exists());
+		assert!(WriteTarget::open(&dir.join("missing/c.txt")).await.is_err());
+
+		#[cfg(unix)]
+		{
+			std::fs::rename(dir.join("photos"), dir.join("moved")).unwrap();
+			std::fs::create_dir_all(dir.join("photos")).unwrap();
+			assert!(folder.verify().is_err());
+		}
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+}
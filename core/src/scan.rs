@@ -1,16 +1,28 @@
+use crate::checksum_manifest::{
+	ChecksumSummary, MANIFEST_PATTERNS_SETTING, check_claims, is_manifest, patterns_from_setting,
+};
+use crate::config;
+use crate::content_negotiation::{BLOCK_SIZE, BlockIndexing};
+use crate::db::{load_setting, path_column, path_to_sql};
+use crate::mounts::MountTable;
+use crate::safe_open;
+use crate::scan_limits::{ScanLimits, ScanThrottle};
+use crate::throughput::RateEstimator;
 use chrono::{DateTime, Utc};
-#[cfg(feature = "rayon")]
-use rayon::prelude::*;
 use rusqlite::{Connection, ToSql};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::canonicalize;
 use std::io::{self, Read, Seek};
 use std::path::{Path, PathBuf};
-#[cfg(feature = "rayon")]
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc;
+use std::time::{Duration, Instant};
 use walkdir::WalkDir;
 
+/// BLAKE3 digest of a file's contents. Every content hash in the index, in
+/// block lists and on the wire is one; checksum manifests in other
+/// algorithms are checked by hashing the file again with theirs.
 pub type FileHash = [u8; 32];
 
 #[derive(Debug, Default, Serialize)]
@@ -23,6 +35,10 @@ pub struct FileLocation {
 	pub created_at: Option<DateTime<Utc>>,
 	pub modified_at: Option<DateTime<Utc>>,
 	pub accessed_at: Option<DateTime<Utc>>,
+	/// Hashes of each [`BLOCK_SIZE`] block, for files large enough to have
+	/// them stored. Empty otherwise.
+	#[serde(skip)]
+	pub blocks: Vec<FileHash>,
 }
 
 impl PartialEq for FileLocation {
@@ -37,8 +53,20 @@ impl PartialEq for FileLocation {
 	}
 }
 
-fn hash_file<R: Read>(mut reader: R) -> io::Result<[u8; 32]> {
+pub(crate) fn hash_file<R: Read>(reader: R) -> io::Result<[u8; 32]> {
+	hash_file_blocks(reader, false).map(|(hash, _)| hash)
+}
+
+/// The hash of `reader`'s data and, with `blocks`, the hash of each
+/// [`BLOCK_SIZE`] block of it, in one pass.
+pub(crate) fn hash_file_blocks<R: Read>(
+	mut reader: R,
+	blocks: bool,
+) -> io::Result<(FileHash, Vec<FileHash>)> {
 	let mut hasher = blake3::Hasher::new();
+	let mut block = blake3::Hasher::new();
+	let mut block_len = 0u64;
+	let mut block_hashes = Vec::new();
 	let mut buffer = [0u8; 8192];
 	loop {
 		let count = reader.read(&mut buffer)?;
@@ -46,20 +74,43 @@ fn hash_file<R: Read>(mut reader: R) -> io::Result<[u8; 32]> {
 			break;
 		}
 		hasher.update(&buffer[..count]);
+		if !blocks {
+			continue;
+		}
+		let mut rest = &buffer[..count];
+		while !rest.is_empty() {
+			let take = rest.len().min((BLOCK_SIZE - block_len) as usize);
+			block.update(&rest[..take]);
+			block_len += take as u64;
+			rest = &rest[take..];
+			if block_len == BLOCK_SIZE {
+				block_hashes.push(*block.finalize().as_bytes());
+				block.reset();
+				block_len = 0;
+			}
+		}
+	}
+	if block_len > 0 {
+		block_hashes.push(*block.finalize().as_bytes());
 	}
-	let mut hash = [0u8; 32];
-	hash.copy_from_slice(hasher.finalize().as_bytes());
-	Ok(hash)
+	Ok((*hasher.finalize().as_bytes(), block_hashes))
 }
 
 fn to_datetime(m: std::io::Result<std::time::SystemTime>) -> Option<chrono::DateTime<chrono::Utc>> {
 	m.ok().map(|t| chrono::DateTime::from(t))
 }
 
-fn handle_path<P: AsRef<Path>>(path: P) -> FileLocation {
-	let full_path = canonicalize(path.as_ref()).unwrap();
-	log::info!("processing {}", full_path.display());
-	let mut file = std::fs::File::open(path).unwrap();
+/// The file is opened before its canonical path is taken, so the path
+/// indexed is the one the hashed contents were read from.
+fn handle_path<P: AsRef<Path>>(
+	path: P,
+	blocks: &BlockIndexing,
+	throttle: &ScanThrottle,
+) -> FileLocation {
+	let opened = safe_open::open_read_blocking(path.as_ref()).unwrap();
+	let full_path = opened.canonical().to_path_buf();
+	tracing::info!("processing {}", full_path.display());
+	let mut file = opened.into_file();
 	let m = file.metadata().unwrap();
 	let created_at = to_datetime(m.created());
 	let modified_at = to_datetime(m.modified());
@@ -75,7 +126,7 @@ fn handle_path<P: AsRef<Path>>(path: P) -> FileLocation {
 		Err(_) => None,
 	};
 	file.seek(std::io::SeekFrom::Start(0)).unwrap();
-	let hash = hash_file(file).unwrap();
+	let (hash, blocks) = hash_file_blocks(throttle.reader(file), blocks.wants(m.len())).unwrap();
 	FileLocation {
 		path: full_path,
 		hash: Some(hash),
@@ -85,13 +136,56 @@ fn handle_path<P: AsRef<Path>>(path: P) -> FileLocation {
 		created_at,
 		modified_at,
 		accessed_at,
+		blocks,
 	}
 }
 
-const INSERT_FILE_LOCATION: &str = "INSERT INTO file_locations (node_id, path, hash, size, timestamp, created_at, modified_at, accessed_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?)";
+/// Also brings back a deleted file found at its old path again.
+const INSERT_FILE_LOCATION: &str = "INSERT INTO file_locations (node_id, path, hash, size, timestamp, created_at, modified_at, accessed_at, origin, introduced_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, 'local_scan', ?) ON CONFLICT(node_id, path) DO UPDATE SET hash = excluded.hash, size = excluded.size, timestamp = excluded.timestamp, created_at = excluded.created_at, modified_at = excluded.modified_at, accessed_at = excluded.accessed_at, deleted_at = NULL, deleted_by_run = NULL, origin = 'local_scan', origin_peer = NULL, origin_run = NULL, origin_transfer = NULL, origin_user = NULL, introduced_at = excluded.introduced_at";
 const UPDATE_FILE_LOCATION: &str = "UPDATE file_locations SET hash = ?, size = ?, timestamp = ?, created_at = ?, modified_at = ?, accessed_at = ? WHERE node_id = ? and path = ?";
-const DELETE_FILE_LOCATION: &str = "DELETE FROM file_locations WHERE node_id = ? and path = ?";
+/// Deleted files keep their row as a tombstone so they stay searchable.
+const DELETE_FILE_LOCATION: &str = "UPDATE file_locations SET deleted_at = ?, deleted_by_run = NULL WHERE node_id = ? and path = ?";
+const DELETE_CLAIMED_HASHES: &str = "DELETE FROM claimed_hashes WHERE node_id = ? and path = ?";
 const UPSERT_FILE_ENTRY: &str = "INSERT INTO file_entries (hash, size, mime_type, first_datetime, latest_datetime) VALUES (?, ?, ?, ?, ?) ON CONFLICT(hash) DO UPDATE SET latest_datetime = excluded.latest_datetime";
+const INSERT_FILE_BLOCK: &str =
+	"INSERT OR IGNORE INTO file_blocks (hash, block_index, block_hash) VALUES (?, ?, ?)";
+
+/// Days a deleted file stays in the index before it is purged, unless the
+/// node configures its own retention. 0 keeps deleted files forever.
+pub(crate) const DEFAULT_TOMBSTONE_RETENTION_DAYS: u32 = 365;
+pub(crate) const TOMBSTONE_RETENTION_SETTING: &str = "tombstone_retention_days";
+
+/// Files written per transaction while scanning.
+const SCAN_BATCH_FILES: usize = 500;
+/// Longest a hashed file waits before its batch is committed.
+const SCAN_BATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanChangeKind {
+	Added,
+	Changed,
+	Removed,
+}
+
+impl ScanChangeKind {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Added => "added",
+			Self::Changed => "changed",
+			Self::Removed => "removed",
+		}
+	}
+}
+
+/// A file that was added, rehashed to different content, or removed by a scan.
+#[derive(Debug, Clone)]
+pub struct ScanChange {
+	pub path: PathBuf,
+	pub kind: ScanChangeKind,
+	pub old_hash: Option<FileHash>,
+	pub new_hash: Option<FileHash>,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScanResult {
@@ -99,6 +193,19 @@ pub struct ScanResult {
 	pub inserted_count: u64,
 	pub removed_count: u64,
 	pub duration: std::time::Duration,
+	/// Files under the scanned path once the scan finished.
+	#[serde(default)]
+	pub file_count: u64,
+	/// Checks against the checksum manifests found under the scanned
+	/// path; `None` when there were none.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub checksums: Option<ChecksumSummary>,
+	/// Scan run the scanning node recorded, which a peer that asked for
+	/// the scan pulls the results of with `ScanResultsPull`.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub run: Option<i64>,
+	#[serde(skip)]
+	pub changes: Vec<ScanChange>,
 }
 
 const PROGRESS_REPORT_INTERVAL: usize = 25;
@@ -110,29 +217,70 @@ pub struct ScanProgress {
 	pub inserted_count: u64,
 	pub updated_count: u64,
 	pub removed_count: u64,
+	/// Bytes of files gone through per second over the last few seconds;
+	/// zero when the scan stalled.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub bytes_per_sec: Option<u64>,
+	/// Seconds left at the current rate of files.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub eta_secs: Option<u64>,
+	/// Limits the scan reads under, which follow the node's settings
+	/// while it runs.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub limits: Option<ScanLimits>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ScanEvent {
+	/// Held back until the activity window opens at `until` (`HH:MM`).
+	Deferred {
+		until: String,
+	},
 	Progress(ScanProgress),
 	Finished(Result<ScanResult, String>),
 }
 
-fn report_progress<F: FnMut(ScanProgress)>(
-	cb: &mut F,
+/// Turns a scan's counts into [`ScanProgress`], measuring the rate of
+/// bytes and of files as it goes.
+struct ScanMeter<'a> {
 	total_files: usize,
-	processed_files: usize,
-	inserted_count: u64,
-	updated_count: u64,
-	removed_count: u64,
-) {
-	cb(ScanProgress {
-		total_files,
-		processed_files,
-		inserted_count,
-		updated_count,
-		removed_count,
-	});
+	bytes: u64,
+	bytes_rate: RateEstimator,
+	files_rate: RateEstimator,
+	throttle: &'a ScanThrottle,
+}
+
+impl<'a> ScanMeter<'a> {
+	fn new(total_files: usize, throttle: &'a ScanThrottle) -> Self {
+		Self {
+			total_files,
+			bytes: 0,
+			bytes_rate: RateEstimator::new(),
+			files_rate: RateEstimator::new(),
+			throttle,
+		}
+	}
+
+	fn progress(
+		&mut self,
+		processed_files: usize,
+		inserted_count: u64,
+		updated_count: u64,
+		removed_count: u64,
+	) -> ScanProgress {
+		let now = Instant::now();
+		self.files_rate.record(now, processed_files as u64);
+		ScanProgress {
+			total_files: self.total_files,
+			processed_files,
+			inserted_count,
+			updated_count,
+			removed_count,
+			bytes_per_sec: self.bytes_rate.sample(now, self.bytes, None).bytes_per_sec,
+			eta_secs: self.files_rate.eta_secs(now, self.total_files as u64),
+			limits: Some(self.throttle.limits()),
+		}
+	}
 }
 
 fn should_emit_progress(processed: usize, total: usize) -> bool {
@@ -152,6 +300,38 @@ where
 	Ok(())
 }
 
+/// Days deleted files are kept, as configured under
+/// [`TOMBSTONE_RETENTION_SETTING`].
+pub(crate) fn tombstone_retention_days(conn: &Connection) -> u32 {
+	match load_setting(conn, TOMBSTONE_RETENTION_SETTING) {
+		Ok(Some(value)) => value.parse().unwrap_or_else(|err| {
+			tracing::warn!("ignoring invalid tombstone retention {value:?}: {err}");
+			config::startup().tombstone_retention_days
+		}),
+		Ok(None) => config::startup().tombstone_retention_days,
+		Err(err) => {
+			tracing::error!("failed to load tombstone retention: {err}");
+			config::startup().tombstone_retention_days
+		}
+	}
+}
+
+/// Writes `files` for `node_id` the way a scan that found them would,
+/// without looking at the file system, and returns the changes made.
+pub(crate) fn record_locations(
+	conn: &mut Connection,
+	node_id: &[u8],
+	files: Vec<FileLocation>,
+) -> Result<Vec<ScanChange>, String> {
+	let existing = HashMap::new();
+	let mut batch = ScanBatch::new(node_id, &existing);
+	for file in files {
+		batch.push(file.path.clone(), file);
+	}
+	batch.flush(conn)?;
+	Ok(batch.changes)
+}
+
 pub fn scan<P: AsRef<Path>>(
 	node_id: &[u8],
 	path: P,
@@ -173,10 +353,203 @@ where
 	scan_with_progress_cancelable(node_id, path, conn, progress, || false)
 }
 
+/// The location to store for the file at `pbuf`, reusing the previous
+/// hash when size and timestamps say the file is unchanged and no block
+/// hashes are missing for it.
+fn scanned_location(
+	pbuf: &Path,
+	existing: &HashMap<PathBuf, FileLocation>,
+	blocks: &BlockIndexing,
+	throttle: &ScanThrottle,
+) -> FileLocation {
+	let meta = std::fs::metadata(pbuf).unwrap();
+	let created_at = to_datetime(meta.created());
+	let modified_at = to_datetime(meta.modified());
+	let accessed_at = to_datetime(meta.accessed());
+	let size = meta.len();
+	match existing.get(pbuf) {
+		Some(prev)
+			if prev.size == size
+				&& prev.created_at == created_at
+				&& prev.modified_at == modified_at
+				&& prev.accessed_at == accessed_at
+				&& !blocks.missing(prev.hash.as_ref(), size) =>
+		{
+			FileLocation {
+				path: pbuf.to_path_buf(),
+				hash: prev.hash,
+				size,
+				mime_type: prev.mime_type.clone(),
+				timestamp: Utc::now(),
+				created_at,
+				modified_at,
+				accessed_at,
+				blocks: Vec::new(),
+			}
+		}
+		_ => handle_path(pbuf, blocks, throttle),
+	}
+}
+
+/// Scanned files waiting to be written. A flush writes each file's
+/// `file_entries` row and its location in one transaction, so a scan that
+/// dies between flushes leaves no location pointing at a missing entry,
+/// and the counts only move once the rows are committed.
+struct ScanBatch<'a> {
+	node_id: &'a [u8],
+	existing: &'a HashMap<PathBuf, FileLocation>,
+	pending: Vec<(PathBuf, FileLocation)>,
+	oldest: Option<Instant>,
+	inserted_count: u64,
+	updated_count: u64,
+	changes: Vec<ScanChange>,
+}
+
+impl<'a> ScanBatch<'a> {
+	fn new(node_id: &'a [u8], existing: &'a HashMap<PathBuf, FileLocation>) -> Self {
+		Self {
+			node_id,
+			existing,
+			pending: Vec::new(),
+			oldest: None,
+			inserted_count: 0,
+			updated_count: 0,
+			changes: Vec::new(),
+		}
+	}
+
+	fn push(&mut self, pbuf: PathBuf, fl: FileLocation) {
+		self.oldest.get_or_insert_with(Instant::now);
+		self.pending.push((pbuf, fl));
+	}
+
+	fn is_due(&self) -> bool {
+		self.pending.len() >= SCAN_BATCH_FILES
+			|| self
+				.oldest
+				.is_some_and(|oldest| oldest.elapsed() >= SCAN_BATCH_INTERVAL)
+	}
+
+	fn flush(&mut self, conn: &mut Connection) -> Result<(), String> {
+		if self.pending.is_empty() {
+			return Ok(());
+		}
+		let _span = tracing::debug_span!("scan_batch", files = self.pending.len()).entered();
+		let node_id = self.node_id;
+		let mut inserted = 0;
+		let mut updated = 0;
+		let mut changes = Vec::new();
+		let mut write = |conn: &mut Connection| -> rusqlite::Result<()> {
+			let tx = conn.transaction()?;
+			{
+				let mut upsert_stmt = tx.prepare_cached(UPSERT_FILE_ENTRY)?;
+				let mut insert_stmt = tx.prepare_cached(INSERT_FILE_LOCATION)?;
+				let mut update_stmt = tx.prepare_cached(UPDATE_FILE_LOCATION)?;
+				let mut block_stmt = tx.prepare_cached(INSERT_FILE_BLOCK)?;
+				for (path, fl) in &self.pending {
+					for (index, block_hash) in fl.blocks.iter().enumerate() {
+						block_stmt.execute(&[
+							&fl.hash as &dyn ToSql,
+							&(index as i64) as &dyn ToSql,
+							block_hash as &dyn ToSql,
+						])?;
+					}
+					let timestamps: Vec<_> = [fl.created_at, fl.modified_at, fl.accessed_at]
+						.iter()
+						.copied()
+						.flatten()
+						.collect();
+					let first_dt = timestamps.iter().min().copied();
+					let latest_dt = timestamps.iter().max().copied();
+					upsert_stmt.execute(&[
+						&fl.hash as &dyn ToSql,
+						&fl.size as &dyn ToSql,
+						&fl.mime_type as &dyn ToSql,
+						&first_dt as &dyn ToSql,
+						&latest_dt as &dyn ToSql,
+					])?;
+					if let Some(prev) = self.existing.get(path) {
+						if fl == prev {
+							continue;
+						}
+						update_stmt.execute(&[
+							&fl.hash as &dyn ToSql,
+							&fl.size as &dyn ToSql,
+							&fl.timestamp as &dyn ToSql,
+							&fl.created_at as &dyn ToSql,
+							&fl.modified_at as &dyn ToSql,
+							&fl.accessed_at as &dyn ToSql,
+							&node_id as &dyn ToSql,
+							&path_to_sql(&fl.path) as &dyn ToSql,
+						])?;
+						updated += 1;
+						if prev.hash != fl.hash {
+							changes.push(ScanChange {
+								path: path.clone(),
+								kind: ScanChangeKind::Changed,
+								old_hash: prev.hash,
+								new_hash: fl.hash,
+							});
+						}
+					} else {
+						insert_stmt.execute(&[
+							&node_id as &dyn ToSql,
+							&path_to_sql(&fl.path) as &dyn ToSql,
+							&fl.hash as &dyn ToSql,
+							&fl.size as &dyn ToSql,
+							&fl.timestamp as &dyn ToSql,
+							&fl.created_at as &dyn ToSql,
+							&fl.modified_at as &dyn ToSql,
+							&fl.accessed_at as &dyn ToSql,
+							&fl.timestamp.timestamp() as &dyn ToSql,
+						])?;
+						inserted += 1;
+						changes.push(ScanChange {
+							path: path.clone(),
+							kind: ScanChangeKind::Added,
+							old_hash: None,
+							new_hash: fl.hash,
+						});
+					}
+				}
+			}
+			tx.commit()
+		};
+		write(conn).map_err(|e| format!("error writing scanned files: {:?}", e))?;
+		self.inserted_count += inserted;
+		self.updated_count += updated;
+		self.changes.extend(changes);
+		self.pending.clear();
+		self.oldest = None;
+		Ok(())
+	}
+}
+
+/// Scans under the node's limits as stored in `conn`.
 pub fn scan_with_progress_cancelable<P, F, C>(
 	node_id: &[u8],
 	path: P,
 	conn: &mut Connection,
+	progress: F,
+	should_cancel: C,
+) -> Result<ScanResult, String>
+where
+	P: AsRef<Path>,
+	F: FnMut(ScanProgress),
+	C: FnMut() -> bool,
+{
+	let throttle = ScanThrottle::stored(conn);
+	scan_limited(node_id, path, conn, &throttle, progress, should_cancel)
+}
+
+/// Reads the files of `path` on threads of their own, taking turns and
+/// keeping pace as `throttle` allows, and records them in walk order.
+/// Files the throttle skips are left as the index has them.
+pub(crate) fn scan_limited<P, F, C>(
+	node_id: &[u8],
+	path: P,
+	conn: &mut Connection,
+	throttle: &ScanThrottle,
 	mut progress: F,
 	mut should_cancel: C,
 ) -> Result<ScanResult, String>
@@ -186,21 +559,24 @@ where
 	C: FnMut() -> bool,
 {
 	let timer = std::time::Instant::now();
-	let mut updated_count = 0;
-	let mut inserted_count = 0;
-	let mut removed_count = 0;
 	let path = path.as_ref().to_path_buf();
-	let absolute_path = canonicalize(&path).unwrap();
-	let tx = conn.transaction().unwrap();
+	// An absent mount leaves an empty folder behind; walking it would
+	// remove every file the share had.
+	let mounts = MountTable::load();
+	if !mounts.is_present(&path) {
+		return Err(format!("share not mounted: {}", path.display()));
+	}
+	let absolute_path =
+		canonicalize(&path).map_err(|e| format!("share not available: {}: {e}", path.display()))?;
 	let mut processed_files = 0usize;
 
 	// load all existing file_locations into a map
 	let existing: HashMap<PathBuf, FileLocation> = {
-		let mut file_locations_stmt = tx
+		let mut file_locations_stmt = conn
 			.prepare(
 				"SELECT path, hash, size, timestamp, created_at, modified_at, accessed_at \
 			FROM file_locations \
-			WHERE path LIKE ?",
+			WHERE path LIKE ? AND deleted_at IS NULL",
 			)
 			.map_err(|e| format!("error preparing statement: {:?}", e))?;
 		file_locations_stmt
@@ -208,7 +584,7 @@ where
 				[&(absolute_path.to_string_lossy().to_string() + "%")],
 				|row| {
 					Ok(FileLocation {
-						path: PathBuf::from(row.get::<_, String>(0)?),
+						path: path_column(row, 0)?,
 						hash: row.get(1)?,
 						size: row.get(2)?,
 						mime_type: None, // not needed for comparison
@@ -216,6 +592,7 @@ where
 						created_at: row.get(4)?,
 						modified_at: row.get(5)?,
 						accessed_at: row.get(6)?,
+						blocks: Vec::new(),
 					})
 				},
 			)
@@ -228,233 +605,164 @@ where
 	let entries = WalkDir::new(&absolute_path)
 		.into_iter()
 		.filter_map(|e| e.ok())
-		.filter(|e| e.file_type().is_file())
+		.filter(|e| e.file_type().is_file() && !throttle.skips(e.path()))
 		.collect::<Vec<_>>();
 	let total_files = entries.len();
-	report_progress(
-		&mut progress,
-		total_files,
-		processed_files,
-		inserted_count,
-		updated_count,
-		removed_count,
+	let patterns = patterns_from_setting(
+		load_setting(conn, MANIFEST_PATTERNS_SETTING)
+			.ok()
+			.flatten()
+			.as_deref(),
 	);
+	let manifests: Vec<PathBuf> = entries
+		.iter()
+		.map(|entry| entry.path().to_path_buf())
+		.filter(|path| is_manifest(path, &patterns))
+		.collect();
+	let blocks = BlockIndexing::load(conn);
+	let mut meter = ScanMeter::new(total_files, throttle);
+	progress(meter.progress(processed_files, 0, 0, 0));
 	cancel_if_requested(&mut should_cancel)?;
 
-	let mut scanned: HashMap<PathBuf, FileLocation>;
-	#[cfg(feature = "rayon")]
+	let mut batch = ScanBatch::new(node_id, &existing);
+	let mut scanned: HashMap<PathBuf, Option<FileHash>> = HashMap::new();
+	// Takes each hashed file in turn. On cancel the files recorded so far
+	// are committed before the scan stops, so what was reported stays.
+	let mut record = |pbuf: PathBuf, fl: FileLocation| -> Result<(), String> {
+		if should_cancel() {
+			batch.flush(conn)?;
+			progress(meter.progress(
+				processed_files,
+				batch.inserted_count,
+				batch.updated_count,
+				0,
+			));
+			return Err(String::from("Scan cancelled"));
+		}
+		scanned.insert(pbuf.clone(), fl.hash);
+		meter.bytes += fl.size;
+		batch.push(pbuf, fl);
+		processed_files += 1;
+		let flushed = batch.is_due();
+		if flushed {
+			batch.flush(conn)?;
+		}
+		if flushed || should_emit_progress(processed_files, total_files) {
+			progress(meter.progress(
+				processed_files,
+				batch.inserted_count,
+				batch.updated_count,
+				0,
+			));
+		}
+		Ok(())
+	};
+
 	{
-		let (tx, rx) = mpsc::channel();
-		let existing = &existing;
-		entries
-			.par_iter()
-			.for_each_with(tx.clone(), |sender, entry| {
-				let pbuf = entry.path().to_path_buf();
-				let meta = std::fs::metadata(&pbuf).unwrap();
-				let created_at = to_datetime(meta.created());
-				let modified_at = to_datetime(meta.modified());
-				let accessed_at = to_datetime(meta.accessed());
-				let size = meta.len();
-				let fl = if let Some(prev) = existing.get(&pbuf) {
-					if prev.size == size
-						&& prev.created_at == created_at
-						&& prev.modified_at == modified_at
-						&& prev.accessed_at == accessed_at
-					{
-						FileLocation {
-							path: pbuf.clone(),
-							hash: prev.hash,
-							size,
-							mime_type: prev.mime_type.clone(),
-							timestamp: Utc::now(),
-							created_at,
-							modified_at,
-							accessed_at,
+		let stop = AtomicBool::new(false);
+		let next = AtomicUsize::new(0);
+		let (existing, blocks, entries) = (&existing, &blocks, &entries);
+		std::thread::scope(|scope| -> Result<(), String> {
+			let (tx, rx) = mpsc::channel();
+			let (stop, next) = (&stop, &next);
+			for _ in 0..throttle.reader_threads() {
+				let tx = tx.clone();
+				scope.spawn(move || {
+					loop {
+						throttle.apply_priority();
+						// The next file is taken with the turn, so one read
+						// at a time goes in walk order.
+						let _turn = throttle.read_turn();
+						if stop.load(Ordering::Relaxed) {
+							return;
+						}
+						let Some(entry) = entries.get(next.fetch_add(1, Ordering::Relaxed)) else {
+							return;
+						};
+						let pbuf = entry.path().to_path_buf();
+						let fl = scanned_location(&pbuf, existing, blocks, throttle);
+						if tx.send((pbuf, fl)).is_err() {
+							return;
 						}
-					} else {
-						handle_path(&pbuf)
 					}
-				} else {
-					handle_path(&pbuf)
-				};
-				let _ = sender.send((pbuf, fl));
-			});
-		drop(tx);
-		let mut map = HashMap::new();
-		for (pbuf, fl) in rx {
-			cancel_if_requested(&mut should_cancel)?;
-			map.insert(pbuf, fl);
-			processed_files += 1;
-			if should_emit_progress(processed_files, total_files) {
-				report_progress(
-					&mut progress,
-					total_files,
-					processed_files,
-					inserted_count,
-					updated_count,
-					removed_count,
-				);
+				});
 			}
-		}
-		scanned = map;
-	}
-	#[cfg(not(feature = "rayon"))]
-	{
-		let mut map = HashMap::new();
-		for entry in entries {
-			cancel_if_requested(&mut should_cancel)?;
-			let pbuf = entry.path().to_path_buf();
-			let meta = std::fs::metadata(&pbuf).unwrap();
-			let created_at = to_datetime(meta.created());
-			let modified_at = to_datetime(meta.modified());
-			let accessed_at = to_datetime(meta.accessed());
-			let size = meta.len();
-
-			let fl = if let Some(prev) = existing.get(&pbuf) {
-				if prev.size == size
-					&& prev.created_at == created_at
-					&& prev.modified_at == modified_at
-					&& prev.accessed_at == accessed_at
-				{
-					FileLocation {
-						path: pbuf.clone(),
-						hash: prev.hash,
-						size,
-						mime_type: prev.mime_type.clone(),
-						timestamp: Utc::now(),
-						created_at,
-						modified_at,
-						accessed_at,
-					}
-				} else {
-					handle_path(&pbuf)
+			drop(tx);
+			for (pbuf, fl) in rx {
+				if let Err(err) = record(pbuf, fl) {
+					stop.store(true, Ordering::Relaxed);
+					return Err(err);
 				}
-			} else {
-				handle_path(&pbuf)
-			};
-
-			map.insert(pbuf, fl);
-			processed_files += 1;
-			if should_emit_progress(processed_files, total_files) {
-				report_progress(
-					&mut progress,
-					total_files,
-					processed_files,
-					inserted_count,
-					updated_count,
-					removed_count,
-				);
 			}
-		}
-		scanned = map;
+			Ok(())
+		})?;
 	}
+	cancel_if_requested(&mut should_cancel).or_else(|err| {
+		batch.flush(conn)?;
+		Err(err)
+	})?;
+	batch.flush(conn)?;
+	let checksums = check_claims(conn, node_id, &absolute_path, &manifests, &scanned)?;
 
-	// remove deleted files
-	{
-		let mut delete_stmt = tx.prepare(DELETE_FILE_LOCATION).unwrap();
-		for old in existing.keys() {
-			cancel_if_requested(&mut should_cancel)?;
-			if !scanned.contains_key(old) {
-				delete_stmt
-					.execute(&[&node_id as &dyn ToSql, &old.to_string_lossy() as &dyn ToSql])
-					.unwrap();
-				removed_count += 1;
-				report_progress(
-					&mut progress,
-					total_files,
-					processed_files,
-					inserted_count,
-					updated_count,
-					removed_count,
-				);
-			}
-		}
-	}
-
-	{
-		let mut insert_stmt = tx.prepare(INSERT_FILE_LOCATION).unwrap();
-		let mut update_stmt = tx.prepare(UPDATE_FILE_LOCATION).unwrap();
-		for (path, fl) in scanned.iter() {
-			cancel_if_requested(&mut should_cancel)?;
-			if let Some(prev) = existing.get(path) {
-				if fl == prev {
-					continue;
+	// Only a walk that saw every file can tell which ones are gone, and
+	// only while the mount they were on is still there.
+	let mounts = MountTable::load();
+	let mut removed = Vec::new();
+	if mounts.is_present(&absolute_path) {
+		let tx = conn
+			.transaction()
+			.map_err(|e| format!("error starting transaction: {:?}", e))?;
+		{
+			let deleted_at = Utc::now().timestamp();
+			let mut delete_stmt = tx.prepare(DELETE_FILE_LOCATION).unwrap();
+			let mut delete_claims_stmt = tx.prepare(DELETE_CLAIMED_HASHES).unwrap();
+			for (old, prev) in existing.iter() {
+				if !scanned.contains_key(old) && !throttle.skips(old) && mounts.is_present(old) {
+					delete_stmt
+						.execute(&[
+							&deleted_at as &dyn ToSql,
+							&node_id as &dyn ToSql,
+							&path_to_sql(old) as &dyn ToSql,
+						])
+						.unwrap();
+					delete_claims_stmt
+						.execute(&[&node_id as &dyn ToSql, &path_to_sql(old) as &dyn ToSql])
+						.unwrap();
+					removed.push(ScanChange {
+						path: old.clone(),
+						kind: ScanChangeKind::Removed,
+						old_hash: prev.hash,
+						new_hash: None,
+					});
 				}
-				update_stmt
-					.execute(&[
-						&fl.hash as &dyn ToSql,
-						&fl.size as &dyn ToSql,
-						&fl.timestamp as &dyn ToSql,
-						&fl.created_at as &dyn ToSql,
-						&fl.modified_at as &dyn ToSql,
-						&fl.accessed_at as &dyn ToSql,
-						&node_id as &dyn ToSql,
-						&fl.path.to_string_lossy() as &dyn ToSql,
-					])
-					.unwrap();
-				updated_count += 1;
-			} else {
-				insert_stmt
-					.execute(&[
-						&node_id as &dyn ToSql,
-						&fl.path.to_string_lossy() as &dyn ToSql,
-						&fl.hash as &dyn ToSql,
-						&fl.size as &dyn ToSql,
-						&fl.timestamp as &dyn ToSql,
-						&fl.created_at as &dyn ToSql,
-						&fl.modified_at as &dyn ToSql,
-						&fl.accessed_at as &dyn ToSql,
-					])
-					.unwrap();
-				inserted_count += 1;
 			}
-			report_progress(
-				&mut progress,
-				total_files,
-				processed_files,
-				inserted_count,
-				updated_count,
-				removed_count,
-			);
-		}
-	}
-
-	{
-		let mut upsert_stmt = tx.prepare(UPSERT_FILE_ENTRY).unwrap();
-		for fl in scanned.values() {
-			cancel_if_requested(&mut should_cancel)?;
-			let timestamps: Vec<_> = [fl.created_at, fl.modified_at, fl.accessed_at]
-				.iter()
-				.copied()
-				.flatten()
-				.collect();
-			let first_dt = timestamps.iter().min().copied();
-			let latest_dt = timestamps.iter().max().copied();
-			upsert_stmt
-				.execute(&[
-					&fl.hash as &dyn ToSql,
-					&fl.size as &dyn ToSql,
-					&fl.mime_type as &dyn ToSql,
-					&first_dt as &dyn ToSql,
-					&latest_dt as &dyn ToSql,
-				])
-				.unwrap();
 		}
+		tx.commit()
+			.map_err(|e| format!("error removing deleted files: {:?}", e))?;
 	}
+	let removed_count = removed.len() as u64;
+	let ScanBatch {
+		inserted_count,
+		updated_count,
+		mut changes,
+		..
+	} = batch;
+	changes.extend(removed);
 
-	tx.commit().unwrap();
-	report_progress(
-		&mut progress,
-		total_files,
+	progress(meter.progress(
 		processed_files,
 		inserted_count,
 		updated_count,
 		removed_count,
-	);
+	));
 	Ok(ScanResult {
 		updated_count,
 		inserted_count,
 		removed_count,
 		duration: timer.elapsed(),
+		file_count: scanned.len() as u64,
+		checksums,
+		run: None,
+		changes,
 	})
 }
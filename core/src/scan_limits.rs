@@ -0,0 +1,512 @@
+//! How hard a scan may lean on the machine it runs on: how many files it
+//! reads at once, how many bytes a second, and whether its threads run at
+//! background priority. The node's limits are knobs a running scan picks
+//! up as soon as they change. A scan can be started with limits of its
+//! own; one a peer asks for only ever gets less than the node allows.
+
+use crate::config;
+use crate::db::load_setting;
+use crate::pins::throttle_delay;
+use crate::state::HiddenNames;
+use anyhow::{Result, bail};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+pub(crate) const SCAN_MAX_READS_SETTING: &str = "scan_max_reads";
+pub(crate) const SCAN_MAX_READ_MIB_SETTING: &str = "scan_max_read_mib";
+pub(crate) const SCAN_LOW_PRIORITY_SETTING: &str = "scan_low_priority";
+pub const MAX_SCAN_READS: u32 = 64;
+const MIB: u64 = 1024 * 1024;
+/// Longest a file waits for its turn before looking at the limit again,
+/// so a limit raised mid-scan is noticed without being told.
+const TURN_RECHECK: Duration = Duration::from_millis(200);
+/// Time spent not reading that may be made up for with a burst.
+const MAX_BURST: Duration = Duration::from_secs(1);
+#[cfg(target_os = "linux")]
+const BACKGROUND_NICE: libc::c_int = 10;
+
+/// Files read at once unless configured: as many as rayon has threads in
+/// builds with it, which is what its pool read with, and one otherwise.
+pub(crate) fn default_max_reads() -> u32 {
+	#[cfg(feature = "rayon")]
+	{
+		(rayon::current_num_threads() as u32).clamp(1, MAX_SCAN_READS)
+	}
+	#[cfg(not(feature = "rayon"))]
+	{
+		1
+	}
+}
+
+/// The limits a scan reads under.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanLimits {
+	/// Files read at once.
+	pub max_reads: u32,
+	/// MiB read per second at most; 0 reads as fast as the disk allows.
+	pub max_read_mib: u32,
+	/// Scanning threads run at background CPU and IO priority.
+	pub low_priority: bool,
+}
+
+impl ScanLimits {
+	/// Bytes read per second at most, `None` without a cap.
+	pub fn max_read_rate(&self) -> Option<u64> {
+		(self.max_read_mib > 0).then(|| self.max_read_mib as u64 * MIB)
+	}
+}
+
+/// Limits one scan asks for instead of the node's. Unset ones follow the
+/// node's, also when they change while the scan runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanOverrides {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub max_reads: Option<u32>,
+	/// 0 asks for no cap.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub max_read_mib: Option<u32>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub low_priority: Option<bool>,
+}
+
+impl ScanOverrides {
+	pub fn validate(&self) -> Result<()> {
+		if let Some(reads) = self.max_reads
+			&& !(1..=MAX_SCAN_READS).contains(&reads)
+		{
+			bail!("files read at once must be between 1 and {MAX_SCAN_READS}");
+		}
+		Ok(())
+	}
+
+	/// `node` with these in place of its limits, for scans started here.
+	pub fn over(self, node: ScanLimits) -> ScanLimits {
+		ScanLimits {
+			max_reads: self
+				.max_reads
+				.unwrap_or(node.max_reads)
+				.clamp(1, MAX_SCAN_READS),
+			max_read_mib: self.max_read_mib.unwrap_or(node.max_read_mib),
+			low_priority: self.low_priority.unwrap_or(node.low_priority),
+		}
+	}
+
+	/// `node` lowered by these but never raised, for scans a peer asks
+	/// for: fewer reads, a lower cap and background priority are granted,
+	/// more reads, a higher cap or no cap and normal priority are not.
+	pub fn within(self, node: ScanLimits) -> ScanLimits {
+		let max_read_mib = match (self.max_read_mib.filter(|mib| *mib > 0), node.max_read_mib) {
+			(Some(asked), 0) => asked,
+			(Some(asked), cap) => asked.min(cap),
+			(None, cap) => cap,
+		};
+		ScanLimits {
+			max_reads: self
+				.max_reads
+				.map_or(node.max_reads, |asked| asked.max(1).min(node.max_reads)),
+			max_read_mib,
+			low_priority: node.low_priority || self.low_priority == Some(true),
+		}
+	}
+}
+
+/// Stored under `setting`, or as configured at startup.
+fn stored<T: FromStr>(conn: &Connection, setting: &str, startup: T) -> T {
+	match load_setting(conn, setting) {
+		Ok(Some(value)) => value.parse().unwrap_or_else(|_| {
+			tracing::warn!("ignoring invalid {setting} {value:?}");
+			startup
+		}),
+		Ok(None) => startup,
+		Err(err) => {
+			tracing::error!("failed to load {setting}: {err}");
+			startup
+		}
+	}
+}
+
+/// The node's limits, shared by its running scans.
+pub(crate) struct NodeScanLimits {
+	max_reads: AtomicU32,
+	max_read_mib: AtomicU32,
+	low_priority: AtomicBool,
+}
+
+impl NodeScanLimits {
+	pub(crate) fn load(conn: &Connection) -> Self {
+		let limits = Self {
+			max_reads: AtomicU32::new(1),
+			max_read_mib: AtomicU32::new(0),
+			low_priority: AtomicBool::new(false),
+		};
+		limits.reload(conn);
+		limits
+	}
+
+	pub(crate) fn reload(&self, conn: &Connection) {
+		let startup = config::startup();
+		self.max_reads.store(
+			stored(conn, SCAN_MAX_READS_SETTING, startup.scan_max_reads),
+			Ordering::Relaxed,
+		);
+		self.max_read_mib.store(
+			stored(conn, SCAN_MAX_READ_MIB_SETTING, startup.scan_max_read_mib),
+			Ordering::Relaxed,
+		);
+		self.low_priority.store(
+			stored(conn, SCAN_LOW_PRIORITY_SETTING, startup.scan_low_priority),
+			Ordering::Relaxed,
+		);
+	}
+
+	pub(crate) fn get(&self) -> ScanLimits {
+		ScanLimits {
+			max_reads: self.max_reads.load(Ordering::Relaxed).max(1),
+			max_read_mib: self.max_read_mib.load(Ordering::Relaxed),
+			low_priority: self.low_priority.load(Ordering::Relaxed),
+		}
+	}
+
+	/// Takes the knob stored under `setting`, checked by its kind.
+	pub(crate) fn set(&self, setting: &str, value: &str) -> Result<()> {
+		match setting {
+			SCAN_MAX_READS_SETTING => self.max_reads.store(value.parse()?, Ordering::Relaxed),
+			SCAN_MAX_READ_MIB_SETTING => self.max_read_mib.store(value.parse()?, Ordering::Relaxed),
+			SCAN_LOW_PRIORITY_SETTING => self.low_priority.store(value.parse()?, Ordering::Relaxed),
+			_ => bail!("{setting} is not a scan limit"),
+		}
+		Ok(())
+	}
+}
+
+/// Bytes read since `started` at `rate`.
+struct Pace {
+	rate: u64,
+	started: Instant,
+	bytes: u64,
+}
+
+impl Pace {
+	fn new(rate: u64) -> Self {
+		Self {
+			rate,
+			started: Instant::now(),
+			bytes: 0,
+		}
+	}
+}
+
+/// One scan's hold on its limits: the turns its files take to be read and
+/// the pace of its reads across all of its threads.
+pub(crate) struct ScanThrottle {
+	node: Arc<NodeScanLimits>,
+	overrides: ScanOverrides,
+	/// Overrides only lower the node's limits, as for a peer's scan.
+	clamped: bool,
+	/// Files the peer that asked for the scan doesn't see, which it leaves
+	/// alone.
+	hidden: HiddenNames,
+	reading: Mutex<u32>,
+	turn_ended: Condvar,
+	pace: Mutex<Option<Pace>>,
+}
+
+impl ScanThrottle {
+	/// For a scan started on this node.
+	pub(crate) fn local(node: Arc<NodeScanLimits>, overrides: ScanOverrides) -> Self {
+		Self::new(node, overrides, false, HiddenNames::default())
+	}
+
+	/// For a scan a peer asked for, skipping what `hidden` hides from it.
+	pub(crate) fn for_peer(
+		node: Arc<NodeScanLimits>,
+		overrides: ScanOverrides,
+		hidden: HiddenNames,
+	) -> Self {
+		Self::new(node, overrides, true, hidden)
+	}
+
+	/// Under the node's limits as stored in `conn`, for scans run without
+	/// a node.
+	pub(crate) fn stored(conn: &Connection) -> Self {
+		Self::local(
+			Arc::new(NodeScanLimits::load(conn)),
+			ScanOverrides::default(),
+		)
+	}
+
+	fn new(
+		node: Arc<NodeScanLimits>,
+		overrides: ScanOverrides,
+		clamped: bool,
+		hidden: HiddenNames,
+	) -> Self {
+		Self {
+			node,
+			overrides,
+			clamped,
+			hidden,
+			reading: Mutex::new(0),
+			turn_ended: Condvar::new(),
+			pace: Mutex::new(None),
+		}
+	}
+
+	/// Whether the scan leaves the file at `path` out: neither read nor
+	/// counted as gone.
+	pub(crate) fn skips(&self, path: &Path) -> bool {
+		self.hidden.hides(path, false)
+	}
+
+	/// The limits in effect right now.
+	pub(crate) fn limits(&self) -> ScanLimits {
+		let node = self.node.get();
+		if self.clamped {
+			self.overrides.within(node)
+		} else {
+			self.overrides.over(node)
+		}
+	}
+
+	/// Threads to read with: enough for the machine, or for the limit
+	/// when it is higher. A limit raised past them mid-scan waits for the
+	/// next scan.
+	pub(crate) fn reader_threads(&self) -> usize {
+		let machine = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+		machine.max(self.limits().max_reads as usize)
+	}
+
+	/// Waits until fewer files than the limit are being read.
+	pub(crate) fn read_turn(&self) -> ReadTurn<'_> {
+		let mut reading = self.reading.lock().unwrap();
+		while *reading >= self.limits().max_reads {
+			reading = self
+				.turn_ended
+				.wait_timeout(reading, TURN_RECHECK)
+				.unwrap()
+				.0;
+		}
+		*reading += 1;
+		ReadTurn { throttle: self }
+	}
+
+	/// `reader`, paced to the read cap.
+	pub(crate) fn reader<R: Read>(&self, reader: R) -> Throttled<'_, R> {
+		Throttled {
+			inner: reader,
+			throttle: self,
+		}
+	}
+
+	/// Sleeps as long as reading `bytes` more takes at the cap.
+	fn pace(&self, bytes: u64) {
+		let Some(rate) = self.limits().max_read_rate() else {
+			return;
+		};
+		let delay = {
+			let mut pace = self.pace.lock().unwrap();
+			let pace = pace.get_or_insert_with(|| Pace::new(rate));
+			let idle = pace
+				.started
+				.elapsed()
+				.saturating_sub(Duration::from_secs_f64(pace.bytes as f64 / rate as f64));
+			if pace.rate != rate || idle > MAX_BURST {
+				*pace = Pace::new(rate);
+			}
+			pace.bytes += bytes;
+			throttle_delay(pace.bytes, rate, pace.started.elapsed())
+		};
+		std::thread::sleep(delay);
+	}
+
+	/// Moves the calling thread to the priority the limits ask for. Only
+	/// call it on the scan's own threads, which end with it.
+	pub(crate) fn apply_priority(&self) {
+		thread_local! {
+			static BACKGROUND: Cell<bool> = const { Cell::new(false) };
+		}
+		let background = self.limits().low_priority;
+		if BACKGROUND.get() == background {
+			return;
+		}
+		BACKGROUND.set(background);
+		if let Err(err) = set_background(background) {
+			tracing::debug!("failed to change scan thread priority: {err}");
+		}
+	}
+}
+
+/// A file's turn to be read, over when dropped.
+pub(crate) struct ReadTurn<'a> {
+	throttle: &'a ScanThrottle,
+}
+
+impl Drop for ReadTurn<'_> {
+	fn drop(&mut self) {
+		*self.throttle.reading.lock().unwrap() -= 1;
+		self.throttle.turn_ended.notify_one();
+	}
+}
+
+pub(crate) struct Throttled<'a, R> {
+	inner: R,
+	throttle: &'a ScanThrottle,
+}
+
+impl<R: Read> Read for Throttled<'_, R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let count = self.inner.read(buf)?;
+		self.throttle.pace(count as u64);
+		Ok(count)
+	}
+}
+
+/// Nice and the idle IO class for the calling thread. Going back needs
+/// `CAP_SYS_NICE` for the nice part; without it the thread stays niced.
+#[cfg(target_os = "linux")]
+fn set_background(background: bool) -> io::Result<()> {
+	const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+	const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+	const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+	// Both take a thread id, so only the calling thread moves.
+	let tid = unsafe { libc::gettid() };
+	let ioprio = if background {
+		IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT
+	} else {
+		0
+	};
+	if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, tid, ioprio) } != 0 {
+		return Err(io::Error::last_os_error());
+	}
+	let nice = if background { BACKGROUND_NICE } else { 0 };
+	if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, nice) } != 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+/// Background mode for the calling thread, which lowers its CPU and IO
+/// priority together.
+#[cfg(target_os = "macos")]
+fn set_background(background: bool) -> io::Result<()> {
+	let priority = if background { libc::PRIO_DARWIN_BG } else { 0 };
+	if unsafe { libc::setpriority(libc::PRIO_DARWIN_THREAD, 0, priority) } != 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+/// No thread priority API is linked here; the read limits still apply.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn set_background(_background: bool) -> io::Result<()> {
+	Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::scan::scan_limited;
+	use crate::test_util::{self, test_dir};
+
+	fn node(max_reads: u32, max_read_mib: u32, low_priority: bool) -> Arc<NodeScanLimits> {
+		Arc::new(NodeScanLimits {
+			max_reads: AtomicU32::new(max_reads),
+			max_read_mib: AtomicU32::new(max_read_mib),
+			low_priority: AtomicBool::new(low_priority),
+		})
+	}
+
+	#[test]
+	fn a_peer_can_ask_for_less_but_never_more() {
+		let node = ScanLimits {
+			max_reads: 4,
+			max_read_mib: 50,
+			low_priority: false,
+		};
+		let asked = ScanOverrides {
+			max_reads: Some(16),
+			max_read_mib: Some(0),
+			low_priority: Some(true),
+		};
+		assert_eq!(
+			asked.within(node),
+			ScanLimits {
+				max_reads: 4,
+				max_read_mib: 50,
+				low_priority: true,
+			}
+		);
+		let gentler = ScanOverrides {
+			max_reads: Some(1),
+			max_read_mib: Some(10),
+			low_priority: Some(false),
+		};
+		assert_eq!(
+			gentler.within(ScanLimits {
+				low_priority: true,
+				..node
+			}),
+			ScanLimits {
+				max_reads: 1,
+				max_read_mib: 10,
+				low_priority: true,
+			}
+		);
+		// The node's own user may go past its limits.
+		assert_eq!(asked.over(node).max_reads, 16);
+		assert_eq!(asked.over(node).max_read_rate(), None);
+	}
+
+	#[test]
+	fn reads_stay_near_the_cap() {
+		let throttle = ScanThrottle::local(node(1, 8, false), ScanOverrides::default());
+		let started = Instant::now();
+		let read = io::copy(
+			&mut throttle.reader(io::repeat(7).take(12 * MIB)),
+			&mut io::sink(),
+		)
+		.unwrap();
+		let rate = read as f64 / started.elapsed().as_secs_f64() / MIB as f64;
+		assert!((7.0..=8.4).contains(&rate), "read at {rate:.2} MiB/s");
+	}
+
+	#[test]
+	fn a_limit_changed_mid_scan_applies_to_the_rest_of_it() {
+		let dir = test_dir("mid-scan");
+		for i in 0..12 {
+			std::fs::write(dir.join(format!("{i}.bin")), vec![i as u8; 512 * 1024]).unwrap();
+		}
+		let mut conn = test_util::memory_db();
+		let limits = node(1, 1, false);
+		let throttle = ScanThrottle::local(Arc::clone(&limits), ScanOverrides::default());
+		let mut reported = Vec::new();
+		let started = Instant::now();
+		let result = scan_limited(
+			&[7u8; 16],
+			&dir,
+			&mut conn,
+			&throttle,
+			|progress| {
+				if progress.processed_files == 2 {
+					limits.set(SCAN_MAX_READ_MIB_SETTING, "0").unwrap();
+				}
+				reported.push(progress.limits.unwrap().max_read_mib);
+			},
+			|| false,
+		)
+		.unwrap();
+		// All 6 MiB at 1 MiB/s would take 6 seconds.
+		assert!(started.elapsed() < Duration::from_secs(3));
+		assert_eq!(result.inserted_count, 12);
+		assert_eq!(reported.first(), Some(&1));
+		assert_eq!(reported.last(), Some(&0));
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
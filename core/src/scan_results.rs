@@ -0,0 +1,392 @@
+//! Results of a scan one peer asked another for. The scanning node writes
+//! what it found under its own node id, so the peer that asked learns
+//! nothing of it from the scan itself. The finished scan names its run
+//! ([`ScanResult::run`](crate::scan::ScanResult::run)), and the peer that
+//! asked pulls the locations under the scanned folder, tombstones
+//! included, a batch at a time with `ScanResultsPull`. It keeps them under
+//! the scanning node's id, marked as known from that node's index.
+//!
+//! Only the peer that started a run may pull it, and only while it may
+//! still search the folder. The cursor is the last location row the puller
+//! holds, kept in `scan_result_pulls`, so a pull cut off halfway resumes
+//! where it stopped. A pull stops after [`SCAN_RESULTS_PULL_CAP`]
+//! locations until it is continued, and [`MIRROR_REMOTE_SCANS_SETTING`]
+//! turns pulling off for nodes that don't want to mirror other indexes.
+
+use crate::db::{
+	FileOrigin, NodeID, ScanRun, ScanRunStatus, count_locations_under, load_locations_under,
+	load_scan_run, upsert_index_rows,
+};
+use crate::format::group_digits;
+use crate::p2p::WirePath;
+use crate::replication::{INDEX_DELTA_BATCH, ReplicatedEntry, ReplicatedLocation};
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use serde::{Deserialize, Serialize};
+
+pub(crate) const MIRROR_REMOTE_SCANS_SETTING: &str = "mirror_remote_scans";
+/// Locations per batch, as many as an index delta carries.
+const SCAN_RESULTS_BATCH: usize = INDEX_DELTA_BATCH;
+/// Rows a batch may hold: the locations plus the entry of each.
+const MAX_BATCH_ROWS: usize = 2 * SCAN_RESULTS_BATCH;
+/// Locations one pull fetches before it waits to be continued.
+pub const SCAN_RESULTS_PULL_CAP: u64 = 50_000;
+
+/// Pulling scan results is on unless turned off.
+pub(crate) fn mirror_enabled_from_setting(value: Option<&str>) -> bool {
+	value.is_none_or(|value| value.trim() != "false")
+}
+
+/// One batch of the locations a scan run left under its folder.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanResults {
+	pub run: i64,
+	/// Folder the run scanned.
+	pub path: WirePath,
+	/// Locations under the folder when the batch was read.
+	pub total: u64,
+	/// Last location row in the batch; the next pull asks for the ones
+	/// after it.
+	pub cursor: u64,
+	pub entries: Vec<ReplicatedEntry>,
+	pub locations: Vec<ReplicatedLocation>,
+	/// Locations after `cursor`.
+	pub remaining: u64,
+}
+
+/// How far this node got pulling the results of scan run `run` of `peer`,
+/// from the `scan_result_pulls` table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScanResultsPull {
+	pub peer: String,
+	pub run: i64,
+	/// Folder the run scanned; empty before the first batch.
+	pub path: String,
+	/// Last location row of the peer this node holds.
+	pub cursor: u64,
+	pub total: u64,
+	/// Locations left to pull; `None` before the first batch.
+	pub remaining: Option<u64>,
+	/// Why the last batch failed, until one arrives.
+	pub error: Option<String>,
+	pub updated_at: DateTime<Utc>,
+}
+
+impl ScanResultsPull {
+	pub fn new(peer: &PeerId, run: i64, now: DateTime<Utc>) -> Self {
+		Self {
+			peer: peer.to_string(),
+			run,
+			path: String::new(),
+			cursor: 0,
+			total: 0,
+			remaining: None,
+			error: None,
+			updated_at: now,
+		}
+	}
+
+	pub fn fetched(&self) -> u64 {
+		self.remaining
+			.map_or(0, |remaining| self.total.saturating_sub(remaining))
+	}
+
+	pub fn is_done(&self) -> bool {
+		self.remaining == Some(0)
+	}
+
+	/// "4,200 of 12,000 entries".
+	pub fn describe(&self) -> String {
+		format!(
+			"{} of {} entries",
+			group_digits(self.fetched()),
+			group_digits(self.total)
+		)
+	}
+}
+
+/// Run `run` of this node, if `peer` started it and it completed.
+pub(crate) fn scan_run_for(conn: &Connection, peer: &PeerId, run: i64) -> Result<ScanRun> {
+	let Some(scan) = load_scan_run(conn, run)? else {
+		bail!("no scan run {run}");
+	};
+	if scan.initiated_by.as_deref() != Some(peer.to_string().as_str()) {
+		bail!("scan run {run} was started by another peer");
+	}
+	if scan.status != ScanRunStatus::Completed {
+		bail!("scan run {run} did not complete");
+	}
+	Ok(scan)
+}
+
+/// The batch of `node_id`'s locations under the folder of `scan` after row
+/// `cursor`.
+pub(crate) fn scan_results_batch(
+	conn: &Connection,
+	node_id: &NodeID,
+	scan: &ScanRun,
+	cursor: u64,
+) -> Result<ScanResults> {
+	let changes = load_locations_under(conn, node_id, &scan.path, cursor, SCAN_RESULTS_BATCH)?;
+	Ok(ScanResults {
+		run: scan.id,
+		path: WirePath::from(scan.path.clone()),
+		total: count_locations_under(conn, node_id, &scan.path, 0)?,
+		cursor: changes.through,
+		remaining: count_locations_under(conn, node_id, &scan.path, changes.through)?,
+		entries: changes.entries,
+		locations: changes.locations,
+	})
+}
+
+/// Keeps `results` under `source`'s node id, marked as known from its
+/// index, and records how far `pull` got, in one transaction. `pull` is
+/// left as it was if the batch is not kept.
+pub(crate) fn receive_scan_results(
+	conn: &Connection,
+	source: &PeerId,
+	pull: &mut ScanResultsPull,
+	results: ScanResults,
+	now: DateTime<Utc>,
+) -> Result<()> {
+	let Some(node_id) = crate::app::peer_to_node_id(source) else {
+		bail!("invalid peer id {source}");
+	};
+	if results.run != pull.run {
+		bail!(
+			"results of scan run {} instead of {}",
+			results.run,
+			pull.run
+		);
+	}
+	if results.entries.len() + results.locations.len() > MAX_BATCH_ROWS {
+		bail!("scan results hold more than {MAX_BATCH_ROWS} rows");
+	}
+	if results.cursor < pull.cursor {
+		bail!("scan results go back before row {}", pull.cursor);
+	}
+	let locations = results
+		.locations
+		.into_iter()
+		.map(|location| ReplicatedLocation {
+			origin: FileOrigin::Sync,
+			origin_peer: Some(source.to_string()),
+			origin_run: Some(results.run),
+			..location
+		})
+		.collect::<Vec<_>>();
+	let next = ScanResultsPull {
+		path: results.path.to_string(),
+		cursor: results.cursor,
+		total: results.total,
+		remaining: Some(results.remaining),
+		error: None,
+		updated_at: now,
+		..pull.clone()
+	};
+	let tx = conn.unchecked_transaction()?;
+	upsert_index_rows(&tx, &node_id, &results.entries, &locations)?;
+	save_scan_result_pull(&tx, &next)?;
+	tx.commit()?;
+	*pull = next;
+	Ok(())
+}
+
+const PULL_COLUMNS: &str = "peer, run, path, cursor, total, remaining, error, updated_at";
+
+fn pull_from_row(row: &Row<'_>) -> rusqlite::Result<ScanResultsPull> {
+	Ok(ScanResultsPull {
+		peer: row.get(0)?,
+		run: row.get(1)?,
+		path: row.get(2)?,
+		cursor: row.get::<_, i64>(3)? as u64,
+		total: row.get::<_, i64>(4)? as u64,
+		remaining: row
+			.get::<_, Option<i64>>(5)?
+			.map(|remaining| remaining as u64),
+		error: row.get(6)?,
+		updated_at: DateTime::from_timestamp(row.get(7)?, 0).unwrap_or_default(),
+	})
+}
+
+pub(crate) fn load_scan_result_pull(
+	conn: &Connection,
+	peer: &PeerId,
+	run: i64,
+) -> Result<Option<ScanResultsPull>> {
+	Ok(conn
+		.query_row(
+			&format!("SELECT {PULL_COLUMNS} FROM scan_result_pulls WHERE peer = ?1 AND run = ?2"),
+			params![peer.to_string(), run],
+			pull_from_row,
+		)
+		.optional()?)
+}
+
+/// Pulls of `peer`'s scan results, latest first.
+pub(crate) fn load_scan_result_pulls(
+	conn: &Connection,
+	peer: &PeerId,
+) -> Result<Vec<ScanResultsPull>> {
+	let mut stmt = conn.prepare(&format!(
+		"SELECT {PULL_COLUMNS} FROM scan_result_pulls WHERE peer = ?1
+		ORDER BY updated_at DESC, run DESC"
+	))?;
+	let rows = stmt.query_map([peer.to_string()], pull_from_row)?;
+	Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+pub(crate) fn save_scan_result_pull(conn: &Connection, pull: &ScanResultsPull) -> Result<()> {
+	conn.execute(
+		"INSERT OR REPLACE INTO scan_result_pulls
+			(peer, run, path, cursor, total, remaining, error, updated_at)
+		VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+		params![
+			pull.peer,
+			pull.run,
+			pull.path,
+			pull.cursor as i64,
+			pull.total as i64,
+			pull.remaining.map(|remaining| remaining as i64),
+			pull.error,
+			pull.updated_at.timestamp()
+		],
+	)?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::app::peer_to_node_id;
+	use crate::db::record_scan_run;
+	use crate::scan::ScanResult;
+	use crate::test_util;
+	use chrono::TimeZone;
+	use std::time::Duration;
+
+	fn open() -> Connection {
+		test_util::memory_db()
+	}
+
+	fn add_file(conn: &Connection, node_id: &NodeID, path: &str, deleted: bool) {
+		let hash = blake3::hash(path.as_bytes()).as_bytes().to_vec();
+		conn.execute(
+			"INSERT INTO file_entries (hash, size, mime_type) VALUES (?1, 4, 'text/plain')",
+			params![hash],
+		)
+		.unwrap();
+		conn.execute(
+			"INSERT INTO file_locations (node_id, path, hash, size, timestamp, deleted_at)
+			VALUES (?1, ?2, ?3, 4, '2024-05-01 10:00:00+00:00', ?4)",
+			params![
+				node_id.as_slice(),
+				path,
+				hash,
+				deleted.then_some(1714557600)
+			],
+		)
+		.unwrap();
+	}
+
+	fn record_run(conn: &Connection, path: &str, initiator: &PeerId) -> i64 {
+		let outcome = Ok(ScanResult {
+			updated_count: 0,
+			inserted_count: 3,
+			removed_count: 1,
+			duration: Duration::from_secs(1),
+			file_count: 3,
+			checksums: None,
+			run: None,
+			changes: Vec::new(),
+		});
+		let now = DateTime::from_timestamp(1714557600, 0).unwrap();
+		record_scan_run(
+			conn,
+			path,
+			now,
+			Duration::ZERO,
+			Some(initiator),
+			&outcome,
+			false,
+		)
+		.unwrap()
+	}
+
+	#[test]
+	fn the_initiator_pulls_a_scan_in_batches_and_keeps_it_under_the_scanner() {
+		let scanner = PeerId::random();
+		let initiator = PeerId::random();
+		let scanner_id = peer_to_node_id(&scanner).unwrap();
+		let source = open();
+		for i in 0..SCAN_RESULTS_BATCH + 5 {
+			add_file(
+				&source,
+				&scanner_id,
+				&format!("/data/photos/{i}.jpg"),
+				false,
+			);
+		}
+		add_file(&source, &scanner_id, "/data/photos/gone.jpg", true);
+		add_file(&source, &scanner_id, "/data/other/skip.txt", false);
+		let run = record_run(&source, "/data/photos", &initiator);
+
+		let replica = open();
+		let now = Utc.with_ymd_and_hms(2025, 6, 1, 12, 0, 0).unwrap();
+		let mut pull = ScanResultsPull::new(&scanner, run, now);
+		let scan = scan_run_for(&source, &initiator, run).unwrap();
+		let first = scan_results_batch(&source, &scanner_id, &scan, pull.cursor).unwrap();
+		assert_eq!(first.locations.len(), SCAN_RESULTS_BATCH);
+		receive_scan_results(&replica, &scanner, &mut pull, first, now).unwrap();
+		assert_eq!(pull.describe(), "200 of 206 entries");
+		assert!(!pull.is_done());
+
+		// Resumes from what was kept, as after a restart.
+		let mut pull = load_scan_result_pull(&replica, &scanner, run)
+			.unwrap()
+			.unwrap();
+		let rest = scan_results_batch(&source, &scanner_id, &scan, pull.cursor).unwrap();
+		receive_scan_results(&replica, &scanner, &mut pull, rest, now).unwrap();
+		assert!(pull.is_done());
+		assert_eq!(pull.path, "/data/photos");
+
+		let (kept, deleted, synced): (i64, i64, i64) = replica
+			.query_row(
+				"SELECT COUNT(*), COUNT(deleted_at), SUM(origin = 'sync' AND origin_peer = ?2)
+				FROM file_locations WHERE node_id = ?1",
+				params![scanner_id.as_slice(), scanner.to_string()],
+				|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+			)
+			.unwrap();
+		assert_eq!((kept, deleted, synced), (206, 1, 206));
+		assert_eq!(
+			load_scan_result_pulls(&replica, &scanner).unwrap(),
+			vec![pull]
+		);
+	}
+
+	#[test]
+	fn only_the_initiator_may_pull_a_completed_run() {
+		let initiator = PeerId::random();
+		let conn = open();
+		let run = record_run(&conn, "/data", &initiator);
+		assert!(scan_run_for(&conn, &initiator, run).is_ok());
+		assert!(scan_run_for(&conn, &PeerId::random(), run).is_err());
+		assert!(scan_run_for(&conn, &initiator, run + 1).is_err());
+
+		let now = DateTime::from_timestamp(1714557600, 0).unwrap();
+		let failed = record_scan_run(
+			&conn,
+			"/data",
+			now,
+			Duration::ZERO,
+			Some(&initiator),
+			&Err(String::from("disk gone")),
+			false,
+		)
+		.unwrap();
+		assert!(scan_run_for(&conn, &initiator, failed).is_err());
+	}
+}
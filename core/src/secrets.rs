@@ -0,0 +1,659 @@
+//! Secrets the node keeps, like the HTTP API's JWT signing key and the
+//! HTTP proxy password. They go through a [`SecretStore`] instead of the
+//! settings table, which is not encrypted.
+//!
+//! A secret is read from the first of these that has it:
+//!
+//! 1. `PUPPYNET_SECRET_<NAME>` in the environment, or `JWT_SECRET` for
+//!    [`JWT_SECRET`] as before. Never written to.
+//! 2. The backend named by `PUPPYNET_SECRET_STORE`, else by the
+//!    `secret_store` node setting, else `auto`:
+//!    - `keychain`: the OS keychain (Keychain on macOS, Credential Manager
+//!      on Windows, Secret Service with a keyutils cache on Linux). Needs
+//!      the `keychain` feature.
+//!    - `file`: `secrets.json` next to the database, readable by its
+//!      owner only.
+//!    - `auto`: the keychain when one answers, the file otherwise.
+//!
+//! A keychain that can't be reached, like on a headless Linux box without
+//! a Secret Service, falls back to the file with a warning instead of
+//! failing startup.
+
+use anyhow::{Context, Result, anyhow, bail};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::db::{db_location, load_setting, open_db, run_migrations, save_setting};
+use crate::format::hex;
+
+/// Signs the HTTP API's login tokens. Generated on first use.
+pub const JWT_SECRET: &str = "jwt_secret";
+/// Username and password of the HTTP proxy, as JSON.
+pub(crate) const HTTP_PROXY_SECRET: &str = "http_proxy_credentials";
+pub(crate) const SECRET_STORE_SETTING: &str = "secret_store";
+const SECRET_STORE_ENV: &str = "PUPPYNET_SECRET_STORE";
+const SECRET_ENV_PREFIX: &str = "PUPPYNET_SECRET_";
+/// Read for [`JWT_SECRET`] before there was a store.
+const LEGACY_JWT_ENV: &str = "JWT_SECRET";
+const SECRETS_FILE: &str = "secrets.json";
+/// Where a listed secret that only the environment sets comes from.
+const ENV_SOURCE: &str = "environment";
+
+/// Where secrets are kept.
+pub trait SecretStore: Send + Sync {
+	/// "file", "keychain" or "memory", for `secrets list` and logs.
+	fn backend(&self) -> &'static str;
+	fn get(&self, name: &str) -> Result<Option<String>>;
+	fn set(&self, name: &str, value: &str) -> Result<()>;
+	/// Whether there was a secret to delete.
+	fn delete(&self, name: &str) -> Result<bool>;
+	fn names(&self) -> Result<Vec<String>>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SecretBackend {
+	Auto,
+	File,
+	Keychain,
+}
+
+impl SecretBackend {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			SecretBackend::Auto => "auto",
+			SecretBackend::File => "file",
+			SecretBackend::Keychain => "keychain",
+		}
+	}
+
+	pub fn parse(value: &str) -> Option<Self> {
+		match value.trim() {
+			"auto" => Some(SecretBackend::Auto),
+			"file" => Some(SecretBackend::File),
+			"keychain" => Some(SecretBackend::Keychain),
+			_ => None,
+		}
+	}
+}
+
+/// Secrets as a JSON object in one file, rewritten whole on every change.
+pub struct FileSecretStore {
+	path: PathBuf,
+	lock: Mutex<()>,
+}
+
+impl FileSecretStore {
+	pub fn new(path: PathBuf) -> Self {
+		Self {
+			path,
+			lock: Mutex::new(()),
+		}
+	}
+
+	fn read(&self) -> Result<BTreeMap<String, String>> {
+		match std::fs::read_to_string(&self.path) {
+			Ok(text) => serde_json::from_str(&text)
+				.with_context(|| format!("invalid secrets file {}", self.path.display())),
+			Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+			Err(err) => Err(err).with_context(|| format!("failed to read {}", self.path.display())),
+		}
+	}
+
+	/// Writes next to the file and renames over it, so a crash leaves the
+	/// old secrets rather than half of the new ones.
+	fn write(&self, secrets: &BTreeMap<String, String>) -> Result<()> {
+		if let Some(dir) = self.path.parent() {
+			std::fs::create_dir_all(dir)
+				.with_context(|| format!("failed to create {}", dir.display()))?;
+		}
+		let tmp = self.path.with_extension("json.tmp");
+		let mut options = std::fs::OpenOptions::new();
+		options.write(true).create(true).truncate(true);
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::OpenOptionsExt;
+			options.mode(0o600);
+		}
+		let file = options
+			.open(&tmp)
+			.with_context(|| format!("failed to open {}", tmp.display()))?;
+		serde_json::to_writer_pretty(&file, secrets)?;
+		file.sync_all()?;
+		std::fs::rename(&tmp, &self.path)
+			.with_context(|| format!("failed to replace {}", self.path.display()))
+	}
+}
+
+impl SecretStore for FileSecretStore {
+	fn backend(&self) -> &'static str {
+		"file"
+	}
+
+	fn get(&self, name: &str) -> Result<Option<String>> {
+		let _guard = self.lock.lock().unwrap();
+		Ok(self.read()?.remove(name))
+	}
+
+	fn set(&self, name: &str, value: &str) -> Result<()> {
+		let _guard = self.lock.lock().unwrap();
+		let mut secrets = self.read()?;
+		secrets.insert(name.to_string(), value.to_string());
+		self.write(&secrets)
+	}
+
+	fn delete(&self, name: &str) -> Result<bool> {
+		let _guard = self.lock.lock().unwrap();
+		let mut secrets = self.read()?;
+		if secrets.remove(name).is_none() {
+			return Ok(false);
+		}
+		self.write(&secrets)?;
+		Ok(true)
+	}
+
+	fn names(&self) -> Result<Vec<String>> {
+		let _guard = self.lock.lock().unwrap();
+		Ok(self.read()?.into_keys().collect())
+	}
+}
+
+/// Secrets that are gone when the process exits, for demo mode and tests.
+#[derive(Default)]
+pub struct MemorySecretStore {
+	secrets: Mutex<BTreeMap<String, String>>,
+}
+
+impl SecretStore for MemorySecretStore {
+	fn backend(&self) -> &'static str {
+		"memory"
+	}
+
+	fn get(&self, name: &str) -> Result<Option<String>> {
+		Ok(self.secrets.lock().unwrap().get(name).cloned())
+	}
+
+	fn set(&self, name: &str, value: &str) -> Result<()> {
+		self.secrets
+			.lock()
+			.unwrap()
+			.insert(name.to_string(), value.to_string());
+		Ok(())
+	}
+
+	fn delete(&self, name: &str) -> Result<bool> {
+		Ok(self.secrets.lock().unwrap().remove(name).is_some())
+	}
+
+	fn names(&self) -> Result<Vec<String>> {
+		Ok(self.secrets.lock().unwrap().keys().cloned().collect())
+	}
+}
+
+#[cfg(feature = "keychain")]
+mod keychain {
+	use super::SecretStore;
+	use anyhow::{Result, anyhow};
+
+	const SERVICE: &str = "puppynet";
+	/// Keychains can't list what a service has stored, so the names are
+	/// kept in an entry of their own.
+	const NAMES_ENTRY: &str = "puppynet.names";
+
+	pub struct KeychainSecretStore;
+
+	fn entry(name: &str) -> Result<keyring::Entry> {
+		keyring::Entry::new(SERVICE, name).map_err(|err| anyhow!("keychain: {err}"))
+	}
+
+	fn read(name: &str) -> Result<Option<String>> {
+		match entry(name)?.get_password() {
+			Ok(value) => Ok(Some(value)),
+			Err(keyring::Error::NoEntry) => Ok(None),
+			Err(err) => Err(anyhow!("keychain: {err}")),
+		}
+	}
+
+	impl KeychainSecretStore {
+		/// Fails when no keychain answers, e.g. no Secret Service is running.
+		pub fn connect() -> Result<Self> {
+			read(NAMES_ENTRY)?;
+			Ok(Self)
+		}
+
+		fn save_names(&self, names: &[String]) -> Result<()> {
+			entry(NAMES_ENTRY)?
+				.set_password(&serde_json::to_string(names)?)
+				.map_err(|err| anyhow!("keychain: {err}"))
+		}
+	}
+
+	impl SecretStore for KeychainSecretStore {
+		fn backend(&self) -> &'static str {
+			"keychain"
+		}
+
+		fn get(&self, name: &str) -> Result<Option<String>> {
+			read(name)
+		}
+
+		fn set(&self, name: &str, value: &str) -> Result<()> {
+			entry(name)?
+				.set_password(value)
+				.map_err(|err| anyhow!("keychain: {err}"))?;
+			let mut names = self.names()?;
+			if !names.iter().any(|known| known == name) {
+				names.push(name.to_string());
+				names.sort();
+				self.save_names(&names)?;
+			}
+			Ok(())
+		}
+
+		fn delete(&self, name: &str) -> Result<bool> {
+			let deleted = match entry(name)?.delete_credential() {
+				Ok(()) => true,
+				Err(keyring::Error::NoEntry) => false,
+				Err(err) => return Err(anyhow!("keychain: {err}")),
+			};
+			let mut names = self.names()?;
+			names.retain(|known| known != name);
+			self.save_names(&names)?;
+			Ok(deleted)
+		}
+
+		fn names(&self) -> Result<Vec<String>> {
+			match read(NAMES_ENTRY)? {
+				Some(value) => Ok(serde_json::from_str(&value)?),
+				None => Ok(Vec::new()),
+			}
+		}
+	}
+}
+
+/// A stored secret's name and where it is read from. Never the value.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecretInfo {
+	pub name: String,
+	/// "environment", or the backend holding it.
+	pub source: String,
+}
+
+/// The node's secrets: the environment in front of a [`SecretStore`].
+pub struct Secrets {
+	store: Box<dyn SecretStore>,
+	/// Secrets the environment sets, by name.
+	env: BTreeMap<String, String>,
+}
+
+/// Lowercase letters, digits and `_`, so a name maps to an env var.
+fn check_name(name: &str) -> Result<()> {
+	if name.is_empty()
+		|| !name
+			.chars()
+			.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+	{
+		bail!("invalid secret name {name:?}: use lowercase letters, digits and _");
+	}
+	Ok(())
+}
+
+fn env_secrets(vars: impl IntoIterator<Item = (String, String)>) -> BTreeMap<String, String> {
+	let mut secrets = BTreeMap::new();
+	for (key, value) in vars {
+		let value = value.trim();
+		if value.is_empty() {
+			continue;
+		}
+		let name = if key == LEGACY_JWT_ENV {
+			JWT_SECRET.to_string()
+		} else if key == SECRET_STORE_ENV {
+			// Shares the prefix but picks the backend.
+			continue;
+		} else if let Some(name) = key.strip_prefix(SECRET_ENV_PREFIX) {
+			name.to_ascii_lowercase()
+		} else {
+			continue;
+		};
+		// The prefixed variable wins over the legacy one.
+		if key == LEGACY_JWT_ENV && secrets.contains_key(&name) {
+			continue;
+		}
+		secrets.insert(name, value.to_string());
+	}
+	secrets
+}
+
+fn random_secret() -> String {
+	let mut bytes = [0u8; 32];
+	OsRng.fill_bytes(&mut bytes);
+	hex(&bytes)
+}
+
+/// The backend `selected` asks for, or the file when that can't be had.
+fn open_store(selected: SecretBackend, file: PathBuf) -> Box<dyn SecretStore> {
+	match selected {
+		SecretBackend::File => {}
+		#[cfg(feature = "keychain")]
+		SecretBackend::Auto | SecretBackend::Keychain => match keychain::KeychainSecretStore::connect() {
+			Ok(store) => return Box::new(store),
+			Err(err) => tracing::warn!(
+				"OS keychain unavailable ({err:#}); keeping secrets in {}",
+				file.display()
+			),
+		},
+		#[cfg(not(feature = "keychain"))]
+		SecretBackend::Auto => {}
+		#[cfg(not(feature = "keychain"))]
+		SecretBackend::Keychain => tracing::warn!(
+			"built without keychain support; keeping secrets in {}",
+			file.display()
+		),
+	}
+	Box::new(FileSecretStore::new(file))
+}
+
+/// The backend asked for by the environment, or else `stored` from the
+/// node settings.
+fn selected_backend(stored: Option<String>) -> SecretBackend {
+	let value = std::env::var(SECRET_STORE_ENV).ok().or(stored);
+	match value {
+		Some(value) => SecretBackend::parse(&value).unwrap_or_else(|| {
+			tracing::warn!("ignoring unknown secret store {value:?}");
+			SecretBackend::Auto
+		}),
+		None => SecretBackend::Auto,
+	}
+}
+
+fn stored_backend(conn: &Connection) -> Option<String> {
+	load_setting(conn, SECRET_STORE_SETTING).unwrap_or_else(|err| {
+		tracing::error!("failed to load secret store setting: {err}");
+		None
+	})
+}
+
+/// `secrets.json` next to the database.
+fn secrets_file() -> PathBuf {
+	db_location()
+		.parent()
+		.map(|dir| dir.join(SECRETS_FILE))
+		.unwrap_or_else(|| PathBuf::from(SECRETS_FILE))
+}
+
+impl Secrets {
+	/// `store` behind the secrets set in this process's environment.
+	pub fn new(store: Box<dyn SecretStore>) -> Self {
+		Self::with_env(store, std::env::vars())
+	}
+
+	fn with_env(
+		store: Box<dyn SecretStore>,
+		vars: impl IntoIterator<Item = (String, String)>,
+	) -> Self {
+		Self {
+			store,
+			env: env_secrets(vars),
+		}
+	}
+
+	/// The store picked by the environment or the node settings in `conn`.
+	pub(crate) fn open(conn: &Connection) -> Self {
+		Self::new(open_store(
+			selected_backend(stored_backend(conn)),
+			secrets_file(),
+		))
+	}
+
+	/// The store behind this node's database, for the CLI when no daemon
+	/// is running. Nothing is created if there is no database yet.
+	pub fn open_local() -> Self {
+		let stored = Connection::open_with_flags(db_location(), OpenFlags::SQLITE_OPEN_READ_ONLY)
+			.ok()
+			.and_then(|conn| load_setting(&conn, SECRET_STORE_SETTING).ok().flatten());
+		Self::new(open_store(selected_backend(stored), secrets_file()))
+	}
+
+	/// "file", "keychain" or "memory".
+	pub fn backend(&self) -> &'static str {
+		self.store.backend()
+	}
+
+	pub fn get(&self, name: &str) -> Result<Option<String>> {
+		check_name(name)?;
+		if let Some(value) = self.env.get(name) {
+			return Ok(Some(value.clone()));
+		}
+		self.store.get(name)
+	}
+
+	/// Stores `value` in the backend. A secret the environment sets keeps
+	/// reading from there.
+	pub fn set(&self, name: &str, value: &str) -> Result<()> {
+		check_name(name)?;
+		if value.is_empty() {
+			bail!("secret {name} can't be empty");
+		}
+		self.store.set(name, value)
+	}
+
+	pub fn delete(&self, name: &str) -> Result<bool> {
+		check_name(name)?;
+		self.store.delete(name)
+	}
+
+	/// Every secret there is, by name.
+	pub fn list(&self) -> Result<Vec<SecretInfo>> {
+		let mut secrets = self
+			.store
+			.names()?
+			.into_iter()
+			.map(|name| (name, self.store.backend()))
+			.collect::<BTreeMap<_, _>>();
+		for name in self.env.keys() {
+			secrets.insert(name.clone(), ENV_SOURCE);
+		}
+		Ok(secrets
+			.into_iter()
+			.map(|(name, source)| SecretInfo {
+				name,
+				source: source.to_string(),
+			})
+			.collect())
+	}
+
+	/// The secret `name`, generated and stored when there is none yet. If
+	/// it can't be stored, the generated one lasts until the process exits.
+	/// A store that fails to read also gets a value that lasts until exit,
+	/// so a stored secret is never replaced because of a passing error.
+	pub(crate) fn get_or_generate(&self, name: &str) -> String {
+		match self.get(name) {
+			Ok(Some(value)) => return value,
+			Ok(None) => {}
+			Err(err) => {
+				tracing::error!(
+					"failed to read secret {name}; using a new one until exit: {err:#}"
+				);
+				return random_secret();
+			}
+		}
+		let value = random_secret();
+		match self.set(name, &value) {
+			Ok(()) => tracing::info!("generated {name} in the {} store", self.backend()),
+			Err(err) => tracing::warn!("failed to store {name}; using it until exit: {err:#}"),
+		}
+		value
+	}
+
+	/// Copies every stored secret `other` lacks over to it.
+	fn copy_to(&self, other: &dyn SecretStore) -> Result<usize> {
+		let mut copied = 0;
+		for name in self.store.names()? {
+			if other.get(&name)?.is_some() {
+				continue;
+			}
+			let value = self
+				.store
+				.get(&name)?
+				.ok_or_else(|| anyhow!("secret {name} disappeared while copying"))?;
+			other.set(&name, &value)?;
+			copied += 1;
+		}
+		Ok(copied)
+	}
+
+	/// Stores `backend` as the node's secret store and copies the secrets
+	/// over to it, returning how many were copied. Takes effect on the
+	/// next start.
+	pub(crate) fn switch_backend(
+		&self,
+		conn: &Connection,
+		backend: SecretBackend,
+	) -> Result<usize> {
+		let target = open_store(backend, secrets_file());
+		let copied = if target.backend() == self.backend() {
+			0
+		} else {
+			self.copy_to(target.as_ref())?
+		};
+		save_setting(conn, SECRET_STORE_SETTING, backend.as_str())?;
+		if std::env::var_os(SECRET_STORE_ENV).is_some() {
+			tracing::warn!("{SECRET_STORE_ENV} is set and overrides the secret store setting");
+		}
+		Ok(copied)
+	}
+
+	/// [`Self::switch_backend`] on this node's database, for the CLI when
+	/// no daemon is running.
+	pub fn switch_local_backend(&self, backend: SecretBackend) -> Result<usize> {
+		let mut conn = open_db();
+		run_migrations(&mut conn)?;
+		self.switch_backend(&conn, backend)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::test_dir;
+	use std::sync::Arc;
+
+	fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+		vars.iter()
+			.map(|(key, value)| (key.to_string(), value.to_string()))
+			.collect()
+	}
+
+	#[test]
+	fn the_environment_wins_and_list_never_shows_values() {
+		let secrets = Secrets::with_env(
+			Box::new(MemorySecretStore::default()),
+			env(&[
+				("JWT_SECRET", "legacy"),
+				("PUPPYNET_SECRET_SHARE_SALT", "salty"),
+				("PUPPYNET_SECRET_EMPTY", "  "),
+				("PUPPYNET_SECRET_STORE", "file"),
+				("HOME", "/home/me"),
+			]),
+		);
+		secrets.set(JWT_SECRET, "stored").unwrap();
+		secrets.set("http_proxy_credentials", "hunter2").unwrap();
+
+		assert_eq!(secrets.get(JWT_SECRET).unwrap().as_deref(), Some("legacy"));
+		assert_eq!(secrets.get("share_salt").unwrap().as_deref(), Some("salty"));
+		assert_eq!(secrets.get("empty").unwrap(), None);
+		let listed = secrets.list().unwrap();
+		assert_eq!(
+			listed
+				.iter()
+				.map(|info| (info.name.as_str(), info.source.as_str()))
+				.collect::<Vec<_>>(),
+			vec![
+				("http_proxy_credentials", "memory"),
+				(JWT_SECRET, ENV_SOURCE),
+				("share_salt", ENV_SOURCE),
+			]
+		);
+		let json = serde_json::to_string(&listed).unwrap();
+		assert!(!json.contains("hunter2") && !json.contains("legacy"));
+		assert!(secrets.set("Bad-Name", "x").is_err());
+	}
+
+	#[test]
+	fn a_generated_secret_is_kept() {
+		let secrets = Secrets::with_env(Box::new(MemorySecretStore::default()), Vec::new());
+		let first = secrets.get_or_generate(JWT_SECRET);
+		assert_eq!(first.len(), 64);
+		assert_eq!(secrets.get_or_generate(JWT_SECRET), first);
+		assert!(secrets.delete(JWT_SECRET).unwrap());
+		assert_ne!(secrets.get_or_generate(JWT_SECRET), first);
+	}
+
+	/// Fails every read, like a locked keychain.
+	struct UnreadableStore(Arc<MemorySecretStore>);
+
+	impl SecretStore for UnreadableStore {
+		fn backend(&self) -> &'static str {
+			"unreadable"
+		}
+
+		fn get(&self, _name: &str) -> Result<Option<String>> {
+			Err(anyhow!("store is locked"))
+		}
+
+		fn set(&self, name: &str, value: &str) -> Result<()> {
+			self.0.set(name, value)
+		}
+
+		fn delete(&self, name: &str) -> Result<bool> {
+			self.0.delete(name)
+		}
+
+		fn names(&self) -> Result<Vec<String>> {
+			self.0.names()
+		}
+	}
+
+	#[test]
+	fn a_failed_read_does_not_replace_the_stored_secret() {
+		let store = Arc::new(MemorySecretStore::default());
+		store.set(JWT_SECRET, "stored").unwrap();
+		let secrets = Secrets::with_env(Box::new(UnreadableStore(Arc::clone(&store))), Vec::new());
+		assert_ne!(secrets.get_or_generate(JWT_SECRET), "stored");
+		assert_eq!(store.get(JWT_SECRET).unwrap().as_deref(), Some("stored"));
+	}
+
+	#[test]
+	fn the_file_store_round_trips_and_is_private() {
+		let dir = test_dir("secrets-file");
+		let path = dir.join(SECRETS_FILE);
+		let store = FileSecretStore::new(path.clone());
+		assert_eq!(store.get(JWT_SECRET).unwrap(), None);
+		store.set(JWT_SECRET, "one").unwrap();
+		store.set("share_salt", "two").unwrap();
+
+		let reopened = FileSecretStore::new(path.clone());
+		assert_eq!(reopened.get(JWT_SECRET).unwrap().as_deref(), Some("one"));
+		assert_eq!(reopened.names().unwrap(), vec![JWT_SECRET, "share_salt"]);
+		assert!(reopened.delete("share_salt").unwrap());
+		assert!(!reopened.delete("share_salt").unwrap());
+		assert_eq!(store.names().unwrap(), vec![JWT_SECRET]);
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+			assert_eq!(mode & 0o777, 0o600);
+		}
+
+		let memory = MemorySecretStore::default();
+		memory.set(JWT_SECRET, "kept").unwrap();
+		let secrets = Secrets::with_env(Box::new(reopened), Vec::new());
+		assert_eq!(secrets.copy_to(&memory).unwrap(), 0);
+		assert_eq!(memory.get(JWT_SECRET).unwrap().as_deref(), Some("kept"));
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -0,0 +1,443 @@
+//! What a peer would find in the folders another node may search: per
+//! folder the indexed file count and size, its top-level subdirectories,
+//! what kind of files it mostly holds and when it last changed. A node
+//! answering `ShareSummary` counts only its own indexed files the asker may
+//! search, judged file by file as search results are, so a subfolder
+//! excluded from a grant adds nothing, not even its name. Answers are
+//! capped in folders, subdirectories and files counted, and the asker
+//! reuses one for [`SHARE_SUMMARY_TTL`].
+
+use crate::db::{self, NodeID};
+use crate::format::{SizeUnits, group_digits, human_size, relative_time};
+use crate::index::PeerAccess;
+use crate::scan::FileLocation;
+use crate::state::{FLAG_SEARCH, Permission, Rule};
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Component, Path};
+
+/// Folders one answer summarizes at most.
+pub const SHARE_SUMMARY_MAX_ROOTS: usize = 16;
+/// Subdirectories one folder's summary names at most.
+pub const SHARE_SUMMARY_MAX_SUBDIRS: usize = 12;
+/// Kinds of files one folder's summary names at most.
+pub const SHARE_SUMMARY_MAX_CATEGORIES: usize = 3;
+/// Indexed files counted per folder before the count stops short.
+pub const SHARE_SUMMARY_MAX_FILES: u64 = 100_000;
+/// How long a summary fetched from a peer is shown before asking again.
+pub const SHARE_SUMMARY_TTL: chrono::Duration = chrono::Duration::minutes(10);
+
+/// Files of one kind in a summarized folder. `kind` is the top-level mime
+/// type, such as `image`, or `other` for files without one.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MimeCategory {
+	pub kind: String,
+	pub files: u64,
+}
+
+/// What a folder shared with the asker holds, as far as the index knows.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShareSummary {
+	pub root: String,
+	pub files: u64,
+	pub bytes: u64,
+	/// Top-level subdirectories holding files, most files first.
+	pub subdirs: Vec<String>,
+	/// Most common kinds of files, most first.
+	pub categories: Vec<MimeCategory>,
+	/// Latest modification time of a counted file.
+	pub latest: Option<DateTime<Utc>>,
+	/// Counting stopped at [`SHARE_SUMMARY_MAX_FILES`].
+	pub truncated: bool,
+}
+
+impl ShareSummary {
+	/// "mostly images", or the leading kinds when none is the majority.
+	pub fn content(&self) -> Option<String> {
+		let top = self.categories.first()?;
+		if top.files * 2 > self.files {
+			return Some(format!("mostly {}", category_label(&top.kind)));
+		}
+		let kinds = self
+			.categories
+			.iter()
+			.map(|category| category_label(&category.kind))
+			.collect::<Vec<_>>();
+		Some(kinds.join(", "))
+	}
+
+	/// "48,120 files, 210.00 GiB, mostly images, updated 1 day ago".
+	pub fn describe(&self, now: DateTime<Utc>) -> String {
+		let more = if self.truncated { "+" } else { "" };
+		let mut parts = vec![
+			format!("{}{more} files", group_digits(self.files)),
+			human_size(self.bytes, SizeUnits::Binary),
+		];
+		parts.extend(self.content());
+		if let Some(latest) = self.latest {
+			parts.push(format!("updated {}", relative_time(latest, now)));
+		}
+		parts.join(", ")
+	}
+}
+
+fn category_label(kind: &str) -> &str {
+	match kind {
+		"image" => "images",
+		"video" => "videos",
+		"audio" => "audio",
+		"text" => "text files",
+		"application" => "documents",
+		_ => "other files",
+	}
+}
+
+/// Summary of `root` from `files`, the indexed files under it, counting
+/// only those `visible` allows. `files` holding more than `limit` marks the
+/// summary truncated.
+pub(crate) fn summarize_files(
+	root: &Path,
+	files: Vec<FileLocation>,
+	limit: u64,
+	visible: impl Fn(&Path) -> bool,
+) -> ShareSummary {
+	let truncated = files.len() as u64 > limit;
+	let mut summary = ShareSummary {
+		root: root.to_string_lossy().into_owned(),
+		files: 0,
+		bytes: 0,
+		subdirs: Vec::new(),
+		categories: Vec::new(),
+		latest: None,
+		truncated,
+	};
+	let mut subdirs = HashMap::<String, u64>::new();
+	let mut categories = HashMap::<String, u64>::new();
+	for file in files.into_iter().take(limit as usize) {
+		if !visible(&file.path) {
+			continue;
+		}
+		summary.files += 1;
+		summary.bytes += file.size;
+		summary.latest = summary.latest.max(file.modified_at);
+		if let Ok(rest) = file.path.strip_prefix(root) {
+			let mut components = rest.components();
+			if let (Some(Component::Normal(dir)), Some(_)) = (components.next(), components.next())
+			{
+				*subdirs
+					.entry(dir.to_string_lossy().into_owned())
+					.or_default() += 1;
+			}
+		}
+		let kind = file
+			.mime_type
+			.as_deref()
+			.and_then(|mime| mime.split('/').next())
+			.filter(|kind| !kind.is_empty())
+			.unwrap_or("other");
+		*categories.entry(kind.to_string()).or_default() += 1;
+	}
+	summary.subdirs = most_first(subdirs, SHARE_SUMMARY_MAX_SUBDIRS)
+		.into_iter()
+		.map(|(dir, _)| dir)
+		.collect();
+	summary.categories = most_first(categories, SHARE_SUMMARY_MAX_CATEGORIES)
+		.into_iter()
+		.map(|(kind, files)| MimeCategory { kind, files })
+		.collect();
+	summary
+}
+
+/// The `cap` largest counts, ties by name.
+fn most_first(counts: HashMap<String, u64>, cap: usize) -> Vec<(String, u64)> {
+	let mut counts = counts.into_iter().collect::<Vec<_>>();
+	counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+	counts.truncate(cap);
+	counts
+}
+
+/// Summaries of the folders `peer` may search, from the files `node_id`
+/// has indexed there.
+pub(crate) fn summarize(
+	conn: &Connection,
+	access: &impl PeerAccess,
+	peer: PeerId,
+	node_id: &NodeID,
+	now: DateTime<Utc>,
+) -> anyhow::Result<Vec<ShareSummary>> {
+	let mut roots = access.roots(peer, FLAG_SEARCH);
+	roots.retain(|root| access.may_access(peer, root, FLAG_SEARCH, now));
+	roots.truncate(SHARE_SUMMARY_MAX_ROOTS);
+	let mut summaries = Vec::with_capacity(roots.len());
+	for root in roots {
+		let files = db::indexed_files_under(
+			conn,
+			node_id,
+			&root.to_string_lossy(),
+			SHARE_SUMMARY_MAX_FILES + 1,
+		)?;
+		summaries.push(summarize_files(
+			&root,
+			files,
+			SHARE_SUMMARY_MAX_FILES,
+			|path| access.may_access(peer, path, FLAG_SEARCH, now),
+		));
+	}
+	Ok(summaries)
+}
+
+/// A folder a peer shares with this node, and its summary when the peer
+/// gave one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ShareCard {
+	pub(crate) root: String,
+	pub(crate) summary: Option<ShareSummary>,
+}
+
+/// Cards for the folders a peer shares: its summaries when it answered, or
+/// else the folders of its grants, without figures.
+pub(crate) fn share_cards(
+	summaries: Option<&[ShareSummary]>,
+	grants: &[Permission],
+) -> Vec<ShareCard> {
+	if let Some(summaries) = summaries {
+		return summaries
+			.iter()
+			.map(|summary| ShareCard {
+				root: summary.root.clone(),
+				summary: Some(summary.clone()),
+			})
+			.collect();
+	}
+	let mut roots = grants
+		.iter()
+		.filter_map(|permission| match permission.rule() {
+			Rule::Folder(folder) => Some(folder.path().to_string_lossy().into_owned()),
+			_ => None,
+		})
+		.collect::<Vec<_>>();
+	roots.sort();
+	roots.dedup();
+	roots
+		.into_iter()
+		.map(|root| ShareCard {
+			root,
+			summary: None,
+		})
+		.collect()
+}
+
+/// Summaries fetched from peers, reused for [`SHARE_SUMMARY_TTL`].
+#[derive(Default)]
+pub(crate) struct ShareSummaryCache {
+	entries: HashMap<PeerId, (DateTime<Utc>, Vec<ShareSummary>)>,
+}
+
+impl ShareSummaryCache {
+	pub(crate) fn get(&self, peer: &PeerId, now: DateTime<Utc>) -> Option<Vec<ShareSummary>> {
+		self.entries
+			.get(peer)
+			.filter(|(fetched_at, _)| now - *fetched_at < SHARE_SUMMARY_TTL)
+			.map(|(_, summaries)| summaries.clone())
+	}
+
+	pub(crate) fn store(&mut self, peer: PeerId, summaries: Vec<ShareSummary>, now: DateTime<Utc>) {
+		self.entries.insert(peer, (now, summaries));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::state::{FLAG_READ, FolderRule, State};
+	use crate::test_util;
+	use rusqlite::params;
+	use std::path::PathBuf;
+
+	const HERE: NodeID = [7; 16];
+
+	fn file(path: &str, size: u64, mime: Option<&str>, day: u32) -> FileLocation {
+		FileLocation {
+			path: PathBuf::from(path),
+			hash: Some([size as u8; 32]),
+			size,
+			mime_type: mime.map(str::to_string),
+			timestamp: Utc::now(),
+			created_at: None,
+			modified_at: DateTime::parse_from_rfc3339(&format!("2026-03-{day:02}T12:00:00Z"))
+				.ok()
+				.map(|at| at.with_timezone(&Utc)),
+			accessed_at: None,
+			blocks: Vec::new(),
+		}
+	}
+
+	fn index(conn: &Connection, files: &[FileLocation]) {
+		for (i, file) in files.iter().enumerate() {
+			let hash = vec![i as u8; 32];
+			conn.execute(
+				"INSERT INTO file_entries (hash, size, mime_type) VALUES (?1, ?2, ?3)",
+				params![hash, file.size as i64, file.mime_type],
+			)
+			.unwrap();
+			conn.execute(
+				"INSERT INTO file_locations (node_id, path, hash, size, timestamp, modified_at)
+				 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+				params![
+					HERE.to_vec(),
+					file.path.to_string_lossy(),
+					hash,
+					file.size as i64,
+					file.timestamp,
+					file.modified_at
+				],
+			)
+			.unwrap();
+		}
+	}
+
+	#[test]
+	fn summaries_count_only_files_the_peer_may_search() {
+		let conn = test_util::memory_db();
+		index(
+			&conn,
+			&[
+				file("/srv/photos/2025/beach.jpg", 4_000, Some("image/jpeg"), 3),
+				file("/srv/photos/2025/dune.jpg", 6_000, Some("image/jpeg"), 9),
+				file("/srv/photos/clip.mp4", 50_000, Some("video/mp4"), 1),
+				file(
+					"/srv/photos/private/tax.pdf",
+					900,
+					Some("application/pdf"),
+					20,
+				),
+				file("/srv/music/song.flac", 30_000, Some("audio/flac"), 2),
+			],
+		);
+		let mut state = State::default();
+		let peer = PeerId::random();
+		state.add_shared_folder(FolderRule::new(
+			PathBuf::from("/srv"),
+			FLAG_READ | FLAG_SEARCH,
+		));
+		state.set_peer_permissions(
+			peer,
+			vec![
+				Permission::new(Rule::Folder(FolderRule::new(
+					PathBuf::from("/srv/photos"),
+					FLAG_READ | FLAG_SEARCH,
+				))),
+				Permission::new(Rule::Folder(FolderRule::new(
+					PathBuf::from("/srv/photos/private"),
+					FLAG_READ,
+				))),
+				Permission::new(Rule::Folder(FolderRule::new(
+					PathBuf::from("/srv/music"),
+					FLAG_READ,
+				))),
+			],
+		);
+
+		let summaries = summarize(&conn, &state, peer, &HERE, Utc::now()).unwrap();
+		assert_eq!(summaries.len(), 1, "{summaries:?}");
+		let photos = &summaries[0];
+		assert_eq!(photos.root, "/srv/photos");
+		assert_eq!((photos.files, photos.bytes), (3, 60_000));
+		assert_eq!(photos.subdirs, ["2025"]);
+		assert_eq!(
+			photos.categories,
+			[
+				MimeCategory {
+					kind: String::from("image"),
+					files: 2,
+				},
+				MimeCategory {
+					kind: String::from("video"),
+					files: 1,
+				},
+			]
+		);
+		assert_eq!(
+			photos.latest.map(|at| at.to_rfc3339()),
+			Some(String::from("2026-03-09T12:00:00+00:00"))
+		);
+		assert!(!photos.truncated);
+		let now = DateTime::parse_from_rfc3339("2026-03-10T12:00:00Z")
+			.unwrap()
+			.with_timezone(&Utc);
+		assert_eq!(
+			photos.describe(now),
+			"3 files, 58.59 KiB, mostly images, updated 1 day ago"
+		);
+
+		let stranger = PeerId::random();
+		assert!(
+			summarize(&conn, &state, stranger, &HERE, Utc::now())
+				.unwrap()
+				.is_empty()
+		);
+	}
+
+	#[test]
+	fn counting_stops_at_the_cap() {
+		let files = (0..5)
+			.map(|i| file(&format!("/srv/docs/{i}.txt"), 10, Some("text/plain"), 1))
+			.collect();
+		let summary = summarize_files(Path::new("/srv/docs"), files, 3, |_| true);
+		assert_eq!(summary.files, 3);
+		assert!(summary.truncated);
+		assert!(summary.describe(Utc::now()).starts_with("3+ files"));
+	}
+
+	#[test]
+	fn peers_without_summaries_show_their_granted_folders() {
+		let grants = vec![
+			Permission::new(Rule::Folder(FolderRule::new(
+				PathBuf::from("/media/photos"),
+				FLAG_READ,
+			))),
+			Permission::new(Rule::Inbox),
+			Permission::new(Rule::Folder(FolderRule::new(
+				PathBuf::from("/home/ana/docs"),
+				FLAG_SEARCH,
+			))),
+		];
+		let cards = share_cards(None, &grants);
+		assert_eq!(
+			cards,
+			[
+				ShareCard {
+					root: String::from("/home/ana/docs"),
+					summary: None,
+				},
+				ShareCard {
+					root: String::from("/media/photos"),
+					summary: None,
+				},
+			]
+		);
+
+		let summary = summarize_files(Path::new("/media/photos"), Vec::new(), 10, |_| true);
+		let cards = share_cards(Some(std::slice::from_ref(&summary)), &grants);
+		assert_eq!(cards.len(), 1);
+		assert_eq!(cards[0].summary.as_ref(), Some(&summary));
+		assert_eq!(summary.describe(Utc::now()), "0 files, 0 B");
+	}
+
+	#[test]
+	fn cached_summaries_expire_after_the_ttl() {
+		let mut cache = ShareSummaryCache::default();
+		let peer = PeerId::random();
+		let now = Utc::now();
+		cache.store(peer, Vec::new(), now);
+		assert_eq!(
+			cache.get(&peer, now + chrono::Duration::minutes(9)),
+			Some(Vec::new())
+		);
+		assert_eq!(cache.get(&peer, now + SHARE_SUMMARY_TTL), None);
+		assert_eq!(cache.get(&PeerId::random(), now), None);
+	}
+}
@@ -0,0 +1,112 @@
+//! Holes of sparse files. A sparse read tells the holes of a file apart
+//! where the platform can (`SEEK_DATA`/`SEEK_HOLE` on Linux), so a transfer
+//! sends their length instead of their zeros, and [`SparseSink`] seeks over
+//! them again when writing the copy. Anywhere else every byte is data and
+//! the transfer is dense; the content and its hash are the same either way.
+
+use crate::types::FileChunk;
+use std::io::{self, SeekFrom};
+use tokio::fs;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+/// Zeros hashed for a hole, a block at a time.
+static ZEROS: [u8; 64 * 1024] = [0; 64 * 1024];
+
+/// What a file holds from an offset on, and for how many bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Extent {
+	Data(u64),
+	Hole(u64),
+}
+
+/// The extent of `file`, `len` bytes long, at `offset`. Moves the file's
+/// position, so the caller seeks before reading.
+#[cfg(target_os = "linux")]
+pub(crate) fn extent_at(file: &impl std::os::fd::AsRawFd, offset: u64, len: u64) -> Extent {
+	use libc::{ENXIO, SEEK_DATA, SEEK_HOLE, lseek, off_t};
+	if offset >= len {
+		return Extent::Data(0);
+	}
+	let Ok(start) = off_t::try_from(offset) else {
+		return Extent::Data(len - offset);
+	};
+	let fd = file.as_raw_fd();
+	let data = unsafe { lseek(fd, start, SEEK_DATA) };
+	if data < 0 {
+		// No data follows: the rest of the file is a hole. Anything else
+		// is a file system that can't say, which is read as all data.
+		return match io::Error::last_os_error().raw_os_error() {
+			Some(ENXIO) => Extent::Hole(len - offset),
+			_ => Extent::Data(len - offset),
+		};
+	}
+	let data = (data as u64).min(len);
+	if data > offset {
+		return Extent::Hole(data - offset);
+	}
+	let hole = unsafe { lseek(fd, start, SEEK_HOLE) };
+	if hole < 0 {
+		return Extent::Data(len - offset);
+	}
+	Extent::Data((hole as u64).clamp(offset + 1, len) - offset)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn extent_at<F>(_file: &F, offset: u64, len: u64) -> Extent {
+	Extent::Data(len.saturating_sub(offset))
+}
+
+/// Bytes the file takes on disk, when the platform says.
+pub(crate) fn disk_size(meta: &std::fs::Metadata) -> Option<u64> {
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::MetadataExt;
+		Some(meta.blocks().saturating_mul(512))
+	}
+	#[cfg(not(unix))]
+	{
+		let _ = meta;
+		None
+	}
+}
+
+/// Writes a file from its chunks, read in order from its start, leaving
+/// holes where the chunks have them, and hashes the dense content.
+pub(crate) struct SparseSink {
+	file: fs::File,
+	hasher: blake3::Hasher,
+	len: u64,
+}
+
+impl SparseSink {
+	pub(crate) fn new(file: fs::File) -> Self {
+		Self {
+			file,
+			hasher: blake3::Hasher::new(),
+			len: 0,
+		}
+	}
+
+	pub(crate) async fn write(&mut self, chunk: &FileChunk) -> io::Result<()> {
+		self.file.write_all(&chunk.data).await?;
+		self.hasher.update(&chunk.data);
+		self.len += chunk.span();
+		if chunk.hole > 0 {
+			self.file.seek(SeekFrom::Start(self.len)).await?;
+			let mut left = chunk.hole;
+			while left > 0 {
+				let zeros = left.min(ZEROS.len() as u64) as usize;
+				self.hasher.update(&ZEROS[..zeros]);
+				left -= zeros as u64;
+			}
+		}
+		Ok(())
+	}
+
+	/// Ends the file, a hole at its end included, and returns its hash.
+	pub(crate) async fn finish(mut self) -> io::Result<Vec<u8>> {
+		self.file.flush().await?;
+		self.file.set_len(self.len).await?;
+		Ok(self.hasher.finalize().as_bytes().to_vec())
+	}
+}
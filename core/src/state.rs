@@ -1,24 +1,111 @@
-use crate::auth;
-use anyhow::bail;
-use libp2p::{Multiaddr, PeerId, swarm::ConnectionId};
+use crate::access_volume::AccessAlert;
+use crate::checksum_manifest::matches_pattern;
+use crate::clock_skew::ClockOffset;
+use crate::format::relative_time;
+use crate::identity::IdentityMismatch;
+use crate::index_announce::IndexFreshness;
+use crate::maintenance::Maintenance;
+use crate::mounts::{ShareAvailability, ShareChange};
+use crate::nat::NatStatus;
+use crate::p2p::{
+	ACCESS_DENIED, EntryAccess, ListingAccess, PeerCapabilities, PeerReq, RequestScope,
+};
+use crate::pairing::Pairing;
+use crate::power::PowerState;
+use crate::reachability::AddressReachability;
+use chrono::{DateTime, Utc};
+use libp2p::swarm::{ConnectionError, ConnectionId};
+use libp2p::{Multiaddr, PeerId, multiaddr::Protocol};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
 pub const FLAG_READ: u8 = 0x01;
 pub const FLAG_WRITE: u8 = 0x02;
 pub const FLAG_EXECUTE: u8 = 0x04;
 pub const FLAG_SEARCH: u8 = 0x08;
+/// Writes through the rule land but wait in the review queue until the
+/// owner accepts or rejects them. Grants no access by itself.
+pub const FLAG_QUARANTINE: u8 = 0x10;
+/// Listings and size-capped thumbnails without reading the files.
+/// [`FLAG_READ`] implies it.
+pub const FLAG_PREVIEW: u8 = 0x20;
+/// The rule came from pairing rather than from the owner, so the
+/// permission editor can say so. Grants no access by itself.
+pub const FLAG_PAIRED: u8 = 0x40;
+const MAX_NOTIFICATIONS: usize = 100;
+/// How far back closed connections count towards a peer's drop count.
+const CONNECTION_DROP_WINDOW_SECS: i64 = 60 * 60;
+/// How long an expired temporary grant is remembered, so denials in that
+/// window can explain that access lapsed.
+const LAPSED_GRANT_MEMORY_SECS: i64 = 24 * 60 * 60;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Deny patterns offered for a new share peers may write to, for the
+/// files that should never leave the machine.
+pub const SUGGESTED_DENY_PATTERNS: [&str; 8] = [
+	"*.key",
+	"*.pem",
+	"*.p12",
+	"*.pfx",
+	".env",
+	".env.*",
+	"id_rsa*",
+	"id_ed25519*",
+];
+
+/// What is wrong with a deny or allow pattern, if anything. Patterns are
+/// file names, or paths relative to the folder when they contain a `/`,
+/// with `*` and `?` as wildcards.
+pub fn pattern_error(pattern: &str) -> Option<&'static str> {
+	if pattern.starts_with(['/', '\\']) {
+		Some("patterns are relative to the folder")
+	} else if pattern.contains(['[', ']', '{', '}']) {
+		Some("only * and ? work as wildcards")
+	} else if pattern.split(['/', '\\']).any(|part| part == "..") {
+		Some("patterns can't leave the folder")
+	} else if pattern.contains(['\n', '\0']) {
+		Some("patterns can't contain line breaks")
+	} else {
+		None
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct FolderRule {
 	path: PathBuf,
 	flags: u8,
+	/// Files and folders peers never see under the folder, whatever the
+	/// allow patterns say.
+	#[serde(default)]
+	deny_patterns: Vec<String>,
+	/// When set, the only files peers see under the folder. Folders stay
+	/// visible so the files in them can be reached.
+	#[serde(default)]
+	allow_patterns: Vec<String>,
 }
 
 impl FolderRule {
 	pub fn new(path: PathBuf, flags: u8) -> Self {
-		Self { path, flags }
+		Self {
+			path,
+			flags,
+			deny_patterns: Vec::new(),
+			allow_patterns: Vec::new(),
+		}
+	}
+
+	pub fn with_patterns(mut self, deny: Vec<String>, allow: Vec<String>) -> Self {
+		self.deny_patterns = deny;
+		self.allow_patterns = allow;
+		self
+	}
+
+	/// The rule for `path` with this rule's flags and patterns.
+	pub fn with_path(&self, path: PathBuf) -> Self {
+		Self {
+			path,
+			..self.clone()
+		}
 	}
 
 	pub fn path(&self) -> &Path {
@@ -45,6 +132,84 @@ impl FolderRule {
 		self.flags & FLAG_SEARCH != 0
 	}
 
+	pub fn quarantines(&self) -> bool {
+		self.flags & FLAG_QUARANTINE != 0
+	}
+
+	pub fn can_preview(&self) -> bool {
+		self.flags & (FLAG_READ | FLAG_PREVIEW) != 0
+	}
+
+	pub fn deny_patterns(&self) -> &[String] {
+		&self.deny_patterns
+	}
+
+	pub fn allow_patterns(&self) -> &[String] {
+		&self.allow_patterns
+	}
+
+	/// Whether the patterns hide `path`, somewhere under the folder, from
+	/// peers. Deny patterns are checked against every folder on the way
+	/// down, so a denied folder hides all it holds; allow patterns only
+	/// against files.
+	pub fn hides(&self, path: &Path, is_dir: bool) -> bool {
+		if self.deny_patterns.is_empty() && self.allow_patterns.is_empty() {
+			return false;
+		}
+		let root = rule_components(&self.path);
+		let path = rule_components(path);
+		let Some(relative) = path.strip_prefix(root.as_slice()) else {
+			return false;
+		};
+		let Some(name) = relative.last() else {
+			return false;
+		};
+		let denied = (1..=relative.len()).any(|depth| {
+			let joined = relative[..depth].join("/");
+			self.deny_patterns.iter().any(|pattern| {
+				if pattern.contains('/') {
+					matches_pattern(&joined, pattern)
+				} else {
+					matches_pattern(&relative[depth - 1], pattern)
+				}
+			})
+		});
+		if denied {
+			return true;
+		}
+		if is_dir || self.allow_patterns.is_empty() {
+			return false;
+		}
+		let joined = relative.join("/");
+		!self.allow_patterns.iter().any(|pattern| {
+			if pattern.contains('/') {
+				matches_pattern(&joined, pattern)
+			} else {
+				matches_pattern(name, pattern)
+			}
+		})
+	}
+
+	/// Adds `other`, a rule naming the same folder: the flags and deny
+	/// patterns of both, and allow patterns only while both narrow.
+	fn combine(&mut self, other: &FolderRule) {
+		self.flags |= other.flags;
+		for pattern in &other.deny_patterns {
+			if !self.deny_patterns.contains(pattern) {
+				self.deny_patterns.push(pattern.clone());
+			}
+		}
+		if other.allow_patterns.is_empty() {
+			self.allow_patterns.clear();
+		} else if !self.allow_patterns.is_empty() {
+			for pattern in &other.allow_patterns {
+				if !self.allow_patterns.contains(pattern) {
+					self.allow_patterns.push(pattern.clone());
+				}
+			}
+		}
+	}
+
 	pub fn allows(&self, access: u8) -> bool {
 		if access & FLAG_READ != 0 && !self.can_read() {
 			return false;
@@ -58,17 +223,505 @@ impl FolderRule {
 		if access & FLAG_SEARCH != 0 && !self.can_search() {
 			return false;
 		}
+		if access & FLAG_PREVIEW != 0 && !self.can_preview() {
+			return false;
+		}
 		true
 	}
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Splits a rule path into comparable components. Drive-letter and UNC
+/// paths split on both `/` and `\` and compare case-insensitively like
+/// Windows does; elsewhere a backslash is part of a name, so
+/// `/srv/share\..\x`, a file in `/srv`, never nests in `/srv/share`.
+fn rule_components(path: &Path) -> Vec<String> {
+	let text = path.to_string_lossy();
+	let bytes = text.as_bytes();
+	let windows = (bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':')
+		|| text.starts_with("\\\\");
+	let separators: &[char] = if windows { &['/', '\\'] } else { &['/'] };
+	let mut components = Vec::new();
+	if text.starts_with(separators) {
+		components.push(String::from("/"));
+	}
+	components.extend(
+		text.split(separators)
+			.filter(|part| !part.is_empty() && *part != ".")
+			.map(|part| {
+				if windows {
+					part.to_lowercase()
+				} else {
+					part.to_string()
+				}
+			}),
+	);
+	components
+}
+
+/// Depth of `rule` when it is `path` or one of its ancestors.
+fn rule_depth(rule: &Path, path: &Path) -> Option<usize> {
+	let rule = rule_components(rule);
+	let path = rule_components(path);
+	path.starts_with(&rule).then_some(rule.len())
+}
+
+/// The rule deciding access to `path`: the most specific matching rule
+/// (longest path prefix) wins regardless of order. Rules naming the same
+/// folder tie and are combined, see [`FolderRule::combine`].
+pub fn effective_folder_rule<'a>(
+	rules: impl IntoIterator<Item = &'a FolderRule>,
+	path: &Path,
+) -> Option<FolderRule> {
+	let mut best: Option<(usize, FolderRule)> = None;
+	for rule in rules {
+		let Some(depth) = rule_depth(rule.path(), path) else {
+			continue;
+		};
+		match &mut best {
+			Some((best_depth, best_rule)) if *best_depth == depth => {
+				best_rule.combine(rule);
+			}
+			Some((best_depth, _)) if *best_depth > depth => {}
+			_ => best = Some((depth, rule.clone())),
+		}
+	}
+	best.map(|(_, rule)| rule)
+}
+
+/// One rule per distinct folder with tied rules merged, sorted by path.
+pub fn effective_folder_rules(rules: &[FolderRule]) -> Vec<FolderRule> {
+	let mut merged: Vec<FolderRule> = Vec::new();
+	for rule in rules {
+		match merged
+			.iter_mut()
+			.find(|existing| rule_components(existing.path()) == rule_components(rule.path()))
+		{
+			Some(existing) => existing.combine(rule),
+			None => merged.push(rule.clone()),
+		}
+	}
+	merged.sort_by(|a, b| a.path().cmp(b.path()));
+	merged
+}
+
+/// Two rules where `inner` is nested in (or names the same folder as)
+/// `outer` with different flags.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RuleOverlap {
+	pub outer: FolderRule,
+	pub inner: FolderRule,
+}
+
+fn access_summary(flags: u8) -> &'static str {
+	if flags & FLAG_WRITE != 0 {
+		"read-write"
+	} else if flags & FLAG_READ != 0 {
+		"read-only"
+	} else if flags & FLAG_PREVIEW != 0 {
+		"preview-only"
+	} else if flags & FLAG_SEARCH != 0 {
+		"search-only"
+	} else {
+		"not accessible"
+	}
+}
+
+impl RuleOverlap {
+	/// Effective outcome of the overlap, e.g. "writes under /data/projects
+	/// allowed, rest of /data read-only".
+	pub fn describe(&self) -> String {
+		let outer = self.outer.path().display();
+		let inner = self.inner.path().display();
+		if rule_components(self.outer.path()) == rule_components(self.inner.path()) {
+			return format!(
+				"{outer} is listed twice; the rules are combined to {}",
+				access_summary(self.outer.flags() | self.inner.flags())
+			);
+		}
+		if self.inner.can_write() && !self.outer.can_write() {
+			return format!(
+				"writes under {inner} allowed, rest of {outer} {}",
+				access_summary(self.outer.flags())
+			);
+		}
+		format!(
+			"{inner} {}, rest of {outer} {}",
+			access_summary(self.inner.flags()),
+			access_summary(self.outer.flags())
+		)
+	}
+}
+
+/// Nested or duplicate rules with differing flags. Nested rules with equal
+/// flags are redundant but unambiguous and are not reported.
+pub fn folder_rule_overlaps(rules: &[FolderRule]) -> Vec<RuleOverlap> {
+	let mut overlaps = Vec::new();
+	for (idx, a) in rules.iter().enumerate() {
+		for b in &rules[idx + 1..] {
+			if a.flags() == b.flags() {
+				continue;
+			}
+			let (outer, inner) = match (
+				rule_depth(a.path(), b.path()),
+				rule_depth(b.path(), a.path()),
+			) {
+				(Some(_), _) => (a, b),
+				(None, Some(_)) => (b, a),
+				(None, None) => continue,
+			};
+			overlaps.push(RuleOverlap {
+				outer: outer.clone(),
+				inner: inner.clone(),
+			});
+		}
+	}
+	overlaps
+}
+
+/// Names of the access flags in `flags`, e.g. "read and write".
+fn flag_names(flags: u8) -> String {
+	let names = [
+		(FLAG_READ, "read"),
+		(FLAG_WRITE, "write"),
+		(FLAG_EXECUTE, "execute"),
+		(FLAG_SEARCH, "search"),
+		(FLAG_PREVIEW, "preview"),
+	]
+	.into_iter()
+	.filter(|(flag, _)| flags & flag != 0)
+	.map(|(_, name)| name)
+	.collect::<Vec<_>>();
+	match names.as_slice() {
+		[] => String::from("access"),
+		[name] => name.to_string(),
+		[rest @ .., last] => format!("{} and {last}", rest.join(", ")),
+	}
+}
+
+/// Flags of `access` that `rule` doesn't allow; all of them without a rule.
+fn missing_access(rule: Option<&FolderRule>, access: u8) -> u8 {
+	[
+		FLAG_READ,
+		FLAG_WRITE,
+		FLAG_EXECUTE,
+		FLAG_SEARCH,
+		FLAG_PREVIEW,
+	]
+	.into_iter()
+	.filter(|flag| access & flag != 0 && !rule.is_some_and(|rule| rule.allows(*flag)))
+	.fold(0, |missing, flag| missing | flag)
+}
+
+/// The folder rules whose patterns hide files from one peer, to check
+/// paths away from the [`State`], as a scan does. Hides nothing from this
+/// node itself.
+#[derive(Clone, Debug, Default)]
+pub struct HiddenNames {
+	shared: Vec<FolderRule>,
+	durable: Vec<FolderRule>,
+	temporary: Vec<FolderRule>,
+}
+
+impl HiddenNames {
+	/// Whether the shared folder `path` is in, or a grant covering it,
+	/// hides it. Where a durable and a temporary grant both cover it,
+	/// either one hiding it is enough.
+	pub fn hides(&self, path: &Path, is_dir: bool) -> bool {
+		[&self.shared, &self.durable, &self.temporary]
+			.into_iter()
+			.any(|rules| {
+				effective_folder_rule(rules, path).is_some_and(|rule| rule.hides(path, is_dir))
+			})
+	}
+}
+
+/// A temporary grant that covered the path until `expired_at`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LapsedAccess {
+	pub rule: FolderRule,
+	pub expired_at: DateTime<Utc>,
+}
+
+/// How far this node trusts a peer, which decides the kinds of request it
+/// answers at all before any folder rule is looked at.
+#[derive(
+	Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TrustLevel {
+	/// May connect, say hello and pair, and nothing else. Peers seen for
+	/// the first time start here.
+	#[default]
+	Untrusted,
+	/// Filesystem requests, decided by folder rules. Nothing about the
+	/// machine itself.
+	Basic,
+	/// Every request, each under its own checks.
+	Trusted,
+	/// Every request, with owner access to all shared folders as an
+	/// `Owner` grant gives.
+	Owner,
+}
+
+impl TrustLevel {
+	pub const ALL: [Self; 4] = [Self::Untrusted, Self::Basic, Self::Trusted, Self::Owner];
+
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Self::Untrusted => "untrusted",
+			Self::Basic => "basic",
+			Self::Trusted => "trusted",
+			Self::Owner => "owner",
+		}
+	}
+
+	pub fn parse(value: &str) -> Option<Self> {
+		match value {
+			"untrusted" => Some(Self::Untrusted),
+			"basic" => Some(Self::Basic),
+			"trusted" => Some(Self::Trusted),
+			"owner" => Some(Self::Owner),
+			_ => None,
+		}
+	}
+
+	pub fn label(self) -> &'static str {
+		match self {
+			Self::Untrusted => "Untrusted",
+			Self::Basic => "Basic",
+			Self::Trusted => "Trusted",
+			Self::Owner => "Owner",
+		}
+	}
+
+	pub fn icon(self) -> &'static str {
+		match self {
+			Self::Untrusted => "🔒",
+			Self::Basic => "📁",
+			Self::Trusted => "🤝",
+			Self::Owner => "👑",
+		}
+	}
+
+	/// What a peer at this level may ask for, for the permission editor.
+	pub fn describe(self) -> &'static str {
+		match self {
+			Self::Untrusted => "Can connect and pair, but every request is refused.",
+			Self::Basic => {
+				"Can use the folders granted below; hardware, network and control requests are refused."
+			}
+			Self::Trusted => "Can use the folders granted below and see and control this device.",
+			Self::Owner => "Can do everything, in every shared folder.",
+		}
+	}
+
+	/// The lowest level answered requests of `scope`.
+	pub fn needed_for(scope: RequestScope) -> Self {
+		match scope {
+			RequestScope::Exchange => Self::Untrusted,
+			RequestScope::Files => Self::Basic,
+			RequestScope::System => Self::Trusted,
+		}
+	}
+
+	pub fn allows(self, scope: RequestScope) -> bool {
+		self >= Self::needed_for(scope)
+	}
+}
+
+/// A request refused for the sender's trust level.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TrustRefusal {
+	/// Variant name of the request.
+	pub request: String,
+	pub level: TrustLevel,
+	pub needed: TrustLevel,
+}
+
+/// Why a peer may or may not access a path, sent along with a refusal.
+/// Only rules on `path` or one of its ancestors are described, so a denial
+/// never tells the peer what else is shared.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AccessExplanation {
+	pub path: String,
+	pub requested: u8,
+	/// The rule deciding access to `path`, with the flags left after the
+	/// owner's shared folder limits them. `None` when nothing is shared
+	/// there.
+	pub granted: Option<FolderRule>,
+	/// Flags of `requested` that `granted` doesn't give.
+	pub missing: u8,
+	/// A temporary grant on `path` that lapsed recently.
+	pub lapsed: Option<LapsedAccess>,
+	/// Set when the sender's trust level refused the request before any
+	/// rule was looked at; `path` then names the request, which is what
+	/// peers that predate trust levels show.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub trust: Option<TrustRefusal>,
+}
+
+impl AccessExplanation {
+	/// The refusal of `request` from a peer at `level`.
+	pub fn untrusted(request: &str, level: TrustLevel, needed: TrustLevel) -> Self {
+		Self {
+			path: request.to_string(),
+			requested: 0,
+			granted: None,
+			missing: 0,
+			lapsed: None,
+			trust: Some(TrustRefusal {
+				request: request.to_string(),
+				level,
+				needed,
+			}),
+		}
+	}
+
+	pub fn allowed(&self) -> bool {
+		self.missing == 0 && self.trust.is_none()
+	}
+
+	/// The grant that would have let the request through: the flags
+	/// already given plus the missing ones, on the deciding folder.
+	pub fn request_rule(&self) -> FolderRule {
+		let path = match (&self.granted, &self.lapsed) {
+			(Some(granted), _) => granted.path().to_path_buf(),
+			(None, Some(lapsed)) => lapsed.rule.path().to_path_buf(),
+			(None, None) => PathBuf::from(&self.path),
+		};
+		let granted = self.granted.as_ref().map_or(0, FolderRule::flags);
+		FolderRule::new(path, granted | self.missing)
+	}
+
+	/// Message for `requester` to send the owner, with the command that
+	/// grants the missing access when the CLI can express it.
+	pub fn access_request(&self, requester: &str) -> String {
+		if let Some(trust) = &self.trust {
+			return format!(
+				"{requester} asks to be trusted as {} to send {}. To allow it: set its trust level on its permissions page.",
+				trust.needed.label(),
+				trust.request
+			);
+		}
+		let rule = self.request_rule();
+		let path = rule.path().display();
+		let ask = format!(
+			"{requester} asks for {} access to {path}.",
+			flag_names(self.missing)
+		);
+		if rule.can_write() {
+			format!("{ask} To allow it: puppynet grant {requester} --write {path}")
+		} else if rule.can_read() {
+			format!("{ask} To allow it: puppynet grant {requester} --read {path}")
+		} else {
+			ask
+		}
+	}
+}
+
+impl std::fmt::Display for AccessExplanation {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		if let Some(trust) = &self.trust {
+			return write!(
+				f,
+				"{ACCESS_DENIED}: this device is {} there, and {} needs {} trust",
+				trust.level.as_str(),
+				trust.request,
+				trust.needed.as_str()
+			);
+		}
+		if let Some(lapsed) = &self.lapsed {
+			return write!(
+				f,
+				"{ACCESS_DENIED}: temporary access to {} expired {}",
+				lapsed.rule.path().display(),
+				relative_time(lapsed.expired_at, Utc::now())
+			);
+		}
+		let missing = flag_names(self.missing);
+		match &self.granted {
+			Some(granted) if granted.flags() & !FLAG_QUARANTINE != 0 => write!(
+				f,
+				"{ACCESS_DENIED}: {} share — ask the owner to enable {missing} for {}",
+				access_summary(granted.flags()),
+				granted.path().display()
+			),
+			Some(granted) => write!(
+				f,
+				"{ACCESS_DENIED}: ask the owner to enable {missing} for {}",
+				granted.path().display()
+			),
+			None => write!(f, "{ACCESS_DENIED}: {} is not shared with you", self.path),
+		}
+	}
+}
+
+impl std::error::Error for AccessExplanation {}
+
+fn permission_folder_rules(permissions: &[Permission]) -> Vec<FolderRule> {
+	permissions
+		.iter()
+		.filter_map(|permission| match permission.rule() {
+			Rule::Folder(folder) => Some(folder.clone()),
+			_ => None,
+		})
+		.collect()
+}
+
+/// Overlapping folder grants within `permissions`.
+pub fn permission_overlaps(permissions: &[Permission]) -> Vec<RuleOverlap> {
+	folder_rule_overlaps(&permission_folder_rules(permissions))
+}
+
+/// Folder grants within `permissions` with duplicates merged.
+pub fn effective_permission_rules(permissions: &[Permission]) -> Vec<FolderRule> {
+	effective_folder_rules(&permission_folder_rules(permissions))
+}
+
+/// `existing` with a grant of `rule` added, or `None` when a permanent
+/// grant of the same folder already allows all of `rule`'s flags. Other
+/// grants are kept; nothing is replaced.
+pub fn merge_folder_grant(existing: &[Permission], rule: &FolderRule) -> Option<Vec<Permission>> {
+	let covered = existing.iter().any(|permission| match permission.rule() {
+		Rule::Folder(folder) => {
+			permission.expires_at().is_none()
+				&& rule_components(folder.path()) == rule_components(rule.path())
+				&& folder.flags() & rule.flags() == rule.flags()
+				&& folder.deny_patterns == rule.deny_patterns
+				&& folder.allow_patterns == rule.allow_patterns
+		}
+		_ => false,
+	});
+	if covered {
+		return None;
+	}
+	let mut merged = existing.to_vec();
+	merged.push(Permission::new(Rule::Folder(rule.clone())));
+	Some(merged)
+}
+
+/// What granting a folder to several peers at once did for one of them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum BatchGrantOutcome {
+	/// The folder was added to the peer's grants.
+	Granted,
+	/// The peer already had the folder with these flags.
+	Unchanged,
+	Failed {
+		error: String,
+	},
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum Rule {
 	Owner,
 	Folder(FolderRule),
+	/// Lets the peer drop files into this node's inbox directory.
+	Inbox,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Permission {
 	rule: Rule,
 	expires_at: Option<i64>,
@@ -95,6 +748,54 @@ impl Permission {
 	}
 }
 
+/// A folder grant that lives only in memory until `expires_at`. It is
+/// never stored, so restarting the node drops it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TemporaryGrant {
+	pub id: u64,
+	pub rule: FolderRule,
+	pub expires_at: DateTime<Utc>,
+}
+
+impl TemporaryGrant {
+	pub fn remaining(&self, now: DateTime<Utc>) -> std::time::Duration {
+		(self.expires_at - now).to_std().unwrap_or_default()
+	}
+
+	fn is_active(&self, now: DateTime<Utc>) -> bool {
+		self.expires_at > now
+	}
+}
+
+/// Rules granted to one peer together with the revision they were read at.
+#[derive(Clone, Debug, Serialize)]
+pub struct PermissionSet {
+	pub revision: u64,
+	pub permissions: Vec<Permission>,
+	/// Unexpired temporary grants, which carry no revision.
+	pub temporary: Vec<TemporaryGrant>,
+}
+
+/// A revision-checked permission update lost a race with another writer.
+/// Carries the rules that are stored now so the caller can merge and retry.
+#[derive(Clone, Debug)]
+pub struct PermissionConflict {
+	pub expected_revision: u64,
+	pub latest: PermissionSet,
+}
+
+impl std::fmt::Display for PermissionConflict {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"permissions changed elsewhere (revision {} is now {}), reload",
+			self.expected_revision, self.latest.revision
+		)
+	}
+}
+
+impl std::error::Error for PermissionConflict {}
+
 #[derive(Clone, Debug)]
 pub struct Relationship {
 	src: PeerId,
@@ -119,13 +820,109 @@ pub struct Auth {
 	rules: Vec<Rule>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionDirection {
+	/// We dialed the peer.
+	Outbound,
+	/// The peer dialed us.
+	Inbound,
+}
+
 #[derive(Clone, Debug)]
 pub struct Connection {
 	pub peer_id: PeerId,
 	pub connection_id: ConnectionId,
+	pub direction: ConnectionDirection,
+	/// Only known for inbound connections.
+	pub local_addr: Option<Multiaddr>,
+	pub remote_addr: Multiaddr,
+	pub connected_at: DateTime<Utc>,
 }
 
-#[derive(Clone, Debug)]
+impl Connection {
+	/// Transport protocol of the remote address, e.g. `tcp` or `quic`.
+	pub fn transport(&self) -> &'static str {
+		for protocol in self.remote_addr.iter() {
+			match protocol {
+				Protocol::QuicV1 | Protocol::Quic => return "quic",
+				Protocol::Ws(_) | Protocol::Wss(_) => return "websocket",
+				Protocol::WebRTCDirect => return "webrtc",
+				Protocol::P2pCircuit => return "relay",
+				_ => {}
+			}
+		}
+		if self
+			.remote_addr
+			.iter()
+			.any(|protocol| matches!(protocol, Protocol::Tcp(_)))
+		{
+			"tcp"
+		} else {
+			"unknown"
+		}
+	}
+}
+
+/// Why a connection closed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectReason {
+	/// It carried no requests for the idle timeout. The peer is most
+	/// likely still reachable.
+	Idle,
+	/// It failed or the peer closed it.
+	Lost,
+	/// This node closed it.
+	Deliberate,
+}
+
+impl DisconnectReason {
+	/// The reason behind the swarm's `cause` of a closed connection, which
+	/// is `None` when this node closed it.
+	pub fn from_cause(cause: Option<&ConnectionError>) -> Self {
+		match cause {
+			None => DisconnectReason::Deliberate,
+			Some(ConnectionError::KeepAliveTimeout) => DisconnectReason::Idle,
+			Some(_) => DisconnectReason::Lost,
+		}
+	}
+}
+
+/// A request failed because the last connection to the peer it was sent
+/// to closed before the answer came. Nothing says whether the peer handled
+/// it, so only requests safe to repeat should be sent again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Disconnected {
+	pub peer: PeerId,
+	pub reason: DisconnectReason,
+}
+
+impl std::fmt::Display for Disconnected {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let closed = match self.reason {
+			DisconnectReason::Idle => "went idle",
+			DisconnectReason::Lost => "was lost",
+			DisconnectReason::Deliberate => "was closed",
+		};
+		write!(
+			f,
+			"the connection to {} {closed} before it answered",
+			self.peer
+		)
+	}
+}
+
+impl std::error::Error for Disconnected {}
+
+/// The last connection to a peer that closed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Disconnect {
+	pub at: DateTime<Utc>,
+	pub reason: DisconnectReason,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct DiscoveredPeer {
 	pub peer_id: PeerId,
 	pub multiaddr: Multiaddr,
@@ -135,6 +932,7 @@ pub struct DiscoveredPeer {
 pub struct Peer {
 	pub id: PeerId,
 	pub name: Option<String>,
+	pub trust: TrustLevel,
 }
 
 #[derive(Clone, Debug)]
@@ -143,19 +941,78 @@ pub struct User {
 	pub passw: String,
 }
 
+#[derive(Clone, Debug)]
+pub struct Notification {
+	pub id: u64,
+	pub peer: PeerId,
+	pub message: String,
+}
+
 #[derive(Clone, Debug)]
 pub struct State {
 	pub me: PeerId,
 	pub relationships: Vec<Relationship>,
 	pub auths: Vec<Auth>,
 	pub connections: Vec<Connection>,
-	pub discovered_peers: Vec<DiscoveredPeer>,
 	pub peers: Vec<Peer>,
-	pub users: Vec<User>,
 	pub shared_folders: Vec<FolderRule>,
+	/// Availability of each shared folder as of the last mount check.
+	pub share_availability: HashMap<PathBuf, ShareAvailability>,
+	pub inbox: Option<PathBuf>,
+	/// Permissions other peers have granted to this node, keyed by the granting peer.
+	pub remote_permissions: HashMap<PeerId, Vec<Permission>>,
+	pub notifications: Vec<Notification>,
+	/// Result of the `Hello` handshake per connected peer.
+	pub capabilities: HashMap<PeerId, PeerCapabilities>,
+	/// When each peer's recent connections were lost or closed, oldest
+	/// first. Idle closes don't count.
+	pub connection_drops: HashMap<PeerId, Vec<DateTime<Utc>>>,
+	/// How each peer's last connection closed.
+	pub last_disconnects: HashMap<PeerId, Disconnect>,
+	/// Peers redialed whenever their last connection drops.
+	pub important_peers: HashSet<PeerId>,
+	/// Estimated clock offset of each peer, sampled through `Hello`.
+	pub clock_offsets: HashMap<PeerId, ClockOffset>,
+	/// What each peer announced about its index since this node last
+	/// pulled it.
+	pub index_freshness: HashMap<PeerId, IndexFreshness>,
+	/// Today's latest alert for peers reading or writing far more than
+	/// usual.
+	pub access_alerts: HashMap<PeerId, AccessAlert>,
+	/// Refuses all remote filesystem access without touching stored rules.
+	pub remote_access_suspended: bool,
+	/// Set while the node refuses every change so its data directory can
+	/// be copied.
+	pub maintenance: Option<Maintenance>,
+	/// Router port mapping, when enabled in settings.
+	pub nat: NatStatus,
+	/// Last dial-back self-test of this node's addresses.
+	pub reachability: Vec<AddressReachability>,
+	/// Latest pairing with each peer started from the setup wizard.
+	pub pairings: HashMap<PeerId, Pairing>,
+	/// Set while the database's own node differs from the keypair's.
+	pub identity_mismatch: Option<IdentityMismatch>,
+	/// Power supply and whether background work waits for it.
+	pub power: PowerState,
+	/// Temporary grants this node gave each peer, expired ones included
+	/// until the next sweep.
+	temporary_grants: HashMap<PeerId, Vec<TemporaryGrant>>,
+	/// Swept grants kept for [`LAPSED_GRANT_MEMORY_SECS`].
+	lapsed_grants: HashMap<PeerId, Vec<TemporaryGrant>>,
+	next_temporary_grant_id: u64,
+	next_notification_id: u64,
 	dirty_permission_targets: HashSet<PeerId>,
 }
 
+/// [`State`] together with the collections it no longer carries, in the
+/// shape callers read before they moved behind queries.
+#[derive(Clone, Debug)]
+pub struct FullStateSnapshot {
+	pub state: State,
+	pub discovered_peers: Vec<DiscoveredPeer>,
+	pub users: Vec<String>,
+}
+
 impl Default for State {
 	fn default() -> Self {
 		Self {
@@ -163,10 +1020,30 @@ impl Default for State {
 			relationships: Vec::new(),
 			auths: Vec::new(),
 			connections: Vec::new(),
-			discovered_peers: Vec::new(),
 			peers: Vec::new(),
-			users: Vec::new(),
 			shared_folders: Vec::new(),
+			share_availability: HashMap::new(),
+			inbox: None,
+			remote_permissions: HashMap::new(),
+			notifications: Vec::new(),
+			capabilities: HashMap::new(),
+			connection_drops: HashMap::new(),
+			last_disconnects: HashMap::new(),
+			important_peers: HashSet::new(),
+			clock_offsets: HashMap::new(),
+			index_freshness: HashMap::new(),
+			access_alerts: HashMap::new(),
+			remote_access_suspended: false,
+			maintenance: None,
+			nat: NatStatus::Disabled,
+			reachability: Vec::new(),
+			pairings: HashMap::new(),
+			identity_mismatch: None,
+			power: PowerState::default(),
+			temporary_grants: HashMap::new(),
+			lapsed_grants: HashMap::new(),
+			next_temporary_grant_id: 0,
+			next_notification_id: 0,
 			dirty_permission_targets: HashSet::new(),
 		}
 	}
@@ -175,10 +1052,128 @@ impl Default for State {
 impl State {
 	pub fn authenticate(&mut self, peer_id: PeerId, method: AuthMethod) {}
 
-	pub fn add_shared_folder(&mut self, rule: FolderRule) {
+	/// Adds or replaces a shared folder and returns the overlaps it takes
+	/// part in, so callers can explain which rule wins.
+	pub fn add_shared_folder(&mut self, rule: FolderRule) -> Vec<RuleOverlap> {
 		self.shared_folders
 			.retain(|existing| existing.path() != rule.path());
-		self.shared_folders.push(rule);
+		self.shared_folders.push(rule.clone());
+		folder_rule_overlaps(&self.shared_folders)
+			.into_iter()
+			.filter(|overlap| overlap.outer == rule || overlap.inner == rule)
+			.collect()
+	}
+
+	/// Replaces the availability of the shared folders with `checked` and
+	/// returns the shares that went offline or came back. A share not
+	/// checked before counts as having been available.
+	pub fn record_share_availability(
+		&mut self,
+		checked: Vec<(PathBuf, ShareAvailability)>,
+	) -> Vec<ShareChange> {
+		let previous = std::mem::take(&mut self.share_availability);
+		let mut changes = Vec::new();
+		for (share, availability) in checked {
+			let was_available = previous
+				.get(&share)
+				.is_none_or(ShareAvailability::is_available);
+			if was_available != availability.is_available() {
+				changes.push(ShareChange {
+					share: share.clone(),
+					available: availability.is_available(),
+				});
+			}
+			self.share_availability.insert(share, availability);
+		}
+		changes
+	}
+
+	/// The offline shared folder `path` lies in, if any.
+	pub fn unavailable_share(&self, path: &Path) -> Option<&Path> {
+		self.share_availability
+			.iter()
+			.filter(|(share, availability)| !availability.is_available() && path.starts_with(share))
+			.map(|(share, _)| share.as_path())
+			.max_by_key(|share| share.components().count())
+	}
+
+	/// Removes a closed connection and remembers when and why it closed.
+	/// Returns the connection, or `None` if it wasn't tracked.
+	pub fn remove_connection(
+		&mut self,
+		connection_id: ConnectionId,
+		reason: DisconnectReason,
+		now: DateTime<Utc>,
+	) -> Option<Connection> {
+		let index = self
+			.connections
+			.iter()
+			.position(|connection| connection.connection_id == connection_id)?;
+		let connection = self.connections.remove(index);
+		self.last_disconnects
+			.insert(connection.peer_id, Disconnect { at: now, reason });
+		if reason != DisconnectReason::Idle {
+			let drops = self.connection_drops.entry(connection.peer_id).or_default();
+			drops
+				.retain(|closed_at| (now - *closed_at).num_seconds() < CONNECTION_DROP_WINDOW_SECS);
+			drops.push(now);
+		}
+		Some(connection)
+	}
+
+	/// Whether `peer_id` is unconnected only because its connection went
+	/// idle, so it should not be shown as offline.
+	pub fn is_idle(&self, peer_id: &PeerId) -> bool {
+		self.connections_to(peer_id).is_empty()
+			&& self
+				.last_disconnects
+				.get(peer_id)
+				.is_some_and(|disconnect| disconnect.reason == DisconnectReason::Idle)
+	}
+
+	/// Whether this node shares with `peer_id` in either direction, which
+	/// makes its connection worth keeping through quiet periods.
+	pub fn shares_with(&self, peer_id: &PeerId) -> bool {
+		self.important_peers.contains(peer_id)
+			|| self
+				.remote_permissions
+				.get(peer_id)
+				.is_some_and(|permissions| !permissions.is_empty())
+			|| !self.permissions_for_peer(peer_id).is_empty()
+	}
+
+	pub fn connections_to(&self, peer_id: &PeerId) -> Vec<&Connection> {
+		self.connections
+			.iter()
+			.filter(|connection| connection.peer_id == *peer_id)
+			.collect()
+	}
+
+	/// Connections to `peer_id` that closed within the last hour.
+	pub fn recent_drops(&self, peer_id: &PeerId, now: DateTime<Utc>) -> usize {
+		self.connection_drops.get(peer_id).map_or(0, |drops| {
+			drops
+				.iter()
+				.filter(|closed_at| (now - **closed_at).num_seconds() < CONNECTION_DROP_WINDOW_SECS)
+				.count()
+		})
+	}
+
+	/// "peer clock is ~4m ahead" when `peer_id`'s clock is off by more than
+	/// the warning threshold.
+	pub fn clock_skew(&self, peer_id: &PeerId) -> Option<String> {
+		self.clock_offsets
+			.get(peer_id)
+			.and_then(ClockOffset::describe)
+	}
+
+	/// "index changed 5 minutes ago (120 added), results may be out of
+	/// date" while `peer_id` announced index changes this node hasn't
+	/// pulled.
+	pub fn index_staleness(&self, peer_id: &PeerId, now: DateTime<Utc>) -> Option<String> {
+		self.index_freshness
+			.get(peer_id)
+			.and_then(|freshness| freshness.describe(now))
 	}
 
 	pub fn permissions_for_peer(&self, peer_id: &PeerId) -> Vec<Permission> {
@@ -215,20 +1210,31 @@ impl State {
 	}
 
 	pub fn search_roots_for_peer(&self, peer_id: &PeerId) -> Vec<PathBuf> {
-		let hard_roots = self.hard_roots_for_access(FLAG_SEARCH);
-		if *peer_id == self.me {
+		self.roots_for_peer(peer_id, FLAG_SEARCH)
+	}
+
+	/// Shared folders, narrowed to the peer's grants, that allow `access`.
+	pub fn roots_for_peer(&self, peer_id: &PeerId, access: u8) -> Vec<PathBuf> {
+		let hard_roots = self.hard_roots_for_access(access);
+		if self.trust_level(peer_id) == TrustLevel::Owner {
 			return hard_roots
 				.into_iter()
 				.map(|rule| rule.path().to_path_buf())
 				.collect();
 		}
 
+		let mut granted = self.permissions_granted_to_peer(peer_id);
+		granted.extend(
+			self.temporary_grants_for(peer_id, Utc::now())
+				.into_iter()
+				.map(|grant| Permission::new(Rule::Folder(grant.rule))),
+		);
 		let mut roots = Vec::new();
 		for hard_root in &hard_roots {
-			for permission in self.permissions_granted_to_peer(peer_id) {
+			for permission in &granted {
 				match permission.rule() {
 					Rule::Owner => roots.push(hard_root.path().to_path_buf()),
-					Rule::Folder(folder) if folder.allows(FLAG_SEARCH) => {
+					Rule::Folder(folder) if folder.allows(access) => {
 						if hard_root.path().starts_with(folder.path()) {
 							roots.push(hard_root.path().to_path_buf());
 						} else if folder.path().starts_with(hard_root.path()) {
@@ -244,18 +1250,40 @@ impl State {
 		roots
 	}
 
+	/// Both the shared folders and the peer's grants are resolved with
+	/// [`effective_folder_rule`], so a nested rule overrides its parent
+	/// whichever order they were added in. `Owner` grants skip the folder
+	/// grants but still stay within the shared folders.
 	pub fn has_fs_access(&self, src: PeerId, path: &Path, access: u8) -> bool {
-		let within_hard_root = self
-			.shared_folders
-			.iter()
-			.any(|rule| path.starts_with(rule.path()) && rule.allows(access));
+		self.has_fs_access_at(src, path, access, Utc::now())
+	}
+
+	/// [`Self::has_fs_access`] with temporary grants judged at `now`. A
+	/// temporary grant only ever adds access; it never narrows a durable
+	/// grant on an enclosing folder.
+	pub fn has_fs_access_at(
+		&self,
+		src: PeerId,
+		path: &Path,
+		access: u8,
+		now: DateTime<Utc>,
+	) -> bool {
+		let within_hard_root = effective_folder_rule(&self.shared_folders, path)
+			.is_some_and(|rule| rule.allows(access));
 		if !within_hard_root {
 			return false;
 		}
 		if src == self.me {
 			return true;
 		}
+		if self.remote_access_suspended {
+			return false;
+		}
+		if self.trust_level(&src) == TrustLevel::Owner {
+			return true;
+		}
 
+		let mut granted = Vec::new();
 		for rel in &self.relationships {
 			if rel.src == src || rel.target == src {
 				for rule in &rel.rules {
@@ -263,33 +1291,435 @@ impl State {
 						Rule::Owner => {
 							return true;
 						}
-						Rule::Folder(folder_rule) => {
-							if path.starts_with(folder_rule.path()) && folder_rule.allows(access) {
-								return true;
-							}
-						}
+						Rule::Folder(folder_rule) => granted.push(folder_rule),
+						Rule::Inbox => {}
 					}
 				}
 			}
 		}
 
-		false
+		if effective_folder_rule(granted, path).is_some_and(|rule| rule.allows(access)) {
+			return true;
+		}
+		let temporary = self
+			.temporary_grants
+			.get(&src)
+			.into_iter()
+			.flatten()
+			.filter(|grant| grant.is_active(now))
+			.map(|grant| &grant.rule);
+		effective_folder_rule(temporary, path).is_some_and(|rule| rule.allows(access))
 	}
 
-	pub fn set_peer_permissions(&mut self, peer_id: PeerId, permissions: Vec<Permission>) {
-		let me = self.me;
-		self.dirty_permission_targets.insert(peer_id);
-		self.relationships.retain(|rel| {
-			!(rel.src == me && rel.target == peer_id) && !(rel.src == peer_id && rel.target == me)
+	/// The flags [`Self::has_fs_access_at`] lets `src` use on `path`, each
+	/// checked on its own.
+	pub fn granted_flags_at(&self, src: PeerId, path: &Path, now: DateTime<Utc>) -> u8 {
+		[
+			FLAG_READ,
+			FLAG_WRITE,
+			FLAG_EXECUTE,
+			FLAG_SEARCH,
+			FLAG_PREVIEW,
+		]
+		.into_iter()
+		.filter(|flag| self.has_fs_access_at(src, path, *flag, now))
+		.fold(0, |flags, flag| flags | flag)
+	}
+
+	/// What `src` may do in `dir`, and in the folders directly inside it
+	/// that a shared folder or grant names, with temporary grants judged
+	/// at `now`. Flags come from [`Self::granted_flags_at`], so they match
+	/// what requests are allowed.
+	pub fn listing_access_at(&self, src: PeerId, dir: &Path, now: DateTime<Utc>) -> ListingAccess {
+		let dir_components = rule_components(dir);
+		let granted = self
+			.relationships
+			.iter()
+			.filter(|rel| rel.src == src || rel.target == src)
+			.flat_map(|rel| &rel.rules)
+			.collect::<Vec<_>>();
+		// Owners and this node itself are only limited by the shared folders.
+		let grants_apply = src != self.me
+			&& self.trust_level(&src) != TrustLevel::Owner
+			&& !granted.iter().any(|rule| matches!(rule.rule, Rule::Owner));
+		let durable = granted.into_iter().filter_map(|rule| match &rule.rule {
+			Rule::Folder(folder_rule) if grants_apply => Some(folder_rule),
+			_ => None,
 		});
-		if permissions.is_empty() {
-			return;
+		let temporary = self
+			.temporary_grants
+			.get(&src)
+			.into_iter()
+			.flatten()
+			.filter(|grant| grants_apply && grant.is_active(now))
+			.map(|grant| &grant.rule);
+		let mut named = Vec::new();
+		let mut entries = Vec::new();
+		for rule in durable.chain(temporary).chain(&self.shared_folders) {
+			let components = rule_components(rule.path());
+			if components.len() != dir_components.len() + 1
+				|| !components.starts_with(&dir_components)
+				|| named.contains(&components)
+			{
+				continue;
+			}
+			let Some(name) = components.last().cloned() else {
+				continue;
+			};
+			entries.push(EntryAccess {
+				name: rule
+					.path()
+					.file_name()
+					.map_or(name, |name| name.to_string_lossy().into_owned()),
+				rule: rule.path().display().to_string(),
+				flags: self.granted_flags_at(src, rule.path(), now),
+			});
+			named.push(components);
 		}
-		self.relationships.push(Relationship {
+		entries.sort_by(|a, b| a.name.cmp(&b.name));
+		ListingAccess {
+			flags: self.granted_flags_at(src, dir, now),
+			entries,
+		}
+	}
+
+	/// What the deny and allow patterns hide from `src`, with temporary
+	/// grants judged at `now`. Peers get hidden paths answered as missing,
+	/// never as refused.
+	pub fn hidden_names(&self, src: PeerId, now: DateTime<Utc>) -> HiddenNames {
+		if src == self.me {
+			return HiddenNames::default();
+		}
+		let durable = self
+			.relationships
+			.iter()
+			.filter(|rel| rel.src == src || rel.target == src)
+			.flat_map(|rel| &rel.rules)
+			.filter_map(|rule| match &rule.rule {
+				Rule::Folder(folder_rule) => Some(folder_rule.clone()),
+				_ => None,
+			})
+			.collect();
+		let temporary = self
+			.temporary_grants
+			.get(&src)
+			.into_iter()
+			.flatten()
+			.filter(|grant| grant.is_active(now))
+			.map(|grant| grant.rule.clone())
+			.collect();
+		HiddenNames {
+			shared: self.shared_folders.clone(),
+			durable,
+			temporary,
+		}
+	}
+
+	/// The decision [`Self::has_fs_access`] makes, with what decided it.
+	pub fn explain_access(&self, src: PeerId, path: &Path, access: u8) -> AccessExplanation {
+		self.explain_access_at(src, path, access, Utc::now())
+	}
+
+	/// [`Self::explain_access`] with temporary grants judged at `now`.
+	/// Follows [`Self::has_fs_access_at`] rule for rule; when both a
+	/// durable and a temporary grant cover `path`, the one missing fewer
+	/// flags is described. Remote access being suspended is not explained
+	/// here.
+	pub fn explain_access_at(
+		&self,
+		src: PeerId,
+		path: &Path,
+		access: u8,
+		now: DateTime<Utc>,
+	) -> AccessExplanation {
+		let hard_root = effective_folder_rule(&self.shared_folders, path);
+		let owner = self.trust_level(&src) == TrustLevel::Owner
+			|| self
+				.relationships
+				.iter()
+				.filter(|rel| rel.src == src || rel.target == src)
+				.flat_map(|rel| &rel.rules)
+				.any(|rule| matches!(rule.rule, Rule::Owner));
+		let grant = if owner {
+			hard_root.clone()
+		} else {
+			let durable = self
+				.relationships
+				.iter()
+				.filter(|rel| rel.src == src || rel.target == src)
+				.flat_map(|rel| &rel.rules)
+				.filter_map(|rule| match &rule.rule {
+					Rule::Folder(folder_rule) => Some(folder_rule),
+					_ => None,
+				});
+			let temporary = self
+				.temporary_grants
+				.get(&src)
+				.into_iter()
+				.flatten()
+				.filter(|grant| grant.is_active(now))
+				.map(|grant| &grant.rule);
+			[
+				effective_folder_rule(durable, path),
+				effective_folder_rule(temporary, path),
+			]
+			.into_iter()
+			.flatten()
+			.min_by_key(|rule| missing_access(Some(rule), access).count_ones())
+		};
+		let (granted, missing) = match (hard_root, grant) {
+			(Some(hard_root), Some(grant)) => {
+				let missing =
+					missing_access(Some(&hard_root), access) | missing_access(Some(&grant), access);
+				let flags = grant.flags() & (hard_root.flags() | FLAG_QUARANTINE);
+				(Some(FolderRule { flags, ..grant }), missing)
+			}
+			_ => (None, missing_access(None, access)),
+		};
+		let lapsed = (missing != 0)
+			.then(|| {
+				self.temporary_grants
+					.get(&src)
+					.into_iter()
+					.chain(self.lapsed_grants.get(&src))
+					.flatten()
+					.filter(|grant| {
+						!grant.is_active(now) && rule_depth(grant.rule.path(), path).is_some()
+					})
+					.max_by_key(|grant| grant.expires_at)
+			})
+			.flatten()
+			.map(|grant| LapsedAccess {
+				rule: grant.rule.clone(),
+				expired_at: grant.expires_at,
+			});
+		AccessExplanation {
+			path: path.display().to_string(),
+			requested: access,
+			granted,
+			missing,
+			lapsed,
+			trust: None,
+		}
+	}
+
+	/// Size a thumbnail of `path` may be made at for `src`: as requested
+	/// with read access, fit within `preview_max` with preview access only,
+	/// and `None` without either.
+	pub fn thumbnail_bounds(
+		&self,
+		src: PeerId,
+		path: &Path,
+		max_width: u32,
+		max_height: u32,
+		preview_max: u32,
+	) -> Option<(u32, u32)> {
+		if self.has_fs_access(src, path, FLAG_READ | FLAG_SEARCH) {
+			return Some((max_width, max_height));
+		}
+		self.has_fs_access(src, path, FLAG_PREVIEW | FLAG_SEARCH)
+			.then(|| (max_width.min(preview_max), max_height.min(preview_max)))
+	}
+
+	pub fn grant_temporary(
+		&mut self,
+		peer_id: PeerId,
+		rule: FolderRule,
+		expires_at: DateTime<Utc>,
+	) -> TemporaryGrant {
+		self.next_temporary_grant_id += 1;
+		let grant = TemporaryGrant {
+			id: self.next_temporary_grant_id,
+			rule,
+			expires_at,
+		};
+		self.temporary_grants
+			.entry(peer_id)
+			.or_default()
+			.push(grant.clone());
+		grant
+	}
+
+	/// Removes a temporary grant before it expires. Returns false when no
+	/// such grant exists.
+	pub fn revoke_temporary(&mut self, peer_id: &PeerId, id: u64) -> bool {
+		let Some(grants) = self.temporary_grants.get_mut(peer_id) else {
+			return false;
+		};
+		let before = grants.len();
+		grants.retain(|grant| grant.id != id);
+		let revoked = grants.len() != before;
+		if grants.is_empty() {
+			self.temporary_grants.remove(peer_id);
+		}
+		revoked
+	}
+
+	pub fn temporary_grants_for(
+		&self,
+		peer_id: &PeerId,
+		now: DateTime<Utc>,
+	) -> Vec<TemporaryGrant> {
+		self.temporary_grants
+			.get(peer_id)
+			.into_iter()
+			.flatten()
+			.filter(|grant| grant.is_active(now))
+			.cloned()
+			.collect()
+	}
+
+	/// Moves expired temporary grants aside and forgets ones that lapsed
+	/// long ago. Returns the grants that expired since the last sweep.
+	pub fn sweep_temporary_grants(&mut self, now: DateTime<Utc>) -> Vec<(PeerId, TemporaryGrant)> {
+		let mut expired = Vec::new();
+		self.temporary_grants.retain(|peer_id, grants| {
+			grants.retain(|grant| {
+				if grant.is_active(now) {
+					return true;
+				}
+				expired.push((*peer_id, grant.clone()));
+				false
+			});
+			!grants.is_empty()
+		});
+		for (peer_id, grant) in &expired {
+			self.lapsed_grants
+				.entry(*peer_id)
+				.or_default()
+				.push(grant.clone());
+		}
+		self.lapsed_grants.retain(|_, grants| {
+			grants
+				.retain(|grant| (now - grant.expires_at).num_seconds() < LAPSED_GRANT_MEMORY_SECS);
+			!grants.is_empty()
+		});
+		expired
+	}
+
+	/// Error for a refused filesystem request. When `peer_id` recently held
+	/// a temporary grant covering `path`, says that it lapsed so the remote
+	/// user isn't left guessing.
+	pub fn access_denied_message(
+		&self,
+		peer_id: &PeerId,
+		path: &Path,
+		now: DateTime<Utc>,
+	) -> String {
+		let lapsed = self
+			.temporary_grants
+			.get(peer_id)
+			.into_iter()
+			.chain(self.lapsed_grants.get(peer_id))
+			.flatten()
+			.filter(|grant| !grant.is_active(now) && rule_depth(grant.rule.path(), path).is_some())
+			.max_by_key(|grant| grant.expires_at);
+		match lapsed {
+			Some(grant) => format!(
+				"{ACCESS_DENIED}: temporary access to {} expired {}",
+				grant.rule.path().display(),
+				relative_time(grant.expires_at, now)
+			),
+			None => String::from(ACCESS_DENIED),
+		}
+	}
+
+	/// Whether `peer_id` is this node, is trusted as owner or has been
+	/// granted `Owner`.
+	pub fn is_owner(&self, peer_id: &PeerId) -> bool {
+		self.trust_level(peer_id) == TrustLevel::Owner
+			|| self
+				.permissions_granted_to_peer(peer_id)
+				.iter()
+				.any(|permission| matches!(permission.rule(), Rule::Owner))
+	}
+
+	/// Whether a write by `src` to `path` waits for review: the folder
+	/// grant deciding it is quarantined. This node and owners bypass the
+	/// queue.
+	pub fn write_needs_review(&self, src: PeerId, path: &Path, now: DateTime<Utc>) -> bool {
+		if self.is_owner(&src) {
+			return false;
+		}
+		let durable = self
+			.relationships
+			.iter()
+			.filter(|rel| rel.src == src || rel.target == src)
+			.flat_map(|rel| &rel.rules)
+			.filter_map(|permission| match &permission.rule {
+				Rule::Folder(folder) => Some(folder),
+				_ => None,
+			});
+		let temporary = self
+			.temporary_grants
+			.get(&src)
+			.into_iter()
+			.flatten()
+			.filter(|grant| grant.is_active(now))
+			.map(|grant| &grant.rule);
+		effective_folder_rule(durable.chain(temporary), path).is_some_and(|rule| rule.quarantines())
+	}
+
+	/// Trust of `peer_id`: owner for this node, as stored for known peers
+	/// and untrusted for any other.
+	pub fn trust_level(&self, peer_id: &PeerId) -> TrustLevel {
+		if *peer_id == self.me {
+			return TrustLevel::Owner;
+		}
+		self.peers
+			.iter()
+			.find(|peer| peer.id == *peer_id)
+			.map_or(TrustLevel::Untrusted, |peer| peer.trust)
+	}
+
+	pub fn set_trust_level(&mut self, peer_id: PeerId, trust: TrustLevel) {
+		match self.peers.iter_mut().find(|peer| peer.id == peer_id) {
+			Some(peer) => peer.trust = trust,
+			None => self.peers.push(Peer {
+				id: peer_id,
+				name: None,
+				trust,
+			}),
+		}
+	}
+
+	/// The refusal of `req` from `src` when its trust level is too low for
+	/// the kind of request, before any of the request's own checks.
+	pub fn trust_refusal(&self, src: &PeerId, req: &PeerReq) -> Option<AccessExplanation> {
+		let level = self.trust_level(src);
+		let scope = req.scope();
+		(!level.allows(scope))
+			.then(|| AccessExplanation::untrusted(req.name(), level, TrustLevel::needed_for(scope)))
+	}
+
+	pub fn has_inbox_grant(&self, peer_id: &PeerId) -> bool {
+		self.permissions_granted_to_peer(peer_id)
+			.iter()
+			.any(|permission| matches!(permission.rule(), Rule::Inbox))
+	}
+
+	/// Replaces the grants for `peer_id` and returns any overlapping folder
+	/// grants among them.
+	pub fn set_peer_permissions(
+		&mut self,
+		peer_id: PeerId,
+		permissions: Vec<Permission>,
+	) -> Vec<RuleOverlap> {
+		let me = self.me;
+		self.dirty_permission_targets.insert(peer_id);
+		self.relationships.retain(|rel| {
+			!(rel.src == me && rel.target == peer_id) && !(rel.src == peer_id && rel.target == me)
+		});
+		if permissions.is_empty() {
+			return Vec::new();
+		}
+		let overlaps = permission_overlaps(&permissions);
+		self.relationships.push(Relationship {
 			src: me,
 			target: peer_id,
 			rules: permissions,
 		});
+		overlaps
 	}
 
 	pub fn set_peer_permissions_from_storage(
@@ -311,6 +1741,95 @@ impl State {
 		});
 	}
 
+	/// Replaces every grant this node has given with `stored`, e.g. after
+	/// the database was restored. Peers whose grants may have changed are
+	/// marked to be told.
+	pub fn replace_permissions_from_storage(&mut self, stored: Vec<(PeerId, Vec<Permission>)>) {
+		let me = self.me;
+		for rel in self.relationships.iter().filter(|rel| rel.src == me) {
+			self.dirty_permission_targets.insert(rel.target);
+		}
+		self.relationships.retain(|rel| rel.src != me);
+		for (target, permissions) in stored {
+			self.dirty_permission_targets.insert(target);
+			self.set_peer_permissions_from_storage(target, permissions);
+		}
+	}
+
+	/// Capabilities of `peer_id`, or `None` while the handshake is pending.
+	pub fn peer_capabilities(&self, peer_id: &PeerId) -> Option<PeerCapabilities> {
+		if *peer_id == self.me {
+			return Some(PeerCapabilities::local());
+		}
+		self.capabilities.get(peer_id).cloned()
+	}
+
+	pub fn peer_label(&self, peer_id: &PeerId) -> String {
+		self.peers
+			.iter()
+			.find(|peer| peer.id == *peer_id)
+			.and_then(|peer| peer.name.clone())
+			.unwrap_or_else(|| peer_id.to_string())
+	}
+
+	pub fn push_notification(&mut self, peer: PeerId, message: String) {
+		self.next_notification_id += 1;
+		self.notifications.push(Notification {
+			id: self.next_notification_id,
+			peer,
+			message,
+		});
+		if self.notifications.len() > MAX_NOTIFICATIONS {
+			let excess = self.notifications.len() - MAX_NOTIFICATIONS;
+			self.notifications.drain(..excess);
+		}
+	}
+
+	/// Replaces the cached permissions granted by `peer_id` and queues a
+	/// notification for every rule that was added or revoked.
+	pub fn apply_remote_permissions(&mut self, peer_id: PeerId, permissions: Vec<Permission>) {
+		let previous = if permissions.is_empty() {
+			self.remote_permissions.remove(&peer_id)
+		} else {
+			self.remote_permissions.insert(peer_id, permissions.clone())
+		};
+		let previous = previous.unwrap_or_default();
+		let label = self.peer_label(&peer_id);
+		for permission in &permissions {
+			if !previous.iter().any(|old| old.rule() == permission.rule()) {
+				let message = format!("{label} granted you {}", rule_label(permission.rule()));
+				self.push_notification(peer_id, message);
+			}
+		}
+		for permission in &previous {
+			if !permissions
+				.iter()
+				.any(|new| new.rule() == permission.rule())
+			{
+				let message = format!("{label} revoked your {}", rule_label(permission.rule()));
+				self.push_notification(peer_id, message);
+			}
+		}
+	}
+
+	/// Folders the given peer currently lets this node read or preview.
+	pub fn remote_roots(&self, peer_id: &PeerId) -> Vec<PathBuf> {
+		self.remote_permissions
+			.get(peer_id)
+			.map(|permissions| {
+				permissions
+					.iter()
+					.filter_map(|permission| match permission.rule() {
+						Rule::Folder(folder) if folder.can_preview() => {
+							Some(folder.path().to_path_buf())
+						}
+						_ => None,
+					})
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
 	pub fn save_changes(&mut self) -> anyhow::Result<()> {
 		if self.dirty_permission_targets.is_empty() {
 			return Ok(());
@@ -332,45 +1851,69 @@ impl State {
 
 		Ok(())
 	}
+}
 
-	pub fn peer_discovered(&mut self, peer_id: PeerId, multiaddr: Multiaddr) {
-		if self
-			.discovered_peers
-			.iter()
-			.any(|p| p.peer_id == peer_id && p.multiaddr == multiaddr)
-		{
-			return;
-		}
-		self.discovered_peers
-			.push(DiscoveredPeer { peer_id, multiaddr });
-	}
-
-	pub fn peer_expired(&mut self, peer_id: PeerId, multiaddr: Multiaddr) {
-		self.discovered_peers
-			.retain(|p| !(p.peer_id == peer_id && p.multiaddr == multiaddr));
-	}
-
-	pub fn create_user(&mut self, username: String, password: String) -> anyhow::Result<()> {
-		if self.users.iter().any(|u| u.name == username) {
-			bail!("User already exists");
+fn rule_label(rule: &Rule) -> String {
+	match rule {
+		Rule::Owner => String::from("owner access"),
+		Rule::Inbox => String::from("inbox access"),
+		Rule::Folder(folder) => {
+			let access = match (folder.can_read(), folder.can_write()) {
+				(true, true) => "read-write",
+				(false, true) => "write",
+				(false, false) if folder.can_preview() => "preview",
+				_ => "read",
+			};
+			let review = if folder.quarantines() {
+				" (writes reviewed)"
+			} else {
+				""
+			};
+			format!("{access} access to {}{review}", folder.path().display())
 		}
-		let passw = auth::hash_password(&password)?;
-		self.users.push(User {
-			name: username,
-			passw,
-		});
-		Ok(())
 	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use crate::p2p::WirePath;
+	use chrono::Duration;
 
 	fn rule(path: &str, flags: u8) -> FolderRule {
 		FolderRule::new(PathBuf::from(path), flags)
 	}
 
+	#[test]
+	fn folder_grants_merge_into_existing_ones() {
+		let photos = rule("/srv/photos", FLAG_READ | FLAG_SEARCH);
+		let existing = vec![
+			Permission::new(Rule::Inbox),
+			Permission::new(Rule::Folder(rule(
+				"/srv/photos/",
+				FLAG_READ | FLAG_SEARCH | FLAG_WRITE,
+			))),
+		];
+		assert!(merge_folder_grant(&existing, &photos).is_none());
+
+		let music = rule("/srv/music", FLAG_READ);
+		let merged = merge_folder_grant(&existing, &music).unwrap();
+		assert_eq!(merged.len(), 3);
+		assert_eq!(merged[0].rule(), &Rule::Inbox);
+		assert_eq!(merged[2].rule(), &Rule::Folder(music));
+
+		let expiring = vec![Permission::with_expiration(
+			Rule::Folder(photos.clone()),
+			Some(1),
+		)];
+		assert_eq!(merge_folder_grant(&expiring, &photos).unwrap().len(), 2);
+		let narrower = vec![Permission::new(Rule::Folder(rule(
+			"/srv/photos",
+			FLAG_READ,
+		)))];
+		assert_eq!(merge_folder_grant(&narrower, &photos).unwrap().len(), 2);
+	}
+
 	#[test]
 	fn local_fs_access_is_limited_to_hard_roots() {
 		let mut state = State::default();
@@ -388,6 +1931,60 @@ mod tests {
 		));
 	}
 
+	#[test]
+	fn share_availability_reports_only_changes() {
+		let mut state = State::default();
+		let media = PathBuf::from("/mnt/media");
+		let home = PathBuf::from("/home/ana");
+		let offline = ShareAvailability::NotMounted {
+			mount_point: media.clone(),
+		};
+		let online = ShareAvailability::Available {
+			mount_point: Some(media.clone()),
+		};
+		let local = ShareAvailability::Available { mount_point: None };
+
+		let changes = state.record_share_availability(vec![
+			(media.clone(), offline.clone()),
+			(home.clone(), local.clone()),
+		]);
+		assert_eq!(
+			changes,
+			[ShareChange {
+				share: media.clone(),
+				available: false,
+			}]
+		);
+		assert_eq!(
+			state.unavailable_share(Path::new("/mnt/media/photos/cat.jpg")),
+			Some(media.as_path())
+		);
+		assert!(
+			state
+				.unavailable_share(Path::new("/home/ana/notes"))
+				.is_none()
+		);
+		assert!(
+			state
+				.record_share_availability(vec![(media.clone(), offline), (home.clone(), local)])
+				.is_empty()
+		);
+
+		let changes = state.record_share_availability(vec![(media.clone(), online)]);
+		assert_eq!(
+			changes,
+			[ShareChange {
+				share: media,
+				available: true,
+			}]
+		);
+		assert!(
+			state
+				.unavailable_share(Path::new("/mnt/media/photos"))
+				.is_none()
+		);
+	}
+
 	#[test]
 	fn remote_search_roots_require_explicit_grant_inside_hard_root() {
 		let mut state = State::default();
@@ -417,6 +2014,375 @@ mod tests {
 		));
 	}
 
+	#[test]
+	fn quarantined_grants_queue_writes_except_for_owners() {
+		let mut state = State::default();
+		let guest = PeerId::random();
+		let owner = PeerId::random();
+		let quarantined = FLAG_READ | FLAG_WRITE | FLAG_SEARCH | FLAG_QUARANTINE;
+		state.add_shared_folder(rule("/srv", FLAG_READ | FLAG_WRITE | FLAG_SEARCH));
+		state.set_peer_permissions(
+			guest,
+			vec![
+				Permission::new(Rule::Folder(rule("/srv/drop", quarantined))),
+				Permission::new(Rule::Folder(rule(
+					"/srv/drop/trusted",
+					FLAG_READ | FLAG_WRITE | FLAG_SEARCH,
+				))),
+			],
+		);
+		state.set_peer_permissions(
+			owner,
+			vec![
+				Permission::new(Rule::Owner),
+				Permission::new(Rule::Folder(rule("/srv/drop", quarantined))),
+			],
+		);
+		let now = Utc::now();
+
+		assert!(state.write_needs_review(guest, Path::new("/srv/drop/a.txt"), now));
+		assert!(!state.write_needs_review(guest, Path::new("/srv/drop/trusted/a.txt"), now));
+		assert!(!state.write_needs_review(owner, Path::new("/srv/drop/a.txt"), now));
+		assert!(!state.write_needs_review(state.me, Path::new("/srv/drop/a.txt"), now));
+		assert!(state.has_fs_access(
+			guest,
+			Path::new("/srv/drop/a.txt"),
+			FLAG_WRITE | FLAG_READ | FLAG_SEARCH
+		));
+	}
+
+	#[test]
+	fn preview_grants_list_and_thumbnail_without_reading() {
+		let mut state = State::default();
+		state.add_shared_folder(rule("/srv", FLAG_READ | FLAG_SEARCH));
+		let file = Path::new("/srv/photos/a.jpg");
+		let cases = [
+			(FLAG_READ, true, Some((2048, 1024))),
+			(FLAG_PREVIEW, false, Some((512, 512))),
+			(FLAG_READ | FLAG_PREVIEW, true, Some((2048, 1024))),
+			(0, false, None),
+		];
+		for (flags, can_read, thumbnail) in cases {
+			let peer = PeerId::random();
+			state.set_peer_permissions(
+				peer,
+				vec![Permission::new(Rule::Folder(rule(
+					"/srv/photos",
+					flags | FLAG_SEARCH,
+				)))],
+			);
+			assert_eq!(
+				state.has_fs_access(peer, file, FLAG_READ | FLAG_SEARCH),
+				can_read,
+				"ReadFile with flags {flags:#x}"
+			);
+			assert_eq!(
+				state.has_fs_access(peer, file, FLAG_PREVIEW | FLAG_SEARCH),
+				thumbnail.is_some(),
+				"ListDir with flags {flags:#x}"
+			);
+			assert_eq!(
+				state.thumbnail_bounds(peer, file, 2048, 1024, 512),
+				thumbnail,
+				"GetThumbnail with flags {flags:#x}"
+			);
+		}
+	}
+
+	#[test]
+	fn only_owner_grants_make_a_peer_owner() {
+		let mut state = State::default();
+		let owner = PeerId::random();
+		let guest = PeerId::random();
+		state.set_peer_permissions(owner, vec![Permission::new(Rule::Owner)]);
+		state.set_peer_permissions(
+			guest,
+			vec![
+				Permission::new(Rule::Inbox),
+				Permission::new(Rule::Folder(rule("/tmp/puppynet-allowed", FLAG_READ))),
+			],
+		);
+
+		assert!(state.is_owner(&state.me));
+		assert!(state.is_owner(&owner));
+		assert!(!state.is_owner(&guest));
+		assert!(!state.is_owner(&PeerId::random()));
+	}
+
+	#[test]
+	fn trust_levels_gate_requests_by_scope() {
+		let mut state = State::default();
+		let peer = PeerId::random();
+		let hello = PeerReq::Hello {
+			protocol_version: 1,
+			features: Vec::new(),
+		};
+		let list = PeerReq::ListDir {
+			path: WirePath::from(String::from("/tmp/puppynet-allowed")),
+		};
+
+		assert_eq!(state.trust_level(&peer), TrustLevel::Untrusted);
+		assert!(state.trust_refusal(&peer, &hello).is_none());
+		assert!(state.trust_refusal(&peer, &list).is_some());
+
+		state.set_trust_level(peer, TrustLevel::Basic);
+		assert!(state.trust_refusal(&peer, &list).is_none());
+		let refusal = state.trust_refusal(&peer, &PeerReq::ListCpus).unwrap();
+		assert!(!refusal.allowed());
+		assert_eq!(
+			refusal.trust,
+			Some(TrustRefusal {
+				request: String::from("ListCpus"),
+				level: TrustLevel::Basic,
+				needed: TrustLevel::Trusted,
+			})
+		);
+		assert!(refusal.to_string().contains("needs trusted trust"));
+
+		state.set_trust_level(peer, TrustLevel::Trusted);
+		assert!(state.trust_refusal(&peer, &PeerReq::ListCpus).is_none());
+		assert!(state.trust_refusal(&state.me, &PeerReq::ListCpus).is_none());
+	}
+
+	#[test]
+	fn owner_trust_reaches_every_hard_root() {
+		let mut state = State::default();
+		let peer = PeerId::random();
+		state.add_shared_folder(rule("/tmp/puppynet-allowed", FLAG_READ));
+		let path = Path::new("/tmp/puppynet-allowed/file.txt");
+
+		state.set_trust_level(peer, TrustLevel::Trusted);
+		assert!(!state.is_owner(&peer));
+		assert!(!state.has_fs_access(peer, path, FLAG_READ));
+
+		state.set_trust_level(peer, TrustLevel::Owner);
+		assert!(state.is_owner(&peer));
+		assert!(state.has_fs_access(peer, path, FLAG_READ));
+		assert!(!state.has_fs_access(peer, path, FLAG_WRITE));
+		assert!(!state.has_fs_access(peer, Path::new("/tmp/puppynet-denied/file.txt"), FLAG_READ));
+	}
+
+	#[test]
+	fn closing_a_connection_keeps_the_others_and_counts_the_drop() {
+		let mut state = State::default();
+		let peer = PeerId::random();
+		let now = Utc::now();
+		for (id, direction) in [
+			(1, ConnectionDirection::Outbound),
+			(2, ConnectionDirection::Inbound),
+		] {
+			state.connections.push(Connection {
+				peer_id: peer,
+				connection_id: ConnectionId::new_unchecked(id),
+				direction,
+				local_addr: None,
+				remote_addr: "/ip4/10.0.0.2/tcp/4001".parse().unwrap(),
+				connected_at: now,
+			});
+		}
+
+		let lost = DisconnectReason::Lost;
+		state.remove_connection(
+			ConnectionId::new_unchecked(1),
+			lost,
+			now - Duration::hours(2),
+		);
+		assert!(
+			state
+				.remove_connection(ConnectionId::new_unchecked(2), lost, now)
+				.is_some()
+		);
+		assert!(
+			state
+				.remove_connection(ConnectionId::new_unchecked(2), lost, now)
+				.is_none()
+		);
+		assert!(state.connections_to(&peer).is_empty());
+		assert_eq!(state.recent_drops(&peer, now), 1);
+		assert!(!state.is_idle(&peer));
+
+		let connection = Connection {
+			peer_id: peer,
+			connection_id: ConnectionId::new_unchecked(3),
+			direction: ConnectionDirection::Outbound,
+			local_addr: None,
+			remote_addr: "/ip4/10.0.0.2/udp/4001/quic-v1".parse().unwrap(),
+			connected_at: now,
+		};
+		assert_eq!(connection.transport(), "quic");
+
+		// An idle close is not a drop, and doesn't make the peer look offline.
+		state.connections.push(connection);
+		state.remove_connection(ConnectionId::new_unchecked(3), DisconnectReason::Idle, now);
+		assert_eq!(state.recent_drops(&peer, now), 1);
+		assert!(state.is_idle(&peer));
+	}
+
+	#[test]
+	fn suspended_remote_access_keeps_rules_for_resume() {
+		let mut state = State::default();
+		let peer = PeerId::random();
+		state.add_shared_folder(rule("/tmp/puppynet-allowed", FLAG_READ));
+		state.set_peer_permissions(
+			peer,
+			vec![Permission::new(Rule::Folder(rule(
+				"/tmp/puppynet-allowed",
+				FLAG_READ,
+			)))],
+		);
+		let path = Path::new("/tmp/puppynet-allowed/file.txt");
+
+		state.remote_access_suspended = true;
+		assert!(!state.has_fs_access(peer, path, FLAG_READ));
+		assert!(state.has_fs_access(state.me, path, FLAG_READ));
+		assert_eq!(state.permissions_granted_to_peer(&peer).len(), 1);
+
+		state.remote_access_suspended = false;
+		assert!(state.has_fs_access(peer, path, FLAG_READ));
+	}
+
+	#[test]
+	fn denials_name_the_deciding_grant_and_the_missing_flags() {
+		let mut state = State::default();
+		let peer = PeerId::random();
+		state.add_shared_folder(rule("/data", FLAG_READ | FLAG_WRITE | FLAG_SEARCH));
+		state.set_peer_permissions(
+			peer,
+			vec![Permission::new(Rule::Folder(rule(
+				"/data/projects",
+				FLAG_READ | FLAG_SEARCH,
+			)))],
+		);
+		let file = Path::new("/data/projects/plan.md");
+
+		let read = state.explain_access(peer, file, FLAG_READ | FLAG_SEARCH);
+		assert!(read.allowed());
+		assert!(state.has_fs_access(peer, file, FLAG_READ | FLAG_SEARCH));
+
+		let write = state.explain_access(peer, file, FLAG_WRITE | FLAG_READ | FLAG_SEARCH);
+		assert!(!write.allowed());
+		assert_eq!(write.missing, FLAG_WRITE);
+		assert_eq!(
+			write.granted,
+			Some(rule("/data/projects", FLAG_READ | FLAG_SEARCH))
+		);
+		assert_eq!(
+			write.to_string(),
+			"Access denied: read-only share — ask the owner to enable write for /data/projects"
+		);
+		assert_eq!(
+			write.request_rule(),
+			rule("/data/projects", FLAG_READ | FLAG_WRITE | FLAG_SEARCH)
+		);
+		assert!(
+			write
+				.access_request("12D3KooWpeer")
+				.ends_with("puppynet grant 12D3KooWpeer --write /data/projects")
+		);
+
+		let outside = state.explain_access(peer, Path::new("/data/music/a.mp3"), FLAG_READ);
+		assert_eq!(outside.granted, None);
+		assert_eq!(outside.missing, FLAG_READ);
+		assert_eq!(
+			outside.to_string(),
+			"Access denied: /data/music/a.mp3 is not shared with you"
+		);
+	}
+
+	#[test]
+	fn denials_say_nothing_about_rules_elsewhere() {
+		let mut state = State::default();
+		let peer = PeerId::random();
+		state.add_shared_folder(rule("/data", FLAG_READ | FLAG_WRITE | FLAG_SEARCH));
+		state.add_shared_folder(rule("/secret", FLAG_READ | FLAG_SEARCH));
+		state.set_peer_permissions(
+			peer,
+			vec![
+				Permission::new(Rule::Folder(rule("/data/photos", FLAG_READ))),
+				Permission::new(Rule::Folder(rule("/data/projects/private", FLAG_READ))),
+			],
+		);
+		state.grant_temporary(
+			peer,
+			rule("/data/music", FLAG_READ),
+			Utc::now() - Duration::minutes(1),
+		);
+
+		// A sibling of a grant, the parent of one, and a folder shared with
+		// nobody: none of these may mention the rules beside or below them.
+		for path in ["/data/projects/todo.md", "/data/projects", "/secret/keys"] {
+			let explanation = state.explain_access(peer, Path::new(path), FLAG_READ);
+			assert_eq!(explanation.granted, None, "{path}");
+			assert_eq!(explanation.lapsed, None, "{path}");
+			let encoded = serde_json::to_string(&explanation).unwrap();
+			let message = explanation.to_string();
+			for other in ["photos", "private", "music", "/secret\"", "/data\""] {
+				assert!(!encoded.contains(other), "{path} leaks {other}: {encoded}");
+			}
+			assert!(!message.contains("photos") && !message.contains("private"));
+		}
+
+		let lapsed = state.explain_access(peer, Path::new("/data/music/a.mp3"), FLAG_READ);
+		assert_eq!(
+			lapsed.lapsed.map(|lapsed| lapsed.rule),
+			Some(rule("/data/music", FLAG_READ))
+		);
+	}
+
+	#[test]
+	fn temporary_grants_lapse_and_explain_the_denial() {
+		let mut state = State::default();
+		let peer = PeerId::random();
+		state.add_shared_folder(rule("/tmp/puppynet-allowed", FLAG_READ | FLAG_SEARCH));
+		let now = Utc::now();
+		let file = Path::new("/tmp/puppynet-allowed/report.pdf");
+		let access = FLAG_READ | FLAG_SEARCH;
+		let grant = state.grant_temporary(
+			peer,
+			rule("/tmp/puppynet-allowed/report.pdf", access),
+			now + Duration::hours(1),
+		);
+
+		assert!(state.has_fs_access_at(peer, file, access, now));
+		assert!(!state.has_fs_access_at(peer, file, FLAG_WRITE, now));
+		assert!(!state.has_fs_access_at(
+			peer,
+			Path::new("/tmp/puppynet-allowed/other.pdf"),
+			access,
+			now
+		));
+		assert_eq!(state.temporary_grants_for(&peer, now), vec![grant.clone()]);
+		assert!(state.permissions_granted_to_peer(&peer).is_empty());
+
+		let later = now + Duration::minutes(65);
+		assert!(!state.has_fs_access_at(peer, file, access, later));
+		assert_eq!(state.sweep_temporary_grants(later).len(), 1);
+		assert!(state.temporary_grants_for(&peer, later).is_empty());
+		assert_eq!(
+			state.access_denied_message(&peer, file, later),
+			"Access denied: temporary access to /tmp/puppynet-allowed/report.pdf expired 5 minutes ago"
+		);
+		assert_eq!(
+			state.access_denied_message(&PeerId::random(), file, later),
+			"Access denied"
+		);
+		let next_day = now + Duration::days(2);
+		state.sweep_temporary_grants(next_day);
+		assert_eq!(
+			state.access_denied_message(&peer, file, next_day),
+			"Access denied"
+		);
+
+		let grant = state.grant_temporary(
+			peer,
+			rule("/tmp/puppynet-allowed", access),
+			next_day + Duration::hours(1),
+		);
+		assert!(state.revoke_temporary(&peer, grant.id));
+		assert!(!state.revoke_temporary(&peer, grant.id));
+		assert!(!state.has_fs_access_at(peer, file, access, next_day));
+	}
+
 	#[test]
 	fn remote_search_roots_never_escape_hard_roots() {
 		let mut state = State::default();
@@ -437,4 +2403,353 @@ mod tests {
 			FLAG_READ | FLAG_SEARCH
 		));
 	}
+
+	#[test]
+	fn remote_permission_changes_notify_and_revoke_roots() {
+		let mut state = State::default();
+		let peer = PeerId::random();
+		state.apply_remote_permissions(
+			peer,
+			vec![Permission::new(Rule::Folder(rule("/media", FLAG_READ)))],
+		);
+
+		assert_eq!(state.remote_roots(&peer), vec![PathBuf::from("/media")]);
+		assert_eq!(state.notifications.len(), 1);
+		assert_eq!(
+			state.notifications[0].message,
+			format!("{peer} granted you read access to /media")
+		);
+
+		state.apply_remote_permissions(peer, Vec::new());
+
+		assert!(state.remote_roots(&peer).is_empty());
+		assert!(!state.remote_permissions.contains_key(&peer));
+		assert_eq!(state.notifications.len(), 2);
+		assert_eq!(
+			state.notifications[1].message,
+			format!("{peer} revoked your read access to /media")
+		);
+	}
+
+	#[test]
+	fn most_specific_folder_rule_wins_regardless_of_order() {
+		const RW: u8 = FLAG_READ | FLAG_WRITE | FLAG_SEARCH;
+		const RO: u8 = FLAG_READ | FLAG_SEARCH;
+		// (rules, path, access, expected)
+		type Case<'a> = (&'a [(&'a str, u8)], &'a str, u8, bool);
+		let cases: &[Case] = &[
+			(
+				&[("/data", RO), ("/data/projects", RW)],
+				"/data/projects/a.txt",
+				FLAG_WRITE,
+				true,
+			),
+			(
+				&[("/data/projects", RW), ("/data", RO)],
+				"/data/projects/a.txt",
+				FLAG_WRITE,
+				true,
+			),
+			(
+				&[("/data", RO), ("/data/projects", RW)],
+				"/data/other.txt",
+				FLAG_WRITE,
+				false,
+			),
+			(
+				&[("/data", RW), ("/data/projects", RO)],
+				"/data/projects/a.txt",
+				FLAG_WRITE,
+				false,
+			),
+			(
+				&[("/data", RW), ("/data/projects", RO)],
+				"/data/projects/a.txt",
+				FLAG_READ,
+				true,
+			),
+			(
+				&[("/data", RW), ("/data/projects", RO)],
+				"/data/notes.txt",
+				FLAG_WRITE,
+				true,
+			),
+			(
+				&[("/data", RO), ("/data", FLAG_WRITE)],
+				"/data/a.txt",
+				RW,
+				true,
+			),
+			(
+				&[("/data", RO), ("/data/projects", RW)],
+				"/data/projects-old/a",
+				FLAG_WRITE,
+				false,
+			),
+			(&[("/data/projects", RW)], "/data/a.txt", FLAG_READ, false),
+			(
+				&[("C:\\Data", RO), ("c:\\data\\Projects", RW)],
+				"C:\\DATA\\projects\\a.txt",
+				FLAG_WRITE,
+				true,
+			),
+			(
+				&[("C:\\Data", RO), ("C:\\Data\\Projects", RW)],
+				"C:\\Data\\Docs\\a.txt",
+				FLAG_WRITE,
+				false,
+			),
+			(
+				&[("\\\\nas\\share", RO), ("\\\\NAS\\share\\drop", RW)],
+				"\\\\nas\\Share\\drop\\x",
+				FLAG_WRITE,
+				true,
+			),
+			(
+				&[("C:/Data", RO), ("C:\\Data\\Projects", RW)],
+				"c:/data/projects/a",
+				FLAG_WRITE,
+				true,
+			),
+		];
+		for (idx, (rules, path, access, expected)) in cases.iter().enumerate() {
+			let rules = rules
+				.iter()
+				.map(|(path, flags)| rule(path, *flags))
+				.collect::<Vec<_>>();
+			let allowed = effective_folder_rule(&rules, Path::new(path))
+				.is_some_and(|rule| rule.allows(*access));
+			assert_eq!(allowed, *expected, "case {idx}: {path}");
+		}
+	}
+
+	#[test]
+	fn nested_grant_overrides_broader_grant_for_remote_peer() {
+		let mut state = State::default();
+		let peer = PeerId::random();
+		state.add_shared_folder(rule("/data", FLAG_READ | FLAG_WRITE | FLAG_SEARCH));
+		let overlaps = state.set_peer_permissions(
+			peer,
+			vec![
+				Permission::new(Rule::Folder(rule("/data", FLAG_READ | FLAG_SEARCH))),
+				Permission::new(Rule::Folder(rule(
+					"/data/projects",
+					FLAG_READ | FLAG_WRITE | FLAG_SEARCH,
+				))),
+			],
+		);
+
+		assert!(state.has_fs_access(peer, Path::new("/data/projects/a.txt"), FLAG_WRITE));
+		assert!(!state.has_fs_access(peer, Path::new("/data/a.txt"), FLAG_WRITE));
+		assert_eq!(overlaps.len(), 1);
+		assert_eq!(
+			overlaps[0].describe(),
+			"writes under /data/projects allowed, rest of /data read-only"
+		);
+	}
+
+	#[test]
+	fn listed_access_matches_enforcement() {
+		let mut state = State::default();
+		let peer = PeerId::random();
+		let now = Utc::now();
+		state.add_shared_folder(rule("/data", FLAG_READ | FLAG_WRITE | FLAG_SEARCH));
+		state.add_shared_folder(rule("/data/archive", FLAG_READ | FLAG_SEARCH));
+		state.set_peer_permissions(
+			peer,
+			vec![
+				Permission::new(Rule::Folder(rule("/data", FLAG_READ | FLAG_SEARCH))),
+				Permission::new(Rule::Folder(rule(
+					"/data/projects",
+					FLAG_READ | FLAG_WRITE | FLAG_SEARCH,
+				))),
+				Permission::new(Rule::Folder(rule("/data/archive", FLAG_WRITE))),
+				Permission::new(Rule::Folder(rule("/data/photos", FLAG_PREVIEW))),
+			],
+		);
+		state.grant_temporary(
+			peer,
+			rule("/data/inbox", FLAG_WRITE),
+			now + Duration::minutes(5),
+		);
+
+		let listing = state.listing_access_at(peer, Path::new("/data"), now);
+		assert_eq!(listing.flags, FLAG_READ | FLAG_SEARCH | FLAG_PREVIEW);
+		let listed = listing
+			.entries
+			.iter()
+			.map(|entry| (entry.name.as_str(), entry.rule.as_str()))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			listed,
+			[
+				("archive", "/data/archive"),
+				("inbox", "/data/inbox"),
+				("photos", "/data/photos"),
+				("projects", "/data/projects"),
+			]
+		);
+		for path in [
+			"/data",
+			"/data/projects",
+			"/data/archive",
+			"/data/photos",
+			"/data/inbox",
+		] {
+			let flags = listing
+				.entries
+				.iter()
+				.find(|entry| Path::new(&entry.rule) == Path::new(path))
+				.map_or(listing.flags, |entry| entry.flags);
+			for flag in [FLAG_READ, FLAG_WRITE, FLAG_SEARCH, FLAG_PREVIEW] {
+				assert_eq!(
+					flags & flag != 0,
+					state.has_fs_access_at(peer, Path::new(path), flag, now),
+					"{path} flag {flag:#x}"
+				);
+			}
+		}
+		// Neither the shared folder nor the grant lets the peer write to
+		// the archive; the inbox grant only adds writing.
+		assert_eq!(listing.entries[0].flags, 0);
+		assert_eq!(
+			listing.entries[1].flags,
+			FLAG_READ | FLAG_WRITE | FLAG_SEARCH | FLAG_PREVIEW
+		);
+
+		let nested = state.listing_access_at(peer, Path::new("/data/projects"), now);
+		assert_eq!(
+			nested.flags,
+			FLAG_READ | FLAG_WRITE | FLAG_SEARCH | FLAG_PREVIEW
+		);
+		assert!(nested.entries.is_empty());
+		let me = state.listing_access_at(state.me, Path::new("/data"), now);
+		let listed = me
+			.entries
+			.iter()
+			.map(|entry| entry.name.as_str())
+			.collect::<Vec<_>>();
+		assert_eq!(listed, ["archive"]);
+	}
+
+	#[test]
+	fn shared_folder_overlaps_are_reported_for_the_new_rule() {
+		let mut state = State::default();
+		assert!(
+			state
+				.add_shared_folder(rule("/data", FLAG_READ | FLAG_SEARCH))
+				.is_empty()
+		);
+		assert!(
+			state
+				.add_shared_folder(rule("/music", FLAG_READ | FLAG_SEARCH))
+				.is_empty()
+		);
+
+		let overlaps =
+			state.add_shared_folder(rule("/data/projects", FLAG_READ | FLAG_WRITE | FLAG_SEARCH));
+
+		assert_eq!(overlaps.len(), 1);
+		assert_eq!(overlaps[0].outer.path(), Path::new("/data"));
+	}
+
+	#[test]
+	fn inbox_grant_is_revoked_independently_of_folders() {
+		let mut state = State::default();
+		let peer = PeerId::random();
+		let folder = Permission::new(Rule::Folder(rule("/tmp/puppynet-allowed", FLAG_READ)));
+		state.set_peer_permissions(peer, vec![folder.clone(), Permission::new(Rule::Inbox)]);
+
+		assert!(state.has_inbox_grant(&peer));
+
+		state.set_peer_permissions(peer, vec![folder]);
+
+		assert!(!state.has_inbox_grant(&peer));
+		assert_eq!(state.permissions_granted_to_peer(&peer).len(), 1);
+	}
+
+	fn patterns(patterns: &[&str]) -> Vec<String> {
+		patterns.iter().map(|pattern| pattern.to_string()).collect()
+	}
+
+	#[test]
+	fn deny_patterns_win_over_allow_patterns() {
+		let photos = rule("/srv/photos", FLAG_READ).with_patterns(
+			patterns(&["private", "*.raw"]),
+			patterns(&["*.jpg", "*.raw"]),
+		);
+
+		assert!(!photos.hides(Path::new("/srv/photos/2024/beach.jpg"), false));
+		assert!(photos.hides(Path::new("/srv/photos/2024/beach.raw"), false));
+		assert!(photos.hides(Path::new("/srv/photos/notes.txt"), false));
+		assert!(!photos.hides(Path::new("/srv/photos/2024"), true));
+		assert!(photos.hides(Path::new("/srv/photos/private"), true));
+		assert!(photos.hides(Path::new("/srv/photos/private/me.jpg"), false));
+		assert!(!photos.hides(Path::new("/srv/music/notes.txt"), false));
+		assert!(!photos.hides(Path::new("/srv/photos"), true));
+	}
+
+	#[test]
+	fn path_patterns_match_from_the_folder_root() {
+		let project = rule("/work/app", FLAG_READ)
+			.with_patterns(patterns(&["config/secrets*", ".env"]), Vec::new());
+
+		assert!(project.hides(Path::new("/work/app/config/secrets.toml"), false));
+		assert!(!project.hides(Path::new("/work/app/lib/config/secrets.toml"), false));
+		assert!(project.hides(Path::new("/work/app/lib/.ENV"), false));
+		assert!(!project.hides(Path::new("/work/app/config/app.toml"), false));
+	}
+
+	#[test]
+	fn bad_patterns_are_explained() {
+		assert_eq!(pattern_error("*.key"), None);
+		assert_eq!(pattern_error("config/secrets*"), None);
+		assert!(pattern_error("/etc/*").is_some());
+		assert!(pattern_error("[ab].txt").is_some());
+		assert!(pattern_error("../*.key").is_some());
+	}
+
+	#[test]
+	fn patterns_hide_names_from_peers_only() {
+		let mut state = State::default();
+		let peer = PeerId::random();
+		let now = Utc::now();
+		state.add_shared_folder(
+			rule("/data", FLAG_READ | FLAG_SEARCH).with_patterns(patterns(&["*.key"]), Vec::new()),
+		);
+		state.set_peer_permissions(
+			peer,
+			vec![Permission::new(Rule::Folder(
+				rule("/data", FLAG_READ).with_patterns(patterns(&[".env"]), Vec::new()),
+			))],
+		);
+		state.grant_temporary(
+			peer,
+			rule("/data/tmp", FLAG_READ).with_patterns(patterns(&["*.log"]), Vec::new()),
+			now + Duration::hours(1),
+		);
+
+		let own = state.hidden_names(state.me, now);
+		assert!(!own.hides(Path::new("/data/site.key"), false));
+		let hidden = state.hidden_names(peer, now);
+		assert!(hidden.hides(Path::new("/data/site.key"), false));
+		assert!(hidden.hides(Path::new("/data/.env"), false));
+		assert!(hidden.hides(Path::new("/data/tmp/run.log"), false));
+		assert!(!hidden.hides(Path::new("/data/readme.txt"), false));
+		let later = state.hidden_names(peer, now + Duration::hours(2));
+		assert!(!later.hides(Path::new("/data/tmp/run.log"), false));
+	}
+
+	#[test]
+	fn tied_rules_combine_their_patterns() {
+		let rules = [
+			rule("/data", FLAG_READ).with_patterns(patterns(&["*.key"]), patterns(&["*.jpg"])),
+			rule("/data/", FLAG_SEARCH).with_patterns(patterns(&[".env"]), Vec::new()),
+		];
+
+		let effective = effective_folder_rule(&rules, Path::new("/data/a.txt")).unwrap();
+		assert_eq!(effective.deny_patterns(), patterns(&["*.key", ".env"]));
+		assert!(effective.allow_patterns().is_empty());
+		assert!(!effective.hides(Path::new("/data/a.txt"), false));
+	}
 }
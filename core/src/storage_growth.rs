@@ -0,0 +1,493 @@
+//! How much the folders this node scans grew over a period, and when each
+//! of its disks runs low. Growth comes from the changes scan runs recorded,
+//! summed per top-level folder of each scanned path, and only from the
+//! second completed scan of a path on: the first one finds everything that
+//! was already there. A file moved within a top-level folder takes as many
+//! bytes out as it brings in, so it is left out altogether.
+//!
+//! Projections fit the used space of each disk over the last
+//! [`PROJECTION_DAYS`] of disk samples. A folder scanned once or a disk
+//! sampled once is no trend, and the report says so instead of guessing.
+
+use crate::db::{load_all_disk_samples, path_column};
+use crate::disk_history::{DiskSample, days_until_low, usage_per_day};
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{Connection, params};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Days of disk samples a projection is based on, and the period the
+/// storage page reports on.
+pub const PROJECTION_DAYS: i64 = 30;
+/// Folders listed as growing fastest.
+const FASTEST_GROWING: usize = 5;
+/// Completed runs in the period that follow an earlier completed run of
+/// the same path. Binds the period as `?1` and `?2`.
+const MEASURED_RUNS: &str = "r.status = 'completed' AND r.started_at >= ?1 AND r.started_at <= ?2
+	AND EXISTS (SELECT 1 FROM scan_runs earlier
+		WHERE earlier.path = r.path AND earlier.status = 'completed' AND earlier.id < r.id)";
+
+/// Bytes one top-level folder gained and lost over the period.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DirectoryGrowth {
+	pub path: String,
+	pub bytes_added: u64,
+	pub bytes_removed: u64,
+	pub net_bytes: i64,
+}
+
+/// When one disk drops below the low-space threshold at the rate its used
+/// space grew over the last [`PROJECTION_DAYS`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiskProjection {
+	pub disk_id: String,
+	pub mount_path: String,
+	pub total_space: u64,
+	pub available_space: u64,
+	pub samples: u64,
+	/// Used space gained per day; `None` with fewer than two samples.
+	pub bytes_per_day: Option<f64>,
+	/// Days until free space drops below the threshold, 0 once it has.
+	/// `None` while usage isn't growing or there are too few samples.
+	pub days_until_low: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GrowthReport {
+	pub since: DateTime<Utc>,
+	pub until: DateTime<Utc>,
+	/// Completed scans in the period whose changes are counted.
+	pub scans: u64,
+	/// Why growth can't be told yet: no folder was scanned twice.
+	pub insufficient_data: Option<String>,
+	/// Folders that changed, the one that grew most first.
+	pub directories: Vec<DirectoryGrowth>,
+	/// The folders that grew most, up to five.
+	pub fastest_growing: Vec<DirectoryGrowth>,
+	/// Free space, in percent, the projections count down to.
+	pub low_space_percent: u8,
+	pub projection_days: i64,
+	pub disks: Vec<DiskProjection>,
+}
+
+/// A file a scan found added, removed or changed, with the hash and size
+/// of its contents before and after.
+struct SizedChange {
+	root: PathBuf,
+	path: PathBuf,
+	before: Option<(Option<Vec<u8>>, u64)>,
+	after: Option<(Option<Vec<u8>>, u64)>,
+}
+
+/// The folder directly under `root` that holds `path`, or `root` for the
+/// files right in it.
+fn top_level(root: &Path, path: &Path) -> PathBuf {
+	let Ok(rest) = path.strip_prefix(root) else {
+		return root.to_path_buf();
+	};
+	let mut components = rest.components();
+	match (components.next(), components.next()) {
+		(Some(first), Some(_)) => root.join(first),
+		_ => root.to_path_buf(),
+	}
+}
+
+/// Growth of each top-level folder `changes` touched. Contents that left a
+/// folder and turned up elsewhere in it were moved, and count neither way.
+fn directory_growth(changes: Vec<SizedChange>) -> Vec<DirectoryGrowth> {
+	#[derive(Default)]
+	struct Tally {
+		added: Vec<(Option<Vec<u8>>, u64)>,
+		removed: Vec<(Option<Vec<u8>>, u64)>,
+		grown: u64,
+		shrunk: u64,
+	}
+	let mut folders = HashMap::<PathBuf, Tally>::new();
+	for change in changes {
+		let tally = folders
+			.entry(top_level(&change.root, &change.path))
+			.or_default();
+		match (change.before, change.after) {
+			(None, Some(after)) => tally.added.push(after),
+			(Some(before), None) => tally.removed.push(before),
+			(Some((_, before)), Some((_, after))) if after >= before => {
+				tally.grown += after - before
+			}
+			(Some((_, before)), Some((_, after))) => tally.shrunk += before - after,
+			(None, None) => {}
+		}
+	}
+	let mut rows = folders
+		.into_iter()
+		.map(|(path, tally)| {
+			let mut left = HashMap::<&[u8], usize>::new();
+			for hash in tally.removed.iter().filter_map(|(hash, _)| hash.as_deref()) {
+				*left.entry(hash).or_default() += 1;
+			}
+			let mut moved = HashMap::<&[u8], usize>::new();
+			let mut bytes_added = tally.grown;
+			for (hash, size) in &tally.added {
+				match hash
+					.as_deref()
+					.and_then(|hash| Some((hash, left.get_mut(hash)?)))
+				{
+					Some((hash, count)) if *count > 0 => {
+						*count -= 1;
+						*moved.entry(hash).or_default() += 1;
+					}
+					_ => bytes_added += size,
+				}
+			}
+			let mut bytes_removed = tally.shrunk;
+			for (hash, size) in &tally.removed {
+				match hash.as_deref().and_then(|hash| moved.get_mut(hash)) {
+					Some(count) if *count > 0 => *count -= 1,
+					_ => bytes_removed += size,
+				}
+			}
+			DirectoryGrowth {
+				path: path.to_string_lossy().into_owned(),
+				bytes_added,
+				bytes_removed,
+				net_bytes: bytes_added as i64 - bytes_removed as i64,
+			}
+		})
+		.filter(|row| row.bytes_added > 0 || row.bytes_removed > 0)
+		.collect::<Vec<_>>();
+	rows.sort_by(|left, right| {
+		right
+			.net_bytes
+			.cmp(&left.net_bytes)
+			.then_with(|| left.path.cmp(&right.path))
+	});
+	rows
+}
+
+fn load_changes(
+	conn: &Connection,
+	since: DateTime<Utc>,
+	until: DateTime<Utc>,
+) -> anyhow::Result<Vec<SizedChange>> {
+	let mut stmt = conn.prepare(&format!(
+		"SELECT r.path, c.path, c.change, c.old_hash, old_entry.size, c.new_hash, new_entry.size
+		FROM scan_run_changes c
+		JOIN scan_runs r ON r.id = c.run_id
+		LEFT JOIN file_entries old_entry ON old_entry.hash = c.old_hash
+		LEFT JOIN file_entries new_entry ON new_entry.hash = c.new_hash
+		WHERE {MEASURED_RUNS}
+		ORDER BY c.run_id ASC, c.id ASC"
+	))?;
+	let rows = stmt.query_map(params![since.timestamp(), until.timestamp()], |row| {
+		let change: String = row.get(2)?;
+		let content = |hash: usize, size: usize| -> rusqlite::Result<_> {
+			Ok((
+				row.get::<_, Option<Vec<u8>>>(hash)?,
+				row.get::<_, Option<i64>>(size)?.unwrap_or(0).max(0) as u64,
+			))
+		};
+		Ok(SizedChange {
+			root: PathBuf::from(row.get::<_, String>(0)?),
+			path: path_column(row, 1)?,
+			before: (change != "added").then(|| content(3, 4)).transpose()?,
+			after: (change != "removed").then(|| content(5, 6)).transpose()?,
+		})
+	})?;
+	let mut changes = Vec::new();
+	for row in rows {
+		changes.push(row?);
+	}
+	Ok(changes)
+}
+
+/// Why no growth can be told from the scans recorded, if it can't.
+fn insufficient_data(conn: &Connection) -> anyhow::Result<Option<String>> {
+	let (scans, rescanned): (i64, bool) = conn.query_row(
+		"SELECT COUNT(*), EXISTS (SELECT 1 FROM scan_runs WHERE status = 'completed'
+			GROUP BY path HAVING COUNT(*) > 1)
+		FROM scan_runs WHERE status = 'completed'",
+		[],
+		|row| Ok((row.get(0)?, row.get(1)?)),
+	)?;
+	Ok(match (scans, rescanned) {
+		(0, _) => Some(String::from("No scans recorded yet")),
+		(_, false) => Some(String::from(
+			"Every folder was scanned only once; growth shows once one is scanned again",
+		)),
+		_ => None,
+	})
+}
+
+/// Projections of the disks `samples` were taken of, by mount path.
+fn disk_projections(samples: Vec<DiskSample>, low_space_percent: u8) -> Vec<DiskProjection> {
+	let mut disks = BTreeMap::<String, Vec<DiskSample>>::new();
+	for sample in samples {
+		disks
+			.entry(sample.disk_id.clone())
+			.or_default()
+			.push(sample);
+	}
+	let mut projections = disks
+		.into_values()
+		.filter_map(|samples| {
+			let latest = samples.iter().max_by_key(|sample| sample.sampled_at)?;
+			Some(DiskProjection {
+				disk_id: latest.disk_id.clone(),
+				mount_path: latest.mount_path.clone(),
+				total_space: latest.total_space,
+				available_space: latest.available_space,
+				samples: samples.len() as u64,
+				bytes_per_day: usage_per_day(&samples),
+				days_until_low: days_until_low(&samples, low_space_percent),
+			})
+		})
+		.collect::<Vec<_>>();
+	projections.sort_by(|left, right| left.mount_path.cmp(&right.mount_path));
+	projections
+}
+
+/// Growth of the scanned folders between `since` and `until`, with the
+/// disks projected from the samples of the [`PROJECTION_DAYS`] before
+/// `until`.
+pub(crate) fn growth_report(
+	conn: &Connection,
+	since: DateTime<Utc>,
+	until: DateTime<Utc>,
+	low_space_percent: u8,
+) -> anyhow::Result<GrowthReport> {
+	let scans: i64 = conn.query_row(
+		&format!("SELECT COUNT(*) FROM scan_runs r WHERE {MEASURED_RUNS}"),
+		params![since.timestamp(), until.timestamp()],
+		|row| row.get(0),
+	)?;
+	let directories = directory_growth(load_changes(conn, since, until)?);
+	let fastest_growing = directories
+		.iter()
+		.filter(|row| row.net_bytes > 0)
+		.take(FASTEST_GROWING)
+		.cloned()
+		.collect();
+	let samples = load_all_disk_samples(conn, until - Duration::days(PROJECTION_DAYS), until)?;
+	Ok(GrowthReport {
+		since,
+		until,
+		scans: scans.max(0) as u64,
+		insufficient_data: insufficient_data(conn)?,
+		directories,
+		fastest_growing,
+		low_space_percent,
+		projection_days: PROJECTION_DAYS,
+		disks: disk_projections(samples, low_space_percent),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::db::{record_disk_samples, record_scan_run};
+	use crate::scan::{ScanChange, ScanChangeKind, ScanResult};
+	use crate::test_util;
+	use chrono::TimeZone;
+
+	const GB: u64 = 1024 * 1024 * 1024;
+
+	fn open() -> Connection {
+		test_util::memory_db()
+	}
+
+	fn day(day: u32) -> DateTime<Utc> {
+		Utc.with_ymd_and_hms(2025, 3, day, 12, 0, 0).unwrap()
+	}
+
+	/// Content `id` of `size` bytes, known to the index.
+	fn content(conn: &Connection, id: u8, size: u64) -> [u8; 32] {
+		let hash = [id; 32];
+		conn.execute(
+			"INSERT INTO file_entries (hash, size) VALUES (?1, ?2)",
+			params![hash.to_vec(), size as i64],
+		)
+		.unwrap();
+		hash
+	}
+
+	fn change(
+		path: &str,
+		kind: ScanChangeKind,
+		old_hash: Option<[u8; 32]>,
+		new_hash: Option<[u8; 32]>,
+	) -> ScanChange {
+		ScanChange {
+			path: PathBuf::from(path),
+			kind,
+			old_hash,
+			new_hash,
+		}
+	}
+
+	fn record(conn: &Connection, path: &str, at: DateTime<Utc>, changes: Vec<ScanChange>) {
+		let outcome = Ok(ScanResult {
+			updated_count: 0,
+			inserted_count: 0,
+			removed_count: 0,
+			duration: std::time::Duration::from_secs(1),
+			file_count: 0,
+			checksums: None,
+			run: None,
+			changes,
+		});
+		record_scan_run(
+			conn,
+			path,
+			at,
+			std::time::Duration::from_secs(1),
+			None,
+			&outcome,
+			false,
+		)
+		.unwrap();
+	}
+
+	fn growth(report: &GrowthReport) -> Vec<(&str, u64, u64, i64)> {
+		report
+			.directories
+			.iter()
+			.map(|row| {
+				(
+					row.path.as_str(),
+					row.bytes_added,
+					row.bytes_removed,
+					row.net_bytes,
+				)
+			})
+			.collect()
+	}
+
+	#[test]
+	fn growth_adds_up_the_scans_after_the_first_and_leaves_out_moves() {
+		use ScanChangeKind::{Added, Changed, Removed};
+		let conn = open();
+		let a1 = content(&conn, 1, 100);
+		let b2 = content(&conn, 2, 50);
+		let c4 = content(&conn, 4, 30);
+		let a3 = content(&conn, 5, 200);
+		let a1_edited = content(&conn, 6, 150);
+		let top = content(&conn, 7, 5);
+		record(
+			&conn,
+			"/data",
+			day(1),
+			vec![
+				change("/data/a/1", Added, None, Some(a1)),
+				change("/data/b/2", Added, None, Some(b2)),
+				change("/data/c/4", Added, None, Some(c4)),
+			],
+		);
+		record(
+			&conn,
+			"/data",
+			day(10),
+			vec![
+				change("/data/a/3", Added, None, Some(a3)),
+				change("/data/b/2", Removed, Some(b2), None),
+				change("/data/a/1", Changed, Some(a1), Some(a1_edited)),
+			],
+		);
+		record(
+			&conn,
+			"/data",
+			day(20),
+			vec![
+				change("/data/c/4", Removed, Some(c4), None),
+				change("/data/c/sub/4", Added, None, Some(c4)),
+				change("/data/top.txt", Added, None, Some(top)),
+			],
+		);
+		// Scanned once: everything it found was already there.
+		record(
+			&conn,
+			"/other",
+			day(15),
+			vec![change("/other/x/big", Added, None, Some(a3))],
+		);
+
+		let report = growth_report(&conn, day(5), day(25), 10).unwrap();
+		assert_eq!(report.scans, 2);
+		assert_eq!(report.insufficient_data, None);
+		assert_eq!(
+			growth(&report),
+			[
+				("/data/a", 250, 0, 250),
+				("/data", 5, 0, 5),
+				("/data/b", 0, 50, -50),
+			]
+		);
+		assert_eq!(
+			report
+				.fastest_growing
+				.iter()
+				.map(|row| row.path.as_str())
+				.collect::<Vec<_>>(),
+			["/data/a", "/data"]
+		);
+
+		let recent = growth_report(&conn, day(15), day(25), 10).unwrap();
+		assert_eq!(recent.scans, 1);
+		assert_eq!(growth(&recent), [("/data", 5, 0, 5)]);
+	}
+
+	#[test]
+	fn a_single_scan_is_not_enough_to_tell_growth() {
+		let conn = open();
+		let report = growth_report(&conn, day(1), day(25), 10).unwrap();
+		assert_eq!(
+			report.insufficient_data.as_deref(),
+			Some("No scans recorded yet")
+		);
+
+		let file = content(&conn, 1, 100);
+		record(
+			&conn,
+			"/data",
+			day(2),
+			vec![change("/data/a/1", ScanChangeKind::Added, None, Some(file))],
+		);
+		let report = growth_report(&conn, day(1), day(25), 10).unwrap();
+		assert!(report.insufficient_data.is_some());
+		assert_eq!(report.scans, 0);
+		assert!(report.directories.is_empty());
+		assert!(report.fastest_growing.is_empty());
+	}
+
+	#[test]
+	fn disks_project_to_the_low_space_threshold_only_with_a_trend() {
+		let conn = open();
+		let sample = |disk_id: &str, day_of_month: u32, available_space: u64| DiskSample {
+			disk_id: disk_id.to_string(),
+			mount_path: format!("/mnt/{disk_id}"),
+			sampled_at: day(day_of_month),
+			total_space: 100 * GB,
+			available_space,
+		};
+		let mut samples = (1..=5)
+			.map(|day| sample("steady", day, (50 - u64::from(day)) * GB))
+			.collect::<Vec<_>>();
+		samples.push(sample("once", 5, 80 * GB));
+		samples.push(sample("full", 4, 8 * GB));
+		samples.push(sample("full", 5, 5 * GB));
+		record_disk_samples(&conn, &samples).unwrap();
+
+		let report = growth_report(&conn, day(1), day(5), 10).unwrap();
+		let disks = report
+			.disks
+			.iter()
+			.map(|disk| (disk.mount_path.as_str(), disk))
+			.collect::<HashMap<_, _>>();
+		let steady = disks["/mnt/steady"];
+		assert!((steady.bytes_per_day.unwrap() - GB as f64).abs() < 1.0);
+		// 45 GB free, 10 GB of it kept back, at 1 GB a day.
+		assert!((steady.days_until_low.unwrap() - 35.0).abs() < 0.01);
+		assert_eq!(disks["/mnt/once"].bytes_per_day, None);
+		assert_eq!(disks["/mnt/once"].days_until_low, None);
+		assert_eq!(disks["/mnt/full"].days_until_low, Some(0.0));
+	}
+}
@@ -0,0 +1,701 @@
+//! What each node has indexed as a tree of folders with their total size,
+//! item count and latest change, built once from the storage usage rows.
+//! The export and the treemap on the storage page both read this tree.
+//!
+//! The export writes one row per entry, parents before their children and
+//! largest first, so a reader can stream it. JSON is an array with one
+//! object per line; CSV has a header and quotes fields the way
+//! spreadsheets expect.
+//!
+//! The treemap is squarified: each folder's children fill its rectangle in
+//! rows chosen to keep them close to square. Children too small to see, or
+//! past the rectangle budget, share one "other" rectangle instead.
+
+use crate::db::StorageUsageFile;
+use crate::path::PathStyle;
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::{Read, Write};
+
+/// The entry holding every file of the node. Its path is empty.
+pub const STORAGE_ROOT: usize = 0;
+/// Rectangles the storage page draws at most, parents included.
+pub const TREEMAP_MAX_RECTS: usize = 2000;
+/// Rectangles smaller than this, in square units of the laid out area, go
+/// into "other".
+const TREEMAP_MIN_AREA: f64 = 12.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageEntry {
+	pub path: String,
+	pub name: String,
+	pub size: u64,
+	/// Files at or below the entry; 1 for a file.
+	pub items: u64,
+	pub last_changed: Option<DateTime<Utc>>,
+	/// Levels below the root; the root is 0.
+	pub depth: u32,
+	/// Largest first.
+	pub children: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StorageTree {
+	entries: Vec<StorageEntry>,
+	by_path: HashMap<String, usize>,
+}
+
+impl StorageTree {
+	pub fn build<'a>(files: impl IntoIterator<Item = &'a StorageUsageFile>) -> Self {
+		let mut tree = Self {
+			entries: vec![StorageEntry {
+				path: String::new(),
+				name: String::new(),
+				size: 0,
+				items: 0,
+				last_changed: None,
+				depth: 0,
+				children: Vec::new(),
+			}],
+			by_path: HashMap::new(),
+		};
+		for file in files {
+			let style = PathStyle::detect(file.path.as_bytes());
+			let names = path_names(&file.path, style);
+			if names.is_empty() {
+				continue;
+			}
+			tree.count(STORAGE_ROOT, file);
+			let mut parent = STORAGE_ROOT;
+			for name in names {
+				let path = join_path(&tree.entries[parent].path, name, style);
+				let idx = match tree.by_path.get(&path) {
+					Some(idx) => *idx,
+					None => tree.add_child(parent, path, name),
+				};
+				tree.count(idx, file);
+				parent = idx;
+			}
+		}
+		let sizes = tree
+			.entries
+			.iter()
+			.map(|entry| entry.size)
+			.collect::<Vec<_>>();
+		for entry in &mut tree.entries {
+			entry
+				.children
+				.sort_by(|left, right| sizes[*right].cmp(&sizes[*left]).then(left.cmp(right)));
+		}
+		tree
+	}
+
+	fn add_child(&mut self, parent: usize, path: String, name: &str) -> usize {
+		let idx = self.entries.len();
+		let depth = self.entries[parent].depth + 1;
+		self.entries.push(StorageEntry {
+			path: path.clone(),
+			name: name.to_string(),
+			size: 0,
+			items: 0,
+			last_changed: None,
+			depth,
+			children: Vec::new(),
+		});
+		self.entries[parent].children.push(idx);
+		self.by_path.insert(path, idx);
+		idx
+	}
+
+	fn count(&mut self, idx: usize, file: &StorageUsageFile) {
+		let entry = &mut self.entries[idx];
+		entry.size += file.size;
+		entry.items += 1;
+		entry.last_changed = entry.last_changed.max(file.last_changed);
+	}
+
+	pub fn entry(&self, idx: usize) -> &StorageEntry {
+		&self.entries[idx]
+	}
+
+	pub fn find(&self, path: &str) -> Option<usize> {
+		if path.is_empty() {
+			return Some(STORAGE_ROOT);
+		}
+		self.by_path.get(path).copied()
+	}
+
+	/// `idx` and the entries above it, from the root down.
+	pub fn ancestors(&self, idx: usize) -> Vec<usize> {
+		let mut chain = vec![idx];
+		let mut path = self.entries[idx].path.as_str();
+		while let Some(parent) = parent_path(path) {
+			let Some(parent_idx) = self.find(parent) else {
+				break;
+			};
+			chain.push(parent_idx);
+			path = parent;
+		}
+		if chain.last() != Some(&STORAGE_ROOT) {
+			chain.push(STORAGE_ROOT);
+		}
+		chain.reverse();
+		chain
+	}
+
+	/// Rows of the entries from the root down to `max_depth`, parents first.
+	pub fn rows(&self, max_depth: Option<u32>) -> impl Iterator<Item = StorageTreeRow> + '_ {
+		let mut stack = vec![STORAGE_ROOT];
+		std::iter::from_fn(move || {
+			let idx = stack.pop()?;
+			let entry = &self.entries[idx];
+			if max_depth.is_none_or(|max| entry.depth < max) {
+				stack.extend(entry.children.iter().rev());
+			}
+			Some(StorageTreeRow {
+				path: entry.path.clone(),
+				size: entry.size,
+				items: entry.items,
+				last_changed: entry.last_changed,
+				depth: entry.depth,
+			})
+		})
+	}
+}
+
+/// The names along `path`, without the separators and the leading `/`.
+fn path_names(path: &str, style: PathStyle) -> Vec<&str> {
+	path.split(|c| c == '/' || (style == PathStyle::Windows && c == '\\'))
+		.filter(|name| !name.is_empty())
+		.collect()
+}
+
+fn join_path(parent: &str, name: &str, style: PathStyle) -> String {
+	match (parent.is_empty(), style) {
+		(true, PathStyle::Unix) => format!("/{name}"),
+		(true, PathStyle::Windows) => name.to_string(),
+		(false, PathStyle::Unix) => format!("{parent}/{name}"),
+		(false, PathStyle::Windows) => format!("{parent}\\{name}"),
+	}
+}
+
+fn parent_path(path: &str) -> Option<&str> {
+	path.rfind(['/', '\\']).map(|at| &path[..at])
+}
+
+/// The storage tree of one node.
+#[derive(Debug, Clone)]
+pub struct NodeStorageTree {
+	pub node_id: Vec<u8>,
+	pub node_name: String,
+	pub tree: StorageTree,
+}
+
+/// One tree per node in `files`, in the order the nodes first appear.
+pub fn trees_by_node(files: &[StorageUsageFile]) -> Vec<NodeStorageTree> {
+	let mut nodes: Vec<(&[u8], &str, Vec<&StorageUsageFile>)> = Vec::new();
+	for file in files {
+		match nodes
+			.iter_mut()
+			.find(|(id, _, _)| *id == file.node_id.as_slice())
+		{
+			Some((_, _, node_files)) => node_files.push(file),
+			None => nodes.push((file.node_id.as_slice(), file.node_name.as_str(), vec![file])),
+		}
+	}
+	nodes
+		.into_iter()
+		.map(|(node_id, node_name, node_files)| NodeStorageTree {
+			node_id: node_id.to_vec(),
+			node_name: node_name.to_string(),
+			tree: StorageTree::build(node_files),
+		})
+		.collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageExportFormat {
+	Json,
+	Csv,
+}
+
+impl StorageExportFormat {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Self::Json => "json",
+			Self::Csv => "csv",
+		}
+	}
+
+	pub fn parse(value: &str) -> Option<Self> {
+		match value {
+			"json" => Some(Self::Json),
+			"csv" => Some(Self::Csv),
+			_ => None,
+		}
+	}
+}
+
+/// One exported entry. The root has an empty path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageTreeRow {
+	pub path: String,
+	pub size: u64,
+	pub items: u64,
+	pub last_changed: Option<DateTime<Utc>>,
+	pub depth: u32,
+}
+
+const CSV_HEADER: &str = "path,size,items,last_changed,depth";
+
+/// Writes the rows of `tree` down to `max_depth` to `out` as they are
+/// walked, and returns how many were written.
+pub fn write_storage_export(
+	tree: &StorageTree,
+	max_depth: Option<u32>,
+	format: StorageExportFormat,
+	out: &mut impl Write,
+) -> Result<u64> {
+	let mut written = 0;
+	match format {
+		StorageExportFormat::Json => out.write_all(b"[")?,
+		StorageExportFormat::Csv => writeln!(out, "{CSV_HEADER}")?,
+	}
+	for row in tree.rows(max_depth) {
+		match format {
+			StorageExportFormat::Json => {
+				out.write_all(if written == 0 { b"\n" } else { b",\n" })?;
+				serde_json::to_writer(&mut *out, &row)?;
+			}
+			StorageExportFormat::Csv => writeln!(
+				out,
+				"{},{},{},{},{}",
+				csv_field(&row.path),
+				row.size,
+				row.items,
+				row.last_changed
+					.map(|at| at.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+					.unwrap_or_default(),
+				row.depth,
+			)?,
+		}
+		written += 1;
+	}
+	if format == StorageExportFormat::Json {
+		out.write_all(b"\n]\n")?;
+	}
+	Ok(written)
+}
+
+fn csv_field(value: &str) -> String {
+	if value.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", value.replace('"', "\"\""))
+	} else {
+		value.to_string()
+	}
+}
+
+/// Reads back what [`write_storage_export`] wrote, to compare snapshots.
+pub fn read_storage_export(
+	mut input: impl Read,
+	format: StorageExportFormat,
+) -> Result<Vec<StorageTreeRow>> {
+	match format {
+		StorageExportFormat::Json => Ok(serde_json::from_reader(input)?),
+		StorageExportFormat::Csv => {
+			let mut text = String::new();
+			input.read_to_string(&mut text)?;
+			let mut records = csv_records(&text)?.into_iter();
+			match records.next() {
+				Some(header) if header.join(",") == CSV_HEADER => {}
+				_ => bail!("not a storage export: the header is missing"),
+			}
+			records.map(|record| csv_row(&record)).collect()
+		}
+	}
+}
+
+fn csv_row(record: &[String]) -> Result<StorageTreeRow> {
+	let [path, size, items, last_changed, depth] = record else {
+		bail!("expected 5 fields, found {}", record.len());
+	};
+	Ok(StorageTreeRow {
+		path: path.clone(),
+		size: size
+			.parse()
+			.with_context(|| format!("invalid size {size:?}"))?,
+		items: items
+			.parse()
+			.with_context(|| format!("invalid item count {items:?}"))?,
+		last_changed: match last_changed.as_str() {
+			"" => None,
+			value => Some(
+				DateTime::parse_from_rfc3339(value)
+					.with_context(|| format!("invalid time {value:?}"))?
+					.with_timezone(&Utc),
+			),
+		},
+		depth: depth
+			.parse()
+			.with_context(|| format!("invalid depth {depth:?}"))?,
+	})
+}
+
+fn csv_records(text: &str) -> Result<Vec<Vec<String>>> {
+	let mut records = Vec::new();
+	let mut record = Vec::new();
+	let mut field = String::new();
+	let mut quoted = false;
+	let mut chars = text.chars().peekable();
+	while let Some(c) = chars.next() {
+		match (quoted, c) {
+			(true, '"') if chars.peek() == Some(&'"') => {
+				chars.next();
+				field.push('"');
+			}
+			(true, '"') => quoted = false,
+			(true, c) => field.push(c),
+			(false, '"') if field.is_empty() => quoted = true,
+			(false, ',') => record.push(std::mem::take(&mut field)),
+			(false, '\r') => {}
+			(false, '\n') => {
+				record.push(std::mem::take(&mut field));
+				records.push(std::mem::take(&mut record));
+			}
+			(false, c) => field.push(c),
+		}
+	}
+	if quoted {
+		bail!("a quoted field is not closed");
+	}
+	if !field.is_empty() || !record.is_empty() {
+		record.push(field);
+		records.push(record);
+	}
+	Ok(records)
+}
+
+/// One rectangle of a treemap, in the units of the area it was laid out in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreemapRect {
+	/// `None` for the rectangle standing in for children too small to draw.
+	pub entry: Option<usize>,
+	pub size: u64,
+	pub items: u64,
+	/// Levels below the entry the treemap starts at, from 1.
+	pub depth: u32,
+	pub x: f64,
+	pub y: f64,
+	pub width: f64,
+	pub height: f64,
+	/// Whether the rectangle has children drawn inside it.
+	pub split: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Area {
+	x: f64,
+	y: f64,
+	width: f64,
+	height: f64,
+}
+
+/// Lays out the entries below `root` down to `max_depth` levels in a
+/// `width` by `height` area, parents before their children and at most
+/// `max_rects` rectangles. Empty when nothing below `root` has a size.
+pub fn lay_out_treemap(
+	tree: &StorageTree,
+	root: usize,
+	width: f64,
+	height: f64,
+	max_depth: u32,
+	max_rects: usize,
+) -> Vec<TreemapRect> {
+	let mut rects: Vec<TreemapRect> = Vec::new();
+	let area = Area {
+		x: 0.0,
+		y: 0.0,
+		width,
+		height,
+	};
+	let mut queue = VecDeque::from([(root, area, 0, None::<usize>)]);
+	while let Some((idx, area, depth, parent_rect)) = queue.pop_front() {
+		if depth >= max_depth {
+			continue;
+		}
+		let entry = tree.entry(idx);
+		let scale = area.width * area.height / entry.size.max(1) as f64;
+		let budget = max_rects.saturating_sub(rects.len());
+		let mut shown = Vec::new();
+		let mut other = (0, 0);
+		for child in &entry.children {
+			let child_entry = tree.entry(*child);
+			if child_entry.size > 0
+				&& shown.len() + 1 < budget
+				&& child_entry.size as f64 * scale >= TREEMAP_MIN_AREA
+			{
+				shown.push(*child);
+			} else {
+				other.0 += child_entry.size;
+				other.1 += child_entry.items;
+			}
+		}
+		if shown.is_empty() {
+			continue;
+		}
+		let mut sizes = shown
+			.iter()
+			.map(|child| tree.entry(*child).size)
+			.collect::<Vec<_>>();
+		if other.0 > 0 {
+			sizes.push(other.0);
+		}
+		let areas = sizes
+			.iter()
+			.map(|size| *size as f64 * scale)
+			.collect::<Vec<_>>();
+		if let Some(parent_rect) = parent_rect {
+			rects[parent_rect].split = true;
+		}
+		for (i, child_area) in squarify(&areas, area).into_iter().enumerate() {
+			let (entry, items) = match shown.get(i) {
+				Some(child) => (Some(*child), tree.entry(*child).items),
+				None => (None, other.1),
+			};
+			rects.push(TreemapRect {
+				entry,
+				size: sizes[i],
+				items,
+				depth: depth + 1,
+				x: child_area.x,
+				y: child_area.y,
+				width: child_area.width,
+				height: child_area.height,
+				split: false,
+			});
+			if let Some(child) = entry {
+				queue.push_back((child, child_area, depth + 1, Some(rects.len() - 1)));
+			}
+		}
+	}
+	rects
+}
+
+/// Splits `area` into rectangles of `areas`, which are sorted largest first
+/// and sum to the area.
+fn squarify(areas: &[f64], area: Area) -> Vec<Area> {
+	let mut placed = Vec::with_capacity(areas.len());
+	let mut free = area;
+	let mut start = 0;
+	while start < areas.len() {
+		let side = free.width.min(free.height);
+		let mut end = start + 1;
+		let mut sum = areas[start];
+		let mut worst = worst_ratio(&areas[start..end], sum, side);
+		while end < areas.len() {
+			let next_sum = sum + areas[end];
+			let next = worst_ratio(&areas[start..=end], next_sum, side);
+			if next > worst {
+				break;
+			}
+			worst = next;
+			sum = next_sum;
+			end += 1;
+		}
+		free = place_row(&areas[start..end], sum, free, &mut placed);
+		start = end;
+	}
+	placed
+}
+
+/// The most elongated aspect ratio of `row` laid along a side of `side`.
+fn worst_ratio(row: &[f64], sum: f64, side: f64) -> f64 {
+	let (largest, smallest) = (row[0], row[row.len() - 1]);
+	if smallest <= 0.0 || sum <= 0.0 {
+		return f64::INFINITY;
+	}
+	let side = side * side;
+	let sum = sum * sum;
+	(side * largest / sum).max(sum / (side * smallest))
+}
+
+/// Places `row` along the shorter side of `free` and returns what is left.
+fn place_row(row: &[f64], sum: f64, free: Area, placed: &mut Vec<Area>) -> Area {
+	if free.width >= free.height {
+		let width = if free.height > 0.0 {
+			sum / free.height
+		} else {
+			0.0
+		};
+		let mut y = free.y;
+		for area in row {
+			let height = if width > 0.0 { area / width } else { 0.0 };
+			placed.push(Area {
+				x: free.x,
+				y,
+				width,
+				height,
+			});
+			y += height;
+		}
+		Area {
+			x: free.x + width,
+			width: (free.width - width).max(0.0),
+			..free
+		}
+	} else {
+		let height = if free.width > 0.0 {
+			sum / free.width
+		} else {
+			0.0
+		};
+		let mut x = free.x;
+		for area in row {
+			let width = if height > 0.0 { area / height } else { 0.0 };
+			placed.push(Area {
+				x,
+				y: free.y,
+				width,
+				height,
+			});
+			x += width;
+		}
+		Area {
+			y: free.y + height,
+			height: (free.height - height).max(0.0),
+			..free
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::TimeZone;
+
+	fn file(path: &str, size: u64) -> StorageUsageFile {
+		StorageUsageFile {
+			node_id: vec![1],
+			node_name: String::from("nas"),
+			path: path.to_string(),
+			size,
+			last_changed: Some(Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap()),
+		}
+	}
+
+	fn sample_tree() -> StorageTree {
+		StorageTree::build(&[
+			file("/data/photos/2024/a.jpg", 600),
+			file("/data/photos/2025/b.jpg", 300),
+			file("/data/music/song.flac", 250),
+			file("/data/notes, \"draft\".txt", 40),
+			file("/data/empty.txt", 0),
+			file("/home/ana/todo.txt", 10),
+		])
+	}
+
+	#[test]
+	fn folders_sum_their_files_and_list_children_largest_first() {
+		let tree = sample_tree();
+		let root = tree.entry(STORAGE_ROOT);
+		assert_eq!((root.size, root.items), (1200, 6));
+
+		let data = tree.entry(tree.find("/data").unwrap());
+		assert_eq!((data.size, data.items, data.depth), (1190, 5, 1));
+		let names = data
+			.children
+			.iter()
+			.map(|child| tree.entry(*child).name.as_str())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			names,
+			["photos", "music", "notes, \"draft\".txt", "empty.txt"]
+		);
+
+		let year = tree.find("/data/photos/2025").unwrap();
+		let chain = tree
+			.ancestors(year)
+			.into_iter()
+			.map(|idx| tree.entry(idx).path.as_str())
+			.collect::<Vec<_>>();
+		assert_eq!(chain, ["", "/data", "/data/photos", "/data/photos/2025"]);
+	}
+
+	#[test]
+	fn exports_read_back_the_rows_they_wrote() {
+		let tree = sample_tree();
+		for format in [StorageExportFormat::Json, StorageExportFormat::Csv] {
+			let mut out = Vec::new();
+			let written = write_storage_export(&tree, Some(2), format, &mut out).unwrap();
+			let rows = read_storage_export(out.as_slice(), format).unwrap();
+			assert_eq!(rows, tree.rows(Some(2)).collect::<Vec<_>>());
+			assert_eq!(rows.len() as u64, written);
+			assert!(rows.iter().all(|row| row.depth <= 2));
+			assert_eq!(rows[0].path, "");
+			assert_eq!(rows[1].path, "/data");
+			assert_eq!(rows[2].path, "/data/photos");
+		}
+		assert!(read_storage_export("a,b\n1,2\n".as_bytes(), StorageExportFormat::Csv).is_err());
+	}
+
+	fn overlap(left: &TreemapRect, right: &TreemapRect) -> f64 {
+		let width = (left.x + left.width).min(right.x + right.width) - left.x.max(right.x);
+		let height = (left.y + left.height).min(right.y + right.height) - left.y.max(right.y);
+		width.max(0.0) * height.max(0.0)
+	}
+
+	#[test]
+	fn treemap_areas_follow_sizes_without_overlapping() {
+		let files = (1..=300)
+			.map(|i| file(&format!("/data/dir{}/file{i}", i % 7), i * 13 % 997 + 1))
+			.collect::<Vec<_>>();
+		let tree = StorageTree::build(&files);
+		let rects = lay_out_treemap(&tree, STORAGE_ROOT, 1000.0, 600.0, 3, TREEMAP_MAX_RECTS);
+		assert!(!rects.is_empty());
+
+		let total = tree.entry(STORAGE_ROOT).size as f64;
+		for rect in &rects {
+			let expected = rect.size as f64 / total * 1000.0 * 600.0;
+			assert!((rect.width * rect.height - expected).abs() < 1e-6 * expected.max(1.0));
+			assert!(rect.x >= -1e-9 && rect.x + rect.width <= 1000.0 + 1e-6);
+			assert!(rect.y >= -1e-9 && rect.y + rect.height <= 600.0 + 1e-6);
+		}
+		for depth in 1..=3 {
+			let level = rects
+				.iter()
+				.filter(|rect| rect.depth == depth)
+				.collect::<Vec<_>>();
+			for (i, left) in level.iter().enumerate() {
+				for right in &level[i + 1..] {
+					assert!(overlap(left, right) < 1e-6);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn treemap_folds_small_and_surplus_children_into_other() {
+		let mut files = vec![file("/big", 1_000_000)];
+		files.extend((0..50).map(|i| file(&format!("/small{i}"), 1)));
+		let tree = StorageTree::build(&files);
+
+		let rects = lay_out_treemap(&tree, STORAGE_ROOT, 100.0, 100.0, 1, TREEMAP_MAX_RECTS);
+		assert_eq!(rects.len(), 2);
+		assert_eq!(rects[1].entry, None);
+		assert_eq!((rects[1].size, rects[1].items), (50, 50));
+
+		let files = (0..40)
+			.map(|i| file(&format!("/even{i}"), 10))
+			.collect::<Vec<_>>();
+		let tree = StorageTree::build(&files);
+		let rects = lay_out_treemap(&tree, STORAGE_ROOT, 1000.0, 1000.0, 1, 10);
+		assert_eq!(rects.len(), 10);
+		assert_eq!(rects[9].entry, None);
+		assert_eq!(rects[9].size, 310);
+
+		let empty = StorageTree::build(&[file("/empty", 0)]);
+		assert!(lay_out_treemap(&empty, STORAGE_ROOT, 100.0, 100.0, 3, 10).is_empty());
+	}
+}
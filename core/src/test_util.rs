@@ -0,0 +1,24 @@
+//! Helpers shared by the unit tests.
+
+use crate::db::run_migrations;
+use rusqlite::Connection;
+use std::path::PathBuf;
+
+/// A fresh directory under the system temp dir, unique to this process and
+/// call.
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+	let now = std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.unwrap()
+		.as_nanos();
+	let dir = std::env::temp_dir().join(format!("puppynet-{name}-{}-{now}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	dir
+}
+
+/// An in-memory database with every migration applied.
+pub(crate) fn memory_db() -> Connection {
+	let mut conn = Connection::open_in_memory().unwrap();
+	run_migrations(&mut conn).unwrap();
+	conn
+}
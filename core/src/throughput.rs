@@ -0,0 +1,202 @@
+//! Rate and time left for long-running progress: scans, updates, downloads
+//! and uploads. The producer feeds its running total to a [`RateEstimator`],
+//! which measures over the last [`RATE_WINDOW`] rather than the whole run so
+//! a slowdown shows within seconds, and reports a stall once nothing moved
+//! for the configured `progress_stall_secs` instead of the last rate it saw.
+
+use crate::config;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Span the rate is measured over.
+pub const RATE_WINDOW: Duration = Duration::from_secs(10);
+pub(crate) const DEFAULT_PROGRESS_STALL_SECS: u64 = 15;
+/// History needed before a rate is given at all.
+const MIN_SPAN: Duration = Duration::from_secs(1);
+
+/// Rate and time left as producers attach them to their progress events.
+/// A rate of zero means stalled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Throughput {
+	pub bytes_per_sec: Option<u64>,
+	pub eta_secs: Option<u64>,
+}
+
+impl Throughput {
+	/// What a consumer shows after hearing nothing for `quiet`, which is a
+	/// stall once it reaches the stall period even though the producer
+	/// never got to say so.
+	pub fn after_quiet(self, quiet: Duration) -> Self {
+		if self.bytes_per_sec.is_some() && quiet >= stall_period() {
+			Self {
+				bytes_per_sec: Some(0),
+				eta_secs: None,
+			}
+		} else {
+			self
+		}
+	}
+}
+
+fn stall_period() -> Duration {
+	Duration::from_secs(config::startup().progress_stall_secs)
+}
+
+/// Windowed rate of a growing count, in units per second.
+pub(crate) struct RateEstimator {
+	window: Duration,
+	stall_after: Duration,
+	/// `(when, count)`, oldest first. One sample older than the window is
+	/// kept so the rate always spans the whole window.
+	samples: VecDeque<(Instant, u64)>,
+	advanced_at: Option<Instant>,
+}
+
+impl RateEstimator {
+	pub(crate) fn new() -> Self {
+		Self::with_window(RATE_WINDOW, stall_period())
+	}
+
+	pub(crate) fn with_window(window: Duration, stall_after: Duration) -> Self {
+		Self {
+			window,
+			stall_after,
+			samples: VecDeque::new(),
+			advanced_at: None,
+		}
+	}
+
+	/// Records that `count` units were done at `now`.
+	pub(crate) fn record(&mut self, now: Instant, count: u64) {
+		let advanced = self.samples.back().is_none_or(|&(_, last)| count > last);
+		if advanced {
+			self.advanced_at = Some(now);
+		}
+		self.samples.push_back((now, count));
+		while self
+			.samples
+			.get(1)
+			.is_some_and(|&(at, _)| now.saturating_duration_since(at) >= self.window)
+		{
+			self.samples.pop_front();
+		}
+	}
+
+	/// Units per second over the window: `Some(0)` once nothing moved for
+	/// the stall period, `None` until there is enough history to tell.
+	pub(crate) fn per_sec(&self, now: Instant) -> Option<u64> {
+		let advanced_at = self.advanced_at?;
+		if now.saturating_duration_since(advanced_at) >= self.stall_after {
+			return Some(0);
+		}
+		let (&(first_at, first), &(_, last)) = (self.samples.front()?, self.samples.back()?);
+		let span = now.saturating_duration_since(first_at);
+		if span < MIN_SPAN || last == first {
+			return None;
+		}
+		let rate = (last - first) as f64 / span.as_secs_f64();
+		Some((rate.round() as u64).max(1))
+	}
+
+	/// Seconds until `total` units are done at the current rate.
+	pub(crate) fn eta_secs(&self, now: Instant, total: u64) -> Option<u64> {
+		let rate = self.per_sec(now).filter(|&rate| rate > 0)?;
+		let &(_, done) = self.samples.back()?;
+		Some(total.saturating_sub(done).div_ceil(rate))
+	}
+
+	/// Records `done` of `total` bytes and returns what to attach to the
+	/// progress event.
+	pub(crate) fn sample(&mut self, now: Instant, done: u64, total: Option<u64>) -> Throughput {
+		self.record(now, done);
+		Throughput {
+			bytes_per_sec: self.per_sec(now),
+			eta_secs: total.and_then(|total| self.eta_secs(now, total)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn estimator() -> (RateEstimator, Instant) {
+		(
+			RateEstimator::with_window(Duration::from_secs(10), Duration::from_secs(5)),
+			Instant::now(),
+		)
+	}
+
+	fn secs(start: Instant, secs: u64) -> Instant {
+		start + Duration::from_secs(secs)
+	}
+
+	#[test]
+	fn a_steady_rate_gives_rate_and_time_left() {
+		let (mut rate, start) = estimator();
+		assert_eq!(rate.sample(start, 0, Some(1000)), Throughput::default());
+		for second in 1..=20 {
+			rate.record(secs(start, second), second * 10);
+		}
+		let now = secs(start, 20);
+		assert_eq!(rate.per_sec(now), Some(10));
+		assert_eq!(rate.eta_secs(now, 1000), Some(80));
+		assert_eq!(rate.eta_secs(now, 100), Some(0));
+	}
+
+	#[test]
+	fn a_burst_fades_out_of_the_window() {
+		let (mut rate, start) = estimator();
+		rate.record(start, 0);
+		rate.record(secs(start, 1), 1000);
+		for second in 2..=30 {
+			rate.record(secs(start, second), 1000 + (second - 1) * 10);
+		}
+		// The burst sits in the first second; a whole-run average would
+		// still be over 40 per second.
+		assert_eq!(rate.per_sec(secs(start, 30)), Some(10));
+	}
+
+	#[test]
+	fn a_slowdown_shows_within_the_window() {
+		let (mut rate, start) = estimator();
+		let mut done = 0;
+		for second in 1..=30 {
+			done += if second <= 20 { 100 } else { 10 };
+			rate.record(secs(start, second), done);
+		}
+		assert_eq!(rate.per_sec(secs(start, 30)), Some(10));
+	}
+
+	#[test]
+	fn a_stall_reads_as_zero_until_progress_resumes() {
+		let (mut rate, start) = estimator();
+		for second in 0..=10 {
+			rate.record(secs(start, second), second * 100);
+		}
+		assert_eq!(rate.per_sec(secs(start, 10)), Some(100));
+		// Quiet time counts against the rate before it counts as a stall.
+		assert_eq!(rate.per_sec(secs(start, 12)), Some(83));
+		// Repeated reports of the same count are no progress.
+		rate.record(secs(start, 14), 1000);
+		assert_eq!(
+			rate.sample(secs(start, 15), 1000, Some(2000)),
+			Throughput {
+				bytes_per_sec: Some(0),
+				eta_secs: None,
+			}
+		);
+		rate.record(secs(start, 16), 1100);
+		assert_eq!(rate.per_sec(secs(start, 16)), Some(50));
+	}
+
+	#[test]
+	fn no_rate_without_enough_history() {
+		let (mut rate, start) = estimator();
+		assert_eq!(rate.per_sec(start), None);
+		rate.record(start, 0);
+		rate.record(start + Duration::from_millis(500), 400);
+		assert_eq!(rate.per_sec(start + Duration::from_millis(500)), None);
+		assert_eq!(rate.eta_secs(start, 1000), None);
+	}
+}
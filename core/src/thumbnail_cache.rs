@@ -0,0 +1,423 @@
+use crate::config;
+use crate::p2p::Thumbnail;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const THUMBNAIL_CACHE_ENTRIES: usize = 256;
+pub(crate) const PREVIEW_MAX_DIMENSION_SETTING: &str = "preview_max_dimension";
+/// Longest edge of thumbnails served to peers with preview access only.
+pub(crate) const DEFAULT_PREVIEW_MAX_DIMENSION: u32 = 512;
+
+pub(crate) const THUMBNAIL_CONCURRENCY_SETTING: &str = "thumbnail_concurrency";
+/// Thumbnails generated at once, so one browsing client cannot keep every
+/// core of a small machine busy. Read at startup.
+pub(crate) const DEFAULT_THUMBNAIL_CONCURRENCY: usize = 2;
+
+/// Cap stored under [`PREVIEW_MAX_DIMENSION_SETTING`], in pixels.
+pub(crate) fn preview_max_from_setting(value: Option<&str>) -> u32 {
+	value
+		.and_then(|value| value.trim().parse::<u32>().ok())
+		.filter(|px| *px > 0)
+		.unwrap_or_else(|| config::startup().preview_max_dimension)
+}
+
+/// Limit stored under [`THUMBNAIL_CONCURRENCY_SETTING`].
+pub(crate) fn concurrency_from_setting(value: Option<&str>) -> usize {
+	value
+		.and_then(|value| value.trim().parse::<usize>().ok())
+		.filter(|limit| *limit > 0)
+		.unwrap_or_else(|| config::startup().thumbnail_concurrency)
+}
+
+/// What a thumbnail was generated from. A thumbnail is stale once the source
+/// file's stamp no longer matches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct SourceStamp {
+	modified: Option<SystemTime>,
+	len: u64,
+}
+
+impl SourceStamp {
+	pub(crate) fn of(metadata: &Metadata) -> Self {
+		Self {
+			modified: metadata.modified().ok(),
+			len: metadata.len(),
+		}
+	}
+}
+
+const DISK_MAGIC: &[u8; 4] = b"PNT1";
+/// Magic, source length, modified flag, seconds and nanoseconds, width,
+/// height and the length of the mime type.
+const DISK_HEADER_LEN: usize = 4 + 8 + 1 + 8 + 4 + 4 + 4 + 2;
+
+/// Thumbnail file layout: a header with the source stamp and dimensions,
+/// the mime type, then the encoded image.
+fn encode_entry(source: SourceStamp, thumbnail: &Thumbnail) -> Vec<u8> {
+	let modified = source
+		.modified
+		.and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok());
+	let mime = thumbnail.mime_type.as_bytes();
+	let mut out = Vec::with_capacity(DISK_HEADER_LEN + mime.len() + thumbnail.data.len());
+	out.extend_from_slice(DISK_MAGIC);
+	out.extend_from_slice(&source.len.to_le_bytes());
+	out.push(u8::from(modified.is_some()));
+	let modified = modified.unwrap_or_default();
+	out.extend_from_slice(&modified.as_secs().to_le_bytes());
+	out.extend_from_slice(&modified.subsec_nanos().to_le_bytes());
+	out.extend_from_slice(&thumbnail.width.to_le_bytes());
+	out.extend_from_slice(&thumbnail.height.to_le_bytes());
+	out.extend_from_slice(&(mime.len() as u16).to_le_bytes());
+	out.extend_from_slice(mime);
+	out.extend_from_slice(&thumbnail.data);
+	out
+}
+
+/// The thumbnail in `bytes` if it was made from `source`.
+fn decode_entry(bytes: &[u8], source: SourceStamp) -> Option<Thumbnail> {
+	fn take<const N: usize>(bytes: &mut &[u8]) -> Option<[u8; N]> {
+		let (head, rest) = bytes.split_first_chunk::<N>()?;
+		*bytes = rest;
+		Some(*head)
+	}
+	let mut rest = bytes;
+	if &take::<4>(&mut rest)? != DISK_MAGIC {
+		return None;
+	}
+	let len = u64::from_le_bytes(take(&mut rest)?);
+	let has_modified = take::<1>(&mut rest)?[0] == 1;
+	let secs = u64::from_le_bytes(take(&mut rest)?);
+	let nanos = u32::from_le_bytes(take(&mut rest)?);
+	let modified = has_modified.then(|| SystemTime::UNIX_EPOCH + Duration::new(secs, nanos));
+	if (SourceStamp { modified, len }) != source {
+		return None;
+	}
+	let width = u32::from_le_bytes(take(&mut rest)?);
+	let height = u32::from_le_bytes(take(&mut rest)?);
+	let mime_len = u16::from_le_bytes(take(&mut rest)?) as usize;
+	if rest.len() < mime_len {
+		return None;
+	}
+	let (mime, data) = rest.split_at(mime_len);
+	Some(Thumbnail {
+		data: data.to_vec(),
+		width,
+		height,
+		mime_type: String::from_utf8(mime.to_vec()).ok()?,
+	})
+}
+
+/// Thumbnails kept across restarts. The source path is mirrored under the
+/// cache folder with one file per size inside, so dropping a path or a
+/// folder drops the thumbnails under it. Each file records the source it
+/// was made from, so an edited source reads as a miss.
+pub(crate) struct ThumbnailDisk {
+	dir: PathBuf,
+}
+
+impl ThumbnailDisk {
+	pub(crate) fn new(dir: PathBuf) -> Self {
+		Self { dir }
+	}
+
+	pub(crate) fn default_dir() -> PathBuf {
+		config::startup().thumbnail_cache_dir.clone()
+	}
+
+	fn entries_dir(&self, path: &Path) -> PathBuf {
+		let mut dir = self.dir.clone();
+		for component in path.components() {
+			match component {
+				Component::Prefix(prefix) => dir.push(
+					prefix
+						.as_os_str()
+						.to_string_lossy()
+						.replace([':', '\\', '/', '?'], "_"),
+				),
+				Component::Normal(name) => dir.push(name),
+				Component::ParentDir => dir.push("_up"),
+				Component::RootDir | Component::CurDir => {}
+			}
+		}
+		dir
+	}
+
+	pub(crate) fn entry_path(&self, path: &Path, max_width: u32, max_height: u32) -> PathBuf {
+		self.entries_dir(path)
+			.join(format!("{max_width}x{max_height}.thumb"))
+	}
+
+	pub(crate) async fn get(
+		&self,
+		path: &Path,
+		max_width: u32,
+		max_height: u32,
+		source: SourceStamp,
+	) -> Option<Thumbnail> {
+		let bytes = tokio::fs::read(self.entry_path(path, max_width, max_height))
+			.await
+			.ok()?;
+		decode_entry(&bytes, source)
+	}
+
+	/// Writes through a temporary file so readers never see half an entry.
+	pub(crate) async fn insert(
+		&self,
+		path: &Path,
+		max_width: u32,
+		max_height: u32,
+		source: SourceStamp,
+		thumbnail: &Thumbnail,
+	) -> std::io::Result<()> {
+		let entry = self.entry_path(path, max_width, max_height);
+		if let Some(parent) = entry.parent() {
+			tokio::fs::create_dir_all(parent).await?;
+		}
+		let partial = entry.with_extension("partial");
+		tokio::fs::write(&partial, encode_entry(source, thumbnail)).await?;
+		tokio::fs::rename(&partial, &entry).await
+	}
+
+	/// Drops thumbnails of `path` and of anything under it.
+	pub(crate) fn invalidate(&self, path: &Path) -> std::io::Result<()> {
+		match std::fs::remove_dir_all(self.entries_dir(path)) {
+			Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+			_ => Ok(()),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct ThumbnailKey {
+	path: PathBuf,
+	max_width: u32,
+	max_height: u32,
+}
+
+struct CachedThumbnail {
+	source: SourceStamp,
+	thumbnail: Thumbnail,
+	last_used: u64,
+}
+
+/// Generated thumbnails keyed by canonical path and requested size, evicting
+/// the least recently used entry when full.
+#[derive(Default)]
+pub(crate) struct ThumbnailCache {
+	entries: HashMap<ThumbnailKey, CachedThumbnail>,
+	uses: u64,
+}
+
+impl ThumbnailCache {
+	/// Cached thumbnail for `path` if it was generated from the same version
+	/// of the file. Stale entries are dropped.
+	pub(crate) fn get(
+		&mut self,
+		path: &Path,
+		max_width: u32,
+		max_height: u32,
+		source: SourceStamp,
+	) -> Option<Thumbnail> {
+		let key = ThumbnailKey {
+			path: path.to_path_buf(),
+			max_width,
+			max_height,
+		};
+		let entry = self.entries.get_mut(&key)?;
+		if entry.source != source {
+			self.entries.remove(&key);
+			return None;
+		}
+		self.uses += 1;
+		entry.last_used = self.uses;
+		Some(entry.thumbnail.clone())
+	}
+
+	pub(crate) fn insert(
+		&mut self,
+		path: &Path,
+		max_width: u32,
+		max_height: u32,
+		source: SourceStamp,
+		thumbnail: Thumbnail,
+	) {
+		if self.entries.len() >= THUMBNAIL_CACHE_ENTRIES
+			&& let Some(oldest) = self
+				.entries
+				.iter()
+				.min_by_key(|(_, entry)| entry.last_used)
+				.map(|(key, _)| key.clone())
+		{
+			self.entries.remove(&oldest);
+		}
+		self.uses += 1;
+		self.entries.insert(
+			ThumbnailKey {
+				path: path.to_path_buf(),
+				max_width,
+				max_height,
+			},
+			CachedThumbnail {
+				source,
+				thumbnail,
+				last_used: self.uses,
+			},
+		);
+	}
+
+	/// Drops thumbnails of `path` and of anything under it, returning how
+	/// many were removed.
+	pub(crate) fn invalidate(&mut self, path: &Path) -> usize {
+		let before = self.entries.len();
+		self.entries.retain(|key, _| !key.path.starts_with(path));
+		before - self.entries.len()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn thumbnail(width: u32) -> Thumbnail {
+		Thumbnail {
+			data: vec![0; 4],
+			width,
+			height: 1,
+			mime_type: String::from("image/jpeg"),
+		}
+	}
+
+	fn stamp(secs: u64, len: u64) -> SourceStamp {
+		SourceStamp {
+			modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+			len,
+		}
+	}
+
+	#[test]
+	fn stale_thumbnails_are_dropped_when_the_source_changes() {
+		let mut cache = ThumbnailCache::default();
+		let photo = Path::new("/photos/cat.png");
+		cache.insert(photo, 200, 200, stamp(10, 100), thumbnail(200));
+
+		assert_eq!(
+			cache.get(photo, 200, 200, stamp(10, 100)).map(|t| t.width),
+			Some(200)
+		);
+		assert!(cache.get(photo, 100, 100, stamp(10, 100)).is_none());
+		assert!(cache.get(photo, 200, 200, stamp(11, 100)).is_none());
+		// The stale entry is gone even if the old stamp comes back.
+		assert!(cache.get(photo, 200, 200, stamp(10, 100)).is_none());
+	}
+
+	#[test]
+	fn invalidate_covers_the_path_and_its_children() {
+		let mut cache = ThumbnailCache::default();
+		cache.insert(
+			Path::new("/photos/a.png"),
+			10,
+			10,
+			stamp(1, 1),
+			thumbnail(10),
+		);
+		cache.insert(
+			Path::new("/photos/a.png"),
+			20,
+			20,
+			stamp(1, 1),
+			thumbnail(20),
+		);
+		cache.insert(
+			Path::new("/photos/trip/b.png"),
+			10,
+			10,
+			stamp(1, 1),
+			thumbnail(10),
+		);
+		cache.insert(
+			Path::new("/photos-old/c.png"),
+			10,
+			10,
+			stamp(1, 1),
+			thumbnail(10),
+		);
+
+		assert_eq!(cache.invalidate(Path::new("/photos/a.png")), 2);
+		assert_eq!(cache.invalidate(Path::new("/photos")), 1);
+		assert!(
+			cache
+				.get(Path::new("/photos-old/c.png"), 10, 10, stamp(1, 1))
+				.is_some()
+		);
+	}
+
+	#[test]
+	fn disk_entries_only_answer_for_the_source_they_were_made_from() {
+		let made = Thumbnail {
+			data: vec![0xff, 0xd8, 1, 2, 3],
+			width: 96,
+			height: 64,
+			mime_type: String::from("image/jpeg"),
+		};
+		let bytes = encode_entry(stamp(10, 100), &made);
+		let read = decode_entry(&bytes, stamp(10, 100)).unwrap();
+		assert_eq!(
+			(read.data, read.width, read.height, read.mime_type),
+			(made.data, made.width, made.height, made.mime_type)
+		);
+		assert!(decode_entry(&bytes, stamp(11, 100)).is_none());
+		assert!(decode_entry(&bytes, stamp(10, 101)).is_none());
+		assert!(decode_entry(&bytes[..DISK_HEADER_LEN - 1], stamp(10, 100)).is_none());
+	}
+
+	#[test]
+	fn kept_thumbnails_mirror_the_source_tree() {
+		let disk = ThumbnailDisk::new(PathBuf::from("/cache"));
+		assert_eq!(
+			disk.entry_path(Path::new("/photos/trip/a.png"), 96, 96),
+			Path::new("/cache/photos/trip/a.png/96x96.thumb")
+		);
+		assert!(
+			disk.entry_path(Path::new("/photos/trip/a.png"), 96, 96)
+				.starts_with(disk.entries_dir(Path::new("/photos")))
+		);
+		assert!(
+			!disk
+				.entry_path(Path::new("/photos-old/c.png"), 96, 96)
+				.starts_with(disk.entries_dir(Path::new("/photos")))
+		);
+	}
+
+	#[test]
+	fn least_recently_used_entry_is_evicted() {
+		let mut cache = ThumbnailCache::default();
+		for i in 0..THUMBNAIL_CACHE_ENTRIES {
+			let path = PathBuf::from(format!("/photos/{i}.png"));
+			cache.insert(&path, 10, 10, stamp(1, 1), thumbnail(10));
+		}
+		assert!(
+			cache
+				.get(Path::new("/photos/0.png"), 10, 10, stamp(1, 1))
+				.is_some()
+		);
+		cache.insert(
+			Path::new("/photos/new.png"),
+			10,
+			10,
+			stamp(1, 1),
+			thumbnail(10),
+		);
+		assert!(
+			cache
+				.get(Path::new("/photos/0.png"), 10, 10, stamp(1, 1))
+				.is_some()
+		);
+		assert!(
+			cache
+				.get(Path::new("/photos/1.png"), 10, 10, stamp(1, 1))
+				.is_none()
+		);
+	}
+}
@@ -0,0 +1,405 @@
+//! Thumbnails made ahead of browsing for the images a scan found. The queue
+//! is worked off only while the node is otherwise idle: no scan running,
+//! no thumbnail or file request for a while, the activity window open
+//! for scans and no power hold. Foreground requests never wait behind it; the worker takes a
+//! generation permit only when one is free and checks the gate again
+//! before every file.
+
+use crate::activity_window::{ActivityKind, ActivityWindow};
+use crate::power::PowerGate;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// `"false"` turns pregeneration off; anything else leaves it on.
+pub const THUMBNAIL_PREGEN_SETTING: &str = "thumbnail_pregeneration";
+/// Edge of pregenerated thumbnails, the size the file browsers ask for.
+pub const PREGEN_THUMBNAIL_SIZE: u32 = 96;
+/// Smaller images are cheap enough to make when they are first shown.
+pub const PREGEN_MIN_FILE_SIZE: u64 = 64 * 1024;
+/// Paths waiting at once; later scans' images are dropped past it.
+pub(crate) const MAX_PREGEN_QUEUE: usize = 100_000;
+/// How long after a foreground request the worker keeps waiting.
+pub(crate) const FOREGROUND_QUIET: Duration = Duration::from_secs(10);
+/// Window the recent request rate is counted over.
+pub(crate) const REQUEST_RATE_WINDOW: Duration = Duration::from_secs(60);
+/// Foreground requests within [`REQUEST_RATE_WINDOW`] that count as busy.
+pub(crate) const BUSY_REQUESTS: usize = 20;
+/// How often a paused or empty worker looks again.
+pub(crate) const PREGEN_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Pregeneration stored under [`THUMBNAIL_PREGEN_SETTING`]; on unless
+/// turned off, and always off in builds without the `thumbnails` feature.
+pub(crate) fn enabled_from_setting(value: Option<&str>) -> bool {
+	cfg!(feature = "thumbnails") && value.is_none_or(|value| value.trim() != "false")
+}
+
+/// Why the worker is not making thumbnails right now.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PregenPause {
+	Disabled,
+	Scanning,
+	Busy,
+	OutsideActivityWindow,
+	OnBattery,
+}
+
+impl PregenPause {
+	pub fn label(&self) -> &'static str {
+		match self {
+			Self::Disabled => "turned off",
+			Self::Scanning => "waiting for a scan to finish",
+			Self::Busy => "waiting for requests to quiet down",
+			Self::OutsideActivityWindow => "waiting for the activity window",
+			Self::OnBattery => "paused on battery",
+		}
+	}
+}
+
+/// Depth and progress of the pregeneration queue.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ThumbnailQueueStatus {
+	pub enabled: bool,
+	pub queued: usize,
+	pub generated: u64,
+	/// Already cached for an unchanged file.
+	pub skipped: u64,
+	pub failed: u64,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub paused: Option<PregenPause>,
+}
+
+impl ThumbnailQueueStatus {
+	/// One line for the jobs panel, e.g. "Thumbnails: 12 queued, 40 made,
+	/// 3 already cached (waiting for a scan to finish)".
+	pub fn summary(&self) -> String {
+		if !self.enabled {
+			return String::from("Thumbnails: pregeneration turned off");
+		}
+		let mut line = format!(
+			"Thumbnails: {} queued, {} made, {} already cached",
+			self.queued, self.generated, self.skipped
+		);
+		if self.failed > 0 {
+			line.push_str(&format!(", {} failed", self.failed));
+		}
+		if let Some(paused) = self.paused.filter(|_| self.queued > 0) {
+			line.push_str(&format!(" ({})", paused.label()));
+		}
+		line
+	}
+}
+
+/// What became of one queued image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PregenOutcome {
+	Generated,
+	Skipped,
+	Failed,
+	/// A foreground request holds the permits; the image goes back to the
+	/// front of the queue.
+	Yielded,
+}
+
+#[derive(Default)]
+struct QueueState {
+	enabled: bool,
+	pending: VecDeque<PathBuf>,
+	queued: HashSet<PathBuf>,
+	generated: u64,
+	skipped: u64,
+	failed: u64,
+	scans: usize,
+	foreground: VecDeque<Instant>,
+	paused: Option<PregenPause>,
+}
+
+impl QueueState {
+	fn forget_requests_before(&mut self, now: Instant) {
+		while self
+			.foreground
+			.front()
+			.is_some_and(|at| now.duration_since(*at) >= REQUEST_RATE_WINDOW)
+		{
+			self.foreground.pop_front();
+		}
+	}
+
+	fn busy(&self, now: Instant) -> bool {
+		self.foreground
+			.back()
+			.is_some_and(|last| now.duration_since(*last) < FOREGROUND_QUIET)
+			|| self.foreground.len() >= BUSY_REQUESTS
+	}
+}
+
+/// Images waiting for a thumbnail, shared by the event loop, which fills it
+/// and reports activity, and the worker that drains it.
+pub(crate) struct ThumbnailQueue {
+	state: Mutex<QueueState>,
+	window: Arc<Mutex<ActivityWindow>>,
+	power: Arc<Mutex<PowerGate>>,
+}
+
+impl ThumbnailQueue {
+	pub(crate) fn new(
+		enabled: bool,
+		window: Arc<Mutex<ActivityWindow>>,
+		power: Arc<Mutex<PowerGate>>,
+	) -> Self {
+		Self {
+			state: Mutex::new(QueueState {
+				enabled,
+				..Default::default()
+			}),
+			window,
+			power,
+		}
+	}
+
+	/// Turning pregeneration off drops what was queued.
+	pub(crate) fn set_enabled(&self, enabled: bool) {
+		let mut state = self.state.lock().unwrap();
+		state.enabled = enabled;
+		if !enabled {
+			state.pending.clear();
+			state.queued.clear();
+		}
+	}
+
+	pub(crate) fn is_enabled(&self) -> bool {
+		self.state.lock().unwrap().enabled
+	}
+
+	/// Queues images a scan found, skipping ones already waiting.
+	pub(crate) fn enqueue(&self, paths: impl IntoIterator<Item = PathBuf>) -> usize {
+		let mut state = self.state.lock().unwrap();
+		if !state.enabled {
+			return 0;
+		}
+		let mut added = 0;
+		for path in paths {
+			if state.pending.len() >= MAX_PREGEN_QUEUE {
+				break;
+			}
+			if state.queued.insert(path.clone()) {
+				state.pending.push_back(path);
+				added += 1;
+			}
+		}
+		added
+	}
+
+	/// Holds the worker back until the returned guard is dropped.
+	pub(crate) fn scan_started(self: &Arc<Self>) -> ScanRunning {
+		self.state.lock().unwrap().scans += 1;
+		ScanRunning(Arc::clone(self))
+	}
+
+	/// A thumbnail or file request arrived; the worker steps aside.
+	pub(crate) fn note_foreground(&self, now: Instant) {
+		let mut state = self.state.lock().unwrap();
+		state.forget_requests_before(now);
+		state.foreground.push_back(now);
+	}
+
+	/// No scan running and no recent run of foreground requests. Other
+	/// background readers, like integrity sampling, wait for this too.
+	pub(crate) fn is_quiet(&self, now: Instant) -> bool {
+		let mut state = self.state.lock().unwrap();
+		state.forget_requests_before(now);
+		state.scans == 0 && !state.busy(now)
+	}
+
+	fn pause_reason(
+		&self,
+		state: &mut QueueState,
+		now: Instant,
+		wall: DateTime<Utc>,
+	) -> Option<PregenPause> {
+		state.forget_requests_before(now);
+		if !state.enabled {
+			Some(PregenPause::Disabled)
+		} else if state.scans > 0 {
+			Some(PregenPause::Scanning)
+		} else if state.busy(now) {
+			Some(PregenPause::Busy)
+		} else if !self.window.lock().unwrap().allows(ActivityKind::Scan, wall) {
+			Some(PregenPause::OutsideActivityWindow)
+		} else if self.power.lock().unwrap().holds() {
+			Some(PregenPause::OnBattery)
+		} else {
+			None
+		}
+	}
+
+	/// The next image to make a thumbnail for, or `None` while the queue
+	/// is empty or the node is not idle.
+	pub(crate) fn next(&self, now: Instant, wall: DateTime<Utc>) -> Option<PathBuf> {
+		let mut state = self.state.lock().unwrap();
+		let paused = self.pause_reason(&mut state, now, wall);
+		state.paused = paused;
+		if paused.is_some() {
+			return None;
+		}
+		let path = state.pending.pop_front()?;
+		state.queued.remove(&path);
+		Some(path)
+	}
+
+	pub(crate) fn finish(&self, path: PathBuf, outcome: PregenOutcome) {
+		let mut state = self.state.lock().unwrap();
+		match outcome {
+			PregenOutcome::Generated => state.generated += 1,
+			PregenOutcome::Skipped => state.skipped += 1,
+			PregenOutcome::Failed => state.failed += 1,
+			PregenOutcome::Yielded => {
+				if state.enabled && state.queued.insert(path.clone()) {
+					state.pending.push_front(path);
+				}
+				state.paused = Some(PregenPause::Busy);
+			}
+		}
+	}
+
+	pub(crate) fn status(&self) -> ThumbnailQueueStatus {
+		let state = self.state.lock().unwrap();
+		ThumbnailQueueStatus {
+			enabled: state.enabled,
+			queued: state.pending.len(),
+			generated: state.generated,
+			skipped: state.skipped,
+			failed: state.failed,
+			paused: if state.enabled {
+				state.paused
+			} else {
+				Some(PregenPause::Disabled)
+			},
+		}
+	}
+}
+
+/// A scan in progress; see [`ThumbnailQueue::scan_started`].
+pub(crate) struct ScanRunning(Arc<ThumbnailQueue>);
+
+impl Drop for ScanRunning {
+	fn drop(&mut self) {
+		let mut state = self.0.state.lock().unwrap();
+		state.scans = state.scans.saturating_sub(1);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::activity_window::TimeRange;
+
+	fn queue(window: ActivityWindow) -> Arc<ThumbnailQueue> {
+		Arc::new(ThumbnailQueue::new(
+			true,
+			Arc::new(Mutex::new(window)),
+			Arc::new(Mutex::new(PowerGate::default())),
+		))
+	}
+
+	fn photos(count: usize) -> Vec<PathBuf> {
+		(0..count)
+			.map(|i| PathBuf::from(format!("/photos/{i}.jpg")))
+			.collect()
+	}
+
+	#[test]
+	fn the_worker_waits_for_scans_and_foreground_requests() {
+		let queue = queue(ActivityWindow::default());
+		let start = Instant::now();
+		let wall = Utc::now();
+		assert_eq!(queue.enqueue(photos(2)), 2);
+		assert_eq!(queue.enqueue(photos(2)), 0);
+
+		let scan = queue.scan_started();
+		assert_eq!(queue.next(start, wall), None);
+		assert_eq!(queue.status().paused, Some(PregenPause::Scanning));
+		drop(scan);
+
+		queue.note_foreground(start);
+		assert_eq!(queue.next(start + Duration::from_secs(1), wall), None);
+		assert_eq!(queue.status().paused, Some(PregenPause::Busy));
+
+		let quiet = start + FOREGROUND_QUIET;
+		assert_eq!(
+			queue.next(quiet, wall),
+			Some(PathBuf::from("/photos/0.jpg"))
+		);
+		queue.finish(PathBuf::from("/photos/0.jpg"), PregenOutcome::Generated);
+		assert_eq!(
+			queue.status(),
+			ThumbnailQueueStatus {
+				enabled: true,
+				queued: 1,
+				generated: 1,
+				skipped: 0,
+				failed: 0,
+				paused: None,
+			}
+		);
+	}
+
+	#[test]
+	fn a_steady_trickle_of_requests_counts_as_busy() {
+		let queue = queue(ActivityWindow::default());
+		let start = Instant::now();
+		queue.enqueue(photos(1));
+		for i in 0..BUSY_REQUESTS {
+			queue.note_foreground(start + FOREGROUND_QUIET * i as u32 / BUSY_REQUESTS as u32);
+		}
+		// Quiet since the last request, but too many in the last minute.
+		let after = start + FOREGROUND_QUIET * 2;
+		assert_eq!(queue.next(after, Utc::now()), None);
+		assert!(
+			queue
+				.next(start + REQUEST_RATE_WINDOW + FOREGROUND_QUIET, Utc::now())
+				.is_some()
+		);
+	}
+
+	#[test]
+	fn a_closed_activity_window_holds_the_queue() {
+		let wall = DateTime::parse_from_rfc3339("2026-01-01T12:00:00Z")
+			.unwrap()
+			.with_timezone(&Utc);
+		let queue = queue(ActivityWindow {
+			categories: vec![ActivityKind::Scan],
+			allowed: vec![TimeRange::parse("01:00-05:00").unwrap()],
+			..Default::default()
+		});
+		queue.enqueue(photos(1));
+		assert_eq!(queue.next(Instant::now(), wall), None);
+		assert_eq!(
+			queue.status().paused,
+			Some(PregenPause::OutsideActivityWindow)
+		);
+		let night = wall + chrono::Duration::hours(15);
+		assert!(queue.next(Instant::now(), night).is_some());
+	}
+
+	#[test]
+	fn yielded_images_go_first_and_turning_off_clears_the_queue() {
+		let queue = queue(ActivityWindow::default());
+		let now = Instant::now();
+		queue.enqueue(photos(3));
+		let first = queue.next(now, Utc::now()).unwrap();
+		queue.finish(first.clone(), PregenOutcome::Yielded);
+		assert_eq!(queue.next(now, Utc::now()), Some(first));
+
+		queue.set_enabled(false);
+		assert_eq!(queue.status().queued, 0);
+		assert_eq!(queue.status().paused, Some(PregenPause::Disabled));
+		assert_eq!(queue.enqueue(photos(3)), 0);
+		assert!(!enabled_from_setting(Some("false")));
+		assert!(enabled_from_setting(None));
+	}
+}
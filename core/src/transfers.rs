@@ -0,0 +1,268 @@
+//! Files copied between this node and its peers. Every download and
+//! upload leaves a row in the `transfers` table, capped at
+//! [`TRANSFER_RETENTION`] rows, and downloads land in a per-peer folder
+//! that falls back to a global one and then to the user's Downloads.
+
+use crate::locations::{FolderKind, LocationEnv, well_known_folders};
+use crate::throughput::RateEstimator;
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Global default download folder.
+pub(crate) const DOWNLOAD_DIR_SETTING: &str = "download_dir";
+/// Oldest transfers beyond this many are pruned on every write.
+pub(crate) const TRANSFER_RETENTION: u64 = 5000;
+
+/// Setting holding the download folder for files from `peer`.
+pub(crate) fn peer_download_dir_setting(peer: &PeerId) -> String {
+	format!("{DOWNLOAD_DIR_SETTING}:{peer}")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDirection {
+	Download,
+	Upload,
+}
+
+impl TransferDirection {
+	pub(crate) fn as_str(&self) -> &'static str {
+		match self {
+			TransferDirection::Download => "download",
+			TransferDirection::Upload => "upload",
+		}
+	}
+
+	pub(crate) fn parse(value: &str) -> Option<Self> {
+		match value {
+			"download" => Some(TransferDirection::Download),
+			"upload" => Some(TransferDirection::Upload),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferStatus {
+	Completed,
+	Failed,
+}
+
+impl TransferStatus {
+	pub(crate) fn as_str(&self) -> &'static str {
+		match self {
+			TransferStatus::Completed => "completed",
+			TransferStatus::Failed => "failed",
+		}
+	}
+
+	pub(crate) fn parse(value: &str) -> Option<Self> {
+		match value {
+			"completed" => Some(TransferStatus::Completed),
+			"failed" => Some(TransferStatus::Failed),
+			_ => None,
+		}
+	}
+}
+
+/// One download or upload, successful or not.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Transfer {
+	pub direction: TransferDirection,
+	pub peer: String,
+	pub remote_path: String,
+	pub local_path: String,
+	/// Bytes copied, which is the whole file for completed transfers.
+	pub size: u64,
+	pub duration_ms: u64,
+	pub status: TransferStatus,
+	pub error: Option<String>,
+	/// Blake3 hash of the content, as the index stores it. Only known for
+	/// completed downloads.
+	#[serde(skip)]
+	pub hash: Option<Vec<u8>>,
+	pub finished_at: DateTime<Utc>,
+	/// What the receiver already had, for uploads that sent less than
+	/// the whole file.
+	pub note: Option<String>,
+}
+
+impl Transfer {
+	pub(crate) fn new(
+		direction: TransferDirection,
+		peer: PeerId,
+		remote_path: impl Into<String>,
+		local_path: impl Into<String>,
+		elapsed: Duration,
+	) -> Self {
+		Self {
+			direction,
+			peer: peer.to_string(),
+			remote_path: remote_path.into(),
+			local_path: local_path.into(),
+			size: 0,
+			duration_ms: elapsed.as_millis() as u64,
+			status: TransferStatus::Completed,
+			error: None,
+			hash: None,
+			finished_at: Utc::now(),
+			note: None,
+		}
+	}
+
+	pub(crate) fn failed(mut self, err: &anyhow::Error) -> Self {
+		self.status = TransferStatus::Failed;
+		self.error = Some(err.to_string());
+		self
+	}
+}
+
+/// What [`crate::PuppyNet::download_file`] did.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum DownloadOutcome {
+	Downloaded(Transfer),
+	/// The same content was downloaded before and is still at
+	/// `local_path`; nothing was copied.
+	AlreadyDownloaded(Transfer),
+}
+
+/// How far a download or upload got, reported after every chunk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TransferProgress {
+	pub done: u64,
+	/// `None` when the size was not known up front.
+	pub total: Option<u64>,
+	/// Over the last few seconds; zero when the transfer stalled.
+	pub bytes_per_sec: Option<u64>,
+	pub eta_secs: Option<u64>,
+}
+
+impl TransferProgress {
+	/// Reports `done` bytes of `total`, measured by `rate`.
+	pub(crate) fn measure(rate: &mut RateEstimator, done: u64, total: Option<u64>) -> Self {
+		let throughput = rate.sample(Instant::now(), done, total);
+		Self {
+			done,
+			total,
+			bytes_per_sec: throughput.bytes_per_sec,
+			eta_secs: throughput.eta_secs,
+		}
+	}
+}
+
+/// The folder downloads from a peer go to: its own folder if set, else
+/// the global one, else the user's Downloads, home or the current
+/// directory, whichever exists first.
+pub(crate) fn resolve_download_dir(
+	peer_dir: Option<String>,
+	global_dir: Option<String>,
+	env: &LocationEnv,
+) -> PathBuf {
+	if let Some(dir) = peer_dir.or(global_dir) {
+		return PathBuf::from(dir);
+	}
+	let folders = well_known_folders(env, &[]);
+	[FolderKind::Downloads, FolderKind::Home]
+		.iter()
+		.find_map(|kind| folders.iter().find(|folder| folder.kind == *kind))
+		.map(|folder| PathBuf::from(&folder.path))
+		.unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// `dir/name`, or `dir/name (n).ext` with the first free `n` when a file
+/// of that name is already there.
+pub(crate) fn unique_destination(dir: &Path, name: &str) -> PathBuf {
+	let candidate = dir.join(name);
+	if !candidate.exists() {
+		return candidate;
+	}
+	let (stem, ext) = match name.rsplit_once('.') {
+		Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{ext}")),
+		_ => (name, String::new()),
+	};
+	(1..)
+		.map(|n| dir.join(format!("{stem} ({n}){ext}")))
+		.find(|path| !path.exists())
+		.unwrap()
+}
+
+/// The last component of a peer path in either separator style.
+pub(crate) fn remote_file_name(remote_path: &str) -> Option<&str> {
+	remote_path
+		.rsplit(['/', '\\'])
+		.next()
+		.filter(|name| !name.is_empty() && *name != "." && *name != "..")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::locations::Platform;
+	use std::collections::HashMap;
+
+	fn linux_env(home: Option<&str>) -> LocationEnv {
+		LocationEnv {
+			platform: Platform::Linux,
+			home: home.map(String::from),
+			vars: HashMap::new(),
+			xdg_user_dirs: None,
+		}
+	}
+
+	#[test]
+	fn peer_folder_wins_over_global_and_platform_default() {
+		let env = linux_env(Some("/home/ana"));
+		assert_eq!(
+			resolve_download_dir(
+				Some(String::from("/srv/from-laptop")),
+				Some(String::from("/srv/all")),
+				&env
+			),
+			PathBuf::from("/srv/from-laptop")
+		);
+		assert_eq!(
+			resolve_download_dir(None, Some(String::from("/srv/all")), &env),
+			PathBuf::from("/srv/all")
+		);
+		assert_eq!(
+			resolve_download_dir(None, None, &env),
+			PathBuf::from("/home/ana/Downloads")
+		);
+		assert_eq!(
+			resolve_download_dir(None, None, &linux_env(None)),
+			PathBuf::from(".")
+		);
+	}
+
+	#[test]
+	fn existing_names_get_a_counter_before_the_extension() {
+		let dir = std::env::temp_dir().join(format!("puppynet-transfers-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		std::fs::write(dir.join("report.pdf"), b"x").unwrap();
+		std::fs::write(dir.join("report (1).pdf"), b"x").unwrap();
+		std::fs::write(dir.join("Makefile"), b"x").unwrap();
+		assert_eq!(unique_destination(&dir, "notes.txt"), dir.join("notes.txt"));
+		assert_eq!(
+			unique_destination(&dir, "report.pdf"),
+			dir.join("report (2).pdf")
+		);
+		assert_eq!(
+			unique_destination(&dir, "Makefile"),
+			dir.join("Makefile (1)")
+		);
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn remote_names_come_from_either_separator() {
+		assert_eq!(remote_file_name("/home/ana/a.txt"), Some("a.txt"));
+		assert_eq!(remote_file_name(r"C:\Users\ana\b.txt"), Some("b.txt"));
+		assert_eq!(remote_file_name("/home/ana/"), None);
+		assert_eq!(remote_file_name(".."), None);
+	}
+}
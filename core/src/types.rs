@@ -5,4 +5,25 @@ pub struct FileChunk {
 	pub offset: u64,
 	pub data: Vec<u8>,
 	pub eof: bool,
+	/// Bytes from `offset` the sender holds as a hole and did not send;
+	/// they read as zeros. `data` is empty when set. Only answers to a
+	/// sparse `ReadFile` have holes.
+	#[serde(default)]
+	pub hole: u64,
+}
+
+impl FileChunk {
+	/// Bytes of the file the chunk covers, its hole included.
+	pub fn span(&self) -> u64 {
+		self.data.len() as u64 + self.hole
+	}
+}
+
+/// What a remote shell answered to a round of input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShellOutput {
+	/// Bytes the shell wrote since the last round, possibly none.
+	Output(Vec<u8>),
+	/// The shell exited or its session was closed.
+	Exited,
 }
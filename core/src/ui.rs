@@ -1,25 +1,76 @@
+use crate::activity_log::{ActivityEvent, ActivityEventKind, DEFAULT_ACTIVITY_PAGE};
+use crate::activity_window::{
+	ActivityKind, ActivityWindow, TimeRange, format_utc_offset, parse_utc_offset,
+};
+use crate::app::peer_to_node_id;
 use crate::auth;
-use crate::db::FileEntry;
+use crate::contact_sheet::{ContactSheetOptions, ContactSheetResult};
+use crate::db::{FileEntry, FileSearchResult, ScanRun, ScanRunStatus, ScanTrend, SearchFilesArgs};
+use crate::disk_history::{DiskSample, days_until_full};
+use crate::format::{
+	SizeUnits, TimestampStyle, abbrev_hash, abbrev_peer_id, hex, human_duration, human_rate,
+	human_size, human_timestamp, relative_time,
+};
+use crate::free_space::describe_free_hint;
+use crate::image_decode::{ImageTooLarge, decoded_size, header_dimensions};
+use crate::ingest::{IngestProgress, IngestSettings, describe_drive, drive_label};
+use crate::integrity::{IntegrityFinding, IntegrityReplica, IntegritySettings};
+use crate::jobs::{Job, JobManager, JobProgress, JobReporter, JobStatus};
+use crate::locations::{FolderKind, WellKnownFolder};
+use crate::maintenance::Maintenance;
+use crate::media_metadata::{is_media_mime, media_duration};
 use crate::media_webrtc::{CreateMediaSession, MediaSessionManager};
 use crate::p2p::{
-	AudioCapability, AudioDevice, AudioDeviceKind, CpuInfo, DesktopInput, DirEntry, InterfaceInfo,
-	LiveSearchArgs, MediaCapability, MediaSource, MediaSourceKind, MouseButton, PeerInfo,
-	SearchEvent, SearchSort,
+	AudioCapability, AudioDevice, AudioDeviceKind, BrowseRootKind, BrowseRoots, CpuInfo,
+	DesktopInput, DirEntry, DirListing, DiskInfo, FEATURE_INBOX, FEATURE_RESTART, FEATURE_SHELL,
+	FEATURE_UPDATE, InterfaceInfo, ListingAccess, LiveSearchArgs, MediaCapability, MediaSource,
+	MediaSourceKind, MimeSource, MouseButton, PROTOCOL_VERSION, PeerCapabilities, PeerInfo,
+	SearchEvent, SearchSort, WirePath, path_bytes,
+};
+use crate::path::{PathError, PathStyle, SafePath};
+use crate::pins::safe_entry_name;
+use crate::preview::{PREVIEW_PAGE_SIZE, PreviewKind, build_preview, number_lines};
+use crate::scan::{FileHash, ScanEvent, ScanResult};
+use crate::scan_limits::{ScanLimits, ScanOverrides};
+use crate::share_summary::{ShareCard, share_cards};
+use crate::state::{effective_folder_rule, folder_rule_overlaps};
+use crate::ui_focus::{FocusAction, FocusRow, Key, ListFocus};
+use crate::ui_notifications::{AppNotification, NotificationCenter, Severity};
+use crate::ui_prefs::{
+	DirSort, DirView, FONT_SCALES, PAGE_SIZES, REFRESH_INTERVALS, UiPrefs, UiTheme, prefs_path,
+};
+use crate::updater::{UpdateProgress, UpdateRetryPolicy};
+use crate::version::{version_number, version_number_from_label};
+use crate::{
+	AccessAlertLimits, AccessExplanation, AddressReachability, BackupKind, BackupRun,
+	BackupSettings, BatchGrantOutcome, ConfigRollback, ConfigSnapshotInfo, Connection,
+	ConnectionDirection, ConnectionPolicy, CreateUserError, DeleteDecision, DeleteOutcome,
+	DeleteProposal, Diagnostic, DiagnosticStatus, DiagnosticsReport, DiffLineKind, DiffOptions,
+	DirectoryGrowth, DiscoveredPeerFilter, DiskProjection, DownloadOutcome, DraftKind,
+	DraftRejected, FLAG_PREVIEW, FLAG_QUARANTINE, FLAG_READ, FLAG_SEARCH, FLAG_WRITE,
+	FailedLoginGroup, FanOutOptions, FanOutSummary, FileDiff, FileRef, FolderRule, GrowthReport,
+	HttpProxySettings, IdKind, IdentityMismatch, LoginResult, LoginSource, NatStatus,
+	NodeStorageTree, OutboxRule, OutboxStatus, PROJECTION_DAYS, Pairing, PairingInfo,
+	PairingStatus, PendingReview, Permission, PermissionDraft, PinOptions, PinStatus, PowerPolicy,
+	ProtocolLimits, ProtocolRate, ProxyCredentials, PuppyNet, Reachability, ReceivedProposal,
+	RemoteGrants, ReplicationRole, ReviewDecision, Rule, RuleDraft, SUGGESTED_DENY_PATTERNS,
+	ScanResultsPull, SendSavings, ShareSummary, StorageUsageFile, TemporaryGrant, Throughput,
+	Transfer, TransferDirection, TransferProgress, TransferStatus, TrustLevel, WAKE_TIMEOUT,
+	fan_out, parse_patterns, port_mapping_worthwhile, trees_by_node,
 };
-use crate::updater::UpdateProgress;
-use crate::{FLAG_WRITE, LiveSearchPeerEvent, PuppyNet, StorageUsageFile};
 use anyhow::{Context, Result};
 use base64::Engine;
 use libp2p::PeerId;
 use rand::RngCore;
 use rand::rngs::OsRng;
-use std::collections::{BTreeSet, HashMap};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::future::Future;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
-use std::sync::mpsc;
-use std::sync::mpsc::TryRecvError;
+use std::sync::atomic::AtomicBool;
 use tokio::{signal, sync::Mutex, task};
 use wgui::wui::runtime::Ctx;
 use wgui::{HttpRequest, HttpResponse, Wgui, WguiModel};
@@ -36,18 +87,69 @@ const COMMON_SEARCH_MIME_TYPES: &[&str] = &[
 const FAVICON_ICO: &[u8] = include_bytes!("../http_assets/favicon.ico");
 const MEDIA_RECEIVER_JS: &[u8] = include_bytes!("../http_assets/media_receiver.js");
 const TRACKPAD_JS: &[u8] = include_bytes!("../http_assets/trackpad.js");
+const KEYBOARD_JS: &[u8] = include_bytes!("../http_assets/keyboard.js");
+const COPY_BUTTON_JS: &[u8] = include_bytes!("../http_assets/copy_button.js");
+const TREEMAP_JS: &[u8] = include_bytes!("../http_assets/treemap.js");
 const SEARCH_ALL_DEVICES: &str = "__all__";
+const WEB_UI_LOGIN_SOURCE: &str = "web ui";
+/// Discovered peers offered by the setup wizard, most recently seen first.
+const NEARBY_PEER_LIMIT: usize = 50;
+/// Minimum delay between re-renders caused by one job's progress.
+const JOB_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+/// How often a running shell of the terminal page is asked for output.
+const SHELL_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+/// How long the device list waits for each peer's version and uptime.
+const PEER_INFO_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(1500);
+/// Outdated peers updated at once by "Update outdated peers".
+const FLEET_UPDATE_CONCURRENCY: usize = 4;
+/// How long each of those updates may take, download and install included.
+const FLEET_UPDATE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30 * 60);
+/// How far back the peer detail view draws disk trends.
+const DISK_TREND_DAYS: i64 = 30;
+const DISK_SPARKLINE_WIDTH: usize = 32;
+/// Longest edge of the thumbnails shown next to files while browsing.
+const PEER_FILE_THUMBNAIL_SIZE: u32 = 96;
+/// Durations offered by the temporary access buttons, in seconds.
+const TEMPORARY_GRANT_PRESETS: [(&str, u64); 3] = [
+	("15 minutes", 15 * 60),
+	("1 hour", 60 * 60),
+	("24 hours", 24 * 60 * 60),
+];
+/// Results a search scoped to a device folder shows.
+const SCOPED_SEARCH_LIMIT: usize = 200;
+/// Index age past which a scoped search warns that results may be missing.
+const STALE_INDEX_HOURS: i64 = 24;
+/// Pulls of scan results listed on a device page, latest first.
+const SCAN_RESULT_PULLS_SHOWN: usize = 5;
+/// Titles of the setup wizard steps, in order.
+const ONBOARDING_STEPS: [&str; 3] = [
+	"Name this device",
+	"Share folders",
+	"Pair with nearby devices",
+];
 
 #[path = "pages/mod.rs"]
 mod pages;
+#[path = "ui_resume.rs"]
+mod resume;
 
 use pages::{
-	FilesController, HomeController, LoginController, NotFoundController, PeerControlController,
-	PeerController, PeerFilesController, PeerWebcamsController, PeersController, SearchController,
-	SettingsController, StorageController, UpdatesController, UsersController,
+	DraftFlag, FilesController, GrowthColumn, GrowthMsg, GrowthSession, HomeController,
+	JobsController, LoginController, NotFoundController, NotificationsController,
+	PeerControlController, PeerController, PeerFilesController, PeerFilesMsg, PeerFilesSession,
+	PeerPermissionsController, PeerShellController, PeerShellMsg, PeerShellSession,
+	PeerWebcamsController, PeersController, PermissionEditorMsg, PermissionEditorSession,
+	ProtocolColumn, ProtocolMsg, ProtocolSession, ReviewController, ReviewMsg, ReviewSession,
+	ScopedSearch, SearchController, SearchMsg, SearchSession, SearchStream, SettingsController,
+	ShellPoll, StorageController, TREEMAP_DEPTHS, TREEMAP_HEIGHT, TREEMAP_WIDTH, ThumbnailFetch,
+	ThumbnailMsg, TimelineController, TimelineMsg, TimelineSession, TreemapMsg, TreemapSession,
+	TreemapTile, UpdatesController, UsersController, WelcomeController, pattern_text,
+	shown_entries,
 };
+use resume::{PendingResume, RESUME_SAVE_INTERVAL, ResumedSearch, UiResume, resume_path};
 
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 enum Page {
 	Home,
 	Peers,
@@ -55,12 +157,19 @@ enum Page {
 	PeerControl { peer_id: String },
 	PeerFiles { peer_id: String, path: String },
 	PeerWebcams { peer_id: String },
+	PeerPermissions { peer_id: String },
+	PeerShell { peer_id: String },
 	Files,
 	Search,
 	Storage,
 	Users,
 	Updates,
+	Jobs,
+	Review,
+	Notifications,
+	Timeline,
 	Settings,
+	Welcome,
 }
 
 #[derive(Clone)]
@@ -71,6 +180,18 @@ struct PeerRow {
 	version: String,
 	os: String,
 	uptime: String,
+	connections: Vec<Connection>,
+	/// Connections that closed within the last hour.
+	recent_drops: usize,
+	/// "peer clock is ~4m ahead" when the peer's clock is off.
+	clock_skew: Option<String>,
+	/// Set while the peer announced index changes this node hasn't pulled.
+	index_stale: Option<String>,
+	/// Unconnected only because its last connection went idle.
+	idle: bool,
+	/// Redialed whenever its connection drops.
+	important: bool,
+	trust: TrustLevel,
 }
 
 #[derive(Clone)]
@@ -78,10 +199,20 @@ struct UiState {
 	page: Page,
 	local_peer_id: Option<String>,
 	peers: Vec<PeerRow>,
+	/// How the last round of asking peers for their info went.
+	peers_refresh: FanOutSummary,
+	/// Peers that answered with a release older than this node's.
+	outdated_peers: Vec<PeerId>,
+	/// Today's unusual access alert of each peer, by peer id.
+	access_alerts: HashMap<String, String>,
 	selected_peer: Option<String>,
 	search_mime_types: Vec<String>,
 	peer_cpus: Vec<CpuInfo>,
+	peer_disks: Vec<(DiskInfo, Vec<DiskSample>)>,
+	/// Temporary grants this node gave `selected_peer`.
+	peer_temporary_grants: Vec<TemporaryGrant>,
 	peer_interfaces: Vec<InterfaceInfo>,
+	peer_capabilities: Option<PeerCapabilities>,
 	peer_audio_capability: Option<AudioCapability>,
 	peer_audio_devices: Vec<AudioDevice>,
 	peer_webcam_capability: Option<MediaCapability>,
@@ -90,11 +221,69 @@ struct UiState {
 	peer_screens: Vec<MediaSource>,
 	peer_screen_status: String,
 	peer_files_path: String,
+	/// `peer_files_listed` as `peer_files_view` shows them.
 	peer_files: Vec<DirEntry>,
+	/// Entries of `peer_files_path` as `selected_peer` listed them.
+	peer_files_listed: Vec<DirEntry>,
+	peer_files_view: DirView,
+	/// Why `selected_peer` refused to list `peer_files_path`, when it did.
+	peer_files_denied: Option<AccessExplanation>,
+	/// Room `selected_peer` says is left to this node in `peer_files_path`.
+	peer_files_free_hint: Option<u64>,
+	/// What `selected_peer` says this node may do in `peer_files_path`.
+	/// `None` from peers that predate it.
+	peer_files_access: Option<ListingAccess>,
+	/// Browse roots of `selected_peer`, fetched once per peer.
+	peer_roots: Option<(String, BrowseRoots)>,
+	/// Standard folders of `selected_peer`, fetched once per peer.
+	peer_folders: Option<(String, Vec<WellKnownFolder>)>,
+	/// What `selected_peer` lets this node access, from the grant cache.
+	peer_grants: Option<(String, RemoteGrants)>,
+	/// Summaries of the folders `selected_peer` lets this node search;
+	/// `None` inside when it can't give them.
+	peer_shares: Option<(String, Option<Vec<ShareSummary>>)>,
+	/// Standard folders on this device, offered when sharing or scanning.
+	local_folders: Vec<WellKnownFolder>,
 	shared_folders: Vec<UiSharedFolder>,
 	files: Vec<FileEntry>,
+	/// Which of `files` the content store holds, looked up with the list.
+	files_in_store: HashSet<FileHash>,
 	storage: Vec<StorageUsageFile>,
+	scan_runs: Vec<ScanRun>,
+	scan_trends: Vec<ScanTrend>,
+	/// Growth of the scanned folders over the last [`PROJECTION_DAYS`].
+	growth: Option<GrowthReport>,
+	/// `storage` as one folder tree per node, for the treemap.
+	storage_trees: Arc<Vec<NodeStorageTree>>,
 	users: Vec<String>,
+	failed_logins: Vec<FailedLoginGroup>,
+	/// Recent backups and restores, newest first.
+	backup_runs: Vec<BackupRun>,
+	/// Stored configuration snapshots, newest first.
+	config_snapshots: Vec<ConfigSnapshotInfo>,
+	/// Recent downloads and uploads, newest first.
+	transfers: Vec<Transfer>,
+	/// Pinned remote folders, oldest first.
+	pins: Vec<PinStatus>,
+	/// Watched folders sending to peers, oldest first.
+	outboxes: Vec<OutboxStatus>,
+	/// Peer writes waiting for review, oldest first.
+	reviews: Vec<PendingReview>,
+	/// Deletion proposals from peers waiting for a decision, oldest first.
+	delete_requests: Vec<ReceivedProposal>,
+	/// Deletion proposals made here, newest first.
+	delete_proposals: Vec<DeleteProposal>,
+	remote_access_suspended: bool,
+	identity_mismatch: Option<IdentityMismatch>,
+	nat: NatStatus,
+	/// Last dial-back self-test of this node's addresses.
+	reachability: Vec<AddressReachability>,
+	/// Name of this node as stored, shown by the setup wizard.
+	node_name: String,
+	/// Discovered peers and peers with a pairing, in discovery order.
+	nearby_peers: Vec<PeerId>,
+	pairings: HashMap<PeerId, Pairing>,
+	last_notification_id: u64,
 	status: String,
 }
 
@@ -104,10 +293,16 @@ impl UiState {
 			page: Page::Home,
 			local_peer_id: None,
 			peers: Vec::new(),
+			peers_refresh: FanOutSummary::default(),
+			outdated_peers: Vec::new(),
+			access_alerts: HashMap::new(),
 			selected_peer: None,
 			search_mime_types: Vec::new(),
 			peer_cpus: Vec::new(),
+			peer_disks: Vec::new(),
+			peer_temporary_grants: Vec::new(),
 			peer_interfaces: Vec::new(),
+			peer_capabilities: None,
 			peer_audio_capability: None,
 			peer_audio_devices: Vec::new(),
 			peer_webcam_capability: None,
@@ -115,12 +310,44 @@ impl UiState {
 			peer_microphones: Vec::new(),
 			peer_screens: Vec::new(),
 			peer_screen_status: String::from("Monitor capability not checked yet."),
-			peer_files_path: String::from("/"),
+			peer_files_path: String::new(),
 			peer_files: Vec::new(),
+			peer_files_listed: Vec::new(),
+			peer_files_view: DirView::default(),
+			peer_files_denied: None,
+			peer_files_free_hint: None,
+			peer_files_access: None,
+			peer_roots: None,
+			peer_grants: None,
+			peer_shares: None,
+			peer_folders: None,
+			local_folders: Vec::new(),
 			shared_folders: Vec::new(),
 			files: Vec::new(),
+			files_in_store: HashSet::new(),
 			storage: Vec::new(),
+			scan_runs: Vec::new(),
+			scan_trends: Vec::new(),
+			growth: None,
+			storage_trees: Arc::default(),
 			users: Vec::new(),
+			failed_logins: Vec::new(),
+			backup_runs: Vec::new(),
+			config_snapshots: Vec::new(),
+			transfers: Vec::new(),
+			pins: Vec::new(),
+			outboxes: Vec::new(),
+			reviews: Vec::new(),
+			delete_requests: Vec::new(),
+			delete_proposals: Vec::new(),
+			remote_access_suspended: false,
+			identity_mismatch: None,
+			nat: NatStatus::Disabled,
+			reachability: Vec::new(),
+			node_name: String::new(),
+			nearby_peers: Vec::new(),
+			pairings: HashMap::new(),
+			last_notification_id: 0,
 			status: String::from("Ready"),
 		}
 	}
@@ -135,18 +362,38 @@ enum UiAction {
 	RefreshStorage,
 	RefreshUsers,
 	RefreshSearchOptions,
+	RefreshOnboarding,
 }
 
 pub(super) struct UiServer {
 	media: Arc<MediaSessionManager>,
 	puppy: Arc<PuppyNet>,
 	state: Mutex<UiState>,
+	/// A std mutex so it can be locked while `state` or a session is held.
+	notifications: std::sync::Mutex<NotificationCenter>,
+}
+
+/// A connected client that gets re-rendered when a job changes. `push`
+/// returns false once the UI it renders into is gone.
+struct JobWatcher {
+	route: String,
+	push: Arc<dyn Fn(String) -> bool + Send + Sync>,
 }
 
 pub(super) struct UiContext {
 	server: Arc<UiServer>,
 	sessions: std::sync::Mutex<HashMap<String, UiClientSession>>,
 	pending_login_tokens: std::sync::Mutex<HashMap<String, String>>,
+	jobs: Arc<JobManager>,
+	/// Keyed by client id.
+	job_watchers: std::sync::Mutex<HashMap<String, JobWatcher>>,
+	prefs: std::sync::Mutex<UiPrefs>,
+	prefs_path: PathBuf,
+	resume_path: PathBuf,
+	pending_resume: std::sync::Mutex<PendingResume>,
+	/// Key of the session that changed last, whose fields are saved for
+	/// the next start.
+	last_session: std::sync::Mutex<Option<String>>,
 }
 
 #[derive(Clone, WguiModel)]
@@ -162,6 +409,12 @@ struct UiPeer {
 	uptime: String,
 	version: String,
 	last_seen: String,
+	connections: String,
+	stability: String,
+	clock_skew: String,
+	trust_icon: String,
+	trust_label: String,
+	focused: bool,
 }
 
 #[derive(Clone, WguiModel)]
@@ -169,11 +422,61 @@ struct UiCpu {
 	line: String,
 }
 
+#[derive(Clone, WguiModel)]
+struct UiDisk {
+	line: String,
+	trend: String,
+	forecast: String,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiTemporaryGrant {
+	id: u64,
+	line: String,
+	countdown: String,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiShareCard {
+	root: String,
+	summary: String,
+	subdirs: String,
+	browse_href: String,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiNearbyPeer {
+	label: String,
+	short_id: String,
+	code: String,
+	status: String,
+	can_pair: bool,
+	can_confirm: bool,
+}
+
 #[derive(Clone, WguiModel)]
 struct UiInterface {
 	line: String,
 }
 
+#[derive(Clone, WguiModel)]
+struct UiFolderChip {
+	label: String,
+	path: String,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiShareWizardPeer {
+	label: String,
+	selected: bool,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiShareWizardResult {
+	line: String,
+	color: String,
+}
+
 #[derive(Clone, WguiModel)]
 struct UiSelectOption {
 	value: String,
@@ -196,14 +499,28 @@ struct UiWebcamDevice {
 struct UiFileRow {
 	hash: String,
 	line: String,
+	in_store: bool,
 }
 
 #[derive(Clone, WguiModel)]
 struct UiPeerFileRow {
 	name: String,
+	undecodable: bool,
 	summary: String,
 	href: String,
 	is_dir: bool,
+	highlighted: bool,
+	focused: bool,
+	/// A folder pinned for offline use.
+	pinned: bool,
+	can_pin: bool,
+	/// Data URL of the file's thumbnail, once loaded.
+	thumbnail: String,
+	thumbnail_loading: bool,
+	/// The device holding the image refused to decode it for a thumbnail.
+	thumbnail_too_large: bool,
+	/// The rule of its own that governs the entry, with what it allows.
+	rule: String,
 }
 
 #[derive(Clone, WguiModel)]
@@ -211,10 +528,176 @@ struct UiStorageRow {
 	line: String,
 }
 
+#[derive(Clone, WguiModel)]
+struct UiGrowthRow {
+	folder: String,
+	added: String,
+	removed: String,
+	net: String,
+	color: String,
+}
+
+/// A treemap rectangle, in percent of the treemap's width and height.
+#[derive(Clone, WguiModel)]
+struct UiTreemapTile {
+	x: f64,
+	y: f64,
+	width: f64,
+	height: f64,
+	label: String,
+	tooltip: String,
+	color: String,
+	/// Drawn as an outline around the tiles inside it.
+	split: bool,
+	/// Path clicking it zooms to; empty when it doesn't zoom.
+	zoom_to: String,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiTreemapProps {
+	aspect_ratio: f64,
+	tiles: Vec<UiTreemapTile>,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiJobRow {
+	id: u64,
+	label: String,
+	status: String,
+	progress: String,
+	elapsed: String,
+	detail: String,
+	error: String,
+	has_error: bool,
+	can_cancel: bool,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiConfigSnapshotRow {
+	summary: String,
+	detail: String,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiOutboxRow {
+	label: String,
+	status: String,
+	error: String,
+	has_error: bool,
+	paused: bool,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiPinRow {
+	label: String,
+	status: String,
+	error: String,
+	has_error: bool,
+	paused: bool,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiScanResultsPull {
+	path: String,
+	progress: String,
+	error: String,
+	has_error: bool,
+	can_continue: bool,
+	can_browse: bool,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiReviewRow {
+	label: String,
+	detail: String,
+	selected: bool,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiDeletionRow {
+	label: String,
+	reason: String,
+	detail: String,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiNotificationRow {
+	title: String,
+	detail: String,
+	/// Severity, where it came from, when, and how often.
+	meta: String,
+	copy: UiCopyProps,
+	color: String,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiCopyProps {
+	text: String,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiFilterChip {
+	label: String,
+	selected: bool,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiPermissionRuleRow {
+	label: String,
+	detail: String,
+	selected: bool,
+	errors: String,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiTimelineRow {
+	icon: String,
+	line: String,
+	detail: String,
+	/// Opens the scan, transfer or peer the event is about.
+	linked: bool,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiScanRunRow {
+	line: String,
+	status: String,
+	selected: bool,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiRemovableDrive {
+	line: String,
+	/// "Resume ingest" when the last ingest stopped because it was removed.
+	action: String,
+}
+
+/// A run of one colour of the remote shell's output.
+#[derive(Clone, WguiModel)]
+struct UiShellSpan {
+	text: String,
+	color: String,
+	/// The span begins a new line of the terminal.
+	line_start: bool,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiIntegrityRow {
+	line: String,
+	/// Rows naming another copy of a damaged file open it.
+	replica: bool,
+}
+
 #[derive(Clone, WguiModel)]
 struct UiSharedFolder {
 	path: String,
 	access: String,
+	/// Explains how an overlapping folder rule resolves, empty otherwise.
+	overlap_note: String,
+	has_overlap_note: bool,
+	/// Why the folder cannot be read right now, empty while it can.
+	unavailable_note: String,
+	unavailable: bool,
 }
 
 #[derive(Clone, WguiModel)]
@@ -233,6 +716,50 @@ struct UiSearchRow {
 	device: String,
 	mime_type: String,
 	modified_at: String,
+	/// Set when the row's timestamp comes from a node with a skewed clock.
+	clock_warning: String,
+	/// Set when the row's node announced index changes not pulled yet.
+	index_stale: String,
+	duration: String,
+	/// "deleted 2025-03-30" for an index row whose file is gone.
+	deleted: String,
+	/// Who put the row in the index, for index searches.
+	provenance: String,
+	provenance_open: bool,
+	focused: bool,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiReachability {
+	addr: String,
+	status: String,
+	color: String,
+	detail: String,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiProtocolRow {
+	peer: String,
+	request: String,
+	requests: String,
+	traffic: String,
+	failed: String,
+	color: String,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiDiagnostic {
+	status: String,
+	color: String,
+	check: String,
+	detail: String,
+	hint: String,
+}
+
+#[derive(Clone, WguiModel)]
+struct UiDiffLine {
+	text: String,
+	color: String,
 }
 
 #[derive(Clone)]
@@ -243,6 +770,7 @@ struct UiSearchRawRow {
 	mime_type: Option<String>,
 	modified_at: Option<String>,
 	peer_id: String,
+	duration_ms: Option<u64>,
 }
 
 #[derive(Clone, WguiModel)]
@@ -258,6 +786,79 @@ struct UiMediaReceiverProps {
 	source_id: String,
 }
 
+/// Activity window being edited on the settings page.
+#[derive(Clone, Default)]
+struct UiActivityDraft {
+	ranges: String,
+	utc_offset: String,
+	scans: bool,
+	updates: bool,
+	syncs: bool,
+	hard_stop: bool,
+}
+
+/// Backup settings being edited on the settings page.
+#[derive(Clone, Default)]
+struct UiBackupDraft {
+	scheduled: bool,
+	hour: String,
+	keep: String,
+	dir: String,
+}
+
+/// Ingest settings being edited on the storage page.
+#[derive(Clone, Default)]
+struct UiIngestDraft {
+	destination: String,
+	/// Comma separated.
+	patterns: String,
+	delete_originals: bool,
+	eject_when_done: bool,
+}
+
+/// Integrity sampling settings being edited on the storage page.
+#[derive(Clone, Default)]
+struct UiIntegrityDraft {
+	enabled: bool,
+	files_per_cycle: String,
+	/// MiB per second.
+	read_rate: String,
+}
+
+/// An outbox rule being added, or edited when `editing` is set.
+#[derive(Clone, Default)]
+struct UiOutboxDraft {
+	editing: Option<u64>,
+	watch_path: String,
+	peer: String,
+	dest_path: String,
+	pattern: String,
+	delete_after_send: bool,
+}
+
+#[derive(Clone, Default)]
+struct UiProxyDraft {
+	url: String,
+	username: String,
+	/// Left empty to keep the password already set.
+	password: String,
+}
+
+/// The "share a folder" wizard on the peers page, open while set.
+#[derive(Clone, Default)]
+struct UiShareWizard {
+	path: String,
+	write: bool,
+	preview_only: bool,
+	/// Deny patterns as typed, comma separated.
+	deny_patterns: String,
+	/// Ids of the peers ticked to receive the grant.
+	peers: BTreeSet<String>,
+	/// One line per peer after the grant ran, with its color.
+	results: Vec<(String, &'static str)>,
+	status: String,
+}
+
 #[derive(Clone, Default)]
 struct UiClientSession {
 	authenticated: bool,
@@ -269,39 +870,131 @@ struct UiClientSession {
 	new_password: String,
 	confirm_password: String,
 	password_change_status: String,
-	search_name_query: String,
-	search_target: String,
-	search_sort: String,
-	search_page_size: String,
-	search_visible_count: usize,
-	search_raw_rows: Vec<UiSearchRawRow>,
-	search_selected_mimes: Vec<String>,
-	search_results: Vec<UiSearchRow>,
-	search_status: String,
-	search_in_progress: bool,
-	search_total_peers: usize,
-	search_done_peers: usize,
-	search_truncated: bool,
-	search_rx: Option<Arc<std::sync::Mutex<mpsc::Receiver<LiveSearchPeerEvent>>>>,
+	prefs_status: String,
+	remote_access_status: String,
+	revoke_access_status: String,
+	access_alert_status: String,
+	index_replica_status: String,
+	index_updates_status: String,
+	/// Folder to scan on the selected peer.
+	remote_scan_path: String,
+	remote_scan_status: String,
+	/// Remote scan or results pull last started from a device page.
+	remote_scan_job: Option<u64>,
+	mirror_remote_scans_status: String,
+	identity_status: String,
+	nat_mapping_status: String,
+	reachability_status: String,
+	diagnostics: Option<DiagnosticsReport>,
+	diagnostics_status: String,
+	temporary_grant_path: String,
+	temporary_grant_access: String,
+	temporary_grant_status: String,
+	peer_files: PeerFilesSession,
+	peers_focus: ListFocus,
+	peer_files_focus: ListFocus,
+	search_focus: ListFocus,
+	onboarding_step: usize,
+	onboarding_node_name: Option<String>,
+	onboarding_status: String,
+	pairing_status: String,
+	/// The pairing string shown last and its QR code as an image URL.
+	pairing_string: Option<(PairingInfo, String)>,
+	join_pairing: String,
+	pairing_string_status: String,
+	activity_draft: Option<UiActivityDraft>,
+	activity_status: String,
+	disk_alert_draft: Option<String>,
+	disk_alert_status: String,
+	idle_timeout_draft: Option<String>,
+	ping_interval_draft: Option<String>,
+	connection_policy_status: String,
+	keep_connected_status: String,
+	power_mode_draft: Option<String>,
+	power_percent_draft: Option<String>,
+	power_policy_status: String,
+	backup_draft: Option<UiBackupDraft>,
+	backup_status: String,
+	ingest_draft: Option<UiIngestDraft>,
+	ingest_status: String,
+	integrity_draft: Option<UiIntegrityDraft>,
+	integrity_status: String,
+	config_snapshot_label: String,
+	config_snapshot_users: bool,
+	config_snapshot_status: String,
+	undo_status: String,
+	outbox_draft: UiOutboxDraft,
+	outbox_status: String,
+	download_dir_draft: Option<String>,
+	download_dir_status: String,
+	proxy_draft: Option<UiProxyDraft>,
+	proxy_status: String,
+	/// Edited download folder and the peer it is for.
+	peer_download_dir_draft: Option<(String, String)>,
+	peer_download_dir_status: String,
+	share_wizard: Option<UiShareWizard>,
+	search: SearchSession,
+	review: ReviewSession,
+	timeline: TimelineSession,
+	permission_editor: PermissionEditorSession,
+	protocol: ProtocolSession,
 	shared_folder_path: String,
 	shared_folder_access: String,
 	shared_folder_status: String,
 	new_user_username: String,
 	new_user_password: String,
 	new_user_status: String,
+	/// What the password policy says about each field, from the last
+	/// attempt.
+	new_user_username_error: String,
+	new_user_password_error: String,
 	new_user_modal_open: bool,
 	file_preview_peer: String,
 	file_preview_path: String,
+	file_preview_raw_path: Option<Vec<u8>>,
 	file_preview_status: String,
 	file_preview_content: String,
 	file_preview_image_src: String,
 	file_preview_loaded: bool,
 	file_preview_modal_open: bool,
-	shell_peer: String,
-	shell_input: String,
-	shell_output: String,
-	shell_status: String,
-	shell_session_id: Option<u64>,
+	file_preview_offset: u64,
+	file_preview_first_line: usize,
+	file_preview_next: Option<(u64, usize)>,
+	file_preview_history: Vec<(u64, usize)>,
+	file_preview_download_status: String,
+	/// Dimensions and duration the index knows for the previewed file.
+	file_preview_media: String,
+	/// Device holding the previewed image while asking whether to preview
+	/// it although it is too large to decode comfortably.
+	file_preview_large_image: Option<PeerId>,
+	/// Earlier download of the previewed file, while asking whether to
+	/// download it again.
+	file_preview_previous_download: Option<Transfer>,
+	/// The modal shows a folder's contact sheet, which has nothing to
+	/// download.
+	file_preview_contact_sheet: bool,
+	/// Search result picked to compare, as (peer, path), until a second
+	/// one is picked.
+	compare_first: Option<(String, String)>,
+	compare_modal_open: bool,
+	compare_title: String,
+	compare_status: String,
+	compare_diff: Option<FileDiff>,
+	peer_shell: PeerShellSession,
+	send_file_path: String,
+	send_file_status: String,
+	scan_diff_runs: Vec<i64>,
+	scan_diff_lines: Vec<String>,
+	scan_diff_status: String,
+	growth: GrowthSession,
+	treemap: TreemapSession,
+	scan_path: String,
+	scan_status: String,
+	/// MiB per second typed for the next scan; blank keeps the node's
+	/// limit.
+	scan_read_limit: String,
+	scan_low_priority: bool,
+	store_status: String,
 	control_text: String,
 	control_status: String,
 	monitor_stream_enabled: bool,
@@ -311,9 +1004,19 @@ struct UiClientSession {
 	microphone_selected_device: String,
 	update_version: String,
 	update_status: String,
-	update_events: Vec<String>,
-	update_in_progress: bool,
-	update_rx: Option<Arc<std::sync::Mutex<mpsc::Receiver<UpdateProgress>>>>,
+	update_job: Option<u64>,
+	restart_after_update: bool,
+	/// The peers that didn't answer the last refresh are listed.
+	peer_failures_open: bool,
+	fleet_update_status: String,
+	restart_confirm: bool,
+	restart_status: String,
+	restart_job: Option<u64>,
+	/// Edited MAC address and the peer it is for.
+	wake_mac_draft: Option<(String, String)>,
+	wake_mac_status: String,
+	wake_status: String,
+	wake_job: Option<u64>,
 }
 
 #[derive(Clone, WguiModel)]
@@ -329,6 +1032,94 @@ pub(super) struct UiViewState {
 	new_password: String,
 	confirm_password: String,
 	password_change_status: String,
+	prefs_theme: String,
+	prefs_theme_options: Vec<UiSelectOption>,
+	prefs_font_scale: String,
+	prefs_font_scale_options: Vec<UiSelectOption>,
+	prefs_page_size: String,
+	prefs_page_size_options: Vec<UiSelectOption>,
+	prefs_refresh_interval: String,
+	prefs_refresh_interval_options: Vec<UiSelectOption>,
+	prefs_status: String,
+	remote_access_suspended: bool,
+	remote_access_status: String,
+	revoke_access_status: String,
+	demo_mode: bool,
+	has_identity_mismatch: bool,
+	identity_mismatch: String,
+	identity_status: String,
+	nat_enabled: bool,
+	nat_status: String,
+	nat_mapping_status: String,
+	reachability: Vec<UiReachability>,
+	has_reachability: bool,
+	/// Whether the tests suggest asking the router to forward ports.
+	reachability_hint: String,
+	reachability_status: String,
+	diagnostics: Vec<UiDiagnostic>,
+	has_diagnostics: bool,
+	diagnostics_status: String,
+	protocol_rows: Vec<UiProtocolRow>,
+	has_protocol_rows: bool,
+	protocol_alert: String,
+	protocol_limiter: bool,
+	protocol_status: String,
+	activity_ranges: String,
+	activity_utc_offset: String,
+	activity_scans: bool,
+	activity_updates: bool,
+	activity_syncs: bool,
+	activity_hard_stop: bool,
+	activity_status: String,
+	disk_alert_threshold: String,
+	disk_alert_status: String,
+	idle_timeout: String,
+	ping_interval: String,
+	kept_connected: String,
+	connection_policy_status: String,
+	peer_idle: bool,
+	peer_important: bool,
+	keep_connected_status: String,
+	power_mode: String,
+	power_mode_options: Vec<UiSelectOption>,
+	power_percent: String,
+	power_reading: String,
+	power_policy_status: String,
+	backup_scheduled: bool,
+	backup_hour: String,
+	backup_keep: String,
+	backup_dir: String,
+	backup_status: String,
+	has_backup_runs: bool,
+	backup_runs: Vec<String>,
+	config_snapshot_label: String,
+	config_snapshot_users: bool,
+	has_config_snapshots: bool,
+	config_snapshots: Vec<UiConfigSnapshotRow>,
+	config_snapshot_status: String,
+	download_dir: String,
+	download_dir_status: String,
+	proxy_url: String,
+	proxy_username: String,
+	proxy_password: String,
+	proxy_status: String,
+	peer_download_dir: String,
+	peer_download_dir_status: String,
+	wake_mac: String,
+	/// Where the MAC address came from.
+	wake_mac_note: String,
+	wake_mac_status: String,
+	wake_status: String,
+	wake_in_progress: bool,
+	has_deferred_work: bool,
+	deferred_work_notice: String,
+	background_paused: bool,
+	power_notice: String,
+	in_maintenance: bool,
+	maintenance_notice: String,
+	has_undo: bool,
+	undo_notice: String,
+	undo_status: String,
 	search_name_query: String,
 	search_target: String,
 	search_target_options: Vec<UiSelectOption>,
@@ -346,16 +1137,89 @@ pub(super) struct UiViewState {
 	search_results: Vec<UiSearchRow>,
 	search_has_results: bool,
 	is_current_device: bool,
+	peer_connections: Vec<String>,
+	has_peer_connections: bool,
+	peer_clock_skew: String,
+	/// How reviewed writes from the selected peer were decided.
+	peer_trust: String,
+	/// Today's alert for the selected peer reading or writing far more
+	/// than usual.
+	peer_access_alert: String,
+	/// Label of the button applying the temporary limit the alert offers.
+	peer_traffic_limit: String,
+	access_alert_status: String,
+	/// Index replication with the selected peer, either way round.
+	index_replica: String,
+	replicating_index: bool,
+	index_replica_status: String,
+	/// Announced changes to the selected peer's index not pulled yet.
+	peer_index_stale: String,
+	subscribed_to_index: bool,
+	index_updates_status: String,
+	remote_scan_path: String,
+	remote_scan_status: String,
+	remote_scan_running: bool,
+	/// Results of scans started on the selected peer, as far as pulled.
+	scan_result_pulls: Vec<UiScanResultsPull>,
+	mirror_remote_scans: bool,
+	mirror_remote_scans_status: String,
 	shared_folder_path: String,
+	local_folders: Vec<UiFolderChip>,
+	has_local_folders: bool,
 	shared_folder_access: String,
 	shared_folder_access_options: Vec<UiSelectOption>,
+	temporary_grant_access_options: Vec<UiSelectOption>,
 	shared_folder_status: String,
 	shared_folders: Vec<UiSharedFolder>,
 	has_shared_folders: bool,
+	temporary_grant_path: String,
+	temporary_grant_access: String,
+	temporary_grant_presets: Vec<String>,
+	temporary_grant_status: String,
+	temporary_grants: Vec<UiTemporaryGrant>,
+	peer_shares: Vec<UiShareCard>,
+	has_peer_shares: bool,
+	/// Set when the peer can't summarize its shares and only their folders
+	/// show.
+	peer_shares_degraded: bool,
+	onboarding_step_text: String,
+	onboarding_is_identity: bool,
+	onboarding_is_share: bool,
+	onboarding_is_pair: bool,
+	onboarding_has_back: bool,
+	onboarding_node_name: String,
+	onboarding_status: String,
+	node_peer_id: String,
+	node_fingerprint: String,
+	nearby_peers: Vec<UiNearbyPeer>,
+	has_nearby_peers: bool,
+	pairing_status: String,
+	pairing_string: String,
+	pairing_qr: String,
+	pairing_string_note: String,
+	join_pairing: String,
+	pairing_string_status: String,
 	new_user_username: String,
 	new_user_password: String,
 	new_user_status: String,
+	new_user_username_error: String,
+	new_user_password_error: String,
+	/// The password rules, while the form is open.
+	new_user_password_hint: String,
 	new_user_modal_open: bool,
+	share_wizard_open: bool,
+	share_wizard_path: String,
+	share_wizard_write: bool,
+	share_wizard_preview_only: bool,
+	share_wizard_deny_patterns: String,
+	/// Offers [`SUGGESTED_DENY_PATTERNS`] for a share peers may write to
+	/// that hides nothing yet.
+	share_wizard_suggest_patterns: bool,
+	share_wizard_peers: Vec<UiShareWizardPeer>,
+	has_share_wizard_peers: bool,
+	share_wizard_results: Vec<UiShareWizardResult>,
+	has_share_wizard_results: bool,
+	share_wizard_status: String,
 	file_preview_peer: String,
 	file_preview_path: String,
 	file_preview_status: String,
@@ -364,11 +1228,33 @@ pub(super) struct UiViewState {
 	file_preview_has_image: bool,
 	file_preview_can_load: bool,
 	file_preview_modal_open: bool,
-	shell_peer: String,
-	shell_input: String,
-	shell_output: String,
-	shell_status: String,
-	shell_has_session: bool,
+	file_preview_has_prev: bool,
+	file_preview_has_next: bool,
+	file_preview_can_download: bool,
+	file_preview_download_status: String,
+	file_preview_media: String,
+	file_preview_large_image: bool,
+	file_preview_has_previous_download: bool,
+	file_preview_previous_download: String,
+	has_compare_first: bool,
+	compare_first: String,
+	compare_modal_open: bool,
+	compare_title: String,
+	compare_status: String,
+	compare_lines: Vec<UiDiffLine>,
+	shell_supported: bool,
+	peer_shell_status: String,
+	peer_shell_connected: bool,
+	peer_shell_ended: bool,
+	peer_shell_input: String,
+	peer_shell_spans: Vec<UiShellSpan>,
+	update_supported: bool,
+	restart_supported: bool,
+	inbox_supported: bool,
+	capability_notice: String,
+	has_capability_notice: bool,
+	send_file_path: String,
+	send_file_status: String,
 	control_text: String,
 	control_status: String,
 	trackpad_props: UiTrackpadProps,
@@ -403,6 +1289,10 @@ pub(super) struct UiViewState {
 	update_events: Vec<String>,
 	has_update_events: bool,
 	update_in_progress: bool,
+	restart_after_update: bool,
+	restart_confirm: bool,
+	restart_status: String,
+	restart_in_progress: bool,
 	home_peers: String,
 	home_files: String,
 	home_storage: String,
@@ -410,7 +1300,16 @@ pub(super) struct UiViewState {
 	current_peer: String,
 	grant_command: String,
 	has_peers: bool,
+	/// "4 of 5 peers responded", empty without other peers.
+	peers_responded: String,
+	peers_has_failures: bool,
+	peers_failures_open: bool,
+	peers_failures: Vec<String>,
+	has_outdated_peers: bool,
+	fleet_update_label: String,
+	fleet_update_status: String,
 	has_cpus: bool,
+	has_disks: bool,
 	has_interfaces: bool,
 	has_audio_devices: bool,
 	has_files: bool,
@@ -420,19 +1319,159 @@ pub(super) struct UiViewState {
 	selected_peer_control_href: String,
 	selected_peer_files_href: String,
 	selected_peer_webcams_href: String,
+	selected_peer_permissions_href: String,
+	selected_peer_shell_href: String,
 	peer_files_parent_href: String,
 	peer_files_has_parent: bool,
+	/// Shown while the peer's cached grants are being refetched.
+	peer_grants_status: String,
+	/// How much this node may still write in a writable folder, when the
+	/// peer says.
+	peer_files_free_hint: String,
+	/// "read-only", "read + write" or "preview only" for the listed folder,
+	/// empty when neither the peer nor its grants tell.
+	peer_files_access: String,
+	/// The last listing was refused with an explanation.
+	peer_files_denied: bool,
+	/// Message asking the owner for the missing access, once requested.
+	peer_files_access_request: String,
+	peer_files_search_query: String,
+	peer_files_search_include_deleted: bool,
+	peer_files_disk_sizes: bool,
+	/// Order of the listed folder, empty at the disks and shared folders.
+	peer_files_sort: String,
+	peer_files_sort_options: Vec<UiSelectOption>,
+	peer_files_show_hidden: bool,
+	peer_files_thumbnails: bool,
+	peer_files_search_active: bool,
+	peer_files_search_scope: String,
+	peer_files_search_status: String,
+	peer_files_search_has_results: bool,
+	peer_files_search_results: Vec<UiSearchRow>,
+	/// "thumbnails 42/300" while a folder's thumbnails load.
+	peer_files_thumbnail_progress: String,
+	/// "Show 200 more (1,800 not shown)" while a long folder is rendered
+	/// in part.
+	peer_files_more: String,
 	has_storage_rows: bool,
+	has_scan_history: bool,
+	has_scan_trends: bool,
+	/// How much the growth table covers, or why there is none yet.
+	growth_summary: String,
+	has_growth_rows: bool,
+	growth_fastest: String,
+	growth_caveat: String,
+	has_treemap: bool,
+	treemap_props: UiTreemapProps,
+	/// Where the treemap starts, from the node's root down.
+	treemap_trail: Vec<UiStorageRow>,
+	/// Hex id of the node the treemap shows.
+	treemap_node: String,
+	treemap_node_options: Vec<UiSelectOption>,
+	treemap_depth: String,
+	treemap_depth_options: Vec<UiSelectOption>,
+	treemap_note: String,
+	has_scan_diff: bool,
+	scan_diff_status: String,
+	scan_path: String,
+	scan_status: String,
+	scan_read_limit: String,
+	scan_low_priority: bool,
+	store_status: String,
+	has_removable_drives: bool,
+	ingest_destination: String,
+	ingest_patterns: String,
+	ingest_delete_originals: bool,
+	ingest_eject_when_done: bool,
+	ingest_status: String,
+	integrity_summary: String,
+	integrity_enabled: bool,
+	integrity_files_per_cycle: String,
+	integrity_read_rate: String,
+	integrity_status: String,
+	has_integrity_findings: bool,
+	has_jobs: bool,
+	jobs_nav_label: String,
+	thumbnail_queue: String,
+	thumbnail_pregeneration: bool,
+	has_transfers: bool,
+	transfers: Vec<String>,
+	has_pins: bool,
+	pins: Vec<UiPinRow>,
+	has_outboxes: bool,
+	outboxes: Vec<UiOutboxRow>,
+	outbox_peer_options: Vec<UiSelectOption>,
+	outbox_watch_path: String,
+	outbox_peer: String,
+	outbox_dest_path: String,
+	outbox_pattern: String,
+	outbox_delete_after_send: bool,
+	outbox_editing: bool,
+	outbox_status: String,
+	review_nav_label: String,
+	notifications_nav_label: String,
+	has_toasts: bool,
+	toasts: Vec<UiNotificationRow>,
+	has_notifications: bool,
+	notifications: Vec<UiNotificationRow>,
+	has_reviews: bool,
+	reviews: Vec<UiReviewRow>,
+	review_status: String,
+	review_selection: String,
+	has_review_selection: bool,
+	has_delete_requests: bool,
+	delete_requests: Vec<UiDeletionRow>,
+	delete_reject_reason: String,
+	has_delete_proposals: bool,
+	delete_proposals: Vec<UiDeletionRow>,
+	timeline_kinds: Vec<UiFilterChip>,
+	timeline_peers: Vec<UiFilterChip>,
+	has_timeline_peers: bool,
+	timeline_text: String,
+	timeline_events: Vec<UiTimelineRow>,
+	has_timeline_events: bool,
+	timeline_can_load_more: bool,
+	timeline_status: String,
+	permission_rules: Vec<UiPermissionRuleRow>,
+	has_permission_rules: bool,
+	/// A folder rule is selected, so its path and flags can be edited.
+	permission_folder_selected: bool,
+	permission_path: String,
+	permission_path_error: String,
+	permission_flags: Vec<UiFilterChip>,
+	permission_flags_error: String,
+	permission_deny_patterns: String,
+	permission_deny_patterns_error: String,
+	permission_allow_patterns: String,
+	permission_allow_patterns_error: String,
+	permission_merge: bool,
+	permission_revision: String,
+	permission_status: String,
+	/// [`TrustLevel::as_str`] of the peer being edited.
+	permission_trust: String,
+	permission_trust_options: Vec<UiSelectOption>,
+	permission_trust_note: String,
 	has_users: bool,
+	has_failed_logins: bool,
+	failed_logins: Vec<String>,
 	selected_peer: String,
 	peers: Vec<UiPeer>,
 	cpus: Vec<UiCpu>,
+	disks: Vec<UiDisk>,
 	interfaces: Vec<UiInterface>,
 	audio_devices: Vec<UiAudioDevice>,
 	webcam_devices: Vec<UiWebcamDevice>,
 	files: Vec<UiFileRow>,
 	peer_files: Vec<UiPeerFileRow>,
 	storage_rows: Vec<UiStorageRow>,
+	scan_history: Vec<UiScanRunRow>,
+	scan_trends: Vec<UiStorageRow>,
+	growth_rows: Vec<UiGrowthRow>,
+	growth_projections: Vec<UiStorageRow>,
+	scan_diff_rows: Vec<UiStorageRow>,
+	removable_drives: Vec<UiRemovableDrive>,
+	integrity_rows: Vec<UiIntegrityRow>,
+	jobs: Vec<UiJobRow>,
 	users: Vec<String>,
 }
 
@@ -458,10 +1497,45 @@ impl<'a> UiControllerCore<'a> {
 		tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(fut))
 	}
 
+	fn prefs(&self) -> UiPrefs {
+		self.ctx.state.prefs.lock().unwrap().clone()
+	}
+
+	fn new_session(&self) -> UiClientSession {
+		let mut session = UiClientSession {
+			search_page_size: self.prefs().default_page_size.to_string(),
+			..UiClientSession::default()
+		};
+		let resumed = if self.is_authenticated() {
+			self.ctx.state.pending_resume.lock().unwrap().fields.take()
+		} else {
+			None
+		};
+		if let Some((search, scan_path)) = resumed {
+			session.search.name_query = search.name_query;
+			session.search.target = search.target;
+			session.search.sort = search.sort;
+			session.search.page_size = search.page_size;
+			session.search.selected_mimes = search.selected_mimes;
+			session.scan_path = scan_path;
+		}
+		session
+	}
+
+	/// Route of the page open before the last restart, once, for the first
+	/// client opening the home page.
+	pub(super) fn take_resumed_route(&self) -> Option<String> {
+		let page = self.ctx.state.pending_resume.lock().unwrap().page.take()?;
+		Some(page_href(&page))
+	}
+
 	fn current_session(&self) -> UiClientSession {
 		let key = self.session_key();
 		let mut sessions = self.ctx.state.sessions.lock().unwrap();
-		sessions.entry(key).or_default().clone()
+		sessions
+			.entry(key)
+			.or_insert_with(|| self.new_session())
+			.clone()
 	}
 
 	fn update_session<F>(&self, f: F)
@@ -469,11 +1543,31 @@ impl<'a> UiControllerCore<'a> {
 		F: FnOnce(&mut UiClientSession),
 	{
 		let key = self.session_key();
+		*self.ctx.state.last_session.lock().unwrap() = Some(key.clone());
 		let mut sessions = self.ctx.state.sessions.lock().unwrap();
-		let entry = sessions.entry(key).or_default();
+		let entry = sessions.entry(key).or_insert_with(|| self.new_session());
 		f(entry);
 	}
 
+	/// Records a notification from an action on this client's page, see
+	/// [`UiServer::notify`].
+	fn notify(
+		&self,
+		severity: Severity,
+		title: impl Into<String>,
+		detail: impl std::fmt::Display,
+	) -> String {
+		let source = self.ctx.route().map(|route| route.path).unwrap_or_default();
+		self.ctx
+			.state
+			.server
+			.notify(severity, source, title, detail)
+	}
+
+	fn notify_error(&self, title: impl Into<String>, err: impl std::fmt::Display) -> String {
+		self.notify(Severity::Error, title, err)
+	}
+
 	fn authenticated_username(&self) -> Option<String> {
 		if let Some(session_id) = self.ctx.session_id() {
 			let hash = auth::token_hash(&session_id);
@@ -498,7 +1592,7 @@ fn search_sort_options() -> Vec<UiSelectOption> {
 		},
 		UiSelectOption {
 			value: String::from("name"),
-			name: String::from("Name"),
+			name: String::from("Name (natural)"),
 		},
 		UiSelectOption {
 			value: String::from("size"),
@@ -508,7 +1602,7 @@ fn search_sort_options() -> Vec<UiSelectOption> {
 }
 
 fn search_page_size_options() -> Vec<UiSelectOption> {
-	["25", "50", "100"]
+	PAGE_SIZES
 		.into_iter()
 		.map(|value| UiSelectOption {
 			value: value.to_string(),
@@ -517,378 +1611,1711 @@ fn search_page_size_options() -> Vec<UiSelectOption> {
 		.collect()
 }
 
-fn shared_folder_access_options() -> Vec<UiSelectOption> {
+fn peer_files_sort_options() -> Vec<UiSelectOption> {
+	DirSort::ALL
+		.into_iter()
+		.map(|sort| UiSelectOption {
+			value: sort.as_str().to_string(),
+			name: String::from(match sort {
+				DirSort::Name => "Name",
+				DirSort::Size => "Size",
+				DirSort::Modified => "Modified",
+				DirSort::Type => "Type",
+			}),
+		})
+		.collect()
+}
+
+fn prefs_theme_options() -> Vec<UiSelectOption> {
 	vec![
 		UiSelectOption {
-			value: String::from("read"),
-			name: String::from("Read"),
+			value: String::from(UiTheme::Dark.as_str()),
+			name: String::from("Dark"),
 		},
 		UiSelectOption {
-			value: String::from("write"),
-			name: String::from("Read/write"),
+			value: String::from(UiTheme::Light.as_str()),
+			name: String::from("Light"),
 		},
 	]
 }
 
-fn shared_folder_access_label(flags: u8) -> String {
-	if flags & FLAG_WRITE != 0 {
-		String::from("read/write/search")
-	} else {
-		String::from("read/search")
-	}
+/// Banner text for maintenance mode, shown on every page.
+fn maintenance_notice(maintenance: &Maintenance, now: chrono::DateTime<chrono::Utc>) -> String {
+	let expected = maintenance
+		.expected()
+		.map(|expected| format!(", expected to take {}", human_duration(expected)))
+		.unwrap_or_default();
+	format!(
+		"MAINTENANCE: {} (started {}{expected}). Nothing here can be changed until it ends; browsing and search still work.",
+		maintenance.reason,
+		relative_time(maintenance.since, now)
+	)
 }
 
-fn search_target_options(peers: &[PeerRow]) -> Vec<UiSelectOption> {
-	let mut options = vec![UiSelectOption {
-		value: String::from(SEARCH_ALL_DEVICES),
-		name: String::from("All devices"),
-	}];
-	options.extend(peers.iter().map(|peer| UiSelectOption {
-		value: peer.id.clone(),
-		name: if peer.local {
-			format!("{} (current)", peer.name)
-		} else {
-			peer.name.clone()
-		},
-	}));
-	options
-}
+/// Battery level offered for [`PowerPolicy::PauseBelow`] until one is set.
+const DEFAULT_LOW_BATTERY_PERCENT: u8 = 20;
 
-fn search_sort(value: &str) -> SearchSort {
-	match value {
-		"name" => SearchSort::Name,
-		"size" => SearchSort::Size,
-		_ => SearchSort::Latest,
+fn power_mode(policy: PowerPolicy) -> &'static str {
+	match policy {
+		PowerPolicy::Always => "always",
+		PowerPolicy::PauseOnBattery => "pause_on_battery",
+		PowerPolicy::PauseBelow { .. } => "pause_below",
 	}
 }
 
-fn search_page_size(value: &str) -> usize {
-	value.parse::<usize>().unwrap_or(50).clamp(1, 250)
-}
-
-fn reset_search_visible_count(session: &mut UiClientSession) {
-	session.search_visible_count = search_page_size(&session.search_page_size);
+fn power_mode_options() -> Vec<UiSelectOption> {
+	[
+		("always", "Always run"),
+		("pause_on_battery", "Pause on battery"),
+		("pause_below", "Pause when the battery is low"),
+	]
+	.into_iter()
+	.map(|(value, name)| UiSelectOption {
+		value: String::from(value),
+		name: String::from(name),
+	})
+	.collect()
 }
 
-fn search_row_device(raw: &UiSearchRawRow) -> String {
-	short_peer_id(&raw.peer_id)
+fn trust_level_options() -> Vec<UiSelectOption> {
+	TrustLevel::ALL
+		.into_iter()
+		.map(|trust| UiSelectOption {
+			value: String::from(trust.as_str()),
+			name: format!("{} {}", trust.icon(), trust.label()),
+		})
+		.collect()
 }
 
-fn search_row_to_ui(raw: UiSearchRawRow) -> UiSearchRow {
-	let device = search_row_device(&raw);
-	UiSearchRow {
-		name: raw.name,
-		path: raw.path,
-		size: format_size(raw.size),
-		replicas: String::from("Live result"),
-		peer_id: raw.peer_id.clone(),
-		device,
-		mime_type: raw.mime_type.unwrap_or_else(|| String::from("unknown")),
-		modified_at: raw.modified_at.unwrap_or_else(|| String::from("unknown")),
-	}
+fn prefs_font_scale_options() -> Vec<UiSelectOption> {
+	FONT_SCALES
+		.into_iter()
+		.map(|scale| UiSelectOption {
+			value: scale.to_string(),
+			name: format!("{scale}%"),
+		})
+		.collect()
 }
 
-fn rebuild_search_results(session: &mut UiClientSession) {
-	let page_size = search_page_size(&session.search_page_size);
-	let visible_count = session.search_visible_count.max(page_size);
-	let mut rows = session.search_raw_rows.clone();
-	match search_sort(&session.search_sort) {
-		SearchSort::Name => rows.sort_by(|left, right| {
-			left.name
-				.to_ascii_lowercase()
-				.cmp(&right.name.to_ascii_lowercase())
-		}),
-		SearchSort::Size => rows.sort_by_key(|row| std::cmp::Reverse(row.size)),
-		SearchSort::Latest => rows.sort_by(|left, right| {
-			right
-				.modified_at
-				.cmp(&left.modified_at)
-				.then_with(|| left.name.cmp(&right.name))
-		}),
-	}
-	session.search_visible_count = visible_count;
-	session.search_results = rows
+fn prefs_refresh_interval_options() -> Vec<UiSelectOption> {
+	REFRESH_INTERVALS
 		.into_iter()
-		.take(visible_count)
-		.map(search_row_to_ui)
-		.collect();
+		.map(|secs| UiSelectOption {
+			value: secs.to_string(),
+			name: if secs == 0 {
+				String::from("Off")
+			} else {
+				format!("Every {secs}s")
+			},
+		})
+		.collect()
 }
 
-fn short_peer_id(peer_id: &str) -> String {
-	const EDGE: usize = 10;
-	if peer_id.chars().count() <= EDGE * 2 + 1 {
-		return peer_id.to_string();
-	}
-	let start = peer_id.chars().take(EDGE).collect::<String>();
-	let end = peer_id
-		.chars()
-		.rev()
-		.take(EDGE)
-		.collect::<String>()
-		.chars()
-		.rev()
-		.collect::<String>();
-	format!("{start}...{end}")
-}
-
-fn audio_device_kind_label(kind: &AudioDeviceKind) -> &'static str {
-	match kind {
-		AudioDeviceKind::Sink => "Output",
-		AudioDeviceKind::Source => "Input",
+fn activity_draft(window: &ActivityWindow) -> UiActivityDraft {
+	// A window that was never set up starts in this node's timezone
+	// with every kind of heavy work selected.
+	let unset = window.allowed.is_empty();
+	let utc_offset = if unset {
+		chrono::Local::now().offset().local_minus_utc() / 60
+	} else {
+		window.utc_offset_minutes
+	};
+	UiActivityDraft {
+		ranges: window
+			.allowed
+			.iter()
+			.map(|range| range.to_string())
+			.collect::<Vec<_>>()
+			.join(", "),
+		utc_offset: format_utc_offset(utc_offset),
+		scans: unset || window.categories.contains(&ActivityKind::Scan),
+		updates: unset || window.categories.contains(&ActivityKind::Update),
+		syncs: unset || window.categories.contains(&ActivityKind::Sync),
+		hard_stop: window.hard_stop,
 	}
 }
 
-fn audio_device_line(device: &AudioDevice) -> String {
-	let muted = if device.muted { "muted" } else { "unmuted" };
-	let default = if device.is_default { "default " } else { "" };
-	format!(
-		"{} - {}{} | {}% | {}",
-		device.name,
-		default,
-		audio_device_kind_label(&device.kind),
-		device.volume,
-		muted
-	)
-}
-
-fn default_audio_output(devices: &[AudioDevice]) -> Option<&AudioDevice> {
-	devices
-		.iter()
-		.find(|device| device.is_default && matches!(device.kind, AudioDeviceKind::Sink))
-		.or_else(|| {
-			devices
-				.iter()
-				.find(|device| matches!(device.kind, AudioDeviceKind::Sink))
+fn activity_window_from_draft(draft: &UiActivityDraft) -> Result<ActivityWindow, String> {
+	let allowed = draft
+		.ranges
+		.split(',')
+		.map(str::trim)
+		.filter(|range| !range.is_empty())
+		.map(|range| {
+			TimeRange::parse(range)
+				.ok_or_else(|| format!("Invalid time range {range}; use HH:MM-HH:MM"))
+		})
+		.collect::<Result<Vec<_>, _>>()?;
+	let utc_offset_minutes = parse_utc_offset(&draft.utc_offset)
+		.ok_or_else(|| format!("Invalid UTC offset {}; use +HH:MM", draft.utc_offset))?;
+	let categories = ActivityKind::ALL
+		.into_iter()
+		.filter(|kind| match kind {
+			ActivityKind::Scan => draft.scans,
+			ActivityKind::Update => draft.updates,
+			ActivityKind::Sync => draft.syncs,
 		})
+		.collect();
+	Ok(ActivityWindow {
+		categories,
+		allowed,
+		utc_offset_minutes,
+		hard_stop: draft.hard_stop,
+	})
 }
 
-fn audio_capability_status(capability: Option<&AudioCapability>) -> String {
-	match capability {
-		Some(capability) => capability.message.clone(),
-		None => String::from("Audio capability not checked yet."),
-	}
+fn shared_folder_access_options() -> Vec<UiSelectOption> {
+	vec![
+		UiSelectOption {
+			value: String::from("read"),
+			name: String::from("Read"),
+		},
+		UiSelectOption {
+			value: String::from("write"),
+			name: String::from("Read/write"),
+		},
+		UiSelectOption {
+			value: String::from("preview"),
+			name: String::from("Previews only"),
+		},
+	]
 }
 
-fn audio_supported(capability: Option<&AudioCapability>) -> bool {
-	capability
-		.map(|capability| capability.supported)
-		.unwrap_or(false)
+/// Temporary grants can additionally queue the peer's writes for review.
+fn temporary_grant_access_options() -> Vec<UiSelectOption> {
+	let mut options = shared_folder_access_options();
+	options.insert(
+		2,
+		UiSelectOption {
+			value: String::from("review"),
+			name: String::from("Read/write, review writes"),
+		},
+	);
+	options
 }
 
-fn webcam_capability_status(capability: Option<&MediaCapability>) -> String {
-	match capability {
-		Some(capability) => capability.message.clone(),
-		None => String::from("Webcam capability not checked yet."),
-	}
+/// Badge for the access flags a peer reports this node holds.
+fn access_badge(flags: u8) -> String {
+	let badge = if flags & FLAG_WRITE != 0 && flags & FLAG_READ != 0 {
+		"read + write"
+	} else if flags & FLAG_WRITE != 0 {
+		"write only"
+	} else if flags & FLAG_READ != 0 {
+		"read-only"
+	} else if flags & FLAG_PREVIEW != 0 {
+		"preview only"
+	} else if flags & FLAG_SEARCH != 0 {
+		"search only"
+	} else {
+		"no access"
+	};
+	String::from(badge)
 }
 
-fn webcam_supported(capability: Option<&MediaCapability>) -> bool {
-	capability
-		.map(|capability| capability.supported)
-		.unwrap_or(false)
+/// Flags the grants fetched from a peer give this node on `path`, for peers
+/// whose listings don't report them. `None` when the grants don't tell,
+/// as with an owner grant that only the peer's shared folders bound.
+fn granted_flags(permissions: &[Permission], path: &str) -> Option<u8> {
+	if permissions
+		.iter()
+		.any(|permission| matches!(permission.rule(), Rule::Owner))
+	{
+		return None;
+	}
+	let folders = permissions
+		.iter()
+		.filter_map(|permission| match permission.rule() {
+			Rule::Folder(folder) => Some(folder),
+			_ => None,
+		});
+	effective_folder_rule(folders, Path::new(path)).map(|rule| rule.flags())
 }
 
-fn format_uptime(seconds: u64) -> String {
-	if seconds == 0 {
-		return String::from("unknown");
-	}
-	let days = seconds / 86_400;
-	let hours = (seconds % 86_400) / 3_600;
-	let minutes = (seconds % 3_600) / 60;
-	if days > 0 {
-		format!("{days}d {hours}h {minutes}m")
-	} else if hours > 0 {
-		format!("{hours}h {minutes}m")
+fn shared_folder_access_label(flags: u8) -> String {
+	if flags & FLAG_WRITE != 0 {
+		String::from("read/write/search")
+	} else if flags & FLAG_READ == 0 && flags & FLAG_PREVIEW != 0 {
+		String::from("preview/search")
 	} else {
-		format!("{minutes}m")
+		String::from("read/search")
 	}
 }
 
-fn normalize_peer_file_path(path: String) -> String {
-	let path = path.trim();
-	if path.is_empty() {
-		String::from("/")
-	} else {
-		path.to_string()
+/// `text` as a QR code, as an SVG data URL for an `Image`. Empty when it
+/// doesn't fit in one.
+fn qr_data_url(text: &str) -> String {
+	// Upper case fits the QR alphanumeric mode, which makes a smaller code;
+	// pairing strings decode in either case.
+	match qrcode::QrCode::new(text.to_ascii_uppercase()) {
+		Ok(code) => {
+			let svg = code
+				.render::<qrcode::render::svg::Color>()
+				.min_dimensions(220, 220)
+				.dark_color(qrcode::render::svg::Color("#020807"))
+				.light_color(qrcode::render::svg::Color("#ffffff"))
+				.build();
+			let encoded = base64::engine::general_purpose::STANDARD.encode(svg);
+			format!("data:image/svg+xml;base64,{encoded}")
+		}
+		Err(err) => {
+			tracing::warn!("failed to draw the pairing QR code: {err}");
+			String::new()
+		}
 	}
 }
 
-fn parent_peer_file_path(path: &str) -> Option<String> {
-	let normalized = normalize_peer_file_path(path.to_string());
-	if normalized == "/" {
-		return None;
-	}
-	let trimmed = normalized.trim_end_matches('/');
-	match trimmed.rsplit_once('/') {
-		Some(("", _)) => Some(String::from("/")),
-		Some((parent, _)) => Some(parent.to_string()),
-		None => Some(String::from("/")),
+fn pairing_string_note(info: &PairingInfo, now: chrono::DateTime<chrono::Utc>) -> String {
+	if info.expires_at <= now {
+		return String::from("Expired; show a new pairing string");
 	}
+	let expires_in = (info.expires_at - now).to_std().unwrap_or_default();
+	format!(
+		"Pairs one device, expires in {}. Reachable at {}",
+		human_duration(expires_in),
+		info.addrs.join(", ")
+	)
 }
 
-fn child_peer_file_path(path: &str, name: &str) -> String {
-	let normalized = normalize_peer_file_path(path.to_string());
-	if normalized == "/" {
-		format!("/{name}")
-	} else if normalized.ends_with('/') {
-		format!("{normalized}{name}")
-	} else {
-		format!("{normalized}/{name}")
+fn nearby_peer_row(peer: &PeerId, pairing: Option<&Pairing>) -> UiNearbyPeer {
+	let peer_id = peer.to_string();
+	let label = pairing
+		.map(|pairing| pairing.peer_name.clone())
+		.filter(|name| !name.is_empty())
+		.unwrap_or_else(|| String::from("Unnamed device"));
+	UiNearbyPeer {
+		label,
+		short_id: abbrev_peer_id(&peer_id),
+		code: pairing
+			.map(|pairing| format!("Code {}", pairing.code))
+			.unwrap_or_default(),
+		status: pairing.map(Pairing::describe).unwrap_or_default(),
+		can_pair: pairing
+			.is_none_or(|pairing| !pairing.is_pending() && pairing.status != PairingStatus::Paired),
+		can_confirm: pairing.is_some_and(|pairing| pairing.status == PairingStatus::AwaitingYou),
 	}
 }
 
-fn peer_files_href(peer_id: &str, path: &str) -> String {
-	if peer_id.is_empty() {
-		return String::from("/devices");
+fn search_target_options(peers: &[PeerRow]) -> Vec<UiSelectOption> {
+	let mut options = vec![UiSelectOption {
+		value: String::from(SEARCH_ALL_DEVICES),
+		name: String::from("All devices"),
+	}];
+	options.extend(peers.iter().map(|peer| UiSelectOption {
+		value: peer.id.clone(),
+		name: if peer.local {
+			format!("{} (current)", peer.name)
+		} else {
+			peer.name.clone()
+		},
+	}));
+	options
+}
+
+fn search_sort(value: &str) -> SearchSort {
+	match value {
+		"name" => SearchSort::Name,
+		"size" => SearchSort::Size,
+		_ => SearchSort::Latest,
 	}
-	let query = url::form_urlencoded::Serializer::new(String::new())
-		.append_pair("path", path)
-		.finish();
-	format!("/devices/{peer_id}/files?{query}")
 }
 
-fn peer_details_href(peer_id: &str) -> String {
-	if peer_id.is_empty() {
-		String::from("/devices")
-	} else {
-		format!("/devices/{peer_id}")
+fn search_page_size(value: &str) -> usize {
+	value.parse::<usize>().unwrap_or(50).clamp(1, 250)
+}
+
+fn search_row_device(raw: &UiSearchRawRow) -> String {
+	abbrev_peer_id(&raw.peer_id)
+}
+
+fn search_row_duration(duration_ms: Option<u64>) -> String {
+	duration_ms
+		.map(|ms| media_duration(std::time::Duration::from_millis(ms)))
+		.unwrap_or_default()
+}
+
+fn search_row_to_ui(raw: UiSearchRawRow) -> UiSearchRow {
+	let device = search_row_device(&raw);
+	UiSearchRow {
+		name: raw.name,
+		path: raw.path,
+		size: human_size(raw.size, SizeUnits::Binary),
+		replicas: String::from("Live result"),
+		peer_id: raw.peer_id.clone(),
+		device,
+		mime_type: raw.mime_type.unwrap_or_else(|| String::from("unknown")),
+		modified_at: raw.modified_at.unwrap_or_else(|| String::from("unknown")),
+		clock_warning: String::new(),
+		index_stale: String::new(),
+		duration: search_row_duration(raw.duration_ms),
+		deleted: String::new(),
+		provenance: String::new(),
+		provenance_open: false,
+		focused: false,
 	}
 }
 
-fn peer_control_href(peer_id: &str) -> String {
-	if peer_id.is_empty() {
-		String::from("/devices")
-	} else {
-		format!("/devices/{peer_id}/control")
+fn scoped_search_row(result: FileSearchResult, peer_id: &str, provenance: String) -> UiSearchRow {
+	UiSearchRow {
+		name: result.name,
+		path: result.path,
+		size: human_size(result.size, SizeUnits::Binary),
+		// Every empty file shares one hash; its count says nothing about
+		// copies of this one.
+		replicas: if result.size == 0 {
+			String::from("empty file")
+		} else {
+			format!("{} replica(s)", result.replicas)
+		},
+		peer_id: peer_id.to_string(),
+		device: abbrev_peer_id(peer_id),
+		mime_type: result.mime_type.unwrap_or_else(|| String::from("unknown")),
+		modified_at: result
+			.latest_datetime
+			.unwrap_or_else(|| String::from("unknown")),
+		clock_warning: String::new(),
+		index_stale: String::new(),
+		duration: search_row_duration(result.duration_ms),
+		deleted: result
+			.deleted_at
+			.and_then(|at| chrono::DateTime::from_timestamp(at, 0))
+			.map(|at| format!("deleted {}", at.format("%Y-%m-%d")))
+			.unwrap_or_default(),
+		provenance,
+		provenance_open: false,
+		focused: false,
 	}
 }
 
-fn peer_webcams_href(peer_id: &str) -> String {
-	if peer_id.is_empty() {
-		String::from("/devices")
+fn search_focus_key(row: &UiSearchRow) -> String {
+	format!("{}:{}", row.peer_id, row.path)
+}
+
+/// Status line for a scoped search, warning when the index for the peer
+/// is missing or older than [`STALE_INDEX_HOURS`].
+fn scoped_search_status(
+	found: usize,
+	total: usize,
+	indexed_at: Option<chrono::DateTime<chrono::Utc>>,
+	now: chrono::DateTime<chrono::Utc>,
+) -> String {
+	let found = if total > found {
+		format!("Showing {found} of {total} match(es)")
 	} else {
-		format!("/devices/{peer_id}/webcams")
+		format!("{total} match(es)")
+	};
+	match indexed_at {
+		None => format!("{found}; nothing from this device is indexed yet"),
+		Some(at) if now - at > chrono::Duration::hours(STALE_INDEX_HOURS) => {
+			format!(
+				"{found}; index for this device last updated {}",
+				relative_time(at, now)
+			)
+		}
+		Some(_) => found,
 	}
 }
 
-fn media_sessions_href(peer_id: &str) -> String {
-	if peer_id.is_empty() {
-		String::new()
+fn failed_login_line(group: &FailedLoginGroup, now: chrono::DateTime<chrono::Utc>) -> String {
+	let attempts = if group.count == 1 {
+		String::from("1 failed login")
 	} else {
-		format!("/api/peers/{peer_id}/media/sessions")
+		format!("{} failed logins", group.count)
+	};
+	format!(
+		"{attempts} for {} from {} in the last hour, latest {}",
+		group.username,
+		group.source_ip,
+		relative_time(group.last_at, now)
+	)
+}
+
+fn backup_draft(settings: &BackupSettings) -> UiBackupDraft {
+	UiBackupDraft {
+		scheduled: settings.scheduled,
+		hour: settings.hour.to_string(),
+		keep: settings.keep.to_string(),
+		dir: settings
+			.dir
+			.as_ref()
+			.map(|dir| dir.display().to_string())
+			.unwrap_or_default(),
 	}
 }
 
-fn control_key(idx: u32) -> Option<&'static str> {
-	match idx {
-		0 => Some("Return"),
-		1 => Some("Tab"),
-		2 => Some("BackSpace"),
-		3 => Some("Escape"),
-		4 => Some("Up"),
-		5 => Some("Down"),
-		6 => Some("Left"),
-		7 => Some("Right"),
-		_ => None,
+fn proxy_draft(settings: &HttpProxySettings) -> UiProxyDraft {
+	UiProxyDraft {
+		url: settings.url.clone().unwrap_or_default(),
+		username: settings
+			.credentials
+			.as_ref()
+			.map(|credentials| credentials.username.clone())
+			.unwrap_or_default(),
+		password: String::new(),
 	}
 }
 
-fn json_i32(payload: &wgui::serde_json::Value, key: &str) -> Option<i32> {
-	payload
-		.get(key)
-		.and_then(|value| value.as_i64())
-		.and_then(|value| i32::try_from(value).ok())
+fn backup_settings_from_draft(draft: &UiBackupDraft) -> Result<BackupSettings, String> {
+	let hour = draft
+		.hour
+		.trim()
+		.parse::<u8>()
+		.ok()
+		.filter(|hour| *hour <= 23)
+		.ok_or_else(|| String::from("Hour must be between 0 and 23"))?;
+	let keep = draft
+		.keep
+		.trim()
+		.parse::<usize>()
+		.ok()
+		.filter(|keep| *keep > 0)
+		.ok_or_else(|| String::from("Keep at least one backup"))?;
+	let dir = draft.dir.trim();
+	Ok(BackupSettings {
+		scheduled: draft.scheduled,
+		hour,
+		keep,
+		dir: (!dir.is_empty()).then(|| PathBuf::from(dir)),
+	})
 }
 
-fn json_mouse_button(payload: &wgui::serde_json::Value) -> MouseButton {
-	match payload
-		.get("button")
-		.and_then(|value| value.as_str())
-		.unwrap_or("left")
-	{
-		"middle" => MouseButton::Middle,
-		"right" => MouseButton::Right,
-		_ => MouseButton::Left,
+fn ingest_draft(settings: &IngestSettings) -> UiIngestDraft {
+	UiIngestDraft {
+		destination: settings.destination.clone(),
+		patterns: settings.patterns.join(", "),
+		delete_originals: settings.delete_originals,
+		eject_when_done: settings.eject_when_done,
 	}
 }
 
-fn cookie_value(headers: &HashMap<String, String>, name: &str) -> Option<String> {
-	let header = headers.get("cookie")?;
-	for part in header.split(';') {
-		let mut kv = part.trim().splitn(2, '=');
-		let (Some(key), Some(value)) = (kv.next(), kv.next()) else {
-			continue;
-		};
-		if key == name && !value.is_empty() {
-			return Some(value.to_string());
-		}
+fn ingest_settings_from_draft(draft: &UiIngestDraft) -> IngestSettings {
+	IngestSettings {
+		destination: draft.destination.trim().to_string(),
+		patterns: draft
+			.patterns
+			.split(',')
+			.map(str::trim)
+			.filter(|pattern| !pattern.is_empty())
+			.map(String::from)
+			.collect(),
+		delete_originals: draft.delete_originals,
+		eject_when_done: draft.eject_when_done,
 	}
-	None
 }
 
-fn clear_session_cookie() -> String {
-	format!("{SESSION_COOKIE}=; HttpOnly; Path=/; Max-Age=0; SameSite=Lax")
+fn integrity_draft(settings: &IntegritySettings) -> UiIntegrityDraft {
+	UiIntegrityDraft {
+		enabled: settings.enabled,
+		files_per_cycle: settings.files_per_cycle.to_string(),
+		read_rate: (settings.max_read_rate as f64 / (1024.0 * 1024.0)).to_string(),
+	}
 }
 
-fn generate_login_nonce() -> String {
-	let mut bytes = [0u8; 16];
-	OsRng.fill_bytes(&mut bytes);
-	bytes.iter().map(|b| format!("{b:02x}")).collect()
+fn integrity_settings_from_draft(draft: &UiIntegrityDraft) -> Result<IntegritySettings, String> {
+	let files_per_cycle = draft
+		.files_per_cycle
+		.trim()
+		.parse::<u32>()
+		.map_err(|_| String::from("Files per cycle must be a whole number"))?;
+	let read_rate = draft
+		.read_rate
+		.trim()
+		.parse::<f64>()
+		.ok()
+		.filter(|rate| rate.is_finite() && *rate > 0.0)
+		.ok_or_else(|| String::from("Read rate must be a number of MiB/s"))?;
+	Ok(IntegritySettings {
+		enabled: draft.enabled,
+		files_per_cycle,
+		max_read_rate: (read_rate * 1024.0 * 1024.0) as u64,
+	})
 }
 
-fn json_response(status: u16, value: serde_json::Value) -> HttpResponse {
-	HttpResponse::new(status, value.to_string()).header("content-type", "application/json")
+/// A line per damaged file followed by a line per copy it can be recovered
+/// from, each copy paired with the replica it opens.
+fn integrity_rows<'a>(
+	findings: &'a [IntegrityFinding],
+	local_node: &str,
+	now: chrono::DateTime<chrono::Utc>,
+) -> Vec<(UiIntegrityRow, Option<&'a IntegrityReplica>)> {
+	let mut rows = Vec::new();
+	for finding in findings {
+		let line = format!(
+			"{}: expected {}, read {} ({})",
+			finding.path.display(),
+			abbrev_hash(&finding.expected),
+			abbrev_hash(&finding.actual),
+			relative_time(finding.checked_at, now)
+		);
+		rows.push((
+			UiIntegrityRow {
+				line,
+				replica: false,
+			},
+			None,
+		));
+		if finding.replicas.is_empty() {
+			rows.push((
+				UiIntegrityRow {
+					line: String::from("No other copy is indexed"),
+					replica: false,
+				},
+				None,
+			));
+		}
+		for replica in &finding.replicas {
+			let node = hex(&replica.node_id);
+			let holder = if node == local_node {
+				String::from("this device")
+			} else if replica.node_name.is_empty() {
+				node
+			} else {
+				replica.node_name.clone()
+			};
+			rows.push((
+				UiIntegrityRow {
+					line: format!("Copy on {holder}: {}", replica.path.display()),
+					replica: true,
+				},
+				Some(replica),
+			));
+		}
+	}
+	rows
 }
 
-fn redirect_response(location: &str) -> HttpResponse {
-	HttpResponse::new(303, Vec::new()).header("location", location)
+fn config_snapshot_row(
+	snapshot: &ConfigSnapshotInfo,
+	now: chrono::DateTime<chrono::Utc>,
+) -> UiConfigSnapshotRow {
+	UiConfigSnapshotRow {
+		summary: snapshot.summary(),
+		detail: format!(
+			"#{} {}, {}",
+			snapshot.id,
+			snapshot.reason,
+			relative_time(snapshot.taken_at, now)
+		),
+	}
 }
 
-fn favicon_response() -> HttpResponse {
-	HttpResponse::new(200, FAVICON_ICO.to_vec())
-		.header("content-type", "image/x-icon")
-		.header("cache-control", "public, max-age=86400")
+/// What a rollback changed, for the status line.
+fn rollback_summary(rollback: &ConfigRollback) -> String {
+	let mut summary = if rollback.changes.is_empty() {
+		format!(
+			"Rolled back to snapshot #{}; nothing had changed",
+			rollback.snapshot_id
+		)
+	} else {
+		format!(
+			"Rolled back to snapshot #{}: {}",
+			rollback.snapshot_id,
+			rollback.changes.join("; ")
+		)
+	};
+	if !rollback.skipped.is_empty() {
+		summary.push_str(&format!(
+			". Skipped {} that no longer exist",
+			rollback.skipped.join(", ")
+		));
+	}
+	summary
 }
 
-fn session_cookie(token: &str) -> String {
+fn backup_run_line(run: &BackupRun, now: chrono::DateTime<chrono::Utc>) -> String {
+	let what = match (run.kind, run.scheduled) {
+		(BackupKind::Restore, _) => "Restored from",
+		(BackupKind::Backup, true) => "Scheduled backup",
+		(BackupKind::Backup, false) => "Backup",
+	};
+	let outcome = match (&run.error, run.size_bytes) {
+		(Some(err), _) => format!("failed: {err}"),
+		(None, Some(size)) => human_size(size, SizeUnits::Binary),
+		(None, None) => String::from("ok"),
+	};
 	format!(
-		"{}={}; HttpOnly; Path=/; Max-Age={}; SameSite=Lax",
-		SESSION_COOKIE, token, SESSION_TTL_SECS
+		"{what} {} {}, {outcome}",
+		run.path,
+		relative_time(run.finished_at, now)
 	)
 }
 
-impl UiControllerCore<'_> {
-	pub(super) fn state(&self) -> UiViewState {
-		let state = self.block_on(self.ctx.state.server.snapshot());
-		let session = self.current_session();
-		let authenticated_username = self.authenticated_username();
-		let search_targets = search_target_options(&state.peers);
-		let peers = state
-			.peers
-			.into_iter()
-			.map(|peer| UiPeer {
-				id: peer.id.clone(),
-				short_id: short_peer_id(&peer.id),
-				label: if peer.local {
-					format!("{} (you)", peer.name)
+/// One of this node's addresses with its last dial-back result; the
+/// tester is named when it is a known peer.
+fn reachability_row(
+	result: &AddressReachability,
+	peers: &[PeerRow],
+	now: chrono::DateTime<chrono::Utc>,
+) -> UiReachability {
+	let color = match result.status {
+		Reachability::Reachable => "#4cff91",
+		Reachability::Unreachable => "#ff8a8a",
+		Reachability::Untested => "#f2c879",
+	};
+	let tester = result.tested_by.as_deref().map(|id| {
+		peers
+			.iter()
+			.find(|peer| peer.id == id)
+			.map(|peer| peer.name.clone())
+			.unwrap_or_else(|| abbrev_peer_id(id))
+	});
+	let mut detail = match (result.status, &tester) {
+		(Reachability::Reachable, Some(tester)) => match result.latency_ms {
+			Some(latency_ms) => format!("reached by {tester} in {latency_ms} ms"),
+			None => format!("reached by {tester}"),
+		},
+		(Reachability::Unreachable, Some(tester)) => format!(
+			"{tester} could not connect: {}",
+			result.error.as_deref().unwrap_or("unknown error")
+		),
+		_ => result
+			.error
+			.clone()
+			.unwrap_or_else(|| String::from("not tested yet")),
+	};
+	if let Some(tested_at) = result.tested_at {
+		detail.push_str(&format!(", {}", relative_time(tested_at, now)));
+	}
+	UiReachability {
+		addr: result.addr.clone(),
+		status: result.status.label().to_uppercase(),
+		color: color.to_string(),
+		detail,
+	}
+}
+
+fn protocol_row(rate: &ProtocolRate, peers: &[PeerRow]) -> UiProtocolRow {
+	let counts = &rate.last_minute;
+	let failed = counts.errors + counts.denials + counts.rate_limited;
+	UiProtocolRow {
+		peer: peers
+			.iter()
+			.find(|peer| peer.id == rate.peer)
+			.map(|peer| peer.name.clone())
+			.unwrap_or_else(|| abbrev_peer_id(&rate.peer)),
+		request: rate.request.clone(),
+		requests: format!("{}/min", counts.requests),
+		traffic: format!(
+			"{} in, {} out",
+			human_size(counts.bytes_in, SizeUnits::Binary),
+			human_size(counts.bytes_out, SizeUnits::Binary)
+		),
+		failed: if counts.rate_limited > 0 {
+			format!("{failed} failed, {} limited", counts.rate_limited)
+		} else {
+			format!("{failed} failed")
+		},
+		color: String::from(if rate.limited { "#ff8a8a" } else { "#d6eee9" }),
+	}
+}
+
+fn diagnostic_row(diagnostic: &Diagnostic) -> UiDiagnostic {
+	let color = match diagnostic.status {
+		DiagnosticStatus::Pass => "#4cff91",
+		DiagnosticStatus::Warn => "#f2c879",
+		DiagnosticStatus::Fail => "#ff8a8a",
+	};
+	UiDiagnostic {
+		status: diagnostic.status.label().to_uppercase(),
+		color: color.to_string(),
+		check: diagnostic.check.clone(),
+		detail: diagnostic.detail.clone(),
+		hint: diagnostic.hint.clone().unwrap_or_default(),
+	}
+}
+
+fn transfer_line(transfer: &Transfer, now: chrono::DateTime<chrono::Utc>) -> String {
+	let (what, direction) = match transfer.direction {
+		TransferDirection::Download => ("Downloaded", "from"),
+		TransferDirection::Upload => ("Uploaded", "to"),
+	};
+	let outcome = match (&transfer.status, &transfer.error) {
+		(TransferStatus::Failed, Some(err)) => format!("failed: {err}"),
+		(TransferStatus::Failed, None) => String::from("failed"),
+		(TransferStatus::Completed, _) => format!(
+			"{} in {}",
+			human_size(transfer.size, SizeUnits::Binary),
+			human_duration(std::time::Duration::from_millis(transfer.duration_ms))
+		),
+	};
+	let outcome = match &transfer.note {
+		Some(note) => format!("{outcome}, {note}"),
+		None => outcome,
+	};
+	format!(
+		"{what} {} {direction} {} as {} {}, {outcome}",
+		transfer.remote_path,
+		abbrev_peer_id(&transfer.peer),
+		transfer.local_path,
+		relative_time(transfer.finished_at, now)
+	)
+}
+
+fn review_row(
+	review: &PendingReview,
+	peer_name: &str,
+	selected: bool,
+	now: chrono::DateTime<chrono::Utc>,
+) -> UiReviewRow {
+	let hash = review
+		.hash
+		.as_deref()
+		.map(|hash| format!(", blake3 {}", &hex(hash)[..12]))
+		.unwrap_or_default();
+	UiReviewRow {
+		label: format!("{} from {peer_name}", review.path),
+		detail: format!(
+			"{}, written {}{hash}",
+			human_size(review.size, SizeUnits::Binary),
+			relative_time(review.received_at, now)
+		),
+		selected,
+	}
+}
+
+fn delete_request_row(
+	proposal: &ReceivedProposal,
+	proposer: &str,
+	now: chrono::DateTime<chrono::Utc>,
+) -> UiDeletionRow {
+	UiDeletionRow {
+		label: format!("{proposer} asks to delete {}", proposal.subject()),
+		reason: proposal.reason.clone(),
+		detail: format!(
+			"{}; asked {}, expires {}",
+			proposal.paths_hint.join(", "),
+			relative_time(proposal.received_at, now),
+			relative_time(proposal.expires_at, now)
+		),
+	}
+}
+
+fn delete_proposal_row(
+	proposal: &DeleteProposal,
+	name: impl Fn(&str) -> String,
+	now: chrono::DateTime<chrono::Utc>,
+) -> UiDeletionRow {
+	let asked = relative_time(proposal.created_at, now);
+	UiDeletionRow {
+		label: format!("Delete {} everywhere, proposed {asked}", proposal.subject()),
+		reason: proposal.reason.clone(),
+		detail: proposal.describe(name),
+	}
+}
+
+/// The temporary limit an access alert offers, e.g. `1.00 MiB/s for 1h 00m`.
+fn traffic_limit_label(limits: &AccessAlertLimits) -> String {
+	format!(
+		"{}/s for {}",
+		human_size(limits.limit_bytes_per_sec, SizeUnits::Binary),
+		human_duration(std::time::Duration::from_secs(
+			limits.limit_minutes.saturating_mul(60)
+		))
+	)
+}
+
+fn notification_row(
+	notification: &AppNotification,
+	peers: &[PeerRow],
+	now: chrono::DateTime<chrono::Utc>,
+) -> UiNotificationRow {
+	let source = peers
+		.iter()
+		.find(|peer| peer.id == notification.source)
+		.map(|peer| peer.name.clone())
+		.unwrap_or_else(|| notification.source.clone());
+	let mut meta = format!(
+		"{} from {source}, {}",
+		notification.severity.label(),
+		relative_time(notification.at, now)
+	);
+	if notification.count > 1 {
+		meta.push_str(&format!(" (×{})", notification.count));
+	}
+	UiNotificationRow {
+		title: notification.title.clone(),
+		detail: notification.detail.clone(),
+		meta,
+		copy: UiCopyProps {
+			text: notification.message(),
+		},
+		color: String::from(match notification.severity {
+			Severity::Info => "#8fb8b0",
+			Severity::Warning => "#f2c879",
+			Severity::Error => "#ff8a8a",
+		}),
+	}
+}
+
+fn pin_row(pin: &PinStatus, now: chrono::DateTime<chrono::Utc>) -> UiPinRow {
+	let synced = match pin.last_sync_at {
+		Some(at) => format!("synced {}", relative_time(at, now)),
+		None => String::from("not synced yet"),
+	};
+	let status = if pin.paused {
+		format!("Paused, {synced}")
+	} else if pin.syncing {
+		format!("Syncing, {} file(s) left", pin.files_pending)
+	} else if pin.files_pending > 0 {
+		format!("{} file(s) pending, {synced}", pin.files_pending)
+	} else if pin.last_sync_at.is_some() {
+		format!("Up to date, {synced}")
+	} else {
+		String::from("Waiting for the first sync")
+	};
+	UiPinRow {
+		label: format!(
+			"{} on {} to {}",
+			pin.remote_path,
+			abbrev_peer_id(&pin.peer),
+			pin.local_dest
+		),
+		status: format!(
+			"{status}, {} transferred",
+			human_size(pin.bytes_transferred, SizeUnits::Binary)
+		),
+		error: pin.last_error.clone().unwrap_or_default(),
+		has_error: pin.last_error.is_some(),
+		paused: pin.paused,
+	}
+}
+
+/// Peers an outbox can send to, for the destination picker.
+fn outbox_peer_options(peers: &[PeerRow]) -> Vec<UiSelectOption> {
+	peers
+		.iter()
+		.map(|peer| UiSelectOption {
+			value: peer.id.clone(),
+			name: if peer.local {
+				format!("{} (current)", peer.name)
+			} else {
+				peer.name.clone()
+			},
+		})
+		.collect()
+}
+
+fn outbox_row(
+	rule: &OutboxStatus,
+	peers: &[UiSelectOption],
+	now: chrono::DateTime<chrono::Utc>,
+) -> UiOutboxRow {
+	let peer = peers
+		.iter()
+		.find(|option| option.value == rule.dest_peer)
+		.map_or_else(
+			|| abbrev_peer_id(&rule.dest_peer),
+			|option| option.name.clone(),
+		);
+	let files = if rule.pattern.is_empty() {
+		String::from("all files")
+	} else {
+		rule.pattern.clone()
+	};
+	let sent = match (&rule.last_sent_file, rule.last_sent_at) {
+		(Some(file), Some(at)) => format!("last sent {file} {}", relative_time(at, now)),
+		_ => String::from("nothing sent yet"),
+	};
+	let state = if rule.paused {
+		String::from("Paused")
+	} else if rule.pending > 0 {
+		format!("{} file(s) waiting", rule.pending)
+	} else {
+		String::from("Watching")
+	};
+	let after = if rule.delete_after_send {
+		"deleted once verified"
+	} else {
+		"moved to sent"
+	};
+	UiOutboxRow {
+		label: format!(
+			"{} ({files}) to {} on {peer}, {after}",
+			rule.watch_path, rule.dest_path
+		),
+		status: format!("{state}, {sent}, {} sent in total", rule.files_sent),
+		error: rule.last_error.clone().unwrap_or_default(),
+		has_error: rule.last_error.is_some(),
+		paused: rule.paused,
+	}
+}
+
+fn previous_download_notice(transfer: &Transfer) -> String {
+	format!(
+		"You already downloaded this on {} to {}",
+		human_timestamp(transfer.finished_at, TimestampStyle::Absolute),
+		transfer.local_path
+	)
+}
+
+fn diff_summary(diff: &FileDiff) -> String {
+	match diff {
+		FileDiff::Text(text) if text.hunks.is_empty() => String::from("The files are identical."),
+		FileDiff::Text(text) => format!(
+			"{} line(s) added and {} removed in {} hunk(s).",
+			text.added,
+			text.removed,
+			text.hunks.len()
+		),
+		FileDiff::Binary(binary) => {
+			let first = match binary.first_difference {
+				Some(offset) => format!("First difference at byte {offset}."),
+				None if binary.size_a != binary.size_b => {
+					String::from("The compared bytes match, but the sizes differ.")
+				}
+				None => String::from("The compared bytes are identical."),
+			};
+			let mut summary = format!(
+				"{} vs {}. {first} {} of {} blocks differ ({:.1}%).",
+				human_size(binary.size_a, SizeUnits::Binary),
+				human_size(binary.size_b, SizeUnits::Binary),
+				binary.differing_blocks,
+				binary.compared_blocks,
+				binary.differing_percent
+			);
+			if let Some(note) = &binary.note {
+				summary.push(' ');
+				summary.push_str(note);
+			}
+			summary
+		}
+	}
+}
+
+fn diff_lines(diff: &FileDiff) -> Vec<UiDiffLine> {
+	let FileDiff::Text(text) = diff else {
+		return Vec::new();
+	};
+	let line = |text: String, color: &str| UiDiffLine {
+		text,
+		color: color.to_string(),
+	};
+	let mut lines = Vec::new();
+	for hunk in &text.hunks {
+		lines.push(line(hunk.header(), "#8fbab1"));
+		for diff_line in &hunk.lines {
+			lines.push(match diff_line.kind {
+				DiffLineKind::Context => line(format!(" {}", diff_line.text), "#d6eee9"),
+				DiffLineKind::Added => line(format!("+{}", diff_line.text), "#79f2c0"),
+				DiffLineKind::Removed => line(format!("-{}", diff_line.text), "#f27979"),
+			});
+		}
+	}
+	lines
+}
+
+fn audio_device_kind_label(kind: &AudioDeviceKind) -> &'static str {
+	match kind {
+		AudioDeviceKind::Sink => "Output",
+		AudioDeviceKind::Source => "Input",
+	}
+}
+
+fn audio_device_line(device: &AudioDevice) -> String {
+	let muted = if device.muted { "muted" } else { "unmuted" };
+	let default = if device.is_default { "default " } else { "" };
+	format!(
+		"{} - {}{} | {}% | {}",
+		device.name,
+		default,
+		audio_device_kind_label(&device.kind),
+		device.volume,
+		muted
+	)
+}
+
+fn default_audio_output(devices: &[AudioDevice]) -> Option<&AudioDevice> {
+	devices
+		.iter()
+		.find(|device| device.is_default && matches!(device.kind, AudioDeviceKind::Sink))
+		.or_else(|| {
+			devices
+				.iter()
+				.find(|device| matches!(device.kind, AudioDeviceKind::Sink))
+		})
+}
+
+fn audio_capability_status(capability: Option<&AudioCapability>) -> String {
+	match capability {
+		Some(capability) => capability.message.clone(),
+		None => String::from("Audio capability not checked yet."),
+	}
+}
+
+fn audio_supported(capability: Option<&AudioCapability>) -> bool {
+	capability
+		.map(|capability| capability.supported)
+		.unwrap_or(false)
+}
+
+fn webcam_capability_status(capability: Option<&MediaCapability>) -> String {
+	match capability {
+		Some(capability) => capability.message.clone(),
+		None => String::from("Webcam capability not checked yet."),
+	}
+}
+
+fn webcam_supported(capability: Option<&MediaCapability>) -> bool {
+	capability
+		.map(|capability| capability.supported)
+		.unwrap_or(false)
+}
+
+/// Unknown capabilities (handshake still pending) leave actions enabled.
+fn peer_supports(capabilities: Option<&PeerCapabilities>, feature: &str) -> bool {
+	capabilities
+		.map(|capabilities| capabilities.supports(feature))
+		.unwrap_or(true)
+}
+
+fn capability_notice(capabilities: Option<&PeerCapabilities>) -> String {
+	match capabilities {
+		Some(capabilities) if capabilities.protocol_version < PROTOCOL_VERSION => format!(
+			"peer runs protocol v{}, some features unavailable",
+			capabilities.protocol_version
+		),
+		_ => String::new(),
+	}
+}
+
+fn connection_summary(connections: &[Connection], idle: bool) -> String {
+	let inbound = connections
+		.iter()
+		.filter(|connection| connection.direction == ConnectionDirection::Inbound)
+		.count();
+	let outbound = connections.len() - inbound;
+	match connections.len() {
+		0 if idle => String::from("Idle, reconnects when used"),
+		0 => String::from("Not connected"),
+		1 => format!("1 connection ({inbound} in / {outbound} out)"),
+		count => format!("{count} connections ({inbound} in / {outbound} out)"),
+	}
+}
+
+fn connection_stability(recent_drops: usize) -> String {
+	match recent_drops {
+		0 => String::from("No drops in the last hour"),
+		1 => String::from("1 drop in the last hour"),
+		drops => format!("{drops} drops in the last hour"),
+	}
+}
+
+fn connection_line(connection: &Connection, now: chrono::DateTime<chrono::Utc>) -> String {
+	let arrow = match connection.direction {
+		ConnectionDirection::Outbound => "→ out",
+		ConnectionDirection::Inbound => "← in",
+	};
+	format!(
+		"{arrow} {} {} · {}",
+		connection.transport(),
+		connection.remote_addr,
+		relative_time(connection.connected_at, now)
+	)
+}
+
+fn unknown_peer_info() -> PeerInfo {
+	PeerInfo {
+		version: String::from("unknown"),
+		os: String::from("unknown"),
+		uptime_seconds: 0,
+	}
+}
+
+fn uptime_label(seconds: u64) -> String {
+	if seconds == 0 {
+		return String::from("unknown");
+	}
+	human_duration(std::time::Duration::from_secs(seconds))
+}
+
+/// An empty path is the roots view listing the peer's drives and shares.
+fn normalize_peer_file_path(path: &str) -> Result<String, PathError> {
+	let path = path.trim();
+	if path.is_empty() {
+		return Ok(String::new());
+	}
+	SafePath::parse(path, PathStyle::detect(path.as_bytes())).map(|path| path.to_string())
+}
+
+fn peer_path_style(windows: bool) -> PathStyle {
+	if windows {
+		PathStyle::Windows
+	} else {
+		PathStyle::Unix
+	}
+}
+
+/// Parent directory using the *target* peer's path rules. Filesystem roots
+/// lead back to the roots view (`""`).
+fn parent_peer_file_path(path: &str, windows: bool) -> Option<String> {
+	if path.trim().is_empty() {
+		return None;
+	}
+	let parent = SafePath::parse(path, peer_path_style(windows))
+		.ok()
+		.and_then(|path| path.parent());
+	Some(parent.map(|parent| parent.to_string()).unwrap_or_default())
+}
+
+/// `None` when `name` can't be a name on the target peer.
+fn child_peer_file_path(path: &str, name: &str, windows: bool) -> Option<String> {
+	SafePath::parse(path, peer_path_style(windows))
+		.and_then(|dir| dir.join(name))
+		.map(|path| path.to_string())
+		.ok()
+}
+
+fn child_peer_file_raw_path(path: &str, name_raw: &[u8], windows: bool) -> Option<Vec<u8>> {
+	let dir = SafePath::parse(path, peer_path_style(windows)).ok()?;
+	let separator = if windows { b'\\' } else { b'/' };
+	let mut raw = dir.as_bytes().to_vec();
+	if !raw.ends_with(&[separator]) {
+		raw.push(separator);
+	}
+	raw.extend_from_slice(name_raw);
+	Some(raw)
+}
+
+fn peer_files_href(peer_id: &str, path: &str) -> String {
+	if peer_id.is_empty() {
+		return String::from("/devices");
+	}
+	if path.is_empty() {
+		return format!("/devices/{peer_id}/files");
+	}
+	let query = url::form_urlencoded::Serializer::new(String::new())
+		.append_pair("path", path)
+		.finish();
+	format!("/devices/{peer_id}/files?{query}")
+}
+
+/// Cards for the folders `peer_id` shares with this node: its summaries,
+/// or the folders of its grants when it can't give them.
+fn peer_share_cards(state: &UiState, peer_id: &str) -> Vec<ShareCard> {
+	let summaries = state
+		.peer_shares
+		.as_ref()
+		.filter(|(shares_peer, _)| shares_peer == peer_id)
+		.and_then(|(_, summaries)| summaries.as_deref());
+	let grants = state
+		.peer_grants
+		.as_ref()
+		.filter(|(grants_peer, _)| grants_peer == peer_id)
+		.map(|(_, grants)| grants.permissions.as_slice())
+		.unwrap_or_default();
+	share_cards(summaries, grants)
+}
+
+fn peer_details_href(peer_id: &str) -> String {
+	if peer_id.is_empty() {
+		String::from("/devices")
+	} else {
+		format!("/devices/{peer_id}")
+	}
+}
+
+/// Peers the timeline can be narrowed to, in the order of its chips.
+fn timeline_peers(peers: &[PeerRow]) -> Vec<&PeerRow> {
+	peers.iter().filter(|peer| !peer.local).collect()
+}
+
+fn peer_label(peers: &[PeerRow], id: &str) -> String {
+	peers
+		.iter()
+		.find(|peer| peer.id == id)
+		.map(|peer| peer.name.clone())
+		.unwrap_or_else(|| abbrev_peer_id(id))
+}
+
+/// Page with the details of `event`. Scans open on the storage page with
+/// their run selected, see [`UiControllerCore::open_activity`].
+fn activity_href(event: &ActivityEvent) -> Option<String> {
+	match event.kind {
+		ActivityEventKind::Scan => event.ref_id.as_ref().map(|_| String::from("/storage")),
+		ActivityEventKind::Transfer => Some(String::from("/jobs")),
+		ActivityEventKind::Permission | ActivityEventKind::Peer => {
+			event.peer.as_deref().map(peer_details_href)
+		}
+		ActivityEventKind::Deletion => Some(String::from("/review")),
+	}
+}
+
+fn timeline_row(
+	event: &ActivityEvent,
+	peers: &[PeerRow],
+	now: chrono::DateTime<chrono::Utc>,
+) -> UiTimelineRow {
+	let line = if event.count > 1 {
+		format!("{} (×{})", event.summary, event.count)
+	} else {
+		event.summary.clone()
+	};
+	let detail = match &event.peer {
+		Some(peer) => format!(
+			"{}, {}",
+			peer_label(peers, peer),
+			relative_time(event.at, now)
+		),
+		None => relative_time(event.at, now),
+	};
+	UiTimelineRow {
+		icon: event.kind.icon().to_string(),
+		line,
+		detail,
+		linked: activity_href(event).is_some(),
+	}
+}
+
+fn permission_rule_row(
+	idx: usize,
+	rule: &RuleDraft,
+	editor: &PermissionEditorSession,
+) -> UiPermissionRuleRow {
+	let (label, access) = match rule.kind {
+		DraftKind::Owner => (String::from("Full access"), String::from("Owner")),
+		DraftKind::Inbox => (String::from("Inbox"), String::from("May drop files")),
+		DraftKind::Folder => {
+			let path = rule.path.trim();
+			let flags = DraftFlag::ALL
+				.into_iter()
+				.filter(|flag| flag.is_set(rule))
+				.map(DraftFlag::label)
+				.collect::<Vec<_>>();
+			(
+				if path.is_empty() {
+					String::from("New folder")
 				} else {
-					peer.name
+					path.to_string()
 				},
-				node_kind: if peer.local {
-					String::from("Control node")
+				if flags.is_empty() {
+					String::from("No access")
 				} else {
-					String::from("Mesh device")
+					flags.join(", ")
 				},
-				local: peer.local,
-				status: if peer.local {
-					String::from("LOCAL")
+			)
+		}
+	};
+	let access = if rule.paired {
+		format!("{access}, granted by pairing")
+	} else {
+		access
+	};
+	let patterns = |patterns: &[String]| {
+		patterns
+			.iter()
+			.map(|pattern| pattern.trim())
+			.filter(|pattern| !pattern.is_empty())
+			.collect::<Vec<_>>()
+			.join(", ")
+	};
+	let access = match patterns(&rule.deny_patterns) {
+		denied if denied.is_empty() => access,
+		denied => format!("{access}, hiding {denied}"),
+	};
+	let access = match patterns(&rule.allow_patterns) {
+		allowed if allowed.is_empty() => access,
+		allowed => format!("{access}, only {allowed}"),
+	};
+	let detail = match rule
+		.expires_at
+		.and_then(|at| chrono::DateTime::from_timestamp(at, 0))
+	{
+		Some(at) => format!("{access}, until {}", at.format("%Y-%m-%d %H:%M")),
+		None => access,
+	};
+	UiPermissionRuleRow {
+		label,
+		detail,
+		selected: editor
+			.selected()
+			.is_some_and(|(selected, _)| selected == idx),
+		errors: editor.errors_for(
+			idx,
+			&[
+				"kind",
+				"path",
+				"read",
+				"quarantine",
+				"deny_patterns",
+				"allow_patterns",
+				"expires_at",
+			],
+		),
+	}
+}
+
+fn peer_control_href(peer_id: &str) -> String {
+	if peer_id.is_empty() {
+		String::from("/devices")
+	} else {
+		format!("/devices/{peer_id}/control")
+	}
+}
+
+fn peer_webcams_href(peer_id: &str) -> String {
+	if peer_id.is_empty() {
+		String::from("/devices")
+	} else {
+		format!("/devices/{peer_id}/webcams")
+	}
+}
+
+fn peer_permissions_href(peer_id: &str) -> String {
+	if peer_id.is_empty() {
+		String::from("/devices")
+	} else {
+		format!("/devices/{peer_id}/permissions")
+	}
+}
+
+fn peer_shell_href(peer_id: &str) -> String {
+	if peer_id.is_empty() {
+		String::from("/devices")
+	} else {
+		format!("/devices/{peer_id}/shell")
+	}
+}
+
+fn media_sessions_href(peer_id: &str) -> String {
+	if peer_id.is_empty() {
+		String::new()
+	} else {
+		format!("/api/peers/{peer_id}/media/sessions")
+	}
+}
+
+fn control_key(idx: u32) -> Option<&'static str> {
+	match idx {
+		0 => Some("Return"),
+		1 => Some("Tab"),
+		2 => Some("BackSpace"),
+		3 => Some("Escape"),
+		4 => Some("Up"),
+		5 => Some("Down"),
+		6 => Some("Left"),
+		7 => Some("Right"),
+		_ => None,
+	}
+}
+
+fn json_focus_key(payload: &wgui::serde_json::Value) -> Option<Key> {
+	payload
+		.get("key")
+		.and_then(|value| value.as_str())
+		.and_then(Key::parse)
+}
+
+fn json_i32(payload: &wgui::serde_json::Value, key: &str) -> Option<i32> {
+	payload
+		.get(key)
+		.and_then(|value| value.as_i64())
+		.and_then(|value| i32::try_from(value).ok())
+}
+
+fn json_mouse_button(payload: &wgui::serde_json::Value) -> MouseButton {
+	match payload
+		.get("button")
+		.and_then(|value| value.as_str())
+		.unwrap_or("left")
+	{
+		"middle" => MouseButton::Middle,
+		"right" => MouseButton::Right,
+		_ => MouseButton::Left,
+	}
+}
+
+fn cookie_value(headers: &HashMap<String, String>, name: &str) -> Option<String> {
+	let header = headers.get("cookie")?;
+	for part in header.split(';') {
+		let mut kv = part.trim().splitn(2, '=');
+		let (Some(key), Some(value)) = (kv.next(), kv.next()) else {
+			continue;
+		};
+		if key == name && !value.is_empty() {
+			return Some(value.to_string());
+		}
+	}
+	None
+}
+
+fn clear_session_cookie() -> String {
+	format!("{SESSION_COOKIE}=; HttpOnly; Path=/; Max-Age=0; SameSite=Lax")
+}
+
+fn generate_login_nonce() -> String {
+	let mut bytes = [0u8; 16];
+	OsRng.fill_bytes(&mut bytes);
+	bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn json_response(status: u16, value: serde_json::Value) -> HttpResponse {
+	HttpResponse::new(status, value.to_string()).header("content-type", "application/json")
+}
+
+fn redirect_response(location: &str) -> HttpResponse {
+	HttpResponse::new(303, Vec::new()).header("location", location)
+}
+
+fn favicon_response() -> HttpResponse {
+	HttpResponse::new(200, FAVICON_ICO.to_vec())
+		.header("content-type", "image/x-icon")
+		.header("cache-control", "public, max-age=86400")
+}
+
+fn session_cookie(token: &str) -> String {
+	format!(
+		"{}={}; HttpOnly; Path=/; Max-Age={}; SameSite=Lax",
+		SESSION_COOKIE, token, SESSION_TTL_SECS
+	)
+}
+
+/// Applies what a shell printed, if anything, to one client's session and
+/// re-renders it when there is something new to show; returns what the
+/// poller does next.
+type PeerShellUpdate = Arc<dyn Fn(Option<PeerShellMsg>) -> ShellPoll + Send + Sync>;
+
+/// Reads the output of a client's shell until its session ends, and keeps
+/// rendering the client while output it printed waits to be shown.
+fn spawn_shell_poller(
+	puppy: Arc<PuppyNet>,
+	update: PeerShellUpdate,
+	peer: PeerId,
+	session_id: u64,
+) {
+	tokio::spawn(async move {
+		let mut next = update(None);
+		while next != ShellPoll::Stop {
+			tokio::time::sleep(SHELL_POLL_INTERVAL).await;
+			let msg = match next {
+				ShellPoll::Read => Some(
+					match puppy.shell_input(peer, session_id, Vec::new()).await {
+						Ok(output) => PeerShellMsg::from_output(session_id, output),
+						Err(err) => PeerShellMsg::Lost {
+							session_id,
+							error: format!("Shell disconnected: {err}"),
+						},
+					},
+				),
+				ShellPoll::Render | ShellPoll::Stop => None,
+			};
+			next = update(msg);
+		}
+	});
+}
+
+/// Applies a thumbnail result to one client's session, returning the
+/// fetches to start next.
+type ThumbnailUpdate = Arc<dyn Fn(ThumbnailMsg) -> Vec<ThumbnailFetch> + Send + Sync>;
+
+/// Fetches one thumbnail of a browsed folder, then starts whatever the
+/// session queued behind it.
+fn spawn_thumbnail_fetch(puppy: Arc<PuppyNet>, update: ThumbnailUpdate, fetch: ThumbnailFetch) {
+	tokio::spawn(async move {
+		let thumbnail = match PeerId::from_str(&fetch.peer_id) {
+			Ok(peer) => puppy
+				.get_thumbnail(
+					peer,
+					fetch.path.clone(),
+					PEER_FILE_THUMBNAIL_SIZE,
+					PEER_FILE_THUMBNAIL_SIZE,
+				)
+				.await
+				.inspect_err(|err| tracing::debug!("no thumbnail for {}: {err}", fetch.path)),
+			Err(err) => Err(err.into()),
+		};
+		let too_large = thumbnail
+			.as_ref()
+			.is_err_and(|err| err.is::<ImageTooLarge>());
+		let thumbnail = thumbnail.ok().map(|thumbnail| {
+			let encoded = base64::engine::general_purpose::STANDARD.encode(thumbnail.data);
+			format!("data:{};base64,{encoded}", thumbnail.mime_type)
+		});
+		let next = update(ThumbnailMsg::Loaded {
+			generation: fetch.generation,
+			name: fetch.name,
+			thumbnail,
+			too_large,
+		});
+		for fetch in next {
+			spawn_thumbnail_fetch(Arc::clone(&puppy), Arc::clone(&update), fetch);
+		}
+	});
+}
+
+impl UiControllerCore<'_> {
+	/// Registers this client for re-renders while jobs make progress.
+	fn watch_jobs(&self) {
+		let Some(client_id) = self.ctx.client_id() else {
+			return;
+		};
+		let route = self
+			.ctx
+			.route()
+			.map(|route| route.path)
+			.unwrap_or_else(|| String::from("/"));
+		let ctx = Arc::downgrade(self.ctx);
+		let mut watchers = self.ctx.state.job_watchers.lock().unwrap();
+		watchers
+			.entry(client_id.to_string())
+			.or_insert_with(|| JobWatcher {
+				route: route.clone(),
+				push: Arc::new(move |route: String| {
+					let Some(ctx) = ctx.upgrade() else {
+						return false;
+					};
+					ctx.push_state_for_client(client_id, route);
+					true
+				}),
+			})
+			.route = route;
+	}
+
+	pub(super) fn state(&self) -> UiViewState {
+		self.watch_jobs();
+		self.block_on(self.ctx.state.server.sync_notifications());
+		let state = self.block_on(self.ctx.state.server.snapshot());
+		let session = self.current_session();
+		let authenticated_username = self.authenticated_username();
+		let search_targets = search_target_options(&state.peers);
+		let outbox_peers = outbox_peer_options(&state.peers);
+		let now = chrono::Utc::now();
+		let share_peer_id = state.selected_peer.clone().unwrap_or_default();
+		let peer_shares_degraded = state
+			.peer_shares
+			.as_ref()
+			.is_some_and(|(peer_id, summaries)| *peer_id == share_peer_id && summaries.is_none());
+		let peer_shares = peer_share_cards(&state, &share_peer_id)
+			.into_iter()
+			.map(|card| UiShareCard {
+				summary: card
+					.summary
+					.as_ref()
+					.map(|summary| summary.describe(now))
+					.unwrap_or_else(|| String::from("Shared with you")),
+				subdirs: card
+					.summary
+					.map(|summary| summary.subdirs.join(", "))
+					.unwrap_or_default(),
+				browse_href: peer_files_href(&share_peer_id, &card.root),
+				root: card.root,
+			})
+			.collect::<Vec<_>>();
+		let selected_row = state
+			.peers
+			.iter()
+			.find(|peer| !peer.local && state.selected_peer.as_deref() == Some(peer.id.as_str()));
+		let peer_connections = selected_row
+			.iter()
+			.flat_map(|peer| peer.connections.iter())
+			.map(|connection| connection_line(connection, now))
+			.collect::<Vec<_>>();
+		let peer_idle = selected_row.is_some_and(|peer| peer.idle);
+		let peer_important = selected_row.is_some_and(|peer| peer.important);
+		let kept_connected = state
+			.peers
+			.iter()
+			.filter(|peer| peer.important)
+			.map(|peer| peer.name.as_str())
+			.collect::<Vec<_>>();
+		let kept_connected = if kept_connected.is_empty() {
+			String::from("No peers are kept connected. Turn it on from a peer's page.")
+		} else {
+			format!("Kept connected: {}", kept_connected.join(", "))
+		};
+		let clock_skews = state
+			.peers
+			.iter()
+			.filter_map(|peer| Some((peer.id.clone(), peer.clock_skew.clone()?)))
+			.collect::<HashMap<_, _>>();
+		let clock_warning = |peer_id: &str| clock_skews.get(peer_id).cloned().unwrap_or_default();
+		let peer_clock_skew = state
+			.selected_peer
+			.as_deref()
+			.map(clock_warning)
+			.unwrap_or_default();
+		let index_stales = state
+			.peers
+			.iter()
+			.filter_map(|peer| Some((peer.id.clone(), peer.index_stale.clone()?)))
+			.collect::<HashMap<_, _>>();
+		let index_stale = |peer_id: &str| index_stales.get(peer_id).cloned().unwrap_or_default();
+		let peer_index_stale = state
+			.selected_peer
+			.as_deref()
+			.map(index_stale)
+			.unwrap_or_default();
+		let peer_names = state
+			.peers
+			.iter()
+			.map(|peer| (peer.id.as_str(), peer.name.as_str()))
+			.collect::<HashMap<_, _>>();
+		let reviews = state
+			.reviews
+			.iter()
+			.map(|review| {
+				let short_id = abbrev_peer_id(&review.peer);
+				let peer_name = peer_names
+					.get(review.peer.as_str())
+					.copied()
+					.unwrap_or(&short_id);
+				review_row(
+					review,
+					peer_name,
+					session.review.is_selected(review.id),
+					now,
+				)
+			})
+			.collect::<Vec<_>>();
+		let peer_name = |peer: &str| {
+			peer_names
+				.get(peer)
+				.map(|name| name.to_string())
+				.unwrap_or_else(|| abbrev_peer_id(peer))
+		};
+		let delete_requests = state
+			.delete_requests
+			.iter()
+			.map(|proposal| delete_request_row(proposal, &peer_name(&proposal.proposer), now))
+			.collect::<Vec<_>>();
+		let delete_proposals = state
+			.delete_proposals
+			.iter()
+			.map(|proposal| delete_proposal_row(proposal, peer_name, now))
+			.collect::<Vec<_>>();
+		let peers = state
+			.peers
+			.into_iter()
+			.map(|peer| UiPeer {
+				focused: session.peers_focus.is_focused(&peer.id),
+				id: peer.id.clone(),
+				short_id: abbrev_peer_id(&peer.id),
+				label: if peer.local {
+					format!("{} (you)", peer.name)
+				} else {
+					peer.name
+				},
+				node_kind: if peer.local {
+					String::from("Control node")
 				} else {
+					String::from("Mesh device")
+				},
+				local: peer.local,
+				status: if peer.local {
+					String::from("LOCAL")
+				} else if !peer.connections.is_empty() {
 					String::from("ONLINE")
+				} else if peer.idle {
+					String::from("IDLE")
+				} else {
+					String::from("OFFLINE")
 				},
 				status_color: if peer.local {
 					String::from("#7bdcff")
-				} else {
+				} else if !peer.connections.is_empty() {
 					String::from("#4cff91")
+				} else if peer.idle {
+					String::from("#9fbdb6")
+				} else {
+					String::from("#ff8a8a")
 				},
 				os: peer.os,
 				uptime: peer.uptime,
@@ -898,6 +3325,49 @@ impl UiControllerCore<'_> {
 				} else {
 					String::from("active")
 				},
+				connections: if peer.local {
+					String::new()
+				} else {
+					connection_summary(&peer.connections, peer.idle)
+				},
+				stability: if peer.local {
+					String::new()
+				} else {
+					connection_stability(peer.recent_drops)
+				},
+				clock_skew: peer.clock_skew.unwrap_or_default(),
+				trust_icon: if peer.local {
+					String::new()
+				} else {
+					String::from(peer.trust.icon())
+				},
+				trust_label: if peer.local {
+					String::new()
+				} else {
+					format!("{} trust", peer.trust.label())
+				},
+			})
+			.collect::<Vec<_>>();
+		let share_wizard = session.share_wizard.clone().unwrap_or_default();
+		let new_user_password_hint = if session.new_user_modal_open {
+			self.ctx.state.server.puppy.password_policy().describe()
+		} else {
+			String::new()
+		};
+		let share_wizard_peers = peers
+			.iter()
+			.filter(|peer| !peer.local)
+			.map(|peer| UiShareWizardPeer {
+				label: format!("{} ({})", peer.label, peer.short_id),
+				selected: share_wizard.peers.contains(&peer.id),
+			})
+			.collect::<Vec<_>>();
+		let share_wizard_results = share_wizard
+			.results
+			.iter()
+			.map(|(line, color)| UiShareWizardResult {
+				line: line.clone(),
+				color: String::from(*color),
 			})
 			.collect::<Vec<_>>();
 		let cpus = state
@@ -907,6 +3377,34 @@ impl UiControllerCore<'_> {
 				line: format!("{} - {:.1}% | {} Hz", cpu.name, cpu.usage, cpu.frequency_hz),
 			})
 			.collect::<Vec<_>>();
+		let temporary_grants = state
+			.peer_temporary_grants
+			.into_iter()
+			.filter(|grant| grant.expires_at > now)
+			.map(|grant| UiTemporaryGrant {
+				id: grant.id,
+				line: format!(
+					"{} ({})",
+					grant.rule.path().display(),
+					shared_folder_access_label(grant.rule.flags())
+				),
+				countdown: format!("expires in {}", human_duration(grant.remaining(now))),
+			})
+			.collect::<Vec<_>>();
+		let disks = state
+			.peer_disks
+			.into_iter()
+			.map(|(disk, samples)| UiDisk {
+				line: format!(
+					"{} - {} free of {}",
+					disk.mount_path,
+					human_size(disk.available_space, SizeUnits::Binary),
+					human_size(disk.total_space, SizeUnits::Binary)
+				),
+				trend: disk_sparkline(&samples, DISK_SPARKLINE_WIDTH),
+				forecast: disk_forecast(&disk.mount_path, &samples),
+			})
+			.collect::<Vec<_>>();
 		let interfaces = state
 			.peer_interfaces
 			.into_iter()
@@ -938,6 +3436,19 @@ impl UiControllerCore<'_> {
 			.zip(state.local_peer_id.as_deref())
 			.map(|(selected, local)| selected == local)
 			.unwrap_or(false);
+		let local_folders = state
+			.local_folders
+			.iter()
+			.map(|folder| UiFolderChip {
+				label: folder.label.clone(),
+				path: folder.path.clone(),
+			})
+			.collect::<Vec<_>>();
+		let shell_supported = peer_supports(state.peer_capabilities.as_ref(), FEATURE_SHELL);
+		let update_supported = peer_supports(state.peer_capabilities.as_ref(), FEATURE_UPDATE);
+		let restart_supported = peer_supports(state.peer_capabilities.as_ref(), FEATURE_RESTART);
+		let inbox_supported = peer_supports(state.peer_capabilities.as_ref(), FEATURE_INBOX);
+		let capability_notice = capability_notice(state.peer_capabilities.as_ref());
 		let is_audio_supported = audio_supported(state.peer_audio_capability.as_ref());
 		let audio_capability_status = audio_capability_status(state.peer_audio_capability.as_ref());
 		let audio_devices = state
@@ -1021,46 +3532,269 @@ impl UiControllerCore<'_> {
 			.into_iter()
 			.take(20)
 			.map(|entry| UiFileRow {
-				hash: format_hash(&entry.hash),
-				line: format!("{} - {} bytes", format_hash(&entry.hash), entry.size),
-			})
-			.collect::<Vec<_>>();
-		let peer_files = state
-			.peer_files
-			.iter()
-			.map(|entry| UiPeerFileRow {
-				name: entry.name.clone(),
-				summary: if entry.is_dir {
-					String::from("Directory")
-				} else {
-					let kind = entry
-						.mime
-						.clone()
-						.or_else(|| entry.extension.clone())
-						.unwrap_or_else(|| String::from("File"));
-					format!("{kind} - {}", format_size(entry.size))
-				},
-				href: peer_files_href(
-					state.selected_peer.as_deref().unwrap_or_default(),
-					&child_peer_file_path(&state.peer_files_path, &entry.name),
-				),
-				is_dir: entry.is_dir,
+				hash: hex(&entry.hash),
+				line: format!("{} - {} bytes", hex(&entry.hash), entry.size),
+				in_store: state.files_in_store.contains(&entry.hash),
 			})
 			.collect::<Vec<_>>();
-		let peer_files_parent_href = state
+		let selected_peer_id = state.selected_peer.as_deref().unwrap_or_default();
+		let peer_roots = state
+			.peer_roots
+			.as_ref()
+			.filter(|(peer_id, _)| peer_id == selected_peer_id)
+			.map(|(_, roots)| roots);
+		let peer_windows = peer_roots.is_some_and(|roots| roots.windows);
+		let peer_folders = state
+			.peer_folders
+			.as_ref()
+			.filter(|(peer_id, _)| peer_id == selected_peer_id)
+			.map(|(_, folders)| folders.as_slice())
+			.unwrap_or_default();
+		let peer_grants = state
+			.peer_grants
+			.as_ref()
+			.filter(|(peer_id, _)| peer_id == selected_peer_id)
+			.map(|(_, grants)| grants);
+		let peer_grants_status = if peer_grants.is_some_and(|grants| grants.revalidating) {
+			String::from("Revalidating shared folders...")
+		} else {
+			String::new()
+		};
+		let peer_files = if state.peer_files_path.is_empty() {
+			let roots = peer_roots
+				.map(|roots| roots.roots.as_slice())
+				.unwrap_or_default();
+			// Granted folders show even while the peer's roots can't be
+			// fetched, since the grants may come from the cache.
+			let granted = peer_grants
+				.map(|grants| grants.permissions.as_slice())
+				.unwrap_or_default()
+				.iter()
+				.filter_map(|permission| match permission.rule() {
+					Rule::Folder(folder) => Some(folder.path().to_string_lossy().into_owned()),
+					_ => None,
+				})
+				.filter(|path| !roots.iter().any(|root| root.path == *path))
+				.collect::<BTreeSet<_>>()
+				.into_iter()
+				.map(|path| UiPeerFileRow {
+					name: path.clone(),
+					undecodable: false,
+					summary: String::from("Shared with you"),
+					href: peer_files_href(selected_peer_id, &path),
+					is_dir: true,
+					highlighted: false,
+					focused: false,
+					pinned: false,
+					can_pin: false,
+					thumbnail: String::new(),
+					thumbnail_loading: false,
+					thumbnail_too_large: false,
+					rule: String::new(),
+				});
+			let quick_access = peer_folders
+				.iter()
+				.filter(|folder| folder.kind != FolderKind::Drive)
+				.filter(|folder| !roots.iter().any(|root| root.path == folder.path))
+				.map(|folder| UiPeerFileRow {
+					name: folder.label.clone(),
+					undecodable: false,
+					summary: format!("Quick access - {}", folder.path),
+					href: peer_files_href(selected_peer_id, &folder.path),
+					is_dir: true,
+					highlighted: false,
+					focused: false,
+					pinned: false,
+					can_pin: false,
+					thumbnail: String::new(),
+					thumbnail_loading: false,
+					thumbnail_too_large: false,
+					rule: String::new(),
+				});
+			roots
+				.iter()
+				.map(|root| UiPeerFileRow {
+					name: root.label.clone(),
+					undecodable: false,
+					summary: {
+						let kind = match root.kind {
+							BrowseRootKind::Disk => "Disk",
+							BrowseRootKind::SharedFolder => "Shared folder",
+						};
+						match root.flags {
+							Some(flags) => format!("{kind} - {}", access_badge(flags)),
+							None => String::from(kind),
+						}
+					},
+					href: peer_files_href(selected_peer_id, &root.path),
+					is_dir: true,
+					highlighted: false,
+					focused: false,
+					pinned: false,
+					can_pin: false,
+					thumbnail: String::new(),
+					thumbnail_loading: false,
+					thumbnail_too_large: false,
+					rule: String::new(),
+				})
+				.chain(granted)
+				.chain(quick_access)
+				.collect::<Vec<_>>()
+		} else {
+			let thumbnails = state
+				.peer_files_view
+				.thumbnails
+				.then_some(&session.peer_files.thumbnails);
+			state.peer_files[session.peer_files.rendered(&state.peer_files)]
+				.iter()
+				.map(|entry| {
+					let path =
+						child_peer_file_path(&state.peer_files_path, &entry.name, peer_windows);
+					let rule = state
+						.peer_files_access
+						.iter()
+						.flat_map(|access| &access.entries)
+						.find(|access| access.name == entry.name)
+						.map(|access| {
+							format!("◆ own rule {}: {}", access.rule, access_badge(access.flags))
+						})
+						.unwrap_or_default();
+					let pinned = entry.is_dir
+						&& state.pins.iter().any(|pin| {
+							pin.peer == selected_peer_id
+								&& path.as_ref().is_some_and(|path| pin.remote_path == *path)
+						});
+					UiPeerFileRow {
+						name: entry.name.clone(),
+						undecodable: entry.has_undecodable_name(),
+						summary: if entry.is_dir {
+							String::from("Directory")
+						} else {
+							let kind = match (&entry.mime, entry.mime_source) {
+								(Some(mime), MimeSource::Extension) => mime.clone(),
+								(Some(mime), MimeSource::Index) => format!("{mime} (from index)"),
+								(Some(mime), MimeSource::Sniffed) => format!("{mime} (sniffed)"),
+								(None, _) => entry
+									.extension
+									.clone()
+									.unwrap_or_else(|| String::from("File")),
+							};
+							format!(
+								"{kind} - {}",
+								entry_size(entry, session.peer_files.disk_sizes)
+							)
+						},
+						href: path
+							.as_deref()
+							.map(|path| peer_files_href(selected_peer_id, path))
+							.unwrap_or_default(),
+						is_dir: entry.is_dir,
+						highlighted: !entry.is_dir && entry.name == session.peer_files.highlight,
+						focused: false,
+						pinned,
+						can_pin: entry.is_dir
+							&& !pinned && !entry.has_undecodable_name()
+							&& safe_entry_name(&entry.name).is_some(),
+						thumbnail: thumbnails
+							.and_then(|batch| batch.thumbnail(&entry.name))
+							.unwrap_or_default()
+							.to_string(),
+						thumbnail_loading: thumbnails
+							.is_some_and(|batch| batch.is_loading(&entry.name)),
+						thumbnail_too_large: thumbnails
+							.is_some_and(|batch| batch.is_too_large(&entry.name)),
+						rule,
+					}
+				})
+				.collect::<Vec<_>>()
+		}
+		.into_iter()
+		.map(|row| UiPeerFileRow {
+			focused: session.peer_files_focus.is_focused(&row.href),
+			..row
+		})
+		.collect::<Vec<_>>();
+		// Peers that predate reporting access fall back to their grants.
+		let peer_files_access = if state.peer_files_path.is_empty() {
+			None
+		} else if let Some(access) = &state.peer_files_access {
+			Some(access.flags)
+		} else {
+			peer_grants
+				.and_then(|grants| granted_flags(&grants.permissions, &state.peer_files_path))
+		}
+		.map(access_badge)
+		.unwrap_or_default();
+		let peer_files_more = if state.peer_files_path.is_empty() {
+			String::new()
+		} else {
+			session.peer_files.more_label(&state.peer_files)
+		};
+		let peer_files_sort = if state.peer_files_path.is_empty() {
+			String::new()
+		} else {
+			state.peer_files_view.sort.as_str().to_string()
+		};
+		let peer_files_access_request = state
+			.peer_files_denied
+			.as_ref()
+			.filter(|_| session.peer_files.access_request_open)
+			.map(|denied| {
+				denied.access_request(state.local_peer_id.as_deref().unwrap_or("this device"))
+			})
+			.unwrap_or_default();
+		let peer_files_search = session.peer_files.search_for(selected_peer_id);
+		let peer_files_search_scope = peer_files_search.map(|search| {
+			if search.path.is_empty() {
+				format!("{} › everywhere", abbrev_peer_id(&search.peer_id))
+			} else {
+				format!("{} › {}", abbrev_peer_id(&search.peer_id), search.path)
+			}
+		});
+		let peer_files_search_status = peer_files_search
+			.map(|search| search.status.clone())
+			.unwrap_or_default();
+		let peer_files_search_results = peer_files_search
+			.map(|search| {
+				search
+					.results
+					.iter()
+					.map(|row| UiSearchRow {
+						clock_warning: clock_warning(&row.peer_id),
+						index_stale: index_stale(&row.peer_id),
+						..row.clone()
+					})
+					.collect::<Vec<_>>()
+			})
+			.unwrap_or_default();
+		let at_browse_root = peer_roots.is_some_and(|roots| {
+			roots
+				.roots
+				.iter()
+				.any(|root| root.path == state.peer_files_path)
+		});
+		let peer_files_parent = if at_browse_root {
+			Some(String::new())
+		} else {
+			parent_peer_file_path(&state.peer_files_path, peer_windows)
+		};
+		let peer_files_parent_href = state
 			.selected_peer
 			.as_deref()
-			.zip(parent_peer_file_path(&state.peer_files_path))
+			.zip(peer_files_parent)
 			.map(|(peer_id, parent)| peer_files_href(peer_id, &parent))
 			.unwrap_or_default();
 		let selected_peer_details_href =
 			peer_details_href(state.selected_peer.as_deref().unwrap_or_default());
 		let selected_peer_control_href =
 			peer_control_href(state.selected_peer.as_deref().unwrap_or_default());
-		let selected_peer_files_href =
-			peer_files_href(state.selected_peer.as_deref().unwrap_or_default(), "/");
+		let selected_peer_files_href = peer_files_href(selected_peer_id, "");
 		let selected_peer_webcams_href =
 			peer_webcams_href(state.selected_peer.as_deref().unwrap_or_default());
+		let selected_peer_permissions_href =
+			peer_permissions_href(state.selected_peer.as_deref().unwrap_or_default());
+		let selected_peer_shell_href =
+			peer_shell_href(state.selected_peer.as_deref().unwrap_or_default());
 		let media_sessions_endpoint = state
 			.selected_peer
 			.as_deref()
@@ -1086,40 +3820,461 @@ impl UiControllerCore<'_> {
 					"{} - {} | {}",
 					entry.node_name,
 					entry.path,
-					format_size(entry.size),
+					human_size(entry.size, SizeUnits::Binary),
 				),
 			})
 			.collect::<Vec<_>>();
+		let scan_history = state
+			.scan_runs
+			.iter()
+			.map(|run| UiScanRunRow {
+				line: scan_run_line(run),
+				status: scan_run_status_label(run),
+				selected: session.scan_diff_runs.contains(&run.id),
+			})
+			.collect::<Vec<_>>();
+		let scan_trends = state
+			.scan_trends
+			.iter()
+			.map(|trend| UiStorageRow {
+				line: scan_trend_line(trend),
+			})
+			.collect::<Vec<_>>();
+		let growth = state.growth.as_ref();
+		let growth_rows = growth
+			.map(|report| session.growth.sorted(report.directories.clone()))
+			.unwrap_or_default()
+			.iter()
+			.map(growth_row)
+			.collect::<Vec<_>>();
+		let growth_projections = growth
+			.map(|report| {
+				report
+					.disks
+					.iter()
+					.map(|disk| UiStorageRow {
+						line: disk_projection_line(disk, report.low_space_percent),
+					})
+					.collect::<Vec<_>>()
+			})
+			.unwrap_or_default();
+		let treemap = session.treemap.layout();
+		let treemap_props = UiTreemapProps {
+			aspect_ratio: TREEMAP_WIDTH / TREEMAP_HEIGHT,
+			tiles: treemap.tiles.iter().map(treemap_tile).collect(),
+		};
+		let treemap_trail = treemap
+			.trail
+			.iter()
+			.map(|(name, _)| UiStorageRow { line: name.clone() })
+			.collect::<Vec<_>>();
+		let treemap_node_options = state
+			.storage_trees
+			.iter()
+			.map(|tree| UiSelectOption {
+				value: hex(&tree.node_id),
+				name: tree.node_name.clone(),
+			})
+			.collect::<Vec<_>>();
+		let scan_diff_rows = session
+			.scan_diff_lines
+			.iter()
+			.map(|line| UiStorageRow { line: line.clone() })
+			.collect::<Vec<_>>();
+		let (removable_drives, ingest) = match &state.page {
+			Page::Storage => {
+				let puppy = &self.ctx.state.server.puppy;
+				let drives = puppy
+					.removable_drives()
+					.iter()
+					.map(|drive| UiRemovableDrive {
+						line: describe_drive(&drive.disk),
+						action: String::from(if drive.interrupted {
+							"Resume ingest"
+						} else {
+							"Ingest"
+						}),
+					})
+					.collect::<Vec<_>>();
+				let draft = session
+					.ingest_draft
+					.clone()
+					.unwrap_or_else(|| ingest_draft(&puppy.ingest_settings()));
+				(drives, draft)
+			}
+			_ => (Vec::new(), UiIngestDraft::default()),
+		};
+		let (integrity_summary, integrity_rows, integrity) = match &state.page {
+			Page::Storage => {
+				let puppy = &self.ctx.state.server.puppy;
+				let summary = match puppy.integrity_status() {
+					Ok(status) => status.summary(),
+					Err(err) => format!("Integrity status unavailable: {err:#}"),
+				};
+				let local_node = state
+					.local_peer_id
+					.as_deref()
+					.map(peer_to_node_id_hex)
+					.unwrap_or_default();
+				let rows = match puppy.integrity_findings() {
+					Ok(findings) => integrity_rows(&findings, &local_node, now)
+						.into_iter()
+						.map(|(row, _)| row)
+						.collect(),
+					Err(err) => {
+						tracing::warn!("failed to load integrity findings: {err:#}");
+						Vec::new()
+					}
+				};
+				let draft = session
+					.integrity_draft
+					.clone()
+					.unwrap_or_else(|| integrity_draft(&puppy.integrity_settings()));
+				(summary, rows, draft)
+			}
+			_ => (String::new(), Vec::new(), UiIntegrityDraft::default()),
+		};
+		let active_jobs = self.ctx.state.jobs.active_count();
+		let update_job = session
+			.update_job
+			.and_then(|id| self.ctx.state.jobs.job(id));
+		let restart_job = session
+			.restart_job
+			.and_then(|id| self.ctx.state.jobs.job(id));
+		let wake_job = session.wake_job.and_then(|id| self.ctx.state.jobs.job(id));
+		let remote_scan_job = session
+			.remote_scan_job
+			.and_then(|id| self.ctx.state.jobs.job(id));
+		let jobs = self
+			.ctx
+			.state
+			.jobs
+			.jobs()
+			.iter()
+			.map(job_row)
+			.collect::<Vec<_>>();
+		let thumbnail_queue = self.ctx.state.server.puppy.thumbnail_queue_status();
 		let shared_folders = state.shared_folders;
 		let users = state.users;
+		let failed_logins = state
+			.failed_logins
+			.iter()
+			.map(|group| failed_login_line(group, now))
+			.collect::<Vec<_>>();
+		let backup_runs = state
+			.backup_runs
+			.iter()
+			.map(|run| backup_run_line(run, now))
+			.collect::<Vec<_>>();
+		let config_snapshots = state
+			.config_snapshots
+			.iter()
+			.map(|snapshot| config_snapshot_row(snapshot, now))
+			.collect::<Vec<_>>();
+		let undo_notice = self.ctx.state.server.puppy.undoable_change().map(|change| {
+			format!(
+				"Changed {}: {}",
+				relative_time(change.taken_at, now),
+				change.reason.trim_start_matches("before ")
+			)
+		});
+		let reachability = state
+			.reachability
+			.iter()
+			.map(|result| reachability_row(result, &state.peers, now))
+			.collect::<Vec<_>>();
+		let diagnostics = session
+			.diagnostics
+			.iter()
+			.flat_map(|report| report.checks.iter().map(diagnostic_row))
+			.collect::<Vec<_>>();
+		let protocol_limits = self.ctx.state.server.puppy.protocol_limits();
+		let connection_policy = self.ctx.state.server.puppy.connection_policy();
+		let power = self.ctx.state.server.puppy.power_state();
+		let maintenance = self.ctx.state.server.puppy.maintenance();
+		let power_policy = self.ctx.state.server.puppy.power_policy();
+		let power_reading = if power.run_anyway {
+			format!(
+				"Power: {}. Background work runs anyway until it is plugged in.",
+				power.label()
+			)
+		} else {
+			format!("Power: {}.", power.label())
+		};
+		let protocol_rows = session
+			.protocol
+			.sorted(self.ctx.state.server.puppy.protocol_stats())
+			.iter()
+			.map(|rate| protocol_row(rate, &state.peers))
+			.collect::<Vec<_>>();
+		let reachability_hint = if state.reachability.is_empty() {
+			String::new()
+		} else if port_mapping_worthwhile(&state.reachability) {
+			String::from("No public address was reachable; port mapping may help.")
+		} else {
+			String::from("A public address is reachable; port mapping is not needed.")
+		};
+		let transfers = state
+			.transfers
+			.iter()
+			.map(|transfer| transfer_line(transfer, now))
+			.collect::<Vec<_>>();
+		let pins = state
+			.pins
+			.iter()
+			.map(|pin| pin_row(pin, now))
+			.collect::<Vec<_>>();
+		let outboxes = state
+			.outboxes
+			.iter()
+			.map(|rule| outbox_row(rule, &outbox_peers, now))
+			.collect::<Vec<_>>();
+		let outbox_draft = session.outbox_draft.clone();
+		let review_selection = session.review.selection(&state.reviews).len();
+		let timeline_kinds = ActivityEventKind::ALL
+			.iter()
+			.map(|&kind| UiFilterChip {
+				label: kind.label().to_string(),
+				selected: session.timeline.shows_kind(kind),
+			})
+			.collect::<Vec<_>>();
+		let timeline_peers = timeline_peers(&state.peers)
+			.into_iter()
+			.map(|peer| UiFilterChip {
+				label: peer_label(&state.peers, &peer.id),
+				selected: session.timeline.shows_peer(&peer.id),
+			})
+			.collect::<Vec<_>>();
+		let timeline_events = session
+			.timeline
+			.events()
+			.iter()
+			.map(|event| timeline_row(event, &state.peers, now))
+			.collect::<Vec<_>>();
+		let editor = &session.permission_editor;
+		let permission_rules = editor
+			.draft()
+			.rules
+			.iter()
+			.enumerate()
+			.map(|(idx, rule)| permission_rule_row(idx, rule, editor))
+			.collect::<Vec<_>>();
+		let selected_folder = editor
+			.selected()
+			.filter(|(_, rule)| rule.kind == DraftKind::Folder);
+		let permission_flags = selected_folder
+			.map(|(_, rule)| {
+				DraftFlag::ALL
+					.into_iter()
+					.map(|flag| UiFilterChip {
+						label: flag.label().to_string(),
+						selected: flag.is_set(rule),
+					})
+					.collect::<Vec<_>>()
+			})
+			.unwrap_or_default();
+		let (unseen_notifications, toasts, notifications) = {
+			let center = self.ctx.state.server.notifications.lock().unwrap();
+			let toasts = center
+				.toasts(now)
+				.iter()
+				.map(|notification| notification_row(notification, &state.peers, now))
+				.collect::<Vec<_>>();
+			let notifications = center
+				.recent()
+				.map(|notification| notification_row(notification, &state.peers, now))
+				.collect::<Vec<_>>();
+			(center.unseen_count(), toasts, notifications)
+		};
+		let pending_reviews = self
+			.ctx
+			.state
+			.server
+			.puppy
+			.pending_review_count()
+			.unwrap_or_else(|err| {
+				tracing::warn!("failed to count pending reviews: {err}");
+				0
+			});
+		let pending_deletions = self
+			.ctx
+			.state
+			.server
+			.puppy
+			.pending_delete_proposal_count()
+			.unwrap_or_else(|err| {
+				tracing::warn!("failed to count pending deletion proposals: {err}");
+				0
+			});
+		let peer_trust = match (&state.page, &state.selected_peer) {
+			(Page::PeerDetail(_), Some(peer_id)) => PeerId::from_str(peer_id)
+				.ok()
+				.and_then(|peer| self.ctx.state.server.puppy.peer_trust(peer).ok())
+				.map(|trust| format!("Reviewed writes: {}", trust.describe()))
+				.unwrap_or_default(),
+			_ => String::new(),
+		};
+		let peer_access_alert = match (&state.page, &state.selected_peer) {
+			(Page::PeerDetail(_), Some(peer_id)) => state
+				.access_alerts
+				.get(peer_id)
+				.cloned()
+				.unwrap_or_default(),
+			_ => String::new(),
+		};
+		let peer_traffic_limit = if peer_access_alert.is_empty() {
+			String::new()
+		} else {
+			let limits = self.ctx.state.server.puppy.access_alert_limits();
+			format!("Limit to {}", traffic_limit_label(&limits))
+		};
+		let index_replications = match (&state.page, &state.selected_peer) {
+			(Page::PeerDetail(_), Some(peer_id)) => PeerId::from_str(peer_id)
+				.ok()
+				.and_then(|peer| self.ctx.state.server.puppy.index_replications(peer).ok())
+				.unwrap_or_default(),
+			_ => Vec::new(),
+		};
+		let subscribed_to_index = match (&state.page, &state.selected_peer) {
+			(Page::PeerDetail(_), Some(peer_id)) => PeerId::from_str(peer_id)
+				.ok()
+				.and_then(|peer| {
+					self.ctx
+						.state
+						.server
+						.puppy
+						.is_subscribed_to_index_updates(peer)
+						.ok()
+				})
+				.unwrap_or_default(),
+			_ => false,
+		};
+		let scan_result_pulls = match (&state.page, &state.selected_peer) {
+			(Page::PeerDetail(_), Some(peer_id)) => PeerId::from_str(peer_id)
+				.ok()
+				.and_then(|peer| self.ctx.state.server.puppy.scan_result_pulls(&peer).ok())
+				.unwrap_or_default()
+				.iter()
+				.take(SCAN_RESULT_PULLS_SHOWN)
+				.map(scan_results_pull_row)
+				.collect::<Vec<_>>(),
+			_ => Vec::new(),
+		};
+		let mirror_remote_scans = matches!(state.page, Page::Settings | Page::PeerDetail(_))
+			&& self.ctx.state.server.puppy.mirror_remote_scans();
 		let search_mime_options = state
 			.search_mime_types
 			.iter()
 			.map(|mime| UiMimeOption {
 				name: mime.clone(),
 				selected: session
-					.search_selected_mimes
+					.search
+					.selected_mimes
 					.iter()
 					.any(|selected| selected == mime),
 			})
 			.collect::<Vec<_>>();
-		let search_target = if session.search_target.is_empty() {
+		let search_target = if session.search.target.is_empty() {
 			String::from(SEARCH_ALL_DEVICES)
 		} else {
-			session.search_target.clone()
+			session.search.target.clone()
 		};
-		let search_sort = if session.search_sort.is_empty() {
+		let search_sort = if session.search.sort.is_empty() {
 			String::from("latest")
 		} else {
-			session.search_sort.clone()
+			session.search.sort.clone()
 		};
-		let search_page_size_text = if session.search_page_size.is_empty() {
-			String::from("50")
+		let prefs = self.prefs();
+		let search_page_size_text = if session.search.page_size.is_empty() {
+			prefs.default_page_size.to_string()
 		} else {
-			session.search_page_size.clone()
+			session.search.page_size.clone()
+		};
+		let activity_draft = session
+			.activity_draft
+			.clone()
+			.unwrap_or_else(|| activity_draft(&self.ctx.state.server.puppy.activity_window()));
+		let backup_draft = session
+			.backup_draft
+			.clone()
+			.unwrap_or_else(|| backup_draft(&self.ctx.state.server.puppy.backup_settings()));
+		let proxy_draft = session
+			.proxy_draft
+			.clone()
+			.unwrap_or_else(|| proxy_draft(&self.ctx.state.server.puppy.http_proxy()));
+		let download_dir = match &state.page {
+			Page::Settings => session.download_dir_draft.clone().unwrap_or_else(|| {
+				self.ctx
+					.state
+					.server
+					.puppy
+					.configured_download_dir(None)
+					.unwrap_or_default()
+					.unwrap_or_default()
+			}),
+			_ => String::new(),
+		};
+		let peer_download_dir = match (&state.page, &session.peer_download_dir_draft) {
+			(Page::PeerDetail(peer_id), Some((draft_peer, draft))) if draft_peer == peer_id => {
+				draft.clone()
+			}
+			(Page::PeerDetail(peer_id), _) => PeerId::from_str(peer_id)
+				.ok()
+				.and_then(|peer| {
+					self.ctx
+						.state
+						.server
+						.puppy
+						.configured_download_dir(Some(peer))
+						.unwrap_or_default()
+				})
+				.unwrap_or_default(),
+			_ => String::new(),
+		};
+		let wake_target = match &state.page {
+			Page::PeerDetail(peer_id) => PeerId::from_str(peer_id)
+				.ok()
+				.and_then(|peer| self.ctx.state.server.puppy.wake_target(peer).ok())
+				.flatten(),
+			_ => None,
+		};
+		let wake_mac = match (&state.page, &session.wake_mac_draft) {
+			(Page::PeerDetail(peer_id), Some((draft_peer, draft))) if draft_peer == peer_id => {
+				draft.clone()
+			}
+			_ => wake_target
+				.as_ref()
+				.map(|target| target.mac.clone())
+				.unwrap_or_default(),
 		};
-		let search_total_rows = session.search_raw_rows.len();
-		let search_visible_rows = session.search_results.len();
+		let wake_mac_note = match &wake_target {
+			Some(target) if target.confirmed => String::from("Set by hand."),
+			Some(target) => format!(
+				"Learned {} from the network card this device connected through. Correct it if the device has several.",
+				relative_time(target.updated_at, now)
+			),
+			None => String::from(
+				"Not known yet. It is learned the next time this device connects, or enter it here.",
+			),
+		};
+		let deferred = self.ctx.state.server.puppy.deferred_activities();
+		let deferred_work_notice = match deferred.iter().map(|item| &item.until).min() {
+			Some(until) => format!(
+				"Heavy transfers deferred (metered hours): {} waiting until {until}",
+				deferred.len()
+			),
+			None => String::new(),
+		};
+		let onboarding_step = session.onboarding_step.min(ONBOARDING_STEPS.len() - 1);
+		let node_peer_id = state.local_peer_id.clone().unwrap_or_default();
+		let nearby_peers = state
+			.nearby_peers
+			.iter()
+			.map(|peer| nearby_peer_row(peer, state.pairings.get(peer)))
+			.collect::<Vec<_>>();
+		let pairing_string = session.pairing_string;
+		let search_total_rows = session.search.raw_rows.len();
+		let search_visible_rows = session.search.results.len();
 		let search_page_text =
 			format!("Showing {search_visible_rows} of {search_total_rows} result(s)");
 		UiViewState {
@@ -1134,7 +4289,132 @@ impl UiControllerCore<'_> {
 			new_password: session.new_password,
 			confirm_password: session.confirm_password,
 			password_change_status: session.password_change_status,
-			search_name_query: session.search_name_query,
+			prefs_theme: prefs.theme.as_str().to_string(),
+			prefs_theme_options: prefs_theme_options(),
+			prefs_font_scale: prefs.font_scale.to_string(),
+			prefs_font_scale_options: prefs_font_scale_options(),
+			prefs_page_size: prefs.default_page_size.to_string(),
+			prefs_page_size_options: search_page_size_options(),
+			prefs_refresh_interval: prefs.refresh_interval.to_string(),
+			prefs_refresh_interval_options: prefs_refresh_interval_options(),
+			prefs_status: session.prefs_status,
+			remote_access_suspended: state.remote_access_suspended,
+			remote_access_status: session.remote_access_status,
+			revoke_access_status: session.revoke_access_status,
+			access_alert_status: session.access_alert_status,
+			demo_mode: self.ctx.state.server.puppy.is_demo(),
+			has_identity_mismatch: state.identity_mismatch.is_some(),
+			identity_mismatch: state
+				.identity_mismatch
+				.as_ref()
+				.map(|mismatch| {
+					format!(
+						"This database belongs to node {} but this node is {}. {}",
+						mismatch.previous_node_id, mismatch.current_node_id, mismatch.explanation
+					)
+				})
+				.unwrap_or_default(),
+			identity_status: session.identity_status,
+			nat_enabled: state.nat != NatStatus::Disabled,
+			nat_status: state.nat.describe(chrono::Utc::now()),
+			nat_mapping_status: session.nat_mapping_status,
+			has_reachability: !reachability.is_empty(),
+			reachability,
+			reachability_hint,
+			reachability_status: session.reachability_status,
+			has_diagnostics: !diagnostics.is_empty(),
+			diagnostics,
+			diagnostics_status: session.diagnostics_status,
+			has_protocol_rows: !protocol_rows.is_empty(),
+			protocol_rows,
+			protocol_alert: session
+				.protocol
+				.alert_draft
+				.clone()
+				.unwrap_or_else(|| protocol_limits.alert_per_min.to_string()),
+			protocol_limiter: protocol_limits.limiter,
+			protocol_status: session.protocol.status.clone(),
+			activity_ranges: activity_draft.ranges,
+			activity_utc_offset: activity_draft.utc_offset,
+			activity_scans: activity_draft.scans,
+			activity_updates: activity_draft.updates,
+			activity_syncs: activity_draft.syncs,
+			activity_hard_stop: activity_draft.hard_stop,
+			activity_status: session.activity_status,
+			disk_alert_threshold: session.disk_alert_draft.unwrap_or_else(|| {
+				self.ctx
+					.state
+					.server
+					.puppy
+					.disk_alert_threshold()
+					.to_string()
+			}),
+			disk_alert_status: session.disk_alert_status,
+			idle_timeout: session
+				.idle_timeout_draft
+				.unwrap_or_else(|| connection_policy.idle_timeout_secs.to_string()),
+			ping_interval: session
+				.ping_interval_draft
+				.unwrap_or_else(|| connection_policy.ping_interval_secs.to_string()),
+			kept_connected,
+			connection_policy_status: session.connection_policy_status,
+			peer_idle,
+			peer_important,
+			keep_connected_status: session.keep_connected_status,
+			power_mode: session
+				.power_mode_draft
+				.unwrap_or_else(|| String::from(power_mode(power_policy))),
+			power_mode_options: power_mode_options(),
+			power_percent: session.power_percent_draft.unwrap_or_else(|| {
+				match power_policy {
+					PowerPolicy::PauseBelow { percent } => percent,
+					_ => DEFAULT_LOW_BATTERY_PERCENT,
+				}
+				.to_string()
+			}),
+			power_reading,
+			power_policy_status: session.power_policy_status,
+			backup_scheduled: backup_draft.scheduled,
+			backup_hour: backup_draft.hour,
+			backup_keep: backup_draft.keep,
+			backup_dir: backup_draft.dir,
+			backup_status: session.backup_status,
+			has_backup_runs: !backup_runs.is_empty(),
+			backup_runs,
+			config_snapshot_label: session.config_snapshot_label,
+			config_snapshot_users: session.config_snapshot_users,
+			has_config_snapshots: !config_snapshots.is_empty(),
+			config_snapshots,
+			config_snapshot_status: session.config_snapshot_status,
+			download_dir,
+			download_dir_status: session.download_dir_status,
+			proxy_url: proxy_draft.url,
+			proxy_username: proxy_draft.username,
+			proxy_password: proxy_draft.password,
+			proxy_status: session.proxy_status,
+			peer_download_dir,
+			peer_download_dir_status: session.peer_download_dir_status,
+			wake_mac,
+			wake_mac_note,
+			wake_mac_status: session.wake_mac_status,
+			wake_status: wake_job
+				.as_ref()
+				.map(|job| job.detail.clone())
+				.unwrap_or_else(|| session.wake_status.clone()),
+			wake_in_progress: wake_job.as_ref().is_some_and(Job::is_active),
+			has_deferred_work: !deferred.is_empty(),
+			deferred_work_notice,
+			background_paused: power.background_paused,
+			power_notice: format!("Background tasks paused ({})", power.label()),
+			in_maintenance: maintenance.is_some(),
+			maintenance_notice: maintenance
+				.as_ref()
+				.map(|maintenance| maintenance_notice(maintenance, chrono::Utc::now()))
+				.unwrap_or_default(),
+			has_undo: undo_notice.is_some(),
+			undo_notice: undo_notice.unwrap_or_default(),
+			undo_status: session.undo_status,
+			search_name_query: session.search.name_query,
 			search_target,
 			search_target_options: search_targets,
 			search_sort,
@@ -1143,32 +4423,135 @@ impl UiControllerCore<'_> {
 			search_page_size_options: search_page_size_options(),
 			search_page_text,
 			search_can_load_more: search_visible_rows < search_total_rows,
-			search_in_progress: session.search_in_progress,
-			search_selected_mimes_text: if session.search_selected_mimes.is_empty() {
+			search_in_progress: session.search.in_progress,
+			search_selected_mimes_text: if session.search.selected_mimes.is_empty() {
 				String::from("All mime types")
 			} else {
-				session.search_selected_mimes.join(", ")
+				session.search.selected_mimes.join(", ")
 			},
 			has_search_mime_options: !search_mime_options.is_empty(),
 			search_mime_options,
-			search_status: session.search_status,
-			search_has_results: !session.search_results.is_empty(),
-			search_results: session.search_results,
+			search_status: session.search.status,
+			search_has_results: !session.search.results.is_empty(),
+			search_results: session
+				.search
+				.results
+				.into_iter()
+				.map(|row| UiSearchRow {
+					focused: session.search_focus.is_focused(&search_focus_key(&row)),
+					clock_warning: clock_warning(&row.peer_id),
+					index_stale: index_stale(&row.peer_id),
+					..row
+				})
+				.collect(),
 			is_current_device,
+			has_peer_connections: !peer_connections.is_empty(),
+			peer_connections,
+			peer_clock_skew,
+			peer_trust,
+			peer_access_alert,
+			peer_traffic_limit,
+			index_replica: index_replications
+				.iter()
+				.map(|status| format!("Index: {}", status.describe()))
+				.collect::<Vec<_>>()
+				.join("\n"),
+			replicating_index: index_replications
+				.iter()
+				.any(|status| status.role == ReplicationRole::Source),
+			index_replica_status: session.index_replica_status,
+			peer_index_stale,
+			subscribed_to_index,
+			index_updates_status: session.index_updates_status,
+			remote_scan_path: session.remote_scan_path,
+			remote_scan_status: remote_scan_job
+				.as_ref()
+				.map(|job| job.detail.clone())
+				.unwrap_or_else(|| session.remote_scan_status.clone()),
+			remote_scan_running: remote_scan_job.as_ref().is_some_and(Job::is_active),
+			scan_result_pulls,
+			mirror_remote_scans,
+			mirror_remote_scans_status: session.mirror_remote_scans_status,
 			shared_folder_path: session.shared_folder_path,
+			has_local_folders: !local_folders.is_empty(),
+			local_folders,
 			shared_folder_access: if session.shared_folder_access.is_empty() {
 				String::from("read")
 			} else {
 				session.shared_folder_access
 			},
 			shared_folder_access_options: shared_folder_access_options(),
+			temporary_grant_access_options: temporary_grant_access_options(),
 			shared_folder_status: session.shared_folder_status,
 			has_shared_folders: !shared_folders.is_empty(),
 			shared_folders,
+			temporary_grant_path: session.temporary_grant_path,
+			temporary_grant_access: if session.temporary_grant_access.is_empty() {
+				String::from("read")
+			} else {
+				session.temporary_grant_access
+			},
+			temporary_grant_presets: TEMPORARY_GRANT_PRESETS
+				.iter()
+				.map(|(label, _)| format!("Allow for {label}"))
+				.collect(),
+			onboarding_step_text: format!(
+				"Step {} of {}: {}",
+				onboarding_step + 1,
+				ONBOARDING_STEPS.len(),
+				ONBOARDING_STEPS[onboarding_step]
+			),
+			onboarding_is_identity: onboarding_step == 0,
+			onboarding_is_share: onboarding_step == 1,
+			onboarding_is_pair: onboarding_step == 2,
+			onboarding_has_back: onboarding_step > 0,
+			onboarding_node_name: session
+				.onboarding_node_name
+				.unwrap_or_else(|| state.node_name.clone()),
+			onboarding_status: session.onboarding_status,
+			node_fingerprint: abbrev_peer_id(&node_peer_id),
+			node_peer_id,
+			has_nearby_peers: !nearby_peers.is_empty(),
+			nearby_peers,
+			pairing_status: session.pairing_status,
+			pairing_string: pairing_string
+				.as_ref()
+				.map(|(info, _)| info.pairing_string.clone())
+				.unwrap_or_default(),
+			pairing_qr: pairing_string
+				.as_ref()
+				.map(|(_, qr)| qr.clone())
+				.unwrap_or_default(),
+			pairing_string_note: pairing_string
+				.as_ref()
+				.map(|(info, _)| pairing_string_note(info, now))
+				.unwrap_or_default(),
+			join_pairing: session.join_pairing,
+			pairing_string_status: session.pairing_string_status,
+			temporary_grant_status: session.temporary_grant_status,
+			temporary_grants,
+			has_peer_shares: !peer_shares.is_empty(),
+			peer_shares,
+			peer_shares_degraded,
 			new_user_username: session.new_user_username,
 			new_user_password: session.new_user_password,
 			new_user_status: session.new_user_status,
+			new_user_username_error: session.new_user_username_error,
+			new_user_password_error: session.new_user_password_error,
+			new_user_password_hint,
 			new_user_modal_open: session.new_user_modal_open,
+			share_wizard_open: session.share_wizard.is_some(),
+			share_wizard_path: share_wizard.path,
+			share_wizard_write: share_wizard.write,
+			share_wizard_preview_only: share_wizard.preview_only,
+			share_wizard_suggest_patterns: share_wizard.write
+				&& share_wizard.deny_patterns.trim().is_empty(),
+			share_wizard_deny_patterns: share_wizard.deny_patterns,
+			has_share_wizard_peers: !share_wizard_peers.is_empty(),
+			share_wizard_peers,
+			has_share_wizard_results: !share_wizard_results.is_empty(),
+			share_wizard_results,
+			share_wizard_status: share_wizard.status,
 			file_preview_peer: session.file_preview_peer,
 			file_preview_path: session.file_preview_path,
 			file_preview_status: session.file_preview_status,
@@ -1177,11 +4560,61 @@ impl UiControllerCore<'_> {
 			file_preview_image_src: session.file_preview_image_src,
 			file_preview_can_load: !session.file_preview_loaded,
 			file_preview_modal_open: session.file_preview_modal_open,
-			shell_peer: session.shell_peer,
-			shell_input: session.shell_input,
-			shell_output: session.shell_output,
-			shell_status: session.shell_status,
-			shell_has_session: session.shell_session_id.is_some(),
+			file_preview_has_prev: !session.file_preview_history.is_empty(),
+			file_preview_has_next: session.file_preview_next.is_some(),
+			file_preview_can_download: !session.file_preview_peer.trim().is_empty()
+				&& session.file_preview_raw_path.is_none()
+				&& !session.file_preview_contact_sheet,
+			file_preview_download_status: session.file_preview_download_status,
+			file_preview_media: session.file_preview_media,
+			file_preview_large_image: session.file_preview_large_image.is_some(),
+			file_preview_has_previous_download: session.file_preview_previous_download.is_some(),
+			file_preview_previous_download: session
+				.file_preview_previous_download
+				.as_ref()
+				.map(previous_download_notice)
+				.unwrap_or_default(),
+			has_compare_first: session.compare_first.is_some(),
+			compare_first: session
+				.compare_first
+				.as_ref()
+				.map(|(peer, path)| {
+					format!(
+						"Comparing {path} on {}, pick the file to compare it with",
+						abbrev_peer_id(peer)
+					)
+				})
+				.unwrap_or_default(),
+			compare_modal_open: session.compare_modal_open,
+			compare_title: session.compare_title,
+			compare_status: session.compare_status,
+			compare_lines: session
+				.compare_diff
+				.as_ref()
+				.map(diff_lines)
+				.unwrap_or_default(),
+			shell_supported,
+			peer_shell_status: session.peer_shell.status(),
+			peer_shell_connected: session.peer_shell.live().is_some(),
+			peer_shell_ended: session.peer_shell.ended(),
+			peer_shell_input: session.peer_shell.input.clone(),
+			peer_shell_spans: session
+				.peer_shell
+				.spans()
+				.into_iter()
+				.map(|span| UiShellSpan {
+					color: span.style.color().to_string(),
+					text: span.text,
+					line_start: span.line_start,
+				})
+				.collect(),
+			update_supported,
+			restart_supported,
+			inbox_supported,
+			has_capability_notice: !capability_notice.is_empty(),
+			capability_notice,
+			send_file_path: session.send_file_path,
+			send_file_status: session.send_file_status,
 			control_text: session.control_text,
 			control_status: session.control_status,
 			trackpad_props: UiTrackpadProps { sensitivity: 1.0 },
@@ -1233,10 +4666,23 @@ impl UiControllerCore<'_> {
 				&& !selected_screen.is_empty(),
 			monitor_status,
 			update_version: session.update_version,
-			update_status: session.update_status,
-			update_events: session.update_events.clone(),
-			has_update_events: !session.update_events.is_empty(),
-			update_in_progress: session.update_in_progress,
+			update_status: update_job
+				.as_ref()
+				.map(|job| job.detail.clone())
+				.unwrap_or_else(|| session.update_status.clone()),
+			update_events: update_job
+				.as_ref()
+				.map(|job| job.log.clone())
+				.unwrap_or_default(),
+			has_update_events: update_job.as_ref().is_some_and(|job| !job.log.is_empty()),
+			update_in_progress: update_job.as_ref().is_some_and(Job::is_active),
+			restart_after_update: session.restart_after_update,
+			restart_confirm: session.restart_confirm,
+			restart_status: restart_job
+				.as_ref()
+				.map(|job| job.detail.clone())
+				.unwrap_or_else(|| session.restart_status.clone()),
+			restart_in_progress: restart_job.as_ref().is_some_and(Job::is_active),
 			home_peers: format!("Devices: {}", peers.len()),
 			home_files: format!("Files captured: {}", files.len()),
 			home_storage: format!("Storage entries: {}", storage_rows.len()),
@@ -1250,736 +4696,3904 @@ impl UiControllerCore<'_> {
 				None => String::from("Grant command unavailable"),
 			},
 			has_peers: !peers.is_empty(),
+			peers_responded: if state.peers_refresh.total == 0 {
+				String::new()
+			} else {
+				state.peers_refresh.describe()
+			},
+			peers_has_failures: !state.peers_refresh.failures.is_empty(),
+			peers_failures_open: session.peer_failures_open,
+			peers_failures: state
+				.peers_refresh
+				.failures
+				.iter()
+				.map(|(peer, reason)| format!("{}: {reason}", abbrev_peer_id(&peer.to_string())))
+				.collect(),
+			has_outdated_peers: !state.outdated_peers.is_empty(),
+			fleet_update_label: format!("Update {} outdated peer(s)", state.outdated_peers.len()),
+			fleet_update_status: session.fleet_update_status.clone(),
 			has_cpus: !cpus.is_empty(),
+			has_disks: !disks.is_empty(),
 			has_interfaces: !interfaces.is_empty(),
 			has_audio_devices: !audio_devices.is_empty(),
 			has_files: !files.is_empty(),
 			has_peer_files: !peer_files.is_empty(),
-			peer_files_path: state.peer_files_path,
+			peer_files_path: if state.peer_files_path.is_empty() {
+				String::from("Disks and shared folders")
+			} else {
+				state.peer_files_path
+			},
 			selected_peer_details_href,
 			selected_peer_control_href,
 			selected_peer_files_href,
 			selected_peer_webcams_href,
+			selected_peer_permissions_href,
+			selected_peer_shell_href,
 			peer_files_has_parent: !peer_files_parent_href.is_empty(),
+			peer_grants_status,
+			peer_files_free_hint: state
+				.peer_files_free_hint
+				.map(describe_free_hint)
+				.unwrap_or_default(),
+			peer_files_access,
 			peer_files_parent_href,
+			peer_files_denied: state.peer_files_denied.is_some(),
+			peer_files_access_request,
+			peer_files_search_query: session.peer_files.query.clone(),
+			peer_files_search_include_deleted: session.peer_files.include_deleted,
+			peer_files_disk_sizes: session.peer_files.disk_sizes,
+			peer_files_sort,
+			peer_files_sort_options: peer_files_sort_options(),
+			peer_files_show_hidden: state.peer_files_view.show_hidden,
+			peer_files_thumbnails: state.peer_files_view.thumbnails,
+			peer_files_search_active: peer_files_search_scope.is_some(),
+			peer_files_search_scope: peer_files_search_scope.unwrap_or_default(),
+			peer_files_search_status,
+			peer_files_search_has_results: !peer_files_search_results.is_empty(),
+			peer_files_search_results,
+			peer_files_thumbnail_progress: session.peer_files.thumbnails.progress(),
+			peer_files_more,
 			has_storage_rows: !storage_rows.is_empty(),
+			has_scan_history: !scan_history.is_empty(),
+			has_scan_trends: !scan_trends.is_empty(),
+			growth_summary: growth.map(growth_summary).unwrap_or_default(),
+			has_growth_rows: !growth_rows.is_empty(),
+			growth_fastest: growth.map(growth_fastest).unwrap_or_default(),
+			has_treemap: !treemap_props.tiles.is_empty(),
+			treemap_props,
+			treemap_trail,
+			treemap_node: treemap.node.clone().unwrap_or_default(),
+			treemap_node_options,
+			treemap_depth: session.treemap.depth().to_string(),
+			treemap_depth_options: TREEMAP_DEPTHS
+				.into_iter()
+				.map(|depth| UiSelectOption {
+					value: depth.to_string(),
+					name: format!("{depth} levels"),
+				})
+				.collect(),
+			treemap_note: treemap.note.clone(),
+			growth_caveat: growth
+				.filter(|report| !report.disks.is_empty())
+				.map(|report| {
+					format!(
+						"Projections are based on the last {} days of disk samples.",
+						report.projection_days
+					)
+				})
+				.unwrap_or_default(),
+			has_scan_diff: !scan_diff_rows.is_empty(),
+			scan_diff_status: session.scan_diff_status.clone(),
+			scan_path: session.scan_path.clone(),
+			scan_status: session.scan_status.clone(),
+			scan_read_limit: session.scan_read_limit.clone(),
+			scan_low_priority: session.scan_low_priority,
+			store_status: session.store_status.clone(),
+			has_removable_drives: !removable_drives.is_empty(),
+			ingest_destination: ingest.destination,
+			ingest_patterns: ingest.patterns,
+			ingest_delete_originals: ingest.delete_originals,
+			ingest_eject_when_done: ingest.eject_when_done,
+			ingest_status: session.ingest_status.clone(),
+			integrity_summary,
+			integrity_enabled: integrity.enabled,
+			integrity_files_per_cycle: integrity.files_per_cycle,
+			integrity_read_rate: integrity.read_rate,
+			integrity_status: session.integrity_status.clone(),
+			has_integrity_findings: !integrity_rows.is_empty(),
+			has_jobs: !jobs.is_empty(),
+			jobs_nav_label: if active_jobs > 0 {
+				format!("Jobs ({active_jobs})")
+			} else {
+				String::from("Jobs")
+			},
+			thumbnail_pregeneration: thumbnail_queue.enabled,
+			thumbnail_queue: thumbnail_queue.summary(),
+			has_transfers: !transfers.is_empty(),
+			transfers,
+			has_pins: !pins.is_empty(),
+			pins,
+			has_outboxes: !outboxes.is_empty(),
+			outboxes,
+			outbox_peer_options: outbox_peers,
+			outbox_watch_path: outbox_draft.watch_path,
+			outbox_peer: outbox_draft.peer,
+			outbox_dest_path: outbox_draft.dest_path,
+			outbox_pattern: outbox_draft.pattern,
+			outbox_delete_after_send: outbox_draft.delete_after_send,
+			outbox_editing: outbox_draft.editing.is_some(),
+			outbox_status: session.outbox_status.clone(),
+			review_nav_label: if pending_reviews + pending_deletions > 0 {
+				format!("Review ({})", pending_reviews + pending_deletions)
+			} else {
+				String::from("Review")
+			},
+			notifications_nav_label: if unseen_notifications > 0 {
+				format!("Notifications ({unseen_notifications})")
+			} else {
+				String::from("Notifications")
+			},
+			has_toasts: !toasts.is_empty(),
+			toasts,
+			has_notifications: !notifications.is_empty(),
+			notifications,
+			has_reviews: !reviews.is_empty(),
+			reviews,
+			review_status: session.review.status.clone(),
+			review_selection: format!("{review_selection} selected"),
+			has_review_selection: review_selection > 0,
+			has_delete_requests: !delete_requests.is_empty(),
+			delete_requests,
+			delete_reject_reason: session.review.reject_reason.clone(),
+			has_delete_proposals: !delete_proposals.is_empty(),
+			delete_proposals,
+			timeline_kinds,
+			has_timeline_peers: !timeline_peers.is_empty(),
+			timeline_peers,
+			timeline_text: session.timeline.text.clone(),
+			has_timeline_events: !timeline_events.is_empty(),
+			timeline_events,
+			timeline_can_load_more: session.timeline.next_cursor().is_some(),
+			timeline_status: session.timeline.status.clone(),
+			has_permission_rules: !permission_rules.is_empty(),
+			permission_rules,
+			permission_folder_selected: selected_folder.is_some(),
+			permission_path: selected_folder
+				.map(|(_, rule)| rule.path.clone())
+				.unwrap_or_default(),
+			permission_path_error: selected_folder
+				.map(|(idx, _)| editor.errors_for(idx, &["path"]))
+				.unwrap_or_default(),
+			permission_flags,
+			permission_flags_error: selected_folder
+				.map(|(idx, _)| editor.errors_for(idx, &["read", "quarantine"]))
+				.unwrap_or_default(),
+			permission_deny_patterns: selected_folder
+				.map(|(_, rule)| pattern_text(&rule.deny_patterns))
+				.unwrap_or_default(),
+			permission_deny_patterns_error: selected_folder
+				.map(|(idx, _)| editor.errors_for(idx, &["deny_patterns"]))
+				.unwrap_or_default(),
+			permission_allow_patterns: selected_folder
+				.map(|(_, rule)| pattern_text(&rule.allow_patterns))
+				.unwrap_or_default(),
+			permission_allow_patterns_error: selected_folder
+				.map(|(idx, _)| editor.errors_for(idx, &["allow_patterns"]))
+				.unwrap_or_default(),
+			permission_merge: editor.merge(),
+			permission_trust: String::from(editor.trust().as_str()),
+			permission_trust_options: trust_level_options(),
+			permission_trust_note: String::from(editor.trust().describe()),
+			permission_revision: editor
+				.revision()
+				.map(|revision| format!("Revision {revision}"))
+				.unwrap_or_default(),
+			permission_status: editor.status.clone(),
 			has_users: !users.is_empty(),
+			has_failed_logins: !failed_logins.is_empty(),
+			failed_logins,
 			selected_peer: state.selected_peer.unwrap_or_default(),
 			peers,
 			cpus,
+			disks,
 			interfaces,
 			audio_devices,
 			webcam_devices,
 			files,
 			peer_files,
 			storage_rows,
+			scan_history,
+			scan_trends,
+			growth_rows,
+			growth_projections,
+			scan_diff_rows,
+			removable_drives,
+			integrity_rows,
+			jobs,
 			users,
 		}
 	}
 
-	fn state_for_page(&self, page: Page) -> UiViewState {
-		self.block_on(self.ctx.state.server.set_page(page));
-		self.state()
+	fn state_for_page(&self, page: Page) -> UiViewState {
+		self.block_on(self.ctx.state.server.set_page(page));
+		self.state()
+	}
+
+	pub(super) fn files_state(&self) -> UiViewState {
+		self.state_for_page(Page::Files)
+	}
+
+	pub(super) fn home_state(&self) -> UiViewState {
+		self.state_for_page(Page::Home)
+	}
+
+	pub(super) fn peer_state(&self, peer_id: String) -> UiViewState {
+		let should_refresh =
+			self.block_on(self.ctx.state.server.snapshot())
+				.selected_peer
+				.as_deref() != Some(peer_id.as_str());
+		self.block_on(
+			self.ctx
+				.state
+				.server
+				.set_page(Page::PeerDetail(peer_id.clone())),
+		);
+		if should_refresh {
+			self.block_on(self.ctx.state.server.refresh_peer_detail(&peer_id));
+		}
+		self.state()
+	}
+
+	pub(super) fn peer_control_state(&self, peer_id: String) -> UiViewState {
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let page = Page::PeerControl {
+			peer_id: peer_id.clone(),
+		};
+		let should_refresh = snapshot.page != page;
+		self.block_on(self.ctx.state.server.set_page(page));
+		if should_refresh {
+			self.block_on(self.ctx.state.server.refresh_peer_screens(&peer_id));
+		}
+		self.state()
+	}
+
+	/// Queues thumbnails of the rendered images in the listed folder `dir`.
+	/// Rendering the same folder again leaves a running batch alone and
+	/// only adds rows that weren't rendered before.
+	fn load_peer_file_thumbnails(&self, peer_id: String, dir: String) {
+		let Some(client_id) = self.ctx.client_id() else {
+			return;
+		};
+		let session = self.current_session();
+		let files = self.block_on(async {
+			let state = self.ctx.state.server.state.lock().await;
+			// With thumbnails off, what was queued is dropped unfetched.
+			if dir.is_empty() || state.peer_files_path != dir || !state.peer_files_view.thumbnails {
+				return Vec::new();
+			}
+			let windows = state
+				.peer_roots
+				.as_ref()
+				.is_some_and(|(roots_peer, roots)| *roots_peer == peer_id && roots.windows);
+			state.peer_files[session.peer_files.rendered(&state.peer_files)]
+				.iter()
+				.filter(|entry| !entry.is_dir && !entry.has_undecodable_name())
+				.filter(|entry| {
+					entry
+						.mime
+						.as_deref()
+						.is_some_and(|mime| mime.starts_with("image/"))
+				})
+				.filter_map(|entry| {
+					let path = child_peer_file_path(&state.peer_files_path, &entry.name, windows)?;
+					Some((entry.name.clone(), path))
+				})
+				.collect::<Vec<_>>()
+		});
+		let mut fetches = Vec::new();
+		self.update_session(|session| {
+			fetches = session
+				.peer_files
+				.thumbnails
+				.update(ThumbnailMsg::Opened {
+					peer_id,
+					dir,
+					files,
+				})
+				.unwrap_or_default();
+		});
+		if fetches.is_empty() {
+			return;
+		}
+		let session_key = self.session_key();
+		let route_path = self.ctx.route().map(|route| route.path).unwrap_or_default();
+		let ctx = Arc::clone(self.ctx);
+		// Results of a folder the client left change nothing and must not
+		// push it back to this route.
+		let update: ThumbnailUpdate = Arc::new(move |msg| {
+			let next = match ctx.state.sessions.lock() {
+				Ok(mut sessions) => sessions
+					.get_mut(&session_key)
+					.and_then(|session| session.peer_files.thumbnails.update(msg)),
+				Err(_) => None,
+			};
+			if next.is_some() {
+				ctx.push_state_for_client(client_id, route_path.clone());
+			}
+			next.unwrap_or_default()
+		});
+		let puppy = Arc::clone(&self.ctx.state.server.puppy);
+		for fetch in fetches {
+			spawn_thumbnail_fetch(Arc::clone(&puppy), Arc::clone(&update), fetch);
+		}
+	}
+
+	/// Stops queueing thumbnails once the client leaves the file browser.
+	pub(super) fn leave_peer_files(&self) {
+		self.update_session(|session| {
+			session.peer_files.thumbnails.update(ThumbnailMsg::Left);
+		});
+	}
+
+	pub(super) fn peer_files_state(&self, peer_id: String, path: String) -> UiViewState {
+		let path = match normalize_peer_file_path(&path) {
+			Ok(path) => path,
+			Err(err) => {
+				self.block_on(async {
+					self.ctx.state.server.state.lock().await.status = err.to_string();
+				});
+				String::new()
+			}
+		};
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let page = Page::PeerFiles {
+			peer_id: peer_id.clone(),
+			path: path.clone(),
+		};
+		let should_refresh = snapshot.page != page;
+		self.block_on(self.ctx.state.server.set_page(page));
+		if should_refresh {
+			let view = self.prefs().dir_view(&peer_id, &path);
+			self.block_on(async {
+				self.ctx.state.server.state.lock().await.peer_files_view = view;
+			});
+			self.update_session(|session| session.peer_files.update(PeerFilesMsg::FolderOpened));
+			self.block_on(self.ctx.state.server.refresh_peer_files(&peer_id, &path));
+			self.block_on(self.ctx.state.server.refresh_pins());
+		}
+		self.load_peer_file_thumbnails(peer_id, path);
+		self.state()
+	}
+
+	pub(super) fn peer_webcams_state(&self, peer_id: String) -> UiViewState {
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let page = Page::PeerWebcams {
+			peer_id: peer_id.clone(),
+		};
+		let should_refresh = snapshot.page != page;
+		self.block_on(self.ctx.state.server.set_page(page));
+		if should_refresh {
+			self.block_on(self.ctx.state.server.refresh_peer_webcams(&peer_id));
+		}
+		self.state()
+	}
+
+	pub(super) fn peers_state(&self) -> UiViewState {
+		self.state_for_page(Page::Peers)
+	}
+
+	pub(super) fn search_state(&self) -> UiViewState {
+		self.state_for_page(Page::Search)
+	}
+
+	pub(super) fn settings_state(&self) -> UiViewState {
+		self.block_on(self.ctx.state.server.refresh_backups());
+		self.block_on(self.ctx.state.server.refresh_config_snapshots());
+		self.state_for_page(Page::Settings)
+	}
+
+	pub(super) fn welcome_state(&self) -> UiViewState {
+		self.block_on(self.ctx.state.server.refresh_nearby());
+		self.state_for_page(Page::Welcome)
+	}
+
+	pub(super) fn storage_state(&self) -> UiViewState {
+		self.state_for_page(Page::Storage)
+	}
+
+	pub(super) fn updates_state(&self) -> UiViewState {
+		self.state_for_page(Page::Updates)
+	}
+
+	pub(super) fn jobs_state(&self) -> UiViewState {
+		self.block_on(self.ctx.state.server.refresh_transfers());
+		self.block_on(self.ctx.state.server.refresh_pins());
+		self.block_on(self.ctx.state.server.refresh_outboxes());
+		self.state_for_page(Page::Jobs)
+	}
+
+	pub(super) fn review_state(&self) -> UiViewState {
+		self.block_on(self.ctx.state.server.refresh_reviews());
+		self.state_for_page(Page::Review)
+	}
+
+	/// Loads the newest events matching the timeline's filter, or with
+	/// `append` the ones after those already shown.
+	fn load_timeline(&self, append: bool) {
+		let timeline = self.current_session().timeline;
+		let cursor = if append {
+			match timeline.next_cursor() {
+				Some(cursor) => Some(cursor.clone()),
+				None => return,
+			}
+		} else {
+			None
+		};
+		let msg = match self.ctx.state.server.puppy.activity(
+			&timeline.filter(),
+			cursor.as_ref(),
+			DEFAULT_ACTIVITY_PAGE,
+		) {
+			Ok(page) => TimelineMsg::Loaded { page, append },
+			Err(err) => TimelineMsg::Failed(err),
+		};
+		self.update_session(|session| session.timeline.update(msg));
+	}
+
+	pub(super) fn timeline_state(&self) -> UiViewState {
+		if self.current_session().timeline.needs_load() {
+			self.load_timeline(false);
+		}
+		self.state_for_page(Page::Timeline)
+	}
+
+	pub(super) fn leave_timeline(&self) {
+		self.update_session(|session| session.timeline.update(TimelineMsg::Left));
+	}
+
+	/// Loads what `peer_id` is granted into the permissions editor,
+	/// dropping any unsaved edits.
+	fn load_permission_editor(&self, peer_id: &str) {
+		let puppy = &self.ctx.state.server.puppy;
+		let msg = match peer_id.parse::<PeerId>() {
+			Ok(peer) => match puppy
+				.granted_permission_set(peer)
+				.and_then(|set| Ok((set, puppy.trust_level(peer)?)))
+			{
+				Ok((set, trust)) => PermissionEditorMsg::Loaded {
+					peer_id: peer_id.to_string(),
+					draft: PermissionDraft::from_permissions(&set.permissions),
+					revision: set.revision,
+					trust,
+				},
+				Err(err) => {
+					PermissionEditorMsg::Failed(format!("Failed to load permissions: {err}"))
+				}
+			},
+			Err(err) => PermissionEditorMsg::Failed(format!("Invalid device id: {err}")),
+		};
+		self.update_session(|session| session.permission_editor.update(msg));
+	}
+
+	pub(super) fn peer_permissions_state(&self, peer_id: String) -> UiViewState {
+		if self
+			.current_session()
+			.permission_editor
+			.needs_load(&peer_id)
+		{
+			self.load_permission_editor(&peer_id);
+		}
+		self.state_for_page(Page::PeerPermissions { peer_id })
+	}
+
+	pub(super) fn leave_permission_editor(&self) {
+		self.update_session(|session| session.permission_editor.update(PermissionEditorMsg::Left));
+	}
+
+	/// Starts a shell on `peer_id` for this client's terminal and reads its
+	/// output in the background until it ends or the client leaves.
+	fn start_peer_shell(&self, peer_id: String) {
+		let Some(client_id) = self.ctx.client_id() else {
+			return;
+		};
+		self.update_session(|session| {
+			session.peer_shell.update(PeerShellMsg::Starting {
+				peer_id: peer_id.clone(),
+			});
+		});
+		let peer = match PeerId::from_str(&peer_id) {
+			Ok(peer) => peer,
+			Err(err) => {
+				let msg = PeerShellMsg::Failed(format!("Invalid device id: {err}"));
+				self.update_session(|session| session.peer_shell.update(msg));
+				return;
+			}
+		};
+		let puppy = Arc::clone(&self.ctx.state.server.puppy);
+		let session_id = puppy.next_id(IdKind::Shell);
+		let session_id = match self.block_on(puppy.start_shell(peer, session_id)) {
+			Ok(session_id) => session_id,
+			Err(err) => {
+				let msg = PeerShellMsg::Failed(self.notify_error("Failed to start shell", err));
+				self.update_session(|session| session.peer_shell.update(msg));
+				return;
+			}
+		};
+		self.update_session(|session| {
+			session
+				.peer_shell
+				.update(PeerShellMsg::Started { session_id });
+		});
+		let session_key = self.session_key();
+		let route_path = self.ctx.route().map(|route| route.path).unwrap_or_default();
+		let ctx = Arc::clone(self.ctx);
+		let update: PeerShellUpdate = Arc::new(move |msg| {
+			let changed = match &msg {
+				Some(PeerShellMsg::Output { data, .. }) => !data.is_empty(),
+				Some(_) => true,
+				None => false,
+			};
+			let polled = match ctx.state.sessions.lock() {
+				Ok(mut sessions) => sessions.get_mut(&session_key).map(|session| {
+					if let Some(msg) = msg {
+						session.peer_shell.update(msg);
+					}
+					(
+						session.peer_shell.poll(session_id),
+						session.peer_shell.waiting(),
+					)
+				}),
+				Err(_) => None,
+			};
+			let Some((next, waiting)) = polled else {
+				return ShellPoll::Stop;
+			};
+			if changed || waiting {
+				ctx.push_state_for_client(client_id, route_path.clone());
+			}
+			next
+		});
+		spawn_shell_poller(puppy, update, peer, session_id);
+	}
+
+	pub(super) fn peer_shell_state(&self, peer_id: String) -> UiViewState {
+		if self.current_session().peer_shell.needs_start(&peer_id) {
+			self.start_peer_shell(peer_id.clone());
+		}
+		self.update_session(|session| session.peer_shell.update(PeerShellMsg::Frame));
+		self.state_for_page(Page::PeerShell { peer_id })
+	}
+
+	/// Closes the shell once the client leaves the terminal, so it doesn't
+	/// keep running on the peer for nobody.
+	pub(super) fn leave_peer_shell(&self) {
+		let live = self
+			.current_session()
+			.peer_shell
+			.live()
+			.and_then(|(peer_id, session_id)| Some((PeerId::from_str(peer_id).ok()?, session_id)));
+		self.update_session(|session| session.peer_shell.update(PeerShellMsg::Left));
+		let Some((peer, session_id)) = live else {
+			return;
+		};
+		let puppy = Arc::clone(&self.ctx.state.server.puppy);
+		tokio::spawn(async move {
+			if let Err(err) = puppy.close_shell(peer, session_id).await {
+				tracing::debug!("failed to close shell session {session_id}: {err}");
+			}
+		});
+	}
+
+	/// Everything listed is seen once the page shows it, which also ends the
+	/// toasts and the count on the navbar.
+	pub(super) fn notifications_state(&self) -> UiViewState {
+		self.ctx
+			.state
+			.server
+			.notifications
+			.lock()
+			.unwrap()
+			.mark_all_seen();
+		self.state_for_page(Page::Notifications)
+	}
+
+	pub fn clear_notifications(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.ctx.state.server.notifications.lock().unwrap().clear();
+	}
+
+	pub(super) fn users_state(&self) -> UiViewState {
+		self.state_for_page(Page::Users)
+	}
+
+	pub fn logout(&self) {
+		if let Some(session_id) = self.ctx.session_id() {
+			let hash = auth::token_hash(&session_id);
+			let _ = self.ctx.state.server.puppy.drop_session(&hash);
+		}
+		self.update_session(|session| {
+			session.authenticated = false;
+			session.username.clear();
+		});
+		self.ctx.navigate("/auth/logout");
+	}
+
+	pub fn edit_login_username(&self, value: String) {
+		self.update_session(|session| {
+			session.login_username = value;
+			session.login_error.clear();
+		});
+	}
+
+	pub fn edit_login_password(&self, value: String) {
+		self.update_session(|session| {
+			session.login_password = value;
+			session.login_error.clear();
+		});
+	}
+
+	pub fn edit_current_password(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session.current_password = value;
+			session.password_change_status.clear();
+		});
+	}
+
+	pub fn edit_new_password(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session.new_password = value;
+			session.password_change_status.clear();
+		});
+	}
+
+	pub fn edit_confirm_password(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session.confirm_password = value;
+			session.password_change_status.clear();
+		});
+	}
+
+	pub fn change_password(&self) {
+		let Some(username) = self.authenticated_username() else {
+			self.ctx.push_state("/login");
+			return;
+		};
+		let (current_password, new_password, confirm_password) = {
+			let session = self.current_session();
+			(
+				session.current_password,
+				session.new_password,
+				session.confirm_password,
+			)
+		};
+		self.change_password_for_user(username, current_password, new_password, confirm_password);
+	}
+
+	pub fn change_password_values(
+		&self,
+		current_password: String,
+		new_password: String,
+		confirm_password: String,
+	) -> bool {
+		let Some(username) = self.authenticated_username() else {
+			return false;
+		};
+		self.change_password_for_user(username, current_password, new_password, confirm_password)
+	}
+
+	fn change_password_for_user(
+		&self,
+		username: String,
+		current_password: String,
+		new_password: String,
+		confirm_password: String,
+	) -> bool {
+		if current_password.trim().is_empty()
+			|| new_password.trim().is_empty()
+			|| confirm_password.trim().is_empty()
+		{
+			self.update_session(|session| {
+				session.password_change_status = String::from("All password fields are required");
+			});
+			return false;
+		}
+		if new_password != confirm_password {
+			self.update_session(|session| {
+				session.password_change_status =
+					String::from("New password and confirmation do not match");
+			});
+			return false;
+		}
+		match self
+			.ctx
+			.state
+			.server
+			.puppy
+			.change_password(username, current_password, new_password)
+		{
+			Ok(()) => {
+				self.update_session(|session| {
+					session.current_password.clear();
+					session.new_password.clear();
+					session.confirm_password.clear();
+					session.password_change_status = String::from("Password changed");
+				});
+				true
+			}
+			Err(err) => {
+				self.update_session(|session| {
+					session.password_change_status =
+						self.notify_error("Password change failed", err);
+				});
+				false
+			}
+		}
+	}
+
+	/// Applies and persists a preference change. `restart_note` marks
+	/// preferences that are baked into the stylesheet when the UI starts.
+	fn update_prefs<F>(&self, f: F, restart_note: bool)
+	where
+		F: FnOnce(&mut UiPrefs),
+	{
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let saved = {
+			let mut prefs = self.ctx.state.prefs.lock().unwrap();
+			f(&mut prefs);
+			*prefs = prefs.clone().normalized();
+			prefs.save(&self.ctx.state.prefs_path)
+		};
+		let status = match saved {
+			Ok(()) if restart_note => {
+				String::from("Saved; theme and text size apply when the UI restarts")
+			}
+			Ok(()) => String::from("Saved"),
+			Err(err) => self.notify_error("Failed to save preferences", err),
+		};
+		self.update_session(|session| session.prefs_status = status);
+	}
+
+	pub fn select_theme(&self, value: String) {
+		let Some(theme) = UiTheme::parse(&value) else {
+			return;
+		};
+		self.update_prefs(|prefs| prefs.theme = theme, true);
+	}
+
+	pub fn select_font_scale(&self, value: String) {
+		let Ok(scale) = value.parse::<u16>() else {
+			return;
+		};
+		self.update_prefs(|prefs| prefs.font_scale = scale, true);
+	}
+
+	pub fn select_default_page_size(&self, value: String) {
+		let Ok(page_size) = value.parse::<usize>() else {
+			return;
+		};
+		self.update_prefs(|prefs| prefs.default_page_size = page_size, false);
+	}
+
+	pub fn select_refresh_interval(&self, value: String) {
+		let Ok(secs) = value.parse::<u64>() else {
+			return;
+		};
+		self.update_prefs(|prefs| prefs.refresh_interval = secs, false);
+	}
+
+	fn update_activity_draft<F>(&self, f: F)
+	where
+		F: FnOnce(&mut UiActivityDraft),
+	{
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let window = self.ctx.state.server.puppy.activity_window();
+		self.update_session(|session| {
+			f(session
+				.activity_draft
+				.get_or_insert_with(|| activity_draft(&window)));
+			session.activity_status.clear();
+		});
+	}
+
+	pub fn edit_activity_ranges(&self, value: String) {
+		self.update_activity_draft(|draft| draft.ranges = value);
+	}
+
+	pub fn edit_activity_utc_offset(&self, value: String) {
+		self.update_activity_draft(|draft| draft.utc_offset = value);
+	}
+
+	pub fn toggle_activity_scans(&self) {
+		self.update_activity_draft(|draft| draft.scans = !draft.scans);
+	}
+
+	pub fn toggle_activity_updates(&self) {
+		self.update_activity_draft(|draft| draft.updates = !draft.updates);
+	}
+
+	pub fn toggle_activity_syncs(&self) {
+		self.update_activity_draft(|draft| draft.syncs = !draft.syncs);
+	}
+
+	pub fn toggle_activity_hard_stop(&self) {
+		self.update_activity_draft(|draft| draft.hard_stop = !draft.hard_stop);
+	}
+
+	pub fn save_activity_window(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let puppy = &self.ctx.state.server.puppy;
+		let draft = self
+			.current_session()
+			.activity_draft
+			.unwrap_or_else(|| activity_draft(&puppy.activity_window()));
+		let status = match activity_window_from_draft(&draft) {
+			Ok(window) => {
+				let unrestricted = window.allowed.is_empty();
+				match puppy.set_activity_window(window) {
+					Ok(()) if unrestricted => String::from("Saved; heavy work may run at any time"),
+					Ok(()) => String::from("Saved"),
+					Err(err) => self.notify_error("Failed to save activity window", err),
+				}
+			}
+			Err(err) => err,
+		};
+		self.update_session(|session| session.activity_status = status);
+	}
+
+	pub fn sort_protocol(&self, column: ProtocolColumn) {
+		self.update_session(|session| session.protocol.update(ProtocolMsg::SortedBy(column)));
+	}
+
+	pub fn edit_protocol_alert(&self, value: String) {
+		self.update_session(|session| session.protocol.update(ProtocolMsg::AlertEdited(value)));
+	}
+
+	pub fn save_protocol_alert(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some(draft) = self.current_session().protocol.alert_draft else {
+			return;
+		};
+		let puppy = &self.ctx.state.server.puppy;
+		let status = match draft.trim().parse::<u64>() {
+			Ok(alert_per_min) => match puppy.set_protocol_limits(ProtocolLimits {
+				alert_per_min,
+				..puppy.protocol_limits()
+			}) {
+				Ok(()) if alert_per_min == 0 => {
+					String::from("Saved; request rate alerts and the limiter are off")
+				}
+				Ok(()) => String::from("Saved"),
+				Err(err) => self.notify_error("Failed to save request rate alert", err),
+			},
+			Err(_) => String::from("Alert rate must be a whole number of requests a minute"),
+		};
+		self.update_session(|session| session.protocol.update(ProtocolMsg::Saved(status)));
+	}
+
+	pub fn toggle_protocol_limiter(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let puppy = &self.ctx.state.server.puppy;
+		let limits = puppy.protocol_limits();
+		let status = match puppy.set_protocol_limits(ProtocolLimits {
+			limiter: !limits.limiter,
+			..limits
+		}) {
+			Ok(()) if limits.limiter => String::from("Limiter off"),
+			Ok(()) => String::from("Limiter on; peers over the alert rate are refused"),
+			Err(err) => self.notify_error("Failed to save limiter", err),
+		};
+		self.update_session(|session| session.protocol.status = status);
+	}
+
+	pub fn edit_disk_alert_threshold(&self, value: String) {
+		self.update_session(|session| {
+			session.disk_alert_draft = Some(value);
+			session.disk_alert_status.clear();
+		});
+	}
+
+	pub fn save_disk_alert_threshold(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some(draft) = self.current_session().disk_alert_draft else {
+			return;
+		};
+		let status = match draft.trim().trim_end_matches('%').trim().parse::<u8>() {
+			Ok(percent) => match self
+				.ctx
+				.state
+				.server
+				.puppy
+				.set_disk_alert_threshold(percent)
+			{
+				Ok(()) if percent == 0 => String::from("Saved; low-space alerts are off"),
+				Ok(()) => String::from("Saved"),
+				Err(err) => self.notify_error("Failed to save disk alert threshold", err),
+			},
+			Err(_) => String::from("Threshold must be a percentage between 0 and 100"),
+		};
+		self.update_session(|session| session.disk_alert_status = status);
+	}
+
+	pub fn edit_idle_timeout(&self, value: String) {
+		self.update_session(|session| {
+			session.idle_timeout_draft = Some(value);
+			session.connection_policy_status.clear();
+		});
+	}
+
+	pub fn edit_ping_interval(&self, value: String) {
+		self.update_session(|session| {
+			session.ping_interval_draft = Some(value);
+			session.connection_policy_status.clear();
+		});
+	}
+
+	pub fn save_connection_policy(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let session = self.current_session();
+		let puppy = &self.ctx.state.server.puppy;
+		let current = puppy.connection_policy();
+		let parse = |draft: Option<String>, current: u64| match draft {
+			Some(draft) => draft.trim().parse::<u64>().ok(),
+			None => Some(current),
+		};
+		let policy = parse(session.idle_timeout_draft, current.idle_timeout_secs).zip(parse(
+			session.ping_interval_draft,
+			current.ping_interval_secs,
+		));
+		let (saved, status) = match policy {
+			Some((idle_timeout_secs, ping_interval_secs)) => {
+				match puppy.set_connection_policy(ConnectionPolicy {
+					idle_timeout_secs,
+					ping_interval_secs,
+				}) {
+					Ok(()) => (true, String::from("Saved; takes effect after a restart")),
+					Err(err) => (false, self.notify_error("Failed to save", err)),
+				}
+			}
+			None => (false, String::from("Enter whole seconds")),
+		};
+		self.update_session(|session| {
+			if saved {
+				session.idle_timeout_draft = None;
+				session.ping_interval_draft = None;
+			}
+			session.connection_policy_status = status;
+		});
+	}
+
+	pub fn select_power_mode(&self, value: String) {
+		self.update_session(|session| {
+			session.power_mode_draft = Some(value);
+			session.power_policy_status.clear();
+		});
+	}
+
+	pub fn edit_power_percent(&self, value: String) {
+		self.update_session(|session| {
+			session.power_percent_draft = Some(value);
+			session.power_policy_status.clear();
+		});
+	}
+
+	pub fn save_power_policy(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let session = self.current_session();
+		let puppy = &self.ctx.state.server.puppy;
+		let current = puppy.power_policy();
+		let mode = session
+			.power_mode_draft
+			.unwrap_or_else(|| String::from(power_mode(current)));
+		let percent = match session.power_percent_draft {
+			Some(draft) => draft.trim().parse::<u8>().ok(),
+			None => match current {
+				PowerPolicy::PauseBelow { percent } => Some(percent),
+				_ => Some(DEFAULT_LOW_BATTERY_PERCENT),
+			},
+		};
+		let policy = match (mode.as_str(), percent) {
+			("always", _) => Some(PowerPolicy::Always),
+			("pause_on_battery", _) => Some(PowerPolicy::PauseOnBattery),
+			("pause_below", Some(percent)) => Some(PowerPolicy::PauseBelow { percent }),
+			_ => None,
+		};
+		let (saved, status) = match policy {
+			Some(policy) => match puppy.set_power_policy(policy) {
+				Ok(()) => (true, String::from("Saved")),
+				Err(err) => (false, self.notify_error("Failed to save", err)),
+			},
+			None => (
+				false,
+				String::from("Enter a battery level between 1 and 100"),
+			),
+		};
+		self.update_session(|session| {
+			if saved {
+				session.power_mode_draft = None;
+				session.power_percent_draft = None;
+			}
+			session.power_policy_status = status;
+		});
+	}
+
+	/// Lets background work held on battery run until it is plugged in.
+	pub fn run_background_anyway(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.ctx.state.server.puppy.run_background_anyway();
+	}
+
+	pub fn edit_config_snapshot_label(&self, value: String) {
+		self.update_session(|session| {
+			session.config_snapshot_label = value;
+			session.config_snapshot_status.clear();
+		});
+	}
+
+	pub fn toggle_config_snapshot_users(&self) {
+		self.update_session(|session| {
+			session.config_snapshot_users = !session.config_snapshot_users;
+		});
+	}
+
+	pub fn snapshot_config(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let session = self.current_session();
+		let status = match self.ctx.state.server.puppy.snapshot_config(
+			&session.config_snapshot_label,
+			session.config_snapshot_users,
+		) {
+			Ok(snapshot) => {
+				self.update_session(|session| session.config_snapshot_label.clear());
+				format!("Saved snapshot #{}: {}", snapshot.id, snapshot.summary())
+			}
+			Err(err) => self.notify_error("Failed to take snapshot", err),
+		};
+		self.update_session(|session| session.config_snapshot_status = status);
+		self.block_on(self.ctx.state.server.refresh_config_snapshots());
+	}
+
+	fn roll_back_config_snapshot(&self, idx: u32, force: bool) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let Some(config) = snapshot.config_snapshots.get(idx as usize) else {
+			return;
+		};
+		let status = match self
+			.ctx
+			.state
+			.server
+			.puppy
+			.rollback_to_snapshot(config.id, force)
+		{
+			Ok(rollback) => {
+				self.block_on(self.ctx.state.server.refresh_peers());
+				rollback_summary(&rollback)
+			}
+			Err(err) => self.notify_error("Rollback refused", err),
+		};
+		self.update_session(|session| session.config_snapshot_status = status);
+		self.block_on(self.ctx.state.server.refresh_config_snapshots());
+	}
+
+	pub fn roll_back_to_snapshot(&self, idx: u32) {
+		self.roll_back_config_snapshot(idx, false);
+	}
+
+	pub fn force_roll_back_to_snapshot(&self, idx: u32) {
+		self.roll_back_config_snapshot(idx, true);
+	}
+
+	pub fn undo_last_change(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let result = self.ctx.state.server.puppy.undo_last_change();
+		if result.is_ok() {
+			self.block_on(self.ctx.state.server.refresh_peers());
+		}
+		let status = match result {
+			Ok(rollback) => rollback_summary(&rollback),
+			Err(err) => self.notify_error("Undo failed", err),
+		};
+		self.update_session(|session| session.undo_status = status);
+		self.block_on(self.ctx.state.server.refresh_config_snapshots());
+	}
+
+	fn update_backup_draft<F>(&self, f: F)
+	where
+		F: FnOnce(&mut UiBackupDraft),
+	{
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let settings = self.ctx.state.server.puppy.backup_settings();
+		self.update_session(|session| {
+			f(session
+				.backup_draft
+				.get_or_insert_with(|| backup_draft(&settings)));
+			session.backup_status.clear();
+		});
+	}
+
+	pub fn toggle_backup_schedule(&self) {
+		self.update_backup_draft(|draft| draft.scheduled = !draft.scheduled);
+	}
+
+	pub fn edit_backup_hour(&self, value: String) {
+		self.update_backup_draft(|draft| draft.hour = value);
+	}
+
+	pub fn edit_backup_keep(&self, value: String) {
+		self.update_backup_draft(|draft| draft.keep = value);
+	}
+
+	pub fn edit_backup_dir(&self, value: String) {
+		self.update_backup_draft(|draft| draft.dir = value);
+	}
+
+	pub fn save_backup_settings(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let puppy = &self.ctx.state.server.puppy;
+		let draft = self
+			.current_session()
+			.backup_draft
+			.unwrap_or_else(|| backup_draft(&puppy.backup_settings()));
+		let status = match backup_settings_from_draft(&draft) {
+			Ok(settings) => match puppy.set_backup_settings(settings) {
+				Ok(()) => String::from("Saved"),
+				Err(err) => self.notify_error("Failed to save backup settings", err),
+			},
+			Err(err) => err,
+		};
+		self.update_session(|session| session.backup_status = status);
+	}
+
+	pub fn edit_download_dir(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session.download_dir_draft = Some(value);
+			session.download_dir_status.clear();
+		});
+	}
+
+	pub fn save_download_dir(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some(dir) = self.current_session().download_dir_draft else {
+			return;
+		};
+		let status = match self
+			.ctx
+			.state
+			.server
+			.puppy
+			.set_download_dir(None, Some(dir))
+		{
+			Ok(()) => String::from("Saved"),
+			Err(err) => self.notify_error("Failed to save download folder", err),
+		};
+		self.update_session(|session| {
+			session.download_dir_draft = None;
+			session.download_dir_status = status;
+		});
+	}
+
+	fn review_ids(&self, idx: Option<u32>) -> Vec<i64> {
+		let reviews = self.block_on(self.ctx.state.server.snapshot()).reviews;
+		match idx {
+			Some(idx) => reviews
+				.get(idx as usize)
+				.map(|review| vec![review.id])
+				.unwrap_or_default(),
+			None => self.current_session().review.selection(&reviews),
+		}
+	}
+
+	fn decide_reviews(&self, ids: Vec<i64>, decision: ReviewDecision) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		if ids.is_empty() {
+			return;
+		}
+		let msg = match self.ctx.state.server.puppy.decide_reviews(&ids, decision) {
+			Ok(count) => ReviewMsg::Decided {
+				ids,
+				status: match decision {
+					ReviewDecision::Accept => format!("Accepted {count} file(s)"),
+					ReviewDecision::Reject => format!("Rejected and removed {count} file(s)"),
+				},
+			},
+			Err(err) => ReviewMsg::Failed(self.notify_error("Failed to save review", err)),
+		};
+		self.update_session(|session| session.review.update(msg));
+		self.block_on(self.ctx.state.server.refresh_reviews());
+	}
+
+	pub fn toggle_review(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		if let Some(id) = self.review_ids(Some(idx)).pop() {
+			self.update_session(|session| session.review.update(ReviewMsg::Toggled(id)));
+		}
+	}
+
+	pub fn toggle_all_reviews(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let ids = self
+			.block_on(self.ctx.state.server.snapshot())
+			.reviews
+			.iter()
+			.map(|review| review.id)
+			.collect();
+		self.update_session(|session| session.review.update(ReviewMsg::AllToggled(ids)));
+	}
+
+	pub fn accept_review(&self, idx: u32) {
+		self.decide_reviews(self.review_ids(Some(idx)), ReviewDecision::Accept);
+	}
+
+	pub fn reject_review(&self, idx: u32) {
+		self.decide_reviews(self.review_ids(Some(idx)), ReviewDecision::Reject);
+	}
+
+	pub fn accept_selected_reviews(&self) {
+		self.decide_reviews(self.review_ids(None), ReviewDecision::Accept);
+	}
+
+	pub fn reject_selected_reviews(&self) {
+		self.decide_reviews(self.review_ids(None), ReviewDecision::Reject);
+	}
+
+	fn decide_delete_request(&self, idx: u32, decision: DeleteDecision) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some(id) = self
+			.block_on(self.ctx.state.server.snapshot())
+			.delete_requests
+			.get(idx as usize)
+			.map(|proposal| proposal.id)
+		else {
+			return;
+		};
+		let msg = match self
+			.ctx
+			.state
+			.server
+			.puppy
+			.decide_delete_proposal(id, decision)
+		{
+			Ok(proposal) => ReviewMsg::ProposalDecided(match &proposal.outcome {
+				Some(DeleteOutcome::Deleted { removed }) => {
+					format!("Deleted {removed} copy(ies) of {}", proposal.subject())
+				}
+				Some(DeleteOutcome::Failed { error }) => {
+					self.notify_error(format!("Failed to delete {}", proposal.subject()), error)
+				}
+				_ => format!("Declined deleting {}", proposal.subject()),
+			}),
+			Err(err) => ReviewMsg::Failed(self.notify_error("Failed to decide deletion", err)),
+		};
+		self.update_session(|session| session.review.update(msg));
+		self.block_on(self.ctx.state.server.refresh_reviews());
+	}
+
+	pub fn approve_delete_request(&self, idx: u32) {
+		self.decide_delete_request(idx, DeleteDecision::Approve);
+	}
+
+	pub fn reject_delete_request(&self, idx: u32) {
+		let reason = self.current_session().review.reject_reason;
+		self.decide_delete_request(idx, DeleteDecision::Reject { reason });
+	}
+
+	pub fn edit_delete_reject_reason(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| session.review.update(ReviewMsg::ReasonEdited(value)));
+	}
+
+	pub fn toggle_timeline_kind(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some(&kind) = ActivityEventKind::ALL.get(idx as usize) else {
+			return;
+		};
+		self.update_session(|session| session.timeline.update(TimelineMsg::KindToggled(kind)));
+	}
+
+	pub fn toggle_timeline_peer(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let Some(peer) = timeline_peers(&snapshot.peers)
+			.get(idx as usize)
+			.map(|peer| peer.id.clone())
+		else {
+			return;
+		};
+		self.update_session(|session| session.timeline.update(TimelineMsg::PeerToggled(peer)));
+	}
+
+	pub fn edit_timeline_text(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| session.timeline.update(TimelineMsg::TextEdited(value)));
+	}
+
+	pub fn load_more_activity(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.load_timeline(true);
+	}
+
+	pub fn refresh_timeline(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.load_timeline(false);
+	}
+
+	/// Opens the details of timeline entry `idx`. A scan opens the storage
+	/// page with its run selected for comparison.
+	pub fn open_activity(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some(event) = self
+			.current_session()
+			.timeline
+			.events()
+			.get(idx as usize)
+			.cloned()
+		else {
+			return;
+		};
+		let Some(href) = activity_href(&event) else {
+			return;
+		};
+		if event.kind == ActivityEventKind::Scan
+			&& let Some(run_id) = event.ref_id.as_deref().and_then(|id| id.parse().ok())
+		{
+			self.update_session(|session| {
+				session.scan_diff_runs = vec![run_id];
+				session.scan_diff_lines.clear();
+				session.scan_diff_status =
+					String::from("Select two scans of the same folder to compare");
+			});
+		}
+		self.ctx.push_state(href);
+	}
+
+	fn update_permission_editor(&self, msg: PermissionEditorMsg) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| session.permission_editor.update(msg));
+	}
+
+	pub fn select_permission_rule(&self, idx: u32) {
+		self.update_permission_editor(PermissionEditorMsg::Selected(idx as usize));
+	}
+
+	pub fn edit_permission_path(&self, value: String) {
+		self.update_permission_editor(PermissionEditorMsg::PathEdited(value));
+	}
+
+	pub fn toggle_permission_flag(&self, idx: u32) {
+		if let Some(&flag) = DraftFlag::ALL.get(idx as usize) {
+			self.update_permission_editor(PermissionEditorMsg::FlagToggled(flag));
+		}
+	}
+
+	pub fn edit_permission_deny_patterns(&self, value: String) {
+		self.update_permission_editor(PermissionEditorMsg::DenyPatternsEdited(value));
+	}
+
+	pub fn edit_permission_allow_patterns(&self, value: String) {
+		self.update_permission_editor(PermissionEditorMsg::AllowPatternsEdited(value));
+	}
+
+	pub fn add_permission_rule(&self) {
+		self.update_permission_editor(PermissionEditorMsg::RuleAdded);
+	}
+
+	pub fn remove_permission_rule(&self, idx: u32) {
+		self.update_permission_editor(PermissionEditorMsg::RuleRemoved(idx as usize));
+	}
+
+	pub fn toggle_permission_merge(&self) {
+		self.update_permission_editor(PermissionEditorMsg::MergeToggled);
+	}
+
+	/// Sets the trust level of the peer being edited right away, without
+	/// saving the rules.
+	pub fn select_permission_trust(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let editor = self.current_session().permission_editor;
+		let msg = match (
+			editor.peer_id().parse::<PeerId>(),
+			TrustLevel::parse(&value),
+		) {
+			(Ok(peer), Some(trust)) => {
+				match self.ctx.state.server.puppy.set_trust_level(peer, trust) {
+					Ok(()) => PermissionEditorMsg::TrustSet(trust),
+					Err(err) => {
+						PermissionEditorMsg::Failed(format!("Failed to set trust level: {err}"))
+					}
+				}
+			}
+			(Err(err), _) => PermissionEditorMsg::Failed(format!("Invalid device id: {err}")),
+			(_, None) => PermissionEditorMsg::Failed(format!("Unknown trust level {value:?}")),
+		};
+		self.update_session(|session| session.permission_editor.update(msg));
+	}
+
+	/// Saves the edited rules the way `PUT /api/peers/{id}/permissions/drafts`
+	/// does, marking the fields to fix when they don't validate.
+	pub fn save_permissions(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let editor = self.current_session().permission_editor;
+		let msg = match editor.peer_id().parse::<PeerId>() {
+			Ok(peer) => match self.ctx.state.server.puppy.save_permission_draft(
+				peer,
+				editor.draft(),
+				editor.merge(),
+				editor.revision(),
+			) {
+				Ok(overlaps) => {
+					let mut status = String::from("Permissions saved");
+					for overlap in &overlaps {
+						status.push_str(&format!("; {}", overlap.describe()));
+					}
+					PermissionEditorMsg::Saved(status)
+				}
+				Err(err) => match err.downcast::<DraftRejected>() {
+					Ok(rejected) => PermissionEditorMsg::Rejected(rejected.errors),
+					Err(err) => PermissionEditorMsg::Failed(err.to_string()),
+				},
+			},
+			Err(err) => PermissionEditorMsg::Failed(format!("Invalid device id: {err}")),
+		};
+		self.update_session(|session| session.permission_editor.update(msg));
+	}
+
+	pub fn reload_permissions(&self, peer_id: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.load_permission_editor(&peer_id);
+	}
+
+	fn update_proxy_draft<F>(&self, f: F)
+	where
+		F: FnOnce(&mut UiProxyDraft),
+	{
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let settings = self.ctx.state.server.puppy.http_proxy();
+		self.update_session(|session| {
+			f(session
+				.proxy_draft
+				.get_or_insert_with(|| proxy_draft(&settings)));
+			session.proxy_status.clear();
+		});
+	}
+
+	pub fn edit_proxy_url(&self, value: String) {
+		self.update_proxy_draft(|draft| draft.url = value);
+	}
+
+	pub fn edit_proxy_username(&self, value: String) {
+		self.update_proxy_draft(|draft| draft.username = value);
+	}
+
+	pub fn edit_proxy_password(&self, value: String) {
+		self.update_proxy_draft(|draft| draft.password = value);
+	}
+
+	pub fn save_http_proxy(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some(draft) = self.current_session().proxy_draft else {
+			return;
+		};
+		let username = draft.username.trim();
+		let credentials = (!username.is_empty()).then(|| ProxyCredentials {
+			username: username.to_string(),
+			password: draft.password,
+		});
+		let url = Some(draft.url);
+		let status = match self.ctx.state.server.puppy.set_http_proxy(url, credentials) {
+			Ok(()) => String::from("Saved"),
+			Err(err) => self.notify_error("Failed to save proxy", err),
+		};
+		self.update_session(|session| {
+			session.proxy_draft = None;
+			session.proxy_status = status;
+		});
+	}
+
+	pub fn test_http_proxy(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let status = match self.block_on(self.ctx.state.server.puppy.test_http_proxy()) {
+			Ok(status) => status,
+			Err(err) => format!("{err:#}"),
+		};
+		self.update_session(|session| session.proxy_status = status);
+	}
+
+	pub fn edit_peer_download_dir(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some(peer_id) = self
+			.block_on(self.ctx.state.server.snapshot())
+			.selected_peer
+		else {
+			return;
+		};
+		self.update_session(|session| {
+			session.peer_download_dir_draft = Some((peer_id, value));
+			session.peer_download_dir_status.clear();
+		});
+	}
+
+	/// Saves the download folder for the selected peer; an empty folder
+	/// goes back to the global default.
+	pub fn save_peer_download_dir(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some((peer_id, dir)) = self.current_session().peer_download_dir_draft else {
+			return;
+		};
+		let status = match PeerId::from_str(&peer_id) {
+			Ok(peer) => match self
+				.ctx
+				.state
+				.server
+				.puppy
+				.set_download_dir(Some(peer), Some(dir))
+			{
+				Ok(()) => String::from("Saved"),
+				Err(err) => self.notify_error("Failed to save download folder", err),
+			},
+			Err(_) => String::from("Invalid selected peer"),
+		};
+		self.update_session(|session| {
+			session.peer_download_dir_draft = None;
+			session.peer_download_dir_status = status;
+		});
+	}
+
+	pub fn edit_wake_mac(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some(peer_id) = self
+			.block_on(self.ctx.state.server.snapshot())
+			.selected_peer
+		else {
+			return;
+		};
+		self.update_session(|session| {
+			session.wake_mac_draft = Some((peer_id, value));
+			session.wake_mac_status.clear();
+		});
+	}
+
+	/// Saves the MAC address typed for the selected peer, replacing the one
+	/// learned when it connected.
+	pub fn save_wake_mac(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some((peer_id, mac)) = self.current_session().wake_mac_draft else {
+			return;
+		};
+		let (saved, status) = match PeerId::from_str(&peer_id) {
+			Ok(peer) => match self.ctx.state.server.puppy.set_wake_mac(peer, &mac) {
+				Ok(target) => (true, format!("Saved {}", target.mac)),
+				Err(err) => (false, self.notify_error("Failed to save MAC address", err)),
+			},
+			Err(_) => (false, String::from("Invalid selected peer")),
+		};
+		self.update_session(|session| {
+			if saved {
+				session.wake_mac_draft = None;
+			}
+			session.wake_mac_status = status;
+		});
+	}
+
+	pub fn run_backup_now(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let status = match self.block_on(self.ctx.state.server.puppy.backup_now()) {
+			Ok(run) => format!("Backup written to {}", run.path),
+			Err(err) => format!("{err:#}"),
+		};
+		self.update_session(|session| session.backup_status = status);
+	}
+
+	pub fn login(&self) {
+		let (username, password) = {
+			let session = self.current_session();
+			(
+				session.login_username.trim().to_string(),
+				session.login_password.clone(),
+			)
+		};
+		if let Some(token) = self.login_with_credentials(username, password) {
+			let nonce = generate_login_nonce();
+			self.ctx
+				.state
+				.pending_login_tokens
+				.lock()
+				.unwrap()
+				.insert(nonce.clone(), token);
+			self.ctx.navigate(format!("/auth/finish?nonce={nonce}"));
+		}
+	}
+
+	pub fn login_with_credentials(&self, username: String, password: String) -> Option<String> {
+		let username = username.trim().to_string();
+		if username.is_empty() || password.trim().is_empty() {
+			self.update_session(|session| {
+				session.login_username = username;
+				session.login_error = String::from("Username and password are required");
+			});
+			return None;
+		}
+		// wgui does not expose the client address, so each web UI client
+		// gets its own address bucket instead of all of them sharing one;
+		// the per-username limits and lockouts still apply across clients.
+		let source = LoginSource {
+			ip: format!("{WEB_UI_LOGIN_SOURCE} {}", self.session_key()),
+			user_agent: String::new(),
+		};
+		match self
+			.ctx
+			.state
+			.server
+			.puppy
+			.attempt_login(&username, &password, &source)
+		{
+			Ok(LoginResult::Success) => {
+				let (token, hash) = auth::generate_session_token();
+				if let Err(err) =
+					self.ctx
+						.state
+						.server
+						.puppy
+						.save_session(&hash, &username, SESSION_TTL_SECS)
+				{
+					self.update_session(|session| {
+						session.login_error = format!("Login failed: {err}");
+					});
+					return None;
+				}
+				self.update_session(|session| {
+					session.authenticated = true;
+					session.username = username.clone();
+					session.login_username = username;
+					session.login_password.clear();
+					session.login_error.clear();
+				});
+				Some(token)
+			}
+			Ok(LoginResult::InvalidCredentials) => {
+				self.update_session(|session| {
+					session.login_username = username;
+					session.login_error = String::from("Invalid credentials");
+				});
+				None
+			}
+			Ok(
+				LoginResult::Throttled { retry_after_secs }
+				| LoginResult::LockedOut { retry_after_secs },
+			) => {
+				self.update_session(|session| {
+					session.login_username = username;
+					session.login_error = format!(
+						"Too many login attempts; try again in {}",
+						human_duration(std::time::Duration::from_secs(retry_after_secs))
+					);
+				});
+				None
+			}
+			Err(err) => {
+				self.update_session(|session| {
+					session.login_username = username;
+					session.login_error = format!("Login failed: {err}");
+				});
+				None
+			}
+		}
+	}
+
+	pub fn peer_row(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let peer_id = {
+			let state = self.block_on(self.ctx.state.server.snapshot());
+			state.peers.get(idx as usize).map(|peer| peer.id.clone())
+		};
+		self.block_on(
+			self.ctx
+				.state
+				.server
+				.handle_action(UiAction::PeerRow(idx as usize)),
+		);
+		if let Some(peer_id) = peer_id {
+			self.ctx.push_state(format!("/devices/{peer_id}"));
+		}
+	}
+
+	pub fn peer_back(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.block_on(self.ctx.state.server.handle_action(UiAction::PeerBack));
+		self.ctx.push_state("/devices");
+	}
+
+	pub fn refresh_peer_files(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let Some(peer_id) = snapshot.selected_peer else {
+			return;
+		};
+		if snapshot.peer_files_path.is_empty() {
+			self.block_on(async {
+				let mut state = self.ctx.state.server.state.lock().await;
+				state.peer_roots = None;
+				state.peer_folders = None;
+			});
+		}
+		self.block_on(
+			self.ctx
+				.state
+				.server
+				.refresh_peer_files(&peer_id, &snapshot.peer_files_path),
+		);
+	}
+
+	/// Shows the images of the browsed folder as one contact sheet in the
+	/// preview modal.
+	pub fn contact_sheet_peer_files(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let Some(peer_id) = snapshot.selected_peer else {
+			return;
+		};
+		let path = snapshot.peer_files_path;
+		let result = match PeerId::from_str(&peer_id) {
+			Ok(peer) => self
+				.block_on(self.ctx.state.server.puppy.contact_sheet(
+					peer,
+					path.clone(),
+					ContactSheetOptions::default(),
+				))
+				.map_err(|err| self.notify_error("Failed to make contact sheet", err)),
+			Err(err) => Err(self.notify_error("Invalid peer id", err)),
+		};
+		let (status, image_src) = match result {
+			Ok(ContactSheetResult::Sheet(sheet)) => {
+				let encoded = base64::engine::general_purpose::STANDARD.encode(sheet.image.data);
+				let status = if sheet.partial {
+					format!(
+						"Contact sheet of {} of {} images (partial: the device ran out of time)",
+						sheet.cells, sheet.images
+					)
+				} else {
+					format!("Contact sheet of {} images", sheet.cells)
+				};
+				(
+					status,
+					format!("data:{};base64,{encoded}", sheet.image.mime_type),
+				)
+			}
+			Ok(ContactSheetResult::Empty) => {
+				(String::from("No images in this folder"), String::new())
+			}
+			Err(status) => (status, String::new()),
+		};
+		self.update_session(|session| {
+			session.file_preview_peer = peer_id;
+			session.file_preview_path = path;
+			session.file_preview_raw_path = None;
+			session.file_preview_status = status;
+			session.file_preview_content.clear();
+			session.file_preview_image_src = image_src;
+			session.file_preview_loaded = true;
+			session.file_preview_next = None;
+			session.file_preview_history.clear();
+			session.file_preview_download_status.clear();
+			session.file_preview_media.clear();
+			session.file_preview_large_image = None;
+			session.file_preview_contact_sheet = true;
+			session.file_preview_modal_open = true;
+		});
+	}
+
+	pub fn refresh_audio(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let Some(peer_id) = snapshot.selected_peer else {
+			self.update_session(|session| {
+				session.audio_status = String::from("Select a peer first");
+			});
+			return;
+		};
+		self.block_on(self.ctx.state.server.refresh_peer_audio(&peer_id));
+	}
+
+	pub fn refresh_webcams(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let Some(peer_id) = snapshot.selected_peer else {
+			self.update_session(|session| {
+				session.webcam_status = String::from("Select a peer first");
+			});
+			return;
+		};
+		self.block_on(self.ctx.state.server.refresh_peer_webcams(&peer_id));
+	}
+
+	pub fn view_webcam(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let Some(device) = snapshot.peer_webcams.get(idx as usize) else {
+			self.update_session(|session| {
+				session.webcam_status = String::from("Unknown webcam device");
+			});
+			return;
+		};
+		let device_id = device.id.clone();
+		let device_name = device.name.clone();
+		self.update_session(|session| {
+			session.webcam_selected_device = device_id;
+			session.webcam_status = format!("Viewing {device_name}");
+		});
+	}
+
+	pub fn select_microphone(&self, source_id: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let Some(source) = snapshot
+			.peer_microphones
+			.iter()
+			.find(|source| source.id == source_id)
+		else {
+			self.update_session(|session| {
+				session.microphone_selected_device.clear();
+			});
+			return;
+		};
+		let id = source.id.clone();
+		self.update_session(|session| {
+			session.microphone_selected_device = id;
+		});
+	}
+
+	pub fn set_audio_volume(&self, value: i32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let Some(peer_id) = snapshot.selected_peer else {
+			self.update_session(|session| {
+				session.audio_status = String::from("Select a peer first");
+			});
+			return;
+		};
+		if !audio_supported(snapshot.peer_audio_capability.as_ref()) {
+			self.update_session(|session| {
+				session.audio_status =
+					audio_capability_status(snapshot.peer_audio_capability.as_ref());
+			});
+			return;
+		}
+		let Ok(peer) = PeerId::from_str(&peer_id) else {
+			self.update_session(|session| {
+				session.audio_status = String::from("Invalid selected peer");
+			});
+			return;
+		};
+		let volume = value.clamp(0, 100) as u8;
+		match self.block_on(
+			self.ctx
+				.state
+				.server
+				.puppy
+				.set_audio_volume(peer, None, volume),
+		) {
+			Ok(devices) => {
+				self.block_on(self.ctx.state.server.set_peer_audio_devices(devices));
+				self.update_session(|session| {
+					session.audio_status = format!("Set volume to {volume}%");
+				});
+			}
+			Err(err) => {
+				self.update_session(|session| {
+					session.audio_status = self.notify_error("Failed to set volume", err);
+				});
+			}
+		}
+	}
+
+	pub fn toggle_audio_mute(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let Some(peer_id) = snapshot.selected_peer else {
+			self.update_session(|session| {
+				session.audio_status = String::from("Select a peer first");
+			});
+			return;
+		};
+		if !audio_supported(snapshot.peer_audio_capability.as_ref()) {
+			self.update_session(|session| {
+				session.audio_status =
+					audio_capability_status(snapshot.peer_audio_capability.as_ref());
+			});
+			return;
+		}
+		let Ok(peer) = PeerId::from_str(&peer_id) else {
+			self.update_session(|session| {
+				session.audio_status = String::from("Invalid selected peer");
+			});
+			return;
+		};
+		let muted = !default_audio_output(&snapshot.peer_audio_devices)
+			.map(|device| device.muted)
+			.unwrap_or(false);
+		match self.block_on(
+			self.ctx
+				.state
+				.server
+				.puppy
+				.set_audio_muted(peer, None, muted),
+		) {
+			Ok(devices) => {
+				self.block_on(self.ctx.state.server.set_peer_audio_devices(devices));
+				self.update_session(|session| {
+					session.audio_status = if muted {
+						String::from("Muted default output")
+					} else {
+						String::from("Unmuted default output")
+					};
+				});
+			}
+			Err(err) => {
+				self.update_session(|session| {
+					session.audio_status = self.notify_error("Failed to change mute", err);
+				});
+			}
+		}
+	}
+
+	pub fn select_audio_device(&self, device_id: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let Some(peer_id) = snapshot.selected_peer else {
+			self.update_session(|session| {
+				session.audio_status = String::from("Select a peer first");
+			});
+			return;
+		};
+		if !audio_supported(snapshot.peer_audio_capability.as_ref()) {
+			self.update_session(|session| {
+				session.audio_status =
+					audio_capability_status(snapshot.peer_audio_capability.as_ref());
+			});
+			return;
+		}
+		let Some(device) = snapshot
+			.peer_audio_devices
+			.iter()
+			.find(|device| device.id == device_id)
+		else {
+			self.update_session(|session| {
+				session.audio_status = String::from("Unknown audio device");
+			});
+			return;
+		};
+		if !matches!(device.kind, AudioDeviceKind::Sink) {
+			self.update_session(|session| {
+				session.audio_status = String::from("Only output devices can be selected");
+			});
+			return;
+		}
+		let Ok(peer) = PeerId::from_str(&peer_id) else {
+			self.update_session(|session| {
+				session.audio_status = String::from("Invalid selected peer");
+			});
+			return;
+		};
+		let device_name = device.name.clone();
+		match self.block_on(
+			self.ctx
+				.state
+				.server
+				.puppy
+				.set_default_audio_device(peer, device_id),
+		) {
+			Ok(devices) => {
+				self.block_on(self.ctx.state.server.set_peer_audio_devices(devices));
+				self.update_session(|session| {
+					session.audio_status = format!("Selected {device_name}");
+				});
+			}
+			Err(err) => {
+				self.update_session(|session| {
+					session.audio_status = self.notify_error("Failed to select audio output", err);
+				});
+			}
+		}
+	}
+
+	pub fn refresh_peers(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.block_on(self.ctx.state.server.handle_action(UiAction::RefreshPeers));
+	}
+
+	pub fn refresh_files(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.block_on(self.ctx.state.server.handle_action(UiAction::RefreshFiles));
+	}
+
+	pub fn preview_local_file(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let hash = {
+			let state = self.block_on(self.ctx.state.server.snapshot());
+			state.files.get(idx as usize).map(|entry| entry.hash)
+		};
+		let Some(hash) = hash else {
+			return;
+		};
+		match self
+			.ctx
+			.state
+			.server
+			.puppy
+			.resolve_local_file_by_hash(&hash)
+		{
+			Ok(Some((path, _entry))) => {
+				self.update_session(|session| {
+					session.file_preview_peer.clear();
+					session.file_preview_path = path.to_string_lossy().into_owned();
+					session.file_preview_raw_path =
+						path.to_str().is_none().then(|| path_bytes(&path));
+					session.file_preview_status.clear();
+					session.file_preview_content.clear();
+					session.file_preview_image_src.clear();
+					session.file_preview_loaded = false;
+					session.file_preview_modal_open = true;
+				});
+				self.load_file_preview();
+			}
+			Ok(None) => {
+				self.update_session(|session| {
+					session.file_preview_modal_open = true;
+					session.file_preview_status =
+						String::from("Local file path not found for selected hash");
+					session.file_preview_content.clear();
+					session.file_preview_image_src.clear();
+					session.file_preview_loaded = false;
+				});
+			}
+			Err(err) => {
+				self.update_session(|session| {
+					session.file_preview_modal_open = true;
+					session.file_preview_status = self.notify_error("Failed to resolve file", err);
+					session.file_preview_content.clear();
+					session.file_preview_image_src.clear();
+					session.file_preview_loaded = false;
+				});
+			}
+		}
+	}
+
+	pub fn preview_peer_file(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let target = {
+			let state = self.block_on(self.ctx.state.server.snapshot());
+			let Some(peer_id) = state.selected_peer else {
+				return;
+			};
+			let windows = state
+				.peer_roots
+				.as_ref()
+				.is_some_and(|(roots_peer, roots)| *roots_peer == peer_id && roots.windows);
+			state.peer_files.get(idx as usize).and_then(|entry| {
+				if entry.is_dir {
+					None
+				} else {
+					let raw_path = if entry.has_undecodable_name() {
+						Some(child_peer_file_raw_path(
+							&state.peer_files_path,
+							&entry.name_raw,
+							windows,
+						)?)
+					} else {
+						None
+					};
+					let is_image = entry
+						.mime
+						.as_deref()
+						.is_some_and(|mime| mime.starts_with("image/"));
+					Some((
+						peer_id,
+						child_peer_file_path(&state.peer_files_path, &entry.name, windows)?,
+						raw_path,
+						is_image,
+					))
+				}
+			})
+		};
+		let Some((peer_id, path, raw_path, is_image)) = target else {
+			return;
+		};
+		// The listing may know a file is an image when its name doesn't
+		// say so; go straight to the thumbnail then.
+		let thumbnail = PeerId::from_str(&peer_id)
+			.ok()
+			.filter(|_| is_image && raw_path.is_none())
+			.map(|peer| (peer, path.clone(), peer_id.clone()));
+		self.update_session(|session| {
+			session.file_preview_peer = peer_id;
+			session.file_preview_path = path;
+			session.file_preview_raw_path = raw_path;
+			session.file_preview_status.clear();
+			session.file_preview_content.clear();
+			session.file_preview_image_src.clear();
+			session.file_preview_loaded = false;
+			session.file_preview_modal_open = true;
+		});
+		match thumbnail {
+			Some((peer, path, peer_label)) => {
+				self.update_session(|session| {
+					session.file_preview_history.clear();
+					session.file_preview_download_status.clear();
+					session.file_preview_previous_download = None;
+					session.file_preview_media.clear();
+				});
+				self.load_image_preview(peer, path, peer_label);
+			}
+			None => self.load_file_preview(),
+		}
+	}
+
+	/// Pins `path` of `peer_id` for offline use, mirrored into the folder
+	/// `name` in the peer's download folder.
+	fn pin_remote_folder(&self, peer_id: &str, path: &str, name: &str) {
+		let puppy = &self.ctx.state.server.puppy;
+		let result = PeerId::from_str(peer_id)
+			.map_err(anyhow::Error::from)
+			.and_then(|peer| {
+				let dest = puppy.download_dir(Some(peer))?.join(name);
+				puppy.pin_remote_folder(peer, path, dest, PinOptions::default())
+			});
+		self.block_on(async {
+			let mut state = self.ctx.state.server.state.lock().await;
+			state.status = match result {
+				Ok(pin) => format!("Pinned {path} to {}", pin.local_dest),
+				Err(err) => self.notify_error(format!("Failed to pin {path}"), format!("{err:#}")),
+			};
+		});
+		self.block_on(self.ctx.state.server.refresh_pins());
+	}
+
+	/// Pins the folder at `idx` for offline use, mirrored into a folder of
+	/// the same name in the peer's download folder.
+	pub fn pin_peer_folder(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let target = {
+			let state = self.block_on(self.ctx.state.server.snapshot());
+			let Some(peer_id) = state.selected_peer else {
+				return;
+			};
+			let windows = state
+				.peer_roots
+				.as_ref()
+				.is_some_and(|(roots_peer, roots)| *roots_peer == peer_id && roots.windows);
+			state
+				.peer_files
+				.get(idx as usize)
+				.filter(|entry| entry.is_dir && !entry.has_undecodable_name())
+				.and_then(|entry| {
+					let name = safe_entry_name(&entry.name)?.to_string();
+					let path = child_peer_file_path(&state.peer_files_path, &entry.name, windows)?;
+					Some((peer_id, path, name))
+				})
+		};
+		let Some((peer_id, path, name)) = target else {
+			return;
+		};
+		self.pin_remote_folder(&peer_id, &path, &name);
+	}
+
+	/// Pins the shared folder on the card at `idx` for offline use,
+	/// mirrored into a folder of the same name in the peer's download
+	/// folder.
+	pub fn pin_shared_folder(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let state = self.block_on(self.ctx.state.server.snapshot());
+		let Some(peer_id) = state.selected_peer.clone() else {
+			return;
+		};
+		let Some(card) = peer_share_cards(&state, &peer_id)
+			.into_iter()
+			.nth(idx as usize)
+		else {
+			return;
+		};
+		let name = card
+			.root
+			.rsplit(['/', '\\'])
+			.find(|part| !part.is_empty())
+			.and_then(safe_entry_name)
+			.map(str::to_string);
+		let Some(name) = name else {
+			self.block_on(async {
+				self.ctx.state.server.state.lock().await.status =
+					format!("Can't pin {}: no folder name to mirror it into", card.root);
+			});
+			return;
+		};
+		self.pin_remote_folder(&peer_id, &card.root, &name);
+	}
+
+	pub fn edit_peer_files_search(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| session.peer_files.update(PeerFilesMsg::QueryEdited(value)));
+	}
+
+	/// Searches the index for files of `peer_id` under `path` with the
+	/// session's query, for the device file browser to show.
+	fn search_peer_folder(&self, state: &UiState, peer_id: String, path: String) {
+		let Ok(peer) = PeerId::from_str(&peer_id) else {
+			return;
+		};
+		let peer_name = |id: &str| {
+			state
+				.peers
+				.iter()
+				.find(|peer| peer.id == id)
+				.map(|peer| peer.name.clone())
+				.filter(|name| !name.is_empty())
+		};
+		let device = peer_name(&peer_id).unwrap_or_else(|| abbrev_peer_id(&peer_id));
+		let session = self.current_session();
+		let query = session.peer_files.query;
+		let args = SearchFilesArgs {
+			name_query: Some(query.trim().to_string()).filter(|query| !query.is_empty()),
+			node_id: peer_to_node_id(&peer),
+			path_prefix: Some(path.clone()).filter(|path| !path.is_empty()),
+			include_deleted: session.peer_files.include_deleted,
+			sort_desc: true,
+			page_size: SCOPED_SEARCH_LIMIT,
+			..Default::default()
+		};
+		let puppy = &self.ctx.state.server.puppy;
+		let (results, status) = match puppy.search_files(args) {
+			Ok((page, _, total)) => {
+				let indexed_at = puppy.index_updated_at(peer).unwrap_or_else(|err| {
+					tracing::warn!("{err}");
+					None
+				});
+				let status =
+					scoped_search_status(page.rows.len(), total, indexed_at, chrono::Utc::now());
+				let rows = page
+					.rows
+					.into_iter()
+					.map(|result| {
+						let provenance = puppy
+							.file_provenance(&result.hash, &result.path)
+							.unwrap_or_else(|err| {
+								tracing::warn!("{err}");
+								None
+							})
+							.map(|provenance| {
+								let name = provenance.peer.as_deref().and_then(peer_name);
+								provenance.describe(&device, name.as_deref())
+							})
+							.unwrap_or_default();
+						scoped_search_row(result, &peer_id, provenance)
+					})
+					.collect();
+				(rows, status)
+			}
+			Err(err) => (Vec::new(), self.notify_error("Search failed", err)),
+		};
+		self.update_session(|session| {
+			session
+				.peer_files
+				.update(PeerFilesMsg::Searched(ScopedSearch {
+					peer_id,
+					path,
+					results,
+					status,
+				}));
+		});
+	}
+
+	/// Searches the index for files under the folder the browser shows,
+	/// on the device it shows. `*` and `?` work as wildcards.
+	pub fn run_peer_files_search(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let state = self.block_on(self.ctx.state.server.snapshot());
+		let Page::PeerFiles { peer_id, path } = state.page.clone() else {
+			return;
+		};
+		self.search_peer_folder(&state, peer_id, path);
+	}
+
+	/// Lists every indexed file of the shared folder on the card at `idx`
+	/// and opens the device file browser there to show them.
+	pub fn search_shared_folder(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let state = self.block_on(self.ctx.state.server.snapshot());
+		let Some(peer_id) = state.selected_peer.clone() else {
+			return;
+		};
+		let Some(card) = peer_share_cards(&state, &peer_id)
+			.into_iter()
+			.nth(idx as usize)
+		else {
+			return;
+		};
+		self.update_session(|session| {
+			session
+				.peer_files
+				.update(PeerFilesMsg::QueryEdited(String::new()))
+		});
+		self.search_peer_folder(&state, peer_id.clone(), card.root.clone());
+		self.ctx.push_state(peer_files_href(&peer_id, &card.root));
+	}
+
+	pub fn clear_peer_files_search(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| session.peer_files.update(PeerFilesMsg::Cleared));
+	}
+
+	/// Shows or hides the message asking the owner of the browsed device
+	/// for the access it just refused.
+	pub fn toggle_peer_files_access_request(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session
+				.peer_files
+				.update(PeerFilesMsg::AccessRequestToggled)
+		});
+	}
+
+	/// Shows or hides where the search result at `idx` came from.
+	pub fn toggle_peer_files_provenance(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session
+				.peer_files
+				.update(PeerFilesMsg::ProvenanceToggled(idx as usize))
+		});
+	}
+
+	/// Shows or hides files the index saw deleted, searching again when a
+	/// search is shown.
+	pub fn toggle_peer_files_deleted(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let mut searched = false;
+		self.update_session(|session| {
+			searched = session.peer_files.search_active();
+			session.peer_files.update(PeerFilesMsg::DeletedToggled);
+		});
+		if searched {
+			self.run_peer_files_search();
+		}
+	}
+
+	/// Applies `change` to how the browsed folder shows, and remembers it
+	/// for that folder.
+	fn change_peer_files_view<F>(&self, change: F)
+	where
+		F: FnOnce(&mut DirView),
+	{
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let (page, mut view) = self.block_on(async {
+			let state = self.ctx.state.server.state.lock().await;
+			(state.page.clone(), state.peer_files_view)
+		});
+		let Page::PeerFiles { peer_id, path } = page else {
+			return;
+		};
+		change(&mut view);
+		let saved = {
+			let mut prefs = self.ctx.state.prefs.lock().unwrap();
+			prefs.set_dir_view(&peer_id, &path, view);
+			prefs.save(&self.ctx.state.prefs_path)
+		};
+		if let Err(err) = saved {
+			self.notify_error("Failed to save the folder view", err);
+		}
+		self.block_on(self.ctx.state.server.show_peer_files(view));
+	}
+
+	pub fn select_peer_files_sort(&self, value: String) {
+		let Some(sort) = DirSort::parse(&value) else {
+			return;
+		};
+		self.change_peer_files_view(|view| view.sort = sort);
+	}
+
+	pub fn toggle_peer_files_hidden(&self) {
+		self.change_peer_files_view(|view| view.show_hidden = !view.show_hidden);
+	}
+
+	/// Turns thumbnails of the browsed folder on or off; off, those not
+	/// fetched yet are dropped on the next render.
+	pub fn toggle_peer_files_thumbnails(&self) {
+		self.change_peer_files_view(|view| view.thumbnails = !view.thumbnails);
+	}
+
+	pub fn toggle_peer_files_disk_sizes(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session.peer_files.update(PeerFilesMsg::DiskSizesToggled);
+		});
+	}
+
+	/// Renders more rows of a long folder once its list is scrolled to the
+	/// end of the rendered ones.
+	pub fn show_more_peer_files(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| session.peer_files.update(PeerFilesMsg::ShowMore));
+	}
+
+	/// Browses to the folder holding a search result with the file marked.
+	pub fn open_peer_files_search_result(&self, peer_id: &str, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some(row) = self
+			.current_session()
+			.peer_files
+			.search_for(peer_id)
+			.and_then(|search| search.results.get(idx as usize).cloned())
+		else {
+			return;
+		};
+		let windows = row.path.contains('\\');
+		let parent = parent_peer_file_path(&row.path, windows).unwrap_or_default();
+		self.update_session(|session| {
+			session
+				.peer_files
+				.update(PeerFilesMsg::Highlighted(row.name))
+		});
+		self.ctx.push_state(peer_files_href(&row.peer_id, &parent));
+	}
+
+	pub fn refresh_storage(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.block_on(async {
+			self.ctx
+				.state
+				.server
+				.handle_action(UiAction::RefreshStorage)
+				.await;
+			self.lay_out_treemap().await;
+		});
+	}
+
+	pub fn edit_scan_path(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session.scan_path = value;
+		});
+	}
+
+	pub fn edit_scan_read_limit(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session.scan_read_limit = value;
+		});
+	}
+
+	pub fn toggle_scan_low_priority(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session.scan_low_priority = !session.scan_low_priority;
+		});
+	}
+
+	pub fn pick_scan_folder(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let folder = self
+			.block_on(self.ctx.state.server.snapshot())
+			.local_folders
+			.get(idx as usize)
+			.cloned();
+		if let Some(folder) = folder {
+			self.update_session(|session| {
+				session.scan_path = folder.path;
+			});
+		}
+	}
+
+	pub fn start_scan(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let session = self.current_session();
+		let path = session.scan_path.trim().to_string();
+		if path.is_empty() {
+			self.update_session(|session| {
+				session.scan_status = String::from("Enter a folder to scan");
+			});
+			return;
+		}
+		let limits = match scan_overrides(&session) {
+			Ok(limits) => limits,
+			Err(err) => {
+				self.update_session(|session| session.scan_status = err);
+				return;
+			}
+		};
+		let puppy = &self.ctx.state.server.puppy;
+		match puppy.scan_folder_with_options(path.clone(), false, false, limits) {
+			Ok(handle) => {
+				let reporter = self.ctx.state.jobs.start(
+					puppy.next_id(IdKind::Job),
+					format!("Scan {path}"),
+					Some(handle.cancel_flag()),
+				);
+				forward_scan_events(
+					reporter,
+					handle.receiver(),
+					Arc::clone(&self.ctx.state.server),
+				);
+				self.update_session(|session| {
+					session.scan_path.clear();
+					session.scan_status = format!("Scanning {path}; progress is listed under Jobs");
+				});
+			}
+			Err(err) => {
+				self.update_session(|session| {
+					session.scan_status = self.notify_error("Failed to start scan", err);
+				});
+			}
+		}
+	}
+
+	pub fn gc_store(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let status = match self.ctx.state.server.puppy.gc_store() {
+			Ok(report) if report.in_use > 0 => format!(
+				"Removed {} unused file(s), freed {}; {} in use",
+				report.removed,
+				human_size(report.freed_bytes, SizeUnits::Binary),
+				report.in_use
+			),
+			Ok(report) => format!(
+				"Removed {} unused file(s), freed {}",
+				report.removed,
+				human_size(report.freed_bytes, SizeUnits::Binary)
+			),
+			Err(err) => self.notify_error("Store cleanup failed", err),
+		};
+		self.update_session(|session| session.store_status = status);
 	}
 
-	pub(super) fn files_state(&self) -> UiViewState {
-		self.state_for_page(Page::Files)
+	fn update_ingest_draft<F>(&self, f: F)
+	where
+		F: FnOnce(&mut UiIngestDraft),
+	{
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let settings = self.ctx.state.server.puppy.ingest_settings();
+		self.update_session(|session| {
+			f(session
+				.ingest_draft
+				.get_or_insert_with(|| ingest_draft(&settings)));
+			session.ingest_status.clear();
+		});
 	}
 
-	pub(super) fn home_state(&self) -> UiViewState {
-		self.state_for_page(Page::Home)
+	pub fn edit_ingest_destination(&self, value: String) {
+		self.update_ingest_draft(|draft| draft.destination = value);
 	}
 
-	pub(super) fn peer_state(&self, peer_id: String) -> UiViewState {
-		let should_refresh =
-			self.block_on(self.ctx.state.server.snapshot())
-				.selected_peer
-				.as_deref() != Some(peer_id.as_str());
-		self.block_on(
-			self.ctx
-				.state
-				.server
-				.set_page(Page::PeerDetail(peer_id.clone())),
-		);
-		if should_refresh {
-			self.block_on(self.ctx.state.server.refresh_peer_detail(&peer_id));
-		}
-		self.state()
+	pub fn edit_ingest_patterns(&self, value: String) {
+		self.update_ingest_draft(|draft| draft.patterns = value);
 	}
 
-	pub(super) fn peer_control_state(&self, peer_id: String) -> UiViewState {
-		let snapshot = self.block_on(self.ctx.state.server.snapshot());
-		let page = Page::PeerControl {
-			peer_id: peer_id.clone(),
+	pub fn toggle_ingest_delete_originals(&self) {
+		self.update_ingest_draft(|draft| draft.delete_originals = !draft.delete_originals);
+	}
+
+	pub fn toggle_ingest_eject(&self) {
+		self.update_ingest_draft(|draft| draft.eject_when_done = !draft.eject_when_done);
+	}
+
+	/// Saves the ingest settings and ingests the `idx`th removable drive as a
+	/// job.
+	pub fn ingest_drive(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let puppy = Arc::clone(&self.ctx.state.server.puppy);
+		let Some(drive) = puppy.removable_drives().into_iter().nth(idx as usize) else {
+			self.update_session(|session| {
+				session.ingest_status = String::from("The drive is no longer attached");
+			});
+			return;
 		};
-		let should_refresh = snapshot.page != page;
-		self.block_on(self.ctx.state.server.set_page(page));
-		if should_refresh {
-			self.block_on(self.ctx.state.server.refresh_peer_screens(&peer_id));
+		let draft = self
+			.current_session()
+			.ingest_draft
+			.unwrap_or_else(|| ingest_draft(&puppy.ingest_settings()));
+		let settings = ingest_settings_from_draft(&draft);
+		if let Err(err) = puppy.set_ingest_settings(settings.clone()) {
+			let status = self.notify_error("Failed to save ingest settings", err);
+			self.update_session(|session| session.ingest_status = status);
+			return;
 		}
-		self.state()
+		let label = drive_label(&drive.disk);
+		let cancel = Arc::new(AtomicBool::new(false));
+		let reporter = self.ctx.state.jobs.start(
+			puppy.next_id(IdKind::Job),
+			format!("Ingest {label}"),
+			Some(Arc::clone(&cancel)),
+		);
+		let server = Arc::clone(&self.ctx.state.server);
+		tokio::spawn(async move {
+			let progress = {
+				let reporter = reporter.clone();
+				move |progress: &IngestProgress| {
+					reporter.progress(
+						JobProgress::Determinate {
+							done: progress.done as u64,
+							total: progress.total as u64,
+						},
+						progress.line(),
+					)
+				}
+			};
+			let result = puppy
+				.ingest_drive(&drive.disk.id, settings, cancel, progress)
+				.await;
+			if let Some(report) = result.as_ref().ok().filter(|report| report.interrupted) {
+				server.notify(
+					Severity::Warning,
+					"/storage",
+					"Ingest interrupted",
+					report.summary(),
+				);
+			}
+			reporter.finish(
+				result
+					.map(|report| report.summary())
+					.map_err(|err| format!("{err:#}")),
+			);
+		});
+		self.update_session(|session| {
+			session.ingest_status = format!("Ingesting {label}; progress is listed under Jobs");
+		});
 	}
 
-	pub(super) fn peer_files_state(&self, peer_id: String, path: String) -> UiViewState {
-		let path = normalize_peer_file_path(path);
-		let snapshot = self.block_on(self.ctx.state.server.snapshot());
-		let page = Page::PeerFiles {
-			peer_id: peer_id.clone(),
-			path: path.clone(),
-		};
-		let should_refresh = snapshot.page != page;
-		self.block_on(self.ctx.state.server.set_page(page));
-		if should_refresh {
-			self.block_on(self.ctx.state.server.refresh_peer_files(&peer_id, &path));
+	fn update_integrity_draft<F>(&self, f: F)
+	where
+		F: FnOnce(&mut UiIntegrityDraft),
+	{
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
 		}
-		self.state()
+		let settings = self.ctx.state.server.puppy.integrity_settings();
+		self.update_session(|session| {
+			f(session
+				.integrity_draft
+				.get_or_insert_with(|| integrity_draft(&settings)));
+			session.integrity_status.clear();
+		});
 	}
 
-	pub(super) fn peer_webcams_state(&self, peer_id: String) -> UiViewState {
-		let snapshot = self.block_on(self.ctx.state.server.snapshot());
-		let page = Page::PeerWebcams {
-			peer_id: peer_id.clone(),
+	pub fn toggle_integrity_sampling(&self) {
+		self.update_integrity_draft(|draft| draft.enabled = !draft.enabled);
+	}
+
+	pub fn edit_integrity_files_per_cycle(&self, value: String) {
+		self.update_integrity_draft(|draft| draft.files_per_cycle = value);
+	}
+
+	pub fn edit_integrity_read_rate(&self, value: String) {
+		self.update_integrity_draft(|draft| draft.read_rate = value);
+	}
+
+	pub fn save_integrity_settings(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some(draft) = self.current_session().integrity_draft else {
+			return;
 		};
-		let should_refresh = snapshot.page != page;
-		self.block_on(self.ctx.state.server.set_page(page));
-		if should_refresh {
-			self.block_on(self.ctx.state.server.refresh_peer_webcams(&peer_id));
+		let result = integrity_settings_from_draft(&draft).and_then(|settings| {
+			self.ctx
+				.state
+				.server
+				.puppy
+				.set_integrity_settings(settings)
+				.map_err(|err| self.notify_error("Failed to save integrity settings", err))
+		});
+		self.update_session(|session| match result {
+			Ok(()) => {
+				session.integrity_draft = None;
+				session.integrity_status = String::from("Integrity settings saved");
+			}
+			Err(status) => session.integrity_status = status,
+		});
+	}
+
+	/// Browses to another copy of a damaged file with the file marked.
+	pub fn open_integrity_replica(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
 		}
-		self.state()
+		let findings = match self.ctx.state.server.puppy.integrity_findings() {
+			Ok(findings) => findings,
+			Err(err) => {
+				let status = self.notify_error("Failed to load integrity findings", err);
+				self.update_session(|session| session.integrity_status = status);
+				return;
+			}
+		};
+		let local_node = self
+			.block_on(self.ctx.state.server.snapshot())
+			.local_peer_id
+			.as_deref()
+			.map(peer_to_node_id_hex)
+			.unwrap_or_default();
+		let Some(replica) = integrity_rows(&findings, &local_node, chrono::Utc::now())
+			.into_iter()
+			.nth(idx as usize)
+			.and_then(|(_, replica)| replica.cloned())
+		else {
+			return;
+		};
+		let Some(peer) = self.resolve_peer_ref(&hex(&replica.node_id)) else {
+			self.update_session(|session| {
+				session.integrity_status =
+					String::from("The device holding that copy is not one of your devices");
+			});
+			return;
+		};
+		let path = replica.path.to_string_lossy().into_owned();
+		let parent = parent_peer_file_path(&path, path.contains('\\')).unwrap_or_default();
+		let name = path
+			.rsplit(['/', '\\'])
+			.next()
+			.unwrap_or_default()
+			.to_string();
+		self.update_session(|session| session.peer_files.update(PeerFilesMsg::Highlighted(name)));
+		self.ctx
+			.push_state(peer_files_href(&peer.to_string(), &parent));
 	}
 
-	pub(super) fn peers_state(&self) -> UiViewState {
-		self.state_for_page(Page::Peers)
+	fn set_remote_access_suspended(&self, suspended: bool) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let puppy = &self.ctx.state.server.puppy;
+		let result = if suspended {
+			puppy.suspend_remote_access()
+		} else {
+			puppy.resume_remote_access()
+		};
+		let status = match result {
+			Ok(()) => {
+				self.block_on(self.ctx.state.server.refresh_peers());
+				if suspended {
+					String::from("Remote access suspended")
+				} else {
+					String::from("Remote access resumed; previous grants apply again")
+				}
+			}
+			Err(err) => self.notify_error("Failed to change remote access", err),
+		};
+		self.update_session(|session| session.remote_access_status = status);
 	}
 
-	pub(super) fn search_state(&self) -> UiViewState {
-		self.state_for_page(Page::Search)
+	pub fn suspend_remote_access(&self) {
+		self.set_remote_access_suspended(true);
 	}
 
-	pub(super) fn settings_state(&self) -> UiViewState {
-		self.state_for_page(Page::Settings)
+	pub fn resume_remote_access(&self) {
+		self.set_remote_access_suspended(false);
 	}
 
-	pub(super) fn storage_state(&self) -> UiViewState {
-		self.state_for_page(Page::Storage)
+	pub fn adopt_previous_identity(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let status = match self.block_on(self.ctx.state.server.puppy.adopt_previous_identity()) {
+			Ok(()) => {
+				self.block_on(self.ctx.state.server.refresh_peers());
+				String::from("Adopted the previous identity")
+			}
+			Err(err) => {
+				self.notify_error("Failed to adopt the previous identity", format!("{err:#}"))
+			}
+		};
+		self.update_session(|session| session.identity_status = status);
 	}
 
-	pub(super) fn updates_state(&self) -> UiViewState {
-		self.state_for_page(Page::Updates)
+	pub fn start_fresh_identity(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let status = match self.block_on(self.ctx.state.server.puppy.start_fresh_identity()) {
+			Ok(()) => {
+				self.block_on(self.ctx.state.server.refresh_peers());
+				String::from("Started a fresh identity; the previous node is kept as another node")
+			}
+			Err(err) => self.notify_error("Failed to start a fresh identity", format!("{err:#}")),
+		};
+		self.update_session(|session| session.identity_status = status);
 	}
 
-	pub(super) fn users_state(&self) -> UiViewState {
-		self.state_for_page(Page::Users)
+	fn set_nat_mapping(&self, enabled: bool) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let status = match self.ctx.state.server.puppy.set_nat_port_mapping(enabled) {
+			Ok(()) => {
+				self.block_on(self.ctx.state.server.refresh_peers());
+				if enabled {
+					String::from("Port mapping enabled")
+				} else {
+					String::from("Port mapping disabled and removed from the router")
+				}
+			}
+			Err(err) => self.notify_error("Failed to change port mapping", err),
+		};
+		self.update_session(|session| session.nat_mapping_status = status);
 	}
 
-	pub fn logout(&self) {
-		if let Some(session_id) = self.ctx.session_id() {
-			let hash = auth::token_hash(&session_id);
-			let _ = self.ctx.state.server.puppy.drop_session(&hash);
+	pub fn enable_nat_mapping(&self) {
+		self.set_nat_mapping(true);
+	}
+
+	pub fn test_reachability(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
 		}
-		self.update_session(|session| {
-			session.authenticated = false;
-			session.username.clear();
-		});
-		self.ctx.navigate("/auth/logout");
+		let status = match self.block_on(self.ctx.state.server.puppy.test_reachability()) {
+			Ok(results) if results.is_empty() => String::from("No listen addresses to test"),
+			Ok(results)
+				if results
+					.iter()
+					.all(|result| result.status == Reachability::Untested) =>
+			{
+				String::from("No peer available to test")
+			}
+			Ok(results) => format!("Tested {} address(es)", results.len()),
+			Err(err) => self.notify_error("Reachability test failed", format!("{err:#}")),
+		};
+		self.block_on(self.ctx.state.server.refresh_peers());
+		self.update_session(|session| session.reachability_status = status);
 	}
 
-	pub fn edit_login_username(&self, value: String) {
+	pub fn run_diagnostics(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let report = self.block_on(self.ctx.state.server.puppy.run_diagnostics());
+		let status = format!(
+			"{} passed, {} warnings, {} failed",
+			report.count(DiagnosticStatus::Pass),
+			report.count(DiagnosticStatus::Warn),
+			report.count(DiagnosticStatus::Fail),
+		);
 		self.update_session(|session| {
-			session.login_username = value;
-			session.login_error.clear();
+			session.diagnostics = Some(report);
+			session.diagnostics_status = status;
 		});
 	}
 
-	pub fn edit_login_password(&self, value: String) {
-		self.update_session(|session| {
-			session.login_password = value;
-			session.login_error.clear();
-		});
+	pub fn disable_nat_mapping(&self) {
+		self.set_nat_mapping(false);
+	}
+
+	/// Clears every grant the selected peer has on this node.
+	pub fn revoke_peer_access(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let selected_peer = self
+			.block_on(self.ctx.state.server.snapshot())
+			.selected_peer;
+		let status = match selected_peer.map(|peer| PeerId::from_str(&peer)) {
+			Some(Ok(peer)) => match self.ctx.state.server.puppy.revoke_all_permissions(peer) {
+				Ok(()) => String::from("All access revoked"),
+				Err(err) => self.notify_error("Failed to revoke access", err),
+			},
+			Some(Err(_)) => String::from("Invalid selected peer"),
+			None => String::from("Select a peer first"),
+		};
+		self.update_session(|session| session.revoke_access_status = status);
+	}
+
+	/// Holds the selected peer to the byte rate its access alert offers, for
+	/// as long as the alert settings say.
+	pub fn limit_peer_traffic(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let selected_peer = self
+			.block_on(self.ctx.state.server.snapshot())
+			.selected_peer;
+		let puppy = &self.ctx.state.server.puppy;
+		let limits = puppy.access_alert_limits();
+		let duration = std::time::Duration::from_secs(limits.limit_minutes.saturating_mul(60));
+		let status = match selected_peer.map(|peer| PeerId::from_str(&peer)) {
+			Some(Ok(peer)) => {
+				match puppy.limit_peer_traffic(peer, limits.limit_bytes_per_sec, duration) {
+					Ok(()) => format!(
+						"Limited to {}; requests over it are refused",
+						traffic_limit_label(&limits)
+					),
+					Err(err) => self.notify_error("Failed to limit the peer", err),
+				}
+			}
+			Some(Err(_)) => String::from("Invalid selected peer"),
+			None => String::from("Select a peer first"),
+		};
+		self.update_session(|session| session.access_alert_status = status);
+	}
+
+	/// Drops the selected peer to untrusted, which leaves it only hello and
+	/// pairing until its trust is raised again in the permission editor.
+	pub fn suspend_peer(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let selected_peer = self
+			.block_on(self.ctx.state.server.snapshot())
+			.selected_peer;
+		let status = match selected_peer.map(|peer| PeerId::from_str(&peer)) {
+			Some(Ok(peer)) => match self
+				.ctx
+				.state
+				.server
+				.puppy
+				.set_trust_level(peer, TrustLevel::Untrusted)
+			{
+				Ok(()) => String::from(
+					"Access suspended; raise its trust level in the permission editor to restore it",
+				),
+				Err(err) => self.notify_error("Failed to suspend the peer", err),
+			},
+			Some(Err(_)) => String::from("Invalid selected peer"),
+			None => String::from("Select a peer first"),
+		};
+		self.update_session(|session| session.access_alert_status = status);
 	}
 
-	pub fn edit_current_password(&self, value: String) {
+	/// Keeps the selected peer connected, redialing it whenever its
+	/// connection drops, or stops doing so.
+	pub fn toggle_keep_connected(&self) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		self.update_session(|session| {
-			session.current_password = value;
-			session.password_change_status.clear();
-		});
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let Some(row) = snapshot
+			.selected_peer
+			.as_deref()
+			.and_then(|selected| snapshot.peers.iter().find(|peer| peer.id == selected))
+		else {
+			return;
+		};
+		let important = !row.important;
+		let result = match PeerId::from_str(&row.id) {
+			Ok(peer) => self
+				.ctx
+				.state
+				.server
+				.puppy
+				.set_peer_important(peer, important),
+			Err(_) => Err(anyhow::anyhow!("invalid selected peer")),
+		};
+		let status = match result {
+			Ok(()) => {
+				let peer_id = row.id.clone();
+				self.block_on(async {
+					let mut state = self.ctx.state.server.state.lock().await;
+					if let Some(row) = state.peers.iter_mut().find(|row| row.id == peer_id) {
+						row.important = important;
+					}
+				});
+				if important {
+					String::from("Kept connected; redialed whenever the connection drops")
+				} else {
+					String::from("No longer kept connected")
+				}
+			}
+			Err(err) => self.notify_error("Failed to update", err),
+		};
+		self.update_session(|session| session.keep_connected_status = status);
 	}
 
-	pub fn edit_new_password(&self, value: String) {
+	/// Keeps a standby copy of this device's index on the selected peer.
+	pub fn replicate_index(&self) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		self.update_session(|session| {
-			session.new_password = value;
-			session.password_change_status.clear();
-		});
+		let selected_peer = self
+			.block_on(self.ctx.state.server.snapshot())
+			.selected_peer;
+		let status = match selected_peer.map(|peer| PeerId::from_str(&peer)) {
+			Some(Ok(peer)) => {
+				match self.block_on(self.ctx.state.server.puppy.replicate_index_to(peer)) {
+					Ok(_) => String::from("Replicating this device's index"),
+					Err(err) => self.notify_error("Failed to replicate the index", err),
+				}
+			}
+			Some(Err(_)) => String::from("Invalid selected peer"),
+			None => String::from("Select a peer first"),
+		};
+		self.update_session(|session| session.index_replica_status = status);
 	}
 
-	pub fn edit_confirm_password(&self, value: String) {
+	pub fn stop_index_replication(&self) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		self.update_session(|session| {
-			session.confirm_password = value;
-			session.password_change_status.clear();
-		});
+		let selected_peer = self
+			.block_on(self.ctx.state.server.snapshot())
+			.selected_peer;
+		let status = match selected_peer.map(|peer| PeerId::from_str(&peer)) {
+			Some(Ok(peer)) => match self.ctx.state.server.puppy.stop_replicating_index_to(peer) {
+				Ok(()) => String::from("Stopped replicating the index"),
+				Err(err) => self.notify_error("Failed to stop replicating the index", err),
+			},
+			Some(Err(_)) => String::from("Invalid selected peer"),
+			None => String::from("Select a peer first"),
+		};
+		self.update_session(|session| session.index_replica_status = status);
 	}
 
-	pub fn change_password(&self) {
-		let Some(username) = self.authenticated_username() else {
+	/// Pulls the selected peer's index changes whenever it announces them,
+	/// or stops doing so.
+	pub fn toggle_index_updates(&self) {
+		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
+		}
+		let selected_peer = self
+			.block_on(self.ctx.state.server.snapshot())
+			.selected_peer;
+		let puppy = &self.ctx.state.server.puppy;
+		let status = match selected_peer.map(|peer| PeerId::from_str(&peer)) {
+			Some(Ok(peer)) => {
+				let subscribe = !puppy
+					.is_subscribed_to_index_updates(peer)
+					.unwrap_or_default();
+				match puppy.subscribe_index_updates(peer, subscribe) {
+					Ok(()) if subscribe => String::from("Pulling index changes as they happen"),
+					Ok(()) => String::from("Stopped pulling index changes"),
+					Err(err) => self.notify_error("Failed to change index updates", err),
+				}
+			}
+			Some(Err(_)) => String::from("Invalid selected peer"),
+			None => String::from("Select a peer first"),
 		};
-		let (current_password, new_password, confirm_password) = {
-			let session = self.current_session();
-			(
-				session.current_password,
-				session.new_password,
-				session.confirm_password,
-			)
-		};
-		self.change_password_for_user(username, current_password, new_password, confirm_password);
+		self.update_session(|session| session.index_updates_status = status);
 	}
 
-	pub fn change_password_values(
-		&self,
-		current_password: String,
-		new_password: String,
-		confirm_password: String,
-	) -> bool {
-		let Some(username) = self.authenticated_username() else {
-			return false;
-		};
-		self.change_password_for_user(username, current_password, new_password, confirm_password)
+	pub fn edit_remote_scan_path(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| session.remote_scan_path = value);
 	}
 
-	fn change_password_for_user(
-		&self,
-		username: String,
-		current_password: String,
-		new_password: String,
-		confirm_password: String,
-	) -> bool {
-		if current_password.trim().is_empty()
-			|| new_password.trim().is_empty()
-			|| confirm_password.trim().is_empty()
-		{
-			self.update_session(|session| {
-				session.password_change_status = String::from("All password fields are required");
-			});
-			return false;
+	/// Asks the selected peer to scan a folder and pulls what it found
+	/// into this device's index once it finishes.
+	pub fn start_remote_scan(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
 		}
-		if new_password != confirm_password {
+		let session = self.current_session();
+		let path = session.remote_scan_path.trim().to_string();
+		if path.is_empty() {
 			self.update_session(|session| {
-				session.password_change_status =
-					String::from("New password and confirmation do not match");
+				session.remote_scan_status = String::from("Enter a folder to scan");
 			});
-			return false;
+			return;
 		}
-		match self
-			.ctx
-			.state
-			.server
-			.puppy
-			.change_password(username, current_password, new_password)
-		{
-			Ok(()) => {
-				self.update_session(|session| {
-					session.current_password.clear();
-					session.new_password.clear();
-					session.confirm_password.clear();
-					session.password_change_status = String::from("Password changed");
-				});
-				true
-			}
+		let limits = match scan_overrides(&session) {
+			Ok(limits) => limits,
 			Err(err) => {
-				self.update_session(|session| {
-					session.password_change_status = format!("Password change failed: {err}");
-				});
-				false
+				self.update_session(|session| session.remote_scan_status = err);
+				return;
 			}
-		}
-	}
-
-	pub fn login(&self) {
-		let (username, password) = {
-			let session = self.current_session();
-			(
-				session.login_username.trim().to_string(),
-				session.login_password.clone(),
-			)
 		};
-		if let Some(token) = self.login_with_credentials(username, password) {
-			let nonce = generate_login_nonce();
-			self.ctx
-				.state
-				.pending_login_tokens
-				.lock()
-				.unwrap()
-				.insert(nonce.clone(), token);
-			self.ctx.navigate(format!("/auth/finish?nonce={nonce}"));
-		}
-	}
-
-	pub fn login_with_credentials(&self, username: String, password: String) -> Option<String> {
-		let username = username.trim().to_string();
-		if username.is_empty() || password.trim().is_empty() {
+		let selected_peer = self
+			.block_on(self.ctx.state.server.snapshot())
+			.selected_peer;
+		let Some(Ok(peer)) = selected_peer.as_deref().map(PeerId::from_str) else {
 			self.update_session(|session| {
-				session.login_username = username;
-				session.login_error = String::from("Username and password are required");
+				session.remote_scan_status = String::from("Select a peer first");
 			});
-			return None;
-		}
-		match self
-			.ctx
-			.state
-			.server
-			.puppy
-			.verify_user_credentials(&username, &password)
-		{
-			Ok(true) => {
-				let (token, hash) = auth::generate_session_token();
-				if let Err(err) =
-					self.ctx
-						.state
-						.server
-						.puppy
-						.save_session(&hash, &username, SESSION_TTL_SECS)
-				{
-					self.update_session(|session| {
-						session.login_error = format!("Login failed: {err}");
-					});
-					return None;
-				}
-				self.update_session(|session| {
-					session.authenticated = true;
-					session.username = username.clone();
-					session.login_username = username;
-					session.login_password.clear();
-					session.login_error.clear();
-				});
-				Some(token)
-			}
-			Ok(false) => {
+			return;
+		};
+		let puppy = &self.ctx.state.server.puppy;
+		match puppy.scan_remote_peer(peer, path.clone(), limits) {
+			Ok(handle) => {
+				let reporter = self.ctx.state.jobs.start(
+					puppy.next_id(IdKind::Job),
+					format!("Scan {path} on {}", abbrev_peer_id(&peer.to_string())),
+					Some(handle.cancel_flag()),
+				);
+				let job_id = reporter.id();
+				forward_remote_scan_events(reporter, handle.receiver(), Arc::clone(puppy), peer);
 				self.update_session(|session| {
-					session.login_username = username;
-					session.login_error = String::from("Invalid credentials");
+					session.remote_scan_path.clear();
+					session.remote_scan_job = Some(job_id);
+					session.remote_scan_status = format!("Scanning {path}...");
 				});
-				None
 			}
 			Err(err) => {
 				self.update_session(|session| {
-					session.login_username = username;
-					session.login_error = format!("Login failed: {err}");
+					session.remote_scan_status = self.notify_error("Failed to start scan", err);
 				});
-				None
 			}
 		}
 	}
 
-	pub fn peer_row(&self, idx: u32) {
-		if !self.is_authenticated() {
-			self.ctx.push_state("/login");
-			return;
-		}
-		let peer_id = {
-			let state = self.block_on(self.ctx.state.server.snapshot());
-			state.peers.get(idx as usize).map(|peer| peer.id.clone())
-		};
-		self.block_on(
-			self.ctx
-				.state
-				.server
-				.handle_action(UiAction::PeerRow(idx as usize)),
-		);
-		if let Some(peer_id) = peer_id {
-			self.ctx.push_state(format!("/devices/{peer_id}"));
-		}
-	}
-
-	pub fn peer_back(&self) {
-		if !self.is_authenticated() {
-			self.ctx.push_state("/login");
-			return;
-		}
-		self.block_on(self.ctx.state.server.handle_action(UiAction::PeerBack));
-		self.ctx.push_state("/devices");
+	/// The pull of scan results at `idx` on the selected peer's page.
+	fn scan_results_pull(&self, idx: u32) -> Option<(PeerId, ScanResultsPull)> {
+		let selected_peer = self
+			.block_on(self.ctx.state.server.snapshot())
+			.selected_peer?;
+		let peer = PeerId::from_str(&selected_peer).ok()?;
+		let pull = self
+			.ctx
+			.state
+			.server
+			.puppy
+			.scan_result_pulls(&peer)
+			.ok()?
+			.into_iter()
+			.take(SCAN_RESULT_PULLS_SHOWN)
+			.nth(idx as usize)?;
+		Some((peer, pull))
 	}
 
-	pub fn refresh_peer_files(&self) {
+	/// Pulls the rest of the scan results at `idx`, past the size cap or
+	/// an interruption.
+	pub fn continue_scan_results(&self, idx: u32) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		let snapshot = self.block_on(self.ctx.state.server.snapshot());
-		let Some(peer_id) = snapshot.selected_peer else {
+		let Some((peer, pull)) = self.scan_results_pull(idx) else {
 			return;
 		};
-		self.block_on(
-			self.ctx
-				.state
-				.server
-				.refresh_peer_files(&peer_id, &snapshot.peer_files_path),
+		let puppy = &self.ctx.state.server.puppy;
+		let reporter = self.ctx.state.jobs.start(
+			puppy.next_id(IdKind::Job),
+			format!("Fetch scan results from {}", abbrev_peer_id(&pull.peer)),
+			None,
 		);
+		let job_id = reporter.id();
+		tokio::spawn(continue_and_watch(
+			Arc::clone(puppy),
+			peer,
+			pull.run,
+			reporter,
+		));
+		self.update_session(|session| {
+			session.remote_scan_job = Some(job_id);
+			session.remote_scan_status = String::from("fetching results index...");
+		});
 	}
 
-	pub fn refresh_audio(&self) {
+	/// Opens the selected peer's files searched within the folder of the
+	/// scan results at `idx`.
+	pub fn browse_scan_results(&self, idx: u32) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		let snapshot = self.block_on(self.ctx.state.server.snapshot());
-		let Some(peer_id) = snapshot.selected_peer else {
-			self.update_session(|session| {
-				session.audio_status = String::from("Select a peer first");
-			});
+		let Some((_, pull)) = self.scan_results_pull(idx) else {
 			return;
 		};
-		self.block_on(self.ctx.state.server.refresh_peer_audio(&peer_id));
+		let state = self.block_on(self.ctx.state.server.snapshot());
+		self.update_session(|session| {
+			session
+				.peer_files
+				.update(PeerFilesMsg::QueryEdited(String::new()))
+		});
+		self.search_peer_folder(&state, pull.peer.clone(), pull.path.clone());
+		self.ctx.push_state(peer_files_href(&pull.peer, &pull.path));
 	}
 
-	pub fn refresh_webcams(&self) {
+	/// Turns pulling the results of scans asked of peers on or off.
+	pub fn toggle_mirror_remote_scans(&self) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		let snapshot = self.block_on(self.ctx.state.server.snapshot());
-		let Some(peer_id) = snapshot.selected_peer else {
-			self.update_session(|session| {
-				session.webcam_status = String::from("Select a peer first");
-			});
-			return;
+		let puppy = &self.ctx.state.server.puppy;
+		let enabled = !puppy.mirror_remote_scans();
+		let status = match puppy.set_mirror_remote_scans(enabled) {
+			Ok(()) if enabled => String::from("Results of remote scans are pulled into the index"),
+			Ok(()) => String::from("Results of remote scans stay on the scanning device"),
+			Err(err) => self.notify_error("Failed to change remote scan results", err),
 		};
-		self.block_on(self.ctx.state.server.refresh_peer_webcams(&peer_id));
+		self.update_session(|session| session.mirror_remote_scans_status = status);
 	}
 
-	pub fn view_webcam(&self, idx: u32) {
+	pub fn edit_temporary_grant_path(&self, value: String) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		let snapshot = self.block_on(self.ctx.state.server.snapshot());
-		let Some(device) = snapshot.peer_webcams.get(idx as usize) else {
-			self.update_session(|session| {
-				session.webcam_status = String::from("Unknown webcam device");
-			});
-			return;
-		};
-		let device_id = device.id.clone();
-		let device_name = device.name.clone();
 		self.update_session(|session| {
-			session.webcam_selected_device = device_id;
-			session.webcam_status = format!("Viewing {device_name}");
+			session.temporary_grant_path = value;
+			session.temporary_grant_status.clear();
 		});
 	}
 
-	pub fn select_microphone(&self, source_id: String) {
+	pub fn select_temporary_grant_access(&self, value: String) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		let snapshot = self.block_on(self.ctx.state.server.snapshot());
-		let Some(source) = snapshot
-			.peer_microphones
-			.iter()
-			.find(|source| source.id == source_id)
-		else {
-			self.update_session(|session| {
-				session.microphone_selected_device.clear();
-			});
-			return;
-		};
-		let id = source.id.clone();
 		self.update_session(|session| {
-			session.microphone_selected_device = id;
+			session.temporary_grant_access = value;
+			session.temporary_grant_status.clear();
 		});
 	}
 
-	pub fn set_audio_volume(&self, value: i32) {
+	/// Grants the selected peer access to the entered path for the preset
+	/// duration at `idx` in [`TEMPORARY_GRANT_PRESETS`].
+	pub fn grant_temporary_access(&self, idx: u32) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		let snapshot = self.block_on(self.ctx.state.server.snapshot());
-		let Some(peer_id) = snapshot.selected_peer else {
-			self.update_session(|session| {
-				session.audio_status = String::from("Select a peer first");
-			});
+		let Some((label, secs)) = TEMPORARY_GRANT_PRESETS.get(idx as usize).copied() else {
 			return;
 		};
-		if !audio_supported(snapshot.peer_audio_capability.as_ref()) {
+		let selected_peer = self
+			.block_on(self.ctx.state.server.snapshot())
+			.selected_peer;
+		let Some(Ok(peer)) = selected_peer.map(|peer| PeerId::from_str(&peer)) else {
 			self.update_session(|session| {
-				session.audio_status =
-					audio_capability_status(snapshot.peer_audio_capability.as_ref());
+				session.temporary_grant_status = String::from("Select a peer first");
 			});
 			return;
-		}
-		let Ok(peer) = PeerId::from_str(&peer_id) else {
+		};
+		let (path, access) = {
+			let session = self.current_session();
+			(
+				session.temporary_grant_path.trim().to_string(),
+				session.temporary_grant_access,
+			)
+		};
+		if path.is_empty() {
 			self.update_session(|session| {
-				session.audio_status = String::from("Invalid selected peer");
+				session.temporary_grant_status = String::from("Path is required");
 			});
 			return;
-		};
-		let volume = value.clamp(0, 100) as u8;
-		match self.block_on(
-			self.ctx
-				.state
-				.server
-				.puppy
-				.set_audio_volume(peer, None, volume),
-		) {
-			Ok(devices) => {
-				self.block_on(self.ctx.state.server.set_peer_audio_devices(devices));
+		}
+		let mut flags = FLAG_READ | FLAG_SEARCH;
+		match access.as_str() {
+			"write" => flags |= FLAG_WRITE,
+			"review" => flags |= FLAG_WRITE | FLAG_QUARANTINE,
+			"preview" => flags = FLAG_PREVIEW | FLAG_SEARCH,
+			_ => {}
+		}
+		let result = self.ctx.state.server.puppy.grant_temporary(
+			peer,
+			&path,
+			flags,
+			std::time::Duration::from_secs(secs),
+		);
+		match result {
+			Ok(grant) => {
+				self.block_on(self.ctx.state.server.refresh_temporary_grants(peer));
 				self.update_session(|session| {
-					session.audio_status = format!("Set volume to {volume}%");
+					session.temporary_grant_path.clear();
+					session.temporary_grant_status =
+						format!("Allowed {} for {label}", grant.rule.path().display());
 				});
 			}
 			Err(err) => {
 				self.update_session(|session| {
-					session.audio_status = format!("Failed to set volume: {err}");
+					session.temporary_grant_status =
+						self.notify_error("Failed to grant temporary access", err);
 				});
 			}
 		}
 	}
 
-	pub fn toggle_audio_mute(&self) {
+	pub fn revoke_temporary_grant(&self, id: u64) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
 		let snapshot = self.block_on(self.ctx.state.server.snapshot());
-		let Some(peer_id) = snapshot.selected_peer else {
-			self.update_session(|session| {
-				session.audio_status = String::from("Select a peer first");
-			});
+		let Some(Ok(peer)) = snapshot.selected_peer.map(|peer| PeerId::from_str(&peer)) else {
 			return;
 		};
-		if !audio_supported(snapshot.peer_audio_capability.as_ref()) {
-			self.update_session(|session| {
-				session.audio_status =
-					audio_capability_status(snapshot.peer_audio_capability.as_ref());
-			});
-			return;
-		}
-		let Ok(peer) = PeerId::from_str(&peer_id) else {
-			self.update_session(|session| {
-				session.audio_status = String::from("Invalid selected peer");
-			});
+		let Some(grant) = snapshot
+			.peer_temporary_grants
+			.into_iter()
+			.find(|grant| grant.id == id)
+		else {
 			return;
 		};
-		let muted = !default_audio_output(&snapshot.peer_audio_devices)
-			.map(|device| device.muted)
-			.unwrap_or(false);
-		match self.block_on(
-			self.ctx
-				.state
-				.server
-				.puppy
-				.set_audio_muted(peer, None, muted),
-		) {
-			Ok(devices) => {
-				self.block_on(self.ctx.state.server.set_peer_audio_devices(devices));
-				self.update_session(|session| {
-					session.audio_status = if muted {
-						String::from("Muted default output")
-					} else {
-						String::from("Unmuted default output")
-					};
-				});
-			}
-			Err(err) => {
-				self.update_session(|session| {
-					session.audio_status = format!("Failed to change mute: {err}");
-				});
+		let status = match self.ctx.state.server.puppy.revoke_temporary(peer, grant.id) {
+			Ok(()) => format!(
+				"Temporary access to {} revoked",
+				grant.rule.path().display()
+			),
+			Err(err) => self.notify_error("Failed to revoke temporary access", err),
+		};
+		self.block_on(self.ctx.state.server.refresh_temporary_grants(peer));
+		self.update_session(|session| session.temporary_grant_status = status);
+	}
+
+	fn update_share_wizard<F>(&self, f: F)
+	where
+		F: FnOnce(&mut UiShareWizard),
+	{
+		self.update_session(|session| {
+			if let Some(wizard) = session.share_wizard.as_mut() {
+				f(wizard);
 			}
+		});
+	}
+
+	pub fn open_share_wizard(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
 		}
+		self.block_on(self.ctx.state.server.refresh_local_folders());
+		self.update_session(|session| session.share_wizard = Some(UiShareWizard::default()));
 	}
 
-	pub fn select_audio_device(&self, device_id: String) {
+	pub fn close_share_wizard(&self) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		let snapshot = self.block_on(self.ctx.state.server.snapshot());
-		let Some(peer_id) = snapshot.selected_peer else {
-			self.update_session(|session| {
-				session.audio_status = String::from("Select a peer first");
-			});
+		self.update_session(|session| session.share_wizard = None);
+	}
+
+	pub fn edit_share_wizard_path(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
 			return;
-		};
-		if !audio_supported(snapshot.peer_audio_capability.as_ref()) {
-			self.update_session(|session| {
-				session.audio_status =
-					audio_capability_status(snapshot.peer_audio_capability.as_ref());
-			});
+		}
+		self.update_share_wizard(|wizard| {
+			wizard.path = value;
+			wizard.status.clear();
+		});
+	}
+
+	pub fn pick_share_wizard_folder(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
 			return;
 		}
-		let Some(device) = snapshot
-			.peer_audio_devices
-			.iter()
-			.find(|device| device.id == device_id)
-		else {
-			self.update_session(|session| {
-				session.audio_status = String::from("Unknown audio device");
+		let folder = self
+			.block_on(self.ctx.state.server.snapshot())
+			.local_folders
+			.get(idx as usize)
+			.cloned();
+		if let Some(folder) = folder {
+			self.update_share_wizard(|wizard| {
+				wizard.path = folder.path;
+				wizard.status.clear();
 			});
+		}
+	}
+
+	pub fn toggle_share_wizard_write(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
 			return;
-		};
-		if !matches!(device.kind, AudioDeviceKind::Sink) {
-			self.update_session(|session| {
-				session.audio_status = String::from("Only output devices can be selected");
-			});
+		}
+		self.update_share_wizard(|wizard| wizard.write = !wizard.write);
+	}
+
+	pub fn toggle_share_wizard_preview_only(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
 			return;
 		}
-		let Ok(peer) = PeerId::from_str(&peer_id) else {
-			self.update_session(|session| {
-				session.audio_status = String::from("Invalid selected peer");
-			});
+		self.update_share_wizard(|wizard| wizard.preview_only = !wizard.preview_only);
+	}
+
+	pub fn edit_share_wizard_deny_patterns(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
 			return;
-		};
-		let device_name = device.name.clone();
-		match self.block_on(
-			self.ctx
-				.state
-				.server
-				.puppy
-				.set_default_audio_device(peer, device_id),
-		) {
-			Ok(devices) => {
-				self.block_on(self.ctx.state.server.set_peer_audio_devices(devices));
-				self.update_session(|session| {
-					session.audio_status = format!("Selected {device_name}");
-				});
-			}
-			Err(err) => {
-				self.update_session(|session| {
-					session.audio_status = format!("Failed to select audio output: {err}");
-				});
-			}
 		}
+		self.update_share_wizard(|wizard| {
+			wizard.deny_patterns = value;
+			wizard.status.clear();
+		});
 	}
 
-	pub fn refresh_peers(&self) {
+	/// Fills in the suggested deny patterns; they can still be edited or
+	/// cleared before sharing.
+	pub fn suggest_share_wizard_patterns(&self) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		self.block_on(self.ctx.state.server.handle_action(UiAction::RefreshPeers));
+		self.update_share_wizard(|wizard| {
+			wizard.deny_patterns = SUGGESTED_DENY_PATTERNS.join(", ");
+		});
 	}
 
-	pub fn refresh_files(&self) {
+	/// Ticks or unticks the remote peer at `idx` in the peer list.
+	pub fn toggle_share_wizard_peer(&self, idx: u32) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		self.block_on(self.ctx.state.server.handle_action(UiAction::RefreshFiles));
+		let peer = self
+			.block_on(self.ctx.state.server.snapshot())
+			.peers
+			.into_iter()
+			.filter(|peer| !peer.local)
+			.nth(idx as usize);
+		if let Some(peer) = peer {
+			self.update_share_wizard(|wizard| {
+				if !wizard.peers.remove(&peer.id) {
+					wizard.peers.insert(peer.id);
+				}
+				wizard.status.clear();
+			});
+		}
 	}
 
-	pub fn preview_local_file(&self, idx: u32) {
+	/// Grants the wizard's folder to every ticked peer and lists how each
+	/// one went; a failure for one peer does not stop the others.
+	pub fn run_share_wizard(&self) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		let hash = {
-			let state = self.block_on(self.ctx.state.server.snapshot());
-			state.files.get(idx as usize).map(|entry| entry.hash)
-		};
-		let Some(hash) = hash else {
+		let Some(wizard) = self.current_session().share_wizard else {
 			return;
 		};
-		match self
-			.ctx
-			.state
-			.server
-			.puppy
-			.resolve_local_file_by_hash(&hash)
+		let path = wizard.path.trim().to_string();
+		let deny_patterns = parse_patterns(
+			&wizard
+				.deny_patterns
+				.split(',')
+				.map(String::from)
+				.collect::<Vec<_>>(),
+		);
+		let status = if path.is_empty() {
+			Some(String::from("Path is required"))
+		} else if !std::path::Path::new(&path).is_dir() {
+			Some(format!("{path} is not a folder on this device"))
+		} else if wizard.peers.is_empty() {
+			Some(String::from("Select at least one peer"))
+		} else if let Err(err) = &deny_patterns {
+			Some(err.clone())
+		} else {
+			None
+		};
+		if let Some(status) = status {
+			self.update_share_wizard(|wizard| wizard.status = status);
+			return;
+		}
+		let peers = match wizard
+			.peers
+			.iter()
+			.map(|peer| PeerId::from_str(peer))
+			.collect::<Result<Vec<_>, _>>()
 		{
-			Ok(Some((path, _entry))) => {
-				self.update_session(|session| {
-					session.file_preview_peer.clear();
-					session.file_preview_path = path.to_string_lossy().into_owned();
-					session.file_preview_status.clear();
-					session.file_preview_content.clear();
-					session.file_preview_image_src.clear();
-					session.file_preview_loaded = false;
-					session.file_preview_modal_open = true;
+			Ok(peers) => peers,
+			Err(err) => {
+				self.update_share_wizard(|wizard| {
+					wizard.status = self.notify_error("Invalid peer id", err);
 				});
-				self.load_file_preview();
+				return;
 			}
-			Ok(None) => {
-				self.update_session(|session| {
-					session.file_preview_modal_open = true;
-					session.file_preview_status =
-						String::from("Local file path not found for selected hash");
-					session.file_preview_content.clear();
-					session.file_preview_image_src.clear();
-					session.file_preview_loaded = false;
+		};
+		let mut flags = if wizard.preview_only {
+			FLAG_PREVIEW | FLAG_SEARCH
+		} else {
+			FLAG_READ | FLAG_SEARCH
+		};
+		if wizard.write {
+			flags |= FLAG_WRITE;
+		}
+		let folder = FolderRule::new(PathBuf::from(&path), flags)
+			.with_patterns(deny_patterns.unwrap_or_default(), Vec::new());
+		let result = self
+			.ctx
+			.state
+			.server
+			.puppy
+			.grant_folder_to_peers(folder, peers);
+		let names = self
+			.block_on(self.ctx.state.server.snapshot())
+			.peers
+			.into_iter()
+			.map(|peer| (peer.id, peer.name))
+			.collect::<HashMap<_, _>>();
+		match result {
+			Ok(results) => {
+				let lines = results
+					.into_iter()
+					.map(|(peer, outcome)| {
+						let id = peer.to_string();
+						let name = names
+							.get(&id)
+							.cloned()
+							.unwrap_or_else(|| abbrev_peer_id(&id));
+						match outcome {
+							BatchGrantOutcome::Granted => (format!("{name}: granted"), "#4cff91"),
+							BatchGrantOutcome::Unchanged => {
+								(format!("{name}: unchanged, already granted"), "#8fb8b0")
+							}
+							BatchGrantOutcome::Failed { error } => {
+								(format!("{name}: failed, {error}"), "#ff8a8a")
+							}
+						}
+					})
+					.collect();
+				self.update_share_wizard(|wizard| {
+					wizard.results = lines;
+					wizard.status = format!("Shared {path}");
 				});
 			}
 			Err(err) => {
-				self.update_session(|session| {
-					session.file_preview_modal_open = true;
-					session.file_preview_status = format!("Failed to resolve file: {err}");
-					session.file_preview_content.clear();
-					session.file_preview_image_src.clear();
-					session.file_preview_loaded = false;
+				self.update_share_wizard(|wizard| {
+					wizard.results.clear();
+					wizard.status = self.notify_error(format!("Failed to share {path}"), err);
 				});
 			}
 		}
 	}
 
-	pub fn preview_peer_file(&self, idx: u32) {
+	pub fn sort_growth(&self, column: GrowthColumn) {
+		self.update_session(|session| session.growth.update(GrowthMsg::SortedBy(column)));
+	}
+
+	/// Lays the treemap out again for what the session shows, on a blocking
+	/// thread since a tree of many thousand entries takes a while.
+	pub(super) async fn lay_out_treemap(&self) {
+		let trees = self.ctx.state.server.storage_trees().await;
+		let session = self.current_session().treemap;
+		match task::spawn_blocking(move || session.lay_out(&trees)).await {
+			Ok(layout) => self.update_session(|session| {
+				session
+					.treemap
+					.update(TreemapMsg::LaidOut(Arc::new(layout)))
+			}),
+			Err(err) => tracing::warn!("failed to lay out the storage treemap: {err}"),
+		}
+	}
+
+	pub fn update_treemap(&self, msg: TreemapMsg) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		let target = {
-			let state = self.block_on(self.ctx.state.server.snapshot());
-			let Some(peer_id) = state.selected_peer else {
-				return;
-			};
-			state.peer_files.get(idx as usize).and_then(|entry| {
-				if entry.is_dir {
-					None
-				} else {
-					Some((
-						peer_id,
-						child_peer_file_path(&state.peer_files_path, &entry.name),
-					))
-				}
-			})
-		};
-		let Some((peer_id, path)) = target else {
-			return;
-		};
-		self.update_session(|session| {
-			session.file_preview_peer = peer_id;
-			session.file_preview_path = path;
-			session.file_preview_status.clear();
-			session.file_preview_content.clear();
-			session.file_preview_image_src.clear();
-			session.file_preview_loaded = false;
-			session.file_preview_modal_open = true;
-		});
-		self.load_file_preview();
+		self.update_session(|session| session.treemap.update(msg));
+		self.block_on(self.lay_out_treemap());
 	}
 
-	pub fn refresh_storage(&self) {
+	pub fn select_treemap_depth(&self, value: String) {
+		if let Ok(depth) = value.parse() {
+			self.update_treemap(TreemapMsg::DepthSelected(depth));
+		}
+	}
+
+	/// Zooms into the tile the treemap reported a click on.
+	pub fn zoom_treemap(&self, payload: wgui::serde_json::Value) {
+		if let Some(path) = payload.get("path").and_then(|path| path.as_str()) {
+			self.update_treemap(TreemapMsg::ZoomedTo(path.to_string()));
+		}
+	}
+
+	/// Zooms back out to step `idx` of the breadcrumb.
+	pub fn zoom_treemap_out(&self, idx: u32) {
+		let path = self
+			.current_session()
+			.treemap
+			.layout()
+			.trail
+			.get(idx as usize)
+			.map(|(_, path)| path.clone());
+		if let Some(path) = path {
+			self.update_treemap(TreemapMsg::ZoomedTo(path));
+		}
+	}
+
+	/// Toggles a scan run for comparison; once two runs of the same folder
+	/// are selected their diff is loaded.
+	pub fn select_scan_run(&self, idx: u32) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		self.block_on(
-			self.ctx
-				.state
-				.server
-				.handle_action(UiAction::RefreshStorage),
-		);
+		let run = self
+			.block_on(self.ctx.state.server.snapshot())
+			.scan_runs
+			.get(idx as usize)
+			.cloned();
+		let Some(run) = run else {
+			return;
+		};
+		if run.status != ScanRunStatus::Completed {
+			self.update_session(|session| {
+				session.scan_diff_status = String::from("Only completed scans can be compared");
+			});
+			return;
+		}
+		let mut selected = self.current_session().scan_diff_runs;
+		if let Some(pos) = selected.iter().position(|id| *id == run.id) {
+			selected.remove(pos);
+		} else {
+			selected.push(run.id);
+			if selected.len() > 2 {
+				selected.remove(0);
+			}
+		}
+		let (lines, status) = match selected.as_slice() {
+			[a, b] => match self.ctx.state.server.puppy.scan_diff(*a, *b, 0) {
+				Ok(entries) if entries.is_empty() => (
+					Vec::new(),
+					String::from("No files changed between these scans"),
+				),
+				Ok(entries) => (
+					entries
+						.iter()
+						.map(|entry| format!("{} {}", entry.change.as_str(), entry.path))
+						.collect(),
+					format!("{} changed files", entries.len()),
+				),
+				Err(err) => (Vec::new(), err),
+			},
+			_ => (
+				Vec::new(),
+				String::from("Select two scans of the same folder to compare"),
+			),
+		};
+		self.update_session(|session| {
+			session.scan_diff_runs = selected;
+			session.scan_diff_lines = lines;
+			session.scan_diff_status = status;
+		});
 	}
 
 	pub fn refresh_users(&self) {
@@ -1996,7 +8610,7 @@ impl UiControllerCore<'_> {
 			return;
 		}
 		self.update_session(|session| {
-			session.search_name_query = value;
+			session.search.update(SearchMsg::QueryEdited(value));
 		});
 	}
 
@@ -2013,15 +8627,7 @@ impl UiControllerCore<'_> {
 			return;
 		};
 		self.update_session(|session| {
-			if let Some(pos) = session
-				.search_selected_mimes
-				.iter()
-				.position(|item| item == &mime)
-			{
-				session.search_selected_mimes.remove(pos);
-			} else {
-				session.search_selected_mimes.push(mime);
-			}
+			session.search.update(SearchMsg::MimeToggled(mime));
 		});
 	}
 
@@ -2031,7 +8637,7 @@ impl UiControllerCore<'_> {
 			return;
 		}
 		self.update_session(|session| {
-			session.search_selected_mimes.clear();
+			session.search.update(SearchMsg::MimesCleared);
 		});
 	}
 
@@ -2046,6 +8652,24 @@ impl UiControllerCore<'_> {
 		});
 	}
 
+	pub fn pick_shared_folder(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let folder = self
+			.block_on(self.ctx.state.server.snapshot())
+			.local_folders
+			.get(idx as usize)
+			.cloned();
+		if let Some(folder) = folder {
+			self.update_session(|session| {
+				session.shared_folder_path = folder.path;
+				session.shared_folder_status.clear();
+			});
+		}
+	}
+
 	pub fn select_shared_folder_access(&self, value: String) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
@@ -2063,7 +8687,7 @@ impl UiControllerCore<'_> {
 			return;
 		}
 		let snapshot = self.block_on(self.ctx.state.server.snapshot());
-		if snapshot.selected_peer != snapshot.local_peer_id {
+		if snapshot.page != Page::Welcome && snapshot.selected_peer != snapshot.local_peer_id {
 			self.update_session(|session| {
 				session.shared_folder_status =
 					String::from("Allowed folders can only be changed on the current device");
@@ -2077,48 +8701,250 @@ impl UiControllerCore<'_> {
 				session.shared_folder_access,
 			)
 		};
-		if path.is_empty() {
+		let draft = PermissionDraft {
+			rules: vec![RuleDraft::with_access(&path, &access)],
+		};
+		if let Err(errors) = draft.validate() {
 			self.update_session(|session| {
-				session.shared_folder_status = String::from("Path is required");
+				session.shared_folder_status = DraftRejected { errors }.to_string();
 			});
 			return;
 		}
 		let result = self.block_on(async {
 			tokio::time::timeout(std::time::Duration::from_secs(3), async {
-				if access == "write" {
-					self.ctx
-						.state
-						.server
-						.puppy
-						.share_read_write_folder_async(&path)
-						.await
-				} else {
-					self.ctx
-						.state
-						.server
-						.puppy
-						.share_read_only_folder_async(&path)
-						.await
+				let puppy = &self.ctx.state.server.puppy;
+				match access.as_str() {
+					"write" => puppy.share_read_write_folder_async(&path).await,
+					"preview" => puppy.share_preview_folder_async(&path).await,
+					_ => puppy.share_read_only_folder_async(&path).await,
 				}
 			})
 			.await
 			.unwrap_or_else(|_| Err(anyhow::anyhow!("daemon did not respond in time")))
 		});
 		match result {
-			Ok(()) => {
+			Ok(overlaps) => {
 				self.block_on(self.ctx.state.server.refresh_peers());
+				let mut status = format!("Added allowed folder {path}");
+				for overlap in &overlaps {
+					status.push_str(&format!("; {}", overlap.describe()));
+				}
 				self.update_session(|session| {
 					session.shared_folder_path.clear();
 					session.shared_folder_access = String::from("read");
-					session.shared_folder_status = format!("Added allowed folder {path}");
+					session.shared_folder_status = status;
 				});
 			}
 			Err(err) => {
 				self.update_session(|session| {
-					session.shared_folder_status = format!("Failed to add folder: {err}");
+					session.shared_folder_status = self.notify_error("Failed to add folder", err);
+				});
+			}
+		}
+	}
+
+	fn mark_onboarding_done(&self) {
+		let mut prefs = self.ctx.state.prefs.lock().unwrap();
+		if prefs.onboarding_done {
+			return;
+		}
+		prefs.onboarding_done = true;
+		if let Err(err) = prefs.save(&self.ctx.state.prefs_path) {
+			tracing::warn!("failed to save UI preferences: {err}");
+		}
+	}
+
+	pub fn onboarding_next(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session.onboarding_step = (session.onboarding_step + 1).min(ONBOARDING_STEPS.len() - 1);
+			session.onboarding_status.clear();
+		});
+	}
+
+	pub fn onboarding_back(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session.onboarding_step = session.onboarding_step.saturating_sub(1);
+			session.onboarding_status.clear();
+		});
+	}
+
+	/// Leaves the wizard for the device list. Skipping and finishing both
+	/// stop it from opening by itself; the Setup menu entry reopens it.
+	pub fn finish_onboarding(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.mark_onboarding_done();
+		self.update_session(|session| {
+			session.onboarding_step = 0;
+			session.onboarding_node_name = None;
+			session.onboarding_status.clear();
+			session.pairing_status.clear();
+		});
+		self.ctx.push_state("/devices");
+	}
+
+	pub fn edit_node_name(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session.onboarding_node_name = Some(value);
+			session.onboarding_status.clear();
+		});
+	}
+
+	pub fn save_node_name(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some(name) = self.current_session().onboarding_node_name else {
+			self.onboarding_next();
+			return;
+		};
+		match self.ctx.state.server.puppy.set_node_name(name) {
+			Ok(name) => {
+				self.block_on(async {
+					self.ctx.state.server.state.lock().await.node_name = name.clone();
+				});
+				self.update_session(|session| {
+					session.onboarding_node_name = None;
+					session.onboarding_step = 1;
+					session.onboarding_status = format!("This device is now called {name}");
+				});
+			}
+			Err(err) => self.update_session(|session| {
+				session.onboarding_status = self.notify_error("Failed to rename device", err);
+			}),
+		}
+	}
+
+	fn nearby_peer(&self, idx: u32) -> Option<PeerId> {
+		self.block_on(self.ctx.state.server.snapshot())
+			.nearby_peers
+			.get(idx as usize)
+			.copied()
+	}
+
+	pub fn pair_nearby_peer(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some(peer) = self.nearby_peer(idx) else {
+			return;
+		};
+		let result = self.block_on(self.ctx.state.server.puppy.start_pairing(peer));
+		let status = match result {
+			Ok(pairing) => format!(
+				"Pairing requested; check that the other device shows code {}",
+				pairing.code
+			),
+			Err(err) => self.notify_error("Failed to start pairing", err),
+		};
+		self.block_on(self.ctx.state.server.refresh_nearby());
+		self.update_session(|session| session.pairing_status = status);
+	}
+
+	fn answer_pairing(&self, idx: u32, accept: bool) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some(peer) = self.nearby_peer(idx) else {
+			return;
+		};
+		let status = match self.ctx.state.server.puppy.answer_pairing(peer, accept) {
+			Ok(()) if accept => {
+				String::from("Paired; both devices can now browse each other's shared folders")
+			}
+			Ok(()) => String::from("Pairing declined"),
+			Err(err) => self.notify_error("Failed to answer pairing", err),
+		};
+		self.block_on(self.ctx.state.server.refresh_nearby());
+		self.update_session(|session| session.pairing_status = status);
+	}
+
+	pub fn confirm_pairing(&self, idx: u32) {
+		self.answer_pairing(idx, true);
+	}
+
+	pub fn decline_pairing(&self, idx: u32) {
+		self.answer_pairing(idx, false);
+	}
+
+	pub fn show_pairing_string(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		match self.block_on(self.ctx.state.server.puppy.pairing_info()) {
+			Ok(info) => {
+				let qr = qr_data_url(&info.pairing_string);
+				self.update_session(|session| {
+					session.pairing_string = Some((info, qr));
+					session.pairing_string_status.clear();
 				});
 			}
+			Err(err) => {
+				let status = self.notify_error("Failed to make a pairing string", err);
+				self.update_session(|session| session.pairing_string_status = status);
+			}
+		}
+	}
+
+	pub fn edit_join_pairing(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session.join_pairing = value;
+			session.pairing_string_status.clear();
+		});
+	}
+
+	pub fn join_pairing(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let pairing = self.current_session().join_pairing;
+		if pairing.trim().is_empty() {
+			self.update_session(|session| {
+				session.pairing_string_status =
+					String::from("Paste the pairing string the other device shows")
+			});
+			return;
 		}
+		let status = match self.block_on(self.ctx.state.server.puppy.pair_with(&pairing)) {
+			Ok(paired) => {
+				self.update_session(|session| session.join_pairing.clear());
+				let name = if paired.node_name.is_empty() {
+					abbrev_peer_id(&paired.peer_id.to_string())
+				} else {
+					paired.node_name
+				};
+				format!(
+					"Paired with {name}; it shared {} folder(s) with this device",
+					paired.granted.len()
+				)
+			}
+			Err(err) => self.notify_error("Failed to pair", format!("{err:#}")),
+		};
+		self.block_on(self.ctx.state.server.refresh_nearby());
+		self.update_session(|session| session.pairing_string_status = status);
 	}
 
 	pub fn select_search_target(&self, value: String) {
@@ -2127,9 +8953,7 @@ impl UiControllerCore<'_> {
 			return;
 		}
 		self.update_session(|session| {
-			session.search_target = value;
-			reset_search_visible_count(session);
-			rebuild_search_results(session);
+			session.search.update(SearchMsg::TargetSelected(value));
 		});
 	}
 
@@ -2139,9 +8963,7 @@ impl UiControllerCore<'_> {
 			return;
 		}
 		self.update_session(|session| {
-			session.search_sort = value;
-			reset_search_visible_count(session);
-			rebuild_search_results(session);
+			session.search.update(SearchMsg::SortSelected(value));
 		});
 	}
 
@@ -2151,9 +8973,7 @@ impl UiControllerCore<'_> {
 			return;
 		}
 		self.update_session(|session| {
-			session.search_page_size = value;
-			reset_search_visible_count(session);
-			rebuild_search_results(session);
+			session.search.update(SearchMsg::PageSizeSelected(value));
 		});
 	}
 
@@ -2163,16 +8983,11 @@ impl UiControllerCore<'_> {
 			return;
 		}
 		self.update_session(|session| {
-			let page_size = search_page_size(&session.search_page_size);
-			session.search_visible_count = session
-				.search_visible_count
-				.max(page_size)
-				.saturating_add(page_size);
-			rebuild_search_results(session);
+			session.search.update(SearchMsg::LoadMore);
 		});
 	}
 
-	fn watch_live_search(&self, rx: Arc<std::sync::Mutex<mpsc::Receiver<LiveSearchPeerEvent>>>) {
+	fn watch_live_search(&self, rx: SearchStream) {
 		let Some(client_id) = self.ctx.client_id() else {
 			return;
 		};
@@ -2183,117 +8998,61 @@ impl UiControllerCore<'_> {
 			.map(|route| route.path)
 			.unwrap_or_else(|| String::from("/search"));
 		let ctx = Arc::clone(self.ctx);
+		let stream = Arc::clone(&rx);
+		// False once the session has moved on to another search.
+		let update = move |msg: SearchMsg| {
+			let applied = match ctx.state.sessions.lock() {
+				Ok(mut sessions) => sessions
+					.get_mut(&session_key)
+					.is_some_and(|session| session.search.update(msg)),
+				Err(_) => false,
+			};
+			if applied {
+				ctx.push_state_for_client(client_id, route_path.clone());
+			}
+			applied
+		};
+		let puppy = Arc::clone(&self.ctx.state.server.puppy);
 		std::thread::spawn(move || {
 			loop {
 				let event = match rx.lock() {
 					Ok(stream) => stream.recv(),
 					Err(err) => {
-						if let Ok(mut sessions) = ctx.state.sessions.lock()
-							&& let Some(session) = sessions.get_mut(&session_key)
-						{
-							session.search_status = format!("Search stream lock failed: {err}");
-							session.search_in_progress = false;
-							if session
-								.search_rx
-								.as_ref()
-								.map(|current| Arc::ptr_eq(current, &rx))
-								.unwrap_or(false)
-							{
-								session.search_rx = None;
-							}
-						}
-						ctx.push_state_for_client(client_id, route_path.clone());
+						update(SearchMsg::StreamFailed {
+							stream,
+							error: err.to_string(),
+						});
 						break;
 					}
 				};
-				let event = match event {
-					Ok(event) => event,
-					Err(_) => {
-						if let Ok(mut sessions) = ctx.state.sessions.lock()
-							&& let Some(session) = sessions.get_mut(&session_key)
-							&& session
-								.search_rx
-								.as_ref()
-								.map(|current| Arc::ptr_eq(current, &rx))
-								.unwrap_or(false)
-						{
-							session.search_in_progress = false;
-							session.search_rx = None;
-							rebuild_search_results(session);
-							if session.search_status == "Search started" {
-								session.search_status =
-									format!("Found {} result(s)", session.search_raw_rows.len());
-							}
-						}
-						ctx.push_state_for_client(client_id, route_path.clone());
-						break;
-					}
+				let Ok(event) = event else {
+					update(SearchMsg::StreamClosed { stream });
+					break;
 				};
-				if let Ok(mut sessions) = ctx.state.sessions.lock()
-					&& let Some(session) = sessions.get_mut(&session_key)
-				{
-					if !session
-						.search_rx
-						.as_ref()
-						.map(|current| Arc::ptr_eq(current, &rx))
-						.unwrap_or(false)
-					{
-						break;
-					}
-					match event.event {
-						SearchEvent::Rows { rows } => {
-							session.search_raw_rows.extend(rows.into_iter().map(|row| {
-								UiSearchRawRow {
-									name: row.name,
-									path: row.path,
-									size: row.size,
-									mime_type: row.mime_type,
-									modified_at: row.modified_at,
-									peer_id: event.peer.to_string(),
-								}
-							}));
-							rebuild_search_results(session);
-							session.search_status =
-								format!("Searching... {} result(s)", session.search_raw_rows.len());
-						}
-						SearchEvent::Progress { visited, matched } => {
-							session.search_status =
-								format!("Searching... visited {visited}, matched {matched}");
-						}
-						SearchEvent::Finished { total, truncated } => {
-							session.search_done_peers = session.search_done_peers.saturating_add(1);
-							session.search_truncated |= truncated;
-							rebuild_search_results(session);
-							if session.search_done_peers >= session.search_total_peers {
-								session.search_in_progress = false;
-								session.search_rx = None;
-								session.search_status = if session.search_truncated {
-									format!(
-										"Found {} result(s); result set was truncated",
-										session.search_raw_rows.len()
-									)
-								} else {
-									format!("Found {} result(s)", session.search_raw_rows.len())
-								};
-							} else {
-								session.search_status = format!(
-									"Device finished with {total} result(s); {}/{} done",
-									session.search_done_peers, session.search_total_peers
-								);
-							}
-						}
-						SearchEvent::Failed { error } => {
-							session.search_done_peers = session.search_done_peers.saturating_add(1);
-							rebuild_search_results(session);
-							if session.search_done_peers >= session.search_total_peers {
-								session.search_in_progress = false;
-								session.search_rx = None;
-							}
-							session.search_status = format!("Search failed on a device: {error}");
-						}
-					}
+				// Looked up before the session lock; the index knows
+				// durations of content this node has scanned or synced.
+				let durations = match &event.event {
+					SearchEvent::Rows { rows } => rows
+						.iter()
+						.map(|row| {
+							let is_media = row.mime_type.as_deref().is_some_and(is_media_mime);
+							is_media
+								.then(|| puppy.media_metadata_at(event.peer, &row.path))
+								.and_then(|metadata| metadata.ok().flatten())
+								.and_then(|metadata| metadata.duration_ms)
+						})
+						.collect(),
+					_ => Vec::new(),
+				};
+				let applied = update(SearchMsg::Event {
+					stream: Arc::clone(&stream),
+					peer: event.peer.to_string(),
+					event: event.event,
+					durations,
+				});
+				if !applied {
+					break;
 				}
-				ctx.push_state_for_client(client_id, route_path.clone());
 			}
 		});
 	}
@@ -2305,11 +9064,11 @@ impl UiControllerCore<'_> {
 		}
 		let snapshot = self.block_on(self.ctx.state.server.snapshot());
 		let session = self.current_session();
-		let query = session.search_name_query.clone();
-		let target = if session.search_target.is_empty() {
+		let query = session.search.name_query.clone();
+		let target = if session.search.target.is_empty() {
 			String::from(SEARCH_ALL_DEVICES)
 		} else {
-			session.search_target.clone()
+			session.search.target.clone()
 		};
 		let peer_ids = snapshot
 			.peers
@@ -2319,26 +9078,24 @@ impl UiControllerCore<'_> {
 			.collect::<Vec<_>>();
 		if peer_ids.is_empty() {
 			self.update_session(|session| {
-				session.search_raw_rows.clear();
-				session.search_results.clear();
-				session.search_status = String::from("No target devices available");
-				session.search_in_progress = false;
-				session.search_rx = None;
+				session.search.update(SearchMsg::Stopped {
+					status: String::from("No target devices available"),
+				});
 			});
 			return;
 		}
 		let total_peers = peer_ids.len();
-		let page_size = search_page_size(&session.search_page_size);
+		let page_size = search_page_size(&session.search.page_size);
 		let args = LiveSearchArgs {
 			name_query: if query.trim().is_empty() {
 				None
 			} else {
 				Some(query.clone())
 			},
-			mime_types: session.search_selected_mimes.clone(),
+			mime_types: session.search.selected_mimes.clone(),
 			page: 0,
 			page_size,
-			sort: search_sort(&session.search_sort),
+			sort: search_sort(&session.search.sort),
 			sort_desc: true,
 		};
 		match self
@@ -2351,26 +9108,20 @@ impl UiControllerCore<'_> {
 			Ok(rx) => {
 				let watch_rx = Arc::clone(&rx);
 				self.update_session(|session| {
-					session.search_target = target;
-					session.search_visible_count = page_size;
-					session.search_raw_rows.clear();
-					session.search_results.clear();
-					session.search_status = String::from("Search started");
-					session.search_in_progress = true;
-					session.search_total_peers = total_peers;
-					session.search_done_peers = 0;
-					session.search_truncated = false;
-					session.search_rx = Some(rx);
+					session.search.update(SearchMsg::Started {
+						target,
+						page_size,
+						total_peers,
+						stream: rx,
+					});
 				});
 				self.watch_live_search(watch_rx);
 			}
 			Err(err) => {
 				self.update_session(|session| {
-					session.search_status = format!("Search failed: {err}");
-					session.search_raw_rows.clear();
-					session.search_results.clear();
-					session.search_in_progress = false;
-					session.search_rx = None;
+					session.search.update(SearchMsg::Stopped {
+						status: self.notify_error("Search failed", err),
+					});
 				});
 			}
 		}
@@ -2383,12 +9134,14 @@ impl UiControllerCore<'_> {
 		}
 		let row = self
 			.current_session()
-			.search_results
+			.search
+			.results
 			.get(idx as usize)
 			.cloned();
 		if let Some(row) = row {
 			self.update_session(|session| {
 				session.file_preview_path = row.path;
+				session.file_preview_raw_path = None;
 				session.file_preview_peer = row.peer_id;
 				session.file_preview_status.clear();
 				session.file_preview_content.clear();
@@ -2400,6 +9153,84 @@ impl UiControllerCore<'_> {
 		}
 	}
 
+	/// The first press picks a result to compare; the second compares the
+	/// picked file with this one and shows the diff.
+	pub fn search_compare(&self, idx: u32) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let (row, first) = {
+			let session = self.current_session();
+			(
+				session.search.results.get(idx as usize).cloned(),
+				session.compare_first.clone(),
+			)
+		};
+		let Some(row) = row else {
+			return;
+		};
+		let Some((first_peer, first_path)) = first else {
+			self.update_session(|session| {
+				session.compare_first = Some((row.peer_id, row.path));
+			});
+			return;
+		};
+		let (Some(peer_a), Some(peer_b)) = (
+			self.resolve_peer_ref(&first_peer),
+			self.resolve_peer_ref(&row.peer_id),
+		) else {
+			self.update_session(|session| {
+				session.compare_first = None;
+				session.search.status = String::from("Cannot compare: unknown device");
+			});
+			return;
+		};
+		self.update_session(|session| {
+			session.compare_first = None;
+			session.compare_modal_open = true;
+			session.compare_title = format!(
+				"{first_path} on {} vs {} on {}",
+				abbrev_peer_id(&first_peer),
+				row.path,
+				abbrev_peer_id(&row.peer_id)
+			);
+			session.compare_status = String::from("Comparing...");
+			session.compare_diff = None;
+		});
+		let result = self.block_on(self.ctx.state.server.puppy.diff_files(
+			FileRef::new(peer_a, first_path),
+			FileRef::new(peer_b, row.path),
+			DiffOptions::default(),
+		));
+		self.update_session(|session| match result {
+			Ok(diff) => {
+				session.compare_status = diff_summary(&diff);
+				session.compare_diff = Some(diff);
+			}
+			Err(err) => session.compare_status = self.notify_error("Compare failed", err),
+		});
+	}
+
+	pub fn cancel_search_compare(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| session.compare_first = None);
+	}
+
+	pub fn close_compare_modal(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session.compare_modal_open = false;
+			session.compare_diff = None;
+		});
+	}
+
 	pub fn close_file_preview_modal(&self) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
@@ -2407,7 +9238,126 @@ impl UiControllerCore<'_> {
 		}
 		self.update_session(|session| {
 			session.file_preview_modal_open = false;
+			session.file_preview_download_status.clear();
+			session.file_preview_previous_download = None;
+			session.file_preview_contact_sheet = false;
+		});
+	}
+
+	/// Moves the focus marker over the device list; Enter opens the device.
+	pub fn peers_key(&self, payload: wgui::serde_json::Value) {
+		let Some(key) = json_focus_key(&payload) else {
+			return;
+		};
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let view = self.state();
+		let rows = view
+			.peers
+			.iter()
+			.map(|peer| FocusRow {
+				key: &peer.id,
+				label: &peer.label,
+			})
+			.collect::<Vec<_>>();
+		let mut focus = self.current_session().peers_focus;
+		let action = focus.handle(key, &rows, std::time::Instant::now());
+		self.update_session(|session| session.peers_focus = focus);
+		if let FocusAction::Activate(idx) = action {
+			self.peer_row(idx as u32);
+		}
+	}
+
+	/// Enter opens the focused folder or previews the focused file, Escape
+	/// closes the preview or goes up a folder.
+	pub fn peer_files_key(&self, payload: wgui::serde_json::Value) {
+		let Some(key) = json_focus_key(&payload) else {
+			return;
+		};
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let session = self.current_session();
+		if key == Key::Escape && session.file_preview_modal_open {
+			self.close_file_preview_modal();
+			return;
+		}
+		let view = self.state();
+		let rows = view
+			.peer_files
+			.iter()
+			.map(|entry| FocusRow {
+				key: &entry.href,
+				label: &entry.name,
+			})
+			.collect::<Vec<_>>();
+		let mut focus = session.peer_files_focus;
+		let action = focus.handle(key, &rows, std::time::Instant::now());
+		let focused = focus.index(&rows);
+		self.update_session(|session| {
+			session.peer_files_focus = focus;
+			// Rendering rows past the focused one keeps focus from ever
+			// reaching the end of what is rendered.
+			if let Some(idx) = focused {
+				session.peer_files.update(PeerFilesMsg::Focused(idx));
+			}
 		});
+		match action {
+			FocusAction::Activate(idx) => match view.peer_files.get(idx) {
+				Some(entry) if entry.is_dir => self.ctx.push_state(entry.href.clone()),
+				Some(_) => self.preview_peer_file(idx as u32),
+				None => {}
+			},
+			FocusAction::Back if view.peer_files_has_parent => {
+				self.ctx.push_state(view.peer_files_parent_href)
+			}
+			FocusAction::Back => self.ctx.push_state(view.selected_peer_details_href),
+			FocusAction::None | FocusAction::Moved | FocusAction::NextPage => {}
+		}
+	}
+
+	/// Enter previews the focused result and PageDown on the last result
+	/// loads more.
+	pub fn search_key(&self, payload: wgui::serde_json::Value) {
+		let Some(key) = json_focus_key(&payload) else {
+			return;
+		};
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let session = self.current_session();
+		if key == Key::Escape && session.file_preview_modal_open {
+			self.close_file_preview_modal();
+			return;
+		}
+		let keys = session
+			.search
+			.results
+			.iter()
+			.map(search_focus_key)
+			.collect::<Vec<_>>();
+		let rows = session
+			.search
+			.results
+			.iter()
+			.zip(&keys)
+			.map(|(row, key)| FocusRow {
+				key,
+				label: &row.name,
+			})
+			.collect::<Vec<_>>();
+		let mut focus = session.search_focus.clone();
+		let action = focus.handle(key, &rows, std::time::Instant::now());
+		self.update_session(|session| session.search_focus = focus);
+		match action {
+			FocusAction::Activate(idx) => self.search_preview(idx as u32),
+			FocusAction::NextPage => self.search_load_more(),
+			FocusAction::None | FocusAction::Moved | FocusAction::Back => {}
+		}
 	}
 
 	pub fn edit_file_preview_path(&self, value: String) {
@@ -2417,6 +9367,7 @@ impl UiControllerCore<'_> {
 		}
 		self.update_session(|session| {
 			session.file_preview_path = value;
+			session.file_preview_raw_path = None;
 			session.file_preview_status.clear();
 			session.file_preview_content.clear();
 			session.file_preview_image_src.clear();
@@ -2439,6 +9390,18 @@ impl UiControllerCore<'_> {
 	}
 
 	pub fn load_file_preview(&self) {
+		self.update_session(|session| {
+			session.file_preview_history.clear();
+			session.file_preview_download_status.clear();
+			session.file_preview_previous_download = None;
+			session.file_preview_large_image = None;
+		});
+		self.load_file_preview_page(0, 1);
+	}
+
+	/// Copies the previewed file into the download folder for its peer.
+	/// Without `force`, a file downloaded before asks first.
+	fn download_preview_file_with(&self, force: bool) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
@@ -2450,6 +9413,117 @@ impl UiControllerCore<'_> {
 				session.file_preview_path.trim().to_string(),
 			)
 		};
+		let Some(peer) = self.resolve_peer_ref(&peer_text) else {
+			self.update_session(|session| {
+				session.file_preview_download_status = String::from("Invalid or missing peer id");
+			});
+			return;
+		};
+		let result = self.block_on(self.ctx.state.server.puppy.download_file_with_progress(
+			peer,
+			&path,
+			force,
+			|progress| {
+				self.update_session(|session| {
+					session.file_preview_download_status = transfer_line("Downloading", &progress);
+				});
+			},
+		));
+		self.update_session(|session| match result {
+			Ok(DownloadOutcome::Downloaded(transfer)) => {
+				session.file_preview_previous_download = None;
+				session.file_preview_download_status = format!("Saved to {}", transfer.local_path);
+			}
+			Ok(DownloadOutcome::AlreadyDownloaded(transfer)) => {
+				session.file_preview_download_status.clear();
+				session.file_preview_previous_download = Some(transfer);
+			}
+			Err(err) => {
+				session.file_preview_previous_download = None;
+				session.file_preview_download_status = self.notify_error("Download failed", err);
+			}
+		});
+	}
+
+	pub fn download_preview_file(&self) {
+		self.download_preview_file_with(false);
+	}
+
+	pub fn download_preview_file_again(&self) {
+		self.download_preview_file_with(true);
+	}
+
+	/// Previews the local copy from the earlier download instead.
+	pub fn open_previous_download(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let Some(transfer) = self.current_session().file_preview_previous_download else {
+			return;
+		};
+		self.update_session(|session| {
+			session.file_preview_peer.clear();
+			session.file_preview_path = transfer.local_path;
+			session.file_preview_raw_path = None;
+			session.file_preview_status.clear();
+			session.file_preview_content.clear();
+			session.file_preview_image_src.clear();
+			session.file_preview_loaded = false;
+		});
+		self.load_file_preview();
+	}
+
+	/// Previews the image the viewer warned about anyway.
+	pub fn preview_large_image(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let session = self.current_session();
+		let Some(peer) = session.file_preview_large_image else {
+			return;
+		};
+		let path = session.file_preview_path.trim().to_string();
+		self.load_image_preview(peer, path, peer.to_string());
+	}
+
+	pub fn next_file_preview_page(&self) {
+		let session = self.current_session();
+		let Some((offset, first_line)) = session.file_preview_next else {
+			return;
+		};
+		self.update_session(|session| {
+			session
+				.file_preview_history
+				.push((session.file_preview_offset, session.file_preview_first_line));
+		});
+		self.load_file_preview_page(offset, first_line);
+	}
+
+	pub fn previous_file_preview_page(&self) {
+		let mut previous = None;
+		self.update_session(|session| {
+			previous = session.file_preview_history.pop();
+		});
+		if let Some((offset, first_line)) = previous {
+			self.load_file_preview_page(offset, first_line);
+		}
+	}
+
+	fn load_file_preview_page(&self, offset: u64, first_line: usize) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let (peer_text, path, raw_path) = {
+			let session = self.current_session();
+			(
+				session.file_preview_peer.trim().to_string(),
+				session.file_preview_path.trim().to_string(),
+				session.file_preview_raw_path,
+			)
+		};
 		if path.is_empty() {
 			self.update_session(|session| {
 				session.file_preview_status = String::from("Path is required");
@@ -2484,67 +9558,136 @@ impl UiControllerCore<'_> {
 			}
 		};
 		let peer_label = peer.to_string();
-		if is_image_path(&path) {
-			match self.block_on(self.ctx.state.server.puppy.get_thumbnail(
-				peer,
-				path.clone(),
-				900,
-				700,
-			)) {
-				Ok(thumbnail) => {
-					let encoded = base64::engine::general_purpose::STANDARD.encode(thumbnail.data);
-					self.update_session(|session| {
-						session.file_preview_peer = peer_label.clone();
+		let media = match self.ctx.state.server.puppy.media_metadata_at(peer, &path) {
+			Ok(metadata) => metadata.and_then(|metadata| metadata.describe()),
+			Err(err) => {
+				tracing::warn!("failed to load media metadata for {path}: {err}");
+				None
+			}
+		};
+		self.update_session(|session| {
+			session.file_preview_media = media.unwrap_or_default();
+		});
+		let wire_path = match raw_path {
+			Some(bytes) => WirePath::Raw(bytes),
+			None => WirePath::Display(path.clone()),
+		};
+		let chunk = match self.block_on(self.ctx.state.server.puppy.read_file_range(
+			peer,
+			wire_path,
+			offset..offset + PREVIEW_PAGE_SIZE,
+		)) {
+			Ok(chunk) => chunk,
+			Err(err) => {
+				self.update_session(|session| {
+					session.file_preview_peer = peer_label;
+					session.file_preview_status = self.notify_error("Failed to read file", err);
+					session.file_preview_content.clear();
+					session.file_preview_image_src.clear();
+					session.file_preview_loaded = false;
+					session.file_preview_next = None;
+				});
+				return;
+			}
+		};
+		let preview = build_preview(&path, offset, &chunk.data, chunk.eof);
+		let end = offset + chunk.data.len() as u64;
+		let (status, content, next) = match preview.kind {
+			PreviewKind::Image => {
+				let budget = self.ctx.state.server.puppy.thumbnail_decode_budget();
+				match header_dimensions(&chunk.data)
+					.filter(|&(width, height)| decoded_size(width, height) > budget)
+				{
+					Some((width, height)) => self.update_session(|session| {
+						session.file_preview_peer = peer_label;
 						session.file_preview_status = format!(
-							"Loaded image preview ({}x{})",
-							thumbnail.width, thumbnail.height
+							"{width}x{height} image: previewing it decodes about {}, which may be refused or strain the device holding it",
+							human_size(decoded_size(width, height), SizeUnits::Binary)
 						);
-						session.file_preview_image_src =
-							format!("data:{};base64,{encoded}", thumbnail.mime_type);
-						session.file_preview_content.clear();
-						session.file_preview_loaded = true;
-					});
-				}
-				Err(err) => {
-					self.update_session(|session| {
-						session.file_preview_peer = peer_label.clone();
-						session.file_preview_status =
-							format!("Failed to load image preview: {err}");
 						session.file_preview_content.clear();
 						session.file_preview_image_src.clear();
-						session.file_preview_loaded = false;
-					});
+						session.file_preview_loaded = true;
+						session.file_preview_next = None;
+						session.file_preview_large_image = Some(peer);
+					}),
+					None => self.load_image_preview(peer, path, peer_label),
 				}
+				return;
 			}
-			return;
-		}
-		match self.block_on(self.ctx.state.server.puppy.read_file(
-			peer,
-			path.clone(),
-			0,
-			Some(8 * 1024),
-		)) {
-			Ok(chunk) => {
-				let preview = format_preview_bytes(&chunk.data);
+			PreviewKind::Text => (
+				format!(
+					"{} - lines {}-{} (bytes {offset}-{end})",
+					preview.detected_mime,
+					first_line,
+					first_line + preview.line_count.saturating_sub(1)
+				),
+				number_lines(&preview.content, first_line),
+				preview.next_offset.map(|next| {
+					let newlines = preview.content.matches('\n').count();
+					(next, first_line + newlines)
+				}),
+			),
+			PreviewKind::Binary | PreviewKind::TooLarge => (
+				format!("{} - bytes {offset}-{end}", preview.detected_mime),
+				preview.content,
+				preview.next_offset.map(|next| (next, first_line)),
+			),
+		};
+		self.update_session(|session| {
+			session.file_preview_peer = peer_label;
+			session.file_preview_status = status;
+			session.file_preview_content = content;
+			session.file_preview_image_src.clear();
+			session.file_preview_loaded = true;
+			session.file_preview_offset = offset;
+			session.file_preview_first_line = first_line;
+			session.file_preview_next = next;
+		});
+	}
+
+	fn load_image_preview(&self, peer: PeerId, path: String, peer_label: String) {
+		self.update_session(|session| session.file_preview_large_image = None);
+		match self.block_on(
+			self.ctx
+				.state
+				.server
+				.puppy
+				.get_thumbnail(peer, path, 900, 700),
+		) {
+			Ok(thumbnail) => {
+				let encoded = base64::engine::general_purpose::STANDARD.encode(thumbnail.data);
 				self.update_session(|session| {
-					session.file_preview_peer = peer_label.clone();
+					session.file_preview_peer = peer_label;
 					session.file_preview_status = format!(
-						"Loaded {} byte(s){}",
-						chunk.data.len(),
-						if chunk.eof { "" } else { " (truncated)" }
+						"Loaded image preview ({}x{})",
+						thumbnail.width, thumbnail.height
 					);
-					session.file_preview_content = preview;
-					session.file_preview_image_src.clear();
+					session.file_preview_image_src =
+						format!("data:{};base64,{encoded}", thumbnail.mime_type);
+					session.file_preview_content.clear();
 					session.file_preview_loaded = true;
+					session.file_preview_next = None;
 				});
 			}
 			Err(err) => {
+				let status = match err.downcast_ref::<ImageTooLarge>() {
+					Some(too_large) => self.notify(
+						Severity::Warning,
+						format!(
+							"Preview unavailable — image too large ({}x{})",
+							too_large.width, too_large.height
+						),
+						"",
+					),
+					None => self.notify_error("Failed to load image preview", err),
+				};
 				self.update_session(|session| {
 					session.file_preview_peer = peer_label;
-					session.file_preview_status = format!("Failed to read file: {err}");
+					session.file_preview_status = status;
 					session.file_preview_content.clear();
 					session.file_preview_image_src.clear();
 					session.file_preview_loaded = false;
+					session.file_preview_next = None;
 				});
 			}
 		}
@@ -2621,7 +9764,7 @@ impl UiControllerCore<'_> {
 			}
 			Err(err) => {
 				self.update_session(|session| {
-					session.control_status = format!("Control input failed: {err}");
+					session.control_status = self.notify_error("Control input failed", err);
 				});
 				false
 			}
@@ -2638,107 +9781,146 @@ impl UiControllerCore<'_> {
 				.desktop_input(peer, DesktopInput::MouseMove { dx, dy })
 				.await
 			{
-				log::warn!("mouse move input failed: {err}");
+				tracing::warn!("mouse move input failed: {err}");
 			}
 		});
 	}
 
-	pub fn edit_shell_input(&self, value: String) {
+	pub fn edit_peer_shell_input(&self, value: String) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		self.update_session(|session| {
-			session.shell_input = value;
-		});
+		self.update_session(|session| session.peer_shell.update(PeerShellMsg::InputEdited(value)));
 	}
 
-	pub fn start_shell(&self) {
+	/// Writes `data` to this client's shell and shows what it printed.
+	fn write_peer_shell(&self, data: Vec<u8>) -> bool {
+		let live = self
+			.current_session()
+			.peer_shell
+			.live()
+			.map(|(peer_id, session_id)| (peer_id.to_string(), session_id));
+		let Some((peer_id, session_id)) = live else {
+			return false;
+		};
+		let msg = match PeerId::from_str(&peer_id) {
+			Ok(peer) => match self.block_on(
+				self.ctx
+					.state
+					.server
+					.puppy
+					.shell_input(peer, session_id, data),
+			) {
+				Ok(output) => PeerShellMsg::from_output(session_id, output),
+				Err(err) => PeerShellMsg::Lost {
+					session_id,
+					error: self.notify_error("Shell input failed", err),
+				},
+			},
+			Err(err) => PeerShellMsg::Lost {
+				session_id,
+				error: format!("Invalid device id: {err}"),
+			},
+		};
+		self.update_session(|session| session.peer_shell.update(msg));
+		true
+	}
+
+	pub fn send_peer_shell_input(&self) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		let selected_peer = self
-			.block_on(self.ctx.state.server.snapshot())
-			.selected_peer;
-		let Some(selected_peer) = selected_peer else {
-			self.update_session(|session| {
-				session.shell_status = String::from("Select a peer first");
-			});
+		let mut line = self.current_session().peer_shell.input;
+		line.push('\n');
+		if self.write_peer_shell(line.into_bytes()) {
+			self.update_session(|session| session.peer_shell.update(PeerShellMsg::Sent));
+		}
+	}
+
+	/// Sends Ctrl-C, the interrupt byte, to this client's shell.
+	pub fn interrupt_peer_shell(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
 			return;
-		};
-		let Ok(peer) = PeerId::from_str(&selected_peer) else {
-			self.update_session(|session| {
-				session.shell_status = String::from("Invalid selected peer");
-			});
+		}
+		self.write_peer_shell(vec![0x03]);
+	}
+
+	pub fn restart_peer_shell(&self, peer_id: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		if self.current_session().peer_shell.ended() {
+			self.start_peer_shell(peer_id);
+		}
+	}
+
+	pub fn edit_send_file_path(&self, value: String) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
 			return;
-		};
-		let session_id = std::time::SystemTime::now()
-			.duration_since(std::time::UNIX_EPOCH)
-			.map(|value| value.as_millis() as u64)
-			.unwrap_or(1);
-		match self.block_on(self.ctx.state.server.puppy.start_shell(peer, session_id)) {
-			Ok(remote_session) => {
-				self.update_session(|session| {
-					session.shell_peer = selected_peer;
-					session.shell_session_id = Some(remote_session);
-					session.shell_status = format!("Shell started (session {remote_session})");
-				});
-			}
-			Err(err) => {
-				self.update_session(|session| {
-					session.shell_status = format!("Failed to start shell: {err}");
-					session.shell_session_id = None;
-				});
-			}
 		}
+		self.update_session(|session| {
+			session.send_file_path = value;
+		});
 	}
 
-	pub fn send_shell_input(&self) {
+	pub fn send_file(&self) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		let (peer_text, session_id, input) = {
-			let session = self.current_session();
-			(
-				session.shell_peer.clone(),
-				session.shell_session_id,
-				session.shell_input.clone(),
-			)
-		};
-		if input.is_empty() {
+		let local_path = self.current_session().send_file_path.trim().to_string();
+		if local_path.is_empty() {
+			self.update_session(|session| {
+				session.send_file_status = String::from("Enter a file path first");
+			});
 			return;
 		}
-		let Some(session_id) = session_id else {
+		let selected_peer = self
+			.block_on(self.ctx.state.server.snapshot())
+			.selected_peer;
+		let Some(selected_peer) = selected_peer else {
 			self.update_session(|session| {
-				session.shell_status = String::from("Start a shell first");
+				session.send_file_status = String::from("Select a peer first");
 			});
 			return;
 		};
-		let Ok(peer) = PeerId::from_str(&peer_text) else {
+		let Ok(peer) = PeerId::from_str(&selected_peer) else {
 			self.update_session(|session| {
-				session.shell_status = String::from("Invalid shell peer");
+				session.send_file_status = String::from("Invalid selected peer");
 			});
 			return;
 		};
-		match self.block_on(self.ctx.state.server.puppy.shell_input(
+		let result = self.block_on(self.ctx.state.server.puppy.send_file_with_progress(
 			peer,
-			session_id,
-			input.clone().into_bytes(),
-		)) {
-			Ok(out) => {
-				let out_text = String::from_utf8_lossy(&out);
+			&local_path,
+			|progress| {
+				self.update_session(|session| {
+					session.send_file_status = transfer_line("Sending", &progress);
+				});
+			},
+		));
+		match result {
+			Ok(upload) => {
+				let mut savings = SendSavings::default();
+				savings.add(&upload);
+				let status = match (upload.remote_path(), savings.describe()) {
+					(Some(remote_path), Some(note)) => format!("Sent as {remote_path}, {note}"),
+					(Some(remote_path), None) => format!("Sent as {remote_path}"),
+					(None, note) => format!("Not sent, {}", note.unwrap_or_default()),
+				};
 				self.update_session(|session| {
-					session.shell_output.push_str(&input);
-					session.shell_output.push_str(&out_text);
-					session.shell_input.clear();
-					session.shell_status = String::from("Shell command sent");
+					session.send_file_path.clear();
+					session.send_file_status = status;
 				});
 			}
 			Err(err) => {
 				self.update_session(|session| {
-					session.shell_status = format!("Shell command failed: {err}");
+					session.send_file_status = self.notify_error("Send failed", err);
 				});
 			}
 		}
@@ -2835,211 +10017,406 @@ impl UiControllerCore<'_> {
 		});
 	}
 
-	fn watch_peer_update(&self, rx: Arc<std::sync::Mutex<mpsc::Receiver<UpdateProgress>>>) {
-		let Some(client_id) = self.ctx.client_id() else {
+	pub fn start_peer_update(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let selected_peer = self
+			.block_on(self.ctx.state.server.snapshot())
+			.selected_peer;
+		let Some(selected_peer) = selected_peer else {
+			self.update_session(|session| {
+				session.update_status = String::from("Select a peer first");
+			});
 			return;
 		};
-		let session_key = self.session_key();
-		let route_path = self
+		let Ok(peer) = PeerId::from_str(&selected_peer) else {
+			self.update_session(|session| {
+				session.update_status = String::from("Invalid selected peer");
+			});
+			return;
+		};
+		let version = {
+			let session = self.current_session();
+			let trimmed = session.update_version.trim().to_string();
+			if trimmed.is_empty() {
+				None
+			} else {
+				Some(trimmed)
+			}
+		};
+		let restart = self
+			.current_session()
+			.restart_after_update
+			.then(|| (Arc::clone(&self.ctx.state.server.puppy), peer));
+		match self.ctx.state.server.puppy.update_remote_peer_with_retry(
+			peer,
+			version,
+			UpdateRetryPolicy::default(),
+		) {
+			Ok(rx) => {
+				let reporter = self.ctx.state.jobs.start(
+					self.ctx.state.server.puppy.next_id(IdKind::Job),
+					format!("Update {}", abbrev_peer_id(&selected_peer)),
+					None,
+				);
+				let job_id = reporter.id();
+				forward_update_progress(reporter, rx, restart);
+				self.update_session(|session| {
+					session.update_job = Some(job_id);
+					session.update_status = String::from("Update started");
+				});
+			}
+			Err(err) => {
+				self.update_session(|session| {
+					session.update_status = self.notify_error("Failed to start update", err);
+				});
+			}
+		}
+	}
+
+	/// Updates every peer the device list found on an older release, a
+	/// few at a time, as one job.
+	pub fn update_outdated_peers(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let peers = self
+			.block_on(self.ctx.state.server.snapshot())
+			.outdated_peers;
+		if peers.is_empty() {
+			self.update_session(|session| {
+				session.fleet_update_status = String::from("All peers are up to date");
+			});
+			return;
+		}
+		let puppy = Arc::clone(&self.ctx.state.server.puppy);
+		let reporter = self.ctx.state.jobs.start(
+			puppy.next_id(IdKind::Job),
+			format!("Update {} peer(s)", peers.len()),
+			None,
+		);
+		let count = peers.len();
+		tokio::spawn(update_peers(puppy, peers, reporter));
+		self.update_session(|session| {
+			session.fleet_update_status =
+				format!("Updating {count} peer(s); follow the progress under Jobs");
+		});
+	}
+
+	pub fn toggle_peer_failures(&self) {
+		self.update_session(|session| {
+			session.peer_failures_open = !session.peer_failures_open;
+		});
+	}
+
+	pub fn toggle_restart_after_update(&self) {
+		self.update_session(|session| {
+			session.restart_after_update = !session.restart_after_update;
+		});
+	}
+
+	pub fn request_peer_restart(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session.restart_confirm = true;
+		});
+	}
+
+	pub fn cancel_peer_restart(&self) {
+		self.update_session(|session| {
+			session.restart_confirm = false;
+		});
+	}
+
+	pub fn confirm_peer_restart(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			session.restart_confirm = false;
+		});
+		let selected_peer = self
+			.block_on(self.ctx.state.server.snapshot())
+			.selected_peer;
+		let Some(selected_peer) = selected_peer else {
+			self.update_session(|session| {
+				session.restart_status = String::from("Select a peer first");
+			});
+			return;
+		};
+		let Ok(peer) = PeerId::from_str(&selected_peer) else {
+			self.update_session(|session| {
+				session.restart_status = String::from("Invalid selected peer");
+			});
+			return;
+		};
+		let reporter = self.ctx.state.jobs.start(
+			self.ctx.state.server.puppy.next_id(IdKind::Job),
+			format!("Restart {}", abbrev_peer_id(&selected_peer)),
+			None,
+		);
+		let job_id = reporter.id();
+		tokio::spawn(restart_and_watch(
+			Arc::clone(&self.ctx.state.server.puppy),
+			peer,
+			reporter,
+		));
+		self.update_session(|session| {
+			session.restart_job = Some(job_id);
+			session.restart_status = String::from("Restart requested");
+		});
+	}
+
+	/// Wakes the selected peer over the LAN and follows it until it
+	/// connects.
+	pub fn wake_peer(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let selected_peer = self
+			.block_on(self.ctx.state.server.snapshot())
+			.selected_peer;
+		let Some(selected_peer) = selected_peer else {
+			self.update_session(|session| {
+				session.wake_status = String::from("Select a peer first");
+			});
+			return;
+		};
+		let Ok(peer) = PeerId::from_str(&selected_peer) else {
+			self.update_session(|session| {
+				session.wake_status = String::from("Invalid selected peer");
+			});
+			return;
+		};
+		let reporter = self.ctx.state.jobs.start(
+			self.ctx.state.server.puppy.next_id(IdKind::Job),
+			format!("Wake {}", abbrev_peer_id(&selected_peer)),
+			None,
+		);
+		let job_id = reporter.id();
+		tokio::spawn(wake_and_watch(
+			Arc::clone(&self.ctx.state.server.puppy),
+			peer,
+			reporter,
+		));
+		self.update_session(|session| {
+			session.wake_job = Some(job_id);
+			session.wake_status = String::from("Sending magic packet...");
+		});
+	}
+
+	pub fn cancel_job(&self, id: u64) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.ctx.state.jobs.cancel(id);
+	}
+
+	/// Runs `action` on the pin at `idx` on the Jobs page and reloads the
+	/// list.
+	fn update_pin(&self, idx: u32, verb: &str, action: impl FnOnce(&PuppyNet, u64) -> Result<()>) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let Some(pin) = snapshot.pins.get(idx as usize) else {
+			return;
+		};
+		let result = action(&self.ctx.state.server.puppy, pin.id);
+		self.block_on(async {
+			let mut state = self.ctx.state.server.state.lock().await;
+			state.status = match result {
+				Ok(()) => format!("{verb} {}", pin.remote_path),
+				Err(err) => self.notify_error("Failed to update pin", format!("{err:#}")),
+			};
+		});
+		self.block_on(self.ctx.state.server.refresh_pins());
+	}
+
+	fn set_thumbnail_pregeneration(&self, enabled: bool) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let result = self
 			.ctx
-			.route()
-			.map(|route| route.path)
-			.unwrap_or_else(|| String::from("/devices"));
-		let ctx = Arc::clone(self.ctx);
-		std::thread::spawn(move || {
-			loop {
-				let event = match rx.lock() {
-					Ok(stream) => stream.recv(),
-					Err(err) => {
-						if let Ok(mut sessions) = ctx.state.sessions.lock()
-							&& let Some(session) = sessions.get_mut(&session_key)
-						{
-							session.update_status = format!("Update stream lock failed: {err}");
-							session.update_in_progress = false;
-							if session
-								.update_rx
-								.as_ref()
-								.map(|current| Arc::ptr_eq(current, &rx))
-								.unwrap_or(false)
-							{
-								session.update_rx = None;
-							}
-						}
-						ctx.push_state_for_client(client_id, route_path.clone());
-						break;
-					}
-				};
-				let event = match event {
-					Ok(event) => event,
-					Err(_) => {
-						if let Ok(mut sessions) = ctx.state.sessions.lock()
-							&& let Some(session) = sessions.get_mut(&session_key)
-							&& session
-								.update_rx
-								.as_ref()
-								.map(|current| Arc::ptr_eq(current, &rx))
-								.unwrap_or(false)
-						{
-							session.update_in_progress = false;
-							session.update_rx = None;
-						}
-						ctx.push_state_for_client(client_id, route_path.clone());
-						break;
-					}
+			.state
+			.server
+			.puppy
+			.set_thumbnail_pregeneration(enabled);
+		self.block_on(async {
+			let mut state = self.ctx.state.server.state.lock().await;
+			state.status = match result {
+				Ok(()) if enabled => String::from("Scanned images get thumbnails while idle"),
+				Ok(()) => String::from("Thumbnail pregeneration turned off"),
+				Err(err) => self.notify_error(
+					"Failed to change thumbnail pregeneration",
+					format!("{err:#}"),
+				),
+			};
+		});
+	}
+
+	pub fn enable_thumbnail_pregeneration(&self) {
+		self.set_thumbnail_pregeneration(true);
+	}
+
+	pub fn disable_thumbnail_pregeneration(&self) {
+		self.set_thumbnail_pregeneration(false);
+	}
+
+	pub fn pause_pin(&self, idx: u32) {
+		self.update_pin(idx, "Paused", |puppy, id| puppy.pause_pin(id));
+	}
+
+	pub fn resume_pin(&self, idx: u32) {
+		self.update_pin(idx, "Resumed", |puppy, id| puppy.resume_pin(id));
+	}
+
+	pub fn unpin(&self, idx: u32) {
+		self.update_pin(idx, "Unpinned", |puppy, id| puppy.unpin(id));
+	}
+
+	fn update_outbox_draft<F>(&self, f: F)
+	where
+		F: FnOnce(&mut UiOutboxDraft),
+	{
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		self.update_session(|session| {
+			f(&mut session.outbox_draft);
+			session.outbox_status.clear();
+		});
+	}
+
+	pub fn edit_outbox_watch_path(&self, value: String) {
+		self.update_outbox_draft(|draft| draft.watch_path = value);
+	}
+
+	pub fn select_outbox_peer(&self, value: String) {
+		self.update_outbox_draft(|draft| draft.peer = value);
+	}
+
+	pub fn edit_outbox_dest_path(&self, value: String) {
+		self.update_outbox_draft(|draft| draft.dest_path = value);
+	}
+
+	pub fn edit_outbox_pattern(&self, value: String) {
+		self.update_outbox_draft(|draft| draft.pattern = value);
+	}
+
+	pub fn toggle_outbox_delete_after_send(&self) {
+		self.update_outbox_draft(|draft| draft.delete_after_send = !draft.delete_after_send);
+	}
+
+	pub fn cancel_outbox_edit(&self) {
+		self.update_outbox_draft(|draft| *draft = UiOutboxDraft::default());
+	}
+
+	pub fn save_outbox_rule(&self) {
+		if !self.is_authenticated() {
+			self.ctx.push_state("/login");
+			return;
+		}
+		let draft = self.current_session().outbox_draft;
+		let result = match draft.peer.parse::<PeerId>() {
+			Ok(dest_peer) => {
+				let rule = OutboxRule {
+					watch_path: PathBuf::from(draft.watch_path.trim()),
+					dest_peer,
+					dest_path: draft.dest_path,
+					delete_after_send: draft.delete_after_send,
+					pattern: draft.pattern,
 				};
-				let completed = matches!(
-					event,
-					UpdateProgress::Completed { .. }
-						| UpdateProgress::Failed { .. }
-						| UpdateProgress::AlreadyUpToDate { .. }
-				);
-				let line = format_update_progress(&event);
-				if let Ok(mut sessions) = ctx.state.sessions.lock()
-					&& let Some(session) = sessions.get_mut(&session_key)
-				{
-					if !session
-						.update_rx
-						.as_ref()
-						.map(|current| Arc::ptr_eq(current, &rx))
-						.unwrap_or(false)
-					{
-						break;
-					}
-					session.update_status = line.clone();
-					session.update_events.push(line);
-					if completed {
-						session.update_in_progress = false;
-						session.update_rx = None;
-					}
+				let puppy = &self.ctx.state.server.puppy;
+				match draft.editing {
+					Some(id) => puppy.update_outbox_rule(id, rule),
+					None => puppy.add_outbox_rule(rule).map(|_| ()),
 				}
-				ctx.push_state_for_client(client_id, route_path.clone());
-				if completed {
-					break;
+			}
+			Err(_) => Err(anyhow::anyhow!("choose the peer to send to")),
+		};
+		self.update_session(|session| {
+			session.outbox_status = match result {
+				Ok(()) => {
+					session.outbox_draft = UiOutboxDraft::default();
+					if draft.editing.is_some() {
+						String::from("Rule saved")
+					} else {
+						String::from(
+							"Watching the folder; new files are sent once they stop changing",
+						)
+					}
 				}
-			}
+				Err(err) => self.notify_error("Failed to save rule", format!("{err:#}")),
+			};
 		});
+		self.block_on(self.ctx.state.server.refresh_outboxes());
 	}
 
-	pub fn start_peer_update(&self) {
-		if !self.is_authenticated() {
-			self.ctx.push_state("/login");
-			return;
-		}
-		let selected_peer = self
-			.block_on(self.ctx.state.server.snapshot())
-			.selected_peer;
-		let Some(selected_peer) = selected_peer else {
-			self.update_session(|session| {
-				session.update_status = String::from("Select a peer first");
-			});
-			return;
-		};
-		let Ok(peer) = PeerId::from_str(&selected_peer) else {
-			self.update_session(|session| {
-				session.update_status = String::from("Invalid selected peer");
-			});
+	/// Loads rule `idx` into the form.
+	pub fn edit_outbox_rule(&self, idx: u32) {
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let Some(rule) = snapshot.outboxes.get(idx as usize) else {
 			return;
 		};
-		let version = {
-			let session = self.current_session();
-			let trimmed = session.update_version.trim().to_string();
-			if trimmed.is_empty() {
-				None
-			} else {
-				Some(trimmed)
-			}
-		};
-		match self
-			.ctx
-			.state
-			.server
-			.puppy
-			.update_remote_peer(peer, version)
-		{
-			Ok(rx) => {
-				let update_rx = Arc::clone(&rx);
-				self.update_session(|session| {
-					session.update_rx = Some(rx);
-					session.update_in_progress = true;
-					session.update_events.clear();
-					session.update_status = String::from("Update started");
-				});
-				self.watch_peer_update(update_rx);
+		self.update_outbox_draft(|draft| {
+			*draft = UiOutboxDraft {
+				editing: Some(rule.id),
+				watch_path: rule.watch_path.clone(),
+				peer: rule.dest_peer.clone(),
+				dest_path: rule.dest_path.clone(),
+				pattern: rule.pattern.clone(),
+				delete_after_send: rule.delete_after_send,
 			}
-			Err(err) => {
-				self.update_session(|session| {
-					session.update_status = format!("Failed to start update: {err}");
-				});
-			}
-		}
+		});
 	}
 
-	pub fn poll_peer_update(&self) {
+	fn update_outbox_rule(
+		&self,
+		idx: u32,
+		verb: &str,
+		action: impl FnOnce(&PuppyNet, u64) -> Result<()>,
+	) {
 		if !self.is_authenticated() {
 			self.ctx.push_state("/login");
 			return;
 		}
-		let rx = self.current_session().update_rx;
-		let Some(rx) = rx else {
-			self.update_session(|session| {
-				session.update_status = String::from("No update in progress");
-			});
+		let snapshot = self.block_on(self.ctx.state.server.snapshot());
+		let Some(rule) = snapshot.outboxes.get(idx as usize) else {
 			return;
 		};
-		let events = {
-			let mut events = Vec::new();
-			let stream = match rx.lock() {
-				Ok(guard) => guard,
-				Err(err) => {
-					self.update_session(|session| {
-						session.update_status = format!("Update stream lock failed: {err}");
-						session.update_in_progress = false;
-						session.update_rx = None;
-					});
-					return;
-				}
-			};
-			loop {
-				match stream.try_recv() {
-					Ok(event) => events.push(event),
-					Err(TryRecvError::Empty) => break,
-					Err(TryRecvError::Disconnected) => break,
-				}
-			}
-			events
+		let status = match action(&self.ctx.state.server.puppy, rule.id) {
+			Ok(()) => format!("{verb} {}", rule.watch_path),
+			Err(err) => self.notify_error("Failed to update rule", format!("{err:#}")),
 		};
-		if events.is_empty() {
-			self.update_session(|session| {
-				if session.update_in_progress {
-					session.update_status = String::from("Waiting for update events...");
-				}
-			});
-			return;
-		}
-		let mut completed = false;
-		let lines = events
-			.into_iter()
-			.map(|event| {
-				if matches!(
-					event,
-					UpdateProgress::Completed { .. }
-						| UpdateProgress::Failed { .. }
-						| UpdateProgress::AlreadyUpToDate { .. }
-				) {
-					completed = true;
-				}
-				format_update_progress(&event)
-			})
-			.collect::<Vec<_>>();
-		self.update_session(|session| {
-			for line in lines {
-				session.update_status = line.clone();
-				session.update_events.push(line);
-			}
-			if completed {
-				session.update_in_progress = false;
-				session.update_rx = None;
-			}
+		self.update_session(|session| session.outbox_status = status);
+		self.block_on(self.ctx.state.server.refresh_outboxes());
+	}
+
+	pub fn pause_outbox_rule(&self, idx: u32) {
+		self.update_outbox_rule(idx, "Paused", |puppy, id| puppy.pause_outbox_rule(id));
+	}
+
+	pub fn resume_outbox_rule(&self, idx: u32) {
+		self.update_outbox_rule(idx, "Resumed", |puppy, id| puppy.resume_outbox_rule(id));
+	}
+
+	pub fn remove_outbox_rule(&self, idx: u32) {
+		self.update_outbox_rule(idx, "Stopped watching", |puppy, id| {
+			puppy.remove_outbox_rule(id)
 		});
 	}
 
@@ -3050,6 +10427,7 @@ impl UiControllerCore<'_> {
 		}
 		self.update_session(|session| {
 			session.new_user_username = value;
+			session.new_user_username_error.clear();
 		});
 	}
 
@@ -3060,6 +10438,7 @@ impl UiControllerCore<'_> {
 		}
 		self.update_session(|session| {
 			session.new_user_password = value;
+			session.new_user_password_error.clear();
 		});
 	}
 
@@ -3083,6 +10462,8 @@ impl UiControllerCore<'_> {
 			session.new_user_modal_open = false;
 			session.new_user_status.clear();
 			session.new_user_password.clear();
+			session.new_user_username_error.clear();
+			session.new_user_password_error.clear();
 		});
 	}
 
@@ -3103,71 +10484,65 @@ impl UiControllerCore<'_> {
 		}
 	}
 
-	pub fn create_user_values(&self, username: String, password: String) -> bool {
-		let username = username.trim().to_string();
-		if username.is_empty() || password.trim().is_empty() {
-			self.update_session(|session| {
-				session.new_user_modal_open = true;
-				session.new_user_username = username;
-				session.new_user_status = String::from("Username and password are required");
-			});
-			return false;
-		}
-		match self.ctx.state.server.puppy.create_user(username, password) {
+	/// Shows how creating `username` went: the form closes on success and
+	/// stays open with the refused fields marked otherwise.
+	fn finish_create_user(&self, username: String, result: anyhow::Result<()>) -> bool {
+		match result {
 			Ok(()) => {
 				self.update_session(|session| {
 					session.new_user_username.clear();
 					session.new_user_password.clear();
+					session.new_user_username_error.clear();
+					session.new_user_password_error.clear();
 					session.new_user_modal_open = false;
 					session.new_user_status = String::from("User created");
 				});
 				true
 			}
 			Err(err) => {
+				let refused = err.downcast_ref::<CreateUserError>().cloned();
+				let field = |name| {
+					refused
+						.as_ref()
+						.and_then(|refused| refused.field(name))
+						.map(String::from)
+						.unwrap_or_default()
+				};
+				let (username_error, password_error) = (field("username"), field("password"));
 				self.update_session(|session| {
 					session.new_user_modal_open = true;
-					session.new_user_status = format!("Create user failed: {err}");
+					session.new_user_username = username;
+					session.new_user_username_error = username_error;
+					session.new_user_password_error = password_error;
+					session.new_user_status = match &refused {
+						Some(_) => String::new(),
+						None => self.notify_error("Create user failed", err),
+					};
 				});
 				false
 			}
 		}
 	}
 
+	pub fn create_user_values(&self, username: String, password: String) -> bool {
+		let result = self
+			.ctx
+			.state
+			.server
+			.puppy
+			.create_user(username.clone(), password);
+		self.finish_create_user(username, result)
+	}
+
 	pub async fn create_user_values_async(&self, username: String, password: String) -> bool {
-		let username = username.trim().to_string();
-		if username.is_empty() || password.trim().is_empty() {
-			self.update_session(|session| {
-				session.new_user_modal_open = true;
-				session.new_user_username = username;
-				session.new_user_status = String::from("Username and password are required");
-			});
-			return false;
-		}
-		match self
+		let result = self
 			.ctx
 			.state
 			.server
 			.puppy
-			.create_user_async(username, password)
-			.await
-		{
-			Ok(()) => {
-				self.update_session(|session| {
-					session.new_user_username.clear();
-					session.new_user_password.clear();
-					session.new_user_modal_open = false;
-					session.new_user_status = String::from("User created");
-				});
-				true
-			}
-			Err(err) => {
-				self.update_session(|session| {
-					session.new_user_modal_open = true;
-					session.new_user_status = format!("Create user failed: {err}");
-				});
-				false
-			}
-		}
+			.create_user_async(username.clone(), password)
+			.await;
+		self.finish_create_user(username, result)
 	}
 
 	pub async fn delete_user_values_async(&self, username: String) -> bool {
@@ -3200,7 +10575,7 @@ impl UiControllerCore<'_> {
 			}
 			Err(err) => {
 				self.update_session(|session| {
-					session.new_user_status = format!("Delete user failed: {err}");
+					session.new_user_status = self.notify_error("Delete user failed", err);
 				});
 				false
 			}
@@ -3254,6 +10629,10 @@ impl<'a> UiControllers<'a> {
 	async fn refresh_users(&self) {
 		self.server.refresh_users().await;
 	}
+
+	async fn refresh_onboarding(&self) {
+		self.server.refresh_onboarding().await;
+	}
 }
 
 impl UiServer {
@@ -3262,15 +10641,41 @@ impl UiServer {
 			media: MediaSessionManager::new()?,
 			puppy,
 			state: Mutex::new(UiState::new()),
+			notifications: std::sync::Mutex::new(NotificationCenter::default()),
 		})
 	}
 
+	/// Records a notification in the notification center and returns the
+	/// message for the status line.
+	fn notify(
+		&self,
+		severity: Severity,
+		source: impl Into<String>,
+		title: impl Into<String>,
+		detail: impl std::fmt::Display,
+	) -> String {
+		self.notifications
+			.lock()
+			.unwrap()
+			.push(severity, source, title, detail, chrono::Utc::now())
+	}
+
+	fn notify_error(
+		&self,
+		source: impl Into<String>,
+		title: impl Into<String>,
+		err: impl std::fmt::Display,
+	) -> String {
+		self.notify(Severity::Error, source, title, err)
+	}
+
 	async fn refresh_all(&self) {
 		self.refresh_peers().await;
 		self.refresh_files().await;
 		self.refresh_storage().await;
 		self.refresh_users().await;
 		self.refresh_search_mime_types().await;
+		self.refresh_nearby().await;
 	}
 
 	async fn refresh_search_mime_types(&self) {
@@ -3287,76 +10692,154 @@ impl UiServer {
 			Err(err) => {
 				let mut state = self.state.lock().await;
 				state.search_mime_types = merged.into_iter().collect();
-				state.status = format!("Failed to load mime types: {err}");
+				state.status = self.notify_error("search", "Failed to load mime types", err);
 			}
 		}
 	}
 
-	async fn peer_info(&self, peer_id: &str) -> PeerInfo {
-		let Ok(peer) = PeerId::from_str(peer_id) else {
-			return PeerInfo {
-				version: String::from("unknown"),
-				os: String::from("unknown"),
-				uptime_seconds: 0,
-			};
-		};
-		tokio::time::timeout(
-			std::time::Duration::from_millis(1500),
-			self.puppy.peer_info(peer),
+	/// Info of `peers` and this node, asked all at once. Peers that don't
+	/// answer within [`PEER_INFO_TIMEOUT`] are left out and summarized.
+	async fn peer_infos(
+		&self,
+		peers: Vec<PeerId>,
+		local: PeerId,
+	) -> (HashMap<PeerId, PeerInfo>, FanOutSummary) {
+		let mut targets = peers;
+		if !targets.contains(&local) {
+			targets.push(local);
+		}
+		let results = fan_out(
+			targets,
+			|peer| self.puppy.peer_info(peer),
+			FanOutOptions {
+				per_peer_timeout: PEER_INFO_TIMEOUT,
+				..FanOutOptions::default()
+			},
 		)
-		.await
-		.ok()
-		.and_then(Result::ok)
-		.map(|mut info| {
-			if info.os.trim().is_empty() {
-				info.os = String::from("unknown");
-			}
-			info
-		})
-		.unwrap_or_else(|| PeerInfo {
-			version: String::from("unknown"),
-			os: String::from("unknown"),
-			uptime_seconds: 0,
-		})
+		.await;
+		let (local_result, remote): (Vec<_>, Vec<_>) =
+			results.into_iter().partition(|(peer, _)| *peer == local);
+		let summary = FanOutSummary::of(&remote);
+		let infos = local_result
+			.into_iter()
+			.chain(remote)
+			.filter_map(|(peer, info)| {
+				let mut info = info.ok()?;
+				if info.os.trim().is_empty() {
+					info.os = String::from("unknown");
+				}
+				Some((peer, info))
+			})
+			.collect();
+		(infos, summary)
 	}
 
 	async fn refresh_peers(&self) {
 		match self.puppy.state_snapshot().await {
 			Some(snapshot) => {
 				let local_id = snapshot.me.to_string();
+				let (mut infos, refresh) = self
+					.peer_infos(
+						snapshot.peers.iter().map(|peer| peer.id).collect(),
+						snapshot.me,
+					)
+					.await;
+				let local_version = version_number();
+				let mut outdated = infos
+					.iter()
+					.filter(|(peer, info)| {
+						**peer != snapshot.me
+							&& version_number_from_label(&info.version)
+								.is_some_and(|version| version < local_version)
+					})
+					.map(|(peer, _)| *peer)
+					.collect::<Vec<_>>();
+				outdated.sort();
 				let mut peers = Vec::new();
 				for peer in &snapshot.peers {
-					let id = peer.id.to_string();
-					let info = self.peer_info(&id).await;
+					let info = infos.remove(&peer.id).unwrap_or_else(unknown_peer_info);
 					peers.push(PeerRow {
 						id: peer.id.to_string(),
 						name: peer.name.clone().unwrap_or_else(|| "Unnamed".to_string()),
 						local: peer.id.to_string() == local_id,
 						version: info.version,
 						os: info.os,
-						uptime: format_uptime(info.uptime_seconds),
+						uptime: uptime_label(info.uptime_seconds),
+						connections: snapshot
+							.connections_to(&peer.id)
+							.into_iter()
+							.cloned()
+							.collect(),
+						recent_drops: snapshot.recent_drops(&peer.id, chrono::Utc::now()),
+						clock_skew: snapshot.clock_skew(&peer.id),
+						index_stale: snapshot.index_staleness(&peer.id, chrono::Utc::now()),
+						idle: snapshot.is_idle(&peer.id),
+						important: snapshot.important_peers.contains(&peer.id),
+						trust: snapshot.trust_level(&peer.id),
 					});
 				}
 				if !peers.iter().any(|peer| peer.id == local_id) {
-					let info = self.peer_info(&local_id).await;
+					let info = infos.remove(&snapshot.me).unwrap_or_else(unknown_peer_info);
 					peers.push(PeerRow {
 						id: local_id.clone(),
 						name: String::from("Current device"),
 						local: true,
 						version: info.version,
 						os: info.os,
-						uptime: format_uptime(info.uptime_seconds),
+						uptime: uptime_label(info.uptime_seconds),
+						connections: Vec::new(),
+						recent_drops: 0,
+						clock_skew: None,
+						index_stale: None,
+						idle: false,
+						important: false,
+						trust: TrustLevel::Owner,
 					});
 				}
 				let mut state = self.state.lock().await;
 				state.peers = peers;
+				state.peers_refresh = refresh;
+				state.outdated_peers = outdated;
+				state.access_alerts = snapshot
+					.access_alerts
+					.iter()
+					.map(|(peer, alert)| (peer.to_string(), alert.message()))
+					.collect();
 				state.local_peer_id = Some(local_id);
+				state.remote_access_suspended = snapshot.remote_access_suspended;
+				state.identity_mismatch = snapshot.identity_mismatch.clone();
+				state.nat = snapshot.nat.clone();
+				state.reachability = snapshot.reachability.clone();
+				let overlaps = folder_rule_overlaps(&snapshot.shared_folders);
 				state.shared_folders = snapshot
 					.shared_folders
-					.into_iter()
-					.map(|folder| UiSharedFolder {
-						path: folder.path().to_string_lossy().into_owned(),
-						access: shared_folder_access_label(folder.flags()),
+					.iter()
+					.map(|folder| {
+						let overlap_note = overlaps
+							.iter()
+							.filter(|overlap| overlap.outer == *folder || overlap.inner == *folder)
+							.map(|overlap| overlap.describe())
+							.collect::<Vec<_>>()
+							.join("; ");
+						let overlap_note = if overlap_note.is_empty() {
+							overlap_note
+						} else {
+							format!("ⓘ {overlap_note}")
+						};
+						let unavailable_note = snapshot
+							.share_availability
+							.get(folder.path())
+							.filter(|availability| !availability.is_available())
+							.map(|availability| format!("⚠ {}", availability.describe()))
+							.unwrap_or_default();
+						UiSharedFolder {
+							path: folder.path().to_string_lossy().into_owned(),
+							access: shared_folder_access_label(folder.flags()),
+							has_overlap_note: !overlap_note.is_empty(),
+							overlap_note,
+							unavailable: !unavailable_note.is_empty(),
+							unavailable_note,
+						}
 					})
 					.collect();
 				state.status = format!("Loaded {} device(s)", state.peers.len());
@@ -3372,13 +10855,19 @@ impl UiServer {
 		if let Some(peer) = self.local_peer_id().await {
 			match self.puppy.list_file_entries(peer, 0, 25).await {
 				Ok(entries) => {
+					let hashes = entries.iter().map(|entry| entry.hash).collect::<Vec<_>>();
+					let in_store = self.puppy.stored_content(&hashes).unwrap_or_else(|err| {
+						tracing::warn!("failed to check the content store: {err}");
+						HashSet::new()
+					});
 					let mut state = self.state.lock().await;
 					state.files = entries;
+					state.files_in_store = in_store;
 					state.status = format!("Loaded {} file entries", state.files.len());
 				}
 				Err(err) => {
 					let mut state = self.state.lock().await;
-					state.status = format!("Failed to load files: {err}");
+					state.status = self.notify_error("files", "Failed to load files", err);
 				}
 			}
 		} else {
@@ -3387,67 +10876,312 @@ impl UiServer {
 		}
 	}
 
+	/// Fetches the peer's browse roots unless they are cached already.
+	async fn refresh_peer_roots(&self, peer_id: &str, peer: PeerId) -> Result<()> {
+		let cached = self
+			.state
+			.lock()
+			.await
+			.peer_roots
+			.as_ref()
+			.is_some_and(|(cached, _)| cached == peer_id);
+		if cached {
+			return Ok(());
+		}
+		let roots = self.puppy.list_roots(peer).await?;
+		self.state.lock().await.peer_roots = Some((peer_id.to_string(), roots));
+		Ok(())
+	}
+
+	/// Fetches the peer's standard folders unless they are cached already.
+	async fn refresh_peer_folders(&self, peer_id: &str, peer: PeerId) -> Result<()> {
+		let cached = self
+			.state
+			.lock()
+			.await
+			.peer_folders
+			.as_ref()
+			.is_some_and(|(cached, _)| cached == peer_id);
+		if cached {
+			return Ok(());
+		}
+		let folders = self.puppy.well_known_folders(peer).await?;
+		self.state.lock().await.peer_folders = Some((peer_id.to_string(), folders));
+		Ok(())
+	}
+
+	/// Loads this device's standard folders once; they rarely change.
+	async fn refresh_local_folders(&self) {
+		if !self.state.lock().await.local_folders.is_empty() {
+			return;
+		}
+		let Some(peer) = self.local_peer_id().await else {
+			return;
+		};
+		match self.puppy.well_known_folders(peer).await {
+			Ok(folders) => self.state.lock().await.local_folders = folders,
+			Err(err) => tracing::warn!("failed to load well-known folders: {err}"),
+		}
+	}
+
+	/// Loads what the peer lets this node access. Answered from the grant
+	/// cache when it has the peer, so it doesn't hold up the browser.
+	async fn refresh_peer_grants(&self, peer_id: &str, peer: PeerId) {
+		match self.puppy.list_permissions(peer, false).await {
+			Ok(grants) => {
+				self.state.lock().await.peer_grants = Some((peer_id.to_string(), grants));
+			}
+			Err(err) => tracing::warn!("failed to load grants from {peer_id}: {err}"),
+		}
+	}
+
+	/// Loads what the folders the peer shares with this node hold. Peers
+	/// that can't say are left without summaries, so their granted folders
+	/// show instead.
+	async fn refresh_peer_shares(&self, peer_id: &str, peer: PeerId) {
+		let summaries = match self.puppy.share_summaries(peer, false).await {
+			Ok(summaries) => Some(summaries),
+			Err(err) => {
+				tracing::debug!("no share summaries from {peer_id}: {err}");
+				None
+			}
+		};
+		self.state.lock().await.peer_shares = Some((peer_id.to_string(), summaries));
+	}
+
+	/// Shows the listed folder as `view` has it, without listing it again.
+	async fn show_peer_files(&self, view: DirView) {
+		let mut state = self.state.lock().await;
+		state.peer_files_view = view;
+		state.peer_files = shown_entries(&state.peer_files_listed, view);
+	}
+
 	async fn refresh_peer_files(&self, peer_id: &str, path: &str) {
-		match PeerId::from_str(peer_id) {
-			Ok(peer) => match self.puppy.list_dir(peer, path.to_string()).await {
-				Ok(mut entries) => {
-					entries.sort_by(|left, right| {
-						right
-							.is_dir
-							.cmp(&left.is_dir)
-							.then_with(|| left.name.cmp(&right.name))
-					});
+		let peer = PeerId::from_str(peer_id);
+		if let Ok(peer) = &peer {
+			self.refresh_peer_grants(peer_id, *peer).await;
+		}
+		let roots = match &peer {
+			Ok(peer) => self.refresh_peer_roots(peer_id, *peer).await,
+			Err(_) => Ok(()),
+		};
+		if let Ok(peer) = &peer
+			&& let Err(err) = self.refresh_peer_folders(peer_id, *peer).await
+		{
+			tracing::warn!("failed to load well-known folders for {peer_id}: {err}");
+		}
+		if path.is_empty() {
+			let mut state = self.state.lock().await;
+			state.peer_files.clear();
+			state.peer_files_listed.clear();
+			state.peer_files_path.clear();
+			state.peer_files_denied = None;
+			state.peer_files_free_hint = None;
+			state.peer_files_access = None;
+			state.status = match (peer, roots) {
+				(Err(err), _) => self.notify_error(peer_id, "Invalid peer id", err),
+				(Ok(_), Err(err)) => {
+					self.notify_error(peer_id, "Failed to load disks and shared folders", err)
+				}
+				(Ok(_), Ok(())) => String::from("Choose a disk or shared folder"),
+			};
+			return;
+		}
+		if let Err(err) = roots {
+			tracing::warn!("failed to load browse roots for {peer_id}: {err}");
+		}
+		match peer {
+			Ok(peer) => match self.puppy.list_dir_with_hint(peer, path.to_string()).await {
+				Ok(DirListing {
+					entries,
+					free_hint,
+					access,
+				}) => {
 					let mut state = self.state.lock().await;
-					state.peer_files = entries;
+					state.peer_files = shown_entries(&entries, state.peer_files_view);
+					state.peer_files_listed = entries;
 					state.peer_files_path = path.to_string();
-					state.status = format!("Loaded {} item(s) from {path}", state.peer_files.len());
+					state.peer_files_denied = None;
+					state.peer_files_free_hint = free_hint;
+					state.peer_files_access = access;
+					state.status = format!(
+						"Loaded {} item(s) from {path}",
+						state.peer_files_listed.len()
+					);
 				}
 				Err(err) => {
 					let mut state = self.state.lock().await;
 					state.peer_files.clear();
+					state.peer_files_listed.clear();
 					state.peer_files_path = path.to_string();
-					state.status = format!("Failed to load {path}: {err}");
+					state.peer_files_denied = err.downcast_ref::<AccessExplanation>().cloned();
+					state.peer_files_free_hint = None;
+					state.peer_files_access = None;
+					state.status =
+						self.notify_error(peer_id, format!("Failed to load {path}"), err);
 				}
 			},
 			Err(err) => {
 				let mut state = self.state.lock().await;
-				state.peer_files.clear();
-				state.peer_files_path = path.to_string();
-				state.status = format!("Invalid peer id: {err}");
+				state.peer_files.clear();
+				state.peer_files_listed.clear();
+				state.peer_files_path = path.to_string();
+				state.peer_files_denied = None;
+				state.peer_files_free_hint = None;
+				state.peer_files_access = None;
+				state.status = self.notify_error(peer_id, "Invalid peer id", err);
+			}
+		}
+	}
+
+	async fn refresh_storage(&self) {
+		self.refresh_local_folders().await;
+		match self.puppy.list_storage_files().await {
+			Ok(entries) => {
+				let (entries, trees) = task::spawn_blocking(move || {
+					let trees = trees_by_node(&entries);
+					(entries, trees)
+				})
+				.await
+				.unwrap_or_default();
+				let mut state = self.state.lock().await;
+				state.storage = entries;
+				state.storage_trees = Arc::new(trees);
+				state.status = format!("Indexed {} storage rows", state.storage.len());
+			}
+			Err(err) => {
+				let mut state = self.state.lock().await;
+				state.status = self.notify_error("storage", "Failed to load storage data", err);
+			}
+		}
+		self.refresh_scan_history().await;
+	}
+
+	async fn refresh_scan_history(&self) {
+		match self.puppy.scan_history(None, 0) {
+			Ok(runs) => {
+				let paths = runs
+					.iter()
+					.map(|run| run.path.clone())
+					.collect::<BTreeSet<_>>();
+				let trends = paths
+					.iter()
+					.filter_map(|path| self.puppy.scan_trend(path).ok().flatten())
+					.collect();
+				let mut state = self.state.lock().await;
+				state.scan_runs = runs;
+				state.scan_trends = trends;
+			}
+			Err(err) => {
+				let mut state = self.state.lock().await;
+				state.status = self.notify_error("storage", "Failed to load scan history", err);
+			}
+		}
+		self.refresh_growth().await;
+	}
+
+	async fn refresh_growth(&self) {
+		let report = match self.puppy.local_peer().await {
+			Ok(local) => {
+				self.puppy
+					.growth_report(local, chrono::Duration::days(PROJECTION_DAYS))
+					.await
+			}
+			Err(err) => Err(err),
+		};
+		let mut state = self.state.lock().await;
+		match report {
+			Ok(report) => state.growth = Some(report),
+			Err(err) => {
+				state.growth = None;
+				state.status = self.notify_error("storage", "Failed to load storage growth", err);
+			}
+		}
+	}
+
+	async fn refresh_users(&self) {
+		self.refresh_failed_logins().await;
+		let users = self.puppy.query_users().await;
+		let mut state = self.state.lock().await;
+		state.status = format!("Loaded {} users", users.len());
+		state.users = users;
+	}
+
+	async fn refresh_failed_logins(&self) {
+		let puppy = Arc::clone(&self.puppy);
+		let since = chrono::Utc::now() - chrono::Duration::hours(1);
+		match task::spawn_blocking(move || puppy.failed_logins_since(since)).await {
+			Ok(Ok(groups)) => self.state.lock().await.failed_logins = groups,
+			Ok(Err(err)) => tracing::warn!("{err}"),
+			Err(err) => tracing::warn!("failed to load failed logins: {err}"),
+		}
+	}
+
+	async fn refresh_backups(&self) {
+		let puppy = Arc::clone(&self.puppy);
+		match task::spawn_blocking(move || puppy.backup_runs(5)).await {
+			Ok(Ok(runs)) => self.state.lock().await.backup_runs = runs,
+			Ok(Err(err)) => tracing::warn!("{err}"),
+			Err(err) => tracing::warn!("failed to load backup runs: {err}"),
+		}
+	}
+
+	async fn refresh_config_snapshots(&self) {
+		let puppy = Arc::clone(&self.puppy);
+		match task::spawn_blocking(move || puppy.list_config_snapshots()).await {
+			Ok(Ok(snapshots)) => self.state.lock().await.config_snapshots = snapshots,
+			Ok(Err(err)) => tracing::warn!("failed to load config snapshots: {err}"),
+			Err(err) => tracing::warn!("failed to load config snapshots: {err}"),
+		}
+	}
+
+	async fn refresh_transfers(&self) {
+		let puppy = Arc::clone(&self.puppy);
+		match task::spawn_blocking(move || puppy.transfer_history(None, 0)).await {
+			Ok(Ok(transfers)) => self.state.lock().await.transfers = transfers,
+			Ok(Err(err)) => tracing::warn!("{err}"),
+			Err(err) => tracing::warn!("failed to load transfers: {err}"),
+		}
+	}
+
+	async fn refresh_reviews(&self) {
+		let puppy = Arc::clone(&self.puppy);
+		match task::spawn_blocking(move || puppy.pending_reviews()).await {
+			Ok(Ok(reviews)) => self.state.lock().await.reviews = reviews,
+			Ok(Err(err)) => tracing::warn!("failed to load reviews: {err}"),
+			Err(err) => tracing::warn!("failed to load reviews: {err}"),
+		}
+		let puppy = Arc::clone(&self.puppy);
+		let loaded = task::spawn_blocking(move || {
+			Ok::<_, anyhow::Error>((puppy.pending_delete_proposals()?, puppy.delete_proposals()?))
+		})
+		.await;
+		match loaded {
+			Ok(Ok((requests, proposals))) => {
+				let mut state = self.state.lock().await;
+				state.delete_requests = requests;
+				state.delete_proposals = proposals;
 			}
+			Ok(Err(err)) => tracing::warn!("failed to load deletion proposals: {err}"),
+			Err(err) => tracing::warn!("failed to load deletion proposals: {err}"),
 		}
 	}
 
-	async fn refresh_storage(&self) {
-		match self.puppy.list_storage_files().await {
-			Ok(entries) => {
-				let mut state = self.state.lock().await;
-				state.storage = entries;
-				state.status = format!("Indexed {} storage rows", state.storage.len());
-			}
-			Err(err) => {
-				let mut state = self.state.lock().await;
-				state.status = format!("Failed to load storage data: {err}");
-			}
+	async fn refresh_pins(&self) {
+		let puppy = Arc::clone(&self.puppy);
+		match task::spawn_blocking(move || puppy.list_pins()).await {
+			Ok(Ok(pins)) => self.state.lock().await.pins = pins,
+			Ok(Err(err)) => tracing::warn!("failed to load pins: {err}"),
+			Err(err) => tracing::warn!("failed to load pins: {err}"),
 		}
 	}
 
-	async fn refresh_users(&self) {
+	async fn refresh_outboxes(&self) {
 		let puppy = Arc::clone(&self.puppy);
-		match task::spawn_blocking(move || puppy.list_users_db()).await {
-			Ok(Ok(users)) => {
-				let mut state = self.state.lock().await;
-				state.users = users;
-				state.status = format!("Loaded {} users", state.users.len());
-			}
-			Ok(Err(err)) => {
-				let mut state = self.state.lock().await;
-				state.status = format!("Failed to load users: {err}");
-			}
-			Err(err) => {
-				let mut state = self.state.lock().await;
-				state.status = format!("Failed to load users: {err}");
-			}
+		match task::spawn_blocking(move || puppy.list_outbox_rules()).await {
+			Ok(Ok(rules)) => self.state.lock().await.outboxes = rules,
+			Ok(Err(err)) => tracing::warn!("failed to load outbox rules: {err}"),
+			Err(err) => tracing::warn!("failed to load outbox rules: {err}"),
 		}
 	}
 
@@ -3517,7 +11251,11 @@ impl UiServer {
 						state.peer_webcam_capability = Some(capability);
 						state.peer_screens.clear();
 						state.peer_screen_status = format!("Failed to load monitors: {err}");
-						state.status = format!("Failed to load monitors for {peer_id}: {err}");
+						state.status = self.notify_error(
+							peer_id,
+							format!("Failed to load monitors for {peer_id}"),
+							err,
+						);
 					}
 				}
 			}
@@ -3530,7 +11268,7 @@ impl UiServer {
 				});
 				state.peer_screens.clear();
 				state.peer_screen_status = format!("Invalid peer id: {err}");
-				state.status = format!("Invalid peer id: {err}");
+				state.status = self.notify_error(peer_id, "Invalid peer id", err);
 			}
 		}
 	}
@@ -3581,7 +11319,11 @@ impl UiServer {
 						let mut state = self.state.lock().await;
 						state.peer_webcam_capability = Some(capability);
 						state.peer_webcams.clear();
-						state.status = format!("Failed to load webcams for {peer_id}: {err}");
+						state.status = self.notify_error(
+							peer_id,
+							format!("Failed to load webcams for {peer_id}"),
+							err,
+						);
 					}
 				}
 			}
@@ -3593,7 +11335,7 @@ impl UiServer {
 					message: format!("Invalid peer id: {err}"),
 				});
 				state.peer_webcams.clear();
-				state.status = format!("Invalid peer id: {err}");
+				state.status = self.notify_error(peer_id, "Invalid peer id", err);
 			}
 		}
 	}
@@ -3639,7 +11381,11 @@ impl UiServer {
 						let mut state = self.state.lock().await;
 						state.peer_webcam_capability = Some(capability);
 						state.peer_microphones.clear();
-						state.status = format!("Failed to load microphones for {peer_id}: {err}");
+						state.status = self.notify_error(
+							peer_id,
+							format!("Failed to load microphones for {peer_id}"),
+							err,
+						);
 					}
 				}
 			}
@@ -3651,7 +11397,7 @@ impl UiServer {
 					message: format!("Invalid peer id: {err}"),
 				});
 				state.peer_microphones.clear();
-				state.status = format!("Invalid peer id: {err}");
+				state.status = self.notify_error(peer_id, "Invalid peer id", err);
 			}
 		}
 	}
@@ -3689,7 +11435,11 @@ impl UiServer {
 						let mut state = self.state.lock().await;
 						state.peer_audio_capability = Some(capability);
 						state.peer_audio_devices.clear();
-						state.status = format!("Failed to load audio devices for {peer_id}: {err}");
+						state.status = self.notify_error(
+							peer_id,
+							format!("Failed to load audio devices for {peer_id}"),
+							err,
+						);
 					}
 				}
 			}
@@ -3701,9 +11451,90 @@ impl UiServer {
 					message: format!("Invalid peer id: {err}"),
 				});
 				state.peer_audio_devices.clear();
-				state.status = format!("Invalid peer id: {err}");
+				state.status = self.notify_error(peer_id, "Invalid peer id", err);
+			}
+		}
+	}
+
+	async fn refresh_peer_disks(&self, peer: PeerId) {
+		let disks = match self.puppy.list_disks(peer).await {
+			Ok(disks) => disks,
+			Err(err) => {
+				let mut state = self.state.lock().await;
+				state.peer_disks.clear();
+				state.status = self.notify_error(
+					peer.to_string(),
+					format!("Failed to load disks for {peer}"),
+					err,
+				);
+				return;
+			}
+		};
+		let now = chrono::Utc::now();
+		let since = now - chrono::Duration::days(DISK_TREND_DAYS);
+		let mut peer_disks = Vec::new();
+		for disk in disks.into_iter().filter(|disk| disk.total_space > 0) {
+			let samples = self
+				.puppy
+				.disk_history(peer, &disk.mount_path, since..now)
+				.await
+				.unwrap_or_default();
+			peer_disks.push((disk, samples));
+		}
+		self.state.lock().await.peer_disks = peer_disks;
+	}
+
+	async fn refresh_temporary_grants(&self, peer: PeerId) {
+		let grants = self
+			.puppy
+			.state_snapshot()
+			.await
+			.map(|snapshot| snapshot.temporary_grants_for(&peer, chrono::Utc::now()))
+			.unwrap_or_default();
+		self.state.lock().await.peer_temporary_grants = grants;
+	}
+
+	/// Discovered peers and pairings; cheap enough to read on every render
+	/// of the setup wizard, which is what keeps its peer list live.
+	async fn refresh_nearby(&self) {
+		let Some(snapshot) = self.puppy.state_snapshot().await else {
+			return;
+		};
+		let discovered = self
+			.puppy
+			.query_discovered_peers(DiscoveredPeerFilter::default(), NEARBY_PEER_LIMIT)
+			.await;
+		let mut nearby = Vec::new();
+		let candidates = discovered
+			.iter()
+			.map(|discovered| discovered.peer_id)
+			.chain(snapshot.pairings.keys().copied());
+		for peer in candidates {
+			if peer != snapshot.me && !nearby.contains(&peer) {
+				nearby.push(peer);
 			}
 		}
+		let mut state = self.state.lock().await;
+		state.nearby_peers = nearby;
+		state.pairings = snapshot.pairings;
+	}
+
+	async fn refresh_onboarding(&self) {
+		self.refresh_peers().await;
+		self.refresh_local_folders().await;
+		self.refresh_nearby().await;
+		match self.puppy.node_name() {
+			Ok(name) => self.state.lock().await.node_name = name.unwrap_or_default(),
+			Err(err) => tracing::warn!("failed to load node name: {err}"),
+		}
+	}
+
+	/// True on a node with nothing shared and no known peers yet.
+	async fn needs_onboarding(&self) -> bool {
+		self.puppy.state_snapshot().await.is_some_and(|snapshot| {
+			snapshot.shared_folders.is_empty()
+				&& snapshot.peers.iter().all(|peer| peer.id == snapshot.me)
+		})
 	}
 
 	async fn refresh_peer_detail(&self, peer_id: &str) {
@@ -3714,140 +11545,847 @@ impl UiServer {
 					state.peer_cpus = cpus;
 				} else {
 					let mut state = self.state.lock().await;
-					state.status = format!("Failed to load CPU info for {peer_id}");
+					state.status = self.notify_error(
+						peer_id,
+						format!("Failed to load CPU info for {peer_id}"),
+						"",
+					);
 				}
+				self.refresh_peer_disks(peer).await;
+				self.refresh_temporary_grants(peer).await;
+				self.refresh_peer_grants(peer_id, peer).await;
+				self.refresh_peer_shares(peer_id, peer).await;
 				if let Ok(interfaces) = self.puppy.list_interfaces(peer).await {
 					let mut state = self.state.lock().await;
 					state.peer_interfaces = interfaces;
 					state.status = format!("Loaded detail for {peer_id}");
 				} else {
 					let mut state = self.state.lock().await;
-					state.status = format!("Failed to load interfaces for {peer_id}");
+					state.status = self.notify_error(
+						peer_id,
+						format!("Failed to load interfaces for {peer_id}"),
+						"",
+					);
 				}
+				let capabilities = self.puppy.peer_capabilities(peer).await;
+				self.state.lock().await.peer_capabilities = capabilities;
 				self.refresh_peer_audio(peer_id).await;
 				self.refresh_peer_microphones(peer_id).await;
+				self.refresh_local_folders().await;
 			}
 			Err(err) => {
 				let mut state = self.state.lock().await;
-				state.status = format!("Invalid peer id: {err}");
+				state.status = self.notify_error(peer_id, "Invalid peer id", err);
+			}
+		}
+	}
+
+	async fn local_peer_id(&self) -> Option<PeerId> {
+		self.puppy.state_snapshot().await.map(|state| state.me)
+	}
+
+	async fn sync_notifications(&self) {
+		for change in self.puppy.take_drive_changes() {
+			self.notify(Severity::Info, "/storage", change.message(), "");
+		}
+		let Some(snapshot) = self.puppy.state_snapshot().await else {
+			return;
+		};
+		let mut state = self.state.lock().await;
+		let fresh = snapshot
+			.notifications
+			.into_iter()
+			.filter(|notification| notification.id > state.last_notification_id)
+			.collect::<Vec<_>>();
+		let Some(latest) = fresh.last() else {
+			return;
+		};
+		for notification in &fresh {
+			self.notify(
+				Severity::Info,
+				notification.peer.to_string(),
+				notification.message.as_str(),
+				"",
+			);
+		}
+		state.last_notification_id = latest.id;
+		state.status = latest.message.clone();
+		let browsing = match &state.page {
+			Page::PeerFiles { peer_id, path }
+				if fresh
+					.iter()
+					.any(|notification| notification.peer.to_string() == *peer_id) =>
+			{
+				Some((peer_id.clone(), path.clone()))
+			}
+			_ => None,
+		};
+		drop(state);
+		if let Some((peer_id, path)) = browsing {
+			self.refresh_peer_files(&peer_id, &path).await;
+			self.state.lock().await.status = latest.message.clone();
+		}
+	}
+
+	async fn handle_action(&self, action: UiAction) {
+		let controllers = UiControllers::new(self);
+		match action {
+			UiAction::PeerRow(idx) => controllers.open_peer_row(idx).await,
+			UiAction::PeerBack => controllers.peer_back().await,
+			UiAction::RefreshPeers => controllers.refresh_peers().await,
+			UiAction::RefreshFiles => controllers.refresh_files().await,
+			UiAction::RefreshStorage => controllers.refresh_storage().await,
+			UiAction::RefreshUsers => controllers.refresh_users().await,
+			UiAction::RefreshSearchOptions => controllers.refresh_search_options().await,
+			UiAction::RefreshOnboarding => controllers.refresh_onboarding().await,
+		}
+	}
+
+	async fn set_page(&self, page: Page) {
+		let mut state = self.state.lock().await;
+		let previous_peer = state.selected_peer.clone();
+		state.page = page.clone();
+		state.selected_peer = match page {
+			Page::PeerDetail(peer_id) => Some(peer_id),
+			Page::PeerControl { peer_id } => Some(peer_id),
+			Page::PeerFiles { peer_id, path } => {
+				state.peer_files_path = path;
+				Some(peer_id)
+			}
+			Page::PeerWebcams { peer_id } => Some(peer_id),
+			Page::PeerPermissions { peer_id } => Some(peer_id),
+			Page::PeerShell { peer_id } => Some(peer_id),
+			_ => None,
+		};
+		if state.selected_peer != previous_peer {
+			state.peer_audio_capability = None;
+			state.peer_audio_devices.clear();
+			state.peer_webcam_capability = None;
+			state.peer_webcams.clear();
+			state.peer_microphones.clear();
+			state.peer_screens.clear();
+			state.peer_screen_status = String::from("Monitor capability not checked yet.");
+		}
+	}
+
+	async fn snapshot(&self) -> UiState {
+		self.state.lock().await.clone()
+	}
+
+	async fn page(&self) -> Page {
+		self.state.lock().await.page.clone()
+	}
+
+	async fn storage_trees(&self) -> Arc<Vec<NodeStorageTree>> {
+		Arc::clone(&self.state.lock().await.storage_trees)
+	}
+}
+
+fn page_href(page: &Page) -> String {
+	match page {
+		Page::Home => String::from("/"),
+		Page::Peers => String::from("/devices"),
+		Page::PeerDetail(peer_id) => peer_details_href(peer_id),
+		Page::PeerControl { peer_id } => peer_control_href(peer_id),
+		Page::PeerFiles { peer_id, path } => peer_files_href(peer_id, path),
+		Page::PeerWebcams { peer_id } => peer_webcams_href(peer_id),
+		Page::PeerPermissions { peer_id } => peer_permissions_href(peer_id),
+		Page::PeerShell { peer_id } => peer_shell_href(peer_id),
+		page => format!("/{}", page_label(page)),
+	}
+}
+
+fn page_label(page: &Page) -> &'static str {
+	match page {
+		Page::Home => "home",
+		Page::Peers => "peers",
+		Page::PeerDetail(_) => "peer_detail",
+		Page::PeerControl { .. } => "peer_control",
+		Page::PeerFiles { .. } => "peer_files",
+		Page::PeerWebcams { .. } => "peer_webcams",
+		Page::PeerPermissions { .. } => "peer_permissions",
+		Page::PeerShell { .. } => "peer_shell",
+		Page::Files => "files",
+		Page::Search => "search",
+		Page::Storage => "storage",
+		Page::Users => "users",
+		Page::Updates => "updates",
+		Page::Jobs => "jobs",
+		Page::Review => "review",
+		Page::Notifications => "notifications",
+		Page::Timeline => "timeline",
+		Page::Settings => "settings",
+		Page::Welcome => "welcome",
+	}
+}
+
+/// Free space over time as a row of block characters, `width` wide at most.
+fn disk_sparkline(samples: &[DiskSample], width: usize) -> String {
+	const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+	if samples.len() < 2 || width == 0 {
+		return String::new();
+	}
+	let bucket = samples.len().div_ceil(width);
+	samples
+		.chunks(bucket)
+		.map(|chunk| {
+			let free = chunk
+				.iter()
+				.map(|sample| sample.available_space as f64 / sample.total_space.max(1) as f64)
+				.sum::<f64>()
+				/ chunk.len() as f64;
+			BARS[((free * BARS.len() as f64) as usize).min(BARS.len() - 1)]
+		})
+		.collect()
+}
+
+fn disk_forecast(mount_path: &str, samples: &[DiskSample]) -> String {
+	match days_until_full(samples) {
+		Some(days) if days < 1.0 => format!("at current growth, {mount_path} full within a day"),
+		Some(days) => {
+			let days = days.round() as u64;
+			let unit = if days == 1 { "day" } else { "days" };
+			format!("at current growth, {mount_path} full in ~{days} {unit}")
+		}
+		None => String::new(),
+	}
+}
+
+fn scan_run_line(run: &ScanRun) -> String {
+	let files = run
+		.file_count
+		.map(|count| format!(", {count} files"))
+		.unwrap_or_default();
+	format!(
+		"{} {} +{} ~{} -{}{} in {}",
+		human_timestamp(run.started_at, TimestampStyle::Absolute),
+		run.path,
+		run.inserted_count,
+		run.updated_count,
+		run.removed_count,
+		files,
+		human_duration(std::time::Duration::from_millis(run.duration_ms)),
+	)
+}
+
+fn scan_run_status_label(run: &ScanRun) -> String {
+	match (&run.status, &run.error) {
+		(ScanRunStatus::Completed, _) => String::new(),
+		(ScanRunStatus::Cancelled, _) => String::from("cancelled"),
+		(ScanRunStatus::Failed, Some(err)) => format!("failed: {err}"),
+		(ScanRunStatus::Failed, None) => String::from("failed"),
+	}
+}
+
+fn scan_trend_line(trend: &ScanTrend) -> String {
+	let since = trend.baseline_started_at.format("%Y-%m-%d");
+	match trend.file_delta() {
+		0 => format!("{} unchanged since {since}", trend.path),
+		delta if delta > 0 => format!("{} grew by {delta} files since {since}", trend.path),
+		delta => format!("{} shrank by {} files since {since}", trend.path, -delta),
+	}
+}
+
+/// Size of a listed file, with the space it takes on disk when asked for
+/// and far enough off, as for a sparse file.
+fn entry_size(entry: &DirEntry, disk_sizes: bool) -> String {
+	const MIN_DIFFERENCE: u64 = 1024 * 1024;
+	let size = human_size(entry.size, SizeUnits::Binary);
+	match entry.disk_size {
+		Some(disk) if disk_sizes => {
+			let difference = disk.abs_diff(entry.size);
+			if difference >= MIN_DIFFERENCE && difference * 10 >= entry.size {
+				format!("{size} ({} on disk)", human_size(disk, SizeUnits::Binary))
+			} else {
+				size
 			}
 		}
+		_ => size,
+	}
+}
+
+fn signed_size(bytes: i64) -> String {
+	let size = human_size(bytes.unsigned_abs(), SizeUnits::Binary);
+	match bytes {
+		0 => size,
+		bytes if bytes > 0 => format!("+{size}"),
+		_ => format!("-{size}"),
+	}
+}
+
+fn growth_row(growth: &DirectoryGrowth) -> UiGrowthRow {
+	UiGrowthRow {
+		folder: growth.path.clone(),
+		added: format!("+{}", human_size(growth.bytes_added, SizeUnits::Binary)),
+		removed: format!("-{}", human_size(growth.bytes_removed, SizeUnits::Binary)),
+		net: signed_size(growth.net_bytes),
+		color: String::from(if growth.net_bytes > 0 {
+			"#f2c879"
+		} else {
+			"#79f2c0"
+		}),
+	}
+}
+
+fn treemap_tile(tile: &TreemapTile) -> UiTreemapTile {
+	const DEPTH_COLORS: [&str; 5] = ["#1f6f5c", "#2b8a6e", "#3aa57f", "#54bf93", "#79d8ab"];
+	let rect = &tile.rect;
+	let size = human_size(rect.size, SizeUnits::Binary);
+	let (label, tooltip, color) = if tile.path.is_empty() {
+		(
+			tile.name.clone(),
+			format!("{} - {size}", tile.name),
+			String::from("#3b4a47"),
+		)
+	} else {
+		let files = if rect.items == 1 {
+			String::new()
+		} else {
+			format!(" in {} files", rect.items)
+		};
+		(
+			format!("{} {size}", tile.name),
+			format!("{}\n{size}{files}", tile.path),
+			String::from(DEPTH_COLORS[(rect.depth as usize - 1).min(DEPTH_COLORS.len() - 1)]),
+		)
+	};
+	UiTreemapTile {
+		x: rect.x / TREEMAP_WIDTH * 100.0,
+		y: rect.y / TREEMAP_HEIGHT * 100.0,
+		width: rect.width / TREEMAP_WIDTH * 100.0,
+		height: rect.height / TREEMAP_HEIGHT * 100.0,
+		label,
+		tooltip,
+		color,
+		split: rect.split,
+		zoom_to: tile.zoom_to.clone().unwrap_or_default(),
+	}
+}
+
+fn growth_summary(report: &GrowthReport) -> String {
+	if let Some(reason) = &report.insufficient_data {
+		return reason.clone();
+	}
+	let days = (report.until - report.since).num_days();
+	let scans = if report.scans == 1 { "scan" } else { "scans" };
+	if report.directories.is_empty() {
+		format!(
+			"No folder changed size in {} {scans} over the last {days} days",
+			report.scans
+		)
+	} else {
+		format!(
+			"Changes found by {} {scans} over the last {days} days",
+			report.scans
+		)
+	}
+}
+
+fn growth_fastest(report: &GrowthReport) -> String {
+	if report.fastest_growing.is_empty() {
+		return String::new();
+	}
+	let folders = report
+		.fastest_growing
+		.iter()
+		.map(|growth| format!("{} ({})", growth.path, signed_size(growth.net_bytes)))
+		.collect::<Vec<_>>();
+	format!("Growing fastest: {}", folders.join(", "))
+}
+
+fn disk_projection_line(disk: &DiskProjection, low_space_percent: u8) -> String {
+	let free = human_size(disk.available_space, SizeUnits::Binary);
+	let Some(per_day) = disk.bytes_per_day else {
+		return format!(
+			"{}: {free} free, not enough samples to project",
+			disk.mount_path
+		);
+	};
+	let rate = if per_day >= 0.0 {
+		format!("+{}/day", human_size(per_day as u64, SizeUnits::Binary))
+	} else {
+		format!("-{}/day", human_size((-per_day) as u64, SizeUnits::Binary))
+	};
+	match disk.days_until_low {
+		Some(days) if days <= 0.0 => format!(
+			"{}: {free} free, {rate}, already below {low_space_percent}% free",
+			disk.mount_path
+		),
+		Some(days) if days < 1.0 => format!(
+			"{}: {free} free, {rate}, below {low_space_percent}% free within a day",
+			disk.mount_path
+		),
+		Some(days) => {
+			let days = days.round() as u64;
+			let unit = if days == 1 { "day" } else { "days" };
+			format!(
+				"{}: {free} free, {rate}, reaches {low_space_percent}% free in ~{days} {unit}",
+				disk.mount_path
+			)
+		}
+		None => format!("{}: {free} free, {rate}, not filling up", disk.mount_path),
+	}
+}
+
+fn job_progress_bar(job: &Job) -> String {
+	const WIDTH: usize = 20;
+	let bar = match job.progress.percent() {
+		Some(percent) => {
+			let filled = usize::from(percent) * WIDTH / 100;
+			format!(
+				"[{}{}] {percent}%",
+				"#".repeat(filled),
+				"-".repeat(WIDTH - filled)
+			)
+		}
+		None if job.is_active() => String::from("[ working... ]"),
+		None => String::new(),
+	};
+	match job.rate() {
+		Some(rate) => format!("{bar} {rate}"),
+		None => bar,
+	}
+}
+
+/// `Downloading, 4.00 MiB of 12.00 MiB, 1.00 MiB/s, 8s left`.
+fn transfer_line(verb: &str, progress: &TransferProgress) -> String {
+	let done = human_size(progress.done, SizeUnits::Binary);
+	let mut line = match progress.total {
+		Some(total) => format!("{verb}, {done} of {}", human_size(total, SizeUnits::Binary)),
+		None => format!("{verb}, {done}"),
+	};
+	if let Some(rate) = human_rate(progress.bytes_per_sec, progress.eta_secs, SizeUnits::Binary) {
+		line.push_str(", ");
+		line.push_str(&rate);
+	}
+	line
+}
+
+fn job_row(job: &Job) -> UiJobRow {
+	let (status, error) = match &job.status {
+		JobStatus::Running => ("running", String::new()),
+		JobStatus::Succeeded => ("done", String::new()),
+		JobStatus::Failed(err) => ("failed", err.clone()),
+		JobStatus::Cancelled => ("cancelled", String::new()),
+	};
+	UiJobRow {
+		id: job.id,
+		label: job.label.clone(),
+		status: status.to_string(),
+		progress: job_progress_bar(job),
+		elapsed: human_duration(job.elapsed()),
+		detail: job.detail.clone(),
+		has_error: !error.is_empty(),
+		error,
+		can_cancel: job.can_cancel(),
+	}
+}
+
+fn scan_results_pull_row(pull: &ScanResultsPull) -> UiScanResultsPull {
+	let error = pull.error.clone().unwrap_or_default();
+	UiScanResultsPull {
+		path: if pull.path.is_empty() {
+			format!("Scan run {}", pull.run)
+		} else {
+			pull.path.clone()
+		},
+		progress: format!("Results index: {}", pull.describe()),
+		has_error: !error.is_empty(),
+		error,
+		can_continue: !pull.is_done(),
+		can_browse: !pull.path.is_empty(),
+	}
+}
+
+/// "+{inserted} ~{updated} -{removed} of {files} files", with checksum
+/// mismatches when there are any.
+fn scan_summary(stats: &ScanResult) -> String {
+	let mismatches = stats
+		.checksums
+		.as_ref()
+		.filter(|checksums| checksums.mismatched > 0)
+		.map(|checksums| format!(", {} checksum mismatches", checksums.mismatched))
+		.unwrap_or_default();
+	format!(
+		"+{} ~{} -{} of {} files{}",
+		stats.inserted_count,
+		stats.updated_count,
+		stats.removed_count,
+		stats.file_count,
+		mismatches
+	)
+}
+
+/// The limits typed for the next scan. Low priority only ever asks for
+/// it; unticked, the node's setting stands.
+fn scan_overrides(session: &UiClientSession) -> Result<ScanOverrides, String> {
+	let limit = session.scan_read_limit.trim();
+	let max_read_mib = if limit.is_empty() {
+		None
+	} else {
+		Some(
+			limit
+				.parse()
+				.map_err(|_| String::from("Enter the read limit in whole MiB/s"))?,
+		)
+	};
+	Ok(ScanOverrides {
+		max_read_mib,
+		low_priority: session.scan_low_priority.then_some(true),
+		..ScanOverrides::default()
+	})
+}
+
+/// `, limited to 80.00 MiB/s, low priority` for a scan held back by its
+/// limits, empty for one that is not.
+fn scan_limit_note(limits: Option<&ScanLimits>) -> String {
+	let Some(limits) = limits else {
+		return String::new();
+	};
+	let mut note = String::new();
+	if let Some(rate) = limits.max_read_rate() {
+		note.push_str(&format!(
+			", limited to {}/s",
+			human_size(rate, SizeUnits::Binary)
+		));
 	}
-
-	async fn local_peer_id(&self) -> Option<PeerId> {
-		self.puppy.state_snapshot().await.map(|state| state.me)
+	if limits.low_priority {
+		note.push_str(", low priority");
 	}
+	note
+}
 
-	async fn handle_action(&self, action: UiAction) {
-		let controllers = UiControllers::new(self);
-		match action {
-			UiAction::PeerRow(idx) => controllers.open_peer_row(idx).await,
-			UiAction::PeerBack => controllers.peer_back().await,
-			UiAction::RefreshPeers => controllers.refresh_peers().await,
-			UiAction::RefreshFiles => controllers.refresh_files().await,
-			UiAction::RefreshStorage => controllers.refresh_storage().await,
-			UiAction::RefreshUsers => controllers.refresh_users().await,
-			UiAction::RefreshSearchOptions => controllers.refresh_search_options().await,
+/// Reports the progress of the scan behind `rx` until it finishes.
+async fn watch_scan(
+	reporter: &JobReporter,
+	rx: &Mutex<tokio::sync::mpsc::Receiver<ScanEvent>>,
+) -> Result<ScanResult, String> {
+	let mut rx = rx.lock().await;
+	loop {
+		match rx.recv().await {
+			Some(ScanEvent::Deferred { until }) => reporter.progress(
+				JobProgress::Indeterminate,
+				format!("Deferred until {until}"),
+			),
+			Some(ScanEvent::Progress(progress)) => reporter.progress_at(
+				JobProgress::Determinate {
+					done: progress.processed_files as u64,
+					total: progress.total_files as u64,
+				},
+				Throughput {
+					bytes_per_sec: progress.bytes_per_sec,
+					eta_secs: progress.eta_secs,
+				},
+				format!(
+					"{}/{} files{}",
+					progress.processed_files,
+					progress.total_files,
+					scan_limit_note(progress.limits.as_ref())
+				),
+			),
+			Some(ScanEvent::Finished(result)) => return result,
+			None => return Err(String::from("Scan stream closed")),
 		}
 	}
+}
 
-	async fn set_page(&self, page: Page) {
-		let mut state = self.state.lock().await;
-		let previous_peer = state.selected_peer.clone();
-		state.page = page.clone();
-		state.selected_peer = match page {
-			Page::PeerDetail(peer_id) => Some(peer_id),
-			Page::PeerControl { peer_id } => Some(peer_id),
-			Page::PeerFiles { peer_id, path } => {
-				state.peer_files_path = path;
-				Some(peer_id)
+fn forward_scan_events(
+	reporter: JobReporter,
+	rx: Arc<Mutex<tokio::sync::mpsc::Receiver<ScanEvent>>>,
+	server: Arc<UiServer>,
+) {
+	tokio::spawn(async move {
+		let result = watch_scan(&reporter, &rx).await;
+		server.refresh_scan_history().await;
+		reporter.finish(result.map(|stats| scan_summary(&stats)));
+	});
+}
+
+/// Pulls `peer`'s results of scan run `run` into the index, reporting
+/// every batch, and says how far it got.
+async fn pull_scan_results(
+	puppy: &PuppyNet,
+	peer: PeerId,
+	run: i64,
+	reporter: &JobReporter,
+) -> Result<String, String> {
+	let pulled = puppy
+		.pull_scan_results(peer, run, |pull| {
+			reporter.progress(
+				JobProgress::Determinate {
+					done: pull.fetched(),
+					total: pull.total,
+				},
+				format!("fetching results index... {}", pull.describe()),
+			)
+		})
+		.await;
+	match pulled {
+		Ok(pull) if pull.is_done() => Ok(format!("results index fetched, {}", pull.describe())),
+		Ok(pull) => Ok(format!(
+			"fetched {}, continue from the device page",
+			pull.describe()
+		)),
+		Err(err) => Err(format!("fetching results index failed: {err:#}")),
+	}
+}
+
+/// Reports the scan `peer` runs for this node, then pulls what it found
+/// into the index unless mirroring remote scans is turned off.
+fn forward_remote_scan_events(
+	reporter: JobReporter,
+	rx: Arc<Mutex<tokio::sync::mpsc::Receiver<ScanEvent>>>,
+	puppy: Arc<PuppyNet>,
+	peer: PeerId,
+) {
+	tokio::spawn(async move {
+		let stats = match watch_scan(&reporter, &rx).await {
+			Ok(stats) => stats,
+			Err(err) => {
+				reporter.finish(Err(err));
+				return;
 			}
-			Page::PeerWebcams { peer_id } => Some(peer_id),
-			_ => None,
 		};
-		if state.selected_peer != previous_peer {
-			state.peer_audio_capability = None;
-			state.peer_audio_devices.clear();
-			state.peer_webcam_capability = None;
-			state.peer_webcams.clear();
-			state.peer_microphones.clear();
-			state.peer_screens.clear();
-			state.peer_screen_status = String::from("Monitor capability not checked yet.");
-		}
-	}
+		let summary = scan_summary(&stats);
+		let Some(run) = stats.run.filter(|_| puppy.mirror_remote_scans()) else {
+			reporter.finish(Ok(summary));
+			return;
+		};
+		reporter.progress(
+			JobProgress::Indeterminate,
+			format!("{summary}; fetching results index..."),
+		);
+		let pulled = pull_scan_results(&puppy, peer, run, &reporter).await;
+		reporter.finish(
+			pulled
+				.map(|pulled| format!("{summary}; {pulled}"))
+				.map_err(|err| format!("{summary}; {err}")),
+		);
+	});
+}
 
-	async fn snapshot(&self) -> UiState {
-		self.state.lock().await.clone()
-	}
+/// Continues pulling `peer`'s results of scan run `run` where the last
+/// pull stopped.
+async fn continue_and_watch(puppy: Arc<PuppyNet>, peer: PeerId, run: i64, reporter: JobReporter) {
+	let pulled = pull_scan_results(&puppy, peer, run, &reporter).await;
+	reporter.finish(pulled);
 }
 
-fn page_label(page: &Page) -> &'static str {
-	match page {
-		Page::Home => "home",
-		Page::Peers => "peers",
-		Page::PeerDetail(_) => "peer_detail",
-		Page::PeerControl { .. } => "peer_control",
-		Page::PeerFiles { .. } => "peer_files",
-		Page::PeerWebcams { .. } => "peer_webcams",
-		Page::Files => "files",
-		Page::Search => "search",
-		Page::Storage => "storage",
-		Page::Users => "users",
-		Page::Updates => "updates",
-		Page::Settings => "settings",
+/// Wakes `peer` and reports until it connects.
+async fn wake_and_watch(puppy: Arc<PuppyNet>, peer: PeerId, reporter: JobReporter) {
+	let target = match puppy.wake_peer(peer) {
+		Ok(target) => target,
+		Err(err) => {
+			reporter.finish(Err(format!("Wake failed: {err:#}")));
+			return;
+		}
+	};
+	reporter.progress(
+		JobProgress::Indeterminate,
+		format!("magic packet sent to {}, waiting...", target.mac),
+	);
+	match puppy.wait_for_peer(peer, WAKE_TIMEOUT).await {
+		Ok(elapsed) => reporter.finish(Ok(format!("peer online after {}s", elapsed.as_secs()))),
+		Err(err) => reporter.finish(Err(err.to_string())),
 	}
 }
 
-fn format_hash(hash: &[u8]) -> String {
-	let mut result = String::with_capacity(hash.len() * 2);
-	for byte in hash {
-		result.push_str(&format!("{:02x}", byte));
+/// Restarts `peer` and reports until a fresh process answers.
+async fn restart_and_watch(puppy: Arc<PuppyNet>, peer: PeerId, reporter: JobReporter) {
+	let requested = std::time::Instant::now();
+	if let Err(err) = puppy.restart_peer(peer).await {
+		reporter.finish(Err(format!("Restart failed: {err}")));
+		return;
+	}
+	reporter.progress(
+		JobProgress::Indeterminate,
+		"waiting for peer to come back...",
+	);
+	match puppy.wait_for_restart(peer, requested).await {
+		Ok(elapsed) => reporter.finish(Ok(format!("reconnected after {}s", elapsed.as_secs()))),
+		Err(err) => reporter.finish(Err(err.to_string())),
 	}
-	result
 }
 
-fn format_size(bytes: u64) -> String {
-	const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
-	if bytes == 0 {
-		return "0 B".to_string();
-	}
-	let mut size = bytes as f64;
-	let mut unit = 0;
-	while size >= 1024.0 && unit < UNITS.len() - 1 {
-		size /= 1024.0;
-		unit += 1;
+/// Reports update progress. With `restart` set, a completed update goes on
+/// to restart the peer in the same job.
+/// Updates `peers` with [`FLEET_UPDATE_CONCURRENCY`] at a time, reporting
+/// each peer that finishes into `reporter` and all of them at the end.
+async fn update_peers(puppy: Arc<PuppyNet>, peers: Vec<PeerId>, reporter: JobReporter) {
+	let total = peers.len() as u64;
+	let done = Arc::new(std::sync::atomic::AtomicU64::new(0));
+	reporter.progress(
+		JobProgress::Determinate { done: 0, total },
+		"Starting updates",
+	);
+	let results = fan_out(
+		peers,
+		|peer| {
+			let puppy = Arc::clone(&puppy);
+			let reporter = reporter.clone();
+			let done = Arc::clone(&done);
+			async move {
+				let outcome: anyhow::Result<String> = async {
+					let mut rx = puppy
+						.update_remote_peer_with_retry(peer, None, UpdateRetryPolicy::default())
+						.map_err(|err| anyhow::anyhow!(err))?;
+					while let Some(event) = rx.recv().await {
+						let line = format_update_progress(&event);
+						match event {
+							UpdateProgress::Completed { .. }
+							| UpdateProgress::AlreadyUpToDate { .. } => return Ok(line),
+							UpdateProgress::Failed { .. } => anyhow::bail!(line),
+							_ => {}
+						}
+					}
+					anyhow::bail!("Update stream closed")
+				}
+				.await;
+				let finished = done.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+				let line = match &outcome {
+					Ok(line) => line.clone(),
+					Err(err) => err.to_string(),
+				};
+				reporter.progress(
+					JobProgress::Determinate {
+						done: finished,
+						total,
+					},
+					format!("{}: {line}", abbrev_peer_id(&peer.to_string())),
+				);
+				outcome
+			}
+		},
+		FanOutOptions {
+			concurrency: FLEET_UPDATE_CONCURRENCY,
+			per_peer_timeout: FLEET_UPDATE_TIMEOUT,
+		},
+	)
+	.await;
+	let summary = FanOutSummary::of(&results);
+	if summary.failures.is_empty() {
+		reporter.finish(Ok(format!("{} updated", summary.describe())));
+	} else {
+		let failed = summary
+			.failures
+			.iter()
+			.map(|(peer, reason)| format!("{}: {reason}", abbrev_peer_id(&peer.to_string())))
+			.collect::<Vec<_>>()
+			.join("; ");
+		reporter.finish(Err(format!("{}; failed: {failed}", summary.describe())));
 	}
-	format!("{:.2} {}", size, UNITS[unit])
+}
+
+fn forward_update_progress(
+	reporter: JobReporter,
+	mut rx: tokio::sync::mpsc::Receiver<UpdateProgress>,
+	restart: Option<(Arc<PuppyNet>, PeerId)>,
+) {
+	tokio::spawn(async move {
+		// Once retried, every line says which attempt it belongs to.
+		let mut attempt = None;
+		loop {
+			let Some(event) = rx.recv().await else {
+				reporter.finish(Err(String::from("Update stream closed")));
+				return;
+			};
+			if let UpdateProgress::Retrying {
+				attempt: current,
+				max_attempts,
+				..
+			} = event
+			{
+				attempt = Some((current, max_attempts));
+			}
+			let line = match attempt {
+				Some((current, max_attempts))
+					if !matches!(event, UpdateProgress::Retrying { .. }) =>
+				{
+					format!(
+						"[attempt {current}/{max_attempts}] {}",
+						format_update_progress(&event)
+					)
+				}
+				_ => format_update_progress(&event),
+			};
+			match event {
+				UpdateProgress::Completed { .. } => {
+					if let Some((puppy, peer)) = restart {
+						reporter.progress(JobProgress::Indeterminate, line);
+						restart_and_watch(puppy, peer, reporter).await;
+					} else {
+						reporter.finish(Ok(line));
+					}
+					return;
+				}
+				UpdateProgress::AlreadyUpToDate { .. } => {
+					reporter.finish(Ok(line));
+					return;
+				}
+				UpdateProgress::Failed { .. } => {
+					reporter.finish(Err(line));
+					return;
+				}
+				UpdateProgress::Downloading {
+					received,
+					total,
+					bytes_per_sec,
+					eta_secs,
+					..
+				} => reporter.progress_at(
+					match (received, total) {
+						(Some(done), Some(total)) => JobProgress::Determinate { done, total },
+						_ => JobProgress::Indeterminate,
+					},
+					Throughput {
+						bytes_per_sec,
+						eta_secs,
+					},
+					line,
+				),
+				_ => reporter.progress(JobProgress::Indeterminate, line),
+			}
+		}
+	});
 }
 
 fn format_update_progress(progress: &UpdateProgress) -> String {
 	match progress {
+		UpdateProgress::Deferred { until } => format!("Deferred until {until}"),
 		UpdateProgress::FetchingRelease => String::from("Fetching release metadata"),
-		UpdateProgress::Downloading { filename } => format!("Downloading {filename}"),
+		UpdateProgress::Downloading {
+			filename,
+			received: Some(received),
+			total,
+			..
+		} => {
+			let received = human_size(*received, SizeUnits::Binary);
+			match total {
+				Some(total) => format!(
+					"Downloading {filename}, {received} of {}",
+					human_size(*total, SizeUnits::Binary)
+				),
+				None => format!("Downloading {filename}, {received}"),
+			}
+		}
+		UpdateProgress::Downloading { filename, .. } => format!("Downloading {filename}"),
 		UpdateProgress::Unpacking => String::from("Unpacking update"),
 		UpdateProgress::Verifying => String::from("Verifying package"),
 		UpdateProgress::Installing => String::from("Installing update"),
 		UpdateProgress::Completed { version } => format!("Update completed: {version}"),
-		UpdateProgress::Failed { error } => format!("Update failed: {error}"),
+		UpdateProgress::Failed { error, .. } => format!("Update failed: {error}"),
+		UpdateProgress::Retrying {
+			attempt,
+			max_attempts,
+			delay_secs,
+			error,
+		} => format!("Attempt {attempt} of {max_attempts} in {delay_secs}s after: {error}"),
 		UpdateProgress::AlreadyUpToDate { current_version } => {
 			format!("Already up to date ({current_version})")
 		}
 	}
 }
 
-fn format_preview_bytes(data: &[u8]) -> String {
-	match std::str::from_utf8(data) {
-		Ok(text) => text.to_string(),
-		Err(_) => {
-			let mut out = String::from("Binary data (first 128 bytes as hex)\n");
-			for byte in data.iter().take(128) {
-				out.push_str(&format!("{byte:02x} "));
-			}
-			out.trim_end().to_string()
-		}
-	}
-}
-
 fn peer_to_node_id_hex(peer: &str) -> String {
 	let Ok(peer) = PeerId::from_str(peer) else {
 		return String::new();
@@ -3856,14 +12394,7 @@ fn peer_to_node_id_hex(peer: &str) -> String {
 	let mut node = [0u8; 16];
 	let len = node.len().min(bytes.len());
 	node[..len].copy_from_slice(&bytes[..len]);
-	format_hash(&node)
-}
-
-fn is_image_path(path: &str) -> bool {
-	mime_guess::from_path(path)
-		.first_raw()
-		.map(|mime| mime.starts_with("image/"))
-		.unwrap_or(false)
+	hex(&node)
 }
 
 #[cfg(any())]
@@ -3956,14 +12487,14 @@ async fn handle_peer_webcam_stream(
 		})
 		.is_some();
 	if !authenticated {
-		log::warn!("{stream_label} stream rejected: not authenticated");
+		tracing::warn!("{stream_label} stream rejected: not authenticated");
 		return HttpResponse::new(401, "not authenticated")
 			.header("content-type", "text/plain")
 			.header("cache-control", "no-store");
 	}
 	if stream_label == "monitor" && !monitor_stream_enabled_for_session(&ctx, session_id.as_deref())
 	{
-		log::info!("monitor stream rejected: disabled for session");
+		tracing::info!("monitor stream rejected: disabled for session");
 		return HttpResponse::new(409, "monitor stream disabled")
 			.header("content-type", "text/plain")
 			.header("cache-control", "no-store");
@@ -3989,12 +12520,12 @@ async fn handle_peer_webcam_stream(
 			.header("cache-control", "no-store");
 	}
 
-	log::info!("{stream_label} stream started for peer {peer_id} source {device_id}");
+	tracing::info!("{stream_label} stream started for peer {peer_id} source {device_id}");
 	let puppy = Arc::clone(&ctx.state.server.puppy);
 	let first_frame = match puppy.get_media_frame(peer, device_id.clone()).await {
 		Ok(frame) => frame,
 		Err(err) => {
-			log::warn!("{stream_label} stream failed to start: {err}");
+			tracing::warn!("{stream_label} stream failed to start: {err}");
 			return HttpResponse::new(503, format!("failed to start {stream_label} stream: {err}"))
 				.header("content-type", "text/plain")
 				.header("cache-control", "no-store");
@@ -4025,7 +12556,7 @@ async fn handle_peer_webcam_stream(
 			if stream_label == "monitor"
 				&& !monitor_stream_enabled_for_session(&ctx, session_id.as_deref())
 			{
-				log::info!("monitor stream stopped: disabled for session");
+				tracing::info!("monitor stream stopped: disabled for session");
 				return None;
 			}
 			let frame = match pending_frame {
@@ -4037,7 +12568,7 @@ async fn handle_peer_webcam_stream(
 					let next_logged_first_frame = if logged_first_frame {
 						true
 					} else {
-						log::info!(
+						tracing::info!(
 							"{stream_label} stream produced first frame: {} bytes ({})",
 							frame.data.len(),
 							frame.mime
@@ -4061,7 +12592,7 @@ async fn handle_peer_webcam_stream(
 					))
 				}
 				Err(err) => {
-					log::warn!("{stream_label} stream stopped: {err}");
+					tracing::warn!("{stream_label} stream stopped: {err}");
 					None
 				}
 			}
@@ -4090,7 +12621,7 @@ async fn handle_peer_microphone_stream(
 		})
 		.is_some();
 	if !authenticated {
-		log::warn!("microphone stream rejected: not authenticated");
+		tracing::warn!("microphone stream rejected: not authenticated");
 		return HttpResponse::new(401, "not authenticated")
 			.header("content-type", "text/plain")
 			.header("cache-control", "no-store");
@@ -4117,7 +12648,7 @@ async fn handle_peer_microphone_stream(
 			.header("content-type", frame.mime)
 			.header("cache-control", "no-store"),
 		Err(err) => {
-			log::warn!("microphone stream failed: {err}");
+			tracing::warn!("microphone stream failed: {err}");
 			HttpResponse::new(503, format!("failed to capture microphone audio: {err}"))
 				.header("content-type", "text/plain")
 				.header("cache-control", "no-store")
@@ -4205,7 +12736,7 @@ async fn handle_create_media_session(
 			Err(error) => media_error(500, format!("failed to serialize media session: {error}")),
 		},
 		Err(error) => {
-			log::warn!("failed to create local WebRTC media session: {error}");
+			tracing::warn!("failed to create local WebRTC media session: {error}");
 			media_error(503, format!("failed to create media session: {error}"))
 		}
 	}
@@ -4247,6 +12778,21 @@ async fn handle_ui_http(
 				.header("content-type", "text/javascript")
 				.header("cache-control", "no-store"),
 		),
+		("GET", "/assets/keyboard.js") => Some(
+			HttpResponse::new(200, KEYBOARD_JS.to_vec())
+				.header("content-type", "text/javascript")
+				.header("cache-control", "no-store"),
+		),
+		("GET", "/assets/copy_button.js") => Some(
+			HttpResponse::new(200, COPY_BUTTON_JS.to_vec())
+				.header("content-type", "text/javascript")
+				.header("cache-control", "no-store"),
+		),
+		("GET", "/assets/treemap.js") => Some(
+			HttpResponse::new(200, TREEMAP_JS.to_vec())
+				.header("content-type", "text/javascript")
+				.header("cache-control", "no-store"),
+		),
 		("GET", "/assets/media_receiver.js") => Some(
 			HttpResponse::new(200, MEDIA_RECEIVER_JS.to_vec())
 				.header("content-type", "text/javascript")
@@ -4304,9 +12850,120 @@ async fn verify_ui_addr_available(bind: SocketAddr) -> Result<()> {
 	Ok(())
 }
 
+fn ui_css(prefs: &UiPrefs) -> String {
+	let mut css = String::from(include_str!("ui_style.css"));
+	if prefs.font_scale != 100 {
+		css.push_str(&format!("\nhtml {{\n\tzoom: {}%;\n}}\n", prefs.font_scale));
+	}
+	if prefs.theme == UiTheme::Light {
+		// Templates carry inline dark colors, so invert the page and flip
+		// media back rather than restyling every element.
+		css.push_str(
+			"\nhtml {\n\tfilter: invert(1) hue-rotate(180deg);\n}\n\n\
+			 img,\nvideo,\ncanvas {\n\tfilter: invert(1) hue-rotate(180deg);\n}\n",
+		);
+	}
+	css
+}
+
+/// Re-renders every watching client. When a job has just finished the
+/// watchers are dropped after this last push; clients register again the
+/// next time they render. Watchers whose push fails are dropped as well.
+fn push_to_watchers(ctx: &UiContext, job_finished: bool) {
+	let watchers = {
+		let mut watchers = ctx.job_watchers.lock().unwrap();
+		if job_finished {
+			std::mem::take(&mut *watchers)
+				.into_iter()
+				.map(|(client_id, watcher)| (client_id, watcher.push, watcher.route))
+				.collect::<Vec<_>>()
+		} else {
+			watchers
+				.iter()
+				.map(|(client_id, watcher)| {
+					(
+						client_id.clone(),
+						Arc::clone(&watcher.push),
+						watcher.route.clone(),
+					)
+				})
+				.collect::<Vec<_>>()
+		}
+	};
+	for (client_id, push, route) in watchers {
+		if push(route) || job_finished {
+			continue;
+		}
+		let mut watchers = ctx.job_watchers.lock().unwrap();
+		if watchers
+			.get(&client_id)
+			.is_some_and(|watcher| Arc::ptr_eq(&watcher.push, &push))
+		{
+			watchers.remove(&client_id);
+		}
+	}
+}
+
+/// Periodically refreshes shared state and re-renders connected clients
+/// while the refresh interval preference is enabled.
+async fn auto_refresh(ctx: std::sync::Weak<Ctx<UiContext, ()>>) {
+	loop {
+		let Some(interval) = ctx
+			.upgrade()
+			.map(|ctx| ctx.state.prefs.lock().unwrap().refresh_interval)
+		else {
+			return;
+		};
+		tokio::time::sleep(std::time::Duration::from_secs(interval.max(1))).await;
+		if interval == 0 {
+			continue;
+		}
+		let Some(ctx) = ctx.upgrade() else {
+			return;
+		};
+		ctx.state.server.refresh_all().await;
+		push_to_watchers(&ctx.state, false);
+	}
+}
+
+/// Saves where the UI is for the next start: the page shown and the
+/// fields of the session that changed last.
+async fn save_resume(ui: &UiContext) {
+	let page = ui.server.page().await;
+	let last_session = ui.last_session.lock().unwrap().clone();
+	let session = last_session.and_then(|key| ui.sessions.lock().unwrap().get(&key).cloned());
+	let mut resume = UiResume {
+		page,
+		..UiResume::default()
+	};
+	if let Some(session) = session {
+		resume.search = ResumedSearch {
+			name_query: session.search.name_query,
+			target: session.search.target,
+			sort: session.search.sort,
+			page_size: session.search.page_size,
+			selected_mimes: session.search.selected_mimes,
+		};
+		resume.scan_path = session.scan_path;
+	}
+	if let Err(err) = resume.save(&ui.resume_path) {
+		tracing::warn!("failed to save the UI session: {err}");
+	}
+}
+
+async fn save_resume_periodically(ctx: std::sync::Weak<Ctx<UiContext, ()>>) {
+	loop {
+		tokio::time::sleep(RESUME_SAVE_INTERVAL).await;
+		let Some(ctx) = ctx.upgrade() else {
+			return;
+		};
+		save_resume(&ctx.state).await;
+	}
+}
+
 pub async fn run_ui(puppy: Arc<PuppyNet>, bind: SocketAddr) -> Result<()> {
 	verify_ui_addr_available(bind).await?;
-	log::info!("starting PuppyNet UI on {}", bind);
+	tracing::info!("starting PuppyNet UI on {}", bind);
 	let _template_rebuild_sentinel = [
 		include_str!("../wui/pages/home.wui"),
 		include_str!("../wui/pages/peers.wui"),
@@ -4315,16 +12972,55 @@ pub async fn run_ui(puppy: Arc<PuppyNet>, bind: SocketAddr) -> Result<()> {
 		include_str!("../wui/pages/users.wui"),
 		include_str!("../wui/partials/file_preview_modal.wui"),
 	];
+	let prefs_path = prefs_path();
+	let prefs = UiPrefs::load(&prefs_path);
+	let resume_path = resume_path(&prefs_path);
 	let mut wgui = Wgui::new(bind);
-	wgui.set_css(include_str!("ui_style.css"));
+	wgui.set_css(&ui_css(&prefs));
 	let server_state = Arc::new(UiServer::new(puppy)?);
 	server_state.refresh_all().await;
+	let mut pending_resume = PendingResume::default();
+	if let Some(resume) = UiResume::load(&resume_path) {
+		let snapshot = server_state.snapshot().await;
+		let (page, note) = resume.restore(&snapshot.peers, snapshot.storage.is_empty());
+		if let Some(note) = note {
+			let mut state = server_state.state.lock().await;
+			state.status = server_state.notify(Severity::Warning, "/", note, "");
+		}
+		pending_resume = PendingResume {
+			page: (page != Page::Home).then_some(page),
+			fields: Some((resume.search, resume.scan_path)),
+		};
+	}
 
 	let ctx = Arc::new(Ctx::new(UiContext {
 		server: Arc::clone(&server_state),
 		sessions: std::sync::Mutex::new(HashMap::new()),
 		pending_login_tokens: std::sync::Mutex::new(HashMap::new()),
+		jobs: Arc::new(JobManager::new()),
+		job_watchers: std::sync::Mutex::new(HashMap::new()),
+		prefs: std::sync::Mutex::new(prefs),
+		prefs_path,
+		resume_path,
+		pending_resume: std::sync::Mutex::new(pending_resume),
+		last_session: std::sync::Mutex::new(None),
 	}));
+	let job_ctx = Arc::downgrade(&ctx);
+	ctx.state
+		.jobs
+		.run_dispatcher(JOB_REFRESH_INTERVAL, move |job_id| {
+			if let Some(ctx) = job_ctx.upgrade() {
+				let finished = ctx
+					.state
+					.jobs
+					.job(job_id)
+					.is_some_and(|job| !job.is_active());
+				push_to_watchers(&ctx.state, finished);
+			}
+		});
+	tokio::spawn(auto_refresh(Arc::downgrade(&ctx)));
+	tokio::spawn(save_resume_periodically(Arc::downgrade(&ctx)));
+	let resume_ctx = Arc::clone(&ctx);
 	let http_ctx = Arc::clone(&ctx);
 	wgui.set_http_handler(move |request| {
 		let http_ctx = Arc::clone(&http_ctx);
@@ -4337,18 +13033,27 @@ pub async fn run_ui(puppy: Arc<PuppyNet>, bind: SocketAddr) -> Result<()> {
 	wgui.add_page::<PeerControlController>("/devices/:peer_id/control");
 	wgui.add_page::<PeerFilesController>("/devices/:peer_id/files");
 	wgui.add_page::<PeerWebcamsController>("/devices/:peer_id/webcams");
+	wgui.add_page::<PeerPermissionsController>("/devices/:peer_id/permissions");
+	wgui.add_page::<PeerShellController>("/devices/:peer_id/shell");
 	wgui.add_page::<PeerController>("/devices/:peer_id");
 	wgui.add_page::<PeersController>("/peers");
 	wgui.add_page::<PeerControlController>("/peers/:peer_id/control");
 	wgui.add_page::<PeerFilesController>("/peers/:peer_id/files");
 	wgui.add_page::<PeerWebcamsController>("/peers/:peer_id/webcams");
+	wgui.add_page::<PeerPermissionsController>("/peers/:peer_id/permissions");
+	wgui.add_page::<PeerShellController>("/peers/:peer_id/shell");
 	wgui.add_page::<PeerController>("/peers/:peer_id");
 	wgui.add_page::<FilesController>("/files");
 	wgui.add_page::<SearchController>("/search");
 	wgui.add_page::<StorageController>("/storage");
 	wgui.add_page::<UsersController>("/users");
 	wgui.add_page::<UpdatesController>("/updates");
+	wgui.add_page::<JobsController>("/jobs");
+	wgui.add_page::<ReviewController>("/review");
+	wgui.add_page::<NotificationsController>("/notifications");
+	wgui.add_page::<TimelineController>("/timeline");
 	wgui.add_page::<SettingsController>("/settings");
+	wgui.add_page::<WelcomeController>("/welcome");
 	wgui.add_page::<NotFoundController>("/*");
 
 	let shutdown = signal::ctrl_c();
@@ -4358,13 +13063,14 @@ pub async fn run_ui(puppy: Arc<PuppyNet>, bind: SocketAddr) -> Result<()> {
 	tokio::select! {
 		_ = &mut run_task => {}
 		_ = &mut shutdown => {
-			log::info!("shutting down UI");
+			tracing::info!("shutting down UI");
 		}
 	}
 	if !run_task.is_finished() {
 		run_task.abort();
 	}
 	let _ = run_task.await;
+	save_resume(&resume_ctx.state).await;
 	Ok(())
 }
 
@@ -4393,6 +13099,21 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn legacy_peers_hide_unadvertised_actions() {
+		let legacy = PeerCapabilities::legacy();
+		let current = PeerCapabilities::local();
+
+		assert!(!peer_supports(Some(&legacy), FEATURE_SHELL));
+		assert!(peer_supports(Some(&current), FEATURE_SHELL));
+		assert!(peer_supports(None, FEATURE_UPDATE));
+		assert_eq!(
+			capability_notice(Some(&legacy)),
+			"peer runs protocol v1, some features unavailable"
+		);
+		assert!(capability_notice(Some(&current)).is_empty());
+	}
+
 	#[test]
 	fn wui_templates_parse() {
 		let base_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("wui");
@@ -4404,12 +13125,19 @@ mod tests {
 			"pages/peer",
 			"pages/peer_control",
 			"pages/peer_webcams",
+			"pages/peer_permissions",
+			"pages/peer_shell",
 			"pages/files",
 			"pages/search",
 			"pages/storage",
 			"pages/users",
 			"pages/updates",
+			"pages/jobs",
+			"pages/notifications",
 			"pages/settings",
+			"pages/review",
+			"pages/timeline",
+			"pages/welcome",
 			"pages/not_found",
 		] {
 			let path = base_dir.join(format!("{module_name}.wui"));
@@ -4545,4 +13273,310 @@ mod tests {
 			]
 		);
 	}
+
+	#[test]
+	fn peer_file_paths_follow_the_target_platform() {
+		// A Linux UI browsing a Windows peer.
+		assert_eq!(
+			parent_peer_file_path("C:\\Users\\bob", true).as_deref(),
+			Some("C:\\Users")
+		);
+		assert_eq!(
+			parent_peer_file_path("C:\\Users", true).as_deref(),
+			Some("C:\\")
+		);
+		assert_eq!(parent_peer_file_path("C:\\", true).as_deref(), Some(""));
+		assert_eq!(
+			parent_peer_file_path("\\\\nas\\media\\films", true).as_deref(),
+			Some("\\\\nas\\media")
+		);
+		assert_eq!(
+			parent_peer_file_path("\\\\nas\\media", true).as_deref(),
+			Some("")
+		);
+		assert_eq!(
+			child_peer_file_path("C:\\", "Users", true).as_deref(),
+			Some("C:\\Users")
+		);
+		assert_eq!(
+			child_peer_file_path("C:\\Users", "bob", true).as_deref(),
+			Some("C:\\Users\\bob")
+		);
+		assert_eq!(child_peer_file_path("C:\\Users", "CON", true), None);
+
+		// A Windows UI browsing a Linux peer: backslashes are part of names.
+		assert_eq!(
+			parent_peer_file_path("/home/bob", false).as_deref(),
+			Some("/home")
+		);
+		assert_eq!(parent_peer_file_path("/home", false).as_deref(), Some("/"));
+		assert_eq!(parent_peer_file_path("/", false).as_deref(), Some(""));
+		assert_eq!(
+			parent_peer_file_path("/tmp/a\\b", false).as_deref(),
+			Some("/tmp")
+		);
+		assert_eq!(
+			child_peer_file_path("/", "home", false).as_deref(),
+			Some("/home")
+		);
+		assert_eq!(
+			child_peer_file_path("/home", "a\\b", false).as_deref(),
+			Some("/home/a\\b")
+		);
+		assert_eq!(child_peer_file_path("/home", "..", false), None);
+		assert_eq!(parent_peer_file_path("", false), None);
+		assert_eq!(peer_files_href("peer", ""), "/devices/peer/files");
+	}
+	#[test]
+	fn disk_trend_shows_free_space_and_forecast() {
+		let samples = (0..4u64)
+			.map(|day| DiskSample {
+				disk_id: String::from("uuid:1"),
+				mount_path: String::from("/srv"),
+				sampled_at: chrono::DateTime::from_timestamp((day * 86_400) as i64, 0).unwrap(),
+				total_space: 800,
+				available_space: 500 - day * 100,
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(disk_sparkline(&samples, 8), "▆▅▄▃");
+		assert_eq!(disk_sparkline(&samples, 2), "▅▃");
+		assert_eq!(
+			disk_forecast("/srv", &samples),
+			"at current growth, /srv full in ~2 days"
+		);
+		assert!(disk_forecast("/srv", &samples[..1]).is_empty());
+	}
+
+	#[test]
+	fn sparse_files_show_their_disk_size_when_asked() {
+		const MIB: u64 = 1024 * 1024;
+		let entry = |size, disk_size| DirEntry {
+			name: String::from("vm.img"),
+			name_raw: Vec::new(),
+			is_dir: false,
+			extension: None,
+			mime: None,
+			mime_source: MimeSource::Extension,
+			size,
+			disk_size,
+			created_at: None,
+			modified_at: None,
+			accessed_at: None,
+			hidden: false,
+		};
+		let sparse = entry(100 * 1024 * MIB, Some(3 * MIB));
+		assert_eq!(entry_size(&sparse, true), "100.00 GiB (3.00 MiB on disk)");
+		assert_eq!(entry_size(&sparse, false), "100.00 GiB");
+		assert_eq!(
+			entry_size(&entry(10 * MIB, Some(10 * MIB + 4096)), true),
+			"10.00 MiB"
+		);
+		assert_eq!(entry_size(&entry(10 * MIB, None), true), "10.00 MiB");
+	}
+
+	#[test]
+	fn limited_scans_say_so_in_their_progress() {
+		let limits = ScanLimits {
+			max_reads: 2,
+			max_read_mib: 80,
+			low_priority: true,
+		};
+		assert_eq!(
+			scan_limit_note(Some(&limits)),
+			", limited to 80.00 MiB/s, low priority"
+		);
+		let unlimited = ScanLimits {
+			max_read_mib: 0,
+			low_priority: false,
+			..limits
+		};
+		assert_eq!(scan_limit_note(Some(&unlimited)), "");
+		assert_eq!(scan_limit_note(None), "");
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn every_page_loads_in_demo_mode() {
+		let puppy = Arc::new(PuppyNet::new_demo(crate::demo::DEFAULT_DEMO_SEED));
+		assert!(puppy.is_demo());
+		let server = UiServer::new(puppy).unwrap();
+		server.refresh_all().await;
+		let state = server.snapshot().await;
+		assert!(state.peers.len() > 1);
+		assert!(!state.files.is_empty());
+		assert!(!state.storage.is_empty());
+		assert!(!state.users.is_empty());
+		let peer_id = state
+			.peers
+			.iter()
+			.find(|peer| !peer.local)
+			.map(|peer| peer.id.clone())
+			.unwrap();
+		let pages = [
+			Page::Home,
+			Page::Peers,
+			Page::PeerDetail(peer_id.clone()),
+			Page::PeerControl {
+				peer_id: peer_id.clone(),
+			},
+			Page::PeerFiles {
+				peer_id: peer_id.clone(),
+				path: String::from("/"),
+			},
+			Page::PeerWebcams {
+				peer_id: peer_id.clone(),
+			},
+			Page::PeerPermissions {
+				peer_id: peer_id.clone(),
+			},
+			Page::PeerShell {
+				peer_id: peer_id.clone(),
+			},
+			Page::Files,
+			Page::Search,
+			Page::Storage,
+			Page::Users,
+			Page::Updates,
+			Page::Jobs,
+			Page::Review,
+			Page::Notifications,
+			Page::Timeline,
+			Page::Settings,
+			Page::Welcome,
+		];
+		for page in pages {
+			server.set_page(page.clone()).await;
+			match &page {
+				Page::PeerDetail(peer_id) => server.refresh_peer_detail(peer_id).await,
+				Page::PeerControl { peer_id } => server.refresh_peer_screens(peer_id).await,
+				Page::PeerFiles { peer_id, path } => {
+					server.refresh_peer_files(peer_id, path).await;
+					server.refresh_pins().await;
+				}
+				Page::PeerWebcams { peer_id } => server.refresh_peer_webcams(peer_id).await,
+				Page::Jobs => {
+					server.refresh_transfers().await;
+					server.refresh_pins().await;
+					server.refresh_outboxes().await;
+				}
+				Page::Review => server.refresh_reviews().await,
+				Page::Settings => {
+					server.refresh_backups().await;
+					server.refresh_config_snapshots().await;
+				}
+				Page::Welcome => server.refresh_nearby().await,
+				_ => {}
+			}
+			assert!(server.snapshot().await.page == page);
+		}
+		let state = server.snapshot().await;
+		assert!(!state.peer_cpus.is_empty());
+		assert!(!state.peer_disks.is_empty());
+		assert!(!state.peer_files.is_empty());
+		assert!(!state.nearby_peers.is_empty());
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn changing_the_folder_view_keeps_the_fetched_listing() {
+		let puppy = Arc::new(PuppyNet::new_demo(crate::demo::DEFAULT_DEMO_SEED));
+		let server = UiServer::new(puppy).unwrap();
+		server.refresh_peers().await;
+		let peer_id = server
+			.snapshot()
+			.await
+			.peers
+			.iter()
+			.find(|peer| !peer.local)
+			.map(|peer| peer.id.clone())
+			.unwrap();
+		server.refresh_peer_files(&peer_id, "/").await;
+		// Only this test lists these; a listing fetched again drops them.
+		let added = ["small.txt", ".env", "large.bin"];
+		let listed = {
+			let mut state = server.state.lock().await;
+			assert!(!state.peer_files.is_empty());
+			for (name, size) in added.into_iter().zip([1, 5, 9]) {
+				state.peer_files_listed.push(DirEntry {
+					name: name.to_string(),
+					name_raw: Vec::new(),
+					is_dir: false,
+					extension: None,
+					mime: None,
+					mime_source: MimeSource::Extension,
+					size,
+					disk_size: None,
+					created_at: None,
+					modified_at: None,
+					accessed_at: None,
+					hidden: false,
+				});
+			}
+			state.peer_files_listed.len()
+		};
+		let shown = |state: &UiState| {
+			state
+				.peer_files
+				.iter()
+				.map(|entry| entry.name.as_str())
+				.filter(|name| added.contains(name))
+				.map(str::to_string)
+				.collect::<Vec<_>>()
+		};
+
+		server
+			.show_peer_files(DirView {
+				sort: DirSort::Size,
+				show_hidden: false,
+				thumbnails: true,
+			})
+			.await;
+		let state = server.snapshot().await;
+		assert_eq!(state.peer_files_listed.len(), listed);
+		assert_eq!(shown(&state), ["large.bin", "small.txt"]);
+
+		server.show_peer_files(DirView::default()).await;
+		let state = server.snapshot().await;
+		assert_eq!(state.peer_files_listed.len(), listed);
+		assert_eq!(shown(&state), [".env", "large.bin", "small.txt"]);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn errors_from_another_page_are_kept_in_the_notification_center() {
+		let puppy = Arc::new(PuppyNet::new_demo(crate::demo::DEFAULT_DEMO_SEED));
+		let server = UiServer::new(puppy).unwrap();
+		server.set_page(Page::Settings).await;
+		server.refresh_peer_files("not-a-peer", "").await;
+		server.refresh_peer_files("not-a-peer", "").await;
+		server.set_page(Page::Storage).await;
+
+		let status = server.snapshot().await.status;
+		assert!(status.starts_with("Invalid peer id: "), "{status}");
+		let center = server.notifications.lock().unwrap();
+		let recorded = center.recent().collect::<Vec<_>>();
+		assert_eq!(recorded.len(), 1);
+		assert_eq!(recorded[0].severity, Severity::Error);
+		assert_eq!(recorded[0].source, "not-a-peer");
+		assert_eq!(recorded[0].message(), status);
+		assert_eq!(recorded[0].count, 2);
+		assert_eq!(center.unseen_count(), 1);
+		assert_eq!(center.toasts(chrono::Utc::now()).len(), 1);
+	}
+
+	#[test]
+	fn peers_without_reported_access_fall_back_to_their_grants() {
+		let folder = |path: &str, flags| {
+			Permission::new(Rule::Folder(FolderRule::new(PathBuf::from(path), flags)))
+		};
+		let grants = [
+			folder("/srv", FLAG_READ | FLAG_SEARCH),
+			folder("/srv/drop", FLAG_READ | FLAG_WRITE | FLAG_SEARCH),
+			folder("/srv/photos", FLAG_PREVIEW),
+		];
+		let badge = |path| granted_flags(&grants, path).map(access_badge);
+		assert_eq!(badge("/srv/docs").as_deref(), Some("read-only"));
+		assert_eq!(badge("/srv/drop/in").as_deref(), Some("read + write"));
+		assert_eq!(badge("/srv/photos").as_deref(), Some("preview only"));
+		assert_eq!(badge("/home"), None);
+		let owner = [Permission::new(Rule::Owner)];
+		assert_eq!(granted_flags(&owner, "/srv"), None);
+	}
 }
@@ -0,0 +1,234 @@
+//! Keyboard focus for the lists of the web UI. Arrow keys move a focus
+//! marker over the rows, Enter activates the focused row the way clicking
+//! it would, Escape goes back and typing jumps to the first row whose label
+//! starts with the typed text. Tab and Shift-Tab are left to the browser,
+//! which already moves between the menu, inputs and buttons.
+//!
+//! Focus remembers the key of the focused row rather than its position, so
+//! it stays on the same peer or file when a refresh re-sorts the list.
+
+use std::time::{Duration, Instant};
+
+/// Rows skipped by PageUp and PageDown.
+pub(crate) const PAGE_ROWS: usize = 10;
+/// Pause after which type-ahead starts over instead of extending the
+/// typed prefix.
+pub(crate) const TYPE_AHEAD_RESET: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Key {
+	Up,
+	Down,
+	Home,
+	End,
+	PageUp,
+	PageDown,
+	Enter,
+	Escape,
+	Char(char),
+}
+
+impl Key {
+	/// Parses a `KeyboardEvent.key` value.
+	pub(crate) fn parse(key: &str) -> Option<Self> {
+		match key {
+			"ArrowUp" => Some(Key::Up),
+			"ArrowDown" => Some(Key::Down),
+			"Home" => Some(Key::Home),
+			"End" => Some(Key::End),
+			"PageUp" => Some(Key::PageUp),
+			"PageDown" => Some(Key::PageDown),
+			"Enter" => Some(Key::Enter),
+			"Escape" => Some(Key::Escape),
+			_ => {
+				let mut chars = key.chars();
+				match (chars.next(), chars.next()) {
+					(Some(ch), None) if !ch.is_control() => Some(Key::Char(ch)),
+					_ => None,
+				}
+			}
+		}
+	}
+}
+
+/// What the page should do after a key press.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FocusAction {
+	None,
+	Moved,
+	/// Same as clicking the row at this index.
+	Activate(usize),
+	Back,
+	/// PageDown on the last row; pages that load more rows do so.
+	NextPage,
+}
+
+/// A row as seen by the focus model.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FocusRow<'a> {
+	/// Stable identity of the row, such as a peer id or a path.
+	pub key: &'a str,
+	/// Text matched by type-ahead.
+	pub label: &'a str,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ListFocus {
+	focused: Option<String>,
+	typed: String,
+	typed_at: Option<Instant>,
+}
+
+impl ListFocus {
+	pub(crate) fn is_focused(&self, key: &str) -> bool {
+		self.focused.as_deref() == Some(key)
+	}
+
+	/// Position of the focused row, if it is still in `rows`.
+	pub(crate) fn index(&self, rows: &[FocusRow<'_>]) -> Option<usize> {
+		let focused = self.focused.as_deref()?;
+		rows.iter().position(|row| row.key == focused)
+	}
+
+	fn focus(&mut self, rows: &[FocusRow<'_>], index: usize) -> FocusAction {
+		match rows.get(index) {
+			Some(row) => {
+				self.focused = Some(row.key.to_string());
+				FocusAction::Moved
+			}
+			None => FocusAction::None,
+		}
+	}
+
+	fn type_ahead(&mut self, rows: &[FocusRow<'_>], ch: char, now: Instant) -> FocusAction {
+		if self
+			.typed_at
+			.is_none_or(|at| now.duration_since(at) >= TYPE_AHEAD_RESET)
+		{
+			self.typed.clear();
+		}
+		self.typed_at = Some(now);
+		self.typed.extend(ch.to_lowercase());
+		let typed = &self.typed;
+		match rows
+			.iter()
+			.position(|row| row.label.to_lowercase().starts_with(typed.as_str()))
+		{
+			Some(index) => self.focus(rows, index),
+			None => FocusAction::None,
+		}
+	}
+
+	pub(crate) fn handle(&mut self, key: Key, rows: &[FocusRow<'_>], now: Instant) -> FocusAction {
+		if !matches!(key, Key::Char(_)) {
+			self.typed.clear();
+		}
+		let last = rows.len().saturating_sub(1);
+		let current = self.index(rows);
+		match key {
+			Key::Down => self.focus(rows, current.map_or(0, |index| (index + 1).min(last))),
+			Key::Up => self.focus(rows, current.map_or(0, |index| index.saturating_sub(1))),
+			Key::Home => self.focus(rows, 0),
+			Key::End => self.focus(rows, last),
+			Key::PageDown => match current {
+				Some(index) if index == last => FocusAction::NextPage,
+				None if rows.is_empty() => FocusAction::NextPage,
+				_ => self.focus(
+					rows,
+					current.map_or(0, |index| (index + PAGE_ROWS).min(last)),
+				),
+			},
+			Key::PageUp => self.focus(
+				rows,
+				current.map_or(0, |index| index.saturating_sub(PAGE_ROWS)),
+			),
+			Key::Enter => current.map_or(FocusAction::None, FocusAction::Activate),
+			Key::Escape => FocusAction::Back,
+			Key::Char(ch) => self.type_ahead(rows, ch, now),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rows<'a>(labels: &'a [&'a str]) -> Vec<FocusRow<'a>> {
+		labels
+			.iter()
+			.map(|label| FocusRow { key: label, label })
+			.collect()
+	}
+
+	#[test]
+	fn arrows_move_and_enter_activates_the_focused_row() {
+		let labels = ["alpha", "beta", "gamma"];
+		let rows = rows(&labels);
+		let now = Instant::now();
+		let mut focus = ListFocus::default();
+		assert_eq!(focus.handle(Key::Enter, &rows, now), FocusAction::None);
+		assert_eq!(focus.handle(Key::Down, &rows, now), FocusAction::Moved);
+		assert!(focus.is_focused("alpha"));
+		focus.handle(Key::Down, &rows, now);
+		focus.handle(Key::Down, &rows, now);
+		focus.handle(Key::Down, &rows, now);
+		assert!(focus.is_focused("gamma"));
+		focus.handle(Key::Up, &rows, now);
+		assert_eq!(
+			focus.handle(Key::Enter, &rows, now),
+			FocusAction::Activate(1)
+		);
+		assert_eq!(focus.handle(Key::Escape, &rows, now), FocusAction::Back);
+		assert_eq!(focus.handle(Key::PageDown, &rows, now), FocusAction::Moved);
+		assert_eq!(
+			focus.handle(Key::PageDown, &rows, now),
+			FocusAction::NextPage
+		);
+	}
+
+	#[test]
+	fn focus_follows_the_row_when_the_list_is_re_sorted() {
+		let now = Instant::now();
+		let mut focus = ListFocus::default();
+		let before = ["nas", "phone", "laptop"];
+		focus.handle(Key::Down, &rows(&before), now);
+		focus.handle(Key::Down, &rows(&before), now);
+		assert!(focus.is_focused("phone"));
+
+		let after = ["laptop", "nas", "phone"];
+		assert_eq!(focus.index(&rows(&after)), Some(2));
+		assert_eq!(
+			focus.handle(Key::Enter, &rows(&after), now),
+			FocusAction::Activate(2)
+		);
+	}
+
+	#[test]
+	fn type_ahead_extends_the_prefix_until_a_pause() {
+		let labels = ["Documents", "vacation", "Valve", "video"];
+		let rows = rows(&labels);
+		let start = Instant::now();
+		let mut focus = ListFocus::default();
+		focus.handle(Key::Char('v'), &rows, start);
+		assert!(focus.is_focused("vacation"));
+		focus.handle(Key::Char('a'), &rows, start);
+		focus.handle(Key::Char('l'), &rows, start);
+		assert!(focus.is_focused("Valve"));
+		let later = start + TYPE_AHEAD_RESET;
+		focus.handle(Key::Char('D'), &rows, later);
+		assert!(focus.is_focused("Documents"));
+		assert_eq!(
+			focus.handle(Key::Char('x'), &rows, later),
+			FocusAction::None
+		);
+		assert!(focus.is_focused("Documents"));
+	}
+
+	#[test]
+	fn parses_browser_key_names() {
+		assert_eq!(Key::parse("ArrowDown"), Some(Key::Down));
+		assert_eq!(Key::parse("v"), Some(Key::Char('v')));
+		assert_eq!(Key::parse("Shift"), None);
+		assert_eq!(Key::parse("F5"), None);
+	}
+}
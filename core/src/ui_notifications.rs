@@ -0,0 +1,240 @@
+//! Errors and notices the web UI has shown, kept so they can be read again
+//! after the status line moved on. Each page still shows the latest message
+//! in its status line; the center keeps the recent ones with where they came
+//! from, shows new warnings and errors as toasts, and counts those not yet
+//! looked at for the navbar badge.
+
+use chrono::{DateTime, Duration, Utc};
+use std::collections::VecDeque;
+use std::fmt::Display;
+
+/// Notifications kept; the oldest are dropped past this.
+pub(crate) const MAX_NOTIFICATIONS: usize = 100;
+/// An entry identical to one recorded this recently bumps its count instead
+/// of adding another.
+const COLLAPSE_WINDOW: Duration = Duration::seconds(60);
+/// How long a new warning or error shows as a toast.
+const TOAST_DURATION: Duration = Duration::seconds(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Severity {
+	/// Routine notices: recorded, never toasted.
+	Info,
+	Warning,
+	Error,
+}
+
+impl Severity {
+	pub(crate) fn label(self) -> &'static str {
+		match self {
+			Severity::Info => "info",
+			Severity::Warning => "warning",
+			Severity::Error => "error",
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct AppNotification {
+	pub(crate) severity: Severity,
+	pub(crate) title: String,
+	pub(crate) detail: String,
+	/// The page or peer the notification came from.
+	pub(crate) source: String,
+	/// When it was last recorded.
+	pub(crate) at: DateTime<Utc>,
+	/// Times it was recorded within the collapse window of the previous one.
+	pub(crate) count: u32,
+	pub(crate) seen: bool,
+}
+
+impl AppNotification {
+	/// Title and detail on one line, as the status line shows them.
+	pub(crate) fn message(&self) -> String {
+		if self.detail.is_empty() {
+			self.title.clone()
+		} else {
+			format!("{}: {}", self.title, self.detail)
+		}
+	}
+
+	fn shows_as_toast(&self, now: DateTime<Utc>) -> bool {
+		!self.seen && self.severity != Severity::Info && now - self.at < TOAST_DURATION
+	}
+}
+
+#[derive(Default)]
+pub(crate) struct NotificationCenter {
+	/// Oldest first.
+	entries: VecDeque<AppNotification>,
+}
+
+impl NotificationCenter {
+	/// Records a notification and returns its message for the status line.
+	pub(crate) fn push(
+		&mut self,
+		severity: Severity,
+		source: impl Into<String>,
+		title: impl Into<String>,
+		detail: impl Display,
+		now: DateTime<Utc>,
+	) -> String {
+		let source = source.into();
+		let title = title.into();
+		let detail = detail.to_string();
+		let repeated = self.entries.iter().position(|entry| {
+			entry.severity == severity
+				&& entry.source == source
+				&& entry.title == title
+				&& entry.detail == detail
+				&& now - entry.at < COLLAPSE_WINDOW
+		});
+		let entry = match repeated.and_then(|idx| self.entries.remove(idx)) {
+			Some(entry) => AppNotification {
+				at: now,
+				count: entry.count + 1,
+				seen: false,
+				..entry
+			},
+			None => AppNotification {
+				severity,
+				title,
+				detail,
+				source,
+				at: now,
+				count: 1,
+				seen: false,
+			},
+		};
+		let message = entry.message();
+		self.entries.push_back(entry);
+		while self.entries.len() > MAX_NOTIFICATIONS {
+			self.entries.pop_front();
+		}
+		message
+	}
+
+	/// Newest first.
+	pub(crate) fn recent(&self) -> impl Iterator<Item = &AppNotification> {
+		self.entries.iter().rev()
+	}
+
+	pub(crate) fn unseen_count(&self) -> usize {
+		self.entries.iter().filter(|entry| !entry.seen).count()
+	}
+
+	/// New warnings and errors to show as toasts, newest first.
+	pub(crate) fn toasts(&self, now: DateTime<Utc>) -> Vec<AppNotification> {
+		self.recent()
+			.filter(|entry| entry.shows_as_toast(now))
+			.cloned()
+			.collect()
+	}
+
+	pub(crate) fn mark_all_seen(&mut self) {
+		for entry in &mut self.entries {
+			entry.seen = true;
+		}
+	}
+
+	pub(crate) fn clear(&mut self) {
+		self.entries.clear();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn at(seconds: i64) -> DateTime<Utc> {
+		DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap()
+	}
+
+	#[test]
+	fn errors_are_kept_and_toasted_until_seen() {
+		let mut center = NotificationCenter::default();
+		let message = center.push(
+			Severity::Error,
+			"settings",
+			"Failed to save preferences",
+			"disk full",
+			at(0),
+		);
+		assert_eq!(message, "Failed to save preferences: disk full");
+		center.push(Severity::Info, "peer-a", "Peer connected", "", at(1));
+
+		assert_eq!(center.unseen_count(), 2);
+		let toasts = center.toasts(at(2));
+		assert_eq!(toasts.len(), 1);
+		assert_eq!(toasts[0].source, "settings");
+		assert!(center.toasts(at(20)).is_empty());
+
+		center.mark_all_seen();
+		assert_eq!(center.unseen_count(), 0);
+		assert!(center.toasts(at(2)).is_empty());
+		assert_eq!(center.recent().count(), 2);
+		assert_eq!(center.recent().next().unwrap().title, "Peer connected");
+
+		center.clear();
+		assert_eq!(center.recent().count(), 0);
+	}
+
+	#[test]
+	fn repeated_errors_collapse_within_the_window() {
+		let mut center = NotificationCenter::default();
+		for second in [0, 10, 20] {
+			center.push(
+				Severity::Error,
+				"files",
+				"Failed to load files",
+				"timeout",
+				at(second),
+			);
+		}
+		center.push(
+			Severity::Error,
+			"files",
+			"Failed to load storage data",
+			"timeout",
+			at(21),
+		);
+		let recent = center.recent().collect::<Vec<_>>();
+		assert_eq!(recent.len(), 2);
+		assert_eq!(recent[1].count, 3);
+		assert_eq!(recent[1].at, at(20));
+
+		center.mark_all_seen();
+		center.push(
+			Severity::Error,
+			"files",
+			"Failed to load files",
+			"timeout",
+			at(30),
+		);
+		let newest = center.recent().next().unwrap();
+		assert_eq!(
+			(newest.title.as_str(), newest.count),
+			("Failed to load files", 4)
+		);
+		assert!(!newest.seen);
+
+		center.push(
+			Severity::Error,
+			"files",
+			"Failed to load files",
+			"timeout",
+			at(200),
+		);
+		assert_eq!(center.recent().next().unwrap().count, 1);
+	}
+
+	#[test]
+	fn the_oldest_notifications_are_dropped() {
+		let mut center = NotificationCenter::default();
+		for n in 0..MAX_NOTIFICATIONS + 5 {
+			center.push(Severity::Warning, "jobs", format!("warning {n}"), "", at(0));
+		}
+		assert_eq!(center.recent().count(), MAX_NOTIFICATIONS);
+		assert_eq!(center.recent().last().unwrap().title, "warning 5");
+	}
+}
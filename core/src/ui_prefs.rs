@@ -0,0 +1,294 @@
+use crate::config;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub(crate) const FONT_SCALES: [u16; 5] = [90, 100, 115, 130, 150];
+pub(crate) const PAGE_SIZES: [usize; 3] = [25, 50, 100];
+/// Auto refresh intervals in seconds; 0 turns auto refresh off.
+pub(crate) const REFRESH_INTERVALS: [u64; 5] = [0, 2, 5, 10, 30];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum UiTheme {
+	#[default]
+	Dark,
+	Light,
+}
+
+impl UiTheme {
+	pub(crate) fn as_str(&self) -> &'static str {
+		match self {
+			Self::Dark => "dark",
+			Self::Light => "light",
+		}
+	}
+
+	pub(crate) fn parse(value: &str) -> Option<Self> {
+		match value {
+			"dark" => Some(Self::Dark),
+			"light" => Some(Self::Light),
+			_ => None,
+		}
+	}
+}
+
+/// Order of a folder in the file browser. Folders always come first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DirSort {
+	/// Natural order of the names, so `file2` comes before `file10`.
+	#[default]
+	Name,
+	/// Largest first.
+	Size,
+	/// Newest first.
+	Modified,
+	/// By extension, then name.
+	Type,
+}
+
+impl DirSort {
+	pub(crate) const ALL: [Self; 4] = [Self::Name, Self::Size, Self::Modified, Self::Type];
+
+	pub(crate) fn as_str(&self) -> &'static str {
+		match self {
+			Self::Name => "name",
+			Self::Size => "size",
+			Self::Modified => "modified",
+			Self::Type => "type",
+		}
+	}
+
+	pub(crate) fn parse(value: &str) -> Option<Self> {
+		Self::ALL.into_iter().find(|sort| sort.as_str() == value)
+	}
+}
+
+/// How the file browser shows one folder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct DirView {
+	pub sort: DirSort,
+	/// Shows dotfiles and files the file system marks hidden.
+	pub show_hidden: bool,
+	/// Fetches previews of the folder's images.
+	pub thumbnails: bool,
+}
+
+impl Default for DirView {
+	fn default() -> Self {
+		Self {
+			sort: DirSort::Name,
+			show_hidden: true,
+			thumbnails: true,
+		}
+	}
+}
+
+/// Web UI preferences shared by every browser session of this node.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct UiPrefs {
+	pub theme: UiTheme,
+	/// Percent of the default size.
+	pub font_scale: u16,
+	pub default_page_size: usize,
+	/// Seconds between automatic refreshes, 0 when disabled.
+	pub refresh_interval: u64,
+	/// Set once the setup wizard was finished or skipped, so it no longer
+	/// opens by itself.
+	pub onboarding_done: bool,
+	/// How folders show that don't have a view of their own.
+	pub default_dir_view: DirView,
+	/// Views of folders changed from the default, by [`dir_view_key`].
+	pub dir_views: BTreeMap<String, DirView>,
+}
+
+impl Default for UiPrefs {
+	fn default() -> Self {
+		Self {
+			theme: UiTheme::Dark,
+			font_scale: 100,
+			default_page_size: 50,
+			refresh_interval: 0,
+			onboarding_done: false,
+			default_dir_view: DirView::default(),
+			dir_views: BTreeMap::new(),
+		}
+	}
+}
+
+/// Key of `path` on `peer_id` in [`UiPrefs::dir_views`].
+fn dir_view_key(peer_id: &str, path: &str) -> String {
+	format!("{peer_id}:{path}")
+}
+
+impl UiPrefs {
+	/// Loads preferences, falling back to defaults when the file is missing.
+	/// An unreadable file is moved aside and replaced with defaults.
+	pub(crate) fn load(path: &Path) -> Self {
+		let contents = match std::fs::read_to_string(path) {
+			Ok(contents) => contents,
+			Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+			Err(err) => {
+				tracing::warn!("failed to read UI preferences {}: {err}", path.display());
+				return Self::default();
+			}
+		};
+		match serde_json::from_str::<Self>(&contents) {
+			Ok(prefs) => prefs.normalized(),
+			Err(err) => {
+				let backup = path.with_extension("json.corrupt");
+				tracing::warn!(
+					"UI preferences {} are invalid ({err}); moving them to {}",
+					path.display(),
+					backup.display()
+				);
+				if let Err(err) = std::fs::rename(path, &backup) {
+					tracing::warn!("failed to move invalid UI preferences aside: {err}");
+				}
+				let prefs = Self::default();
+				if let Err(err) = prefs.save(path) {
+					tracing::warn!("failed to write default UI preferences: {err}");
+				}
+				prefs
+			}
+		}
+	}
+
+	pub(crate) fn save(&self, path: &Path) -> Result<()> {
+		if let Some(parent) = path.parent() {
+			std::fs::create_dir_all(parent)?;
+		}
+		let tmp = path.with_extension("json.tmp");
+		std::fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+		std::fs::rename(&tmp, path)?;
+		Ok(())
+	}
+
+	/// How the file browser shows `path` on `peer_id`.
+	pub(crate) fn dir_view(&self, peer_id: &str, path: &str) -> DirView {
+		self.dir_views
+			.get(&dir_view_key(peer_id, path))
+			.copied()
+			.unwrap_or(self.default_dir_view)
+	}
+
+	/// Keeps `view` for `path` on `peer_id`; a view like the default one
+	/// is forgotten rather than stored.
+	pub(crate) fn set_dir_view(&mut self, peer_id: &str, path: &str, view: DirView) {
+		let key = dir_view_key(peer_id, path);
+		if view == self.default_dir_view {
+			self.dir_views.remove(&key);
+		} else {
+			self.dir_views.insert(key, view);
+		}
+	}
+
+	/// Snaps hand-edited values to the nearest supported option.
+	pub(crate) fn normalized(mut self) -> Self {
+		self.font_scale = nearest(&FONT_SCALES, self.font_scale);
+		self.default_page_size = nearest(&PAGE_SIZES, self.default_page_size);
+		self.refresh_interval = nearest(&REFRESH_INTERVALS, self.refresh_interval);
+		self
+	}
+}
+
+fn nearest<T: Copy + Ord + std::ops::Sub<Output = T>>(options: &[T], value: T) -> T {
+	options
+		.iter()
+		.copied()
+		.min_by_key(|option| {
+			if *option > value {
+				*option - value
+			} else {
+				value - *option
+			}
+		})
+		.unwrap_or(value)
+}
+
+pub(crate) fn prefs_path() -> PathBuf {
+	config::startup().ui_prefs_path.clone()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn test_path(name: &str) -> PathBuf {
+		let now = std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.unwrap()
+			.as_nanos();
+		std::env::temp_dir()
+			.join(format!("puppynet-{name}-{}-{now}", std::process::id()))
+			.join("ui_prefs.json")
+	}
+
+	#[test]
+	fn prefs_round_trip_and_default_when_missing() {
+		let path = test_path("ui-prefs");
+		assert_eq!(UiPrefs::load(&path), UiPrefs::default());
+
+		let prefs = UiPrefs {
+			theme: UiTheme::Light,
+			font_scale: 130,
+			default_page_size: 100,
+			refresh_interval: 10,
+			onboarding_done: true,
+			..UiPrefs::default()
+		};
+		prefs.save(&path).unwrap();
+		assert_eq!(UiPrefs::load(&path), prefs);
+
+		std::fs::write(&path, r#"{"theme":"light","font_scale":120}"#).unwrap();
+		let partial = UiPrefs::load(&path);
+		assert_eq!(partial.theme, UiTheme::Light);
+		assert_eq!(partial.font_scale, 115);
+		assert_eq!(partial.default_page_size, 50);
+		assert!(!partial.onboarding_done);
+	}
+
+	#[test]
+	fn folder_views_round_trip_per_device_and_path() {
+		let path = test_path("ui-prefs-dir-views");
+		let mut prefs = UiPrefs::default();
+		let photos = DirView {
+			sort: DirSort::Modified,
+			thumbnails: false,
+			..DirView::default()
+		};
+		prefs.set_dir_view("peer-a", "/srv/photos", photos);
+		prefs.set_dir_view("peer-a", "/srv", DirView::default());
+		prefs.save(&path).unwrap();
+
+		let loaded = UiPrefs::load(&path);
+		assert_eq!(loaded.dir_view("peer-a", "/srv/photos"), photos);
+		assert_eq!(loaded.dir_view("peer-b", "/srv/photos"), DirView::default());
+		assert_eq!(loaded.dir_views.len(), 1);
+
+		let mut reset = loaded;
+		reset.set_dir_view("peer-a", "/srv/photos", DirView::default());
+		assert!(reset.dir_views.is_empty());
+
+		std::fs::write(&path, r#"{"dir_views":{"peer-a:/srv":{"sort":"size"}}}"#).unwrap();
+		let partial = UiPrefs::load(&path).dir_view("peer-a", "/srv");
+		assert_eq!(partial.sort, DirSort::Size);
+		assert!(partial.show_hidden && partial.thumbnails);
+	}
+
+	#[test]
+	fn corrupted_prefs_are_moved_aside_and_regenerated() {
+		let path = test_path("ui-prefs-corrupt");
+		std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+		std::fs::write(&path, "{not json").unwrap();
+
+		assert_eq!(UiPrefs::load(&path), UiPrefs::default());
+		let backup = path.with_extension("json.corrupt");
+		assert_eq!(std::fs::read_to_string(backup).unwrap(), "{not json");
+		assert_eq!(UiPrefs::load(&path), UiPrefs::default());
+	}
+}
@@ -0,0 +1,273 @@
+//! Where the web UI was when the node stopped: the page and device shown,
+//! the folder browsed there, the search being typed and the folder to
+//! scan. Saved next to the UI preferences every
+//! [`RESUME_SAVE_INTERVAL`] and on shutdown, and read once at startup. A
+//! file that can't be read, or was written by another version, is dropped.
+//!
+//! Restoring only picks a page from what the startup refresh already
+//! knows; the page fetches its data when the browser opens it, like any
+//! other navigation. Pages that need a device that is offline or gone open
+//! the nearest page that works without it, with a note saying why.
+
+use super::{Page, PeerRow};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Bumped when saved fields change meaning.
+const RESUME_VERSION: u32 = 1;
+pub(super) const RESUME_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(super) struct ResumedSearch {
+	pub(super) name_query: String,
+	pub(super) target: String,
+	pub(super) sort: String,
+	pub(super) page_size: String,
+	pub(super) selected_mimes: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(super) struct UiResume {
+	version: u32,
+	pub(super) page: Page,
+	pub(super) search: ResumedSearch,
+	pub(super) scan_path: String,
+}
+
+impl Default for UiResume {
+	fn default() -> Self {
+		Self {
+			version: RESUME_VERSION,
+			page: Page::Home,
+			search: ResumedSearch::default(),
+			scan_path: String::new(),
+		}
+	}
+}
+
+/// What the first browser after a restart picks up: the session fields
+/// go to the first signed in session, the page to the first one opening
+/// the home page.
+#[derive(Default)]
+pub(super) struct PendingResume {
+	pub(super) page: Option<Page>,
+	pub(super) fields: Option<(ResumedSearch, String)>,
+}
+
+impl UiResume {
+	/// The saved state, or `None` when there is none to use.
+	pub(super) fn load(path: &Path) -> Option<Self> {
+		let contents = match std::fs::read_to_string(path) {
+			Ok(contents) => contents,
+			Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+			Err(err) => {
+				tracing::warn!("failed to read UI session {}: {err}", path.display());
+				return None;
+			}
+		};
+		match serde_json::from_str::<Self>(&contents) {
+			Ok(resume) if resume.version == RESUME_VERSION => Some(resume),
+			Ok(resume) => {
+				tracing::info!(
+					"ignoring UI session {} saved by version {}",
+					path.display(),
+					resume.version
+				);
+				None
+			}
+			Err(err) => {
+				tracing::warn!("ignoring invalid UI session {}: {err}", path.display());
+				None
+			}
+		}
+	}
+
+	pub(super) fn save(&self, path: &Path) -> Result<()> {
+		if let Some(parent) = path.parent() {
+			std::fs::create_dir_all(parent)?;
+		}
+		let tmp = path.with_extension("json.tmp");
+		std::fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+		std::fs::rename(&tmp, path)?;
+		Ok(())
+	}
+
+	/// The page to open after a restart and, when it can't open as it
+	/// was, a note on what opened instead. `peers` and `index_empty` are
+	/// what the startup refresh found.
+	pub(super) fn restore(&self, peers: &[PeerRow], index_empty: bool) -> (Page, Option<String>) {
+		let peer_id = match &self.page {
+			Page::PeerDetail(peer_id)
+			| Page::PeerControl { peer_id }
+			| Page::PeerFiles { peer_id, .. }
+			| Page::PeerWebcams { peer_id }
+			| Page::PeerPermissions { peer_id }
+			| Page::PeerShell { peer_id } => peer_id,
+			Page::Search if index_empty && !self.search.name_query.is_empty() => {
+				return (
+					Page::Home,
+					Some(format!(
+						"couldn't restore the search for \"{}\" — the index is empty",
+						self.search.name_query
+					)),
+				);
+			}
+			page => return (page.clone(), None),
+		};
+		let what = match &self.page {
+			Page::PeerFiles { path, .. } if !path.is_empty() => path.clone(),
+			Page::PeerFiles { .. } => String::from("the files"),
+			Page::PeerControl { .. } => String::from("remote control"),
+			Page::PeerWebcams { .. } => String::from("the webcams"),
+			Page::PeerShell { .. } => String::from("the shell"),
+			_ => String::from("the device page"),
+		};
+		let Some(peer) = peers.iter().find(|peer| peer.id == *peer_id) else {
+			return (
+				Page::Peers,
+				Some(format!(
+					"couldn't restore {what} on {} — device no longer known",
+					crate::format::abbrev_peer_id(peer_id)
+				)),
+			);
+		};
+		let needs_connection = matches!(
+			self.page,
+			Page::PeerControl { .. }
+				| Page::PeerFiles { .. }
+				| Page::PeerWebcams { .. }
+				| Page::PeerShell { .. }
+		);
+		if needs_connection && !peer.local && peer.connections.is_empty() {
+			return (
+				Page::PeerDetail(peer_id.clone()),
+				Some(format!(
+					"couldn't restore {what} on {} — peer offline",
+					peer.name
+				)),
+			);
+		}
+		(self.page.clone(), None)
+	}
+}
+
+pub(super) fn resume_path(prefs_path: &Path) -> PathBuf {
+	prefs_path.with_file_name("ui_session.json")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::state::TrustLevel;
+	use crate::test_util::test_dir;
+
+	fn peer(id: &str, name: &str, local: bool) -> PeerRow {
+		PeerRow {
+			id: id.to_string(),
+			name: name.to_string(),
+			local,
+			version: String::new(),
+			os: String::new(),
+			uptime: String::new(),
+			connections: Vec::new(),
+			recent_drops: 0,
+			clock_skew: None,
+			index_stale: None,
+			idle: false,
+			important: false,
+			trust: TrustLevel::default(),
+		}
+	}
+
+	fn resume(page: Page) -> UiResume {
+		UiResume {
+			page,
+			..UiResume::default()
+		}
+	}
+
+	#[test]
+	fn saved_session_loads_back() {
+		let path = test_dir("ui-resume").join("ui_session.json");
+		let saved = UiResume {
+			page: Page::PeerFiles {
+				peer_id: String::from("abc"),
+				path: String::from("/photos"),
+			},
+			search: ResumedSearch {
+				name_query: String::from("holiday"),
+				selected_mimes: vec![String::from("image/jpeg")],
+				..ResumedSearch::default()
+			},
+			scan_path: String::from("/home/me"),
+			..UiResume::default()
+		};
+		saved.save(&path).unwrap();
+		assert_eq!(UiResume::load(&path), Some(saved));
+	}
+
+	#[test]
+	fn unusable_session_is_dropped() {
+		let dir = test_dir("ui-resume-unusable");
+		assert_eq!(UiResume::load(&dir.join("missing.json")), None);
+		let invalid = dir.join("invalid.json");
+		std::fs::write(&invalid, "{ not json").unwrap();
+		assert_eq!(UiResume::load(&invalid), None);
+		let other_version = dir.join("other.json");
+		std::fs::write(&other_version, r#"{"version": 99, "page": "storage"}"#).unwrap();
+		assert_eq!(UiResume::load(&other_version), None);
+	}
+
+	#[test]
+	fn offline_peer_opens_its_detail_page() {
+		let page = Page::PeerFiles {
+			peer_id: String::from("abc"),
+			path: String::from("/photos"),
+		};
+		let (restored, note) = resume(page).restore(&[peer("abc", "laptop", false)], false);
+		assert_eq!(restored, Page::PeerDetail(String::from("abc")));
+		assert_eq!(
+			note.as_deref(),
+			Some("couldn't restore /photos on laptop — peer offline")
+		);
+	}
+
+	#[test]
+	fn unknown_peer_opens_the_device_list() {
+		let page = Page::PeerShell {
+			peer_id: String::from("gone"),
+		};
+		let (restored, note) = resume(page).restore(&[peer("abc", "laptop", false)], false);
+		assert_eq!(restored, Page::Peers);
+		assert!(note.unwrap().ends_with("device no longer known"));
+	}
+
+	#[test]
+	fn search_over_empty_index_opens_home() {
+		let mut saved = resume(Page::Search);
+		saved.search.name_query = String::from("holiday");
+		let (restored, note) = saved.restore(&[], true);
+		assert_eq!(restored, Page::Home);
+		assert!(note.unwrap().contains("holiday"));
+		assert_eq!(saved.restore(&[], false), (Page::Search, None));
+	}
+
+	#[test]
+	fn reachable_pages_open_as_they_were() {
+		let page = Page::PeerControl {
+			peer_id: String::from("me"),
+		};
+		let peers = [peer("me", "desk", true)];
+		assert_eq!(resume(page.clone()).restore(&peers, false), (page, None));
+		let details = Page::PeerDetail(String::from("abc"));
+		let peers = [peer("abc", "laptop", false)];
+		assert_eq!(
+			resume(details.clone()).restore(&peers, false),
+			(details, None)
+		);
+	}
+}
@@ -0,0 +1,421 @@
+//! A small terminal for the output of remote shells. It understands what
+//! line-oriented programs print: SGR colours and bold, carriage returns and
+//! cursor-to-column moves that progress bars redraw a line with, backspace
+//! and erase-in-line. Everything else an escape sequence asks for is
+//! dropped rather than shown.
+//!
+//! Output is queued as it arrives and fed to the screen in batches of
+//! [`FEED_BATCH`] bytes, one per render, so a command that floods the
+//! terminal never holds up the page. Lines scrolled past
+//! [`SCROLLBACK_LINES`] are forgotten.
+
+use std::collections::VecDeque;
+
+/// Lines kept above and including the current one.
+pub(crate) const SCROLLBACK_LINES: usize = 1000;
+/// Output bytes fed to the screen per render.
+pub(crate) const FEED_BATCH: usize = 16 * 1024;
+/// Column past which a line continues on the next one.
+const MAX_COLUMNS: usize = 1024;
+/// Longest parameter list of a control sequence kept; longer ones are
+/// still consumed, only their parameters are lost.
+const MAX_PARAMS: usize = 64;
+
+/// The 16 colours of SGR 30-37 and 90-97.
+const PALETTE: [&str; 16] = [
+	"#4a5a58", "#ff6b6b", "#79f2c0", "#f2c879", "#6fa8ff", "#d291ff", "#5fd7d7", "#d6eee9",
+	"#7f8c8a", "#ff8a8a", "#a6ffd9", "#ffe0a0", "#9cc4ff", "#e4b8ff", "#8ff0f0", "#ffffff",
+];
+const DEFAULT_COLOR: &str = "#d6eee9";
+const DEFAULT_BOLD_COLOR: &str = "#ffffff";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Style {
+	/// Index into the 16 colour palette; `None` is the default colour.
+	pub(crate) fg: Option<u8>,
+	pub(crate) bold: bool,
+}
+
+impl Style {
+	/// The colour text of this style is drawn in. Bold brightens the eight
+	/// normal colours, as most terminals do.
+	pub(crate) fn color(self) -> &'static str {
+		match (self.fg, self.bold) {
+			(Some(fg), true) if fg < 8 => PALETTE[usize::from(fg) + 8],
+			(Some(fg), _) => PALETTE[usize::from(fg)],
+			(None, true) => DEFAULT_BOLD_COLOR,
+			(None, false) => DEFAULT_COLOR,
+		}
+	}
+
+	fn apply_sgr(&mut self, params: &str) {
+		let mut codes = params
+			.split(';')
+			.map(|code| code.parse::<u16>().unwrap_or(0));
+		while let Some(code) = codes.next() {
+			match code {
+				0 => *self = Self::default(),
+				1 => self.bold = true,
+				22 => self.bold = false,
+				30..=37 => self.fg = Some((code - 30) as u8),
+				39 => self.fg = None,
+				90..=97 => self.fg = Some((code - 90 + 8) as u8),
+				38 | 48 => match codes.next() {
+					Some(5) => {
+						let color = codes.next();
+						if code == 38 {
+							self.fg = color.filter(|color| *color < 16).map(|color| color as u8);
+						}
+					}
+					Some(2) => {
+						codes.nth(2);
+					}
+					_ => {}
+				},
+				_ => {}
+			}
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Cell {
+	ch: char,
+	style: Style,
+}
+
+/// A run of text of one style on one line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Span {
+	pub(crate) text: String,
+	pub(crate) style: Style,
+	/// The span starts a line; an empty line is one empty span.
+	pub(crate) line_start: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+enum Parser {
+	#[default]
+	Ground,
+	Escape,
+	/// A control sequence, with the parameters read so far.
+	Csi(String),
+	/// An operating system command such as a window title, up to BEL or ST.
+	Osc,
+	OscEscape,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Terminal {
+	lines: VecDeque<Vec<Cell>>,
+	cursor: usize,
+	style: Style,
+	parser: Parser,
+	/// The start of a UTF-8 sequence the last batch ended inside of.
+	partial: Vec<u8>,
+	/// Output not fed to the screen yet.
+	queued: VecDeque<u8>,
+}
+
+impl Default for Terminal {
+	fn default() -> Self {
+		Self {
+			lines: VecDeque::from([Vec::new()]),
+			cursor: 0,
+			style: Style::default(),
+			parser: Parser::Ground,
+			partial: Vec::new(),
+			queued: VecDeque::new(),
+		}
+	}
+}
+
+impl Terminal {
+	/// Queues output to be fed by [`Terminal::feed_batch`].
+	pub(crate) fn push(&mut self, bytes: &[u8]) {
+		self.queued.extend(bytes);
+	}
+
+	pub(crate) fn has_queued(&self) -> bool {
+		!self.queued.is_empty()
+	}
+
+	/// Bytes of output not shown yet.
+	pub(crate) fn queued(&self) -> usize {
+		self.queued.len()
+	}
+
+	/// Feeds up to [`FEED_BATCH`] queued bytes to the screen.
+	pub(crate) fn feed_batch(&mut self) {
+		let take = self.queued.len().min(FEED_BATCH);
+		let batch = self.queued.drain(..take).collect::<Vec<_>>();
+		self.feed(&batch);
+	}
+
+	/// Decodes `bytes` as UTF-8, replacing what is not, and keeps a
+	/// sequence cut at the end for the next batch.
+	fn feed(&mut self, bytes: &[u8]) {
+		let mut data = std::mem::take(&mut self.partial);
+		data.extend_from_slice(bytes);
+		let mut rest = data.as_slice();
+		loop {
+			match std::str::from_utf8(rest) {
+				Ok(text) => {
+					self.feed_str(text);
+					break;
+				}
+				Err(err) => {
+					let (valid, after) = rest.split_at(err.valid_up_to());
+					// Only the valid prefix was checked; it decodes.
+					self.feed_str(std::str::from_utf8(valid).unwrap_or_default());
+					match err.error_len() {
+						Some(len) => {
+							self.put(char::REPLACEMENT_CHARACTER);
+							rest = &after[len..];
+						}
+						None => {
+							self.partial = after.to_vec();
+							break;
+						}
+					}
+				}
+			}
+		}
+	}
+
+	fn feed_str(&mut self, text: &str) {
+		for ch in text.chars() {
+			self.feed_char(ch);
+		}
+	}
+
+	fn feed_char(&mut self, ch: char) {
+		match std::mem::take(&mut self.parser) {
+			Parser::Ground => match ch {
+				'\x1b' => self.parser = Parser::Escape,
+				'\n' => self.new_line(),
+				'\r' => self.cursor = 0,
+				'\x08' => self.cursor = self.cursor.saturating_sub(1),
+				'\t' => self.cursor = (self.cursor / 8 + 1) * 8,
+				ch if ch.is_control() => {}
+				ch => self.put(ch),
+			},
+			Parser::Escape => {
+				self.parser = match ch {
+					'[' => Parser::Csi(String::new()),
+					']' => Parser::Osc,
+					// Charset selections like `ESC ( B` end on the next byte.
+					' '..='/' => Parser::Escape,
+					_ => Parser::Ground,
+				}
+			}
+			Parser::Csi(mut params) => match ch {
+				'0'..='?' => {
+					if params.len() < MAX_PARAMS {
+						params.push(ch);
+					}
+					self.parser = Parser::Csi(params);
+				}
+				' '..='/' => self.parser = Parser::Csi(params),
+				'@'..='~' => self.control(ch, &params),
+				_ => {}
+			},
+			Parser::Osc => {
+				self.parser = match ch {
+					'\x07' => Parser::Ground,
+					'\x1b' => Parser::OscEscape,
+					_ => Parser::Osc,
+				}
+			}
+			Parser::OscEscape => {}
+		}
+	}
+
+	fn control(&mut self, action: char, params: &str) {
+		let count = params.parse::<usize>().unwrap_or(1).max(1);
+		match action {
+			'm' => self.style.apply_sgr(params),
+			'G' => self.cursor = (count - 1).min(MAX_COLUMNS - 1),
+			'C' => self.cursor = (self.cursor + count).min(MAX_COLUMNS - 1),
+			'D' => self.cursor = self.cursor.saturating_sub(count),
+			'K' => {
+				let cursor = self.cursor;
+				let line = self.current_line();
+				match params {
+					"1" => line
+						.iter_mut()
+						.take(cursor + 1)
+						.for_each(|cell| cell.ch = ' '),
+					"2" => line.clear(),
+					_ => line.truncate(cursor),
+				}
+			}
+			_ => {}
+		}
+	}
+
+	fn current_line(&mut self) -> &mut Vec<Cell> {
+		if self.lines.is_empty() {
+			self.lines.push_back(Vec::new());
+		}
+		self.lines.back_mut().expect("a line was just added")
+	}
+
+	fn new_line(&mut self) {
+		self.lines.push_back(Vec::new());
+		while self.lines.len() > SCROLLBACK_LINES {
+			self.lines.pop_front();
+		}
+		self.cursor = 0;
+	}
+
+	fn put(&mut self, ch: char) {
+		if self.cursor >= MAX_COLUMNS {
+			self.new_line();
+		}
+		let cursor = self.cursor;
+		let cell = Cell {
+			ch,
+			style: self.style,
+		};
+		let line = self.current_line();
+		if cursor < line.len() {
+			line[cursor] = cell;
+		} else {
+			line.resize(
+				cursor,
+				Cell {
+					ch: ' ',
+					style: Style::default(),
+				},
+			);
+			line.push(cell);
+		}
+		self.cursor += 1;
+	}
+
+	/// The screen as runs of one style, line by line.
+	pub(crate) fn spans(&self) -> Vec<Span> {
+		let mut spans = Vec::new();
+		for line in &self.lines {
+			let mut line_start = true;
+			let mut cells = line.iter().peekable();
+			if cells.peek().is_none() {
+				spans.push(Span {
+					text: String::new(),
+					style: Style::default(),
+					line_start,
+				});
+			}
+			while let Some(first) = cells.next() {
+				let mut text = String::from(first.ch);
+				while let Some(cell) = cells.next_if(|cell| cell.style == first.style) {
+					text.push(cell.ch);
+				}
+				spans.push(Span {
+					text,
+					style: first.style,
+					line_start,
+				});
+				line_start = false;
+			}
+		}
+		spans
+	}
+
+	/// The screen as plain text, one line per line.
+	#[cfg(test)]
+	pub(crate) fn text(&self) -> String {
+		self.lines
+			.iter()
+			.map(|line| line.iter().map(|cell| cell.ch).collect::<String>())
+			.collect::<Vec<_>>()
+			.join("\n")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn fed(bytes: &[u8]) -> Terminal {
+		let mut terminal = Terminal::default();
+		terminal.push(bytes);
+		while terminal.has_queued() {
+			terminal.feed_batch();
+		}
+		terminal
+	}
+
+	#[test]
+	fn colours_and_bold_split_a_line_into_spans() {
+		let terminal = fed(b"ok \x1b[1;31mFAIL\x1b[0m done\r\n$ ");
+		let spans = terminal.spans();
+
+		assert_eq!(terminal.text(), "ok FAIL done\n$ ");
+		assert_eq!(
+			spans
+				.iter()
+				.map(|span| (span.text.as_str(), span.line_start))
+				.collect::<Vec<_>>(),
+			vec![
+				("ok ", true),
+				("FAIL", false),
+				(" done", false),
+				("$ ", true)
+			]
+		);
+		assert_eq!(
+			spans[1].style,
+			Style {
+				fg: Some(1),
+				bold: true
+			}
+		);
+		assert_eq!(spans[1].style.color(), PALETTE[9]);
+		assert_eq!(spans[2].style, Style::default());
+	}
+
+	#[test]
+	fn carriage_returns_and_column_moves_redraw_a_progress_bar() {
+		let terminal = fed(b"[##   ] 40%\r[#####] 100%\n\x1b[10Gend\rx\x1b[K");
+
+		assert_eq!(terminal.text(), "[#####] 100%\nx");
+		let terminal = fed(b"abc\x1b[5Gz\x08\x08y");
+		assert_eq!(terminal.text(), "abcyz");
+	}
+
+	#[test]
+	fn other_sequences_are_dropped_and_titles_hidden() {
+		let terminal = fed(b"\x1b]0;user@host\x07\x1b[?2004hls\x1b[2J\x1b(B\n");
+
+		assert_eq!(terminal.text(), "ls\n");
+	}
+
+	#[test]
+	fn invalid_utf8_is_replaced_and_split_characters_are_joined() {
+		let mut terminal = Terminal::default();
+		terminal.push(&[b'a', 0xff, b'b', 0xc3]);
+		terminal.feed_batch();
+		terminal.push(&[0xa9]);
+		terminal.feed_batch();
+
+		assert_eq!(terminal.text(), "a\u{fffd}bé");
+	}
+
+	#[test]
+	fn output_is_fed_in_bounded_batches_into_a_capped_scrollback() {
+		let mut terminal = Terminal::default();
+		let line = "x".repeat(99) + "\n";
+		terminal.push(line.repeat(SCROLLBACK_LINES * 2).as_bytes());
+
+		terminal.feed_batch();
+		assert!(terminal.has_queued());
+		assert_eq!(terminal.text().len(), FEED_BATCH);
+		while terminal.has_queued() {
+			terminal.feed_batch();
+		}
+		assert_eq!(terminal.lines.len(), SCROLLBACK_LINES);
+		assert!(terminal.text().ends_with(&format!("{}\n", "x".repeat(99))));
+	}
+}
@@ -0,0 +1,115 @@
+//! Rendering long lists of the web UI a window at a time. Every push of
+//! the view builds and diffs each row of a `For`, so a folder with tens of
+//! thousands of entries costs that much on every redraw, however few of
+//! them are on screen. A list keeps a [`RowWindow`] and only renders the
+//! rows in [`RowWindow::range`]: the first [`ROW_WINDOW_STEP`], growing by
+//! that much whenever the list is scrolled to its end or focus reaches it.
+//! Rows keep their position in the full list as their index, so click
+//! handlers taking an index work unchanged.
+
+use std::ops::Range;
+
+use crate::format::group_digits;
+
+/// Rows rendered at first and added each time the window grows. Lists
+/// shorter than this render exactly as before.
+pub(crate) const ROW_WINDOW_STEP: usize = 200;
+/// Rows kept rendered past a focused or highlighted row, so moving down
+/// from it never reaches the end of the window.
+pub(crate) const ROW_WINDOW_OVERSCAN: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct RowWindow {
+	shown: usize,
+}
+
+impl Default for RowWindow {
+	fn default() -> Self {
+		Self {
+			shown: ROW_WINDOW_STEP,
+		}
+	}
+}
+
+impl RowWindow {
+	/// Rows of a `len` long list to render, always including `keep` and
+	/// the overscan after it.
+	pub(crate) fn range(&self, len: usize, keep: Option<usize>) -> Range<usize> {
+		let shown = match keep {
+			Some(index) => self.shown.max(index + 1 + ROW_WINDOW_OVERSCAN),
+			None => self.shown,
+		};
+		0..len.min(shown)
+	}
+
+	pub(crate) fn grow(&mut self) {
+		self.shown += ROW_WINDOW_STEP;
+	}
+
+	/// Grows the window when `index` is within the overscan of its end.
+	pub(crate) fn reveal(&mut self, index: usize) {
+		while index + ROW_WINDOW_OVERSCAN >= self.shown {
+			self.grow();
+		}
+	}
+
+	/// "Show 200 more (1,800 not shown)" when rows are left out, empty
+	/// otherwise.
+	pub(crate) fn more_label(&self, len: usize, keep: Option<usize>) -> String {
+		let hidden = len - self.range(len, keep).end;
+		if hidden == 0 {
+			return String::new();
+		}
+		format!(
+			"Show {} more ({} not shown)",
+			hidden.min(ROW_WINDOW_STEP),
+			group_digits(hidden as u64)
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn short_lists_render_whole() {
+		let window = RowWindow::default();
+		assert_eq!(window.range(0, None), 0..0);
+		assert_eq!(window.range(120, None), 0..120);
+		assert_eq!(window.more_label(120, None), "");
+		assert_eq!(window.more_label(ROW_WINDOW_STEP, None), "");
+	}
+
+	#[test]
+	fn the_rendered_rows_stay_the_same_however_long_the_list() {
+		let window = RowWindow::default();
+		for len in [5_000, 50_000, 500_000] {
+			assert_eq!(window.range(len, None).len(), ROW_WINDOW_STEP);
+		}
+		assert_eq!(
+			window.more_label(50_000, None),
+			"Show 200 more (49,800 not shown)"
+		);
+
+		let mut window = window;
+		window.grow();
+		assert_eq!(window.range(50_000, None), 0..2 * ROW_WINDOW_STEP);
+		assert_eq!(window.range(250, None), 0..250);
+	}
+
+	#[test]
+	fn focused_and_highlighted_rows_are_always_rendered() {
+		let mut window = RowWindow::default();
+		assert_eq!(
+			window.range(50_000, Some(1_000)).end,
+			1_001 + ROW_WINDOW_OVERSCAN
+		);
+		assert_eq!(window.range(1_005, Some(1_000)), 0..1_005);
+
+		window.reveal(ROW_WINDOW_STEP - ROW_WINDOW_OVERSCAN - 1);
+		assert_eq!(window.range(50_000, None).end, ROW_WINDOW_STEP);
+		window.reveal(ROW_WINDOW_STEP - 1);
+		assert_eq!(window.range(50_000, None).end, 2 * ROW_WINDOW_STEP);
+	}
+}
@@ -1,34 +1,65 @@
+use std::path::PathBuf;
 #[cfg(target_os = "linux")]
 use std::process::{Command, Stdio};
-use std::{
-	io::BufReader,
-	path::{Path, PathBuf},
-};
+#[cfg(feature = "self-update")]
+use std::time::{Duration, Instant};
+#[cfg(feature = "self-update")]
+use std::{io::BufReader, path::Path};
 
 use anyhow::bail;
+#[cfg(feature = "self-update")]
 use flate2::read::GzDecoder;
+#[cfg(feature = "self-update")]
 use rsa::signature::Verifier;
+#[cfg(feature = "self-update")]
 use rsa::{RsaPublicKey, pkcs1v15, pkcs8::DecodePublicKey};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "self-update")]
 use serde_json::Value;
+#[cfg(feature = "self-update")]
 use sha2::Sha256;
+#[cfg(feature = "self-update")]
 use tar::Archive;
+#[cfg(feature = "self-update")]
 use tokio::{fs::File, io::AsyncWriteExt};
+#[cfg(feature = "self-update")]
 use zip::ZipArchive;
 
+#[cfg(feature = "self-update")]
+use crate::http_proxy::HttpClient;
+#[cfg(feature = "self-update")]
+use crate::throughput::RateEstimator;
+#[cfg(feature = "self-update")]
 use crate::version;
 
 /// Path resolution: this file is core/src/updater.rs; the key lives at repository root.
 pub const PUBLIC_KEY: &str = include_str!("../../public_key.pem");
+#[cfg(any(target_os = "linux", feature = "self-update"))]
 const SERVICE_LABEL: &str = "puppynet";
+/// How often a download in progress is reported.
+#[cfg(feature = "self-update")]
+const DOWNLOAD_REPORT_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Progress information during an update operation.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum UpdateProgress {
+	/// Held back until the activity window opens at `until` (`HH:MM`)
+	Deferred { until: String },
 	/// Fetching release metadata from GitHub
 	FetchingRelease,
-	/// Downloading the binary
-	Downloading { filename: String },
+	/// Downloading the binary. Sent again as the bytes arrive; peers that
+	/// predate the byte counts only send the first one.
+	Downloading {
+		filename: String,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		received: Option<u64>,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		total: Option<u64>,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		bytes_per_sec: Option<u64>,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		eta_secs: Option<u64>,
+	},
 	/// Unpacking the archive
 	Unpacking,
 	/// Verifying signature
@@ -37,12 +68,127 @@ pub enum UpdateProgress {
 	Installing,
 	/// Update completed successfully
 	Completed { version: String },
-	/// Update failed with error
-	Failed { error: String },
+	/// Update failed with error. `kind` is missing from peers that predate
+	/// it; classify those with [`classify_update_error`].
+	Failed {
+		error: String,
+		#[serde(default)]
+		kind: Option<UpdateErrorKind>,
+	},
+	/// A transient failure that is retried after `delay_secs`.
+	Retrying {
+		attempt: u32,
+		max_attempts: u32,
+		delay_secs: u64,
+		error: String,
+	},
 	/// Already up to date
 	AlreadyUpToDate { current_version: u32 },
 }
 
+/// Whether retrying a failed update can help.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateErrorKind {
+	/// The network or release server hiccuped.
+	Transient,
+	/// Retrying would fail the same way, like a bad signature or no build
+	/// for this platform.
+	Permanent,
+}
+
+/// Error texts, lowercased, that come from the connection rather than the
+/// release itself.
+const TRANSIENT_ERRORS: [&str; 14] = [
+	"error sending request",
+	"http status server error",
+	"too many requests",
+	"error decoding response body",
+	"connection reset",
+	"connection refused",
+	"connection closed",
+	"broken pipe",
+	"timed out",
+	"operation timed out",
+	"dns error",
+	"unexpected eof",
+	"request failed",
+	"network is unreachable",
+];
+
+/// Classifies an updater error by its text, which is all a remote peer
+/// reports. Unknown errors are permanent so nothing is retried blindly.
+pub fn classify_update_error(error: &str) -> UpdateErrorKind {
+	let error = error.to_lowercase();
+	if let Some((_, status)) = error.split_once("http status: ") {
+		let code = status.get(..3).and_then(|code| code.parse::<u16>().ok());
+		return match code {
+			Some(408 | 429 | 500..=599) => UpdateErrorKind::Transient,
+			_ => UpdateErrorKind::Permanent,
+		};
+	}
+	if TRANSIENT_ERRORS
+		.iter()
+		.any(|transient| error.contains(transient))
+	{
+		UpdateErrorKind::Transient
+	} else {
+		UpdateErrorKind::Permanent
+	}
+}
+
+impl UpdateProgress {
+	/// A `Failed` event for `err`, classified on its full cause chain.
+	pub fn failed(err: &anyhow::Error) -> Self {
+		UpdateProgress::Failed {
+			error: err.to_string(),
+			kind: Some(classify_update_error(&format!("{err:#}"))),
+		}
+	}
+
+	fn failed_permanently(error: String) -> Self {
+		UpdateProgress::Failed {
+			error,
+			kind: Some(UpdateErrorKind::Permanent),
+		}
+	}
+}
+
+/// How often and how patiently a controller retries a remote update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpdateRetryPolicy {
+	/// Attempts in total, the first one included.
+	pub max_attempts: u32,
+	pub initial_backoff: std::time::Duration,
+	pub max_backoff: std::time::Duration,
+}
+
+impl Default for UpdateRetryPolicy {
+	fn default() -> Self {
+		Self {
+			max_attempts: 3,
+			initial_backoff: std::time::Duration::from_secs(5),
+			max_backoff: std::time::Duration::from_secs(60),
+		}
+	}
+}
+
+impl UpdateRetryPolicy {
+	/// The wait before attempt `attempt + 1` after attempt `attempt` failed
+	/// with `kind`, or `None` when the failure is final. Doubles each time.
+	pub fn retry_after(&self, attempt: u32, kind: UpdateErrorKind) -> Option<std::time::Duration> {
+		if kind != UpdateErrorKind::Transient || attempt >= self.max_attempts {
+			return None;
+		}
+		let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+		Some(
+			self.initial_backoff
+				.saturating_mul(factor)
+				.min(self.max_backoff),
+		)
+	}
+}
+
 /// Result of an update operation
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpdateResult {
@@ -51,8 +197,9 @@ pub struct UpdateResult {
 	pub new_version: Option<String>,
 }
 
+#[cfg(feature = "self-update")]
 pub fn verify_signature(bin: &Path, sig: &Path) -> anyhow::Result<bool> {
-	log::info!("verifying {} with {}", bin.display(), sig.display());
+	tracing::info!("verifying {} with {}", bin.display(), sig.display());
 	let signature = std::fs::read(sig)?;
 	let signature = rsa::pkcs1v15::Signature::try_from(signature.as_slice())?;
 	let data = std::fs::read(bin)?;
@@ -61,11 +208,13 @@ pub fn verify_signature(bin: &Path, sig: &Path) -> anyhow::Result<bool> {
 	Ok(verifying_key.verify(&data, &signature).is_ok())
 }
 
+#[cfg(feature = "self-update")]
 fn get_os_name() -> String {
 	let os = std::env::consts::OS;
 	os.to_string()
 }
 
+#[cfg(feature = "self-update")]
 fn app_dir() -> PathBuf {
 	let path = homedir::my_home().unwrap().unwrap().join(".puppynet");
 	if !path.exists() {
@@ -74,6 +223,7 @@ fn app_dir() -> PathBuf {
 	path
 }
 
+#[cfg(feature = "self-update")]
 fn bin_dir() -> PathBuf {
 	let path = app_dir().join("bin");
 	if !path.exists() {
@@ -82,6 +232,7 @@ fn bin_dir() -> PathBuf {
 	path
 }
 
+#[cfg(feature = "self-update")]
 async fn install_binary(source: &Path, bin_name: &str) -> anyhow::Result<()> {
 	let target = bin_dir().join(bin_name);
 	let temp_target = target.with_extension("new");
@@ -98,7 +249,7 @@ async fn install_binary(source: &Path, bin_name: &str) -> anyhow::Result<()> {
 	}
 
 	tokio::fs::rename(&temp_target, &target).await?;
-	log::info!("installed update to {}", target.display());
+	tracing::info!("installed update to {}", target.display());
 
 	Ok(())
 }
@@ -125,35 +276,94 @@ fn systemctl_restart(args: &[&str]) -> anyhow::Result<()> {
 	Ok(())
 }
 
+/// Asks systemd to restart the puppynet service. `None` when puppynet is
+/// not running as an active user or system service.
 #[cfg(target_os = "linux")]
-fn restart_active_service() {
+fn restart_service() -> Option<anyhow::Result<()>> {
 	if systemctl_is_active(&["--user", "is-active", "--quiet", SERVICE_LABEL]) {
-		if let Err(err) = systemctl_restart(&["--user", "--no-block", "restart", SERVICE_LABEL]) {
-			log::warn!("failed to restart user service {}: {err}", SERVICE_LABEL);
-		}
-		return;
+		return Some(systemctl_restart(&[
+			"--user",
+			"--no-block",
+			"restart",
+			SERVICE_LABEL,
+		]));
 	}
-
 	if systemctl_is_active(&["is-active", "--quiet", SERVICE_LABEL]) {
-		if let Err(err) = systemctl_restart(&["--no-block", "restart", SERVICE_LABEL]) {
-			log::warn!("failed to restart system service {}: {err}", SERVICE_LABEL);
-		}
-		return;
+		return Some(systemctl_restart(&["--no-block", "restart", SERVICE_LABEL]));
 	}
-
-	log::info!(
-		"service {} is not active; update installed without restart",
-		SERVICE_LABEL
-	);
+	None
 }
 
 #[cfg(not(target_os = "linux"))]
+fn restart_service() -> Option<anyhow::Result<()>> {
+	None
+}
+
+#[cfg(feature = "self-update")]
 fn restart_active_service() {
-	log::info!("automatic service restart is not supported on this platform");
+	match restart_service() {
+		Some(Ok(())) => {}
+		Some(Err(err)) => tracing::warn!("failed to restart service {}: {err}", SERVICE_LABEL),
+		None => tracing::info!(
+			"service {} is not active; update installed without restart",
+			SERVICE_LABEL
+		),
+	}
+}
+
+/// The binary this process was started from. After an update replaced it,
+/// Linux reports the old inode as `<path> (deleted)`; the new binary sits
+/// at the original path.
+fn current_binary() -> anyhow::Result<PathBuf> {
+	let exe = std::env::current_exe()?;
+	Ok(
+		match exe
+			.to_str()
+			.and_then(|path| path.strip_suffix(" (deleted)"))
+		{
+			Some(path) => PathBuf::from(path),
+			None => exe,
+		},
+	)
+}
+
+#[cfg(unix)]
+fn reexec() -> anyhow::Result<()> {
+	use std::os::unix::process::CommandExt;
+	let exe = current_binary()?;
+	tracing::info!("re-executing {}", exe.display());
+	// Only returns if the exec failed, leaving this process untouched.
+	let err = std::process::Command::new(&exe)
+		.args(std::env::args_os().skip(1))
+		.exec();
+	bail!("failed to exec {}: {err}", exe.display())
+}
+
+#[cfg(not(unix))]
+fn reexec() -> anyhow::Result<()> {
+	let exe = current_binary()?;
+	tracing::info!("restarting {}", exe.display());
+	std::process::Command::new(&exe)
+		.args(std::env::args_os().skip(1))
+		.spawn()
+		.map_err(|err| anyhow::anyhow!("failed to start {}: {err}", exe.display()))?;
+	std::process::exit(0)
 }
 
+/// Restarts puppynet the way it was launched: through systemd when it runs
+/// as a service, otherwise by re-executing the current binary with the same
+/// arguments. Returns an error, with this process still running, when the
+/// restart could not be started.
+pub(crate) fn restart_process() -> anyhow::Result<()> {
+	match restart_service() {
+		Some(result) => result,
+		None => reexec(),
+	}
+}
+
+#[cfg(feature = "self-update")]
 async fn fetch_release(version: Option<&str>) -> anyhow::Result<Value> {
-	let client = reqwest::Client::new();
+	let client = HttpClient::new()?;
 	let url = match version {
 		Some(tag) => format!(
 			"https://api.github.com/repos/j45k4/puppynet/releases/tags/{}",
@@ -161,32 +371,89 @@ async fn fetch_release(version: Option<&str>) -> anyhow::Result<Value> {
 		),
 		None => "https://api.github.com/repos/j45k4/puppynet/releases/latest".to_string(),
 	};
-	let res = client
-		.get(url)
-		.header("User-Agent", "puppynet")
-		.send()
-		.await?
-		.error_for_status()?;
+	let res = client.send(client.get(&url)).await?.error_for_status()?;
 	let body = res.text().await?;
 
 	Ok(serde_json::from_str::<Value>(&body)?)
 }
 
-async fn download_bin(url: &str, filename: &str) -> anyhow::Result<PathBuf> {
-	let res = reqwest::get(url).await?;
-	if !res.status().is_success() {
-		bail!("Failed to download asset. HTTP status: {}", res.status());
+/// Where a download of release asset `asset_id` is kept until it is
+/// complete, so a retried update resumes it instead of starting over.
+#[cfg(feature = "self-update")]
+fn partial_download_path(filename: &str, asset_id: u64) -> PathBuf {
+	app_dir().join(format!("{filename}.{asset_id}.part"))
+}
+
+#[cfg(feature = "self-update")]
+async fn download_bin(
+	url: &str,
+	filename: &str,
+	asset_id: u64,
+	progress: &impl Fn(UpdateProgress),
+) -> anyhow::Result<PathBuf> {
+	let partial = partial_download_path(filename, asset_id);
+	let have = tokio::fs::metadata(&partial)
+		.await
+		.map(|meta| meta.len())
+		.unwrap_or(0);
+	let client = HttpClient::new()?;
+	let mut request = client.get(url);
+	if have > 0 {
+		tracing::info!("resuming download of {filename} at byte {have}");
+		request = request.header(reqwest::header::RANGE, format!("bytes={have}-"));
+	}
+	let mut res = client.send(request).await?;
+	let mut file = match res.status() {
+		reqwest::StatusCode::PARTIAL_CONTENT => {
+			tokio::fs::OpenOptions::new()
+				.append(true)
+				.open(&partial)
+				.await?
+		}
+		// Everything is already here.
+		reqwest::StatusCode::RANGE_NOT_SATISFIABLE if have > 0 => {
+			let path = app_dir().join(filename);
+			tokio::fs::rename(&partial, &path).await?;
+			return Ok(path);
+		}
+		status if status.is_success() => File::create(&partial).await?,
+		status => bail!("Failed to download asset. HTTP status: {}", status),
+	};
+	let mut received = if res.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+		have
+	} else {
+		0
+	};
+	let total = res.content_length().map(|length| received + length);
+	let mut rate = RateEstimator::new();
+	let mut reported = Instant::now();
+	while let Some(chunk) = res.chunk().await? {
+		file.write_all(&chunk).await?;
+		received += chunk.len() as u64;
+		let now = Instant::now();
+		let throughput = rate.sample(now, received, total);
+		if now.duration_since(reported) >= DOWNLOAD_REPORT_INTERVAL {
+			reported = now;
+			progress(UpdateProgress::Downloading {
+				filename: filename.to_string(),
+				received: Some(received),
+				total,
+				bytes_per_sec: throughput.bytes_per_sec,
+				eta_secs: throughput.eta_secs,
+			});
+		}
 	}
-	let bytes = res.bytes().await?;
+	file.flush().await?;
+	drop(file);
 	let path = app_dir().join(filename);
-	let mut file = File::create(&path).await?;
-	file.write_all(&bytes).await?;
+	tokio::fs::rename(&partial, &path).await?;
 	Ok(path)
 }
 
 /// Perform update with progress callback.
 /// The callback receives UpdateProgress events during the update process.
 /// The callback must be Send + 'static to work across async boundaries.
+#[cfg(feature = "self-update")]
 pub async fn update_with_progress<F>(
 	version: Option<&str>,
 	current_version: u32,
@@ -204,16 +471,16 @@ where
 	};
 
 	if let Some(requested_tag) = version {
-		log::info!("requested tag: {}", requested_tag);
+		tracing::info!("requested tag: {}", requested_tag);
 	}
-	log::info!("current: {}", current_version);
-	log::info!("release tag: {}", tag);
+	tracing::info!("current: {}", current_version);
+	tracing::info!("release tag: {}", tag);
 
 	if version.is_none() {
 		if let Some(tag_number) = version::version_number_from_label(&tag) {
-			log::info!("latest release version number: {}", tag_number);
+			tracing::info!("latest release version number: {}", tag_number);
 			if tag_number <= current_version {
-				log::info!("Already up to date");
+				tracing::info!("Already up to date");
 				progress_callback(UpdateProgress::AlreadyUpToDate { current_version });
 				return Ok(UpdateResult {
 					success: true,
@@ -222,7 +489,7 @@ where
 				});
 			}
 		} else {
-			log::info!(
+			tracing::info!(
 				"latest release tag {} is not numeric or semantic; skipping automatic version comparison",
 				tag
 			);
@@ -250,21 +517,26 @@ where
 		.as_str()
 		.ok_or_else(|| anyhow::anyhow!("no download url found"))?;
 
-	log::info!("download_url: {}", download_url);
+	tracing::info!("download_url: {}", download_url);
 
 	let filename = asset["name"]
 		.as_str()
 		.map(|s| s.to_string())
 		.unwrap_or_else(|| "downloaded_binary".to_string());
 
-	log::info!("Downloading asset: {}", filename);
+	tracing::info!("Downloading asset: {}", filename);
 	progress_callback(UpdateProgress::Downloading {
 		filename: filename.clone(),
+		received: None,
+		total: None,
+		bytes_per_sec: None,
+		eta_secs: None,
 	});
 
-	let path = download_bin(download_url, &filename).await?;
+	let asset_id = asset["id"].as_u64().unwrap_or(0);
+	let path = download_bin(download_url, &filename, asset_id, &progress_callback).await?;
 
-	log::info!("Downloaded asset to: {:?}", path);
+	tracing::info!("Downloaded asset to: {:?}", path);
 
 	progress_callback(UpdateProgress::Unpacking);
 
@@ -278,7 +550,7 @@ where
 		if filename_clone.ends_with(".zip") {
 			// Extract ZIP archive (Windows)
 			// Flatten the archive - extract files directly to app_dir using only filename
-			log::info!("extracting ZIP archive");
+			tracing::info!("extracting ZIP archive");
 			let mut archive = ZipArchive::new(file)?;
 			for i in 0..archive.len() {
 				let mut entry = archive.by_index(i)?;
@@ -298,16 +570,16 @@ where
 					None => continue,
 				};
 
-				log::info!("unpacking: {:?} (from {:?})", file_name, full_path);
+				tracing::info!("unpacking: {:?} (from {:?})", file_name, full_path);
 				let dst = app_dir().join(file_name);
-				log::info!("unpacking to {:?}", dst);
+				tracing::info!("unpacking to {:?}", dst);
 
 				let mut outfile = std::fs::File::create(&dst)?;
 				std::io::copy(&mut entry, &mut outfile)?;
 			}
 		} else {
 			// Extract tar.gz archive (Linux/macOS)
-			log::info!("extracting tar.gz archive");
+			tracing::info!("extracting tar.gz archive");
 			let buf_reader = BufReader::new(file);
 			let decoder = GzDecoder::new(buf_reader);
 			let mut archive = Archive::new(decoder);
@@ -318,9 +590,9 @@ where
 					Ok(name) => name,
 					Err(_) => continue,
 				};
-				log::info!("unpacking: {:?}", name);
+				tracing::info!("unpacking: {:?}", name);
 				let dst = app_dir().join(name);
-				log::info!("unpacking to {:?}", dst);
+				tracing::info!("unpacking to {:?}", dst);
 				file.unpack(dst)?;
 			}
 		}
@@ -342,11 +614,11 @@ where
 	let entries: Vec<_> = std::fs::read_dir(app_dir())
 		.map(|rd| rd.filter_map(|e| e.ok().map(|e| e.file_name())).collect())
 		.unwrap_or_default();
-	log::info!("app_dir contents after extraction: {:?}", entries);
+	tracing::info!("app_dir contents after extraction: {:?}", entries);
 
 	// Check that binary exists
 	if !bin_path.exists() {
-		log::error!(
+		tracing::error!(
 			"Binary not found at {:?}, directory contains: {:?}",
 			bin_path,
 			entries
@@ -355,9 +627,7 @@ where
 			"Binary not found: {:?}. Directory contains: {:?}",
 			bin_path, entries
 		);
-		progress_callback(UpdateProgress::Failed {
-			error: error.clone(),
-		});
+		progress_callback(UpdateProgress::failed_permanently(error.clone()));
 		bail!("{}", error);
 	}
 
@@ -378,7 +648,7 @@ where
 
 	let sig_path = match sig_path {
 		Some(p) => {
-			log::info!("Found signature file: {:?}", p);
+			tracing::info!("Found signature file: {:?}", p);
 			p
 		}
 		None => {
@@ -386,9 +656,7 @@ where
 				"Signature file not found. Tried: {:?}, also searched for any .sig file. Directory contains: {:?}",
 				known_sig_names, entries
 			);
-			progress_callback(UpdateProgress::Failed {
-				error: error.clone(),
-			});
+			progress_callback(UpdateProgress::failed_permanently(error.clone()));
 			bail!("{}", error);
 		}
 	};
@@ -402,9 +670,7 @@ where
 
 	if !verify_result {
 		let error = "Signature verification failed".to_string();
-		progress_callback(UpdateProgress::Failed {
-			error: error.clone(),
-		});
+		progress_callback(UpdateProgress::failed_permanently(error.clone()));
 		bail!("{}", error);
 	}
 
@@ -425,7 +691,100 @@ where
 	})
 }
 
+/// Builds without the `self-update` feature leave updating to the package
+/// manager; every update fails permanently.
+#[cfg(not(feature = "self-update"))]
+pub async fn update_with_progress<F>(
+	_version: Option<&str>,
+	_current_version: u32,
+	progress_callback: F,
+) -> anyhow::Result<UpdateResult>
+where
+	F: Fn(UpdateProgress) + Send + 'static,
+{
+	let error = "this build of puppynet cannot update itself".to_string();
+	progress_callback(UpdateProgress::failed_permanently(error.clone()));
+	bail!("{}", error)
+}
+
 /// Perform update without progress callback (simple version).
 pub async fn update(version: Option<&str>, current_version: u32) -> anyhow::Result<UpdateResult> {
 	update_with_progress(version, current_version, |_| {}).await
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn network_errors_are_transient_and_release_problems_are_not() {
+		for error in [
+			"Failed to download asset. HTTP status: 503 Service Unavailable",
+			"Failed to download asset. HTTP status: 429 Too Many Requests",
+			"error sending request for url (https://github.com/j45k4/puppynet/releases/download/v42/puppynet-linux.tar.gz)",
+			"error decoding response body: connection reset by peer (os error 104)",
+			"HTTP status server error (502 Bad Gateway) for url (https://api.github.com/repos/j45k4/puppynet/releases/latest)",
+			"request failed: Timeout",
+			"request failed: Io(Kind(UnexpectedEof))",
+		] {
+			assert_eq!(
+				classify_update_error(error),
+				UpdateErrorKind::Transient,
+				"{error}"
+			);
+		}
+		for error in [
+			"Signature verification failed",
+			"no asset found for os: freebsd",
+			"Failed to download asset. HTTP status: 404 Not Found",
+			"release response missing tag_name",
+			"Signature file not found. Tried: [\"puppynet.sig\", \"puppynet.exe.sig\"], also searched for any .sig file. Directory contains: []",
+			"Binary not found: \"/home/me/.puppynet/puppynet\". Directory contains: []",
+			"HTTP status client error (404 Not Found) for url (https://api.github.com/repos/j45k4/puppynet/releases/tags/v0)",
+		] {
+			assert_eq!(
+				classify_update_error(error),
+				UpdateErrorKind::Permanent,
+				"{error}"
+			);
+		}
+	}
+
+	#[test]
+	fn retries_back_off_and_stop_at_the_limit() {
+		let policy = UpdateRetryPolicy {
+			max_attempts: 4,
+			initial_backoff: Duration::from_secs(5),
+			max_backoff: Duration::from_secs(12),
+		};
+		let transient = UpdateErrorKind::Transient;
+		assert_eq!(
+			policy.retry_after(1, transient),
+			Some(Duration::from_secs(5))
+		);
+		assert_eq!(
+			policy.retry_after(2, transient),
+			Some(Duration::from_secs(10))
+		);
+		assert_eq!(
+			policy.retry_after(3, transient),
+			Some(Duration::from_secs(12))
+		);
+		assert_eq!(policy.retry_after(4, transient), None);
+		assert_eq!(policy.retry_after(1, UpdateErrorKind::Permanent), None);
+	}
+
+	#[test]
+	fn failed_events_from_older_peers_still_parse() {
+		let event: UpdateProgress =
+			serde_json::from_str(r#"{"Failed":{"error":"request failed: Timeout"}}"#).unwrap();
+		match event {
+			UpdateProgress::Failed { error, kind } => {
+				assert_eq!(kind, None);
+				assert_eq!(classify_update_error(&error), UpdateErrorKind::Transient);
+			}
+			other => panic!("unexpected event {other:?}"),
+		}
+	}
+}
@@ -0,0 +1,317 @@
+//! Wake-on-LAN for peers that sleep. When a peer connects its interfaces
+//! are listed and the MAC address of the one it connected through is kept
+//! in the `peer_wake` table together with that interface's network. A
+//! machine with several NICs can leave the wrong address there, so the
+//! user may set it by hand, after which learned addresses no longer
+//! replace it.
+//!
+//! The magic packet goes to the broadcast address of the stored network
+//! and to the limited broadcast address, both on [`WAKE_PORT`]. Networks
+//! that drop broadcasts usually fail the send outright; that error is
+//! returned instead of waiting out [`WAKE_TIMEOUT`] for a peer that was
+//! never reached.
+
+use crate::db::load_wake_target;
+use crate::db_pool::Db;
+use crate::p2p::InterfaceInfo;
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// The discard port, which wake-on-LAN listens on by convention.
+pub const WAKE_PORT: u16 = 9;
+/// How long a woken peer gets to boot and connect.
+pub const WAKE_TIMEOUT: Duration = Duration::from_secs(180);
+pub(crate) const WAKE_POLL: Duration = Duration::from_secs(2);
+const SEND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Where to send the magic packet that wakes a peer.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct WakeTarget {
+	pub peer: String,
+	/// As `aa:bb:cc:dd:ee:ff`.
+	pub mac: String,
+	/// Network of the interface the peer last connected through, e.g.
+	/// `192.168.1.20/24`. `None` when it was not IPv4.
+	pub subnet: Option<String>,
+	/// Set by the user; learned addresses no longer replace it.
+	pub confirmed: bool,
+	pub updated_at: DateTime<Utc>,
+}
+
+/// A woken peer did not connect in time. Returned apart from other errors
+/// so callers can tell "asleep or unplugged" from "could not try".
+#[derive(Clone, Debug)]
+pub struct DidNotWake {
+	pub peer: PeerId,
+	pub waited: Duration,
+}
+
+impl std::fmt::Display for DidNotWake {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"peer {} did not wake within {}s",
+			self.peer,
+			self.waited.as_secs()
+		)
+	}
+}
+
+impl std::error::Error for DidNotWake {}
+
+/// Parses a MAC address written with `:` or `-` separators, or as twelve
+/// hex digits. The all-zero address loopback interfaces report is refused.
+pub fn parse_mac(value: &str) -> Result<[u8; 6]> {
+	let digits = value
+		.trim()
+		.chars()
+		.filter(|c| !matches!(c, ':' | '-'))
+		.collect::<String>();
+	if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+		bail!("{value:?} is not a MAC address");
+	}
+	let mut mac = [0u8; 6];
+	for (i, byte) in mac.iter_mut().enumerate() {
+		*byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+			.map_err(|_| anyhow!("{value:?} is not a MAC address"))?;
+	}
+	if mac == [0; 6] {
+		bail!("{value:?} is not a usable MAC address");
+	}
+	Ok(mac)
+}
+
+pub fn format_mac(mac: &[u8; 6]) -> String {
+	mac.iter()
+		.map(|byte| format!("{byte:02x}"))
+		.collect::<Vec<_>>()
+		.join(":")
+}
+
+/// Six `0xff` bytes followed by the MAC address sixteen times.
+pub fn magic_packet(mac: &[u8; 6]) -> [u8; 102] {
+	let mut packet = [0xff; 102];
+	for chunk in packet[6..].chunks_exact_mut(6) {
+		chunk.copy_from_slice(mac);
+	}
+	packet
+}
+
+fn parse_network(network: &str) -> Option<(IpAddr, u8)> {
+	let (ip, prefix) = network.split_once('/')?;
+	Some((ip.parse().ok()?, prefix.parse().ok()?))
+}
+
+/// Broadcast address of an IPv4 network such as `192.168.1.20/24`.
+fn broadcast_address(subnet: &str) -> Option<Ipv4Addr> {
+	let (IpAddr::V4(ip), prefix @ 0..=30) = parse_network(subnet)? else {
+		return None;
+	};
+	let host_bits = u32::MAX.checked_shr(prefix.into()).unwrap_or(0);
+	Some(Ipv4Addr::from(u32::from(ip) | host_bits))
+}
+
+pub(crate) fn multiaddr_ip(addr: &Multiaddr) -> Option<IpAddr> {
+	addr.iter().find_map(|protocol| match protocol {
+		Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+		Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+		_ => None,
+	})
+}
+
+/// MAC address and IPv4 network to wake a peer with, from its interfaces.
+/// The interface holding `remote_ip`, the address the peer connected from,
+/// wins; otherwise the first one with a MAC and a non-loopback IPv4
+/// network.
+pub(crate) fn learn_target(
+	interfaces: &[InterfaceInfo],
+	remote_ip: Option<IpAddr>,
+) -> Option<(String, Option<String>)> {
+	let ipv4_network = |iface: &InterfaceInfo| {
+		iface
+			.ips
+			.iter()
+			.find(|network| {
+				parse_network(network).is_some_and(|(ip, _)| ip.is_ipv4() && !ip.is_loopback())
+			})
+			.cloned()
+	};
+	let usable = || {
+		interfaces
+			.iter()
+			.filter_map(|iface| Some((iface, parse_mac(&iface.mac).ok()?)))
+	};
+	let connected = remote_ip.and_then(|remote_ip| {
+		usable().find(|(iface, _)| {
+			iface
+				.ips
+				.iter()
+				.any(|network| parse_network(network).is_some_and(|(ip, _)| ip == remote_ip))
+		})
+	});
+	let (iface, mac) =
+		connected.or_else(|| usable().find(|(iface, _)| ipv4_network(iface).is_some()))?;
+	Some((format_mac(&mac), ipv4_network(iface)))
+}
+
+/// Broadcasts the magic packet for `target`. Succeeds when at least one
+/// broadcast address took it and returns those addresses.
+pub(crate) fn send_magic_packet(target: &WakeTarget) -> Result<Vec<SocketAddr>> {
+	let packet = magic_packet(&parse_mac(&target.mac)?);
+	let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+		.map_err(|err| anyhow!("failed to open a UDP socket: {err}"))?;
+	socket
+		.set_broadcast(true)
+		.map_err(|err| anyhow!("broadcast is not allowed here: {err}"))?;
+	socket.set_write_timeout(Some(SEND_TIMEOUT))?;
+	let mut destinations = target
+		.subnet
+		.as_deref()
+		.and_then(broadcast_address)
+		.into_iter()
+		.collect::<Vec<_>>();
+	destinations.push(Ipv4Addr::BROADCAST);
+	let mut sent = Vec::new();
+	let mut last_error = None;
+	for ip in destinations {
+		let addr = SocketAddr::from((ip, WAKE_PORT));
+		match socket.send_to(&packet, addr) {
+			Ok(_) => sent.push(addr),
+			Err(err) => {
+				tracing::debug!("wake packet to {addr} failed: {err}");
+				last_error = Some(err);
+			}
+		}
+	}
+	match last_error {
+		Some(err) if sent.is_empty() => bail!("failed to broadcast wake packet: {err}"),
+		_ => Ok(sent),
+	}
+}
+
+/// Sends the magic packet for `peer` to the target stored for it.
+pub(crate) fn send_wake(db: &Db, peer: PeerId) -> Result<WakeTarget> {
+	let target = {
+		let conn = db
+			.lock()
+			.map_err(|err| anyhow!("db lock poisoned: {err}"))?;
+		load_wake_target(&conn, &peer)?
+	};
+	let Some(target) = target else {
+		bail!(
+			"no MAC address known for {peer}; it is learned once the peer connects, or can be set by hand"
+		);
+	};
+	send_magic_packet(&target)?;
+	Ok(target)
+}
+
+/// Polls `is_connected` every [`WAKE_POLL`] until it holds, returning how
+/// long that took, or gives up after `timeout`.
+pub(crate) async fn wait_until_connected<F, Fut>(
+	peer: PeerId,
+	timeout: Duration,
+	mut is_connected: F,
+) -> Result<Duration, DidNotWake>
+where
+	F: FnMut() -> Fut,
+	Fut: std::future::Future<Output = bool>,
+{
+	let started = Instant::now();
+	loop {
+		if is_connected().await {
+			return Ok(started.elapsed());
+		}
+		if started.elapsed() >= timeout {
+			return Err(DidNotWake {
+				peer,
+				waited: timeout,
+			});
+		}
+		tokio::time::sleep(WAKE_POLL).await;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn iface(mac: &str, ips: &[&str]) -> InterfaceInfo {
+		InterfaceInfo {
+			name: String::from("eth"),
+			mac: mac.to_string(),
+			ips: ips.iter().map(|ip| ip.to_string()).collect(),
+			total_received: 0,
+			total_transmitted: 0,
+			packets_received: 0,
+			packets_transmitted: 0,
+			errors_on_received: 0,
+			errors_on_transmitted: 0,
+			mtu: 1500,
+		}
+	}
+
+	#[test]
+	fn macs_parse_in_common_notations() {
+		let mac = [0x00, 0x1b, 0x21, 0xaa, 0xbc, 0x0f];
+		for written in ["00:1b:21:aa:bc:0f", "00-1B-21-AA-BC-0F", "001b21aabc0f"] {
+			assert_eq!(parse_mac(written).unwrap(), mac);
+		}
+		assert_eq!(format_mac(&mac), "00:1b:21:aa:bc:0f");
+		assert!(parse_mac("00:00:00:00:00:00").is_err());
+		assert!(parse_mac("00:1b:21:aa:bc").is_err());
+		assert!(parse_mac("zz:1b:21:aa:bc:0f").is_err());
+	}
+
+	#[test]
+	fn magic_packet_repeats_the_mac_after_a_sync_stream() {
+		let mac = [1, 2, 3, 4, 5, 6];
+		let packet = magic_packet(&mac);
+		assert_eq!(packet[..6], [0xff; 6]);
+		assert!(packet[6..].chunks(6).all(|chunk| chunk == mac));
+		assert_eq!(packet[6..].chunks(6).count(), 16);
+	}
+
+	#[test]
+	fn broadcast_address_covers_the_host_bits() {
+		assert_eq!(
+			broadcast_address("192.168.1.20/24"),
+			Some(Ipv4Addr::new(192, 168, 1, 255))
+		);
+		assert_eq!(
+			broadcast_address("10.1.2.3/12"),
+			Some(Ipv4Addr::new(10, 15, 255, 255))
+		);
+		assert_eq!(broadcast_address("10.1.2.3/32"), None);
+		assert_eq!(broadcast_address("fe80::1/64"), None);
+	}
+
+	#[test]
+	fn learned_target_prefers_the_interface_the_peer_connected_through() {
+		let interfaces = [
+			iface("00:00:00:00:00:00", &["127.0.0.1/8"]),
+			iface("aa:aa:aa:aa:aa:01", &["172.17.0.1/16"]),
+			iface("AA:AA:AA:AA:AA:02", &["192.168.1.20/24", "fe80::2/64"]),
+		];
+		assert_eq!(
+			learn_target(&interfaces, Some("192.168.1.20".parse().unwrap())),
+			Some((
+				String::from("aa:aa:aa:aa:aa:02"),
+				Some(String::from("192.168.1.20/24"))
+			))
+		);
+		assert_eq!(
+			learn_target(&interfaces, Some("203.0.113.7".parse().unwrap())),
+			Some((
+				String::from("aa:aa:aa:aa:aa:01"),
+				Some(String::from("172.17.0.1/16"))
+			))
+		);
+		assert_eq!(learn_target(&interfaces[..1], None), None);
+	}
+}
@@ -147,8 +147,8 @@ pub(crate) fn microphone_device(source_id: &str) -> Result<(cpal::Device, Suppor
 		let rest = source_id
 			.strip_prefix("microphone:")
 			.ok_or_else(|| anyhow!("invalid microphone source"))?;
-		let id = DeviceId::from_str(rest)
-			.map_err(|err| anyhow!("invalid microphone source: {err}"))?;
+		let id =
+			DeviceId::from_str(rest).map_err(|err| anyhow!("invalid microphone source: {err}"))?;
 		host.device_by_id(&id)
 	}
 	.ok_or_else(|| anyhow!("No microphone input device is available for {source_id}"))?;
@@ -230,11 +230,11 @@ async fn cosmic_screen_available() -> bool {
 	{
 		Ok(Ok(available)) => available,
 		Ok(Err(err)) => {
-			log::warn!("COSMIC screen capture availability task failed: {err}");
+			tracing::warn!("COSMIC screen capture availability task failed: {err}");
 			false
 		}
 		Err(_) => {
-			log::warn!("timed out checking COSMIC screen capture availability");
+			tracing::warn!("timed out checking COSMIC screen capture availability");
 			false
 		}
 	}
@@ -561,11 +561,13 @@ async fn capture_screen_frame(source_id: String) -> Result<MediaFrame> {
 			Ok(frame) => Ok(frame),
 			Err(cosmic_err) => {
 				if command_available("grim", "-h").await {
-					log::warn!("COSMIC screen capture failed, falling back to grim: {cosmic_err}");
+					tracing::warn!(
+						"COSMIC screen capture failed, falling back to grim: {cosmic_err}"
+					);
 					return capture_external_screen_frame(ScreenCaptureCommand::Grim).await;
 				}
 				if command_available("import", "-version").await {
-					log::warn!(
+					tracing::warn!(
 						"COSMIC screen capture failed, falling back to ImageMagick import: {cosmic_err}"
 					);
 					return capture_external_screen_frame(ScreenCaptureCommand::Import).await;
@@ -0,0 +1,1217 @@
+//! Decoding of the peer protocol enums that survives messages from newer
+//! nodes. `PeerReq` and `PeerRes` derive their serde impls with
+//! `#[serde(remote = "Self")]`, which turns them into inherent functions;
+//! the trait impls wrap those so a variant this build doesn't know decodes
+//! as `Unknown(name)` instead of failing the whole substream.
+//!
+//! Only the variant name is looked at before handing over to the derived
+//! code, so known messages are decoded in one pass without buffering.
+
+use serde::de::value::{Error as ValueError, StrDeserializer};
+use serde::de::{
+	self, DeserializeSeed, Deserializer, EnumAccess, IgnoredAny, IntoDeserializer, MapAccess,
+	VariantAccess, Visitor,
+};
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A message enum encoded externally tagged, with a catch-all variant for
+/// names it doesn't know.
+pub(crate) trait TolerantEnum: Sized {
+	/// The derived decoding, which rejects unknown variants.
+	fn deserialize_known<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>;
+	fn unknown(name: String) -> Self;
+	/// Names of the variants `deserialize_known` accepts.
+	fn variants() -> &'static [&'static str];
+}
+
+/// Reads the variant names the derived code passes to `deserialize_enum`
+/// and then gives up.
+struct VariantProbe<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for VariantProbe<'_> {
+	type Error = ValueError;
+
+	fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ValueError> {
+		Err(de::Error::custom("not an enum"))
+	}
+
+	fn deserialize_enum<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		variants: &'static [&'static str],
+		_visitor: V,
+	) -> Result<V::Value, ValueError> {
+		*self.0 = variants;
+		Err(de::Error::custom("variant names read"))
+	}
+
+	serde::forward_to_deserialize_any! {
+		bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+		bytes byte_buf option unit unit_struct newtype_struct seq tuple
+		tuple_struct map struct identifier ignored_any
+	}
+}
+
+pub(crate) fn variant_names<T: TolerantEnum>() -> &'static [&'static str] {
+	let mut names: &'static [&'static str] = &[];
+	let _ = T::deserialize_known(VariantProbe(&mut names));
+	names
+}
+
+/// The rest of a `{"Variant": body}` map whose key was already read.
+struct KnownVariant<'a, A> {
+	name: String,
+	map: &'a mut A,
+}
+
+impl<'de, A: MapAccess<'de>> Deserializer<'de> for KnownVariant<'_, A> {
+	type Error = A::Error;
+
+	fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, A::Error> {
+		visitor.visit_enum(self)
+	}
+
+	serde::forward_to_deserialize_any! {
+		bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+		bytes byte_buf option unit unit_struct newtype_struct seq tuple
+		tuple_struct map struct enum identifier ignored_any
+	}
+}
+
+impl<'de, A: MapAccess<'de>> EnumAccess<'de> for KnownVariant<'_, A> {
+	type Error = A::Error;
+	type Variant = Self;
+
+	fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), A::Error> {
+		let name: StrDeserializer<'_, A::Error> = self.name.as_str().into_deserializer();
+		let variant = seed.deserialize(name)?;
+		Ok((variant, self))
+	}
+}
+
+impl<'de, A: MapAccess<'de>> VariantAccess<'de> for KnownVariant<'_, A> {
+	type Error = A::Error;
+
+	fn unit_variant(self) -> Result<(), A::Error> {
+		self.map.next_value()
+	}
+
+	fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, A::Error> {
+		self.map.next_value_seed(seed)
+	}
+
+	fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, A::Error> {
+		self.map.next_value_seed(TupleBody { len, visitor })
+	}
+
+	fn struct_variant<V: Visitor<'de>>(
+		self,
+		fields: &'static [&'static str],
+		visitor: V,
+	) -> Result<V::Value, A::Error> {
+		self.map.next_value_seed(StructBody { fields, visitor })
+	}
+}
+
+struct TupleBody<V> {
+	len: usize,
+	visitor: V,
+}
+
+impl<'de, V: Visitor<'de>> DeserializeSeed<'de> for TupleBody<V> {
+	type Value = V::Value;
+
+	fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<V::Value, D::Error> {
+		deserializer.deserialize_tuple(self.len, self.visitor)
+	}
+}
+
+struct StructBody<V> {
+	fields: &'static [&'static str],
+	visitor: V,
+}
+
+impl<'de, V: Visitor<'de>> DeserializeSeed<'de> for StructBody<V> {
+	type Value = V::Value;
+
+	fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<V::Value, D::Error> {
+		deserializer.deserialize_struct("", self.fields, self.visitor)
+	}
+}
+
+struct TolerantVisitor<T>(PhantomData<T>);
+
+impl<'de, T: TolerantEnum> Visitor<'de> for TolerantVisitor<T> {
+	type Value = T;
+
+	fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("a variant name or a map with a single variant")
+	}
+
+	fn visit_str<E: de::Error>(self, name: &str) -> Result<T, E> {
+		if T::variants().contains(&name) {
+			let name: StrDeserializer<'_, E> = name.into_deserializer();
+			T::deserialize_known(name)
+		} else {
+			Ok(T::unknown(name.to_string()))
+		}
+	}
+
+	fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<T, A::Error> {
+		let Some(name) = map.next_key::<String>()? else {
+			return Err(de::Error::invalid_length(0, &self));
+		};
+		let message = if T::variants().contains(&name.as_str()) {
+			T::deserialize_known(KnownVariant {
+				name,
+				map: &mut map,
+			})?
+		} else {
+			map.next_value::<IgnoredAny>()?;
+			T::unknown(name)
+		};
+		if map.next_key::<IgnoredAny>()?.is_some() {
+			return Err(de::Error::invalid_length(2, &self));
+		}
+		Ok(message)
+	}
+}
+
+pub(crate) fn deserialize_tolerant<'de, T: TolerantEnum, D: Deserializer<'de>>(
+	deserializer: D,
+) -> Result<T, D::Error> {
+	deserializer.deserialize_any(TolerantVisitor(PhantomData))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::contact_sheet::{ContactSheet, ContactSheetResult};
+	use crate::db::{FileEntry, FileOrigin, FileSearchResult, SearchFilesArgs, SearchSortBy};
+	use crate::deletion::DeleteOutcome;
+	use crate::dialer::PeerDialStats;
+	use crate::disk_history::DiskSample;
+	use crate::image_decode::ImageTooLarge;
+	use crate::keepalive::PeerChurn;
+	use crate::locations::{FolderKind, WellKnownFolder};
+	use crate::p2p::*;
+	use crate::pairing_invite::PairingRejected;
+	use crate::password_policy::{CreateUserError, UserFieldError};
+	use crate::replication::{IndexDelta, IndexDeltaAck, ReplicatedEntry, ReplicatedLocation};
+	use crate::scan::{ScanEvent, ScanProgress, ScanResult};
+	use crate::scan_limits::{ScanLimits, ScanOverrides};
+	use crate::scan_results::ScanResults;
+	use crate::share_summary::{MimeCategory, ShareSummary};
+	use crate::state::{
+		AccessExplanation, FolderRule, LapsedAccess, Permission, Rule, TrustLevel, TrustRefusal,
+	};
+	use crate::types::FileChunk;
+	use crate::updater::{UpdateErrorKind, UpdateProgress};
+	use chrono::{DateTime, Utc};
+	use serde::Serialize;
+	use serde::de::DeserializeOwned;
+	use serde_json::{Value, json};
+	use std::collections::BTreeSet;
+	use std::path::{Path, PathBuf};
+	use std::time::Duration;
+
+	const REQUEST_FIXTURES: &str = "tests/fixtures/peer_protocol/requests.json";
+	const RESPONSE_FIXTURES: &str = "tests/fixtures/peer_protocol/responses.json";
+	/// Set to append fixtures for variants that have none yet. Existing
+	/// fixtures are never rewritten: they are what older nodes send.
+	const BLESS_ENV: &str = "PUPPYNET_BLESS_FIXTURES";
+
+	/// Deterministic xorshift source for the round-trip property test.
+	struct Gen(u64);
+
+	impl Gen {
+		fn new(seed: u64) -> Self {
+			Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+		}
+
+		fn next(&mut self) -> u64 {
+			self.0 ^= self.0 << 13;
+			self.0 ^= self.0 >> 7;
+			self.0 ^= self.0 << 17;
+			self.0
+		}
+
+		fn below(&mut self, n: u64) -> u64 {
+			self.next() % n
+		}
+
+		fn bool(&mut self) -> bool {
+			self.next() & 1 == 1
+		}
+
+		fn string(&mut self) -> String {
+			const PIECES: [&str; 8] = ["a", "Ä", "/", "\\", "\"", " ", "😀", "\n"];
+			(0..self.below(8))
+				.map(|_| PIECES[self.below(8) as usize])
+				.collect()
+		}
+
+		fn opt_string(&mut self) -> Option<String> {
+			if self.bool() {
+				Some(self.string())
+			} else {
+				None
+			}
+		}
+
+		fn strings(&mut self) -> Vec<String> {
+			(0..self.below(3)).map(|_| self.string()).collect()
+		}
+
+		fn bytes(&mut self) -> Vec<u8> {
+			(0..self.below(16)).map(|_| self.next() as u8).collect()
+		}
+
+		fn time(&mut self) -> DateTime<Utc> {
+			DateTime::from_timestamp(self.below(4_000_000_000) as i64, 0).unwrap()
+		}
+
+		fn ratio(&mut self) -> f32 {
+			self.below(800) as f32 / 8.0
+		}
+
+		fn grants(&mut self) -> Vec<PermissionGrant> {
+			let mut grants = vec![PermissionGrant::Owner, PermissionGrant::Viewer];
+			grants.push(PermissionGrant::Files {
+				path: self.string(),
+				access: if self.bool() {
+					FileAccess::Read
+				} else {
+					FileAccess::ReadWrite
+				},
+			});
+			grants.extend([
+				PermissionGrant::Inbox,
+				PermissionGrant::SystemInfo,
+				PermissionGrant::DiskInfo,
+				PermissionGrant::NetworkInfo,
+			]);
+			grants.truncate(self.below(8) as usize);
+			grants
+		}
+
+		fn permissions(&mut self) -> Vec<Permission> {
+			vec![
+				Permission::new(Rule::Owner),
+				Permission::new(Rule::Folder(FolderRule::new(
+					PathBuf::from(self.string()),
+					self.next() as u8,
+				))),
+				Permission::new(Rule::Inbox),
+			]
+		}
+
+		fn dir_entry(&mut self) -> DirEntry {
+			DirEntry {
+				name: self.string(),
+				name_raw: self.bytes(),
+				is_dir: self.bool(),
+				extension: self.opt_string(),
+				mime: self.opt_string(),
+				mime_source: MimeSource::Index,
+				size: self.next(),
+				disk_size: self.bool().then(|| self.next()),
+				created_at: self.bool().then_some(DateTime::UNIX_EPOCH),
+				modified_at: Some(self.time()),
+				accessed_at: None,
+				hidden: self.bool(),
+			}
+		}
+	}
+
+	/// One of every request, with values from `g`.
+	fn requests(g: &mut Gen) -> Vec<PeerReq> {
+		vec![
+			PeerReq::PeerInfo,
+			PeerReq::ListDir {
+				path: WirePath::from(g.string()),
+			},
+			PeerReq::StatFile {
+				path: WirePath::Raw(g.bytes()),
+			},
+			PeerReq::ReadFile {
+				path: WirePath::from(g.string()),
+				offset: g.next(),
+				length: g.bool().then(|| g.next()),
+				sparse: g.bool(),
+			},
+			PeerReq::WriteFile {
+				path: WirePath::from(g.string()),
+				offset: g.next(),
+				data: g.bytes(),
+			},
+			PeerReq::ListCpus,
+			PeerReq::ListDisks,
+			PeerReq::ListRoots,
+			PeerReq::WellKnownFolders,
+			PeerReq::ListInterfaces,
+			PeerReq::AudioCapability,
+			PeerReq::ListAudioDevices,
+			PeerReq::SetAudioMuted {
+				device_id: g.opt_string(),
+				muted: g.bool(),
+			},
+			PeerReq::SetAudioVolume {
+				device_id: g.opt_string(),
+				volume: g.next() as u8,
+			},
+			PeerReq::SetDefaultAudioDevice {
+				device_id: g.string(),
+			},
+			PeerReq::MediaCapability,
+			PeerReq::ListMediaSources,
+			PeerReq::GetMediaFrame {
+				source_id: g.string(),
+			},
+			PeerReq::StartScan {
+				id: g.next(),
+				path: g.string(),
+				limits: ScanOverrides {
+					max_reads: g.bool().then(|| g.below(64) as u32),
+					max_read_mib: g.bool().then(|| g.next() as u32),
+					low_priority: g.bool().then(|| g.bool()),
+				},
+			},
+			PeerReq::FileEntries {
+				offset: g.next(),
+				limit: g.next(),
+			},
+			PeerReq::StartSearch {
+				id: g.next(),
+				args: LiveSearchArgs {
+					name_query: g.opt_string(),
+					mime_types: g.strings(),
+					sort: [SearchSort::Latest, SearchSort::Name, SearchSort::Size]
+						[g.below(3) as usize],
+					sort_desc: g.bool(),
+					page: g.below(1000) as usize,
+					page_size: g.below(1000) as usize,
+				},
+			},
+			PeerReq::SearchEvent {
+				id: g.next(),
+				event: match g.below(4) {
+					0 => SearchEvent::Progress {
+						visited: g.below(1000) as usize,
+						matched: g.below(1000) as usize,
+					},
+					1 => SearchEvent::Rows {
+						rows: vec![LiveSearchRow {
+							name: g.string(),
+							path: g.string(),
+							size: g.next(),
+							mime_type: g.opt_string(),
+							modified_at: g.opt_string(),
+						}],
+					},
+					2 => SearchEvent::Finished {
+						total: g.below(1000) as usize,
+						truncated: g.bool(),
+					},
+					_ => SearchEvent::Failed { error: g.string() },
+				},
+			},
+			PeerReq::ScanEvent {
+				id: g.next(),
+				event: match g.below(4) {
+					0 => ScanEvent::Deferred { until: g.string() },
+					1 => ScanEvent::Progress(ScanProgress {
+						total_files: g.below(1000) as usize,
+						processed_files: g.below(1000) as usize,
+						inserted_count: g.next(),
+						updated_count: g.next(),
+						removed_count: g.next(),
+						bytes_per_sec: g.bool().then(|| g.next()),
+						eta_secs: g.bool().then(|| g.next()),
+						limits: g.bool().then(|| ScanLimits {
+							max_reads: g.below(64) as u32 + 1,
+							max_read_mib: g.next() as u32,
+							low_priority: g.bool(),
+						}),
+					}),
+					2 => ScanEvent::Finished(Ok(ScanResult {
+						updated_count: g.next(),
+						inserted_count: g.next(),
+						removed_count: g.next(),
+						duration: Duration::from_millis(g.below(1 << 40)),
+						file_count: g.next(),
+						checksums: None,
+						run: g.bool().then(|| g.next() as i64),
+						changes: Vec::new(),
+					})),
+					_ => ScanEvent::Finished(Err(g.string())),
+				},
+			},
+			PeerReq::Authenticate {
+				method: if g.bool() {
+					AuthMethod::Token { token: g.string() }
+				} else {
+					AuthMethod::Credentials {
+						username: g.string(),
+						password: g.string(),
+					}
+				},
+			},
+			PeerReq::CreateUser {
+				username: g.string(),
+				password: g.string(),
+				roles: g.strings(),
+				permissions: g.grants(),
+			},
+			PeerReq::CreateToken {
+				username: g.string(),
+				label: g.opt_string(),
+				expires_in: g.bool().then(|| g.next()),
+				permissions: g.grants(),
+			},
+			PeerReq::GrantAccess {
+				username: g.string(),
+				permissions: g.grants(),
+				merge: g.bool(),
+			},
+			PeerReq::ListUsers,
+			PeerReq::ListTokens {
+				username: g.opt_string(),
+			},
+			PeerReq::RevokeToken {
+				token_id: g.string(),
+			},
+			PeerReq::RevokeUser {
+				username: g.string(),
+			},
+			PeerReq::ListPermissions,
+			PeerReq::GetThumbnail {
+				path: g.string(),
+				max_width: g.next() as u32,
+				max_height: g.next() as u32,
+			},
+			PeerReq::UpdateSelf {
+				id: g.next(),
+				version: g.opt_string(),
+			},
+			PeerReq::UpdateEvent {
+				id: g.next(),
+				event: match g.below(10) {
+					0 => UpdateProgress::Deferred { until: g.string() },
+					1 => UpdateProgress::FetchingRelease,
+					2 => UpdateProgress::Downloading {
+						filename: g.string(),
+						received: g.bool().then(|| g.next()),
+						total: g.bool().then(|| g.next()),
+						bytes_per_sec: g.bool().then(|| g.next()),
+						eta_secs: g.bool().then(|| g.next()),
+					},
+					3 => UpdateProgress::Unpacking,
+					4 => UpdateProgress::Verifying,
+					5 => UpdateProgress::Installing,
+					6 => UpdateProgress::Completed {
+						version: g.string(),
+					},
+					7 => UpdateProgress::Failed {
+						error: g.string(),
+						kind: g.bool().then_some(UpdateErrorKind::Transient),
+					},
+					8 => UpdateProgress::AlreadyUpToDate {
+						current_version: g.next() as u32,
+					},
+					_ => UpdateProgress::Retrying {
+						attempt: g.next() as u32,
+						max_attempts: g.next() as u32,
+						delay_secs: g.next(),
+						error: g.string(),
+					},
+				},
+			},
+			PeerReq::StartShell { id: g.next() },
+			PeerReq::ShellInput {
+				id: g.next(),
+				data: g.bytes(),
+			},
+			PeerReq::CloseShell { id: g.next() },
+			PeerReq::DesktopInput {
+				input: match g.below(7) {
+					0 => DesktopInput::MouseMove {
+						dx: g.next() as i32,
+						dy: g.next() as i32,
+					},
+					1 => DesktopInput::MouseScroll {
+						amount: g.next() as i32,
+					},
+					2 => DesktopInput::MouseClick {
+						button: MouseButton::Left,
+					},
+					3 => DesktopInput::MousePress {
+						button: MouseButton::Middle,
+					},
+					4 => DesktopInput::MouseRelease {
+						button: MouseButton::Right,
+					},
+					5 => DesktopInput::KeyboardText { text: g.string() },
+					_ => DesktopInput::KeyboardKey { key: g.string() },
+				},
+			},
+			PeerReq::PermissionsChanged {
+				permissions: g.permissions(),
+			},
+			PeerReq::OpenInbox {
+				name: g.string(),
+				size: g.next(),
+			},
+			PeerReq::Hello {
+				protocol_version: g.next() as u32,
+				features: g.strings(),
+			},
+			PeerReq::Restart {
+				delay_secs: g.next(),
+			},
+			PeerReq::HealthCheck,
+			PeerReq::DiskHistory {
+				mount: g.string(),
+				from: g.time(),
+				to: g.time(),
+			},
+			PeerReq::PairRequest {
+				node_name: g.string(),
+			},
+			PeerReq::PairResponse { accepted: g.bool() },
+			PeerReq::Pair {
+				secret: g.bytes(),
+				requested_permissions: g.permissions(),
+			},
+			PeerReq::Traced {
+				corr: g.next(),
+				request: Box::new(PeerReq::ListDir {
+					path: WirePath::from(g.string()),
+				}),
+			},
+			PeerReq::SearchFiles {
+				args: SearchFilesArgs {
+					name_query: g.opt_string(),
+					mime_types: g.strings(),
+					node_id: g.bool().then_some([g.next() as u8; 16]),
+					path_prefix: g.opt_string(),
+					min_duration: g.bool().then(|| g.next()),
+					sort_by: if g.bool() {
+						SearchSortBy::Name
+					} else {
+						SearchSortBy::Date
+					},
+					sort_desc: g.bool(),
+					page: g.below(100) as usize,
+					page_size: g.below(500) as usize,
+					..Default::default()
+				},
+			},
+			PeerReq::DialBack { addr: g.string() },
+			PeerReq::IndexDelta {
+				delta: IndexDelta {
+					generation: g.string(),
+					after: g.next(),
+					through: g.next(),
+					entries: vec![ReplicatedEntry {
+						hash: g.bytes(),
+						size: g.next() as i64,
+						mime_type: g.opt_string(),
+						first_datetime: g.opt_string(),
+						latest_datetime: g.opt_string(),
+					}],
+					locations: vec![ReplicatedLocation {
+						path: WirePath::from(g.string()),
+						hash: g.bool().then(|| g.bytes()),
+						size: g.next(),
+						timestamp: g.time(),
+						created_at: g.bool().then(|| g.time()),
+						modified_at: g.bool().then(|| g.time()),
+						accessed_at: g.bool().then(|| g.time()),
+						deleted_at: g.bool().then(|| g.next() as i64),
+						origin: FileOrigin::LocalScan,
+						origin_peer: g.opt_string(),
+						origin_run: g.bool().then(|| g.next() as i64),
+						origin_transfer: g.opt_string(),
+						origin_user: g.opt_string(),
+						introduced_at: g.bool().then(|| g.next() as i64),
+					}],
+					remaining: g.next(),
+				},
+			},
+			PeerReq::IndexAnnounce {
+				node_id: g.string(),
+				added: g.next(),
+				updated: g.next(),
+				removed: g.next(),
+				high_water_mark: g.next(),
+			},
+			PeerReq::IndexPull {
+				generation: g.string(),
+				after: g.next(),
+			},
+			PeerReq::HaveHashes {
+				hashes: vec![[g.next() as u8; 32], [g.next() as u8; 32]],
+			},
+			PeerReq::HaveBlocks {
+				hash: [g.next() as u8; 32],
+				block_size: g.next(),
+				blocks: vec![[g.next() as u8; 32]],
+			},
+			PeerReq::WriteKnownBlock {
+				path: g.string(),
+				offset: g.next(),
+				block_hash: [g.next() as u8; 32],
+			},
+			PeerReq::WriteInbox {
+				path: g.string(),
+				offset: g.next(),
+				data: g.bytes(),
+			},
+			PeerReq::ShareSummary,
+			PeerReq::DeleteProposal {
+				id: g.string(),
+				hash: [g.next() as u8; 32],
+				paths_hint: g.strings(),
+				reason: g.string(),
+				expires_at: g.time(),
+			},
+			PeerReq::DeleteOutcome {
+				id: g.string(),
+				outcome: DeleteOutcome::Deleted { removed: g.next() },
+			},
+			PeerReq::ContactSheet {
+				path: g.string(),
+				columns: g.next() as u32,
+				cell_size: g.next() as u32,
+				max_items: g.next() as u32,
+			},
+			PeerReq::ScanResultsPull {
+				run: g.next() as i64,
+				cursor: g.next(),
+			},
+		]
+	}
+
+	/// One of every response, with values from `g`.
+	fn responses(g: &mut Gen) -> Vec<PeerRes> {
+		vec![
+			PeerRes::PeerInfo(PeerInfo {
+				version: g.string(),
+				os: g.string(),
+				uptime_seconds: g.next(),
+			}),
+			PeerRes::DirEntries(vec![g.dir_entry(), g.dir_entry()]),
+			PeerRes::FileStat(g.dir_entry()),
+			PeerRes::FileChunk(FileChunk {
+				offset: g.next(),
+				data: g.bytes(),
+				eof: g.bool(),
+				hole: g.next(),
+			}),
+			PeerRes::WriteAck(FileWriteAck {
+				bytes_written: g.next(),
+			}),
+			PeerRes::Cpus(vec![CpuInfo {
+				name: g.string(),
+				usage: g.ratio(),
+				frequency_hz: g.next(),
+			}]),
+			PeerRes::Disks(vec![DiskInfo {
+				name: g.string(),
+				mount_path: g.string(),
+				filesystem: g.string(),
+				total_space: g.next(),
+				available_space: g.next(),
+				usage_percent: g.ratio(),
+				total_read_bytes: g.next(),
+				total_written_bytes: g.next(),
+				read_only: g.bool(),
+				removable: g.bool(),
+				kind: g.string(),
+				id: g.string(),
+			}]),
+			PeerRes::Roots(BrowseRoots {
+				windows: g.bool(),
+				roots: vec![BrowseRoot {
+					label: g.string(),
+					path: g.string(),
+					kind: if g.bool() {
+						BrowseRootKind::Disk
+					} else {
+						BrowseRootKind::SharedFolder
+					},
+					flags: g.bool().then(|| g.next() as u8),
+				}],
+			}),
+			PeerRes::WellKnownFolders(vec![WellKnownFolder {
+				kind: FolderKind::Downloads,
+				label: g.string(),
+				path: g.string(),
+			}]),
+			PeerRes::Interfaces(vec![InterfaceInfo {
+				name: g.string(),
+				mac: g.string(),
+				ips: g.strings(),
+				total_received: g.next(),
+				total_transmitted: g.next(),
+				packets_received: g.next(),
+				packets_transmitted: g.next(),
+				errors_on_received: g.next(),
+				errors_on_transmitted: g.next(),
+				mtu: g.next(),
+			}]),
+			PeerRes::AudioCapability(AudioCapability {
+				supported: g.bool(),
+				backend: g.opt_string(),
+				message: g.string(),
+			}),
+			PeerRes::AudioDevices(vec![AudioDevice {
+				id: g.string(),
+				name: g.string(),
+				description: g.string(),
+				kind: if g.bool() {
+					AudioDeviceKind::Sink
+				} else {
+					AudioDeviceKind::Source
+				},
+				volume: g.next() as u8,
+				muted: g.bool(),
+				is_default: g.bool(),
+			}]),
+			PeerRes::MediaCapability(MediaCapability {
+				supported: g.bool(),
+				backend: g.opt_string(),
+				message: g.string(),
+			}),
+			PeerRes::MediaSources(vec![MediaSource {
+				id: g.string(),
+				name: g.string(),
+				kind: MediaSourceKind::Screen,
+				live: g.bool(),
+				outputs: vec![MediaOutput {
+					transport: MediaTransport::WebRtc,
+					mime: g.string(),
+					codec: g.opt_string(),
+				}],
+			}]),
+			PeerRes::MediaFrame(MediaFrame {
+				mime: g.string(),
+				data: g.bytes(),
+			}),
+			PeerRes::FileEntries(vec![FileEntry {
+				hash: [g.next() as u8; 32],
+				size: g.next() as i64,
+				mime_type: g.opt_string(),
+				first_datetime: g.string(),
+				latest_datetime: g.string(),
+			}]),
+			PeerRes::SearchStarted(if g.bool() { Ok(()) } else { Err(g.string()) }),
+			PeerRes::SearchEventAck,
+			PeerRes::ScanStarted(if g.bool() { Ok(()) } else { Err(g.string()) }),
+			PeerRes::ScanEventAck,
+			PeerRes::AuthSuccess {
+				session: SessionInfo {
+					session_id: g.string(),
+					username: g.string(),
+					roles: g.strings(),
+					permissions: g.grants(),
+					expires_at: g.bool().then(|| g.next()),
+				},
+			},
+			PeerRes::AuthFailure { reason: g.string() },
+			PeerRes::UserCreated {
+				username: g.string(),
+			},
+			PeerRes::UserRemoved {
+				username: g.string(),
+			},
+			PeerRes::TokenIssued {
+				token: g.string(),
+				token_id: g.string(),
+				username: g.string(),
+				permissions: g.grants(),
+				expires_at: g.bool().then(|| g.next()),
+			},
+			PeerRes::TokenRevoked {
+				token_id: g.string(),
+			},
+			PeerRes::AccessGranted {
+				username: g.string(),
+				permissions: g.grants(),
+			},
+			PeerRes::Users(vec![UserSummary {
+				username: g.string(),
+				roles: g.strings(),
+				permissions: g.grants(),
+			}]),
+			PeerRes::Tokens(vec![TokenInfo {
+				id: g.string(),
+				username: g.string(),
+				label: g.opt_string(),
+				permissions: g.grants(),
+				expires_at: g.bool().then(|| g.next()),
+				revoked: g.bool(),
+				issued_at: g.next(),
+				issued_by: g.string(),
+			}]),
+			PeerRes::Error(g.string()),
+			PeerRes::Permissions(g.permissions()),
+			PeerRes::Thumbnail(Thumbnail {
+				data: g.bytes(),
+				width: g.next() as u32,
+				height: g.next() as u32,
+				mime_type: g.string(),
+			}),
+			PeerRes::UpdateStarted(if g.bool() { Ok(()) } else { Err(g.string()) }),
+			PeerRes::UpdateEventAck,
+			PeerRes::ShellStarted { id: g.next() },
+			PeerRes::ShellOutput {
+				id: g.next(),
+				data: g.bytes(),
+			},
+			PeerRes::ShellExited { id: g.next() },
+			PeerRes::DesktopInputAck(if g.bool() { Ok(()) } else { Err(g.string()) }),
+			PeerRes::PermissionsChangedAck,
+			PeerRes::InboxOpened { path: g.string() },
+			PeerRes::Hello {
+				protocol_version: g.next() as u32,
+				features: g.strings(),
+				time: g.bool().then(|| g.time()),
+			},
+			PeerRes::RestartScheduled {
+				delay_secs: g.next(),
+			},
+			PeerRes::Health(PeerHealth {
+				version: g.string(),
+				uptime_secs: g.next(),
+				db_ok: g.bool(),
+				listen_addrs: g.strings(),
+				pending_requests: g.below(1000) as usize,
+				last_restart_error: g.opt_string(),
+				pending_dials: g.below(1000) as usize,
+				queued_dials: g.below(1000) as usize,
+				dials: vec![PeerDialStats {
+					peer: g.string(),
+					succeeded: g.next(),
+					failed: g.next(),
+					last_error: g.opt_string(),
+				}],
+				churn: vec![PeerChurn {
+					peer: g.string(),
+					reconnects: g.next(),
+					idle_closes: g.next(),
+					lost: g.next(),
+					closed: g.next(),
+				}],
+			}),
+			PeerRes::DiskHistory(vec![DiskSample {
+				disk_id: g.string(),
+				mount_path: g.string(),
+				sampled_at: g.time(),
+				total_space: g.next(),
+				available_space: g.next(),
+			}]),
+			PeerRes::PairPending {
+				node_name: g.string(),
+			},
+			PeerRes::PairResponseAck,
+			PeerRes::Paired {
+				node_name: g.string(),
+				granted: g.permissions(),
+			},
+			PeerRes::PairRejected {
+				reason: if g.bool() {
+					PairingRejected::Expired
+				} else {
+					PairingRejected::AlreadyUsed
+				},
+			},
+			PeerRes::SearchResults(vec![FileSearchResult {
+				hash: g.bytes(),
+				name: g.string(),
+				path: g.string(),
+				node_id: g.bytes(),
+				size: g.next(),
+				mime_type: g.opt_string(),
+				replicas: g.next(),
+				first_datetime: g.opt_string(),
+				latest_datetime: g.opt_string(),
+				duration_ms: g.bool().then(|| g.next()),
+				width: g.bool().then(|| g.next() as u32),
+				height: g.bool().then(|| g.next() as u32),
+				codec: g.opt_string(),
+				deleted_at: g.bool().then(|| g.next() as i64),
+			}]),
+			PeerRes::DialBack {
+				reachable: g.bool(),
+				latency_ms: g.bool().then(|| g.next()),
+				error: g.opt_string(),
+			},
+			PeerRes::DialBackDeclined { reason: g.string() },
+			PeerRes::Unavailable { share: g.string() },
+			PeerRes::AccessDenied(AccessExplanation {
+				path: g.string(),
+				requested: g.next() as u8,
+				granted: g
+					.bool()
+					.then(|| FolderRule::new(g.string().into(), g.next() as u8)),
+				missing: g.next() as u8,
+				lapsed: g.bool().then(|| LapsedAccess {
+					rule: FolderRule::new(g.string().into(), g.next() as u8),
+					expired_at: g.time(),
+				}),
+				trust: g.bool().then(|| TrustRefusal {
+					request: g.string(),
+					level: TrustLevel::ALL[g.below(4) as usize],
+					needed: TrustLevel::ALL[g.below(4) as usize],
+				}),
+			}),
+			PeerRes::IndexDeltaAck(IndexDeltaAck {
+				generation: g.string(),
+				acked: g.next(),
+			}),
+			PeerRes::IndexAnnounceAck,
+			PeerRes::IndexDelta(IndexDelta {
+				generation: g.string(),
+				after: g.next(),
+				through: g.next(),
+				entries: Vec::new(),
+				locations: Vec::new(),
+				remaining: g.next(),
+			}),
+			PeerRes::Have { bitmap: g.bytes() },
+			PeerRes::DirListing(DirListing {
+				entries: vec![g.dir_entry()],
+				free_hint: g.bool().then(|| g.next()),
+				access: g.bool().then(|| ListingAccess {
+					flags: g.next() as u8,
+					entries: vec![EntryAccess {
+						name: g.string(),
+						rule: g.string(),
+						flags: g.next() as u8,
+					}],
+				}),
+			}),
+			PeerRes::RateLimited {
+				retry_after_secs: g.next(),
+			},
+			PeerRes::ImageTooLarge(ImageTooLarge {
+				width: g.next() as u32,
+				height: g.next() as u32,
+				decoded_bytes: g.next(),
+				budget: g.next(),
+			}),
+			PeerRes::ShareSummaries(vec![ShareSummary {
+				root: g.string(),
+				files: g.next(),
+				bytes: g.next(),
+				subdirs: g.strings(),
+				categories: vec![MimeCategory {
+					kind: g.string(),
+					files: g.next(),
+				}],
+				latest: g.bool().then(|| g.time()),
+				truncated: g.bool(),
+			}]),
+			PeerRes::DeleteProposalReceived {
+				outcome: g
+					.bool()
+					.then(|| DeleteOutcome::Rejected { reason: g.string() }),
+			},
+			PeerRes::DeleteOutcomeAck,
+			PeerRes::ContactSheet(ContactSheetResult::Sheet(ContactSheet {
+				image: Thumbnail {
+					data: g.bytes(),
+					width: g.next() as u32,
+					height: g.next() as u32,
+					mime_type: g.string(),
+				},
+				cells: g.next() as u32,
+				images: g.next() as u32,
+				partial: g.bool(),
+			})),
+			PeerRes::ContactSheet(ContactSheetResult::Empty),
+			PeerRes::ScanResults(ScanResults {
+				run: g.next() as i64,
+				path: WirePath::from(g.string()),
+				total: g.next(),
+				cursor: g.next(),
+				entries: Vec::new(),
+				locations: Vec::new(),
+				remaining: g.next(),
+			}),
+			PeerRes::UserRefused(CreateUserError::Invalid(vec![UserFieldError {
+				field: String::from("password"),
+				message: g.string(),
+			}])),
+			PeerRes::Unsupported {
+				request: g.string(),
+			},
+		]
+	}
+
+	/// The variant an encoded message carries: the string itself for unit
+	/// variants, the only key otherwise.
+	fn variant_of(message: &Value) -> String {
+		match message {
+			Value::String(name) => name.clone(),
+			Value::Object(map) => map.keys().next().cloned().unwrap_or_default(),
+			_ => String::new(),
+		}
+	}
+
+	fn fixture_path(file: &str) -> PathBuf {
+		Path::new(env!("CARGO_MANIFEST_DIR")).join(file)
+	}
+
+	fn write_fixtures(path: &Path, fixtures: &[Value]) {
+		let lines = fixtures
+			.iter()
+			.map(|fixture| format!("\t{fixture}"))
+			.collect::<Vec<_>>();
+		std::fs::write(path, format!("[\n{}\n]\n", lines.join(",\n"))).unwrap();
+	}
+
+	/// Checks that every fixture in `file` still decodes with the derived,
+	/// strict decoding and that every variant has one.
+	fn check_golden<T: TolerantEnum + Serialize>(file: &str, samples: Vec<T>) {
+		let path = fixture_path(file);
+		let text = std::fs::read_to_string(&path)
+			.unwrap_or_else(|err| panic!("failed to read {}: {err}", path.display()));
+		let mut fixtures: Vec<Value> = serde_json::from_str(&text)
+			.unwrap_or_else(|err| panic!("{} is not a JSON array: {err}", path.display()));
+
+		let broken = fixtures
+			.iter()
+			.filter_map(|fixture| {
+				T::deserialize_known(fixture.clone())
+					.err()
+					.map(|err| format!("  {fixture}\n    {err}"))
+			})
+			.collect::<Vec<_>>();
+		assert!(
+			broken.is_empty(),
+			"golden fixtures in {file} no longer decode. Nodes on older versions \
+			send exactly these messages, so this change breaks mixed-version \
+			networks; add a new variant or a defaulted field instead:\n{}",
+			broken.join("\n")
+		);
+
+		let covered = fixtures.iter().map(variant_of).collect::<BTreeSet<_>>();
+		let missing = T::variants()
+			.iter()
+			.copied()
+			.filter(|name| !covered.contains(*name))
+			.collect::<Vec<_>>();
+		if missing.is_empty() {
+			return;
+		}
+		if std::env::var_os(BLESS_ENV).is_none() {
+			panic!(
+				"no golden fixture for {missing:?} in {file}; run the tests with \
+				{BLESS_ENV}=1 to record them"
+			);
+		}
+		for sample in samples {
+			let encoded = serde_json::to_value(&sample).unwrap();
+			let variant = variant_of(&encoded);
+			if missing.iter().any(|name| *name == variant) {
+				fixtures.push(encoded);
+			}
+		}
+		write_fixtures(&path, &fixtures);
+	}
+
+	#[test]
+	fn golden_request_fixtures_still_decode() {
+		check_golden(REQUEST_FIXTURES, requests(&mut Gen::new(1)));
+	}
+
+	#[test]
+	fn golden_response_fixtures_still_decode() {
+		check_golden(RESPONSE_FIXTURES, responses(&mut Gen::new(1)));
+	}
+
+	fn assert_round_trips<T: TolerantEnum + Serialize + DeserializeOwned>(messages: Vec<T>) {
+		let names = messages
+			.iter()
+			.map(|message| variant_of(&serde_json::to_value(message).unwrap()))
+			.collect::<BTreeSet<_>>();
+		let expected = T::variants()
+			.iter()
+			.map(|name| name.to_string())
+			.collect::<BTreeSet<_>>();
+		assert_eq!(names, expected, "the samples must cover every variant");
+		for message in messages {
+			let encoded = serde_json::to_vec(&message).unwrap();
+			let decoded: T = serde_json::from_slice(&encoded).unwrap();
+			assert_eq!(
+				serde_json::to_vec(&decoded).unwrap(),
+				encoded,
+				"{}",
+				String::from_utf8_lossy(&encoded)
+			);
+		}
+	}
+
+	#[test]
+	fn messages_round_trip() {
+		for seed in 0..64 {
+			assert_round_trips(requests(&mut Gen::new(seed)));
+			assert_round_trips(responses(&mut Gen::new(seed)));
+		}
+	}
+
+	#[test]
+	fn unknown_variants_decode_as_unknown() {
+		let future: PeerReq =
+			serde_json::from_slice(br#"{"FutureRequest":{"nested":[1,{"a":null}]}}"#).unwrap();
+		assert!(matches!(future, PeerReq::Unknown(ref name) if name == "FutureRequest"));
+		let unit: PeerRes = serde_json::from_value(json!("FutureAck")).unwrap();
+		assert!(matches!(unit, PeerRes::Unknown(ref name) if name == "FutureAck"));
+		assert!(serde_json::to_value(&future).is_err());
+	}
+
+	#[test]
+	fn listings_without_a_free_hint_still_decode() {
+		let listing: PeerRes = serde_json::from_value(json!({
+			"DirListing": { "entries": [] }
+		}))
+		.unwrap();
+		assert!(matches!(
+			listing,
+			PeerRes::DirListing(DirListing {
+				free_hint: None,
+				access: None,
+				..
+			})
+		));
+		let roots: PeerRes = serde_json::from_value(json!({
+			"Roots": { "windows": false, "roots": [{ "label": "/", "path": "/", "kind": "Disk" }] }
+		}))
+		.unwrap();
+		assert!(matches!(roots, PeerRes::Roots(roots) if roots.roots[0].flags.is_none()));
+	}
+
+	#[test]
+	fn known_variants_keep_their_strictness() {
+		let extra_field: PeerReq = serde_json::from_value(json!({
+			"ReadFile": { "path": "/srv/a", "offset": 4, "length": null, "priority": 1 }
+		}))
+		.unwrap();
+		assert!(matches!(extra_field, PeerReq::ReadFile { offset: 4, .. }));
+		let unit: PeerReq = serde_json::from_value(json!("HealthCheck")).unwrap();
+		assert!(matches!(unit, PeerReq::HealthCheck));
+
+		assert!(
+			serde_json::from_value::<PeerReq>(json!({ "ReadFile": { "path": "/srv/a" } })).is_err()
+		);
+		assert!(
+			serde_json::from_value::<PeerReq>(json!({ "ListUsers": null, "ListCpus": null }))
+				.is_err()
+		);
+		assert!(serde_json::from_value::<PeerRes>(json!(7)).is_err());
+	}
+}
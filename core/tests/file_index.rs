@@ -0,0 +1,101 @@
+use puppynet_core::SearchFilesArgs;
+use puppynet_core::index::FileIndex;
+use std::fs;
+
+#[test]
+fn scans_and_searches_a_folder_without_a_node() {
+	let root = std::env::temp_dir().join(format!("puppynet-file-index-{}", std::process::id()));
+	let dir = root.join("files");
+	fs::create_dir_all(dir.join("photos")).unwrap();
+	fs::write(dir.join("report.txt"), "quarterly numbers").unwrap();
+	fs::write(dir.join("photos").join("beach.txt"), "sand").unwrap();
+
+	let index = FileIndex::open(root.join("index.db")).unwrap();
+	let scanned = index.scan(dir.join("photos")).unwrap();
+	assert_eq!(scanned.inserted_count, 1);
+	let scanned = index.scan(&dir).unwrap();
+	assert!(scanned.inserted_count >= 1);
+
+	let (page, _, total) = index
+		.search(SearchFilesArgs {
+			name_query: Some(String::from("beach")),
+			..Default::default()
+		})
+		.unwrap();
+	assert_eq!(total, 1);
+	assert_eq!(page.rows[0].name, "beach.txt");
+	assert_eq!(page.rows[0].node_id, index.node_id().to_vec());
+
+	let storage = index.storage_files().unwrap();
+	assert!(storage.iter().any(|file| file.path.ends_with("report.txt")));
+
+	let node_id = index.node_id();
+	drop(index);
+	let reopened = FileIndex::open(root.join("index.db")).unwrap();
+	assert_eq!(reopened.node_id(), node_id);
+	fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn deleted_files_stay_searchable_until_purged() {
+	let root = std::env::temp_dir().join(format!("puppynet-tombstones-{}", std::process::id()));
+	let dir = root.join("files");
+	fs::create_dir_all(&dir).unwrap();
+	fs::write(dir.join("ubuntu.iso"), "image").unwrap();
+	fs::write(dir.join("kept.txt"), "kept").unwrap();
+
+	let index = FileIndex::open(root.join("index.db")).unwrap();
+	index.scan(&dir).unwrap();
+	let search = |index: &FileIndex, include_deleted: bool| {
+		let (page, _, total) = index
+			.search(SearchFilesArgs {
+				name_query: Some(String::from("ubuntu")),
+				include_deleted,
+				..Default::default()
+			})
+			.unwrap();
+		(total, page.rows)
+	};
+	let storage_total = |index: &FileIndex| {
+		index
+			.storage_files()
+			.unwrap()
+			.iter()
+			.map(|file| file.size)
+			.sum::<u64>()
+	};
+	let before = storage_total(&index);
+
+	fs::remove_file(dir.join("ubuntu.iso")).unwrap();
+	assert_eq!(index.scan(&dir).unwrap().removed_count, 1);
+	assert_eq!(search(&index, false).0, 0);
+	let (total, rows) = search(&index, true);
+	assert_eq!(total, 1);
+	assert_eq!(rows[0].name, "ubuntu.iso");
+	assert!(rows[0].deleted_at.is_some());
+	assert_eq!(rows[0].replicas, 0);
+	assert_eq!(storage_total(&index), before - "image".len() as u64);
+
+	fs::write(dir.join("ubuntu.iso"), "image").unwrap();
+	assert_eq!(index.scan(&dir).unwrap().inserted_count, 1);
+	let (total, rows) = search(&index, false);
+	assert_eq!(total, 1);
+	assert_eq!(rows[0].deleted_at, None);
+	assert_eq!(rows[0].replicas, 1);
+	assert_eq!(storage_total(&index), before);
+
+	fs::remove_file(dir.join("ubuntu.iso")).unwrap();
+	index.scan(&dir).unwrap();
+	assert_eq!(
+		index.purge_tombstones(chrono::Duration::days(1)).unwrap(),
+		0
+	);
+	assert_eq!(
+		index
+			.purge_tombstones(chrono::Duration::seconds(-1))
+			.unwrap(),
+		1
+	);
+	assert_eq!(search(&index, true).0, 0);
+	fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,71 @@
+use puppynet_core::index::FileIndex;
+use rand::Rng;
+use rusqlite::Connection;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+const CHILD_ENV: &str = "PUPPYNET_SCAN_CRASH_DIR";
+const TEST_NAME: &str = "killed_scan_leaves_no_orphaned_rows";
+
+fn write_files(dir: &Path, round: usize) {
+	for i in 0..1500 {
+		let sub = dir.join(format!("d{}", i % 20));
+		fs::create_dir_all(&sub).unwrap();
+		fs::write(sub.join(format!("f{i}.txt")), format!("{round}:{i}").repeat(200)).unwrap();
+	}
+}
+
+/// Runs in the child: rescans changing files until it is killed.
+fn scan_until_killed(root: &Path) -> ! {
+	let index = FileIndex::open(root.join("index.db")).unwrap();
+	for round in 1.. {
+		write_files(&root.join("files"), round);
+		index.scan(root.join("files")).unwrap();
+	}
+	unreachable!()
+}
+
+#[test]
+fn killed_scan_leaves_no_orphaned_rows() {
+	if let Some(root) = std::env::var_os(CHILD_ENV) {
+		scan_until_killed(Path::new(&root));
+	}
+	let root = std::env::temp_dir().join(format!("puppynet-scan-crash-{}", std::process::id()));
+	let _ = fs::remove_dir_all(&root);
+	write_files(&root.join("files"), 0);
+	// The schema exists before the first kill can land.
+	drop(FileIndex::open(root.join("index.db")).unwrap());
+
+	let mut rng = rand::thread_rng();
+	for _ in 0..3 {
+		let mut child = Command::new(std::env::current_exe().unwrap())
+			.args([TEST_NAME, "--exact", "--nocapture", "--test-threads=1"])
+			.env(CHILD_ENV, &root)
+			.stdout(Stdio::null())
+			.stderr(Stdio::null())
+			.spawn()
+			.unwrap();
+		std::thread::sleep(Duration::from_millis(rng.gen_range(200..2500)));
+		child.kill().unwrap();
+		child.wait().unwrap();
+
+		let conn = Connection::open(root.join("index.db")).unwrap();
+		let integrity: String = conn
+			.query_row("PRAGMA integrity_check", [], |row| row.get(0))
+			.unwrap();
+		assert_eq!(integrity, "ok");
+		let orphans: i64 = conn
+			.query_row(
+				"SELECT COUNT(*) FROM file_locations fl \
+				 LEFT JOIN file_entries fe ON fe.hash = fl.hash \
+				 WHERE fl.hash IS NOT NULL AND fe.hash IS NULL",
+				[],
+				|row| row.get(0),
+			)
+			.unwrap();
+		assert_eq!(orphans, 0);
+	}
+	fs::remove_dir_all(&root).unwrap();
+}
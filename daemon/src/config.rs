@@ -0,0 +1,83 @@
+//! `puppynet config show`. Goes to the daemon when it is up, so its
+//! command line flags and the settings changed while it runs show, and
+//! reads the config file, environment and database here otherwise.
+//! Secrets are masked either way.
+
+use crate::control;
+use anyhow::Result;
+use puppynet_core::config::{self, ConfigValue, EffectiveConfig, KNOBS};
+use std::fmt::Write;
+
+pub async fn show() -> Result<EffectiveConfig> {
+	if control::daemon_running().await {
+		return control::effective_config().await;
+	}
+	Ok(config::load_local().redacted())
+}
+
+pub fn config_json(config: &EffectiveConfig) -> String {
+	serde_json::to_string_pretty(config).unwrap_or_default()
+}
+
+fn shown_value(value: &ConfigValue) -> &str {
+	value.value.as_deref().unwrap_or("-")
+}
+
+pub fn render_config(config: &EffectiveConfig) -> String {
+	let key_width = config
+		.values
+		.iter()
+		.map(|value| value.key.len())
+		.max()
+		.unwrap_or(0);
+	let value_width = config
+		.values
+		.iter()
+		.map(|value| shown_value(value).len())
+		.max()
+		.unwrap_or(0);
+	let mut out = String::new();
+	for value in &config.values {
+		let _ = writeln!(
+			out,
+			"{:key_width$}  {:value_width$}  {}",
+			value.key,
+			shown_value(value),
+			value.source,
+		);
+	}
+	out
+}
+
+/// Each knob's value followed by what it does, what it takes and where
+/// else it can be set, from the knobs' own docs.
+pub fn describe_config(config: &EffectiveConfig) -> String {
+	let mut out = String::new();
+	for knob in KNOBS {
+		let Some(value) = config.get(knob.key) else {
+			continue;
+		};
+		let _ = writeln!(
+			out,
+			"{} = {}  ({})",
+			knob.key,
+			shown_value(value),
+			value.source
+		);
+		let _ = writeln!(out, "    {}", knob.doc);
+		let _ = writeln!(out, "    takes      {}", knob.kind.label());
+		if let Some(default) = knob.default_value() {
+			let _ = writeln!(out, "    default    {default}");
+		}
+		if !knob.env.is_empty() {
+			let _ = writeln!(out, "    env        {}", knob.env.join(", "));
+		}
+		let changes = if knob.setting.is_some() {
+			"while running"
+		} else {
+			"at restart"
+		};
+		let _ = writeln!(out, "    changes    {changes}");
+	}
+	out
+}
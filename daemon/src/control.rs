@@ -1,12 +1,17 @@
+use crate::secrets;
+use crate::status::{self, NodeStatus};
 use anyhow::{Context, Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use puppynet_core::config::EffectiveConfig;
+use puppynet_core::format::{human_duration, relative_time};
 use puppynet_core::{
-	FLAG_READ, FLAG_SEARCH, FLAG_WRITE, FolderRule, PeerId, Permission, PuppyNet, Rule, updater,
+	DiagnosticsReport, FLAG_READ, FLAG_SEARCH, FLAG_WRITE, FolderRule, Maintenance, PairedWith,
+	PairingInfo, PeerId, Permission, PuppyNet, Rule, SecretBackend, SecretInfo, updater,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeSet;
 use std::path::PathBuf;
 use std::sync::Arc;
-#[cfg(unix)]
 use std::time::Duration;
 #[cfg(unix)]
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
@@ -29,11 +34,50 @@ enum ControlRequest {
 		read: Vec<String>,
 		write: Vec<String>,
 	},
+	Revoke {
+		peer_id: String,
+	},
+	SuspendRemoteAccess,
+	ResumeRemoteAccess,
 	Peers,
+	Status,
+	Diagnostics,
+	Backup {
+		dir: Option<String>,
+	},
+	Restore {
+		path: String,
+		force: bool,
+	},
+	ExtractMetadata {
+		path: Option<String>,
+	},
 	Update {
 		version: Option<String>,
 		current_version: u32,
 	},
+	SetSecret {
+		name: String,
+		value: String,
+	},
+	GetSecret {
+		name: String,
+	},
+	ListSecrets,
+	SetSecretBackend {
+		backend: String,
+	},
+	Config,
+	EnterMaintenance {
+		reason: String,
+		expected_secs: Option<u64>,
+	},
+	ExitMaintenance,
+	MaintenanceStatus,
+	ShowPairing,
+	JoinPairing {
+		pairing: String,
+	},
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -42,6 +86,14 @@ struct ControlResponse {
 	message: String,
 	#[serde(default)]
 	peers: Option<Vec<String>>,
+	#[serde(default)]
+	status: Option<NodeStatus>,
+	#[serde(default)]
+	diagnostics: Option<DiagnosticsReport>,
+	#[serde(default)]
+	secrets: Option<Vec<SecretInfo>>,
+	#[serde(default)]
+	config: Option<EffectiveConfig>,
 }
 
 fn app_dir() -> Result<PathBuf> {
@@ -63,6 +115,10 @@ fn ok(message: impl Into<String>) -> ControlResponse {
 		ok: true,
 		message: message.into(),
 		peers: None,
+		status: None,
+		diagnostics: None,
+		secrets: None,
+		config: None,
 	}
 }
 
@@ -71,6 +127,10 @@ fn error_response(message: impl Into<String>) -> ControlResponse {
 		ok: false,
 		message: message.into(),
 		peers: None,
+		status: None,
+		diagnostics: None,
+		secrets: None,
+		config: None,
 	}
 }
 
@@ -79,6 +139,59 @@ fn peers_response(peer_ids: Vec<String>) -> ControlResponse {
 		ok: true,
 		message: String::new(),
 		peers: Some(peer_ids),
+		status: None,
+		diagnostics: None,
+		secrets: None,
+		config: None,
+	}
+}
+
+fn status_response(status: NodeStatus) -> ControlResponse {
+	ControlResponse {
+		ok: true,
+		message: String::new(),
+		peers: None,
+		status: Some(status),
+		diagnostics: None,
+		secrets: None,
+		config: None,
+	}
+}
+
+fn diagnostics_response(report: DiagnosticsReport) -> ControlResponse {
+	ControlResponse {
+		ok: true,
+		message: String::new(),
+		peers: None,
+		status: None,
+		diagnostics: Some(report),
+		secrets: None,
+		config: None,
+	}
+}
+
+/// `message` names the backend.
+fn secrets_response(backend: &str, secrets: Vec<SecretInfo>) -> ControlResponse {
+	ControlResponse {
+		ok: true,
+		message: backend.to_string(),
+		peers: None,
+		status: None,
+		diagnostics: None,
+		secrets: Some(secrets),
+		config: None,
+	}
+}
+
+fn config_response(config: EffectiveConfig) -> ControlResponse {
+	ControlResponse {
+		ok: true,
+		message: String::new(),
+		peers: None,
+		status: None,
+		diagnostics: None,
+		secrets: None,
+		config: Some(config),
 	}
 }
 
@@ -145,10 +258,61 @@ fn grant_permissions(all: bool, read: Vec<String>, write: Vec<String>) -> Result
 	Ok(permissions)
 }
 
+/// What `puppynet pair show` prints.
+fn pairing_message(info: &PairingInfo, now: DateTime<Utc>) -> String {
+	let expires_in = (info.expires_at - now).to_std().unwrap_or_default();
+	format!(
+		"{}\n\nRun `puppynet pair join` with it on the other device. It pairs one device and expires in {}.\nAddresses: {}",
+		info.pairing_string,
+		human_duration(expires_in),
+		info.addrs.join(", ")
+	)
+}
+
+/// What `puppynet pair join` prints.
+fn paired_message(paired: &PairedWith) -> String {
+	let name = if paired.node_name.is_empty() {
+		paired.peer_id.to_string()
+	} else {
+		format!("{} ({})", paired.node_name, paired.peer_id)
+	};
+	match paired.granted.len() {
+		0 => format!("paired with {name}; it granted no new folders"),
+		1 => format!("paired with {name}; it granted 1 folder"),
+		n => format!("paired with {name}; it granted {n} folders"),
+	}
+}
+
+/// What `puppynet maintenance status` prints.
+fn maintenance_message(
+	maintenance: Option<Maintenance>,
+	running: &[String],
+	now: DateTime<Utc>,
+) -> String {
+	let mut status = match maintenance {
+		Some(maintenance) => {
+			let expected = maintenance
+				.expected()
+				.map(|expected| format!(", expected to take {}", human_duration(expected)))
+				.unwrap_or_default();
+			format!(
+				"maintenance mode on since {}{expected}: {}",
+				relative_time(maintenance.since, now),
+				maintenance.reason
+			)
+		}
+		None => String::from("maintenance mode off"),
+	};
+	if !running.is_empty() {
+		status.push_str(&format!("\nstill running: {}", running.join(", ")));
+	}
+	status
+}
+
 async fn handle_request(peer: &PuppyNet, request: ControlRequest) -> ControlResponse {
 	match request {
 		ControlRequest::CreateUser { username, password } => {
-			match peer.create_user(username.clone(), password) {
+			match peer.create_user_async(username.clone(), password).await {
 				Ok(()) => ok(format!("user {username} created")),
 				Err(err) => error_response(format!("failed to create user {username}: {err:?}")),
 			}
@@ -163,7 +327,17 @@ async fn handle_request(peer: &PuppyNet, request: ControlRequest) -> ControlResp
 				let permissions = grant_permissions(all, read, write);
 				match permissions {
 					Ok(permissions) => match peer.set_peer_permissions(peer_id, permissions) {
-						Ok(()) => ok(format!("granted access to peer {peer_id}")),
+						Ok(overlaps) if overlaps.is_empty() => {
+							ok(format!("granted access to peer {peer_id}"))
+						}
+						Ok(overlaps) => ok(format!(
+							"granted access to peer {peer_id}; overlapping rules: {}",
+							overlaps
+								.iter()
+								.map(|overlap| overlap.describe())
+								.collect::<Vec<_>>()
+								.join("; ")
+						)),
 						Err(err) => error_response(format!(
 							"failed to grant access to peer {peer_id}: {err:?}"
 						)),
@@ -175,6 +349,23 @@ async fn handle_request(peer: &PuppyNet, request: ControlRequest) -> ControlResp
 			}
 			Err(err) => error_response(format!("invalid peer id {peer_id}: {err}")),
 		},
+		ControlRequest::Revoke { peer_id } => match peer_id.parse::<PeerId>() {
+			Ok(peer_id) => match peer.revoke_all_permissions(peer_id) {
+				Ok(()) => ok(format!("revoked all access for peer {peer_id}")),
+				Err(err) => error_response(format!(
+					"failed to revoke access for peer {peer_id}: {err:?}"
+				)),
+			},
+			Err(err) => error_response(format!("invalid peer id {peer_id}: {err}")),
+		},
+		ControlRequest::SuspendRemoteAccess => match peer.suspend_remote_access() {
+			Ok(()) => ok("remote access suspended"),
+			Err(err) => error_response(format!("failed to suspend remote access: {err:?}")),
+		},
+		ControlRequest::ResumeRemoteAccess => match peer.resume_remote_access() {
+			Ok(()) => ok("remote access resumed"),
+			Err(err) => error_response(format!("failed to resume remote access: {err:?}")),
+		},
 		ControlRequest::Peers => match peer.state_snapshot().await {
 			Some(state) => {
 				let peer_ids = state
@@ -188,6 +379,34 @@ async fn handle_request(peer: &PuppyNet, request: ControlRequest) -> ControlResp
 			}
 			None => error_response("failed to read daemon state"),
 		},
+		ControlRequest::Status => match status::collect(peer).await {
+			Ok(status) => status_response(status),
+			Err(err) => error_response(format!("failed to read daemon status: {err:?}")),
+		},
+		ControlRequest::Diagnostics => diagnostics_response(peer.run_diagnostics().await),
+		ControlRequest::Backup { dir } => {
+			let result = match dir {
+				Some(dir) => peer.backup_database(dir).await,
+				None => peer.backup_now().await,
+			};
+			match result {
+				Ok(run) => ok(format!("backup written to {}", run.path)),
+				Err(err) => error_response(format!("{err:#}")),
+			}
+		}
+		ControlRequest::Restore { path, force } => {
+			match peer.restore_database(&path, force).await {
+				Ok(_) => ok(format!("restored database from {path}")),
+				Err(err) => error_response(format!("failed to restore {path}: {err:#}")),
+			}
+		}
+		ControlRequest::ExtractMetadata { path } => match peer.extract_media_metadata(path).await {
+			Ok(report) => ok(format!(
+				"extracted media metadata for {} files, {} failed",
+				report.extracted, report.failed
+			)),
+			Err(err) => error_response(format!("failed to extract media metadata: {err:#}")),
+		},
 		ControlRequest::Update {
 			version,
 			current_version,
@@ -196,6 +415,60 @@ async fn handle_request(peer: &PuppyNet, request: ControlRequest) -> ControlResp
 			Ok(result) => error_response(result.message),
 			Err(err) => error_response(format!("failed to update: {err:?}")),
 		},
+		ControlRequest::SetSecret { name, value } => match peer.secrets().set(&name, &value) {
+			Ok(()) => ok(secrets::stored_message(&name, peer.secrets().backend())),
+			Err(err) => error_response(format!("failed to store secret {name}: {err:#}")),
+		},
+		ControlRequest::GetSecret { name } => match peer.secrets().get(&name) {
+			Ok(Some(value)) => ok(value),
+			Ok(None) => error_response(format!("no secret named {name}")),
+			Err(err) => error_response(format!("failed to read secret {name}: {err:#}")),
+		},
+		ControlRequest::ListSecrets => match peer.secrets().list() {
+			Ok(list) => secrets_response(peer.secrets().backend(), list),
+			Err(err) => error_response(format!("failed to list secrets: {err:#}")),
+		},
+		ControlRequest::SetSecretBackend { backend } => match SecretBackend::parse(&backend) {
+			Some(backend) => match peer.set_secret_backend(backend) {
+				Ok(copied) => ok(secrets::switched_message(backend, copied)),
+				Err(err) => error_response(format!("failed to change the secret store: {err:#}")),
+			},
+			None => error_response(format!("unknown secret store {backend}")),
+		},
+		ControlRequest::Config => match peer.effective_config() {
+			Ok(config) => config_response(config.redacted()),
+			Err(err) => error_response(format!("failed to read the configuration: {err:#}")),
+		},
+		ControlRequest::EnterMaintenance {
+			reason,
+			expected_secs,
+		} => match peer
+			.enter_maintenance(&reason, expected_secs.map(Duration::from_secs))
+			.await
+		{
+			Ok(report) if report.safe_to_copy() => {
+				ok(format!("maintenance mode on; {}", report.summary()))
+			}
+			Ok(report) => error_response(format!("maintenance mode on, but {}", report.summary())),
+			Err(err) => error_response(format!("failed to enter maintenance: {err:#}")),
+		},
+		ControlRequest::ExitMaintenance => match peer.exit_maintenance().await {
+			Ok(()) => ok("maintenance mode off"),
+			Err(err) => error_response(format!("failed to exit maintenance: {err:#}")),
+		},
+		ControlRequest::MaintenanceStatus => ok(maintenance_message(
+			peer.maintenance(),
+			&peer.mutating_work(),
+			Utc::now(),
+		)),
+		ControlRequest::ShowPairing => match peer.pairing_info().await {
+			Ok(info) => ok(pairing_message(&info, Utc::now())),
+			Err(err) => error_response(format!("failed to make a pairing string: {err:#}")),
+		},
+		ControlRequest::JoinPairing { pairing } => match peer.pair_with(&pairing).await {
+			Ok(paired) => ok(paired_message(&paired)),
+			Err(err) => error_response(format!("failed to pair: {err:#}")),
+		},
 	}
 }
 
@@ -286,9 +559,10 @@ async fn connect_or_start_daemon(path: &PathBuf) -> Result<tokio::net::UnixStrea
 }
 
 #[cfg(unix)]
-async fn send_request(request: ControlRequest) -> Result<ControlResponse> {
-	let path = socket_path()?;
-	let mut stream = connect_or_start_daemon(&path).await?;
+async fn exchange(
+	mut stream: tokio::net::UnixStream,
+	request: ControlRequest,
+) -> Result<ControlResponse> {
 	let mut line = serde_json::to_vec(&request).context("failed to encode control request")?;
 	line.push(b'\n');
 	stream
@@ -311,11 +585,31 @@ async fn send_request(request: ControlRequest) -> Result<ControlResponse> {
 	}
 }
 
+#[cfg(unix)]
+async fn send_request(request: ControlRequest) -> Result<ControlResponse> {
+	let path = socket_path()?;
+	let stream = connect_or_start_daemon(&path).await?;
+	exchange(stream, request).await
+}
+
+/// Like [`send_request`] but never starts the service.
+#[cfg(unix)]
+async fn send_request_to_running(request: ControlRequest) -> Result<ControlResponse> {
+	let path = socket_path()?;
+	let stream = connect_socket(&path).await?;
+	exchange(stream, request).await
+}
+
 #[cfg(not(unix))]
 async fn send_request(_request: ControlRequest) -> Result<ControlResponse> {
 	bail!("daemon control socket is only supported on Unix platforms")
 }
 
+#[cfg(not(unix))]
+async fn send_request_to_running(_request: ControlRequest) -> Result<ControlResponse> {
+	bail!("daemon control socket is only supported on Unix platforms")
+}
+
 fn validate_grant_options(all: bool, read: &[String], write: &[String]) -> Result<()> {
 	if all && (!read.is_empty() || !write.is_empty()) {
 		bail!("--all cannot be combined with --read or --write");
@@ -345,6 +639,53 @@ pub async fn grant(peer_id: &str, all: bool, read: &[String], write: &[String])
 	Ok(send_request(request).await?.message)
 }
 
+pub async fn revoke(peer_id: &str) -> Result<String> {
+	let request = ControlRequest::Revoke {
+		peer_id: peer_id.to_string(),
+	};
+	Ok(send_request(request).await?.message)
+}
+
+pub async fn set_remote_access_suspended(suspended: bool) -> Result<String> {
+	let request = if suspended {
+		ControlRequest::SuspendRemoteAccess
+	} else {
+		ControlRequest::ResumeRemoteAccess
+	};
+	Ok(send_request(request).await?.message)
+}
+
+pub async fn backup(dir: Option<&str>) -> Result<String> {
+	let request = ControlRequest::Backup {
+		dir: dir.map(str::to_string),
+	};
+	Ok(send_request(request).await?.message)
+}
+
+pub async fn restore(path: &str, force: bool) -> Result<String> {
+	let request = ControlRequest::Restore {
+		path: path.to_string(),
+		force,
+	};
+	Ok(send_request(request).await?.message)
+}
+
+/// `path` is made absolute here since the daemon runs elsewhere.
+pub async fn extract_metadata(path: Option<&str>) -> Result<String> {
+	let path = match path {
+		Some(path) => Some(
+			std::path::absolute(path)
+				.with_context(|| format!("invalid path {path}"))?
+				.to_string_lossy()
+				.into_owned(),
+		),
+		None => None,
+	};
+	Ok(send_request(ControlRequest::ExtractMetadata { path })
+		.await?
+		.message)
+}
+
 pub async fn connected_peers() -> Result<Vec<String>> {
 	let response = send_request(ControlRequest::Peers).await?;
 	response
@@ -352,6 +693,109 @@ pub async fn connected_peers() -> Result<Vec<String>> {
 		.ok_or_else(|| anyhow!("daemon returned no connected peer list"))
 }
 
+/// Whether a daemon answers on the control socket right now.
+#[cfg(unix)]
+pub async fn daemon_running() -> bool {
+	match socket_path() {
+		Ok(path) => tokio::net::UnixStream::connect(path).await.is_ok(),
+		Err(_) => false,
+	}
+}
+
+#[cfg(not(unix))]
+pub async fn daemon_running() -> bool {
+	false
+}
+
+pub async fn status() -> Result<NodeStatus> {
+	send_request_to_running(ControlRequest::Status)
+		.await?
+		.status
+		.ok_or_else(|| anyhow!("daemon returned no status"))
+}
+
+/// Diagnostics run by the daemon, which knows the ports it holds.
+pub async fn diagnostics() -> Result<DiagnosticsReport> {
+	send_request_to_running(ControlRequest::Diagnostics)
+		.await?
+		.diagnostics
+		.ok_or_else(|| anyhow!("daemon returned no diagnostics"))
+}
+
+pub async fn set_secret(name: &str, value: &str) -> Result<String> {
+	let request = ControlRequest::SetSecret {
+		name: name.to_string(),
+		value: value.to_string(),
+	};
+	Ok(send_request_to_running(request).await?.message)
+}
+
+pub async fn get_secret(name: &str) -> Result<String> {
+	let request = ControlRequest::GetSecret {
+		name: name.to_string(),
+	};
+	Ok(send_request_to_running(request).await?.message)
+}
+
+/// The backend's name and the secrets in it.
+pub async fn list_secrets() -> Result<(String, Vec<SecretInfo>)> {
+	let response = send_request_to_running(ControlRequest::ListSecrets).await?;
+	let secrets = response
+		.secrets
+		.ok_or_else(|| anyhow!("daemon returned no secrets"))?;
+	Ok((response.message, secrets))
+}
+
+pub async fn set_secret_backend(backend: SecretBackend) -> Result<String> {
+	let request = ControlRequest::SetSecretBackend {
+		backend: backend.as_str().to_string(),
+	};
+	Ok(send_request_to_running(request).await?.message)
+}
+
+/// The running daemon's configuration, secrets masked.
+pub async fn effective_config() -> Result<EffectiveConfig> {
+	send_request_to_running(ControlRequest::Config)
+		.await?
+		.config
+		.ok_or_else(|| anyhow!("daemon returned no configuration"))
+}
+
+/// Fails, with maintenance left on, when the node is not safe to copy yet.
+pub async fn enter_maintenance(reason: &str, expected: Option<Duration>) -> Result<String> {
+	let request = ControlRequest::EnterMaintenance {
+		reason: reason.to_string(),
+		expected_secs: expected.map(|expected| expected.as_secs()),
+	};
+	Ok(send_request_to_running(request).await?.message)
+}
+
+pub async fn exit_maintenance() -> Result<String> {
+	Ok(send_request_to_running(ControlRequest::ExitMaintenance)
+		.await?
+		.message)
+}
+
+pub async fn maintenance_status() -> Result<String> {
+	Ok(send_request_to_running(ControlRequest::MaintenanceStatus)
+		.await?
+		.message)
+}
+
+/// A new pairing string of the running daemon, with how to use it.
+pub async fn show_pairing() -> Result<String> {
+	Ok(send_request_to_running(ControlRequest::ShowPairing)
+		.await?
+		.message)
+}
+
+pub async fn join_pairing(pairing: &str) -> Result<String> {
+	let request = ControlRequest::JoinPairing {
+		pairing: pairing.to_string(),
+	};
+	Ok(send_request(request).await?.message)
+}
+
 pub async fn update(version: Option<&str>, current_version: u32) -> Result<String> {
 	let request = ControlRequest::Update {
 		version: version.map(str::to_string),
@@ -385,3 +829,36 @@ pub async fn run(peer: Arc<PuppyNet>) -> Result<()> {
 pub async fn run(_peer: Arc<PuppyNet>) -> Result<()> {
 	bail!("daemon control socket is only supported on Unix platforms")
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn users_created_from_the_cli_name_what_was_refused() {
+		let peer = PuppyNet::new_demo(1);
+		let create = |username: &str, password: &str| ControlRequest::CreateUser {
+			username: username.to_string(),
+			password: password.to_string(),
+		};
+
+		let created = handle_request(&peer, create("carol", "long enough")).await;
+		assert!(created.ok, "{}", created.message);
+		assert!(
+			peer.list_users_db()
+				.unwrap()
+				.contains(&String::from("carol"))
+		);
+
+		let taken = handle_request(&peer, create("carol", "long enough")).await;
+		assert!(!taken.ok);
+		assert!(taken.message.contains("user carol already exists"));
+
+		let weak = handle_request(&peer, create("dave", "short")).await;
+		assert!(!weak.ok);
+		assert!(
+			weak.message
+				.contains("Password must be at least 8 characters")
+		);
+	}
+}
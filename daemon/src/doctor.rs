@@ -0,0 +1,48 @@
+//! `puppynet doctor`. The checks run inside the daemon when it is up, so
+//! the ports it holds don't read as taken, and in this process otherwise.
+
+use crate::control;
+use anyhow::Result;
+use puppynet_core::{DiagnosticStatus, DiagnosticsReport, run_diagnostics};
+use std::fmt::Write;
+
+pub async fn run() -> Result<DiagnosticsReport> {
+	if control::daemon_running().await {
+		return control::diagnostics().await;
+	}
+	Ok(run_diagnostics().await)
+}
+
+pub fn report_json(report: &DiagnosticsReport) -> String {
+	serde_json::to_string_pretty(report).unwrap_or_default()
+}
+
+pub fn render_report(report: &DiagnosticsReport) -> String {
+	let width = report
+		.checks
+		.iter()
+		.map(|check| check.check.len())
+		.max()
+		.unwrap_or(0);
+	let mut out = String::new();
+	for check in &report.checks {
+		let _ = writeln!(
+			out,
+			"{}  {:width$}  {}",
+			check.status.label().to_uppercase(),
+			check.check,
+			check.detail,
+		);
+		if let Some(hint) = &check.hint {
+			let _ = writeln!(out, "      {:width$}  {hint}", "");
+		}
+	}
+	let _ = writeln!(
+		out,
+		"{} passed, {} warnings, {} failed",
+		report.count(DiagnosticStatus::Pass),
+		report.count(DiagnosticStatus::Warn),
+		report.count(DiagnosticStatus::Fail),
+	);
+	out
+}
@@ -1,50 +1,87 @@
 use anyhow::{Context, Result};
-use puppynet_core::{PuppyNet, http_api, ui};
+use puppynet_core::PuppyNet;
+#[cfg(feature = "http-api")]
+use puppynet_core::http_api;
+#[cfg(feature = "web-ui")]
+use puppynet_core::ui;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
+pub mod config;
 pub mod control;
+pub mod doctor;
+pub mod secrets;
+pub mod status;
 
 #[derive(Debug, Clone)]
 pub struct Config {
 	pub read: Vec<String>,
 	pub write: Vec<String>,
-	pub ui_bind: String,
+	pub ui_bind: Option<String>,
 	pub http: Option<String>,
 }
 
+/// The flags that set configuration knobs, by knob key.
+fn cli_layer(config: &Config) -> puppynet_core::config::Layer {
+	let mut layer = puppynet_core::config::Layer::new();
+	if let Some(bind) = &config.ui_bind {
+		layer.insert("ui_bind".to_string(), bind.clone());
+	}
+	if let Some(bind) = &config.http {
+		layer.insert("http_bind".to_string(), bind.clone());
+	}
+	layer
+}
+
 fn register_shared_folders(peer: &PuppyNet, config: &Config) -> Result<()> {
+	let mut overlaps = Vec::new();
 	for path in &config.read {
-		peer.share_read_only_folder(path)
-			.with_context(|| format!("failed to share {path} for read"))?;
+		overlaps.extend(
+			peer.share_read_only_folder(path)
+				.with_context(|| format!("failed to share {path} for read"))?,
+		);
 	}
 	for path in &config.write {
-		peer.share_read_write_folder(path)
-			.with_context(|| format!("failed to share {path} for read/write"))?;
+		overlaps.extend(
+			peer.share_read_write_folder(path)
+				.with_context(|| format!("failed to share {path} for read/write"))?,
+		);
+	}
+	overlaps.dedup();
+	for overlap in overlaps {
+		log::warn!("overlapping shared folders: {}", overlap.describe());
 	}
 	Ok(())
 }
 
-fn parse_socket_addr(label: &str, value: &str) -> Result<SocketAddr> {
-	value
-		.parse::<SocketAddr>()
-		.with_context(|| format!("invalid {label} address {value}"))
-}
-
-fn spawn_ui(peer: Arc<PuppyNet>, bind: SocketAddr) -> tokio::task::JoinHandle<()> {
-	tokio::spawn(async move {
+#[cfg(feature = "web-ui")]
+fn spawn_ui(peer: Arc<PuppyNet>, bind: SocketAddr) -> Option<tokio::task::JoinHandle<()>> {
+	Some(tokio::spawn(async move {
 		if let Err(err) = ui::run_ui(peer, bind).await {
 			log::error!("ui server error: {err:?}");
 		}
-	})
+	}))
 }
 
-fn spawn_http(peer: Arc<PuppyNet>, bind: SocketAddr) -> tokio::task::JoinHandle<()> {
-	tokio::spawn(async move {
+#[cfg(not(feature = "web-ui"))]
+fn spawn_ui(_peer: Arc<PuppyNet>, bind: SocketAddr) -> Option<tokio::task::JoinHandle<()>> {
+	log::info!("built without the web UI; not serving it on {bind}");
+	None
+}
+
+#[cfg(feature = "http-api")]
+fn spawn_http(peer: Arc<PuppyNet>, bind: SocketAddr) -> Option<tokio::task::JoinHandle<()>> {
+	Some(tokio::spawn(async move {
 		if let Err(err) = http_api::serve(peer, bind).await {
 			log::error!("http server error: {err:?}");
 		}
-	})
+	}))
+}
+
+#[cfg(not(feature = "http-api"))]
+fn spawn_http(_peer: Arc<PuppyNet>, bind: SocketAddr) -> Option<tokio::task::JoinHandle<()>> {
+	log::warn!("built without the HTTP API; not serving it on {bind}");
+	None
 }
 
 fn spawn_control(peer: Arc<PuppyNet>) -> tokio::task::JoinHandle<()> {
@@ -76,25 +113,22 @@ async fn wait_for_peer(peer: Arc<PuppyNet>) {
 }
 
 pub async fn run(config: Config) -> Result<()> {
+	let settings = puppynet_core::config::init(cli_layer(&config))?;
 	let peer = Arc::new(PuppyNet::new());
 	register_shared_folders(&peer, &config)?;
 
-	let ui_addr = parse_socket_addr("--ui-bind", &config.ui_bind)?;
-	let ui_task = spawn_ui(Arc::clone(&peer), ui_addr);
+	let ui_task = spawn_ui(Arc::clone(&peer), settings.ui_bind);
 	let control_task = spawn_control(Arc::clone(&peer));
 
-	let http_task = if let Some(addr_str) = &config.http {
-		Some(spawn_http(
-			Arc::clone(&peer),
-			parse_socket_addr("--http", addr_str)?,
-		))
-	} else {
-		None
-	};
+	let http_task = settings
+		.http_bind
+		.and_then(|addr| spawn_http(Arc::clone(&peer), addr));
 
 	wait_for_shutdown().await;
 	stop_task(control_task).await;
-	stop_task(ui_task).await;
+	if let Some(task) = ui_task {
+		stop_task(task).await;
+	}
 	if let Some(task) = http_task {
 		stop_task(task).await;
 	}
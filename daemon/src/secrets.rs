@@ -0,0 +1,73 @@
+//! `puppynet secrets`. Goes to the daemon when it is up, so the store it
+//! reads is the one changed, and to the store in this process otherwise.
+//! Listing shows names and where they come from, never values.
+
+use crate::control;
+use anyhow::{Result, bail};
+use puppynet_core::{SecretBackend, SecretInfo, Secrets};
+use std::fmt::Write;
+
+/// Stored secrets are read once at startup.
+pub(crate) fn stored_message(name: &str, backend: &str) -> String {
+	format!("stored {name} in the {backend} store; restart the daemon to use it")
+}
+
+pub(crate) fn switched_message(backend: SecretBackend, copied: usize) -> String {
+	format!(
+		"secrets go to the {} store from the next start; copied {copied} secrets",
+		backend.as_str()
+	)
+}
+
+pub async fn set(name: &str, value: &str) -> Result<String> {
+	if control::daemon_running().await {
+		return control::set_secret(name, value).await;
+	}
+	let secrets = Secrets::open_local();
+	secrets.set(name, value)?;
+	Ok(format!("stored {name} in the {} store", secrets.backend()))
+}
+
+pub async fn get(name: &str) -> Result<String> {
+	if control::daemon_running().await {
+		return control::get_secret(name).await;
+	}
+	match Secrets::open_local().get(name)? {
+		Some(value) => Ok(value),
+		None => bail!("no secret named {name}"),
+	}
+}
+
+/// The backend's name and the secrets it and the environment hold.
+pub async fn list() -> Result<(String, Vec<SecretInfo>)> {
+	if control::daemon_running().await {
+		return control::list_secrets().await;
+	}
+	let secrets = Secrets::open_local();
+	Ok((secrets.backend().to_string(), secrets.list()?))
+}
+
+pub async fn set_backend(backend: &str) -> Result<String> {
+	let Some(backend) = SecretBackend::parse(backend) else {
+		bail!("unknown secret store {backend}; use auto, file or keychain");
+	};
+	if control::daemon_running().await {
+		return control::set_secret_backend(backend).await;
+	}
+	let copied = Secrets::open_local().switch_local_backend(backend)?;
+	Ok(switched_message(backend, copied))
+}
+
+pub fn render_list(backend: &str, secrets: &[SecretInfo]) -> String {
+	let width = secrets
+		.iter()
+		.map(|secret| secret.name.len())
+		.max()
+		.unwrap_or(0);
+	let mut out = String::new();
+	let _ = writeln!(out, "store  {backend}");
+	for secret in secrets {
+		let _ = writeln!(out, "{:width$}  {}", secret.name, secret.source);
+	}
+	out
+}
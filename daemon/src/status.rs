@@ -0,0 +1,315 @@
+//! Node status for `puppynet status` and `puppynet peers`. The JSON form is
+//! meant for scripts: bump [`STATUS_SCHEMA_VERSION`] whenever a field is
+//! renamed, removed or changes meaning. New fields do not need a bump.
+
+use crate::control;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use puppynet_core::format::{SizeUnits, abbrev_peer_id, human_size, relative_time};
+use puppynet_core::{DiscoveredPeerFilter, DiscoveredPeerInfo, FolderRule, PuppyNet, State};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+pub const STATUS_SCHEMA_VERSION: u32 = 1;
+/// How long a node started just to answer a status query listens for
+/// peers before reporting.
+pub const DISCOVERY_WAIT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeStatus {
+	pub schema_version: u32,
+	pub peer_id: String,
+	pub fingerprint: String,
+	pub listen_addresses: Vec<String>,
+	pub shared_folders: Vec<SharedFolderStatus>,
+	/// Discovered and connected peers, ordered by peer id.
+	pub peers: Vec<PeerStatus>,
+	pub database: DatabaseStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SharedFolderStatus {
+	pub path: String,
+	/// Any of `read`, `write`, `execute` and `search`.
+	pub flags: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerStatus {
+	pub peer_id: String,
+	pub name: Option<String>,
+	pub connected: bool,
+	pub addresses: Vec<String>,
+	/// Now for connected peers, otherwise when the last connection closed,
+	/// if there was one.
+	pub last_seen: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatabaseStatus {
+	pub path: String,
+	pub size_bytes: Option<u64>,
+}
+
+fn folder_flags(folder: &FolderRule) -> Vec<String> {
+	[
+		("read", folder.can_read()),
+		("write", folder.can_write()),
+		("execute", folder.can_execute()),
+		("search", folder.can_search()),
+	]
+	.into_iter()
+	.filter(|(_, set)| *set)
+	.map(|(flag, _)| flag.to_string())
+	.collect()
+}
+
+fn peer_entry(peers: &mut BTreeMap<String, PeerStatus>, peer_id: String) -> &mut PeerStatus {
+	peers.entry(peer_id.clone()).or_insert_with(|| PeerStatus {
+		peer_id,
+		name: None,
+		connected: false,
+		addresses: Vec::new(),
+		last_seen: None,
+	})
+}
+
+pub fn build_status(
+	state: &State,
+	discovered: &[DiscoveredPeerInfo],
+	listen_addresses: Vec<String>,
+	db_path: &Path,
+	now: DateTime<Utc>,
+) -> NodeStatus {
+	let mut peers = BTreeMap::<String, PeerStatus>::new();
+	for connection in &state.connections {
+		let peer = peer_entry(&mut peers, connection.peer_id.to_string());
+		peer.connected = true;
+		peer.addresses.push(connection.remote_addr.to_string());
+	}
+	for discovered in discovered {
+		let peer = peer_entry(&mut peers, discovered.peer_id.to_string());
+		peer.addresses
+			.extend(discovered.addresses.iter().map(ToString::to_string));
+	}
+	for (peer_id, drops) in &state.connection_drops {
+		if let Some(peer) = peers.get_mut(&peer_id.to_string()) {
+			peer.last_seen = drops.last().copied();
+		}
+	}
+	for known in &state.peers {
+		if let Some(peer) = peers.get_mut(&known.id.to_string()) {
+			peer.name = known.name.clone();
+		}
+	}
+	let peers = peers
+		.into_values()
+		.map(|mut peer| {
+			peer.addresses.sort();
+			peer.addresses.dedup();
+			if peer.connected {
+				peer.last_seen = Some(now);
+			}
+			peer
+		})
+		.collect();
+
+	let peer_id = state.me.to_string();
+	NodeStatus {
+		schema_version: STATUS_SCHEMA_VERSION,
+		fingerprint: abbrev_peer_id(&peer_id),
+		peer_id,
+		listen_addresses,
+		shared_folders: state
+			.shared_folders
+			.iter()
+			.map(|folder| SharedFolderStatus {
+				path: folder.path().display().to_string(),
+				flags: folder_flags(folder),
+			})
+			.collect(),
+		peers,
+		database: DatabaseStatus {
+			path: db_path.display().to_string(),
+			size_bytes: std::fs::metadata(db_path).ok().map(|meta| meta.len()),
+		},
+	}
+}
+
+pub async fn collect(peer: &PuppyNet) -> Result<NodeStatus> {
+	let state = peer
+		.state_snapshot()
+		.await
+		.ok_or_else(|| anyhow!("failed to read node state"))?;
+	let discovered = peer
+		.query_discovered_peers(DiscoveredPeerFilter::default(), usize::MAX)
+		.await;
+	let listen_addresses = match peer.health_check(state.me).await {
+		Ok(health) => health.listen_addrs,
+		Err(err) => {
+			log::warn!("failed to read listen addresses: {err:?}");
+			Vec::new()
+		}
+	};
+	Ok(build_status(
+		&state,
+		&discovered,
+		listen_addresses,
+		&peer.db_path(),
+		Utc::now(),
+	))
+}
+
+/// Where status comes from: the running daemon when its control socket
+/// answers, otherwise a node started in this process.
+pub enum StatusSource {
+	Daemon,
+	Local(Box<PuppyNet>),
+}
+
+impl StatusSource {
+	pub async fn connect() -> Self {
+		if control::daemon_running().await {
+			return StatusSource::Daemon;
+		}
+		let peer = PuppyNet::new();
+		tokio::time::sleep(DISCOVERY_WAIT).await;
+		StatusSource::Local(Box::new(peer))
+	}
+
+	pub async fn status(&self) -> Result<NodeStatus> {
+		match self {
+			StatusSource::Daemon => control::status().await,
+			StatusSource::Local(peer) => collect(peer).await,
+		}
+	}
+}
+
+/// Whether two peer lists match, ignoring the `last_seen` of connected
+/// peers, which moves on every query.
+pub fn same_peers(a: &[PeerStatus], b: &[PeerStatus]) -> bool {
+	a.len() == b.len()
+		&& a.iter().zip(b).all(|(a, b)| {
+			a.peer_id == b.peer_id
+				&& a.name == b.name
+				&& a.connected == b.connected
+				&& a.addresses == b.addresses
+				&& (a.connected || a.last_seen == b.last_seen)
+		})
+}
+
+pub fn status_json(status: &NodeStatus) -> String {
+	serde_json::to_string_pretty(status).unwrap_or_default()
+}
+
+/// The peer list on one line, so `--watch` output can be read line by line.
+pub fn peers_json(status: &NodeStatus) -> String {
+	serde_json::json!({
+		"schema_version": status.schema_version,
+		"peers": status.peers,
+	})
+	.to_string()
+}
+
+pub fn render_peers(peers: &[PeerStatus]) -> String {
+	let now = Utc::now();
+	let mut out = String::new();
+	if peers.is_empty() {
+		out.push_str("no peers found\n");
+		return out;
+	}
+	for peer in peers {
+		let _ = writeln!(
+			out,
+			"{}  {}  {}  {}",
+			peer.peer_id,
+			peer.name.as_deref().unwrap_or("-"),
+			if peer.connected {
+				"connected"
+			} else {
+				"discovered"
+			},
+			peer.last_seen
+				.map(|at| relative_time(at, now))
+				.unwrap_or_else(|| String::from("never")),
+		);
+		for address in &peer.addresses {
+			let _ = writeln!(out, "    {address}");
+		}
+	}
+	out
+}
+
+pub fn render_status(status: &NodeStatus) -> String {
+	let mut out = String::new();
+	let _ = writeln!(out, "peer id      {}", status.peer_id);
+	let _ = writeln!(out, "fingerprint  {}", status.fingerprint);
+	let _ = writeln!(
+		out,
+		"database     {} ({})",
+		status.database.path,
+		status
+			.database
+			.size_bytes
+			.map(|size| human_size(size, SizeUnits::Binary))
+			.unwrap_or_else(|| String::from("missing")),
+	);
+	let _ = writeln!(out, "listening    {}", status.listen_addresses.len());
+	for address in &status.listen_addresses {
+		let _ = writeln!(out, "    {address}");
+	}
+	let _ = writeln!(out, "shared       {}", status.shared_folders.len());
+	for folder in &status.shared_folders {
+		let _ = writeln!(out, "    {}  {}", folder.path, folder.flags.join(","));
+	}
+	let _ = writeln!(out, "peers        {}", status.peers.len());
+	out.push_str(&render_peers(&status.peers));
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::TimeZone;
+
+	#[test]
+	fn json_matches_snapshot() {
+		let status = NodeStatus {
+			schema_version: STATUS_SCHEMA_VERSION,
+			peer_id: String::from("12D3KooWLocal"),
+			fingerprint: String::from("12D3KooWLocal"),
+			listen_addresses: vec![String::from("/ip4/0.0.0.0/tcp/8336")],
+			shared_folders: vec![SharedFolderStatus {
+				path: String::from("/srv/media"),
+				flags: vec![String::from("read"), String::from("search")],
+			}],
+			peers: vec![
+				PeerStatus {
+					peer_id: String::from("12D3KooWNas"),
+					name: Some(String::from("nas")),
+					connected: true,
+					addresses: vec![String::from("/ip4/192.168.1.20/tcp/8336")],
+					last_seen: Some(Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap()),
+				},
+				PeerStatus {
+					peer_id: String::from("12D3KooWPhone"),
+					name: None,
+					connected: false,
+					addresses: Vec::new(),
+					last_seen: None,
+				},
+			],
+			database: DatabaseStatus {
+				path: String::from("/home/me/.puppynet/puppynet.db"),
+				size_bytes: Some(4096),
+			},
+		};
+		assert_eq!(
+			status_json(&status),
+			include_str!("snapshots/status.json").trim_end()
+		);
+	}
+}